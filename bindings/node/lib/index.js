@@ -0,0 +1,35 @@
+"use strict";
+
+// Node.js bindings for the BinJS encoder and decoder.
+//
+// See `native/src/lib.rs` for the options accepted by each function.
+
+var native = require("../native");
+
+function promisify(fn) {
+    return function(input, options) {
+        return new Promise(function(resolve, reject) {
+            fn(input, options, function(error, result) {
+                if (error) {
+                    reject(error);
+                } else {
+                    resolve(result);
+                }
+            });
+        });
+    };
+}
+
+module.exports = {
+    // Encode a source, returning a `Promise` of a `Buffer`.
+    encode: promisify(native.encode),
+
+    // Decode a `Buffer` or an `ArrayBuffer`, returning a `Promise` of the source.
+    decode: promisify(native.decode),
+
+    // Encode a source, returning a `Buffer`. Blocks the event loop.
+    encodeSync: native.encodeSync,
+
+    // Decode a `Buffer` or an `ArrayBuffer`, returning the source. Blocks the event loop.
+    decodeSync: native.decodeSync,
+};
@@ -0,0 +1,5 @@
+extern crate neon_build;
+
+fn main() {
+    neon_build::setup();
+}
@@ -0,0 +1,266 @@
+//! Node.js bindings for the BinJS encoder and decoder, so that JS build tools
+//! (webpack or rollup plugins, etc.) may encode sources in-process rather than
+//! launching `binjs_encode` for each file.
+//!
+//! Exported functions, wrapped by `lib/index.js`:
+//!
+//! - `encodeSync(source, options)`, returning a `Buffer`;
+//! - `decodeSync(data, options)`, where `data` is a `Buffer` or an `ArrayBuffer`,
+//!   returning the source as a string;
+//! - `encode(source, options, callback)` and `decode(data, options, callback)`, which
+//!   run on the libuv thread pool and call `callback(error, result)`.
+//!
+//! `options` may be `undefined` or an object with members:
+//!
+//! - `format`, the name of a format followed by its arguments, as in
+//!   `binjs_encode advanced ...`, `["multipart"]` by default;
+//! - `lazify`, the number of layers of functions to lazify when encoding, 0 by default.
+//!
+//! Sources are still parsed and pretty-printed by Shift, in long-lived Node processes
+//! shared by all calls, see `binjs::source::DaemonPool`.
+
+extern crate binjs;
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate neon;
+
+use binjs::generic::{ FromJSON, ToJSON };
+use binjs::io::Format;
+use binjs::source::{ DaemonPool, Shift, SourceParser };
+use binjs::specialized::es6::ast::{ Program, Walker, WalkPath };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use neon::prelude::*;
+
+use std::io::Cursor;
+use std::sync::Arc;
+use std::thread;
+
+/// The maximal number of idle parser processes kept alive between calls.
+const MAX_IDLE_DAEMONS: usize = 4;
+
+lazy_static! {
+    static ref DAEMONS: Arc<DaemonPool> = Arc::new(DaemonPool::new("node", MAX_IDLE_DAEMONS));
+}
+
+/// The options of a call.
+#[derive(Clone)]
+struct Options {
+    /// The name of a format followed by its arguments, e.g. `["multipart"]`.
+    format: Vec<String>,
+
+    /// The number of layers of functions to lazify when encoding.
+    lazification: u32,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            format: vec!["multipart".to_string()],
+            lazification: 0,
+        }
+    }
+}
+impl Options {
+    fn format(&self) -> Result<Format, String> {
+        let args : Vec<&str> = self.format.iter()
+            .map(String::as_str)
+            .collect();
+        Format::from_args(&args)
+            .map_err(|err| format!("Invalid format: {}", err))
+    }
+}
+
+/// Run `f` on a thread with a large stack, as encoding and decoding deeply nested
+/// ASTs recurses deeply, turning panics into errors.
+fn with_large_stack<T, F>(f: F) -> Result<T, String>
+    where T: Send + 'static,
+          F: FnOnce() -> Result<T, String> + Send + 'static
+{
+    thread::Builder::new()
+        .name("binjs".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(f)
+        .map_err(|err| format!("Could not launch thread: {}", err))?
+        .join()
+        .unwrap_or_else(|_| Err("panic".to_string()))
+}
+
+fn encode_source(source: String, options: Options) -> Result<Vec<u8>, String> {
+    with_large_stack(move || {
+        let parser = Shift::new()
+            .with_daemons(Some(DAEMONS.clone()));
+        let json = parser.parse_str(&source)
+            .map_err(|err| format!("Could not parse source: {:?}", err))?;
+        let mut ast = Program::import(&json)
+            .map_err(|err| format!("Could not import AST: {:?}", err))?;
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+        ast.walk(&mut WalkPath::new(), &mut binjs::specialized::es6::lazy::LazifierVisitor::new(options.lazification))
+            .map_err(|err| format!("Could not introduce laziness: {:?}", err))?;
+        let mut format = options.format()?;
+        let data = Encoder::new()
+            .encode(&mut format, &ast)
+            .map_err(|err| format!("Could not encode: {:?}", err))?;
+        Ok((*data).as_ref().to_vec())
+    })
+}
+
+fn decode_data(data: Vec<u8>, options: Options) -> Result<String, String> {
+    with_large_stack(move || {
+        let mut format = options.format()?;
+        let ast : Program = Decoder::new()
+            .decode(&mut format, Cursor::new(&data))
+            .map_err(|err| format!("Could not decode: {:?}", err))?;
+
+        let mut builder = binjs::meta::spec::SpecBuilder::new();
+        let _ = binjs::generic::es6::Library::new(&mut builder);
+        let spec_options = binjs::meta::spec::SpecOptions {
+            null: &builder.node_name(""),
+            root: &builder.node_name("Program"),
+        };
+        let spec = builder.into_spec(spec_options);
+        Shift::new()
+            .with_daemons(Some(DAEMONS.clone()))
+            .to_source(&spec, &ast.export())
+            .map_err(|err| format!("Could not pretty-print: {:?}", err))
+    })
+}
+
+/// Read the options from argument `index`, if specified.
+fn read_options(cx: &mut FunctionContext, index: i32) -> NeonResult<Options> {
+    let mut options = Options::default();
+    let object = match cx.argument_opt(index) {
+        None => return Ok(options),
+        Some(ref value) if value.is_a::<JsUndefined>() || value.is_a::<JsNull>() => return Ok(options),
+        Some(value) => value.downcast_or_throw::<JsObject, _>(cx)?
+    };
+
+    let format = object.get(cx, "format")?;
+    if !format.is_a::<JsUndefined>() {
+        let args = format.downcast_or_throw::<JsArray, _>(cx)?
+            .to_vec(cx)?;
+        options.format.clear();
+        for arg in args {
+            let arg = arg.downcast_or_throw::<JsString, _>(cx)?
+                .value();
+            options.format.push(arg);
+        }
+    }
+
+    let lazify = object.get(cx, "lazify")?;
+    if !lazify.is_a::<JsUndefined>() {
+        let lazify = lazify.downcast_or_throw::<JsNumber, _>(cx)?
+            .value();
+        if lazify < 0. || lazify.fract() != 0. {
+            return cx.throw_range_error("Expected `lazify` as a non-negative integer");
+        }
+        options.lazification = lazify as u32;
+    }
+    Ok(options)
+}
+
+/// Copy the contents of argument `index`, a `Buffer` or an `ArrayBuffer`.
+fn read_data(cx: &mut FunctionContext, index: i32) -> NeonResult<Vec<u8>> {
+    let value = cx.argument::<JsValue>(index)?;
+    if let Ok(buffer) = value.downcast::<JsBuffer>() {
+        return Ok(cx.borrow(&buffer, |contents| contents.as_slice::<u8>().to_vec()));
+    }
+    let buffer = value.downcast_or_throw::<JsArrayBuffer, _>(cx)?;
+    Ok(cx.borrow(&buffer, |contents| contents.as_slice::<u8>().to_vec()))
+}
+
+/// Copy `data` into a new `Buffer`.
+fn to_buffer<'a, C: Context<'a>>(cx: &mut C, data: &[u8]) -> JsResult<'a, JsBuffer> {
+    let mut buffer = JsBuffer::new(cx, data.len() as u32)?;
+    cx.borrow_mut(&mut buffer, |contents| contents.as_mut_slice::<u8>().copy_from_slice(data));
+    Ok(buffer)
+}
+
+fn encode_sync(mut cx: FunctionContext) -> JsResult<JsBuffer> {
+    let source = cx.argument::<JsString>(0)?.value();
+    let options = read_options(&mut cx, 1)?;
+    match encode_source(source, options) {
+        Ok(data) => to_buffer(&mut cx, &data),
+        Err(err) => cx.throw_error(err)
+    }
+}
+
+fn decode_sync(mut cx: FunctionContext) -> JsResult<JsString> {
+    let data = read_data(&mut cx, 0)?;
+    let options = read_options(&mut cx, 1)?;
+    match decode_data(data, options) {
+        Ok(source) => Ok(cx.string(source)),
+        Err(err) => cx.throw_error(err)
+    }
+}
+
+/// Encoding on the libuv thread pool.
+struct EncodeTask {
+    source: String,
+    options: Options,
+}
+impl Task for EncodeTask {
+    type Output = Vec<u8>;
+    type Error = String;
+    type JsEvent = JsBuffer;
+
+    fn perform(&self) -> Result<Vec<u8>, String> {
+        encode_source(self.source.clone(), self.options.clone())
+    }
+
+    fn complete(self, mut cx: TaskContext, result: Result<Vec<u8>, String>) -> JsResult<JsBuffer> {
+        match result {
+            Ok(data) => to_buffer(&mut cx, &data),
+            Err(err) => cx.throw_error(err)
+        }
+    }
+}
+
+/// Decoding on the libuv thread pool.
+struct DecodeTask {
+    data: Vec<u8>,
+    options: Options,
+}
+impl Task for DecodeTask {
+    type Output = String;
+    type Error = String;
+    type JsEvent = JsString;
+
+    fn perform(&self) -> Result<String, String> {
+        decode_data(self.data.clone(), self.options.clone())
+    }
+
+    fn complete(self, mut cx: TaskContext, result: Result<String, String>) -> JsResult<JsString> {
+        match result {
+            Ok(source) => Ok(cx.string(source)),
+            Err(err) => cx.throw_error(err)
+        }
+    }
+}
+
+fn encode_async(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let source = cx.argument::<JsString>(0)?.value();
+    let options = read_options(&mut cx, 1)?;
+    let callback = cx.argument::<JsFunction>(2)?;
+    EncodeTask { source, options }
+        .schedule(callback);
+    Ok(cx.undefined())
+}
+
+fn decode_async(mut cx: FunctionContext) -> JsResult<JsUndefined> {
+    let data = read_data(&mut cx, 0)?;
+    let options = read_options(&mut cx, 1)?;
+    let callback = cx.argument::<JsFunction>(2)?;
+    DecodeTask { data, options }
+        .schedule(callback);
+    Ok(cx.undefined())
+}
+
+register_module!(mut cx, {
+    cx.export_function("encodeSync", encode_sync)?;
+    cx.export_function("decodeSync", decode_sync)?;
+    cx.export_function("encode", encode_async)?;
+    cx.export_function("decode", decode_async)?;
+    Ok(())
+});
@@ -0,0 +1,38 @@
+"use strict";
+
+// Encode and decode a source, synchronously and asynchronously.
+
+var assert = require("assert");
+var binjs = require("..");
+
+var source = "function foo(a, b) { return a + b; }\nfoo(1, 2);\n";
+
+var data = binjs.encodeSync(source, { lazify: 1 });
+assert.ok(Buffer.isBuffer(data));
+var decoded = binjs.decodeSync(data);
+assert.equal(binjs.decodeSync(binjs.encodeSync(decoded)), decoded);
+
+// The result owns its `ArrayBuffer`, which may be transferred to a worker.
+assert.equal(data.byteOffset, 0);
+assert.equal(data.buffer.byteLength, data.length);
+assert.equal(binjs.decodeSync(data.buffer), decoded);
+
+assert.throws(function() { binjs.encodeSync("function (", {}); });
+assert.throws(function() { binjs.encodeSync(source, { format: ["no-such-format"] }); });
+
+binjs.encode(source, { format: ["multipart"] })
+    .then(binjs.decode)
+    .then(function(result) {
+        assert.equal(result, decoded);
+        return binjs.decode(Buffer.from("not a binjs file"));
+    })
+    .then(function() {
+        assert.fail("Decoding garbage should fail");
+    }, function(error) {
+        assert.ok(error instanceof Error);
+        console.log("ok");
+    })
+    .catch(function(error) {
+        console.error(error);
+        process.exit(1);
+    });
@@ -0,0 +1,27 @@
+use core::fmt;
+
+/// An error while decoding.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The data ended in the middle of a value.
+    UnexpectedEnd,
+
+    /// A varnum does not fit in 32 bits or is an invalid encoding of 0.
+    InvalidVarNum(&'static str),
+
+    /// A float or varfloat is invalid, or a NaN is rejected by the `NaNPolicy`.
+    InvalidFloat(&'static str),
+
+    /// The state of an entropy decoder is inconsistent with the distribution.
+    InvalidState(&'static str),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            Error::UnexpectedEnd => write!(f, "unexpected end of data"),
+            Error::InvalidVarNum(message) => write!(f, "invalid varnum ({})", message),
+            Error::InvalidFloat(message) => write!(f, "invalid float ({})", message),
+            Error::InvalidState(message) => write!(f, "invalid state ({})", message),
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use { ByteSource, Error, Input };
+use varnum::{ read_varnum, VARNUM_INVALID_ZERO_1, VARNUM_INVALID_ZERO_2 };
+
+/// The representation of "no float", used for `float | null`.
+///
+/// Note that this is also the representation of a signaling NaN.
+pub const NONE_FLOAT_REPR: u64 = 0x7FF0000000000001;
+
+/// The representation of the canonical NaN, a quiet NaN without payload.
+pub const CANONICAL_NAN_REPR: u64 = 0x7FF8000000000000;
+
+/// The prefix of varfloats represented with 64 bits.
+pub const VARNUM_PREFIX_FLOAT: [u8; 2] = VARNUM_INVALID_ZERO_1;
+
+/// The varfloat representing null.
+pub const VARNUM_NULL: [u8; 3] = VARNUM_INVALID_ZERO_2;
+
+/// The largest exponent `e` of a decimal varfloat `m / 10^e`.
+pub const VARFLOAT_MAX_EXPONENT: i32 = 8;
+
+/// Integer varfloats are in [-VARFLOAT_INTEGER_BOUND, VARFLOAT_INTEGER_BOUND).
+pub const VARFLOAT_INTEGER_BOUND: i64 = 1 << 30;
+
+/// The mantissa of decimal varfloats is in [-VARFLOAT_MANTISSA_BOUND, VARFLOAT_MANTISSA_BOUND).
+pub const VARFLOAT_MANTISSA_BOUND: i64 = 1 << 27;
+
+/// The maximal number of bytes in the varnum of a varfloat, i.e. in a 32 bit varnum.
+const MAX_HEADER_LEN: usize = 5;
+
+/// `10^e` for each exponent `e` of decimal varfloats, all exactly representable.
+const POWERS_OF_TEN: [f64; VARFLOAT_MAX_EXPONENT as usize] = [1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8];
+
+/// How NaN values are handled when encoding/decoding floats.
+///
+/// The same policy is enforced by writers and readers, so a reader never
+/// returns a NaN that a writer with the same policy could not have written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NaNPolicy {
+    /// Replace all NaNs, whatever their payload, with the canonical quiet NaN.
+    #[default]
+    Canonicalize,
+
+    /// Preserve the payload of NaNs, including signaling NaNs.
+    ///
+    /// Writing the NaN whose representation is reserved for null is an error.
+    Preserve,
+
+    /// Writing or reading any NaN is an error.
+    Reject,
+}
+impl NaNPolicy {
+    /// The names of the policies, as used on the command-line.
+    pub const NAMES: &'static [&'static str] = &["canonicalize", "preserve", "reject"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "canonicalize" => Some(NaNPolicy::Canonicalize),
+            "preserve" => Some(NaNPolicy::Preserve),
+            "reject" => Some(NaNPolicy::Reject),
+            _ => None
+        }
+    }
+
+    /// The name of the policy, as used on the command-line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NaNPolicy::Canonicalize => "canonicalize",
+            NaNPolicy::Preserve => "preserve",
+            NaNPolicy::Reject => "reject",
+        }
+    }
+
+    /// Apply the policy to the representation of a float.
+    pub fn apply(&self, as_u64: u64) -> Result<u64, &'static str> {
+        if !f64::from_bits(as_u64).is_nan() {
+            return Ok(as_u64)
+        }
+        match *self {
+            NaNPolicy::Canonicalize => Ok(CANONICAL_NAN_REPR),
+            NaNPolicy::Preserve => Ok(as_u64),
+            NaNPolicy::Reject => Err("NaN rejected by policy"),
+        }
+    }
+}
+
+/// Map signed integers to unsigned integers, so that numbers with a small
+/// absolute value are mapped to small numbers: 0, -1, 1, -2, 2... are mapped
+/// to 0, 1, 2, 3, 4...
+pub fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+pub fn unzigzag(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+/// Decode a f64 | null, little-endian, handling NaNs as specified by `policy`.
+pub fn float_of_bytes_with_policy(buf: &[u8; 8], policy: NaNPolicy) -> Result<Option<f64>, Error> {
+    let as_u64 = buf.iter()
+        .rev()
+        .fold(0, |as_u64, byte| (as_u64 << 8) | *byte as u64);
+    if as_u64 == NONE_FLOAT_REPR {
+        return Ok(None)
+    }
+    let as_u64 = policy.apply(as_u64)
+        .map_err(Error::InvalidFloat)?;
+    Ok(Some(f64::from_bits(as_u64)))
+}
+
+/// Read a varfloat, see `binjs_io::bytes::float::WriteVarFloat` for the representation.
+///
+/// ```
+/// use binjs_core::Input;
+/// use binjs_core::float::{ read_maybe_varfloat, NaNPolicy, VARNUM_NULL };
+///
+/// // Integers.
+/// assert_eq!(read_maybe_varfloat(&mut Input::new(&[0]), NaNPolicy::Canonicalize), Ok(Some(0.)));
+/// assert_eq!(read_maybe_varfloat(&mut Input::new(&[0b0001_0100]), NaNPolicy::Canonicalize), Ok(Some(-3.)));
+///
+/// // 0.5 = 5 / 10^1.
+/// assert_eq!(read_maybe_varfloat(&mut Input::new(&[0b0100_0011, 0b0000_0010]), NaNPolicy::Canonicalize), Ok(Some(0.5)));
+///
+/// assert_eq!(read_maybe_varfloat(&mut Input::new(&VARNUM_NULL), NaNPolicy::Canonicalize), Ok(None));
+/// ```
+pub fn read_maybe_varfloat<S: ByteSource>(source: &mut S, policy: NaNPolicy) -> Result<Option<f64>, Error> {
+    // Buffer the varnum, as `read_varnum` rejects VARNUM_PREFIX_FLOAT and VARNUM_NULL.
+    let mut header = [0; MAX_HEADER_LEN];
+    let mut len = 0;
+    loop {
+        let byte = source.next_byte()?;
+        header[len] = byte;
+        len += 1;
+        if byte & 1 == 0 {
+            break;
+        }
+        if len == MAX_HEADER_LEN {
+            return Err(Error::InvalidFloat("header doesn't fit in 32 bits"));
+        }
+    }
+    let header = &header[..len];
+    if header == &VARNUM_NULL[..] {
+        return Ok(None);
+    }
+    if header == &VARNUM_PREFIX_FLOAT[..] {
+        let mut bytes : [u8; 8] = [0; 8];
+        source.read_bytes(&mut bytes)?;
+        return match float_of_bytes_with_policy(&bytes, policy)? {
+            None => Err(Error::InvalidFloat("null as a float")),
+            result => Ok(result)
+        };
+    }
+
+    let (value, _) = read_varnum(&mut Input::new(header))?;
+    if value & 1 == 0 {
+        return Ok(Some(unzigzag(value >> 1) as f64))
+    }
+    let exponent = ((value >> 1) & 7) as usize;
+    let mantissa = unzigzag(value >> 4);
+    Ok(Some(mantissa as f64 / POWERS_OF_TEN[exponent]))
+}
+
+#[test]
+fn test_zigzag() {
+    for value in &[0, 1, -1, 2, -2, 1 << 29, -(1 << 29)] {
+        assert_eq!(unzigzag(zigzag(*value)), *value);
+    }
+    assert_eq!(zigzag(-1), 1);
+    assert_eq!(zigzag(1), 2);
+}
@@ -0,0 +1,72 @@
+use Error;
+
+/// A source of bytes.
+///
+/// Implemented by `Input` for slices. Crates with `std` may implement it for their readers.
+pub trait ByteSource {
+    /// Read the next byte, failing with `Error::UnexpectedEnd` at the end of the data.
+    fn next_byte(&mut self) -> Result<u8, Error>;
+
+    /// Fill `buf`, failing with `Error::UnexpectedEnd` if there are not enough bytes.
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        for byte in buf.iter_mut() {
+            *byte = self.next_byte()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reading from a slice of bytes.
+///
+/// ```
+/// use binjs_core::{ ByteSource, Error, Input };
+///
+/// let mut input = Input::new(&[1, 2, 3]);
+/// assert_eq!(input.next_byte(), Ok(1));
+/// let mut buf = [0; 2];
+/// input.read_bytes(&mut buf).unwrap();
+/// assert_eq!(buf, [2, 3]);
+/// assert_eq!(input.position(), 3);
+/// assert_eq!(input.next_byte(), Err(Error::UnexpectedEnd));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Input<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+impl<'a> Input<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Input {
+            data,
+            position: 0,
+        }
+    }
+
+    /// The number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The bytes that have not been read yet.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+}
+impl<'a> ByteSource for Input<'a> {
+    fn next_byte(&mut self) -> Result<u8, Error> {
+        let byte = *self.data.get(self.position)
+            .ok_or(Error::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if self.data.len() - self.position < buf.len() {
+            return Err(Error::UnexpectedEnd);
+        }
+        let end = self.position + buf.len();
+        buf.copy_from_slice(&self.data[self.position..end]);
+        self.position = end;
+        Ok(())
+    }
+}
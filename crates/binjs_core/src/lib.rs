@@ -0,0 +1,32 @@
+//! The pure decoding logic of BinJS, without `std`.
+//!
+//! This crate depends only on `core` and `alloc`, so that decoders may be embedded in
+//! constrained environments, e.g. the test harness of a JS engine. It contains:
+//!
+//! - reading varnums and varfloats from bytes, see modules `varnum` and `float`;
+//! - the decoders of the entropy formats, see modules `range` and `rans`.
+//!
+//! Crate `binjs_io` builds the encoders and the container formats on top of this crate.
+
+#![no_std]
+
+extern crate alloc;
+
+mod error;
+pub use error::Error;
+
+/// Sources of bytes.
+pub mod input;
+pub use input::{ ByteSource, Input };
+
+/// Variable-length unsigned integers.
+pub mod varnum;
+
+/// Floats and variable-length floats.
+pub mod float;
+
+/// Decoding symbols coded with the range coder of Opus.
+pub mod range;
+
+/// Decoding symbols coded with rANS.
+pub mod rans;
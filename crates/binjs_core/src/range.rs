@@ -0,0 +1,143 @@
+//! The range decoder of Opus, i.e. `ec_dec` in `celt/entdec.c`, with 8 bits symbols and
+//! a 32 bits state.
+//!
+//! The encoder is `range_encoding::opus::Writer`, which is a port of `ec_enc`. As in Opus,
+//! bytes past the end of the data are read as 0, since the encoder does not write the
+//! trailing bits that are not needed to identify the final interval.
+
+use { ByteSource, Error };
+use rans::Distribution;
+
+/// The number of bits output at a time.
+const SYM_BITS : u32 = 8;
+
+/// The maximal value of a symbol.
+const SYM_MAX : u32 = (1 << SYM_BITS) - 1;
+
+/// The number of bits of the state.
+const CODE_BITS : u32 = 32;
+
+/// The bits of the state that are not output in full symbols.
+const CODE_EXTRA : u32 = (CODE_BITS - 2) % SYM_BITS + 1;
+
+const CODE_TOP : u32 = 1 << (CODE_BITS - 1);
+
+/// The lower bound of the normalized range `(CODE_BOT, CODE_TOP]`.
+const CODE_BOT : u32 = CODE_TOP >> SYM_BITS;
+
+/// Read the next byte, or 0 past the end of the data.
+fn read_byte<S: ByteSource + ?Sized>(source: &mut S) -> Result<u32, Error> {
+    match source.next_byte() {
+        Ok(byte) => Ok(byte as u32),
+        Err(Error::UnexpectedEnd) => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+/// A range decoder.
+///
+/// The decoder does not own its source, so that it may be used with any `ByteSource`,
+/// as long as the same bytes are passed to each call.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    /// The size of the current range.
+    range: u32,
+
+    /// The difference between the top of the current range and the code value, minus 1.
+    value: u32,
+
+    /// The last byte read, whose low bits have not been used yet.
+    remainder: u32,
+}
+impl Decoder {
+    /// Start decoding, reading the first bytes.
+    pub fn new<S: ByteSource + ?Sized>(source: &mut S) -> Result<Self, Error> {
+        let remainder = read_byte(source)?;
+        let mut decoder = Decoder {
+            range: 1 << CODE_EXTRA,
+            value: (1 << CODE_EXTRA) - 1 - (remainder >> (SYM_BITS - CODE_EXTRA)),
+            remainder,
+        };
+        decoder.normalize(source)?;
+        Ok(decoder)
+    }
+
+    /// Read bytes until the range is larger than `CODE_BOT`.
+    fn normalize<S: ByteSource + ?Sized>(&mut self, source: &mut S) -> Result<(), Error> {
+        while self.range <= CODE_BOT {
+            self.range <<= SYM_BITS;
+            let previous = self.remainder;
+            self.remainder = read_byte(source)?;
+            let symbol = ((previous << SYM_BITS) | self.remainder) >> (SYM_BITS - CODE_EXTRA);
+            self.value = ((self.value << SYM_BITS) + (SYM_MAX & !symbol)) & (CODE_TOP - 1);
+        }
+        Ok(())
+    }
+
+    /// Read a symbol, return its index in `distribution`.
+    pub fn symbol<S: ByteSource + ?Sized, D: Distribution + ?Sized>(&mut self, source: &mut S, distribution: &D) -> Result<u32, Error> {
+        let width = distribution.width();
+        if width == 0 {
+            return Err(Error::InvalidState("empty distribution"));
+        }
+        let scale = self.range / width;
+        if scale == 0 {
+            return Err(Error::InvalidState("distribution too wide for the range coder"));
+        }
+        let position = self.value / scale + 1;
+        let frequency = width - if position < width { position } else { width };
+        let index = distribution.find(frequency)
+            .ok_or(Error::InvalidState("invalid range state"))?;
+        let (low, next) = distribution.segment(index)
+            .ok_or(Error::InvalidState("invalid symbol index"))?;
+
+        let above = scale * (width - next);
+        self.value -= above;
+        self.range =
+            if low > 0 {
+                scale * (next - low)
+            } else {
+                self.range - above
+            };
+        self.normalize(source)?;
+        Ok(index as u32)
+    }
+}
+
+#[test]
+fn test_decode_single_symbol() {
+    use Input;
+    use rans::Frequencies;
+
+    // With a single symbol, decoding does not consume any data past the first bytes.
+    let frequencies = Frequencies::new(&[4]);
+    let data = [];
+    let mut input = Input::new(&data);
+    let mut decoder = Decoder::new(&mut input)
+        .expect("Could not read initial state");
+    for _ in 0..10 {
+        assert_eq!(decoder.symbol(&mut input, &frequencies), Ok(0));
+    }
+
+    // Symbols without instances cannot be decoded.
+    let frequencies = Frequencies::new(&[0, 4, 0]);
+    assert_eq!(decoder.symbol(&mut input, &frequencies), Ok(1));
+    assert!(decoder.symbol(&mut input, &Frequencies::new(&[])).is_err());
+}
+
+#[test]
+fn test_decode() {
+    use Input;
+    use rans::Frequencies;
+
+    // Symbols encoded with `ec_enc`.
+    let distributions = [Frequencies::new(&[1, 1]), Frequencies::new(&[100, 1, 20, 3])];
+    let symbols = [(0, 1), (1, 0), (1, 2), (0, 0), (1, 3), (1, 1), (0, 1), (1, 0), (1, 0), (1, 2)];
+    let data = [220, 93, 180];
+    let mut input = Input::new(&data);
+    let mut decoder = Decoder::new(&mut input)
+        .expect("Could not read initial state");
+    for &(distribution, index) in &symbols {
+        assert_eq!(decoder.symbol(&mut input, &distributions[distribution]), Ok(index));
+    }
+}
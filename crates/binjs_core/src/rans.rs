@@ -0,0 +1,188 @@
+//! rANS, with a 64 bits state and 32 bits words.
+//!
+//! rANS requires the total frequency to be a power of two. As distributions may
+//! have any width, each segment `[low, next)` of a distribution of width `width`
+//! is scaled to `[low * 2^SCALE_BITS / width, next * 2^SCALE_BITS / width)`.
+//! With `SCALE_BITS = 31`, no segment becomes empty as long as `width <= 2^31`.
+//!
+//! The encoder is `binjs_io::entropy::coder::rans::Writer`.
+
+use { ByteSource, Error };
+
+use alloc::vec::Vec;
+
+pub const SCALE_BITS : u32 = 31;
+
+/// The lower bound of the normalized state interval `[LOWER_BOUND, LOWER_BOUND << 32)`.
+pub const LOWER_BOUND : u64 = 1 << 31;
+
+/// A distribution of frequencies among symbols `0..n`.
+pub trait Distribution {
+    /// The sum of all frequencies.
+    fn width(&self) -> u32;
+
+    /// The symbol whose segment `[low, next)` contains `frequency`.
+    fn find(&self, frequency: u32) -> Option<usize>;
+
+    /// The segment `[low, next)` of symbol `index`.
+    fn segment(&self, index: usize) -> Option<(u32, u32)>;
+}
+
+/// A `Distribution` given by the number of instances of each symbol.
+///
+/// ```
+/// use binjs_core::rans::{ Distribution, Frequencies };
+///
+/// let frequencies = Frequencies::new(&[3, 0, 5]);
+/// assert_eq!(frequencies.width(), 8);
+/// assert_eq!(frequencies.segment(2), Some((3, 8)));
+/// assert_eq!(frequencies.find(2), Some(0));
+/// assert_eq!(frequencies.find(3), Some(2));
+/// assert_eq!(frequencies.find(8), None);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Frequencies {
+    /// `cumulative[i]` is the sum of the instances of symbols `0..i`.
+    cumulative: Vec<u32>,
+}
+impl Frequencies {
+    pub fn new(instances: &[u32]) -> Self {
+        let mut cumulative = Vec::with_capacity(instances.len() + 1);
+        let mut total = 0;
+        cumulative.push(total);
+        for instances in instances {
+            total += *instances;
+            cumulative.push(total);
+        }
+        Frequencies {
+            cumulative,
+        }
+    }
+}
+impl Distribution for Frequencies {
+    fn width(&self) -> u32 {
+        self.cumulative[self.cumulative.len() - 1]
+    }
+
+    fn find(&self, frequency: u32) -> Option<usize> {
+        if frequency >= self.width() {
+            return None;
+        }
+        // The last symbol whose segment starts at or before `frequency`.
+        match self.cumulative.binary_search(&frequency) {
+            Ok(mut index) => {
+                // Skip symbols with no instances.
+                while self.cumulative[index + 1] == frequency {
+                    index += 1;
+                }
+                Some(index)
+            }
+            Err(index) => Some(index - 1)
+        }
+    }
+
+    fn segment(&self, index: usize) -> Option<(u32, u32)> {
+        if index + 1 >= self.cumulative.len() {
+            return None;
+        }
+        Some((self.cumulative[index], self.cumulative[index + 1]))
+    }
+}
+
+/// Scale a cumulative frequency of a distribution of width `width`.
+pub fn scale(frequency: u32, width: u32) -> u64 {
+    ((frequency as u64) << SCALE_BITS) / width as u64
+}
+
+/// The scaled `(start, frequency)` of symbol `index`.
+pub fn scaled_segment<D: Distribution + ?Sized>(index: usize, distribution: &D) -> Result<(u64, u64), Error> {
+    let width = distribution.width();
+    if width > 1 << SCALE_BITS {
+        return Err(Error::InvalidState("distribution too wide for rANS"));
+    }
+    let (low, next) = distribution.segment(index)
+        .ok_or(Error::InvalidState("invalid symbol index"))?;
+    let start = scale(low, width);
+    let next = scale(next, width);
+    Ok((start, next - start))
+}
+
+/// Read a little-endian 32 bits word.
+pub fn read_word<S: ByteSource + ?Sized>(source: &mut S) -> Result<u32, Error> {
+    let mut buf = [0; 4];
+    source.read_bytes(&mut buf)?;
+    Ok(buf.iter()
+        .enumerate()
+        .fold(0, |word, (i, byte)| word | (*byte as u32) << (8 * i)))
+}
+
+/// An rANS decoder.
+///
+/// The decoder does not own its source, so that it may be used with any `ByteSource`,
+/// as long as the same bytes are passed to each call.
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    state: u64,
+}
+impl Decoder {
+    /// Start decoding, reading the initial state.
+    pub fn new<S: ByteSource + ?Sized>(source: &mut S) -> Result<Self, Error> {
+        let low = read_word(source)? as u64;
+        let high = read_word(source)? as u64;
+        Ok(Decoder {
+            state: (high << 32) | low,
+        })
+    }
+
+    /// Read a symbol, return its index in `distribution`.
+    pub fn symbol<S: ByteSource + ?Sized, D: Distribution + ?Sized>(&mut self, source: &mut S, distribution: &D) -> Result<u32, Error> {
+        let width = distribution.width();
+        let slot = self.state & ((1 << SCALE_BITS) - 1);
+
+        // Scaling is monotonic, so the symbol containing `slot` is the one containing
+        // the corresponding unscaled frequency, or one of its neighbours.
+        let frequency = ((slot * width as u64) >> SCALE_BITS) as u32;
+        let mut index = distribution.find(frequency)
+            .ok_or(Error::InvalidState("invalid rANS state"))?;
+        let (start, frequency) = loop {
+            let (start, frequency) = scaled_segment(index, distribution)?;
+            if slot < start {
+                if index == 0 {
+                    return Err(Error::InvalidState("invalid rANS state"));
+                }
+                index -= 1;
+            } else if slot >= start + frequency {
+                index += 1;
+            } else {
+                break (start, frequency);
+            }
+        };
+
+        self.state = frequency * (self.state >> SCALE_BITS) + slot - start;
+        while self.state < LOWER_BOUND {
+            self.state = (self.state << 32) | read_word(source)? as u64;
+        }
+        Ok(index as u32)
+    }
+}
+
+#[test]
+fn test_decode_single_symbol() {
+    use Input;
+
+    // With a single symbol, decoding does not consume the state.
+    let frequencies = Frequencies::new(&[4]);
+    let data = [0, 0, 0, 0x80, 0, 0, 0, 0];
+    let mut input = Input::new(&data);
+    let mut decoder = Decoder::new(&mut input)
+        .expect("Could not read initial state");
+    for _ in 0..10 {
+        assert_eq!(decoder.symbol(&mut input, &frequencies), Ok(0));
+    }
+    assert_eq!(input.position(), 8);
+
+    // Symbols without instances cannot be decoded.
+    let frequencies = Frequencies::new(&[0, 4, 0]);
+    assert_eq!(decoder.symbol(&mut input, &frequencies), Ok(1));
+    assert_eq!(Decoder::new(&mut Input::new(&data[..6])).err(), Some(Error::UnexpectedEnd));
+}
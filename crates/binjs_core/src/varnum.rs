@@ -0,0 +1,65 @@
+use { ByteSource, Error };
+
+/// The encodings of 0 with more than one byte, which are not valid varnums, hence
+/// may be used as magic constants.
+pub const VARNUM_INVALID_ZERO_1: [u8; 2] = [1, 0];
+pub const VARNUM_INVALID_ZERO_2: [u8; 3] = [1, 1, 0];
+pub const VARNUM_INVALID_ZERO_3: [u8; 4] = [1, 1, 1, 0];
+pub const VARNUM_INVALID_ZERO_4: [u8; 5] = [1, 1, 1, 1, 0];
+pub const VARNUM_INVALID_ZERO_5: [u8; 6] = [1, 1, 1, 1, 1, 0];
+pub const VARNUM_INVALID_ZERO_6: [u8; 7] = [1, 1, 1, 1, 1, 1, 0];
+
+/// Read a varnum, returning its value and its length in bytes.
+///
+/// Each byte holds 7 bits of the value, least significant first, in its 7 high
+/// bits. The lowest bit is set if more bytes follow.
+///
+/// ```
+/// use binjs_core::Input;
+/// use binjs_core::varnum::read_varnum;
+///
+/// assert_eq!(read_varnum(&mut Input::new(&[0])), Ok((0, 1)));
+/// assert_eq!(read_varnum(&mut Input::new(&[0b0000_0011, 0b0000_0010])), Ok((129, 2)));
+///
+/// // Encodings of 0 with more than one byte are reserved as magic constants.
+/// assert!(read_varnum(&mut Input::new(&[1, 0])).is_err());
+///
+/// // Truncated varnums are rejected.
+/// assert!(read_varnum(&mut Input::new(&[1])).is_err());
+/// ```
+pub fn read_varnum<S: ByteSource>(source: &mut S) -> Result<(u32, usize), Error> {
+    let mut bytes = 0;
+    let mut result : u32 = 0;
+    let mut shift : u32 = 0;
+    loop {
+        if shift >= 32 {
+            return Err(Error::InvalidVarNum("doesn't fit in 32 bits"));
+        }
+        let byte = source.next_byte()?;
+        bytes += 1;
+
+        result |= (byte as u32 >> 1) << shift;
+        if byte & 1 == 0 {
+            if result == 0 && shift != 0 {
+                return Err(Error::InvalidVarNum("invalid 0"));
+            }
+            return Ok((result, bytes));
+        }
+        shift += 7;
+    }
+}
+
+#[test]
+fn test_read_varnum() {
+    use Input;
+
+    // 2^32 - 1 fits in 5 bytes, a sixth byte does not fit.
+    let max = [0xFF, 0xFF, 0xFF, 0xFF, 0x1E];
+    assert_eq!(read_varnum(&mut Input::new(&max)), Ok((u32::MAX, 5)));
+    assert!(read_varnum(&mut Input::new(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0])).is_err());
+
+    for magic in &[&VARNUM_INVALID_ZERO_1[..], &VARNUM_INVALID_ZERO_2[..], &VARNUM_INVALID_ZERO_3[..]] {
+        assert_eq!(read_varnum(&mut Input::new(magic)), Err(Error::InvalidVarNum("invalid 0")));
+    }
+    assert_eq!(read_varnum(&mut Input::new(&[])), Err(Error::UnexpectedEnd));
+}
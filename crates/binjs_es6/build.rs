@@ -12,8 +12,14 @@ use std::io::*;
 
 const PATH_GRAMMAR_ES6 : &'static str = "../../spec/es6.webidl";
 
+/// A list of webidl files extending the grammar with vendor-specific
+/// interfaces and fields, separated as in `PATH`. See
+/// `binjs_meta::import::Importer::import_extended`.
+const ENV_GRAMMAR_EXTENSIONS : &'static str = "BINJS_GRAMMAR_EXTENSIONS";
+
 fn main() {
     println!("cargo:rerun-if-changed={}", PATH_GRAMMAR_ES6);
+    println!("cargo:rerun-if-env-changed={}", ENV_GRAMMAR_EXTENSIONS);
 
     // Load webidl.
 
@@ -26,11 +32,28 @@ fn main() {
     let ast = webidl::parse_string(&source)
         .expect("Could not parse source");
 
+    // Load grammar extensions, if any.
+
+    let extension_paths : Vec<_> = env::var_os(ENV_GRAMMAR_EXTENSIONS)
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    let extensions : Vec<_> = extension_paths.iter()
+        .map(|path| {
+            println!("cargo:rerun-if-changed={}", path.display());
+            let mut source = String::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut source))
+                .unwrap_or_else(|e| panic!("Could not read grammar extension {}: {}", path.display(), e));
+            webidl::parse_string(&source)
+                .unwrap_or_else(|e| panic!("Could not parse grammar extension {}: {:?}", path.display(), e))
+        })
+        .collect();
+
     // Check spec. We don't really need fake_root
     // for this operation. It may change in the future,
     // we'll see then.
 
-    let mut builder = Importer::import(&ast);
+    let mut builder = Importer::import_extended(&ast, &extensions);
     let fake_root = builder.node_name("@@ROOT@@"); // Ignored.
     let null = builder.node_name("");      // Actually used
     builder.add_interface(&null)
@@ -40,7 +63,11 @@ fn main() {
             null: &null,
         });
 
+    // Identify the grammar, so that files encoded with another grammar are rejected.
+    let fingerprint = spec.fingerprint();
+
     // Generate source code.
+    let arena_code = ArenaExporter::new(&spec).to_rust_source();
     let exporter = RustExporter::new(spec);
     let code = exporter.to_rust_source();
 
@@ -52,6 +79,38 @@ fn main() {
         .expect("Could not create rust strongly-typed source output");
     dest.write_all(code.typed.as_bytes())
         .expect("Could not write rust strongly-typed source output");
+    // Name the grammar after its extensions, e.g. `es6+instrumentation`.
+    let name = Some("es6".to_string()).into_iter()
+        .chain(extension_paths.iter()
+            .map(|path| path.file_stem()
+                .expect("Grammar extension has no file name")
+                .to_string_lossy()
+                .into_owned()))
+        .collect::<Vec<_>>()
+        .join("+");
+    write!(dest, "
+/// The name of the grammar, as written to files.
+pub const GRAMMAR_NAME : &'static str = \"{name}\";
+
+/// The fingerprint of the grammar, as computed by `binjs_meta::spec::Spec::fingerprint`.
+pub const GRAMMAR_FINGERPRINT : u64 = 0x{fingerprint:016x};
+
+/// If `true`, the grammar was composed with vendor extensions at build time,
+/// from the webidl files listed in `{env}`.
+pub const GRAMMAR_EXTENDED : bool = {extended};
+",
+        name = name,
+        fingerprint = fingerprint,
+        env = ENV_GRAMMAR_EXTENSIONS,
+        extended = !extensions.is_empty())
+        .expect("Could not write grammar identifier");
+
+    // Export arena-allocated source, used with feature `arena`.
+    let dest_name = format!("{}/arena.rs", dest_dir);
+    let mut dest = File::create(dest_name)
+        .expect("Could not create rust arena-allocated source output");
+    dest.write_all(arena_code.as_bytes())
+        .expect("Could not write rust arena-allocated source output");
 
     println!("...done");
 }
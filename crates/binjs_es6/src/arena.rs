@@ -0,0 +1,177 @@
+//! A variant of the strongly-typed AST whose nodes, lists and strings are borrowed
+//! from a `bumpalo` arena for lifetime `'a`.
+//!
+//! Decoding to module `ast` allocates each node, list and string separately, while
+//! decoding to this module, with `io::Decoder::decode_in`, allocates them in a few
+//! large blocks, which are released at once when the arena is dropped. As the arena
+//! does not run destructors, nodes only hold references, primitive values and the
+//! string enums of `ast`. Use `ToAST::to_ast` to convert a node to module `ast`,
+//! e.g. to walk it or to encode it.
+//!
+//! The types are generated by `binjs_generate_library::ArenaExporter`.
+
+use binjs_shared;
+
+use bumpalo;
+pub use bumpalo::Bump;
+
+include!(concat!(env!("OUT_DIR"), "/arena.rs"));
+
+/// A structure used for deserialization purposes, allocating nodes in `arena`.
+pub struct Deserializer<'a, R> where R: TokenReader {
+    pub reader: R,
+    arena: &'a Bump,
+}
+impl<'a, R> Deserializer<'a, R> where R: TokenReader {
+    pub fn new(reader: R, arena: &'a Bump) -> Self {
+        Self {
+            reader,
+            arena,
+        }
+    }
+}
+
+/// A node that may be deserialized into an arena from any `TokenReader`.
+///
+/// Implemented by all the nodes of the AST, so that `io::Decoder::decode_in` may pick
+/// the `TokenReader` from the format of the file.
+pub trait Decodable<'a>: Sized {
+    fn deserialize_from<R: TokenReader>(deserializer: &mut Deserializer<'a, R>, path: &mut IOPath) -> Result<Self, TokenReaderError>;
+}
+
+/// Conversion of a node to the matching node of module `ast`.
+pub trait ToAST {
+    type Target;
+    fn to_ast(&self) -> Self::Target;
+}
+
+/// Implement a string borrowed from the arena, read with `TokenReader::$read` and
+/// converted to `$shared`.
+macro_rules! arena_string {
+    ($name:ident, $shared:ty, $read:ident, $empty:ident) => {
+        /// A string borrowed from the arena.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub struct $name<'a>(pub &'a str);
+        impl<'a> $name<'a> {
+            pub fn as_str(&self) -> &'a str {
+                self.0
+            }
+        }
+        impl<'a> ToAST for $name<'a> {
+            type Target = $shared;
+            fn to_ast(&self) -> $shared {
+                <$shared>::from_string(self.0.to_string())
+            }
+        }
+        impl<'a, R> Deserialization<R, Option<$name<'a>>> for Deserializer<'a, R> where R: TokenReader {
+            fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<$name<'a>>, TokenReaderError> {
+                let arena = self.arena;
+                let value = self.reader.$read(path)?
+                    .map(|value| $name(arena.alloc_str(value.as_str())));
+                Ok(value)
+            }
+        }
+        impl<'a, R> Deserialization<R, $name<'a>> for Deserializer<'a, R> where R: TokenReader {
+            fn deserialize(&mut self, path: &mut IOPath) -> Result<$name<'a>, TokenReaderError> {
+                let maybe : Option<$name<'a>> = self.deserialize(path)?;
+                maybe.ok_or_else(|| From::from(TokenReaderError::$empty))
+            }
+        }
+    }
+}
+
+arena_string!(SharedString, binjs_shared::SharedString, string_at, EmptyString);
+arena_string!(IdentifierName, binjs_shared::IdentifierName, identifier_name_at, EmptyString);
+arena_string!(PropertyKey, binjs_shared::PropertyKey, property_key_at, EmptyString);
+arena_string!(BigInt, binjs_shared::BigInt, big_int_at, EmptyBigInt);
+arena_string!(RegExpPattern, binjs_shared::RegExpPattern, reg_exp_pattern_at, EmptyString);
+arena_string!(RegExpFlags, binjs_shared::RegExpFlags, reg_exp_flags_at, EmptyString);
+
+impl<'a, R> Deserialization<R, Option<bool>> for Deserializer<'a, R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<bool>, TokenReaderError> {
+        self.reader.bool_at(path)
+    }
+}
+impl<'a, R> Deserialization<R, bool> for Deserializer<'a, R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<bool, TokenReaderError> {
+        self.reader.bool_at(path)?
+            .ok_or_else(|| From::from(TokenReaderError::EmptyBool))
+    }
+}
+impl<'a, R> Deserialization<R, Option<f64>> for Deserializer<'a, R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<f64>, TokenReaderError> {
+        self.reader.float_at(path)
+    }
+}
+impl<'a, R> Deserialization<R, f64> for Deserializer<'a, R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<f64, TokenReaderError> {
+        self.reader.float_at(path)?
+            .ok_or_else(|| From::from(TokenReaderError::EmptyBool))
+    }
+}
+impl<'a, R> Deserialization<R, u32> for Deserializer<'a, R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<u32, TokenReaderError> {
+        self.reader.unsigned_long_at(path)
+    }
+}
+impl<'a, R> Deserialization<R, Offset> for Deserializer<'a, R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Offset, TokenReaderError> {
+        Ok(Offset(self.reader.offset_at(path)?))
+    }
+}
+impl<'a, R, T> Deserialization<R, &'a [T]> for Deserializer<'a, R> where R: TokenReader, Self: Deserialization<R, T> {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<&'a [T], TokenReaderError> {
+        let len = self.reader.enter_list_at(path)?;
+        let mut result = bumpalo::collections::Vec::with_capacity_in(len as usize, self.arena);
+        for _ in 0..len {
+            result.push(self.deserialize(path)?);
+        }
+        self.reader.exit_list_at(path)?;
+        Ok(result.into_bump_slice())
+    }
+}
+
+impl ToAST for bool {
+    type Target = bool;
+    fn to_ast(&self) -> bool {
+        *self
+    }
+}
+impl ToAST for f64 {
+    type Target = f64;
+    fn to_ast(&self) -> f64 {
+        *self
+    }
+}
+impl ToAST for u32 {
+    type Target = u32;
+    fn to_ast(&self) -> u32 {
+        *self
+    }
+}
+impl ToAST for () {
+    type Target = ();
+    fn to_ast(&self) {
+    }
+}
+impl ToAST for Offset {
+    type Target = Offset;
+    fn to_ast(&self) -> Offset {
+        self.clone()
+    }
+}
+impl<T> ToAST for Option<T> where T: ToAST {
+    type Target = Option<T::Target>;
+    fn to_ast(&self) -> Option<T::Target> {
+        self.as_ref()
+            .map(ToAST::to_ast)
+    }
+}
+impl<'a, T> ToAST for &'a [T] where T: ToAST {
+    type Target = Vec<T::Target>;
+    fn to_ast(&self) -> Vec<T::Target> {
+        self.iter()
+            .map(ToAST::to_ast)
+            .collect()
+    }
+}
@@ -0,0 +1,49 @@
+//! Encoding to `AsyncWrite` and decoding from `AsyncRead`, for use with tokio.
+//!
+//! Encoding and decoding themselves remain synchronous and run on the task that
+//! polls the future, but waiting for data never blocks the thread, so services
+//! do not need to dedicate a blocking thread to each file.
+//!
+//! As all formats need the complete file, the source is read entirely before
+//! decoding starts and the destination is only written once encoding is complete.
+
+use ast::Program;
+use io::{ Decoder, Encoder };
+
+use binjs_io::{ self, TokenReaderError, TokenWriterError };
+
+use std::io::Cursor;
+
+use futures::future::{ self, Future };
+use tokio_io::{ AsyncRead, AsyncWrite };
+use tokio_io::io::{ read_to_end, write_all };
+
+/// Read a file from `source`, then decode it.
+///
+/// Resolves to the source, for reuse, and the program.
+pub fn decode<'a, R>(format: &'a mut binjs_io::Format, source: R) -> impl Future<Item = (R, Program), Error = binjs_io::Error> + 'a
+    where R: AsyncRead + 'a
+{
+    read_to_end(source, Vec::new())
+        .map_err(|err| binjs_io::Error::from(TokenReaderError::ReadError(err)))
+        .and_then(move |(source, data)| {
+            let program = Decoder::new()
+                .decode(format, Cursor::new(data))?;
+            Ok((source, program))
+        })
+}
+
+/// Encode a program, then write it to `dest`.
+///
+/// Resolves to the destination, for reuse.
+pub fn encode<'a, W>(format: &'a mut binjs_io::Format, program: &'a Program, dest: W) -> impl Future<Item = W, Error = binjs_io::Error> + 'a
+    where W: AsyncWrite + 'a
+{
+    future::lazy(move || {
+        let data = Encoder::new()
+            .encode(format, program)?;
+        Ok((*data).as_ref().to_vec())
+    }).and_then(|data| write_all(dest, data)
+        .map(|(dest, _)| dest)
+        .map_err(|err| binjs_io::Error::from(TokenWriterError::WriteError(err))))
+}
@@ -1,8 +1,20 @@
-use binjs_io::{ self, Deserialization, TokenReader, TokenReaderError, TokenWriterTreeAdapter, TokenWriterError };
+use binjs_io::{ self, Deserialization, GrammarId, ReaderVisitor, TokenReader, TokenReaderError, TokenWriterTreeAdapter, TokenWriterError };
+use binjs_io::events::{ EventHandler, IgnoreEvents, TokenReaderEventAdapter };
+use binjs_io::positions::SourcePositions;
+use binjs_io::startup::StartupProfile;
+#[cfg(feature = "profiling")]
+use binjs_io::profile::{ Profile, TokenReaderProfiler };
+use binjs_io::progress::{ NoProgress, Phase, ProgressSink, TokenWriterProgressAdapter };
 pub use binjs_io::{ Serialization, TokenSerializer, TokenWriter };
-use binjs_shared::{ FieldName, IdentifierName, InterfaceName, Offset, PropertyKey, SharedString, self };
+use binjs_shared::{ BigInt, FieldName, IdentifierName, InterfaceName, Offset, PropertyKey, RegExpFlags, RegExpPattern, SharedString, self };
+
+use ast::Walker;
+use scope_checks::ScopeCheckPolicy;
 
 use std::io::{ Read, Seek };
+use std::marker::PhantomData;
+
+use tracing;
 
 /// A path used when (de)serializing ES6 ASTs.
 pub type IOPath = binjs_shared::ast::Path<InterfaceName, (/* child index */ usize, /* field name */ FieldName)>;
@@ -19,6 +31,14 @@ impl<R> Deserializer<R> where R: TokenReader {
     }
 }
 
+/// A node that may be deserialized from any `TokenReader`.
+///
+/// Implemented by all the nodes of the AST, so that `Decoder` may pick the
+/// `TokenReader` from the format of the file.
+pub trait Decodable: Sized {
+    fn deserialize_from<R: TokenReader>(deserializer: &mut Deserializer<R>, path: &mut IOPath) -> Result<Self, TokenReaderError>;
+}
+
 
 impl<R> Deserialization<R, Option<bool>> for Deserializer<R> where R: TokenReader {
     fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<bool>, TokenReaderError> {
@@ -95,6 +115,39 @@ impl<R> Deserialization<R, Option<PropertyKey>> for Deserializer<R> where R: Tok
         self.reader.property_key_at(path)
     }
 }
+impl<R> Deserialization<R, BigInt> for Deserializer<R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<BigInt, TokenReaderError> {
+        self.reader.big_int_at(path)?
+            .ok_or_else(|| From::from(TokenReaderError::EmptyBigInt))
+    }
+}
+impl<R> Deserialization<R, Option<BigInt>> for Deserializer<R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<BigInt>, TokenReaderError> {
+        self.reader.big_int_at(path)
+    }
+}
+impl<R> Deserialization<R, RegExpPattern> for Deserializer<R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<RegExpPattern, TokenReaderError> {
+        self.reader.reg_exp_pattern_at(path)?
+            .ok_or_else(|| From::from(TokenReaderError::EmptyString))
+    }
+}
+impl<R> Deserialization<R, Option<RegExpPattern>> for Deserializer<R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<RegExpPattern>, TokenReaderError> {
+        self.reader.reg_exp_pattern_at(path)
+    }
+}
+impl<R> Deserialization<R, RegExpFlags> for Deserializer<R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<RegExpFlags, TokenReaderError> {
+        self.reader.reg_exp_flags_at(path)?
+            .ok_or_else(|| From::from(TokenReaderError::EmptyString))
+    }
+}
+impl<R> Deserialization<R, Option<RegExpFlags>> for Deserializer<R> where R: TokenReader {
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        self.reader.reg_exp_flags_at(path)
+    }
+}
 
 
 impl<R, T> Deserialization<R, Vec<T>> for Deserializer<R> where R: TokenReader, Self: Deserialization<R, T> {
@@ -123,14 +176,38 @@ impl<R, T> Deserialization<R, Vec<T>> for Deserializer<R> where R: TokenReader,
 ///
 /// Use `Serializer.deserialize` to read a structure from a token self.writer.
 pub struct Serializer<W> where W: TokenWriter {
-    pub writer: W
+    pub writer: W,
+    check_identifiers: bool,
 }
 impl<W> Serializer<W> where W: TokenWriter {
     pub fn new(writer: W) -> Self {
         Self {
-            writer
+            writer,
+            check_identifiers: false,
+        }
+    }
+
+    /// If `true`, fail to serialize identifier names and property keys that are not
+    /// valid ECMAScript IdentifierNames, see `IdentifierName::is_valid` and
+    /// `PropertyKey::is_valid`.
+    pub fn with_identifier_checks(self, check_identifiers: bool) -> Self {
+        Self {
+            check_identifiers,
+            ..self
         }
     }
+    fn check_identifier(&self, value: &IdentifierName, path: &IOPath) -> Result<(), TokenWriterError> {
+        self.check_name(value.as_str(), value.is_valid(), path)
+    }
+    fn check_property_key(&self, value: &PropertyKey, path: &IOPath) -> Result<(), TokenWriterError> {
+        self.check_name(value.as_str(), value.is_valid(), path)
+    }
+    fn check_name(&self, name: &str, is_valid: bool, path: &IOPath) -> Result<(), TokenWriterError> {
+        if self.check_identifiers && !is_valid {
+            return Err(TokenWriterError::InvalidIdentifierName(format!("{:?} at {:?}", name, path)));
+        }
+        Ok(())
+    }
     pub fn serialize<T>(&mut self, value: T, path: &mut IOPath) -> Result<(), TokenWriterError> where Self: Serialization<W, T> {
         (self as &mut Serialization<W, T>).serialize(value, path)
     }
@@ -216,90 +293,486 @@ impl<'a, W> Serialization<W, &'a Option<SharedString>> for Serializer<W> where W
 }
 impl<'a, W> Serialization<W, &'a IdentifierName> for Serializer<W> where W: TokenWriter {
     fn serialize(&mut self, value: &'a IdentifierName, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.check_identifier(value, path)?;
         self.writer.identifier_name_at(Some(&value), path)
     }
 }
 impl<'a, W> Serialization<W, &'a PropertyKey> for Serializer<W> where W: TokenWriter {
     fn serialize(&mut self, value: &'a PropertyKey, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.check_property_key(value, path)?;
         self.writer.property_key_at(Some(&value), path)
     }
 }
 impl<'a, W> Serialization<W, &'a Option<IdentifierName>> for Serializer<W> where W: TokenWriter {
     fn serialize(&mut self, value: &'a Option<IdentifierName>, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        if let Some(ref value) = *value {
+            self.check_identifier(value, path)?;
+        }
         self.writer.identifier_name_at(value.as_ref(), path)
     }
 }
 impl<'a, W> Serialization<W, &'a Option<PropertyKey>> for Serializer<W> where W: TokenWriter {
     fn serialize(&mut self, value: &'a Option<PropertyKey>, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        if let Some(ref value) = *value {
+            self.check_property_key(value, path)?;
+        }
         self.writer.property_key_at(value.as_ref(), path)
     }
 }
+impl<'a, W> Serialization<W, &'a BigInt> for Serializer<W> where W: TokenWriter {
+    fn serialize(&mut self, value: &'a BigInt, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.writer.big_int_at(Some(value), path)
+    }
+}
+impl<'a, W> Serialization<W, &'a Option<BigInt>> for Serializer<W> where W: TokenWriter {
+    fn serialize(&mut self, value: &'a Option<BigInt>, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.writer.big_int_at(value.as_ref(), path)
+    }
+}
+impl<'a, W> Serialization<W, &'a RegExpPattern> for Serializer<W> where W: TokenWriter {
+    fn serialize(&mut self, value: &'a RegExpPattern, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.writer.reg_exp_pattern_at(Some(value), path)
+    }
+}
+impl<'a, W> Serialization<W, &'a Option<RegExpPattern>> for Serializer<W> where W: TokenWriter {
+    fn serialize(&mut self, value: &'a Option<RegExpPattern>, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.writer.reg_exp_pattern_at(value.as_ref(), path)
+    }
+}
+impl<'a, W> Serialization<W, &'a RegExpFlags> for Serializer<W> where W: TokenWriter {
+    fn serialize(&mut self, value: &'a RegExpFlags, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.writer.reg_exp_flags_at(Some(value), path)
+    }
+}
+impl<'a, W> Serialization<W, &'a Option<RegExpFlags>> for Serializer<W> where W: TokenWriter {
+    fn serialize(&mut self, value: &'a Option<RegExpFlags>, path: &mut IOPath) -> Result<(), TokenWriterError> {
+        self.writer.reg_exp_flags_at(value.as_ref(), path)
+    }
+}
 impl<'a, W> Serialization<W, &'a Offset> for Serializer<W> where W: TokenWriter {
     fn serialize(&mut self, _: &'a Offset, path: &mut IOPath) -> Result<(), TokenWriterError> {
          self.writer.offset_at(path)
    }
 }
 
-pub struct Decoder;
+/// The grammar of the strongly-typed AST, written by encoders to formats that support it.
+pub fn grammar_id() -> GrammarId {
+    GrammarId::new(::ast::GRAMMAR_NAME, ::ast::GRAMMAR_FINGERPRINT)
+        .with_extended(::ast::GRAMMAR_EXTENDED)
+}
+
+/// The grammars for which we have a compiled decoder.
+///
+/// Files that declare any other grammar are rejected, as they would be misdecoded.
+pub fn supported_grammars() -> Vec<GrammarId> {
+    vec![grammar_id()]
+}
+
+/// Fail unless `grammar`, as declared by a file, is supported. Files that do not
+/// declare a grammar predate grammar identifiers and are assumed to be supported.
+fn check_grammar(grammar: Option<&GrammarId>) -> Result<(), TokenReaderError> {
+    match grammar {
+        Some(grammar) if !supported_grammars().contains(grammar) => {
+            Err(TokenReaderError::UnsupportedGrammar(grammar.clone()))
+        }
+        _ => Ok(())
+    }
+}
+
+pub struct Decoder {
+    scope_checks: ScopeCheckPolicy,
+}
 impl Decoder {
     pub fn new() -> Self {
-        Decoder
+        Decoder {
+            scope_checks: ScopeCheckPolicy::default(),
+        }
+    }
+
+    /// Check that the scope annotations of decoded ASTs are consistent, see module `scope_checks`.
+    ///
+    /// Checks apply to `decode`, `decode_with_positions`, `decode_parallel` and `decode_entry`.
+    /// By default, scope annotations are not checked.
+    pub fn with_scope_checks(self, scope_checks: ScopeCheckPolicy) -> Self {
+        Decoder {
+            scope_checks,
+        }
+    }
+
+    fn check_scopes<AST>(&self, ast: &mut AST) -> Result<(), TokenReaderError>
+        where
+            AST: for<'b> Walker<'b>,
+    {
+        if self.scope_checks == ScopeCheckPolicy::Ignore {
+            return Ok(());
+        }
+        let problems = ::scope_checks::check(ast);
+        if self.scope_checks == ScopeCheckPolicy::Reject {
+            if let Some(problem) = problems.into_iter().next() {
+                return Err(TokenReaderError::InconsistentScopes(problem));
+            }
+        } else {
+            for problem in problems {
+                tracing::warn!(target: "scope_checks", "Inconsistent scopes: {}", problem);
+            }
+        }
+        Ok(())
     }
-    pub fn decode<'a, R: Read + Seek, AST>(&self, format: &mut binjs_io::Format, source: R) -> Result<AST, TokenReaderError>
+
+    pub fn decode<R: Read + Seek, AST>(&self, format: &mut binjs_io::Format, source: R) -> Result<AST, binjs_io::Error>
         where
-            Deserializer<binjs_io::simple::TreeTokenReader<R>> : Deserialization<binjs_io::simple::TreeTokenReader<R>, AST>,
-            Deserializer<binjs_io::multipart::TreeTokenReader> : Deserialization<binjs_io::multipart::TreeTokenReader, AST>,
-            Deserializer<binjs_io::entropy::read::Decoder<R>> : Deserialization<binjs_io::entropy::read::Decoder<R>, AST>,
+            AST: Decodable + for<'b> Walker<'b>,
+    {
+        let (mut ast, _) = format.read(source, EventsVisitor::new(IgnoreEvents))?;
+        self.check_scopes(&mut ast)?;
+        Ok(ast)
+    }
+
+    /// Decode an AST, along with the source positions it was encoded with, if any.
+    ///
+    /// Source positions are only supported by the multipart format. Other formats
+    /// always return `None`.
+    pub fn decode_with_positions<R: Read + Seek, AST>(&self, format: &mut binjs_io::Format, source: R) -> Result<(AST, Option<SourcePositions>), binjs_io::Error>
+        where
+            AST: Decodable + for<'b> Walker<'b>,
     {
-        let mut path = IOPath::new();
         match *format {
-            binjs_io::Format::Simple { .. } => {
-                let reader = binjs_io::simple::TreeTokenReader::new(source);
-                let mut deserializer = Deserializer::new(reader);
-                let ast = deserializer.deserialize(&mut path)?;
-                Ok(ast)
+            binjs_io::Format::Multipart { ref options, .. } => {
+                let reader = binjs_io::multipart::TreeTokenReader::with_options(source, options)?;
+                check_grammar(reader.grammar())?;
+                let positions = reader.positions().cloned();
+                let (mut ast, _) = deserialize_with_events(reader, IgnoreEvents)?;
+                self.check_scopes(&mut ast)?;
+                Ok((ast, positions))
             }
-            binjs_io::Format::Multipart { .. } => {
-                let reader = binjs_io::multipart::TreeTokenReader::new(source)?;
+            _ => {
+                let ast = self.decode(format, source)?;
+                Ok((ast, None))
+            }
+        }
+    }
+
+    /// Decode a program, decoding the contents of its lazy functions with `jobs` threads,
+    /// see module `parallel`.
+    ///
+    /// Only the multipart format stores lazy functions as independent ranges of bytes,
+    /// other formats are decoded sequentially.
+    pub fn decode_parallel<R: Read + Seek>(&self, format: &mut binjs_io::Format, source: R, jobs: usize) -> Result<(::ast::Program, Option<SourcePositions>), binjs_io::Error> {
+        match *format {
+            binjs_io::Format::Multipart { ref options, .. } => {
+                let mut reader = binjs_io::multipart::TreeTokenReader::with_options(source, options)?;
+                check_grammar(reader.grammar())?;
+                let positions = reader.positions().cloned();
+                reader.defer_lazy_subtrees();
+                let mut path = IOPath::new();
                 let mut deserializer = Deserializer::new(reader);
-                let ast = deserializer.deserialize(&mut path)?;
-                Ok(ast)
+                let mut ast : ::ast::Program = deserializer.deserialize(&mut path)?;
+
+                // Convert strings before sharing them between threads.
+                let snapshot = deserializer.reader.snapshot()?;
+                let subtrees = deserializer.reader.deferred_subtrees();
+                tracing::debug!(target: "parallel", functions = subtrees.len(), threads = jobs, "Decoding lazy functions");
+                let contents = ::parallel::decode_subtrees(&snapshot, subtrees, jobs)?;
+                ::parallel::Stitcher::new(contents)
+                    .stitch(&mut ast)?;
+                self.check_scopes(&mut ast)?;
+                Ok((ast, positions))
             }
-            binjs_io::Format::Entropy { ref options } => {
-                let reader = binjs_io::entropy::read::Decoder::new((*options).clone(), source)?;
+            _ => self.decode_with_positions(format, source)
+        }
+    }
+
+    /// List the lazy functions of a program, in the order in which they appear in the
+    /// file, e.g. to let an engine decide which functions to compile eagerly.
+    ///
+    /// Lazy functions nested in lazy functions are not listed, as engines only need them
+    /// once the enclosing function is compiled.
+    ///
+    /// With the multipart format, the contents of lazy functions are skipped, except for
+    /// their parameters and scopes. Other formats cannot skip contents, so the entire
+    /// program is decoded.
+    pub fn lazy_functions<R: Read + Seek>(&self, format: &mut binjs_io::Format, source: R) -> Result<Vec<::lazy::LazyFunction>, binjs_io::Error> {
+        let collector = ::lazy::LazyFunctionCollector::new()
+            .with_nested(false);
+        match *format {
+            binjs_io::Format::Multipart { ref options, .. } => {
+                let mut reader = binjs_io::multipart::TreeTokenReader::with_options(source, options)?;
+                check_grammar(reader.grammar())?;
+                reader.defer_lazy_subtrees();
                 let mut deserializer = Deserializer::new(reader);
-                let ast = deserializer.deserialize(&mut path)?;
-                Ok(ast)
+                let mut program : ::ast::Program = deserializer.deserialize(&mut IOPath::new())?;
+                let subtrees = deserializer.reader.deferred_subtrees();
+
+                // The contents of each lazy function were skipped, in order.
+                let mut functions = collector.collect(&mut program);
+                if functions.len() != subtrees.len() {
+                    return Err(TokenReaderError::InvalidValue.into());
+                }
+                for (function, subtree) in functions.iter_mut().zip(subtrees) {
+                    function.read_contents_header(&mut deserializer, subtree.offset)?;
+                }
+                Ok(functions)
+            }
+            _ => {
+                let mut program : ::ast::Program = self.decode(format, source)?;
+                Ok(collector.collect(&mut program))
+            }
+        }
+    }
 
+    /// Decode the entry `entry` of an archive.
+    ///
+    /// Archives are only supported by the multipart format.
+    pub fn decode_entry<R: Read + Seek, AST>(&self, format: &mut binjs_io::Format, source: R, entry: &str) -> Result<AST, binjs_io::Error>
+        where
+            AST: Decodable + for<'b> Walker<'b>,
+    {
+        match *format {
+            binjs_io::Format::Multipart { ref options, .. } => {
+                let reader = binjs_io::multipart::TreeTokenReader::new_entry(source, entry, options)?;
+                check_grammar(reader.grammar())?;
+                let (mut ast, _) = deserialize_with_events(reader, IgnoreEvents)?;
+                self.check_scopes(&mut ast)?;
+                Ok(ast)
             }
-            _ => unimplemented!()
+            _ => Err(TokenReaderError::ArchiveUnsupported(format.name()).into())
         }
     }
+
+    /// Decode an AST, reporting the tokens read to `handler`, see `binjs_io::events`.
+    ///
+    /// Returns the AST and the handler.
+    pub fn decode_with_events<R: Read + Seek, AST, H: EventHandler>(&self, format: &mut binjs_io::Format, source: R, handler: H) -> Result<(AST, H), binjs_io::Error>
+        where
+            AST: Decodable,
+    {
+        Ok(format.read(source, EventsVisitor::new(handler))?)
+    }
+
+    /// Decode an AST, counting the symbols read by path and kind of symbol,
+    /// see `binjs_io::profile`.
+    #[cfg(feature = "profiling")]
+    pub fn decode_profiled<R: Read + Seek, AST>(&self, format: &mut binjs_io::Format, source: R) -> Result<(AST, Profile), binjs_io::Error>
+        where
+            AST: Decodable,
+    {
+        Ok(format.read(source, ProfiledVisitor::new())?)
+    }
+
+    /// Decode an AST whose nodes, lists and strings are allocated in `arena`,
+    /// see module `arena`.
+    ///
+    /// Scope annotations are not checked, regardless of `with_scope_checks`.
+    /// Readers that skip the contents of lazy functions are not supported, as
+    /// skipped nodes have no placeholder in the arena.
+    #[cfg(feature = "arena")]
+    pub fn decode_in<'a, R: Read + Seek, AST>(&self, format: &mut binjs_io::Format, source: R, arena: &'a ::arena::Bump) -> Result<AST, binjs_io::Error>
+        where
+            AST: ::arena::Decodable<'a>,
+    {
+        Ok(format.read(source, ArenaVisitor::new(arena))?)
+    }
+}
+
+/// Deserialize an AST from `reader`, reporting the tokens read to `handler`.
+///
+/// Decoders read through the event API, see `binjs_io::events`, with `IgnoreEvents`
+/// unless they are given a handler.
+fn deserialize_with_events<R, AST, H>(reader: R, handler: H) -> Result<(AST, H), TokenReaderError>
+    where R: TokenReader, AST: Decodable, H: EventHandler
+{
+    let mut deserializer = Deserializer::new(TokenReaderEventAdapter::new(reader, handler));
+    let ast = AST::deserialize_from(&mut deserializer, &mut IOPath::new())?;
+    let (_, handler) = deserializer.reader.done();
+    Ok((ast, handler))
+}
+
+/// Deserialize an AST from the token reader of any format, reporting the tokens read
+/// to a handler, see `Decoder::decode` and `Decoder::decode_with_events`.
+struct EventsVisitor<AST, H> {
+    handler: H,
+    phantom: PhantomData<AST>,
+}
+impl<AST, H> EventsVisitor<AST, H> {
+    fn new(handler: H) -> Self {
+        EventsVisitor {
+            handler,
+            phantom: PhantomData,
+        }
+    }
+}
+impl<AST, H> ReaderVisitor for EventsVisitor<AST, H> where AST: Decodable, H: EventHandler {
+    type Output = (AST, H);
+    fn visit<R: TokenReader>(self, reader: R, grammar: Option<&GrammarId>) -> Result<(AST, H), TokenReaderError> {
+        check_grammar(grammar)?;
+        deserialize_with_events(reader, self.handler)
+    }
+}
+
+/// As `EventsVisitor`, counting the symbols read, see `Decoder::decode_profiled`.
+#[cfg(feature = "profiling")]
+struct ProfiledVisitor<AST> {
+    phantom: PhantomData<AST>,
+}
+#[cfg(feature = "profiling")]
+impl<AST> ProfiledVisitor<AST> {
+    fn new() -> Self {
+        ProfiledVisitor {
+            phantom: PhantomData,
+        }
+    }
+}
+#[cfg(feature = "profiling")]
+impl<AST> ReaderVisitor for ProfiledVisitor<AST> where AST: Decodable {
+    type Output = (AST, Profile);
+    fn visit<R: TokenReader>(self, reader: R, grammar: Option<&GrammarId>) -> Result<(AST, Profile), TokenReaderError> {
+        check_grammar(grammar)?;
+        let mut deserializer = Deserializer::new(TokenReaderProfiler::new(reader));
+        let ast = AST::deserialize_from(&mut deserializer, &mut IOPath::new())?;
+        let (_, profile) = deserializer.reader.done();
+        Ok((ast, profile))
+    }
+}
+
+/// As `EventsVisitor`, allocating the AST in an arena, see `Decoder::decode_in`.
+#[cfg(feature = "arena")]
+struct ArenaVisitor<'a, AST> {
+    arena: &'a ::arena::Bump,
+    phantom: PhantomData<AST>,
+}
+#[cfg(feature = "arena")]
+impl<'a, AST> ArenaVisitor<'a, AST> {
+    fn new(arena: &'a ::arena::Bump) -> Self {
+        ArenaVisitor {
+            arena,
+            phantom: PhantomData,
+        }
+    }
+}
+#[cfg(feature = "arena")]
+impl<'a, AST> ReaderVisitor for ArenaVisitor<'a, AST> where AST: ::arena::Decodable<'a> {
+    type Output = AST;
+    fn visit<R: TokenReader>(self, reader: R, grammar: Option<&GrammarId>) -> Result<AST, TokenReaderError> {
+        check_grammar(grammar)?;
+        let mut deserializer = ::arena::Deserializer::new(reader, self.arena);
+        AST::deserialize_from(&mut deserializer, &mut IOPath::new())
+    }
+}
+
+pub struct Encoder {
+    positions: Option<SourcePositions>,
+    profile: Option<StartupProfile>,
+    allow_invalid_identifiers: bool,
 }
-pub struct Encoder;
 impl Encoder {
     pub fn new() -> Self {
-        Encoder
+        Encoder {
+            positions: None,
+            profile: None,
+            allow_invalid_identifiers: false,
+        }
+    }
+
+    /// If specified, store these source positions alongside the tree.
+    ///
+    /// Source positions are only supported by the multipart format and ignored
+    /// by other formats and by `encode_archive`.
+    pub fn with_positions(self, positions: Option<SourcePositions>) -> Self {
+        Encoder {
+            positions,
+            ..self
+        }
     }
-    pub fn encode<'a, AST>(&self, format: &'a mut binjs_io::Format, ast: &'a AST) -> Result<Box<AsRef<[u8]>>, TokenWriterError>
+
+    /// If specified, record this startup profile in the file, typically the profile
+    /// used to lazify the AST, see `lazy::Policy::Profile`.
+    ///
+    /// Startup profiles are only supported by the multipart format and ignored
+    /// by other formats and by `encode_archive`.
+    pub fn with_profile(self, profile: Option<StartupProfile>) -> Self {
+        Encoder {
+            profile,
+            ..self
+        }
+    }
+
+    /// If `true`, encode identifier names that are not valid ECMAScript IdentifierNames,
+    /// e.g. `"foo bar"`, instead of failing with an error caused by `TokenWriterError::InvalidIdentifierName`.
+    pub fn with_invalid_identifiers(self, allow_invalid_identifiers: bool) -> Self {
+        Encoder {
+            allow_invalid_identifiers,
+            ..self
+        }
+    }
+    /// A serializer for `writer`, checking identifier names unless `with_invalid_identifiers`.
+    fn serializer<W: TokenWriter>(&self, writer: W) -> Serializer<W> {
+        Serializer::new(writer)
+            .with_identifier_checks(!self.allow_invalid_identifiers)
+    }
+
+    pub fn encode<'a, AST>(&self, format: &'a mut binjs_io::Format, ast: &'a AST) -> Result<Box<AsRef<[u8]>>, binjs_io::Error>
         where
-            Serializer<TokenWriterTreeAdapter<binjs_io::simple::TreeTokenWriter>> : Serialization<TokenWriterTreeAdapter<binjs_io::simple::TreeTokenWriter>, &'a AST>,
-            Serializer<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>> : Serialization<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>, &'a AST>,
-            Serializer<TokenWriterTreeAdapter<binjs_io::xml::Encoder>> : Serialization<TokenWriterTreeAdapter<binjs_io::xml::Encoder>, &'a AST>,
-            Serializer<binjs_io::entropy::write::Encoder> : Serialization<binjs_io::entropy::write::Encoder, &'a AST>
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::simple::TreeTokenWriter>, NoProgress>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::simple::TreeTokenWriter>, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>, NoProgress>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::xml::Encoder>, NoProgress>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::xml::Encoder>, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::templates::Encoder>, NoProgress>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::templates::Encoder>, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::dag::Encoder>, NoProgress>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::dag::Encoder>, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::entropy::write::Encoder, NoProgress>> : Serialization<TokenWriterProgressAdapter<binjs_io::entropy::write::Encoder, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::entropy::adaptive::Encoder, NoProgress>> : Serialization<TokenWriterProgressAdapter<binjs_io::entropy::adaptive::Encoder, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::entropy::huffman::Encoder, NoProgress>> : Serialization<TokenWriterProgressAdapter<binjs_io::entropy::huffman::Encoder, NoProgress>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::text::Encoder, NoProgress>> : Serialization<TokenWriterProgressAdapter<binjs_io::text::Encoder, NoProgress>, &'a AST>
+    {
+        self.encode_with_progress(format, ast, NoProgress)
+    }
+
+    /// Encode an AST, reporting progress to `sink`.
+    ///
+    /// The sink is informed when the encode and compress phases start, of the
+    /// number of nodes encoded and of the number of bytes produced.
+    pub fn encode_with_progress<'a, AST, S>(&self, format: &'a mut binjs_io::Format, ast: &'a AST, mut sink: S) -> Result<Box<AsRef<[u8]>>, binjs_io::Error>
+        where
+            S: ProgressSink,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::simple::TreeTokenWriter>, S>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::simple::TreeTokenWriter>, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>, S>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::xml::Encoder>, S>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::xml::Encoder>, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::templates::Encoder>, S>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::templates::Encoder>, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::dag::Encoder>, S>> : Serialization<TokenWriterProgressAdapter<TokenWriterTreeAdapter<binjs_io::dag::Encoder>, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::entropy::write::Encoder, S>> : Serialization<TokenWriterProgressAdapter<binjs_io::entropy::write::Encoder, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::entropy::adaptive::Encoder, S>> : Serialization<TokenWriterProgressAdapter<binjs_io::entropy::adaptive::Encoder, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::entropy::huffman::Encoder, S>> : Serialization<TokenWriterProgressAdapter<binjs_io::entropy::huffman::Encoder, S>, &'a AST>,
+            Serializer<TokenWriterProgressAdapter<binjs_io::text::Encoder, S>> : Serialization<TokenWriterProgressAdapter<binjs_io::text::Encoder, S>, &'a AST>
     {
+        let _span = tracing::info_span!("encode", format = format.name().as_str()).entered();
         let mut path = IOPath::new();
+        sink.phase(Phase::Encode);
         match *format {
             binjs_io::Format::Simple { .. } => {
                 let writer = binjs_io::simple::TreeTokenWriter::new();
-                let mut serializer = Serializer::new(TokenWriterTreeAdapter::new(writer));
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(TokenWriterTreeAdapter::new(writer), sink));
                 serializer.serialize(ast, &mut path)?;
                 let data = serializer.done()?;
                 Ok(Box::new(data))
             }
-            binjs_io::Format::Multipart { ref mut targets, .. } => {
-                let writer = binjs_io::multipart::TreeTokenWriter::new(targets.clone());
-                let mut serializer = Serializer::new(TokenWriterTreeAdapter::new(writer));
+            binjs_io::Format::Multipart { ref mut targets, ref stats, ref options } => {
+                let writer = binjs_io::multipart::TreeTokenWriter::new(targets.clone())
+                    .with_checksum(options.integrity.write_checksum)
+                    .with_sign_key(options.integrity.sign_key)
+                    .with_encryption_key(options.integrity.encryption_key)
+                    .with_front_coding(options.front_coding)
+                    .with_blobs(options.blob_threshold, options.blob_compression.clone())
+                    .with_nan_policy(options.nan_policy)
+                    .with_varfloats(options.varfloats)
+                    .with_runs(options.runs)
+                    .with_split_prelude(options.split_prelude)
+                    .with_chunks(options.chunks)
+                    .with_string_dictionary(options.string_dictionary.clone())
+                    .with_metadata(options.metadata.clone())
+                    .with_reproducible(options.reproducible)
+                    .with_grammar(Some(grammar_id()))
+                    .with_positions(self.positions.clone())
+                    .with_profile(self.profile.clone())
+                    .with_statistics(Some(stats.clone()));
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(TokenWriterTreeAdapter::new(writer), sink));
                 serializer.serialize(ast, &mut path)?;
                 let data = serializer.done()?;
                 Ok(Box::new(data))
@@ -307,19 +780,95 @@ impl Encoder {
 
             binjs_io::Format::XML => {
                 let writer = binjs_io::xml::Encoder::new();
-                let mut serializer = Serializer::new(TokenWriterTreeAdapter::new(writer));
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(TokenWriterTreeAdapter::new(writer), sink));
+                serializer.serialize(ast, &mut path)?;
+                let data = serializer.done()?;
+                Ok(Box::new(data))
+            }
+            binjs_io::Format::Text => {
+                let writer = binjs_io::text::Encoder::new();
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(writer, sink));
                 serializer.serialize(ast, &mut path)?;
                 let data = serializer.done()?;
                 Ok(Box::new(data))
             }
             binjs_io::Format::Entropy { ref options } => {
+                let _span = tracing::info_span!("entropy_coding").entered();
                 let writer = binjs_io::entropy::write::Encoder::new((*options).clone());
-                let mut serializer = Serializer::new(writer);
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(writer, sink));
+                serializer.serialize(ast, &mut path)?;
+                let data = serializer.done()?;
+                Ok(Box::new(data))
+            }
+            binjs_io::Format::AdaptiveEntropy { ref options } => {
+                let _span = tracing::info_span!("entropy_coding").entered();
+                let writer = binjs_io::entropy::adaptive::Encoder::new((*options).clone());
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(writer, sink));
+                serializer.serialize(ast, &mut path)?;
+                let data = serializer.done()?;
+                Ok(Box::new(data))
+            }
+            binjs_io::Format::HuffmanEntropy { ref options } => {
+                let _span = tracing::info_span!("entropy_coding").entered();
+                let writer = binjs_io::entropy::huffman::Encoder::new((*options).clone());
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(writer, sink));
+                serializer.serialize(ast, &mut path)?;
+                let data = serializer.done()?;
+                Ok(Box::new(data))
+            }
+            binjs_io::Format::Templates { ref options } => {
+                let writer = binjs_io::templates::Encoder::new((*options).clone());
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(TokenWriterTreeAdapter::new(writer), sink));
+                serializer.serialize(ast, &mut path)?;
+                let data = serializer.done()?;
+                Ok(Box::new(data))
+            }
+            binjs_io::Format::Dag { ref options } => {
+                let writer = binjs_io::dag::Encoder::new((*options).clone());
+                let mut serializer = self.serializer(TokenWriterProgressAdapter::new(TokenWriterTreeAdapter::new(writer), sink));
                 serializer.serialize(ast, &mut path)?;
                 let data = serializer.done()?;
                 Ok(Box::new(data))
             }
         }
     }
-}
 
+    /// Encode several ASTs as the entries of a single archive, sharing
+    /// their grammar and strings tables.
+    ///
+    /// Archives are only supported by the multipart format.
+    pub fn encode_archive<'a, AST>(&self, format: &'a mut binjs_io::Format, entries: &[(&str, &'a AST)]) -> Result<Box<AsRef<[u8]>>, binjs_io::Error>
+        where
+            Serializer<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>> : Serialization<TokenWriterTreeAdapter<binjs_io::multipart::TreeTokenWriter>, &'a AST>,
+    {
+        match *format {
+            binjs_io::Format::Multipart { ref mut targets, ref options, .. } => {
+                let writer = binjs_io::multipart::TreeTokenWriter::new(targets.clone())
+                    .with_checksum(options.integrity.write_checksum)
+                    .with_sign_key(options.integrity.sign_key)
+                    .with_encryption_key(options.integrity.encryption_key)
+                    .with_front_coding(options.front_coding)
+                    .with_blobs(options.blob_threshold, options.blob_compression.clone())
+                    .with_nan_policy(options.nan_policy)
+                    .with_varfloats(options.varfloats)
+                    .with_runs(options.runs)
+                    .with_split_prelude(options.split_prelude)
+                    .with_chunks(options.chunks)
+                    .with_string_dictionary(options.string_dictionary.clone())
+                    .with_metadata(options.metadata.clone())
+                    .with_reproducible(options.reproducible)
+                    .with_grammar(Some(grammar_id()));
+                let mut serializer = self.serializer(TokenWriterTreeAdapter::new(writer));
+                for &(name, ast) in entries {
+                    let mut path = IOPath::new();
+                    serializer.serialize(ast, &mut path)?;
+                    serializer.writer.writer_mut()
+                        .end_entry(name);
+                }
+                let data = serializer.done()?;
+                Ok(Box::new(data))
+            }
+            _ => Err(TokenWriterError::ArchiveUnsupported(format.name()).into())
+        }
+    }
+}
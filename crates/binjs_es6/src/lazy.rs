@@ -1,12 +1,17 @@
 use ast::*;
 
-use binjs_shared::{ FromJSON, Offset, ToJSON, VisitMe };
+use io::{ Deserializer, IOPath };
+
+use binjs_io::{ Deserialization, TokenReader, TokenReaderError };
+use binjs_io::multipart::TreeTokenReader;
+use binjs_io::positions::Location;
+use binjs_io::startup::StartupProfile;
+use binjs_shared::{ FromJSON, JSON, Offset, ToJSON, VisitMe };
 
 use std;
 use std::cell::RefCell;
 use std::rc::Rc;
-
-use json::JsonValue as JSON;
+use std::string::String; // Rather than `ast::String`.
 
 /// Keep track of the number of nested levels of functions/methods/...
 /// we have crossed.
@@ -30,15 +35,90 @@ impl Drop for LevelGuard {
     }
 }
 
-/// A visitor in charge of rewriting an AST to introduce laziness.
-pub struct LazifierVisitor {
+/// Which functions to lazify.
+///
+/// Function expressions that are called immediately are never lazified.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Policy {
     /// A nesting level at which to stop.
     ///
     /// 0 = lazify nothing
     /// 1 = lazify functions defined at topevel
     /// 2 = lazify functions defined at toplevel and functions defined immediately inside them
     /// ...
-    threshold: u32,
+    Depth(u32),
+
+    /// Lazify functions whose source text is at least this many bytes long.
+    MinBytes(u32),
+
+    /// Lazify functions whose source text spans exactly one of these
+    /// `(start, end)` ranges of byte offsets.
+    Spans(Vec<(u32, u32)>),
+
+    /// Lazify functions that were not executed at startup, according to a profile.
+    ///
+    /// Functions are identified by their index among function declarations,
+    /// function expressions, methods, getters and setters, in source order.
+    Profile(StartupProfile),
+}
+impl Policy {
+    pub fn none() -> Self {
+        Policy::Depth(0)
+    }
+    pub fn all() -> Self {
+        Policy::Depth(std::u32::MAX)
+    }
+
+    /// Parse a policy from the command line: `none`, `all`, a number of layers
+    /// (see `Depth`), `min-bytes=N` or `spans=START-END,START-END,...`.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let number = |value: &str| value.parse::<u32>()
+            .map_err(|e| format!("Invalid number {}", e));
+        match source {
+            "none" => return Ok(Self::none()),
+            "all" => return Ok(Self::all()),
+            _ => {}
+        }
+        if source.starts_with("min-bytes=") {
+            return Ok(Policy::MinBytes(number(&source["min-bytes=".len()..])?));
+        }
+        if source.starts_with("spans=") {
+            let mut spans = vec![];
+            for span in source["spans=".len()..].split(',').filter(|span| !span.is_empty()) {
+                let mut bounds = span.splitn(2, '-');
+                let start = number(bounds.next().unwrap())?; // `splitn` always yields at least one item.
+                let end = number(bounds.next()
+                    .ok_or_else(|| format!("Invalid span {}, expected START-END", span))?)?;
+                spans.push((start, end));
+            }
+            return Ok(Policy::Spans(spans));
+        }
+        Ok(Policy::Depth(number(source)?))
+    }
+
+    /// `true` if the policy needs the source locations of functions.
+    pub fn needs_locations(&self) -> bool {
+        match *self {
+            Policy::Depth(_) | Policy::Profile(_) => false,
+            Policy::MinBytes(_) | Policy::Spans(_) => true,
+        }
+    }
+}
+
+/// A visitor in charge of rewriting an AST to introduce laziness.
+pub struct LazifierVisitor {
+    policy: Policy,
+
+    /// If the policy needs them, the locations of function declarations,
+    /// function expressions, methods, getters and setters, in walk order.
+    locations: Vec<Option<Location>>,
+
+    /// The number of functions entered so far, i.e. the index of the next
+    /// function in `locations`.
+    functions: usize,
+
+    /// For each function we are in, `true` if it should be lazified.
+    decisions: Vec<bool>,
 
     /// Current nesting level.
     ///
@@ -47,9 +127,25 @@ pub struct LazifierVisitor {
 }
 
 impl LazifierVisitor {
+    /// Lazify `threshold` layers of functions, see `Policy::Depth`.
     pub fn new(threshold: u32) -> Self {
+        Self::with_policy(Policy::Depth(threshold), vec![])
+    }
+
+    /// Lazify functions according to `policy`.
+    ///
+    /// If `policy.needs_locations()`, `locations` must hold the location of
+    /// each function declaration, function expression, method, getter and
+    /// setter, in the order in which they are walked, e.g. as returned by
+    /// `binjs::source::positions::function_locations`. Each function is
+    /// lazified depending on its own source span. Functions without a
+    /// location are not lazified.
+    pub fn with_policy(policy: Policy, locations: Vec<Option<Location>>) -> Self {
         Self {
-            threshold,
+            policy,
+            locations,
+            functions: 0,
+            decisions: vec![],
             level: Rc::new(RefCell::new(0)),
         }
     }
@@ -81,21 +177,47 @@ impl LazifierVisitor {
         Ok(Some(decorator(stolen)))
     }
 
-    /// Return `DoneHere` if we're beyond the threshold, hence skipping the subtree.
-    /// Otherwise, acquire a `LevelGuard` that will be released once we're done
-    /// with this subtree.
+    /// With `Policy::Depth`, return `DoneHere` if we're beyond the threshold,
+    /// hence skipping the subtree. Otherwise, decide whether to lazify this
+    /// function and acquire a `LevelGuard` that will be released once we're
+    /// done with this subtree.
     fn cut_at_threshold(&mut self) -> Result<VisitMe<Option<LevelGuard>>, ()> {
-        {
-            if *self.level.borrow() >= self.threshold {
-                return Ok(VisitMe::DoneHere);
+        let index = self.functions;
+        self.functions += 1;
+        let lazify = match self.policy {
+            Policy::Depth(threshold) => {
+                if *self.level.borrow() >= threshold {
+                    return Ok(VisitMe::DoneHere);
+                }
+                true
             }
-        }
+            Policy::MinBytes(min_bytes) => self.span(index)
+                .map_or(false, |(start, end)| end.saturating_sub(start) >= min_bytes),
+            Policy::Spans(ref spans) => self.span(index)
+                .map_or(false, |span| spans.contains(&span)),
+            Policy::Profile(ref profile) => !profile.is_executed(index as u32),
+        };
+        self.decisions.push(lazify);
         Ok(VisitMe::HoldThis(Some(LevelGuard::new(self))))
     }
+
+    /// The `(start, end)` byte offsets of the `index`-th function, if known.
+    fn span(&self, index: usize) -> Option<(u32, u32)> {
+        match self.locations.get(index) {
+            Some(&Some(ref location)) => Some((location.start.offset, location.end.offset)),
+            _ => None
+        }
+    }
+
+    /// The decision taken by `cut_at_threshold` for the function we're exiting.
+    fn should_lazify(&mut self) -> bool {
+        self.decisions.pop()
+            .expect("Exiting a function we haven't entered")
+    }
 }
 
 impl Visitor<(), Option<LevelGuard>> for LazifierVisitor {
-    /// Skip subtrees that are beyond the threshold.
+    /// With `Policy::Depth`, skip subtrees that are beyond the threshold.
     fn enter_method_definition(&mut self, _path: &WalkPath, _node: &mut ViewMutMethodDefinition) -> Result<VisitMe<Option<LevelGuard>>, ()> {
         self.cut_at_threshold()
     }
@@ -104,6 +226,9 @@ impl Visitor<(), Option<LevelGuard>> for LazifierVisitor {
     ///
     /// Only called if we haven't skipped the subtree.
     fn exit_method_definition(&mut self, _path: &WalkPath, node: &mut ViewMutMethodDefinition) -> Result<Option<MethodDefinition>, ()> {
+        if !self.should_lazify() {
+            return Ok(None)
+        }
         match *node {
             ViewMutMethodDefinition::EagerGetter(ref mut steal) => {
                 Self::steal(*steal, |stolen| {
@@ -143,7 +268,7 @@ impl Visitor<(), Option<LevelGuard>> for LazifierVisitor {
         }
     }
 
-    /// Skip subtrees that are beyond the threshold.
+    /// With `Policy::Depth`, skip subtrees that are beyond the threshold.
     fn enter_function_declaration(&mut self, _path: &WalkPath, _node: &mut ViewMutFunctionDeclaration) -> Result<VisitMe<Option<LevelGuard>>, ()> {
         self.cut_at_threshold()
     }
@@ -152,6 +277,9 @@ impl Visitor<(), Option<LevelGuard>> for LazifierVisitor {
     ///
     /// Only called if we haven't skipped the subtree.
     fn exit_function_declaration(&mut self, _path: &WalkPath, node: &mut ViewMutFunctionDeclaration) -> Result<Option<FunctionDeclaration>, ()> {
+        if !self.should_lazify() {
+            return Ok(None)
+        }
         match *node {
             ViewMutFunctionDeclaration::EagerFunctionDeclaration(ref mut steal) => {
                 Self::steal(*steal, |stolen| {
@@ -170,7 +298,7 @@ impl Visitor<(), Option<LevelGuard>> for LazifierVisitor {
         }
     }
 
-    /// Skip subtrees that are beyond the threshold.
+    /// With `Policy::Depth`, skip subtrees that are beyond the threshold.
     fn enter_function_expression(&mut self, _path: &WalkPath, _node: &mut ViewMutFunctionExpression) -> Result<VisitMe<Option<LevelGuard>>, ()> {
         self.cut_at_threshold()
     }
@@ -179,6 +307,9 @@ impl Visitor<(), Option<LevelGuard>> for LazifierVisitor {
     ///
     /// Only called if we haven't skipped the subtree.
     fn exit_function_expression(&mut self, path: &WalkPath, node: &mut ViewMutFunctionExpression) -> Result<Option<FunctionExpression>, ()> {
+        if !self.should_lazify() {
+            return Ok(None)
+        }
         // Don't lazify code that's going to be used immediately.
         if let Some(WalkPathItem { interface: ASTNode::CallExpression, field: ASTField::Callee }) = path.get(0) {
             return Ok(None)
@@ -201,3 +332,366 @@ impl Visitor<(), Option<LevelGuard>> for LazifierVisitor {
         }
     }
 }
+
+/// A visitor in charge of rewriting an AST to remove laziness, e.g. to
+/// introduce laziness again with a different policy.
+pub struct EagerifierVisitor;
+
+impl EagerifierVisitor {
+    pub fn new() -> Self {
+        EagerifierVisitor
+    }
+    pub fn annotate_script(&mut self, script: &mut Script) {
+        script.walk(&mut WalkPath::new(), self)
+            .expect("Could not walk script");
+    }
+}
+
+impl Visitor<()> for EagerifierVisitor {
+    fn exit_method_definition(&mut self, _path: &WalkPath, node: &mut ViewMutMethodDefinition) -> Result<Option<MethodDefinition>, ()> {
+        match *node {
+            ViewMutMethodDefinition::LazyGetter(ref mut steal) => {
+                LazifierVisitor::steal(*steal, |stolen| {
+                    EagerGetter {
+                        name: stolen.name,
+                        directives: stolen.directives,
+                        contents: stolen.contents
+                    }.into()
+                })
+            }
+            ViewMutMethodDefinition::LazySetter(ref mut steal) => {
+                LazifierVisitor::steal(*steal, |stolen| {
+                    EagerSetter {
+                        name: stolen.name,
+                        length: stolen.length,
+                        directives: stolen.directives,
+                        contents: stolen.contents
+                    }.into()
+                })
+            }
+            ViewMutMethodDefinition::LazyMethod(ref mut steal) => {
+                LazifierVisitor::steal(*steal, |stolen| {
+                    EagerMethod {
+                        is_async: stolen.is_async,
+                        is_generator: stolen.is_generator,
+                        name: stolen.name,
+                        length: stolen.length,
+                        directives: stolen.directives,
+                        contents: stolen.contents
+                    }.into()
+                })
+            }
+            _ => Ok(None)
+        }
+    }
+
+    fn exit_function_declaration(&mut self, _path: &WalkPath, node: &mut ViewMutFunctionDeclaration) -> Result<Option<FunctionDeclaration>, ()> {
+        match *node {
+            ViewMutFunctionDeclaration::LazyFunctionDeclaration(ref mut steal) => {
+                LazifierVisitor::steal(*steal, |stolen| {
+                    EagerFunctionDeclaration {
+                        is_async: stolen.is_async,
+                        is_generator: stolen.is_generator,
+                        name: stolen.name,
+                        length: stolen.length,
+                        directives: stolen.directives,
+                        contents: stolen.contents
+                    }.into()
+                })
+            }
+            _ => Ok(None)
+        }
+    }
+
+    fn exit_function_expression(&mut self, _path: &WalkPath, node: &mut ViewMutFunctionExpression) -> Result<Option<FunctionExpression>, ()> {
+        match *node {
+            ViewMutFunctionExpression::LazyFunctionExpression(ref mut steal) => {
+                LazifierVisitor::steal(*steal, |stolen| {
+                    EagerFunctionExpression {
+                        is_async: stolen.is_async,
+                        is_generator: stolen.is_generator,
+                        name: stolen.name,
+                        length: stolen.length,
+                        directives: stolen.directives,
+                        contents: stolen.contents
+                    }.into()
+                })
+            }
+            _ => Ok(None)
+        }
+    }
+
+    fn exit_arrow_expression(&mut self, _path: &WalkPath, node: &mut ViewMutArrowExpression) -> Result<Option<ArrowExpression>, ()> {
+        match *node {
+            ViewMutArrowExpression::LazyArrowExpressionWithFunctionBody(ref mut steal) => {
+                LazifierVisitor::steal(*steal, |stolen| {
+                    EagerArrowExpressionWithFunctionBody {
+                        is_async: stolen.is_async,
+                        length: stolen.length,
+                        directives: stolen.directives,
+                        contents: stolen.contents
+                    }.into()
+                })
+            }
+            ViewMutArrowExpression::LazyArrowExpressionWithExpression(ref mut steal) => {
+                LazifierVisitor::steal(*steal, |stolen| {
+                    EagerArrowExpressionWithExpression {
+                        is_async: stolen.is_async,
+                        length: stolen.length,
+                        contents: stolen.contents
+                    }.into()
+                })
+            }
+            _ => Ok(None)
+        }
+    }
+}
+
+/// The kind of a lazy function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LazyFunctionKind {
+    Declaration,
+    Expression,
+    Method,
+    Getter,
+    Setter,
+    Arrow,
+}
+
+/// A summary of the scopes declared by a lazy function.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScopeSummary {
+    /// The number of names bound by the parameters.
+    pub parameter_names: usize,
+
+    /// The number of names declared in the body.
+    pub declared_names: usize,
+
+    /// The number of parameter or declared names captured by inner functions.
+    pub captured_names: usize,
+
+    /// `true` if the parameters or the body contain a direct call to `eval`.
+    pub has_direct_eval: bool,
+}
+impl ScopeSummary {
+    fn new(parameter_scope: Option<&AssertedParameterScope>, body_scope: &AssertedVarScope) -> Self {
+        let mut summary = ScopeSummary {
+            declared_names: body_scope.declared_names.len(),
+            captured_names: body_scope.declared_names.iter()
+                .filter(|name| name.is_captured)
+                .count(),
+            has_direct_eval: body_scope.has_direct_eval,
+            ..ScopeSummary::default()
+        };
+        if let Some(parameter_scope) = parameter_scope {
+            summary.parameter_names = parameter_scope.param_names.len();
+            summary.captured_names += parameter_scope.param_names.iter()
+                .filter(|name| match **name {
+                    AssertedMaybePositionalParameterName::AssertedPositionalParameterName(ref name) => name.is_captured,
+                    AssertedMaybePositionalParameterName::AssertedRestParameterName(ref name) => name.is_captured,
+                    AssertedMaybePositionalParameterName::AssertedParameterName(ref name) => name.is_captured,
+                })
+                .count();
+            summary.has_direct_eval |= parameter_scope.has_direct_eval;
+        }
+        summary
+    }
+}
+
+/// What engines may want to know about a lazy function before compiling it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LazyFunction {
+    pub kind: LazyFunctionKind,
+
+    /// The name of the function or method, if any. `None` for anonymous
+    /// functions, arrows and computed property names.
+    pub name: Option<String>,
+
+    /// The byte length of the contents of the function, as stored in the file.
+    /// 0 if the AST was not decoded from a file.
+    pub byte_len: u32,
+
+    /// The number of formal parameters, including the rest parameter.
+    pub parameters: u32,
+
+    /// The scopes declared by the function.
+    pub scope: ScopeSummary,
+}
+
+impl LazyFunction {
+    /// Read the parameters and scopes of this function from the beginning of its contents,
+    /// at `offset` in the tree of a multipart file, once the AST was decoded without
+    /// the contents, see `TreeTokenReader::defer_lazy_subtrees`.
+    ///
+    /// The rest of the contents, e.g. the body, is not read.
+    pub fn read_contents_header(&mut self, deserializer: &mut Deserializer<TreeTokenReader>, offset: u64) -> Result<(), TokenReaderError> {
+        deserializer.reader.seek_to(offset)?;
+        let mut path = IOPath::new();
+        let (interface, _) = deserializer.reader.enter_tagged_tuple_at(&path)?;
+        path.enter_interface(interface);
+
+        let (parameter_scope, body_scope) = match self.kind {
+            LazyFunctionKind::Getter => {
+                let _is_this_captured : bool = deserializer.deserialize(&mut path)?;
+                let body_scope : AssertedVarScope = deserializer.deserialize(&mut path)?;
+                (None, body_scope)
+            }
+            LazyFunctionKind::Setter => {
+                let _is_this_captured : bool = deserializer.deserialize(&mut path)?;
+                let parameter_scope : AssertedParameterScope = deserializer.deserialize(&mut path)?;
+                let _param : Parameter = deserializer.deserialize(&mut path)?;
+                let body_scope : AssertedVarScope = deserializer.deserialize(&mut path)?;
+                (Some(parameter_scope), body_scope)
+            }
+            LazyFunctionKind::Declaration
+            | LazyFunctionKind::Expression
+            | LazyFunctionKind::Method
+            | LazyFunctionKind::Arrow => {
+                if self.kind == LazyFunctionKind::Expression {
+                    let _is_function_name_captured : bool = deserializer.deserialize(&mut path)?;
+                }
+                if self.kind != LazyFunctionKind::Arrow {
+                    let _is_this_captured : bool = deserializer.deserialize(&mut path)?;
+                }
+                let parameter_scope : AssertedParameterScope = deserializer.deserialize(&mut path)?;
+                let params : FormalParameters = deserializer.deserialize(&mut path)?;
+                let body_scope : AssertedVarScope = deserializer.deserialize(&mut path)?;
+                self.parameters = LazyFunctionCollector::parameters(&params);
+                (Some(parameter_scope), body_scope)
+            }
+        };
+        self.scope = ScopeSummary::new(parameter_scope.as_ref(), &body_scope);
+        Ok(())
+    }
+}
+
+/// A visitor collecting the lazy functions of an AST, in the order in which
+/// they appear in the file.
+pub struct LazyFunctionCollector {
+    functions: Vec<LazyFunction>,
+    nested: bool,
+}
+impl LazyFunctionCollector {
+    pub fn new() -> Self {
+        LazyFunctionCollector {
+            functions: vec![],
+            nested: true,
+        }
+    }
+
+    /// If `false`, do not collect lazy functions nested in lazy functions.
+    /// By default, they are collected.
+    pub fn with_nested(self, nested: bool) -> Self {
+        LazyFunctionCollector {
+            nested,
+            ..self
+        }
+    }
+
+    /// Collect the lazy functions of `ast`.
+    pub fn collect<AST>(mut self, ast: &mut AST) -> Vec<LazyFunction> where AST: for<'a> Walker<'a> {
+        ast.walk(&mut WalkPath::new(), &mut self)
+            .expect("Could not walk AST");
+        self.functions
+    }
+
+    /// Once a lazy function has been collected, whether to visit its contents.
+    fn visit_contents(&self) -> Result<VisitMe<()>, ()> {
+        if self.nested {
+            Ok(VisitMe::HoldThis(()))
+        } else {
+            Ok(VisitMe::DoneHere)
+        }
+    }
+
+    fn parameters(params: &FormalParameters) -> u32 {
+        params.items.len() as u32 + params.rest.is_some() as u32
+    }
+
+    fn property_name(name: &PropertyName) -> Option<String> {
+        match *name {
+            PropertyName::LiteralPropertyName(ref name) => Some(name.value.as_str().to_string()),
+            PropertyName::ComputedPropertyName(_) => None,
+        }
+    }
+}
+
+impl Visitor<()> for LazyFunctionCollector {
+    fn enter_lazy_function_declaration(&mut self, _path: &WalkPath, node: &mut LazyFunctionDeclaration) -> Result<VisitMe<()>, ()> {
+        self.functions.push(LazyFunction {
+            kind: LazyFunctionKind::Declaration,
+            name: Some(node.name.name.as_str().to_string()),
+            byte_len: node.contents_skip.0,
+            parameters: Self::parameters(&node.contents.params),
+            scope: ScopeSummary::new(Some(&node.contents.parameter_scope), &node.contents.body_scope),
+        });
+        self.visit_contents()
+    }
+
+    fn enter_lazy_function_expression(&mut self, _path: &WalkPath, node: &mut LazyFunctionExpression) -> Result<VisitMe<()>, ()> {
+        self.functions.push(LazyFunction {
+            kind: LazyFunctionKind::Expression,
+            name: node.name.as_ref()
+                .map(|name| name.name.as_str().to_string()),
+            byte_len: node.contents_skip.0,
+            parameters: Self::parameters(&node.contents.params),
+            scope: ScopeSummary::new(Some(&node.contents.parameter_scope), &node.contents.body_scope),
+        });
+        self.visit_contents()
+    }
+
+    fn enter_lazy_method(&mut self, _path: &WalkPath, node: &mut LazyMethod) -> Result<VisitMe<()>, ()> {
+        self.functions.push(LazyFunction {
+            kind: LazyFunctionKind::Method,
+            name: Self::property_name(&node.name),
+            byte_len: node.contents_skip.0,
+            parameters: Self::parameters(&node.contents.params),
+            scope: ScopeSummary::new(Some(&node.contents.parameter_scope), &node.contents.body_scope),
+        });
+        self.visit_contents()
+    }
+
+    fn enter_lazy_getter(&mut self, _path: &WalkPath, node: &mut LazyGetter) -> Result<VisitMe<()>, ()> {
+        self.functions.push(LazyFunction {
+            kind: LazyFunctionKind::Getter,
+            name: Self::property_name(&node.name),
+            byte_len: node.contents_skip.0,
+            parameters: 0,
+            scope: ScopeSummary::new(None, &node.contents.body_scope),
+        });
+        self.visit_contents()
+    }
+
+    fn enter_lazy_setter(&mut self, _path: &WalkPath, node: &mut LazySetter) -> Result<VisitMe<()>, ()> {
+        self.functions.push(LazyFunction {
+            kind: LazyFunctionKind::Setter,
+            name: Self::property_name(&node.name),
+            byte_len: node.contents_skip.0,
+            parameters: 1,
+            scope: ScopeSummary::new(Some(&node.contents.parameter_scope), &node.contents.body_scope),
+        });
+        self.visit_contents()
+    }
+
+    fn enter_lazy_arrow_expression_with_function_body(&mut self, _path: &WalkPath, node: &mut LazyArrowExpressionWithFunctionBody) -> Result<VisitMe<()>, ()> {
+        self.functions.push(LazyFunction {
+            kind: LazyFunctionKind::Arrow,
+            name: None,
+            byte_len: node.contents_skip.0,
+            parameters: Self::parameters(&node.contents.params),
+            scope: ScopeSummary::new(Some(&node.contents.parameter_scope), &node.contents.body_scope),
+        });
+        self.visit_contents()
+    }
+
+    fn enter_lazy_arrow_expression_with_expression(&mut self, _path: &WalkPath, node: &mut LazyArrowExpressionWithExpression) -> Result<VisitMe<()>, ()> {
+        self.functions.push(LazyFunction {
+            kind: LazyFunctionKind::Arrow,
+            name: None,
+            byte_len: node.contents_skip.0,
+            parameters: Self::parameters(&node.contents.params),
+            scope: ScopeSummary::new(Some(&node.contents.parameter_scope), &node.contents.body_scope),
+        });
+        self.visit_contents()
+    }
+}
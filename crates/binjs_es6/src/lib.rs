@@ -5,15 +5,25 @@
 
 #[macro_use]
 extern crate binjs_io;
+#[macro_use]
 extern crate binjs_shared;
 
 #[macro_use]
 extern crate assert_matches;
 extern crate itertools;
 #[macro_use]
-extern crate json;
-#[macro_use]
 extern crate log;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate tracing;
+
+#[cfg(feature = "arena")]
+extern crate bumpalo;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio_io;
 
 /// A strongly-typed AST for ES6.
 pub mod ast;
@@ -21,8 +31,25 @@ pub mod ast;
 /// Serialization/deserialization utilities.
 pub mod io;
 
+/// A variant of the strongly-typed AST, allocated in an arena.
+#[cfg(feature = "arena")]
+pub mod arena;
+
+/// Asynchronous serialization/deserialization utilities.
+#[cfg(feature = "async")]
+pub mod async_io;
+
 /// Computing scope information from a strongly-typed AST.
 pub mod scopes;
 
+/// Checking that the scope information of an AST is consistent.
+pub mod scope_checks;
+
 /// Introducing laziness in an AST.
 pub mod lazy;
+
+/// Reordering toplevel functions according to a startup profile.
+pub mod reorder;
+
+/// Decoding the contents of lazy functions in parallel.
+pub mod parallel;
@@ -0,0 +1,178 @@
+//! Decoding the contents of lazy functions in parallel.
+//!
+//! The contents of lazy functions are independent ranges of bytes of the tree. While
+//! decoding the tree, the multipart reader skips them and records their ranges, see
+//! `binjs_io::multipart::TreeTokenReader::defer_lazy_subtrees`. Once the rest of the tree
+//! has been decoded, the ranges are decoded by a pool of threads, each with its own reader,
+//! then stitched back into the AST, in the order in which they appear in the file.
+//!
+//! Readers share the strings table of the file, which is checked and converted entirely
+//! on the calling thread before the threads start, see `TreeTokenReader::snapshot`.
+
+use ast::*;
+use io::{ Deserializer, IOPath };
+
+use binjs_io::{ Deserialization, TokenReaderError };
+use binjs_io::multipart::{ DeferredSubtree, TreeSnapshot };
+use binjs_shared::VisitMe;
+
+use std;
+use std::sync::{ Arc, Mutex };
+use std::sync::mpsc::channel;
+use std::thread;
+
+/// The decoded contents of a lazy function.
+pub enum LazyContents {
+    /// For `LazyFunctionDeclaration` and `LazyMethod`.
+    FunctionOrMethod(FunctionOrMethodContents),
+    FunctionExpression(FunctionExpressionContents),
+    Getter(GetterContents),
+    Setter(SetterContents),
+    ArrowWithFunctionBody(ArrowExpressionContentsWithFunctionBody),
+    ArrowWithExpression(ArrowExpressionContentsWithExpression),
+}
+
+/// Decode `subtrees` with `jobs` threads, returning their contents in the same order.
+///
+/// If several subtrees cannot be decoded, report the error of the first one.
+pub fn decode_subtrees(snapshot: &TreeSnapshot, subtrees: Vec<DeferredSubtree>, jobs: usize) -> Result<Vec<LazyContents>, TokenReaderError> {
+    let len = subtrees.len();
+    let queue = Arc::new(Mutex::new(subtrees.into_iter().enumerate()));
+    let (sender, receiver) = channel();
+    let mut workers = vec![];
+    for _ in 0..std::cmp::min(std::cmp::max(jobs, 1), len) {
+        let queue = queue.clone();
+        let sender = sender.clone();
+        let snapshot = snapshot.clone();
+        workers.push(thread::spawn(move || {
+            // Reuse the reader, as each reader owns a copy of the tree.
+            let mut deserializer = Deserializer::new(snapshot.reader());
+            loop {
+                let next = queue.lock()
+                    .unwrap()
+                    .next();
+                let (index, subtree) = match next {
+                    None => return,
+                    Some(job) => job
+                };
+                let result = decode_subtree(&mut deserializer, &subtree);
+                if sender.send((index, result)).is_err() {
+                    // The receiver has given up.
+                    return;
+                }
+            }
+        }));
+    }
+    drop(sender);
+
+    let mut results : Vec<Option<Result<LazyContents, TokenReaderError>>> = (0..len)
+        .map(|_| None)
+        .collect();
+    for (index, result) in receiver {
+        results[index] = Some(result);
+    }
+    for worker in workers {
+        worker.join()
+            .expect("A decoding thread panicked");
+    }
+    results.into_iter()
+        .map(|result| result.expect("Every subtree has been decoded"))
+        .collect()
+}
+
+/// Decode a single subtree, checking that it ends where expected.
+///
+/// Also used to decode the chunks received by `TreeTokenReader::receive_chunk`.
+pub fn decode_subtree(deserializer: &mut Deserializer<::binjs_io::multipart::TreeTokenReader>, subtree: &DeferredSubtree) -> Result<LazyContents, TokenReaderError> {
+    debug!(target: "parallel", "Decoding {} bytes of {} at {}", subtree.byte_len, subtree.interface.as_str(), subtree.offset);
+    deserializer.reader.seek_to(subtree.offset)?;
+    let mut path = IOPath::new();
+    let contents = match subtree.interface.as_str() {
+        "LazyFunctionDeclaration" | "LazyMethod" =>
+            LazyContents::FunctionOrMethod(deserializer.deserialize(&mut path)?),
+        "LazyFunctionExpression" =>
+            LazyContents::FunctionExpression(deserializer.deserialize(&mut path)?),
+        "LazyGetter" =>
+            LazyContents::Getter(deserializer.deserialize(&mut path)?),
+        "LazySetter" =>
+            LazyContents::Setter(deserializer.deserialize(&mut path)?),
+        "LazyArrowExpressionWithFunctionBody" =>
+            LazyContents::ArrowWithFunctionBody(deserializer.deserialize(&mut path)?),
+        "LazyArrowExpressionWithExpression" =>
+            LazyContents::ArrowWithExpression(deserializer.deserialize(&mut path)?),
+        _ => return Err(TokenReaderError::invalid_value(&subtree.interface))
+    };
+    let expected = subtree.offset + subtree.byte_len as u64;
+    let found = deserializer.reader.position()?;
+    if found != expected {
+        return Err(TokenReaderError::EndOffsetError {
+            start: subtree.offset,
+            expected,
+            found,
+            description: format!("{} contents", subtree.interface.as_str()),
+        });
+    }
+    Ok(contents)
+}
+
+/// A visitor replacing the placeholder contents of lazy functions with decoded contents,
+/// in the order in which they appear in the file.
+pub struct Stitcher {
+    contents: std::vec::IntoIter<LazyContents>,
+}
+impl Stitcher {
+    pub fn new(contents: Vec<LazyContents>) -> Self {
+        Stitcher {
+            contents: contents.into_iter(),
+        }
+    }
+
+    /// Stitch the contents into `program`.
+    ///
+    /// Fails with `TokenReaderError::InvalidValue` if the contents do not match the lazy
+    /// functions of `program`.
+    pub fn stitch(mut self, program: &mut Program) -> Result<(), TokenReaderError> {
+        program.walk(&mut WalkPath::new(), &mut self)?;
+        if self.contents.next().is_some() {
+            return Err(TokenReaderError::InvalidValue);
+        }
+        Ok(())
+    }
+}
+
+macro_rules! stitch {
+    ( $self_:ident, $node:ident, $variant:ident ) => {
+        match $self_.contents.next() {
+            Some(LazyContents::$variant(contents)) => {
+                $node.contents = contents;
+                // Lazy functions nested in the contents have been decoded with them.
+                Ok(VisitMe::DoneHere)
+            }
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+}
+
+impl Visitor<TokenReaderError> for Stitcher {
+    fn enter_lazy_function_declaration(&mut self, _path: &WalkPath, node: &mut LazyFunctionDeclaration) -> Result<VisitMe<()>, TokenReaderError> {
+        stitch!(self, node, FunctionOrMethod)
+    }
+    fn enter_lazy_method(&mut self, _path: &WalkPath, node: &mut LazyMethod) -> Result<VisitMe<()>, TokenReaderError> {
+        stitch!(self, node, FunctionOrMethod)
+    }
+    fn enter_lazy_function_expression(&mut self, _path: &WalkPath, node: &mut LazyFunctionExpression) -> Result<VisitMe<()>, TokenReaderError> {
+        stitch!(self, node, FunctionExpression)
+    }
+    fn enter_lazy_getter(&mut self, _path: &WalkPath, node: &mut LazyGetter) -> Result<VisitMe<()>, TokenReaderError> {
+        stitch!(self, node, Getter)
+    }
+    fn enter_lazy_setter(&mut self, _path: &WalkPath, node: &mut LazySetter) -> Result<VisitMe<()>, TokenReaderError> {
+        stitch!(self, node, Setter)
+    }
+    fn enter_lazy_arrow_expression_with_function_body(&mut self, _path: &WalkPath, node: &mut LazyArrowExpressionWithFunctionBody) -> Result<VisitMe<()>, TokenReaderError> {
+        stitch!(self, node, ArrowWithFunctionBody)
+    }
+    fn enter_lazy_arrow_expression_with_expression(&mut self, _path: &WalkPath, node: &mut LazyArrowExpressionWithExpression) -> Result<VisitMe<()>, TokenReaderError> {
+        stitch!(self, node, ArrowWithExpression)
+    }
+}
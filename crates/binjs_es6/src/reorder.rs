@@ -0,0 +1,161 @@
+//! Reordering the toplevel function declarations of a script, so that the functions
+//! executed at startup appear early in the stream.
+//!
+//! Toplevel function declarations are instantiated before the script starts executing,
+//! wherever they appear among its statements, so moving them does not change the
+//! behavior of the script, with two exceptions, which we avoid:
+//!
+//! - if several function declarations share a name, the last one wins, so these
+//!   declarations are never moved;
+//! - the functions of modules may be exported, so modules are left unchanged.
+//!
+//! The only remaining observable difference is the order in which the properties of
+//! the global object are created.
+//!
+//! With chunked multipart files, the contents of lazy functions are chunks in the order
+//! of the tree, so the functions executed at startup come first in the file and clients
+//! may fetch them before the others.
+
+use ast::*;
+use binjs_io::startup::StartupProfile;
+use binjs_shared::{ IdentifierName, VisitMe };
+
+use std::collections::HashMap;
+
+/// A visitor counting the functions that may be lazified, i.e. function declarations,
+/// function expressions, methods, getters and setters, as `lazy::LazifierVisitor`
+/// numbers them.
+struct FunctionCounter {
+    functions: u32,
+}
+impl Visitor<()> for FunctionCounter {
+    fn enter_method_definition(&mut self, _path: &WalkPath, _node: &mut ViewMutMethodDefinition) -> Result<VisitMe<()>, ()> {
+        self.functions += 1;
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn enter_function_declaration(&mut self, _path: &WalkPath, _node: &mut ViewMutFunctionDeclaration) -> Result<VisitMe<()>, ()> {
+        self.functions += 1;
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn enter_function_expression(&mut self, _path: &WalkPath, _node: &mut ViewMutFunctionExpression) -> Result<VisitMe<()>, ()> {
+        self.functions += 1;
+        Ok(VisitMe::HoldThis(()))
+    }
+}
+
+/// The name of `statement`, if it is a function declaration.
+fn declared_function(statement: &Statement) -> Option<&IdentifierName> {
+    match *statement {
+        Statement::EagerFunctionDeclaration(ref declaration) => Some(&declaration.name.name),
+        Statement::LazyFunctionDeclaration(ref declaration) => Some(&declaration.name.name),
+        _ => None
+    }
+}
+
+/// Move the toplevel function declarations of `program` that were executed at startup
+/// according to `profile` before its other statements, see `reorder_script`.
+///
+/// Returns the original index of each statement, or an empty vector if `program`
+/// is a module.
+pub fn reorder_program(program: &mut Program, profile: &StartupProfile) -> Vec<usize> {
+    match *program {
+        Program::Script(ref mut script) => reorder_script(script, profile),
+        Program::Module(_) => vec![],
+    }
+}
+
+/// Move the toplevel function declarations of `script` that were executed at startup
+/// according to `profile` before its other statements.
+///
+/// Functions are identified as in `lazy::Policy::Profile`, i.e. by their index in the
+/// script before reordering, so laziness may be introduced before or after reordering.
+/// Otherwise, the order of statements is preserved.
+///
+/// Returns the original index of each statement, e.g. to reorder source positions.
+pub fn reorder_script(script: &mut Script, profile: &StartupProfile) -> Vec<usize> {
+    let mut declarations = HashMap::new();
+    for statement in &script.statements {
+        if let Some(name) = declared_function(statement) {
+            *declarations.entry(name.clone()).or_insert(0) += 1;
+        }
+    }
+
+    // Find the index of each toplevel function, in the order of the script.
+    let mut counter = FunctionCounter {
+        functions: 0
+    };
+    let mut hot = vec![];
+    for statement in script.statements.iter_mut() {
+        let id = counter.functions;
+        statement.walk(&mut WalkPath::new(), &mut counter)
+            .expect("Counting functions should never fail");
+        hot.push(match declared_function(statement) {
+            Some(name) => profile.is_executed(id) && declarations[name] == 1,
+            None => false
+        });
+    }
+
+    let order : Vec<usize> = (0..hot.len())
+        .filter(|&index| hot[index])
+        .chain((0..hot.len()).filter(|&index| !hot[index]))
+        .collect();
+    debug!(target: "reorder", "Moving {} function declarations first", hot.iter().filter(|&&hot| hot).count());
+
+    let mut statements : Vec<_> = script.statements.drain(..)
+        .map(Some)
+        .collect();
+    script.statements = order.iter()
+        .map(|&index| statements[index].take()
+            .expect("Each statement is moved once"))
+        .collect();
+    order
+}
+
+/// Renumber the functions of `profile` after the statements of `program` were
+/// reordered by `reorder_program`, which returned `order`.
+///
+/// The function IDs of a profile depend on the order of functions in the program,
+/// so the profile used to reorder a program no longer matches the reordered program.
+pub fn reorder_profile(program: &mut Program, order: &[usize], profile: &StartupProfile) -> StartupProfile {
+    let script = match *program {
+        Program::Script(ref mut script) => script,
+        Program::Module(_) => return profile.clone(),
+    };
+    assert_eq!(script.statements.len(), order.len());
+
+    // The number of functions in each statement, in the new order.
+    let counts : Vec<u32> = script.statements.iter_mut()
+        .map(|statement| {
+            let mut counter = FunctionCounter {
+                functions: 0
+            };
+            statement.walk(&mut WalkPath::new(), &mut counter)
+                .expect("Counting functions should never fail");
+            counter.functions
+        })
+        .collect();
+
+    // The ID of the first function of each statement, in the original order.
+    let mut original_counts = vec![0; order.len()];
+    for (&original, &count) in order.iter().zip(&counts) {
+        original_counts[original] = count;
+    }
+    let mut original_starts = Vec::with_capacity(order.len());
+    let mut start = 0;
+    for count in original_counts {
+        original_starts.push(start);
+        start += count;
+    }
+
+    let mut result = StartupProfile::new();
+    let mut id = 0;
+    for (&original, &count) in order.iter().zip(&counts) {
+        for offset in 0..count {
+            if profile.is_executed(original_starts[original] + offset) {
+                result.set_executed(id);
+            }
+            id += 1;
+        }
+    }
+    result
+}
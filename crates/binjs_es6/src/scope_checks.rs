@@ -0,0 +1,412 @@
+//! Checking that the scope annotations of an AST are consistent with each other.
+//!
+//! Scope annotations are computed by the encoder (see module `scopes`) and trusted
+//! by engines. A malformed file may contain annotations that no encoder could have
+//! produced, e.g. a name declared twice in the same scope, which engines would only
+//! notice much later, if at all.
+//!
+//! Most checks only compare annotations with each other:
+//!
+//! - a name is declared at most once per scope;
+//! - positional parameters have distinct indices and there is at most one rest parameter;
+//! - the names of parameters are distinct, unless the parameter list is simple;
+//! - the body of a function does not lexically declare one of its parameters;
+//! - the name of a function expression may only be captured if the function has a name.
+//!
+//! One more check compares annotations with the code they annotate: a name used by a
+//! function nested in the scope that declares it must be declared as captured, as
+//! engines may otherwise store it in a place that the nested function cannot reach.
+//! Names that are not declared anywhere, e.g. builtins, are not checked.
+
+use ast::*;
+use binjs_shared::{ IdentifierName, VisitMe };
+
+use std::collections::{ HashMap, HashSet };
+use std::string::String; // Rather than `ast::String`.
+
+/// What to do with scope annotations that are inconsistent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScopeCheckPolicy {
+    /// Do not check scope annotations.
+    Ignore,
+
+    /// Log each inconsistency as a warning, then accept the AST.
+    Warn,
+
+    /// Reject any AST with inconsistent scope annotations.
+    Reject,
+}
+impl Default for ScopeCheckPolicy {
+    fn default() -> Self {
+        ScopeCheckPolicy::Ignore
+    }
+}
+impl ScopeCheckPolicy {
+    /// The names of the policies, as used on the command-line.
+    pub const NAMES: &'static [&'static str] = &["ignore", "warn", "reject"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ignore" => Some(ScopeCheckPolicy::Ignore),
+            "warn" => Some(ScopeCheckPolicy::Warn),
+            "reject" => Some(ScopeCheckPolicy::Reject),
+            _ => None
+        }
+    }
+}
+
+/// Check the scope annotations of `ast`.
+///
+/// Returns one message per inconsistency, in the order in which they appear in the AST.
+pub fn check<'a, T>(ast: &'a mut T) -> Vec<String> where T: Walker<'a> {
+    let mut checker = ScopeChecker::new();
+    ast.walk(&mut WalkPath::new(), &mut checker)
+        .expect("Checking scopes should never fail");
+    checker.problems
+}
+
+fn parameter_name(param: &AssertedMaybePositionalParameterName) -> &IdentifierName {
+    match *param {
+        AssertedMaybePositionalParameterName::AssertedPositionalParameterName(ref p) => &p.name,
+        AssertedMaybePositionalParameterName::AssertedRestParameterName(ref p) => &p.name,
+        AssertedMaybePositionalParameterName::AssertedParameterName(ref p) => &p.name,
+    }
+}
+
+/// The names declared by a scope annotation, with their `is_captured` flag.
+struct Scope {
+    /// The number of functions enclosing the annotation.
+    function_depth: usize,
+    names: HashMap<IdentifierName, bool>,
+}
+
+/// A visitor collecting the inconsistencies between scope annotations.
+struct ScopeChecker {
+    problems: Vec<String>,
+
+    /// The scopes in which names are currently resolved, innermost last.
+    scopes: Vec<Scope>,
+
+    /// For each node owning scope annotations, the length of `scopes` when
+    /// we entered it, so that we can drop its scopes when we leave it.
+    scope_marks: Vec<usize>,
+
+    /// The number of functions enclosing the current node.
+    function_depth: usize,
+}
+impl ScopeChecker {
+    fn new() -> Self {
+        ScopeChecker {
+            problems: vec![],
+            scopes: vec![],
+            scope_marks: vec![],
+            function_depth: 0,
+        }
+    }
+
+    fn push_scope<'b, I>(&mut self, names: I) where I: IntoIterator<Item = (&'b IdentifierName, bool)> {
+        let mut scope = Scope {
+            function_depth: self.function_depth,
+            names: HashMap::new(),
+        };
+        for (name, is_captured) in names {
+            *scope.names.entry(name.clone())
+                .or_insert(false) |= is_captured;
+        }
+        self.scopes.push(scope);
+    }
+
+    fn enter_scope_owner(&mut self) {
+        self.scope_marks.push(self.scopes.len());
+    }
+    fn exit_scope_owner(&mut self) {
+        let mark = self.scope_marks.pop()
+            .expect("Unbalanced scopes");
+        self.scopes.truncate(mark);
+    }
+
+    fn enter_function(&mut self) {
+        self.enter_scope_owner();
+        self.function_depth += 1;
+    }
+    fn exit_function(&mut self) {
+        self.function_depth -= 1;
+        self.exit_scope_owner();
+    }
+
+    /// Check that a name used across a function boundary is declared as captured.
+    fn check_reference(&mut self, path: &WalkPath, name: &IdentifierName) {
+        let function_depth = self.function_depth;
+        let uncaptured = match self.scopes.iter_mut()
+            .rev()
+            .filter_map(|scope| {
+                let depth = scope.function_depth;
+                scope.names.get_mut(name)
+                    .map(|is_captured| (depth, is_captured))
+            })
+            .next()
+        {
+            Some((depth, is_captured)) if depth < function_depth && !*is_captured => {
+                // Report each declaration once, however many times it is used.
+                *is_captured = true;
+                true
+            }
+            _ => false
+        };
+        if uncaptured {
+            let message = format!("{:?} is used in a nested function but is not declared as captured", name.as_str());
+            self.report(path, message);
+        }
+    }
+
+    fn report(&mut self, path: &WalkPath, message: String) {
+        debug!(target: "scope_checks", "At {:?}: {}", path, message);
+        self.problems.push(format!("At {:?}: {}", path, message));
+    }
+
+    fn check_declared_names(&mut self, path: &WalkPath, declared_names: &[AssertedDeclaredName]) {
+        let mut kinds: HashMap<&IdentifierName, &AssertedDeclaredKind> = HashMap::new();
+        for declared in declared_names {
+            if let Some(kind) = kinds.insert(&declared.name, &declared.kind) {
+                let message = if *kind == declared.kind {
+                    format!("{:?} is declared twice as {:?}", declared.name.as_str(), kind)
+                } else {
+                    format!("{:?} is declared both as {:?} and as {:?}", declared.name.as_str(), kind, declared.kind)
+                };
+                self.report(path, message);
+            }
+        }
+    }
+
+    /// Check that the lexical declarations of the body of a function do not
+    /// shadow its parameters, which is an early error.
+    fn check_parameters_against_body(&mut self, path: &WalkPath, parameter_scope: &AssertedParameterScope, body_scope: &AssertedVarScope) {
+        let parameters: HashSet<&IdentifierName> = parameter_scope.param_names.iter()
+            .map(parameter_name)
+            .collect();
+        for declared in &body_scope.declared_names {
+            if declared.kind != AssertedDeclaredKind::Var && parameters.contains(&declared.name) {
+                let message = format!("{:?} is both a parameter and declared as {:?} in the function body", declared.name.as_str(), declared.kind);
+                self.report(path, message);
+            }
+        }
+    }
+
+    fn check_function_name(&mut self, path: &WalkPath, name: &Option<BindingIdentifier>, contents: &FunctionExpressionContents) {
+        if name.is_none() && contents.is_function_name_captured {
+            self.report(path, "the name of an anonymous function expression is captured".to_string());
+        }
+    }
+}
+
+impl Visitor<()> for ScopeChecker {
+    fn enter_asserted_block_scope(&mut self, path: &WalkPath, node: &mut AssertedBlockScope) -> Result<VisitMe<()>, ()> {
+        self.check_declared_names(path, &node.declared_names);
+        self.push_scope(node.declared_names.iter()
+            .map(|declared| (&declared.name, declared.is_captured)));
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_asserted_script_global_scope(&mut self, path: &WalkPath, node: &mut AssertedScriptGlobalScope) -> Result<VisitMe<()>, ()> {
+        self.check_declared_names(path, &node.declared_names);
+        self.push_scope(node.declared_names.iter()
+            .map(|declared| (&declared.name, declared.is_captured)));
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_asserted_var_scope(&mut self, path: &WalkPath, node: &mut AssertedVarScope) -> Result<VisitMe<()>, ()> {
+        self.check_declared_names(path, &node.declared_names);
+        self.push_scope(node.declared_names.iter()
+            .map(|declared| (&declared.name, declared.is_captured)));
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_asserted_bound_names_scope(&mut self, path: &WalkPath, node: &mut AssertedBoundNamesScope) -> Result<VisitMe<()>, ()> {
+        let mut names = HashSet::new();
+        for bound in &node.bound_names {
+            if !names.insert(&bound.name) {
+                let message = format!("{:?} is bound twice", bound.name.as_str());
+                self.report(path, message);
+            }
+        }
+        self.push_scope(node.bound_names.iter()
+            .map(|bound| (&bound.name, bound.is_captured)));
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_asserted_parameter_scope(&mut self, path: &WalkPath, node: &mut AssertedParameterScope) -> Result<VisitMe<()>, ()> {
+        let mut indices = HashSet::new();
+        let mut names = HashSet::new();
+        let mut has_rest = false;
+        for param in &node.param_names {
+            match *param {
+                AssertedMaybePositionalParameterName::AssertedPositionalParameterName(ref p) => {
+                    if !indices.insert(p.index) {
+                        let message = format!("several parameters have position {}", p.index);
+                        self.report(path, message);
+                    }
+                }
+                AssertedMaybePositionalParameterName::AssertedRestParameterName(_) => {
+                    if has_rest {
+                        self.report(path, "several parameters are rest parameters".to_string());
+                    }
+                    has_rest = true;
+                }
+                AssertedMaybePositionalParameterName::AssertedParameterName(_) => {}
+            }
+            // Sloppy functions with a simple parameter list may repeat parameters, e.g. `function(a, a) {}`.
+            let name = parameter_name(param);
+            if !names.insert(name) && !node.is_simple_parameter_list {
+                let message = format!("{:?} is a parameter twice, in a parameter list that is not simple", name.as_str());
+                self.report(path, message);
+            }
+        }
+        self.push_scope(node.param_names.iter()
+            .map(|param| {
+                let is_captured = match *param {
+                    AssertedMaybePositionalParameterName::AssertedPositionalParameterName(ref p) => p.is_captured,
+                    AssertedMaybePositionalParameterName::AssertedRestParameterName(ref p) => p.is_captured,
+                    AssertedMaybePositionalParameterName::AssertedParameterName(ref p) => p.is_captured,
+                };
+                (parameter_name(param), is_captured)
+            }));
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    // Nodes owning scope annotations.
+
+    fn enter_script(&mut self, _path: &WalkPath, _node: &mut Script) -> Result<VisitMe<()>, ()> {
+        self.enter_scope_owner();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_script(&mut self, _path: &WalkPath, _node: &mut Script) -> Result<Option<Script>, ()> {
+        self.exit_scope_owner();
+        Ok(None)
+    }
+
+    fn enter_module(&mut self, _path: &WalkPath, _node: &mut Module) -> Result<VisitMe<()>, ()> {
+        self.enter_scope_owner();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_module(&mut self, _path: &WalkPath, _node: &mut Module) -> Result<Option<Module>, ()> {
+        self.exit_scope_owner();
+        Ok(None)
+    }
+
+    fn enter_block(&mut self, _path: &WalkPath, _node: &mut Block) -> Result<VisitMe<()>, ()> {
+        self.enter_scope_owner();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_block(&mut self, _path: &WalkPath, _node: &mut Block) -> Result<Option<Block>, ()> {
+        self.exit_scope_owner();
+        Ok(None)
+    }
+
+    fn enter_catch_clause(&mut self, _path: &WalkPath, _node: &mut CatchClause) -> Result<VisitMe<()>, ()> {
+        self.enter_scope_owner();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_catch_clause(&mut self, _path: &WalkPath, _node: &mut CatchClause) -> Result<Option<CatchClause>, ()> {
+        self.exit_scope_owner();
+        Ok(None)
+    }
+
+    fn enter_function_expression_contents(&mut self, path: &WalkPath, node: &mut FunctionExpressionContents) -> Result<VisitMe<()>, ()> {
+        self.check_parameters_against_body(path, &node.parameter_scope, &node.body_scope);
+        self.enter_function();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_function_expression_contents(&mut self, _path: &WalkPath, _node: &mut FunctionExpressionContents) -> Result<Option<FunctionExpressionContents>, ()> {
+        self.exit_function();
+        Ok(None)
+    }
+
+    fn enter_function_or_method_contents(&mut self, path: &WalkPath, node: &mut FunctionOrMethodContents) -> Result<VisitMe<()>, ()> {
+        self.check_parameters_against_body(path, &node.parameter_scope, &node.body_scope);
+        self.enter_function();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_function_or_method_contents(&mut self, _path: &WalkPath, _node: &mut FunctionOrMethodContents) -> Result<Option<FunctionOrMethodContents>, ()> {
+        self.exit_function();
+        Ok(None)
+    }
+
+    fn enter_getter_contents(&mut self, _path: &WalkPath, _node: &mut GetterContents) -> Result<VisitMe<()>, ()> {
+        self.enter_function();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_getter_contents(&mut self, _path: &WalkPath, _node: &mut GetterContents) -> Result<Option<GetterContents>, ()> {
+        self.exit_function();
+        Ok(None)
+    }
+
+    fn enter_setter_contents(&mut self, path: &WalkPath, node: &mut SetterContents) -> Result<VisitMe<()>, ()> {
+        self.check_parameters_against_body(path, &node.parameter_scope, &node.body_scope);
+        self.enter_function();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_setter_contents(&mut self, _path: &WalkPath, _node: &mut SetterContents) -> Result<Option<SetterContents>, ()> {
+        self.exit_function();
+        Ok(None)
+    }
+
+    fn enter_arrow_expression_contents_with_function_body(&mut self, path: &WalkPath, node: &mut ArrowExpressionContentsWithFunctionBody) -> Result<VisitMe<()>, ()> {
+        self.check_parameters_against_body(path, &node.parameter_scope, &node.body_scope);
+        self.enter_function();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_arrow_expression_contents_with_function_body(&mut self, _path: &WalkPath, _node: &mut ArrowExpressionContentsWithFunctionBody) -> Result<Option<ArrowExpressionContentsWithFunctionBody>, ()> {
+        self.exit_function();
+        Ok(None)
+    }
+
+    fn enter_arrow_expression_contents_with_expression(&mut self, path: &WalkPath, node: &mut ArrowExpressionContentsWithExpression) -> Result<VisitMe<()>, ()> {
+        self.check_parameters_against_body(path, &node.parameter_scope, &node.body_scope);
+        self.enter_function();
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_arrow_expression_contents_with_expression(&mut self, _path: &WalkPath, _node: &mut ArrowExpressionContentsWithExpression) -> Result<Option<ArrowExpressionContentsWithExpression>, ()> {
+        self.exit_function();
+        Ok(None)
+    }
+
+    fn enter_eager_function_expression(&mut self, path: &WalkPath, node: &mut EagerFunctionExpression) -> Result<VisitMe<()>, ()> {
+        self.check_function_name(path, &node.name, &node.contents);
+        // The name of a function expression is bound in a scope of its own, around the function.
+        self.enter_scope_owner();
+        if let Some(ref name) = node.name {
+            self.push_scope(Some((&name.name, node.contents.is_function_name_captured)));
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_eager_function_expression(&mut self, _path: &WalkPath, _node: &mut EagerFunctionExpression) -> Result<Option<EagerFunctionExpression>, ()> {
+        self.exit_scope_owner();
+        Ok(None)
+    }
+
+    fn enter_lazy_function_expression(&mut self, path: &WalkPath, node: &mut LazyFunctionExpression) -> Result<VisitMe<()>, ()> {
+        self.check_function_name(path, &node.name, &node.contents);
+        // The name of a function expression is bound in a scope of its own, around the function.
+        self.enter_scope_owner();
+        if let Some(ref name) = node.name {
+            self.push_scope(Some((&name.name, node.contents.is_function_name_captured)));
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_lazy_function_expression(&mut self, _path: &WalkPath, _node: &mut LazyFunctionExpression) -> Result<Option<LazyFunctionExpression>, ()> {
+        self.exit_scope_owner();
+        Ok(None)
+    }
+
+    // Uses of names.
+
+    fn enter_identifier_expression(&mut self, path: &WalkPath, node: &mut IdentifierExpression) -> Result<VisitMe<()>, ()> {
+        self.check_reference(path, &node.name);
+        Ok(VisitMe::HoldThis(()))
+    }
+
+    fn enter_assignment_target_identifier(&mut self, path: &WalkPath, node: &mut AssignmentTargetIdentifier) -> Result<VisitMe<()>, ()> {
+        self.check_reference(path, &node.name);
+        Ok(VisitMe::HoldThis(()))
+    }
+}
@@ -1,10 +1,10 @@
 use ast::*;
-use binjs_shared::{ FromJSON, IdentifierName, ToJSON, VisitMe };
+use binjs_shared::{ FromJSON, IdentifierName, JSON, ToJSON, VisitMe };
 
 use std::collections::{  HashSet, HashMap };
 
 use itertools::Itertools;
-use json::JsonValue as JSON;
+use tracing;
 
 #[derive(Debug, PartialEq, Eq)]
 enum BindingKind {
@@ -600,7 +600,7 @@ impl Visitor<()> for AnnotationVisitor {
             path = path);
         match self.binding_kind_stack.last() {
             None => {
-                println!("exit_binding identifier – marking {name:?} at {path:?}",
+                tracing::warn!(target: "annotating", "exit_binding identifier without a binding kind – marking {name:?} at {path:?}",
                        name = node.name,
                        path = path);
             }
@@ -761,6 +761,26 @@ impl Visitor<()> for AnnotationVisitor {
         Ok(None)
     }
 
+    // Imports, which bind names immutably at the toplevel of a module.
+
+    fn enter_import(&mut self, _path: &WalkPath, _node: &mut Import) -> Result<VisitMe<()>, ()> {
+        self.binding_kind_stack.push(BindingKind::ConstLexical);
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_import(&mut self, _path: &WalkPath, _node: &mut Import) -> Result<Option<Import>, ()> {
+        assert_eq!(self.binding_kind_stack.pop().unwrap(), BindingKind::ConstLexical);
+        Ok(None)
+    }
+
+    fn enter_import_namespace(&mut self, _path: &WalkPath, _node: &mut ImportNamespace) -> Result<VisitMe<()>, ()> {
+        self.binding_kind_stack.push(BindingKind::ConstLexical);
+        Ok(VisitMe::HoldThis(()))
+    }
+    fn exit_import_namespace(&mut self, _path: &WalkPath, _node: &mut ImportNamespace) -> Result<Option<ImportNamespace>, ()> {
+        assert_eq!(self.binding_kind_stack.pop().unwrap(), BindingKind::ConstLexical);
+        Ok(None)
+    }
+
     // Functions, methods, arguments.
     fn enter_setter_contents(&mut self, path: &WalkPath, _node: &mut SetterContents) -> Result<VisitMe<()>, ()> {
         // Just like FormalParameters, push BindingKind::RestParam to mark
@@ -926,7 +946,9 @@ impl Visitor<()> for AnnotationVisitor {
         debug!(target: "annotating", "exit_eager_function_declaration sees {:?} at {:?}", node.name.name, path.get(0));
         match path.get(0).expect("Impossible AST walk") {
             &WalkPathItem { field: ASTField::Statements, interface: ASTNode::Script } |
-            &WalkPathItem { field: ASTField::Statements, interface: ASTNode::Module } => {
+            &WalkPathItem { field: ASTField::Items, interface: ASTNode::Module } |
+            &WalkPathItem { field: ASTField::Declaration, interface: ASTNode::Export } |
+            &WalkPathItem { field: ASTField::Body, interface: ASTNode::ExportDefault } => {
                 // Case 1.
                 debug!(target: "annotating", "exit_eager_function_declaration says it's a var (case 1)");
                 self.var_names_stack.last_mut()
@@ -1172,6 +1194,17 @@ impl AnnotationVisitor {
         script.walk(&mut WalkPath::new(), &mut cleanup)
             .expect("Could not walk script for eval cleanup");
     }
+    /// Annotate a script or a module.
+    pub fn annotate_program(&mut self, program: &mut Program) {
+        program.walk(&mut WalkPath::new(), self)
+            .expect("Could not walk program");
+
+        let mut cleanup = EvalCleanupAnnotator {
+            eval_bindings: vec![false]
+        };
+        program.walk(&mut WalkPath::new(), &mut cleanup)
+            .expect("Could not walk program for eval cleanup");
+    }
     pub fn annotate(&mut self, ast: &mut JSON) {
         // Import script
         let mut script = Script::import(ast)
@@ -26,6 +26,16 @@ fn main() {
             Arg::with_name("OUTPUT")
                 .required(true)
                 .help("Prefix of output files to use. OUTPUT-strong.rs and OUTPUT-generic.rs will be produced."),
+            Arg::with_name("cpp-header")
+                .long("cpp-header")
+                .takes_value(true)
+                .help("If specified, also export C++ tables of interfaces, fields and string enums to this header file, for engine-side decoders."),
+            Arg::with_name("cpp-namespace")
+                .long("cpp-namespace")
+                .takes_value(true)
+                .default_value("es6")
+                .requires("cpp-header")
+                .help("The namespace of the C++ tables, within namespace `binast`."),
         ])
     .get_matches();
 
@@ -55,6 +65,18 @@ fn main() {
             null: &null,
         });
 
+    if let Some(header_path) = matches.value_of("cpp-header") {
+        println!("...exporting C++ tables to {}", header_path);
+        let namespace = matches.value_of("cpp-namespace")
+            .unwrap(); // Guaranteed by `clap`.
+        let header = CppExporter::new(&spec)
+            .to_cpp_header(namespace);
+        let mut dest = File::create(header_path)
+            .expect("Could not create C++ header output");
+        dest.write_all(header.as_bytes())
+            .expect("Could not write C++ header output");
+    }
+
     println!("...generating source code");
     let exporter = RustExporter::new(spec);
     let code = exporter.to_rust_source();
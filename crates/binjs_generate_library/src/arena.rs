@@ -0,0 +1,477 @@
+//! Generate an arena-allocated variant of the strongly-typed AST.
+
+use binjs_meta::export::{ TypeDeanonymizer, TypeName };
+use binjs_meta::spec::*;
+use binjs_meta::util::*;
+
+use std::collections::HashSet;
+
+use itertools::Itertools;
+
+/// The types of strings, implemented by hand alongside the generated source.
+const STRING_TYPES : [&'static str; 5] = ["BigInt", "IdentifierName", "PropertyKey", "RegExpFlags", "RegExpPattern"];
+
+/// Generate Rust source for a variant of the strongly-typed AST whose nodes, lists
+/// and strings are borrowed from an arena for lifetime `'a`.
+///
+/// The generated source expects to be included in a module that implements the
+/// string types (`SharedString<'a>`, `IdentifierName<'a>`, ...), `Deserializer<'a, R>`,
+/// `Decodable<'a>` and `ToAST`, and the deserialization of lists into `&'a [T]`.
+/// String enums do not borrow anything, so they are reused from module `ast`.
+pub struct ArenaExporter<'a> {
+    spec: &'a Spec
+}
+impl<'a> ArenaExporter<'a> {
+    /// Create an arena exporter from the original specifications.
+    pub fn new(spec: &'a Spec) -> Self {
+        ArenaExporter {
+            spec
+        }
+    }
+
+    pub fn to_rust_source(&self) -> String {
+        let deanonymized = TypeDeanonymizer::new(self.spec)
+            .into_spec(SpecOptions {
+                root: self.spec.get_root_name(),
+                null: self.spec.get_null_name(),
+            });
+        let null_name = self.spec.get_null_name().to_str();
+        let borrowing = Self::borrowing_types(&deanonymized);
+        let lifetime = |name: &str| if borrowing.contains(name) { "<'a>" } else { "" };
+
+        let mut buffer = String::new();
+        buffer.push_str("// This file was generated by binjs_meta generate_library.
+
+use binjs_io::{ Deserialization, InnerDeserialization, TokenReader, TokenReaderError };
+use binjs_shared::{ FieldName, Offset };
+
+use ast;
+use io::IOPath;
+
+");
+
+        buffer.push_str("\n// String enums (by lexicographical order)\n");
+        let string_enums : Vec<_> = deanonymized.string_enums_by_name()
+            .keys()
+            .sorted();
+        buffer.push_str(&format!("pub use ast::{{ {} }};\n",
+            string_enums.iter()
+                .map(|name| name.to_class_cases())
+                .format(", ")));
+        for name in string_enums {
+            let string_enum = deanonymized.string_enums_by_name().get(&name).unwrap();
+            buffer.push_str(&format!("
+impl<'a, R> Deserialization<R, {name}> for Deserializer<'a, R> where R: TokenReader {{
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<{name}, TokenReaderError> {{
+        let result = match self.reader.string_enum_at(path)?.as_str() {{
+{variants}
+            _ => Err(From::from(TokenReaderError::invalid_value(&\"{lowercase_name}\"))),
+        }};
+        if result.is_err() {{
+            self.reader.poison();
+        }}
+        result
+    }}
+}}
+impl ToAST for {name} {{
+    type Target = {name};
+    fn to_ast(&self) -> {name} {{
+        self.clone()
+    }}
+}}
+",
+                name = name.to_class_cases(),
+                lowercase_name = name.to_rust_identifier_case(),
+                variants = string_enum.strings()
+                    .iter()
+                    .map(|s| format!("            \"{string}\" => Ok({name}::{typed}),",
+                        name = name.to_class_cases(),
+                        typed = s.to_cpp_enum_case(),
+                        string = s))
+                    .format("\n")));
+        }
+
+        let typedefs = deanonymized.typedefs_by_name();
+        let typedef_names : Vec<_> = typedefs.keys()
+            .sorted();
+
+        buffer.push_str("\n\n// Type sums (by lexicographical order)\n");
+        for name in &typedef_names {
+            let typedef = typedefs.get(name).unwrap();
+            let types = match *typedef.spec() {
+                TypeSpec::TypeSum(ref sum) if !typedef.is_optional() => sum.types(),
+                _ => continue
+            };
+            let cases : Vec<_> = types.iter()
+                .map(|case| {
+                    if let TypeSpec::NamedType(ref case) = *case {
+                        case
+                    } else {
+                        panic!("Unexpected type in sum {name}: {case:?}",
+                            name = name,
+                            case = case)
+                    }
+                })
+                .collect();
+            buffer.push_str(&format!("
+/// Implementation of interface sum {node_name}
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum {name}<'a> {{
+{contents}
+}}
+
+impl<'a, R> Deserialization<R, {name}<'a>> for Deserializer<'a, R> where R: TokenReader {{
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<{name}<'a>, TokenReaderError> {{
+        let arena = self.arena;
+        let (kind, _) = self.reader.enter_tagged_tuple_at(path)?;
+        let path_interface = kind.clone();
+        let result = match kind.as_str() {{
+{variants}
+            _ => Err(From::from(TokenReaderError::BadEnumVariant))
+        }};
+        if result.is_err() {{
+            self.reader.poison();
+        }}
+        self.reader.exit_tagged_tuple_at(path)?;
+        result
+    }}
+}}
+impl<'a> Decodable<'a> for {name}<'a> {{
+    fn deserialize_from<R: TokenReader>(deserializer: &mut Deserializer<'a, R>, path: &mut IOPath) -> Result<Self, TokenReaderError> {{
+        deserializer.deserialize(path)
+    }}
+}}
+impl<'a, R> Deserialization<R, Option<{name}<'a>>> for Deserializer<'a, R> where R: TokenReader {{
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<{name}<'a>>, TokenReaderError> {{
+        let arena = self.arena;
+        let (kind, _) = self.reader.enter_tagged_tuple_at(path)?;
+        let path_interface = kind.clone();
+        let result = match kind.as_str() {{
+{variants_some}
+            \"{null}\" => Ok(None),
+            _ => Err(From::from(TokenReaderError::BadEnumVariant))
+        }};
+        if result.is_err() {{
+            self.reader.poison();
+        }}
+        self.reader.exit_tagged_tuple_at(path)?;
+        result
+    }}
+}}
+
+impl<'a> ToAST for {name}<'a> {{
+    type Target = ast::{name};
+    fn to_ast(&self) -> ast::{name} {{
+        match *self {{
+{to_ast}
+        }}
+    }}
+}}
+",
+                node_name = name,
+                name = name.to_class_cases(),
+                null = null_name,
+                contents = cases.iter()
+                    .map(|case| format!("    {case}(&'a {case}{lifetime})",
+                        case = case.to_class_cases(),
+                        lifetime = lifetime(&case.to_class_cases())))
+                    .format(",\n"),
+                variants = cases.iter()
+                    .map(|case| format!("            \"{case}\" => {{
+                path.enter_interface(path_interface.clone());
+                let result = self.deserialize_inner(path)
+                    .map(|r| {name}::{constructor}(arena.alloc(r)));
+                path.exit_interface(path_interface);
+                result
+            }}",
+                        name = name.to_class_cases(),
+                        case = case,
+                        constructor = case.to_class_cases()))
+                    .format("\n"),
+                variants_some = cases.iter()
+                    .map(|case| format!("            \"{case}\" => {{
+                path.enter_interface(path_interface.clone());
+                let result = self.deserialize_inner(path)
+                    .map(|r| Some({name}::{constructor}(arena.alloc(r))));
+                path.exit_interface(path_interface);
+                result
+            }}",
+                        name = name.to_class_cases(),
+                        case = case,
+                        constructor = case.to_class_cases()))
+                    .format("\n"),
+                to_ast = cases.iter()
+                    .map(|case| format!("            {name}::{constructor}(value) => ast::{name}::{constructor}(Box::new(value.to_ast())),",
+                        name = name.to_class_cases(),
+                        constructor = case.to_class_cases()))
+                    .format("\n")));
+        }
+
+        buffer.push_str("\n\n// Aliases to primitive types (by lexicographical order)\n");
+        for name in &typedef_names {
+            let typedef = typedefs.get(name).unwrap();
+            if typedef.is_optional() {
+                continue;
+            }
+            let contents = match *typedef.spec() {
+                TypeSpec::Boolean => "bool",
+                TypeSpec::Number => "f64",
+                TypeSpec::UnsignedLong => "u32",
+                TypeSpec::String => "SharedString<'a>",
+                TypeSpec::Offset => "Offset",
+                TypeSpec::Void => "()",
+                _ => continue
+            };
+            buffer.push_str(&format!("/// Alias to primitive type.
+pub type {name}{lifetime} = {contents};
+",
+                name = name.to_class_cases(),
+                lifetime = lifetime(&name.to_class_cases()),
+                contents = contents));
+        }
+
+        buffer.push_str("\n\n// Aliases to list types (by lexicographical order)\n");
+        for name in &typedef_names {
+            let typedef = typedefs.get(name).unwrap();
+            let contents = match *typedef.spec() {
+                TypeSpec::Array { ref contents, .. } if !typedef.is_optional() => contents,
+                _ => continue
+            };
+            if let TypeSpec::NamedType(ref contents) = *contents.spec() {
+                buffer.push_str(&format!("
+/// Implementation of list type {name}.
+pub type {name}<'a> = &'a [{contents}{lifetime}];
+",
+                    name = name.to_class_cases(),
+                    contents = contents.to_class_cases(),
+                    lifetime = lifetime(&contents.to_class_cases())));
+            } else {
+                panic!("Could not implement alias to list type {name}: {contents:?}",
+                    contents = contents,
+                    name = name);
+            }
+        }
+
+        buffer.push_str("\n\n// Aliases to optional types (by lexicographical order)\n");
+        for name in &typedef_names {
+            let typedef = typedefs.get(name).unwrap();
+            if !typedef.is_optional() {
+                continue;
+            }
+            if let TypeSpec::NamedType(ref contents) = *typedef.spec() {
+                buffer.push_str(&format!("/// Alias to optional type.
+pub type {name}{lifetime} = Option<{contents}{lifetime}>;
+",
+                    name = name.to_class_cases(),
+                    contents = contents.to_class_cases(),
+                    lifetime = lifetime(&contents.to_class_cases())));
+            } else {
+                panic!("Could not implement alias to optional type {name}: {contents:?}",
+                    contents = typedef.spec(),
+                    name = name);
+            }
+        }
+
+        buffer.push_str("\n\n// Interfaces (by lexicographical order)\n");
+        let interfaces = deanonymized.interfaces_by_name();
+        let interface_names : Vec<_> = interfaces.keys()
+            .sorted();
+        for name in interface_names {
+            if name.to_str() == null_name {
+                // The null interface only marks absent optional values, i.e. `None`.
+                continue;
+            }
+            let interface = interfaces.get(name).unwrap();
+            let rust_name = name.to_class_cases();
+            let field_specs : Vec<_> = interface.contents().fields()
+                .iter()
+                .map(|field| {
+                    let spec = Self::field_type(field);
+                    let spec = format!("{spec}{lifetime}",
+                        lifetime = lifetime(&spec),
+                        spec = spec);
+                    (field, spec)
+                })
+                .collect();
+            buffer.push_str(&format!("
+/// Implementation of interface {name}.
+#[derive(PartialEq, Debug, Clone)]
+pub struct {rust_name}{lifetime} {{
+{fields}
+}}
+
+impl<'a, R> InnerDeserialization<R, {rust_name}{lifetime}> for Deserializer<'a, R> where R: TokenReader {{
+    fn deserialize_inner(&mut self, path: &mut IOPath) -> Result<{rust_name}{lifetime}, TokenReaderError> {{
+        let _ = path; // Deactivate warnings if there are no fields.
+{fields_def}
+        Ok({rust_name} {{
+{fields_use}
+        }})
+    }}
+}}
+
+impl<'a, R> Deserialization<R, {rust_name}{lifetime}> for Deserializer<'a, R> where R: TokenReader {{
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<{rust_name}{lifetime}, TokenReaderError> {{
+        let (interface_name, _) = self.reader.enter_tagged_tuple_at(path)?;
+        let result =
+            if let \"{name}\" = interface_name.as_str() {{
+                path.enter_interface(interface_name.clone());
+                let result = self.deserialize_inner(path);
+                path.exit_interface(interface_name);
+                result
+            }} else {{
+                Err(From::from(TokenReaderError::BadEnumVariant))
+            }};
+        if result.is_err() {{
+            self.reader.poison();
+        }}
+        self.reader.exit_tagged_tuple_at(path)?;
+        result
+    }}
+}}
+impl<'a> Decodable<'a> for {rust_name}{lifetime} {{
+    fn deserialize_from<R: TokenReader>(deserializer: &mut Deserializer<'a, R>, path: &mut IOPath) -> Result<Self, TokenReaderError> {{
+        deserializer.deserialize(path)
+    }}
+}}
+impl<'a, R> Deserialization<R, Option<{rust_name}{lifetime}>> for Deserializer<'a, R> where R: TokenReader {{
+    fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<{rust_name}{lifetime}>, TokenReaderError> {{
+        let (kind, _) = self.reader.enter_tagged_tuple_at(path)?;
+        let result = match kind.as_str() {{
+            \"{name}\" => {{
+                let path_interface = kind.clone();
+                path.enter_interface(path_interface.clone());
+                let result = self.deserialize_inner(path).map(Some);
+                path.exit_interface(path_interface);
+                result
+            }}
+            \"{null}\" => Ok(None),
+            _ => Err(From::from(TokenReaderError::BadEnumVariant))
+        }};
+        if result.is_err() {{
+            self.reader.poison();
+        }}
+        self.reader.exit_tagged_tuple_at(path)?;
+        result
+    }}
+}}
+
+impl{lifetime} ToAST for {rust_name}{lifetime} {{
+    type Target = ast::{rust_name};
+    fn to_ast(&self) -> ast::{rust_name} {{
+        ast::{rust_name} {{
+{fields_to_ast}
+        }}
+    }}
+}}
+",
+                name = name,
+                null = null_name,
+                rust_name = rust_name,
+                lifetime = lifetime(&rust_name),
+                fields = field_specs.iter()
+                    .map(|&(field, ref spec)| format!("    /// Implementation of field {spec_name}
+    pub {rust_name}: {contents},",
+                        spec_name = field.name().to_str(),
+                        rust_name = field.name().to_rust_identifier_case(),
+                        contents = spec))
+                    .format("\n"),
+                fields_def = field_specs.iter()
+                    .enumerate()
+                    .map(|(index, &(field, ref spec))| format!("
+        let path_field = ({index}, FieldName::from_str(\"{field_name}\")); // String is shared
+        path.enter_field(path_field.clone());
+        let data_{rust_field_name} = {deserialize};
+        path.exit_field(path_field);
+        let data_{rust_field_name} = data_{rust_field_name}?;
+",
+                        index = index,
+                        field_name = field.name().to_str(),
+                        rust_field_name = field.name().to_rust_identifier_case(),
+                        deserialize = if field.is_lazy() {
+                            // Nodes that the reader skips have no placeholder in the arena.
+                            format!("match self.reader.skip_lazy_at(data_{rust_field_name}_skip.0, path) {{
+            Ok(true) => Err(From::from(TokenReaderError::invalid_value(&\"skipped lazy field {field_name}\"))),
+            Ok(false) => self.deserialize(path) as Result<{spec}, TokenReaderError>,
+            Err(err) => Err(err)
+        }}",
+                                rust_field_name = field.name().to_rust_identifier_case(),
+                                field_name = field.name().to_str(),
+                                spec = spec)
+                        } else {
+                            format!("self.deserialize(path) as Result<{spec}, TokenReaderError>",
+                                spec = spec)
+                        }))
+                    .format("\n"),
+                fields_use = interface.contents()
+                    .fields()
+                    .iter()
+                    .map(|field| format!("            {name}: data_{name},",
+                        name = field.name().to_rust_identifier_case()))
+                    .format("\n"),
+                fields_to_ast = interface.contents()
+                    .fields()
+                    .iter()
+                    .map(|field| format!("            {name}: self.{name}.to_ast(),",
+                        name = field.name().to_rust_identifier_case()))
+                    .format("\n")));
+        }
+
+        buffer
+    }
+
+    /// The name of the type of a field, as in module `ast`.
+    fn field_type(field: &Field) -> String {
+        if field.type_().is_optional() {
+            return TypeName::type_(field.type_())
+        }
+        match *field.type_().spec() {
+            TypeSpec::NamedType(ref contents) => contents.to_class_cases(),
+            TypeSpec::Boolean => "bool".to_string(),
+            TypeSpec::Number => "f64".to_string(),
+            TypeSpec::UnsignedLong => "u32".to_string(),
+            TypeSpec::String => "String".to_string(),
+            TypeSpec::Void => "()".to_string(),
+            TypeSpec::Offset => "Offset".to_string(),
+            _ => TypeName::type_(field.type_())
+        }
+    }
+
+    /// The names of the types that borrow from the arena, i.e. strings, sums, lists,
+    /// and the options and interfaces that contain any of them.
+    fn borrowing_types(spec: &Spec) -> HashSet<String> {
+        let mut borrowing : HashSet<String> = STRING_TYPES.iter()
+            .map(|name| name.to_string())
+            .collect();
+        for (name, typedef) in spec.typedefs_by_name() {
+            if typedef.is_optional() {
+                continue;
+            }
+            match *typedef.spec() {
+                TypeSpec::TypeSum(_) | TypeSpec::Array { .. } | TypeSpec::String => {
+                    borrowing.insert(name.to_class_cases());
+                }
+                _ => {}
+            }
+        }
+        // Options and interfaces may contain one another, so iterate until we reach a fixpoint.
+        loop {
+            let mut changed = false;
+            for (name, typedef) in spec.typedefs_by_name() {
+                if let TypeSpec::NamedType(ref contents) = *typedef.spec() {
+                    if typedef.is_optional() && borrowing.contains(&contents.to_class_cases()) {
+                        changed |= borrowing.insert(name.to_class_cases());
+                    }
+                }
+            }
+            for (name, interface) in spec.interfaces_by_name() {
+                if interface.contents().fields().iter().any(|field| borrowing.contains(&Self::field_type(field))) {
+                    changed |= borrowing.insert(name.to_class_cases());
+                }
+            }
+            if !changed {
+                return borrowing;
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Generate C++ tables describing a Spec, for engine-side decoders.
+
+use binjs_meta::spec::*;
+use binjs_meta::util::*;
+
+use itertools::Itertools;
+
+/// Generate a C++ header declaring the interfaces, fields and string enums
+/// of a Spec, along with the grammar fingerprint.
+///
+/// Interfaces and fields are numbered by lexicographical order, so the
+/// header only depends on the grammar. Decoders compare `GRAMMAR_FINGERPRINT`
+/// with the grammar identifier of a file before trusting these tables.
+pub struct CppExporter<'a> {
+    spec: &'a Spec
+}
+impl<'a> CppExporter<'a> {
+    /// Create a C++ exporter from the original specifications.
+    pub fn new(spec: &'a Spec) -> Self {
+        CppExporter {
+            spec
+        }
+    }
+
+    /// Generate the header, declaring everything in namespace `binast::{namespace}`.
+    pub fn to_cpp_header(&self, namespace: &str) -> String {
+        let mut buffer = String::new();
+        let guard = format!("BINAST_{}_TABLES_H", namespace.to_uppercase());
+        buffer.push_str(&format!("// This file was autogenerated by binjs_generate_library, do not edit.
+
+#ifndef {guard}
+#define {guard}
+
+#include <cstddef>
+#include <cstdint>
+
+namespace binast {{
+namespace {namespace} {{
+
+// The fingerprint of the grammar, as written in the grammar identifier of files.
+const uint64_t GRAMMAR_FINGERPRINT = 0x{fingerprint:016x}ULL;
+",
+            guard = guard,
+            namespace = namespace,
+            fingerprint = self.spec.fingerprint()));
+
+        self.export_fields(&mut buffer);
+        self.export_interfaces(&mut buffer);
+        self.export_string_enums(&mut buffer);
+
+        buffer.push_str(&format!("
+}} // namespace {namespace}
+}} // namespace binast
+
+#endif // {guard}
+",
+            guard = guard,
+            namespace = namespace));
+        buffer
+    }
+
+    fn export_interfaces(&self, buffer: &mut String) {
+        let interfaces = self.spec.interfaces_by_name()
+            .iter()
+            .sorted_by(|a, b| str::cmp(a.0.to_str(), b.0.to_str()));
+
+        buffer.push_str("\n// ----- Interfaces (by lexicographical order)\n");
+        buffer.push_str(&format!("enum class InterfaceId : uint16_t {{\n    {cases}\n}};\n\n",
+            cases = interfaces.iter()
+                .enumerate()
+                .map(|(index, &(name, _))| format!("{} = {}", name.to_class_cases(), index))
+                .format(",\n    ")));
+        buffer.push_str(&format!("const size_t INTERFACE_COUNT = {};\n\n", interfaces.len()));
+        buffer.push_str(&format!("const char* const INTERFACE_NAMES[] = {{\n    {names}\n}};\n",
+            names = interfaces.iter()
+                .map(|&(name, _)| format!("\"{}\"", name.to_str()))
+                .format(",\n    ")));
+
+        buffer.push_str("\n// ----- Fields of each interface, in the order in which they are written\n");
+        for &(name, interface) in &interfaces {
+            let fields = interface.contents().fields();
+            if fields.is_empty() {
+                continue;
+            }
+            buffer.push_str(&format!("const FieldId FIELDS_OF_{name}[] = {{ {fields} }};\n",
+                name = name.to_class_cases(),
+                fields = fields.iter()
+                    .map(|field| format!("FieldId::{}", field.name().to_cpp_enum_case()))
+                    .format(", ")));
+        }
+        buffer.push_str("\nstruct InterfaceFields {\n    const FieldId* fields;\n    size_t length;\n    // A bitset of the lazy fields, by position.\n    uint64_t lazy;\n};\n\n");
+        buffer.push_str(&format!("// By `InterfaceId`.\nconst InterfaceFields INTERFACE_FIELDS[] = {{\n    {entries}\n}};\n",
+            entries = interfaces.iter()
+                .map(|&(name, interface)| {
+                    let fields = interface.contents().fields();
+                    assert!(fields.len() <= 64, "Too many fields in {}", name.to_str());
+                    let lazy = fields.iter()
+                        .enumerate()
+                        .filter(|&(_, field)| field.is_lazy())
+                        .fold(0u64, |lazy, (position, _)| lazy | 1 << position);
+                    if fields.is_empty() {
+                        "{ nullptr, 0, 0 }".to_string()
+                    } else {
+                        format!("{{ FIELDS_OF_{name}, {length}, 0x{lazy:x} }}",
+                            name = name.to_class_cases(),
+                            length = fields.len(),
+                            lazy = lazy)
+                    }
+                })
+                .format(",\n    ")));
+    }
+
+    fn export_fields(&self, buffer: &mut String) {
+        let fields = self.spec.field_names()
+            .keys()
+            .sorted();
+
+        buffer.push_str("\n// ----- Fields (by lexicographical order)\n");
+        buffer.push_str(&format!("enum class FieldId : uint16_t {{\n    {cases}\n}};\n\n",
+            cases = fields.iter()
+                .enumerate()
+                .map(|(index, name)| format!("{} = {}", name.to_cpp_enum_case(), index))
+                .format(",\n    ")));
+        buffer.push_str(&format!("const size_t FIELD_COUNT = {};\n\n", fields.len()));
+        buffer.push_str(&format!("const char* const FIELD_NAMES[] = {{\n    {names}\n}};\n",
+            names = fields.iter()
+                .map(|name| format!("\"{}\"", name))
+                .format(",\n    ")));
+    }
+
+    fn export_string_enums(&self, buffer: &mut String) {
+        let string_enums = self.spec.string_enums_by_name()
+            .iter()
+            .sorted_by(|a, b| str::cmp(a.0.to_str(), b.0.to_str()));
+
+        buffer.push_str("\n// ----- String enums (by lexicographical order), values in the order of the grammar\n");
+        for (name, string_enum) in string_enums {
+            buffer.push_str(&format!("enum class {name} : uint8_t {{\n    {cases}\n}};\n\n",
+                name = name.to_class_cases(),
+                cases = string_enum.strings()
+                    .iter()
+                    .enumerate()
+                    .map(|(index, value)| format!("{case:<20} = {index:<3} /* \"{original}\" */",
+                        case = value.to_cpp_enum_case(),
+                        index = index,
+                        original = value))
+                    .format(",\n    ")));
+            buffer.push_str(&format!("const char* const VALUES_OF_{name}[] = {{ {values} }};\n\n",
+                name = name.to_class_cases(),
+                values = string_enum.strings()
+                    .iter()
+                    .map(|value| format!("\"{}\"", value))
+                    .format(", ")));
+        }
+    }
+}
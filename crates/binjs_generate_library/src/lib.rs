@@ -12,6 +12,12 @@ use std::rc::Rc;
 
 use itertools::Itertools;
 
+mod arena;
+pub use arena::ArenaExporter;
+
+mod cpp;
+pub use cpp::CppExporter;
+
 /// Source code produced by exporting a spec to Rust.
 pub struct ExportedSource {
     /// Source code for a strongly-typed data structure implementing the specification.
@@ -87,6 +93,15 @@ impl RustExporter {
             TypeSpec::PropertyKey =>
                 format!("{prefix}Type::property_key()",
                     prefix = prefix),
+            TypeSpec::BigInt =>
+                format!("{prefix}Type::big_int()",
+                    prefix = prefix),
+            TypeSpec::RegExpPattern =>
+                format!("{prefix}Type::reg_exp_pattern()",
+                    prefix = prefix),
+            TypeSpec::RegExpFlags =>
+                format!("{prefix}Type::reg_exp_flags()",
+                    prefix = prefix),
             TypeSpec::NamedType(ref name) =>
                 format!("{prefix}Type::named(&names.{name})",
                     name = name.to_rust_identifier_case(),
@@ -123,7 +138,7 @@ impl RustExporter {
         let mut ast_buffer = String::new();
         ast_buffer.push_str("
 use binjs_shared;
-use binjs_shared::{ FieldName, FromJSON, FromJSONError, IdentifierName, InterfaceName, Offset, PropertyKey, SharedString, ToJSON, VisitMe };
+use binjs_shared::{ BigInt, FieldName, FromJSON, FromJSONError, IdentifierName, InterfaceName, JSON, JSONExt, Offset, PropertyKey, RegExpFlags, RegExpPattern, SharedString, ToJSON, VisitMe };
 use binjs_io::{ Deserialization, InnerDeserialization, Serialization, TokenReader, TokenReaderError, TokenWriter, TokenWriterError };
 
 use io::*;
@@ -131,8 +146,6 @@ use io::*;
 use std;
 use std::convert::{ From };
 
-use json;
-use json::JsonValue as JSON;
 
 ");
 
@@ -175,15 +188,17 @@ use json::JsonValue as JSON;
                 let rust_name = name.to_class_cases();
                 let definition = format!("
 /// Implementation of string enum {name}
-#[derive(PartialEq, Debug, Clone)]\npub enum {rust_name} {{\n{values}\n    }}\n",
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]\npub enum {rust_name} {{\n{values}\n    }}\n",
                     name = name,
                     rust_name = rust_name,
                     values = string_enum.strings()
                         .iter()
                         .map(|s| format!(
 "/// Implementation of variant \"{spec_variant_name}\"
+     #[serde(rename = {spec_variant_literal:?})]
      {rust_variant_name}",
                             spec_variant_name = s,
+                            spec_variant_literal = s,
                             rust_variant_name = ToCases::to_cpp_enum_case(s)))
                         .format(",\n"));
                 let default = format!("
@@ -206,7 +221,7 @@ impl Default for {name} {{
                 let to_json = format!("
 impl ToJSON for {name} {{
     fn export(&self) -> JSON {{
-        json::from(match *self {{
+        JSON::from(match *self {{
 {cases}
         }})
     }}
@@ -262,13 +277,15 @@ impl FromJSON for {name} {{
     fn import(source: &JSON) -> Result<Self, FromJSONError > {{
         match source.as_str() {{
 {cases},
-            _ => Err(FromJSONError {{
-                expected: \"Instance of {name}\".to_string(),
-                got: source.dump(),
-            }})
+            _ => Err(FromJSONError::new(\"Instance of {name}\", source)
+                .with_valid(&[{valid}]))
         }}
     }}
 }}\n\n",
+                    valid = string_enum.strings()
+                        .iter()
+                        .map(|s| format!("\"{}\"", s))
+                        .format(", "),
                     cases = string_enum.strings()
                         .iter()
                         .map(|s| format!("           Some(\"{string}\") => Ok({name}::{typed})",
@@ -384,7 +401,7 @@ impl<'a> Walker<'a> for {name} where Self: 'a {{
 
                     let definition = format!("
 /// Implementation of interface sum {node_name}
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum {name} {{\n{contents}\n}}\n
 
 /// A mechanism to view value as an instance of interface sum {node_name}
@@ -485,6 +502,11 @@ impl<R> Deserialization<R, {name}> for Deserializer<R> where R: TokenReader {{
         result
     }}
 }}
+impl Decodable for {name} {{
+    fn deserialize_from<R: TokenReader>(deserializer: &mut Deserializer<R>, path: &mut IOPath) -> Result<Self, TokenReaderError> {{
+        deserializer.deserialize(path)
+    }}
+}}
 impl<R> Deserialization<R, Option<{name}>> for Deserializer<R> where R: TokenReader {{
     fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<{name}>, TokenReaderError> {{
         debug!(target: \"deserialize_es6\", \"Deserializing optional sum {name}\");
@@ -545,15 +567,16 @@ impl FromJSON for {name} {{
     fn import(value: &JSON) -> Result<Self, FromJSONError> {{
         match value[\"type\"].as_str() {{
 {cases},
-            _ => Err(FromJSONError {{
-                expected: \"Instance of {kind}\".to_string(),
-                got: value.dump()
-            }})
+            _ => Err(FromJSONError::new(\"Instance of {kind}\", value)
+                .with_valid(&[{valid}]))
         }}
     }}
 }}\n\n",
                                 name = name,
                                 kind = name,
+                                valid = types.iter()
+                                    .map(|case| format!("\"{}\"", case))
+                                    .format(", "),
                                 cases = types.iter()
                                     .map(|case| {
                                         format!("           Some(\"{case}\") => Ok({name}::{constructor}(Box::new(FromJSON::import(value)?)))",
@@ -962,7 +985,7 @@ impl<'a> Walker<'a> for ViewMut{name}<'a> {{
                     .collect();
                 let definition = format!("
 /// Implementation of interface {spec_name}.
-#[derive(Default, PartialEq, Debug, Clone)]
+#[derive(Default, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct {rust_name} {{
 {fields}
 }}
@@ -980,9 +1003,11 @@ impl binjs_shared::Node for {rust_name} {{
                         .map(|(field_name, spec)| {
                             format!(
 "    /// Implementation of field {spec_name}
+    #[serde(rename = {spec_literal:?})]
     pub {rust_name}: {contents}",
                                 rust_name = field_name.to_rust_identifier_case(),
                                 spec_name = field_name.to_str(),
+                                spec_literal = field_name.to_str(),
                                 contents = spec)
                         })
                         .format(",\n"),
@@ -1033,6 +1058,11 @@ impl<R> Deserialization<R, {rust_name}> for Deserializer<R> where R: TokenReader
         self.deserialize_tuple_{lowercase_name}(path)
     }}
 }}
+impl Decodable for {rust_name} {{
+    fn deserialize_from<R: TokenReader>(deserializer: &mut Deserializer<R>, path: &mut IOPath) -> Result<Self, TokenReaderError> {{
+        deserializer.deserialize(path)
+    }}
+}}
 impl<R> Deserialization<R, Option<{rust_name}>> for Deserializer<R> where R: TokenReader {{
     fn deserialize(&mut self, path: &mut IOPath) -> Result<Option<{rust_name}>, TokenReaderError> {{
         debug!(target: \"deserialize_es6\", \"Deserializing optional tuple {rust_name}\");
@@ -1077,13 +1107,25 @@ impl<R> Deserialization<R, Option<{rust_name}>> for Deserializer<R> where R: Tok
         print_file_structure!(self.reader, \".{field_name}\");
         let path_field = ({index}, FieldName::from_str(\"{field_name}\")); // String is shared
         path.enter_field(path_field.clone());
-        let data_{rust_field_name} = self.deserialize(path) as Result<{spec}, TokenReaderError>;
+        let data_{rust_field_name} = {deserialize};
         path.exit_field(path_field);
         let data_{rust_field_name} = data_{rust_field_name}?;
 ",
                             rust_field_name = field.name().to_rust_identifier_case(),
                             field_name = field.name().to_str(),
-                            spec = field_specs_map.get(field.name()).unwrap(),
+                            deserialize = if field.is_lazy() {
+                                // The reader may skip the contents, e.g. to read them later.
+                                format!("match self.reader.skip_lazy_at(data_{rust_field_name}_skip.0, path) {{
+            Ok(true) => Ok(Default::default()),
+            Ok(false) => self.deserialize(path) as Result<{spec}, TokenReaderError>,
+            Err(err) => Err(err)
+        }}",
+                                    rust_field_name = field.name().to_rust_identifier_case(),
+                                    spec = field_specs_map.get(field.name()).unwrap())
+                            } else {
+                                format!("self.deserialize(path) as Result<{spec}, TokenReaderError>",
+                                    spec = field_specs_map.get(field.name()).unwrap())
+                            },
                             index = index))
                         .format("\n"),
                     fields_use = interface.contents()
@@ -1163,10 +1205,8 @@ impl FromJSON for {rust_name} {{
     fn import(value: &JSON) -> Result<Self, FromJSONError> {{
         match value[\"type\"].as_str() {{
             Some(\"{kind}\") => {{ /* Good */ }},
-            _ => return Err(FromJSONError {{
-                expected: \"Instance of {kind}\".to_string(),
-                got: value.dump()
-            }})
+            _ => return Err(FromJSONError::new(\"Instance of {kind}\", value)
+                .with_valid(&[\"{kind}\"]))
         }}
         Ok({rust_name} {{
 {fields}
@@ -1178,7 +1218,9 @@ impl FromJSON for {rust_name} {{
                     fields = interface.contents()
                         .fields()
                         .iter()
-                        .map(|field| format!("            {name}: FromJSON::import(&value[\"{key}\"])?,\n",
+                        .map(|field| format!("            {name}: FromJSON::import(&value[\"{key}\"])
+                .map_err(|err| err.in_field(\"{kind}\", \"{key}\"))?,\n",
+                            kind = name,
                             key = field.name().to_str(),
                             name = field.name().to_rust_identifier_case()))
                         .format("")
@@ -1188,7 +1230,7 @@ impl FromJSON for {rust_name} {{
 impl ToJSON for {rust_name} {{
     fn export(&self) -> JSON {{
         object!{{
-            \"type\" => json::from(\"{kind}\"),
+            \"type\" => \"{kind}\",
 {fields}
         }}
     }}
@@ -1414,6 +1456,27 @@ impl<'a> From<&'a mut PropertyKey> for ViewMutNothing<PropertyKey> {{
         ViewMutNothing::default()
     }}
 }}
+
+type ViewMutBigInt = ViewMutNothing<BigInt>;
+impl<'a> From<&'a mut BigInt> for ViewMutNothing<BigInt> {{
+    fn from(_: &'a mut BigInt) -> Self {{
+        ViewMutNothing::default()
+    }}
+}}
+
+type ViewMutRegExpPattern = ViewMutNothing<RegExpPattern>;
+impl<'a> From<&'a mut RegExpPattern> for ViewMutNothing<RegExpPattern> {{
+    fn from(_: &'a mut RegExpPattern) -> Self {{
+        ViewMutNothing::default()
+    }}
+}}
+
+type ViewMutRegExpFlags = ViewMutNothing<RegExpFlags>;
+impl<'a> From<&'a mut RegExpFlags> for ViewMutNothing<RegExpFlags> {{
+    fn from(_: &'a mut RegExpFlags) -> Self {{
+        ViewMutNothing::default()
+    }}
+}}
 \n\n\n",
                 interfaces = interface_names
                     .drain(..)
@@ -1552,6 +1615,44 @@ impl<'a> From<&'a mut PropertyKey> for ViewMutNothing<PropertyKey> {{
         }
 
         impl_buffer.push_str("        names\n    }\n");
+
+        // Buffer used to generate the typed accessors to the fields of JSON ASTs.
+        let mut fields_buffer = String::new();
+        fields_buffer.push_str("
+/// The fields of each interface, for typed navigation in JSON ASTs.
+pub mod fields {
+");
+        for name in self.spec.interfaces_by_name().keys().sorted() {
+            let interface = self.spec.get_interface_by_name(name)
+                .unwrap();
+            let mut fields = vec![];
+            for field in interface.contents().fields() {
+                if field.is_lazy() {
+                    fields.push(format!("{}_skip", field.name().to_str()));
+                }
+                fields.push(field.name().to_str().to_string());
+            }
+            if fields.is_empty() {
+                continue;
+            }
+            let source = format!("
+    /// The fields of interface `{name}`.
+    pub mod {snake} {{
+        use path::ASTField;
+{fields}
+    }}
+",
+                name = name,
+                snake = name.to_rust_identifier_case(),
+                fields = fields.iter()
+                    .map(|field| format!("        pub const {constant}: ASTField = ASTField {{ interface: \"{name}\", field: \"{field}\" }};",
+                        constant = field.to_rust_identifier_case().to_uppercase(),
+                        name = name,
+                        field = field))
+                    .format("\n"));
+            fields_buffer.push_str(&source);
+        }
+        fields_buffer.push_str("}\n");
         impl_buffer.push_str("
 }
 
@@ -1578,9 +1679,10 @@ impl Annotator for Library {
         ExportedSource {
             typed: format!("// This file was generated by binjs_meta generate_library.\n{ast_}\n",
                 ast_ = ast_buffer),
-            generic: format!("// This file was generated by binjs_meta generate_library.\npub use annotate::Annotator;\nuse binjs_meta::spec::*;\nuse json::JsonValue as JSON;\n\n\n{struct_}\n{impl_}",
+            generic: format!("// This file was generated by binjs_meta generate_library.\npub use annotate::Annotator;\nuse binjs_meta::spec::*;\nuse binjs_shared::JSON;\n\n\n{struct_}\n{impl_}\n{fields}",
                 struct_ = struct_buffer,
-                impl_ = impl_buffer)
+                impl_ = impl_buffer,
+                fields = fields_buffer)
         }
     }
 }
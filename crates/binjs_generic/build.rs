@@ -12,8 +12,14 @@ use std::io::*;
 
 const PATH_GRAMMAR_ES6 : &'static str = "../../spec/es6.webidl";
 
+/// A list of webidl files extending the grammar with vendor-specific
+/// interfaces and fields, separated as in `PATH`. See
+/// `binjs_meta::import::Importer::import_extended`.
+const ENV_GRAMMAR_EXTENSIONS : &'static str = "BINJS_GRAMMAR_EXTENSIONS";
+
 fn main() {
     println!("cargo:rerun-if-changed={}", PATH_GRAMMAR_ES6);
+    println!("cargo:rerun-if-env-changed={}", ENV_GRAMMAR_EXTENSIONS);
 
     // Load webidl.
 
@@ -26,11 +32,28 @@ fn main() {
     let ast = webidl::parse_string(&source)
         .expect("Could not parse source");
 
+    // Load grammar extensions, if any.
+
+    let extension_paths : Vec<_> = env::var_os(ENV_GRAMMAR_EXTENSIONS)
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    let extensions : Vec<_> = extension_paths.iter()
+        .map(|path| {
+            println!("cargo:rerun-if-changed={}", path.display());
+            let mut source = String::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut source))
+                .unwrap_or_else(|e| panic!("Could not read grammar extension {}: {}", path.display(), e));
+            webidl::parse_string(&source)
+                .unwrap_or_else(|e| panic!("Could not parse grammar extension {}: {:?}", path.display(), e))
+        })
+        .collect();
+
     // Check spec. We don't really need null/fake_root
     // for this operation. It may change in the future,
     // we'll see then.
 
-    let mut builder = Importer::import(&ast);
+    let mut builder = Importer::import_extended(&ast, &extensions);
     let fake_root = builder.node_name(""); // Ignored.
     let null = builder.node_name("_Null"); // Ignored.
     builder.add_interface(&null)
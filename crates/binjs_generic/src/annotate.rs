@@ -1,4 +1,4 @@
-use json::JsonValue as JSON;
+use binjs_shared::JSON;
 
 pub trait Annotator {
     fn annotate(&self, _ast: &mut JSON) {
@@ -0,0 +1,874 @@
+//! Encoding generic (i.e. JSON) ASTs, following a grammar loaded at runtime.
+//!
+//! Unlike `binjs_es6::io`, which is generated from the grammar at build time, the
+//! serializer walks the `Spec` as it walks the AST, dispatching on the type of each
+//! value. This is slower than the specialized encoder, but lets us experiment with
+//! changes to the grammar without rebuilding.
+//!
+//! Conversely, the deserializer walks the `Spec` as it reads tokens, without building
+//! an AST. Combined with `binjs_io::events`, this lets tools observe the contents of
+//! a file, e.g. to compute metrics, without paying for a materialized tree. The
+//! `Transcoder` combines both, writing each token to another format as it is read.
+
+use syntax::ASTError;
+use util::type_of;
+
+use binjs_io::{ self, GrammarId, Path, ReaderVisitor, Token, TokenKind, TokenReader, TokenReaderError, TokenWriter, TokenWriterError, TokenWriterTreeAdapter };
+use binjs_io::events::{ EventHandler, TokenReaderEventAdapter };
+use binjs_io::progress::{ NoProgress, Phase, ProgressSink, TokenWriterProgressAdapter };
+use binjs_meta::export::TypeDeanonymizer;
+use binjs_meta::spec::*;
+use binjs_shared::{ BigInt, FieldName, IdentifierName, InterfaceName, JSON, JSONExt, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
+use binjs_shared::ast::Node;
+
+use std::io::{ Read, Seek };
+
+#[derive(Debug)]
+pub enum Error {
+    /// The AST does not match the grammar.
+    AST(ASTError),
+    TokenWriter(TokenWriterError),
+}
+impl From<ASTError> for Error {
+    fn from(value: ASTError) -> Self {
+        Error::AST(value)
+    }
+}
+impl From<TokenWriterError> for Error {
+    fn from(value: TokenWriterError) -> Self {
+        Error::TokenWriter(value)
+    }
+}
+impl From<Error> for binjs_io::Error {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::AST(err) => binjs_io::Error::new(binjs_io::ErrorKind::Encode, &err),
+            Error::TokenWriter(err) => err.into(),
+        }
+    }
+}
+
+/// The node passed to `TokenWriter::enter_tagged_tuple_at`.
+///
+/// We have no strongly-typed node to give to token writers, which do not need one.
+struct GenericNode;
+impl Node for GenericNode {
+    fn name(&self) -> &'static str {
+        "GenericNode"
+    }
+}
+
+/// Deanonymize `spec`, so that lazy fields are preceded by their `_skip` offset.
+fn deanonymize(spec: &Spec) -> Spec {
+    TypeDeanonymizer::new(spec)
+        .into_spec(SpecOptions {
+            root: spec.get_root_name(),
+            null: spec.get_null_name(),
+        })
+}
+
+/// A structure used to write a JSON AST to a token writer, following a `Spec`.
+///
+/// The `Spec` is expected to be deanonymized, as `Encoder` does, so that lazy
+/// fields are preceded by their `_skip` offset.
+pub struct Serializer<'a, W> where W: TokenWriter {
+    spec: &'a Spec,
+    pub writer: W,
+}
+impl<'a, W> Serializer<'a, W> where W: TokenWriter {
+    pub fn new(spec: &'a Spec, writer: W) -> Self {
+        Serializer {
+            spec,
+            writer,
+        }
+    }
+
+    pub fn done(self) -> Result<W::Data, TokenWriterError> {
+        self.writer.done()
+    }
+
+    /// Serialize `value` as an instance of the root of the grammar.
+    pub fn serialize(&mut self, value: &JSON, path: &mut Path) -> Result<(), Error> {
+        let root = TypeSpec::NamedType(self.spec.get_root_name().clone());
+        self.serialize_type_spec(value, &root, path)
+    }
+
+    fn serialize_type(&mut self, value: &JSON, type_: &Type, path: &mut Path) -> Result<(), Error> {
+        if value.is_null() && type_.is_optional() {
+            return self.serialize_null(type_.spec(), path);
+        }
+        self.serialize_type_spec(value, type_.spec(), path)
+    }
+
+    /// Serialize `null` as a value of type `spec`, following the representation
+    /// chosen by the specialized serializer.
+    fn serialize_null(&mut self, spec: &TypeSpec, path: &mut Path) -> Result<(), Error> {
+        match *spec {
+            TypeSpec::Boolean => self.writer.bool_at(None, path)?,
+            TypeSpec::Number => self.writer.float_at(None, path)?,
+            TypeSpec::String => self.writer.string_at(None, path)?,
+            TypeSpec::IdentifierName => self.writer.identifier_name_at(None, path)?,
+            TypeSpec::PropertyKey => self.writer.property_key_at(None, path)?,
+            TypeSpec::BigInt => self.writer.big_int_at(None, path)?,
+            TypeSpec::RegExpPattern => self.writer.reg_exp_pattern_at(None, path)?,
+            TypeSpec::RegExpFlags => self.writer.reg_exp_flags_at(None, path)?,
+            TypeSpec::Void => { /* Nothing to write */ }
+            TypeSpec::NamedType(ref name) => {
+                if let Some(NamedType::Typedef(ref type_)) = self.spec.get_type_by_name(name) {
+                    return self.serialize_null(type_.spec(), path);
+                }
+                self.serialize_null_interface(path)?;
+            }
+            _ => self.serialize_null_interface(path)?,
+        }
+        Ok(())
+    }
+
+    fn serialize_null_interface(&mut self, path: &mut Path) -> Result<(), Error> {
+        let interface_name = InterfaceName::from_string(self.spec.get_null_name().to_str().to_string());
+        self.writer.enter_tagged_tuple_at(&GenericNode, &interface_name, &[], path)?;
+        self.writer.exit_tagged_tuple_at(&GenericNode, &interface_name, &[], path)?;
+        Ok(())
+    }
+
+    fn serialize_type_spec(&mut self, value: &JSON, spec: &TypeSpec, path: &mut Path) -> Result<(), Error> {
+        match *spec {
+            TypeSpec::Boolean => {
+                if let Some(b) = value.as_bool() {
+                    return Ok(self.writer.bool_at(Some(b), path)?);
+                }
+            }
+            TypeSpec::Number => {
+                if let Some(f) = value.as_f64() {
+                    return Ok(self.writer.float_at(Some(f), path)?);
+                }
+            }
+            TypeSpec::UnsignedLong => {
+                if let Some(u) = value.as_u32() {
+                    return Ok(self.writer.unsigned_long_at(u, path)?);
+                }
+            }
+            TypeSpec::Offset => {
+                // The value is computed by the writer.
+                return Ok(self.writer.offset_at(path)?);
+            }
+            TypeSpec::String => {
+                if let Some(s) = value.as_str() {
+                    let string = SharedString::from_string(s.to_string());
+                    return Ok(self.writer.string_at(Some(&string), path)?);
+                }
+            }
+            TypeSpec::IdentifierName => {
+                if let Some(s) = value.as_str() {
+                    let name = IdentifierName::from_string(s.to_string());
+                    return Ok(self.writer.identifier_name_at(Some(&name), path)?);
+                }
+            }
+            TypeSpec::PropertyKey => {
+                if let Some(s) = value.as_str() {
+                    let key = PropertyKey::from_string(s.to_string());
+                    return Ok(self.writer.property_key_at(Some(&key), path)?);
+                }
+            }
+            TypeSpec::BigInt => {
+                if let Some(s) = value.as_str() {
+                    let big_int = BigInt::from_string(s.to_string());
+                    return Ok(self.writer.big_int_at(Some(&big_int), path)?);
+                }
+            }
+            TypeSpec::RegExpPattern => {
+                if let Some(s) = value.as_str() {
+                    let pattern = RegExpPattern::from_string(s.to_string());
+                    return Ok(self.writer.reg_exp_pattern_at(Some(&pattern), path)?);
+                }
+            }
+            TypeSpec::RegExpFlags => {
+                if let Some(s) = value.as_str() {
+                    let flags = RegExpFlags::from_string(s.to_string());
+                    return Ok(self.writer.reg_exp_flags_at(Some(&flags), path)?);
+                }
+            }
+            TypeSpec::Void => {
+                return Ok(());
+            }
+            TypeSpec::NamedType(ref name) => {
+                let named = self.spec.get_type_by_name(name)
+                    .ok_or_else(|| ASTError::InvalidType(name.to_str().to_string()))?;
+                return match named {
+                    NamedType::Interface(ref interface) => self.serialize_interface(value, interface, path),
+                    NamedType::Typedef(ref type_) => self.serialize_type(value, type_, path),
+                    NamedType::StringEnum(ref enum_) => self.serialize_string_enum(value, enum_, path),
+                };
+            }
+            TypeSpec::Array { ref contents, supports_empty } => {
+                if value.is_array() {
+                    if value.len() == 0 && !supports_empty {
+                        return Err(From::from(ASTError::InvalidValue {
+                            expected: "non-empty array".to_string(),
+                            got: "empty array".to_string()
+                        }));
+                    }
+                    self.writer.enter_list_at(value.len(), path)?;
+                    for item in value.members() {
+                        self.serialize_type(item, contents, path)?;
+                    }
+                    self.writer.exit_list_at(path)?;
+                    return Ok(());
+                }
+            }
+            TypeSpec::TypeSum(ref sum) => {
+                // Find out to which interface of the sum we belong.
+                let kind = value["type"].as_str()
+                    .ok_or_else(|| ASTError::InvalidValue {
+                        expected: "object with a type".to_string(),
+                        got: type_of(value)
+                    })?;
+                let interface = self.spec.get_node_name(kind)
+                    .and_then(|kind_name| sum.get_interface(self.spec, kind_name))
+                    .ok_or_else(|| ASTError::InvalidType(kind.to_string()))?;
+                return self.serialize_interface(value, &interface, path);
+            }
+        }
+        Err(From::from(ASTError::InvalidValue {
+            expected: format!("{:?}", spec),
+            got: type_of(value)
+        }))
+    }
+
+    fn serialize_string_enum(&mut self, value: &JSON, enum_: &StringEnum, path: &mut Path) -> Result<(), Error> {
+        match value.as_str() {
+            Some(s) if enum_.strings().iter().any(|x| x == s) => {
+                let string = SharedString::from_string(s.to_string());
+                Ok(self.writer.string_enum_at(&string, path)?)
+            }
+            _ => Err(From::from(ASTError::InvalidValue {
+                expected: format!("One of {:?}", enum_.strings()),
+                got: value.dump()
+            }))
+        }
+    }
+
+    fn serialize_interface(&mut self, value: &JSON, interface: &Interface, path: &mut Path) -> Result<(), Error> {
+        if !value.is_object() || value["type"].as_str() != Some(interface.name().to_str()) {
+            return Err(From::from(ASTError::invalid_value(value, &format!("Instance of {:?}", interface.name()))));
+        }
+        let interface_name = InterfaceName::from_string(interface.name().to_str().to_string());
+        let field_names : Vec<_> = interface.contents()
+            .fields()
+            .iter()
+            .map(|field| FieldName::from_string(field.name().to_str().to_string()))
+            .collect();
+        let field_refs : Vec<_> = field_names.iter()
+            .collect();
+
+        self.writer.enter_tagged_tuple_at(&GenericNode, &interface_name, &field_refs, path)?;
+        path.enter_interface(interface_name.clone());
+        let result = self.serialize_fields(value, interface, &field_names, path);
+        path.exit_interface(interface_name.clone());
+        result?;
+        self.writer.exit_tagged_tuple_at(&GenericNode, &interface_name, &field_refs, path)?;
+        Ok(())
+    }
+
+    fn serialize_fields(&mut self, value: &JSON, interface: &Interface, field_names: &[FieldName], path: &mut Path) -> Result<(), Error> {
+        for (index, (field, field_name)) in interface.contents().fields().iter().zip(field_names).enumerate() {
+            let path_item = (index, field_name.clone());
+            path.enter_field(path_item.clone());
+            let result = self.serialize_type(&value[field.name().to_str()], field.type_(), path);
+            path.exit_field(path_item);
+            result?;
+        }
+        Ok(())
+    }
+}
+
+/// Encode JSON ASTs to any format, following a grammar loaded at runtime.
+pub struct Encoder {
+    /// The deanonymized grammar.
+    spec: Spec,
+
+    /// The grammar, as written by formats that support grammar identifiers.
+    grammar: GrammarId,
+}
+impl Encoder {
+    /// Create an encoder for `spec`, which will be identified as `name`
+    /// in files that support grammar identifiers.
+    pub fn new(spec: &Spec, name: &str) -> Self {
+        // Fingerprint the grammar as written, as `binjs_es6` does, so that files
+        // encoded with the same grammar may be decoded by the specialized decoder.
+        let grammar = GrammarId::new(name, spec.fingerprint());
+        Encoder {
+            spec: deanonymize(spec),
+            grammar,
+        }
+    }
+
+    pub fn grammar(&self) -> &GrammarId {
+        &self.grammar
+    }
+
+    pub fn encode(&self, format: &mut binjs_io::Format, value: &JSON) -> Result<Box<AsRef<[u8]>>, Error> {
+        self.encode_with_progress(format, value, NoProgress)
+    }
+
+    /// Encode an AST, reporting progress to `sink`.
+    pub fn encode_with_progress<S>(&self, format: &mut binjs_io::Format, value: &JSON, mut sink: S) -> Result<Box<AsRef<[u8]>>, Error>
+        where S: ProgressSink
+    {
+        sink.phase(Phase::Encode);
+        self.write(format, EncodeVisitor {
+            spec: &self.spec,
+            value,
+            sink,
+        })
+    }
+
+    /// Run `visitor` on the token writer of `format`.
+    fn write<V: WriterVisitor>(&self, format: &mut binjs_io::Format, visitor: V) -> Result<Box<AsRef<[u8]>>, V::Error> {
+        match *format {
+            binjs_io::Format::Simple => {
+                let writer = binjs_io::simple::TreeTokenWriter::new();
+                visitor.visit(TokenWriterTreeAdapter::new(writer))
+            }
+            binjs_io::Format::Multipart { ref mut targets, ref options, .. } => {
+                let writer = binjs_io::multipart::TreeTokenWriter::new(targets.clone())
+                    .with_checksum(options.integrity.write_checksum)
+                    .with_sign_key(options.integrity.sign_key)
+                    .with_encryption_key(options.integrity.encryption_key)
+                    .with_front_coding(options.front_coding)
+                    .with_blobs(options.blob_threshold, options.blob_compression.clone())
+                    .with_nan_policy(options.nan_policy)
+                    .with_varfloats(options.varfloats)
+                    .with_runs(options.runs)
+                    .with_split_prelude(options.split_prelude)
+                    .with_chunks(options.chunks)
+                    .with_string_dictionary(options.string_dictionary.clone())
+                    .with_metadata(options.metadata.clone())
+                    .with_reproducible(options.reproducible)
+                    .with_grammar(Some(self.grammar.clone()));
+                visitor.visit(TokenWriterTreeAdapter::new(writer))
+            }
+            binjs_io::Format::XML => {
+                let writer = binjs_io::xml::Encoder::new();
+                visitor.visit(TokenWriterTreeAdapter::new(writer))
+            }
+            binjs_io::Format::Text => {
+                visitor.visit(binjs_io::text::Encoder::new())
+            }
+            binjs_io::Format::Entropy { ref options } => {
+                visitor.visit(binjs_io::entropy::write::Encoder::new((*options).clone()))
+            }
+            binjs_io::Format::AdaptiveEntropy { ref options } => {
+                visitor.visit(binjs_io::entropy::adaptive::Encoder::new((*options).clone()))
+            }
+            binjs_io::Format::HuffmanEntropy { ref options } => {
+                visitor.visit(binjs_io::entropy::huffman::Encoder::new((*options).clone()))
+            }
+            binjs_io::Format::Templates { ref options } => {
+                let writer = binjs_io::templates::Encoder::new((*options).clone());
+                visitor.visit(TokenWriterTreeAdapter::new(writer))
+            }
+            binjs_io::Format::Dag { ref options } => {
+                let writer = binjs_io::dag::Encoder::new((*options).clone());
+                visitor.visit(TokenWriterTreeAdapter::new(writer))
+            }
+        }
+    }
+}
+
+/// A computation on a `TokenWriter` of any type, see `Encoder::write`.
+///
+/// Each format is written by a different type of `TokenWriter`, which closures
+/// cannot be generic over.
+trait WriterVisitor {
+    type Error: From<TokenWriterError>;
+
+    /// Run the computation on `writer`, returning the data written.
+    fn visit<W>(self, writer: W) -> Result<Box<AsRef<[u8]>>, Self::Error>
+        where W: TokenWriter, W::Data: 'static;
+}
+
+/// Write a JSON AST to the token writer of any format, see `Encoder::encode_with_progress`.
+struct EncodeVisitor<'a, S> {
+    spec: &'a Spec,
+    value: &'a JSON,
+    sink: S,
+}
+impl<'a, S> WriterVisitor for EncodeVisitor<'a, S> where S: ProgressSink {
+    type Error = Error;
+    fn visit<W>(self, writer: W) -> Result<Box<AsRef<[u8]>>, Error>
+        where W: TokenWriter, W::Data: 'static
+    {
+        let mut path = Path::new();
+        let mut serializer = Serializer::new(self.spec, TokenWriterProgressAdapter::new(writer, self.sink));
+        serializer.serialize(self.value, &mut path)?;
+        let data = serializer.done()?;
+        Ok(Box::new(data))
+    }
+}
+
+/// The kind of token holding a value of type `spec`, if it is read as a single
+/// token, see `TokenReader::tokens_at`.
+fn token_kind(spec: &TypeSpec) -> Option<TokenKind> {
+    match *spec {
+        TypeSpec::Boolean => Some(TokenKind::Bool),
+        TypeSpec::Number => Some(TokenKind::Float),
+        TypeSpec::UnsignedLong => Some(TokenKind::UnsignedLong),
+        TypeSpec::String => Some(TokenKind::String),
+        TypeSpec::IdentifierName => Some(TokenKind::IdentifierName),
+        TypeSpec::PropertyKey => Some(TokenKind::PropertyKey),
+        _ => None
+    }
+}
+
+/// Reject null tokens where the grammar does not accept them.
+fn check_token(token: &Token, optional: bool) -> Result<(), TokenReaderError> {
+    if optional {
+        return Ok(());
+    }
+    match *token {
+        Token::Bool(None) => Err(TokenReaderError::EmptyBool),
+        Token::Float(None) => Err(TokenReaderError::InvalidValue),
+        Token::String(None)
+        | Token::IdentifierName(None)
+        | Token::PropertyKey(None) => Err(TokenReaderError::EmptyString),
+        _ => Ok(())
+    }
+}
+
+/// A structure used to read a tree from a token reader, following a `Spec`,
+/// without building it.
+///
+/// As with `Serializer`, the `Spec` is expected to be deanonymized, so that lazy
+/// fields are preceded by their `_skip` offset.
+pub struct Deserializer<'a, R> where R: TokenReader {
+    spec: &'a Spec,
+    pub reader: R,
+}
+impl<'a, R> Deserializer<'a, R> where R: TokenReader {
+    pub fn new(spec: &'a Spec, reader: R) -> Self {
+        Deserializer {
+            spec,
+            reader,
+        }
+    }
+
+    /// Read an instance of the root of the grammar.
+    pub fn deserialize(&mut self, path: &mut Path) -> Result<(), TokenReaderError> {
+        let root = TypeSpec::NamedType(self.spec.get_root_name().clone());
+        self.deserialize_type_spec(&root, false, path)
+    }
+
+    fn deserialize_type(&mut self, type_: &Type, path: &mut Path) -> Result<(), TokenReaderError> {
+        self.deserialize_type_spec(type_.spec(), type_.is_optional(), path)
+    }
+
+    fn deserialize_type_spec(&mut self, spec: &TypeSpec, optional: bool, path: &mut Path) -> Result<(), TokenReaderError> {
+        // Reject null values where the grammar does not accept them.
+        let check = |present: bool, error: TokenReaderError| {
+            if present || optional {
+                Ok(())
+            } else {
+                Err(error)
+            }
+        };
+        match *spec {
+            TypeSpec::Boolean => check(self.reader.bool_at(path)?.is_some(), TokenReaderError::EmptyBool),
+            TypeSpec::Number => check(self.reader.float_at(path)?.is_some(), TokenReaderError::InvalidValue),
+            TypeSpec::UnsignedLong => self.reader.unsigned_long_at(path).map(|_| ()),
+            TypeSpec::Offset => self.reader.offset_at(path).map(|_| ()),
+            TypeSpec::String => check(self.reader.string_at(path)?.is_some(), TokenReaderError::EmptyString),
+            TypeSpec::IdentifierName => check(self.reader.identifier_name_at(path)?.is_some(), TokenReaderError::EmptyString),
+            TypeSpec::PropertyKey => check(self.reader.property_key_at(path)?.is_some(), TokenReaderError::EmptyString),
+            TypeSpec::BigInt => check(self.reader.big_int_at(path)?.is_some(), TokenReaderError::EmptyBigInt),
+            TypeSpec::RegExpPattern => check(self.reader.reg_exp_pattern_at(path)?.is_some(), TokenReaderError::EmptyString),
+            TypeSpec::RegExpFlags => check(self.reader.reg_exp_flags_at(path)?.is_some(), TokenReaderError::EmptyString),
+            TypeSpec::Void => Ok(()),
+            TypeSpec::NamedType(ref name) => {
+                let spec = self.spec;
+                match spec.get_type_by_name(name) {
+                    Some(NamedType::Interface(ref interface)) =>
+                        self.deserialize_tagged_tuple(|name| name == interface.name(), optional, path),
+                    Some(NamedType::Typedef(ref type_)) =>
+                        self.deserialize_type_spec(type_.spec(), optional || type_.is_optional(), path),
+                    Some(NamedType::StringEnum(ref enum_)) => {
+                        let value = self.reader.string_enum_at(path)?;
+                        if enum_.strings().iter().any(|x| x.as_str() == value.as_str()) {
+                            Ok(())
+                        } else {
+                            Err(TokenReaderError::BadEnumVariant)
+                        }
+                    }
+                    None => Err(TokenReaderError::InvalidValue)
+                }
+            }
+            TypeSpec::Array { ref contents, .. } => {
+                let len = self.reader.enter_list_at(path)?;
+                match token_kind(contents.spec()) {
+                    Some(kind) => {
+                        // Lists of primitive values are read in a single batch.
+                        let mut tokens = Vec::with_capacity(len as usize);
+                        self.reader.tokens_at(kind, len as usize, path, &mut tokens)?;
+                        for token in &tokens {
+                            check_token(token, contents.is_optional())?;
+                        }
+                    }
+                    None => {
+                        for _ in 0..len {
+                            self.deserialize_type(contents, path)?;
+                        }
+                    }
+                }
+                self.reader.exit_list_at(path)
+            }
+            TypeSpec::TypeSum(ref sum) => {
+                let spec = self.spec;
+                self.deserialize_tagged_tuple(|name| sum.get_interface(spec, name).is_some(), optional, path)
+            }
+        }
+    }
+
+    /// Read a tagged tuple, which must be an interface accepted by `accept` or,
+    /// if `optional`, null.
+    fn deserialize_tagged_tuple<F>(&mut self, accept: F, optional: bool, path: &mut Path) -> Result<(), TokenReaderError>
+        where F: Fn(&NodeName) -> bool
+    {
+        let (interface_name, _) = self.reader.enter_tagged_tuple_at(path)?;
+        let result = self.deserialize_interface(&interface_name, accept, optional, path);
+        if result.is_err() {
+            self.reader.poison();
+        }
+        self.reader.exit_tagged_tuple_at(path)?;
+        result
+    }
+
+    fn deserialize_interface<F>(&mut self, interface_name: &InterfaceName, accept: F, optional: bool, path: &mut Path) -> Result<(), TokenReaderError>
+        where F: Fn(&NodeName) -> bool
+    {
+        let spec = self.spec;
+        if interface_name.as_str() == spec.get_null_name().to_str() {
+            return if optional {
+                Ok(())
+            } else {
+                Err(TokenReaderError::BadEnumVariant)
+            };
+        }
+        let interface = spec.get_node_name(interface_name.as_str())
+            .filter(|name| accept(*name))
+            .and_then(|name| spec.get_interface_by_name(name))
+            .ok_or(TokenReaderError::BadEnumVariant)?;
+
+        path.enter_interface(interface_name.clone());
+        let result = self.deserialize_fields(interface, path);
+        path.exit_interface(interface_name.clone());
+        result
+    }
+
+    fn deserialize_fields(&mut self, interface: &Interface, path: &mut Path) -> Result<(), TokenReaderError> {
+        // The byte length of the next lazy field, read from its `_skip` field.
+        let mut byte_len = None;
+
+        // Consecutive fields of primitive values, read in a single batch, and
+        // whether each of them accepts null values.
+        let mut batch = vec![];
+        let mut optional = vec![];
+        for (index, field) in interface.contents().fields().iter().enumerate() {
+            let path_item = (index, FieldName::from_string(field.name().to_str().to_string()));
+            if !field.is_lazy() {
+                if let Some(kind) = token_kind(field.type_().spec()) {
+                    batch.push((path_item, kind));
+                    optional.push(field.type_().is_optional());
+                    continue;
+                }
+            }
+            self.deserialize_batch(&mut batch, &mut optional, path)?;
+
+            path.enter_field(path_item.clone());
+            let result = match *field.type_().spec() {
+                TypeSpec::Offset => {
+                    self.reader.offset_at(path)
+                        .map(|len| byte_len = Some(len))
+                }
+                _ if field.is_lazy() => {
+                    match byte_len.take() {
+                        Some(len) => match self.reader.skip_lazy_at(len, path) {
+                            Ok(true) => Ok(()),
+                            Ok(false) => self.deserialize_type(field.type_(), path),
+                            Err(err) => Err(err)
+                        },
+                        None => self.deserialize_type(field.type_(), path)
+                    }
+                }
+                _ => self.deserialize_type(field.type_(), path)
+            };
+            path.exit_field(path_item);
+            result?;
+        }
+        self.deserialize_batch(&mut batch, &mut optional, path)
+    }
+
+    /// Read the fields of `batch`, if any, with `TokenReader::fields_at`, then clear it.
+    fn deserialize_batch(&mut self, batch: &mut Vec<((usize, FieldName), TokenKind)>, optional: &mut Vec<bool>, path: &mut Path) -> Result<(), TokenReaderError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let mut tokens = Vec::with_capacity(batch.len());
+        self.reader.fields_at(batch, path, &mut tokens)?;
+        for (token, optional) in tokens.iter().zip(optional.iter()) {
+            check_token(token, *optional)?;
+        }
+        batch.clear();
+        optional.clear();
+        Ok(())
+    }
+}
+
+/// Fail unless `found`, as declared by a file, is `expected`. Files that do not
+/// declare their grammar are accepted.
+fn check_grammar(expected: &GrammarId, found: Option<&GrammarId>) -> Result<(), TokenReaderError> {
+    match found {
+        Some(grammar) if grammar != expected => {
+            Err(TokenReaderError::UnsupportedGrammar(grammar.clone()))
+        }
+        _ => Ok(())
+    }
+}
+
+/// Read files in any format, following a grammar loaded at runtime, reporting
+/// the tokens read to an `EventHandler`, without building an AST.
+pub struct EventDecoder {
+    /// The deanonymized grammar.
+    spec: Spec,
+
+    /// The grammar, as declared by files that support grammar identifiers.
+    grammar: GrammarId,
+}
+impl EventDecoder {
+    /// Create a decoder for files encoded with `spec`, identified as `grammar`
+    /// in files that support grammar identifiers.
+    ///
+    /// Files that declare another grammar are rejected.
+    pub fn new(spec: &Spec, grammar: GrammarId) -> Self {
+        EventDecoder {
+            spec: deanonymize(spec),
+            grammar,
+        }
+    }
+
+    /// Read a file, reporting its tokens to `handler`. Returns the handler.
+    pub fn decode<R: Read + Seek, H: EventHandler>(&self, format: &mut binjs_io::Format, source: R, handler: H) -> Result<H, TokenReaderError> {
+        format.read(source, EventsVisitor {
+            spec: &self.spec,
+            grammar: &self.grammar,
+            handler,
+        })
+    }
+}
+
+/// Read a tree from the token reader of any format, see `EventDecoder::decode`.
+struct EventsVisitor<'a, H> {
+    spec: &'a Spec,
+    grammar: &'a GrammarId,
+    handler: H,
+}
+impl<'a, H> ReaderVisitor for EventsVisitor<'a, H> where H: EventHandler {
+    type Output = H;
+    fn visit<R: TokenReader>(self, reader: R, grammar: Option<&GrammarId>) -> Result<H, TokenReaderError> {
+        check_grammar(self.grammar, grammar)?;
+        let mut path = Path::new();
+        let mut deserializer = Deserializer::new(self.spec, TokenReaderEventAdapter::new(reader, self.handler));
+        deserializer.deserialize(&mut path)?;
+        let (_, handler) = deserializer.reader.done();
+        Ok(handler)
+    }
+}
+
+/// An error while converting a file from one format to another.
+#[derive(Debug)]
+pub enum TranscodeError {
+    Read(TokenReaderError),
+    Write(TokenWriterError),
+}
+impl From<TokenWriterError> for TranscodeError {
+    fn from(value: TokenWriterError) -> Self {
+        TranscodeError::Write(value)
+    }
+}
+
+/// Convert files from one format to another, following a grammar loaded at runtime.
+///
+/// The tokens read from the source are written to the destination as they are read,
+/// without building an AST, hence without parsing or annotating the text source again.
+pub struct Transcoder {
+    /// The encoder of the destination, which also holds the deanonymized grammar.
+    encoder: Encoder,
+}
+impl Transcoder {
+    /// Create a transcoder for files encoded with `spec`, identified as `grammar`
+    /// in files that support grammar identifiers.
+    ///
+    /// Source files that declare another grammar are rejected.
+    pub fn new(spec: &Spec, grammar: GrammarId) -> Self {
+        Transcoder {
+            encoder: Encoder {
+                spec: deanonymize(spec),
+                grammar,
+            }
+        }
+    }
+
+    /// Convert `source`, encoded with format `from`, to format `to`.
+    ///
+    /// Source positions are not kept, as they are not read as tokens.
+    pub fn transcode<R: Read + Seek>(&self, from: &mut binjs_io::Format, to: &mut binjs_io::Format, source: R) -> Result<Box<AsRef<[u8]>>, TranscodeError> {
+        from.read(source, TranscodeVisitor {
+            encoder: &self.encoder,
+            to,
+        }).map_err(TranscodeError::Read)?
+    }
+}
+
+/// Read a tree from the token reader of any format, see `Transcoder::transcode`.
+struct TranscodeVisitor<'a> {
+    encoder: &'a Encoder,
+    to: &'a mut binjs_io::Format,
+}
+impl<'a> ReaderVisitor for TranscodeVisitor<'a> {
+    type Output = Result<Box<AsRef<[u8]>>, TranscodeError>;
+    fn visit<R: TokenReader>(self, reader: R, grammar: Option<&GrammarId>) -> Result<Self::Output, TokenReaderError> {
+        check_grammar(&self.encoder.grammar, grammar)?;
+        Ok(self.encoder.write(self.to, BridgeVisitor {
+            spec: &self.encoder.spec,
+            reader,
+        }))
+    }
+}
+
+/// Write the tokens read from `reader` to the token writer of any format, see `Transcoder::transcode`.
+struct BridgeVisitor<'a, R> {
+    spec: &'a Spec,
+    reader: R,
+}
+impl<'a, R> WriterVisitor for BridgeVisitor<'a, R> where R: TokenReader {
+    type Error = TranscodeError;
+    fn visit<W>(self, writer: W) -> Result<Box<AsRef<[u8]>>, TranscodeError>
+        where W: TokenWriter, W::Data: 'static
+    {
+        let handler = WriterHandler {
+            spec: self.spec,
+            writer,
+            interfaces: vec![],
+            error: None,
+        };
+        let mut path = Path::new();
+        let mut deserializer = Deserializer::new(self.spec, TokenReaderEventAdapter::new(self.reader, handler));
+        if let Err(err) = deserializer.deserialize(&mut path) {
+            // Report the error of the writer that stopped reading, if any.
+            return Err(match deserializer.reader.handler_mut().error.take() {
+                Some(write_err) => TranscodeError::Write(write_err),
+                None => TranscodeError::Read(err)
+            });
+        }
+        let (_, handler) = deserializer.reader.done();
+        let data = handler.writer.done()?;
+        Ok(Box::new(data))
+    }
+}
+
+/// An `EventHandler` writing each token read to a `TokenWriter`, see `Transcoder`.
+struct WriterHandler<'a, W> where W: TokenWriter {
+    spec: &'a Spec,
+    writer: W,
+
+    /// The tagged tuples being written, with the names of their fields, innermost last.
+    interfaces: Vec<(InterfaceName, Vec<FieldName>)>,
+
+    /// The error of `writer`, if any. As handlers may only return `TokenReaderError`s,
+    /// the error is kept here while reading stops.
+    error: Option<TokenWriterError>,
+}
+
+/// Keep the error of a `WriterHandler`, if any, to stop reading.
+fn check_write(error: &mut Option<TokenWriterError>, result: Result<(), TokenWriterError>) -> Result<(), TokenReaderError> {
+    result.map_err(|err| {
+        *error = Some(err);
+        TokenReaderError::InvalidValue
+    })
+}
+
+impl<'a, W> EventHandler for WriterHandler<'a, W> where W: TokenWriter {
+    fn enter_interface(&mut self, name: &InterfaceName, path: &Path) -> Result<(), TokenReaderError> {
+        let spec = self.spec;
+        let field_names : Vec<_> =
+            if name.as_str() == spec.get_null_name().to_str() {
+                vec![]
+            } else {
+                let interface = spec.get_node_name(name.as_str())
+                    .and_then(|node_name| spec.get_interface_by_name(node_name))
+                    .ok_or(TokenReaderError::BadEnumVariant)?;
+                interface.contents()
+                    .fields()
+                    .iter()
+                    .map(|field| FieldName::from_string(field.name().to_str().to_string()))
+                    .collect()
+            };
+        let result = {
+            let field_refs : Vec<_> = field_names.iter()
+                .collect();
+            self.writer.enter_tagged_tuple_at(&GenericNode, name, &field_refs, path)
+        };
+        self.interfaces.push((name.clone(), field_names));
+        check_write(&mut self.error, result)
+    }
+    fn exit_interface(&mut self, _name: &InterfaceName, path: &Path) -> Result<(), TokenReaderError> {
+        let (name, field_names) = self.interfaces.pop()
+            .ok_or(TokenReaderError::InvalidValue)?;
+        let field_refs : Vec<_> = field_names.iter()
+            .collect();
+        check_write(&mut self.error, self.writer.exit_tagged_tuple_at(&GenericNode, &name, &field_refs, path))
+    }
+    fn enter_list(&mut self, len: u32, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.enter_list_at(len as usize, path))
+    }
+    fn exit_list(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.exit_list_at(path))
+    }
+    fn bool(&mut self, value: Option<bool>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.bool_at(value, path))
+    }
+    fn float(&mut self, value: Option<f64>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.float_at(value, path))
+    }
+    fn unsigned_long(&mut self, value: u32, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.unsigned_long_at(value, path))
+    }
+    fn string(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.string_at(value, path))
+    }
+    fn string_enum(&mut self, value: &SharedString, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.string_enum_at(value, path))
+    }
+    fn identifier_name(&mut self, value: Option<&IdentifierName>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.identifier_name_at(value, path))
+    }
+    fn property_key(&mut self, value: Option<&PropertyKey>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.property_key_at(value, path))
+    }
+    fn big_int(&mut self, value: Option<&BigInt>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.big_int_at(value, path))
+    }
+    fn reg_exp_pattern(&mut self, value: Option<&RegExpPattern>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.reg_exp_pattern_at(value, path))
+    }
+    fn reg_exp_flags(&mut self, value: Option<&RegExpFlags>, path: &Path) -> Result<(), TokenReaderError> {
+        check_write(&mut self.error, self.writer.reg_exp_flags_at(value, path))
+    }
+    fn offset(&mut self, _byte_len: u32, skipped: bool, path: &Path) -> Result<(), TokenReaderError> {
+        if skipped {
+            // We cannot write contents that we have not read.
+            return Err(TokenReaderError::InvalidValue);
+        }
+        // Writers compute the byte length of the contents themselves.
+        check_write(&mut self.error, self.writer.offset_at(path))
+    }
+}
@@ -6,10 +6,9 @@
 extern crate binjs_es6;
 extern crate binjs_io;
 extern crate binjs_meta;
+#[macro_use]
 extern crate binjs_shared;
 
-#[macro_use]
-extern crate json;
 extern crate rand;
 
 pub mod annotate;
@@ -17,6 +16,12 @@ pub mod annotate;
 /// Generic instance of `Spec` representing the es6 AST.
 pub mod es6;
 
+/// Encoding ASTs following a grammar loaded at runtime.
+pub mod io;
+
+/// Typed navigation in JSON ASTs.
+pub mod path;
+
 /// Generating random ASTs (for fuzzing purposes).
 pub mod pick;
 
@@ -0,0 +1,217 @@
+//! Typed navigation in JSON ASTs.
+//!
+//! Rather than indexing JSON values with string literals, converters and annotators
+//! use the `ASTField` constants generated from the grammar in `es6::fields`, so that
+//! a misspelled interface or field name is a compile-time error.
+//!
+//! Only nodes of the es6 grammar have generated fields. The Shift converter declares
+//! `ASTField` constants for the fields of Shift nodes that the es6 grammar does not
+//! have, e.g. `FunctionDeclaration.body`. Converters from ESTree and Babel still index
+//! nodes by name.
+
+use syntax::ASTError;
+
+use binjs_shared::{ JSON, JSONExt };
+
+use std;
+
+/// A field of an interface, e.g. `VariableDeclaration.declarators`.
+///
+/// Values for the es6 grammar are generated in `es6::fields`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ASTField {
+    /// The name of the interface, as it appears in the `type` of nodes.
+    pub interface: &'static str,
+
+    /// The name of the field.
+    pub field: &'static str,
+}
+impl ASTField {
+    /// Check that `node` is an instance of `self.interface`.
+    fn check_interface(&self, node: &JSON) -> Result<(), ASTError> {
+        if node["type"] == self.interface {
+            Ok(())
+        } else {
+            Err(ASTError::invalid_value(node, self.interface))
+        }
+    }
+
+    /// Access this field in `node`.
+    ///
+    /// Fails if `node` is not an instance of `self.interface` or does not have the field.
+    pub fn get<'a>(&self, node: &'a JSON) -> Result<&'a JSON, ASTError> {
+        self.check_interface(node)?;
+        node.get(self.field)
+            .ok_or_else(|| ASTError::missing_field(&self.to_string()))
+    }
+
+    /// Access this field in `node`, mutably.
+    ///
+    /// Fails if `node` is not an instance of `self.interface` or does not have the field.
+    pub fn get_mut<'a>(&self, node: &'a mut JSON) -> Result<&'a mut JSON, ASTError> {
+        self.check_interface(node)?;
+        let name = self.to_string();
+        node.get_mut(self.field)
+            .ok_or_else(|| ASTError::missing_field(&name))
+    }
+
+    /// Remove this field from `node`, returning its value.
+    ///
+    /// Fails if `node` is not an instance of `self.interface` or does not have the field.
+    pub fn take(&self, node: &mut JSON) -> Result<JSON, ASTError> {
+        self.get_mut(node)?;
+        Ok(node.remove(self.field))
+    }
+
+    /// Remove this field from `node`, if it has it, returning its value.
+    ///
+    /// Fails if `node` is not an instance of `self.interface`.
+    pub fn remove(&self, node: &mut JSON) -> Result<Option<JSON>, ASTError> {
+        self.check_interface(node)?;
+        if node.get(self.field).is_some() {
+            Ok(Some(node.remove(self.field)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Set this field in `node`, which may not have it yet.
+    ///
+    /// Fails if `node` is not an instance of `self.interface`.
+    pub fn set(&self, node: &mut JSON, value: JSON) -> Result<(), ASTError> {
+        self.check_interface(node)?;
+        node[self.field] = value;
+        Ok(())
+    }
+}
+impl std::fmt::Display for ASTField {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}.{}", self.interface, self.field)
+    }
+}
+
+/// A step of an `ASTPath`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ASTPathItem {
+    /// Enter a field of an interface.
+    Field(ASTField),
+
+    /// Enter an item of a list.
+    Index(usize),
+}
+impl std::fmt::Display for ASTPathItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            ASTPathItem::Field(ref field) => write!(f, ".{}", field),
+            ASTPathItem::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+/// A path from a node of a JSON AST to one of its descendants.
+///
+/// ```
+/// # #[macro_use] extern crate binjs_shared;
+/// extern crate binjs_generic;
+///
+/// use binjs_generic::es6::fields::{ variable_declaration, variable_declarator };
+/// use binjs_generic::path::ASTPath;
+///
+/// # fn main() {
+/// let declaration = object!{
+///     "type" => "VariableDeclaration",
+///     "kind" => "var",
+///     "declarators" => array![
+///         object!{
+///             "type" => "VariableDeclarator",
+///             "binding" => object!{
+///                 "type" => "BindingIdentifier",
+///                 "name" => "x"
+///             },
+///             "init" => binjs_shared::JSON::Null
+///         }
+///     ]
+/// };
+///
+/// let path = ASTPath::new()
+///     .with_field(variable_declaration::DECLARATORS)
+///     .with_index(0)
+///     .with_field(variable_declarator::BINDING);
+/// assert_eq!(path.get(&declaration).unwrap()["name"], "x");
+/// assert_eq!(path.to_string(), ".VariableDeclaration.declarators[0].VariableDeclarator.binding");
+///
+/// // Navigation fails if the nodes are not instances of the expected interfaces.
+/// let path = ASTPath::new()
+///     .with_field(variable_declarator::BINDING);
+/// assert!(path.get(&declaration).is_err());
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ASTPath {
+    items: Vec<ASTPathItem>,
+}
+impl ASTPath {
+    /// An empty path, designating the node itself.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Extend the path with a field.
+    pub fn with_field(mut self, field: ASTField) -> Self {
+        self.items.push(ASTPathItem::Field(field));
+        self
+    }
+
+    /// Extend the path with an item of a list.
+    pub fn with_index(mut self, index: usize) -> Self {
+        self.items.push(ASTPathItem::Index(index));
+        self
+    }
+
+    /// The steps of this path, from the root.
+    pub fn items(&self) -> &[ASTPathItem] {
+        &self.items
+    }
+
+    /// Access the descendant of `root` designated by this path.
+    pub fn get<'a>(&self, root: &'a JSON) -> Result<&'a JSON, ASTError> {
+        let mut node = root;
+        for item in &self.items {
+            node = match *item {
+                ASTPathItem::Field(ref field) => field.get(node)?,
+                ASTPathItem::Index(index) => Self::index(node, index)?,
+            };
+        }
+        Ok(node)
+    }
+
+    /// Access the descendant of `root` designated by this path, mutably.
+    pub fn get_mut<'a>(&self, root: &'a mut JSON) -> Result<&'a mut JSON, ASTError> {
+        let mut node = root;
+        for item in &self.items {
+            node = match *item {
+                ASTPathItem::Field(ref field) => field.get_mut(node)?,
+                ASTPathItem::Index(index) => {
+                    Self::index(node, index)?;
+                    &mut node[index]
+                }
+            };
+        }
+        Ok(node)
+    }
+
+    fn index(node: &JSON, index: usize) -> Result<&JSON, ASTError> {
+        match *node {
+            JSON::Array(ref array) if index < array.len() => Ok(&array[index]),
+            _ => Err(ASTError::invalid_value(node, &format!("list with at least {} items", index + 1)))
+        }
+    }
+}
+impl std::fmt::Display for ASTPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        for item in &self.items {
+            item.fmt(f)?;
+        }
+        Ok(())
+    }
+}
@@ -1,14 +1,23 @@
+//! Instances are valid with respect to the grammar, i.e. each node has the `type` of
+//! an interface and the fields of this interface, with values of the types declared
+//! by the grammar, so they may be encoded with any format. They are not expected
+//! to make sense as JavaScript, e.g. scopes do not match the variables they declare.
+
 use util::pick;
 
 use binjs_meta::spec::*;
 use std::iter;
 
-use json;
-use json::JsonValue as JSON;
+use binjs_shared::{ JSON, JSONObject, RegExpFlags };
 use rand;
 use rand::distributions::Alphanumeric;
 
 pub trait Pick {
+    /// Generate a random value.
+    ///
+    /// Once `depth_limit` reaches 0, the generator only produces the smallest
+    /// values permitted by the grammar, i.e. `null` for optional values, empty or
+    /// single-item lists, and the interfaces with the fewest subtrees in sums.
     fn random<T: rand::Rng>(&self, syntax: &Spec, rng: &mut T, depth_limit: isize) -> JSON;
 }
 
@@ -44,7 +53,11 @@ impl Pick for TypeSpec {
                     return array![]
                 }
                 let min = if supports_empty { 0 } else { 1 };
-                let len = rng.gen_range(min, MAX_ARRAY_LEN);
+                let len = if depth_limit <= 0 {
+                    min
+                } else {
+                    rng.gen_range(min, MAX_ARRAY_LEN)
+                };
                 let mut buf = Vec::with_capacity(len);
                 for _ in 0..len {
                     buf.push(type_.random(syntax, rng, depth_limit - 1));
@@ -59,29 +72,64 @@ impl Pick for TypeSpec {
                 }
             }
             TypeSpec::TypeSum(ref types) => {
-                let type_ = pick(rng, types.types());
+                let type_ = if depth_limit <= 0 {
+                    // Pick among the alternatives with the fewest subtrees, to make sure
+                    // that generation terminates.
+                    let smallest = types.types().iter()
+                        .map(|type_| subtrees(type_, syntax))
+                        .min()
+                        .expect("Empty sum");
+                    let candidates : Vec<_> = types.types().iter()
+                        .filter(|type_| subtrees(type_, syntax) == smallest)
+                        .collect();
+                    *pick(rng, &candidates)
+                } else {
+                    pick(rng, types.types())
+                };
                 type_.random(syntax, rng, depth_limit)
             }
             TypeSpec::Boolean => {
-                JSON::Boolean(rng.gen())
+                JSON::Bool(rng.gen())
             }
             TypeSpec::String
-            | TypeSpec::PropertyKey
-            | TypeSpec::IdentifierName =>
+            | TypeSpec::RegExpPattern =>
             {
                 const MAX_STRING_LEN : usize = 10;
                 let len = rng.gen_range(0, MAX_STRING_LEN);
                 let string : String = iter::repeat(()).map(|()| rng.sample(Alphanumeric)).take(len).collect();
-                json::from(string)
+                JSON::from(string)
+            }
+            TypeSpec::PropertyKey
+            | TypeSpec::IdentifierName =>
+            {
+                // Valid IdentifierNames, which may not be empty or start with a digit,
+                // as encoders reject other names.
+                const MAX_STRING_LEN : usize = 10;
+                let len = rng.gen_range(0, MAX_STRING_LEN);
+                let first = (b'a' + rng.gen_range(0, 26)) as char;
+                let rest : String = iter::repeat(()).map(|()| rng.sample(Alphanumeric)).take(len).collect();
+                JSON::from(format!("{}{}", first, rest))
             }
             TypeSpec::Number => {
-                json::from(rng.gen::<f64>())
+                JSON::from(rng.gen::<f64>())
+            }
+            TypeSpec::BigInt => {
+                JSON::from(rng.gen::<i64>().to_string())
+            }
+            TypeSpec::RegExpFlags => {
+                let bits = rng.gen_range(0, 1 << RegExpFlags::ALL.len());
+                let flags = RegExpFlags::from_bits(bits)
+                    .unwrap(); // We only picked known flags.
+                JSON::from(flags.as_str())
             }
             TypeSpec::Void =>
                 JSON::Null,
-            TypeSpec::Offset |
+            TypeSpec::Offset => {
+                // Offsets are computed by encoders, and decoded as 0 by the specialized AST.
+                JSON::from(0)
+            }
             TypeSpec::UnsignedLong => {
-                json::from(rng.gen_range(0, u32::max_value()))
+                JSON::from(rng.gen_range(0, u32::max_value()))
             }
         }
     }
@@ -91,7 +139,7 @@ impl Pick for Type {
     fn random<T: rand::Rng>(&self, syntax: &Spec, rng: &mut T, depth_limit: isize) -> JSON {
         if self.is_optional() {
             // 10% chance of returning the default value
-            if depth_limit <= 0 || rng.gen_range(0, 10) > 0 {
+            if depth_limit <= 0 || rng.gen_range(0, 10) == 0 {
                 return JSON::Null
             }
         }
@@ -102,12 +150,13 @@ impl Pick for Type {
 impl Pick for Interface {
     /// Generate a random instance of this interface matching the syntax.
     fn random<T: rand::Rng>(&self, syntax: &Spec, rng: &mut T, depth_limit: isize) -> JSON {
-        let mut obj = json::object::Object::with_capacity(self.contents().fields().len());
+        let mut obj = JSONObject::with_capacity(self.contents().fields().len() + 1);
+        obj.insert("type".to_string(), JSON::from(self.name().to_str()));
         for field in self.contents().fields() {
             let value = field.type_().random(syntax, rng, depth_limit - 1);
-            obj.insert(field.name().to_str(), value);
+            obj.insert(field.name().to_str().to_string(), value);
         }
-        json::JsonValue::Object(obj)
+        JSON::Object(obj)
     }
 }
 
@@ -121,3 +170,32 @@ impl Pick for Spec {
         root.random(self, rng, depth_limit)
     }
 }
+
+/// The number of subtrees that a value of type `type_` must have, as a hint of its size.
+///
+/// For sums, this is the number of subtrees of the smallest alternative.
+fn subtrees(type_: &TypeSpec, syntax: &Spec) -> usize {
+    match *type_ {
+        TypeSpec::NamedType(ref name) => match syntax.get_type_by_name(name) {
+            Some(NamedType::Interface(ref interface)) => interface.contents().fields().iter()
+                .filter(|field| !field.type_().is_optional())
+                .filter(|field| match *field.type_().spec() {
+                    TypeSpec::NamedType(ref name) => match syntax.get_type_by_name(name) {
+                        Some(NamedType::StringEnum(_)) | None => false,
+                        Some(_) => true,
+                    },
+                    TypeSpec::TypeSum(_) => true,
+                    TypeSpec::Array { supports_empty, .. } => !supports_empty,
+                    _ => false,
+                })
+                .count(),
+            Some(NamedType::Typedef(ref type_)) if !type_.is_optional() => subtrees(type_.spec(), syntax),
+            _ => 0,
+        },
+        TypeSpec::TypeSum(ref types) => types.types().iter()
+            .map(|type_| subtrees(type_, syntax))
+            .min()
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
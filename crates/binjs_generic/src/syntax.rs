@@ -1,12 +1,11 @@
 use util::type_of;
 
+use binjs_io;
 use binjs_meta::spec::*;
-use binjs_shared;
+use binjs_shared::{ self, BigInt, FromJSON, JSON, JSONExt, RegExpFlags };
 
 use std;
 
-use json::JsonValue as JSON;
-
 pub type WalkPathItem = binjs_shared::ast::PathItem<NodeName, FieldName>;
 pub type WalkPath = binjs_shared::ast::Path<NodeName, FieldName>;
 
@@ -25,17 +24,22 @@ impl Comparator {
 
 impl Compare for TypeSpec {
     fn compare(&self, syntax: &Spec, left: &JSON, right: &JSON) -> Result<bool, ASTError> {
-        use json::JsonValue::*;
         match (self, left, right) {
-            (&TypeSpec::Boolean, &Boolean(ref a), &Boolean(ref b)) =>
+            (&TypeSpec::Boolean, &JSON::Bool(ref a), &JSON::Bool(ref b)) =>
                 Ok(a == b),
-            (&TypeSpec::String, _, _) | (&TypeSpec::PropertyKey, _, _) | (&TypeSpec::IdentifierName, _, _) if left.as_str().is_some() && right.as_str().is_some() => // Strings are complicated as they have two different representations in JSON.
+            (&TypeSpec::String, _, _) | (&TypeSpec::PropertyKey, _, _) | (&TypeSpec::IdentifierName, _, _) | (&TypeSpec::BigInt, _, _) | (&TypeSpec::RegExpPattern, _, _) if left.as_str().is_some() && right.as_str().is_some() => // Strings are complicated as they have two different representations in JSON.
                 Ok(left.as_str() == right.as_str()),
-            (&TypeSpec::Number, &Number(ref a), &Number(ref b)) =>
-                Ok(a == b),
-            (&TypeSpec::UnsignedLong, &Number(ref a), &Number(ref b)) =>
-                Ok(a == b),
-            (&TypeSpec::Array { contents: ref type_, .. }, &Array(ref vec_a), &Array(ref vec_b)) => {
+            // Flags are sets, compare them regardless of their order.
+            (&TypeSpec::RegExpFlags, &JSON::String(ref a), &JSON::String(ref b)) => {
+                let bits = |s: &String| RegExpFlags::from_string(s.clone()).to_bits();
+                Ok(bits(a).is_some() && bits(a) == bits(b))
+            }
+            // Compare as floats, as integers may have been parsed as such, then exported as floats.
+            (&TypeSpec::Number, &JSON::Number(ref a), &JSON::Number(ref b)) =>
+                Ok(a.as_f64() == b.as_f64()),
+            (&TypeSpec::UnsignedLong, &JSON::Number(ref a), &JSON::Number(ref b)) =>
+                Ok(a.as_f64() == b.as_f64()),
+            (&TypeSpec::Array { contents: ref type_, .. }, &JSON::Array(ref vec_a), &JSON::Array(ref vec_b)) => {
                 if vec_a.len() != vec_b.len() {
                     Ok(false)
                 } else {
@@ -89,9 +93,8 @@ impl Compare for Type {
     /// Compare two ASTs, restricting comparison to the
     /// items that appear in the grammar.
     fn compare(&self, syntax: &Spec, left: &JSON, right: &JSON) -> Result<bool, ASTError> {
-        use json::JsonValue::*;
         if self.is_optional() {
-            if let (&Null, &Null) = (left, right) {
+            if let (&JSON::Null, &JSON::Null) = (left, right) {
                 // This is the only case in which we accept `null` as a value.
                 return Ok(true)                
             }
@@ -263,7 +266,7 @@ macro_rules! make_ast_visitor {
             fn walk_type_spec_aux(&mut self, value: & $($mutability)* JSON, spec: &TypeSpec, name: &NodeName) -> Result<(), ASTError> {
                 match spec {
                     &TypeSpec::Boolean => {
-                        if let JSON::Boolean(_) = *value {
+                        if let JSON::Bool(_) = *value {
                             return Ok(())
                         }
                     }
@@ -282,11 +285,21 @@ macro_rules! make_ast_visitor {
                             return Ok(())
                         }
                     }
-                    &TypeSpec::String | &TypeSpec::IdentifierName | &TypeSpec::PropertyKey  => {
+                    &TypeSpec::String | &TypeSpec::IdentifierName | &TypeSpec::PropertyKey | &TypeSpec::RegExpPattern => {
                         if value.as_str().is_some() {
                             return Ok(())
                         }
                     }
+                    &TypeSpec::BigInt => {
+                        if BigInt::import(value).is_ok() {
+                            return Ok(())
+                        }
+                    }
+                    &TypeSpec::RegExpFlags => {
+                        if RegExpFlags::import(value).is_ok() {
+                            return Ok(())
+                        }
+                    }
                     &TypeSpec::Void => {
                         if let JSON::Null = *value {
                             return Ok(())
@@ -404,6 +417,26 @@ pub enum ASTError {
     MissingField(String),
     InvalidScope
 }
+impl std::fmt::Display for ASTError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            ASTError::InvalidField(ref name) => write!(f, "invalid field {}", name),
+            ASTError::Mismatch(ref type_) => write!(f, "value does not match type {:?}", type_),
+            ASTError::InvalidValue { ref got, ref expected } => write!(f, "invalid value {}, expected {}", got, expected),
+            ASTError::InvalidType(ref name) => write!(f, "invalid type {}", name),
+            ASTError::InvalidDescendent { ref got, ref valid } => write!(f, "invalid descendent {}, expected one of {}", got, valid.join(", ")),
+            ASTError::MissingParent(ref name) => write!(f, "missing parent for {}", name),
+            ASTError::MissingField(ref name) => write!(f, "missing field {}", name),
+            ASTError::InvalidScope => write!(f, "invalid scope"),
+        }
+    }
+}
+impl std::error::Error for ASTError {}
+impl From<ASTError> for binjs_io::Error {
+    fn from(error: ASTError) -> Self {
+        binjs_io::Error::new(binjs_io::ErrorKind::Annotation, &error)
+    }
+}
 impl ASTError {
     pub fn invalid_field(name: &str) -> Self {
         ASTError::InvalidField(name.to_string())
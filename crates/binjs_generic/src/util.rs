@@ -1,11 +1,11 @@
-use json::JsonValue as JSON;
+use binjs_shared::JSON;
 use rand;
 
 /// Return a string describing a JSON value
 /// without dumping the entire AST.
 /// 
 /// ```
-/// # #[macro_use] extern crate json;
+/// # #[macro_use] extern crate binjs_shared;
 /// extern crate binjs_generic;
 /// # fn main() {
 /// 
@@ -19,9 +19,9 @@ use rand;
 ///
 /// // Strings
 /// let strings = [
-///     json::from("some string"),
-///     json::from(""), // Short string
-///     json::from("111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111"), // Long string
+///     binjs_shared::JSON::from("some string"),
+///     binjs_shared::JSON::from(""),
+///     binjs_shared::JSON::from("111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111"),
 /// ];
 /// for string in &strings {
 ///     assert_eq!(&binjs_generic::util::type_of(string), "String");
@@ -29,8 +29,8 @@ use rand;
 ///
 /// // Numbers
 /// let numbers = [
-///     json::from(1),
-///     json::from(0.0)
+///     binjs_shared::JSON::from(1),
+///     binjs_shared::JSON::from(0.0)
 /// ];
 /// for num in &numbers {
 ///     assert_eq!(&binjs_generic::util::type_of(num), "Number");
@@ -38,26 +38,25 @@ use rand;
 ///
 /// // Booleans
 /// let booleans = [
-///     json::from(false),
-///     json::from(true),
+///     binjs_shared::JSON::from(false),
+///     binjs_shared::JSON::from(true),
 /// ];
 /// for bool in &booleans {
 ///     assert_eq!(&binjs_generic::util::type_of(bool), "Bool")
 /// }
 ///
 /// // Null
-/// assert_eq!(&binjs_generic::util::type_of(&json::JsonValue::Null), "Null")
+/// assert_eq!(&binjs_generic::util::type_of(&binjs_shared::JSON::Null), "Null")
 /// # }
 /// ```
 pub fn type_of(tree: &JSON) -> String {
-    use json::JsonValue::*;
     match *tree {
-        Object(_) => "Object",
-        String(_) | Short(_) => "String",
-        Number(_) => "Number",
-        Null      => "Null",
-        Boolean(_) => "Bool",
-        Array(_)  => "Array"
+        JSON::Object(_) => "Object",
+        JSON::String(_) => "String",
+        JSON::Number(_) => "Number",
+        JSON::Null      => "Null",
+        JSON::Bool(_)   => "Bool",
+        JSON::Array(_)  => "Array"
     }.to_owned()
 }
 
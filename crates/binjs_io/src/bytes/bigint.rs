@@ -0,0 +1,232 @@
+use bytes::varnum::*;
+
+use std;
+use std::io::{ Cursor, Read, Write };
+
+/// The representation of "no BigInt", used for `BigInt | null`.
+const VARNUM_NULL: [u8; 2] = VARNUM_INVALID_ZERO_1;
+
+/// The maximal number of bytes in the header of a varbigint, i.e. in a 32 bit varnum.
+const MAX_HEADER_LEN: usize = 5;
+
+/// Convert a string of decimal digits, optionally preceded by `-`, into
+/// `(is_negative, magnitude)`, where the magnitude is little-endian, without
+/// trailing zeros.
+///
+/// Returns `None` if `value` is not a decimal number. Leading zeros and `-0`
+/// are accepted, but they are lost in the conversion.
+pub fn magnitude_of_decimal(value: &str) -> Option<(bool, Vec<u8>)> {
+    let (is_negative, digits) = if value.starts_with('-') {
+        (true, &value[1..])
+    } else {
+        (false, value)
+    };
+    let magnitude = magnitude_of_digits(digits, 10)?;
+    // There is no such thing as -0n.
+    Some((is_negative && !magnitude.is_empty(), magnitude))
+}
+
+/// Convert a non-empty string of digits in base `radix`, without sign or prefix,
+/// into a magnitude, little-endian, without trailing zeros.
+///
+/// Returns `None` if `digits` is empty or contains characters that are not digits
+/// in base `radix`.
+///
+/// # Panics
+///
+/// If `radix` is not in [2, 16].
+pub fn magnitude_of_digits(digits: &str, radix: u32) -> Option<Vec<u8>> {
+    assert!((2..=16).contains(&radix));
+    if digits.is_empty() {
+        return None;
+    }
+    let mut magnitude : Vec<u8> = Vec::with_capacity(digits.len() / 2 + 1);
+    for digit in digits.chars() {
+        // magnitude := magnitude * radix + digit
+        let mut carry = digit.to_digit(radix)?;
+        for byte in &mut magnitude {
+            let value = *byte as u32 * radix + carry;
+            *byte = (value & 0xFF) as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            magnitude.push(carry as u8);
+        }
+    }
+    Some(magnitude)
+}
+
+/// Convert `(is_negative, magnitude)`, where the magnitude is little-endian,
+/// into a string of decimal digits, preceded by `-` if the value is negative.
+pub fn decimal_of_magnitude(is_negative: bool, magnitude: &[u8]) -> String {
+    // Most significant byte first, to perform long division by 10.
+    let mut remaining : Vec<u8> = magnitude.iter()
+        .rev()
+        .cloned()
+        .collect();
+    let mut start = 0;
+    let mut digits = vec![];
+    loop {
+        while start < remaining.len() && remaining[start] == 0 {
+            start += 1;
+        }
+        if start == remaining.len() {
+            break;
+        }
+        let mut rem = 0;
+        for byte in &mut remaining[start..] {
+            let value = (rem << 8) | *byte as u32;
+            *byte = (value / 10) as u8;
+            rem = value % 10;
+        }
+        digits.push(b'0' + rem as u8);
+    }
+    if digits.is_empty() {
+        digits.push(b'0');
+    } else if is_negative {
+        digits.push(b'-');
+    }
+    digits.reverse();
+    String::from_utf8(digits)
+        .unwrap() // Only ASCII digits and `-`.
+}
+
+/// Utility for manipulating `varbigints`, a representation of BigInt values of
+/// arbitrary size.
+///
+/// Varbigints are represented as follows:
+/// - null is represented as VARNUM_NULL (16 bits);
+/// - other values are represented as a varnum `2 * byte_length + is_negative`,
+///     followed by the `byte_length` bytes of the magnitude, little-endian,
+///     without trailing zeros.
+///
+/// So, 0n takes 8 bits, values in [-255n, 255n] take 16 bits, etc.
+pub trait WriteVarBigInt {
+    fn write_maybe_varbigint(&mut self, value: Option<&str>) -> Result<usize, std::io::Error>;
+    fn write_varbigint(&mut self, value: &str) -> Result<usize, std::io::Error>;
+}
+
+pub trait ReadVarBigInt {
+    fn read_maybe_varbigint(&mut self) -> Result<Option<String>, std::io::Error>;
+}
+
+impl<T> WriteVarBigInt for T where T: Write {
+    fn write_maybe_varbigint(&mut self, value: Option<&str>) -> Result<usize, std::io::Error> {
+        match value {
+            None => {
+                self.write_all(&VARNUM_NULL)?;
+                Ok(VARNUM_NULL.len())
+            }
+            Some(value) => self.write_varbigint(value)
+        }
+    }
+
+    fn write_varbigint(&mut self, value: &str) -> Result<usize, std::io::Error> {
+        let (is_negative, magnitude) = magnitude_of_decimal(value)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid BigInt (not a decimal number)"))?;
+        if magnitude.len() > (std::u32::MAX >> 1) as usize {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid BigInt (too large)"));
+        }
+        let header = ((magnitude.len() as u32) << 1) | is_negative as u32;
+        let header_len = self.write_varnum(header)?;
+        self.write_all(&magnitude)?;
+        Ok(header_len + magnitude.len())
+    }
+}
+
+impl<T> ReadVarBigInt for T where T: Read {
+    fn read_maybe_varbigint(&mut self) -> Result<Option<String>, std::io::Error> {
+        // Buffer the header, as `read_varnum` rejects VARNUM_NULL.
+        let mut header = Vec::with_capacity(MAX_HEADER_LEN);
+        let mut buf : [u8; 1] = [0];
+        loop {
+            self.read_exact(&mut buf)?;
+            header.push(buf[0]);
+            if buf[0] & 1 == 0 {
+                break;
+            }
+            if header.len() == MAX_HEADER_LEN {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid varbigint (header doesn't fit in 32 bits)"));
+            }
+        }
+        if header[..] == VARNUM_NULL[..] {
+            return Ok(None);
+        }
+        let header = Cursor::new(header).read_varnum()?;
+        let is_negative = header & 1 == 1;
+        let byte_length = (header >> 1) as usize;
+
+        // The length is read from the file, don't trust it for allocations.
+        let mut magnitude = Vec::with_capacity(std::cmp::min(byte_length, 1024));
+        self.by_ref()
+            .take(byte_length as u64)
+            .read_to_end(&mut magnitude)?;
+        if magnitude.len() != byte_length {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Invalid varbigint (truncated magnitude)"));
+        }
+        if magnitude.last() == Some(&0) || (is_negative && magnitude.is_empty()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid varbigint (not canonical)"));
+        }
+        Ok(Some(decimal_of_magnitude(is_negative, &magnitude)))
+    }
+}
+
+#[test]
+fn test_varbigint() {
+    let values = [
+        Some("0"),
+        Some("1"),
+        Some("-1"),
+        Some("255"),
+        Some("256"),
+        Some("-65536"),
+        Some("18446744073709551616"), // 2^64
+        Some("-123456789012345678901234567890123456789"),
+        None,
+    ];
+    let mut buf : Vec<u8> = vec![];
+    let mut total = 0;
+    for value in &values {
+        total += buf.write_maybe_varbigint(*value)
+            .expect("Could not write varbigint");
+    }
+    assert_eq!(total, buf.len());
+
+    let mut inp = Cursor::new(&buf);
+    for value in &values {
+        let decoded = inp.read_maybe_varbigint()
+            .expect("Could not read varbigint");
+        assert_eq!(decoded.as_ref().map(String::as_str), *value);
+    }
+    assert_eq!(inp.position() as usize, buf.len());
+
+    // Small values are short.
+    let len = |value: &str| {
+        let mut buf : Vec<u8> = vec![];
+        buf.write_varbigint(value).unwrap()
+    };
+    assert_eq!(len("0"), 1);
+    assert_eq!(len("-255"), 2);
+    assert_eq!(len("65535"), 3);
+
+    // Non-canonical decimal values are canonicalized.
+    for &(value, canonical) in &[("-0", "0"), ("007", "7")] {
+        let mut buf : Vec<u8> = vec![];
+        buf.write_varbigint(value).unwrap();
+        assert_eq!(Cursor::new(buf).read_maybe_varbigint().unwrap(), Some(canonical.to_string()));
+    }
+
+    // Other bases.
+    assert_eq!(magnitude_of_digits("FfFf", 16), Some(vec![255, 255]));
+    assert_eq!(magnitude_of_digits("1000000000", 2), Some(vec![0, 2]));
+    assert_eq!(magnitude_of_digits("8", 8), None);
+
+    // Invalid values are rejected.
+    for value in &["", "-", "1.5", "0x10", "1n"] {
+        assert!(Vec::<u8>::new().write_varbigint(value).is_err());
+    }
+    let invalid : [Vec<u8>; 5] = [vec![2], vec![6, 0], vec![1], vec![4], vec![1, 1, 1, 1, 1, 0]];
+    for bytes in &invalid {
+        assert!(Cursor::new(bytes).read_maybe_varbigint().is_err());
+    }
+}
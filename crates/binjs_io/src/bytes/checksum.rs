@@ -0,0 +1,49 @@
+use std;
+use std::io::{ Read, Write };
+
+/// Compute the CRC32 (IEEE 802.3, as used by gzip and zip) of a sequence of bytes.
+pub fn crc32(data: &[u8]) -> u32 {
+    const POLYNOMIAL: u32 = 0xEDB8_8320;
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (POLYNOMIAL & mask);
+        }
+    }
+    !crc
+}
+
+/// Write a checksum as 4 bytes, little-endian.
+pub fn write_checksum<W: Write>(out: &mut W, checksum: u32) -> Result<usize, std::io::Error> {
+    let bytes = [
+        checksum as u8,
+        (checksum >> 8) as u8,
+        (checksum >> 16) as u8,
+        (checksum >> 24) as u8,
+    ];
+    out.write_all(&bytes)?;
+    Ok(bytes.len())
+}
+
+/// Read a checksum written by `write_checksum`.
+pub fn read_checksum<R: Read>(inp: &mut R) -> Result<u32, std::io::Error> {
+    let mut bytes = [0; 4];
+    inp.read_exact(&mut bytes)?;
+    Ok(bytes[0] as u32
+        | (bytes[1] as u32) << 8
+        | (bytes[2] as u32) << 16
+        | (bytes[3] as u32) << 24)
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+    let mut buf = vec![];
+    write_checksum(&mut buf, 0xCBF4_3926).unwrap();
+    assert_eq!(buf, [0x26, 0x39, 0xF4, 0xCB]);
+    assert_eq!(read_checksum(&mut std::io::Cursor::new(buf)).unwrap(), 0xCBF4_3926);
+}
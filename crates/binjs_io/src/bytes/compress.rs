@@ -9,15 +9,24 @@ use rand::distributions::Standard;
 use rand::thread_rng;
 use rand::seq::SliceRandom;
 
+use sha2::{ Digest, Sha256 };
+
 use std;
 use std::collections::HashSet;
 use std::io::{ Cursor, Read, Write };
+use std::sync::Arc;
+
+use tracing;
 
 const BROTLI_BUFFER_SIZE : usize = 4096;
 const BROTLI_QUALITY: u32 = 8;
 const BROTLI_LG_WINDOW_SIZE: u32 = 20;
 const LZW_MIN_CODE_SIZE: u8 = 8;
 
+/// The settings of `brotli -11`, used to compress sources for comparison.
+const BROTLI_SOURCE_QUALITY: u32 = 11;
+const BROTLI_SOURCE_LG_WINDOW_SIZE: u32 = 22;
+
 /// The compression mechanisms supported by this encoder.
 /// They are designed to match HTTP's Accept-Encoding:
 /// https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Accept-Encoding
@@ -33,12 +42,17 @@ pub enum Compression {
     Brotli,
     /// Lwz compression (`compress;`)
     Lzw,
+    /// Whichever of the `candidates()` produces the smallest data, decided
+    /// for each section. The header of the section records the compression
+    /// actually used, so the data may be decompressed without knowing that
+    /// it was chosen automatically.
+    Auto,
 }
 
 impl Distribution<Compression> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Compression {
         use self::Compression::*;
-        let choices = [Identity, Gzip, /* Deflate is apprently broken https://github.com/alexcrichton/flate2-rs/issues/151 , */ Brotli /*, Lzw doesn't work yet */];
+        let choices = [Identity, Gzip, /* Deflate is apprently broken https://github.com/alexcrichton/flate2-rs/issues/151 , */ Brotli /*, Lzw doesn't work yet */, Auto];
         choices.choose(rng)
             .unwrap()
             .clone()
@@ -46,6 +60,52 @@ impl Distribution<Compression> for Standard {
 }
 
 
+/// A raw brotli custom dictionary, e.g. common fragments of JS identifiers, shared by
+/// the encoder and the decoder out-of-band.
+///
+/// The dictionary is identified by the SHA-256 hash of its bytes.
+#[derive(Clone)]
+pub struct BrotliDictionary {
+    bytes: Arc<Vec<u8>>,
+    hash: [u8; 32],
+}
+impl BrotliDictionary {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        let mut hash = [0; 32];
+        hash.copy_from_slice(&Sha256::digest(&bytes));
+        BrotliDictionary {
+            bytes: Arc::new(bytes),
+            hash,
+        }
+    }
+
+    /// Read a dictionary from a file.
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self, std::io::Error> {
+        let mut bytes = vec![];
+        std::fs::File::open(path)?
+            .read_to_end(&mut bytes)?;
+        Ok(Self::new(bytes))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The SHA-256 hash of the dictionary.
+    pub fn hash(&self) -> &[u8; 32] {
+        &self.hash
+    }
+}
+impl std::fmt::Debug for BrotliDictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "BrotliDictionary({} bytes, sha256 ", self.bytes.len())?;
+        for byte in self.hash.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, ")")
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct CompressionResult {
     pub before_bytes: usize,
@@ -62,6 +122,7 @@ impl Compression {
             Deflate => "Deflate",
             Brotli => "Brotli",
             Lzw => "Lzw",
+            Auto => "Auto",
         }
     }
 
@@ -73,6 +134,7 @@ impl Compression {
             Deflate => "deflate",
             Brotli => "br",
             Lzw => "lzw",
+            Auto => "auto",
         }
     }
 
@@ -83,6 +145,7 @@ impl Compression {
             Some("br") => Compression::Brotli,
             Some("gzip") => Compression::Gzip,
             Some("deflate") => Compression::Deflate,
+            Some("auto") => Compression::Auto,
             Some("random") => thread_rng().gen(),
             Some(_) => {
                 return None;
@@ -96,6 +159,15 @@ impl Compression {
         Box::new([Identity, Gzip, Deflate, Brotli /*, Lzw doesn't work yet*/])
     }
 
+    /// The compressions tried by `Compression::Auto`, in order of preference
+    /// if several of them produce data of the same size.
+    ///
+    /// Deflate is left out, as it is broken, see `Distribution<Compression>`.
+    pub fn candidates() -> Box<[Self]> {
+        use self::Compression::*;
+        Box::new([Identity, Gzip, Brotli])
+    }
+
     pub fn is_compressed(&self) -> bool {
         if let Compression::Identity = *self {
             true
@@ -109,8 +181,32 @@ impl Compression {
     // - compressed byte length (varnum);
     // - data.
     pub fn compress<W: Write>(&self, data: &[u8], out: &mut W) -> Result<CompressionResult, std::io::Error> {
+        self.compress_with_dictionary(data, None, out)
+    }
+
+    /// As `compress`, but brotli uses `dictionary` as custom dictionary, if specified.
+    /// Other compressions ignore `dictionary`.
+    ///
+    /// The data must be decompressed with the same dictionary.
+    pub fn compress_with_dictionary<W: Write>(&self, data: &[u8], dictionary: Option<&BrotliDictionary>, out: &mut W) -> Result<CompressionResult, std::io::Error> {
+        let _span = tracing::info_span!("compression", algorithm = self.code()).entered();
         let before_bytes = data.len();
         let after_bytes = match *self {
+            Compression::Auto => {
+                let mut best : Option<(Vec<u8>, CompressionResult)> = None;
+                for candidate in Self::candidates().iter() {
+                    let mut buffer = vec![];
+                    let result = candidate.compress_with_dictionary(data, dictionary, &mut buffer)?;
+                    tracing::debug!(target: "compression", candidate = candidate.code(), bytes = buffer.len(), "Auto: candidate compressed");
+                    if best.as_ref().map_or(true, |&(ref best, _)| buffer.len() < best.len()) {
+                        best = Some((buffer, result));
+                    }
+                }
+                let (buffer, result) = best
+                    .expect("There is at least one candidate");
+                out.write_all(&buffer)?;
+                return Ok(result);
+            }
             Compression::Identity => {
                 out.write_all(b"identity;")?;
                 out.write_varnum(data.len() as u32)?;
@@ -148,9 +244,28 @@ impl Compression {
                 out.write_all(b"br;")?;
                 // Compress
                 let mut buffer = Vec::with_capacity(data.len());
-                {
-                    let mut encoder = brotli::CompressorWriter::new(&mut buffer, BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_LG_WINDOW_SIZE);
-                    encoder.write(data)?;
+                match dictionary {
+                    None => {
+                        let mut encoder = brotli::CompressorWriter::new(&mut buffer, BROTLI_BUFFER_SIZE, BROTLI_QUALITY, BROTLI_LG_WINDOW_SIZE);
+                        encoder.write(data)?;
+                    }
+                    Some(dictionary) => {
+                        let mut params = brotli::enc::BrotliEncoderParams::default();
+                        params.quality = BROTLI_QUALITY as i32;
+                        params.lgwin = BROTLI_LG_WINDOW_SIZE as i32;
+                        let mut input_buffer = [0; BROTLI_BUFFER_SIZE];
+                        let mut output_buffer = [0; BROTLI_BUFFER_SIZE];
+                        brotli::enc::BrotliCompressCustomIoCustomDict(
+                            &mut brotli::IoReaderWrapper(&mut Cursor::new(data)),
+                            &mut brotli::IoWriterWrapper(&mut buffer),
+                            &mut input_buffer,
+                            &mut output_buffer,
+                            &params,
+                            brotli::enc::StandardAlloc::default(),
+                            &mut |_, _, _, _| (),
+                            dictionary.bytes(),
+                            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Unexpected end of data"))?;
+                    }
                 }
                 // Write
                 out.write_varnum(buffer.len() as u32)?;
@@ -181,6 +296,28 @@ impl Compression {
     }
 
     pub fn decompress<R: Read, T>(inp: &mut R, deserializer: &T) -> Result<T::Target, std::io::Error> where T: Deserializer {
+        Self::decompress_with_dictionary(inp, deserializer, None)
+    }
+
+    /// As `decompress`, for data compressed by `compress_with_dictionary` with `dictionary`.
+    pub fn decompress_with_dictionary<R: Read, T>(inp: &mut R, deserializer: &T, dictionary: Option<&BrotliDictionary>) -> Result<T::Target, std::io::Error> where T: Deserializer {
+        let (compression, byte_len) = Self::read_header(inp)?;
+
+        let mut compressed_bytes = Vec::with_capacity(byte_len as usize);
+        unsafe { compressed_bytes.set_len(byte_len as usize )};
+        inp.read_exact(&mut compressed_bytes)?;
+
+        let mut decompressed_bytes = Vec::with_capacity(1024);
+        compression.decompressor(Cursor::new(compressed_bytes), dictionary)?
+            .read_to_end(&mut decompressed_bytes)?;
+
+        let value = deserializer.read(&mut Cursor::new(decompressed_bytes))?;
+        Ok(value)
+    }
+
+    /// Read the header of compressed data, returning the compression used and the
+    /// byte length of the compressed bytes that follow.
+    pub fn read_header<R: Read>(inp: &mut R) -> Result<(Compression, u32), std::io::Error> {
         const MAX_LENGTH: usize = 32;
         let mut header = Vec::with_capacity(MAX_LENGTH);
         let mut found = false;
@@ -218,46 +355,218 @@ impl Compression {
 
         let mut byte_len = 0;
         inp.read_varnum_to(&mut byte_len)?;
+        Ok((compression, byte_len))
+    }
 
-        let mut compressed_bytes = Vec::with_capacity(byte_len as usize);
-        unsafe { compressed_bytes.set_len(byte_len as usize )};
-        inp.read_exact(&mut compressed_bytes)?;
-
-        let decompressed_bytes = match compression {
-            Compression::Identity => compressed_bytes,
+    /// A reader of the bytes decompressed from `inp`, which yields the compressed bytes
+    /// following a header read by `read_header`, e.g. with `Read::take`.
+    ///
+    /// Bytes are decompressed as they are read from `inp`, so that the first bytes may be used
+    /// before the rest has been received, except with `Lzw`, for which `inp` is read entirely first.
+    pub fn decompressor<'a, R: Read + 'a>(&self, mut inp: R, dictionary: Option<&BrotliDictionary>) -> Result<Box<Read + 'a>, std::io::Error> {
+        let decompressor : Box<Read + 'a> = match *self {
+            Compression::Identity => Box::new(inp),
             Compression::Gzip => {
                 use flate2;
-                let mut decoder = flate2::read::GzDecoder::new(Cursor::new(&compressed_bytes));
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
-                buf
+                Box::new(flate2::read::GzDecoder::new(inp))
             }
             Compression::Deflate => {
                 use flate2;
-                let mut decoder = flate2::read::ZlibDecoder::new(Cursor::new(&compressed_bytes));
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
-                buf
+                Box::new(flate2::read::ZlibDecoder::new(inp))
             }
             Compression::Brotli => {
                 use brotli;
-                let mut decoder = brotli::Decompressor::new(Cursor::new(&compressed_bytes), BROTLI_BUFFER_SIZE);
-                let mut buf = Vec::with_capacity(1024);
-                decoder.read_to_end(&mut buf)?;
-                buf
+                match dictionary {
+                    None => Box::new(brotli::Decompressor::new(inp, BROTLI_BUFFER_SIZE)),
+                    Some(dictionary) => Box::new(brotli::Decompressor::new_with_custom_dict(inp, BROTLI_BUFFER_SIZE, dictionary.bytes().to_vec().into()))
+                }
             }
             Compression::Lzw => {
                 use lzw;
+                let mut compressed_bytes = vec![];
+                inp.read_to_end(&mut compressed_bytes)?;
                 let reader = lzw::LsbReader::new();
                 let mut decoder = lzw::Decoder::new(reader, LZW_MIN_CODE_SIZE);
                 let (_, data) = decoder.decode_bytes(&compressed_bytes)?;
-                let mut buf = Vec::with_capacity(data.len());
-                buf.extend_from_slice(data);
-                buf
+                Box::new(Cursor::new(data.to_vec()))
             }
+            Compression::Auto => unreachable!() // Never parsed from a header.
         };
+        Ok(decompressor)
+    }
+}
 
-        let value = deserializer.read(&mut Cursor::new(decompressed_bytes))?;
-        Ok(value)
+/// The byte length of sources compressed as by web servers, i.e. with `gzip -9` and
+/// `brotli -11`, to compare BinJS with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceCompression {
+    pub source_bytes: usize,
+    pub gzip_bytes: usize,
+    pub brotli_bytes: usize,
+}
+impl SourceCompression {
+    /// Compress `source` with both algorithms.
+    pub fn new(source: &[u8]) -> Result<Self, std::io::Error> {
+        use brotli;
+        use flate2;
+
+        let _span = tracing::info_span!("source compression").entered();
+        let mut encoder = flate2::write::GzEncoder::new(Vec::with_capacity(source.len()), flate2::Compression::best());
+        encoder.write_all(source)?;
+        let gzip_bytes = encoder.finish()?
+            .len();
+
+        let mut buffer = Vec::with_capacity(source.len());
+        {
+            let mut encoder = brotli::CompressorWriter::new(&mut buffer, BROTLI_BUFFER_SIZE, BROTLI_SOURCE_QUALITY, BROTLI_SOURCE_LG_WINDOW_SIZE);
+            encoder.write_all(source)?;
+            encoder.flush()?;
+        }
+
+        Ok(SourceCompression {
+            source_bytes: source.len(),
+            gzip_bytes,
+            brotli_bytes: buffer.len(),
+        })
+    }
+
+    /// Compare with `encoded_bytes`, the byte length of the same sources encoded with BinJS.
+    pub fn compare(&self, encoded_bytes: usize) -> SourceComparison {
+        SourceComparison {
+            compression: self,
+            encoded_bytes,
+        }
+    }
+}
+impl std::ops::AddAssign for SourceCompression {
+    fn add_assign(&mut self, rhs: Self) {
+        self.source_bytes += rhs.source_bytes;
+        self.gzip_bytes += rhs.gzip_bytes;
+        self.brotli_bytes += rhs.brotli_bytes;
+    }
+}
+
+/// Displaying the ratios between BinJS and compressed sources, as part of statistics.
+pub struct SourceComparison<'a> {
+    compression: &'a SourceCompression,
+    encoded_bytes: usize,
+}
+impl<'a> std::fmt::Display for SourceComparison<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let ratio = |numerator: usize, denominator: usize| {
+            if denominator == 0 {
+                0.
+            } else {
+                numerator as f64 / denominator as f64
+            }
+        };
+        write!(f, "\t\tSource bytes with gzip -9: {} (ratio {:.2}, BinJS/gzip {:.2})\n",
+            self.compression.gzip_bytes,
+            ratio(self.compression.gzip_bytes, self.compression.source_bytes),
+            ratio(self.encoded_bytes, self.compression.gzip_bytes))?;
+        write!(f, "\t\tSource bytes with brotli -11: {} (ratio {:.2}, BinJS/brotli {:.2})\n",
+            self.compression.brotli_bytes,
+            ratio(self.compression.brotli_bytes, self.compression.source_bytes),
+            ratio(self.encoded_bytes, self.compression.brotli_bytes))
+    }
+}
+#[test]
+fn test_compression_auto() {
+    struct BufDeserializer;
+    impl Deserializer for BufDeserializer {
+        type Target = Vec<u8>;
+        fn read<R: Read + std::io::Seek>(&self, reader: &mut R) -> Result<Self::Target, std::io::Error> {
+            let mut buf = vec![];
+            reader.read_to_end(&mut buf)?;
+            Ok(buf)
+        }
+    }
+
+    let repetitive : Vec<u8> = b"function foo() { return foo; }".iter()
+        .cycle()
+        .take(3000)
+        .cloned()
+        .collect();
+    assert!(!Compression::candidates().contains(&Compression::Deflate));
+    for data in &[repetitive, b"ab".to_vec(), vec![]] {
+        let mut out = vec![];
+        let result = Compression::Auto.compress(data, &mut out)
+            .expect("Could not compress");
+        assert_eq!(result.algorithms.len(), 1);
+
+        // Auto is never worse than any candidate.
+        for candidate in Compression::candidates().iter() {
+            let mut other = vec![];
+            candidate.compress(data, &mut other).unwrap();
+            assert!(out.len() <= other.len(), "Auto is larger than {}", candidate.name());
+        }
+
+        let decompressed = Compression::decompress(&mut Cursor::new(&out), &BufDeserializer)
+            .expect("Could not decompress");
+        assert_eq!(&decompressed, data);
+    }
+}
+
+#[test]
+fn test_compression_brotli_dictionary() {
+    struct BufDeserializer;
+    impl Deserializer for BufDeserializer {
+        type Target = Vec<u8>;
+        fn read<R: Read + std::io::Seek>(&self, reader: &mut R) -> Result<Self::Target, std::io::Error> {
+            let mut buf = vec![];
+            reader.read_to_end(&mut buf)?;
+            Ok(buf)
+        }
     }
-}
\ No newline at end of file
+
+    let dictionary = BrotliDictionary::new(b"getElementById getElementsByTagName addEventListener querySelectorAll".to_vec());
+    let data = b"querySelectorAll getElementById addEventListener".to_vec();
+
+    let mut plain = vec![];
+    Compression::Brotli.compress(&data, &mut plain)
+        .expect("Could not compress");
+    let mut out = vec![];
+    Compression::Brotli.compress_with_dictionary(&data, Some(&dictionary), &mut out)
+        .expect("Could not compress");
+    assert!(out.len() < plain.len());
+
+    let decompressed = Compression::decompress_with_dictionary(&mut Cursor::new(&out), &BufDeserializer, Some(&dictionary))
+        .expect("Could not decompress");
+    assert_eq!(decompressed, data);
+
+    // The dictionary is required.
+    if let Ok(decompressed) = Compression::decompress(&mut Cursor::new(&out), &BufDeserializer) {
+        assert!(decompressed != data);
+    }
+
+    // Other compressions ignore the dictionary.
+    let mut out = vec![];
+    Compression::Gzip.compress_with_dictionary(&data, Some(&dictionary), &mut out)
+        .expect("Could not compress");
+    let decompressed = Compression::decompress(&mut Cursor::new(&out), &BufDeserializer)
+        .expect("Could not decompress");
+    assert_eq!(decompressed, data);
+}
+
+#[test]
+fn test_source_compression() {
+    let source : Vec<u8> = b"function foo() { return foo; }\n".iter()
+        .cycle()
+        .take(3000)
+        .cloned()
+        .collect();
+    let compression = SourceCompression::new(&source)
+        .expect("Could not compress source");
+    assert_eq!(compression.source_bytes, source.len());
+    assert!(compression.gzip_bytes > 0 && compression.gzip_bytes < source.len());
+    assert!(compression.brotli_bytes > 0 && compression.brotli_bytes < source.len());
+
+    let mut total = SourceCompression::default();
+    total += compression.clone();
+    total += compression.clone();
+    assert_eq!(total.gzip_bytes, 2 * compression.gzip_bytes);
+
+    let report = format!("{}", total.compare(compression.gzip_bytes));
+    assert!(report.contains("gzip -9"));
+    assert!(report.contains("BinJS/gzip 0.50"));
+}
@@ -0,0 +1,43 @@
+use aes_gcm::{ Aes256Gcm, Key, Nonce };
+use aes_gcm::aead::{ Aead, NewAead };
+
+use rand::Rng;
+
+use std;
+
+/// The byte length of an AES-256 key.
+pub const KEY_LENGTH: usize = 32;
+
+/// The byte length of an AES-GCM nonce.
+pub const NONCE_LENGTH: usize = 12;
+
+/// Encrypt `data` with AES-256-GCM, using a random nonce.
+///
+/// Returns the nonce and the encrypted data, including the authentication tag.
+pub fn encrypt(key: &[u8; KEY_LENGTH], data: &[u8]) -> Result<([u8; NONCE_LENGTH], Vec<u8>), std::io::Error> {
+    let nonce : [u8; NONCE_LENGTH] = rand::thread_rng().gen();
+    let cipher = Aes256Gcm::new(Key::from_slice(key));
+    let encrypted = cipher.encrypt(Nonce::from_slice(&nonce), data)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Could not encrypt"))?;
+    Ok((nonce, encrypted))
+}
+
+/// Decrypt and authenticate `data` encrypted by `encrypt`.
+pub fn decrypt(key: &[u8; KEY_LENGTH], nonce: &[u8; NONCE_LENGTH], data: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let cipher = Aes256Gcm::new(Key::from_slice(key));
+    cipher.decrypt(Nonce::from_slice(nonce), data)
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "Could not decrypt, the key is wrong or the data is corrupted"))
+}
+
+#[test]
+fn test_encryption() {
+    let key = [42; KEY_LENGTH];
+    let (nonce, encrypted) = encrypt(&key, b"some data")
+        .expect("Could not encrypt");
+    assert_eq!(decrypt(&key, &nonce, &encrypted).expect("Could not decrypt"), b"some data");
+
+    let mut corrupted = encrypted.clone();
+    corrupted[0] ^= 1;
+    assert!(decrypt(&key, &nonce, &corrupted).is_err());
+    assert!(decrypt(&[0; KEY_LENGTH], &nonce, &encrypted).is_err());
+}
@@ -1,51 +1,68 @@
+use bytes::source::{ io_error, ReadSource };
 use bytes::varnum::*;
 
-use std;
-use std::io::Write;
+use binjs_core;
+use binjs_core::float::{ read_maybe_varfloat, zigzag, NONE_FLOAT_REPR, VARFLOAT_INTEGER_BOUND, VARFLOAT_MANTISSA_BOUND, VARFLOAT_MAX_EXPONENT, VARNUM_NULL, VARNUM_PREFIX_FLOAT };
+pub use binjs_core::float::NaNPolicy;
 
-/// The representation of "no float", used for `float | null`.
-const NONE_FLOAT_REPR: u64 = 0x7FF0000000000001;
-const VARNUM_PREFIX_FLOAT: [u8; 2] = VARNUM_INVALID_ZERO_1;
-const VARNUM_NULL: [u8; 3] = VARNUM_INVALID_ZERO_2;
+use std;
+use std::io::{ Read, Write };
 
-pub fn varbytes_of_float(value: Option<f64>) -> Vec<u8> {
-    let mut buf = Vec::with_capacity(4);
-    buf.write_maybe_varfloat(value)
-        .unwrap(); // The write cannot fail on a Vec<>
-    buf
+/// Encode a f64 | null, little-endian, canonicalizing NaNs.
+pub fn bytes_of_float(value: Option<f64>) -> [u8; 8] {
+    bytes_of_float_with_policy(value, NaNPolicy::Canonicalize)
+        .unwrap() // Canonicalization never fails.
 }
 
-/// Encode a f64 | null, little-endian
-pub fn bytes_of_float(value: Option<f64>) -> [u8; 8] {
+/// Encode a f64 | null, little-endian, handling NaNs as specified by `policy`.
+pub fn bytes_of_float_with_policy(value: Option<f64>, policy: NaNPolicy) -> Result<[u8; 8], std::io::Error> {
     let mut as_u64 : u64 = match value {
         None => NONE_FLOAT_REPR,
-        Some(value) => unsafe { std::mem::transmute::<f64, u64>(value) }
+        Some(value) => {
+            let as_u64 = policy.apply(unsafe { std::mem::transmute::<f64, u64>(value) })
+                .map_err(|msg| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg))?;
+            if as_u64 == NONE_FLOAT_REPR {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "NaN reserved for null"));
+            }
+            as_u64
+        }
     };
     let mut buf: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
     for i in 0..8 {
         buf[i] = (as_u64 % 256) as u8;
         as_u64 >>= 8;
     }
-    buf
+    Ok(buf)
 }
 
 /// Utility for manipulating of `varfloats`, a somewhat optimized representation of floats.
 ///
 /// This format is designed to help the most common floating point numbers (fairly short
-/// integers) take fewer bytes.
+/// integers and short decimal numbers, e.g. `0.5`, `0.25` or `1e-3`) take fewer bytes.
 ///
 /// Instead of always fitting in 64 bits, varfloats are represented as follows:
 /// - null is represented as VARNUM_NULL (24 bits);
-/// - floats with an i32 value are transmuted to u32s and represented as varnums (8 to 40 bits);
-/// - other float values are prefixed with VARNUM_PREFIX_FLOAT (8 bits), then represented
-///     with the usual 64 bits.
+/// - floats with an integer value in [-2^30, 2^30), other than -0, are represented
+///     as a varnum `2 * zigzag(value)` (8 to 40 bits);
+/// - floats equal to `m / 10^e`, for an integer `m` in [-2^27, 2^27) and `e` in [1, 8],
+///     are represented as a varnum `16 * zigzag(m) + 2 * (e - 1) + 1` (8 to 40 bits),
+///     e.g. `0.5` takes 16 bits;
+/// - other float values, including NaNs, are prefixed with VARNUM_PREFIX_FLOAT (16 bits),
+///     then represented with the usual 64 bits, handling NaNs as specified by a `NaNPolicy`.
+///
+/// Since `m / 10^e` is correctly rounded, decimal varfloats decode to the float
+/// that the source text `m * 10^-e` denotes in JavaScript.
 pub trait WriteVarFloat {
-    fn write_maybe_varfloat(&mut self, value: Option<f64>) -> Result<usize, std::io::Error>;
-    fn write_varfloat(&mut self, num: f64) -> Result<usize, std::io::Error>;
+    fn write_maybe_varfloat(&mut self, value: Option<f64>, policy: NaNPolicy) -> Result<usize, std::io::Error>;
+    fn write_varfloat(&mut self, num: f64, policy: NaNPolicy) -> Result<usize, std::io::Error>;
+}
+
+pub trait ReadVarFloat {
+    fn read_maybe_varfloat(&mut self, policy: NaNPolicy) -> Result<Option<f64>, std::io::Error>;
 }
 
 impl<T> WriteVarFloat for T where T: Write {
-    fn write_maybe_varfloat(&mut self, value: Option<f64>) -> Result<usize, std::io::Error> {
+    fn write_maybe_varfloat(&mut self, value: Option<f64>, policy: NaNPolicy) -> Result<usize, std::io::Error> {
         match value {
             None => {
                 // Magic constant NULL.
@@ -53,61 +70,74 @@ impl<T> WriteVarFloat for T where T: Write {
                 Ok(VARNUM_NULL.len())
             }
             Some(v) => {
-                self.write_varfloat(v)
+                self.write_varfloat(v, policy)
             }
         }
     }
 
-    fn write_varfloat(&mut self, value: f64) -> Result<usize, std::io::Error> {
+    fn write_varfloat(&mut self, value: f64, policy: NaNPolicy) -> Result<usize, std::io::Error> {
+        // Let's see if we can represent this as an integer.
+        // We can represent it as an integer if:
+        // - it has the same value as its projection to i64, within bounds;
+        // - it's not -0.0
+        let as_signed_integer = value as i64;
+        if as_signed_integer as f64 == value
+            && -VARFLOAT_INTEGER_BOUND <= as_signed_integer && as_signed_integer < VARFLOAT_INTEGER_BOUND
+            && (as_signed_integer != 0 || value.is_sign_positive())
         {
-            // Let's see if we can represent this as an integer.
-            // We can represent it as an integer if:
-            // - it has the same value as its projection to i32;
-            // - it's not -0.0
-            let as_signed_integer = value as i32;
-            if as_signed_integer as f64 == value && (as_signed_integer != 0 || value.is_sign_positive()) {
-                // This is an i32. We can fit it in at most 5 7bit bytes.
-                //
-                // We pick a representation that favors small numbers, both
-                // positive and negative.
-                let as_unsigned = if as_signed_integer >= 0 {
-                    // Map non-negative to even numbers.
-                    // So, numbers in [0, 128) will fit in a single byte.
-                    2 * (as_signed_integer as u32)
-                } else {
-                    // Map negatives to odd numbers.
-                    // So, numbers in [-127, -1] will fit in a single byte.
-                    2 * ((-as_signed_integer + 1) as u32) - 1
-                };
-                return self.write_varnum(as_unsigned)
+            return self.write_varnum(zigzag(as_signed_integer as i32) << 1)
+        }
+
+        // Let's see if we can represent this as a short decimal number.
+        if value.is_finite() && value != 0. {
+            let mut power_of_ten = 1.;
+            for exponent in 1..VARFLOAT_MAX_EXPONENT + 1 {
+                power_of_ten *= 10.;
+                let mantissa = (value * power_of_ten).round();
+                if mantissa.abs() >= VARFLOAT_MANTISSA_BOUND as f64 {
+                    break;
+                }
+                if mantissa / power_of_ten == value {
+                    let mantissa = zigzag(mantissa as i32);
+                    return self.write_varnum((mantissa << 4) | (((exponent - 1) as u32) << 1) | 1)
+                }
             }
         }
+
         // Encode as a float prefixed by 0b00000001 0b00000000 (which is an invalid integer).
-        let bytes = bytes_of_float(Some(value));
+        let bytes = bytes_of_float_with_policy(Some(value), policy)?;
         self.write_all(&VARNUM_PREFIX_FLOAT)?;
         self.write_all(&bytes)?;
         Ok(bytes.len() + VARNUM_PREFIX_FLOAT.len())
     }
 }
 
+impl<T> ReadVarFloat for T where T: Read {
+    fn read_maybe_varfloat(&mut self, policy: NaNPolicy) -> Result<Option<f64>, std::io::Error> {
+        let mut source = ReadSource::new(self);
+        let result = read_maybe_varfloat(&mut source, policy);
+        source.finish(result)
+    }
+}
 
-/// Decode a f64 | null, little-endian
+/// Encode a f64 | null as a varfloat, canonicalizing NaNs.
+pub fn varbytes_of_float(value: Option<f64>) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(4);
+    buf.write_maybe_varfloat(value, NaNPolicy::Canonicalize)
+        .unwrap(); // The write cannot fail on a Vec<>
+    buf
+}
+
+/// Decode a f64 | null, little-endian, canonicalizing NaNs.
 pub fn float_of_bytes(buf: &[u8; 8]) -> Option<f64> {
-    let as_u64 =
-          ((buf[0] as u64) << 0)
-        | ((buf[1] as u64) << 8)
-        | ((buf[2] as u64) << 16)
-        | ((buf[3] as u64) << 24)
-        | ((buf[4] as u64) << 32)
-        | ((buf[5] as u64) << 40)
-        | ((buf[6] as u64) << 48)
-        | ((buf[7] as u64) << 56);
-    if as_u64 == NONE_FLOAT_REPR {
-        None
-    } else {
-        let as_f64 = unsafe { std::mem::transmute::<_, f64>(as_u64) };
-        Some(as_f64)
-    }
+    float_of_bytes_with_policy(buf, NaNPolicy::Canonicalize)
+        .unwrap() // Canonicalization never fails.
+}
+
+/// Decode a f64 | null, little-endian, handling NaNs as specified by `policy`.
+pub fn float_of_bytes_with_policy(buf: &[u8; 8], policy: NaNPolicy) -> Result<Option<f64>, std::io::Error> {
+    binjs_core::float::float_of_bytes_with_policy(buf, policy)
+        .map_err(io_error)
 }
 
 #[test]
@@ -122,4 +152,106 @@ fn test_floats() {
     }
 
     assert_eq!(float_of_bytes(&bytes_of_float(None)), None);
-}
\ No newline at end of file
+}
+#[test]
+fn test_nan_policy() {
+    use binjs_core::float::CANONICAL_NAN_REPR;
+
+    let bits_of = |value: Option<f64>| value.map(|value| unsafe { std::mem::transmute::<f64, u64>(value) });
+    let of_bits = |bits: u64| unsafe { std::mem::transmute::<u64, f64>(bits) };
+
+    // A signaling NaN with a payload, a quiet NaN with a payload, a negative NaN.
+    let nans = [0x7FF0000000000002, 0x7FF4000000000000, 0x7FF8000000000123, 0xFFF8000000000000];
+    for &bits in &nans {
+        let nan = Some(of_bits(bits));
+
+        let encoded = bytes_of_float_with_policy(nan, NaNPolicy::Preserve)
+            .expect("Could not preserve NaN");
+        let decoded = float_of_bytes_with_policy(&encoded, NaNPolicy::Preserve)
+            .expect("Could not read preserved NaN");
+        assert_eq!(bits_of(decoded), Some(bits));
+
+        // Readers canonicalize NaNs written by other policies.
+        let decoded = float_of_bytes_with_policy(&encoded, NaNPolicy::Canonicalize)
+            .expect("Could not read canonicalized NaN");
+        assert_eq!(bits_of(decoded), Some(CANONICAL_NAN_REPR));
+        assert!(float_of_bytes_with_policy(&encoded, NaNPolicy::Reject).is_err());
+
+        let encoded = bytes_of_float_with_policy(nan, NaNPolicy::Canonicalize)
+            .expect("Could not canonicalize NaN");
+        assert_eq!(bits_of(float_of_bytes(&encoded)), Some(CANONICAL_NAN_REPR));
+
+        assert!(bytes_of_float_with_policy(nan, NaNPolicy::Reject).is_err());
+    }
+
+    // The NaN reserved for null cannot be preserved, but may be canonicalized.
+    let reserved = Some(of_bits(NONE_FLOAT_REPR));
+    assert!(bytes_of_float_with_policy(reserved, NaNPolicy::Preserve).is_err());
+    assert_eq!(bits_of(float_of_bytes(&bytes_of_float(reserved))), Some(CANONICAL_NAN_REPR));
+
+    // Null and other values are unaffected by the policy.
+    for &policy in &[NaNPolicy::Canonicalize, NaNPolicy::Preserve, NaNPolicy::Reject] {
+        for &value in &[None, Some(1.5), Some(std::f64::INFINITY), Some(-0.)] {
+            let encoded = bytes_of_float_with_policy(value, policy)
+                .expect("Could not write float");
+            let decoded = float_of_bytes_with_policy(&encoded, policy)
+                .expect("Could not read float");
+            assert_eq!(bits_of(decoded), bits_of(value));
+        }
+    }
+}
+#[test]
+fn test_varfloats() {
+    use std::f64::*;
+    let bits_of = |value: Option<f64>| value.map(|value| unsafe { std::mem::transmute::<f64, u64>(value) });
+    let values = [
+        Some(0.), Some(1.), Some(-1.), Some(128.), Some(-64.), Some(1000.), Some(-1073741824.), Some(1073741823.), Some(1073741824.),
+        Some(0.5), Some(-0.5), Some(0.25), Some(0.1), Some(3.14), Some(1e-8), Some(-13421.7727), Some(1e-9),
+        Some(1. / 3.), Some(-0.), Some(MIN_POSITIVE), Some(MAX), Some(INFINITY), Some(NEG_INFINITY),
+        None,
+    ];
+    let mut buf : Vec<u8> = vec![];
+    let mut total = 0;
+    for value in &values {
+        total += buf.write_maybe_varfloat(*value, NaNPolicy::Canonicalize)
+            .expect("Could not write varfloat");
+    }
+    assert_eq!(total, buf.len());
+
+    let mut inp = std::io::Cursor::new(&buf);
+    for value in &values {
+        let decoded = inp.read_maybe_varfloat(NaNPolicy::Canonicalize)
+            .expect("Could not read varfloat");
+        assert_eq!(bits_of(decoded), bits_of(*value), "Could not roundtrip {:?}", value);
+    }
+    assert_eq!(inp.position() as usize, buf.len());
+
+    // Common values are short.
+    let len = |value: f64| varbytes_of_float(Some(value)).len();
+    assert_eq!(len(0.), 1);
+    assert_eq!(len(-7.), 1);
+    assert_eq!(len(0.5), 2);
+    assert_eq!(len(0.25), 2);
+    assert_eq!(len(-0.75), 2);
+    assert_eq!(len(1e-3), 1);
+    assert_eq!(len(0.1), 1);
+    assert_eq!(len(1. / 3.), 10);
+    assert_eq!(varbytes_of_float(None).len(), 3);
+
+    // NaNs follow the policy.
+    let nan = Some(unsafe { std::mem::transmute::<u64, f64>(0x7FF8000000000123) });
+    let mut buf : Vec<u8> = vec![];
+    buf.write_maybe_varfloat(nan, NaNPolicy::Preserve)
+        .expect("Could not preserve NaN");
+    let decoded = std::io::Cursor::new(&buf).read_maybe_varfloat(NaNPolicy::Preserve)
+        .expect("Could not read preserved NaN");
+    assert_eq!(bits_of(decoded), bits_of(nan));
+    assert!(std::io::Cursor::new(&buf).read_maybe_varfloat(NaNPolicy::Reject).is_err());
+    assert!(Vec::<u8>::new().write_maybe_varfloat(nan, NaNPolicy::Reject).is_err());
+
+    // Truncated and invalid varfloats are rejected.
+    let invalid : [Vec<u8>; 4] = [vec![], vec![1], vec![1, 1, 1, 1, 1], vec![1, 0, 0, 0]];
+    for bytes in &invalid {
+        assert!(std::io::Cursor::new(bytes).read_maybe_varfloat(NaNPolicy::Canonicalize).is_err());
+    }
+}
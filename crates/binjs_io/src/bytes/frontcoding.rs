@@ -0,0 +1,164 @@
+use bytes::varnum::*;
+
+use std;
+use std::collections::VecDeque;
+use std::io::{ Read, Write };
+
+/// Prefixes shorter than this are not worth a back-reference, which costs at
+/// least two bytes.
+const MIN_PREFIX_LEN: usize = 3;
+
+/// The number of previous entries searched for a shared prefix, by default.
+pub const DEFAULT_WINDOW: usize = 64;
+
+/// Encode a sequence of byte strings, sharing prefixes with recent entries.
+///
+/// Each entry is written as:
+/// - the distance to the previous entry with which it shares a prefix,
+///   or 0 if it shares no prefix (varnum);
+/// - if the distance is not 0, the byte length of the shared prefix (varnum);
+/// - the byte length of the rest of the entry (varnum);
+/// - the rest of the entry.
+pub struct FrontEncoder {
+    /// The number of previous entries searched for a shared prefix.
+    window: usize,
+
+    /// The last `window` entries, most recent last.
+    recent: VecDeque<Vec<u8>>,
+}
+impl FrontEncoder {
+    pub fn new(window: usize) -> Self {
+        FrontEncoder {
+            window,
+            recent: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Write an entry, returning the number of bytes written.
+    pub fn write<W: Write>(&mut self, entry: &[u8], out: &mut W) -> Result<usize, std::io::Error> {
+        // Find the longest prefix shared with a recent entry, preferring the closest one.
+        let mut best = None;
+        for (distance, previous) in self.recent.iter().rev().enumerate() {
+            let len = previous.iter()
+                .zip(entry)
+                .take_while(|&(a, b)| a == b)
+                .count();
+            if len >= MIN_PREFIX_LEN && len > best.map_or(0, |(_, best_len)| best_len) {
+                best = Some((distance + 1, len));
+            }
+        }
+
+        let mut total = 0;
+        let suffix = match best {
+            None => {
+                total += out.write_varnum(0)?;
+                entry
+            }
+            Some((distance, len)) => {
+                total += out.write_varnum(distance as u32)?;
+                total += out.write_varnum(len as u32)?;
+                &entry[len..]
+            }
+        };
+        total += out.write_varnum(suffix.len() as u32)?;
+        out.write_all(suffix)?;
+        total += suffix.len();
+
+        if self.window > 0 {
+            if self.recent.len() == self.window {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(entry.to_vec());
+        }
+        Ok(total)
+    }
+}
+
+/// Decode a sequence of byte strings written by a `FrontEncoder`.
+///
+/// The window of the decoder must be at least that of the encoder.
+pub struct FrontDecoder {
+    window: usize,
+
+    /// The last `window` entries, most recent last.
+    recent: VecDeque<Vec<u8>>,
+}
+impl FrontDecoder {
+    pub fn new(window: usize) -> Self {
+        FrontDecoder {
+            window,
+            // The window is read from the file, don't trust it for allocations.
+            recent: VecDeque::with_capacity(std::cmp::min(window, DEFAULT_WINDOW)),
+        }
+    }
+
+    /// Read an entry.
+    pub fn read<R: Read>(&mut self, inp: &mut R) -> Result<Vec<u8>, std::io::Error> {
+        let distance = inp.read_varnum()? as usize;
+        let mut entry = if distance == 0 {
+            vec![]
+        } else {
+            let len = inp.read_varnum()? as usize;
+            let previous = self.recent.len().checked_sub(distance)
+                .and_then(|index| self.recent.get(index))
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid back-reference"))?;
+            if len > previous.len() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid prefix length"));
+            }
+            previous[..len].to_vec()
+        };
+        let suffix_len = inp.read_varnum()? as usize;
+        let start = entry.len();
+        entry.resize(start + suffix_len, 0);
+        inp.read_exact(&mut entry[start..])?;
+
+        if self.window > 0 {
+            if self.recent.len() == self.window {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(entry.clone());
+        }
+        Ok(entry)
+    }
+}
+
+#[test]
+fn test_front_coding() {
+    let entries : Vec<Vec<u8>> = ["getElementById", "", "getElementsByTagName", "foo", "getAttribute", "getElementById", "ge"]
+        .iter()
+        .map(|entry| entry.as_bytes().to_vec())
+        .chain(Some(vec![255, 0])) // The null string.
+        .collect();
+    for &window in &[0, 1, 2, DEFAULT_WINDOW] {
+        let mut encoder = FrontEncoder::new(window);
+        let mut buf = vec![];
+        let mut total = 0;
+        for entry in &entries {
+            total += encoder.write(entry, &mut buf)
+                .expect("Could not write entry");
+        }
+        assert_eq!(total, buf.len());
+
+        let mut decoder = FrontDecoder::new(window);
+        let mut inp = std::io::Cursor::new(&buf);
+        for entry in &entries {
+            assert_eq!(&decoder.read(&mut inp).expect("Could not read entry"), entry);
+        }
+        assert_eq!(inp.position() as usize, buf.len());
+    }
+
+    // Shared prefixes make the encoding shorter.
+    let mut plain = vec![];
+    let mut coded = vec![];
+    let mut encoder = FrontEncoder::new(DEFAULT_WINDOW);
+    for entry in &entries {
+        plain.write_varnum(entry.len() as u32).unwrap();
+        plain.write_all(entry).unwrap();
+        encoder.write(entry, &mut coded).unwrap();
+    }
+    assert!(coded.len() < plain.len());
+
+    // Back-references to missing entries are rejected.
+    let mut decoder = FrontDecoder::new(DEFAULT_WINDOW);
+    assert!(decoder.read(&mut std::io::Cursor::new(vec![2, 2, 0])).is_err());
+}
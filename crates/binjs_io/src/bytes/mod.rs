@@ -1,19 +1,40 @@
 //! Tools for manipulating byte-level data.
 
+/// Encoding/decoding BigInt values.
+pub mod bigint;
+
 /// Encoding/decoding booleans.
 pub mod bool;
 
+/// Computing checksums, to detect corrupted files.
+pub mod checksum;
+
 /// Compressing/decompressing from/to common formats.
 pub mod compress;
 
+/// Encrypting/decrypting data, for private code delivery.
+pub mod encryption;
+
+/// Sharing prefixes between the entries of string tables.
+pub mod frontcoding;
+
 /// Encoding/decoding floating-point numbers.
 pub mod float;
 
 /// Determining the length of a stream without actually writing/storing data.
 pub mod lengthwriter;
 
+/// Encoding/decoding the flags of RegExp literals.
+pub mod regexp;
+
+/// Signing data and verifying signatures, to detect tampered files.
+pub mod signature;
+
 /// Serializing/deserializing traits.
 pub mod serialize;
 
+/// Reading from a `Read` with the decoders of `binjs_core`.
+pub mod source;
+
 /// Encoding/decoding variable-length numbers.
 pub mod varnum;
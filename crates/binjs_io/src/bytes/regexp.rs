@@ -0,0 +1,105 @@
+use bytes::varnum::*;
+
+use binjs_shared::RegExpFlags;
+
+use std;
+use std::io::{ Read, Write };
+
+/// The representation of "no flags", used for `RegExpFlags | null`.
+const VARNUM_NULL: [u8; 2] = VARNUM_INVALID_ZERO_1;
+
+/// Utility for manipulating the flags of RegExp literals, as a bitfield.
+///
+/// Flags are represented as follows:
+/// - null is represented as VARNUM_NULL (16 bits);
+/// - other values are represented as the varnum of their bitfield, in which
+///     flag `RegExpFlags::ALL[i]` is bit `1 << i` (8 bits).
+///
+/// Since the bitfield fits in 6 bits, the varnum is always a single even byte,
+/// so a byte `1` can only start VARNUM_NULL.
+pub trait WriteRegExpFlags {
+    fn write_maybe_reg_exp_flags(&mut self, value: Option<&RegExpFlags>) -> Result<usize, std::io::Error>;
+}
+
+pub trait ReadRegExpFlags {
+    fn read_maybe_reg_exp_flags(&mut self) -> Result<Option<RegExpFlags>, std::io::Error>;
+}
+
+impl<T> WriteRegExpFlags for T where T: Write {
+    fn write_maybe_reg_exp_flags(&mut self, value: Option<&RegExpFlags>) -> Result<usize, std::io::Error> {
+        match value {
+            None => {
+                self.write_all(&VARNUM_NULL)?;
+                Ok(VARNUM_NULL.len())
+            }
+            Some(flags) => {
+                let bits = flags.to_bits()
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid RegExp flags"))?;
+                self.write_varnum(bits as u32)
+            }
+        }
+    }
+}
+
+impl<T> ReadRegExpFlags for T where T: Read {
+    fn read_maybe_reg_exp_flags(&mut self) -> Result<Option<RegExpFlags>, std::io::Error> {
+        let mut buf : [u8; 1] = [0];
+        self.read_exact(&mut buf)?;
+        if buf[0] == VARNUM_NULL[0] {
+            self.read_exact(&mut buf)?;
+            if buf[0] != VARNUM_NULL[1] {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid RegExp flags (not a bitfield)"));
+            }
+            return Ok(None);
+        }
+        if buf[0] & 1 != 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid RegExp flags (not a bitfield)"));
+        }
+        let flags = RegExpFlags::from_bits(buf[0] >> 1)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid RegExp flags (unknown flag)"))?;
+        Ok(Some(flags))
+    }
+}
+
+#[test]
+fn test_reg_exp_flags() {
+    use std::io::Cursor;
+
+    let values = [
+        Some(RegExpFlags::from_str("")),
+        Some(RegExpFlags::from_str("g")),
+        Some(RegExpFlags::from_str("gimsuy")),
+        None,
+        Some(RegExpFlags::from_str("iu")),
+    ];
+    let mut buf : Vec<u8> = vec![];
+    let mut total = 0;
+    for value in &values {
+        total += buf.write_maybe_reg_exp_flags(value.as_ref())
+            .expect("Could not write flags");
+    }
+    assert_eq!(total, buf.len());
+    assert_eq!(total, 6);
+
+    let mut inp = Cursor::new(&buf);
+    for value in &values {
+        let decoded = inp.read_maybe_reg_exp_flags()
+            .expect("Could not read flags");
+        assert_eq!(decoded.as_ref(), value.as_ref());
+    }
+    assert_eq!(inp.position() as usize, buf.len());
+
+    // Flags are decoded in canonical order.
+    let mut buf : Vec<u8> = vec![];
+    buf.write_maybe_reg_exp_flags(Some(&RegExpFlags::from_str("yg"))).unwrap();
+    assert_eq!(Cursor::new(buf).read_maybe_reg_exp_flags().unwrap(), Some(RegExpFlags::from_str("gy")));
+
+    // Invalid flags are rejected.
+    for flags in &["gg", "x", "/"] {
+        assert!(Vec::<u8>::new().write_maybe_reg_exp_flags(Some(&RegExpFlags::from_str(*flags))).is_err());
+    }
+    let invalid : [Vec<u8>; 5] = [vec![], vec![1], vec![1, 2], vec![3], vec![128]];
+    for bytes in &invalid {
+        assert!(Cursor::new(bytes).read_maybe_reg_exp_flags().is_err());
+    }
+}
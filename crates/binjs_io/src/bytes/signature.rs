@@ -0,0 +1,92 @@
+use ed25519_dalek::{ Keypair, PublicKey, SecretKey, Signature };
+use sha2::Sha512;
+
+use std;
+use std::io::Read;
+use std::path::Path;
+
+/// The byte length of an Ed25519 key, secret or public.
+pub const KEY_LENGTH: usize = 32;
+
+/// The byte length of an Ed25519 signature.
+pub const SIGNATURE_LENGTH: usize = 64;
+
+fn invalid<E: std::fmt::Display>(error: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{}", error))
+}
+
+/// Parse a hex-encoded key.
+pub fn parse_key(hex: &str) -> Result<[u8; KEY_LENGTH], std::io::Error> {
+    let hex = hex.trim();
+    if hex.len() != 2 * KEY_LENGTH {
+        return Err(invalid(format!("Expected a key of {} hex digits, got {}", 2 * KEY_LENGTH, hex.len())));
+    }
+    let mut key = [0; KEY_LENGTH];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i .. 2 * i + 2], 16)
+            .map_err(invalid)?;
+    }
+    Ok(key)
+}
+
+/// Read a hex-encoded key from a file.
+pub fn read_key<P: AsRef<Path>>(path: P) -> Result<[u8; KEY_LENGTH], std::io::Error> {
+    let mut hex = String::new();
+    std::fs::File::open(path)?
+        .read_to_string(&mut hex)?;
+    parse_key(&hex)
+}
+
+/// Hex-encode a key.
+pub fn to_hex(key: &[u8]) -> String {
+    key.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Compute the public key matching a secret key.
+pub fn public_key(secret: &[u8; KEY_LENGTH]) -> Result<[u8; KEY_LENGTH], std::io::Error> {
+    let secret = SecretKey::from_bytes(secret)
+        .map_err(invalid)?;
+    Ok(PublicKey::from_secret::<Sha512>(&secret).to_bytes())
+}
+
+/// Sign `data` with a secret key.
+pub fn sign(secret: &[u8; KEY_LENGTH], data: &[u8]) -> Result<[u8; SIGNATURE_LENGTH], std::io::Error> {
+    let secret = SecretKey::from_bytes(secret)
+        .map_err(invalid)?;
+    let public = PublicKey::from_secret::<Sha512>(&secret);
+    let keypair = Keypair {
+        secret,
+        public,
+    };
+    Ok(keypair.sign::<Sha512>(data).to_bytes())
+}
+
+/// Check that `signature` is a valid signature of `data` by the owner of a public key.
+pub fn verify(public: &[u8; KEY_LENGTH], data: &[u8], signature: &[u8]) -> bool {
+    let public = match PublicKey::from_bytes(public) {
+        Ok(public) => public,
+        Err(_) => return false
+    };
+    let signature = match Signature::from_bytes(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false
+    };
+    public.verify::<Sha512>(data, &signature).is_ok()
+}
+
+#[test]
+fn test_signature() {
+    let secret = parse_key("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+        .expect("Could not parse key");
+    let public = public_key(&secret)
+        .expect("Could not compute public key");
+    // Test vector from RFC 8032, section 7.1.
+    assert_eq!(to_hex(&public), "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a");
+
+    let signature = sign(&secret, b"some data")
+        .expect("Could not sign");
+    assert!(verify(&public, b"some data", &signature));
+    assert!(!verify(&public, b"some other data", &signature));
+}
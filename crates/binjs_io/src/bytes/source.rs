@@ -0,0 +1,61 @@
+use binjs_core;
+use binjs_core::ByteSource;
+
+use std;
+use std::io::Read;
+
+/// Reading from a `Read` with the decoders of `binjs_core`.
+///
+/// `binjs_core` does not know about `std::io::Error`, so errors of the reader are
+/// kept aside and returned by `finish`. The end of the data is reported to the decoder
+/// as `binjs_core::Error::UnexpectedEnd`, as some decoders accept it.
+pub struct ReadSource<'a, R: 'a + Read + ?Sized> {
+    reader: &'a mut R,
+    error: Option<std::io::Error>,
+}
+impl<'a, R: 'a + Read + ?Sized> ReadSource<'a, R> {
+    pub fn new(reader: &'a mut R) -> Self {
+        ReadSource {
+            reader,
+            error: None,
+        }
+    }
+
+    /// Convert the result of a decoder to a `std::io::Error`, returning the error of
+    /// the reader, if any.
+    pub fn finish<T>(self, result: Result<T, binjs_core::Error>) -> Result<T, std::io::Error> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        result.map_err(io_error)
+    }
+}
+impl<'a, R: 'a + Read + ?Sized> ByteSource for ReadSource<'a, R> {
+    fn next_byte(&mut self) -> Result<u8, binjs_core::Error> {
+        let mut buf = [0];
+        self.read_bytes(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), binjs_core::Error> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(()),
+            Err(ref error) if error.kind() == std::io::ErrorKind::UnexpectedEof => {
+                Err(binjs_core::Error::UnexpectedEnd)
+            }
+            Err(error) => {
+                self.error = Some(error);
+                Err(binjs_core::Error::UnexpectedEnd)
+            }
+        }
+    }
+}
+
+/// Convert an error of `binjs_core` to a `std::io::Error`.
+pub fn io_error(error: binjs_core::Error) -> std::io::Error {
+    let kind = match error {
+        binjs_core::Error::UnexpectedEnd => std::io::ErrorKind::UnexpectedEof,
+        _ => std::io::ErrorKind::InvalidData
+    };
+    std::io::Error::new(kind, error.to_string())
+}
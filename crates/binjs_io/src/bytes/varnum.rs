@@ -1,13 +1,11 @@
+use bytes::source::ReadSource;
+
+use binjs_core::varnum::read_varnum;
+pub use binjs_core::varnum::{ VARNUM_INVALID_ZERO_1, VARNUM_INVALID_ZERO_2, VARNUM_INVALID_ZERO_3, VARNUM_INVALID_ZERO_4, VARNUM_INVALID_ZERO_5, VARNUM_INVALID_ZERO_6 };
+
 use std;
 use std::io::{Read, Write};
 
-pub const VARNUM_INVALID_ZERO_1: [u8; 2] = [1, 0];
-pub const VARNUM_INVALID_ZERO_2: [u8; 3] = [1, 1, 0];
-pub const VARNUM_INVALID_ZERO_3: [u8; 4] = [1, 1, 1, 0];
-pub const VARNUM_INVALID_ZERO_4: [u8; 5] = [1, 1, 1, 1, 0];
-pub const VARNUM_INVALID_ZERO_5: [u8; 6] = [1, 1, 1, 1, 1, 0];
-pub const VARNUM_INVALID_ZERO_6: [u8; 7] = [1, 1, 1, 1, 1, 1, 0];
-
 pub trait WriteVarNum {
     fn write_maybe_varnum(&mut self, value: Option<u32>) -> Result<usize, std::io::Error>;
     fn write_varnum(&mut self, num: u32) -> Result<usize, std::io::Error>;
@@ -74,28 +72,11 @@ impl<T> ReadVarNum for T where T: Read {
     }
 
     fn read_varnum_to(&mut self, num: &mut u32) -> Result<usize, std::io::Error> {
-        let mut bytes = 0;
-        let mut result : u32 = 0;
-        let mut shift : u32 = 0;
-        let mut buf : [u8;1] = [0];
-        loop {
-            if shift >= 32 {
-                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid varnum (doesn't fit in 32 bits)"))
-            }
-            bytes += self.read(&mut buf)?;
-
-            let byte = buf[0];
-            result |= (byte as u32 >> 1) << shift;
-            if byte & 1 == 0 {
-                if result == 0 && shift != 0 {
-                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid varnum (invalid 0)"))
-                } else {
-                    *num = result;
-                    return Ok(bytes);
-                }
-            }
-            shift += 7;
-        }
+        let mut source = ReadSource::new(self);
+        let result = read_varnum(&mut source);
+        let (value, bytes) = source.finish(result)?;
+        *num = value;
+        Ok(bytes)
     }
 }
 
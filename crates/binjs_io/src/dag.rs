@@ -0,0 +1,891 @@
+//! An experimental encoding that writes identical subtrees once, then encodes
+//! their other occurrences as back-references, turning the tree into a DAG.
+//!
+//! While the tree is built, identical subtrees are detected by hash-consing,
+//! i.e. each subtree is looked up by its root and the indices of its children
+//! in a table of the distinct subtrees built so far. Once done, subtrees rooted
+//! at a tagged tuple that occur several times and are large enough are written
+//! in full the first time, as *definitions*, and replaced by a reference to
+//! the definition afterwards.
+//!
+//! The file is laid out as follows:
+//!
+//! - "[DAG]";
+//! - the number of tags (varnum), then each tag, as its byte length (varnum)
+//!   followed by its UTF-8 bytes;
+//! - "[TREE]";
+//! - the tree.
+//!
+//! In the tree, a tagged tuple is one of:
+//!
+//! - `3 * tag index` (varnum), followed by its fields;
+//! - `3 * tag index + 1` (varnum), followed by its fields, for a definition;
+//! - `3 * definition index + 2` (varnum), for a reference, definitions being
+//!   numbered from 0 in the order in which they *end*, so that definitions nested
+//!   in a definition are numbered before it.
+//!
+//! Other values are encoded as by the templates format:
+//!
+//! - a list is its number of items (varnum), followed by its items;
+//! - a string is `byte length + 1` (varnum), followed by its UTF-8 bytes, or 0
+//!   (varnum) for null;
+//! - a float is a varfloat, a BigInt is a varbigint, an unsigned long is a varnum
+//!   and a bool is a single byte.
+//!
+//! As the tree may only be read sequentially, offsets are not written, and
+//! always read as 0.
+
+use bytes::bigint::{ ReadVarBigInt, WriteVarBigInt };
+use bytes::float::{ NaNPolicy, ReadVarFloat, WriteVarFloat };
+use bytes::regexp::{ ReadRegExpFlags, WriteRegExpFlags };
+use bytes::varnum::{ ReadVarNum, WriteVarNum };
+use io::{ FileStructurePrinter, Path, TokenReader, TokenWriterWithTree };
+use util::ReadConst;
+use ::{ TokenReaderError, TokenWriterError };
+
+use binjs_shared::{ BigInt, FieldName, InterfaceName, RegExpFlags, SharedString };
+
+use std;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{ Read, Write };
+use std::rc::Rc;
+
+use clap;
+
+const HEADER_DAG: &str = "[DAG]";
+const HEADER_TREE: &str = "[TREE]";
+
+/// The kinds of tagged tuples, as the remainder of their value by 3.
+const INLINE: u32 = 0;
+const DEFINITION: u32 = 1;
+const REFERENCE: u32 = 2;
+
+/// The default minimal size of a subtree to replace its repeated occurrences with references.
+pub const DEFAULT_MIN_SIZE: usize = 4;
+
+#[derive(Clone)]
+pub struct Options {
+    /// The minimal size of a subtree, as the approximate number of bytes it takes
+    /// when written in full, to replace its repeated occurrences with references.
+    min_size: usize,
+
+    /// Statistics obtained while writing. If several files are written with
+    /// the same options, the statistics are accumulated.
+    statistics: Rc<RefCell<Statistics>>,
+}
+impl Options {
+    pub fn new(min_size: usize) -> Self {
+        Options {
+            min_size,
+            statistics: Rc::new(RefCell::new(Statistics::default())),
+        }
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn statistics_for_write(&self) -> Statistics {
+        self.statistics.borrow()
+            .clone()
+    }
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self::new(DEFAULT_MIN_SIZE)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Statistics {
+    pub number_of_files: usize,
+
+    /// The number of tagged tuples, as if the trees were written without references.
+    pub tagged_tuples: usize,
+
+    /// The number of distinct subtrees rooted at a tagged tuple.
+    pub distinct_tagged_tuples: usize,
+
+    /// The number of subtrees written in full once, then referenced.
+    pub definitions: usize,
+
+    /// The number of references to a definition.
+    pub references: usize,
+
+    /// The number of tagged tuples replaced by references.
+    pub elided_tagged_tuples: usize,
+
+    /// The number of bytes taken by the trees.
+    pub tree_bytes: usize,
+}
+impl std::fmt::Display for Statistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "DAG:
+\tFiles: {number_of_files}
+\tTagged tuples: {tagged_tuples} ({distinct_tagged_tuples} distinct)
+\tDefinitions: {definitions}
+\tReferences: {references}, replacing {elided_tagged_tuples} tagged tuples
+\tTree: {tree_bytes} bytes
+",
+            number_of_files = self.number_of_files,
+            tagged_tuples = self.tagged_tuples,
+            distinct_tagged_tuples = self.distinct_tagged_tuples,
+            definitions = self.definitions,
+            references = self.references,
+            elided_tagged_tuples = self.elided_tagged_tuples,
+            tree_bytes = self.tree_bytes,
+        )
+    }
+}
+
+/// A distinct subtree, whose children are identified by their index in `Encoder::nodes`.
+#[derive(PartialEq, Eq, Hash)]
+enum Node {
+    /// A primitive value, already encoded.
+    Leaf(Vec<u8>),
+    List(Vec<u32>),
+    Tagged(InterfaceName, Vec<u32>),
+}
+impl Node {
+    fn children(&self) -> &[u32] {
+        match *self {
+            Node::Leaf(_) => &[],
+            Node::List(ref children) |
+            Node::Tagged(_, ref children) => children.as_slice(),
+        }
+    }
+}
+
+/// Abstract type for the contents of the tree, as the index of a distinct subtree.
+#[derive(Clone)]
+pub struct Tree(u32);
+
+/// Collect the tags of the subtree `index`, in order of first appearance.
+fn collect_tags(nodes: &[Rc<Node>], index: u32, visited: &mut [bool], tags: &mut Vec<InterfaceName>, indices: &mut HashMap<InterfaceName, u32>) {
+    if visited[index as usize] {
+        return;
+    }
+    visited[index as usize] = true;
+    if let Node::Tagged(ref tag, _) = *nodes[index as usize] {
+        if !indices.contains_key(tag) {
+            indices.insert(tag.clone(), tags.len() as u32);
+            tags.push(tag.clone());
+        }
+    }
+    for child in nodes[index as usize].children() {
+        collect_tags(nodes, *child, visited, tags, indices);
+    }
+}
+
+/// Write a tree, replacing repeated occurrences of candidate subtrees with references.
+struct TreeWriter<'a> {
+    nodes: &'a [Rc<Node>],
+    tags: &'a HashMap<InterfaceName, u32>,
+
+    /// For each subtree, `true` if its repeated occurrences may be replaced with references.
+    candidates: Vec<bool>,
+
+    /// For each subtree, the number of tagged tuples it contains.
+    tagged_tuples: Vec<usize>,
+
+    /// For each subtree, the number of times it is reached while writing, i.e.
+    /// not counting the occurrences inside a reference.
+    uses: Vec<usize>,
+
+    /// For each subtree written as a definition, the index of the definition.
+    definitions: Vec<Option<u32>>,
+
+    number_of_definitions: u32,
+    references: usize,
+    elided_tagged_tuples: usize,
+}
+impl<'a> TreeWriter<'a> {
+    /// Count the uses of the subtree `index` and its descendants.
+    ///
+    /// Must be called on the root before `write`, which only writes as definitions
+    /// the candidates used several times.
+    fn count_uses(&mut self, index: u32) {
+        let nodes = self.nodes;
+        let i = index as usize;
+        self.uses[i] += 1;
+        if self.candidates[i] && self.uses[i] > 1 {
+            // This occurrence will be a reference.
+            return;
+        }
+        for child in nodes[i].children() {
+            self.count_uses(*child);
+        }
+    }
+
+    fn write(&mut self, index: u32, out: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        let nodes = self.nodes;
+        let i = index as usize;
+        match *nodes[i] {
+            Node::Leaf(ref bytes) => {
+                out.write_all(bytes)?;
+            }
+            Node::List(ref items) => {
+                out.write_varnum(items.len() as u32)?;
+                for item in items {
+                    self.write(*item, out)?;
+                }
+            }
+            Node::Tagged(ref tag, ref children) => {
+                if let Some(definition) = self.definitions[i] {
+                    out.write_varnum(definition * 3 + REFERENCE)?;
+                    self.references += 1;
+                    self.elided_tagged_tuples += self.tagged_tuples[i];
+                    return Ok(());
+                }
+                let is_definition = self.candidates[i] && self.uses[i] > 1;
+                let tag_index = self.tags.get(tag)
+                    .expect("All tags should have been collected by now");
+                out.write_varnum(tag_index * 3 + if is_definition { DEFINITION } else { INLINE })?;
+                for child in children {
+                    self.write(*child, out)?;
+                }
+                if is_definition {
+                    // Definitions are numbered once complete, as by the reader.
+                    self.definitions[i] = Some(self.number_of_definitions);
+                    self.number_of_definitions += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A writer for the DAG format.
+pub struct Encoder {
+    options: Options,
+
+    /// The distinct subtrees, children before their parents.
+    nodes: Vec<Rc<Node>>,
+
+    /// The index of each distinct subtree in `nodes`.
+    indices: HashMap<Rc<Node>, u32>,
+
+    /// For each distinct subtree, the approximate number of bytes it takes when
+    /// written in full.
+    byte_lens: Vec<usize>,
+
+    /// The latest tree written, i.e. the root once we are done.
+    root: Option<u32>,
+}
+impl Encoder {
+    pub fn new(options: Options) -> Self {
+        Encoder {
+            options,
+            nodes: vec![],
+            indices: HashMap::new(),
+            byte_lens: vec![],
+            root: None,
+        }
+    }
+
+    /// Return the subtree identical to `node`, registering it if it is new.
+    fn register(&mut self, node: Node) -> Tree {
+        let existing = self.indices.get(&node)
+            .cloned();
+        let index = match existing {
+            Some(index) => index,
+            None => {
+                let byte_len = {
+                    let byte_lens = &self.byte_lens;
+                    match node {
+                        Node::Leaf(ref bytes) => bytes.len(),
+                        Node::List(ref children) |
+                        Node::Tagged(_, ref children) => 1 + children.iter()
+                            .map(|child| byte_lens[*child as usize])
+                            .sum::<usize>()
+                    }
+                };
+                let index = self.nodes.len() as u32;
+                let node = Rc::new(node);
+                self.nodes.push(node.clone());
+                self.indices.insert(node, index);
+                self.byte_lens.push(byte_len);
+                index
+            }
+        };
+        self.root = Some(index);
+        Tree(index)
+    }
+
+    fn leaf<F>(&mut self, f: F) -> Result<Tree, TokenWriterError> where F: FnOnce(&mut Vec<u8>) -> Result<usize, std::io::Error> {
+        let mut buf = vec![];
+        f(&mut buf)
+            .map_err(TokenWriterError::WriteError)?;
+        Ok(self.register(Node::Leaf(buf)))
+    }
+}
+
+impl TokenWriterWithTree for Encoder {
+    type Tree = Tree;
+    type Data = Vec<u8>;
+
+    fn done(mut self) -> Result<Self::Data, TokenWriterError> {
+        let root = self.root;
+        let root = match root {
+            Some(root) => root,
+            None => self.register(Node::Leaf(vec![])).0
+        };
+        let len = self.nodes.len();
+
+        // Children are registered before their parents, so the number of occurrences
+        // of each subtree may be propagated from the root down in a single pass.
+        let mut occurrences = vec![0usize; len];
+        occurrences[root as usize] = 1;
+        for i in (0..len).rev() {
+            let count = occurrences[i];
+            if count == 0 {
+                continue;
+            }
+            for child in self.nodes[i].children() {
+                occurrences[*child as usize] += count;
+            }
+        }
+
+        let mut tagged_tuples = vec![0; len];
+        for i in 0..len {
+            let own = match *self.nodes[i] {
+                Node::Tagged(..) => 1,
+                _ => 0
+            };
+            let count = own + self.nodes[i].children()
+                .iter()
+                .map(|child| tagged_tuples[*child as usize])
+                .sum::<usize>();
+            tagged_tuples[i] = count;
+        }
+
+        let mut tags = vec![];
+        let mut tag_indices = HashMap::new();
+        collect_tags(&self.nodes, root, &mut vec![false; len], &mut tags, &mut tag_indices);
+
+        let candidates = (0..len)
+            .map(|i| match *self.nodes[i] {
+                Node::Tagged(..) => occurrences[i] > 1 && self.byte_lens[i] >= self.options.min_size,
+                _ => false
+            })
+            .collect();
+        let mut writer = TreeWriter {
+            nodes: &self.nodes,
+            tags: &tag_indices,
+            candidates,
+            tagged_tuples,
+            uses: vec![0; len],
+            definitions: vec![None; len],
+            number_of_definitions: 0,
+            references: 0,
+            elided_tagged_tuples: 0,
+        };
+        writer.count_uses(root);
+
+        let mut data = vec![];
+        data.write_all(HEADER_DAG.as_bytes())
+            .map_err(TokenWriterError::WriteError)?;
+        data.write_varnum(tags.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        for tag in &tags {
+            data.write_varnum(tag.as_str().len() as u32)
+                .map_err(TokenWriterError::WriteError)?;
+            data.write_all(tag.as_str().as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+        }
+
+        data.write_all(HEADER_TREE.as_bytes())
+            .map_err(TokenWriterError::WriteError)?;
+        let tree_start = data.len();
+        writer.write(root, &mut data)
+            .map_err(TokenWriterError::WriteError)?;
+        debug!(target: "dag", "Wrote {} definitions, {} references", writer.number_of_definitions, writer.references);
+
+        let mut statistics = self.options.statistics.borrow_mut();
+        statistics.number_of_files += 1;
+        statistics.tagged_tuples += writer.tagged_tuples[root as usize];
+        statistics.distinct_tagged_tuples += (0..len)
+            .filter(|&i| occurrences[i] > 0)
+            .filter(|&i| match *self.nodes[i] {
+                Node::Tagged(..) => true,
+                _ => false
+            })
+            .count();
+        statistics.definitions += writer.number_of_definitions as usize;
+        statistics.references += writer.references;
+        statistics.elided_tagged_tuples += writer.elided_tagged_tuples;
+        statistics.tree_bytes += data.len() - tree_start;
+        Ok(data)
+    }
+
+    fn float(&mut self, value: Option<f64>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_maybe_varfloat(value, NaNPolicy::default()))
+    }
+
+    fn big_int(&mut self, value: Option<&BigInt>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_maybe_varbigint(value.map(BigInt::as_str)))
+    }
+
+    fn reg_exp_flags(&mut self, value: Option<&RegExpFlags>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_maybe_reg_exp_flags(value))
+    }
+
+    fn bool(&mut self, value: Option<bool>) -> Result<Self::Tree, TokenWriterError> {
+        let bytes = ::bytes::bool::bytes_of_bool(value);
+        self.leaf(|buf| buf.write_all(&bytes).map(|_| bytes.len()))
+    }
+
+    fn offset(&mut self) -> Result<Self::Tree, TokenWriterError> {
+        Ok(self.register(Node::Leaf(vec![])))
+    }
+
+    fn unsigned_long(&mut self, value: u32) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_varnum(value))
+    }
+
+    fn string(&mut self, value: Option<&SharedString>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| match value {
+            None => buf.write_varnum(0),
+            Some(value) => {
+                let len = buf.write_varnum(value.len() as u32 + 1)?;
+                buf.write_all(value.as_bytes())?;
+                Ok(len + value.len())
+            }
+        })
+    }
+
+    fn list(&mut self, items: Vec<Self::Tree>) -> Result<Self::Tree, TokenWriterError> {
+        let items = items.into_iter()
+            .map(|item| item.0)
+            .collect();
+        Ok(self.register(Node::List(items)))
+    }
+
+    fn tagged_tuple(&mut self, tag: &InterfaceName, children: &[(&FieldName, Self::Tree)]) -> Result<Self::Tree, TokenWriterError> {
+        let children = children.iter()
+            .map(|&(_, ref child)| child.0)
+            .collect();
+        Ok(self.register(Node::Tagged(tag.clone(), children)))
+    }
+}
+
+/// A token, as read while reading a definition, then replayed for each reference.
+#[derive(Clone, Debug)]
+enum Token {
+    String(Option<SharedString>),
+    Float(Option<f64>),
+    BigInt(Option<BigInt>),
+    RegExpFlags(Option<RegExpFlags>),
+    UnsignedLong(u32),
+    Bool(Option<bool>),
+    Offset,
+    EnterList(u32),
+    ExitList,
+    EnterTaggedTuple(InterfaceName),
+    ExitTaggedTuple,
+}
+
+/// A definition being read.
+struct Recording {
+    /// The number of lists and tagged tuples enclosing the definition.
+    depth: usize,
+
+    /// The tokens read so far, starting with `Token::EnterTaggedTuple`.
+    tokens: Vec<Token>,
+}
+
+/// A list or a tagged tuple being read.
+enum Frame {
+    List,
+    TaggedTuple,
+}
+
+/// A reader for the DAG format.
+pub struct Decoder<R: Read> {
+    reader: R,
+    tags: Vec<InterfaceName>,
+
+    /// The tokens of each definition read so far, by definition index.
+    definitions: Vec<Rc<Vec<Token>>>,
+
+    /// The definitions being read, innermost last.
+    recordings: Vec<Recording>,
+
+    /// If specified, the definition being replayed, and the position of the next token.
+    replay: Option<(Rc<Vec<Token>>, usize)>,
+
+    /// The lists and tagged tuples being read, innermost last.
+    frames: Vec<Frame>,
+}
+impl<R: Read> Decoder<R> {
+    pub fn new(mut reader: R) -> Result<Self, TokenReaderError> {
+        reader.read_const(HEADER_DAG.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        let number_of_tags = reader.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let mut tags = vec![];
+        for _ in 0..number_of_tags {
+            let byte_len = reader.read_varnum()
+                .map_err(TokenReaderError::ReadError)? as usize;
+            let mut bytes = vec![];
+            reader.by_ref()
+                .take(byte_len as u64)
+                .read_to_end(&mut bytes)
+                .map_err(TokenReaderError::ReadError)?;
+            if bytes.len() != byte_len {
+                return Err(TokenReaderError::BadLength { expected: byte_len, got: bytes.len() });
+            }
+            let tag = String::from_utf8(bytes)
+                .map_err(TokenReaderError::Encoding)?;
+            tags.push(InterfaceName::from_string(tag));
+        }
+        debug!(target: "dag", "Read {} tags", tags.len());
+
+        reader.read_const(HEADER_TREE.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(Decoder {
+            reader,
+            tags,
+            definitions: vec![],
+            recordings: vec![],
+            replay: None,
+            frames: vec![],
+        })
+    }
+
+    /// The next token of the definition being replayed, if any.
+    fn replayed(&mut self) -> Option<Token> {
+        let (token, done) = match self.replay {
+            None => return None,
+            Some((ref tokens, ref mut position)) => {
+                let token = tokens[*position].clone();
+                *position += 1;
+                (token, *position == tokens.len())
+            }
+        };
+        if done {
+            self.replay = None;
+        }
+        Some(token)
+    }
+
+    /// Append `token` to the definitions being read.
+    fn record(&mut self, token: &Token) {
+        for recording in &mut self.recordings {
+            recording.tokens.push(token.clone());
+        }
+    }
+
+    /// Read the next token, either replayed or read by `read`.
+    fn next<F>(&mut self, read: F) -> Result<Token, TokenReaderError> where F: FnOnce(&mut R) -> Result<Token, TokenReaderError> {
+        let token = match self.replayed() {
+            Some(token) => token,
+            None => read(&mut self.reader)?
+        };
+        self.record(&token);
+        Ok(token)
+    }
+
+    /// Leave the innermost list or tagged tuple, which must match `frame`.
+    fn exit(&mut self, token: Token) -> Result<(), TokenReaderError> {
+        let replayed = self.replayed()
+            .unwrap_or_else(|| token.clone());
+        match (replayed, self.frames.pop()) {
+            (Token::ExitList, Some(Frame::List)) |
+            (Token::ExitTaggedTuple, Some(Frame::TaggedTuple)) => {}
+            _ => return Err(TokenReaderError::InvalidValue)
+        }
+        self.record(&token);
+        let complete = self.recordings.last()
+            .map_or(false, |recording| recording.depth == self.frames.len());
+        if complete {
+            let recording = self.recordings.pop()
+                .unwrap(); // Just checked.
+            self.definitions.push(Rc::new(recording.tokens));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> FileStructurePrinter for Decoder<R> {}
+
+impl<R: Read> TokenReader for Decoder<R> {
+    fn string_at(&mut self, _path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        let token = self.next(|reader| {
+            let value = reader.read_varnum()
+                .map_err(TokenReaderError::ReadError)?;
+            if value == 0 {
+                return Ok(Token::String(None));
+            }
+            let byte_len = (value - 1) as usize;
+            let mut bytes = vec![];
+            reader.by_ref()
+                .take(byte_len as u64)
+                .read_to_end(&mut bytes)
+                .map_err(TokenReaderError::ReadError)?;
+            if bytes.len() != byte_len {
+                return Err(TokenReaderError::BadLength { expected: byte_len, got: bytes.len() });
+            }
+            let string = String::from_utf8(bytes)
+                .map_err(TokenReaderError::Encoding)?;
+            Ok(Token::String(Some(SharedString::from_string(string))))
+        })?;
+        match token {
+            Token::String(value) => Ok(value),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn float_at(&mut self, _path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        let token = self.next(|reader| reader.read_maybe_varfloat(NaNPolicy::default())
+            .map(Token::Float)
+            .map_err(TokenReaderError::ReadError))?;
+        match token {
+            Token::Float(value) => Ok(value),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn big_int_at(&mut self, _path: &Path) -> Result<Option<BigInt>, TokenReaderError> {
+        let token = self.next(|reader| reader.read_maybe_varbigint()
+            .map(|value| Token::BigInt(value.map(BigInt::from_string)))
+            .map_err(TokenReaderError::ReadError))?;
+        match token {
+            Token::BigInt(value) => Ok(value),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn reg_exp_flags_at(&mut self, _path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        let token = self.next(|reader| reader.read_maybe_reg_exp_flags()
+            .map(Token::RegExpFlags)
+            .map_err(TokenReaderError::ReadError))?;
+        match token {
+            Token::RegExpFlags(value) => Ok(value),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn unsigned_long_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        let token = self.next(|reader| reader.read_varnum()
+            .map(Token::UnsignedLong)
+            .map_err(TokenReaderError::ReadError))?;
+        match token {
+            Token::UnsignedLong(value) => Ok(value),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn bool_at(&mut self, _path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        let token = self.next(|reader| {
+            let mut buf : [u8; 1] = [0];
+            reader.read_exact(&mut buf)
+                .map_err(TokenReaderError::ReadError)?;
+            ::bytes::bool::bool_of_bytes(&buf)
+                .map(Token::Bool)
+                .map_err(|_| TokenReaderError::invalid_value(&"bool"))
+        })?;
+        match token {
+            Token::Bool(value) => Ok(value),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        match self.next(|_| Ok(Token::Offset))? {
+            Token::Offset => Ok(0),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn enter_list_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        let token = self.next(|reader| reader.read_varnum()
+            .map(Token::EnterList)
+            .map_err(TokenReaderError::ReadError))?;
+        match token {
+            Token::EnterList(len) => {
+                self.frames.push(Frame::List);
+                Ok(len)
+            }
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn exit_list_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.exit(Token::ExitList)
+    }
+
+    fn enter_tagged_tuple_at(&mut self, _path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
+        if let Some(token) = self.replayed() {
+            self.record(&token);
+            return match token {
+                Token::EnterTaggedTuple(tag) => {
+                    self.frames.push(Frame::TaggedTuple);
+                    Ok((tag, None))
+                }
+                _ => Err(TokenReaderError::InvalidValue)
+            };
+        }
+
+        let value = self.reader.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let index = value / 3;
+        let tag = match value % 3 {
+            REFERENCE => {
+                let definition = self.definitions.get(index as usize)
+                    .cloned()
+                    .ok_or(TokenReaderError::InvalidValue)?;
+                let tag = match definition.first() {
+                    Some(&Token::EnterTaggedTuple(ref tag)) => tag.clone(),
+                    _ => return Err(TokenReaderError::InvalidValue) // Definitions are tagged tuples.
+                };
+                // Replay the rest of the definition, starting with its fields.
+                self.replay = Some((definition, 1));
+                tag
+            }
+            _ => self.tags.get(index as usize)
+                .cloned()
+                .ok_or(TokenReaderError::BadKindIndex(index))?
+        };
+        let token = Token::EnterTaggedTuple(tag.clone());
+        self.record(&token);
+        if value % 3 == DEFINITION {
+            self.recordings.push(Recording {
+                depth: self.frames.len(),
+                tokens: vec![token],
+            });
+        }
+        self.frames.push(Frame::TaggedTuple);
+        Ok((tag, None))
+    }
+
+    fn exit_tagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.exit(Token::ExitTaggedTuple)
+    }
+
+    fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        unimplemented!()
+    }
+}
+
+/// Command-line management.
+pub struct FormatProvider;
+impl ::FormatProvider for FormatProvider {
+    fn subcommand<'a, 'b>(&self) -> clap::App<'a, 'b> {
+        use clap::*;
+        SubCommand::with_name("dag")
+            .about("(EXPERIMENTAL) Write identical subtrees once, then encode their other occurrences as back-references.")
+            .arg(Arg::with_name("min-size")
+                .long("min-size")
+                .takes_value(true)
+                .default_value("4")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Minimal size of a subtree, in bytes when written in full, to replace its repeated occurrences with back-references.")
+            )
+    }
+
+    fn handle_subcommand(&self, matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
+        let options = match matches {
+            None => Options::default(),
+            Some(matches) => {
+                let min_size = matches.value_of("min-size")
+                    .unwrap() // Guaranteed by `clap`.
+                    .parse::<usize>()
+                    .unwrap(); // Guaranteed by `clap`.
+                Options::new(min_size)
+            }
+        };
+        Ok(::Format::Dag {
+            options
+        })
+    }
+}
+
+#[test]
+fn test_dag_roundtrip() {
+    use binjs_shared::ast::Path;
+    use std::io::Cursor;
+
+    let path = Path::new();
+
+    // `Call(Identifier("foo"), [Literal(1)])`, repeated, then `Wrapper(Identifier("foo"))`.
+    let write = |options: Options| {
+        let mut encoder = Encoder::new(options);
+        let mut items = vec![];
+        for _ in 0..20 {
+            let name = encoder.string(Some(&SharedString::from_str("foo"))).unwrap();
+            let callee = encoder.tagged_tuple(&InterfaceName::from_str("Identifier"), &[(&FieldName::from_str("name"), name)]).unwrap();
+            let value = encoder.float(Some(1.)).unwrap();
+            let argument = encoder.tagged_tuple(&InterfaceName::from_str("Literal"), &[(&FieldName::from_str("value"), value)]).unwrap();
+            let arguments = encoder.list(vec![argument]).unwrap();
+            items.push(encoder.tagged_tuple(&InterfaceName::from_str("Call"), &[
+                (&FieldName::from_str("callee"), callee),
+                (&FieldName::from_str("arguments"), arguments),
+            ]).unwrap());
+        }
+        let name = encoder.string(Some(&SharedString::from_str("foo"))).unwrap();
+        let identifier = encoder.tagged_tuple(&InterfaceName::from_str("Identifier"), &[(&FieldName::from_str("name"), name)]).unwrap();
+        items.push(encoder.tagged_tuple(&InterfaceName::from_str("Wrapper"), &[(&FieldName::from_str("value"), identifier)]).unwrap());
+        encoder.list(items).unwrap();
+        encoder.done()
+            .expect("Could not finalize data")
+    };
+
+    let options = Options::default();
+    let data = write(options.clone());
+    let statistics = options.statistics_for_write();
+    assert_eq!(statistics.tagged_tuples, 62);
+    assert_eq!(statistics.distinct_tagged_tuples, 4);
+    // The first `Call`, and the `Identifier` it contains, which is referenced by `Wrapper`.
+    assert_eq!(statistics.definitions, 2);
+    assert_eq!(statistics.references, 20);
+    assert_eq!(statistics.elided_tagged_tuples, 19 * 3 + 1);
+
+    // Without references, the file is larger.
+    let plain = write(Options::new(std::usize::MAX));
+    assert!(data.len() < plain.len());
+
+    for data in &[data, plain] {
+        let mut decoder = Decoder::new(Cursor::new(data))
+            .expect("Could not read header");
+        assert_eq!(decoder.enter_list_at(&path).unwrap(), 21);
+        for _ in 0..20 {
+            assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Call"));
+            assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Identifier"));
+            assert_eq!(decoder.string_at(&path).unwrap(), Some(SharedString::from_str("foo")));
+            decoder.exit_tagged_tuple_at(&path).unwrap();
+            assert_eq!(decoder.enter_list_at(&path).unwrap(), 1);
+            assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Literal"));
+            assert_eq!(decoder.float_at(&path).unwrap(), Some(1.));
+            decoder.exit_tagged_tuple_at(&path).unwrap();
+            decoder.exit_list_at(&path).unwrap();
+            decoder.exit_tagged_tuple_at(&path).unwrap();
+        }
+        assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Wrapper"));
+        assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Identifier"));
+        assert_eq!(decoder.string_at(&path).unwrap(), Some(SharedString::from_str("foo")));
+        decoder.exit_tagged_tuple_at(&path).unwrap();
+        decoder.exit_tagged_tuple_at(&path).unwrap();
+        decoder.exit_list_at(&path).unwrap();
+    }
+
+    // Reading a reference as a value of another type fails.
+    let mut decoder = Decoder::new(Cursor::new(write(Options::default())))
+        .expect("Could not read header");
+    decoder.enter_list_at(&path).unwrap();
+    decoder.enter_tagged_tuple_at(&path).unwrap();
+    decoder.enter_tagged_tuple_at(&path).unwrap();
+    decoder.string_at(&path).unwrap();
+    decoder.exit_tagged_tuple_at(&path).unwrap();
+    decoder.enter_list_at(&path).unwrap();
+    decoder.enter_tagged_tuple_at(&path).unwrap();
+    decoder.float_at(&path).unwrap();
+    decoder.exit_tagged_tuple_at(&path).unwrap();
+    decoder.exit_list_at(&path).unwrap();
+    decoder.exit_tagged_tuple_at(&path).unwrap();
+    decoder.enter_tagged_tuple_at(&path).unwrap();
+    assert!(decoder.string_at(&path).is_err());
+}
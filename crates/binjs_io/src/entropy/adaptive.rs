@@ -0,0 +1,1189 @@
+//! An adaptive entropy coder, which does not require an external dictionary.
+//!
+//! The encoder and the decoder start from the same empty probability models
+//! and update them after each symbol, so they remain in sync without having
+//! to share a dictionary. In each context (the kind of value and the path in
+//! the AST, up to a configurable depth), a model holds the values encountered
+//! so far, plus an escape symbol. A value that has never been encountered in
+//! its context is coded as the escape symbol, followed by the value itself,
+//! serialized with bincode and coded byte by byte with order-0 adaptive models.
+//!
+//! This typically compresses worse than a good dictionary on small files, as
+//! each file needs to teach the models its values.
+//!
+//! Small integers, i.e. `unsigned long` values and list lengths, are coded in
+//! dedicated streams. As they cluster tightly, rather than coding the value
+//! itself, each stream codes a `Prediction` of the value from the values most
+//! recently encountered in the same context. The file is formatted as:
+//!
+//! - the header, see `header`, which records the bit-level coder and the path
+//!   depth, so that decoders don't need them;
+//! - the byte length of the `unsigned long` stream (`varnum`);
+//! - the byte length of the list lengths stream (`varnum`);
+//! - the `unsigned long` stream;
+//! - the list lengths stream;
+//! - the main stream, containing all other values.
+
+// FIXME: Implement lazy functions
+
+use super::coder::{ Backend, Reader, SymbolReader, SymbolWriter, Writer };
+use super::header::Header;
+
+use ::{ TokenReaderError, TokenWriterError };
+use ::io::{ FileStructurePrinter, Path, TokenReader, TokenWriter };
+use ::io::statistics::{ ContentInfo, Gain };
+use bytes::varnum::{ ReadVarNum, WriteVarNum };
+
+use binjs_shared::{ F64, FieldName, IdentifierName, InterfaceName, Node, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
+
+use std;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{ Cursor, Read };
+use std::rc::Rc;
+
+use bincode;
+use binjs_core::rans::Distribution;
+use serde;
+
+/// The symbol announcing a value that has never been encountered in its context.
+const ESCAPE : u32 = 0;
+
+/// Once the number of instances in a model exceeds this value, all instances are
+/// halved. This keeps frequencies within the limits of the range coder and lets
+/// models forget about old values. Models with many symbols wait until they have
+/// twice as many instances as symbols, so that halving remains infrequent.
+const MAX_TOTAL_INSTANCES : u32 = 1 << 15;
+
+/// The default amount of path context.
+const DEFAULT_DEPTH : usize = 1;
+
+/// The number of recently encountered small integers remembered in each context.
+const RECENT_LEN : usize = 4;
+
+/// The largest difference with the most recently encountered small integer
+/// coded as a `Prediction::Delta`.
+const MAX_DELTA : i64 = 8;
+
+/// Options for adaptive entropy coding.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// The amount of path context used to predict values. The depth is recorded
+    /// in the header of each file, see `header`, decoders don't need it.
+    ///
+    /// With a depth of 0, paths are ignored. With a depth of 1, we only take into account
+    /// the node/field. With a depth of 2, we also take into account the node/field of the
+    /// grand parent, etc.
+    depth: usize,
+
+    /// The bit-level coder. The coder is recorded in the header of each file,
+    /// see `header`, decoders don't need it.
+    backend: Backend,
+
+    /// Statistics obtained while writing: the gain of prediction on the
+    /// small integer streams. If several files are written with the same
+    /// options, we accumulate statistics.
+    gains: Rc<RefCell<ContentInfo<Gain>>>,
+}
+impl Options {
+    pub fn new(depth: usize) -> Self {
+        Options {
+            depth,
+            backend: Backend::default(),
+            gains: Rc::new(RefCell::new(ContentInfo::default())),
+        }
+    }
+
+    /// Use a specific bit-level coder.
+    pub fn with_backend(self, backend: Backend) -> Self {
+        Options {
+            backend,
+            ..self
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Return the gain of prediction on the small integer streams.
+    pub fn statistics_for_write(&self) -> ContentInfo<Gain> {
+        self.gains.borrow()
+            .clone()
+    }
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEPTH)
+    }
+}
+
+/// The number of instances of symbols `0..n`, stored as a Fenwick tree, so that
+/// finding, updating and adding a symbol all take `O(log n)`.
+#[derive(Clone, Debug, Default)]
+struct AdaptiveDistribution {
+    /// `tree[i - 1]` is the sum of the instances of symbols `i - (i & -i)..i`.
+    tree: Vec<u32>,
+
+    /// The sum of the instances of all symbols.
+    total: u32,
+}
+impl AdaptiveDistribution {
+    fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// The sum of the instances of symbols `0..end`.
+    fn prefix(&self, end: usize) -> u32 {
+        let mut sum = 0;
+        let mut i = end;
+        while i > 0 {
+            sum += self.tree[i - 1];
+            i &= i - 1;
+        }
+        sum
+    }
+
+    /// Add symbol `self.len()`, with `instances` instances.
+    fn push(&mut self, instances: u32) {
+        let i = self.tree.len() + 1;
+        let start = i & (i - 1);
+        let sum = self.prefix(i - 1) - self.prefix(start) + instances;
+        self.tree.push(sum);
+        self.total += instances;
+    }
+
+    /// Record one more instance of `symbol`.
+    fn increment(&mut self, symbol: usize) {
+        let mut i = symbol + 1;
+        while i <= self.tree.len() {
+            self.tree[i - 1] += 1;
+            i += i & i.wrapping_neg();
+        }
+        self.total += 1;
+    }
+
+    /// Halve the number of instances of all symbols, keeping at least one instance
+    /// of each symbol.
+    fn halve(&mut self) {
+        let instances : Vec<u32> = (0..self.len())
+            .map(|symbol| (self.prefix(symbol + 1) - self.prefix(symbol) + 1) / 2)
+            .collect();
+        self.tree.clear();
+        self.total = 0;
+        for instances in instances {
+            self.push(instances);
+        }
+    }
+}
+impl Distribution for AdaptiveDistribution {
+    fn width(&self) -> u32 {
+        self.total
+    }
+
+    fn find(&self, frequency: u32) -> Option<usize> {
+        if frequency >= self.total {
+            return None;
+        }
+        // Find the largest `end` such that `prefix(end) <= frequency`, by descending the tree.
+        let mut end = 0;
+        let mut remaining = frequency;
+        let mut step = self.tree.len().next_power_of_two();
+        while step > 0 {
+            let next = end + step;
+            if next <= self.tree.len() && self.tree[next - 1] <= remaining {
+                end = next;
+                remaining -= self.tree[next - 1];
+            }
+            step >>= 1;
+        }
+        Some(end)
+    }
+
+    fn segment(&self, index: usize) -> Option<(u32, u32)> {
+        if index >= self.len() {
+            return None;
+        }
+        Some((self.prefix(index), self.prefix(index + 1)))
+    }
+}
+
+/// An adaptive model of the values encountered in a context.
+///
+/// Symbol `ESCAPE` announces a new value, symbol `i + 1` stands for `values[i]`.
+struct Model<T> where T: Eq + Hash + Clone {
+    values: Vec<T>,
+
+    /// The reverse mapping from `values`.
+    symbol_by_value: HashMap<T, u32>,
+
+    /// The number of instances of each symbol, including `ESCAPE`.
+    instances: AdaptiveDistribution,
+}
+impl<T> Model<T> where T: Eq + Hash + Clone {
+    fn new() -> Self {
+        let mut instances = AdaptiveDistribution::default();
+        instances.push(1);
+        Model {
+            values: vec![],
+            symbol_by_value: HashMap::new(),
+            instances,
+        }
+    }
+
+    fn symbol(&self, value: &T) -> Option<u32> {
+        self.symbol_by_value.get(value)
+            .cloned()
+    }
+
+    fn value(&self, symbol: u32) -> Option<&T> {
+        if symbol == ESCAPE {
+            return None;
+        }
+        self.values.get(symbol as usize - 1)
+    }
+
+    fn distribution(&self) -> &AdaptiveDistribution {
+        &self.instances
+    }
+
+    /// Record one more instance of `symbol`.
+    fn update(&mut self, symbol: u32) {
+        self.instances.increment(symbol as usize);
+        let max = std::cmp::max(MAX_TOTAL_INSTANCES, 2 * self.instances.len() as u32);
+        if self.instances.width() > max {
+            self.instances.halve();
+        }
+    }
+
+    /// Add a value that has never been encountered, with a single instance.
+    fn push(&mut self, value: T) {
+        let symbol = self.instances.len() as u32;
+        self.symbol_by_value.insert(value.clone(), symbol);
+        self.values.push(value);
+        self.instances.push(1);
+    }
+}
+impl Model<u8> {
+    /// A model in which all bytes are already known.
+    fn bytes() -> Self {
+        let mut model = Model::new();
+        for byte in 0..=255u8 {
+            model.push(byte);
+        }
+        model
+    }
+}
+
+/// The models for a kind of value, by path.
+struct Table<T> where T: Eq + Hash + Clone {
+    depth: usize,
+    models: HashMap<Path, Model<T>>,
+}
+impl<T> Table<T> where T: Eq + Hash + Clone {
+    fn new(depth: usize) -> Self {
+        Table {
+            depth,
+            models: HashMap::new(),
+        }
+    }
+
+    fn model(&mut self, path: &Path) -> &mut Model<T> {
+        let tail = path.tail(self.depth);
+        if !self.models.contains_key(tail) {
+            let mut key = Path::new();
+            key.extend_from_slice(tail);
+            self.models.insert(key, Model::new());
+        }
+        self.models.get_mut(tail)
+            .unwrap() // We have just inserted the model.
+    }
+}
+
+/// The models used to code values that have never been encountered in their context.
+struct Literals {
+    /// The length of the serialized value, as LEB128.
+    lengths: Model<u8>,
+
+    /// The bytes of the serialized value.
+    bytes: Model<u8>,
+}
+impl Literals {
+    fn new() -> Self {
+        Literals {
+            lengths: Model::bytes(),
+            bytes: Model::bytes(),
+        }
+    }
+
+    fn write<T>(&mut self, writer: &mut Writer, value: &T) -> Result<(), TokenWriterError> where T: serde::Serialize {
+        let bytes = bincode::serialize(value)
+            .map_err(|err| TokenWriterError::WriteError(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{}", err))))?;
+        let mut len = bytes.len();
+        loop {
+            let mut byte = (len & 0x7F) as u8;
+            len >>= 7;
+            if len != 0 {
+                byte |= 0x80;
+            }
+            write_symbol(writer, &mut self.lengths, byte as u32 + 1)?;
+            if len == 0 {
+                break;
+            }
+        }
+        for byte in bytes {
+            write_symbol(writer, &mut self.bytes, byte as u32 + 1)?;
+        }
+        Ok(())
+    }
+
+    fn read<T, R>(&mut self, reader: &mut Reader<R>) -> Result<T, TokenReaderError> where T: serde::de::DeserializeOwned, R: Read {
+        let mut len = 0usize;
+        let mut shift = 0;
+        loop {
+            let byte = read_byte(reader, &mut self.lengths)?;
+            if shift >= std::mem::size_of::<usize>() * 8 {
+                return Err(TokenReaderError::invalid_value(&byte));
+            }
+            len |= ((byte & 0x7F) as usize) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        let mut bytes = Vec::new();
+        for _ in 0..len {
+            bytes.push(read_byte(reader, &mut self.bytes)?);
+        }
+        bincode::deserialize(&bytes)
+            .map_err(|err| TokenReaderError::invalid_value(&err))
+    }
+}
+
+/// All the models, shared between the encoder and the decoder.
+struct Models {
+    bools: Table<Option<bool>>,
+    floats: Table<Option<F64>>,
+    string_enums: Table<SharedString>,
+    property_keys: Table<Option<PropertyKey>>,
+    identifier_names: Table<Option<IdentifierName>>,
+    interface_names: Table<InterfaceName>,
+    string_literals: Table<Option<SharedString>>,
+    reg_exp_patterns: Table<Option<RegExpPattern>>,
+    reg_exp_flags: Table<Option<RegExpFlags>>,
+    literals: Literals,
+}
+impl Models {
+    fn new(options: &Options) -> Self {
+        Models {
+            bools: Table::new(options.depth),
+            floats: Table::new(options.depth),
+            string_enums: Table::new(options.depth),
+            property_keys: Table::new(options.depth),
+            identifier_names: Table::new(options.depth),
+            interface_names: Table::new(options.depth),
+            string_literals: Table::new(options.depth),
+            reg_exp_patterns: Table::new(options.depth),
+            reg_exp_flags: Table::new(options.depth),
+            literals: Literals::new(),
+        }
+    }
+}
+
+/// A small integer, as predicted from the values most recently encountered in its context.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Prediction {
+    /// The value is the `rank`-th most recently encountered value, starting from 0.
+    Recent(u8),
+
+    /// The value is the most recently encountered value plus `delta`, with `delta != 0`.
+    Delta(i8),
+
+    /// The value could not be predicted.
+    Value(u32),
+}
+
+/// The models for a kind of small integer, shared between the encoder and the decoder.
+struct IntegerModels {
+    depth: usize,
+
+    /// The predictions, by path.
+    predictions: Table<Prediction>,
+
+    /// The values most recently encountered, most recent first, by path.
+    recent: HashMap<Path, Vec<u32>>,
+
+    literals: Literals,
+}
+impl IntegerModels {
+    fn new(depth: usize) -> Self {
+        IntegerModels {
+            depth,
+            predictions: Table::new(depth),
+            recent: HashMap::new(),
+            literals: Literals::new(),
+        }
+    }
+
+    fn recent(&mut self, path: &Path) -> &mut Vec<u32> {
+        let tail = path.tail(self.depth);
+        if !self.recent.contains_key(tail) {
+            let mut key = Path::new();
+            key.extend_from_slice(tail);
+            self.recent.insert(key, Vec::with_capacity(RECENT_LEN + 1));
+        }
+        self.recent.get_mut(tail)
+            .unwrap() // We have just inserted the values.
+    }
+
+    /// Predict `value`, then remember it.
+    fn predict(&mut self, path: &Path, value: u32) -> Prediction {
+        let recent = self.recent(path);
+        let prediction = match recent.iter().position(|known| *known == value) {
+            Some(rank) => Prediction::Recent(rank as u8),
+            None => match recent.first() {
+                Some(&last) if (value as i64 - last as i64).abs() <= MAX_DELTA =>
+                    Prediction::Delta((value as i64 - last as i64) as i8),
+                _ => Prediction::Value(value)
+            }
+        };
+        Self::remember(recent, value);
+        prediction
+    }
+
+    /// Find the value matching `prediction`, then remember it.
+    fn resolve(&mut self, path: &Path, prediction: &Prediction) -> Option<u32> {
+        let recent = self.recent(path);
+        let value = match *prediction {
+            Prediction::Recent(rank) => recent.get(rank as usize)
+                .cloned(),
+            Prediction::Delta(delta) if delta != 0 => recent.first()
+                .map(|&last| last as i64 + delta as i64)
+                .filter(|value| 0 <= *value && *value <= std::u32::MAX as i64)
+                .map(|value| value as u32),
+            Prediction::Delta(_) => None,
+            Prediction::Value(value) => Some(value),
+        }?;
+        Self::remember(recent, value);
+        Some(value)
+    }
+
+    fn remember(recent: &mut Vec<u32>, value: u32) {
+        if let Some(rank) = recent.iter().position(|known| *known == value) {
+            recent.remove(rank);
+        }
+        recent.insert(0, value);
+        recent.truncate(RECENT_LEN);
+    }
+}
+
+fn write_symbol<T>(writer: &mut Writer, model: &mut Model<T>, symbol: u32) -> Result<(), TokenWriterError> where T: Eq + Hash + Clone {
+    writer.symbol_in(symbol, model.distribution())
+        .map_err(TokenWriterError::WriteError)?;
+    model.update(symbol);
+    Ok(())
+}
+
+fn read_symbol<T, R>(reader: &mut Reader<R>, model: &mut Model<T>) -> Result<u32, TokenReaderError> where T: Eq + Hash + Clone, R: Read {
+    let symbol = reader.symbol_in(model.distribution())
+        .map_err(TokenReaderError::ReadError)?;
+    if symbol as usize >= model.instances.len() {
+        return Err(TokenReaderError::invalid_value(&symbol));
+    }
+    model.update(symbol);
+    Ok(symbol)
+}
+
+fn read_byte<R>(reader: &mut Reader<R>, model: &mut Model<u8>) -> Result<u8, TokenReaderError> where R: Read {
+    let symbol = read_symbol(reader, model)?;
+    model.value(symbol)
+        .cloned()
+        .ok_or_else(|| TokenReaderError::invalid_value(&symbol))
+}
+
+/// Write a value to its model, or as a literal if it has never been encountered in this model.
+fn write_value<T>(writer: &mut Writer, model: &mut Model<T>, literals: &mut Literals, value: T) -> Result<(), TokenWriterError> where T: Eq + Hash + Clone + serde::Serialize {
+    match model.symbol(&value) {
+        Some(symbol) => write_symbol(writer, model, symbol),
+        None => {
+            write_symbol(writer, model, ESCAPE)?;
+            literals.write(writer, &value)?;
+            model.push(value);
+            Ok(())
+        }
+    }
+}
+
+/// Read a value from its model, or as a literal if it has never been encountered in this model.
+fn read_value<T, R>(reader: &mut Reader<R>, model: &mut Model<T>, literals: &mut Literals) -> Result<T, TokenReaderError> where T: Eq + Hash + Clone + std::fmt::Debug + serde::de::DeserializeOwned, R: Read {
+    let symbol = read_symbol(reader, model)?;
+    if symbol == ESCAPE {
+        let value = literals.read(reader)?;
+        if model.symbol(&value).is_some() {
+            // The encoder never escapes a known value.
+            return Err(TokenReaderError::invalid_value(&value));
+        }
+        model.push(value.clone());
+        Ok(value)
+    } else {
+        model.value(symbol)
+            .cloned()
+            .ok_or_else(|| TokenReaderError::invalid_value(&symbol))
+    }
+}
+
+/// Write a value to the table, or as a literal if it has never been encountered at this path.
+///
+/// Usage:
+/// `write_value!(self, name_of_the_table, path_in_the_ast, value_to_encode)`
+macro_rules! write_value {
+    ( $me: ident, $table: ident, $path: expr, $value: expr ) => {
+        write_value(&mut $me.writer, $me.models.$table.model($path), &mut $me.models.literals, $value)
+    }
+}
+
+/// Read a value from the table, or as a literal if it has never been encountered at this path.
+///
+/// Usage:
+/// `read_value!(self, name_of_the_table, path_in_the_ast)`
+macro_rules! read_value {
+    ( $me: ident, $table: ident, $path: expr ) => {
+        read_value(&mut $me.reader, $me.models.$table.model($path), &mut $me.models.literals)
+    }
+}
+
+/// The encoder of a small integer stream.
+struct IntegerEncoder {
+    writer: Writer,
+    models: IntegerModels,
+
+    // --- Statistics.
+
+    /// The same values, coded without prediction, to measure the gain of prediction.
+    baseline_writer: Writer,
+    baseline: Table<u32>,
+    baseline_literals: Literals,
+}
+impl IntegerEncoder {
+    fn new(options: &Options) -> Self {
+        IntegerEncoder {
+            writer: Writer::new(options.backend()),
+            models: IntegerModels::new(options.depth),
+            baseline_writer: Writer::new(options.backend()),
+            baseline: Table::new(options.depth),
+            baseline_literals: Literals::new(),
+        }
+    }
+
+    /// Start a new stream, keeping the models.
+    fn restart(self, backend: Backend) -> Self {
+        IntegerEncoder {
+            writer: Writer::new(backend),
+            baseline_writer: Writer::new(backend),
+            ..self
+        }
+    }
+
+    fn write(&mut self, value: u32, path: &Path) -> Result<(), TokenWriterError> {
+        let prediction = self.models.predict(path, value);
+        write_value(&mut self.writer, self.models.predictions.model(path), &mut self.models.literals, prediction)?;
+        write_value(&mut self.baseline_writer, self.baseline.model(path), &mut self.baseline_literals, value)
+    }
+
+    /// Flush the stream, return its bytes, and add the gain of prediction to `gain`.
+    fn done(self, gain: &mut Gain) -> Result<Vec<u8>, TokenWriterError> {
+        let data = self.writer.done()
+            .map_err(TokenWriterError::WriteError)?;
+        let baseline = self.baseline_writer.done()
+            .map_err(TokenWriterError::WriteError)?;
+        *gain += Gain {
+            before: baseline.len().into(),
+            after: data.len().into(),
+        };
+        Ok(data)
+    }
+}
+
+/// The decoder of a small integer stream.
+struct IntegerDecoder {
+    reader: Reader<Cursor<Vec<u8>>>,
+    models: IntegerModels,
+}
+impl IntegerDecoder {
+    /// Read a stream of `byte_len` bytes from `source`.
+    fn new<R: Read>(options: &Options, source: &mut R, byte_len: u32) -> Result<Self, TokenReaderError> {
+        // The length is read from the file, don't trust it for allocations.
+        let mut data = Vec::with_capacity(std::cmp::min(byte_len as usize, 1024));
+        source.by_ref()
+            .take(byte_len as u64)
+            .read_to_end(&mut data)
+            .map_err(TokenReaderError::ReadError)?;
+        if data.len() != byte_len as usize {
+            return Err(TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated small integer stream")));
+        }
+        let reader = Reader::new(options.backend(), Cursor::new(data))
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(IntegerDecoder {
+            reader,
+            models: IntegerModels::new(options.depth),
+        })
+    }
+
+    fn read(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        let prediction = read_value(&mut self.reader, self.models.predictions.model(path), &mut self.models.literals)?;
+        self.models.resolve(path, &prediction)
+            .ok_or_else(|| TokenReaderError::invalid_value(&prediction))
+    }
+}
+
+/// An adaptive entropy encoder.
+pub struct Encoder {
+    /// Bit-level manipulations.
+    writer: Writer,
+
+    models: Models,
+
+    unsigned_longs: IntegerEncoder,
+    list_lengths: IntegerEncoder,
+
+    /// The header of the file, see `header`.
+    header: Header,
+
+    /// Statistics, shared with the options.
+    gains: Rc<RefCell<ContentInfo<Gain>>>,
+}
+
+impl Encoder {
+    pub fn new(options: Options) -> Self {
+        Encoder {
+            writer: Writer::new(options.backend()),
+            models: Models::new(&options),
+            unsigned_longs: IntegerEncoder::new(&options),
+            list_lengths: IntegerEncoder::new(&options),
+            header: Header {
+                backend: options.backend(),
+                depth: options.depth(),
+                ..Header::default()
+            },
+            gains: options.gains,
+        }
+    }
+
+    /// Start a new file, in which values are coded with the models learnt from the
+    /// values written so far, which are dropped. This lets a file be coded against
+    /// another file, e.g. a new version against the previous one, see `delta`.
+    ///
+    /// Such a file may only be decoded with `Decoder::continuing`.
+    pub fn restart(self) -> Self {
+        let backend = self.header.backend;
+        Encoder {
+            writer: Writer::new(backend),
+            unsigned_longs: self.unsigned_longs.restart(backend),
+            list_lengths: self.list_lengths.restart(backend),
+            ..self
+        }
+    }
+}
+
+impl TokenWriter for Encoder {
+    type Data = Vec<u8>;
+
+    fn done(self) -> Result<Self::Data, TokenWriterError> {
+        let mut gains = self.gains.borrow_mut();
+        let unsigned_longs = self.unsigned_longs.done(&mut gains.unsigned_longs)?;
+        let list_lengths = self.list_lengths.done(&mut gains.list_lengths)?;
+        let main = self.writer.done()
+            .map_err(TokenWriterError::WriteError)?;
+
+        let mut data = self.header.write()?;
+        data.reserve(unsigned_longs.len() + list_lengths.len() + main.len() + 10);
+        data.write_varnum(unsigned_longs.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        data.write_varnum(list_lengths.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        data.extend(unsigned_longs);
+        data.extend(list_lengths);
+        data.extend(main);
+        Ok(data)
+    }
+
+    // --- Primitive values
+
+    fn bool_at(&mut self, value: Option<bool>, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, bools, path, value)
+    }
+
+    fn float_at(&mut self, value: Option<f64>, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, floats, path, value.map(F64::from))
+    }
+
+    fn unsigned_long_at(&mut self, value: u32, path: &Path) -> Result<(), TokenWriterError> {
+        self.unsigned_longs.write(value, path)
+    }
+
+    fn string_at(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, string_literals, path, value.cloned())
+    }
+
+    fn string_enum_at(&mut self, value: &SharedString, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, string_enums, path, value.clone())
+    }
+
+    fn identifier_name_at(&mut self, value: Option<&IdentifierName>, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, identifier_names, path, value.cloned())
+    }
+
+    fn property_key_at(&mut self, value: Option<&PropertyKey>, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, property_keys, path, value.cloned())
+    }
+
+    fn reg_exp_pattern_at(&mut self, value: Option<&RegExpPattern>, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, reg_exp_patterns, path, value.cloned())
+    }
+
+    fn reg_exp_flags_at(&mut self, value: Option<&RegExpFlags>, path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, reg_exp_flags, path, value.cloned())
+    }
+
+    // --- Composite stuff
+
+    fn enter_tagged_tuple_at(&mut self, _node: &Node, tag: &InterfaceName, _children: &[&FieldName], path: &Path) -> Result<(), TokenWriterError> {
+        write_value!(self, interface_names, path, tag.clone())
+    }
+
+    fn enter_list_at(&mut self, len: usize, path: &Path) -> Result<(), TokenWriterError> {
+        self.list_lengths.write(len as u32, path)
+    }
+
+    fn offset_at(&mut self, _path: &Path) -> Result<(), TokenWriterError> {
+        unimplemented!()
+    }
+}
+
+/// An adaptive entropy decoder.
+pub struct Decoder<R: Read> {
+    /// Bit-level manipulations.
+    reader: Reader<R>,
+
+    models: Models,
+
+    unsigned_longs: IntegerDecoder,
+    list_lengths: IntegerDecoder,
+}
+
+impl<R: Read> FileStructurePrinter for Decoder<R> {
+
+}
+
+impl<R: Read> Decoder<R> {
+    /// Create a decoder for a file written with `options`, except for the options
+    /// recorded in its header, see `header`.
+    pub fn new(options: Options, mut source: R) -> Result<Self, TokenReaderError> {
+        let header = Header::read(&mut source)?;
+        if header.recency.is_some() || header.fallback {
+            // Only used by the dictionary-based format.
+            return Err(TokenReaderError::BadHeader);
+        }
+        let options = Options {
+            depth: header.depth,
+            backend: header.backend,
+            ..options
+        };
+        let unsigned_longs_len = source.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let list_lengths_len = source.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let unsigned_longs = IntegerDecoder::new(&options, &mut source, unsigned_longs_len)?;
+        let list_lengths = IntegerDecoder::new(&options, &mut source, list_lengths_len)?;
+        let reader = Reader::new(options.backend(), source)
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(Decoder {
+            reader,
+            models: Models::new(&options),
+            unsigned_longs,
+            list_lengths,
+        })
+    }
+
+    /// Create a decoder for a file written by an encoder after `Encoder::restart`,
+    /// given an encoder whose models have learnt the same values, in the same order,
+    /// as the encoder of the file before its restart.
+    pub fn continuing(encoder: Encoder, source: R) -> Result<Self, TokenReaderError> {
+        let options = Options {
+            depth: encoder.header.depth,
+            backend: encoder.header.backend,
+            gains: encoder.gains,
+        };
+        let decoder = Self::new(options, source)?;
+        if decoder.models.bools.depth != encoder.models.bools.depth {
+            // The models were learnt with another path depth.
+            return Err(TokenReaderError::BadHeader);
+        }
+        let Encoder { models, unsigned_longs, list_lengths, .. } = encoder;
+        Ok(Decoder {
+            models,
+            unsigned_longs: IntegerDecoder {
+                models: unsigned_longs.models,
+                ..decoder.unsigned_longs
+            },
+            list_lengths: IntegerDecoder {
+                models: list_lengths.models,
+                ..decoder.list_lengths
+            },
+            ..decoder
+        })
+    }
+}
+
+impl<R: Read> TokenReader for Decoder<R> {
+    // ---- String types
+
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        read_value!(self, string_literals, path)
+    }
+
+    fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
+        read_value!(self, string_enums, path)
+    }
+
+    fn identifier_name_at(&mut self, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
+        read_value!(self, identifier_names, path)
+    }
+
+    fn property_key_at(&mut self, path: &Path) -> Result<Option<PropertyKey>, TokenReaderError> {
+        read_value!(self, property_keys, path)
+    }
+
+    fn reg_exp_pattern_at(&mut self, path: &Path) -> Result<Option<RegExpPattern>, TokenReaderError> {
+        read_value!(self, reg_exp_patterns, path)
+    }
+
+    fn reg_exp_flags_at(&mut self, path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        let value : Option<RegExpFlags> = read_value!(self, reg_exp_flags, path)?;
+        match value {
+            None => Ok(None),
+            Some(flags) => flags.to_bits()
+                .and_then(RegExpFlags::from_bits)
+                .map(Some)
+                .ok_or_else(|| TokenReaderError::invalid_value(&flags))
+        }
+    }
+
+    // ---- Primitive types
+
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        let value : Option<F64> = read_value!(self, floats, path)?;
+        Ok(value.map(F64::into))
+    }
+
+    fn unsigned_long_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.unsigned_longs.read(path)
+    }
+
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        read_value!(self, bools, path)
+    }
+
+    // ---- Lazy
+
+    fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        unimplemented!()
+    }
+
+    // ---- Composed types
+
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.list_lengths.read(path)
+    }
+
+    fn enter_tagged_tuple_at(&mut self, path: &Path) -> Result<(InterfaceName, Option<std::rc::Rc<Box<[FieldName]>>>), TokenReaderError> {
+        let name = read_value!(self, interface_names, path)?;
+        Ok((name, None))
+    }
+
+    fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        unimplemented!()
+    }
+}
+
+/// Command-line management.
+use clap;
+
+pub struct FormatProvider;
+impl ::FormatProvider for FormatProvider {
+    fn subcommand<'a, 'b>(&self) -> clap::App<'a, 'b> {
+        use clap::*;
+        SubCommand::with_name("entropy-adaptive")
+            .about("(EXPERIMENTAL) Encode using adaptive entropy compression. Unlike entropy, this format does not require a dictionary.")
+            .arg(Arg::with_name("path-depth")
+                .long("path-depth")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Amount of path context used to predict values. 0 = no context, 1 = parent and field, 2 = also grand parent, etc. The depth is recorded in each file, decoders don't need it.")
+            )
+            .arg(super::coder::backend_arg())
+    }
+
+    fn handle_subcommand(&self, matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
+        let options = match matches {
+            None => Options::default(),
+            Some(matches) => {
+                let depth = matches.value_of("path-depth")
+                    .unwrap() // Guaranteed by `clap`.
+                    .parse::<usize>()
+                    .unwrap(); // Guaranteed by `clap`.
+                Options::new(depth)
+                    .with_backend(super::coder::backend_of_matches(matches))
+            }
+        };
+        Ok(::Format::AdaptiveEntropy {
+            options
+        })
+    }
+}
+
+#[test]
+fn test_adaptive_roundtrip() {
+    use binjs_shared::ast::PathItem;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("Script"),
+        field: (0, FieldName::from_str("statements")),
+    }]);
+    let names = ["foo", "bar", "foo", "foo", "baz", "bar"];
+
+    let mut encoder = Encoder::new(Options::default());
+    for name in names.iter() {
+        encoder.identifier_name_at(Some(&IdentifierName::from_str(*name)), &path)
+            .expect("Could not write identifier");
+    }
+    encoder.enter_list_at(3, &path)
+        .expect("Could not write list length");
+    encoder.float_at(Some(1.5), &path)
+        .expect("Could not write float");
+    encoder.bool_at(None, &path)
+        .expect("Could not write bool");
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    let mut decoder = Decoder::new(Options::default(), std::io::Cursor::new(data))
+        .expect("Could not create decoder");
+    for name in names.iter() {
+        assert_eq!(decoder.identifier_name_at(&path).expect("Could not read identifier"),
+            Some(IdentifierName::from_str(*name)));
+    }
+    assert_eq!(decoder.enter_list_at(&path).expect("Could not read list length"), 3);
+    assert_eq!(decoder.float_at(&path).expect("Could not read float"), Some(1.5));
+    assert_eq!(decoder.bool_at(&path).expect("Could not read bool"), None);
+}
+
+#[test]
+fn test_adaptive_small_integers() {
+    use binjs_shared::ast::PathItem;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("ArrayExpression"),
+        field: (0, FieldName::from_str("elements")),
+    }]);
+    // Lengths that drift slowly, with an outlier.
+    let lengths : Vec<u32> = (0..200)
+        .map(|i| if i == 100 { 100_000 } else { i / 2 + i % 3 })
+        .collect();
+
+    let options = Options::default();
+    let mut encoder = Encoder::new(options.clone());
+    for &len in &lengths {
+        encoder.enter_list_at(len as usize, &path)
+            .expect("Could not write list length");
+        encoder.unsigned_long_at(len * 2, &path)
+            .expect("Could not write unsigned long");
+    }
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    let mut decoder = Decoder::new(options.clone(), std::io::Cursor::new(data))
+        .expect("Could not create decoder");
+    for &len in &lengths {
+        assert_eq!(decoder.enter_list_at(&path).expect("Could not read list length"), len);
+        assert_eq!(decoder.unsigned_long_at(&path).expect("Could not read unsigned long"), len * 2);
+    }
+
+    // Prediction pays off on clustered values.
+    let gains = options.statistics_for_write();
+    for gain in &[gains.list_lengths, gains.unsigned_longs] {
+        assert!(Into::<usize>::into(gain.after) < Into::<usize>::into(gain.before));
+    }
+
+    // Predictions are checked against the recent values.
+    let mut models = IntegerModels::new(DEFAULT_DEPTH);
+    assert_eq!(models.predict(&path, 5), Prediction::Value(5));
+    assert_eq!(models.predict(&path, 7), Prediction::Delta(2));
+    assert_eq!(models.predict(&path, 5), Prediction::Recent(1));
+    assert_eq!(models.resolve(&path, &Prediction::Recent(RECENT_LEN as u8)), None);
+    assert_eq!(models.resolve(&path, &Prediction::Delta(0)), None);
+    assert_eq!(models.resolve(&path, &Prediction::Delta(-6)), None);
+}
+
+#[test]
+fn test_adaptive_header() {
+    use binjs_shared::ast::PathItem;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("Script"),
+        field: (0, FieldName::from_str("statements")),
+    }]);
+
+    let mut encoder = Encoder::new(Options::default().with_backend(Backend::RANS));
+    for i in 0..20 {
+        encoder.enter_list_at(i, &path)
+            .expect("Could not write list length");
+        encoder.bool_at(Some(i % 3 == 0), &path)
+            .expect("Could not write bool");
+    }
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    // The coder is taken from the header, not from the options of the decoder.
+    let mut decoder = Decoder::new(Options::default().with_backend(Backend::Range), std::io::Cursor::new(data.clone()))
+        .expect("Could not create decoder");
+    for i in 0..20 {
+        assert_eq!(decoder.enter_list_at(&path).expect("Could not read list length"), i as u32);
+        assert_eq!(decoder.bool_at(&path).expect("Could not read bool"), Some(i % 3 == 0));
+    }
+
+    // Files that announce an unknown coder are rejected.
+    let mut bad_backend = data.clone();
+    bad_backend[1] = 0xFF;
+    assert!(Decoder::new(Options::default(), std::io::Cursor::new(bad_backend)).is_err());
+
+    // Files of the dictionary-based format are rejected.
+    let mut data = Header { fallback: true, ..Header::default() }
+        .write()
+        .expect("Could not write header");
+    data.extend_from_slice(&[0, 0]);
+    assert!(Decoder::new(Options::default(), std::io::Cursor::new(data)).is_err());
+}
+
+#[test]
+fn test_adaptive_distribution() {
+    let mut distribution = AdaptiveDistribution::default();
+    let mut instances : Vec<u32> = vec![];
+    let check = |distribution: &AdaptiveDistribution, instances: &[u32]| {
+        let mut low = 0;
+        for (symbol, &count) in instances.iter().enumerate() {
+            assert_eq!(distribution.segment(symbol), Some((low, low + count)));
+            for frequency in low..low + count {
+                assert_eq!(distribution.find(frequency), Some(symbol));
+            }
+            low += count;
+        }
+        assert_eq!(distribution.width(), low);
+        assert_eq!(distribution.find(low), None);
+        assert_eq!(distribution.segment(instances.len()), None);
+    };
+    for i in 0..100u32 {
+        distribution.push(1 + i % 3);
+        instances.push(1 + i % 3);
+        let symbol = (i as usize * 7) % instances.len();
+        distribution.increment(symbol);
+        instances[symbol] += 1;
+        check(&distribution, &instances);
+    }
+    distribution.halve();
+    for count in instances.iter_mut() {
+        *count = (*count + 1) / 2;
+    }
+    check(&distribution, &instances);
+}
+
+#[test]
+fn test_adaptive_many_values() {
+    use binjs_shared::ast::PathItem;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("Script"),
+        field: (0, FieldName::from_str("statements")),
+    }]);
+
+    // Enough distinct values in a single context to exceed `MAX_TOTAL_INSTANCES`,
+    // each encountered a few times.
+    let names : Vec<_> = (0..3 * MAX_TOTAL_INSTANCES as usize)
+        .map(|i| IdentifierName::from_string(format!("name_{}", (i * 7919) % MAX_TOTAL_INSTANCES as usize)))
+        .collect();
+
+    for backend in &[Backend::Range, Backend::RANS] {
+        let mut encoder = Encoder::new(Options::default().with_backend(*backend));
+        for name in &names {
+            encoder.identifier_name_at(Some(name), &path)
+                .expect("Could not write identifier");
+        }
+        let data = encoder.done()
+            .expect("Could not finalize encoding");
+
+        let mut decoder = Decoder::new(Options::default(), std::io::Cursor::new(data))
+            .expect("Could not create decoder");
+        for name in &names {
+            assert_eq!(decoder.identifier_name_at(&path).expect("Could not read identifier").as_ref(), Some(name));
+        }
+    }
+}
+
+#[test]
+fn test_adaptive_restart() {
+    use binjs_shared::ast::PathItem;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("Script"),
+        field: (0, FieldName::from_str("statements")),
+    }]);
+    let names : Vec<_> = (0..100)
+        .map(|i| IdentifierName::from_string(format!("name_{}", i % 10)))
+        .collect();
+    let write = |encoder: &mut Encoder| {
+        for (i, name) in names.iter().enumerate() {
+            encoder.identifier_name_at(Some(name), &path)
+                .expect("Could not write identifier");
+            encoder.enter_list_at(i % 4, &path)
+                .expect("Could not write list length");
+        }
+    };
+
+    let mut encoder = Encoder::new(Options::default());
+    write(&mut encoder);
+    let fresh = encoder.done()
+        .expect("Could not finalize encoding");
+
+    // After a restart, the values are already known to the models.
+    let mut encoder = Encoder::new(Options::default());
+    write(&mut encoder);
+    let mut encoder = encoder.restart();
+    write(&mut encoder);
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+    assert!(data.len() < fresh.len());
+
+    let mut learnt = Encoder::new(Options::default());
+    write(&mut learnt);
+    let mut decoder = Decoder::continuing(learnt, std::io::Cursor::new(data.clone()))
+        .expect("Could not create decoder");
+    for (i, name) in names.iter().enumerate() {
+        assert_eq!(decoder.identifier_name_at(&path).expect("Could not read identifier").as_ref(), Some(name));
+        assert_eq!(decoder.enter_list_at(&path).expect("Could not read list length"), (i % 4) as u32);
+    }
+
+    // Models learnt with another path depth are rejected.
+    assert!(Decoder::continuing(Encoder::new(Options::new(2)), std::io::Cursor::new(data)).is_err());
+}
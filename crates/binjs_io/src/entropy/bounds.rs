@@ -0,0 +1,101 @@
+//! Theoretical bounds on the size of the streams of the entropy format.
+//!
+//! Given the probability `p` of each symbol according to the dictionary, no coder
+//! can write a stream in fewer than `sum(-log2 p)` bits over its symbols (Shannon).
+//! Comparing the bytes actually written with this bound shows which streams lose
+//! bytes in the bit-level coder, e.g. to the precision of frequencies or to flushing,
+//! rather than to the predictions of the dictionary, which only better dictionaries
+//! may improve.
+//!
+//! Bounds are collected while writing, along with the other statistics, see
+//! `Options::entropy_bounds_for_write`.
+
+use ::io::statistics::ContentInfo;
+
+use range_encoding::CumulativeDistributionFrequency;
+
+use std;
+
+/// The information content of the symbol with index `index` in `distribution`, i.e.
+/// `-log2 p`, in bits, or `None` if `distribution` has no such symbol.
+pub fn information(index: usize, distribution: &CumulativeDistributionFrequency) -> Option<f64> {
+    let segment = distribution.at_index(index)?;
+    let probability = (segment.next - segment.low) as f64 / distribution.width() as f64;
+    Some(-probability.log2())
+}
+
+/// The bytes of a stream, compared with its theoretical bound.
+#[derive(Clone, Copy, Debug, Default, Add, AddAssign)]
+pub struct StreamBound {
+    /// The number of symbols written.
+    pub symbols: usize,
+
+    /// The Shannon-optimal size of the stream, in bits: the sum of `-log2 p` over
+    /// its symbols.
+    pub bound_bits: f64,
+
+    /// The number of bytes of the stream, as coded.
+    pub actual_bytes: usize,
+}
+impl StreamBound {
+    /// The Shannon-optimal size of the stream, in bytes.
+    pub fn bound_bytes(&self) -> f64 {
+        self.bound_bits / 8.
+    }
+
+    /// The number of bytes spent by the coder beyond the bound. May be slightly
+    /// negative for small streams, as frequencies are rounded.
+    pub fn gap_bytes(&self) -> f64 {
+        self.actual_bytes as f64 - self.bound_bytes()
+    }
+
+    /// The gap, as a share of the bound.
+    pub fn overhead(&self) -> f64 {
+        if self.bound_bits == 0. {
+            return 0.;
+        }
+        self.gap_bytes() / self.bound_bytes()
+    }
+}
+
+impl std::fmt::Display for ContentInfo<StreamBound> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let mut total = StreamBound::default();
+        write!(formatter, "Entropy bounds:\n")?;
+        for (name, bound) in self.iter() {
+            total += *bound;
+            if bound.symbols == 0 {
+                continue;
+            }
+            write!(formatter, "    {name}: symbols {symbols}, bytes {actual} for a bound of {bound:.0} (gap {gap:.0} bytes = {overhead:.2}%)\n",
+                name = name,
+                symbols = bound.symbols,
+                actual = bound.actual_bytes,
+                bound = bound.bound_bytes(),
+                gap = bound.gap_bytes(),
+                overhead = 100. * bound.overhead())?;
+        }
+        write!(formatter, "Total: bytes {actual} for a bound of {bound:.0} (gap {gap:.0} bytes = {overhead:.2}%)",
+            actual = total.actual_bytes,
+            bound = total.bound_bytes(),
+            gap = total.gap_bytes(),
+            overhead = 100. * total.overhead())
+    }
+}
+
+#[test]
+fn test_information() {
+    let distribution = CumulativeDistributionFrequency::new(vec![1, 1, 2]);
+    assert_eq!(information(0, &distribution), Some(2.));
+    assert_eq!(information(2, &distribution), Some(1.));
+    assert_eq!(information(3, &distribution), None);
+
+    let bound = StreamBound {
+        symbols: 16,
+        bound_bits: 24.,
+        actual_bytes: 4,
+    };
+    assert_eq!(bound.bound_bytes(), 3.);
+    assert_eq!(bound.gap_bytes(), 1.);
+    assert!((bound.overhead() - 1. / 3.).abs() < 1e-9);
+}
@@ -0,0 +1,508 @@
+//! Bit-level entropy coders.
+//!
+//! The entropy formats decide which symbols to write and with which probability
+//! distribution. The actual bit-level coding is delegated to a backend, so that
+//! backends with different performance characteristics may be compared:
+//!
+//! - `Backend::Range`, the range coder used by Opus;
+//! - `Backend::RANS`, a range variant of asymmetric numeral systems, which
+//!     generally decodes faster, but needs to buffer all symbols while encoding,
+//!     as they are encoded in reverse order.
+
+use binjs_core::rans::Distribution;
+use range_encoding::CumulativeDistributionFrequency;
+
+use clap;
+use rand::Rng;
+use rand::distributions::{ Distribution as RandomDistribution, Standard };
+
+use std;
+use std::io::Read;
+
+const INITIAL_BUFFER_SIZE_BYTES : usize = 32768;
+
+/// The available bit-level coders.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Range,
+    RANS,
+}
+impl Backend {
+    /// The names of the backends, as used on the command-line.
+    pub fn names() -> [&'static str; 2] {
+        ["range", "rans"]
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "range" => Some(Backend::Range),
+            "rans" => Some(Backend::RANS),
+            _ => None
+        }
+    }
+
+    /// The name of the backend, as used on the command-line.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Backend::Range => "range",
+            Backend::RANS => "rans",
+        }
+    }
+
+    /// The byte identifying the backend in the header of files, see `header`.
+    pub fn to_byte(&self) -> u8 {
+        match *self {
+            Backend::Range => 0,
+            Backend::RANS => 1,
+        }
+    }
+
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Backend::Range),
+            1 => Some(Backend::RANS),
+            _ => None
+        }
+    }
+}
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Range
+    }
+}
+impl RandomDistribution<Backend> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Backend {
+        if rng.gen() {
+            Backend::Range
+        } else {
+            Backend::RANS
+        }
+    }
+}
+
+/// The command-line argument used by entropy formats to select a `Backend`.
+pub fn backend_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("coder")
+        .long("coder")
+        .takes_value(true)
+        .possible_values(&Backend::names())
+        .default_value("range")
+        .help("Bit-level coder. The coder is recorded in each file, decoders don't need it.")
+}
+
+/// The `Backend` selected by `backend_arg`.
+pub fn backend_of_matches(matches: &clap::ArgMatches) -> Backend {
+    matches.value_of("coder")
+        .and_then(Backend::from_name)
+        .unwrap() // Guaranteed by `clap`.
+}
+
+/// Writing symbols, given their probability distribution.
+pub trait SymbolWriter: Sized {
+    /// Write the symbol with index `index` in `distribution`.
+    fn symbol(&mut self, index: u32, distribution: &mut CumulativeDistributionFrequency) -> Result<(), std::io::Error> {
+        self.symbol_in(index, &Frequencies(distribution))
+    }
+
+    /// As `symbol`, with any `Distribution`, e.g. one that is updated after each symbol.
+    fn symbol_in<D: Distribution + ?Sized>(&mut self, index: u32, distribution: &D) -> Result<(), std::io::Error>;
+
+    /// Flush all symbols, return the bytes written.
+    fn done(self) -> Result<Vec<u8>, std::io::Error>;
+}
+
+/// Reading symbols, given their probability distribution.
+pub trait SymbolReader {
+    /// Read a symbol, return its index in `distribution`.
+    fn symbol(&mut self, distribution: &mut CumulativeDistributionFrequency) -> Result<u32, std::io::Error> {
+        self.symbol_in(&Frequencies(distribution))
+    }
+
+    /// As `symbol`, with any `Distribution`, e.g. one that is updated after each symbol.
+    fn symbol_in<D: Distribution + ?Sized>(&mut self, distribution: &D) -> Result<u32, std::io::Error>;
+}
+
+/// A `CumulativeDistributionFrequency`, seen as a `binjs_core::rans::Distribution`.
+struct Frequencies<'a>(&'a CumulativeDistributionFrequency);
+impl<'a> Distribution for Frequencies<'a> {
+    fn width(&self) -> u32 {
+        self.0.width()
+    }
+    fn find(&self, frequency: u32) -> Option<usize> {
+        self.0.find(frequency)
+            .map(|segment| segment.index)
+    }
+    fn segment(&self, index: usize) -> Option<(u32, u32)> {
+        self.0.at_index(index)
+            .map(|segment| (segment.low, segment.next))
+    }
+}
+
+/// A `SymbolWriter` for any backend.
+pub enum Writer {
+    Range(range::Writer),
+    RANS(rans::Writer),
+}
+impl Writer {
+    pub fn new(backend: Backend) -> Self {
+        match backend {
+            Backend::Range => Writer::Range(range::Writer::new()),
+            Backend::RANS => Writer::RANS(rans::Writer::new()),
+        }
+    }
+}
+impl SymbolWriter for Writer {
+    fn symbol_in<D: Distribution + ?Sized>(&mut self, index: u32, distribution: &D) -> Result<(), std::io::Error> {
+        match *self {
+            Writer::Range(ref mut writer) => writer.symbol_in(index, distribution),
+            Writer::RANS(ref mut writer) => writer.symbol_in(index, distribution),
+        }
+    }
+    fn done(self) -> Result<Vec<u8>, std::io::Error> {
+        match self {
+            Writer::Range(writer) => writer.done(),
+            Writer::RANS(writer) => writer.done(),
+        }
+    }
+}
+
+/// A `SymbolReader` for any backend.
+pub enum Reader<R: Read> {
+    Range(range::Reader<R>),
+    RANS(rans::Reader<R>),
+}
+impl<R: Read> Reader<R> {
+    pub fn new(backend: Backend, source: R) -> Result<Self, std::io::Error> {
+        match backend {
+            Backend::Range => Ok(Reader::Range(range::Reader::new(source)?)),
+            Backend::RANS => Ok(Reader::RANS(rans::Reader::new(source)?)),
+        }
+    }
+}
+impl<R: Read> SymbolReader for Reader<R> {
+    fn symbol_in<D: Distribution + ?Sized>(&mut self, distribution: &D) -> Result<u32, std::io::Error> {
+        match *self {
+            Reader::Range(ref mut reader) => reader.symbol_in(distribution),
+            Reader::RANS(ref mut reader) => reader.symbol_in(distribution),
+        }
+    }
+}
+
+/// The range coder of Opus, with 8 bits output symbols and a 32 bits state.
+///
+/// The encoder is a port of `ec_enc` in `celt/entenc.c`, the decoder is implemented in
+/// `binjs_core::range`, so that it may be used without `std`. Unlike `range_encoding::opus`,
+/// with which they are compatible, they accept any `Distribution`.
+pub mod range {
+    use super::{ SymbolReader, SymbolWriter, INITIAL_BUFFER_SIZE_BYTES };
+
+    use bytes::source::ReadSource;
+
+    use binjs_core::range::Decoder;
+    use binjs_core::rans::Distribution;
+
+    use std;
+    use std::io::Read;
+
+    /// The number of bits output at a time.
+    const SYM_BITS : u32 = 8;
+
+    /// The maximal value of a symbol.
+    const SYM_MAX : u32 = (1 << SYM_BITS) - 1;
+
+    /// The number of bits of the state.
+    const CODE_BITS : u32 = 32;
+
+    const CODE_TOP : u32 = 1 << (CODE_BITS - 1);
+
+    /// The lower bound of the normalized range `(CODE_BOT, CODE_TOP]`.
+    const CODE_BOT : u32 = CODE_TOP >> SYM_BITS;
+
+    /// The shift extracting the next symbol from the state.
+    const CODE_SHIFT : u32 = CODE_BITS - SYM_BITS - 1;
+
+    /// A range encoder.
+    pub struct Writer {
+        output: Vec<u8>,
+
+        /// The size of the current range.
+        range: u32,
+
+        /// The low end of the current range.
+        value: u32,
+
+        /// The last symbol, not written yet as it may still receive a carry.
+        remainder: Option<u32>,
+
+        /// The number of `SYM_MAX` symbols following `remainder`, which may also
+        /// receive a carry.
+        extra: usize,
+    }
+    impl Writer {
+        pub fn new() -> Self {
+            Writer {
+                output: Vec::with_capacity(INITIAL_BUFFER_SIZE_BYTES),
+                range: CODE_TOP,
+                value: 0,
+                remainder: None,
+                extra: 0,
+            }
+        }
+
+        /// Output symbol `symbol`, with a carry in bit `SYM_BITS`.
+        fn carry_out(&mut self, symbol: u32) {
+            if symbol == SYM_MAX {
+                // A carry would propagate through this symbol, wait.
+                self.extra += 1;
+                return;
+            }
+            let carry = symbol >> SYM_BITS;
+            if let Some(remainder) = self.remainder {
+                self.output.push((remainder + carry) as u8);
+            }
+            for _ in 0..self.extra {
+                self.output.push(((SYM_MAX + carry) & SYM_MAX) as u8);
+            }
+            self.extra = 0;
+            self.remainder = Some(symbol & SYM_MAX);
+        }
+
+        /// Output symbols until the range is larger than `CODE_BOT`.
+        fn normalize(&mut self) {
+            while self.range <= CODE_BOT {
+                let symbol = self.value >> CODE_SHIFT;
+                self.carry_out(symbol);
+                self.value = (self.value << SYM_BITS) & (CODE_TOP - 1);
+                self.range <<= SYM_BITS;
+            }
+        }
+    }
+    impl SymbolWriter for Writer {
+        fn symbol_in<D: Distribution + ?Sized>(&mut self, index: u32, distribution: &D) -> Result<(), std::io::Error> {
+            let width = distribution.width();
+            let (low, next) = distribution.segment(index as usize)
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid symbol index"))?;
+            if next == low {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Cannot encode a symbol with probability 0"));
+            }
+            let scale = self.range / width;
+            if scale == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Distribution too wide for the range coder"));
+            }
+            if low > 0 {
+                self.value += self.range - scale * (width - low);
+                self.range = scale * (next - low);
+            } else {
+                self.range -= scale * (width - next);
+            }
+            self.normalize();
+            Ok(())
+        }
+
+        fn done(mut self) -> Result<Vec<u8>, std::io::Error> {
+            // Output the fewest bits that identify a value in the final range, the
+            // decoder reads the missing bits as 0.
+            let mut bits = CODE_BITS - (CODE_BITS - self.range.leading_zeros());
+            let mut mask = (CODE_TOP - 1) >> bits;
+            let mut end = (self.value + mask) & !mask;
+            if (end | mask) >= self.value + self.range {
+                bits += 1;
+                mask >>= 1;
+                end = (self.value + mask) & !mask;
+            }
+            let mut bits = bits as i32;
+            while bits > 0 {
+                self.carry_out(end >> CODE_SHIFT);
+                end = (end << SYM_BITS) & (CODE_TOP - 1);
+                bits -= SYM_BITS as i32;
+            }
+            if self.remainder.is_some() || self.extra > 0 {
+                self.carry_out(0);
+            }
+            Ok(self.output)
+        }
+    }
+
+    /// A range decoder.
+    pub struct Reader<R: Read> {
+        source: R,
+        decoder: Decoder,
+    }
+    impl<R: Read> Reader<R> {
+        pub fn new(mut source: R) -> Result<Self, std::io::Error> {
+            let decoder = {
+                let mut bytes = ReadSource::new(&mut source);
+                let result = Decoder::new(&mut bytes);
+                bytes.finish(result)?
+            };
+            Ok(Reader {
+                source,
+                decoder,
+            })
+        }
+    }
+    impl<R: Read> SymbolReader for Reader<R> {
+        fn symbol_in<D: Distribution + ?Sized>(&mut self, distribution: &D) -> Result<u32, std::io::Error> {
+            let mut bytes = ReadSource::new(&mut self.source);
+            let result = self.decoder.symbol(&mut bytes, distribution);
+            bytes.finish(result)
+        }
+    }
+}
+
+/// An rANS coder, with a 64 bits state and 32 bits output words.
+///
+/// The decoder is implemented in `binjs_core::rans`, which documents the representation.
+pub mod rans {
+    use super::{ SymbolReader, SymbolWriter };
+
+    use bytes::source::{ io_error, ReadSource };
+
+    use binjs_core::rans::{ Decoder, Distribution, LOWER_BOUND, SCALE_BITS };
+
+    use std;
+    use std::io::Read;
+
+    /// Write a little-endian 32 bits word.
+    fn write_word(out: &mut Vec<u8>, word: u32) {
+        for i in 0..4 {
+            out.push((word >> (8 * i)) as u8);
+        }
+    }
+
+    /// The scaled `(start, frequency)` of symbol `index`.
+    fn scaled_segment<D: Distribution + ?Sized>(index: usize, distribution: &D) -> Result<(u64, u64), std::io::Error> {
+        ::binjs_core::rans::scaled_segment(index, distribution)
+            .map_err(io_error)
+    }
+
+    /// An rANS encoder.
+    ///
+    /// As rANS decodes symbols in the reverse order of encoding, symbols
+    /// are buffered and only encoded once we are `done`.
+    pub struct Writer {
+        /// The scaled `(start, frequency)` of all symbols written so far.
+        symbols: Vec<(u64, u64)>,
+    }
+    impl Writer {
+        pub fn new() -> Self {
+            Writer {
+                symbols: Vec::new(),
+            }
+        }
+    }
+    impl SymbolWriter for Writer {
+        fn symbol_in<D: Distribution + ?Sized>(&mut self, index: u32, distribution: &D) -> Result<(), std::io::Error> {
+            let (start, frequency) = scaled_segment(index as usize, distribution)?;
+            if frequency == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Cannot encode a symbol with probability 0"));
+            }
+            self.symbols.push((start, frequency));
+            Ok(())
+        }
+
+        fn done(self) -> Result<Vec<u8>, std::io::Error> {
+            let mut state = LOWER_BOUND;
+            let mut words = Vec::new();
+            for &(start, frequency) in self.symbols.iter().rev() {
+                // Renormalize, so that the state remains in the interval once the symbol is encoded.
+                let max = ((LOWER_BOUND >> SCALE_BITS) << 32) * frequency;
+                while state >= max {
+                    words.push(state as u32);
+                    state >>= 32;
+                }
+                state = ((state / frequency) << SCALE_BITS) + (state % frequency) + start;
+            }
+            // The decoder reads the final state first, then the words in reverse order.
+            let mut result = Vec::with_capacity(8 + 4 * words.len());
+            write_word(&mut result, state as u32);
+            write_word(&mut result, (state >> 32) as u32);
+            for word in words.iter().rev() {
+                write_word(&mut result, *word);
+            }
+            Ok(result)
+        }
+    }
+
+    /// An rANS decoder.
+    pub struct Reader<R: Read> {
+        source: R,
+        decoder: Decoder,
+    }
+    impl<R: Read> Reader<R> {
+        pub fn new(mut source: R) -> Result<Self, std::io::Error> {
+            let decoder = {
+                let mut bytes = ReadSource::new(&mut source);
+                let result = Decoder::new(&mut bytes);
+                bytes.finish(result)?
+            };
+            Ok(Reader {
+                source,
+                decoder,
+            })
+        }
+    }
+    impl<R: Read> SymbolReader for Reader<R> {
+        fn symbol_in<D: Distribution + ?Sized>(&mut self, distribution: &D) -> Result<u32, std::io::Error> {
+            let mut bytes = ReadSource::new(&mut self.source);
+            let result = self.decoder.symbol(&mut bytes, distribution);
+            bytes.finish(result)
+        }
+    }
+}
+
+#[test]
+fn test_backends() {
+    let mut distributions = [
+        CumulativeDistributionFrequency::new(vec![1, 1]),
+        CumulativeDistributionFrequency::new(vec![100, 1, 20, 3]),
+        CumulativeDistributionFrequency::new(vec![7, 1_000_000, 1]),
+    ];
+    let symbols : Vec<_> = (0..1000)
+        .map(|i| {
+            let distribution = i % distributions.len();
+            let index = (i * 7) % distributions[distribution].len();
+            (distribution, index as u32)
+        })
+        .collect();
+
+    for backend in &[Backend::Range, Backend::RANS] {
+        let mut writer = Writer::new(*backend);
+        for &(distribution, index) in &symbols {
+            writer.symbol(index, &mut distributions[distribution])
+                .expect("Could not write symbol");
+        }
+        let data = writer.done()
+            .expect("Could not flush symbols");
+
+        let mut reader = Reader::new(*backend, std::io::Cursor::new(data))
+            .expect("Could not create reader");
+        for &(distribution, index) in &symbols {
+            assert_eq!(reader.symbol(&mut distributions[distribution]).expect("Could not read symbol"), index);
+        }
+    }
+}
+
+#[test]
+fn test_range_opus_compatibility() {
+    use range_encoding::opus;
+
+    let mut distribution = CumulativeDistributionFrequency::new(vec![100, 1, 20, 3]);
+    let symbols : Vec<u32> = (0..1000)
+        .map(|i| (i * 7) % 4)
+        .collect();
+
+    // Files written with `range_encoding` may be decoded.
+    let mut writer = opus::Writer::new(Vec::new());
+    for &index in &symbols {
+        writer.symbol(index as usize, &mut distribution)
+            .expect("Could not write symbol");
+    }
+    let data = writer.done()
+        .expect("Could not flush symbols");
+    let mut reader = range::Reader::new(std::io::Cursor::new(data))
+        .expect("Could not create reader");
+    for &index in &symbols {
+        assert_eq!(reader.symbol(&mut distribution).expect("Could not read symbol"), index);
+    }
+}
@@ -0,0 +1,664 @@
+//! Deltas between two versions of a file, so that a client which already has the
+//! previous version, the *base*, only needs to download what has changed.
+//!
+//! A delta is computed from the tokens of both versions, as written by the serializer
+//! of the AST, see `TokenRecorder`:
+//!
+//! - the tokens of the new version are matched against the tokens of the base, so
+//!   that the new version is described as a sequence of operations, each of which
+//!   either copies a range of tokens of the base, wherever it appears in the base,
+//!   e.g. if a function has moved, or inserts new tokens;
+//! - the inserted tokens are coded with the adaptive entropy coder, see `adaptive`,
+//!   whose models have first learnt the tokens of the base. The delta therefore
+//!   does not need to teach the models the values that already appear in the base,
+//!   only the values that are new.
+//!
+//! The delta is formatted as:
+//!
+//! - the SHA-256 hash of the tokens of the base, so that the delta is not applied
+//!   to another base;
+//! - the byte length of the operations (`varnum`);
+//! - the operations, each of which is either:
+//!   - `len << 1 | 1` (`varnum`), to insert the next `len` tokens;
+//!   - `len << 1` (`varnum`), followed by the zigzag encoding of the distance from
+//!     the end of the previous copy to the start of the range (`varnum`), to copy
+//!     `len` tokens of the base;
+//! - the inserted tokens, coded after `adaptive::Encoder::restart`.
+
+use super::adaptive::{ self, Decoder, Encoder };
+use super::header::Header;
+
+use ::{ TokenReaderError, TokenWriterError };
+use ::io::{ FileStructurePrinter, Path, TokenReader, TokenWriter };
+use bytes::varnum::{ ReadVarNum, WriteVarNum };
+
+use binjs_core::float::{ unzigzag, zigzag };
+use binjs_shared::{ F64, FieldName, IdentifierName, InterfaceName, Node, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
+
+use std;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::io::Cursor;
+use std::rc::Rc;
+
+use bincode;
+use sha2::{ Digest, Sha256 };
+
+/// The minimal number of tokens copied at once. Shorter matches are inserted, as
+/// a copy costs a few bytes.
+const MIN_COPY_LEN : usize = 4;
+
+/// The maximal number of ranges of the base that are tried for each sequence of
+/// `MIN_COPY_LEN` tokens.
+const MAX_CANDIDATES : usize = 16;
+
+/// The byte length of the hash of the base.
+const HASH_LEN : usize = 32;
+
+/// A value written by a serializer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub enum TokenValue {
+    Bool(Option<bool>),
+    Float(Option<F64>),
+    UnsignedLong(u32),
+    String(Option<SharedString>),
+    StringEnum(SharedString),
+    IdentifierName(Option<IdentifierName>),
+    PropertyKey(Option<PropertyKey>),
+    RegExpPattern(Option<RegExpPattern>),
+    RegExpFlags(Option<RegExpFlags>),
+
+    /// The tag of a tagged tuple.
+    Interface(InterfaceName),
+
+    /// The length of a list.
+    List(u32),
+}
+
+/// A token, as recorded by `TokenRecorder`.
+#[derive(Clone)]
+pub struct RecordedToken {
+    pub value: TokenValue,
+
+    /// The path at which the token was written.
+    pub path: Path,
+}
+impl RecordedToken {
+    /// Write this token again.
+    fn write_to<W: TokenWriter>(&self, writer: &mut W) -> Result<(), TokenWriterError> {
+        let path = &self.path;
+        match self.value {
+            TokenValue::Bool(value) => writer.bool_at(value, path),
+            TokenValue::Float(value) => writer.float_at(value.map(F64::into), path),
+            TokenValue::UnsignedLong(value) => writer.unsigned_long_at(value, path),
+            TokenValue::String(ref value) => writer.string_at(value.as_ref(), path),
+            TokenValue::StringEnum(ref value) => writer.string_enum_at(value, path),
+            TokenValue::IdentifierName(ref value) => writer.identifier_name_at(value.as_ref(), path),
+            TokenValue::PropertyKey(ref value) => writer.property_key_at(value.as_ref(), path),
+            TokenValue::RegExpPattern(ref value) => writer.reg_exp_pattern_at(value.as_ref(), path),
+            TokenValue::RegExpFlags(ref value) => writer.reg_exp_flags_at(value.as_ref(), path),
+            TokenValue::Interface(ref tag) => writer.enter_tagged_tuple_at(&Recorded, tag, &[], path),
+            TokenValue::List(len) => writer.enter_list_at(len as usize, path),
+        }
+    }
+}
+
+/// The node of the tagged tuples written again by `RecordedToken::write_to`, as the
+/// nodes themselves are not recorded.
+struct Recorded;
+impl Node for Recorded {
+    fn name(&self) -> &'static str {
+        "Recorded"
+    }
+}
+
+/// A `TokenWriter` which records the tokens written, e.g. by the serializer of an AST.
+pub struct TokenRecorder<'a> {
+    tokens: &'a mut Vec<RecordedToken>,
+}
+impl<'a> TokenRecorder<'a> {
+    /// Record tokens at the end of `tokens`.
+    pub fn new(tokens: &'a mut Vec<RecordedToken>) -> Self {
+        TokenRecorder {
+            tokens
+        }
+    }
+
+    fn record(&mut self, value: TokenValue, path: &Path) -> Result<(), TokenWriterError> {
+        self.tokens.push(RecordedToken {
+            value,
+            path: path.clone(),
+        });
+        Ok(())
+    }
+}
+
+impl<'a> TokenWriter for TokenRecorder<'a> {
+    type Data = [u8;0]; // Placeholder
+
+    fn done(self) -> Result<Self::Data, TokenWriterError> {
+        Ok([])
+    }
+
+    fn bool_at(&mut self, value: Option<bool>, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::Bool(value), path)
+    }
+
+    fn float_at(&mut self, value: Option<f64>, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::Float(value.map(F64::from)), path)
+    }
+
+    fn unsigned_long_at(&mut self, value: u32, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::UnsignedLong(value), path)
+    }
+
+    fn string_at(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::String(value.cloned()), path)
+    }
+
+    fn string_enum_at(&mut self, value: &SharedString, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::StringEnum(value.clone()), path)
+    }
+
+    fn identifier_name_at(&mut self, value: Option<&IdentifierName>, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::IdentifierName(value.cloned()), path)
+    }
+
+    fn property_key_at(&mut self, value: Option<&PropertyKey>, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::PropertyKey(value.cloned()), path)
+    }
+
+    fn reg_exp_pattern_at(&mut self, value: Option<&RegExpPattern>, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::RegExpPattern(value.cloned()), path)
+    }
+
+    fn reg_exp_flags_at(&mut self, value: Option<&RegExpFlags>, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::RegExpFlags(value.cloned()), path)
+    }
+
+    fn enter_tagged_tuple_at(&mut self, _node: &Node, tag: &InterfaceName, _children: &[&FieldName], path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::Interface(tag.clone()), path)
+    }
+
+    fn enter_list_at(&mut self, len: usize, path: &Path) -> Result<(), TokenWriterError> {
+        self.record(TokenValue::List(len as u32), path)
+    }
+
+    fn offset_at(&mut self, _path: &Path) -> Result<(), TokenWriterError> {
+        // Offsets depend on the layout of a file, they are not tokens.
+        Ok(())
+    }
+}
+
+/// An operation of a delta.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    /// Copy the `len` tokens of the base starting at `start`.
+    Copy { start: usize, len: usize },
+
+    /// Insert the next `len` tokens coded in the delta.
+    Insert { len: usize },
+}
+
+/// Hash the values of a sequence of `MIN_COPY_LEN` tokens.
+fn hash_window(tokens: &[RecordedToken]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for token in tokens {
+        token.value.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Describe `new` as copies of ranges of `base` and insertions.
+///
+/// Tokens are matched by value, regardless of their path, so that code which moves
+/// to another depth in the AST is copied rather than inserted.
+fn operations(base: &[RecordedToken], new: &[RecordedToken]) -> Vec<Operation> {
+    let mut candidates : HashMap<u64, Vec<usize>> = HashMap::new();
+    if base.len() >= MIN_COPY_LEN {
+        for start in 0..base.len() - MIN_COPY_LEN + 1 {
+            let positions = candidates.entry(hash_window(&base[start..start + MIN_COPY_LEN]))
+                .or_insert_with(Vec::new);
+            if positions.len() < MAX_CANDIDATES {
+                positions.push(start);
+            }
+        }
+    }
+    let match_len = |start: usize, position: usize| {
+        if start >= base.len() {
+            return 0;
+        }
+        base[start..].iter()
+            .zip(&new[position..])
+            .take_while(|&(a, b)| a.value == b.value)
+            .count()
+    };
+
+    let mut result = vec![];
+    let mut position = 0;
+    let mut pending = 0; // The number of tokens to insert before `position`.
+    let mut expected = 0; // The end of the previous copy.
+    while position < new.len() {
+        // Continuing the previous copy is cheapest, possibly skipping as many tokens
+        // of the base as have been inserted since, if they have been replaced.
+        let mut best = (expected, match_len(expected, position));
+        if pending > 0 {
+            let len = match_len(expected + pending, position);
+            if len > best.1 {
+                best = (expected + pending, len);
+            }
+        }
+        if best.1 < MIN_COPY_LEN && position + MIN_COPY_LEN <= new.len() {
+            if let Some(positions) = candidates.get(&hash_window(&new[position..position + MIN_COPY_LEN])) {
+                for &start in positions {
+                    let len = match_len(start, position);
+                    if len > best.1 {
+                        best = (start, len);
+                    }
+                }
+            }
+        }
+
+        let (start, len) = best;
+        if len >= MIN_COPY_LEN {
+            if pending > 0 {
+                result.push(Operation::Insert { len: pending });
+                pending = 0;
+            }
+            result.push(Operation::Copy { start, len });
+            expected = start + len;
+            position += len;
+        } else {
+            pending += 1;
+            position += 1;
+        }
+    }
+    if pending > 0 {
+        result.push(Operation::Insert { len: pending });
+    }
+    result
+}
+
+fn write_operations(operations: &[Operation]) -> Result<Vec<u8>, std::io::Error> {
+    let mut data = vec![];
+    let mut expected = 0;
+    for operation in operations {
+        match *operation {
+            Operation::Insert { len } => {
+                data.write_varnum(((len as u32) << 1) | 1)?;
+            }
+            Operation::Copy { start, len } => {
+                data.write_varnum((len as u32) << 1)?;
+                data.write_varnum(zigzag(start as i32 - expected as i32))?;
+                expected = start + len;
+            }
+        }
+    }
+    Ok(data)
+}
+
+/// Read operations, checking that they only copy ranges of a base of `base_len` tokens.
+fn read_operations(data: &[u8], base_len: usize) -> Result<Vec<Operation>, TokenReaderError> {
+    let mut result = vec![];
+    let mut source = Cursor::new(data);
+    let mut expected = 0;
+    while (source.position() as usize) < data.len() {
+        let header = source.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let len = (header >> 1) as usize;
+        if len == 0 {
+            return Err(TokenReaderError::invalid_value(&header));
+        }
+        if header & 1 == 1 {
+            result.push(Operation::Insert { len });
+            continue;
+        }
+        let distance = unzigzag(source.read_varnum()
+            .map_err(TokenReaderError::ReadError)?);
+        let start = expected as i64 + distance as i64;
+        if start < 0 || start as usize + len > base_len {
+            return Err(TokenReaderError::invalid_value(&start));
+        }
+        let start = start as usize;
+        result.push(Operation::Copy { start, len });
+        expected = start + len;
+    }
+    Ok(result)
+}
+
+/// Hash the values of `tokens`.
+fn hash_base(tokens: &[RecordedToken]) -> Result<[u8; HASH_LEN], bincode::Error> {
+    let mut bytes = vec![];
+    for token in tokens {
+        bincode::serialize_into(&mut bytes, &token.value)?;
+    }
+    let mut hash = [0; HASH_LEN];
+    hash.copy_from_slice(&Sha256::digest(&bytes));
+    Ok(hash)
+}
+
+/// An adaptive encoder whose models have learnt `base`.
+fn learn(options: adaptive::Options, base: &[RecordedToken]) -> Result<Encoder, TokenWriterError> {
+    let mut encoder = Encoder::new(options);
+    for token in base {
+        token.write_to(&mut encoder)?;
+    }
+    Ok(encoder)
+}
+
+/// Compute deltas between two versions of a file.
+///
+/// Deltas are applied with `Patcher`.
+pub struct Delta {
+    options: adaptive::Options,
+}
+impl Delta {
+    /// Code the inserted tokens with the adaptive entropy coder, configured by `options`.
+    pub fn new(options: adaptive::Options) -> Self {
+        Delta {
+            options
+        }
+    }
+
+    /// Compute the delta between the tokens of `base` and those of `new`.
+    pub fn diff(&self, base: &[RecordedToken], new: &[RecordedToken]) -> Result<Vec<u8>, TokenWriterError> {
+        let operations = operations(base, new);
+
+        let mut encoder = learn(self.options.clone(), base)?
+            .restart();
+        let mut position = 0;
+        for operation in &operations {
+            match *operation {
+                Operation::Copy { len, .. } => {
+                    position += len;
+                }
+                Operation::Insert { len } => {
+                    for token in &new[position..position + len] {
+                        token.write_to(&mut encoder)?;
+                    }
+                    position += len;
+                }
+            }
+        }
+        let inserted = encoder.done()?;
+
+        let hash = hash_base(base)
+            .map_err(|err| TokenWriterError::WriteError(std::io::Error::new(std::io::ErrorKind::InvalidData, err)))?;
+        let operations = write_operations(&operations)
+            .map_err(TokenWriterError::WriteError)?;
+        let mut data = Vec::with_capacity(HASH_LEN + operations.len() + inserted.len() + 5);
+        data.extend_from_slice(&hash);
+        data.write_varnum(operations.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        data.extend_from_slice(&operations);
+        data.extend_from_slice(&inserted);
+        Ok(data)
+    }
+}
+
+/// A `TokenReader` reading the tokens of the new version of a file, from the base
+/// and a delta computed by `Delta::diff`.
+pub struct Patcher<'a> {
+    base: &'a [RecordedToken],
+
+    /// The operations not started yet.
+    operations: std::vec::IntoIter<Operation>,
+
+    /// The remainder of the current operation, if any.
+    current: Option<Operation>,
+
+    /// The inserted tokens.
+    inserted: Decoder<Cursor<&'a [u8]>>,
+}
+impl<'a> Patcher<'a> {
+    /// Start applying `delta` to `base`.
+    ///
+    /// Fails with `TokenReaderError::BadChecksum` if `delta` was computed against
+    /// another base.
+    pub fn new(base: &'a [RecordedToken], delta: &'a [u8]) -> Result<Self, TokenReaderError> {
+        let hash = hash_base(base)
+            .map_err(|err| TokenReaderError::invalid_value(&err))?;
+        if delta.len() < HASH_LEN || delta[..HASH_LEN] != hash[..] {
+            return Err(TokenReaderError::BadChecksum("Delta was computed against another base".to_string()));
+        }
+
+        let mut source = Cursor::new(&delta[HASH_LEN..]);
+        let byte_len = source.read_varnum()
+            .map_err(TokenReaderError::ReadError)? as usize;
+        let start = HASH_LEN + source.position() as usize;
+        if start + byte_len > delta.len() {
+            return Err(TokenReaderError::invalid_value(&byte_len));
+        }
+        let operations = read_operations(&delta[start..start + byte_len], base.len())?;
+
+        // The models of the inserted tokens must learn the base with the same options.
+        let inserted = &delta[start + byte_len..];
+        let header = Header::read(&mut Cursor::new(inserted))?;
+        let options = adaptive::Options::new(header.depth)
+            .with_backend(header.backend);
+        let encoder = learn(options, base)
+            .map_err(|err| TokenReaderError::invalid_value(&err))?;
+
+        Ok(Patcher {
+            base,
+            operations: operations.into_iter(),
+            current: None,
+            inserted: Decoder::continuing(encoder, Cursor::new(inserted))?,
+        })
+    }
+
+    /// Check that the whole delta has been applied.
+    pub fn done(mut self) -> Result<(), TokenReaderError> {
+        let remaining = match self.current {
+            Some(Operation::Copy { len, .. }) | Some(Operation::Insert { len }) => len,
+            None => 0,
+        };
+        if remaining > 0 || self.operations.next().is_some() {
+            return Err(TokenReaderError::invalid_value(&"Unapplied delta operations"));
+        }
+        Ok(())
+    }
+
+    /// Advance by one token, returning the value copied from the base, or `None`
+    /// if the token is inserted.
+    fn next(&mut self) -> Result<Option<&'a TokenValue>, TokenReaderError> {
+        let base = self.base;
+        loop {
+            match self.current {
+                Some(Operation::Copy { start, len }) if len > 0 => {
+                    self.current = Some(Operation::Copy { start: start + 1, len: len - 1 });
+                    return Ok(Some(&base[start].value));
+                }
+                Some(Operation::Insert { len }) if len > 0 => {
+                    self.current = Some(Operation::Insert { len: len - 1 });
+                    return Ok(None);
+                }
+                _ => {}
+            }
+            let operation = self.operations.next()
+                .ok_or_else(|| TokenReaderError::invalid_value(&"Delta has no more tokens"))?;
+            self.current = Some(operation);
+        }
+    }
+}
+
+/// Read the next token, either from the base, in which case it must be a
+/// `TokenValue::$variant`, or from the inserted tokens.
+macro_rules! patch {
+    ( $me: ident, $variant: ident, $read: ident, $path: expr ) => {
+        match $me.next()? {
+            Some(&TokenValue::$variant(ref value)) => Ok(value.clone()),
+            Some(other) => Err(TokenReaderError::invalid_value(other)),
+            None => $me.inserted.$read($path),
+        }
+    }
+}
+
+impl<'a> FileStructurePrinter for Patcher<'a> {}
+
+impl<'a> TokenReader for Patcher<'a> {
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        patch!(self, String, string_at, path)
+    }
+
+    fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
+        patch!(self, StringEnum, string_enum_at, path)
+    }
+
+    fn identifier_name_at(&mut self, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
+        patch!(self, IdentifierName, identifier_name_at, path)
+    }
+
+    fn property_key_at(&mut self, path: &Path) -> Result<Option<PropertyKey>, TokenReaderError> {
+        patch!(self, PropertyKey, property_key_at, path)
+    }
+
+    fn reg_exp_pattern_at(&mut self, path: &Path) -> Result<Option<RegExpPattern>, TokenReaderError> {
+        patch!(self, RegExpPattern, reg_exp_pattern_at, path)
+    }
+
+    fn reg_exp_flags_at(&mut self, path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        patch!(self, RegExpFlags, reg_exp_flags_at, path)
+    }
+
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        match self.next()? {
+            Some(&TokenValue::Float(value)) => Ok(value.map(F64::into)),
+            Some(other) => Err(TokenReaderError::invalid_value(other)),
+            None => self.inserted.float_at(path),
+        }
+    }
+
+    fn unsigned_long_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        patch!(self, UnsignedLong, unsigned_long_at, path)
+    }
+
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        patch!(self, Bool, bool_at, path)
+    }
+
+    fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        // Offsets are not recorded, see `TokenRecorder::offset_at`.
+        Ok(0)
+    }
+
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        patch!(self, List, enter_list_at, path)
+    }
+
+    fn enter_tagged_tuple_at(&mut self, path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
+        match self.next()? {
+            Some(&TokenValue::Interface(ref tag)) => Ok((tag.clone(), None)),
+            Some(other) => Err(TokenReaderError::invalid_value(other)),
+            None => self.inserted.enter_tagged_tuple_at(path),
+        }
+    }
+
+    fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_delta() {
+    use binjs_shared::ast::PathItem;
+
+    let path = |interface: &'static str, field: &'static str| {
+        let mut path = Path::new();
+        path.extend_from_slice(&[PathItem {
+            interface: InterfaceName::from_str(interface),
+            field: (0, FieldName::from_str(field)),
+        }]);
+        path
+    };
+    let statement = |i: usize| {
+        vec![
+            RecordedToken {
+                value: TokenValue::Interface(InterfaceName::from_str("CallExpression")),
+                path: path("ExpressionStatement", "expression"),
+            },
+            RecordedToken {
+                value: TokenValue::IdentifierName(Some(IdentifierName::from_string(format!("function_{}", i % 20)))),
+                path: path("CallExpression", "callee"),
+            },
+            RecordedToken {
+                value: TokenValue::List(2),
+                path: path("CallExpression", "arguments"),
+            },
+            RecordedToken {
+                value: TokenValue::Float(Some(F64::from(i as f64))),
+                path: path("LiteralNumericExpression", "value"),
+            },
+            RecordedToken {
+                value: TokenValue::String(Some(SharedString::from_string(format!("string_{}", i % 7)))),
+                path: path("LiteralStringExpression", "value"),
+            },
+            RecordedToken {
+                value: TokenValue::Bool(Some(i % 3 == 0)),
+                path: path("LiteralBooleanExpression", "value"),
+            },
+        ]
+    };
+    let base : Vec<_> = (0..300)
+        .flat_map(statement)
+        .collect();
+
+    // Edit a statement, insert new ones, and move a block of statements.
+    let mut statements : Vec<_> = (0..300)
+        .map(statement)
+        .collect();
+    statements[10][3].value = TokenValue::Float(Some(F64::from(-1.)));
+    for i in 0..5 {
+        statements.insert(100, statement(1000 + i));
+    }
+    let moved : Vec<_> = statements.drain(200..220).collect();
+    statements.extend(moved);
+    let new : Vec<_> = statements.into_iter()
+        .flat_map(|tokens| tokens)
+        .collect();
+
+    // The moved statements are copied, only the edited and new tokens are inserted.
+    let inserted : usize = operations(&base, &new).iter()
+        .map(|operation| match *operation {
+            Operation::Insert { len } => len,
+            Operation::Copy { .. } => 0,
+        })
+        .sum();
+    assert!(inserted <= 6 * 6, "Inserted {} tokens", inserted);
+
+    // The delta is smaller than the new version, encoded from scratch.
+    let delta = Delta::new(adaptive::Options::default())
+        .diff(&base, &new)
+        .expect("Could not compute delta");
+    let mut encoder = Encoder::new(adaptive::Options::default());
+    for token in &new {
+        token.write_to(&mut encoder)
+            .expect("Could not write token");
+    }
+    let full = encoder.done()
+        .expect("Could not finalize encoding");
+    assert!(delta.len() * 10 < full.len(), "Delta of {} bytes, file of {} bytes", delta.len(), full.len());
+
+    // Applying the delta to the base yields the new version.
+    let mut patcher = Patcher::new(&base, &delta)
+        .expect("Could not start applying delta");
+    for token in &new {
+        let path = &token.path;
+        let value = match token.value {
+            TokenValue::Bool(_) => TokenValue::Bool(patcher.bool_at(path).expect("Could not read bool")),
+            TokenValue::Float(_) => TokenValue::Float(patcher.float_at(path).expect("Could not read float").map(F64::from)),
+            TokenValue::String(_) => TokenValue::String(patcher.string_at(path).expect("Could not read string")),
+            TokenValue::IdentifierName(_) => TokenValue::IdentifierName(patcher.identifier_name_at(path).expect("Could not read identifier")),
+            TokenValue::Interface(_) => TokenValue::Interface(patcher.enter_tagged_tuple_at(path).expect("Could not read tag").0),
+            TokenValue::List(_) => TokenValue::List(patcher.enter_list_at(path).expect("Could not read list length")),
+            _ => unreachable!(),
+        };
+        assert_eq!(value, token.value);
+    }
+    patcher.done()
+        .expect("Could not finish applying delta");
+
+    // The delta may not be applied to another base.
+    assert!(Patcher::new(&new, &delta).is_err());
+}
@@ -1,4 +1,4 @@
-use entropy::predict::{ PathPredict, WindowPredict };
+pub use entropy::predict::{ ContextInformation, DictionaryIndex, PathPredict, WindowPredict, WindowPrediction };
 use entropy::probabilities::{ InstancesToProbabilities, SymbolIndex, SymbolInfo };
 
 use io::TokenWriter;
@@ -6,6 +6,9 @@ use ::TokenWriterError;
 
 use binjs_shared::{ F64, FieldName, IdentifierName, InterfaceName, Node, PropertyKey, SharedString };
 
+use bincode;
+use serde;
+
 use std;
 use std::collections::HashMap;
 
@@ -85,6 +88,15 @@ pub struct Dictionary<T> {
     // - directives?
 }
 impl<T> Dictionary<T> {
+    /// The amount of context used to predict values by path.
+    ///
+    /// With a depth of 0, paths are ignored. With a depth of 1, we only take into account
+    /// the node/field. With a depth of 2, we also take into account the node/field of the
+    /// grand parent, etc.
+    pub fn depth(&self) -> usize {
+        self.bool_by_path.depth()
+    }
+
     pub fn new(depth: usize, width: usize) -> Self {
         Dictionary {
             bool_by_path: PathPredict::new(depth),
@@ -132,6 +144,345 @@ impl<T> Dictionary<T> {
         + interface_name_by_path.len()
     }
 }
+
+/// The outcome of `Dictionary::prune`.
+#[derive(Clone, Debug, Default)]
+pub struct PruneReport {
+    /// The size of the serialized dictionary before pruning, in bytes.
+    pub bytes_before: u64,
+
+    /// The size of the serialized dictionary after pruning, in bytes.
+    pub bytes_after: u64,
+
+    /// The number of (context, value) entries removed.
+    pub entries_removed: usize,
+
+    /// The number of sampled symbols covered by the entries of the dictionary
+    /// before pruning.
+    pub symbols_before: usize,
+
+    /// The number of sampled symbols covered by the removed entries. These
+    /// symbols may no longer be encoded with the pruned dictionary.
+    pub symbols_removed: usize,
+
+    /// The number of bits used by the removed symbols when encoding the sample
+    /// with the original dictionary, i.e. a lower bound for the compression loss.
+    pub bits_removed: f64,
+}
+
+impl std::fmt::Display for PruneReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let percent =
+            if self.symbols_before == 0 {
+                0.
+            } else {
+                100. * self.symbols_removed as f64 / self.symbols_before as f64
+            };
+        write!(f, "{} => {} bytes, removed {} entries covering {} of {} sampled symbols ({:.2}%, at least {:.0} bytes of the sample)",
+            self.bytes_before,
+            self.bytes_after,
+            self.entries_removed,
+            self.symbols_removed,
+            self.symbols_before,
+            percent,
+            self.bits_removed / 8.)
+    }
+}
+
+/// The state of pruning, shared between all the tables of a dictionary.
+struct Pruner {
+    /// Entries with a lower score are removed.
+    threshold: f64,
+
+    /// The number of entries with score `threshold` that should still be removed.
+    ties: usize,
+
+    report: PruneReport,
+}
+impl Pruner {
+    /// The value of an entry, as the number of sampled symbols it covers per byte of dictionary.
+    fn score(bytes: u64, instances: usize) -> f64 {
+        instances as f64 / bytes as f64
+    }
+
+    /// Determine whether an entry should be kept, updating the report.
+    ///
+    /// `total` is the number of symbols in the context of the entry.
+    fn keep(&mut self, bytes: u64, instances: usize, total: usize) -> bool {
+        self.report.symbols_before += instances;
+        let score = Self::score(bytes, instances);
+        let remove =
+            if score < self.threshold {
+                true
+            } else if score == self.threshold && self.ties > 0 {
+                self.ties -= 1;
+                true
+            } else {
+                false
+            };
+        if remove {
+            self.report.entries_removed += 1;
+            self.report.symbols_removed += instances;
+            if instances > 0 {
+                self.report.bits_removed -= instances as f64 * (instances as f64 / total as f64).log2();
+            }
+        }
+        !remove
+    }
+
+    /// The number of bytes occupied by an entry of a `PathPredict`.
+    ///
+    /// This doesn't include the path itself, which is shared between entries.
+    fn path_entry_bytes<V>(value: &V) -> Result<u64, bincode::Error> where V: serde::Serialize {
+        Ok(bincode::serialized_size(value)? + bincode::serialized_size(&Instances::from(0))?)
+    }
+
+    /// The number of bytes occupied by a value of a `WindowPredict`, which appears both
+    /// in the list of values and in the reverse map, plus its statistics.
+    fn window_entry_bytes<V>(value: &V) -> Result<u64, bincode::Error> where V: serde::Serialize {
+        let prediction = WindowPrediction::DictionaryIndex(DictionaryIndex::new(0));
+        Ok(2 * bincode::serialized_size(value)?
+            + bincode::serialized_size(&DictionaryIndex::new(0))?
+            + bincode::serialized_size(&prediction)?
+            + bincode::serialized_size(&Instances::from(0))?)
+    }
+
+    fn collect_path<V>(table: &PathPredict<V, Instances>, entries: &mut Vec<(f64, u64)>) -> Result<(), bincode::Error>
+        where V: Eq + std::hash::Hash + Clone + serde::Serialize
+    {
+        for (_, info) in table.iter() {
+            for (value, instances) in info.iter() {
+                let bytes = Self::path_entry_bytes(value)?;
+                entries.push((Self::score(bytes, (*instances).into()), bytes));
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_window<V>(table: &WindowPredict<V, Instances>, entries: &mut Vec<(f64, u64)>) -> Result<(), bincode::Error>
+        where V: Eq + std::hash::Hash + Clone + serde::Serialize
+    {
+        for (index, value) in table.values().iter().enumerate() {
+            let instances = table.info()
+                .get(&WindowPrediction::DictionaryIndex(DictionaryIndex::new(index)))
+                .map_or(0, |instances| (*instances).into());
+            let bytes = Self::window_entry_bytes(value)?;
+            entries.push((Self::score(bytes, instances), bytes));
+        }
+        Ok(())
+    }
+
+    fn prune_path<V>(&mut self, table: &mut PathPredict<V, Instances>) -> Result<(), bincode::Error>
+        where V: Eq + std::hash::Hash + Clone + serde::Serialize
+    {
+        let totals : HashMap<IOPath, usize> = table.iter()
+            .map(|(path, info)| (path.clone(), info.total()))
+            .collect();
+        let mut result = Ok(());
+        table.retain(|path, value, instances| {
+            match Self::path_entry_bytes(value) {
+                Ok(bytes) => self.keep(bytes, (*instances).into(), totals[path]),
+                Err(err) => {
+                    result = Err(err);
+                    true
+                }
+            }
+        });
+        result
+    }
+
+    fn prune_window<V>(&mut self, table: &mut WindowPredict<V, Instances>) -> Result<(), bincode::Error>
+        where V: Eq + std::hash::Hash + Clone + std::fmt::Debug + serde::Serialize
+    {
+        let total = table.info().total();
+        let mut result = Ok(());
+        table.retain(|value, instances| {
+            match Self::window_entry_bytes(value) {
+                Ok(bytes) => self.keep(bytes, (*instances).into(), total),
+                Err(err) => {
+                    result = Err(err);
+                    true
+                }
+            }
+        });
+        result
+    }
+}
+
+impl Dictionary<Instances> {
+    /// Reduce the amount of context used to predict values by path, merging the
+    /// statistics of paths that become indistinguishable.
+    ///
+    /// Return `None` if `depth` is larger than the depth used to build the dictionary.
+    pub fn with_depth(self, depth: usize) -> Option<Self> {
+        Some(Dictionary {
+            bool_by_path: self.bool_by_path.with_depth(depth)?,
+            float_by_path: self.float_by_path.with_depth(depth)?,
+            unsigned_long_by_path: self.unsigned_long_by_path.with_depth(depth)?,
+            string_enum_by_path: self.string_enum_by_path.with_depth(depth)?,
+            property_key_by_path: self.property_key_by_path.with_depth(depth)?,
+            property_key_by_window: self.property_key_by_window,
+            identifier_name_by_path: self.identifier_name_by_path.with_depth(depth)?,
+            identifier_name_by_window: self.identifier_name_by_window,
+            interface_name_by_path: self.interface_name_by_path.with_depth(depth)?,
+            string_literal_by_path: self.string_literal_by_path.with_depth(depth)?,
+            string_literal_by_window: self.string_literal_by_window,
+            list_length_by_path: self.list_length_by_path.with_depth(depth)?,
+        })
+    }
+
+    /// The window width used to predict strings by window.
+    pub fn width(&self) -> usize {
+        self.string_literal_by_window.width()
+    }
+
+    /// The number of sampled symbols predicted by path.
+    pub fn symbols(&self) -> usize {
+        fn symbols<V>(table: &PathPredict<V, Instances>) -> usize where V: Eq + std::hash::Hash + Clone {
+            table.iter()
+                .map(|(_, info)| info.total())
+                .sum()
+        }
+        symbols(&self.bool_by_path)
+        + symbols(&self.float_by_path)
+        + symbols(&self.unsigned_long_by_path)
+        + symbols(&self.string_enum_by_path)
+        + symbols(&self.property_key_by_path)
+        + symbols(&self.identifier_name_by_path)
+        + symbols(&self.interface_name_by_path)
+        + symbols(&self.string_literal_by_path)
+        + symbols(&self.list_length_by_path)
+    }
+
+    /// Merge dictionaries trained on distinct corpora, e.g. a framework corpus and an
+    /// application corpus, into a single dictionary.
+    ///
+    /// Each dictionary is given a weight, e.g. `0.7` and `0.3`. Regardless of the size
+    /// of their corpora, each dictionary contributes to the merged dictionary in
+    /// proportion to its weight, i.e. as if it had been trained on a corpus of that
+    /// share of the total number of symbols. Values keep at least one instance, so that
+    /// every value of every dictionary with a non-zero weight may still be encoded with
+    /// the merged dictionary.
+    ///
+    /// The merged dictionary uses the smallest depth and window width of `dictionaries`.
+    /// Return `None` if `dictionaries` is empty or if a weight is negative.
+    pub fn merge_weighted(dictionaries: Vec<(Dictionary<Instances>, f64)>) -> Option<Self> {
+        let depth = dictionaries.iter()
+            .map(|&(ref dictionary, _)| dictionary.depth())
+            .min()?;
+        let width = dictionaries.iter()
+            .map(|&(ref dictionary, _)| dictionary.width())
+            .min()?;
+        if dictionaries.iter().any(|&(_, weight)| !(weight >= 0.)) {
+            return None;
+        }
+        let total_weight : f64 = dictionaries.iter()
+            .map(|&(_, weight)| weight)
+            .sum();
+        let total_symbols : usize = dictionaries.iter()
+            .map(|&(ref dictionary, _)| dictionary.symbols())
+            .sum();
+
+        let mut result = Dictionary::new(depth, width);
+        for (dictionary, weight) in dictionaries {
+            let symbols = dictionary.symbols();
+            if symbols == 0 || weight == 0. {
+                continue;
+            }
+            let scale = weight / total_weight * total_symbols as f64 / symbols as f64;
+            let dictionary = dictionary.with_depth(depth)
+                .unwrap(); // We have just checked that `depth` is the smallest depth.
+            result.bool_by_path.merge_scaled(&dictionary.bool_by_path, scale);
+            result.float_by_path.merge_scaled(&dictionary.float_by_path, scale);
+            result.unsigned_long_by_path.merge_scaled(&dictionary.unsigned_long_by_path, scale);
+            result.string_enum_by_path.merge_scaled(&dictionary.string_enum_by_path, scale);
+            result.property_key_by_path.merge_scaled(&dictionary.property_key_by_path, scale);
+            result.property_key_by_window.merge_scaled(&dictionary.property_key_by_window, scale);
+            result.identifier_name_by_path.merge_scaled(&dictionary.identifier_name_by_path, scale);
+            result.identifier_name_by_window.merge_scaled(&dictionary.identifier_name_by_window, scale);
+            result.interface_name_by_path.merge_scaled(&dictionary.interface_name_by_path, scale);
+            result.string_literal_by_path.merge_scaled(&dictionary.string_literal_by_path, scale);
+            result.string_literal_by_window.merge_scaled(&dictionary.string_literal_by_window, scale);
+            result.list_length_by_path.merge_scaled(&dictionary.list_length_by_path, scale);
+        }
+        Some(result)
+    }
+
+    /// Prune this dictionary so that its serialized size does not exceed `max_bytes`.
+    ///
+    /// Entries are removed by increasing number of sampled symbols covered per
+    /// byte of dictionary, i.e. rare values with long representations go first.
+    /// Among entries of equal value, the choice is arbitrary. Values removed from
+    /// the dictionary can no longer be encoded with it.
+    pub fn prune(&mut self, max_bytes: u64) -> Result<PruneReport, bincode::Error> {
+        let bytes_before = bincode::serialized_size(&*self)?;
+
+        // Collect the (score, bytes) of all entries.
+        let mut entries = vec![];
+        Pruner::collect_path(&self.bool_by_path, &mut entries)?;
+        Pruner::collect_path(&self.float_by_path, &mut entries)?;
+        Pruner::collect_path(&self.unsigned_long_by_path, &mut entries)?;
+        Pruner::collect_path(&self.string_enum_by_path, &mut entries)?;
+        Pruner::collect_path(&self.property_key_by_path, &mut entries)?;
+        Pruner::collect_window(&self.property_key_by_window, &mut entries)?;
+        Pruner::collect_path(&self.identifier_name_by_path, &mut entries)?;
+        Pruner::collect_window(&self.identifier_name_by_window, &mut entries)?;
+        Pruner::collect_path(&self.interface_name_by_path, &mut entries)?;
+        Pruner::collect_path(&self.string_literal_by_path, &mut entries)?;
+        Pruner::collect_window(&self.string_literal_by_window, &mut entries)?;
+        Pruner::collect_path(&self.list_length_by_path, &mut entries)?;
+
+        // Find the lowest-value entries that, once removed, bring us within budget.
+        // This ignores the paths that are freed once all their values have been
+        // removed, so we may prune slightly more than strictly necessary.
+        entries.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        let excess = bytes_before.saturating_sub(max_bytes);
+        let mut freed = 0;
+        let removed = entries.iter()
+            .take_while(|&&(_, bytes)| {
+                let done = freed >= excess;
+                freed += bytes;
+                !done
+            })
+            .count();
+        let (threshold, ties) =
+            if removed == 0 {
+                (std::f64::NEG_INFINITY, 0)
+            } else {
+                let threshold = entries[removed - 1].0;
+                let ties = entries[..removed].iter()
+                    .filter(|&&(score, _)| score == threshold)
+                    .count();
+                (threshold, ties)
+            };
+
+        let mut pruner = Pruner {
+            threshold,
+            ties,
+            report: PruneReport {
+                bytes_before,
+                ..PruneReport::default()
+            }
+        };
+        pruner.prune_path(&mut self.bool_by_path)?;
+        pruner.prune_path(&mut self.float_by_path)?;
+        pruner.prune_path(&mut self.unsigned_long_by_path)?;
+        pruner.prune_path(&mut self.string_enum_by_path)?;
+        pruner.prune_path(&mut self.property_key_by_path)?;
+        pruner.prune_window(&mut self.property_key_by_window)?;
+        pruner.prune_path(&mut self.identifier_name_by_path)?;
+        pruner.prune_window(&mut self.identifier_name_by_window)?;
+        pruner.prune_path(&mut self.interface_name_by_path)?;
+        pruner.prune_path(&mut self.string_literal_by_path)?;
+        pruner.prune_window(&mut self.string_literal_by_window)?;
+        pruner.prune_path(&mut self.list_length_by_path)?;
+
+        let mut report = pruner.report;
+        report.bytes_after = bincode::serialized_size(&*self)?;
+        Ok(report)
+    }
+}
+
 impl InstancesToProbabilities for Dictionary<Instances> {
     type AsProbabilities = Dictionary<SymbolInfo>;
 
@@ -217,23 +568,25 @@ impl<K> InstancesToProbabilities for HashMap<K, FilesContaining>
     type AsProbabilities = HashMap<K, SymbolInfo>;
 
     fn instances_to_probabilities(self, _description: &str) -> HashMap<K, SymbolInfo> {
-        use std::cell::RefCell;
-        use std::rc::Rc;
+        use std::sync::Arc;
 
-        let instances = self.values()
+        let instances : Vec<_> = self.values()
             .map(|x| {
                 let x: usize = x.clone().into();
                 x as u32
             })
             .collect();
-        let distribution = Rc::new(RefCell::new(range_encoding::CumulativeDistributionFrequency::new(instances)));
+        let codebook = ::entropy::huffman::Codebook::new(&instances);
+        let distribution = Arc::new(range_encoding::CumulativeDistributionFrequency::new(instances));
+        let codebook = Arc::new(codebook.with_information(&distribution));
 
         self.into_iter()
             .enumerate()
             .map(|(index, (key, _))| {
                 (key, SymbolInfo {
                     index: SymbolIndex::from(index),
-                    distribution: distribution.clone()
+                    distribution: distribution.clone(),
+                    codebook: codebook.clone(),
                 })
             })
             .collect()
@@ -393,3 +746,97 @@ impl<'a> TokenWriter for DictionaryBuilder<'a> {
         Ok(())
     }
 }
+
+#[test]
+fn test_prune() {
+    let path = IOPath::new();
+    let mut dictionary : Dictionary<Instances> = Dictionary::new(1, 2);
+    for _ in 0..10 {
+        dictionary.unsigned_long_by_path.add(path.tail(1), 1);
+    }
+    for value in 2..10 {
+        dictionary.unsigned_long_by_path.add(path.tail(1), value);
+    }
+    for value in &["a", "b", "a", "c"] {
+        dictionary.string_literal_by_window.add(Some(SharedString::from_str(*value)));
+    }
+    let size = bincode::serialized_size(&dictionary)
+        .expect("Could not measure dictionary");
+
+    // Within budget, nothing to prune.
+    let report = dictionary.prune(size)
+        .expect("Could not prune dictionary");
+    assert_eq!(report.entries_removed, 0);
+    assert_eq!(report.bytes_after, size);
+
+    // Remove a single rare value.
+    let report = dictionary.prune(size - 1)
+        .expect("Could not prune dictionary");
+    assert_eq!(report.entries_removed, 1);
+    assert_eq!(report.symbols_removed, 1);
+    assert!(report.bytes_after < size);
+    assert!(dictionary.unsigned_long_by_path.get(&path)
+        .expect("Path should be known")
+        .get(&1)
+        .is_some());
+
+    // Remove everything.
+    let report = dictionary.prune(0)
+        .expect("Could not prune dictionary");
+    assert_eq!(report.symbols_removed, report.symbols_before);
+    assert_eq!(dictionary.unsigned_long_by_path.len(), 0);
+    assert_eq!(dictionary.string_literal_by_window.values().len(), 0);
+}
+
+#[test]
+fn test_merge_weighted() {
+    let path = IOPath::new();
+    let count = |dictionary: &Dictionary<Instances>, value| {
+        dictionary.unsigned_long_by_path.get(&path)
+            .and_then(|info| info.get(&value))
+            .map_or(0, |instances| Into::<usize>::into(*instances))
+    };
+
+    // A large corpus using mostly 1, a small corpus using only 2.
+    let mut framework : Dictionary<Instances> = Dictionary::new(2, 2);
+    for _ in 0..90 {
+        framework.unsigned_long_by_path.add(path.tail(2), 1);
+    }
+    for _ in 0..10 {
+        framework.unsigned_long_by_path.add(path.tail(2), 3);
+    }
+    for value in &["a", "b", "a"] {
+        framework.string_literal_by_window.add(Some(SharedString::from_str(*value)));
+    }
+    let mut app : Dictionary<Instances> = Dictionary::new(1, 4);
+    for _ in 0..10 {
+        app.unsigned_long_by_path.add(path.tail(1), 2);
+    }
+    for value in &["c", "a"] {
+        app.string_literal_by_window.add(Some(SharedString::from_str(*value)));
+    }
+
+    let merged = Dictionary::merge_weighted(vec![(framework.clone(), 0.7), (app.clone(), 0.3)])
+        .expect("Could not merge dictionaries");
+    assert_eq!(merged.depth(), 1);
+    assert_eq!(merged.width(), 2);
+    assert_eq!(merged.symbols(), 110);
+    assert_eq!(count(&merged, 1), 69);
+    assert_eq!(count(&merged, 2), 33);
+    assert_eq!(count(&merged, 3), 8);
+
+    // Window values are merged, without duplicates.
+    let values : Vec<_> = merged.string_literal_by_window.values()
+        .iter()
+        .map(|value| value.as_ref().unwrap().as_str())
+        .collect();
+    assert_eq!(values, vec!["a", "b", "c"]);
+
+    // Dictionaries with weight 0 are ignored.
+    let merged = Dictionary::merge_weighted(vec![(framework.clone(), 1.), (app.clone(), 0.)])
+        .expect("Could not merge dictionaries");
+    assert_eq!(count(&merged, 2), 0);
+
+    assert!(Dictionary::merge_weighted(vec![]).is_none());
+    assert!(Dictionary::merge_weighted(vec![(framework, -1.)]).is_none());
+}
@@ -0,0 +1,514 @@
+//! A fallback for strings missing from the dictionary.
+//!
+//! With a shared dictionary, a file may contain string literals, identifier names
+//! or property keys that do not appear in the dictionary at their path. By default,
+//! such a file cannot be encoded. With `Options::with_fallback`, these strings are
+//! stored instead in a per-file section that does not depend on the dictionary, the
+//! *fallback section*. The file is then formatted as:
+//!
+//! - the header, announcing the fallback section, see `header`;
+//! - the number of strings in the fallback section (`varnum`);
+//! - for each string, in the order in which they are first used, its byte length
+//!     (`varnum`), followed by its WTF-8 bytes;
+//! - the main stream.
+//!
+//! In the main stream, each string is preceded by a flag, coded with an adaptive
+//! model, announcing whether the string is coded with the dictionary or as a
+//! reference to the fallback section. A reference is the index of the string in the
+//! fallback section, as a `varnum`, coded one byte at a time.
+//!
+//! As the fallback section is per-file, a string missing from the dictionary is paid
+//! for in each file in which it appears. `FallbackStatistics` records exactly which
+//! strings fell back, and `FallbackStatistics::suggestions` estimates how much would
+//! be saved by adding them to the dictionary.
+
+use super::coder::{ Reader, SymbolReader, SymbolWriter, Writer };
+use super::dictionary::{ Dictionary, Instances };
+
+use ::{ TokenReaderError, TokenWriterError };
+use ::io::Path;
+use bytes::varnum::{ ReadVarNum, WriteVarNum };
+
+use binjs_shared::SharedString;
+
+use std;
+use std::collections::HashMap;
+use std::io::{ Cursor, Read };
+
+use range_encoding::CumulativeDistributionFrequency;
+
+/// The flag announcing a string coded with the dictionary.
+const IN_DICTIONARY: u32 = 0;
+
+/// The flag announcing a reference to the fallback section.
+const IN_FALLBACK: u32 = 1;
+
+/// Halve the number of instances of each flag once they exceed this total.
+const MAX_TOTAL_INSTANCES: u32 = 1 << 15;
+
+/// The maximal number of bytes of a `varnum`.
+const MAX_VARNUM_BYTES: usize = 5;
+
+/// The kinds of strings that may fall back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StringKind {
+    StringLiteral,
+    IdentifierName,
+    PropertyKey,
+}
+impl StringKind {
+    /// The table of the dictionary in which strings of this kind are looked up.
+    pub fn table(&self) -> &'static str {
+        match *self {
+            StringKind::StringLiteral => "string_literal_by_path",
+            StringKind::IdentifierName => "identifier_name_by_path",
+            StringKind::PropertyKey => "property_key_by_path",
+        }
+    }
+}
+
+/// A string missing from the dictionary.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Fallback {
+    pub kind: StringKind,
+
+    /// The path at which the string was looked up, truncated to the depth of the table.
+    pub path: Path,
+
+    pub value: SharedString,
+}
+
+/// The number of times a string fell back.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FallbackInstances {
+    /// The number of references to the string.
+    pub instances: usize,
+
+    /// The number of files in which the string fell back.
+    pub files: usize,
+}
+
+/// Statistics obtained while writing with a fallback section.
+#[derive(Clone, Debug, Default)]
+pub struct FallbackStatistics {
+    /// The number of files written.
+    pub files: usize,
+
+    /// The number of strings written.
+    pub values: usize,
+
+    /// The number of strings written as references to the fallback section.
+    pub fallbacks: usize,
+
+    /// The number of bytes of the fallback sections.
+    pub bytes: usize,
+
+    /// Each string that fell back.
+    pub strings: HashMap<Fallback, FallbackInstances>,
+}
+impl FallbackStatistics {
+    /// Merge the statistics of `other`, e.g. of another file.
+    pub fn merge(&mut self, other: &FallbackStatistics) {
+        self.files += other.files;
+        self.values += other.values;
+        self.fallbacks += other.fallbacks;
+        self.bytes += other.bytes;
+        for (fallback, instances) in &other.strings {
+            let entry = self.strings.entry(fallback.clone())
+                .or_insert_with(FallbackInstances::default);
+            entry.instances += instances.instances;
+            entry.files += instances.files;
+        }
+    }
+
+    /// The strings that fell back, most frequent first.
+    pub fn by_instances(&self) -> Vec<(&Fallback, &FallbackInstances)> {
+        let mut strings : Vec<_> = self.strings.iter()
+            .collect();
+        strings.sort_by(|a, b| b.1.instances.cmp(&a.1.instances)
+            .then_with(|| a.0.kind.cmp(&b.0.kind))
+            .then_with(|| a.0.value.cmp(&b.0.value)));
+        strings
+    }
+
+    /// Estimate which strings should be added to `dictionary`, most profitable first.
+    ///
+    /// In each file, a string that falls back costs its byte length and its bytes in
+    /// the fallback section, plus roughly one byte per reference. Added at its path
+    /// with as many instances as it fell back, each instance would instead cost
+    /// `log2((total + instances) / instances)` bits, where `total` is the number of
+    /// instances already at this path. The size of the dictionary itself is ignored.
+    ///
+    /// Strings that would not save anything are omitted.
+    pub fn suggestions(&self, dictionary: &Dictionary<Instances>) -> Vec<Suggestion> {
+        let mut suggestions : Vec<_> = self.strings.iter()
+            .filter_map(|(fallback, instances)| {
+                let total = match fallback.kind {
+                    StringKind::StringLiteral => dictionary.string_literal_by_path.get(&fallback.path)
+                        .map_or(0, |info| info.total()),
+                    StringKind::IdentifierName => dictionary.identifier_name_by_path.get(&fallback.path)
+                        .map_or(0, |info| info.total()),
+                    StringKind::PropertyKey => dictionary.property_key_by_path.get(&fallback.path)
+                        .map_or(0, |info| info.total()),
+                };
+                let byte_len = fallback.value.to_wtf8().len();
+                let before = instances.files * (varnum_len(byte_len) + byte_len) + instances.instances;
+                let count = instances.instances as f64;
+                let after = count * ((total as f64 + count) / count).log2() / 8.;
+                let savings = before as f64 - after;
+                if savings <= 0. {
+                    return None;
+                }
+                Some(Suggestion {
+                    fallback: fallback.clone(),
+                    instances: *instances,
+                    savings,
+                })
+            })
+            .collect();
+        suggestions.sort_by(|a, b| b.savings.partial_cmp(&a.savings)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a.fallback.kind.cmp(&b.fallback.kind))
+            .then_with(|| a.fallback.value.cmp(&b.fallback.value)));
+        suggestions
+    }
+}
+impl std::fmt::Display for FallbackStatistics {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(formatter, "Fallback section: {fallbacks} of {values} strings ({rate:.2}%), {distinct} distinct, {bytes} bytes in {files} files\n",
+            fallbacks = self.fallbacks,
+            values = self.values,
+            rate = if self.values == 0 { 0. } else { 100. * self.fallbacks as f64 / self.values as f64 },
+            distinct = self.strings.len(),
+            bytes = self.bytes,
+            files = self.files)
+    }
+}
+
+/// A string that could be added to the dictionary, see `FallbackStatistics::suggestions`.
+#[derive(Clone, Debug)]
+pub struct Suggestion {
+    pub fallback: Fallback,
+
+    pub instances: FallbackInstances,
+
+    /// The estimated number of bytes saved across all files.
+    pub savings: f64,
+}
+
+/// The number of bytes of `value` as a `varnum`.
+fn varnum_len(value: usize) -> usize {
+    let mut len = 1;
+    let mut value = value >> 7;
+    while value != 0 {
+        len += 1;
+        value >>= 7;
+    }
+    len
+}
+
+/// An adaptive model of the flags, so that files in which (almost) no string falls
+/// back pay (almost) nothing for the flags.
+struct Flags {
+    instances: [u32; 2],
+
+    /// The distribution matching `instances`, rebuilt lazily after an update.
+    distribution: Option<CumulativeDistributionFrequency>,
+}
+impl Flags {
+    fn new() -> Self {
+        Flags {
+            instances: [1, 1],
+            distribution: None,
+        }
+    }
+
+    fn distribution(&mut self) -> &mut CumulativeDistributionFrequency {
+        let instances = &self.instances;
+        self.distribution.get_or_insert_with(|| CumulativeDistributionFrequency::new(instances.to_vec()))
+    }
+
+    fn update(&mut self, flag: u32) {
+        self.instances[flag as usize] += 1;
+        if self.instances[IN_DICTIONARY as usize] + self.instances[IN_FALLBACK as usize] > MAX_TOTAL_INSTANCES {
+            for instances in self.instances.iter_mut() {
+                *instances = (*instances + 1) / 2;
+            }
+        }
+        self.distribution = None;
+    }
+}
+
+/// A distribution in which all bytes are equally likely.
+fn bytes_distribution() -> CumulativeDistributionFrequency {
+    CumulativeDistributionFrequency::new(vec![1; 256])
+}
+
+/// Writing the fallback section of a file.
+pub struct FallbackWriter {
+    flags: Flags,
+
+    /// Used to code references.
+    bytes: CumulativeDistributionFrequency,
+
+    /// The strings of the fallback section, in the order in which they are first used.
+    strings: Vec<SharedString>,
+
+    index_by_string: HashMap<SharedString, u32>,
+
+    statistics: FallbackStatistics,
+}
+impl FallbackWriter {
+    pub fn new() -> Self {
+        FallbackWriter {
+            flags: Flags::new(),
+            bytes: bytes_distribution(),
+            strings: vec![],
+            index_by_string: HashMap::new(),
+            statistics: FallbackStatistics::default(),
+        }
+    }
+
+    /// Write the flag announcing whether a string is coded with the dictionary,
+    /// followed, if it is not, by its reference to the fallback section.
+    ///
+    /// `missing` is the string if it is missing from the dictionary at `path`,
+    /// in a table of depth `depth`.
+    ///
+    /// Return `true` if the string has been written as a reference, `false` if the
+    /// caller should code it with the dictionary.
+    pub fn write(&mut self, writer: &mut Writer, kind: StringKind, path: &Path, depth: usize, missing: Option<&SharedString>) -> Result<bool, TokenWriterError> {
+        self.statistics.values += 1;
+        let value = match missing {
+            None => {
+                writer.symbol(IN_DICTIONARY, self.flags.distribution())
+                    .map_err(TokenWriterError::WriteError)?;
+                self.flags.update(IN_DICTIONARY);
+                return Ok(false);
+            }
+            Some(value) => value
+        };
+        writer.symbol(IN_FALLBACK, self.flags.distribution())
+            .map_err(TokenWriterError::WriteError)?;
+        self.flags.update(IN_FALLBACK);
+
+        let index = {
+            let strings = &mut self.strings;
+            *self.index_by_string.entry(value.clone())
+                .or_insert_with(|| {
+                    strings.push(value.clone());
+                    strings.len() as u32 - 1
+                })
+        };
+        let mut reference = Vec::with_capacity(MAX_VARNUM_BYTES);
+        reference.write_varnum(index)
+            .map_err(TokenWriterError::WriteError)?;
+        for byte in reference {
+            writer.symbol(byte as u32, &mut self.bytes)
+                .map_err(TokenWriterError::WriteError)?;
+        }
+
+        // Statistics.
+        let mut key = Path::new();
+        key.extend_from_slice(path.tail(depth));
+        let instances = self.statistics.strings.entry(Fallback {
+                kind,
+                path: key,
+                value: value.clone(),
+            })
+            .or_insert(FallbackInstances {
+                instances: 0,
+                files: 1,
+            });
+        instances.instances += 1;
+        self.statistics.fallbacks += 1;
+        Ok(true)
+    }
+
+    /// Return the fallback section, to be written before the main stream, and the
+    /// statistics for this file.
+    pub fn done(mut self) -> Result<(Vec<u8>, FallbackStatistics), TokenWriterError> {
+        let mut data = vec![];
+        data.write_varnum(self.strings.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        for string in &self.strings {
+            let bytes = string.to_wtf8();
+            data.write_varnum(bytes.len() as u32)
+                .map_err(TokenWriterError::WriteError)?;
+            data.extend_from_slice(&bytes);
+        }
+        self.statistics.files = 1;
+        self.statistics.bytes = data.len();
+        Ok((data, self.statistics))
+    }
+}
+
+/// Reading strings from the fallback section of a file.
+pub struct FallbackReader {
+    flags: Flags,
+
+    /// Used to decode references.
+    bytes: CumulativeDistributionFrequency,
+
+    strings: Vec<SharedString>,
+}
+impl FallbackReader {
+    /// Read the fallback section from the start of `source`.
+    pub fn new<R: Read>(source: &mut R) -> Result<Self, TokenReaderError> {
+        let len = source.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        // Don't trust `len` to preallocate.
+        let mut strings = vec![];
+        for _ in 0..len {
+            let byte_len = source.read_varnum()
+                .map_err(TokenReaderError::ReadError)?;
+            let mut bytes = vec![];
+            (&mut *source).take(byte_len as u64)
+                .read_to_end(&mut bytes)
+                .map_err(TokenReaderError::ReadError)?;
+            if bytes.len() != byte_len as usize {
+                return Err(TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated fallback section")));
+            }
+            let string = SharedString::from_wtf8(bytes)
+                .map_err(|err| TokenReaderError::invalid_value(&err))?;
+            strings.push(string);
+        }
+        Ok(FallbackReader {
+            flags: Flags::new(),
+            bytes: bytes_distribution(),
+            strings,
+        })
+    }
+
+    /// Read the flag written by `FallbackWriter::write`, followed, if needed, by
+    /// the reference to the fallback section.
+    ///
+    /// Return `None` if the caller should decode the string with the dictionary.
+    pub fn read<R: Read>(&mut self, reader: &mut Reader<R>) -> Result<Option<SharedString>, TokenReaderError> {
+        let flag = reader.symbol(self.flags.distribution())
+            .map_err(TokenReaderError::ReadError)?;
+        if flag != IN_DICTIONARY && flag != IN_FALLBACK {
+            return Err(TokenReaderError::invalid_value(&flag));
+        }
+        self.flags.update(flag);
+        if flag == IN_DICTIONARY {
+            return Ok(None);
+        }
+
+        let mut reference = Vec::with_capacity(MAX_VARNUM_BYTES);
+        loop {
+            let byte = reader.symbol(&mut self.bytes)
+                .map_err(TokenReaderError::ReadError)?;
+            reference.push(byte as u8);
+            if byte & 1 == 0 {
+                break;
+            }
+            if reference.len() == MAX_VARNUM_BYTES {
+                return Err(TokenReaderError::invalid_value(&reference));
+            }
+        }
+        let index = Cursor::new(reference)
+            .read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        self.strings.get(index as usize)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| TokenReaderError::invalid_value(&index))
+    }
+}
+
+#[test]
+fn test_fallback() {
+    use binjs_shared::{ FieldName, InterfaceName };
+    use binjs_shared::ast::PathItem;
+    use entropy::probabilities::InstancesToProbabilities;
+    use io::{ Token, TokenKind, TokenReader, TokenWriter };
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("LiteralStringExpression"),
+        field: (0, FieldName::from_str("value")),
+    }]);
+    let known = SharedString::from_str("known");
+    let missing = SharedString::from_str("a string missing from the dictionary");
+    let other = SharedString::from_str("another missing string");
+
+    let mut dictionary : Dictionary<Instances> = Dictionary::new(1, 2);
+    for _ in 0..3 {
+        dictionary.string_literal_by_path.add(path.tail(1), Some(known.clone()));
+    }
+    dictionary.string_literal_by_path.add(path.tail(1), None);
+    let sample = dictionary.clone();
+    let options = ::entropy::Options::new(dictionary.instances_to_probabilities("dictionary"));
+
+    let values = vec![Some(known.clone()), Some(missing.clone()), None, Some(missing.clone()), Some(other.clone()), Some(known.clone())];
+
+    // Without a fallback section, missing strings cannot be encoded.
+    let mut encoder = ::entropy::write::Encoder::new(options.clone());
+    assert!(encoder.string_at(Some(&missing), &path).is_err());
+
+    let options = options.with_fallback();
+    let mut encoder = ::entropy::write::Encoder::new(options.clone());
+    for value in &values {
+        encoder.string_at(value.as_ref(), &path)
+            .expect("Could not write string");
+    }
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    let mut decoder = ::entropy::read::Decoder::new(options.clone(), Cursor::new(data.clone()))
+        .expect("Could not create decoder");
+    let mut decoded = vec![];
+    for _ in &values {
+        decoded.push(decoder.string_at(&path)
+            .expect("Could not read string"));
+    }
+    assert_eq!(decoded, values);
+
+    // Batched reads interleave references to the fallback section.
+    let mut decoder = ::entropy::read::Decoder::new(options.clone(), Cursor::new(data))
+        .expect("Could not create decoder");
+    let mut tokens = vec![];
+    decoder.tokens_at(TokenKind::String, values.len(), &path, &mut tokens)
+        .expect("Could not read batch");
+    let expected : Vec<_> = values.iter()
+        .cloned()
+        .map(Token::String)
+        .collect();
+    assert_eq!(tokens, expected);
+
+    // Statistics record exactly which strings fell back.
+    let statistics = options.fallback_statistics_for_write()
+        .expect("Fallback statistics are available");
+    assert_eq!(statistics.files, 1);
+    assert_eq!(statistics.values, values.len());
+    assert_eq!(statistics.fallbacks, 3);
+    let strings : Vec<_> = statistics.by_instances()
+        .into_iter()
+        .map(|(fallback, instances)| (fallback.kind, fallback.value.clone(), instances.instances, instances.files))
+        .collect();
+    assert_eq!(strings, vec![
+        (StringKind::StringLiteral, missing.clone(), 2, 1),
+        (StringKind::StringLiteral, other.clone(), 1, 1),
+    ]);
+
+    // The most frequent and longest strings are suggested first.
+    let suggestions = statistics.suggestions(&sample);
+    let suggested : Vec<_> = suggestions.iter()
+        .map(|suggestion| suggestion.fallback.value.clone())
+        .collect();
+    assert_eq!(suggested, vec![missing, other]);
+    assert_eq!(suggestions[0].fallback.path, path);
+
+    // References beyond the fallback section are rejected.
+    let mut reader = FallbackReader::new(&mut Cursor::new(vec![0]))
+        .expect("Could not read empty fallback section");
+    let mut writer = Writer::new(options.backend());
+    FallbackWriter::new().write(&mut writer, StringKind::StringLiteral, &path, 1, Some(&known))
+        .expect("Could not write reference");
+    let data = writer.done()
+        .expect("Could not finalize encoding");
+    let mut source = Reader::new(options.backend(), Cursor::new(data))
+        .expect("Could not create reader");
+    assert!(reader.read(&mut source).is_err());
+}
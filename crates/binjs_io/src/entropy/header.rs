@@ -0,0 +1,129 @@
+//! The header of a file in the entropy format.
+//!
+//! The header records the options that change how symbols are coded, so that
+//! decoders don't need to be configured with the same options as the encoder.
+//! Decoders always use the options of the header, rather than their own.
+//!
+//! Format:
+//! - flags (`u8`), see `FLAG_*`, unknown flags are rejected;
+//! - the bit-level coder (`u8`), see `Backend::to_byte`, unknown coders are rejected;
+//! - the path depth (`varnum`), see `Dictionary::depth`;
+//! - if `FLAG_RECENCY`, the recency window (`varnum`), see `Options::with_recency`.
+//!
+//! If `FLAG_FALLBACK`, the header is followed by the fallback section, see `fallback`.
+
+use super::coder::Backend;
+
+use ::{ TokenReaderError, TokenWriterError };
+use bytes::varnum::{ ReadVarNum, WriteVarNum };
+
+use std::io::Read;
+
+/// Identifier names are first looked up among recently used identifier names.
+const FLAG_RECENCY : u8 = 1;
+
+/// Strings missing from the dictionary are written to a fallback section.
+const FLAG_FALLBACK : u8 = 2;
+
+/// All the flags known to this version.
+const FLAGS : u8 = FLAG_RECENCY | FLAG_FALLBACK;
+
+/// The options recorded in the header of a file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Header {
+    /// The bit-level coder, see `Options::with_backend`.
+    pub backend: Backend,
+
+    /// The amount of path context used to predict values, see `Dictionary::depth`.
+    pub depth: usize,
+
+    /// If specified, the recency window, see `Options::with_recency`.
+    pub recency: Option<usize>,
+
+    /// If `true`, the file has a fallback section, see `Options::with_fallback`.
+    pub fallback: bool,
+}
+impl Header {
+    /// The header of a file written with `options`.
+    pub fn new(options: &::entropy::Options) -> Self {
+        Header {
+            backend: options.backend(),
+            depth: options.shared_dictionary().depth(),
+            recency: options.recency(),
+            fallback: options.fallback(),
+        }
+    }
+
+    pub fn write(&self) -> Result<Vec<u8>, TokenWriterError> {
+        let mut flags = 0;
+        if self.recency.is_some() {
+            flags |= FLAG_RECENCY;
+        }
+        if self.fallback {
+            flags |= FLAG_FALLBACK;
+        }
+        let mut data = vec![flags, self.backend.to_byte()];
+        data.write_varnum(self.depth as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        if let Some(window) = self.recency {
+            data.write_varnum(window as u32)
+                .map_err(TokenWriterError::WriteError)?;
+        }
+        Ok(data)
+    }
+
+    pub fn read<R: Read>(source: &mut R) -> Result<Self, TokenReaderError> {
+        let mut buf = [0; 2];
+        source.read_exact(&mut buf)
+            .map_err(TokenReaderError::ReadError)?;
+        let flags = buf[0];
+        if flags & !FLAGS != 0 {
+            return Err(TokenReaderError::BadHeader);
+        }
+        let backend = Backend::from_byte(buf[1])
+            .ok_or(TokenReaderError::BadHeader)?;
+        let depth = source.read_varnum()
+            .map_err(TokenReaderError::ReadError)? as usize;
+        let recency =
+            if flags & FLAG_RECENCY != 0 {
+                let window = source.read_varnum()
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(window as usize)
+            } else {
+                None
+            };
+        Ok(Header {
+            backend,
+            depth,
+            recency,
+            fallback: flags & FLAG_FALLBACK != 0,
+        })
+    }
+}
+
+#[test]
+fn test_header() {
+    use std::io::Cursor;
+
+    for header in vec![
+        Header::default(),
+        Header { backend: Backend::RANS, ..Header::default() },
+        Header { depth: 3, ..Header::default() },
+        Header { recency: Some(300), ..Header::default() },
+        Header { fallback: true, ..Header::default() },
+    ] {
+        let data = header.write()
+            .expect("Could not write header");
+        let mut source = Cursor::new(&data);
+        assert_eq!(Header::read(&mut source).expect("Could not read header"), header);
+        assert_eq!(source.position() as usize, data.len());
+    }
+
+    // Files written by future versions are rejected.
+    for data in &[[0x80u8, 0, 0], [0, 0x80, 0]] {
+        match Header::read(&mut Cursor::new(&data[..])) {
+            Err(TokenReaderError::BadHeader) => {}
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,682 @@
+//! A fast variant of the entropy format, based on canonical Huffman codes.
+//!
+//! This format uses the same dictionary as the entropy format, but each symbol
+//! is written as a whole number of bits, using a canonical Huffman code computed
+//! when the dictionary is loaded. This wastes up to one bit per symbol wrt
+//! arithmetic coding, but decoding a symbol only requires reading its bits.
+//!
+//! Symbols are decoded with a lookup table indexed by the next bits of the file,
+//! see `Codebook::read`, so that most symbols are decoded in a single step.
+
+// FIXME: Implement lazy functions
+
+use ::{ TokenReaderError, TokenWriterError };
+use ::io::{ FileStructurePrinter, Path, TokenReader, TokenWriter };
+use ::io::statistics::{ Bytes, ContentInfo, Instances };
+use super::bounds::{ self, StreamBound };
+use super::probabilities::SymbolIndex;
+
+use binjs_shared::{ F64, FieldName, IdentifierName, InterfaceName, Node, PropertyKey, SharedString };
+
+use std;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::Read;
+
+use itertools::Itertools;
+
+use range_encoding::CumulativeDistributionFrequency;
+
+/// The maximal length of a code, in bits.
+pub const MAX_CODE_LENGTH : u8 = 32;
+
+/// The maximal number of bits decoded at once with the lookup table of a `Codebook`.
+///
+/// Symbols with longer codes, which are rare by definition, are decoded bit by bit.
+pub const LOOKUP_BITS : u8 = 10;
+
+const INITIAL_BUFFER_SIZE_BYTES : usize = 32768;
+
+/// A canonical Huffman code for the symbols of a distribution.
+///
+/// Symbols are identified by their index in the distribution. If the
+/// distribution has a single symbol, its code is empty.
+#[derive(Clone, Debug)]
+pub struct Codebook {
+    /// For each symbol, `(length, code)`. The code is stored in the
+    /// `length` lowest bits, most significant bit first.
+    codes: Vec<(u8, u32)>,
+
+    /// Symbols, sorted by code.
+    sorted: Vec<u32>,
+
+    /// For each length, the number of symbols with a code of this length.
+    count_by_length: Vec<u32>,
+
+    /// For each length, the first code of this length.
+    first_code_by_length: Vec<u32>,
+
+    /// For each length, the position in `sorted` of the first symbol with a code of this length.
+    first_symbol_by_length: Vec<u32>,
+
+    /// The number of bits decoded at once, i.e. the smallest of `LOOKUP_BITS` and
+    /// the length of the longest code.
+    lookup_bits: u8,
+
+    /// For each value of the next `lookup_bits` bits, `(length, symbol)` if the code of
+    /// `symbol` is a prefix of these bits, or `(0, 0)` if the code is longer than
+    /// `lookup_bits`.
+    lookup: Vec<(u8, u32)>,
+
+    /// For each symbol, its information content in the distribution, in bits, see
+    /// `with_information`. Computed once, rather than for each symbol written.
+    information: Vec<Option<f64>>,
+}
+impl Codebook {
+    /// Compute a code for a distribution with the given number of instances for each symbol.
+    pub fn new(instances: &[u32]) -> Self {
+        let lengths = Self::code_lengths(instances);
+
+        let sorted : Vec<u32> = (0..lengths.len() as u32)
+            .sorted_by_key(|&symbol| (lengths[symbol as usize], symbol));
+
+        let table_len = MAX_CODE_LENGTH as usize + 1;
+        let mut codes = vec![(0, 0); lengths.len()];
+        let mut count_by_length = vec![0; table_len];
+        let mut first_code_by_length = vec![0; table_len];
+        let mut first_symbol_by_length = vec![0; table_len];
+
+        let mut code : u64 = 0;
+        let mut previous_length = 0;
+        for (position, &symbol) in sorted.iter().enumerate() {
+            let length = lengths[symbol as usize];
+            if length != previous_length {
+                code <<= length - previous_length;
+                first_code_by_length[length as usize] = code as u32;
+                first_symbol_by_length[length as usize] = position as u32;
+                previous_length = length;
+            }
+            codes[symbol as usize] = (length, code as u32);
+            count_by_length[length as usize] += 1;
+            code += 1;
+        }
+
+        // Each code of length `length <= lookup_bits` is the prefix of
+        // `2^(lookup_bits - length)` values of the next `lookup_bits` bits.
+        let lookup_bits = std::cmp::min(LOOKUP_BITS, previous_length);
+        let mut lookup = vec![(0, 0); 1 << lookup_bits];
+        for &symbol in &sorted {
+            let (length, code) = codes[symbol as usize];
+            if length == 0 || length > lookup_bits {
+                continue;
+            }
+            let shift = lookup_bits - length;
+            let start = (code as usize) << shift;
+            for entry in &mut lookup[start..start + (1 << shift)] {
+                *entry = (length, symbol);
+            }
+        }
+
+        Codebook {
+            codes,
+            sorted,
+            count_by_length,
+            first_code_by_length,
+            first_symbol_by_length,
+            lookup_bits,
+            lookup,
+            information: Vec::new(),
+        }
+    }
+
+    /// Cache the information content of each symbol in `distribution`, the
+    /// distribution of the same instances, for the statistics of encoders.
+    pub fn with_information(mut self, distribution: &CumulativeDistributionFrequency) -> Self {
+        self.information = (0..self.codes.len())
+            .map(|index| bounds::information(index, distribution))
+            .collect();
+        self
+    }
+
+    /// The number of symbols.
+    pub fn len(&self) -> usize {
+        self.codes.len()
+    }
+
+    /// The `(length, code)` of a symbol.
+    pub fn code(&self, index: usize) -> Option<(u8, u32)> {
+        self.codes.get(index)
+            .cloned()
+    }
+
+    /// The information content of a symbol, in bits, or `None` if it was not
+    /// cached with `with_information`.
+    pub fn information(&self, index: usize) -> Option<f64> {
+        self.information.get(index)
+            .cloned()
+            .and_then(|information| information)
+    }
+
+    /// Compute the length of the code of each symbol, limited to `MAX_CODE_LENGTH`.
+    fn code_lengths(instances: &[u32]) -> Vec<u8> {
+        // Symbols may appear with 0 instances, e.g. in pruned dictionaries. They still need a code.
+        let mut weights : Vec<u64> = instances.iter()
+            .map(|&instances| std::cmp::max(instances, 1) as u64)
+            .collect();
+        loop {
+            let lengths = Self::unlimited_code_lengths(&weights);
+            if lengths.iter().all(|&length| length <= MAX_CODE_LENGTH) {
+                return lengths;
+            }
+            // Flatten the distribution until codes are short enough. This always
+            // terminates, as a uniform distribution of up to 2^32 symbols fits.
+            for weight in weights.iter_mut() {
+                *weight = (*weight + 1) / 2;
+            }
+        }
+    }
+
+    fn unlimited_code_lengths(weights: &[u64]) -> Vec<u8> {
+        if weights.len() <= 1 {
+            return vec![0; weights.len()];
+        }
+        // Nodes `0..weights.len()` are the leaves, the next ones are the inner nodes,
+        // so a parent always has a larger number than its children. Ties are broken by
+        // node number, so the encoder and the decoder always compute the same code.
+        let mut parents = vec![0; 2 * weights.len() - 1];
+        let mut heap : BinaryHeap<_> = weights.iter()
+            .enumerate()
+            .map(|(node, &weight)| Reverse((weight, node)))
+            .collect();
+        let mut next = weights.len();
+        while let (Some(Reverse((weight_1, node_1))), Some(Reverse((weight_2, node_2)))) = (heap.pop(), heap.pop()) {
+            parents[node_1] = next;
+            parents[node_2] = next;
+            heap.push(Reverse((weight_1 + weight_2, next)));
+            next += 1;
+        }
+
+        let root = parents.len() - 1;
+        let mut depths = vec![0u32; parents.len()];
+        for node in (0..root).rev() {
+            depths[node] = depths[parents[node]] + 1;
+        }
+        depths[0..weights.len()].iter()
+            .map(|&depth| std::cmp::min(depth, u8::max_value() as u32) as u8)
+            .collect()
+    }
+
+    /// Read a symbol from `reader`, return its index.
+    ///
+    /// The next `lookup_bits` bits give the symbol directly if its code is not longer.
+    /// Otherwise, the following bits are read one at a time.
+    fn read<R: Read>(&self, reader: &mut BitReader<R>) -> Result<u32, std::io::Error> {
+        if self.sorted.len() == 1 {
+            return Ok(self.sorted[0]);
+        }
+        let mut code = reader.peek(self.lookup_bits)?;
+        match self.lookup[code as usize] {
+            (0, _) => reader.consume(self.lookup_bits)?,
+            (length, symbol) => {
+                reader.consume(length)?;
+                return Ok(symbol);
+            }
+        }
+        for length in self.lookup_bits as usize + 1..MAX_CODE_LENGTH as usize + 1 {
+            code = (code << 1) | reader.bit()? as u32;
+            let count = self.count_by_length[length];
+            let first_code = self.first_code_by_length[length];
+            if count > 0 && code >= first_code && code - first_code < count {
+                let position = self.first_symbol_by_length[length] + code - first_code;
+                return Ok(self.sorted[position as usize]);
+            }
+        }
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Invalid Huffman code"))
+    }
+}
+
+/// Writing bits, most significant bit first.
+struct BitWriter {
+    data: Vec<u8>,
+
+    /// Bits that do not fill a byte yet, in the `pending` lowest bits.
+    current: u64,
+    pending: u8,
+}
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            data: Vec::with_capacity(INITIAL_BUFFER_SIZE_BYTES),
+            current: 0,
+            pending: 0,
+        }
+    }
+
+    fn write(&mut self, length: u8, code: u32) {
+        self.current = (self.current << length) | code as u64;
+        self.pending += length;
+        while self.pending >= 8 {
+            self.pending -= 8;
+            self.data.push((self.current >> self.pending) as u8);
+        }
+        self.current &= (1 << self.pending) - 1;
+    }
+
+    /// Flush pending bits, padding with 0s.
+    fn done(mut self) -> Vec<u8> {
+        if self.pending > 0 {
+            self.data.push((self.current << (8 - self.pending)) as u8);
+        }
+        self.data
+    }
+}
+
+/// Reading bits, most significant bit first.
+struct BitReader<R: Read> {
+    source: R,
+
+    /// Bits read from `source` but not consumed yet, in the `available` lowest bits.
+    buffer: u64,
+    available: u8,
+
+    /// `true` once `source` is exhausted.
+    exhausted: bool,
+}
+impl<R: Read> BitReader<R> {
+    fn new(source: R) -> Self {
+        BitReader {
+            source,
+            buffer: 0,
+            available: 0,
+            exhausted: false,
+        }
+    }
+
+    /// Return the next `count <= 32` bits, without consuming them. Past the end of
+    /// the data, bits are read as 0s, consuming them fails.
+    fn peek(&mut self, count: u8) -> Result<u32, std::io::Error> {
+        while self.available < count && !self.exhausted {
+            let mut buf = [0];
+            match self.source.read_exact(&mut buf) {
+                Ok(()) => {
+                    self.buffer = (self.buffer << 8) | buf[0] as u64;
+                    self.available += 8;
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::UnexpectedEof => self.exhausted = true,
+                Err(err) => return Err(err),
+            }
+        }
+        let bits =
+            if self.available >= count {
+                self.buffer >> (self.available - count)
+            } else {
+                self.buffer << (count - self.available)
+            };
+        Ok((bits & ((1 << count) - 1)) as u32)
+    }
+
+    /// Consume `count` bits, which must have been peeked.
+    fn consume(&mut self, count: u8) -> Result<(), std::io::Error> {
+        if count > self.available {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated Huffman code"));
+        }
+        self.available -= count;
+        self.buffer &= (1 << self.available) - 1;
+        Ok(())
+    }
+
+    fn bit(&mut self) -> Result<bool, std::io::Error> {
+        let bit = self.peek(1)?;
+        self.consume(1)?;
+        Ok(bit == 1)
+    }
+}
+
+/// A Huffman encoder, based on the entropy dictionary.
+pub struct Encoder {
+    /// Bit-level manipulations.
+    writer: BitWriter,
+
+    /// Shared dictionaries.
+    options: ::entropy::Options,
+
+    // --- Statistics.
+
+    /// Measure the number of bits written.
+    content_bits: ContentInfo<usize>,
+
+    /// Measure the number of entries written.
+    content_instances: ContentInfo<Instances>,
+
+    /// Measure the Shannon-optimal size of each stream.
+    content_bounds: ContentInfo<StreamBound>,
+}
+
+impl Encoder {
+    pub fn new(options: ::entropy::Options) -> Self {
+        Encoder {
+            writer: BitWriter::new(),
+            options,
+            content_bits: ContentInfo::with(|_| 0),
+            content_instances: ContentInfo::with(|_| 0.into()),
+            content_bounds: ContentInfo::default(),
+        }
+    }
+}
+
+/// Emit a single symbol.
+///
+/// Usage:
+/// `symbol!(self, name_of_the_probability_table, name_of_the_ContentInfo_field, "Description, used for debugging",  path_in_the_ast,  value_to_encode)`
+macro_rules! symbol {
+    ( $me: ident, $table:ident, $info:ident, $description: expr, $path:expr, $value: expr ) => {
+        {
+            use std::borrow::Borrow;
+
+            let path = $path.borrow();
+            let symbol = $me.options
+                .probability_tables
+                .$table
+                .stats_by_node_value(path, &$value)
+                .ok_or_else(|| {
+                    debug!(target: "entropy", "Couldn't find value {:?} at {:?} ({})",
+                        $value, path, $description);
+                    TokenWriterError::NotInDictionary(format!("{}: {:?} at {:?}", $description, $value, path))
+                })?;
+            let index : usize = symbol.index.into();
+            let (length, code) = symbol.codebook
+                .code(index)
+                .ok_or_else(|| TokenWriterError::NotInDictionary(format!("{}: {:?} at {:?}", $description, $value, path)))?;
+            $me.writer.write(length, code);
+
+            $me.content_bits
+                .$info += length as usize;
+            $me.content_instances
+                .$info += Into::<Instances>::into(1);
+            $me.content_bounds
+                .$info
+                .symbols += 1;
+            if let Some(bits) = symbol.codebook.information(index) {
+                $me.content_bounds
+                    .$info
+                    .bound_bits += bits;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl TokenWriter for Encoder {
+    type Data = Vec<u8>;
+
+    fn done(self) -> Result<Self::Data, TokenWriterError> {
+        *self.options
+            .content_lengths
+            .borrow_mut()
+            +=
+        self.content_bits
+            .into_with(|_, bits| Bytes::from((bits + 7) / 8));
+        *self.options
+            .content_instances
+            .borrow_mut()
+            +=
+        self.content_instances;
+        *self.options
+            .content_bounds
+            .borrow_mut()
+            +=
+        self.content_bounds;
+        Ok(self.writer.done())
+    }
+
+    // --- Primitive values
+
+    fn bool_at(&mut self, value: Option<bool>, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, bool_by_path, bools, "bool_by_path",  path,  value)
+    }
+
+    fn float_at(&mut self, value: Option<f64>, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, float_by_path, floats, "float_by_path",  path,  value.map(F64::from))
+    }
+
+    fn unsigned_long_at(&mut self, value: u32, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, unsigned_long_by_path, unsigned_longs, "unsigned_long_by_path",  path,  value)
+    }
+
+    fn string_at(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, string_literal_by_path, string_literals, "string_literal_by_path",  path,  value.cloned())
+    }
+
+    fn string_enum_at(&mut self, value: &SharedString, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, string_enum_by_path, string_enums, "string_enum_by_path",  path,  value)
+    }
+
+    fn identifier_name_at(&mut self, value: Option<&IdentifierName>, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, identifier_name_by_path, identifier_names, "identifier_name_by_path",  path,  value.cloned())
+    }
+
+    fn property_key_at(&mut self, value: Option<&PropertyKey>, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, property_key_by_path, property_keys, "property_key_by_path",  path,  value.cloned())
+    }
+
+    // --- Composite stuff
+
+    fn enter_tagged_tuple_at(&mut self, _node: &Node, tag: &InterfaceName, _children: &[&FieldName], path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, interface_name_by_path, interface_names, "interface_name_by_path",  path,  tag)
+    }
+
+    fn enter_list_at(&mut self, len: usize, path: &Path) -> Result<(), TokenWriterError> {
+        symbol!(self, list_length_by_path, list_lengths, "list_length_by_path",  path,  Some(len as u32))
+    }
+
+    fn offset_at(&mut self, _path: &Path) -> Result<(), TokenWriterError> {
+        unimplemented!()
+    }
+}
+
+/// A Huffman decoder, based on the entropy dictionary.
+pub struct Decoder<R: Read> {
+    /// Bit-level manipulations.
+    reader: BitReader<R>,
+
+    /// Shared dictionaries.
+    options: ::entropy::Options,
+}
+
+impl<R: Read> FileStructurePrinter for Decoder<R> {
+
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(options: ::entropy::Options, source: R) -> Self {
+        Decoder {
+            reader: BitReader::new(source),
+            options,
+        }
+    }
+}
+
+/// Read a single symbol.
+///
+/// Usage:
+/// `read_symbol!(self, name_of_the_probability_table, "Description, used for debugging", path_in_the_ast)`
+macro_rules! read_symbol {
+    ( $me: ident, $table:ident, $description: expr, $path:expr ) => {
+        {
+            use std::borrow::Borrow;
+            let path = $path.borrow();
+
+            let index = {
+                let codebook = $me.options.probability_tables
+                    .$table
+                    .codebook_at(path)
+                    .ok_or_else(|| TokenReaderError::NotInDictionary(format!("{} at {:?}", $description, $path)))?;
+                codebook.read(&mut $me.reader)
+                    .map_err(TokenReaderError::ReadError)?
+            };
+
+            let value = $me.options.probability_tables
+                .$table
+                .value_by_symbol_index(path, SymbolIndex::new(index as usize))
+                .ok_or_else(|| TokenReaderError::NotInDictionary(format!("{} [{}]", $description, index)))?;
+            Ok(value.clone())
+        }
+    }
+}
+
+impl<R: Read> TokenReader for Decoder<R> {
+    // ---- String types
+
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        read_symbol!(self, string_literal_by_path, "string_literal_by_path", path)
+    }
+
+    fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
+        read_symbol!(self, string_enum_by_path, "string_enum_by_path", path)
+    }
+
+    fn identifier_name_at(&mut self, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
+        read_symbol!(self, identifier_name_by_path, "identifier_name_by_path", path)
+    }
+
+    fn property_key_at(&mut self, path: &Path) -> Result<Option<PropertyKey>, TokenReaderError> {
+        read_symbol!(self, property_key_by_path, "property_key_by_path", path)
+    }
+
+    // ---- Primitive types
+
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        let value = read_symbol!(self, float_by_path, "float_by_path", path)?;
+        Ok(value.map(F64::into))
+    }
+
+    fn unsigned_long_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        read_symbol!(self, unsigned_long_by_path, "unsigned_long_by_path", path)
+    }
+
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        read_symbol!(self, bool_by_path, "bool_by_path", path)
+    }
+
+    // ---- Lazy
+
+    fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        unimplemented!()
+    }
+
+    // ---- Composed types
+
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        let length = read_symbol!(self, list_length_by_path, "list_length_by_path", path)?
+            .ok_or_else(|| TokenReaderError::EmptyList)?;
+            // For the moment, we cannot read an optional list.
+        Ok(length)
+    }
+
+    fn enter_tagged_tuple_at(&mut self, path: &Path) -> Result<(InterfaceName, Option<std::rc::Rc<Box<[FieldName]>>>), TokenReaderError> {
+        let name = read_symbol!(self, interface_name_by_path, "interface_name_by_path", path)?;
+        Ok((name, None))
+    }
+
+    fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        unimplemented!()
+    }
+}
+
+/// Command-line management.
+use clap;
+
+pub struct FormatProvider;
+impl ::FormatProvider for FormatProvider {
+    fn subcommand<'a, 'b>(&self) -> clap::App<'a, 'b> {
+        use clap::*;
+        SubCommand::with_name("entropy-huffman")
+            .about("(EXPERIMENTAL) Encode using Huffman codes computed from an entropy dictionary. Compresses slightly worse than entropy, but decodes faster.")
+            .arg(super::dictionary_arg())
+            .arg(super::path_depth_arg())
+    }
+
+    fn handle_subcommand(&self, matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
+        let options = super::options_of_matches(matches.unwrap())?;
+        Ok(::Format::HuffmanEntropy {
+            options
+        })
+    }
+}
+
+#[test]
+fn test_codebook() {
+    // A single symbol needs no bits.
+    let codebook = Codebook::new(&[10]);
+    assert_eq!(codebook.code(0), Some((0, 0)));
+
+    let codebook = Codebook::new(&[8, 1, 1, 2, 4]);
+    let lengths : Vec<_> = (0..codebook.len())
+        .map(|index| codebook.code(index).unwrap().0)
+        .collect();
+    assert_eq!(lengths, vec![1, 4, 4, 3, 2]);
+
+    // Codes are canonical: ordered by length, then by symbol.
+    assert_eq!(codebook.code(0), Some((1, 0b0)));
+    assert_eq!(codebook.code(4), Some((2, 0b10)));
+    assert_eq!(codebook.code(3), Some((3, 0b110)));
+    assert_eq!(codebook.code(1), Some((4, 0b1110)));
+    assert_eq!(codebook.code(2), Some((4, 0b1111)));
+
+    // Lengths are limited, even for very skewed distributions.
+    let fibonacci : Vec<u32> = (0..45)
+        .scan((1, 1), |state, _| {
+            let result = state.0;
+            *state = (state.1, state.0 + state.1);
+            Some(result)
+        })
+        .collect();
+    let codebook = Codebook::new(&fibonacci);
+    assert!((0..codebook.len()).all(|index| codebook.code(index).unwrap().0 <= MAX_CODE_LENGTH));
+
+    // Information is only available once cached.
+    let instances = vec![8, 1, 1, 2, 4];
+    let codebook = Codebook::new(&instances);
+    assert_eq!(codebook.information(0), None);
+    let distribution = CumulativeDistributionFrequency::new(instances);
+    let codebook = codebook.with_information(&distribution);
+    for index in 0..codebook.len() {
+        assert_eq!(codebook.information(index), bounds::information(index, &distribution));
+    }
+    assert_eq!(codebook.information(codebook.len()), None);
+
+    // Roundtrip, with codes both shorter and longer than `LOOKUP_BITS`.
+    let codebook = Codebook::new(&fibonacci);
+    assert_eq!(codebook.lookup_bits, LOOKUP_BITS);
+    assert!((0..codebook.len()).any(|index| codebook.code(index).unwrap().0 > LOOKUP_BITS));
+    let symbols : Vec<u32> = (0..100)
+        .map(|i| (i * 7) % fibonacci.len() as u32)
+        .collect();
+    let mut writer = BitWriter::new();
+    for &symbol in &symbols {
+        let (length, code) = codebook.code(symbol as usize).unwrap();
+        writer.write(length, code);
+    }
+    let data = writer.done();
+    let mut reader = BitReader::new(std::io::Cursor::new(data.clone()));
+    for &symbol in &symbols {
+        assert_eq!(codebook.read(&mut reader).expect("Could not read symbol"), symbol);
+    }
+
+    // Codes shorter than `LOOKUP_BITS` may be read at the end of the data, but
+    // truncated codes are rejected.
+    let last = fibonacci.len() as u32 - 1;
+    let (length, code) = codebook.code(last as usize).unwrap();
+    assert!(length < 8);
+    let mut writer = BitWriter::new();
+    writer.write(length, code);
+    let mut reader = BitReader::new(std::io::Cursor::new(writer.done()));
+    assert_eq!(codebook.read(&mut reader).expect("Could not read last symbol"), last);
+
+    let (length, code) = codebook.code(0).unwrap();
+    assert!(length > 8);
+    let mut writer = BitWriter::new();
+    writer.write(8, code >> (length - 8));
+    let mut reader = BitReader::new(std::io::Cursor::new(writer.done()));
+    assert_eq!(codebook.read(&mut reader).unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+}
@@ -54,27 +54,48 @@
 //! ----- Initially, start with everything equi-likely. We'll add a predefined
 //! and/or custom dictionary later.
 
+pub mod adaptive;
+pub mod bounds;
+pub mod coder;
+pub mod delta;
 pub mod dictionary;
+pub mod fallback;
+pub mod header;
+pub mod huffman;
 pub mod read;
+pub mod recency;
+pub mod smoothing;
 pub mod write;
 
 mod predict;
 pub mod probabilities;
 
+use self::bounds::StreamBound;
+use self::coder::Backend;
 use self::dictionary::Dictionary;
+use self::fallback::FallbackStatistics;
 use self::probabilities::SymbolInfo;
+use self::recency::RecencyStatistics;
 
 use ::io::statistics::{ Bytes, BytesAndInstances, Instances, ContentInfo };
 
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct Options {
     /// The (shared) AST probability tables, generally shipped separately
     /// from the compressed files and used to predict the probability
     /// of a symbol occurring at a specific position in the AST.
-    probability_tables: Dictionary<SymbolInfo>,
+    ///
+    /// Encoders and decoders never modify the tables, so they may be
+    /// shared between any number of options, including across threads.
+    probability_tables: Arc<Dictionary<SymbolInfo>>,
+
+    /// The bit-level coder. The coder is recorded in the header of each file,
+    /// see `header`, decoders don't need it.
+    backend: Backend,
 
     /// Statistics obtained while writing: number of bytes written.
     /// If several files are written with the same options, we accumulate
@@ -85,16 +106,145 @@ pub struct Options {
     /// kind written. If several files are written with the same options,
     /// we accumulate statistics.
     content_instances: Rc<RefCell<ContentInfo<Instances>>>,
+
+    /// Statistics obtained while writing: number of symbols written in each
+    /// stream and their Shannon-optimal size, see `bounds`. If several files
+    /// are written with the same options, we accumulate statistics.
+    content_bounds: Rc<RefCell<ContentInfo<StreamBound>>>,
+
+    /// If specified, identifier names are first looked up among this many
+    /// recently used identifier names, see `recency`. The window is recorded
+    /// in the header of each file, see `header`, decoders don't need it.
+    recency: Option<usize>,
+
+    /// Statistics obtained while writing with `recency`. If several files
+    /// are written with the same options, we accumulate statistics.
+    recency_statistics: Rc<RefCell<RecencyStatistics>>,
+
+    /// If `true`, strings missing from the dictionary are written to a
+    /// per-file fallback section, see `fallback`, instead of failing. The
+    /// setting is recorded in the header of each file, see `header`, decoders
+    /// don't need it.
+    fallback: bool,
+
+    /// Statistics obtained while writing with `fallback`. If several files
+    /// are written with the same options, we accumulate statistics.
+    fallback_statistics: Rc<RefCell<FallbackStatistics>>,
 }
 impl Options {
     pub fn new(probability_tables:Dictionary<SymbolInfo>) -> Self {
+        Self::with_shared_dictionary(Arc::new(probability_tables))
+    }
+
+    /// Create options from probability tables loaded once and shared, e.g.
+    /// between the threads of a server. The tables are not copied.
+    pub fn with_shared_dictionary(probability_tables: Arc<Dictionary<SymbolInfo>>) -> Self {
         Options {
             probability_tables,
+            backend: Backend::default(),
             content_lengths: Rc::new(RefCell::new(ContentInfo::default())),
             content_instances: Rc::new(RefCell::new(ContentInfo::default())),
+            content_bounds: Rc::new(RefCell::new(ContentInfo::default())),
+            recency: None,
+            recency_statistics: Rc::new(RefCell::new(RecencyStatistics::default())),
+            fallback: false,
+            fallback_statistics: Rc::new(RefCell::new(FallbackStatistics::default())),
+        }
+    }
+
+    /// The same options, with new statistics, so that files written with the
+    /// result do not affect the statistics of `self`. The probability tables
+    /// are shared, not copied.
+    pub fn with_new_statistics(&self) -> Self {
+        Options {
+            backend: self.backend,
+            recency: self.recency,
+            fallback: self.fallback,
+            ..Self::with_shared_dictionary(self.probability_tables.clone())
+        }
+    }
+
+    /// Use a specific bit-level coder.
+    pub fn with_backend(self, backend: Backend) -> Self {
+        Options {
+            backend,
+            ..self
+        }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// Look up identifier names among the `window` most recently used
+    /// identifier names before looking them up in the dictionary.
+    pub fn with_recency(self, window: usize) -> Self {
+        Options {
+            recency: Some(window),
+            ..self
+        }
+    }
+
+    pub fn recency(&self) -> Option<usize> {
+        self.recency
+    }
+
+    /// Return the statistics on recently used identifier names, if `recency`
+    /// was specified.
+    pub fn recency_statistics_for_write(&self) -> Option<RecencyStatistics> {
+        self.recency.map(|_| self.recency_statistics.borrow().clone())
+    }
+
+    /// Write strings missing from the dictionary to a fallback section.
+    pub fn with_fallback(self) -> Self {
+        Options {
+            fallback: true,
+            ..self
         }
     }
 
+    pub fn fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// Return the statistics on strings written to the fallback section, if
+    /// `fallback` was specified.
+    pub fn fallback_statistics_for_write(&self) -> Option<FallbackStatistics> {
+        if self.fallback {
+            Some(self.fallback_statistics.borrow().clone())
+        } else {
+            None
+        }
+    }
+
+    /// The probability tables, e.g. to share them with other options.
+    pub fn shared_dictionary(&self) -> &Arc<Dictionary<SymbolInfo>> {
+        &self.probability_tables
+    }
+
+    /// Return the number of bytes written so far in each category of content, e.g.
+    /// to compute the bytes of a single file from two snapshots, see `ContentInfo::since`.
+    pub fn content_lengths_for_write(&self) -> ContentInfo<Bytes> {
+        self.content_lengths.borrow().clone()
+    }
+
+    /// As `content_lengths_for_write`, for the number of instances of each category.
+    pub fn content_instances_for_write(&self) -> ContentInfo<Instances> {
+        self.content_instances.borrow().clone()
+    }
+
+    /// Return the number of bytes written so far in each stream, compared with the
+    /// Shannon-optimal size of the stream given the probabilities of the dictionary,
+    /// to find the streams in which the coder loses bytes, see `bounds`.
+    pub fn entropy_bounds_for_write(&self) -> ContentInfo<StreamBound> {
+        let mut bounds = self.content_bounds.borrow().clone();
+        let lengths = self.content_lengths.borrow();
+        for ((_, bound), (_, bytes)) in bounds.iter_mut().zip(lengths.iter()) {
+            bound.actual_bytes = Into::<usize>::into(*bytes);
+        }
+        bounds
+    }
+
     /// Return the statistics as (number of instances, number of bytes).
     pub fn statistics_for_write(&self) -> ContentInfo<BytesAndInstances> {
         let borrow_lengths = self.content_lengths.borrow();
@@ -116,44 +266,91 @@ impl Options {
 /// Command-line management.
 use clap;
 
+/// The command-line argument used by dictionary-based formats to specify the dictionary.
+fn dictionary_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("dictionary")
+        .help("Path to external probability tables dictionary (generated by binjs_generate_prediction_tables)")
+        .long("dictionary")
+        .takes_value(true)
+        .required(true)
+}
+
+/// The command-line argument used by dictionary-based formats to specify the path depth.
+fn path_depth_arg<'a, 'b>() -> clap::Arg<'a, 'b> {
+    clap::Arg::with_name("path-depth")
+        .long("path-depth")
+        .takes_value(true)
+        .validator(|s| s.parse::<usize>()
+            .map(|_| ())
+            .map_err(|e| format!("Invalid number {}", e)))
+        .help("Amount of path context used to predict values. 0 = no context, 1 = parent and field, 2 = also grand parent, etc. Must not exceed the depth used to build the dictionary, which is the default. The depth is recorded in each file, decoding with a dictionary of another depth fails.")
+}
+
+/// Load the dictionary specified by `dictionary_arg` and `path_depth_arg`.
+fn options_of_matches(matches: &clap::ArgMatches) -> Result<Options, std::io::Error> {
+    use bincode;
+    use self::probabilities::InstancesToProbabilities;
+
+    let probability_tables_path = matches.value_of("dictionary")
+        .unwrap(); // Guaranteed by `clap`.
+    let probability_tables_source = std::fs::File::open(&probability_tables_path)
+        .expect("Could not open dictionary");
+    let probability_tables : Dictionary<Instances> = bincode::deserialize_from(probability_tables_source)
+        .expect("Could not decode dictionary");
+
+    let probability_tables = match matches.value_of("path-depth") {
+        None => probability_tables,
+        Some(depth) => {
+            let depth = depth.parse::<usize>()
+                .unwrap(); // Guaranteed by `clap`.
+            let dictionary_depth = probability_tables.depth();
+            probability_tables.with_depth(depth)
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                    format!("Path depth {} exceeds the depth of the dictionary ({})", depth, dictionary_depth)))?
+        }
+    };
+
+    Ok(Options::new(probability_tables.instances_to_probabilities("probability_tables")))
+}
+
 pub struct FormatProvider;
 impl ::FormatProvider for FormatProvider {
     fn subcommand<'a, 'b>(&self) -> clap::App<'a, 'b> {
         use clap::*;
         SubCommand::with_name("entropy")
             .about("(EXPERIMENTAL) Encode using entropy compression. This format should eventually produce very good compression ratio.")
-            .arg(Arg::with_name("dictionary")
-                .help("Path to external probability tables dictionary (generated by binjs_generate_prediction_tables)")
-                .long("dictionary")
-                .takes_value(true)
-                .required(true)
-            )
-            .arg(Arg::with_name("path-depth")
-                .long("path-depth")
+            .arg(dictionary_arg())
+            .arg(path_depth_arg())
+            .arg(coder::backend_arg())
+            .arg(Arg::with_name("recent-identifiers")
+                .long("recent-identifiers")
                 .takes_value(true)
-                .default_value("3")
-            )
+                .min_values(0)
+                .max_values(1)
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Code identifier names as a position among this many recently used identifier names when possible, falling back to the dictionary. If the window is not specified, it defaults to 16. The window is recorded in each file, decoders don't need it."))
+            .arg(Arg::with_name("fallback")
+                .long("fallback")
+                .help("Write strings missing from the dictionary to a section of each file, instead of failing. The setting is recorded in each file, decoders don't need it."))
     }
 
     fn handle_subcommand(&self, matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
-        use bincode;
-        use self::probabilities::InstancesToProbabilities;
-
         let matches = matches.unwrap();
-
-        let probability_tables_path = matches.value_of("dictionary")
-            .unwrap(); // Guaranteed by `clap`.
-        let probability_tables_source = std::fs::File::open(&probability_tables_path)
-            .expect("Could not open dictionary");
-        let probability_tables : Dictionary<Instances> = bincode::deserialize_from(probability_tables_source)
-            .expect("Could not decode dictionary");
-
+        let mut options = options_of_matches(matches)?
+            .with_backend(coder::backend_of_matches(matches));
+        if matches.is_present("recent-identifiers") {
+            let window = matches.value_of("recent-identifiers")
+                .map_or(recency::DEFAULT_WINDOW, |window| window.parse()
+                    .unwrap()); // Checked by the validator.
+            options = options.with_recency(window);
+        }
+        if matches.is_present("fallback") {
+            options = options.with_fallback();
+        }
         Ok(::Format::Entropy {
-            options: Options {
-                probability_tables: probability_tables.instances_to_probabilities("probability_tables"),
-                content_lengths: Rc::new(RefCell::new(ContentInfo::default())),
-                content_instances: Rc::new(RefCell::new(ContentInfo::default())),
-            }
+            options
         })
     }
 }
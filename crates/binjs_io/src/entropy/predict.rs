@@ -4,10 +4,9 @@ use entropy::probabilities::{ InstancesToProbabilities, SymbolIndex, SymbolInfo
 use binjs_shared::{ FieldName, InterfaceName };
 
 use std;
-use std::cell::RefCell;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[allow(unused_imports)] // We keep enabling/disabling this.
 use itertools::Itertools;
@@ -19,13 +18,13 @@ pub type IOPathItem = binjs_shared::ast::PathItem<InterfaceName, (/* child index
 
 /// A newtype for `usize` used to represent an index in a dictionary of values.
 #[derive(Add, Constructor, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, From, Into, Debug, Hash, Serialize, Deserialize)]
-struct DictionaryIndex(usize);
+pub struct DictionaryIndex(usize);
 
 /// A newtype for `usize` used to represent a reference to a value already encountered.
 ///
 /// By convention, `0` is the latest value, `1` the value before, etc.
 #[derive(Constructor, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Into, Debug, Serialize, Deserialize)]
-struct BackReference(usize);
+pub struct BackReference(usize);
 
 
 mod context_information {
@@ -73,6 +72,16 @@ mod context_information {
         pub fn len(&self) -> usize {
             self.stats_by_node_value.len()
         }
+
+        /// Iterate through the values seen in this context, along with their statistics.
+        pub fn iter(&self) -> impl Iterator<Item=(&NodeValue, &Statistics)> {
+            self.stats_by_node_value.iter()
+        }
+
+        /// Get the statistics of a value in this context.
+        pub fn get(&self, value: &NodeValue) -> Option<&Statistics> {
+            self.stats_by_node_value.get(value)
+        }
     }
 
     // Methods that make sense only when we have finished computing frequency information.
@@ -99,6 +108,37 @@ mod context_information {
             .and_modify(|instances| *instances += 1.into())
             .or_insert(1.into());
         }
+
+        /// Register a number of instances of a value as being used in this context.
+        pub fn add_instances(&mut self, node_value: NodeValue, instances: Instances) {
+            self.stats_by_node_value.entry(node_value)
+            .and_modify(|existing| *existing += instances)
+            .or_insert(instances);
+        }
+
+        /// Retain only the values for which `f` returns `true`.
+        pub fn retain<F>(&mut self, f: F) where F: FnMut(&NodeValue, &mut Instances) -> bool {
+            self.stats_by_node_value.retain(f)
+        }
+
+        /// The total number of instances of all values in this context.
+        pub fn total(&self) -> usize {
+            self.stats_by_node_value.values()
+                .map(|instances| Into::<usize>::into(*instances))
+                .sum()
+        }
+
+        /// The Shannon entropy of the values in this context, i.e. the
+        /// number of bits per symbol required by an optimal encoding.
+        pub fn entropy(&self) -> f64 {
+            let total = self.total() as f64;
+            self.stats_by_node_value.values()
+                .map(|instances| {
+                    let probability = Into::<usize>::into(*instances) as f64 / total;
+                    - probability * probability.log2()
+                })
+                .sum()
+        }
     }
 
     impl<NodeValue> ::entropy::probabilities::InstancesToProbabilities for ContextInformation<NodeValue, Instances> where NodeValue: Clone + Eq + Hash + Ord {
@@ -107,11 +147,13 @@ mod context_information {
             let stats_by_node_value = self.stats_by_node_value.into_iter()
                 .sorted_by(|(value_1, _), (value_2, _)| Ord::cmp(value_1, value_2)); // We need to ensure that the order remains stable across process restarts.
 
-            let instances = stats_by_node_value.iter()
+            let instances : Vec<_> = stats_by_node_value.iter()
                 .map(|(_, instances)| Into::<usize>::into(instances.clone()) as u32)
                 .collect();
 
-            let distribution = std::rc::Rc::new(std::cell::RefCell::new(range_encoding::CumulativeDistributionFrequency::new(instances)));
+            let codebook = ::entropy::huffman::Codebook::new(&instances);
+            let distribution = std::sync::Arc::new(range_encoding::CumulativeDistributionFrequency::new(instances));
+            let codebook = std::sync::Arc::new(codebook.with_information(&distribution));
 
             let (stats_by_node_value, value_by_symbol_index): (HashMap<_, _>, Vec<_>) = stats_by_node_value
                 .into_iter()
@@ -120,6 +162,7 @@ mod context_information {
                     let for_stats_by_node_value = (value.clone(), SymbolInfo {
                         index: index.into(),
                         distribution: distribution.clone(),
+                        codebook: codebook.clone(),
                     });
                     let for_value_by_symbol_index = value;
                     (for_stats_by_node_value, for_value_by_symbol_index)
@@ -132,7 +175,7 @@ mod context_information {
         }
     }
 }
-use self::context_information::ContextInformation;
+pub use self::context_information::ContextInformation;
 
 /// A generic mechanism used to predict possible values in a given context (e.g.
 /// AST path or file position) in a file.
@@ -182,6 +225,17 @@ impl<Context, NodeValue, Statistics> ContextPredict<Context, NodeValue, Statisti
             .map(ContextInformation::len)
             .sum()
     }
+
+    /// Iterate through the contexts known to this predictor, along with
+    /// the values seen in each context.
+    pub fn iter(&self) -> impl Iterator<Item=(&Context, &ContextInformation<NodeValue, Statistics>)> {
+        self.by_context.iter()
+    }
+
+    /// Get the values seen in a context.
+    pub fn get(&self, context: &Context) -> Option<&ContextInformation<NodeValue, Statistics>> {
+        self.by_context.get(context)
+    }
 }
 
 impl<Context, NodeValue> ContextPredict<Context, NodeValue, Instances> where Context: Eq + Hash + Clone, NodeValue: Eq + Hash + Clone {
@@ -191,6 +245,16 @@ impl<Context, NodeValue> ContextPredict<Context, NodeValue, Instances> where Con
             .or_insert_with(|| ContextInformation::new());
         stats_by_node_value.add(value)
     }
+
+    /// Retain only the (context, value) pairs for which `f` returns `true`.
+    ///
+    /// Contexts left without any value are removed.
+    pub fn retain<F>(&mut self, mut f: F) where F: FnMut(&Context, &NodeValue, &Instances) -> bool {
+        for (context, info) in self.by_context.iter_mut() {
+            info.retain(|value, instances| f(context, value, &*instances));
+        }
+        self.by_context.retain(|_, info| info.len() > 0);
+    }
 }
 
 impl<Context, NodeValue> ContextPredict<Context, NodeValue, SymbolInfo> where Context: Eq + Hash + Clone, NodeValue: Eq + Hash + Clone {
@@ -199,7 +263,7 @@ impl<Context, NodeValue> ContextPredict<Context, NodeValue, SymbolInfo> where Co
     /// This method is only implemented when `Statistics=SymbolInfo` as the index is initialized
     /// by `instances_to_probabilities`. The index corresponds to the one defined in
     /// the `SymbolInfo`.
-    pub fn value_by_symbol_index<C2: ?Sized>(&mut self, context: &C2, index: SymbolIndex) -> Option<&NodeValue>
+    pub fn value_by_symbol_index<C2: ?Sized>(&self, context: &C2, index: SymbolIndex) -> Option<&NodeValue>
         where
             Context: std::borrow::Borrow<C2>,
             C2: Hash + Eq
@@ -301,6 +365,22 @@ impl<NodeValue, Statistics> PathPredict<NodeValue, Statistics> where NodeValue:
         self.context_predict.len()
     }
 
+    /// The amount of context used by this predictor.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Iterate through the paths known to this predictor, along with
+    /// the values seen at each path.
+    pub fn iter(&self) -> impl Iterator<Item=(&IOPath, &ContextInformation<NodeValue, Statistics>)> {
+        self.context_predict.iter()
+    }
+
+    /// Get the values seen at a path.
+    pub fn get(&self, path: &IOPath) -> Option<&ContextInformation<NodeValue, Statistics>> {
+        self.context_predict.get(path)
+    }
+
     /// All the paths known to this predictor.
     ///
     /// Used mainly for debugging.
@@ -325,19 +405,69 @@ impl<NodeValue> PathPredict<NodeValue, Instances> where NodeValue: Eq + Hash + C
         as_path.extend_from_slice(tail);
         self.context_predict.add(as_path, value);
     }
+
+    /// Retain only the (path, value) pairs for which `f` returns `true`.
+    ///
+    /// Paths left without any value are removed.
+    pub fn retain<F>(&mut self, f: F) where F: FnMut(&IOPath, &NodeValue, &Instances) -> bool {
+        self.context_predict.retain(f)
+    }
+
+    /// Iterate through the paths known to this predictor, along with the values seen
+    /// at each path, updating their number of instances.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item=(&IOPath, &mut ContextInformation<NodeValue, Instances>)> {
+        self.context_predict.by_context.iter_mut()
+    }
+
+    /// Add the (path, value) pairs of `other` to this predictor, with their number of
+    /// instances multiplied by `scale`. Pairs keep at least one instance.
+    ///
+    /// `other` must have the same depth.
+    pub fn merge_scaled(&mut self, other: &Self, scale: f64) {
+        assert_eq!(self.depth, other.depth);
+        for (path, info) in other.iter() {
+            let merged = self.context_predict.by_context.entry(path.clone())
+                .or_insert_with(|| ContextInformation::new());
+            for (value, instances) in info.iter() {
+                merged.add_instances(value.clone(), scale_instances(*instances, scale));
+            }
+        }
+    }
+
+    /// Reduce the amount of context used by this predictor, merging the
+    /// statistics of all paths that end with the same `depth` items.
+    ///
+    /// Return `None` if `depth` is larger than the current depth, as the
+    /// missing context cannot be recovered.
+    pub fn with_depth(self, depth: usize) -> Option<Self> {
+        if depth > self.depth {
+            return None;
+        }
+        let mut result = PathPredict::new(depth);
+        for (path, info) in self.context_predict.by_context {
+            let mut tail = IOPath::new();
+            tail.extend_from_slice(path.tail(depth));
+            let merged = result.context_predict.by_context.entry(tail)
+                .or_insert_with(|| ContextInformation::new());
+            for (value, instances) in info.iter() {
+                merged.add_instances(value.clone(), *instances);
+            }
+        }
+        Some(result)
+    }
 }
 impl<NodeValue> PathPredict<NodeValue, SymbolInfo> where NodeValue: Eq + Hash + Clone {
     /// Get a value by path and index.
     ///
     /// This method is only implemented when `Statistics=SymbolInfo` as the index is initialized
     /// by `instances_to_probabilities`.
-    pub fn value_by_symbol_index(&mut self, path: &[IOPathItem], index: SymbolIndex) -> Option<&NodeValue> {
+    pub fn value_by_symbol_index(&self, path: &[IOPathItem], index: SymbolIndex) -> Option<&NodeValue> {
         let tail = self.tail(path);
         self.context_predict.value_by_symbol_index(tail, index)
     }
 
 
-    pub fn stats_by_node_value(&mut self, path: &[IOPathItem], value: &NodeValue) -> Option<&SymbolInfo> {
+    pub fn stats_by_node_value(&self, path: &[IOPathItem], value: &NodeValue) -> Option<&SymbolInfo> {
         let tail = self.tail(path);
         self.context_predict.stats_by_node_value(tail, value)
     }
@@ -349,23 +479,36 @@ impl<NodeValue> PathPredict<NodeValue, SymbolInfo> where NodeValue: Eq + Hash +
 
 
     /// Get frequency information for a given path.
-    pub fn frequencies_at(&mut self, path: &[IOPathItem]) -> Option<&Rc<RefCell<range_encoding::CumulativeDistributionFrequency>>> {
+    pub fn frequencies_at(&self, path: &[IOPathItem]) -> Option<&Arc<range_encoding::CumulativeDistributionFrequency>> {
         let tail = self.tail(path);
-        let info =
-            if let Some(info) = self.context_predict
-                .by_context
-                .get_mut(tail)
-            {
-                info
-            } else {
-                return None;
-            };
-        info
-            .stats_by_node_value_mut()
-            .values_mut()
+        self.context_predict
+            .by_context
+            .get(tail)?
+            .stats_by_node_value()
+            .values()
             .next()
             .map(|any| &any.distribution)
     }
+
+    /// Get the values and frequency information for a given path.
+    pub fn context_at(&self, path: &[IOPathItem]) -> Option<&ContextInformation<NodeValue, SymbolInfo>> {
+        let tail = self.tail(path);
+        self.context_predict
+            .by_context
+            .get(tail)
+    }
+
+    /// Get the Huffman code for a given path.
+    pub fn codebook_at(&self, path: &[IOPathItem]) -> Option<&Arc<::entropy::huffman::Codebook>> {
+        let tail = self.tail(path);
+        self.context_predict
+            .by_context
+            .get(tail)?
+            .stats_by_node_value()
+            .values()
+            .next()
+            .map(|any| &any.codebook)
+    }
 }
 
 
@@ -373,7 +516,7 @@ impl<NodeValue> PathPredict<NodeValue, SymbolInfo> where NodeValue: Eq + Hash +
 
 /// An index for a value in `WindowPredict`.
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize, PartialOrd, Ord)]
-enum WindowPrediction {
+pub enum WindowPrediction {
     /// A recently encountered value.
     ///
     /// `0` is the index of the latest value, `width - 1` is the index of the oldest
@@ -386,6 +529,24 @@ enum WindowPrediction {
     DictionaryIndex(DictionaryIndex),
 }
 
+impl WindowPrediction {
+    /// Return `true` if this is a reference to a recently encountered value.
+    pub fn is_back_reference(&self) -> bool {
+        match *self {
+            WindowPrediction::BackReference(_) => true,
+            WindowPrediction::DictionaryIndex(_) => false,
+        }
+    }
+}
+impl std::fmt::Display for WindowPrediction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            WindowPrediction::BackReference(BackReference(index)) => write!(f, "back reference {}", index),
+            WindowPrediction::DictionaryIndex(DictionaryIndex(index)) => write!(f, "dictionary index {}", index),
+        }
+    }
+}
+
 /// A prediction mechanism based on a sliding window.
 ///
 /// Whenever encoding/decoding a value, if this value is one of the `width` latest
@@ -425,6 +586,21 @@ impl<NodeValue, Statistics> WindowPredict<NodeValue, Statistics> where NodeValue
         }
     }
 
+    /// The window width.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// All the values in the global dictionary, by dictionary index.
+    pub fn values(&self) -> &[NodeValue] {
+        &self.value_by_dictionary_index
+    }
+
+    /// The statistics on back references and dictionary indices.
+    pub fn info(&self) -> &ContextInformation<WindowPrediction, Statistics> {
+        &self.info
+    }
+
     /// Update current window by moving the value at index `index` to the latest-seen
     /// position (index 0).
     ///
@@ -504,6 +680,83 @@ impl<NodeValue> WindowPredict<NodeValue, Instances> where NodeValue: Clone + Eq
         };
         self.info.add(symbol);
     }
+
+    /// Add the values and predictions of `other` to this predictor, with their number of
+    /// instances multiplied by `scale`. Predictions keep at least one instance.
+    ///
+    /// The values of `other` missing from this predictor are appended to its global
+    /// dictionary. Back references of `other` beyond the width of this predictor are
+    /// dropped.
+    pub fn merge_scaled(&mut self, other: &Self, scale: f64) {
+        let mut renumbered = HashMap::new();
+        for (index, value) in other.value_by_dictionary_index.iter().enumerate() {
+            let number_of_values = self.value_by_dictionary_index.len();
+            let new_index = *self.dictionary_index_by_value.entry(value.clone())
+                .or_insert(DictionaryIndex(number_of_values));
+            if new_index == DictionaryIndex(number_of_values) {
+                self.value_by_dictionary_index.push(value.clone());
+            }
+            renumbered.insert(DictionaryIndex(index), new_index);
+        }
+        for (prediction, instances) in other.info.iter() {
+            let prediction = match *prediction {
+                WindowPrediction::BackReference(BackReference(index)) if index >= self.width => continue,
+                WindowPrediction::BackReference(_) => prediction.clone(),
+                WindowPrediction::DictionaryIndex(ref index) => match renumbered.get(index) {
+                    Some(new_index) => WindowPrediction::DictionaryIndex(*new_index),
+                    None => continue
+                }
+            };
+            self.info.add_instances(prediction, scale_instances(*instances, scale));
+        }
+    }
+
+    /// The statistics on back references and dictionary indices, for updating their
+    /// number of instances.
+    pub fn info_mut(&mut self) -> &mut ContextInformation<WindowPrediction, Instances> {
+        &mut self.info
+    }
+
+    /// Retain only the values of the global dictionary for which `f` returns `true`.
+    ///
+    /// `f` receives each value along with the number of times it was referenced
+    /// by dictionary index. Remaining values are renumbered, back references are
+    /// left unchanged.
+    pub fn retain<F>(&mut self, mut f: F) where F: FnMut(&NodeValue, &Instances) -> bool {
+        let values = std::mem::replace(&mut self.value_by_dictionary_index, Vec::new());
+        self.dictionary_index_by_value.clear();
+
+        let mut renumbered = HashMap::new();
+        for (index, value) in values.into_iter().enumerate() {
+            let index = DictionaryIndex(index);
+            let instances = self.info.get(&WindowPrediction::DictionaryIndex(index))
+                .cloned()
+                .unwrap_or_else(|| 0.into());
+            if !f(&value, &instances) {
+                continue;
+            }
+            let new_index = DictionaryIndex(self.value_by_dictionary_index.len());
+            renumbered.insert(index, new_index);
+            self.dictionary_index_by_value.insert(value.clone(), new_index);
+            self.value_by_dictionary_index.push(value);
+        }
+
+        let mut info = ContextInformation::new();
+        for (prediction, instances) in self.info.iter() {
+            let prediction = match *prediction {
+                WindowPrediction::BackReference(_) => prediction.clone(),
+                WindowPrediction::DictionaryIndex(ref index) => match renumbered.get(index) {
+                    Some(new_index) => WindowPrediction::DictionaryIndex(*new_index),
+                    None => continue
+                }
+            };
+            info.add_instances(prediction, *instances);
+        }
+        self.info = info;
+
+        let dictionary_index_by_value = &self.dictionary_index_by_value;
+        self.latest_values.retain(|value| dictionary_index_by_value.contains_key(value));
+    }
 }
 
 impl<NodeValue> WindowPredict<NodeValue, SymbolInfo> where NodeValue: Clone + Eq + std::hash::Hash + std::fmt::Debug {
@@ -553,6 +806,12 @@ impl<NodeValue> WindowPredict<NodeValue, SymbolInfo> where NodeValue: Clone + Eq
     }
 }
 
+/// Multiply a number of instances by `scale`, keeping at least one instance.
+fn scale_instances(instances: Instances, scale: f64) -> Instances {
+    let scaled = (Into::<usize>::into(instances) as f64 * scale).round() as usize;
+    std::cmp::max(scaled, 1).into()
+}
+
 impl<NodeValue> InstancesToProbabilities for WindowPredict<NodeValue, Instances> where NodeValue: Clone + Eq + Hash + Ord {
     type AsProbabilities = WindowPredict<NodeValue, SymbolInfo>;
     fn instances_to_probabilities(self, _description: &str) -> WindowPredict<NodeValue, SymbolInfo> {
@@ -565,3 +824,49 @@ impl<NodeValue> InstancesToProbabilities for WindowPredict<NodeValue, Instances>
         }
     }
 }
+
+#[test]
+fn test_entropy() {
+    let mut predict : PathPredict<&str, Instances> = PathPredict::new(1);
+    let path = IOPath::new();
+    predict.add(path.tail(1), "a");
+    predict.add(path.tail(1), "a");
+    predict.add(path.tail(1), "b");
+    predict.add(path.tail(1), "c");
+    let info = predict.get(&path)
+        .expect("Path should be known");
+    assert_eq!(info.total(), 4);
+    assert_eq!(info.entropy(), 1.5);
+
+    let mut predict : PathPredict<&str, Instances> = PathPredict::new(1);
+    predict.add(path.tail(1), "a");
+    assert_eq!(predict.get(&path).unwrap().entropy(), 0.);
+}
+
+#[test]
+fn test_with_depth() {
+    use binjs_shared::ast::PathItem;
+
+    let item = |interface: &'static str, field: &'static str| PathItem {
+        interface: InterfaceName::from_str(interface),
+        field: (0, FieldName::from_str(field)),
+    };
+    let mut predict : PathPredict<&str, Instances> = PathPredict::new(2);
+    predict.add(&[item("A", "a"), item("C", "c")], "x");
+    predict.add(&[item("B", "b"), item("C", "c")], "x");
+    predict.add(&[item("B", "b"), item("C", "c")], "y");
+    assert_eq!(predict.paths().count(), 2);
+
+    assert!(predict.clone().with_depth(3).is_none());
+
+    let predict = predict.with_depth(1)
+        .expect("Could not reduce depth");
+    assert_eq!(predict.depth(), 1);
+    assert_eq!(predict.paths().count(), 1);
+    let mut path = IOPath::new();
+    path.extend_from_slice(&[item("C", "c")]);
+    let info = predict.get(&path)
+        .expect("Path should be known");
+    assert_eq!(info.total(), 3);
+    assert_eq!(info.len(), 2);
+}
@@ -1,5 +1,9 @@
-use std::cell::RefCell;
-use std::rc::Rc;
+use entropy::huffman::Codebook;
+
+use range_encoding::CumulativeDistributionFrequency;
+
+use std::collections::HashMap;
+use std::sync::Arc;
 
 /// A newtype for `usize` used to count the number of some item in a given file.
 #[derive(Default, Serialize, Deserialize, From, Into, AddAssign, Clone, Copy, Constructor)]
@@ -15,7 +19,46 @@ pub struct SymbolInfo {
     pub index: SymbolIndex,
 
     /// The Cumulative Distribution Frequency (CDF), shared between a number of symbols.
-    pub distribution: Rc<RefCell<range_encoding::CumulativeDistributionFrequency>>,
+    ///
+    /// Shared read-only, so that dictionaries may be shared between threads. Bit-level
+    /// coders that require mutable access use their own copy, see `Distributions`.
+    pub distribution: Arc<CumulativeDistributionFrequency>,
+
+    /// A canonical Huffman code for the same distribution, shared between the same symbols.
+    pub codebook: Arc<Codebook>,
+}
+
+/// The copies of shared distributions used by a single encoder or decoder.
+///
+/// `range_encoding` requires mutable access to a distribution to code a symbol, even
+/// though it does not modify it. Rather than locking the distributions of the
+/// dictionary for each symbol, which would serialize concurrent encoders and
+/// decoders, each of them copies the distributions it uses, once.
+#[derive(Default)]
+pub struct Distributions {
+    /// Address of the shared distribution => (shared distribution, copy).
+    ///
+    /// Holding the shared distribution ensures that its address is not reused.
+    copies: HashMap<usize, (Arc<CumulativeDistributionFrequency>, CumulativeDistributionFrequency)>,
+}
+impl Distributions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The copy of `shared`.
+    pub fn get(&mut self, shared: &Arc<CumulativeDistributionFrequency>) -> &mut CumulativeDistributionFrequency {
+        let address = &**shared as *const CumulativeDistributionFrequency as usize;
+        &mut self.copies.entry(address)
+            .or_insert_with(|| {
+                let mut instances = vec![];
+                while let Some(segment) = shared.at_index(instances.len()) {
+                    instances.push(segment.next - segment.low);
+                }
+                (shared.clone(), CumulativeDistributionFrequency::new(instances))
+            })
+            .1
+    }
 }
 
 /// A structure that may be converted into a probability distribution
@@ -24,3 +67,23 @@ pub trait InstancesToProbabilities {
     type AsProbabilities;
     fn instances_to_probabilities(self, description: &str) -> Self::AsProbabilities;
 }
+
+#[test]
+fn test_distributions_copy() {
+    let shared = Arc::new(CumulativeDistributionFrequency::new(vec![3, 1, 12]));
+    let mut distributions = Distributions::new();
+    {
+        let copy = distributions.get(&shared);
+        assert_eq!(copy.width(), shared.width());
+        for index in 0..3 {
+            let (expected, found) = (shared.at_index(index).unwrap(), copy.at_index(index).unwrap());
+            assert_eq!((expected.low, expected.next), (found.low, found.next));
+        }
+        assert!(copy.at_index(3).is_none());
+    }
+
+    // The distribution is copied once.
+    distributions.get(&shared);
+    distributions.get(&shared.clone());
+    assert_eq!(distributions.copies.len(), 1);
+}
@@ -1,22 +1,33 @@
 //! An entropy decoder
-use super::probabilities::SymbolIndex;
+use super::coder::{ Reader, SymbolReader };
+use super::fallback::FallbackReader;
+use super::header::Header;
+use super::probabilities::{ Distributions, SymbolIndex };
+use super::recency::{ self, RecencyModel };
 
 use ::TokenReaderError;
-use ::io::{ FileStructurePrinter, Path, TokenReader };
+use ::io::{ FileStructurePrinter, Path, Token, TokenKind, TokenReader };
 
 use binjs_shared::{ F64, FieldName, IdentifierName, InterfaceName, PropertyKey, SharedString };
 
 use std::io::Read;
 
-use range_encoding::opus;
-
-/// An entropy decoder, based on the Opus bit-level entropy coding.
+/// An entropy decoder, based on a dictionary and a bit-level coder.
 pub struct Decoder<R: Read> {
     /// Bit-level manipulations.
-    reader: opus::Reader<R>,
+    reader: Reader<R>,
 
     /// Shared dictionaries.
     options: ::entropy::Options,
+
+    /// The copies of the distributions of `options` used by `reader`.
+    distributions: Distributions,
+
+    /// If the header specifies a recency window, the recently used identifier names.
+    recency: Option<RecencyModel<Option<IdentifierName>>>,
+
+    /// If the header announces a fallback section, the strings missing from the dictionary.
+    fallback: Option<FallbackReader>,
 }
 
 impl<R: Read> FileStructurePrinter for Decoder<R> {
@@ -24,11 +35,30 @@ impl<R: Read> FileStructurePrinter for Decoder<R> {
 }
 
 impl<R: Read> Decoder<R> {
-    pub fn new(options: ::entropy::Options, source: R) -> Result<Self, TokenReaderError> {
-        let reader = opus::Reader::new(source)
+    /// Create a decoder for a file written with `options`, except for the options
+    /// recorded in its header, see `header`.
+    pub fn new(options: ::entropy::Options, mut source: R) -> Result<Self, TokenReaderError> {
+        let header = Header::read(&mut source)?;
+        let depth = options.probability_tables.depth();
+        if header.depth != depth {
+            return Err(TokenReaderError::BadPathDepth {
+                file: header.depth,
+                dictionary: depth,
+            });
+        }
+        let fallback =
+            if header.fallback {
+                Some(FallbackReader::new(&mut source)?)
+            } else {
+                None
+            };
+        let reader = Reader::new(header.backend, source)
             .map_err(TokenReaderError::ReadError)?;
         Ok(Decoder {
             reader,
+            distributions: Distributions::new(),
+            recency: header.recency.map(RecencyModel::new),
+            fallback,
             options,
         })
     }
@@ -44,7 +74,6 @@ macro_rules! symbol {
     ( $me: ident, $table:ident, $description: expr, $path:expr ) => {
         {
             use std::borrow::Borrow;
-            use std::ops::DerefMut;
             let path = $path.borrow();
 
             let index = {
@@ -53,11 +82,10 @@ macro_rules! symbol {
                     .$table
                     .frequencies_at(path)
                     .ok_or_else(|| TokenReaderError::NotInDictionary(format!("{} at {:?}", $description, $path)))?;
-                let mut borrow = frequencies
-                    .borrow_mut();
+                let distribution = $me.distributions.get(frequencies);
 
                 // 3. Let bit-level I/O determine the symbol index stored.
-                let index = $me.reader.symbol(borrow.deref_mut())
+                let index = $me.reader.symbol(distribution)
                     .map_err(TokenReaderError::ReadError)?;
                 index
             };
@@ -72,11 +100,131 @@ macro_rules! symbol {
     }
 }
 
+/// Read `count` symbols at the same path.
+///
+/// Unlike `symbol!`, the probability table and the copy of its distribution are
+/// looked up once for the entire batch.
+///
+/// Usage:
+/// `symbols!(self, name_of_the_probability_table, "Description, used for debugging", path_in_the_ast, count, callback)`
+macro_rules! symbols {
+    ( $me: ident, $table:ident, $description: expr, $path:expr, $count:expr, $callback:expr ) => {
+        {
+            use std::borrow::Borrow;
+            let path = $path.borrow();
+
+            // 1. Get the values and frequency information for this path.
+            let context = $me.options.probability_tables
+                .$table
+                .context_at(path)
+                .ok_or_else(|| TokenReaderError::NotInDictionary(format!("{} at {:?}", $description, $path)))?;
+            let frequencies = context.stats_by_node_value()
+                .values()
+                .next()
+                .map(|any| &any.distribution)
+                .ok_or_else(|| TokenReaderError::NotInDictionary(format!("{} at {:?}", $description, $path)))?;
+            let distribution = $me.distributions.get(frequencies);
+
+            for _ in 0..$count {
+                // 2. Let bit-level I/O determine the symbol index stored.
+                let index = $me.reader.symbol(distribution)
+                    .map_err(TokenReaderError::ReadError)?;
+
+                // 3. Deduce the value we have just read.
+                let value = context.value_by_symbol_index(SymbolIndex::new(index as usize))
+                    .ok_or_else(|| TokenReaderError::NotInDictionary(format!("{} [{}]", $description, index)))?;
+                $callback(value.clone());
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Read a single string, which may be a reference to the fallback section if
+/// the header announces one.
+///
+/// Usage:
+/// `string_symbol!(self, name_of_the_probability_table, "Description, used for debugging", path_in_the_ast, SharedString_to_value)`
+macro_rules! string_symbol {
+    ( $me: ident, $table:ident, $description: expr, $path:expr, $of_shared_string: expr ) => {
+        {
+            let fallback = match $me.fallback {
+                None => None,
+                Some(ref mut fallback) => fallback.read(&mut $me.reader)?
+            };
+            match fallback {
+                Some(string) => Ok(Some($of_shared_string(string))),
+                None => symbol!($me, $table, $description, $path)
+            }
+        }
+    }
+}
+
+/// The parts of a `Decoder` needed to read symbols from the dictionary, with the
+/// bit-level reader of a specific backend, see `Decoder::fields_at`.
+struct Batch<'a, S: 'a + SymbolReader> {
+    reader: &'a mut S,
+    options: &'a ::entropy::Options,
+    distributions: &'a mut Distributions,
+}
+impl<'a, S: SymbolReader> Batch<'a, S> {
+    /// Read a single token from the dictionary.
+    fn token_at(&mut self, kind: TokenKind, path: &Path) -> Result<Token, TokenReaderError> {
+        let token = match kind {
+            TokenKind::Bool =>
+                Token::Bool(symbol!(self, bool_by_path, "bool_by_path", path)?),
+            TokenKind::Float => {
+                let value : Option<F64> = symbol!(self, float_by_path, "float_by_path", path)?;
+                Token::Float(value.map(F64::into))
+            }
+            TokenKind::UnsignedLong =>
+                Token::UnsignedLong(symbol!(self, unsigned_long_by_path, "unsigned_long_by_path", path)?),
+            TokenKind::String =>
+                Token::String(symbol!(self, string_literal_by_path, "string_literal_by_path", path)?),
+            TokenKind::StringEnum =>
+                Token::StringEnum(symbol!(self, string_enum_by_path, "string_enum_by_path", path)?),
+            TokenKind::IdentifierName =>
+                Token::IdentifierName(symbol!(self, identifier_name_by_path, "identifier_name_by_path", path)?),
+            TokenKind::PropertyKey =>
+                Token::PropertyKey(symbol!(self, property_key_by_path, "property_key_by_path", path)?),
+        };
+        Ok(token)
+    }
+
+    /// Read consecutive fields, see `TokenReader::fields_at`.
+    fn fields_at(&mut self, fields: &[((usize, FieldName), TokenKind)], path: &mut Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        for &(ref field, kind) in fields {
+            path.enter_field(field.clone());
+            let token = self.token_at(kind, path);
+            path.exit_field(field.clone());
+            tokens.push(token?);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    /// Read an identifier name written by `Encoder::identifier_name_with_recency`.
+    fn identifier_name_with_recency(&mut self, model: &mut RecencyModel<Option<IdentifierName>>, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
+        let recency_symbol = self.reader.symbol(model.distribution())
+            .map_err(TokenReaderError::ReadError)?;
+        let value = if recency_symbol == recency::MISS {
+            string_symbol!(self, identifier_name_by_path, "identifier_name_by_path", path, IdentifierName)?
+        } else {
+            model.value(recency_symbol)
+                .cloned()
+                .ok_or_else(|| TokenReaderError::NotInDictionary(format!("recently used identifier name [{}]", recency_symbol)))?
+        };
+        model.update(recency_symbol, value.clone());
+        Ok(value)
+    }
+}
+
 impl<R: Read> TokenReader for Decoder<R> {
     // ---- String types
 
     fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
-        symbol!(self, string_literal_by_path, "string_literal_by_path", path)
+        string_symbol!(self, string_literal_by_path, "string_literal_by_path", path, |string| string)
     }
 
     fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
@@ -84,11 +232,18 @@ impl<R: Read> TokenReader for Decoder<R> {
     }
 
     fn identifier_name_at(&mut self, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
-        symbol!(self, identifier_name_by_path, "identifier_name_by_path", path)
+        match self.recency.take() {
+            None => string_symbol!(self, identifier_name_by_path, "identifier_name_by_path", path, IdentifierName),
+            Some(mut model) => {
+                let result = self.identifier_name_with_recency(&mut model, path);
+                self.recency = Some(model);
+                result
+            }
+        }
     }
 
     fn property_key_at(&mut self, path: &Path) -> Result<Option<PropertyKey>, TokenReaderError> {
-        symbol!(self, property_key_by_path, "property_key_by_path", path)
+        string_symbol!(self, property_key_by_path, "property_key_by_path", path, PropertyKey)
     }
 
     // ---- Primitive types
@@ -129,4 +284,316 @@ impl<R: Read> TokenReader for Decoder<R> {
     fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
         unimplemented!()
     }
+
+    // ---- Batched reads
+
+    fn tokens_at(&mut self, kind: TokenKind, count: usize, path: &Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        tokens.reserve(count);
+        match kind {
+            TokenKind::Bool =>
+                symbols!(self, bool_by_path, "bool_by_path", path, count,
+                    |value| tokens.push(Token::Bool(value))),
+            TokenKind::Float =>
+                symbols!(self, float_by_path, "float_by_path", path, count,
+                    |value: Option<F64>| tokens.push(Token::Float(value.map(F64::into)))),
+            TokenKind::UnsignedLong =>
+                symbols!(self, unsigned_long_by_path, "unsigned_long_by_path", path, count,
+                    |value| tokens.push(Token::UnsignedLong(value))),
+            TokenKind::String if self.fallback.is_none() =>
+                symbols!(self, string_literal_by_path, "string_literal_by_path", path, count,
+                    |value| tokens.push(Token::String(value))),
+            TokenKind::StringEnum =>
+                symbols!(self, string_enum_by_path, "string_enum_by_path", path, count,
+                    |value| tokens.push(Token::StringEnum(value))),
+            TokenKind::PropertyKey if self.fallback.is_none() =>
+                symbols!(self, property_key_by_path, "property_key_by_path", path, count,
+                    |value| tokens.push(Token::PropertyKey(value))),
+            TokenKind::IdentifierName if self.recency.is_none() && self.fallback.is_none() =>
+                symbols!(self, identifier_name_by_path, "identifier_name_by_path", path, count,
+                    |value| tokens.push(Token::IdentifierName(value))),
+            TokenKind::String | TokenKind::PropertyKey | TokenKind::IdentifierName => {
+                // Recently used identifier names and references to the fallback section are
+                // coded with distributions of their own, interleaved with the dictionary, so
+                // read them one at a time.
+                for _ in 0..count {
+                    let token = self.token_at(kind, path)?;
+                    tokens.push(token);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn fields_at(&mut self, fields: &[((usize, FieldName), TokenKind)], path: &mut Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        tokens.reserve(fields.len());
+        let interleaved = fields.iter()
+            .any(|&(_, kind)| match kind {
+                TokenKind::String | TokenKind::PropertyKey => self.fallback.is_some(),
+                TokenKind::IdentifierName => self.fallback.is_some() || self.recency.is_some(),
+                _ => false
+            });
+        if interleaved {
+            // As in `tokens_at`, read recently used identifier names and references to
+            // the fallback section one at a time.
+            for &(ref field, kind) in fields {
+                path.enter_field(field.clone());
+                let token = self.token_at(kind, path);
+                path.exit_field(field.clone());
+                tokens.push(token?);
+            }
+            return Ok(());
+        }
+
+        // All the fields are read from the dictionary, so the backend is selected once
+        // for the entire batch, rather than for each symbol.
+        match self.reader {
+            Reader::Range(ref mut reader) => Batch {
+                reader,
+                options: &self.options,
+                distributions: &mut self.distributions,
+            }.fields_at(fields, path, tokens),
+            Reader::RANS(ref mut reader) => Batch {
+                reader,
+                options: &self.options,
+                distributions: &mut self.distributions,
+            }.fields_at(fields, path, tokens),
+        }
+    }
+}
+
+#[test]
+fn test_tokens_at() {
+    use binjs_shared::ast::PathItem;
+    use entropy::dictionary::Dictionary;
+    use entropy::probabilities::InstancesToProbabilities;
+    use io::TokenWriter;
+    use io::statistics::Instances;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("ArrayExpression"),
+        field: (0, FieldName::from_str("elements")),
+    }]);
+    let values : Vec<u32> = vec![3, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5];
+
+    let mut dictionary : Dictionary<Instances> = Dictionary::new(1, 2);
+    for &value in &values {
+        dictionary.unsigned_long_by_path.add(path.tail(1), value);
+    }
+    dictionary.bool_by_path.add(path.tail(1), Some(true));
+    dictionary.bool_by_path.add(path.tail(1), None);
+    let options = ::entropy::Options::new(dictionary.instances_to_probabilities("dictionary"));
+
+    let mut encoder = ::entropy::write::Encoder::new(options.clone());
+    for &value in &values {
+        encoder.unsigned_long_at(value, &path)
+            .expect("Could not write unsigned long");
+    }
+    encoder.bool_at(None, &path)
+        .expect("Could not write bool");
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    let mut decoder = Decoder::new(options, std::io::Cursor::new(data))
+        .expect("Could not create decoder");
+    let mut tokens = vec![];
+    decoder.tokens_at(TokenKind::UnsignedLong, 4, &path, &mut tokens)
+        .expect("Could not read batch");
+    for _ in 4..values.len() {
+        let token = decoder.token_at(TokenKind::UnsignedLong, &path)
+            .expect("Could not read unsigned long");
+        tokens.push(token);
+    }
+    decoder.tokens_at(TokenKind::Bool, 1, &path, &mut tokens)
+        .expect("Could not read batch");
+
+    let mut expected : Vec<_> = values.iter()
+        .map(|value| Token::UnsignedLong(*value))
+        .collect();
+    expected.push(Token::Bool(None));
+    assert_eq!(tokens, expected);
+
+    // Paths missing from the dictionary are reported.
+    assert!(decoder.tokens_at(TokenKind::Float, 1, &path, &mut tokens).is_err());
+}
+
+#[test]
+fn test_fields_at() {
+    use entropy::dictionary::Dictionary;
+    use entropy::probabilities::InstancesToProbabilities;
+    use io::TokenWriter;
+    use io::statistics::Instances;
+
+    let interface = InterfaceName::from_str("Directive");
+    let fields = [
+        ((0, FieldName::from_str("isStrict")), TokenKind::Bool),
+        ((1, FieldName::from_str("rawValue")), TokenKind::String),
+        ((2, FieldName::from_str("length")), TokenKind::UnsignedLong),
+    ];
+    let rows = [
+        (Some(true), Some(SharedString::from_str("use strict")), 10),
+        (Some(false), None, 0),
+        (Some(true), Some(SharedString::from_str("use asm")), 7),
+    ];
+
+    let mut path = Path::new();
+    let mut dictionary : Dictionary<Instances> = Dictionary::new(1, 2);
+    for &(ref is_strict, ref raw_value, length) in &rows {
+        path.enter_interface(interface.clone());
+        path.enter_field(fields[0].0.clone());
+        dictionary.bool_by_path.add(path.tail(1), is_strict.clone());
+        path.exit_field(fields[0].0.clone());
+        path.enter_field(fields[1].0.clone());
+        dictionary.string_literal_by_path.add(path.tail(1), raw_value.clone());
+        path.exit_field(fields[1].0.clone());
+        path.enter_field(fields[2].0.clone());
+        dictionary.unsigned_long_by_path.add(path.tail(1), length);
+        path.exit_field(fields[2].0.clone());
+        path.exit_interface(interface.clone());
+    }
+    let options = ::entropy::Options::new(dictionary.instances_to_probabilities("dictionary"));
+
+    let mut encoder = ::entropy::write::Encoder::new(options.clone());
+    for &(ref is_strict, ref raw_value, length) in &rows {
+        path.enter_interface(interface.clone());
+        path.enter_field(fields[0].0.clone());
+        encoder.bool_at(is_strict.clone(), &path)
+            .expect("Could not write bool");
+        path.exit_field(fields[0].0.clone());
+        path.enter_field(fields[1].0.clone());
+        encoder.string_at(raw_value.as_ref(), &path)
+            .expect("Could not write string");
+        path.exit_field(fields[1].0.clone());
+        path.enter_field(fields[2].0.clone());
+        encoder.unsigned_long_at(length, &path)
+            .expect("Could not write unsigned long");
+        path.exit_field(fields[2].0.clone());
+        path.exit_interface(interface.clone());
+    }
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    // Read the first instance in a single batch, the second one field by field,
+    // and the third one in two batches.
+    let mut decoder = Decoder::new(options, std::io::Cursor::new(data))
+        .expect("Could not create decoder");
+    let mut tokens = vec![];
+    path.enter_interface(interface.clone());
+    decoder.fields_at(&fields, &mut path, &mut tokens)
+        .expect("Could not read batch");
+    for &(ref field, kind) in &fields {
+        path.enter_field(field.clone());
+        let token = decoder.token_at(kind, &path)
+            .expect("Could not read field");
+        path.exit_field(field.clone());
+        tokens.push(token);
+    }
+    decoder.fields_at(&fields[..1], &mut path, &mut tokens)
+        .expect("Could not read batch");
+    decoder.fields_at(&fields[1..], &mut path, &mut tokens)
+        .expect("Could not read batch");
+
+    let expected : Vec<_> = rows.iter()
+        .flat_map(|&(ref is_strict, ref raw_value, length)| vec![
+            Token::Bool(is_strict.clone()),
+            Token::String(raw_value.clone()),
+            Token::UnsignedLong(length),
+        ])
+        .collect();
+    assert_eq!(tokens, expected);
+
+    // Errors leave the path unchanged.
+    let missing = [((3, FieldName::from_str("missing")), TokenKind::Float)];
+    assert!(decoder.fields_at(&missing, &mut path, &mut tokens).is_err());
+    path.exit_interface(interface.clone());
+    assert_eq!(path.len(), 0);
+}
+
+#[test]
+fn test_header_backend() {
+    use binjs_shared::ast::PathItem;
+    use entropy::coder::Backend;
+    use entropy::dictionary::Dictionary;
+    use entropy::probabilities::InstancesToProbabilities;
+    use io::TokenWriter;
+    use io::statistics::Instances;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("ArrayExpression"),
+        field: (0, FieldName::from_str("elements")),
+    }]);
+    let values : Vec<u32> = vec![3, 1, 4, 1, 5, 9, 2, 6];
+
+    let mut dictionary : Dictionary<Instances> = Dictionary::new(1, 2);
+    for &value in &values {
+        dictionary.unsigned_long_by_path.add(path.tail(1), value);
+    }
+    let options = ::entropy::Options::new(dictionary.instances_to_probabilities("dictionary"));
+
+    let mut encoder = ::entropy::write::Encoder::new(options.clone().with_backend(Backend::RANS));
+    for &value in &values {
+        encoder.unsigned_long_at(value, &path)
+            .expect("Could not write unsigned long");
+    }
+    let mut data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    // The coder is taken from the header, not from the options of the decoder.
+    let mut decoder = Decoder::new(options.clone().with_backend(Backend::Range), std::io::Cursor::new(data.clone()))
+        .expect("Could not create decoder");
+    for &value in &values {
+        assert_eq!(decoder.unsigned_long_at(&path).expect("Could not read unsigned long"), value);
+    }
+
+    // Files that announce an unknown coder are rejected.
+    data[1] = 0xFF;
+    match Decoder::new(options, std::io::Cursor::new(data)) {
+        Err(TokenReaderError::BadHeader) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Unexpected success"),
+    }
+}
+
+#[test]
+fn test_header_path_depth() {
+    use binjs_shared::ast::PathItem;
+    use entropy::dictionary::Dictionary;
+    use entropy::probabilities::InstancesToProbabilities;
+    use io::TokenWriter;
+    use io::statistics::Instances;
+
+    let mut path = Path::new();
+    path.extend_from_slice(&[PathItem {
+        interface: InterfaceName::from_str("Script"),
+        field: (0, FieldName::from_str("statements")),
+    }, PathItem {
+        interface: InterfaceName::from_str("ArrayExpression"),
+        field: (0, FieldName::from_str("elements")),
+    }]);
+
+    let mut dictionary : Dictionary<Instances> = Dictionary::new(2, 2);
+    dictionary.bool_by_path.add(path.tail(2), Some(true));
+    dictionary.bool_by_path.add(path.tail(2), Some(false));
+    let options = ::entropy::Options::new(dictionary.clone().instances_to_probabilities("dictionary"));
+
+    let mut encoder = ::entropy::write::Encoder::new(options.clone());
+    encoder.bool_at(Some(false), &path)
+        .expect("Could not write bool");
+    let data = encoder.done()
+        .expect("Could not finalize encoding");
+
+    let mut decoder = Decoder::new(options, std::io::Cursor::new(data.clone()))
+        .expect("Could not create decoder");
+    assert_eq!(decoder.bool_at(&path).expect("Could not read bool"), Some(false));
+
+    // Decoding with a dictionary of another depth would silently misdecode, so it is rejected.
+    let shallow = dictionary.with_depth(1)
+        .expect("Could not reduce depth");
+    let options = ::entropy::Options::new(shallow.instances_to_probabilities("dictionary"));
+    match Decoder::new(options, std::io::Cursor::new(data)) {
+        Err(TokenReaderError::BadPathDepth { file: 2, dictionary: 1 }) => {}
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Unexpected success"),
+    }
 }
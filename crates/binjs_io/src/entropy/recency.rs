@@ -0,0 +1,136 @@
+//! A model of recently used values, used to code identifier names.
+//!
+//! Most identifier names refer to variables that were declared or used shortly
+//! before. The model keeps the last few values in move-to-front order and codes
+//! each value either as its position in this list (a *hit*), or as a *miss*,
+//! followed by the value itself, coded with the dictionary.
+
+use ::io::statistics::Gain;
+
+use range_encoding::CumulativeDistributionFrequency;
+
+use std;
+use std::collections::VecDeque;
+
+/// The symbol announcing a value that is not among the recent values.
+/// Symbol `i + 1` stands for the value at position `i`.
+pub const MISS: u32 = 0;
+
+/// The default number of recent values.
+pub const DEFAULT_WINDOW: usize = 16;
+
+/// Halve the number of instances once they exceed this total, so that the
+/// distribution adapts to the current part of the file.
+const MAX_TOTAL_INSTANCES: u32 = 1 << 15;
+
+pub struct RecencyModel<T> where T: Eq + Clone {
+    /// The recent values, most recent first.
+    recent: VecDeque<T>,
+
+    /// The maximal number of recent values.
+    window: usize,
+
+    /// The number of instances of each symbol, including `MISS`.
+    instances: Vec<u32>,
+
+    /// The sum of `instances`.
+    total: u32,
+
+    /// The distribution matching `instances`, rebuilt lazily after an update.
+    distribution: Option<CumulativeDistributionFrequency>,
+}
+impl<T> RecencyModel<T> where T: Eq + Clone {
+    pub fn new(window: usize) -> Self {
+        RecencyModel {
+            recent: VecDeque::with_capacity(window),
+            window,
+            instances: vec![1; window + 1],
+            total: window as u32 + 1,
+            distribution: None,
+        }
+    }
+
+    /// The symbol for `value`, i.e. `MISS` or its position among the recent values + 1.
+    pub fn symbol(&self, value: &T) -> u32 {
+        self.recent.iter()
+            .position(|recent| recent == value)
+            .map_or(MISS, |position| position as u32 + 1)
+    }
+
+    /// The value for `symbol`, or `None` for `MISS` or a position we have
+    /// not filled yet.
+    pub fn value(&self, symbol: u32) -> Option<&T> {
+        if symbol == MISS {
+            return None;
+        }
+        self.recent.get(symbol as usize - 1)
+    }
+
+    pub fn distribution(&mut self) -> &mut CumulativeDistributionFrequency {
+        let instances = &self.instances;
+        self.distribution.get_or_insert_with(|| CumulativeDistributionFrequency::new(instances.clone()))
+    }
+
+    /// Record one more instance of `symbol`, which stands for `value`, and move
+    /// `value` to the front of the recent values.
+    pub fn update(&mut self, symbol: u32, value: T) {
+        self.instances[symbol as usize] += 1;
+        self.total += 1;
+        if self.total > MAX_TOTAL_INSTANCES {
+            for instances in self.instances.iter_mut() {
+                *instances = (*instances + 1) / 2;
+            }
+            self.total = self.instances.iter().sum();
+        }
+        self.distribution = None;
+
+        if symbol != MISS {
+            self.recent.remove(symbol as usize - 1);
+        } else if self.recent.len() == self.window {
+            self.recent.pop_back();
+        }
+        if self.window > 0 {
+            self.recent.push_front(value);
+        }
+    }
+}
+
+/// Statistics obtained while writing with a `RecencyModel`.
+#[derive(Clone, Debug, Default)]
+pub struct RecencyStatistics {
+    /// The number of values written.
+    pub values: usize,
+
+    /// The number of values found among the recent values.
+    pub hits: usize,
+
+    /// The number of bytes used by the dictionary alone (before) and with
+    /// the model (after).
+    pub gain: Gain,
+}
+impl std::fmt::Display for RecencyStatistics {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(formatter, "Recently used identifiers: {hits} hits out of {values} ({rate:.2}%), {gain}\n",
+            hits = self.hits,
+            values = self.values,
+            rate = 100. * self.hits as f64 / self.values as f64,
+            gain = self.gain)
+    }
+}
+
+#[test]
+fn test_recency_model() {
+    let mut model = RecencyModel::new(2);
+    for &(value, symbol) in &[("a", MISS), ("b", MISS), ("a", 2), ("a", 1), ("c", MISS), ("b", MISS), ("c", 2)] {
+        assert_eq!(model.symbol(&value), symbol, "Symbol of {}", value);
+        if symbol != MISS {
+            assert_eq!(model.value(symbol), Some(&value));
+        }
+        model.distribution();
+        model.update(symbol, value);
+    }
+    assert_eq!(model.value(1), Some(&"c"));
+    assert_eq!(model.value(2), Some(&"b"));
+    assert_eq!(model.value(3), None);
+    assert_eq!(model.value(MISS), None);
+}
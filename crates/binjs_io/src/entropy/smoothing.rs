@@ -0,0 +1,420 @@
+//! Smoothing the probabilities of a dictionary.
+//!
+//! A dictionary built from a sample assigns to each value the probability with which it
+//! appeared in the sample. When the sample differs from the files eventually encoded,
+//! values that were rare in the sample, typically seen once or twice at some path, may
+//! be much more frequent in practice, and pay for it with very long codes. Smoothing
+//! moves some probability from the frequent values to the rare values of each context:
+//!
+//! - `Method::Laplace` adds a pseudo-count to each value;
+//! - `Method::KneserNey` subtracts a fixed discount from each value, and redistributes
+//!     the mass freed following the lower-order distribution, i.e. the number of distinct
+//!     paths one item shorter in which each value appears, as in interpolated
+//!     Kneser-Ney smoothing.
+//!
+//! Smoothing never adds values to a context: values missing from the dictionary still
+//! cannot be encoded with it, see `entropy::fallback`. Conversely, `Smoothing::with_cutoff`
+//! removes from each context the values that appear too rarely to be worth their entry.
+//!
+//! As the dictionary only stores integer numbers of instances, the smoothed probabilities
+//! are quantized to approximately `RESOLUTION` instances per context. Once smoothed, the
+//! number of instances no longer reflects the number of symbols of the sample, so smoothing
+//! should happen after pruning. `Dictionary::cross_entropy` measures the effect of smoothing
+//! on a held-out sample.
+
+use entropy::dictionary::{ Dictionary, Instances };
+use entropy::predict::{ ContextInformation, IOPath, PathPredict, WindowPredict };
+
+use std;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// The number of instances to which the probabilities of a context are quantized.
+///
+/// Contexts with many values exceed it, as every value keeps at least one instance.
+const RESOLUTION : f64 = 32768.;
+
+/// A smoothing method.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Method {
+    /// Add `pseudo_count` instances to each value of each context.
+    Laplace { pseudo_count: f64 },
+
+    /// Subtract `discount` instances from each value of each context, interpolating
+    /// with the lower-order distribution.
+    KneserNey { discount: f64 },
+}
+impl Method {
+    /// The names of the methods, as used on the command-line.
+    pub fn names() -> [&'static str; 2] {
+        ["laplace", "kneser-ney"]
+    }
+
+    /// The method called `name`, with parameter `parameter`, or its usual parameter.
+    pub fn from_name(name: &str, parameter: Option<f64>) -> Option<Self> {
+        match name {
+            "laplace" => Some(Method::Laplace {
+                pseudo_count: parameter.unwrap_or(1.)
+            }),
+            "kneser-ney" => Some(Method::KneserNey {
+                discount: parameter.unwrap_or(0.75)
+            }),
+            _ => None
+        }
+    }
+}
+
+/// Options for `Dictionary::smooth`.
+///
+/// By default, the dictionary is left unchanged.
+#[derive(Clone, Debug)]
+pub struct Smoothing {
+    method: Option<Method>,
+    cutoff: usize,
+}
+impl Smoothing {
+    pub fn new() -> Self {
+        Smoothing {
+            method: None,
+            cutoff: 1,
+        }
+    }
+
+    /// Smooth the probabilities of each context with `method`.
+    pub fn with_method(self, method: Method) -> Self {
+        Smoothing {
+            method: Some(method),
+            ..self
+        }
+    }
+
+    /// Remove the values that appear fewer than `cutoff` times in their context,
+    /// before smoothing. Values removed from the dictionary can no longer be encoded.
+    pub fn with_cutoff(self, cutoff: usize) -> Self {
+        Smoothing {
+            cutoff,
+            ..self
+        }
+    }
+
+    pub fn method(&self) -> Option<Method> {
+        self.method
+    }
+
+    pub fn cutoff(&self) -> usize {
+        self.cutoff
+    }
+
+    /// Smooth the values of a single context.
+    ///
+    /// `weights` is the lower-order distribution used by `Method::KneserNey`. Values
+    /// missing from `weights` have weight 1, so an empty `weights` stands for the
+    /// uniform distribution.
+    fn smooth_context<V>(&self, info: &mut ContextInformation<V, Instances>, weights: &HashMap<V, usize>) where V: Eq + Hash {
+        let method = match self.method {
+            None => return,
+            Some(method) => method
+        };
+        let total = info.total() as f64;
+        if total == 0. {
+            return;
+        }
+        let weight = |value: &V| weights.get(value)
+            .cloned()
+            .unwrap_or(1) as f64;
+        let len = info.len() as f64;
+        // With `Method::KneserNey`, the mass freed by discounting, spread following `weights`.
+        let (freed, total_weight) = match method {
+            Method::Laplace { .. } => (0., 0.),
+            Method::KneserNey { discount } => {
+                let freed : f64 = info.iter()
+                    .map(|(_, instances)| f64::min(Into::<usize>::into(*instances) as f64, discount))
+                    .sum();
+                let total_weight : f64 = info.iter()
+                    .map(|(value, _)| weight(value))
+                    .sum();
+                (freed, total_weight)
+            }
+        };
+        info.retain(|value, instances| {
+            let count = Into::<usize>::into(*instances) as f64;
+            let probability = match method {
+                Method::Laplace { pseudo_count } =>
+                    (count + pseudo_count) / (total + pseudo_count * len),
+                Method::KneserNey { discount } =>
+                    (f64::max(count - discount, 0.) + freed * weight(value) / total_weight) / total,
+            };
+            let quantized = (probability * RESOLUTION).round() as usize;
+            *instances = std::cmp::max(quantized, 1).into();
+            true
+        });
+    }
+
+    fn smooth_path<V>(&self, table: &mut PathPredict<V, Instances>, report: &mut SmoothingReport) where V: Eq + Hash + Clone {
+        let cutoff = self.cutoff;
+        table.retain(|_, _, instances| {
+            let instances = Into::<usize>::into(*instances);
+            if instances >= cutoff {
+                return true;
+            }
+            report.entries_removed += 1;
+            report.symbols_removed += instances;
+            false
+        });
+
+        // The number of distinct paths in which each value appears, by lower-order path.
+        let mut continuations : HashMap<IOPath, HashMap<V, usize>> = HashMap::new();
+        if let Some(Method::KneserNey { .. }) = self.method {
+            for (path, info) in table.iter() {
+                let weights = continuations.entry(Self::lower_order(path))
+                    .or_insert_with(HashMap::new);
+                for (value, _) in info.iter() {
+                    *weights.entry(value.clone())
+                        .or_insert(0) += 1;
+                }
+            }
+        }
+        if self.method.is_none() {
+            return;
+        }
+        let uniform = HashMap::new();
+        for (path, info) in table.iter_mut() {
+            let weights = continuations.get(&Self::lower_order(path))
+                .unwrap_or(&uniform);
+            self.smooth_context(info, weights);
+            report.contexts_smoothed += 1;
+        }
+    }
+
+    fn smooth_window<V>(&self, table: &mut WindowPredict<V, Instances>, report: &mut SmoothingReport) where V: Eq + Hash + Clone + std::fmt::Debug {
+        let cutoff = self.cutoff;
+        table.retain(|_, instances| {
+            let instances = Into::<usize>::into(*instances);
+            if instances >= cutoff {
+                return true;
+            }
+            report.entries_removed += 1;
+            report.symbols_removed += instances;
+            false
+        });
+
+        if self.method.is_none() {
+            return;
+        }
+        // Windows have a single context, so there is no lower order.
+        self.smooth_context(table.info_mut(), &HashMap::new());
+        report.contexts_smoothed += 1;
+    }
+
+    /// The path one item shorter than `path`, forgetting the farthest ancestor.
+    fn lower_order(path: &IOPath) -> IOPath {
+        let mut result = IOPath::new();
+        result.extend_from_slice(path.tail(path.len().saturating_sub(1)));
+        result
+    }
+}
+impl Default for Smoothing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of `Dictionary::smooth`.
+#[derive(Clone, Debug, Default)]
+pub struct SmoothingReport {
+    /// The number of (context, value) entries removed by the cutoff.
+    pub entries_removed: usize,
+
+    /// The number of sampled symbols covered by the removed entries.
+    pub symbols_removed: usize,
+
+    /// The number of contexts whose probabilities have been smoothed.
+    pub contexts_smoothed: usize,
+}
+
+impl std::fmt::Display for SmoothingReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "cutoff removed {} entries covering {} sampled symbols, smoothed {} contexts",
+            self.entries_removed,
+            self.symbols_removed,
+            self.contexts_smoothed)
+    }
+}
+
+/// The cost of coding a sample with a dictionary, see `Dictionary::cross_entropy`.
+#[derive(Clone, Debug, Default)]
+pub struct CrossEntropy {
+    /// The number of symbols of the sample that may be coded with the dictionary.
+    pub symbols: usize,
+
+    /// The number of bits needed to code these symbols.
+    pub bits: f64,
+
+    /// The number of symbols of the sample missing from the dictionary.
+    pub missing: usize,
+}
+impl CrossEntropy {
+    /// The average number of bits per symbol that may be coded with the dictionary.
+    pub fn bits_per_symbol(&self) -> f64 {
+        if self.symbols == 0 {
+            0.
+        } else {
+            self.bits / self.symbols as f64
+        }
+    }
+
+    fn add_path<V>(&mut self, table: &PathPredict<V, Instances>, sample: &PathPredict<V, Instances>) where V: Eq + Hash + Clone {
+        for (path, sampled) in sample.iter() {
+            let mut tail = IOPath::new();
+            tail.extend_from_slice(path.tail(table.depth()));
+            let info = table.get(&tail);
+            let total = info.map_or(0, |info| info.total()) as f64;
+            for (value, instances) in sampled.iter() {
+                let instances = Into::<usize>::into(*instances);
+                match info.and_then(|info| info.get(value)) {
+                    Some(found) => {
+                        let probability = Into::<usize>::into(*found) as f64 / total;
+                        self.symbols += instances;
+                        self.bits -= instances as f64 * probability.log2();
+                    }
+                    None => {
+                        self.missing += instances;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for CrossEntropy {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let total = self.symbols + self.missing;
+        let percent =
+            if total == 0 {
+                0.
+            } else {
+                100. * self.missing as f64 / total as f64
+            };
+        write!(f, "{:.3} bits per symbol over {} symbols ({:.0} bytes), {} symbols missing from the dictionary ({:.2}%)",
+            self.bits_per_symbol(),
+            self.symbols,
+            self.bits / 8.,
+            self.missing,
+            percent)
+    }
+}
+
+impl Dictionary<Instances> {
+    /// Apply the cutoff and smoothing of `smoothing` to all the tables of this dictionary.
+    pub fn smooth(&mut self, smoothing: &Smoothing) -> SmoothingReport {
+        let mut report = SmoothingReport::default();
+        smoothing.smooth_path(&mut self.bool_by_path, &mut report);
+        smoothing.smooth_path(&mut self.float_by_path, &mut report);
+        smoothing.smooth_path(&mut self.unsigned_long_by_path, &mut report);
+        smoothing.smooth_path(&mut self.string_enum_by_path, &mut report);
+        smoothing.smooth_path(&mut self.property_key_by_path, &mut report);
+        smoothing.smooth_window(&mut self.property_key_by_window, &mut report);
+        smoothing.smooth_path(&mut self.identifier_name_by_path, &mut report);
+        smoothing.smooth_window(&mut self.identifier_name_by_window, &mut report);
+        smoothing.smooth_path(&mut self.interface_name_by_path, &mut report);
+        smoothing.smooth_path(&mut self.string_literal_by_path, &mut report);
+        smoothing.smooth_window(&mut self.string_literal_by_window, &mut report);
+        smoothing.smooth_path(&mut self.list_length_by_path, &mut report);
+        report
+    }
+
+    /// The cost of coding `sample`, e.g. a dictionary built from held-out files,
+    /// with the probabilities of this dictionary.
+    ///
+    /// `sample` must have been built with at least the depth of this dictionary.
+    /// Window tables are ignored, as the values of their dictionary indices depend
+    /// on the order in which the files were sampled.
+    pub fn cross_entropy(&self, sample: &Dictionary<Instances>) -> CrossEntropy {
+        let mut result = CrossEntropy::default();
+        result.add_path(&self.bool_by_path, &sample.bool_by_path);
+        result.add_path(&self.float_by_path, &sample.float_by_path);
+        result.add_path(&self.unsigned_long_by_path, &sample.unsigned_long_by_path);
+        result.add_path(&self.string_enum_by_path, &sample.string_enum_by_path);
+        result.add_path(&self.property_key_by_path, &sample.property_key_by_path);
+        result.add_path(&self.identifier_name_by_path, &sample.identifier_name_by_path);
+        result.add_path(&self.interface_name_by_path, &sample.interface_name_by_path);
+        result.add_path(&self.string_literal_by_path, &sample.string_literal_by_path);
+        result.add_path(&self.list_length_by_path, &sample.list_length_by_path);
+        result
+    }
+}
+
+#[test]
+fn test_smoothing() {
+    use binjs_shared::{ FieldName, InterfaceName, SharedString };
+    use binjs_shared::ast::PathItem;
+
+    let item = |interface: &'static str, field: &'static str| PathItem {
+        interface: InterfaceName::from_str(interface),
+        field: (0, FieldName::from_str(field)),
+    };
+    let path = |items: &[_]| {
+        let mut path = IOPath::new();
+        path.extend_from_slice(items);
+        path
+    };
+    let value = SharedString::from_str;
+    let mut table : PathPredict<SharedString, Instances> = PathPredict::new(2);
+    for _ in 0..97 {
+        table.add(&[item("A", "a"), item("C", "c")], value("x"));
+    }
+    table.add(&[item("A", "a"), item("C", "c")], value("y"));
+    table.add(&[item("A", "a"), item("C", "c")], value("y"));
+    table.add(&[item("A", "a"), item("C", "c")], value("z"));
+    table.add(&[item("B", "b"), item("C", "c")], value("z"));
+
+    let probability = |table: &PathPredict<SharedString, Instances>, string: &'static str| {
+        let info = table.get(&path(&[item("A", "a"), item("C", "c")]))
+            .expect("Path should be known");
+        Into::<usize>::into(*info.get(&value(string)).unwrap()) as f64 / info.total() as f64
+    };
+    let close = |a: f64, b: f64| (a - b).abs() < 0.001;
+    assert!(close(probability(&table, "z"), 0.01));
+
+    // Laplace: (1 + 1) / (100 + 3).
+    let mut laplace = table.clone();
+    let mut report = SmoothingReport::default();
+    Smoothing::new()
+        .with_method(Method::Laplace { pseudo_count: 1. })
+        .smooth_path(&mut laplace, &mut report);
+    assert_eq!(report.contexts_smoothed, 2);
+    assert!(close(probability(&laplace, "z"), 2. / 103.));
+
+    // Kneser-Ney: "z" appears in two paths, "x" and "y" in a single one, so "z"
+    // receives half of the 1.5 instances freed by discounting.
+    let mut kneser_ney = table.clone();
+    Smoothing::new()
+        .with_method(Method::KneserNey { discount: 0.5 })
+        .smooth_path(&mut kneser_ney, &mut SmoothingReport::default());
+    assert!(close(probability(&kneser_ney, "z"), (0.5 + 0.75) / 100.));
+    assert!(close(probability(&kneser_ney, "y"), (1.5 + 0.375) / 100.));
+
+    // Cutoff.
+    let mut cut = table.clone();
+    let mut report = SmoothingReport::default();
+    Smoothing::new()
+        .with_cutoff(2)
+        .smooth_path(&mut cut, &mut report);
+    assert_eq!(report.entries_removed, 2);
+    assert_eq!(report.symbols_removed, 2);
+    assert_eq!(cut.paths().count(), 1);
+    assert!(close(probability(&cut, "x"), 97. / 99.));
+
+    // Cross-entropy.
+    let mut dictionary = Dictionary::new(2, 0);
+    dictionary.string_enum_by_path = table.clone()
+        .with_depth(1)
+        .unwrap();
+    let mut sample = Dictionary::new(2, 0);
+    sample.string_enum_by_path = table;
+    sample.string_enum_by_path.add(&[item("C", "c")], value("w"));
+    let cross_entropy = dictionary.cross_entropy(&sample);
+    assert_eq!(cross_entropy.symbols, 101);
+    assert_eq!(cross_entropy.missing, 1);
+    assert!(close(cross_entropy.bits, dictionary.string_enum_by_path.get(&path(&[item("C", "c")])).unwrap().entropy() * 101.));
+}
@@ -7,28 +7,37 @@
 // FIXME: Split into packets
 // FIXME: Implement lazy functions
 
+use super::bounds::{ self, StreamBound };
+use super::coder::{ SymbolWriter, Writer };
+use super::fallback::{ FallbackWriter, StringKind };
+use super::header::Header;
+use super::probabilities::Distributions;
+use super::recency::{ self, RecencyModel, RecencyStatistics };
+
 use ::TokenWriterError;
 use ::io::{ Path, TokenWriter };
-use ::io::statistics::{ ContentInfo, Instances };
+use ::io::statistics::{ ContentInfo, Gain, Instances };
 use bytes::lengthwriter::LengthWriter;
 
 use binjs_shared::{ F64, FieldName, IdentifierName, InterfaceName, Node, PropertyKey, SharedString };
 
-use std::ops::DerefMut;
 
 use itertools::Itertools;
 use range_encoding::opus;
+use tracing;
 
-const INITIAL_BUFFER_SIZE_BYTES : usize = 32768;
-
-/// An entropy encoder, based on the Opus bit-level entropy coding.
+/// An entropy encoder, based on a dictionary and a bit-level coder.
 pub struct Encoder {
     /// Bit-level manipulations.
-    writer: opus::Writer<Vec<u8>>,
+    writer: Writer,
 
     /// Shared dictionaries.
     options: ::entropy::Options,
 
+    /// The copies of the distributions of `options` used by `writer` and the
+    /// statistics.
+    distributions: Distributions,
+
     // --- Statistics.
 
     /// Measure the number of bytes written.
@@ -36,16 +45,42 @@ pub struct Encoder {
 
     /// Measure the number of entries written.
     content_instances: ContentInfo<Instances>,
+
+    /// Measure the Shannon-optimal size of each stream.
+    content_bounds: ContentInfo<StreamBound>,
+
+    /// If `options.recency` is specified, the recently used identifier names.
+    recency: Option<RecencyModel<Option<IdentifierName>>>,
+
+    /// Measure the number of bytes used by identifier names with the
+    /// dictionary alone.
+    recency_before: opus::Writer<LengthWriter>,
+
+    /// Measure the number of bytes used by identifier names with `recency`.
+    recency_after: opus::Writer<LengthWriter>,
+
+    /// Count identifier names found among the recently used ones.
+    recency_statistics: RecencyStatistics,
+
+    /// If `options.fallback` is specified, the strings missing from the dictionary.
+    fallback: Option<FallbackWriter>,
 }
 
 impl Encoder {
     /// Create a new Encoder.
     pub fn new(options: ::entropy::Options) -> Self { // FIXME: We shouldn't need to clone the entire `options`. A shared immutable reference would do nicely.
         Encoder {
-            writer: opus::Writer::new(Vec::with_capacity(INITIAL_BUFFER_SIZE_BYTES)),
-            options,
+            writer: Writer::new(options.backend()),
+            distributions: Distributions::new(),
             content_lengths: ContentInfo::with(|_| opus::Writer::new(LengthWriter::new())),
             content_instances: ContentInfo::with(|_| 0.into()),
+            content_bounds: ContentInfo::default(),
+            recency: options.recency().map(RecencyModel::new),
+            recency_before: opus::Writer::new(LengthWriter::new()),
+            recency_after: opus::Writer::new(LengthWriter::new()),
+            recency_statistics: RecencyStatistics::default(),
+            fallback: if options.fallback() { Some(FallbackWriter::new()) } else { None },
+            options,
         }
     }
 }
@@ -63,7 +98,7 @@ macro_rules! symbol {
             use std::borrow::Borrow;
 
             let path = $path.borrow();
-            debug!(target: "entropy_details", "Known paths ({}): [{}]",
+            tracing::debug!(target: "entropy_details", "Known paths ({}): [{}]",
                 $description,
                 $me.options
                     .probability_tables
@@ -74,30 +109,133 @@ macro_rules! symbol {
 
             // 1. Locate the `SymbolInfo` information for this value given the
             // path information.
-            let symbol = $me.options
-                .probability_tables
-                .$table
-                .stats_by_node_value_mut(path, &$value)
-                .ok_or_else(|| {
-                    debug!(target: "entropy", "Couldn't find value {:?} at {:?} ({})",
-                        $value, path, $description);
-                    TokenWriterError::NotInDictionary(format!("{}: {:?} at {:?}", $description, $value, path))
-                })?;
+            let symbol = {
+                // One span per symbol is too costly to be enabled by default.
+                #[cfg(feature = "trace-symbols")]
+                let _span = tracing::trace_span!("dictionary_lookup").entered();
+                $me.options
+                    .probability_tables
+                    .$table
+                    .stats_by_node_value(path, &$value)
+                    .ok_or_else(|| {
+                        tracing::debug!(target: "entropy", description = $description,
+                            "Couldn't find value {:?} at {:?}", $value, path);
+                        TokenWriterError::NotInDictionary(format!("{}: {:?} at {:?}", $description, $value, path))
+                    })?
+            };
 
             // 2. This gives us an index (`symbol.index`) and a probability distribution
             // (`symbol.distribution`). Use them to write the probability at bit-level.
-            let mut borrow = symbol.distribution
-                .borrow_mut();
-            $me.writer.symbol(symbol.index.into(), borrow.deref_mut())
+            let index : usize = symbol.index.into();
+            let distribution = $me.distributions.get(&symbol.distribution);
+            $me.writer.symbol(index as u32, distribution)
                 .map_err(TokenWriterError::WriteError)?;
 
             // 3. Also, update statistics
             $me.content_lengths
                 .$info
-                .symbol(symbol.index.into(), borrow.deref_mut())
+                .symbol(index, distribution)
                 .map_err(TokenWriterError::WriteError)?;
             $me.content_instances
                 .$info += Into::<Instances>::into(1);
+            $me.content_bounds
+                .$info
+                .symbols += 1;
+            if let Some(bits) = bounds::information(Into::<usize>::into(symbol.index), &symbol.distribution) {
+                $me.content_bounds
+                    .$info
+                    .bound_bits += bits;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Emit a single string, or a reference to the fallback section if `options.fallback`
+/// is specified and the string is missing from the dictionary.
+///
+/// Usage:
+/// `string_symbol!(self, name_of_the_probability_table, name_of_the_ContentInfo_field, "Description, used for debugging",  path_in_the_ast,  value_to_encode, StringKind, value_to_SharedString)`
+macro_rules! string_symbol {
+    ( $me: ident, $table:ident, $info:ident, $description: expr, $path:expr, $value: expr, $kind: expr, $as_shared_string: expr ) => {
+        {
+            use std::borrow::Borrow;
+
+            let value = $value;
+            let fell_back = match $me.fallback {
+                None => false,
+                Some(ref mut fallback) => {
+                    let path = $path.borrow();
+                    let table = &$me.options
+                        .probability_tables
+                        .$table;
+                    let missing = match value {
+                        Some(ref string) if table.stats_by_node_value(path, &value).is_none() =>
+                            Some($as_shared_string(string)),
+                        _ => None
+                    };
+                    fallback.write(&mut $me.writer, $kind, $path, table.depth(), missing.as_ref())?
+                }
+            };
+            if fell_back {
+                Ok(())
+            } else {
+                symbol!($me, $table, $info, $description, $path, value)
+            }
+        }
+    }
+}
+
+impl Encoder {
+    /// Write an identifier name as its position among the recently used
+    /// identifier names, or as `recency::MISS` followed by the identifier
+    /// name, coded with the dictionary.
+    fn identifier_name_with_recency(&mut self, model: &mut RecencyModel<Option<IdentifierName>>, value: Option<IdentifierName>, path: &Path) -> Result<(), TokenWriterError> {
+        let recency_symbol = model.symbol(&value);
+
+        // Statistics: compare with the dictionary alone.
+        {
+            use std::borrow::Borrow;
+            if let Some(symbol) = self.options
+                .probability_tables
+                .identifier_name_by_path
+                .stats_by_node_value(path.borrow(), &value)
+            {
+                let distribution = self.distributions.get(&symbol.distribution);
+                self.recency_before.symbol(symbol.index.into(), distribution)
+                    .map_err(TokenWriterError::WriteError)?;
+                if recency_symbol == recency::MISS {
+                    self.recency_after.symbol(symbol.index.into(), distribution)
+                        .map_err(TokenWriterError::WriteError)?;
+                }
+            }
+        }
+        self.recency_after.symbol(recency_symbol as usize, model.distribution())
+            .map_err(TokenWriterError::WriteError)?;
+        self.recency_statistics.values += 1;
+
+        self.writer.symbol(recency_symbol, model.distribution())
+            .map_err(TokenWriterError::WriteError)?;
+        self.content_lengths
+            .identifier_names
+            .symbol(recency_symbol as usize, model.distribution())
+            .map_err(TokenWriterError::WriteError)?;
+        self.content_bounds
+            .identifier_names
+            .symbols += 1;
+        if let Some(bits) = bounds::information(recency_symbol as usize, model.distribution()) {
+            self.content_bounds
+                .identifier_names
+                .bound_bits += bits;
+        }
+        model.update(recency_symbol, value.clone());
+        if recency_symbol == recency::MISS {
+            string_symbol!(self, identifier_name_by_path, identifier_names, "identifier_name_by_path",  path,  value,
+                StringKind::IdentifierName, |name: &IdentifierName| name.as_shared_string().clone())
+        } else {
+            self.recency_statistics.hits += 1;
+            self.content_instances
+                .identifier_names += Into::<Instances>::into(1);
             Ok(())
         }
     }
@@ -107,8 +245,21 @@ impl TokenWriter for Encoder {
     type Data = Vec<u8>;
 
     fn done(self) -> Result<Self::Data, TokenWriterError> {
-        let data = self.writer.done()
+        let mut data = self.writer.done()
             .map_err(TokenWriterError::WriteError)?;
+        if let Some(fallback) = self.fallback {
+            let (mut section, statistics) = fallback.done()?;
+            self.options
+                .fallback_statistics
+                .borrow_mut()
+                .merge(&statistics);
+            section.extend(data);
+            data = section;
+        }
+        let mut header = Header::new(&self.options)
+            .write()?;
+        header.extend(data);
+        let data = header;
         *self.options
             .content_lengths
             .borrow_mut()
@@ -122,6 +273,28 @@ impl TokenWriter for Encoder {
             .borrow_mut()
             +=
         self.content_instances;
+        *self.options
+            .content_bounds
+            .borrow_mut()
+            +=
+        self.content_bounds;
+        {
+            let mut borrow = self.options
+                .recency_statistics
+                .borrow_mut();
+            borrow.values += self.recency_statistics.values;
+            borrow.hits += self.recency_statistics.hits;
+            borrow.gain += Gain {
+                before: self.recency_before.done()
+                    .unwrap()
+                    .len()
+                    .into(),
+                after: self.recency_after.done()
+                    .unwrap()
+                    .len()
+                    .into(),
+            };
+        }
         Ok(data)
     }
 
@@ -140,7 +313,8 @@ impl TokenWriter for Encoder {
     }
 
     fn string_at(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenWriterError> {
-        symbol!(self, string_literal_by_path, string_literals, "string_literal_by_path",  path,  value.cloned())
+        string_symbol!(self, string_literal_by_path, string_literals, "string_literal_by_path",  path,  value.cloned(),
+            StringKind::StringLiteral, |string: &SharedString| string.clone())
     }
 
     fn string_enum_at(&mut self, value: &SharedString, path: &Path) -> Result<(), TokenWriterError> {
@@ -148,11 +322,20 @@ impl TokenWriter for Encoder {
     }
 
     fn identifier_name_at(&mut self, value: Option<&IdentifierName>, path: &Path) -> Result<(), TokenWriterError> {
-        symbol!(self, identifier_name_by_path, identifier_names, "identifier_name_by_path",  path,  value.cloned())
+        match self.recency.take() {
+            None => string_symbol!(self, identifier_name_by_path, identifier_names, "identifier_name_by_path",  path,  value.cloned(),
+                StringKind::IdentifierName, |name: &IdentifierName| name.as_shared_string().clone()),
+            Some(mut model) => {
+                let result = self.identifier_name_with_recency(&mut model, value.cloned(), path);
+                self.recency = Some(model);
+                result
+            }
+        }
     }
 
     fn property_key_at(&mut self, value: Option<&PropertyKey>, path: &Path) -> Result<(), TokenWriterError> {
-        symbol!(self, property_key_by_path, property_keys, "property_key_by_path",  path,  value.cloned())
+        string_symbol!(self, property_key_by_path, property_keys, "property_key_by_path",  path,  value.cloned(),
+            StringKind::PropertyKey, |key: &PropertyKey| key.as_shared_string().clone())
     }
 
 
@@ -0,0 +1,170 @@
+//! The error returned by the entry points of the workspace, e.g. `binjs_es6::io::Decoder`
+//! or the stable API of crate `binjs`.
+//!
+//! Each layer of the implementation has its own error type, e.g. `TokenReaderError`.
+//! Entry points convert them to an `Error`, which describes the step that failed and
+//! chains the error of the implementation as its `source`.
+
+use binjs_shared::FromJSONError;
+
+use { TokenReaderError, TokenWriterError };
+
+use std;
+
+/// The step that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A file could not be read.
+    Io,
+
+    /// A source could not be parsed, or the parser returned an invalid AST.
+    Parse,
+
+    /// Scopes or laziness could not be introduced in the AST.
+    Annotation,
+
+    /// An AST could not be encoded, e.g. a string is missing from the dictionary.
+    Encode,
+
+    /// A binary could not be decoded, e.g. it is corrupted or was encoded with another
+    /// dictionary.
+    Decode,
+
+    /// A decoded AST could not be printed back to source.
+    Print,
+
+    /// A dictionary could not be loaded.
+    Dictionary,
+
+    /// More kinds may be added, don't match exhaustively.
+    #[doc(hidden)]
+    __Nonexhaustive,
+}
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str(match *self {
+            ErrorKind::Io => "could not read file",
+            ErrorKind::Parse => "could not parse source",
+            ErrorKind::Annotation => "could not annotate AST",
+            ErrorKind::Encode => "could not encode",
+            ErrorKind::Decode => "could not decode",
+            ErrorKind::Print => "could not print source",
+            ErrorKind::Dictionary => "could not load dictionary",
+            ErrorKind::__Nonexhaustive => unreachable!(),
+        })
+    }
+}
+
+/// A snapshot of the error of the implementation that caused an `Error`, and of its
+/// own sources.
+///
+/// The errors of the implementation are not always `Send`, while `Error` must be.
+#[derive(Debug)]
+struct Cause {
+    message: String,
+    source: Option<Box<Cause>>,
+}
+impl Cause {
+    fn new(error: &std::error::Error) -> Self {
+        Cause {
+            message: error.to_string(),
+            source: error.source()
+                .map(|source| Box::new(Cause::new(source))),
+        }
+    }
+}
+impl std::fmt::Display for Cause {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str(&self.message)
+    }
+}
+impl std::error::Error for Cause {
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        self.source.as_ref()
+            .map(|source| &**source as &(std::error::Error + 'static))
+    }
+}
+
+/// An error while encoding or decoding.
+///
+/// `Display` describes the step that failed, the details are available through
+/// `std::error::Error::source`, e.g. `"could not parse source"`, caused by
+/// `"syntax error at 1:9: Unexpected token"`. `Debug` prints the entire chain,
+/// e.g. for command-line tools.
+pub struct Error {
+    kind: ErrorKind,
+    source: Option<Cause>,
+}
+impl Error {
+    /// An error of kind `kind`, caused by `error`.
+    pub fn new(kind: ErrorKind, error: &std::error::Error) -> Self {
+        Error {
+            kind,
+            source: Some(Cause::new(error)),
+        }
+    }
+
+    /// An error of kind `kind`, without details.
+    pub fn without_source(kind: ErrorKind) -> Self {
+        Error {
+            kind,
+            source: None,
+        }
+    }
+
+    /// The step that failed.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        std::fmt::Display::fmt(&self.kind, f)
+    }
+}
+impl std::fmt::Debug for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        std::fmt::Display::fmt(&self.kind, f)?;
+        let mut source = self.source.as_ref();
+        while let Some(cause) = source {
+            write!(f, ": {}", cause.message)?;
+            source = cause.source.as_ref()
+                .map(|source| &**source);
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        self.source.as_ref()
+            .map(|source| source as &(std::error::Error + 'static))
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::new(ErrorKind::Io, &error)
+    }
+}
+impl From<FromJSONError> for Error {
+    fn from(error: FromJSONError) -> Self {
+        Error::new(ErrorKind::Parse, &error)
+    }
+}
+impl From<TokenWriterError> for Error {
+    fn from(error: TokenWriterError) -> Self {
+        Error::new(ErrorKind::Encode, &error)
+    }
+}
+impl From<TokenReaderError> for Error {
+    fn from(error: TokenReaderError) -> Self {
+        Error::new(ErrorKind::Decode, &error)
+    }
+}
+
+#[test]
+fn test_error_chain() {
+    let error = Error::from(TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "truncated")));
+    assert_eq!(error.kind(), ErrorKind::Decode);
+    assert_eq!(error.to_string(), "could not decode");
+    assert_eq!(format!("{:?}", error), "could not decode: could not read: truncated");
+}
@@ -3,7 +3,7 @@
 //! copy of the old `io::TokenWriter` along with a `TokenWriterTreeAdapter` which wraps a `TokenWriterWithTree`
 //! as a new `io::TokenWriter`.
 
-use binjs_shared::{ IdentifierName, InterfaceName, FieldName, Node, PropertyKey, SharedString };
+use binjs_shared::{ BigInt, IdentifierName, InterfaceName, FieldName, Node, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
 
 use ::{ Path, TokenWriter, TokenWriterError };
 
@@ -54,6 +54,30 @@ pub trait TokenWriterWithTree {
         unimplemented!()
     }
 
+    /// Write the value of a single BigInt literal.
+    ///
+    /// The default implementation writes its decimal digits with `self.string`.
+    fn big_int(&mut self, value: Option<&BigInt>) -> Result<Self::Tree, TokenWriterError> {
+        let string = value.map(BigInt::as_shared_string);
+        self.string(string)
+    }
+
+    /// Write the pattern of a single RegExp literal.
+    ///
+    /// The default implementation writes it with `self.string`.
+    fn reg_exp_pattern(&mut self, value: Option<&RegExpPattern>) -> Result<Self::Tree, TokenWriterError> {
+        let string = value.map(RegExpPattern::as_shared_string);
+        self.string(string)
+    }
+
+    /// Write the flags of a single RegExp literal.
+    ///
+    /// The default implementation writes them with `self.string`.
+    fn reg_exp_flags(&mut self, value: Option<&RegExpFlags>) -> Result<Self::Tree, TokenWriterError> {
+        let string = value.map(RegExpFlags::as_shared_string);
+        self.string(string)
+    }
+
     /// Write a single u32.
     fn unsigned_long(&mut self, _: u32) -> Result<Self::Tree, TokenWriterError> {
         unimplemented!()
@@ -95,6 +119,11 @@ impl<T> TokenWriterTreeAdapter<T>  where T: TokenWriterWithTree {
         }
     }
 
+    /// Access the underlying writer.
+    pub fn writer_mut(&mut self) -> &mut T {
+        &mut self.writer
+    }
+
     pub fn top_mut(&mut self) -> &mut Vec<T::Tree> {
         self.stack.last_mut()
             .expect("Empty stack while replacing last child")
@@ -161,6 +190,24 @@ impl<T> TokenWriter for TokenWriterTreeAdapter<T> where T: TokenWriterWithTree {
             .push(child);
         Ok(())
     }
+    fn big_int_at(&mut self, value: Option<&BigInt>, _path: &Path) -> Result<(), TokenWriterError> {
+        let child = self.writer.big_int(value)?;
+        self.top_mut()
+            .push(child);
+        Ok(())
+    }
+    fn reg_exp_pattern_at(&mut self, value: Option<&RegExpPattern>, _path: &Path) -> Result<(), TokenWriterError> {
+        let child = self.writer.reg_exp_pattern(value)?;
+        self.top_mut()
+            .push(child);
+        Ok(())
+    }
+    fn reg_exp_flags_at(&mut self, value: Option<&RegExpFlags>, _path: &Path) -> Result<(), TokenWriterError> {
+        let child = self.writer.reg_exp_flags(value)?;
+        self.top_mut()
+            .push(child);
+        Ok(())
+    }
     fn unsigned_long_at(&mut self, value: u32, _path: &Path) -> Result<(), TokenWriterError> {
         let child = self.writer.unsigned_long(value)?;
         self.top_mut()
@@ -0,0 +1,406 @@
+//! Reporting the tokens read by decoders as a stream of events.
+//!
+//! A `TokenReaderEventAdapter` forwards each token read from another `TokenReader`
+//! to an `EventHandler`, SAX-style. Tools that only need to observe the tree, e.g.
+//! to compute metrics, may implement `EventHandler` rather than walking a
+//! materialized AST.
+//!
+//! The adapter is itself a `TokenReader`, so that any driver of token readers may
+//! sit on top of it:
+//!
+//! - the strongly-typed deserializer of `binjs_es6`, which builds the AST as usual.
+//!   All its decoders read through the adapter, with `IgnoreEvents` unless a handler
+//!   is given to `binjs_es6::io::Decoder::decode_with_events`;
+//! - the grammar-driven reader of `binjs_generic::io`, which reads the tree
+//!   without materializing it.
+//!
+//! Null values are reported as they are read, e.g. `string(None, ...)`. Null
+//! interfaces are reported as an interface named after the null name of the grammar.
+
+use binjs_shared::{ BigInt, IdentifierName, InterfaceName, FieldName, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
+
+use ::{ FileStructurePrinter, Path, Token, TokenKind, TokenReader, TokenReaderError };
+
+use std;
+use std::rc::Rc;
+
+/// A handler for the tokens read by a `TokenReaderEventAdapter`.
+///
+/// All methods do nothing by default. Returning an error stops reading.
+pub trait EventHandler {
+    /// Called after reading the tag of a tagged tuple.
+    fn enter_interface(&mut self, _name: &InterfaceName, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    /// Called after reading all the fields of a tagged tuple.
+    fn exit_interface(&mut self, _name: &InterfaceName, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    /// Called after reading the length of a list.
+    fn enter_list(&mut self, _len: u32, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    /// Called after reading all the items of a list.
+    fn exit_list(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn bool(&mut self, _value: Option<bool>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn float(&mut self, _value: Option<f64>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn unsigned_long(&mut self, _value: u32, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn string(&mut self, _value: Option<&SharedString>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn string_enum(&mut self, _value: &SharedString, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn identifier_name(&mut self, _value: Option<&IdentifierName>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn property_key(&mut self, _value: Option<&PropertyKey>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn big_int(&mut self, _value: Option<&BigInt>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn reg_exp_pattern(&mut self, _value: Option<&RegExpPattern>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    fn reg_exp_flags(&mut self, _value: Option<&RegExpFlags>, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+
+    /// Called after reading the byte length of a lazy field, with `skipped` set
+    /// if its contents are skipped by the reader, in which case no events are
+    /// reported for them.
+    fn offset(&mut self, _byte_len: u32, _skipped: bool, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+}
+
+/// An `EventHandler` ignoring all events, for drivers that only need the tokens.
+pub struct IgnoreEvents;
+impl EventHandler for IgnoreEvents {}
+
+/// A `TokenReader` that reports the tokens read from another `TokenReader` to an `EventHandler`.
+pub struct TokenReaderEventAdapter<R, H> where R: TokenReader, H: EventHandler {
+    reader: R,
+    handler: H,
+
+    /// The tagged tuples being read, innermost last.
+    interfaces: Vec<InterfaceName>,
+
+    /// The byte length read by the latest call to `offset_at`, until we find out
+    /// whether the contents are skipped.
+    pending_offset: Option<u32>,
+}
+impl<R, H> TokenReaderEventAdapter<R, H> where R: TokenReader, H: EventHandler {
+    pub fn new(reader: R, handler: H) -> Self {
+        TokenReaderEventAdapter {
+            reader,
+            handler,
+            interfaces: vec![],
+            pending_offset: None,
+        }
+    }
+
+    /// Access the underlying reader.
+    pub fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// Access the handler.
+    pub fn handler_mut(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// Stop reporting events, returning the underlying reader and the handler.
+    pub fn done(self) -> (R, H) {
+        (self.reader, self.handler)
+    }
+
+    /// Report an offset that was not followed by `skip_lazy_at`, if any.
+    fn flush_offset(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        match self.pending_offset.take() {
+            Some(byte_len) => self.handler.offset(byte_len, false, path),
+            None => Ok(())
+        }
+    }
+
+    fn token(&mut self, token: &Token, path: &Path) -> Result<(), TokenReaderError> {
+        match *token {
+            Token::Bool(value) => self.handler.bool(value, path),
+            Token::Float(value) => self.handler.float(value, path),
+            Token::UnsignedLong(value) => self.handler.unsigned_long(value, path),
+            Token::String(ref value) => self.handler.string(value.as_ref(), path),
+            Token::StringEnum(ref value) => self.handler.string_enum(value, path),
+            Token::IdentifierName(ref value) => self.handler.identifier_name(value.as_ref(), path),
+            Token::PropertyKey(ref value) => self.handler.property_key(value.as_ref(), path),
+        }
+    }
+}
+
+impl<R, H> FileStructurePrinter for TokenReaderEventAdapter<R, H> where R: TokenReader, H: EventHandler {
+    fn enable_file_structure_print(&mut self) {
+        self.reader.enable_file_structure_print()
+    }
+    fn disable_file_structure_print(&mut self) {
+        self.reader.disable_file_structure_print()
+    }
+    fn is_file_structure_print_enabled(&mut self) -> bool {
+        self.reader.is_file_structure_print_enabled()
+    }
+    fn prepare_file_structure_column(&mut self) {
+        self.reader.prepare_file_structure_column()
+    }
+    fn print_file_structure_label(&mut self, label: std::fmt::Arguments) {
+        self.reader.print_file_structure_label(label)
+    }
+    fn newline_for_file_structure_print(&mut self) {
+        self.reader.newline_for_file_structure_print()
+    }
+}
+
+impl<R, H> TokenReader for TokenReaderEventAdapter<R, H> where R: TokenReader, H: EventHandler {
+    fn poison(&mut self) {
+        self.reader.poison()
+    }
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.string_at(path)?;
+        self.handler.string(value.as_ref(), path)?;
+        Ok(value)
+    }
+    fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.string_enum_at(path)?;
+        self.handler.string_enum(&value, path)?;
+        Ok(value)
+    }
+    fn identifier_name_at(&mut self, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.identifier_name_at(path)?;
+        self.handler.identifier_name(value.as_ref(), path)?;
+        Ok(value)
+    }
+    fn property_key_at(&mut self, path: &Path) -> Result<Option<PropertyKey>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.property_key_at(path)?;
+        self.handler.property_key(value.as_ref(), path)?;
+        Ok(value)
+    }
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.float_at(path)?;
+        self.handler.float(value, path)?;
+        Ok(value)
+    }
+    fn big_int_at(&mut self, path: &Path) -> Result<Option<BigInt>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.big_int_at(path)?;
+        self.handler.big_int(value.as_ref(), path)?;
+        Ok(value)
+    }
+    fn reg_exp_pattern_at(&mut self, path: &Path) -> Result<Option<RegExpPattern>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.reg_exp_pattern_at(path)?;
+        self.handler.reg_exp_pattern(value.as_ref(), path)?;
+        Ok(value)
+    }
+    fn reg_exp_flags_at(&mut self, path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.reg_exp_flags_at(path)?;
+        self.handler.reg_exp_flags(value.as_ref(), path)?;
+        Ok(value)
+    }
+    fn unsigned_long_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.unsigned_long_at(path)?;
+        self.handler.unsigned_long(value, path)?;
+        Ok(value)
+    }
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        self.flush_offset(path)?;
+        let value = self.reader.bool_at(path)?;
+        self.handler.bool(value, path)?;
+        Ok(value)
+    }
+    fn offset_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.flush_offset(path)?;
+        let byte_len = self.reader.offset_at(path)?;
+        self.pending_offset = Some(byte_len);
+        Ok(byte_len)
+    }
+    fn skip_lazy_at(&mut self, byte_len: u32, path: &Path) -> Result<bool, TokenReaderError> {
+        self.pending_offset = None;
+        let skipped = self.reader.skip_lazy_at(byte_len, path)?;
+        self.handler.offset(byte_len, skipped, path)?;
+        Ok(skipped)
+    }
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.flush_offset(path)?;
+        let len = self.reader.enter_list_at(path)?;
+        self.handler.enter_list(len, path)?;
+        Ok(len)
+    }
+    fn exit_list_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.exit_list_at(path)?;
+        self.handler.exit_list(path)
+    }
+    fn enter_tagged_tuple_at(&mut self, path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
+        self.flush_offset(path)?;
+        let (name, fields) = self.reader.enter_tagged_tuple_at(path)?;
+        self.handler.enter_interface(&name, path)?;
+        self.interfaces.push(name.clone());
+        Ok((name, fields))
+    }
+    fn exit_tagged_tuple_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.exit_tagged_tuple_at(path)?;
+        match self.interfaces.pop() {
+            Some(name) => self.handler.exit_interface(&name, path),
+            // The driver exited a tagged tuple that it never entered.
+            None => Err(TokenReaderError::InvalidValue)
+        }
+    }
+    fn enter_untagged_tuple_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.enter_untagged_tuple_at(path)
+    }
+    fn exit_untagged_tuple_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.exit_untagged_tuple_at(path)
+    }
+    fn token_at(&mut self, kind: TokenKind, path: &Path) -> Result<Token, TokenReaderError> {
+        self.flush_offset(path)?;
+        let token = self.reader.token_at(kind, path)?;
+        self.token(&token, path)?;
+        Ok(token)
+    }
+    fn tokens_at(&mut self, kind: TokenKind, count: usize, path: &Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        self.flush_offset(path)?;
+        let start = tokens.len();
+        self.reader.tokens_at(kind, count, path, tokens)?;
+        for token in &tokens[start..] {
+            self.token(token, path)?;
+        }
+        Ok(())
+    }
+    fn fields_at(&mut self, fields: &[((usize, FieldName), TokenKind)], path: &mut Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        self.flush_offset(path)?;
+        let start = tokens.len();
+        self.reader.fields_at(fields, path, tokens)?;
+        for (&(ref field, _), token) in fields.iter().zip(&tokens[start..]) {
+            path.enter_field(field.clone());
+            let result = self.token(token, path);
+            path.exit_field(field.clone());
+            result?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_events() {
+    use binjs_shared::Node;
+    use io::{ TokenWriter, TokenWriterTreeAdapter };
+    use simple;
+
+    struct Dummy;
+    impl Node for Dummy {
+        fn name(&self) -> &'static str {
+            "Dummy"
+        }
+    }
+
+    /// Record events as strings.
+    #[derive(Default)]
+    struct Recorder {
+        events: Vec<String>,
+    }
+    impl EventHandler for Recorder {
+        fn enter_interface(&mut self, name: &InterfaceName, _path: &Path) -> Result<(), TokenReaderError> {
+            self.events.push(format!("enter {}", name.as_str()));
+            Ok(())
+        }
+        fn exit_interface(&mut self, name: &InterfaceName, _path: &Path) -> Result<(), TokenReaderError> {
+            self.events.push(format!("exit {}", name.as_str()));
+            Ok(())
+        }
+        fn enter_list(&mut self, len: u32, _path: &Path) -> Result<(), TokenReaderError> {
+            self.events.push(format!("list {}", len));
+            Ok(())
+        }
+        fn string(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenReaderError> {
+            self.events.push(format!("string {:?} at depth {}", value.map(SharedString::as_str), path.len()));
+            Ok(())
+        }
+    }
+
+    let tag = InterfaceName::from_str("Dummy");
+    let field = FieldName::from_str("names");
+    let mut path = Path::new();
+
+    let data = {
+        let mut writer = TokenWriterTreeAdapter::new(simple::TreeTokenWriter::new());
+        writer.enter_tagged_tuple_at(&Dummy, &tag, &[&field], &path)
+            .expect("Writing tagged tuple");
+        path.enter_interface(tag.clone());
+        path.enter_field((0, field.clone()));
+        writer.enter_list_at(2, &path)
+            .expect("Writing list");
+        writer.string_at(Some(&SharedString::from_str("foo")), &path)
+            .expect("Writing string");
+        writer.string_at(None, &path)
+            .expect("Writing string");
+        writer.exit_list_at(&path)
+            .expect("Writing list");
+        path.exit_field((0, field.clone()));
+        path.exit_interface(tag.clone());
+        writer.exit_tagged_tuple_at(&Dummy, &tag, &[&field], &path)
+            .expect("Writing tagged tuple");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let mut reader = TokenReaderEventAdapter::new(simple::TreeTokenReader::new(std::io::Cursor::new(data)), Recorder::default());
+    reader.enter_tagged_tuple_at(&path)
+        .expect("Reading tagged tuple");
+    path.enter_interface(tag.clone());
+    path.enter_field((0, field.clone()));
+    let len = reader.enter_list_at(&path)
+        .expect("Reading list");
+    let mut tokens = vec![];
+    reader.tokens_at(TokenKind::String, len as usize, &path, &mut tokens)
+        .expect("Reading strings");
+    reader.exit_list_at(&path)
+        .expect("Reading list");
+    path.exit_field((0, field.clone()));
+    path.exit_interface(tag.clone());
+    reader.exit_tagged_tuple_at(&path)
+        .expect("Reading tagged tuple");
+
+    let (_, recorder) = reader.done();
+    assert_eq!(recorder.events, vec![
+        "enter Dummy",
+        "list 2",
+        "string Some(\"foo\") at depth 1",
+        "string None at depth 1",
+        "exit Dummy",
+    ]);
+}
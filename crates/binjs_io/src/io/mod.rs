@@ -7,11 +7,12 @@
 //! In practice, this API is kept as a trait to simplify unit testing and
 //! experimentation of sophisticated compression schemes.
 
-use binjs_shared::{ IdentifierName, InterfaceName, FieldName, PropertyKey, SharedString, self };
+use binjs_shared::{ BigInt, IdentifierName, InterfaceName, FieldName, PropertyKey, RegExpFlags, RegExpPattern, SharedString, self };
 use binjs_shared::ast::Node;
 
-use ::{ TokenReaderError, TokenWriterError };
+use ::{ GrammarId, TokenReaderError, TokenWriterError };
 
+use std;
 use std::rc::Rc;
 
 
@@ -22,6 +23,16 @@ pub use self::deprecated::{ TokenWriterWithTree, TokenWriterTreeAdapter };
 /// Utilities to collect statistics about the data written.
 pub mod statistics;
 
+/// Utilities to report the progress of long encodes.
+pub mod progress;
+
+/// Utilities to report the tokens read by decoders as events.
+pub mod events;
+
+/// Utilities to count the symbols read by decoders.
+#[cfg(feature = "profiling")]
+pub mod profile;
+
 
 /// An API for printing the binary representation and its structural
 /// interpretation of the file.
@@ -87,6 +98,15 @@ pub trait FileStructurePrinter {
     /// number of bytes it reads for each line.
     fn prepare_file_structure_column(&mut self) {}
 
+    /// Prints the structural interpretation itself, between
+    /// `prepare_file_structure_column` and `newline_for_file_structure_print`.
+    ///
+    /// Implementations may override this method to record the structural
+    /// interpretation instead of printing it.
+    fn print_file_structure_label(&mut self, label: std::fmt::Arguments) {
+        print!("{}", label);
+    }
+
     /// Prints newline after printing the structural interpretation column.
     /// The implementation is supposed to print newline character(s) and also
     /// reset the current column position internally, in order to print the
@@ -145,7 +165,7 @@ macro_rules! print_file_structure(
     ( $reader:expr, $fmt:expr $( , $more:expr )* ) => (
         if $reader.is_file_structure_print_enabled() {
             $reader.prepare_file_structure_column();
-            print!( $fmt $( , $more )* );
+            $reader.print_file_structure_label(format_args!( $fmt $( , $more )* ));
             $reader.newline_for_file_structure_print();
         }
     )
@@ -154,6 +174,44 @@ macro_rules! print_file_structure(
 
 pub type Path = binjs_shared::ast::Path<InterfaceName, /* Field */ (usize, FieldName)>;
 
+/// The kinds of tokens that may be read in batches, see `TokenReader::tokens_at`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    Bool,
+    Float,
+    UnsignedLong,
+    String,
+    StringEnum,
+    IdentifierName,
+    PropertyKey,
+}
+impl TokenKind {
+    /// A human-readable name, e.g. `"unsigned_long"`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TokenKind::Bool => "bool",
+            TokenKind::Float => "float",
+            TokenKind::UnsignedLong => "unsigned_long",
+            TokenKind::String => "string",
+            TokenKind::StringEnum => "string_enum",
+            TokenKind::IdentifierName => "identifier_name",
+            TokenKind::PropertyKey => "property_key",
+        }
+    }
+}
+
+/// A token read in a batch, see `TokenReader::tokens_at`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+    Bool(Option<bool>),
+    Float(Option<f64>),
+    UnsignedLong(u32),
+    String(Option<SharedString>),
+    StringEnum(SharedString),
+    IdentifierName(Option<IdentifierName>),
+    PropertyKey(Option<PropertyKey>),
+}
+
 /// An API for reading tokens.
 ///
 /// Note that a `TokenReader` by itself *cannot* determine the nature of the
@@ -214,6 +272,45 @@ pub trait TokenReader: FileStructurePrinter where Self: Sized
     /// Read a single `f64`. Note that all user-level numbers are `f64`.
     fn float_at(&mut self, _path: &Path) -> Result<Option<f64>, TokenReaderError>;
 
+    /// Read the value of a single BigInt literal.
+    ///
+    /// The default implementation reads its decimal digits with `self.string_at`,
+    /// but some encodings may use a more compact representation, see
+    /// `bytes::bigint`.
+    fn big_int_at(&mut self, path: &Path) -> Result<Option<BigInt>, TokenReaderError> {
+        let result = self.string_at(path)?
+            .map(BigInt);
+        Ok(result)
+    }
+
+    /// Read the pattern of a single RegExp literal.
+    ///
+    /// The default implementation reads it with `self.string_at`, but some
+    /// encodings may store patterns apart from other strings.
+    fn reg_exp_pattern_at(&mut self, path: &Path) -> Result<Option<RegExpPattern>, TokenReaderError> {
+        let result = self.string_at(path)?
+            .map(RegExpPattern);
+        Ok(result)
+    }
+
+    /// Read the flags of a single RegExp literal.
+    ///
+    /// The default implementation reads them with `self.string_at`, but some
+    /// encodings may use a bitfield, see `bytes::regexp`. In either case,
+    /// unknown or duplicate flags are rejected.
+    fn reg_exp_flags_at(&mut self, path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        match self.string_at(path)? {
+            None => Ok(None),
+            Some(string) => {
+                let flags = RegExpFlags(string);
+                flags.to_bits()
+                    .and_then(RegExpFlags::from_bits)
+                    .map(Some)
+                    .ok_or_else(|| TokenReaderError::invalid_value(&flags))
+            }
+        }
+    }
+
     /// Read a single `u32`.
     fn unsigned_long_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError>;
 
@@ -223,6 +320,15 @@ pub trait TokenReader: FileStructurePrinter where Self: Sized
     /// Read a single number of bytes.
     fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError>;
 
+    /// Called after reading the offset of a lazy field, with the number of bytes of its
+    /// contents. If this returns `true`, the contents have been skipped and are not read,
+    /// and the deserializer replaces them with a placeholder.
+    ///
+    /// By default, contents are never skipped.
+    fn skip_lazy_at(&mut self, _byte_len: u32, _path: &Path) -> Result<bool, TokenReaderError> {
+        Ok(false)
+    }
+
     /// Start reading a list.
     fn enter_list_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError>;
 
@@ -248,6 +354,70 @@ pub trait TokenReader: FileStructurePrinter where Self: Sized
     fn exit_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
         Ok(())
     }
+
+    // ---- Batched reads
+
+    /// Read a single token of kind `kind`.
+    fn token_at(&mut self, kind: TokenKind, path: &Path) -> Result<Token, TokenReaderError> {
+        let token = match kind {
+            TokenKind::Bool => Token::Bool(self.bool_at(path)?),
+            TokenKind::Float => Token::Float(self.float_at(path)?),
+            TokenKind::UnsignedLong => Token::UnsignedLong(self.unsigned_long_at(path)?),
+            TokenKind::String => Token::String(self.string_at(path)?),
+            TokenKind::StringEnum => Token::StringEnum(self.string_enum_at(path)?),
+            TokenKind::IdentifierName => Token::IdentifierName(self.identifier_name_at(path)?),
+            TokenKind::PropertyKey => Token::PropertyKey(self.property_key_at(path)?),
+        };
+        Ok(token)
+    }
+
+    /// Read `count` consecutive tokens of kind `kind`, all at `path`, e.g. the
+    /// items of a list of primitive values, appending them to `tokens`.
+    ///
+    /// The default implementation reads tokens one at a time, but some encodings
+    /// may amortize the cost of each read, e.g. by looking up the probability
+    /// table of `path` once for the entire batch.
+    fn tokens_at(&mut self, kind: TokenKind, count: usize, path: &Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        tokens.reserve(count);
+        for _ in 0..count {
+            let token = self.token_at(kind, path)?;
+            tokens.push(token);
+        }
+        Ok(())
+    }
+
+    /// Read consecutive fields of the tagged tuple being read at `path`, in order,
+    /// appending them to `tokens`. Each field is specified by its index in the
+    /// interface, its name and the kind of its value.
+    ///
+    /// Each field is entered in `path` while it is read, then exited, so `path`
+    /// is unchanged once this method returns, even in case of error.
+    ///
+    /// The default implementation reads fields one at a time, but some encodings
+    /// may amortize the cost of each read, e.g. by selecting their bit-level coder
+    /// once for the entire batch.
+    fn fields_at(&mut self, fields: &[((usize, FieldName), TokenKind)], path: &mut Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        tokens.reserve(fields.len());
+        for &(ref field, kind) in fields {
+            path.enter_field(field.clone());
+            let token = self.token_at(kind, path);
+            path.exit_field(field.clone());
+            tokens.push(token?);
+        }
+        Ok(())
+    }
+}
+
+/// A computation on a `TokenReader` of any type, see `Format::read`.
+///
+/// Each format is read by a different type of `TokenReader`, which closures
+/// cannot be generic over.
+pub trait ReaderVisitor {
+    type Output;
+
+    /// Run the computation on `reader`. If the format supports it, `grammar` is
+    /// the grammar declared by the file.
+    fn visit<R: TokenReader>(self, reader: R, grammar: Option<&GrammarId>) -> Result<Self::Output, TokenReaderError>;
 }
 
 /// Build an in-memory representation of a BinTree.
@@ -304,6 +474,34 @@ pub trait TokenWriter {
     /// Write a single number.
     fn float_at(&mut self, value: Option<f64>, _path: &Path) -> Result<(), TokenWriterError>;
 
+    /// Write the value of a single BigInt literal.
+    ///
+    /// The default implementation writes its decimal digits with `self.string_at`,
+    /// but some encodings may use a more compact representation, see
+    /// `bytes::bigint`.
+    fn big_int_at(&mut self, value: Option<&BigInt>, path: &Path) -> Result<(), TokenWriterError> {
+        let string = value.map(BigInt::as_shared_string);
+        self.string_at(string, path)
+    }
+
+    /// Write the pattern of a single RegExp literal.
+    ///
+    /// The default implementation writes it with `self.string_at`, but some
+    /// encodings may store patterns apart from other strings.
+    fn reg_exp_pattern_at(&mut self, value: Option<&RegExpPattern>, path: &Path) -> Result<(), TokenWriterError> {
+        let string = value.map(RegExpPattern::as_shared_string);
+        self.string_at(string, path)
+    }
+
+    /// Write the flags of a single RegExp literal.
+    ///
+    /// The default implementation writes them with `self.string_at`, but some
+    /// encodings may use a bitfield, see `bytes::regexp`.
+    fn reg_exp_flags_at(&mut self, value: Option<&RegExpFlags>, path: &Path) -> Result<(), TokenWriterError> {
+        let string = value.map(RegExpFlags::as_shared_string);
+        self.string_at(string, path)
+    }
+
     /// Write a single u32.
     fn unsigned_long_at(&mut self, value: u32, _path: &Path) -> Result<(), TokenWriterError>;
 
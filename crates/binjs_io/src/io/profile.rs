@@ -0,0 +1,305 @@
+//! Profiling decoders.
+//!
+//! A `TokenReaderProfiler` counts the symbols read by a decoder, by path in the
+//! AST and by kind of symbol. The resulting `Profile` may be written in the
+//! folded stacks format used by flame graph tools, e.g.
+//! `flamegraph.pl profile.folded > profile.svg`, with one frame per field
+//! along the path, so that the widest frames are the grammar productions that
+//! dominate the cost of decoding.
+//!
+//! This module is only available with feature `profiling`.
+
+use binjs_shared::{ BigInt, IdentifierName, InterfaceName, FieldName, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
+
+use ::{ FileStructurePrinter, Path, Token, TokenKind, TokenReader, TokenReaderError };
+
+use std;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The number of symbols read, by path and by kind of symbol.
+#[derive(Clone, Default)]
+pub struct Profile {
+    /// Path => kind of symbol (e.g. `"string"`) => number of reads.
+    reads: HashMap<Path, HashMap<&'static str, usize>>,
+}
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a read of a symbol of kind `kind` at `path`.
+    pub fn record(&mut self, path: &Path, kind: &'static str) {
+        // Avoid cloning the path if it has already been seen.
+        if let Some(kinds) = self.reads.get_mut(path) {
+            *kinds.entry(kind)
+                .or_insert(0) += 1;
+            return;
+        }
+        let mut kinds = HashMap::new();
+        kinds.insert(kind, 1);
+        self.reads.insert(path.clone(), kinds);
+    }
+
+    /// The total number of symbols read.
+    pub fn total(&self) -> usize {
+        self.reads.values()
+            .flat_map(|kinds| kinds.values())
+            .sum()
+    }
+
+    /// The number of symbols read, by kind of symbol.
+    pub fn by_kind(&self) -> HashMap<&'static str, usize> {
+        let mut result = HashMap::new();
+        for kinds in self.reads.values() {
+            for (kind, count) in kinds {
+                *result.entry(*kind)
+                    .or_insert(0) += count;
+            }
+        }
+        result
+    }
+
+    /// Merge the reads of `other` into this profile, e.g. to profile a corpus.
+    pub fn merge(&mut self, other: &Profile) {
+        for (path, kinds) in &other.reads {
+            let entry = self.reads.entry(path.clone())
+                .or_insert_with(HashMap::new);
+            for (kind, count) in kinds {
+                *entry.entry(*kind)
+                    .or_insert(0) += count;
+            }
+        }
+    }
+
+    /// Write the profile in the folded stacks format, one line per path and kind
+    /// of symbol, e.g. `Script.statements;ExpressionStatement.expression;string 12`.
+    ///
+    /// Lines are sorted, so that the output of identical profiles is identical.
+    pub fn write_folded<W: std::io::Write>(&self, out: &mut W) -> Result<(), std::io::Error> {
+        let mut lines = vec![];
+        for (path, kinds) in &self.reads {
+            let mut stack = String::new();
+            for item in path.iter() {
+                let (_, ref field) = *item.field();
+                stack.push_str(&format!("{}.{};", item.interface().as_str(), field.as_str()));
+            }
+            for (kind, count) in kinds {
+                lines.push((format!("{}{}", stack, kind), *count));
+            }
+        }
+        lines.sort();
+        for (stack, count) in lines {
+            writeln!(out, "{} {}", stack, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `TokenReader` that records the symbols read from another `TokenReader` in a `Profile`.
+pub struct TokenReaderProfiler<R> where R: TokenReader {
+    reader: R,
+    profile: Profile,
+}
+impl<R> TokenReaderProfiler<R> where R: TokenReader {
+    pub fn new(reader: R) -> Self {
+        TokenReaderProfiler {
+            reader,
+            profile: Profile::new(),
+        }
+    }
+
+    /// Access the underlying reader.
+    pub fn reader_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+
+    /// The symbols read so far.
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    /// Stop profiling, returning the underlying reader and the symbols read.
+    pub fn done(self) -> (R, Profile) {
+        (self.reader, self.profile)
+    }
+}
+
+impl<R> FileStructurePrinter for TokenReaderProfiler<R> where R: TokenReader {
+    fn enable_file_structure_print(&mut self) {
+        self.reader.enable_file_structure_print()
+    }
+    fn disable_file_structure_print(&mut self) {
+        self.reader.disable_file_structure_print()
+    }
+    fn is_file_structure_print_enabled(&mut self) -> bool {
+        self.reader.is_file_structure_print_enabled()
+    }
+    fn prepare_file_structure_column(&mut self) {
+        self.reader.prepare_file_structure_column()
+    }
+    fn print_file_structure_label(&mut self, label: std::fmt::Arguments) {
+        self.reader.print_file_structure_label(label)
+    }
+    fn newline_for_file_structure_print(&mut self) {
+        self.reader.newline_for_file_structure_print()
+    }
+}
+
+impl<R> TokenReader for TokenReaderProfiler<R> where R: TokenReader {
+    fn poison(&mut self) {
+        self.reader.poison()
+    }
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        self.profile.record(path, "string");
+        self.reader.string_at(path)
+    }
+    fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
+        self.profile.record(path, "string_enum");
+        self.reader.string_enum_at(path)
+    }
+    fn identifier_name_at(&mut self, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
+        self.profile.record(path, "identifier_name");
+        self.reader.identifier_name_at(path)
+    }
+    fn property_key_at(&mut self, path: &Path) -> Result<Option<PropertyKey>, TokenReaderError> {
+        self.profile.record(path, "property_key");
+        self.reader.property_key_at(path)
+    }
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        self.profile.record(path, "float");
+        self.reader.float_at(path)
+    }
+    fn big_int_at(&mut self, path: &Path) -> Result<Option<BigInt>, TokenReaderError> {
+        self.profile.record(path, "big_int");
+        self.reader.big_int_at(path)
+    }
+    fn reg_exp_pattern_at(&mut self, path: &Path) -> Result<Option<RegExpPattern>, TokenReaderError> {
+        self.profile.record(path, "reg_exp_pattern");
+        self.reader.reg_exp_pattern_at(path)
+    }
+    fn reg_exp_flags_at(&mut self, path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        self.profile.record(path, "reg_exp_flags");
+        self.reader.reg_exp_flags_at(path)
+    }
+    fn unsigned_long_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.profile.record(path, "unsigned_long");
+        self.reader.unsigned_long_at(path)
+    }
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        self.profile.record(path, "bool");
+        self.reader.bool_at(path)
+    }
+    fn offset_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.profile.record(path, "offset");
+        self.reader.offset_at(path)
+    }
+    fn skip_lazy_at(&mut self, byte_len: u32, path: &Path) -> Result<bool, TokenReaderError> {
+        self.reader.skip_lazy_at(byte_len, path)
+    }
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.profile.record(path, "list");
+        self.reader.enter_list_at(path)
+    }
+    fn exit_list_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.exit_list_at(path)
+    }
+    fn enter_tagged_tuple_at(&mut self, path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
+        self.profile.record(path, "tag");
+        self.reader.enter_tagged_tuple_at(path)
+    }
+    fn exit_tagged_tuple_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.exit_tagged_tuple_at(path)
+    }
+    fn enter_untagged_tuple_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.enter_untagged_tuple_at(path)
+    }
+    fn exit_untagged_tuple_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.reader.exit_untagged_tuple_at(path)
+    }
+    fn token_at(&mut self, kind: TokenKind, path: &Path) -> Result<Token, TokenReaderError> {
+        self.profile.record(path, kind.name());
+        self.reader.token_at(kind, path)
+    }
+    fn tokens_at(&mut self, kind: TokenKind, count: usize, path: &Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        for _ in 0..count {
+            self.profile.record(path, kind.name());
+        }
+        self.reader.tokens_at(kind, count, path, tokens)
+    }
+    fn fields_at(&mut self, fields: &[((usize, FieldName), TokenKind)], path: &mut Path, tokens: &mut Vec<Token>) -> Result<(), TokenReaderError> {
+        for &(ref field, kind) in fields {
+            path.enter_field(field.clone());
+            self.profile.record(path, kind.name());
+            path.exit_field(field.clone());
+        }
+        self.reader.fields_at(fields, path, tokens)
+    }
+}
+
+#[test]
+fn test_profile() {
+    use binjs_shared::Node;
+    use io::{ TokenWriter, TokenWriterTreeAdapter };
+    use simple;
+
+    struct Dummy;
+    impl Node for Dummy {
+        fn name(&self) -> &'static str {
+            "Dummy"
+        }
+    }
+
+    let tag = InterfaceName::from_str("Dummy");
+    let field = FieldName::from_str("names");
+    let mut path = Path::new();
+
+    let data = {
+        let mut writer = TokenWriterTreeAdapter::new(simple::TreeTokenWriter::new());
+        writer.enter_tagged_tuple_at(&Dummy, &tag, &[&field], &path)
+            .expect("Writing tagged tuple");
+        path.enter_interface(tag.clone());
+        path.enter_field((0, field.clone()));
+        writer.enter_list_at(2, &path)
+            .expect("Writing list");
+        for name in &["foo", "bar"] {
+            writer.string_at(Some(&SharedString::from_str(*name)), &path)
+                .expect("Writing string");
+        }
+        writer.exit_list_at(&path)
+            .expect("Writing list");
+        path.exit_field((0, field.clone()));
+        path.exit_interface(tag.clone());
+        writer.exit_tagged_tuple_at(&Dummy, &tag, &[&field], &path)
+            .expect("Writing tagged tuple");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let mut reader = TokenReaderProfiler::new(simple::TreeTokenReader::new(std::io::Cursor::new(data)));
+    reader.enter_tagged_tuple_at(&path)
+        .expect("Reading tagged tuple");
+    path.enter_interface(tag.clone());
+    path.enter_field((0, field.clone()));
+    let len = reader.enter_list_at(&path)
+        .expect("Reading list");
+    for _ in 0..len {
+        reader.string_at(&path)
+            .expect("Reading string");
+    }
+    reader.exit_list_at(&path)
+        .expect("Reading list");
+    path.exit_field((0, field.clone()));
+    path.exit_interface(tag.clone());
+    reader.exit_tagged_tuple_at(&path)
+        .expect("Reading tagged tuple");
+
+    let (_, profile) = reader.done();
+    assert_eq!(profile.total(), 4);
+    assert_eq!(profile.by_kind()["string"], 2);
+
+    let mut folded = vec![];
+    profile.write_folded(&mut folded)
+        .expect("Writing profile");
+    assert_eq!(String::from_utf8(folded).unwrap(), "Dummy.names;list 1\nDummy.names;string 2\ntag 1\n");
+}
@@ -0,0 +1,217 @@
+//! Reporting the progress of long encodes.
+//!
+//! Encoding a large corpus may take a long time. A `ProgressSink` receives
+//! callbacks as the encoding progresses, e.g. to display a progress bar.
+
+use binjs_shared::{ BigInt, IdentifierName, InterfaceName, FieldName, Node, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
+
+use ::{ Path, TokenWriter, TokenWriterError };
+
+use std;
+
+/// Report progress to the sink every time this number of nodes has been processed.
+const NODES_PER_REPORT: usize = 1024;
+
+/// The phases of encoding a source file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Parsing the text source.
+    Parse,
+
+    /// Annotating the AST, e.g. with scope information or laziness.
+    Annotate,
+
+    /// Encoding the AST to tokens.
+    Encode,
+
+    /// Compressing and writing the tokens.
+    Compress,
+}
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let name = match *self {
+            Phase::Parse => "parse",
+            Phase::Annotate => "annotate",
+            Phase::Encode => "encode",
+            Phase::Compress => "compress",
+        };
+        name.fmt(f)
+    }
+}
+
+/// A recipient for progress reports.
+///
+/// All methods have a default no-op implementation.
+pub trait ProgressSink {
+    /// A new phase has started.
+    fn phase(&mut self, _phase: Phase) {}
+
+    /// `count` nodes have been processed since the previous report.
+    fn nodes(&mut self, _count: usize) {}
+
+    /// `count` bytes have been written since the previous report.
+    fn bytes(&mut self, _count: usize) {}
+}
+
+impl<'a, S> ProgressSink for &'a mut S where S: ProgressSink + ?Sized {
+    fn phase(&mut self, phase: Phase) {
+        (**self).phase(phase)
+    }
+    fn nodes(&mut self, count: usize) {
+        (**self).nodes(count)
+    }
+    fn bytes(&mut self, count: usize) {
+        (**self).bytes(count)
+    }
+}
+
+/// A `ProgressSink` that ignores all reports.
+pub struct NoProgress;
+impl ProgressSink for NoProgress {}
+
+/// A `TokenWriter` that reports the number of nodes written to a `ProgressSink`.
+pub struct TokenWriterProgressAdapter<W, S> where W: TokenWriter, S: ProgressSink {
+    writer: W,
+    sink: S,
+
+    /// The number of nodes written since the latest report.
+    pending: usize,
+}
+impl<W, S> TokenWriterProgressAdapter<W, S> where W: TokenWriter, S: ProgressSink {
+    pub fn new(writer: W, sink: S) -> Self {
+        TokenWriterProgressAdapter {
+            writer,
+            sink,
+            pending: 0,
+        }
+    }
+
+    /// Access the underlying writer.
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Access the sink.
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Report the nodes written so far.
+    fn flush(&mut self) {
+        if self.pending > 0 {
+            self.sink.nodes(self.pending);
+            self.pending = 0;
+        }
+    }
+}
+
+impl<W, S> TokenWriter for TokenWriterProgressAdapter<W, S> where W: TokenWriter, S: ProgressSink {
+    type Data = W::Data;
+
+    fn done(mut self) -> Result<Self::Data, TokenWriterError> {
+        self.flush();
+        self.sink.phase(Phase::Compress);
+        let data = self.writer.done()?;
+        self.sink.bytes(data.as_ref().len());
+        Ok(data)
+    }
+
+    fn enter_tagged_tuple_at(&mut self, node: &Node, tag: &InterfaceName, children: &[&FieldName], path: &Path) -> Result<(), TokenWriterError> {
+        self.pending += 1;
+        if self.pending >= NODES_PER_REPORT {
+            self.flush();
+        }
+        self.writer.enter_tagged_tuple_at(node, tag, children, path)
+    }
+    fn exit_tagged_tuple_at(&mut self, node: &Node, tag: &InterfaceName, children: &[&FieldName], path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.exit_tagged_tuple_at(node, tag, children, path)
+    }
+    fn enter_list_at(&mut self, len: usize, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.enter_list_at(len, path)
+    }
+    fn exit_list_at(&mut self, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.exit_list_at(path)
+    }
+    fn string_at(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.string_at(value, path)
+    }
+    fn string_enum_at(&mut self, value: &SharedString, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.string_enum_at(value, path)
+    }
+    fn float_at(&mut self, value: Option<f64>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.float_at(value, path)
+    }
+    fn big_int_at(&mut self, value: Option<&BigInt>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.big_int_at(value, path)
+    }
+    fn reg_exp_pattern_at(&mut self, value: Option<&RegExpPattern>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.reg_exp_pattern_at(value, path)
+    }
+    fn reg_exp_flags_at(&mut self, value: Option<&RegExpFlags>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.reg_exp_flags_at(value, path)
+    }
+    fn unsigned_long_at(&mut self, value: u32, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.unsigned_long_at(value, path)
+    }
+    fn bool_at(&mut self, value: Option<bool>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.bool_at(value, path)
+    }
+    fn offset_at(&mut self, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.offset_at(path)
+    }
+    fn property_key_at(&mut self, value: Option<&PropertyKey>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.property_key_at(value, path)
+    }
+    fn identifier_name_at(&mut self, value: Option<&IdentifierName>, path: &Path) -> Result<(), TokenWriterError> {
+        self.writer.identifier_name_at(value, path)
+    }
+}
+
+#[test]
+fn test_progress() {
+    use io::TokenWriterTreeAdapter;
+    use simple;
+
+    struct Dummy;
+    impl Node for Dummy {
+        fn name(&self) -> &'static str {
+            "Dummy"
+        }
+    }
+
+    #[derive(Default)]
+    struct Report {
+        phases: Vec<Phase>,
+        nodes: usize,
+        bytes: usize,
+    }
+    impl ProgressSink for Report {
+        fn phase(&mut self, phase: Phase) {
+            self.phases.push(phase);
+        }
+        fn nodes(&mut self, count: usize) {
+            self.nodes += count;
+        }
+        fn bytes(&mut self, count: usize) {
+            self.bytes += count;
+        }
+    }
+
+    let mut report = Report::default();
+    let data = {
+        let path = Path::new();
+        let mut writer = TokenWriterProgressAdapter::new(TokenWriterTreeAdapter::new(simple::TreeTokenWriter::new()), &mut report);
+        let tag = InterfaceName::from_str("Dummy");
+        for _ in 0..NODES_PER_REPORT + 1 {
+            writer.enter_tagged_tuple_at(&Dummy, &tag, &[], &path)
+                .expect("Writing tagged tuple");
+            writer.exit_tagged_tuple_at(&Dummy, &tag, &[], &path)
+                .expect("Writing tagged tuple");
+        }
+        writer.done()
+            .expect("Finalizing data")
+    };
+    assert_eq!(report.phases, vec![Phase::Compress]);
+    assert_eq!(report.nodes, NODES_PER_REPORT + 1);
+    assert_eq!(report.bytes, data.len());
+}
@@ -1,6 +1,6 @@
 
 
-#[derive(Default, Display, Add, AddAssign, Into, From, Clone, Copy)]
+#[derive(Debug, Default, Display, Add, AddAssign, Into, From, Clone, Copy)]
 pub struct Bytes(usize);
 
 impl std::iter::Sum for Bytes {
@@ -38,6 +38,27 @@ impl BytesAndInstances {
         }
     }
 }
+/// The effect of prediction on the size of a stream.
+#[derive(Debug, Default, Add, AddAssign, Clone, Copy)]
+pub struct Gain {
+    /// The number of bytes of the stream, without prediction.
+    pub before: Bytes,
+
+    /// The number of bytes of the stream, with prediction.
+    pub after: Bytes,
+}
+impl std::fmt::Display for Gain {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let before = Into::<usize>::into(self.before);
+        let after = Into::<usize>::into(self.after);
+        write!(formatter, "bytes {after} instead of {before} ({gain:.2}% saved)",
+            after = after,
+            before = before,
+            gain = 100. * (before as f64 - after as f64) / before as f64,
+        )
+    }
+}
+
 /// A container for information associated with a type of data we write to the stream
 /// as part of the content (i.e. not the header).
 ///
@@ -188,3 +209,306 @@ impl std::fmt::Display for ContentInfo<BytesAndInstances> {
         Ok(())
     }
 }
+
+impl std::fmt::Display for ContentInfo<Gain> {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(formatter, "Predicted streams:\n")?;
+        for (name, gain) in self.iter() {
+            if Into::<usize>::into(gain.before) == 0 {
+                // Not a predicted stream.
+                continue;
+            }
+            write!(formatter, "    {name}: {gain}\n",
+                name = name,
+                gain = gain)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Copy + Into<usize>> ContentInfo<T> {
+    /// The number of bytes or instances written in each category since `earlier`, a
+    /// snapshot of the same statistics, e.g. the bytes of a single file.
+    pub fn since(&self, earlier: &Self) -> ContentInfo<usize> {
+        let mut result = ContentInfo::with(|_| 0);
+        for (((_, after), (_, before)), (_, delta)) in self.iter().zip(earlier.iter()).zip(result.iter_mut()) {
+            *delta = Into::<usize>::into(*after) - Into::<usize>::into(*before);
+        }
+        result
+    }
+}
+
+/// The distribution of a value across files, e.g. of compression ratios.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distribution {
+    pub mean: f64,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+impl Distribution {
+    /// The distribution of `values`, or `None` if there are no values.
+    pub fn new(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b)
+            .unwrap_or(std::cmp::Ordering::Equal));
+        // Nearest-rank percentiles.
+        let percentile = |p: f64| {
+            let rank = (p / 100. * values.len() as f64).ceil() as usize;
+            values[std::cmp::max(rank, 1) - 1]
+        };
+        Some(Distribution {
+            mean: values.iter().sum::<f64>() / values.len() as f64,
+            min: values[0],
+            p50: percentile(50.),
+            p90: percentile(90.),
+            p99: percentile(99.),
+            max: values[values.len() - 1],
+        })
+    }
+}
+impl std::fmt::Display for Distribution {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(formatter, "mean {:.3}, min {:.3}, p50 {:.3}, p90 {:.3}, p99 {:.3}, max {:.3}",
+            self.mean, self.min, self.p50, self.p90, self.p99, self.max)
+    }
+}
+
+/// Statistics aggregated across the files of a run, e.g. of a directory: totals,
+/// and the distribution of per-file ratios.
+#[derive(Debug, Default, Clone)]
+pub struct Aggregate {
+    /// For each file, the byte length of its source and of its encoding.
+    files: Vec<(usize, usize)>,
+
+    /// For each file whose content is known, the byte length of its encoding and
+    /// the number of bytes of each category of content.
+    content: Vec<(usize, ContentInfo<usize>)>,
+}
+impl Aggregate {
+    /// Record a file. `content` is the number of bytes of each category of content
+    /// in the file, if the format collects them.
+    pub fn add_file(&mut self, source_bytes: usize, encoded_bytes: usize, content: Option<ContentInfo<usize>>) {
+        self.files.push((source_bytes, encoded_bytes));
+        if let Some(content) = content {
+            self.content.push((encoded_bytes, content));
+        }
+    }
+
+    pub fn number_of_files(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn source_bytes(&self) -> usize {
+        self.files.iter()
+            .map(|&(source, _)| source)
+            .sum()
+    }
+
+    pub fn encoded_bytes(&self) -> usize {
+        self.files.iter()
+            .map(|&(_, encoded)| encoded)
+            .sum()
+    }
+
+    /// The distribution of the ratios encoded bytes / source bytes, ignoring empty sources.
+    pub fn ratios(&self) -> Option<Distribution> {
+        Distribution::new(self.files.iter()
+            .filter(|&&(source, _)| source != 0)
+            .map(|&(source, encoded)| encoded as f64 / source as f64)
+            .collect())
+    }
+
+    /// The total of each category of content, across files.
+    pub fn content(&self) -> ContentInfo<usize> {
+        let mut total = ContentInfo::with(|_| 0);
+        for &(_, ref content) in &self.content {
+            for ((_, sum), (_, bytes)) in total.iter_mut().zip(content.iter()) {
+                *sum += *bytes;
+            }
+        }
+        total
+    }
+
+    /// For each category of content, the distribution of its share of the bytes of
+    /// each file, ignoring empty files.
+    pub fn content_shares(&self) -> ContentInfo<Option<Distribution>> {
+        ContentInfo::with(|name| {
+            Distribution::new(self.content.iter()
+                .filter(|&&(encoded, _)| encoded != 0)
+                .map(|&(encoded, ref content)| {
+                    let bytes = content.iter()
+                        .find(|&(field, _)| field == name)
+                        .map_or(0, |(_, bytes)| *bytes);
+                    bytes as f64 / encoded as f64
+                })
+                .collect())
+        })
+    }
+}
+impl std::fmt::Display for Aggregate {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let source_bytes = self.source_bytes();
+        let encoded_bytes = self.encoded_bytes();
+        write!(formatter, "Aggregate statistics:\n")?;
+        write!(formatter, "    Files: {}\n", self.number_of_files())?;
+        write!(formatter, "    Total source bytes: {}\n", source_bytes)?;
+        write!(formatter, "    Total encoded bytes: {}\n", encoded_bytes)?;
+        if source_bytes != 0 {
+            write!(formatter, "    Total ratio: {:.3}\n", encoded_bytes as f64 / source_bytes as f64)?;
+        }
+        if let Some(ratios) = self.ratios() {
+            write!(formatter, "    Per-file ratio: {}\n", ratios)?;
+        }
+        if self.content.is_empty() {
+            return Ok(());
+        }
+        let files = self.content.len();
+        write!(formatter, "    Content, over {} files:\n", files)?;
+        for ((name, total), (_, shares)) in self.content().into_iter().zip(self.content_shares().into_iter()) {
+            write!(formatter, "        {name}: total {total} bytes, mean {mean:.1} bytes per file",
+                name = name,
+                total = total,
+                mean = total as f64 / files as f64)?;
+            if let Some(shares) = shares {
+                write!(formatter, ", per-file share {}", shares)?;
+            }
+            write!(formatter, "\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writing statistics as CSV, one row per (file, category), e.g. to load them into
+/// a spreadsheet.
+pub struct CsvWriter<W: std::io::Write> {
+    out: W,
+}
+impl<W: std::io::Write> CsvWriter<W> {
+    pub const HEADER: &'static str = "file,category,bytes,instances,bytes_percent,instances_percent";
+
+    /// Write the header.
+    pub fn new(mut out: W) -> Result<Self, std::io::Error> {
+        writeln!(out, "{}", Self::HEADER)?;
+        Ok(CsvWriter {
+            out
+        })
+    }
+
+    /// Write the rows of a file: if the format collects them, one per category of
+    /// content, with its `(bytes, instances)`, then one for the entire file, with
+    /// category `total`. Percentages are relative to the file.
+    pub fn write_file(&mut self, file: &str, encoded_bytes: usize, content: Option<(&ContentInfo<usize>, &ContentInfo<usize>)>) -> Result<(), std::io::Error> {
+        let file = csv_field(file);
+        let percent = |numerator: usize, denominator: usize| {
+            if denominator == 0 {
+                0.
+            } else {
+                100. * numerator as f64 / denominator as f64
+            }
+        };
+        let mut total_instances = None;
+        if let Some((bytes, instances)) = content {
+            let sum : usize = instances.iter()
+                .map(|(_, instances)| *instances)
+                .sum();
+            for ((name, bytes), (_, instances)) in bytes.iter().zip(instances.iter()) {
+                writeln!(self.out, "{},{},{},{},{:.2},{:.2}",
+                    file,
+                    name,
+                    bytes,
+                    instances,
+                    percent(*bytes, encoded_bytes),
+                    percent(*instances, sum))?;
+            }
+            total_instances = Some(sum);
+        }
+        writeln!(self.out, "{},total,{},{},{:.2},{}",
+            file,
+            encoded_bytes,
+            total_instances.map_or(String::new(), |sum| sum.to_string()),
+            percent(encoded_bytes, encoded_bytes),
+            total_instances.map_or("", |_| "100.00"))
+    }
+
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.out.flush()
+    }
+}
+
+/// Quote `field` if it contains a separator, a quote or a newline, as per RFC 4180.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[test]
+fn test_aggregate() {
+    let mut aggregate = Aggregate::default();
+    for i in 1..101 {
+        let mut content = ContentInfo::with(|_| 0);
+        content.bools = i;
+        content.floats = 100 - i;
+        aggregate.add_file(1000, 10 * i, Some(content));
+    }
+    assert_eq!(aggregate.number_of_files(), 100);
+    assert_eq!(aggregate.source_bytes(), 100_000);
+    assert_eq!(aggregate.encoded_bytes(), 50_500);
+
+    let ratios = aggregate.ratios()
+        .expect("Missing ratios");
+    assert_eq!(ratios.min, 0.01);
+    assert_eq!(ratios.p50, 0.5);
+    assert_eq!(ratios.p90, 0.9);
+    assert_eq!(ratios.p99, 0.99);
+    assert_eq!(ratios.max, 1.);
+
+    let content = aggregate.content();
+    assert_eq!(content.bools, 5050);
+    assert_eq!(content.floats, 4950);
+    assert_eq!(content.string_literals, 0);
+    let shares = aggregate.content_shares();
+    assert_eq!(shares.bools.map(|shares| shares.max), Some(0.1));
+    assert!(format!("{}", aggregate).contains("Per-file ratio: mean 0.505"));
+
+    let mut earlier = ContentInfo::<Bytes>::default();
+    earlier.bools = 3.into();
+    let mut later = earlier.clone();
+    later.bools = 5.into();
+    later.floats = 2.into();
+    let since = later.since(&earlier);
+    assert_eq!((since.bools, since.floats), (2, 2));
+}
+
+#[test]
+fn test_csv() {
+    let mut bytes = ContentInfo::with(|_| 0);
+    bytes.bools = 2;
+    bytes.floats = 6;
+    let mut instances = ContentInfo::with(|_| 0);
+    instances.bools = 16;
+    instances.floats = 4;
+
+    let mut csv = CsvWriter::new(vec![])
+        .expect("Could not write header");
+    csv.write_file("a,\"b\".js", 10, Some((&bytes, &instances)))
+        .expect("Could not write rows");
+    csv.write_file("c.js", 5, None)
+        .expect("Could not write rows");
+    let text = String::from_utf8(csv.out)
+        .expect("Invalid UTF-8");
+    let lines : Vec<_> = text.lines().collect();
+    assert_eq!(lines[0], CsvWriter::<Vec<u8>>::HEADER);
+    assert_eq!(lines[1], "\"a,\"\"b\"\".js\",bools,2,16,20.00,80.00");
+    assert_eq!(lines[2], "\"a,\"\"b\"\".js\",floats,6,4,60.00,20.00");
+    assert_eq!(lines.len(), 1 + bytes.iter().count() + 1 + 1);
+    assert_eq!(lines[lines.len() - 2], "\"a,\"\"b\"\".js\",total,10,20,100.00,100.00");
+    assert_eq!(lines[lines.len() - 1], "c.js,total,5,,100.00,");
+}
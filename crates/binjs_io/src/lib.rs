@@ -1,13 +1,16 @@
 #![feature(box_patterns)]
 #![feature(vec_resize_default)]
 
+extern crate aes_gcm;
 extern crate bincode; // Used to store dictionaries. This is a temporary format.
+extern crate binjs_core;
 extern crate binjs_shared;
 
 extern crate brotli;
 extern crate clap;
 #[macro_use]
 extern crate derive_more;
+extern crate ed25519_dalek;
 extern crate flate2;
 extern crate itertools;
 extern crate lzw;
@@ -18,6 +21,8 @@ extern crate range_encoding;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+extern crate sha2;
+extern crate tracing;
 
 extern crate vec_map;
 extern crate xml as xml_rs;
@@ -32,11 +37,72 @@ use rand::seq::SliceRandom;
 
 pub use bytes::compress::Compression;
 
+/// Identifies the grammar used to encode a file, so that decoders may reject
+/// files encoded with a grammar they do not know, rather than misdecode them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrammarId {
+    /// A human-readable name, e.g. `"es6"`.
+    pub name: String,
+
+    /// A fingerprint of the grammar, which changes whenever the grammar
+    /// changes, as computed by `binjs_meta::spec::Spec::fingerprint`.
+    pub fingerprint: u64,
+
+    /// If `true`, the grammar composes a base grammar with vendor extensions,
+    /// which formats that support it announce in their header.
+    pub extended: bool,
+}
+impl GrammarId {
+    pub fn new(name: &str, fingerprint: u64) -> Self {
+        GrammarId {
+            name: name.to_string(),
+            fingerprint,
+            extended: false,
+        }
+    }
+
+    /// Mark the grammar as composed with vendor extensions.
+    pub fn with_extended(self, extended: bool) -> Self {
+        GrammarId {
+            extended,
+            ..self
+        }
+    }
+}
+impl std::fmt::Display for GrammarId {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{} ({:016x})", self.name, self.fingerprint)
+    }
+}
+
 #[derive(Debug)]
 pub enum TokenWriterError {
     InvalidOffsetField,
     NotInDictionary(String),
     WriteError(std::io::Error),
+    /// A name is not a valid ECMAScript IdentifierName.
+    InvalidIdentifierName(String),
+    /// Archives are only supported by the multipart format, not by the format named here.
+    ArchiveUnsupported(String),
+}
+impl std::fmt::Display for TokenWriterError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            TokenWriterError::InvalidOffsetField => write!(f, "invalid offset field"),
+            TokenWriterError::NotInDictionary(ref value) => write!(f, "value not in dictionary: {}", value),
+            TokenWriterError::WriteError(_) => write!(f, "could not write"),
+            TokenWriterError::InvalidIdentifierName(ref name) => write!(f, "invalid identifier name: {}", name),
+            TokenWriterError::ArchiveUnsupported(ref format) => write!(f, "format {} does not support archives, use multipart", format),
+        }
+    }
+}
+impl std::error::Error for TokenWriterError {
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        match *self {
+            TokenWriterError::WriteError(ref err) => Some(err),
+            _ => None
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -60,9 +126,82 @@ pub enum TokenReaderError {
     EmptyFieldName,
     EmptyVariant,
     EmptyBool,
+    EmptyBigInt,
     EmptyString,
     EmptyList,
     BadEnumVariant,
+    /// The checksum of a section (or of the entire file) does not match its contents.
+    BadChecksum(String),
+    /// The signature is missing or invalid.
+    BadSignature,
+    /// The content is encrypted and the key is missing or invalid.
+    BadEncryption,
+    /// The file is an archive, an entry must be specified.
+    IsArchive,
+    /// Archives are only supported by the multipart format, not by the format named here.
+    ArchiveUnsupported(String),
+    /// The archive does not contain the requested entry.
+    NoSuchEntry(String),
+    /// The file was encoded with a grammar that the decoder does not support.
+    UnsupportedGrammar(GrammarId),
+    /// The file does not contain the requested section.
+    NoSuchSection(String),
+    /// The strings table was compressed with a custom dictionary, identified by its
+    /// SHA-256 hash, and the decoder was not given that dictionary.
+    UnknownStringDictionary([u8; 32]),
+    /// The scope annotations of the AST are inconsistent, e.g. a name is declared
+    /// twice in the same scope.
+    InconsistentScopes(String),
+    /// The file was encoded with a path depth that does not match the depth of the
+    /// dictionary given to the decoder.
+    BadPathDepth { file: usize, dictionary: usize },
+}
+impl std::fmt::Display for TokenReaderError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        use TokenReaderError::*;
+        match *self {
+            NotInDictionary(ref value) => write!(f, "value not in dictionary: {}", value),
+            ReadError(_) => write!(f, "could not read"),
+            BadLength { expected, got } => write!(f, "bad length: expected {}, got {}", expected, got),
+            BadHeader => write!(f, "bad header"),
+            BadCompression(_) => write!(f, "could not decompress"),
+            EndOffsetError { start, expected, found, ref description } =>
+                write!(f, "{} starting at {} should end at {}, ends at {}", description, start, expected, found),
+            BadStringIndex(index) => write!(f, "bad string index {}", index),
+            InvalidValue => write!(f, "invalid value"),
+            BadKindIndex(index) => write!(f, "bad kind index {}", index),
+            Encoding(_) => write!(f, "invalid UTF-8"),
+            EmptyNodeName => write!(f, "empty node name"),
+            EmptyFieldName => write!(f, "empty field name"),
+            EmptyVariant => write!(f, "empty variant"),
+            EmptyBool => write!(f, "empty bool"),
+            EmptyBigInt => write!(f, "empty BigInt"),
+            EmptyString => write!(f, "empty string"),
+            EmptyList => write!(f, "empty list"),
+            BadEnumVariant => write!(f, "bad enum variant"),
+            BadChecksum(ref description) => write!(f, "bad checksum: {}", description),
+            BadSignature => write!(f, "missing or invalid signature"),
+            BadEncryption => write!(f, "missing or invalid decryption key"),
+            IsArchive => write!(f, "the file is an archive, an entry must be specified"),
+            ArchiveUnsupported(ref format) => write!(f, "format {} does not support archives, use multipart", format),
+            NoSuchEntry(ref entry) => write!(f, "no such entry in archive: {}", entry),
+            UnsupportedGrammar(ref grammar) => write!(f, "unsupported grammar {}", grammar),
+            NoSuchSection(ref section) => write!(f, "no such section: {}", section),
+            UnknownStringDictionary(ref hash) => write!(f, "unknown string dictionary {}", bytes::signature::to_hex(hash)),
+            InconsistentScopes(ref problem) => write!(f, "inconsistent scopes: {}", problem),
+            BadPathDepth { file, dictionary } => write!(f, "the file was encoded with path depth {}, the dictionary has path depth {}", file, dictionary),
+        }
+    }
+}
+impl std::error::Error for TokenReaderError {
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        match *self {
+            TokenReaderError::ReadError(ref err)
+            | TokenReaderError::BadCompression(ref err) => Some(err),
+            TokenReaderError::Encoding(ref err) => Some(err),
+            _ => None
+        }
+    }
 }
 impl TokenReaderError {
     pub fn invalid_value<T: std::fmt::Debug>(value: &T) -> Self {
@@ -72,6 +211,10 @@ impl TokenReaderError {
 }
 
 
+/// The error of entry points, chaining the errors above.
+pub mod error;
+pub use error::{ Error, ErrorKind };
+
 /// Byte-level utilities for writing token readers/writers.
 pub mod bytes;
 
@@ -92,11 +235,26 @@ pub mod multipart;
 /// An encoding using entropy coding.
 pub mod entropy;
 
+/// An encoding that stores the shapes of repeated subtrees as templates.
+pub mod templates;
+
+/// An encoding that replaces repeated subtrees with back-references.
+pub mod dag;
+
 pub mod xml;
 
+/// A line-oriented trace of tokens, designed to be compared with text tools.
+pub mod text;
+
+/// Source positions and comments, carried alongside the tree by some formats.
+pub mod positions;
+
+/// Startup profiles, recording which functions were executed at startup.
+pub mod startup;
+
 mod util;
 
-mod escaped_wtf8;
+use binjs_shared::escaped_wtf8;
 
 const ADVANCED_COMMAND: &str = "advanced";
 
@@ -138,11 +296,15 @@ impl CompressionTarget {
         }
     }
     pub fn done(&mut self) -> std::result::Result<(Rc<Vec<u8>>, bytes::compress::CompressionResult), std::io::Error> {
+        self.done_with_dictionary(None)
+    }
+    /// As `done`, compressing with `dictionary` as brotli custom dictionary, if specified.
+    pub fn done_with_dictionary(&mut self, dictionary: Option<&bytes::compress::BrotliDictionary>) -> std::result::Result<(Rc<Vec<u8>>, bytes::compress::CompressionResult), std::io::Error> {
         let (data, result) = match self.data {
             Compressing::Compressed { ref result, ref data } => return Ok((data.clone(), result.clone())),
             Compressing::Uncompressed(ref data) => {
                 let mut buf = vec![];
-                let result = self.format.compress(&data.borrow().as_ref(), &mut buf)?;
+                let result = self.format.compress_with_dictionary(&data.borrow().as_ref(), dictionary, &mut buf)?;
                 (Rc::new(buf), result)
             }
         };
@@ -205,12 +367,26 @@ pub enum Format {
     Simple,
     Multipart {
         targets: multipart::Targets,
-        stats: Rc<RefCell<multipart::Statistics>>
+        stats: Rc<RefCell<multipart::Statistics>>,
+        options: multipart::Options,
     },
     XML,
+    Text,
     Entropy {
         options: entropy::Options,
-    }
+    },
+    AdaptiveEntropy {
+        options: entropy::adaptive::Options,
+    },
+    HuffmanEntropy {
+        options: entropy::Options,
+    },
+    Templates {
+        options: templates::Options,
+    },
+    Dag {
+        options: dag::Options,
+    },
 }
 
 /// Support picking a random format.
@@ -230,10 +406,18 @@ impl Distribution<Format> for Standard {
                         grammar_table: rng.gen(),
                         tree: rng.gen(),
                     },
-                    stats
+                    stats,
+                    options: multipart::Options {
+                        integrity: multipart::Integrity {
+                            write_checksum: rng.gen(),
+                            ..multipart::Integrity::default()
+                        },
+                        ..multipart::Options::default()
+                    },
                 }
             }),
             Rc::new(|_| Format::XML),
+            Rc::new(|_| Format::Text),
         ];
         let pick : Rc<Fn(&'a mut R) -> Format> = generators.choose(rng)
             .map(Rc::clone)
@@ -253,17 +437,64 @@ impl Format {
         match self {
             Format::Simple => Format::Simple,
             Format::XML => Format::XML,
-            Format::Multipart { stats, .. } =>
+            Format::Text => Format::Text,
+            Format::Multipart { stats, options, .. } =>
                 Format::Multipart {
                     targets: multipart::Targets {
                         strings_table: rng.gen(),
                         grammar_table: rng.gen(),
                         tree: rng.gen(),
                     },
-                    stats
+                    stats,
+                    options,
                 }
             ,
-            Format::Entropy { .. } => unimplemented!()
+            Format::Entropy { options } => {
+                // Fallback is left as is, as disabling it could make some files impossible to encode.
+                let options = options.with_backend(rng.gen());
+                let options =
+                    if rng.gen() {
+                        options.with_recency(rng.gen_range(1, 4 * entropy::recency::DEFAULT_WINDOW))
+                    } else {
+                        options
+                    };
+                Format::Entropy { options }
+            }
+            // The only option of this format is its dictionary.
+            Format::HuffmanEntropy { options } => Format::HuffmanEntropy { options },
+            Format::AdaptiveEntropy { options } => Format::AdaptiveEntropy { options },
+            Format::Templates { options } => Format::Templates { options },
+            Format::Dag { options } => Format::Dag { options },
+        }
+    }
+
+    /// Access the options of the multipart format, e.g. for detecting corrupted or
+    /// tampered files, if this is the multipart format.
+    pub fn multipart_options_mut(&mut self) -> Option<&mut multipart::Options> {
+        match *self {
+            Format::Multipart { ref mut options, .. } => Some(options),
+            _ => None
+        }
+    }
+
+    /// Open the token reader of this format on `source` and pass it to `visitor`.
+    pub fn read<S, V>(&self, source: S, visitor: V) -> Result<V::Output, TokenReaderError>
+        where S: std::io::Read + std::io::Seek, V: ReaderVisitor
+    {
+        match *self {
+            Format::Simple => visitor.visit(simple::TreeTokenReader::new(source), None),
+            Format::Multipart { ref options, .. } => {
+                let reader = multipart::TreeTokenReader::with_options(source, options)?;
+                let grammar = reader.grammar().cloned();
+                visitor.visit(reader, grammar.as_ref())
+            }
+            Format::XML => visitor.visit(xml::Decoder::new(source)?, None),
+            Format::Text => visitor.visit(text::Decoder::new(source), None),
+            Format::Entropy { ref options } => visitor.visit(entropy::read::Decoder::new(options.clone(), source)?, None),
+            Format::AdaptiveEntropy { ref options } => visitor.visit(entropy::adaptive::Decoder::new(options.clone(), source)?, None),
+            Format::HuffmanEntropy { ref options } => visitor.visit(entropy::huffman::Decoder::new(options.clone(), source), None),
+            Format::Templates { .. } => visitor.visit(templates::Decoder::new(source)?, None),
+            Format::Dag { .. } => visitor.visit(dag::Decoder::new(source)?, None),
         }
     }
 
@@ -273,18 +504,104 @@ impl Format {
             Format::Simple { .. } => "Simple".to_string(),
             Format::Multipart { .. } => "Multipart".to_string(),
             Format::XML => "XML".to_string(),
+            Format::Text => "Text".to_string(),
             Format::Entropy { .. } => "Entropy".to_string(),
+            Format::AdaptiveEntropy { .. } => "Adaptive entropy".to_string(),
+            Format::HuffmanEntropy { .. } => "Huffman entropy".to_string(),
+            Format::Templates { .. } => "Templates".to_string(),
+            Format::Dag { .. } => "DAG".to_string(),
+        }
+    }
+
+    /// The options of this format that change the bytes written, as `key=value`
+    /// pairs and flags separated by `;`, in a fixed order, e.g.
+    /// `grammar=br;strings=br;tree=br;nan-policy=canonicalize;checksum`.
+    ///
+    /// Together with `name`, the options identify the files that are written
+    /// identically. Brotli dictionaries are identified by their SHA-256 hash, keys
+    /// by their presence only. The probability tables of the entropy formats are
+    /// not recorded, only their depth.
+    pub fn options(&self) -> String {
+        let mut result = vec![];
+        match *self {
+            Format::Simple |
+            Format::XML |
+            Format::Text => {}
+            Format::Multipart { ref targets, ref options, .. } => {
+                result.push(format!("grammar={}", targets.grammar_table.format.code()));
+                result.push(format!("strings={}", targets.strings_table.format.code()));
+                result.push(format!("tree={}", targets.tree.format.code()));
+                if let Some(ref dictionary) = options.string_dictionary {
+                    let hash : String = dictionary.hash()
+                        .iter()
+                        .map(|byte| format!("{:02x}", byte))
+                        .collect();
+                    result.push(format!("string-dictionary={}", hash));
+                }
+                if let Some(window) = options.front_coding {
+                    result.push(format!("front-coding={}", window));
+                }
+                if let Some(threshold) = options.blob_threshold {
+                    result.push(format!("blob-threshold={}", threshold));
+                    result.push(format!("blob-compression={}", options.blob_compression.code()));
+                }
+                result.push(format!("nan-policy={}", options.nan_policy.name()));
+                let flags = [
+                    (options.varfloats, "varfloats"),
+                    (options.runs, "runs"),
+                    (options.split_prelude, "split-prelude"),
+                    (options.chunks, "chunks"),
+                    (options.integrity.write_checksum, "checksum"),
+                    (options.integrity.sign_key.is_some(), "signed"),
+                    (options.integrity.encryption_key.is_some(), "encrypted"),
+                ];
+                for &(present, flag) in flags.iter() {
+                    if present {
+                        result.push(flag.to_string());
+                    }
+                }
+            }
+            Format::Entropy { ref options } => {
+                result.push(format!("coder={}", options.backend().name()));
+                result.push(format!("path-depth={}", options.shared_dictionary().depth()));
+                if let Some(window) = options.recency() {
+                    result.push(format!("recent-identifiers={}", window));
+                }
+                if options.fallback() {
+                    result.push("fallback".to_string());
+                }
+            }
+            Format::HuffmanEntropy { ref options } => {
+                result.push(format!("path-depth={}", options.shared_dictionary().depth()));
+            }
+            Format::AdaptiveEntropy { ref options } => {
+                result.push(format!("coder={}", options.backend().name()));
+                result.push(format!("path-depth={}", options.depth()));
+            }
+            Format::Templates { ref options } => {
+                result.push(format!("depth={}", options.depth()));
+                result.push(format!("min-uses={}", options.min_uses()));
+            }
+            Format::Dag { ref options } => {
+                result.push(format!("min-size={}", options.min_size()));
+            }
         }
+        result.join(";")
     }
 
     pub fn with_sections<F, E>(&mut self, mut f: F) -> Result<(), E> where F: FnMut(&mut CompressionTarget, &str) -> Result<(), E> {
         match *self {
             Format::Simple { .. } |
-            Format::XML => {
+            Format::XML |
+            Format::Text |
+            Format::Templates { .. } |
+            Format::Dag { .. } => {
                 // Nothing to do
                 Ok(())
             }
-            Format::Entropy { ..} => {
+            Format::Entropy { ..} |
+            Format::AdaptiveEntropy { .. } |
+            Format::HuffmanEntropy { .. } => {
                 // Nothing to do
                 Ok(())
             }
@@ -306,12 +623,17 @@ impl Format {
 
     /// Return all existing format providers, to manage
     /// command-line arguments.
-   fn providers() -> [&'static FormatProvider; 4] {
+   fn providers() -> [&'static FormatProvider; 9] {
         [
             &multipart::FormatProvider,
             &simple::FormatProvider,
             &xml::FormatProvider,
+            &text::FormatProvider,
             &entropy::FormatProvider,
+            &entropy::adaptive::FormatProvider,
+            &entropy::huffman::FormatProvider,
+            &templates::FormatProvider,
+            &dag::FormatProvider,
         ]
     }
 
@@ -348,4 +670,25 @@ impl Format {
         Self::default_provider()
             .handle_subcommand(None)
     }
+    /// Create a Format from the name of a format provider followed by its
+    /// command-line arguments, e.g. `["entropy", "--dictionary", "foo.dict"]`.
+    ///
+    /// Used by tools that need several formats, hence can't use `subcommand`.
+    pub fn from_args(args: &[&str]) -> Result<Self, std::io::Error> {
+        let matches = clap::App::new("format")
+            .setting(clap::AppSettings::SubcommandRequired)
+            .subcommands(Format::providers().iter()
+                .map(|x| x.subcommand())
+            )
+            .get_matches_from_safe(std::iter::once("format").chain(args.iter().cloned()))
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidInput, err.message))?;
+        for provider in Self::providers().into_iter() {
+            let subcommand = provider.subcommand();
+            let key = subcommand.get_name();
+            if let Some(matches) = matches.subcommand_matches(key) {
+                return provider.handle_subcommand(Some(matches));
+            }
+        }
+        unreachable!() // Guaranteed by `SubcommandRequired`.
+    }
 }
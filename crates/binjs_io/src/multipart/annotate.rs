@@ -0,0 +1,584 @@
+//! An `xxd`-style dump of a file, in which each range of bytes is labelled with what it encodes.
+//!
+//! The container (headers, tables, manifest, checksums) is annotated directly from the file,
+//! while the tree is annotated by deserializing it with a `TreeTokenReader` created by
+//! `AnnotatedHex::new`. If the tree is stored uncompressed, its annotations are shown in place,
+//! otherwise the decompressed tree is dumped after the file.
+
+use bytes;
+use bytes::varnum::*;
+use multipart::{ ARCHIVE_FORMAT_VERSION, BLOB_PLACEHOLDER, FLAG_ARCHIVE, FLAGS_FORMAT_VERSION, FORMAT_VERSION, HEADER_CHECKSUM, HEADER_BLOBS, HEADER_ENCRYPTED, HEADER_GRAMMAR_ID, HEADER_GRAMMAR_TABLE, HEADER_MANIFEST, HEADER_METADATA, HEADER_POSITIONS, HEADER_PROFILE, HEADER_SIGNATURE, HEADER_STRING_DICTIONARY, HEADER_STRINGS_TABLE, HEADER_STRINGS_TABLE_FRONT_CODED, HEADER_TREE, HEADER_TREE_CHUNKS, HEADER_TREE_RUNS, HEADER_TREE_RUNS_CHUNKS, Options, VARFLOAT_ARCHIVE_FORMAT_VERSION, read_grammar_id };
+use multipart::TreeTokenReader;
+use util::ReadConst;
+use ::TokenReaderError;
+
+use std;
+use std::cell::RefCell;
+use std::io::{ Cursor, Read, Write };
+use std::rc::Rc;
+
+/// The number of bytes per line.
+const BYTES_PER_LINE : usize = 16;
+
+/// A range of bytes, labelled with what it encodes.
+///
+/// Labels that do not encode anything by themselves, e.g. the name of a field,
+/// have an empty range.
+#[derive(Clone, Debug)]
+pub struct Annotation {
+    pub start: usize,
+    pub end: usize,
+    pub label: String,
+}
+
+/// The structural interpretation of a decompressed tree, as recorded while reading it.
+#[derive(Default)]
+pub struct TreeAnnotations {
+    /// The decompressed tree.
+    pub data: Vec<u8>,
+
+    /// Annotations, as offsets in `data`.
+    pub annotations: Vec<Annotation>,
+}
+impl TreeAnnotations {
+    /// Rebuild the structure of the tree from its annotations: nodes, lists and
+    /// values, nested as in the AST, each with the bytes encoding it.
+    ///
+    /// Nodes are annotated `Kind {` ... `}`, lists `list (length=N) [` ... `]`, and the
+    /// value of each field is preceded by `.field`.
+    pub fn structure(&self) -> Vec<StructureNode> {
+        let mut roots = vec![];
+        let mut stack : Vec<StructureNode> = vec![];
+        let mut field = None;
+        for annotation in &self.annotations {
+            let label = annotation.label.as_str();
+            if label == "}" || label == "]" {
+                if let Some(mut node) = stack.pop() {
+                    node.extend(annotation);
+                    match stack.last_mut() {
+                        Some(parent) => parent.push(node),
+                        None => roots.push(node),
+                    }
+                }
+            } else if label.starts_with('.') && !label.contains(' ') {
+                field = Some(label[1..].to_string());
+            } else {
+                let is_open = label.ends_with(" {") || (label.starts_with("list ") && label.ends_with(" ["));
+                let node = StructureNode {
+                    label: if is_open { label[..label.len() - 2].to_string() } else { label.to_string() },
+                    field: field.take(),
+                    start: annotation.start,
+                    end: annotation.end,
+                    children: vec![],
+                };
+                if is_open {
+                    stack.push(node);
+                } else {
+                    match stack.last_mut() {
+                        Some(parent) => parent.push(node),
+                        None => roots.push(node),
+                    }
+                }
+            }
+        }
+        // Unterminated nodes, e.g. if the tree could not be read entirely.
+        while let Some(node) = stack.pop() {
+            match stack.last_mut() {
+                Some(parent) => parent.push(node),
+                None => roots.push(node),
+            }
+        }
+        roots
+    }
+}
+
+/// A node, list or value of a tree, rebuilt by `TreeAnnotations::structure`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructureNode {
+    /// e.g. `Script`, `list (length=3)` or `string="foo"`.
+    pub label: String,
+
+    /// The field of the parent containing this node, if any.
+    pub field: Option<String>,
+
+    /// The bytes encoding this node and its descendants, as offsets in the decompressed tree.
+    pub start: usize,
+    pub end: usize,
+
+    pub children: Vec<StructureNode>,
+}
+impl StructureNode {
+    /// The number of bytes encoding this node and its descendants.
+    pub fn byte_len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// The number of bytes encoding this node itself, e.g. its kind, but not its descendants.
+    pub fn own_byte_len(&self) -> usize {
+        self.byte_len() - self.children.iter()
+            .map(StructureNode::byte_len)
+            .sum::<usize>()
+    }
+
+    /// This node and its descendants, depth-first.
+    pub fn descendants(&self) -> Vec<&StructureNode> {
+        let mut result = vec![self];
+        for child in &self.children {
+            result.extend(child.descendants());
+        }
+        result
+    }
+
+    fn push(&mut self, child: StructureNode) {
+        self.extend_range(child.start, child.end);
+        self.children.push(child);
+    }
+
+    fn extend(&mut self, annotation: &Annotation) {
+        self.extend_range(annotation.start, annotation.end);
+    }
+
+    /// Include `start..end` in the bytes of this node, ignoring empty ranges.
+    fn extend_range(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        if self.start == self.end {
+            self.start = start;
+            self.end = end;
+        } else {
+            self.start = std::cmp::min(self.start, start);
+            self.end = std::cmp::max(self.end, end);
+        }
+    }
+}
+
+/// An annotated dump of a file.
+pub struct AnnotatedHex {
+    data: Vec<u8>,
+
+    /// Annotations of the container, as offsets in `data`.
+    container: Vec<Annotation>,
+
+    /// The offset of the tree in `data`, if it is stored uncompressed.
+    tree_start: Option<usize>,
+
+    /// Annotations of the tree, filled while the tree is read.
+    tree: Rc<RefCell<TreeAnnotations>>,
+}
+impl AnnotatedHex {
+    /// Annotate the container of a file.
+    ///
+    /// Returns a reader which must be used to deserialize the tree before calling `print`.
+    /// Archives are not supported.
+    pub fn new<R: Read>(mut source: R, options: &Options) -> Result<(Self, TreeTokenReader), TokenReaderError> {
+        let mut data = vec![];
+        source.read_to_end(&mut data)
+            .map_err(TokenReaderError::ReadError)?;
+
+        let tree = Rc::new(RefCell::new(TreeAnnotations::default()));
+        let reader = TreeTokenReader::with_recorder(Cursor::new(&data), options, tree.clone())?;
+
+        let (container, tree_start) = {
+            let mut walker = ContainerWalker {
+                reader: Cursor::new(&data),
+                annotations: vec![],
+                tree_start: None,
+            };
+            walker.walk()
+                .map_err(TokenReaderError::ReadError)?;
+            (walker.annotations, walker.tree_start)
+        };
+
+        Ok((AnnotatedHex {
+            data,
+            container,
+            tree_start,
+            tree,
+        }, reader))
+    }
+
+    /// The annotations of the container, as offsets in the file.
+    pub fn container(&self) -> &[Annotation] {
+        &self.container
+    }
+
+    /// The annotations of the tree, once it has been read.
+    pub fn tree(&self) -> std::cell::Ref<TreeAnnotations> {
+        self.tree.borrow()
+    }
+
+    /// The offset of the tree in the file, if it is stored uncompressed.
+    pub fn tree_start(&self) -> Option<usize> {
+        self.tree_start
+    }
+
+    /// Print the dump.
+    pub fn print<W: Write>(&self, out: &mut W) -> Result<(), std::io::Error> {
+        let tree = self.tree.borrow();
+        match self.tree_start {
+            Some(tree_start) => {
+                // Show the tree in place.
+                let (before, after) : (Vec<_>, Vec<_>) = self.container.iter()
+                    .cloned()
+                    .partition(|annotation| annotation.start < tree_start);
+                let annotations : Vec<_> = before.into_iter()
+                    .chain(tree.annotations.iter()
+                        .map(|annotation| Annotation {
+                            start: annotation.start + tree_start,
+                            end: annotation.end + tree_start,
+                            label: annotation.label.clone(),
+                        }))
+                    .chain(after)
+                    .collect();
+                print_annotations(out, &self.data, &annotations)
+            }
+            None => {
+                print_annotations(out, &self.data, &self.container)?;
+                writeln!(out, "")?;
+                writeln!(out, "Decompressed tree:")?;
+                print_annotations(out, &tree.data, &tree.annotations)
+            }
+        }
+    }
+}
+
+/// Print `data`, labelling bytes with `annotations`, which are sorted by offset.
+fn print_annotations<W: Write>(out: &mut W, data: &[u8], annotations: &[Annotation]) -> Result<(), std::io::Error> {
+    let mut position = 0;
+    for annotation in annotations {
+        if annotation.start > position {
+            print_range(out, data, position, annotation.start, "(unknown)")?;
+        }
+        print_range(out, data, annotation.start, annotation.end, &annotation.label)?;
+        position = std::cmp::max(position, annotation.end);
+    }
+    if position < data.len() {
+        print_range(out, data, position, data.len(), "(unknown)")?;
+    }
+    Ok(())
+}
+
+/// Print `data[start..end]`, at most `BYTES_PER_LINE` bytes per line, with `label` on the first line.
+fn print_range<W: Write>(out: &mut W, data: &[u8], start: usize, end: usize, label: &str) -> Result<(), std::io::Error> {
+    if start == end {
+        return writeln!(out, "{:8}  {:<47}  {:<16}  # {}", "", "", "", label);
+    }
+    for (i, chunk) in data[start..end].chunks(BYTES_PER_LINE).enumerate() {
+        let hex = chunk.iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let ascii : String = chunk.iter()
+            .map(|&b| if b >= 0x20 && b < 0x7f { b as char } else { '.' })
+            .collect();
+        if i == 0 {
+            writeln!(out, "{:08x}: {:<47}  {:<16}  # {}", start, hex, ascii, label)?;
+        } else {
+            writeln!(out, "{:08x}: {:<47}  {:<16}", start + i * BYTES_PER_LINE, hex, ascii)?;
+        }
+    }
+    Ok(())
+}
+
+/// Walk the container of a file, annotating each range of bytes.
+struct ContainerWalker<'a> {
+    reader: Cursor<&'a Vec<u8>>,
+    annotations: Vec<Annotation>,
+
+    /// The offset of the tree, if it is stored uncompressed.
+    tree_start: Option<usize>,
+}
+impl<'a> ContainerWalker<'a> {
+    fn position(&self) -> usize {
+        self.reader.position() as usize
+    }
+
+    /// Label the bytes read since `start`.
+    fn label(&mut self, start: usize, label: String) {
+        let end = self.position();
+        self.annotations.push(Annotation {
+            start,
+            end,
+            label,
+        });
+    }
+
+    fn varnum(&mut self, description: &str) -> Result<u32, std::io::Error> {
+        let start = self.position();
+        let value = self.reader.read_varnum()?;
+        self.label(start, format!("{}={}", description, value));
+        Ok(value)
+    }
+
+    fn bytes(&mut self, byte_len: usize, label: String) -> Result<Vec<u8>, std::io::Error> {
+        let start = self.position();
+        let mut buf = vec![0; byte_len];
+        self.reader.read_exact(&mut buf)?;
+        self.label(start, label);
+        Ok(buf)
+    }
+
+    fn header(&mut self, header: &str) -> Result<(), std::io::Error> {
+        let start = self.position();
+        self.reader.read_const(header.as_bytes())?;
+        self.label(start, format!("header \"{}\"", header));
+        Ok(())
+    }
+
+    fn starts_with(&self, header: &str) -> bool {
+        self.reader.get_ref()[self.position()..].starts_with(header.as_bytes())
+    }
+
+    /// A string, prefixed by its byte length.
+    fn string(&mut self, description: &str) -> Result<(), std::io::Error> {
+        let byte_len = self.varnum("byte length")?;
+        let start = self.position();
+        let mut buf = vec![0; byte_len as usize];
+        self.reader.read_exact(&mut buf)?;
+        let label =
+            if &buf == &[255, 0] {
+                format!("{}=null", description)
+            } else if &buf == &BLOB_PLACEHOLDER {
+                format!("{}=blob", description)
+            } else {
+                format!("{}=\"{}\"", description, String::from_utf8_lossy(&buf))
+            };
+        self.label(start, label);
+        Ok(())
+    }
+
+    /// A source location, as the line, column and offset of its start, then of its end.
+    fn location(&mut self, description: &str) -> Result<(), std::io::Error> {
+        for bound in &["start", "end"] {
+            for component in &["line", "column", "offset"] {
+                self.varnum(&format!("{}, {} {}", description, bound, component))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn walk(&mut self) -> Result<(), std::io::Error> {
+        let start = self.position();
+        self.reader.read_const(b"BINJS")?;
+        self.label(start, "magic header \"BINJS\"".to_string());
+        let version = self.varnum("container version")?;
+        let is_archive = if version == FORMAT_VERSION || version == FLAGS_FORMAT_VERSION {
+            self.varnum("container flags")? & FLAG_ARCHIVE != 0
+        } else {
+            version == ARCHIVE_FORMAT_VERSION || version == VARFLOAT_ARCHIVE_FORMAT_VERSION
+        };
+
+        if self.starts_with(HEADER_GRAMMAR_ID) {
+            self.header(HEADER_GRAMMAR_ID)?;
+            let start = self.position();
+            let grammar = read_grammar_id(&mut self.reader)?;
+            self.label(start, format!("grammar {}", grammar));
+        }
+
+        if self.starts_with(HEADER_STRING_DICTIONARY) {
+            self.header(HEADER_STRING_DICTIONARY)?;
+            self.bytes(32, "SHA-256 of string dictionary".to_string())?;
+        }
+
+        if self.starts_with(HEADER_METADATA) {
+            self.header(HEADER_METADATA)?;
+            let number_of_entries = self.varnum("number of metadata entries")?;
+            for _ in 0..number_of_entries {
+                self.string("key")?;
+                self.string("value")?;
+            }
+        }
+
+        if self.starts_with(HEADER_PROFILE) {
+            self.header(HEADER_PROFILE)?;
+            let number_of_functions = self.varnum("number of profiled functions")?;
+            self.bytes((number_of_functions as usize + 7) / 8, "functions executed at startup".to_string())?;
+        }
+
+        if self.starts_with(HEADER_SIGNATURE) {
+            self.header(HEADER_SIGNATURE)?;
+            self.bytes(bytes::signature::SIGNATURE_LENGTH, "Ed25519 signature".to_string())?;
+        }
+
+        let mut sections = vec!["grammar", "strings"];
+        if is_archive {
+            sections.push("manifest");
+        }
+        sections.push("tree");
+
+        if self.starts_with(HEADER_ENCRYPTED) {
+            // We cannot annotate the content sections.
+            self.header(HEADER_ENCRYPTED)?;
+            self.bytes(bytes::encryption::NONCE_LENGTH, "nonce".to_string())?;
+            let byte_len = self.varnum("encrypted byte length")?;
+            self.bytes(byte_len as usize, "encrypted content sections".to_string())?;
+        } else {
+            // Sections are identified by their header, as some are optional and the
+            // strings table may follow the tree.
+            sections.clear();
+            while let Some(name) = self.next_section() {
+                self.section(name)?;
+                sections.push(name);
+            }
+        }
+
+        if self.position() < self.reader.get_ref().len() {
+            self.header(HEADER_CHECKSUM)?;
+            let number_of_sections = self.varnum("number of sections")? as usize;
+            for i in 0..number_of_sections {
+                let name = sections.get(i)
+                    .cloned()
+                    .unwrap_or("unknown");
+                self.bytes(4, format!("CRC32 of section {}", name))?;
+            }
+            self.bytes(4, "CRC32 of file".to_string())?;
+        }
+        Ok(())
+    }
+
+    /// The name of the content section starting at the current position, if any.
+    fn next_section(&self) -> Option<&'static str> {
+        if self.starts_with(HEADER_GRAMMAR_TABLE) {
+            Some("grammar")
+        } else if self.starts_with(HEADER_STRINGS_TABLE) || self.starts_with(HEADER_STRINGS_TABLE_FRONT_CODED) {
+            Some("strings")
+        } else if self.starts_with(HEADER_BLOBS) {
+            Some("blobs")
+        } else if self.starts_with(HEADER_MANIFEST) {
+            Some("manifest")
+        } else if self.starts_with(HEADER_TREE) || self.starts_with(HEADER_TREE_RUNS)
+            || self.starts_with(HEADER_TREE_CHUNKS) || self.starts_with(HEADER_TREE_RUNS_CHUNKS) {
+            Some("tree")
+        } else if self.starts_with(HEADER_POSITIONS) {
+            Some("positions")
+        } else {
+            None
+        }
+    }
+
+    fn section(&mut self, name: &str) -> Result<(), std::io::Error> {
+        if name == "tree" && (self.starts_with(HEADER_TREE_CHUNKS) || self.starts_with(HEADER_TREE_RUNS_CHUNKS)) {
+            return self.chunks();
+        }
+        let front_coded = name == "strings" && self.starts_with(HEADER_STRINGS_TABLE_FRONT_CODED);
+        let header = match name {
+            "grammar" => HEADER_GRAMMAR_TABLE,
+            "strings" if front_coded => HEADER_STRINGS_TABLE_FRONT_CODED,
+            "strings" => HEADER_STRINGS_TABLE,
+            "blobs" => HEADER_BLOBS,
+            "manifest" => HEADER_MANIFEST,
+            "positions" => HEADER_POSITIONS,
+            _ if self.starts_with(HEADER_TREE_RUNS) => HEADER_TREE_RUNS,
+            _ => HEADER_TREE
+        };
+        self.header(header)?;
+
+        // Compression prefix, e.g. `identity;`.
+        let start = self.position();
+        let mut compression = vec![];
+        loop {
+            let mut buf = [0];
+            self.reader.read_exact(&mut buf)?;
+            if buf[0] == b';' {
+                break;
+            }
+            compression.push(buf[0]);
+        }
+        self.label(start, format!("compression \"{}\"", String::from_utf8_lossy(&compression)));
+        let byte_len = self.varnum("byte length")? as usize;
+        let end = self.position() + byte_len;
+
+        if &compression != b"identity" {
+            self.bytes(byte_len, format!("compressed {} section", name))?;
+            return Ok(());
+        }
+
+        match name {
+            "grammar" => {
+                let number_of_entries = self.varnum("number of node kinds")?;
+                for _ in 0..number_of_entries {
+                    self.string("node kind")?;
+                }
+            }
+            "strings" if front_coded => {
+                self.varnum("front coding window")?;
+                let number_of_entries = self.varnum("number of strings")?;
+                for i in 0..number_of_entries {
+                    let distance = self.varnum(&format!("string #{}, distance to shared prefix", i))?;
+                    if distance != 0 {
+                        self.varnum(&format!("string #{}, byte length of shared prefix", i))?;
+                    }
+                    let byte_len = self.varnum(&format!("string #{}, byte length of rest", i))?;
+                    self.bytes(byte_len as usize, format!("string #{}, rest", i))?;
+                }
+            }
+            "strings" => {
+                let number_of_entries = self.varnum("number of strings")?;
+                for i in 0..number_of_entries {
+                    self.string(&format!("string #{}", i))?;
+                }
+            }
+            "blobs" => {
+                let number_of_blobs = self.varnum("number of blobs")?;
+                for i in 0..number_of_blobs {
+                    let byte_len = self.varnum(&format!("blob #{}, byte length", i))?;
+                    self.bytes(byte_len as usize, format!("blob #{}", i))?;
+                }
+            }
+            "manifest" => {
+                let number_of_entries = self.varnum("number of entries")?;
+                for _ in 0..number_of_entries {
+                    self.string("entry")?;
+                    self.varnum("offset")?;
+                    self.varnum("byte length")?;
+                }
+            }
+            "positions" => {
+                let number_of_locations = self.varnum("number of locations")?;
+                for i in 0..number_of_locations {
+                    self.string(&format!("location #{}, path", i))?;
+                    self.location(&format!("location #{}", i))?;
+                }
+                let number_of_comments = self.varnum("number of comments")?;
+                for i in 0..number_of_comments {
+                    self.string(&format!("comment #{}, kind", i))?;
+                    self.string(&format!("comment #{}, text", i))?;
+                    self.location(&format!("comment #{}", i))?;
+                }
+            }
+            _ => {
+                // Annotated while reading the tree.
+                self.tree_start = Some(self.position());
+            }
+        }
+        self.reader.set_position(end as u64);
+        Ok(())
+    }
+
+    /// A chunked tree section. As the decompressed tree is not stored contiguously,
+    /// its annotations are shown after the file.
+    fn chunks(&mut self) -> Result<(), std::io::Error> {
+        let header =
+            if self.starts_with(HEADER_TREE_RUNS_CHUNKS) {
+                HEADER_TREE_RUNS_CHUNKS
+            } else {
+                HEADER_TREE_CHUNKS
+            };
+        self.header(header)?;
+        let number_of_chunks = self.varnum("number of lazy chunks")?;
+        let mut byte_lens = vec![self.varnum("byte length of toplevel chunk")?];
+        for i in 0..number_of_chunks {
+            self.varnum(&format!("lazy chunk #{}, offset in toplevel chunk", i))?;
+            byte_lens.push(self.varnum(&format!("lazy chunk #{}, byte length", i))?);
+        }
+        for (i, byte_len) in byte_lens.into_iter().enumerate() {
+            let label = match i {
+                0 => "toplevel chunk".to_string(),
+                _ => format!("lazy chunk #{}", i - 1)
+            };
+            self.bytes(byte_len as usize, label)?;
+        }
+        Ok(())
+    }
+}
@@ -6,13 +6,14 @@
 //! skipping a section and/or reading sections concurrently. Each section may be compressed
 //! independently, possibly with different compression formats, with the expectation that this
 //! will let compressors take best advantage of the distinct structures of each section.
+//! With `Compression::Auto`, the encoder picks the smallest compression for each section.
 //!
 //! (future versions may allow file-wide compression, too)
 //!
 //! The sections are:
 //!
 //! 1. the grammar table;
-//! 2. the strings table (which contains both strings and identifiers);
+//! 2. the strings table (which contains strings, identifiers and RegExp patterns);
 //! 3. the representation of the tree.
 //!
 //! The grammar table lists the AST nodes used in the file. Its primary role is to serve as a lightweight
@@ -39,10 +40,96 @@
 //! The entire file is formatted as:
 //!
 //! - the characters `"BINJS"`;
-//! - a container version number (`varnum`, currently `0`);
+//! - the container version number (`varnum`, `6`, see below);
+//! - the container flags (`varnum`, see below);
+//! - optionally, the grammar identifier (see below);
+//! - optionally, the string dictionary identifier (see below);
+//! - optionally, the metadata (see below);
+//! - optionally, the startup profile (see below);
+//! - optionally, the signature (see below);
+//! - optionally, the encryption header (see below), in which case the following sections
+//!   up to, but not including, the checksum section, are encrypted;
+//! - the compressed grammar table (see below);
+//! - the compressed strings table, optionally front-coded (see below), unless it follows the tree;
+//! - optionally, the blobs, immediately after the strings table (see below);
+//! - the compressed tree (see below), optionally chunked (see below);
+//! - with a split prelude, the compressed strings table (see below), and optionally the blobs;
+//! - optionally, the compressed source positions (see below);
+//! - optionally, the checksum section (see below).
+//!
+//! ## Split prelude
+//!
+//! The tree depends on the grammar table to be tokenized, but only depends on the strings
+//! table to convert the indices of strings into strings. With a split prelude, the strings
+//! table is written after the tree rather than before, so that a reader may start decoding
+//! the tree as soon as the grammar table has been received, see
+//! `TreeTokenReader::with_deferred_strings`. Strings are then resolved as the strings table
+//! is received, i.e. a string only waits for the entries preceding its own.
+//!
+//! Readers detect a split prelude from the header following the grammar table. Archives
+//! and chunked trees do not have a split prelude.
+//!
+//! ## Archives
+//!
+//! An archive stores several trees (typically the modules of a bundle) in a single file,
+//! sharing the grammar table and strings table between all entries. An archive has
+//! container flag `1` set, and is formatted as:
+//!
+//! - the characters `"BINJS"`;
+//! - the container version number (`varnum`, `6`);
+//! - the container flags (`varnum`);
+//! - optionally, the grammar identifier (see below);
+//! - optionally, the signature (see below);
+//! - optionally, the encryption header (see below), in which case the following sections
+//!   up to, but not including, the checksum section, are encrypted;
 //! - the compressed grammar table (see below);
 //! - the compressed strings table (see below);
-//! - the compressed tree (see below).
+//! - optionally, the blobs (see below);
+//! - the compressed manifest (see below);
+//! - the compressed tree (see below), containing all the trees, one after the other;
+//! - optionally, the checksum section (see below).
+//!
+//! The manifest lists the entries of the archive:
+//!
+//! - the characters `"[MANIFEST]"`;
+//! - a `prefix` identifying the compression format used for the manifest (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`:
+//!    - the number of entries (`varnum`);
+//!    - for each entry,
+//!      - byte length of the name of the entry (`varnum`);
+//!      - the name of the entry, typically a path (utf-8 encoded, `bytelen` bytes, no terminator);
+//!      - the offset of the tree of the entry in the decompressed tree section (`varnum`);
+//!      - the byte length of the tree of the entry in the decompressed tree section (`varnum`).
+//!
+//! ## Container version
+//!
+//! The container flags are a bitset:
+//!
+//! - `1` if the file is an archive;
+//! - `2` if floats are represented as varfloats;
+//! - `4` if the grammar composes the base grammar with vendor extensions, e.g.
+//!   instrumentation nodes. Only valid if the file has a grammar identifier, which
+//!   identifies the composed grammar;
+//! - `8` if the file ends with a checksum section, so that readers detect files
+//!   truncated before or within it;
+//! - `16` if the payload of NaNs is preserved, `32` if NaNs are rejected, neither if
+//!   NaNs are canonicalized, see `bytes::float::NaNPolicy`. Readers handle NaNs as
+//!   announced by the file.
+//!
+//! Readers reject files with unknown flags, or with both `16` and `32`. Files written with container version `5`
+//! have container flags too, but no metadata, which was introduced by version `6`.
+//!
+//! Files written by earlier encoders have no container flags and use one of the legacy
+//! container version numbers instead:
+//!
+//! - `1`, a single tree;
+//! - `2`, an archive;
+//! - `3`, a single tree in which floats are represented as varfloats;
+//! - `4`, an archive in which floats are represented as varfloats.
+//!
+//! The rest of the file is the same. Use `binjs_upgrade` to rewrite such files with the
+//! current version.
 //!
 //! ## Grammar table
 //!
@@ -74,12 +161,53 @@
 //!        - the invalid strings [255, 0] (representing the null string, only valid if byte length is 2);
 //!        - a utf-8 encoded string (utf-8 encoded, `bytelen` bytes, no terminator).
 //!
+//! ## Front-coded strings table
+//!
+//! With front coding, the strings table shares prefixes between entries, which typically
+//! helps with identifiers such as `getElementById` and `getElementsByTagName`. Entries
+//! keep the order of the strings table.
+//!
+//! - the characters `"[STRINGS-FRONT]"`, instead of `"[STRINGS]"`;
+//! - a `prefix` identifying the compression format used for the strings (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`;
+//!    - the window, i.e. the maximal distance of a back-reference (`varnum`);
+//!    - the number of entries (`varnum`);
+//!    - for each entry,
+//!      - the distance to the previous entry sharing a prefix with this entry, or 0 (`varnum`);
+//!      - if the distance is not 0, the byte length of the shared prefix (`varnum`);
+//!      - the byte length of the rest of the entry (`varnum`);
+//!      - the rest of the entry, such that the prefix followed by the rest is either
+//!        the invalid string [255, 0] (representing the null string) or a utf-8 encoded string.
+//!
+//! ## Blobs
+//!
+//! Very large strings, e.g. base64-encoded images embedded in the source, compress poorly
+//! and get in the way of the compression of the other strings. The encoder may write
+//! the strings of at least a given number of bytes as blobs, which the strings table
+//! replaces with the invalid string [255, 1], in plain and front-coded tables alike.
+//! The blobs are compressed independently from the strings table, typically not at all,
+//! so that decoders may copy them as they arrive.
+//!
+//! If the strings table contains at least one blob, it is immediately followed by
+//!
+//! - the characters `"[BLOBS]"`;
+//! - a `prefix` identifying the compression format used for the blobs (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`:
+//!    - the number of blobs (`varnum`);
+//!    - for each blob, in the order of the strings table,
+//!      - byte length of the blob (`varnum`);
+//!      - the blob (utf-8 encoded, `bytelen` bytes, no terminator).
+//!
+//! For checksums and signatures, the blobs are part of the strings table section.
+//!
 //! ## The tree
 //!
 //! This contains the actual tree for a specific grammar. The file does not contain all the information
 //! to determine the nature of next token. Rather, this must be led by the grammar.
 //!
-//! - the characters `"[TREE]"`;
+//! - the characters `"[TREE]"`, or `"[TREE-RUNS]"` if the tree has runs (see below);
 //! - a `prefix` identifying the compression format used for the grammar (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
 //! - the number of compressed bytes (`varnum`);
 //! - compressed in the format identified by `prefix`:
@@ -93,8 +221,22 @@
 //!     - a `varnum`;
 //!   - a null float, represented as:
 //!     - a low-endian IEEE764 64-bit floating point value signalling NaN (8 bytes),
+//!     - or, in container versions `3` and `4`, the bytes `[1, 1, 0]`;
 //!   - a non-null float, represented as:
 //!     - a low-endian IEEE764 64-bit floating point value non-signalling NaN (8 bytes),
+//!       in which NaNs are handled as announced by the container flags,
+//!       see `bytes::float::NaNPolicy`,
+//!     - or, in container versions `3` and `4`, a varfloat, in which integers and
+//!       short decimal numbers take 1 to 5 bytes, see `bytes::float::WriteVarFloat`;
+//!   - a null BigInt, represented as:
+//!     - the bytes `[1, 0]`, an invalid `varnum` (two bytes);
+//!   - a non-null BigInt, represented as:
+//!     - `2 * byte_length + is_negative` (`varnum`);
+//!     - the magnitude, low-endian, without trailing zeros (`byte_length` bytes);
+//!   - null RegExp flags, represented as:
+//!     - the bytes `[1, 0]`, an invalid `varnum` (two bytes);
+//!   - non-null RegExp flags, represented as:
+//!     - a bitfield, in which flag `"gimsuy"[i]` is bit `1 << i` (`varnum`, one byte);
 //!   - a null boolean, represented as:
 //!     -  a single byte with value `2` (one byte);
 //!   - a non-null boolean, represented as:
@@ -109,26 +251,699 @@
 //!     - an entry in the grammar table (`varnum`);
 //!     - for each field
 //!       - the token
+//!
+//! ### Runs
+//!
+//! Large trees often contain long runs of siblings with the same interface, e.g. array
+//! literals of numbers or large `switch` statements. In a tree with runs, the items of
+//! a list that are tagged tuples are grouped in runs of consecutive items with the same
+//! tag, and only the first item of a run of at least 3 items carries the tag. Tagged
+//! tuples are represented as
+//!
+//!   - if the tagged tuple is an item of a list, but not the first item of its run:
+//!     - for each field
+//!       - the token;
+//!   - if the tagged tuple is the first item of a run:
+//!     - `2 * index + 1`, where `index` is an entry in the grammar table (`varnum`);
+//!     - the number of subsequent items in the run (`varnum`);
+//!     - for each field
+//!       - the token;
+//!   - otherwise:
+//!     - `2 * index`, where `index` is an entry in the grammar table (`varnum`);
+//!     - for each field
+//!       - the token.
+//!
+//! ### Chunks
+//!
+//! A chunked tree is split into independently compressed chunks: the toplevel chunk, which
+//! contains the tree without the contents of the outermost lazy fields, followed by one
+//! chunk per such contents, in the order in which they appear in the tree. Nested lazy
+//! fields remain in the chunk of their outermost lazy field. The chunk index precedes the
+//! chunks, so that a client may fetch the prelude and the index, then the toplevel chunk and
+//! the first few functions with a single HTTP range request, and the rest of the functions
+//! later, see `TreeTokenReader::with_chunks`.
+//!
+//! - the characters `"[TREE-CHUNKS]"`, or `"[TREE-RUNS-CHUNKS]"` if the tree has runs;
+//! - the number of lazy chunks (`varnum`);
+//! - the byte length of the toplevel chunk, as stored (`varnum`);
+//! - for each lazy chunk,
+//!   - the offset in the decompressed toplevel chunk at which the contents belong (`varnum`);
+//!   - the byte length of the chunk, as stored (`varnum`);
+//! - the toplevel chunk, then each lazy chunk, each stored as:
+//!   - a `prefix` identifying the compression format used for the chunk (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//!   - the number of compressed bytes (`varnum`);
+//!   - the decompressed chunk, compressed in the format identified by `prefix`.
+//!
+//! Inserting each lazy chunk at its offset in the toplevel chunk yields the tree token.
+//!
+//! ## Signature
+//!
+//! The signature lets readers check that the file was produced by the owner of a key
+//! and hasn't been tampered with since. Readers that are given a public key require it.
+//!
+//! - the characters `"[SIGNATURE]"`;
+//! - the Ed25519 signature of all the bytes preceding the signature, i.e. the container
+//!   version and flags and the headers, followed by all the sections following it, from
+//!   the grammar table up to, but not including, the checksum section (64 bytes).
+//!   Encrypted sections are signed before encryption.
+//!
+//! ## Grammar identifier
+//!
+//! The grammar identifier lets decoders reject files encoded with a grammar they do not
+//! support, instead of misdecoding them. Files without a grammar identifier are assumed
+//! to use the grammar of the decoder.
+//!
+//! - the characters `"[GRAMMAR-ID]"`;
+//! - the byte length of the name of the grammar (`varnum`);
+//! - the name of the grammar (utf-8 encoded, `bytelen` bytes, no terminator);
+//! - the fingerprint of the grammar (8 bytes, little-endian).
+//!
+//! ## String dictionary identifier
+//!
+//! The strings table may be compressed by brotli with a custom dictionary, e.g. common
+//! fragments of JavaScript identifiers. The dictionary is not part of the file, and is
+//! independent from the entropy dictionary. Decoders reject files compressed with a
+//! dictionary they do not have.
+//!
+//! - the characters `"[STRING-DICTIONARY]"`;
+//! - the SHA-256 hash of the dictionary (32 bytes).
+//!
+//! ## Metadata
+//!
+//! The metadata records how the file was produced, e.g. the version of the encoder, its
+//! options and when it ran, so that operators may audit an artifact. Decoders ignore it.
+//! Like the rest of the prelude, it is signed and covered by the checksum of the file.
+//!
+//! - the characters `"[METADATA]"`;
+//! - the number of entries (`varnum`);
+//! - for each entry,
+//!   - byte length of the key (`varnum`);
+//!   - the key, e.g. `encoder` (utf-8 encoded, `bytelen` bytes, no terminator);
+//!   - byte length of the value (`varnum`);
+//!   - the value (utf-8 encoded, `bytelen` bytes, no terminator).
+//!
+//! ## Startup profile
+//!
+//! The startup profile records which functions were executed at startup, e.g. so that
+//! engines may compile them ahead of time, see `startup::StartupProfile`. The encoder typically
+//! used it to decide which functions to encode eagerly. Decoders skip it, see
+//! `TreeTokenReader::profile`. Like the metadata, it is signed. Archives have
+//! no startup profile.
+//!
+//! - the characters `"[PROFILE]"`;
+//! - the number of functions covered by the profile (`varnum`);
+//! - a bit per function, set if the function was executed at startup, function `id`
+//!   being bit `1 << (id % 8)` of byte `id / 8`, padded with zeros to a whole byte.
+//!
+//! ## Encryption
+//!
+//! The content sections may be encrypted with AES-256-GCM, for experiments with private
+//! code delivery. The key is not part of the file and must be exchanged out-of-band.
+//!
+//! - the characters `"[ENCRYPTED]"`;
+//! - a nonce (12 bytes);
+//! - the number of encrypted bytes (`varnum`);
+//! - the encrypted content sections, followed by the authentication tag.
+//!
+//! ## Checksum
+//!
+//! The checksum section lets readers detect truncated or corrupted files before
+//! decoding the tree. Readers verify it by default. As it is announced by the container
+//! flags, a missing or truncated checksum section is reported as a bad checksum.
+//!
+//! - the characters `"[CHECKSUM]"`;
+//! - the number of sections (`varnum`);
+//! - for each section, in order, the CRC32 of the entire section, including its
+//!   header (4 bytes, little-endian). If the file is encrypted, this is the CRC32 of
+//!   the encrypted bytes of the section, at the same offsets, so that checksums do not
+//!   reveal anything about the decrypted content;
+//! - the CRC32 of the entire file up to, but not including, `"[CHECKSUM]"`
+//!   (4 bytes, little-endian).
+//!
+//! ## Source positions
+//!
+//! Source positions let tools map nodes back to the source text they were encoded from
+//! and, optionally, restore its comments. They are not part of the tree and decoders
+//! are free to ignore them. Archives do not carry source positions.
+//!
+//! - the characters `"[POSITIONS]"`;
+//! - a `prefix` identifying the compression format used for the positions (one of "identity;", "br;", "gzip;", "compress;", "deflate;").
+//! - the number of compressed bytes (`varnum`);
+//! - compressed in the format identified by `prefix`, the positions, as written by
+//!   `positions::SourcePositions::write`.
+
+use bytes::compress::{ BrotliDictionary, Compression };
+use bytes::float::NaNPolicy;
+use bytes::varnum::*;
+use ::GrammarId;
 
 use binjs_shared::SharedString;
 
 use clap;
 
+use std;
+use std::fmt::{ Display, Formatter };
+use std::io::{ Read, Write };
+
 /// Implementation of the token reader.
 mod read;
 
+/// Annotated dumps, for debugging.
+mod annotate;
+
 /// Implementation of the token writer.
 mod write;
 
 /// The header of the strings table section.
 const HEADER_STRINGS_TABLE : &str = "[STRINGS]";
 
+/// The header of the strings table section, if front-coded.
+const HEADER_STRINGS_TABLE_FRONT_CODED : &str = "[STRINGS-FRONT]";
+
+/// The header of the blobs, only present if the strings table contains blobs.
+const HEADER_BLOBS : &str = "[BLOBS]";
+
+/// The entry of the strings table replacing a string written as a blob.
+/// Like the null string [255, 0], it is invalid utf-8.
+const BLOB_PLACEHOLDER : [u8; 2] = [255, 1];
+
 /// The header of the grammars table section.
 const HEADER_GRAMMAR_TABLE: &str = "[GRAMMAR]";
 
 /// The header of the tree section.
 const HEADER_TREE: &str = "[TREE]";
 
+/// The header of the tree section, if the tree has runs.
+const HEADER_TREE_RUNS: &str = "[TREE-RUNS]";
+
+/// The header of the tree section, if the tree is chunked.
+const HEADER_TREE_CHUNKS: &str = "[TREE-CHUNKS]";
+
+/// The header of the tree section, if the tree has runs and is chunked.
+const HEADER_TREE_RUNS_CHUNKS: &str = "[TREE-RUNS-CHUNKS]";
+
+/// The header of the grammar identifier, only present if the encoder specified a grammar.
+const HEADER_GRAMMAR_ID: &str = "[GRAMMAR-ID]";
+
+/// The header of the string dictionary identifier, only present if the encoder specified
+/// a brotli custom dictionary for the strings table.
+const HEADER_STRING_DICTIONARY: &str = "[STRING-DICTIONARY]";
+
+/// The header of the metadata, only present if the encoder recorded metadata.
+const HEADER_METADATA: &str = "[METADATA]";
+
+/// The header of the startup profile, only present if the encoder recorded a profile.
+const HEADER_PROFILE: &str = "[PROFILE]";
+
+/// The header of the manifest section, only present in archives.
+const HEADER_MANIFEST: &str = "[MANIFEST]";
+
+/// The header of the source positions section, only present if the encoder specified positions.
+const HEADER_POSITIONS: &str = "[POSITIONS]";
+
+/// The current container version number, followed by the container flags.
+const FORMAT_VERSION: u32 = 6;
+
+/// The legacy container version number followed by the container flags, in which files
+/// have no metadata.
+const FLAGS_FORMAT_VERSION: u32 = 5;
+
+/// Container flag: the file is an archive.
+const FLAG_ARCHIVE: u32 = 1;
+
+/// Container flag: floats are represented as varfloats.
+const FLAG_VARFLOATS: u32 = 2;
+
+/// Container flag: the grammar has vendor extensions.
+const FLAG_EXTENDED: u32 = 4;
+
+/// Container flag: the file ends with a checksum section.
+const FLAG_CHECKSUM: u32 = 8;
+
+/// Container flag: the payload of NaNs is preserved, see `NaNPolicy::Preserve`.
+const FLAG_NAN_PRESERVE: u32 = 16;
+
+/// Container flag: NaNs are rejected, see `NaNPolicy::Reject`.
+const FLAG_NAN_REJECT: u32 = 32;
+
+/// All the container flags known to this version.
+const FLAGS: u32 = FLAG_ARCHIVE | FLAG_VARFLOATS | FLAG_EXTENDED | FLAG_CHECKSUM | FLAG_NAN_PRESERVE | FLAG_NAN_REJECT;
+
+/// The legacy container version number of single trees.
+const LEGACY_FORMAT_VERSION: u32 = 1;
+
+/// The legacy container version number of archives.
+const ARCHIVE_FORMAT_VERSION: u32 = 2;
+
+/// The legacy container version number of single trees, in which floats are represented as varfloats.
+const VARFLOAT_FORMAT_VERSION: u32 = 3;
+
+/// The legacy container version number of archives, in which floats are represented as varfloats.
+const VARFLOAT_ARCHIVE_FORMAT_VERSION: u32 = 4;
+
+/// The container version of a file, along with the features it announces.
+///
+/// Writers always use the current version. Readers also accept the legacy
+/// version 5, which has no metadata, and the legacy versions 1 to 4, which
+/// encoded the features in the version number itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct ContainerVersion {
+    /// The container version number.
+    pub number: u32,
+
+    /// If `true`, the file is an archive.
+    pub is_archive: bool,
+
+    /// If `true`, floats are represented as varfloats.
+    pub varfloats: bool,
+
+    /// If `true`, the grammar has vendor extensions.
+    pub extended: bool,
+
+    /// If `true`, the file ends with a checksum section.
+    pub checksum: bool,
+
+    /// How NaN values are handled. Always `NaNPolicy::Canonicalize` in legacy versions.
+    #[serde(serialize_with = "serialize_nan_policy")]
+    pub nan_policy: NaNPolicy,
+}
+impl ContainerVersion {
+    /// The current container version, with the given features.
+    pub fn current(is_archive: bool, varfloats: bool, extended: bool, checksum: bool, nan_policy: NaNPolicy) -> Self {
+        ContainerVersion {
+            number: FORMAT_VERSION,
+            is_archive,
+            varfloats,
+            extended,
+            checksum,
+            nan_policy,
+        }
+    }
+
+    /// `true` unless the file should be upgraded with `binjs_upgrade`.
+    pub fn is_current(&self) -> bool {
+        self.number == FORMAT_VERSION
+    }
+
+    /// Read a container version number, followed by the container flags if the
+    /// version has them.
+    ///
+    /// Returns `None` if the version or the flags are not supported.
+    fn read<R: Read>(inp: &mut R) -> Result<Option<Self>, std::io::Error> {
+        let number = inp.read_varnum()?;
+        let (is_archive, varfloats, extended, checksum, nan_policy) = match number {
+            FORMAT_VERSION | FLAGS_FORMAT_VERSION => {
+                let flags = inp.read_varnum()?;
+                if flags & !FLAGS != 0 {
+                    return Ok(None)
+                }
+                let nan_policy = match (flags & FLAG_NAN_PRESERVE != 0, flags & FLAG_NAN_REJECT != 0) {
+                    (false, false) => NaNPolicy::Canonicalize,
+                    (true, false) => NaNPolicy::Preserve,
+                    (false, true) => NaNPolicy::Reject,
+                    (true, true) => return Ok(None),
+                };
+                (flags & FLAG_ARCHIVE != 0, flags & FLAG_VARFLOATS != 0, flags & FLAG_EXTENDED != 0, flags & FLAG_CHECKSUM != 0, nan_policy)
+            }
+            LEGACY_FORMAT_VERSION => (false, false, false, false, NaNPolicy::Canonicalize),
+            ARCHIVE_FORMAT_VERSION => (true, false, false, false, NaNPolicy::Canonicalize),
+            VARFLOAT_FORMAT_VERSION => (false, true, false, false, NaNPolicy::Canonicalize),
+            VARFLOAT_ARCHIVE_FORMAT_VERSION => (true, true, false, false, NaNPolicy::Canonicalize),
+            _ => return Ok(None)
+        };
+        Ok(Some(ContainerVersion {
+            number,
+            is_archive,
+            varfloats,
+            extended,
+            checksum,
+            nan_policy,
+        }))
+    }
+
+    /// Write the container version number and flags.
+    ///
+    /// # Panics
+    ///
+    /// If this is not the current version, as legacy versions are never written.
+    fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
+        assert!(self.is_current());
+        let mut flags = 0;
+        if self.is_archive {
+            flags |= FLAG_ARCHIVE;
+        }
+        if self.varfloats {
+            flags |= FLAG_VARFLOATS;
+        }
+        if self.extended {
+            flags |= FLAG_EXTENDED;
+        }
+        if self.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        match self.nan_policy {
+            NaNPolicy::Canonicalize => {}
+            NaNPolicy::Preserve => flags |= FLAG_NAN_PRESERVE,
+            NaNPolicy::Reject => flags |= FLAG_NAN_REJECT,
+        }
+        Ok(out.write_varnum(self.number)? + out.write_varnum(flags)?)
+    }
+}
+
+fn serialize_nan_policy<S: ::serde::Serializer>(nan_policy: &NaNPolicy, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(nan_policy.name())
+}
+
+/// The header of the signature, only present if the file is signed.
+const HEADER_SIGNATURE: &str = "[SIGNATURE]";
+
+/// The header of the encrypted content, only present if the file is encrypted.
+const HEADER_ENCRYPTED: &str = "[ENCRYPTED]";
+
+/// The header of the checksum section, only present if checksums are enabled.
+const HEADER_CHECKSUM: &str = "[CHECKSUM]";
+
+/// A part of the container, as described by `ContainerLayout`.
+#[derive(Clone, Debug, Serialize)]
+pub struct SectionLayout {
+    /// The name of the part. Sections that may be extracted are named after `SECTION_NAMES`.
+    pub name: &'static str,
+
+    /// The headers that may start the part, each of them announcing a variant of the part.
+    pub headers: Vec<&'static str>,
+
+    /// If `true`, the part is absent from some files.
+    pub optional: bool,
+
+    /// If `true`, the part is compressed, starting with one of `ContainerLayout::compressions`.
+    pub compressed: bool,
+
+    /// If `true`, the part is encrypted if the file is encrypted.
+    pub encrypted: bool,
+}
+
+/// A machine-readable description of the container, as documented in this module.
+///
+/// Designed for generating the specification document and for checking alternative
+/// implementations against this one.
+#[derive(Clone, Debug, Serialize)]
+pub struct ContainerLayout {
+    /// The characters starting every file.
+    pub magic_header: &'static str,
+
+    /// The current container version number.
+    pub version: u32,
+
+    /// The name and bit of each container flag.
+    pub flags: Vec<(&'static str, u32)>,
+
+    /// The legacy container versions, still accepted by readers.
+    ///
+    /// Version 5 is also accepted, followed by the container flags as the current
+    /// version, but without metadata.
+    pub legacy_versions: Vec<ContainerVersion>,
+
+    /// The prefixes identifying the compression format of a compressed part.
+    pub compressions: Vec<&'static str>,
+
+    /// The parts of the container, in the order in which they appear in a file.
+    pub sections: Vec<SectionLayout>,
+}
+impl ContainerLayout {
+    pub fn new() -> Self {
+        let section = |name, headers: &[&'static str], optional, compressed, encrypted| SectionLayout {
+            name,
+            headers: headers.to_vec(),
+            optional,
+            compressed,
+            encrypted,
+        };
+        ContainerLayout {
+            magic_header: "BINJS",
+            version: FORMAT_VERSION,
+            flags: vec![("archive", FLAG_ARCHIVE), ("varfloats", FLAG_VARFLOATS), ("extended", FLAG_EXTENDED), ("checksum", FLAG_CHECKSUM), ("nan-preserve", FLAG_NAN_PRESERVE), ("nan-reject", FLAG_NAN_REJECT)],
+            legacy_versions: vec![
+                ContainerVersion { number: LEGACY_FORMAT_VERSION, is_archive: false, varfloats: false, extended: false, checksum: false, nan_policy: NaNPolicy::Canonicalize },
+                ContainerVersion { number: ARCHIVE_FORMAT_VERSION, is_archive: true, varfloats: false, extended: false, checksum: false, nan_policy: NaNPolicy::Canonicalize },
+                ContainerVersion { number: VARFLOAT_FORMAT_VERSION, is_archive: false, varfloats: true, extended: false, checksum: false, nan_policy: NaNPolicy::Canonicalize },
+                ContainerVersion { number: VARFLOAT_ARCHIVE_FORMAT_VERSION, is_archive: true, varfloats: true, extended: false, checksum: false, nan_policy: NaNPolicy::Canonicalize },
+            ],
+            compressions: vec!["identity;", "br;", "gzip;", "compress;", "deflate;"],
+            sections: vec![
+                section("grammar-id", &[HEADER_GRAMMAR_ID], true, false, false),
+                section("string-dictionary", &[HEADER_STRING_DICTIONARY], true, false, false),
+                section("metadata", &[HEADER_METADATA], true, false, false),
+                section("profile", &[HEADER_PROFILE], true, false, false),
+                section("signature", &[HEADER_SIGNATURE], true, false, false),
+                section("encryption", &[HEADER_ENCRYPTED], true, false, false),
+                section("grammar", &[HEADER_GRAMMAR_TABLE], false, true, true),
+                section("strings", &[HEADER_STRINGS_TABLE, HEADER_STRINGS_TABLE_FRONT_CODED], false, true, true),
+                section("blobs", &[HEADER_BLOBS], true, true, true),
+                section("manifest", &[HEADER_MANIFEST], true, true, true),
+                section("tree", &[HEADER_TREE, HEADER_TREE_RUNS, HEADER_TREE_CHUNKS, HEADER_TREE_RUNS_CHUNKS], false, true, true),
+                section("positions", &[HEADER_POSITIONS], true, true, true),
+                section("checksum", &[HEADER_CHECKSUM], true, false, false),
+            ],
+        }
+    }
+}
+
+/// Options for detecting truncated, corrupted or tampered files, and for keeping
+/// them confidential.
+#[derive(Clone, Debug)]
+pub struct Integrity {
+    /// If `true`, append a checksum section when writing.
+    pub write_checksum: bool,
+
+    /// If `true`, verify the checksum section, if any, when reading.
+    pub verify_checksum: bool,
+
+    /// If specified, sign the content sections with this Ed25519 secret key when writing.
+    pub sign_key: Option<[u8; 32]>,
+
+    /// If specified, require the content sections to be signed by this Ed25519
+    /// public key when reading.
+    pub verify_key: Option<[u8; 32]>,
+
+    /// If specified, encrypt the content sections with this AES-256-GCM key when
+    /// writing, and decrypt them when reading. The key is exchanged out-of-band.
+    pub encryption_key: Option<[u8; 32]>,
+}
+impl Default for Integrity {
+    fn default() -> Self {
+        Integrity {
+            write_checksum: false,
+            verify_checksum: true,
+            sign_key: None,
+            verify_key: None,
+            encryption_key: None,
+        }
+    }
+}
+
+/// Options for writing and reading files, beyond the compression of sections,
+/// see `Targets`.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Detecting corrupted or tampered files.
+    pub integrity: Integrity,
+
+    /// If specified, front-code the strings table when writing, sharing prefixes
+    /// between each string and the strings among this many previous entries.
+    /// Readers detect front-coded tables from their header.
+    pub front_coding: Option<usize>,
+
+    /// If specified, write the strings of at least this many bytes as blobs, outside
+    /// of the strings table.
+    /// Readers detect blobs from the strings table.
+    pub blob_threshold: Option<usize>,
+
+    /// The compression of the blobs, if any, when writing.
+    pub blob_compression: Compression,
+
+    /// How NaN values are handled when writing.
+    /// Readers handle NaNs as announced by the container flags.
+    pub nan_policy: NaNPolicy,
+
+    /// If `true`, represent floats as varfloats when writing.
+    /// Readers detect varfloats from the container version number.
+    pub varfloats: bool,
+
+    /// If `true`, write runs of list items with the same tag.
+    /// Readers detect runs from the header of the tree section.
+    pub runs: bool,
+
+    /// If `true`, write the strings table after the tree.
+    /// Readers detect the order of the sections from their headers.
+    pub split_prelude: bool,
+
+    /// If `true`, write the tree as independently compressed chunks, one
+    /// for the toplevel and one per outermost lazy function.
+    /// Readers detect chunks from the header of the tree section.
+    pub chunks: bool,
+
+    /// If specified, compress the strings table with this brotli custom dictionary when
+    /// writing, and decompress it with this dictionary when reading.
+    /// Readers reject files compressed with another dictionary.
+    pub string_dictionary: Option<BrotliDictionary>,
+
+    /// If specified, record this metadata when writing.
+    /// Readers skip it, see `TreeTokenReader::metadata`.
+    pub metadata: Option<Metadata>,
+
+    /// If `true`, writing the same tree with the same options yields the same bytes,
+    /// whenever and wherever it is written, see `TreeTokenWriter::with_reproducible`.
+    pub reproducible: bool,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            integrity: Integrity::default(),
+            front_coding: None,
+            blob_threshold: None,
+            blob_compression: Compression::Identity,
+            nan_policy: NaNPolicy::default(),
+            varfloats: false,
+            runs: false,
+            split_prelude: false,
+            chunks: false,
+            string_dictionary: None,
+            metadata: None,
+            reproducible: false,
+        }
+    }
+}
+
+/// Information on how a file was produced, e.g. the version of the encoder, recorded
+/// for audits. Decoders ignore it.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Metadata {
+    /// Pairs of key, value, in the order in which they were added.
+    entries: Vec<(String, String)>,
+}
+impl Metadata {
+    /// The key of the name and version of the encoder, e.g. `binjs_encode 0.4.0`.
+    pub const ENCODER: &'static str = "encoder";
+
+    /// The key of the format and its options, as parsed by the encoder, see `Format::options`.
+    pub const OPTIONS: &'static str = "options";
+
+    /// The key of the hash of the dictionary, if any, hex-encoded.
+    pub const DICTIONARY: &'static str = "dictionary";
+
+    /// The key of the time of encoding, in seconds since the UNIX epoch.
+    pub const TIMESTAMP: &'static str = "timestamp";
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an entry, replacing the value of the entry with the same key, if any.
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        match self.entries.iter().position(|&(ref k, _)| k == key) {
+            Some(index) => self.entries[index].1 = value.to_string(),
+            None => self.entries.push((key.to_string(), value.to_string()))
+        }
+        self
+    }
+
+    /// The value of the entry with key `key`, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries.iter()
+            .find(|&&(ref k, _)| k == key)
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// The entries, in the order in which they were added.
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// This metadata, without the entries that vary between runs of the encoder on the
+    /// same input: the time of encoding, and the options, which may contain paths.
+    pub fn reproducible(&self) -> Self {
+        Metadata {
+            entries: self.entries.iter()
+                .filter(|&&(ref key, _)| key != Self::TIMESTAMP && key != Self::OPTIONS)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+impl Display for Metadata {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        for &(ref key, ref value) in &self.entries {
+            write!(f, "{}: {}\n", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write metadata, without its header.
+fn write_metadata<W: Write>(out: &mut W, metadata: &Metadata) -> Result<usize, std::io::Error> {
+    let mut written = out.write_varnum(metadata.entries.len() as u32)?;
+    for &(ref key, ref value) in &metadata.entries {
+        for string in &[key, value] {
+            written += out.write_varnum(string.len() as u32)?;
+            out.write_all(string.as_bytes())?;
+            written += string.len();
+        }
+    }
+    Ok(written)
+}
+
+/// Read metadata, without its header.
+fn read_metadata<R: Read>(inp: &mut R) -> Result<Metadata, std::io::Error> {
+    let read_string = |inp: &mut R| -> Result<String, std::io::Error> {
+        let byte_len = inp.read_varnum()?;
+        let mut buf = vec![0; byte_len as usize];
+        inp.read_exact(&mut buf)?;
+        String::from_utf8(buf)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    };
+    let number_of_entries = inp.read_varnum()?;
+    let mut entries = Vec::with_capacity(std::cmp::min(number_of_entries as usize, 16));
+    for _ in 0..number_of_entries {
+        let key = read_string(inp)?;
+        let value = read_string(inp)?;
+        entries.push((key, value));
+    }
+    Ok(Metadata {
+        entries,
+    })
+}
+
+/// Write a grammar identifier, without its header.
+fn write_grammar_id<W: Write>(out: &mut W, grammar: &GrammarId) -> Result<usize, std::io::Error> {
+    let mut written = out.write_varnum(grammar.name.len() as u32)?;
+    out.write_all(grammar.name.as_bytes())?;
+    written += grammar.name.len();
+    let fingerprint : Vec<u8> = (0..8)
+        .map(|i| (grammar.fingerprint >> (8 * i)) as u8)
+        .collect();
+    out.write_all(&fingerprint)?;
+    Ok(written + fingerprint.len())
+}
+
+/// Read a grammar identifier, without its header.
+fn read_grammar_id<R: Read>(inp: &mut R) -> Result<GrammarId, std::io::Error> {
+    let byte_len = inp.read_varnum()?;
+    let mut name = vec![0; byte_len as usize];
+    inp.read_exact(&mut name)?;
+    let name = String::from_utf8(name)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))?;
+    let mut fingerprint = [0; 8];
+    inp.read_exact(&mut fingerprint)?;
+    Ok(GrammarId {
+        name,
+        fingerprint: fingerprint.iter()
+            .enumerate()
+            .fold(0, |result, (i, byte)| result | (*byte as u64) << (8 * i)),
+        // Announced by the container flags.
+        extended: false,
+    })
+}
+
 /// A trait specifying whether a piece of data needs the addition of a length index.
 trait FormatInTable {
     const HAS_LENGTH_INDEX : bool;
@@ -139,8 +954,9 @@ impl FormatInTable for Option<SharedString> {
     const HAS_LENGTH_INDEX : bool = false;
 }
 
-pub use self::read::TreeTokenReader;
-pub use self::write::{ Statistics, TreeTokenWriter, Targets };
+pub use self::annotate::{ AnnotatedHex, Annotation, StructureNode, TreeAnnotations };
+pub use self::read::{ ArchiveEntry, ChunkIndex, DeferredSubtree, MissingChunk, Section, SECTION_NAMES, StringHandle, StringsTable, TreeSnapshot, TreeTokenReader };
+pub use self::write::{ SectionSizes, Statistics, TreeTokenWriter, Targets };
 
 /// Command-line management.
 pub struct FormatProvider;
@@ -153,16 +969,84 @@ impl ::FormatProvider for FormatProvider {
                 .help("(EXPERIMENTAL) Apply a secondary compression *inside* the file. Used only when compressing.")
                 .long("x-inner-compression")
                 .takes_value(true)
-                .possible_values(&["identity", "gzip", "deflate", "br", "lzw"])
+                .possible_values(&["identity", "gzip", "deflate", "br", "lzw", "auto"])
+            )
+            .arg(Arg::with_name("section-compression")
+                .help("Compression of the grammar, strings and tree sections. `auto` tries identity, gzip and br for each section and keeps the smallest result, which is recorded in the header of the section. Used only when compressing.")
+                .long("section-compression")
+                .takes_value(true)
+                .possible_values(&["identity", "gzip", "deflate", "br", "auto"])
+                .conflicts_with("x-inner-compression")
             )
             .arg(Arg::with_name("x-dump-sections")
                 .help("(EXPERIMENTAL) Export sections to individual files. Used only when compressing.")
                 .long("x-dump-sections")
             )
+            .arg(Arg::with_name("front-coding")
+                .help("Front-code the strings table, sharing prefixes between each string and the strings among the previous WINDOW entries of the table. Used only when compressing.")
+                .long("front-coding")
+                .takes_value(true)
+                .value_name("WINDOW")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+            )
+            .arg(Arg::with_name("blob-threshold")
+                .help("Write the strings of at least BYTES bytes, e.g. base64-encoded images, as blobs outside of the strings table, compressed independently. Used only when compressing.")
+                .long("blob-threshold")
+                .takes_value(true)
+                .value_name("BYTES")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+            )
+            .arg(Arg::with_name("blob-compression")
+                .help("Compression of the blobs, see --blob-threshold. Used only when compressing.")
+                .long("blob-compression")
+                .takes_value(true)
+                .possible_values(&["identity", "gzip", "deflate", "br"])
+                .default_value("identity")
+            )
+            .arg(Arg::with_name("string-dictionary")
+                .help("Compress the strings table with brotli, using the contents of FILE as custom dictionary, e.g. common fragments of identifiers. The SHA-256 hash of the dictionary is recorded in the header. Used both when compressing and decompressing, with the same dictionary.")
+                .long("string-dictionary")
+                .takes_value(true)
+                .value_name("FILE")
+            )
+            .arg(Arg::with_name("nan-policy")
+                .help("How NaN values are handled. `canonicalize` replaces all NaNs with the canonical NaN, `preserve` keeps their payload, `reject` refuses them. The policy is recorded in the header. Used only when compressing.")
+                .long("nan-policy")
+                .takes_value(true)
+                .possible_values(NaNPolicy::NAMES)
+                .default_value("canonicalize")
+            )
+            .arg(Arg::with_name("varfloats")
+                .help("Represent floats as varfloats, in which integers and short decimal numbers such as `0.5` take fewer bytes. Files cannot be read by decoders that only support container versions 1 and 2. Used only when compressing.")
+                .long("varfloats")
+            )
+            .arg(Arg::with_name("runs")
+                .help("Only write the tag of the first item of each run of list items with the same tag, e.g. in array literals of numbers. Used only when compressing.")
+                .long("runs")
+            )
+            .arg(Arg::with_name("split-prelude")
+                .help("Write the strings table after the tree rather than before. Used only when compressing.")
+                .long("split-prelude")
+            )
+            .arg(Arg::with_name("chunks")
+                .help("Compress the toplevel of the tree and the contents of each lazy function as independent chunks, listed in an index, so that clients may fetch the first functions with HTTP range requests and the rest later. Used only when compressing.")
+                .long("chunks")
+            )
+            .arg(Arg::with_name("checksum")
+                .help("Append a checksum section, so that readers may detect truncated or corrupted files. Used only when compressing.")
+                .long("checksum")
+            )
+            .arg(Arg::with_name("no-verify-checksum")
+                .help("Do not verify the checksum section, if any. Used only when decompressing.")
+                .long("no-verify-checksum")
+            )
     }
 
     fn handle_subcommand(&self, matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
-        use bytes::compress::Compression;
         use multipart::{ Statistics, Targets };
 
         use std::cell::RefCell;
@@ -170,16 +1054,57 @@ impl ::FormatProvider for FormatProvider {
         let stats = Rc::new(RefCell::new(Statistics::default()
             .with_source_bytes(0)));
         let compression = matches.map(|matches| {
-            Compression::parse(matches.value_of("x-inner-compression"))
-                .expect("Could not parse x-inner-compression")
+            let name = matches.value_of("section-compression")
+                .or_else(|| matches.value_of("x-inner-compression"));
+            Compression::parse(name)
+                .expect("Could not parse section compression")
         }).unwrap_or(Compression::Identity);
+        let string_dictionary = match matches.and_then(|matches| matches.value_of("string-dictionary")) {
+            Some(path) => Some(BrotliDictionary::from_file(path)?),
+            None => None
+        };
+        let options = matches.map(|matches| {
+            Options {
+                integrity: Integrity {
+                    write_checksum: matches.is_present("checksum"),
+                    verify_checksum: !matches.is_present("no-verify-checksum"),
+                    ..Integrity::default()
+                },
+                front_coding: matches.value_of("front-coding")
+                    .map(|window| window.parse()
+                        .unwrap()), // Checked by the validator.
+                blob_threshold: matches.value_of("blob-threshold")
+                    .map(|threshold| threshold.parse()
+                        .unwrap()), // Checked by the validator.
+                blob_compression: Compression::parse(matches.value_of("blob-compression"))
+                    .expect("Could not parse blob compression"),
+                nan_policy: matches.value_of("nan-policy")
+                    .and_then(NaNPolicy::parse)
+                    .unwrap_or_default(),
+                varfloats: matches.is_present("varfloats"),
+                runs: matches.is_present("runs"),
+                split_prelude: matches.is_present("split-prelude"),
+                chunks: matches.is_present("chunks"),
+                ..Options::default()
+            }
+        }).unwrap_or_default();
+        // The custom dictionary is only used by brotli, including among the candidates of `auto`.
+        let strings_compression = match (&string_dictionary, &compression) {
+            (&Some(_), &Compression::Auto) | (&None, _) => compression.clone(),
+            (&Some(_), _) => Compression::Brotli,
+        };
+        let options = Options {
+            string_dictionary,
+            ..options
+        };
         Ok(::Format::Multipart {
             targets: Targets {
-                strings_table: ::CompressionTarget::new(compression.clone()),
+                strings_table: ::CompressionTarget::new(strings_compression),
                 grammar_table: ::CompressionTarget::new(compression.clone()),
                 tree: ::CompressionTarget::new(compression.clone()),
             },
-            stats
+            stats,
+            options,
         })
     }
 }
@@ -191,7 +1116,7 @@ fn test_multipart_io() {
     extern crate env_logger;
     env_logger::init();
 
-    use binjs_shared::{ FieldName, InterfaceName, SharedString };
+    use binjs_shared::{ BigInt, FieldName, InterfaceName, RegExpFlags, SharedString };
     use binjs_shared::ast::Path;
 
     use ::CompressionTarget;
@@ -265,6 +1190,43 @@ fn test_multipart_io() {
             assert_eq!(escapes_string, data);
         }
 
+        println!("Testing BigInt I/O");
+
+        {
+            options.reset();
+            let data = BigInt::from_str("18446744073709551616");
+            let mut writer = TreeTokenWriter::new(options.clone());
+            writer.big_int(Some(&data))
+                .expect("Writing BigInt");
+
+            let output = writer.done()
+                .expect("Finalizing data");
+
+            let mut reader = TreeTokenReader::new(Cursor::new(&output)).unwrap();
+            let big_int = reader.big_int_at(&path)
+                .expect("Reading BigInt")
+                .expect("Non-null BigInt");
+            assert_eq!(big_int, data);
+        }
+
+        println!("Testing RegExp flags I/O");
+
+        {
+            options.reset();
+            let mut writer = TreeTokenWriter::new(options.clone());
+            writer.reg_exp_flags(Some(&RegExpFlags::from_str("ymg")))
+                .expect("Writing flags");
+
+            let output = writer.done()
+                .expect("Finalizing data");
+
+            let mut reader = TreeTokenReader::new(Cursor::new(&output)).unwrap();
+            let flags = reader.reg_exp_flags_at(&path)
+                .expect("Reading flags")
+                .expect("Non-null flags");
+            assert_eq!(flags, RegExpFlags::from_str("gmy"));
+        }
+
 
         println!("Testing tagged tuple I/O");
 
@@ -406,3 +1368,1448 @@ fn test_multipart_io() {
     }
 }
 
+
+#[test]
+fn test_multipart_archive() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    });
+    let shared = SharedString::from_str("shared string");
+    writer.string(Some(&shared))
+        .expect("Writing first entry");
+    writer.end_entry("first.js");
+
+    let item_0 = writer.string(Some(&shared)).unwrap();
+    let item_1 = writer.string(Some(&SharedString::from_str("second string"))).unwrap();
+    writer.list(vec![item_0, item_1])
+        .expect("Writing second entry");
+    writer.end_entry("dir/second.js");
+
+    let output = writer.done()
+        .expect("Finalizing data");
+
+    // Reading without an entry is an error.
+    match TreeTokenReader::new(Cursor::new(&output)) {
+        Err(TokenReaderError::IsArchive) => {},
+        _ => panic!("Expected an archive")
+    }
+
+    match TreeTokenReader::new_entry(Cursor::new(&output), "third.js", &Options::default()) {
+        Err(TokenReaderError::NoSuchEntry(_)) => {},
+        _ => panic!("Expected a missing entry")
+    }
+
+    let mut reader = TreeTokenReader::new_entry(Cursor::new(&output), "dir/second.js", &Options::default())
+        .expect("Creating reader for second entry");
+    let len = reader.enter_list_at(&path)
+        .expect("Reading list");
+    assert_eq!(len, 2);
+    let string = reader.string_at(&path)
+        .expect("Reading list[0]")
+        .expect("Non-null string");
+    assert_eq!(&string, "shared string");
+    let string = reader.string_at(&path)
+        .expect("Reading list[1]")
+        .expect("Non-null string");
+    assert_eq!(&string, "second string");
+
+    let mut reader = TreeTokenReader::new_entry(Cursor::new(&output), "first.js", &Options::default())
+        .expect("Creating reader for first entry");
+    let string = reader.string_at(&path)
+        .expect("Reading string")
+        .expect("Non-null string");
+    assert_eq!(&string, "shared string");
+}
+
+#[test]
+fn test_multipart_lazy_strings() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    });
+    writer.string(Some(&SharedString::from_str("unused string")))
+        .expect("Writing first entry");
+    writer.end_entry("first.js");
+
+    let item_0 = writer.string(Some(&SharedString::from_str("lazy string"))).unwrap();
+    let item_1 = writer.string(None).unwrap();
+    let item_2 = writer.string(Some(&SharedString::from_str("lazy string"))).unwrap();
+    writer.list(vec![item_0, item_1, item_2])
+        .expect("Writing second entry");
+    writer.end_entry("second.js");
+
+    let output = writer.done()
+        .expect("Finalizing data");
+
+    let mut reader = TreeTokenReader::new_entry_with_lazy_strings(Cursor::new(&output), "second.js", &Options::default())
+        .expect("Creating reader");
+    assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 3);
+    let first = reader.string_handle_at(&path)
+        .expect("Reading list[0]")
+        .expect("Non-null string");
+    assert_eq!(first.bytes(), b"lazy string");
+    assert!(reader.string_handle_at(&path).expect("Reading list[1]").is_none());
+
+    // Handles and eager reads share the same entries.
+    let second = reader.string_at(&path)
+        .expect("Reading list[2]")
+        .expect("Non-null string");
+    assert_eq!(&second, "lazy string");
+    assert_eq!(&first.resolve().expect("Resolving list[0]"), "lazy string");
+}
+
+#[test]
+fn test_multipart_section() {
+    use binjs_shared::SharedString;
+
+    use io::TokenWriterWithTree;
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    });
+    let item_0 = writer.string(Some(&SharedString::from_str("first string"))).unwrap();
+    let item_1 = writer.string(None).unwrap();
+    writer.list(vec![item_0, item_1])
+        .expect("Writing list");
+    let output = writer.done()
+        .expect("Finalizing data");
+
+    let strings = TreeTokenReader::section(Cursor::new(&output), &Options::default(), "strings")
+        .expect("Extracting strings");
+    assert_eq!(strings.name, "strings");
+    assert!(strings.raw.starts_with(HEADER_STRINGS_TABLE.as_bytes()));
+    assert!(strings.listing.contains("\"first string\""));
+    assert!(strings.listing.contains("null"));
+
+    let tree = TreeTokenReader::section(Cursor::new(&output), &Options::default(), "tree")
+        .expect("Extracting tree");
+    assert!(tree.raw.starts_with(HEADER_TREE.as_bytes()));
+    assert!(!tree.listing.is_empty());
+
+    // Only archives have a manifest.
+    match TreeTokenReader::section(Cursor::new(&output), &Options::default(), "manifest") {
+        Err(TokenReaderError::NoSuchSection(_)) => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Extracting a missing section should fail")
+    }
+}
+
+#[test]
+fn test_multipart_positions() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use positions::{ Location, Position, SourcePositions };
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let positions = SourcePositions {
+        locations: vec![
+            ("".to_string(), Location {
+                start: Position { line: 1, column: 0, offset: 0 },
+                end: Position { line: 1, column: 12, offset: 12 },
+            })
+        ],
+        comments: vec![],
+    };
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    })
+        .with_checksum(true)
+        .with_positions(Some(positions.clone()));
+    let item_0 = writer.string(Some(&SharedString::from_str("positioned"))).unwrap();
+    writer.list(vec![item_0])
+        .expect("Writing list");
+    let output = writer.done()
+        .expect("Finalizing data");
+
+    // Positions do not prevent reading the tree, and are covered by the checksum.
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    assert_eq!(reader.positions(), Some(&positions));
+    assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 1);
+    assert_eq!(&reader.string_at(&path).expect("Reading string").expect("Non-null string"), "positioned");
+
+    let section = TreeTokenReader::section(Cursor::new(&output), &Options::default(), "positions")
+        .expect("Extracting positions");
+    assert!(section.raw.starts_with(HEADER_POSITIONS.as_bytes()));
+    assert!(section.listing.contains("1:0-1:12"));
+}
+
+#[test]
+fn test_multipart_front_coding() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    let path = Path::new();
+    let strings = ["getElementById", "getElementsByTagName", "getElementsByClassName", "getAttribute", "getAttributeNode", "getAttributeNS", "foo"];
+    let write = |front_coding| {
+        let statistics = Rc::new(RefCell::new(Statistics::default()));
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        })
+            .with_front_coding(front_coding)
+            .with_statistics(Some(statistics.clone()));
+        let mut items : Vec<_> = strings.iter()
+            .map(|string| writer.string(Some(&SharedString::from_str(string))).unwrap())
+            .collect();
+        items.push(writer.string(None).unwrap());
+        writer.list(items)
+            .expect("Writing list");
+        let output = writer.done()
+            .expect("Finalizing data");
+        (output, statistics)
+    };
+
+    let (plain, _) = write(None);
+    let (front_coded, statistics) = write(Some(::bytes::frontcoding::DEFAULT_WINDOW));
+    assert!(front_coded.len() < plain.len());
+    let before_front_coding = statistics.borrow().strings_table.before_front_coding
+        .expect("Missing front coding statistics");
+    assert!(statistics.borrow().strings_table.compression.before_bytes < before_front_coding);
+
+    let mut reader = TreeTokenReader::new(Cursor::new(&front_coded))
+        .expect("Creating reader");
+    assert_eq!(reader.enter_list_at(&path).expect("Reading list"), strings.len() as u32 + 1);
+    for string in &strings {
+        assert_eq!(&reader.string_at(&path).expect("Reading string").expect("Non-null string"), *string);
+    }
+    assert_eq!(reader.string_at(&path).expect("Reading null string"), None);
+
+    let section = TreeTokenReader::section(Cursor::new(&front_coded), &Options::default(), "strings")
+        .expect("Extracting strings");
+    assert!(section.raw.starts_with(HEADER_STRINGS_TABLE_FRONT_CODED.as_bytes()));
+}
+
+#[test]
+fn test_multipart_blobs() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let blob = format!("data:image/png;base64,{}", "iVBORw0KGgo".repeat(100));
+    let strings = ["foo", blob.as_str(), "bar"];
+    let write = |front_coding, split_prelude, blob_threshold| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::new(Compression::Gzip),
+            tree: ::CompressionTarget::default(),
+        })
+            .with_front_coding(front_coding)
+            .with_split_prelude(split_prelude)
+            .with_blobs(blob_threshold, Compression::Identity);
+        let mut items : Vec<_> = strings.iter()
+            .map(|string| writer.string(Some(&SharedString::from_string(string.to_string()))).unwrap())
+            .collect();
+        items.push(writer.string(None).unwrap());
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    for &front_coding in &[None, Some(::bytes::frontcoding::DEFAULT_WINDOW)] {
+        for &split_prelude in &[false, true] {
+            for &blob_threshold in &[None, Some(blob.len() + 1), Some(blob.len())] {
+                let output = write(front_coding, split_prelude, blob_threshold);
+
+                // Blobs are stored uncompressed.
+                let stored = output.windows(blob.len())
+                    .any(|window| window == blob.as_bytes());
+                assert_eq!(stored, blob_threshold == Some(blob.len()));
+                let has_blobs = output.windows(HEADER_BLOBS.len())
+                    .any(|window| window == HEADER_BLOBS.as_bytes());
+                assert_eq!(has_blobs, stored);
+
+                let mut reader = TreeTokenReader::new(Cursor::new(&output))
+                    .expect("Creating reader");
+                assert_eq!(reader.enter_list_at(&path).expect("Reading list"), strings.len() as u32 + 1);
+                for string in &strings {
+                    assert_eq!(&reader.string_at(&path).expect("Reading string").expect("Non-null string"), *string);
+                }
+                assert_eq!(reader.string_at(&path).expect("Reading null string"), None);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_multipart_section_sizes() {
+    use binjs_shared::SharedString;
+
+    use io::TokenWriterWithTree;
+    use multipart::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let blob = "iVBORw0KGgo".repeat(100);
+    let statistics = Rc::new(RefCell::new(Statistics::default()));
+    let write = || {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::new(Compression::Gzip),
+            tree: ::CompressionTarget::default(),
+        })
+            .with_blobs(Some(blob.len()), Compression::Identity)
+            .with_checksum(true)
+            .with_statistics(Some(statistics.clone()));
+        let items = vec![
+            writer.string(Some(&SharedString::from_str("foo"))).unwrap(),
+            writer.string(Some(&SharedString::from_string(blob.clone()))).unwrap(),
+        ];
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let output = write();
+    {
+        let statistics = statistics.borrow();
+        let names : Vec<_> = statistics.per_section.iter()
+            .map(|section| section.name)
+            .collect();
+        assert_eq!(names, vec!["header", "grammar", "strings", "blobs", "tree", "checksum"]);
+
+        // Sections cover the file.
+        let compressed_bytes : usize = statistics.per_section.iter()
+            .map(|section| section.compressed_bytes)
+            .sum();
+        assert_eq!(compressed_bytes, output.len());
+
+        let strings = &statistics.per_section[2];
+        assert!(strings.algorithms.contains(&Compression::Gzip));
+        assert_eq!(strings.ratio(), strings.compressed_bytes as f64 / strings.uncompressed_bytes as f64);
+        let blobs = &statistics.per_section[3];
+        assert!(blobs.algorithms.contains(&Compression::Identity));
+        assert!(blobs.uncompressed_bytes > blob.len());
+        let header = &statistics.per_section[0];
+        assert!(header.algorithms.is_empty());
+        assert_eq!(header.uncompressed_bytes, header.compressed_bytes);
+    }
+
+    // Sizes are summed by section across files.
+    let first = statistics.borrow().per_section.clone();
+    write();
+    let statistics = statistics.borrow();
+    assert_eq!(statistics.per_section.len(), first.len());
+    for (total, section) in statistics.per_section.iter().zip(first.iter()) {
+        assert_eq!(total.name, section.name);
+        assert_eq!(total.compressed_bytes, 2 * section.compressed_bytes);
+        assert_eq!(total.uncompressed_bytes, 2 * section.uncompressed_bytes);
+    }
+}
+
+#[test]
+fn test_multipart_section_sizes_encrypted() {
+    use binjs_shared::SharedString;
+
+    use io::TokenWriterWithTree;
+    use multipart::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let statistics = Rc::new(RefCell::new(Statistics::default()));
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    })
+        .with_encryption_key(Some([42; 32]))
+        .with_statistics(Some(statistics.clone()));
+    writer.string(Some(&SharedString::from_str("Simple string")))
+        .expect("Writing simple string");
+    let output = writer.done()
+        .expect("Finalizing data");
+
+    let statistics = statistics.borrow();
+    for section in &statistics.per_section {
+        let description = format!("{}", section);
+        match section.name {
+            "grammar" | "strings" | "tree" => {
+                assert!(section.encrypted);
+                assert!(description.contains("bytes compressed before encryption"), "{}", description);
+            }
+            "encryption" => {
+                assert!(!section.encrypted);
+                assert!(description.contains("bytes of overhead"), "{}", description);
+            }
+            _ => {
+                assert!(!section.encrypted);
+                assert!(!description.contains("encryption"), "{}", description);
+            }
+        }
+    }
+
+    // The overhead and the sections before encryption still cover the file.
+    let compressed_bytes : usize = statistics.per_section.iter()
+        .map(|section| section.compressed_bytes)
+        .sum();
+    assert_eq!(compressed_bytes, output.len());
+}
+
+#[test]
+fn test_multipart_nan_policy() {
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use ::{ TokenReaderError, TokenWriterError };
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let signaling_nan = unsafe { std::mem::transmute::<u64, f64>(0x7FF0000000000002) };
+    let write = |nan_policy| -> Result<Box<[u8]>, TokenWriterError> {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_nan_policy(nan_policy);
+        writer.float(Some(signaling_nan))?;
+        writer.done()
+    };
+    let read = |data: &[u8], nan_policy| -> Result<u64, TokenReaderError> {
+        let options = Options {
+            nan_policy,
+            ..Options::default()
+        };
+        let mut reader = TreeTokenReader::with_options(Cursor::new(data), &options)?;
+        let value = reader.float_at(&path)?
+            .expect("Non-null float");
+        Ok(unsafe { std::mem::transmute::<f64, u64>(value) })
+    };
+
+    // Readers handle NaNs as announced by the file, whatever their own policy.
+    let preserved = write(NaNPolicy::Preserve)
+        .expect("Writing preserved NaN");
+    assert_eq!(TreeTokenReader::container_version(Cursor::new(&preserved[..])).expect("Reading container version").nan_policy,
+        NaNPolicy::Preserve);
+    for &nan_policy in &[NaNPolicy::Canonicalize, NaNPolicy::Preserve, NaNPolicy::Reject] {
+        assert_eq!(read(&preserved[..], nan_policy).ok(), Some(0x7FF0000000000002));
+    }
+
+    let canonicalized = write(NaNPolicy::Canonicalize)
+        .expect("Writing canonicalized NaN");
+    for &nan_policy in &[NaNPolicy::Canonicalize, NaNPolicy::Preserve, NaNPolicy::Reject] {
+        assert_eq!(read(&canonicalized[..], nan_policy).ok(), Some(0x7FF8000000000000));
+    }
+
+    assert!(write(NaNPolicy::Reject).is_err());
+}
+
+#[test]
+fn test_multipart_string_dictionary() {
+    use binjs_shared::ast::Path;
+    use binjs_shared::SharedString;
+
+    use bytes::compress::{ BrotliDictionary, Compression };
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let strings = ["getElementById", "addEventListener", "querySelectorAll"];
+    let dictionary = BrotliDictionary::new(b"getElementById getElementsByTagName addEventListener querySelectorAll".to_vec());
+    let other = BrotliDictionary::new(b"prototype constructor hasOwnProperty".to_vec());
+    let write = |string_dictionary: Option<BrotliDictionary>| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::new(Compression::Brotli),
+            tree: ::CompressionTarget::default(),
+        }).with_string_dictionary(string_dictionary);
+        let items : Vec<_> = strings.iter()
+            .map(|string| writer.string(Some(&SharedString::from_str(*string))).expect("Writing string"))
+            .collect();
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+    let read = |data: &[u8], string_dictionary: Option<BrotliDictionary>| -> Result<Vec<String>, TokenReaderError> {
+        let options = Options {
+            string_dictionary,
+            ..Options::default()
+        };
+        let mut reader = TreeTokenReader::with_options(Cursor::new(data), &options)?;
+        let len = reader.enter_list_at(&path)?;
+        let mut result = vec![];
+        for _ in 0..len {
+            result.push(reader.string_at(&path)?
+                .expect("Non-null string")
+                .as_str()
+                .to_string());
+        }
+        Ok(result)
+    };
+
+    let plain = write(None);
+    let compressed = write(Some(dictionary.clone()));
+    // With only three strings, the header identifying the dictionary costs more than it
+    // saves, but the strings table itself must shrink.
+    let header = HEADER_STRING_DICTIONARY.len() + dictionary.hash().len();
+    assert!(compressed.len() < plain.len() + header);
+
+    assert_eq!(read(&compressed, Some(dictionary.clone())).expect("Reading with dictionary"), strings);
+    match read(&compressed, None) {
+        Err(TokenReaderError::UnknownStringDictionary(hash)) => assert_eq!(&hash, dictionary.hash()),
+        _ => panic!("The dictionary is required")
+    }
+    match read(&compressed, Some(other)) {
+        Err(TokenReaderError::UnknownStringDictionary(_)) => {},
+        _ => panic!("The dictionary must match")
+    }
+
+    // Files without a dictionary are read as usual.
+    assert_eq!(read(&plain, Some(dictionary)).expect("Reading without dictionary"), strings);
+}
+
+#[test]
+fn test_multipart_varfloats() {
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let floats = [Some(0.), Some(-3.), Some(0.5), Some(0.25), Some(1e-3), Some(1. / 3.), Some(-0.), None];
+    let write = |varfloats| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_varfloats(varfloats);
+        let items : Vec<_> = floats.iter()
+            .map(|value| writer.float(*value).expect("Writing float"))
+            .collect();
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let plain = write(false);
+    let varfloats = write(true);
+    assert!(varfloats.len() < plain.len());
+    let version = |data: &[u8]| TreeTokenReader::container_version(Cursor::new(data))
+        .expect("Reading container version");
+    assert_eq!(version(&plain), ContainerVersion::current(false, false, false, false, NaNPolicy::Canonicalize));
+    assert_eq!(version(&varfloats), ContainerVersion::current(false, true, false, false, NaNPolicy::Canonicalize));
+
+    for data in &[plain, varfloats] {
+        let mut reader = TreeTokenReader::new(Cursor::new(data))
+            .expect("Creating reader");
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), floats.len() as u32);
+        for value in &floats {
+            let decoded = reader.float_at(&path)
+                .expect("Reading float");
+            assert_eq!(decoded.map(|f| unsafe { std::mem::transmute::<f64, u64>(f) }),
+                value.map(|f| unsafe { std::mem::transmute::<f64, u64>(f) }));
+        }
+    }
+}
+
+#[test]
+fn test_multipart_legacy_versions() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use bytes::varnum::WriteVarNum;
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let write = |varfloats| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_varfloats(varfloats);
+        let item_0 = writer.string(Some(&SharedString::from_str("legacy"))).unwrap();
+        let item_1 = writer.float(Some(0.5)).unwrap();
+        writer.list(vec![item_0, item_1])
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    // Replace the container version number and flags, each one byte long, with `header`.
+    let with_header = |data: &[u8], header: &[u32]| {
+        let mut result = data[..5].to_vec();
+        for number in header {
+            result.write_varnum(*number).unwrap();
+        }
+        result.extend_from_slice(&data[7..]);
+        result
+    };
+
+    for &(varfloats, legacy) in &[(false, LEGACY_FORMAT_VERSION), (true, VARFLOAT_FORMAT_VERSION)] {
+        let data = with_header(&write(varfloats), &[legacy]);
+        let version = TreeTokenReader::container_version(Cursor::new(&data))
+            .expect("Reading container version");
+        assert_eq!(version, ContainerVersion { number: legacy, is_archive: false, varfloats, extended: false, checksum: false, nan_policy: NaNPolicy::Canonicalize });
+        assert!(!version.is_current());
+
+        let mut reader = TreeTokenReader::new(Cursor::new(&data))
+            .expect("Creating reader");
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 2);
+        assert_eq!(reader.string_at(&path).expect("Reading string"), Some(SharedString::from_str("legacy")));
+        assert_eq!(reader.float_at(&path).expect("Reading float"), Some(0.5));
+    }
+
+    // Files written with container flags but without metadata are read as the current version.
+    let data = with_header(&write(true), &[FLAGS_FORMAT_VERSION, FLAG_VARFLOATS]);
+    let version = TreeTokenReader::container_version(Cursor::new(&data))
+        .expect("Reading container version");
+    assert_eq!(version, ContainerVersion { number: FLAGS_FORMAT_VERSION, is_archive: false, varfloats: true, extended: false, checksum: false, nan_policy: NaNPolicy::Canonicalize });
+    assert!(!version.is_current());
+    let mut reader = TreeTokenReader::new(Cursor::new(&data))
+        .expect("Creating reader");
+    assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 2);
+    assert_eq!(reader.string_at(&path).expect("Reading string"), Some(SharedString::from_str("legacy")));
+    assert_eq!(reader.float_at(&path).expect("Reading float"), Some(0.5));
+
+    // Unknown versions and flags are rejected.
+    for header in &[vec![FORMAT_VERSION + 1, 0], vec![FORMAT_VERSION, FLAG_NAN_REJECT << 1], vec![FORMAT_VERSION, FLAG_NAN_PRESERVE | FLAG_NAN_REJECT]] {
+        match TreeTokenReader::new(Cursor::new(with_header(&write(false), header))) {
+            Err(TokenReaderError::BadHeader) => {},
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Reading an unknown container version should fail")
+        }
+    }
+}
+
+#[test]
+fn test_multipart_runs() {
+    use binjs_shared::{ FieldName, InterfaceName };
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    // A long run, a lonely item, a run too short to be collapsed, with a nested list.
+    let tags : Vec<&str> = std::iter::repeat("A").take(20)
+        .chain(vec!["B", "A", "A"])
+        .collect();
+    let write = |runs| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_runs(runs);
+        let mut items = vec![];
+        for tag in &tags {
+            let inner = writer.tagged_tuple(&InterfaceName::from_str("C"), &[])
+                .expect("Writing inner tagged tuple");
+            let list = writer.list(vec![inner])
+                .expect("Writing inner list");
+            items.push(writer.tagged_tuple(&InterfaceName::from_str(tag), &[(&FieldName::from_str("list"), list)])
+                .expect("Writing tagged tuple"));
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    let plain = write(false);
+    let runs = write(true);
+    assert!(runs.len() < plain.len());
+
+    for data in &[plain, runs] {
+        let mut reader = TreeTokenReader::new(Cursor::new(data))
+            .expect("Creating reader");
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), tags.len() as u32);
+        for tag in &tags {
+            let (name, _) = reader.enter_tagged_tuple_at(&path)
+                .expect("Reading tagged tuple");
+            assert_eq!(&name, &InterfaceName::from_str(tag));
+            assert_eq!(reader.enter_list_at(&path).expect("Reading inner list"), 1);
+            let (name, _) = reader.enter_tagged_tuple_at(&path)
+                .expect("Reading inner tagged tuple");
+            assert_eq!(&name, &InterfaceName::from_str("C"));
+            reader.exit_tagged_tuple_at(&path)
+                .expect("Exiting inner tagged tuple");
+            reader.exit_list_at(&path)
+                .expect("Exiting inner list");
+            reader.exit_tagged_tuple_at(&path)
+                .expect("Exiting tagged tuple");
+        }
+        reader.exit_list_at(&path)
+            .expect("Exiting list");
+    }
+
+    let section = TreeTokenReader::section(Cursor::new(&write(true)), &Options::default(), "tree")
+        .expect("Extracting tree");
+    assert!(section.raw.starts_with(HEADER_TREE_RUNS.as_bytes()));
+}
+
+#[test]
+fn test_multipart_split_prelude() {
+    use binjs_shared::{ FieldName, InterfaceName, SharedString };
+    use binjs_shared::ast::Path;
+
+    use ::TokenReaderError;
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::cell::Cell;
+    use std::io::{ Cursor, Read };
+    use std::rc::Rc;
+
+    /// A source delivering a few bytes at a time.
+    struct Trickle {
+        data: Vec<u8>,
+        delivered: Rc<Cell<usize>>,
+    }
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+            let start = self.delivered.get();
+            let len = std::cmp::min(std::cmp::min(buf.len(), 16), self.data.len() - start);
+            buf[..len].copy_from_slice(&self.data[start..start + len]);
+            self.delivered.set(start + len);
+            Ok(len)
+        }
+    }
+
+    let path = Path::new();
+    let strings : Vec<String> = (0..100)
+        .map(|i| format!("string number {}", i))
+        .collect();
+    let write = |split_prelude| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_split_prelude(split_prelude)
+            .with_checksum(true);
+        let mut items = vec![];
+        for string in &strings {
+            let item = writer.string(Some(&SharedString::from_string(string.clone())))
+                .expect("Writing string");
+            items.push(writer.tagged_tuple(&InterfaceName::from_str("A"), &[(&FieldName::from_str("value"), item)])
+                .expect("Writing tagged tuple"));
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    for split_prelude in &[false, true] {
+        let data = write(*split_prelude);
+        let delivered = Rc::new(Cell::new(0));
+        let mut reader = TreeTokenReader::with_deferred_strings(Trickle { data: data.to_vec(), delivered: delivered.clone() }, &Options::default())
+            .expect("Creating reader");
+
+        // The tree is decoded as it is received. With a split prelude, the first string
+        // waits for the rest of the tree, then strings are resolved as the strings table
+        // is received.
+        let created = delivered.get();
+        assert!(created < data.len());
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), strings.len() as u32);
+        let mut previous = created;
+        let mut partial = false;
+        for string in &strings {
+            reader.enter_tagged_tuple_at(&path)
+                .expect("Reading tagged tuple");
+            assert_eq!(reader.string_at(&path).expect("Reading string"), Some(SharedString::from_string(string.clone())));
+            assert!(delivered.get() >= previous);
+            partial |= delivered.get() < data.len();
+            previous = delivered.get();
+            reader.exit_tagged_tuple_at(&path)
+                .expect("Exiting tagged tuple");
+        }
+        assert!(partial);
+        if *split_prelude {
+            assert!(created < previous);
+        }
+        reader.exit_list_at(&path)
+            .expect("Exiting list");
+        reader.receive_strings()
+            .expect("Receiving the rest of the file");
+        assert_eq!(delivered.get(), data.len());
+
+        // A truncated file is detected by the first read past the end of what was received.
+        let tree = TreeTokenReader::section(Cursor::new(&data), &Options::default(), "tree")
+            .expect("Extracting tree");
+        let tree_start = data.windows(tree.raw.len())
+            .position(|window| window == &tree.raw[..])
+            .expect("Finding tree");
+        let truncated = data[..tree_start + tree.raw.len() / 2].to_vec();
+        let mut reader = TreeTokenReader::with_deferred_strings(Trickle { data: truncated, delivered: Rc::new(Cell::new(0)) }, &Options::default())
+            .expect("Creating reader");
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), strings.len() as u32);
+        reader.enter_tagged_tuple_at(&path)
+            .expect("Reading tagged tuple");
+        let result = (|| -> Result<(), TokenReaderError> {
+            for _ in &strings {
+                reader.string_at(&path)?;
+                reader.exit_tagged_tuple_at(&path)?;
+                reader.enter_tagged_tuple_at(&path)?;
+            }
+            Ok(())
+        })();
+        match result {
+            Err(TokenReaderError::ReadError(_)) => {},
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Reading a truncated tree should fail")
+        }
+
+        // Other readers detect the layout.
+        let mut reader = TreeTokenReader::new(Cursor::new(&data))
+            .expect("Creating reader");
+        reader.enter_list_at(&path)
+            .expect("Reading list");
+        reader.enter_tagged_tuple_at(&path)
+            .expect("Reading tagged tuple");
+        assert_eq!(reader.string_at(&path).expect("Reading string"), Some(SharedString::from_str("string number 0")));
+    }
+
+    // The strings table is still verified once it is read. Corrupt the last string,
+    // just before the checksum section of 3 sections.
+    let mut data = write(true).to_vec();
+    let len = data.len();
+    data[len - "[CHECKSUM]".len() - 1 - 4 * 4 - 1] ^= 1;
+    let mut reader = TreeTokenReader::with_deferred_strings(Cursor::new(data), &Options::default())
+        .expect("Creating reader");
+    match reader.receive_strings() {
+        Err(TokenReaderError::BadChecksum(ref name)) if name == "strings" => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Reading a corrupted strings table should fail")
+    }
+}
+
+#[test]
+fn test_multipart_chunks() {
+    use binjs_shared::{ FieldName, InterfaceName, SharedString };
+
+    use ::TokenReaderError;
+    use io::{ Path, TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let function = InterfaceName::from_str("Function");
+    let body = InterfaceName::from_str("Body");
+    let name_field = FieldName::from_str("name");
+    let skip_field = FieldName::from_str("body_skip");
+    let body_field = FieldName::from_str("body");
+    let value_field = FieldName::from_str("value");
+
+    let write = |chunks| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_chunks(chunks)
+            .with_checksum(true);
+        let mut items = vec![];
+        for i in 0..3 {
+            let name = writer.string(Some(&SharedString::from_string(format!("f{}", i))))
+                .expect("Writing string");
+            let skip = writer.offset()
+                .expect("Writing offset");
+            let value = writer.string(Some(&SharedString::from_string(format!("body of f{}", i))))
+                .expect("Writing string");
+            let contents = writer.tagged_tuple(&body, &[(&value_field, value)])
+                .expect("Writing tagged tuple");
+            items.push(writer.tagged_tuple(&function, &[(&name_field, name), (&skip_field, skip), (&body_field, contents)])
+                .expect("Writing tagged tuple"));
+        }
+        writer.list(items)
+            .expect("Writing list");
+        writer.done()
+            .expect("Finalizing data")
+    };
+
+    // Read the contents of a lazy field, unless skipped.
+    let read_body = |reader: &mut TreeTokenReader, path: &Path, i: usize| {
+        reader.enter_tagged_tuple_at(path)
+            .expect("Reading tagged tuple");
+        assert_eq!(reader.string_at(path).expect("Reading string"), Some(SharedString::from_string(format!("body of f{}", i))));
+        reader.exit_tagged_tuple_at(path)
+            .expect("Exiting tagged tuple");
+    };
+    // Read the functions, returning whether the contents of each of them were skipped.
+    let read_functions = |reader: &mut TreeTokenReader| {
+        let mut path = Path::new();
+        let mut skipped = vec![];
+        assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 3);
+        for i in 0..3 {
+            reader.enter_tagged_tuple_at(&path)
+                .expect("Reading tagged tuple");
+            assert_eq!(reader.string_at(&path).expect("Reading string"), Some(SharedString::from_string(format!("f{}", i))));
+            let byte_len = reader.offset_at(&path)
+                .expect("Reading offset");
+            path.enter_interface(function.clone());
+            path.enter_field((2, body_field.clone()));
+            let skip = reader.skip_lazy_at(byte_len, &path)
+                .expect("Skipping contents");
+            if !skip {
+                read_body(&mut *reader, &path, i);
+            }
+            path.exit_field((2, body_field.clone()));
+            path.exit_interface(function.clone());
+            reader.exit_tagged_tuple_at(&path)
+                .expect("Exiting tagged tuple");
+            skipped.push(skip);
+        }
+        reader.exit_list_at(&path)
+            .expect("Exiting list");
+        skipped
+    };
+
+    // Files that are not chunked cannot be read by chunks.
+    let data = write(false);
+    assert_eq!(TreeTokenReader::chunk_index(&data).expect("Reading chunk index"), None);
+
+    // Chunked files are read entirely as any other file.
+    let data = write(true);
+    let mut reader = TreeTokenReader::new(Cursor::new(&data))
+        .expect("Creating reader");
+    assert_eq!(read_functions(&mut reader), vec![false, false, false]);
+
+    // The toplevel chunk and one chunk per function, one after the other.
+    let index = TreeTokenReader::chunk_index(&data)
+        .expect("Reading chunk index")
+        .expect("The file is chunked");
+    assert_eq!(index.chunks.len(), 4);
+    for (chunk, next) in index.chunks.iter().zip(index.chunks.iter().skip(1)) {
+        assert_eq!(chunk.end, next.start);
+    }
+    assert!(TreeTokenReader::chunk_index(&data[..index.chunks[0].start as usize - 1]).is_err());
+
+    // Fetch the toplevel chunk and the first function, then the rest.
+    let prefix = &data[..index.chunks[1].end as usize];
+    let mut reader = TreeTokenReader::with_chunks(prefix, &Options::default())
+        .expect("Creating reader");
+    assert_eq!(read_functions(&mut reader), vec![false, true, true]);
+    let missing = reader.missing_chunks();
+    assert_eq!(missing.iter().map(|missing| missing.chunk).collect::<Vec<_>>(), vec![2, 3]);
+
+    // Invalid chunks are rejected, without poisoning the reader.
+    let range = index.chunks[2].clone();
+    match reader.receive_chunk(&missing[0], &data[range.start as usize..range.end as usize - 1]) {
+        Err(TokenReaderError::BadCompression(_)) | Err(TokenReaderError::BadLength { .. }) => {},
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Receiving a truncated chunk should fail")
+    }
+
+    let path = Path::new();
+    for (i, missing) in missing.iter().enumerate() {
+        assert_eq!(missing.interface, function);
+        let range = index.chunks[missing.chunk].clone();
+        let subtree = reader.receive_chunk(missing, &data[range.start as usize..range.end as usize])
+            .expect("Receiving chunk");
+        reader.seek_to(subtree.offset)
+            .expect("Seeking to contents");
+        read_body(&mut reader, &path, i + 1);
+        assert_eq!(reader.position().expect("Reading position"), subtree.offset + subtree.byte_len as u64);
+    }
+
+    // Reading by chunks requires the toplevel chunk.
+    assert!(TreeTokenReader::with_chunks(&data[..index.chunks[0].end as usize - 1], &Options::default()).is_err());
+}
+
+#[test]
+fn test_multipart_annotated_hex() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    });
+    let item_0 = writer.string(Some(&SharedString::from_str("annotated"))).unwrap();
+    let item_1 = writer.float(Some(1.5)).unwrap();
+    writer.list(vec![item_0, item_1])
+        .expect("Writing list");
+    let output = writer.done()
+        .expect("Finalizing data");
+
+    let (dump, mut reader) = AnnotatedHex::new(Cursor::new(&output), &Options::default())
+        .expect("Creating reader");
+    assert_eq!(reader.enter_list_at(&path).expect("Reading list"), 2);
+    reader.string_at(&path).expect("Reading string");
+    reader.float_at(&path).expect("Reading float");
+
+    let mut printed = vec![];
+    dump.print(&mut printed)
+        .expect("Printing dump");
+    let printed = String::from_utf8(printed)
+        .expect("Dump is not UTF-8");
+    assert!(printed.starts_with("00000000: 42 49 4e 4a 53"));
+    assert!(printed.contains("# magic header \"BINJS\""));
+    assert!(printed.contains("# string #0=\"annotated\""));
+    assert!(printed.contains("# string=\"annotated\""));
+    assert!(printed.contains("# float=1.5"));
+}
+
+#[test]
+fn test_multipart_structure() {
+    use multipart::*;
+
+    let annotation = |start, end, label: &str| Annotation {
+        start,
+        end,
+        label: label.to_string(),
+    };
+    let tree = TreeAnnotations {
+        data: vec![0; 8],
+        annotations: vec![
+            annotation(0, 1, "Script {"),
+            annotation(1, 1, ".directives"),
+            annotation(1, 2, "list (length=0) []"),
+            annotation(2, 2, ".statements"),
+            annotation(2, 3, "list (length=1) ["),
+            annotation(3, 4, "ExpressionStatement {"),
+            annotation(4, 4, ".expression"),
+            annotation(4, 5, "IdentifierExpression {"),
+            annotation(5, 5, ".name"),
+            annotation(5, 7, "string=\"foo\""),
+            annotation(7, 7, "}"),
+            annotation(7, 7, "}"),
+            annotation(7, 7, "]"),
+            annotation(7, 8, "}"),
+        ],
+    };
+    let structure = tree.structure();
+    assert_eq!(structure.len(), 1);
+    let script = &structure[0];
+    assert_eq!(script.label, "Script");
+    assert_eq!(script.field, None);
+    assert_eq!((script.start, script.end), (0, 8));
+    assert_eq!(script.own_byte_len(), 2);
+    assert_eq!(script.children.len(), 2);
+    assert_eq!(script.children[0].label, "list (length=0) []");
+    assert_eq!(script.children[0].field, Some("directives".to_string()));
+
+    let statements = &script.children[1];
+    assert_eq!(statements.label, "list (length=1)");
+    assert_eq!((statements.start, statements.end), (2, 7));
+    let identifier = &statements.children[0].children[0];
+    assert_eq!(identifier.label, "IdentifierExpression");
+    assert_eq!(identifier.field, Some("expression".to_string()));
+    assert_eq!(identifier.children[0].field, Some("name".to_string()));
+    assert_eq!(identifier.children[0].byte_len(), 2);
+    assert_eq!(script.descendants().len(), 6);
+}
+
+#[test]
+fn test_multipart_grammar_id() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let grammar = GrammarId::new("test", 0x0123456789abcdef);
+    let extended = GrammarId::new("test+vendor", 0x0123456789abcdef)
+        .with_extended(true);
+    for declared in &[None, Some(grammar), Some(extended)] {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_grammar(declared.clone());
+        writer.string(Some(&SharedString::from_str("grammar")))
+            .expect("Writing string");
+        let output = writer.done()
+            .expect("Finalizing data");
+
+        let version = TreeTokenReader::container_version(Cursor::new(&output))
+            .expect("Reading container version");
+        assert_eq!(version.extended, declared.as_ref().map_or(false, |grammar| grammar.extended));
+
+        let mut reader = TreeTokenReader::new(Cursor::new(&output))
+            .expect("Creating reader");
+        assert_eq!(reader.grammar(), declared.as_ref());
+        assert_eq!(&reader.string_at(&path).expect("Reading string").expect("Non-null string"), "grammar");
+    }
+}
+
+#[test]
+fn test_multipart_metadata() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let metadata = Metadata::new()
+        .with(Metadata::ENCODER, "test 1.0")
+        .with(Metadata::TIMESTAMP, "0")
+        .with(Metadata::ENCODER, "test 2.0");
+    assert_eq!(metadata.get(Metadata::ENCODER), Some("test 2.0"));
+    assert_eq!(metadata.entries().len(), 2);
+    assert_eq!(format!("{}", metadata), "encoder: test 2.0\ntimestamp: 0\n");
+
+    for declared in &[None, Some(metadata)] {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_grammar(Some(GrammarId::new("test", 0)))
+            .with_metadata(declared.clone())
+            .with_checksum(true);
+        writer.string(Some(&SharedString::from_str("metadata")))
+            .expect("Writing string");
+        let output = writer.done()
+            .expect("Finalizing data");
+
+        let found = TreeTokenReader::metadata(Cursor::new(&output))
+            .expect("Reading metadata");
+        assert_eq!(&found, declared);
+
+        // Decoders skip the metadata.
+        let mut reader = TreeTokenReader::new(Cursor::new(&output))
+            .expect("Creating reader");
+        assert_eq!(&reader.string_at(&path).expect("Reading string").expect("Non-null string"), "metadata");
+    }
+}
+
+#[test]
+fn test_multipart_profile() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use startup::StartupProfile;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let profile = StartupProfile::parse("1\n4\n")
+        .expect("Parsing profile");
+    let metadata = Metadata::new()
+        .with(Metadata::ENCODER, "test 1.0");
+
+    for &(ref declared, ref metadata) in &[(None, None), (Some(profile.clone()), None), (Some(profile), Some(metadata))] {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_profile(declared.clone())
+            .with_metadata(metadata.clone())
+            .with_checksum(true);
+        writer.string(Some(&SharedString::from_str("profile")))
+            .expect("Writing string");
+        let output = writer.done()
+            .expect("Finalizing data");
+
+        let found = TreeTokenReader::profile(Cursor::new(&output))
+            .expect("Reading profile");
+        assert_eq!(&found, declared);
+        let found = TreeTokenReader::metadata(Cursor::new(&output))
+            .expect("Reading metadata");
+        assert_eq!(&found, metadata);
+
+        // Decoders skip the profile.
+        let mut reader = TreeTokenReader::new(Cursor::new(&output))
+            .expect("Creating reader");
+        assert_eq!(&reader.string_at(&path).expect("Reading string").expect("Non-null string"), "profile");
+    }
+}
+
+#[test]
+fn test_multipart_checksum() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let path = Path::new();
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    }).with_checksum(true);
+    writer.string(Some(&SharedString::from_str("simple string")))
+        .expect("Writing simple string");
+    let output = writer.done()
+        .expect("Finalizing data")
+        .into_vec();
+
+    let mut reader = TreeTokenReader::new(Cursor::new(&output))
+        .expect("Creating reader");
+    let simple_string = reader.string_at(&path)
+        .expect("Reading simple string")
+        .expect("Non-null string");
+    assert_eq!(&simple_string, "simple string");
+
+    // Corrupt the strings table.
+    let position = output.windows(6)
+        .position(|window| window == b"simple")
+        .expect("Could not find string");
+    let mut corrupted = output.clone();
+    corrupted[position] = b'S';
+    match TreeTokenReader::new(Cursor::new(&corrupted)) {
+        Err(TokenReaderError::BadChecksum(ref section)) if section == "strings" => {},
+        _ => panic!("Expected a bad checksum")
+    }
+
+    // Opting out of verification.
+    let integrity = Integrity {
+        verify_checksum: false,
+        ..Integrity::default()
+    };
+    let mut reader = TreeTokenReader::with_integrity(Cursor::new(&corrupted), &integrity)
+        .expect("Creating reader without verification");
+    let simple_string = reader.string_at(&path)
+        .expect("Reading simple string")
+        .expect("Non-null string");
+    assert_eq!(&simple_string, "Simple string");
+
+    // Truncate the checksum, or drop it entirely.
+    let checksum_start = output.windows(HEADER_CHECKSUM.len())
+        .rposition(|window| window == HEADER_CHECKSUM.as_bytes())
+        .expect("Could not find checksum section");
+    for &len in &[output.len() - 1, checksum_start + 3, checksum_start] {
+        match TreeTokenReader::new(Cursor::new(&output[..len])) {
+            Err(TokenReaderError::BadChecksum(ref section)) if section == "checksum" => {},
+            Err(err) => panic!("Unexpected error {:?}", err),
+            Ok(_) => panic!("Reading a file without its checksum section should fail")
+        }
+    }
+}
+
+#[test]
+fn test_multipart_signature() {
+    use binjs_shared::SharedString;
+
+    use bytes::signature;
+    use io::TokenWriterWithTree;
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let secret = signature::parse_key("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+        .expect("Could not parse key");
+    let public = signature::public_key(&secret)
+        .expect("Could not compute public key");
+
+    let write = |sign_key: Option<[u8; 32]>| {
+        let mut writer = TreeTokenWriter::new(Targets {
+            grammar_table: ::CompressionTarget::default(),
+            strings_table: ::CompressionTarget::default(),
+            tree: ::CompressionTarget::default(),
+        }).with_checksum(true)
+          .with_sign_key(sign_key);
+        writer.string(Some(&SharedString::from_str("simple string")))
+            .expect("Writing simple string");
+        writer.done()
+            .expect("Finalizing data")
+    };
+    let signed = write(Some(secret));
+    let unsigned = write(None);
+
+    let integrity = Integrity {
+        verify_key: Some(public),
+        ..Integrity::default()
+    };
+    TreeTokenReader::with_integrity(Cursor::new(&signed), &integrity)
+        .expect("Signature should be valid");
+
+    // Readers that are not given a key ignore the signature.
+    TreeTokenReader::new(Cursor::new(&signed))
+        .expect("Signature should be ignored");
+
+    match TreeTokenReader::with_integrity(Cursor::new(&unsigned), &integrity) {
+        Err(TokenReaderError::BadSignature) => {},
+        _ => panic!("Expected a missing signature")
+    }
+
+    let integrity = Integrity {
+        verify_key: Some(secret), // Not the public key.
+        ..Integrity::default()
+    };
+    match TreeTokenReader::with_integrity(Cursor::new(&signed), &integrity) {
+        Err(TokenReaderError::BadSignature) => {},
+        _ => panic!("Expected a bad signature")
+    }
+
+    // The container flags are signed, too. Flipping `FLAG_VARFLOATS` leaves the file
+    // readable, as it has no floats, but invalidates the signature. Both the version
+    // and the flags are single-byte varnums, i.e. shifted by one bit.
+    let mut tampered = signed.clone();
+    assert_eq!(tampered[5], (FORMAT_VERSION as u8) << 1);
+    tampered[6] ^= (FLAG_VARFLOATS as u8) << 1;
+    let integrity = Integrity {
+        verify_checksum: false,
+        ..Integrity::default()
+    };
+    TreeTokenReader::with_integrity(Cursor::new(&tampered), &integrity)
+        .expect("Tampered file should be readable without a key");
+    let integrity = Integrity {
+        verify_key: Some(public),
+        ..integrity
+    };
+    match TreeTokenReader::with_integrity(Cursor::new(&tampered), &integrity) {
+        Err(TokenReaderError::BadSignature) => {},
+        _ => panic!("Expected a bad signature")
+    }
+}
+
+#[test]
+fn test_multipart_encryption() {
+    use binjs_shared::SharedString;
+    use binjs_shared::ast::Path;
+
+    use bytes::signature;
+    use io::{ TokenReader, TokenWriterWithTree };
+    use multipart::*;
+    use ::TokenReaderError;
+
+    use std::io::Cursor;
+
+    let key = [42; 32];
+    let secret = signature::parse_key("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60")
+        .expect("Could not parse key");
+    let public = signature::public_key(&secret)
+        .expect("Could not compute public key");
+
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    }).with_checksum(true)
+      .with_sign_key(Some(secret))
+      .with_encryption_key(Some(key));
+    writer.string(Some(&SharedString::from_str("Simple string")))
+        .expect("Writing simple string");
+    let output = writer.done()
+        .expect("Finalizing data");
+
+    // The strings table is not readable without the key.
+    assert!(!output.windows("Simple string".len()).any(|window| window == b"Simple string"));
+
+    // Nor are the checksums of the sections those of the decrypted sections.
+    let section_checksums = |data: &[u8]| {
+        let start = data.windows(HEADER_CHECKSUM.len())
+            .rposition(|window| window == HEADER_CHECKSUM.as_bytes())
+            .expect("Could not find checksum section");
+        // Skip the number of sections and the checksum of the file.
+        data[start + HEADER_CHECKSUM.len() + 1..data.len() - 4].to_vec()
+    };
+    let mut writer = TreeTokenWriter::new(Targets {
+        grammar_table: ::CompressionTarget::default(),
+        strings_table: ::CompressionTarget::default(),
+        tree: ::CompressionTarget::default(),
+    }).with_checksum(true);
+    writer.string(Some(&SharedString::from_str("Simple string")))
+        .expect("Writing simple string");
+    let plain = writer.done()
+        .expect("Finalizing data");
+    let encrypted_checksums = section_checksums(&output);
+    let plain_checksums = section_checksums(&plain);
+    assert_eq!(encrypted_checksums.len(), plain_checksums.len());
+    for (encrypted, plain) in encrypted_checksums.chunks(4).zip(plain_checksums.chunks(4)) {
+        assert_ne!(encrypted, plain);
+    }
+
+    let integrity = Integrity {
+        verify_key: Some(public),
+        encryption_key: Some(key),
+        ..Integrity::default()
+    };
+    let mut reader = TreeTokenReader::with_integrity(Cursor::new(&output), &integrity)
+        .expect("Creating reader");
+    let path = Path::new();
+    let simple_string = reader.string_at(&path)
+        .expect("Reading simple string")
+        .expect("Non-null string");
+    assert_eq!(&simple_string, "Simple string");
+
+    match TreeTokenReader::new(Cursor::new(&output)) {
+        Err(TokenReaderError::BadEncryption) => {},
+        _ => panic!("Expected a missing key")
+    }
+
+    let integrity = Integrity {
+        encryption_key: Some([0; 32]),
+        ..Integrity::default()
+    };
+    match TreeTokenReader::with_integrity(Cursor::new(&output), &integrity) {
+        Err(TokenReaderError::BadEncryption) => {},
+        _ => panic!("Expected a bad key")
+    }
+}
@@ -1,21 +1,30 @@
 use std;
 use std::cell::RefCell;
 use std::io::{ Cursor, Read, Seek, SeekFrom };
+use std::ops::Range;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use vec_map::VecMap;
 
 use bytes;
+use bytes::bigint::*;
 use bytes::compress::*;
+use bytes::float::ReadVarFloat;
+use bytes::frontcoding::FrontDecoder;
+use bytes::regexp::*;
 use bytes::varnum::*;
 use bytes::serialize::*;
-use ::TokenReaderError;
+use ::{ GrammarId, TokenReaderError };
 use io::*;
 use escaped_wtf8;
-use multipart::{ FormatInTable, HEADER_GRAMMAR_TABLE, HEADER_STRINGS_TABLE, HEADER_TREE };
+use multipart::annotate::{ Annotation, TreeAnnotations };
+use multipart::{ BLOB_PLACEHOLDER, ContainerVersion, FormatInTable, HEADER_BLOBS, HEADER_CHECKSUM, HEADER_GRAMMAR_ID, HEADER_GRAMMAR_TABLE, HEADER_ENCRYPTED, HEADER_MANIFEST, HEADER_METADATA, HEADER_POSITIONS, HEADER_PROFILE, HEADER_SIGNATURE, HEADER_STRING_DICTIONARY, HEADER_STRINGS_TABLE, HEADER_STRINGS_TABLE_FRONT_CODED, HEADER_TREE, HEADER_TREE_CHUNKS, HEADER_TREE_RUNS, HEADER_TREE_RUNS_CHUNKS, Integrity, Metadata, Options, read_grammar_id, read_metadata };
+use positions::SourcePositions;
+use startup::StartupProfile;
 use util::{ PoisonLock, Pos, ReadConst };
 
-use binjs_shared::{ FieldName, InterfaceName, SharedString };
+use binjs_shared::{ BigInt, FieldName, InterfaceName, RegExpFlags, SharedString };
 
 impl Into<std::io::Error> for TokenReaderError {
     fn into(self) -> std::io::Error {
@@ -56,7 +65,760 @@ impl Deserializer for Option<SharedString> {
     }
 }
 
+/// The strings table.
+///
+/// Entries are only checked and converted to `SharedString` when first used, as
+/// most embedders never need most of the strings of a file.
+pub struct StringsTable {
+    /// The bytes of all non-null entries, one after the other.
+    data: Vec<u8>,
+
+    /// For each entry, its range in `data`, or `None` for the null string.
+    entries: Vec<Option<(usize, usize)>>,
+
+    /// The indices of the entries written as blobs, until the blobs are stitched back.
+    blobs: Vec<usize>,
+
+    /// The entries converted so far.
+    resolved: RefCell<VecMap<SharedString>>,
+}
+impl StringsTable {
+    /// An empty table, with room for `number_of_entries` entries totalling `byte_len` bytes.
+    fn with_capacity(number_of_entries: usize, byte_len: usize) -> Self {
+        StringsTable {
+            data: Vec::with_capacity(byte_len),
+            entries: Vec::with_capacity(number_of_entries),
+            blobs: vec![],
+            resolved: RefCell::new(VecMap::with_capacity(number_of_entries)),
+        }
+    }
+
+    /// The number of entries, including the null string.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The range in `data` of a non-null entry.
+    fn range(&self, index: u32) -> Result<Option<(usize, usize)>, TokenReaderError> {
+        self.entries.get(index as usize)
+            .cloned()
+            .ok_or(TokenReaderError::BadStringIndex(index))
+    }
+
+    /// Check and convert an entry, unless this has already been done.
+    fn resolve(&self, index: u32) -> Result<Option<SharedString>, TokenReaderError> {
+        let (start, stop) = match self.range(index)? {
+            None => return Ok(None),
+            Some(range) => range
+        };
+        if let Some(string) = self.resolved.borrow().get(index as usize) {
+            return Ok(Some(string.clone()));
+        }
+        let escaped = escaped_wtf8::escape(self.data[start..stop].to_vec());
+        let string = String::from_utf8(escaped)
+            .map(SharedString::from_string)
+            .map_err(TokenReaderError::Encoding)?;
+        self.resolved.borrow_mut()
+            .insert(index as usize, string.clone());
+        Ok(Some(string))
+    }
+
+    /// Append an entry, as stored in the file.
+    fn push_entry(&mut self, entry: &[u8]) {
+        if entry == &[255, 0] {
+            self.entries.push(None);
+        } else if entry == &BLOB_PLACEHOLDER {
+            self.blobs.push(self.entries.len());
+            self.entries.push(None);
+        } else {
+            let start = self.data.len();
+            self.data.extend_from_slice(entry);
+            self.entries.push(Some((start, self.data.len())));
+        }
+    }
+
+    /// `true` if entry `index` has been received, i.e. it is in the table and is not
+    /// waiting for its blob.
+    fn has_entry(&self, index: usize) -> bool {
+        index < self.entries.len() && !self.blobs.contains(&index)
+    }
+
+    /// A table of entries that have already been checked and converted.
+    fn from_resolved(strings: &[Option<SharedString>]) -> Self {
+        let mut entries = Vec::with_capacity(strings.len());
+        let mut resolved = VecMap::with_capacity(strings.len());
+        for (index, string) in strings.iter().enumerate() {
+            match *string {
+                None => entries.push(None),
+                Some(ref string) => {
+                    entries.push(Some((0, 0)));
+                    resolved.insert(index, string.clone());
+                }
+            }
+        }
+        StringsTable {
+            data: vec![],
+            entries,
+            blobs: vec![],
+            resolved: RefCell::new(resolved),
+        }
+    }
+
+    /// Replace the entries written as blobs with `blobs`, in order.
+    fn stitch_blobs(&mut self, blobs: Vec<Vec<u8>>) -> Result<(), TokenReaderError> {
+        if blobs.len() != self.blobs.len() {
+            return Err(TokenReaderError::BadLength {
+                expected: self.blobs.len(),
+                got: blobs.len(),
+            });
+        }
+        for (index, blob) in self.blobs.drain(..).zip(blobs) {
+            let start = self.data.len();
+            self.data.extend_from_slice(&blob);
+            self.entries[index] = Some((start, self.data.len()));
+        }
+        Ok(())
+    }
+
+    /// Check and convert all entries.
+    fn resolve_all(&self) -> Result<(), TokenReaderError> {
+        for index in 0..self.entries.len() {
+            self.resolve(index as u32)?;
+        }
+        Ok(())
+    }
+}
+
+/// Deserialize a `StringsTable`, without checking or converting its entries.
+struct StringsTableDeserializer;
+impl Deserializer for StringsTableDeserializer {
+    type Target = StringsTable;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        let number_of_entries = inp.read_varnum()?;
+        let mut table = StringsTable::with_capacity(number_of_entries as usize, inp.size());
+        for _ in 0..number_of_entries {
+            let entry = read_entry(inp)?;
+            table.push_entry(&entry);
+        }
+        Ok(table)
+    }
+}
+
+/// Read an entry of a `StringsTable` that is not front-coded.
+fn read_entry<R: Read>(inp: &mut R) -> Result<Vec<u8>, std::io::Error> {
+    let byte_len = inp.read_varnum()?;
+    let mut entry = vec![0; byte_len as usize];
+    inp.read_exact(&mut entry)?;
+    Ok(entry)
+}
+
+/// Deserialize a front-coded `StringsTable`, without checking or converting its entries.
+struct FrontCodedStringsTableDeserializer;
+impl Deserializer for FrontCodedStringsTableDeserializer {
+    type Target = StringsTable;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        let window = inp.read_varnum()?;
+        let number_of_entries = inp.read_varnum()?;
+        let mut decoder = FrontDecoder::new(window as usize);
+        let mut table = StringsTable::with_capacity(number_of_entries as usize, inp.size());
+        for _ in 0..number_of_entries {
+            let entry = decoder.read(inp)?;
+            table.push_entry(&entry);
+        }
+        Ok(table)
+    }
+}
+
+/// Deserialize the blobs of a strings table.
+struct BlobsDeserializer;
+impl Deserializer for BlobsDeserializer {
+    type Target = Vec<Vec<u8>>;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        let number_of_blobs = inp.read_varnum()?;
+        let mut blobs = Vec::with_capacity(number_of_blobs as usize);
+        for _ in 0..number_of_blobs {
+            let byte_len = inp.read_varnum()?;
+            let mut blob = vec![0; byte_len as usize];
+            inp.read_exact(&mut blob)?;
+            blobs.push(blob);
+        }
+        Ok(blobs)
+    }
+}
+
+/// Read a strings table, including its header, then its blobs, if any, without checking
+/// or converting its entries.
+///
+/// If specified, `dictionary` is the brotli custom dictionary identified in the header of the file.
+fn read_strings_table<R: Read>(inp: &mut R, front_coded: bool, dictionary: Option<&BrotliDictionary>) -> Result<StringsTable, TokenReaderError> {
+    let mut strings_table =
+        if front_coded {
+            inp.read_const(HEADER_STRINGS_TABLE_FRONT_CODED.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            Compression::decompress_with_dictionary(inp, &FrontCodedStringsTableDeserializer, dictionary)
+                .map_err(TokenReaderError::BadCompression)?
+        } else {
+            inp.read_const(HEADER_STRINGS_TABLE.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            Compression::decompress_with_dictionary(inp, &StringsTableDeserializer, dictionary)
+                .map_err(TokenReaderError::BadCompression)?
+        };
+    read_blobs(inp, &mut strings_table)?;
+    Ok(strings_table)
+}
+
+/// Read the blobs of `strings_table`, including their header, if it has any.
+fn read_blobs<R: Read>(inp: &mut R, strings_table: &mut StringsTable) -> Result<(), TokenReaderError> {
+    // The blobs immediately follow the table, so we know whether to expect them
+    // without looking ahead, even when reading sequentially.
+    if !strings_table.blobs.is_empty() {
+        debug!(target: "multipart", "Reading {} blobs", strings_table.blobs.len());
+        inp.read_const(HEADER_BLOBS.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        let blobs = Compression::decompress(inp, &BlobsDeserializer)
+            .map_err(TokenReaderError::BadCompression)?;
+        strings_table.stitch_blobs(blobs)?;
+    }
+    Ok(())
+}
+
+/// Read a string dictionary identifier, including its header, returning the dictionary of
+/// `options` if it has the same hash.
+///
+/// Fails with `TokenReaderError::UnknownStringDictionary` otherwise.
+fn read_string_dictionary<R: Read>(inp: &mut R, options: &Options) -> Result<BrotliDictionary, TokenReaderError> {
+    inp.read_const(HEADER_STRING_DICTIONARY.as_bytes())
+        .map_err(TokenReaderError::ReadError)?;
+    let mut hash = [0; 32];
+    inp.read_exact(&mut hash)
+        .map_err(TokenReaderError::ReadError)?;
+    match options.string_dictionary {
+        Some(ref dictionary) if dictionary.hash() == &hash => Ok(dictionary.clone()),
+        _ => Err(TokenReaderError::UnknownStringDictionary(hash))
+    }
+}
+
+/// The headers of the tree section, each with whether the tree has runs and whether it is chunked.
+const TREE_HEADERS: [(&str, bool, bool); 4] = [
+    (HEADER_TREE, false, false),
+    (HEADER_TREE_RUNS, true, false),
+    (HEADER_TREE_CHUNKS, false, true),
+    (HEADER_TREE_RUNS_CHUNKS, true, true),
+];
+
+/// The header of the tree section starting `data`, if any, see `TREE_HEADERS`.
+fn tree_header(data: &[u8]) -> Option<(&'static str, bool, bool)> {
+    TREE_HEADERS.iter()
+        .find(|&&(header, _, _)| data.starts_with(header.as_bytes()))
+        .cloned()
+}
+
+/// The index of a chunked tree section, see `Options::chunks`.
+struct ChunkHeader {
+    /// The byte length of each chunk as stored, starting with the toplevel chunk.
+    byte_lens: Vec<usize>,
+
+    /// The offset in the decompressed toplevel chunk at which each lazy chunk belongs.
+    offsets: Vec<usize>,
+}
+impl ChunkHeader {
+    /// Read the index, following the header of the tree section.
+    fn read<R: Read>(inp: &mut R) -> Result<Self, TokenReaderError> {
+        let number_of_chunks = inp.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let mut byte_lens = vec![inp.read_varnum()
+            .map_err(TokenReaderError::ReadError)? as usize];
+        let mut offsets = vec![];
+        for _ in 0..number_of_chunks {
+            offsets.push(inp.read_varnum()
+                .map_err(TokenReaderError::ReadError)? as usize);
+            byte_lens.push(inp.read_varnum()
+                .map_err(TokenReaderError::ReadError)? as usize);
+        }
+        Ok(ChunkHeader {
+            byte_lens,
+            offsets,
+        })
+    }
+}
+
+/// Decompress a chunk, as stored in the file.
+fn decompress_chunk(stored: &[u8]) -> Result<Vec<u8>, TokenReaderError> {
+    let mut reader = Cursor::new(stored);
+    let chunk = Compression::decompress(&mut reader, &BufDeserializer)
+        .map_err(TokenReaderError::BadCompression)?;
+    if reader.position() as usize != stored.len() {
+        return Err(TokenReaderError::BadLength {
+            expected: stored.len(),
+            got: reader.position() as usize,
+        })
+    }
+    Ok(chunk)
+}
+
+/// Insert the decompressed lazy chunks at their `offsets` in the decompressed toplevel chunk.
+///
+/// Returns the tree and, for each missing chunk, the offset in the tree at which it belongs,
+/// with its index in `ChunkIndex::chunks`.
+fn splice_chunks(toplevel: Vec<u8>, offsets: &[usize], chunks: Vec<Option<Vec<u8>>>) -> Result<(Vec<u8>, Vec<(u64, usize)>), TokenReaderError> {
+    let mut tree = Vec::with_capacity(toplevel.len());
+    let mut holes = vec![];
+    let mut start = 0;
+    for (index, (&offset, chunk)) in offsets.iter().zip(chunks.into_iter()).enumerate() {
+        // Chunks are sorted, and no two chunks belong at the same offset.
+        if offset < start || (index > 0 && offset == start) || offset > toplevel.len() {
+            return Err(TokenReaderError::invalid_value(&offset));
+        }
+        tree.extend_from_slice(&toplevel[start..offset]);
+        start = offset;
+        match chunk {
+            Some(chunk) => tree.extend_from_slice(&chunk),
+            None => holes.push((tree.len() as u64, index + 1)),
+        }
+    }
+    tree.extend_from_slice(&toplevel[start..]);
+    Ok((tree, holes))
+}
+
+/// Read the tree section, including its header, returning the decompressed tree.
+///
+/// The chunks of a chunked tree are inserted back into the toplevel chunk.
+fn read_tree_section<R: Read>(inp: &mut R, header: &str, chunked: bool) -> Result<Vec<u8>, TokenReaderError> {
+    inp.read_const(header.as_bytes())
+        .map_err(TokenReaderError::ReadError)?;
+    if !chunked {
+        return Compression::decompress(inp, &BufDeserializer)
+            .map_err(TokenReaderError::BadCompression);
+    }
+    let index = ChunkHeader::read(inp)?;
+    let mut chunks = Vec::with_capacity(index.offsets.len());
+    for &byte_len in &index.byte_lens {
+        let mut stored = vec![];
+        inp.by_ref()
+            .take(byte_len as u64)
+            .read_to_end(&mut stored)
+            .map_err(TokenReaderError::ReadError)?;
+        if stored.len() != byte_len {
+            return Err(TokenReaderError::BadLength {
+                expected: byte_len,
+                got: stored.len(),
+            })
+        }
+        chunks.push(Some(decompress_chunk(&stored)?));
+    }
+    let toplevel = chunks.remove(0)
+        .unwrap(); // The toplevel chunk was read above.
+    let (tree, _) = splice_chunks(toplevel, &index.offsets, chunks)?;
+    Ok(tree)
+}
+
+/// Verify the checksums and the signature of a file, as specified by `integrity`.
+///
+/// `content` contains the content sections, decrypted if necessary, `sections` the name and
+/// offset in `content` of each content section and `content_end` the end of the last one.
+/// `checksummed` contains the content sections as checksummed, i.e. encrypted if the file
+/// is encrypted, at the same offsets.
+/// The checksum section, if any, starts at `checksum_start` in `data`. If `checksum`
+/// is `true`, as announced by the container flags, the checksum section is required.
+/// The signature, if any, starts at `signature_start` in `data`, and covers the bytes
+/// preceding it followed by the content sections.
+fn verify_integrity(data: &[u8], checksum_start: usize, checksum: bool, content: &[u8], checksummed: &[u8], content_end: usize, sections: &[(&'static str, usize)], signature_start: usize, signature: Option<Vec<u8>>, integrity: &Integrity) -> Result<(), TokenReaderError> {
+    // Verify checksums, if any. A missing or truncated checksum section means that
+    // the file itself was truncated.
+    if checksum || checksum_start < data.len() {
+        let truncated = |_| TokenReaderError::BadChecksum("checksum".to_string());
+        let mut reader = Cursor::new(&data[checksum_start..]);
+        reader.read_const(HEADER_CHECKSUM.as_bytes())
+            .map_err(truncated)?;
+        let number_of_sections = reader.read_varnum()
+            .map_err(truncated)?;
+        if number_of_sections as usize != sections.len() {
+            return Err(TokenReaderError::BadChecksum("sections".to_string()))
+        }
+        let mut checksums = Vec::with_capacity(sections.len());
+        for _ in 0..number_of_sections {
+            checksums.push(bytes::checksum::read_checksum(&mut reader)
+                .map_err(truncated)?);
+        }
+        let file_checksum = bytes::checksum::read_checksum(&mut reader)
+            .map_err(truncated)?;
+
+        if integrity.verify_checksum {
+            // Sections and the file are both checksummed after encryption, so that checksums
+            // do not reveal anything about the decrypted content.
+            let ends = sections.iter()
+                .skip(1)
+                .map(|&(_, start)| start)
+                .chain(std::iter::once(content_end));
+            for ((&(name, start), end), expected) in sections.iter().zip(ends).zip(checksums) {
+                if bytes::checksum::crc32(&checksummed[start..end]) != expected {
+                    return Err(TokenReaderError::BadChecksum(name.to_string()))
+                }
+            }
+            if bytes::checksum::crc32(&data[..checksum_start]) != file_checksum {
+                return Err(TokenReaderError::BadChecksum("file".to_string()))
+            }
+        }
+    }
+
+    // Verify signature, if required.
+    if let Some(ref key) = integrity.verify_key {
+        let signature = signature
+            .ok_or(TokenReaderError::BadSignature)?;
+        let signed = [&data[..signature_start], &content[..content_end]].concat();
+        if !bytes::signature::verify(key, &signed, &signature) {
+            return Err(TokenReaderError::BadSignature)
+        }
+    }
+    Ok(())
+}
+
+/// A file read sequentially, see `TreeTokenReader::with_deferred_strings`.
+///
+/// All the bytes received are kept, so that checksums and signatures may be verified
+/// once the entire file has been received.
+struct SequentialSource {
+    source: Box<Read>,
+
+    /// The bytes received so far.
+    data: Vec<u8>,
+
+    /// The number of bytes of `data` consumed so far.
+    position: usize,
+}
+impl SequentialSource {
+    fn new(source: Box<Read>) -> Self {
+        SequentialSource {
+            source,
+            data: Vec::with_capacity(4096),
+            position: 0,
+        }
+    }
+
+    /// Receive more bytes, returning the number of bytes received, 0 at the end of the file.
+    fn receive(&mut self) -> Result<usize, std::io::Error> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.source.read(&mut buf) {
+                Ok(received) => {
+                    self.data.extend_from_slice(&buf[..received]);
+                    return Ok(received)
+                }
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            }
+        }
+    }
+
+    /// `true` if the next bytes are `header`, receiving them if necessary.
+    /// The bytes are not consumed.
+    fn starts_with(&mut self, header: &str) -> Result<bool, TokenReaderError> {
+        while self.data.len() < self.position + header.len() {
+            if self.receive().map_err(TokenReaderError::ReadError)? == 0 {
+                break;
+            }
+        }
+        Ok(self.data[self.position..].starts_with(header.as_bytes()))
+    }
+
+    /// Consume the bytes up to `offset`, receiving them if necessary, e.g. the compressed
+    /// bytes that a decompressor did not need.
+    fn skip_to(&mut self, offset: usize) -> Result<(), TokenReaderError> {
+        while self.data.len() < offset {
+            if self.receive().map_err(TokenReaderError::ReadError)? == 0 {
+                return Err(TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Truncated section")));
+            }
+        }
+        self.position = std::cmp::max(self.position, offset);
+        Ok(())
+    }
+
+    /// Receive the rest of the file.
+    fn receive_all(&mut self) -> Result<(), TokenReaderError> {
+        self.source.read_to_end(&mut self.data)
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(())
+    }
+
+    /// Receive the rest of the file, returning all its bytes.
+    fn into_data(mut self) -> Result<Vec<u8>, TokenReaderError> {
+        self.receive_all()?;
+        Ok(self.data)
+    }
+}
+impl Read for SequentialSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.data.len() && !buf.is_empty() {
+            self.receive()?;
+        }
+        let len = std::cmp::min(buf.len(), self.data.len() - self.position);
+        buf[..len].copy_from_slice(&self.data[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// A `SequentialSource` shared between the decompressor of a section and the reader
+/// of the following sections, which only resumes once the section has been received.
+struct SharedSource(Rc<RefCell<SequentialSource>>);
+impl Read for SharedSource {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+/// A strings table whose entries are being received, see `ReaderState::receive_string`.
+struct IncomingStrings {
+    /// The decompressed table, positioned at the next entry.
+    stream: Box<Read>,
+
+    /// If the table is front-coded, its decoder.
+    decoder: Option<FrontDecoder>,
+
+    /// The number of entries not received yet.
+    remaining: u32,
+
+    /// The offset in the file of the end of the compressed table.
+    end: usize,
+}
+
+/// The progress of the strings table of a `PendingStrings`.
+enum StringsProgress {
+    /// The header of the strings table has not been received yet.
+    NotStarted,
+
+    /// Some of the entries have been received.
+    Receiving(IncomingStrings),
+
+    /// The strings table has been received entirely, including its blobs.
+    Done,
+}
+
+/// The part of a file that has not been read yet by a reader created with
+/// `TreeTokenReader::with_deferred_strings`, starting with the rest of the tree.
+struct PendingStrings {
+    source: Rc<RefCell<SequentialSource>>,
+
+    /// The offset in the file of the end of the compressed tree.
+    tree_end: usize,
+
+    /// The strings table, whose entries are stored in `ReaderState::strings_table`
+    /// as they are received.
+    strings: StringsProgress,
+
+    /// The offset of the content sections in the file.
+    content_start: usize,
+
+    /// The name and offset of each content section received so far, relative to `content_start`.
+    sections: Vec<(&'static str, usize)>,
+
+    /// The offset of the signature in the file, i.e. the end of the signed prelude.
+    signature_start: usize,
+
+    /// The signature of the file, if any.
+    signature: Option<Vec<u8>>,
+
+    /// If `true`, the file ends with a checksum section.
+    checksum: bool,
+
+    /// The brotli custom dictionary of the strings table, if any.
+    string_dictionary: Option<BrotliDictionary>,
+
+    integrity: Integrity,
+}
+
+/// The sections of a chunked file preceding its chunks, see `TreeTokenReader::with_chunks`.
+struct ChunkedPrelude {
+    grammar: Option<GrammarId>,
+    grammar_table: Table<NodeDescription>,
+    strings_table: StringsTable,
+    nan_policy: bytes::float::NaNPolicy,
+    varfloats: bool,
+
+    /// If `true`, the tree has runs.
+    runs: bool,
+
+    index: ChunkHeader,
+
+    /// The offset of the toplevel chunk in the file.
+    chunks_start: usize,
+}
+impl ChunkedPrelude {
+    /// Read the sections of a file preceding its chunks, from the first bytes of the file.
+    ///
+    /// Returns `None` if the file cannot be read by chunks: archives, encrypted files and
+    /// files whose tree is not chunked or precedes the strings table.
+    fn read(prefix: &[u8], options: &Options) -> Result<Option<Self>, TokenReaderError> {
+        let mut reader = Cursor::new(prefix);
+        let ContainerVersion { is_archive, varfloats, extended, nan_policy, .. } = TreeTokenReader::read_container_version(&mut reader)?;
+        if is_archive {
+            return Ok(None);
+        }
+
+        // Read grammar identifier, if any.
+        let grammar =
+            if prefix[reader.position() as usize..].starts_with(HEADER_GRAMMAR_ID.as_bytes()) {
+                reader.read_const(HEADER_GRAMMAR_ID.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(read_grammar_id(&mut reader)
+                    .map_err(TokenReaderError::ReadError)?
+                    .with_extended(extended))
+            } else if extended {
+                return Err(TokenReaderError::BadHeader);
+            } else {
+                None
+            };
+
+        // Read string dictionary identifier, if any.
+        let string_dictionary =
+            if prefix[reader.position() as usize..].starts_with(HEADER_STRING_DICTIONARY.as_bytes()) {
+                Some(read_string_dictionary(&mut reader, options)?)
+            } else {
+                None
+            };
+
+        // Skip metadata, if any.
+        if prefix[reader.position() as usize..].starts_with(HEADER_METADATA.as_bytes()) {
+            reader.read_const(HEADER_METADATA.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            read_metadata(&mut reader)
+                .map_err(TokenReaderError::ReadError)?;
+        }
+
+        // Skip startup profile, if any.
+        if prefix[reader.position() as usize..].starts_with(HEADER_PROFILE.as_bytes()) {
+            reader.read_const(HEADER_PROFILE.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            StartupProfile::read(&mut reader)
+                .map_err(TokenReaderError::ReadError)?;
+        }
+
+        // Skip signature, if any, as it cannot be verified without the entire file.
+        if prefix[reader.position() as usize..].starts_with(HEADER_SIGNATURE.as_bytes()) {
+            reader.read_const(HEADER_SIGNATURE.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            let mut signature = vec![0; bytes::signature::SIGNATURE_LENGTH];
+            reader.read_exact(&mut signature)
+                .map_err(TokenReaderError::ReadError)?;
+        }
+        if prefix[reader.position() as usize..].starts_with(HEADER_ENCRYPTED.as_bytes()) {
+            return Ok(None);
+        }
+
+        reader.read_const(HEADER_GRAMMAR_TABLE.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        let grammar_deserializer = TableDeserializer {
+            deserializer: NodeDescriptionDeserializer
+        };
+        let grammar_table = Compression::decompress(&mut reader, &grammar_deserializer)
+            .map_err(TokenReaderError::BadCompression)?;
+        if tree_header(&prefix[reader.position() as usize..]).is_some() {
+            // Split prelude.
+            return Ok(None);
+        }
+        let front_coded = prefix[reader.position() as usize..].starts_with(HEADER_STRINGS_TABLE_FRONT_CODED.as_bytes());
+        let strings_table = read_strings_table(&mut reader, front_coded, string_dictionary.as_ref())?;
+
+        let (header, runs) = match tree_header(&prefix[reader.position() as usize..]) {
+            Some((header, runs, true)) => (header, runs),
+            Some(_) => return Ok(None),
+            None => {
+                // Either the prefix ends within the header, or the header is invalid.
+                reader.read_const(HEADER_TREE_CHUNKS.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                return Err(TokenReaderError::BadHeader);
+            }
+        };
+        reader.read_const(header.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        let index = ChunkHeader::read(&mut reader)?;
+        Ok(Some(ChunkedPrelude {
+            grammar,
+            grammar_table,
+            strings_table,
+            nan_policy,
+            varfloats,
+            runs,
+            index,
+            chunks_start: reader.position() as usize,
+        }))
+    }
+
+    /// The range of bytes of each chunk in the file, see `ChunkIndex`.
+    fn ranges(&self) -> Vec<Range<u64>> {
+        let mut start = self.chunks_start as u64;
+        self.index.byte_lens.iter()
+            .map(|&byte_len| {
+                let range = start..start + byte_len as u64;
+                start = range.end;
+                range
+            })
+            .collect()
+    }
+}
+
+/// The lazy chunks of a file that were missing when the `TreeTokenReader` was created,
+/// see `TreeTokenReader::with_chunks`.
+struct PendingChunks {
+    /// The offset in the decompressed tree at which each missing chunk belongs, with its
+    /// index in `ChunkIndex::chunks`, by increasing offset.
+    holes: Vec<(u64, usize)>,
+
+    /// The chunks skipped since the previous call to `TreeTokenReader::missing_chunks`.
+    skipped: Vec<MissingChunk>,
+}
+
+/// A non-null string of the strings table.
+///
+/// The string is only checked and converted to a `SharedString` by `resolve`.
+#[derive(Clone)]
+pub struct StringHandle {
+    table: Rc<StringsTable>,
+    index: u32,
+    start: usize,
+    stop: usize,
+}
+impl StringHandle {
+    /// The index of the string in the strings table.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The bytes of the string, as stored in the file, i.e. unchecked WTF-8.
+    pub fn bytes(&self) -> &[u8] {
+        &self.table.data[self.start..self.stop]
+    }
+
+    /// Check and convert the string. The result is cached, so a string shared
+    /// by several handles is only converted once.
+    pub fn resolve(&self) -> Result<SharedString, TokenReaderError> {
+        self.table.resolve(self.index)?
+            .ok_or(TokenReaderError::EmptyString)
+    }
+}
+
+/// The names of the sections that may be extracted by `TreeTokenReader::section`,
+/// in the order in which they appear in a file, except that the strings table follows
+/// the tree in files with a split prelude. Only archives have a manifest and
+/// only files encoded with source positions have positions.
+pub const SECTION_NAMES : [&'static str; 5] = ["grammar", "strings", "manifest", "tree", "positions"];
+
+/// A single section of a file.
+pub struct Section {
+    /// One of `SECTION_NAMES`.
+    pub name: &'static str,
+
+    /// The bytes of the section, as stored in the file, i.e. including its
+    /// header and compressed. If the file is encrypted, these are the
+    /// decrypted bytes.
+    pub raw: Vec<u8>,
+
+    /// A human-readable listing of the decompressed section.
+    pub listing: String,
+}
+
 /// A table of entries indexed by a varnum.
+#[derive(Clone)]
 pub struct Table<Value> {
     map: VecMap<Value>,
 }
@@ -114,7 +876,7 @@ impl<'a, D> Deserializer for TableDeserializer<D> where D: Deserializer, D::Targ
 }
 
 /// Description of a node in the table.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct NodeDescription {
     kind: SharedString,
 }
@@ -138,151 +900,1343 @@ impl Deserializer for NodeDescriptionDeserializer {
             Some(x) => x
         };
 
-        Ok(NodeDescription {
-            kind: name,
+        Ok(NodeDescription {
+            kind: name,
+        })
+    }
+}
+
+/// An entry of an archive.
+#[derive(Clone, Debug)]
+pub struct ArchiveEntry {
+    /// The name of the entry, typically a path.
+    pub name: SharedString,
+
+    /// The offset of the tree of the entry in the decompressed tree section.
+    pub offset: u32,
+
+    /// The byte length of the tree of the entry in the decompressed tree section.
+    pub byte_len: u32,
+}
+
+/// Deserialize the manifest of an archive.
+struct ManifestDeserializer;
+impl Deserializer for ManifestDeserializer {
+    type Target = Vec<ArchiveEntry>;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        let number_of_entries = inp.read_varnum()?;
+        let mut entries = Vec::with_capacity(number_of_entries as usize);
+        for _ in 0..number_of_entries {
+            let strings_deserializer : Option<SharedString> = None;
+            let name = match strings_deserializer.read(inp)? {
+                None => return Err(TokenReaderError::EmptyString.into()),
+                Some(x) => x
+            };
+            let offset = inp.read_varnum()?;
+            let byte_len = inp.read_varnum()?;
+            entries.push(ArchiveEntry {
+                name,
+                offset,
+                byte_len,
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Deserialize source positions.
+struct PositionsDeserializer;
+impl Deserializer for PositionsDeserializer {
+    type Target = SourcePositions;
+    fn read<R: Read + Seek>(&self, inp: &mut R) -> Result<Self::Target, std::io::Error> {
+        SourcePositions::read(inp)
+    }
+}
+
+/// A wrapper of Cursor which prints the the binary representation and
+/// handles printing structural interpretation.
+/// The underlying implementation for FileStructurePrinter for TreeTokenReader.
+struct DumpCursor {
+    reader: Cursor<Vec<u8>>,
+
+    /// If specified, the rest of the decompressed tree, which is received as it is read.
+    incoming: Option<Box<Read>>,
+
+    file_format_print_enabled: bool,
+    newline: bool,
+
+    /// If specified, the structural interpretation is recorded here instead of being printed.
+    recorder: Option<Rc<RefCell<TreeAnnotations>>>,
+
+    /// When recording, the offset of the first byte read since the last label.
+    pending: Option<usize>,
+}
+impl DumpCursor {
+    fn new(buf: Vec<u8>) -> DumpCursor {
+        DumpCursor {
+            reader: Cursor::new(buf),
+            incoming: None,
+            file_format_print_enabled: false,
+            newline: false,
+            recorder: None,
+            pending: None,
+        }
+    }
+
+    /// A cursor on a tree that is received from `incoming` as it is read.
+    fn receiving(incoming: Box<Read>) -> DumpCursor {
+        DumpCursor {
+            incoming: Some(incoming),
+            ..DumpCursor::new(Vec::with_capacity(4096))
+        }
+    }
+
+    /// Receive the tree until it has at least `len` bytes, or until its end.
+    fn receive_until(&mut self, len: u64) -> std::io::Result<()> {
+        let mut buf = [0; 4096];
+        while (self.reader.get_ref().len() as u64) < len {
+            let received = match self.incoming {
+                None => return Ok(()),
+                Some(ref mut incoming) => incoming.read(&mut buf)
+            };
+            match received {
+                Ok(0) => self.incoming = None,
+                Ok(received) => self.reader.get_mut().extend_from_slice(&buf[..received]),
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err)
+            }
+        }
+        Ok(())
+    }
+
+    /// Receive the rest of the tree.
+    fn receive_all(&mut self) -> std::io::Result<()> {
+        self.receive_until(u64::max_value())
+    }
+
+    /// Record the structural interpretation to `recorder` instead of printing it.
+    fn record(&mut self, recorder: Rc<RefCell<TreeAnnotations>>) {
+        recorder.borrow_mut().data = self.reader.get_ref().clone();
+        self.recorder = Some(recorder);
+        self.enable_file_structure_print();
+    }
+
+    fn enable_file_structure_print(&mut self) {
+        self.file_format_print_enabled = true;
+    }
+    fn disable_file_structure_print(&mut self) {
+        self.file_format_print_enabled = false;
+    }
+    fn is_file_structure_print_enabled(&self) -> bool {
+        self.file_format_print_enabled
+    }
+    fn prepare_file_structure_column(&mut self) {
+        if self.is_file_structure_print_enabled() && self.recorder.is_none() {
+            if self.newline {
+                self.newline = false;
+                print!("{:5} : {:<24}# ", "", "");
+            } else {
+                print!(" ");
+            }
+        };
+    }
+    fn print_file_structure_label(&mut self, label: std::fmt::Arguments) {
+        match self.recorder {
+            Some(ref recorder) => {
+                let end = self.reader.position() as usize;
+                let start = self.pending.take()
+                    .unwrap_or(end);
+                recorder.borrow_mut()
+                    .annotations
+                    .push(Annotation {
+                        start,
+                        end,
+                        label: label.to_string(),
+                    });
+            }
+            None => print!("{}", label)
+        }
+    }
+    fn newline_for_file_structure_print(&mut self) {
+        if self.is_file_structure_print_enabled() && self.recorder.is_none() {
+            println!("");
+            self.newline = true;
+        };
+    }
+    fn newline_for_file_structure_print_if_necessary(&mut self) {
+        if !self.newline {
+            self.newline_for_file_structure_print();
+        };
+    }
+}
+impl std::io::Read for DumpCursor {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let offset = self.seek(SeekFrom::Current(0))?;
+        self.receive_until(offset + buf.len() as u64)?;
+        let x = self.reader.read(buf);
+        if self.recorder.is_some() {
+            if self.pending.is_none() {
+                self.pending = Some(offset as usize);
+            }
+        } else if self.is_file_structure_print_enabled() {
+            self.newline_for_file_structure_print_if_necessary();
+            print!("{:5} : {:<24}#",
+                   offset,
+                   buf
+                   .iter()
+                   .map(|b| format!("{:02x}", b))
+                   .collect::<Vec<String>>()
+                   .join(" "));
+            self.newline = false;
+        }
+        x
+    }
+}
+impl std::io::Seek for DumpCursor {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Start(offset) => self.receive_until(offset)?,
+            SeekFrom::Current(delta) if delta > 0 => {
+                let offset = self.reader.position() + delta as u64;
+                self.receive_until(offset)?
+            }
+            SeekFrom::Current(_) => {}
+            SeekFrom::End(_) => self.receive_all()?,
+        }
+        self.reader.seek(pos)
+    }
+}
+
+/// A list or a tagged tuple being read, in a tree with runs.
+enum Frame {
+    /// A list, with the tag of the subsequent items of the current run and
+    /// their number, if any.
+    List(Option<(InterfaceName, u32)>),
+    TaggedTuple,
+}
+
+/// The state of the `TreeTokenReader`.
+///
+/// Use a `PoisonLock` to access this state.
+pub struct ReaderState {
+    reader: DumpCursor,
+    pub strings_table: Rc<StringsTable>,
+    pub grammar_table: Table<NodeDescription>,
+
+    /// The grammar declared by the file, if any.
+    pub grammar: Option<GrammarId>,
+
+    /// The source positions of the tree, if any.
+    pub positions: Option<SourcePositions>,
+
+    /// How NaN values are read, as announced by the container flags.
+    nan_policy: bytes::float::NaNPolicy,
+
+    /// If `true`, floats are represented as varfloats.
+    varfloats: bool,
+
+    /// If the tree has runs, the lists and tagged tuples being read, innermost last.
+    frames: Option<Vec<Frame>>,
+
+    /// If specified, the contents of lazy fields are skipped and recorded here,
+    /// see `TreeTokenReader::defer_lazy_subtrees`.
+    deferred: Option<Vec<DeferredSubtree>>,
+
+    /// If specified, the strings table has not been read yet, see `TreeTokenReader::with_deferred_strings`.
+    pending_strings: Option<PendingStrings>,
+
+    /// If specified, some lazy chunks were missing, see `TreeTokenReader::with_chunks`.
+    chunks: Option<PendingChunks>,
+}
+impl ReaderState {
+    /// Receive the rest of the tree, if it has not been received yet, up to the next section.
+    fn receive_tree(&mut self) -> Result<(), TokenReaderError> {
+        let tree_end = match self.pending_strings {
+            None => return Ok(()),
+            Some(ref stream) => stream.tree_end
+        };
+        self.reader.receive_all()
+            .map_err(TokenReaderError::ReadError)?;
+        let stream = self.pending_strings.as_mut()
+            .unwrap(); // Checked above.
+        let mut source = stream.source.borrow_mut();
+        source.skip_to(tree_end)
+    }
+
+    /// Receive the header of the strings table, if it has not been received yet.
+    fn start_strings_table(&mut self) -> Result<(), TokenReaderError> {
+        match self.pending_strings {
+            Some(PendingStrings { strings: StringsProgress::NotStarted, .. }) => {},
+            _ => return Ok(())
+        }
+        // With a split prelude, the strings table follows the tree.
+        self.receive_tree()?;
+        debug!(target: "multipart", "Receiving the strings table");
+        let stream = self.pending_strings.as_mut()
+            .unwrap(); // Checked above.
+        let (front_coded, compression, byte_len, end) = {
+            let mut source = stream.source.borrow_mut();
+            let front_coded = source.starts_with(HEADER_STRINGS_TABLE_FRONT_CODED)?;
+            stream.sections.push(("strings", source.position - stream.content_start));
+            let header = if front_coded { HEADER_STRINGS_TABLE_FRONT_CODED } else { HEADER_STRINGS_TABLE };
+            source.read_const(header.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            let (compression, byte_len) = Compression::read_header(&mut *source)
+                .map_err(TokenReaderError::BadCompression)?;
+            (front_coded, compression, byte_len, source.position + byte_len as usize)
+        };
+        let mut decompressed = compression.decompressor(SharedSource(stream.source.clone()).take(byte_len as u64), stream.string_dictionary.as_ref())
+            .map_err(TokenReaderError::BadCompression)?;
+        let decoder =
+            if front_coded {
+                let window = decompressed.read_varnum()
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(FrontDecoder::new(window as usize))
+            } else {
+                None
+            };
+        let remaining = decompressed.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        stream.strings = StringsProgress::Receiving(IncomingStrings {
+            stream: decompressed,
+            decoder,
+            remaining,
+            end,
+        });
+        Ok(())
+    }
+
+    /// Receive the entries of the strings table being received, up to entry `until`, if
+    /// specified, or all of them. Once all entries have been received, receive the blobs.
+    fn receive_entries(&mut self, until: Option<usize>) -> Result<(), TokenReaderError> {
+        let stream = match self.pending_strings {
+            None => return Ok(()),
+            Some(ref mut stream) => stream
+        };
+        match stream.strings {
+            StringsProgress::Receiving(_) => {},
+            _ => return Ok(())
+        }
+        // String handles are only created once the file has been received.
+        let table = Rc::get_mut(&mut self.strings_table)
+            .expect("String handle created before the strings table was received");
+        {
+            let incoming = match stream.strings {
+                StringsProgress::Receiving(ref mut incoming) => incoming,
+                _ => unreachable!() // Checked above.
+            };
+            while incoming.remaining > 0 && until.map_or(true, |index| table.len() <= index) {
+                let entry = match incoming.decoder {
+                    Some(ref mut decoder) => decoder.read(&mut incoming.stream),
+                    None => read_entry(&mut incoming.stream)
+                }.map_err(TokenReaderError::ReadError)?;
+                table.push_entry(&entry);
+                incoming.remaining -= 1;
+            }
+            if incoming.remaining > 0 {
+                return Ok(());
+            }
+        }
+        let mut incoming = match std::mem::replace(&mut stream.strings, StringsProgress::Done) {
+            StringsProgress::Receiving(incoming) => incoming,
+            _ => unreachable!() // Checked above.
+        };
+        // The decompressor may not need the last compressed bytes.
+        std::io::copy(&mut incoming.stream, &mut std::io::sink())
+            .map_err(TokenReaderError::ReadError)?;
+        let mut source = stream.source.borrow_mut();
+        source.skip_to(incoming.end)?;
+        read_blobs(&mut *source, table)
+    }
+
+    /// Receive the strings table until entry `index` is available, if it has not been
+    /// received yet.
+    fn receive_string(&mut self, index: u32) -> Result<(), TokenReaderError> {
+        let index = index as usize;
+        if self.pending_strings.is_none() || self.strings_table.has_entry(index) {
+            return Ok(());
+        }
+        self.start_strings_table()?;
+        self.receive_entries(Some(index))?;
+        if !self.strings_table.has_entry(index) {
+            // Either a blob, which follows the table, or an invalid index.
+            self.receive_entries(None)?;
+        }
+        Ok(())
+    }
+
+    /// Receive the rest of the file, if it has not been received yet, then verify it.
+    fn receive_strings(&mut self) -> Result<(), TokenReaderError> {
+        if self.pending_strings.is_none() {
+            return Ok(());
+        }
+        self.receive_tree()?;
+        self.start_strings_table()?;
+        self.receive_entries(None)?;
+        let stream = self.pending_strings.take()
+            .unwrap(); // Checked above.
+        let mut sections = stream.sections;
+        let mut source = stream.source.borrow_mut();
+
+        let positions =
+            if source.starts_with(HEADER_POSITIONS)? {
+                sections.push(("positions", source.position - stream.content_start));
+                source.read_const(HEADER_POSITIONS.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(Compression::decompress(&mut *source, &PositionsDeserializer)
+                    .map_err(TokenReaderError::BadCompression)?)
+            } else {
+                None
+            };
+
+        let content_start = stream.content_start;
+        let content_end = source.position - content_start;
+        source.receive_all()?;
+        let data = &source.data;
+        verify_integrity(data, content_start + content_end, stream.checksum, &data[content_start..], &data[content_start..], content_end, &sections, stream.signature_start, stream.signature, &stream.integrity)?;
+
+        self.strings_table.resolve_all()?;
+        self.positions = positions;
+        Ok(())
+    }
+}
+
+/// The contents of a lazy field, skipped while reading the tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeferredSubtree {
+    /// The interface containing the lazy field, e.g. `LazyFunctionDeclaration`.
+    pub interface: InterfaceName,
+
+    /// The offset of the contents in the decompressed tree section.
+    pub offset: u64,
+
+    /// The number of bytes of the contents.
+    pub byte_len: u32,
+}
+
+/// The chunks of a chunked file, see `Options::chunks`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkIndex {
+    /// The range of bytes of each chunk in the file: first the toplevel chunk, then the
+    /// contents of each outermost lazy function, in the order in which they appear in the
+    /// tree. Chunks are contiguous, so the toplevel chunk and the first functions may be
+    /// fetched with a single range request.
+    pub chunks: Vec<Range<u64>>,
+}
+
+/// The contents of a lazy field, skipped because its chunk was missing, see
+/// `TreeTokenReader::with_chunks`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MissingChunk {
+    /// The index of the chunk in `ChunkIndex::chunks`.
+    pub chunk: usize,
+
+    /// The interface containing the lazy field, e.g. `LazyFunctionDeclaration`.
+    pub interface: InterfaceName,
+
+    /// The number of bytes of the decompressed contents.
+    pub byte_len: u32,
+}
+
+/// The parts of a file needed to read any of its subtrees, which may be shared
+/// between threads, e.g. to read `DeferredSubtree`s in parallel.
+///
+/// All strings are checked and converted when the snapshot is taken, so readers
+/// created from the snapshot never need to convert strings, and errors in strings
+/// are reported once, by `TreeTokenReader::snapshot`.
+#[derive(Clone)]
+pub struct TreeSnapshot {
+    /// The decompressed tree section.
+    tree: Arc<Vec<u8>>,
+    strings: Arc<Vec<Option<SharedString>>>,
+    grammar_table: Arc<Table<NodeDescription>>,
+    grammar: Option<GrammarId>,
+    nan_policy: bytes::float::NaNPolicy,
+    varfloats: bool,
+
+    /// If `true`, the tree has runs.
+    runs: bool,
+}
+impl TreeSnapshot {
+    /// Create a reader for the subtrees of the file, positioned at the start of the tree.
+    ///
+    /// Use `TreeTokenReader::seek_to` to read a specific subtree. Each reader owns a copy
+    /// of the tree, so readers should be reused to read several subtrees.
+    pub fn reader(&self) -> TreeTokenReader {
+        let implem = ReaderState {
+            strings_table: Rc::new(StringsTable::from_resolved(&self.strings)),
+            grammar_table: (*self.grammar_table).clone(),
+            grammar: self.grammar.clone(),
+            positions: None,
+            nan_policy: self.nan_policy,
+            varfloats: self.varfloats,
+            frames: if self.runs { Some(vec![]) } else { None },
+            deferred: None,
+            pending_strings: None,
+            chunks: None,
+            reader: DumpCursor::new((*self.tree).clone()),
+        };
+        TreeTokenReader {
+            grammar: self.grammar.clone(),
+            positions: None,
+            owner: Rc::new(RefCell::new(PoisonLock::new(implem)))
+        }
+    }
+}
+
+pub struct TreeTokenReader {
+    // Shared with all children.
+    owner: Rc<RefCell<PoisonLock<ReaderState>>>,
+
+    /// The grammar declared by the file, if any.
+    grammar: Option<GrammarId>,
+
+    /// The source positions of the tree, if any.
+    positions: Option<SourcePositions>,
+}
+
+
+impl TreeTokenReader {
+    /// Create a reader for a file containing a single tree.
+    ///
+    /// Use `new_entry` to read from an archive.
+    pub fn new<R: Read + Seek>(reader: R) -> Result<Self, TokenReaderError> {
+        Self::with_options(reader, &Options::default())
+    }
+
+    /// Create a reader for a file containing a single tree, with
+    /// specific options for detecting corrupted files.
+    pub fn with_integrity<R: Read + Seek>(reader: R, integrity: &Integrity) -> Result<Self, TokenReaderError> {
+        let options = Options {
+            integrity: integrity.clone(),
+            ..Options::default()
+        };
+        Self::with_options(reader, &options)
+    }
+
+    /// Create a reader for a file containing a single tree, with specific options,
+    /// e.g. a string dictionary or a NaN policy.
+    pub fn with_options<R: Read + Seek>(reader: R, options: &Options) -> Result<Self, TokenReaderError> {
+        let (implem, manifest) = Self::read_sections(reader, options, false, None)?;
+        Self::single_tree(implem, manifest)
+    }
+
+    /// Create a reader for a file containing a single tree, in which strings
+    /// are only checked and converted when first used.
+    ///
+    /// Use `string_handle_at` to access strings without converting them. Errors
+    /// in strings are only reported when the strings are converted.
+    pub fn with_lazy_strings<R: Read + Seek>(reader: R, options: &Options) -> Result<Self, TokenReaderError> {
+        let (implem, manifest) = Self::read_sections(reader, options, true, None)?;
+        Self::single_tree(implem, manifest)
+    }
+
+    /// Create a reader for a file containing a single tree, read sequentially from `source`,
+    /// decoding the tree as it is received.
+    ///
+    /// This returns once the grammar table and the header of the tree have been received,
+    /// preceded by the strings table unless it follows the tree, see `Options::split_prelude`.
+    /// The tree is then received and decompressed as it is read, except for chunked trees,
+    /// which are received entirely first. With a split prelude, each string waits for the
+    /// rest of the tree and for the strings table up to its entry, or up to its blob.
+    ///
+    /// As checksums and signatures cover the entire file, they are only verified by
+    /// `receive_strings`, which receives the rest of the file, and which is called by
+    /// `string_handle_at` and `snapshot`. Until then, a truncated or corrupted file is only
+    /// detected by the reads that fail. Archives and encrypted files are read entirely
+    /// before returning.
+    pub fn with_deferred_strings<R: Read + 'static>(source: R, options: &Options) -> Result<Self, TokenReaderError> {
+        let mut source = SequentialSource::new(Box::new(source));
+        let ContainerVersion { is_archive, varfloats, extended, checksum, nan_policy, .. } = Self::read_container_version(&mut source)?;
+
+        // Read grammar identifier, if any.
+        let grammar =
+            if source.starts_with(HEADER_GRAMMAR_ID)? {
+                source.read_const(HEADER_GRAMMAR_ID.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(read_grammar_id(&mut source)
+                    .map_err(TokenReaderError::ReadError)?
+                    .with_extended(extended))
+            } else if extended {
+                return Err(TokenReaderError::BadHeader);
+            } else {
+                None
+            };
+
+        // Read string dictionary identifier, if any.
+        let string_dictionary =
+            if source.starts_with(HEADER_STRING_DICTIONARY)? {
+                Some(read_string_dictionary(&mut source, options)?)
+            } else {
+                None
+            };
+
+        // Skip metadata, if any.
+        if source.starts_with(HEADER_METADATA)? {
+            source.read_const(HEADER_METADATA.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            read_metadata(&mut source)
+                .map_err(TokenReaderError::ReadError)?;
+        }
+
+        // Skip startup profile, if any.
+        if source.starts_with(HEADER_PROFILE)? {
+            source.read_const(HEADER_PROFILE.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            StartupProfile::read(&mut source)
+                .map_err(TokenReaderError::ReadError)?;
+        }
+
+        // Read signature, if any.
+        let signature_start = source.position;
+        let signature =
+            if source.starts_with(HEADER_SIGNATURE)? {
+                source.read_const(HEADER_SIGNATURE.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                let mut signature = vec![0; bytes::signature::SIGNATURE_LENGTH];
+                source.read_exact(&mut signature)
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(signature)
+            } else {
+                None
+            };
+
+        // Encrypted content can only be decrypted once received entirely.
+        if is_archive || source.starts_with(HEADER_ENCRYPTED)? {
+            return Self::with_options(Cursor::new(source.into_data()?), options);
+        }
+
+        let content_start = source.position;
+        let mut sections = vec![("grammar", 0)];
+        source.read_const(HEADER_GRAMMAR_TABLE.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        let grammar_deserializer = TableDeserializer {
+            deserializer: NodeDescriptionDeserializer
+        };
+        let grammar_table = Compression::decompress(&mut source, &grammar_deserializer)
+            .map_err(TokenReaderError::BadCompression)?;
+
+        // Without a split prelude, the strings table precedes the tree and is received entirely.
+        let mut found = Self::find_tree_header(&mut source)?;
+        let (strings_table, strings) =
+            if found.is_none() {
+                let front_coded = source.starts_with(HEADER_STRINGS_TABLE_FRONT_CODED)?;
+                sections.push(("strings", source.position - content_start));
+                let strings_table = read_strings_table(&mut source, front_coded, string_dictionary.as_ref())?;
+                found = Self::find_tree_header(&mut source)?;
+                (strings_table, StringsProgress::Done)
+            } else {
+                (StringsTable::from_resolved(&[]), StringsProgress::NotStarted)
+            };
+        let (header, runs, chunked) = found
+            .ok_or(TokenReaderError::BadHeader)?;
+        sections.push(("tree", source.position - content_start));
+
+        let source = Rc::new(RefCell::new(source));
+        let (reader, tree_end) =
+            if chunked {
+                let mut source = source.borrow_mut();
+                let decompressed_tree = read_tree_section(&mut *source, header, chunked)?;
+                (DumpCursor::new(decompressed_tree), source.position)
+            } else {
+                let (compression, byte_len, tree_end) = {
+                    let mut source = source.borrow_mut();
+                    source.read_const(header.as_bytes())
+                        .map_err(TokenReaderError::ReadError)?;
+                    let (compression, byte_len) = Compression::read_header(&mut *source)
+                        .map_err(TokenReaderError::BadCompression)?;
+                    (compression, byte_len, source.position + byte_len as usize)
+                };
+                let incoming = compression.decompressor(SharedSource(source.clone()).take(byte_len as u64), None)
+                    .map_err(TokenReaderError::BadCompression)?;
+                (DumpCursor::receiving(incoming), tree_end)
+            };
+        debug!(target: "multipart", "Receiving the tree, up to byte {}", tree_end);
+
+        let implem = ReaderState {
+            // Completed by `receive_string` with a split prelude.
+            strings_table: Rc::new(strings_table),
+            grammar_table,
+            grammar,
+            positions: None,
+            nan_policy,
+            varfloats,
+            frames: if runs { Some(vec![]) } else { None },
+            deferred: None,
+            pending_strings: Some(PendingStrings {
+                source,
+                tree_end,
+                strings,
+                content_start,
+                sections,
+                signature_start,
+                signature,
+                checksum,
+                string_dictionary,
+                integrity: options.integrity.clone(),
+            }),
+            chunks: None,
+            reader,
+        };
+        Self::single_tree(implem, None)
+    }
+
+    /// The header of the tree section at the current position of `source`, if any, see `TREE_HEADERS`.
+    fn find_tree_header(source: &mut SequentialSource) -> Result<Option<(&'static str, bool, bool)>, TokenReaderError> {
+        for &(header, runs, chunked) in &TREE_HEADERS {
+            if source.starts_with(header)? {
+                return Ok(Some((header, runs, chunked)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// With a reader created by `with_deferred_strings`, receive the rest of the file, including
+    /// the parts of the tree and of the strings table that have not been received yet, and verify
+    /// the file as specified by its `Integrity`.
+    ///
+    /// The source positions of the file, if any, are only available afterwards. Does
+    /// nothing if the file has already been read entirely.
+    pub fn receive_strings(&mut self) -> Result<(), TokenReaderError> {
+        let positions = self.owner.borrow_mut().try(|state| {
+            state.receive_strings()?;
+            Ok(state.positions.take())
+        })?;
+        if positions.is_some() {
+            self.positions = positions;
+        }
+        Ok(())
+    }
+
+    /// Read the chunk index of a chunked file from the first bytes of the file, e.g. to
+    /// fetch its chunks with HTTP range requests, see `Options::chunks`.
+    ///
+    /// Returns `None` if the file cannot be read by chunks, e.g. if it is not chunked, is
+    /// an archive or is encrypted. Fails with `TokenReaderError::ReadError` if `prefix`
+    /// ends before the chunk index, in which case more bytes should be fetched, and with
+    /// `TokenReaderError::UnknownStringDictionary` if the strings table is compressed with
+    /// a custom dictionary, see `chunk_index_with_options`.
+    pub fn chunk_index(prefix: &[u8]) -> Result<Option<ChunkIndex>, TokenReaderError> {
+        Self::chunk_index_with_options(prefix, &Options::default())
+    }
+
+    /// As `chunk_index`, with the string dictionary of `options`, if any.
+    pub fn chunk_index_with_options(prefix: &[u8], options: &Options) -> Result<Option<ChunkIndex>, TokenReaderError> {
+        Ok(ChunkedPrelude::read(prefix, options)?
+            .map(|prelude| ChunkIndex {
+                chunks: prelude.ranges()
+            }))
+    }
+
+    /// Create a reader for a chunked file, from its first bytes, which contain at least
+    /// its toplevel chunk, see `chunk_index`.
+    ///
+    /// The contents of lazy fields whose chunk is entirely in `prefix` are read as usual.
+    /// Other contents are skipped, see `TokenReader::skip_lazy_at`, and reported by
+    /// `missing_chunks`, so that they may be read once received, see `receive_chunk`.
+    ///
+    /// As checksums and signatures cover the entire file, they are not verified, and
+    /// a reader requiring a signature fails with `TokenReaderError::BadSignature`. Source
+    /// positions are not available. Files that cannot be read by chunks are read as by
+    /// `with_options`, from `prefix` alone.
+    pub fn with_chunks(prefix: &[u8], options: &Options) -> Result<Self, TokenReaderError> {
+        if options.integrity.verify_key.is_some() {
+            return Err(TokenReaderError::BadSignature);
+        }
+        let prelude = match ChunkedPrelude::read(prefix, options)? {
+            None => return Self::with_options(Cursor::new(prefix), options),
+            Some(prelude) => prelude
+        };
+        let mut chunks = vec![];
+        for range in prelude.ranges() {
+            if range.end <= prefix.len() as u64 {
+                chunks.push(Some(decompress_chunk(&prefix[range.start as usize..range.end as usize])?));
+            } else {
+                chunks.push(None);
+            }
+        }
+        let toplevel = chunks.remove(0)
+            .ok_or_else(|| TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "The toplevel chunk is incomplete")))?;
+        let (tree, holes) = splice_chunks(toplevel, &prelude.index.offsets, chunks)?;
+        debug!(target: "multipart", "Reading {} chunks, {} missing", prelude.index.byte_lens.len(), holes.len());
+        prelude.strings_table.resolve_all()?;
+
+        let implem = ReaderState {
+            strings_table: Rc::new(prelude.strings_table),
+            grammar_table: prelude.grammar_table,
+            grammar: prelude.grammar,
+            positions: None,
+            nan_policy: prelude.nan_policy,
+            varfloats: prelude.varfloats,
+            frames: if prelude.runs { Some(vec![]) } else { None },
+            deferred: None,
+            pending_strings: None,
+            chunks: Some(PendingChunks {
+                holes,
+                skipped: vec![],
+            }),
+            reader: DumpCursor::new(tree)
+        };
+        Self::single_tree(implem, None)
+    }
+
+    fn single_tree(mut implem: ReaderState, manifest: Option<Vec<ArchiveEntry>>) -> Result<Self, TokenReaderError> {
+        if manifest.is_some() {
+            return Err(TokenReaderError::IsArchive)
+        }
+        Ok(TreeTokenReader {
+            grammar: implem.grammar.clone(),
+            positions: implem.positions.take(),
+            owner: Rc::new(RefCell::new(PoisonLock::new(implem)))
+        })
+    }
+
+    /// Create a reader for a file containing a single tree, which records the
+    /// structural interpretation of the tree to `recorder` as it is read.
+    ///
+    /// See `AnnotatedHex`.
+    pub fn with_recorder<R: Read + Seek>(reader: R, options: &Options, recorder: Rc<RefCell<TreeAnnotations>>) -> Result<Self, TokenReaderError> {
+        let (mut implem, manifest) = Self::read_sections(reader, options, false, None)?;
+        implem.reader.record(recorder);
+        Self::single_tree(implem, manifest)
+    }
+
+    /// Create a reader for the entry `name` of an archive.
+    pub fn new_entry<R: Read + Seek>(reader: R, name: &str, options: &Options) -> Result<Self, TokenReaderError> {
+        let (implem, manifest) = Self::read_sections(reader, options, false, None)?;
+        Self::entry(implem, manifest, name)
+    }
+
+    /// Create a reader for the entry `name` of an archive, in which strings
+    /// are only checked and converted when first used.
+    ///
+    /// As the strings table is shared by all entries, this typically avoids
+    /// converting most strings of the archive.
+    pub fn new_entry_with_lazy_strings<R: Read + Seek>(reader: R, name: &str, options: &Options) -> Result<Self, TokenReaderError> {
+        let (implem, manifest) = Self::read_sections(reader, options, true, None)?;
+        Self::entry(implem, manifest, name)
+    }
+
+    fn entry(mut implem: ReaderState, manifest: Option<Vec<ArchiveEntry>>, name: &str) -> Result<Self, TokenReaderError> {
+        let entry = manifest
+            .and_then(|manifest| manifest.into_iter().find(|entry| &*entry.name == name))
+            .ok_or_else(|| TokenReaderError::NoSuchEntry(name.to_string()))?;
+        debug!(target: "multipart", "Reading archive entry {:?}", entry);
+        implem.reader.seek(SeekFrom::Start(entry.offset as u64))
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(TreeTokenReader {
+            grammar: implem.grammar.clone(),
+            positions: None,
+            owner: Rc::new(RefCell::new(PoisonLock::new(implem)))
+        })
+    }
+
+    /// The grammar declared by the file, if any.
+    ///
+    /// Decoders should check that they support this grammar before reading the tree.
+    pub fn grammar(&self) -> Option<&GrammarId> {
+        self.grammar.as_ref()
+    }
+
+    /// The source positions of the tree, if the file was encoded with positions.
+    pub fn positions(&self) -> Option<&SourcePositions> {
+        self.positions.as_ref()
+    }
+
+    /// From now on, skip the contents of lazy fields instead of reading them, recording
+    /// them as `DeferredSubtree`s, see `TokenReader::skip_lazy_at`. The deserializer
+    /// replaces the contents with placeholders.
+    ///
+    /// Contents nested in skipped contents are not recorded.
+    pub fn defer_lazy_subtrees(&mut self) {
+        self.owner.borrow_mut().try(|state| -> Result<(), ()> {
+            state.deferred = Some(vec![]);
+            Ok(())
+        }).unwrap(); // The closure cannot fail.
+    }
+
+    /// The contents of lazy fields skipped since the previous call, in the order in which
+    /// they appear in the tree.
+    pub fn deferred_subtrees(&mut self) -> Vec<DeferredSubtree> {
+        self.owner.borrow_mut().try(|state| -> Result<_, ()> {
+            Ok(match state.deferred {
+                Some(ref mut deferred) => std::mem::replace(deferred, vec![]),
+                None => vec![]
+            })
+        }).unwrap() // The closure cannot fail.
+    }
+
+    /// With a reader created by `with_chunks`, the lazy chunks skipped since the previous
+    /// call because they were missing, in the order in which they appear in the tree.
+    pub fn missing_chunks(&mut self) -> Vec<MissingChunk> {
+        self.owner.borrow_mut().try(|state| -> Result<_, ()> {
+            Ok(match state.chunks {
+                Some(ref mut chunks) => std::mem::replace(&mut chunks.skipped, vec![]),
+                None => vec![]
+            })
+        }).unwrap() // The closure cannot fail.
+    }
+
+    /// Receive a chunk reported by `missing_chunks`, from the bytes of its range in the file,
+    /// see `ChunkIndex`.
+    ///
+    /// Returns the contents of the lazy field, to be read after `seek_to`. An invalid chunk
+    /// does not poison the reader, so that it may be fetched again.
+    pub fn receive_chunk(&mut self, missing: &MissingChunk, stored: &[u8]) -> Result<DeferredSubtree, TokenReaderError> {
+        let contents = decompress_chunk(stored)?;
+        if contents.len() != missing.byte_len as usize {
+            return Err(TokenReaderError::BadLength {
+                expected: missing.byte_len as usize,
+                got: contents.len(),
+            })
+        }
+        self.owner.borrow_mut().try(|state| -> Result<_, TokenReaderError> {
+            // Append the contents to the tree, after the toplevel.
+            let tree = state.reader.reader.get_mut();
+            let offset = tree.len() as u64;
+            tree.extend_from_slice(&contents);
+            Ok(DeferredSubtree {
+                interface: missing.interface.clone(),
+                offset,
+                byte_len: missing.byte_len,
+            })
+        })
+    }
+
+    /// The offset of the next token in the decompressed tree section.
+    pub fn position(&mut self) -> Result<u64, TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            state.reader.seek(SeekFrom::Current(0))
+                .map_err(TokenReaderError::ReadError)
+        })
+    }
+
+    /// Continue reading at `offset` in the decompressed tree section, e.g. the offset
+    /// of a `DeferredSubtree`.
+    pub fn seek_to(&mut self, offset: u64) -> Result<(), TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            if let Some(ref mut frames) = state.frames {
+                frames.clear();
+            }
+            state.reader.seek(SeekFrom::Start(offset))
+                .map(|_| ())
+                .map_err(TokenReaderError::ReadError)
         })
     }
-}
 
-/// A wrapper of Cursor which prints the the binary representation and
-/// handles printing structural interpretation.
-/// The underlying implementation for FileStructurePrinter for TreeTokenReader.
-struct DumpCursor {
-    reader: Cursor<Vec<u8>>,
-    file_format_print_enabled: bool,
-    newline: bool,
-}
-impl DumpCursor {
-    fn new(buf: Vec<u8>) -> DumpCursor {
-        DumpCursor {
-            reader: Cursor::new(buf),
-            file_format_print_enabled: false,
-            newline: false,
-        }
+    /// Take a snapshot of the file, e.g. to read `DeferredSubtree`s in other threads.
+    ///
+    /// This checks and converts all the strings of the file that have not been converted yet.
+    pub fn snapshot(&mut self) -> Result<TreeSnapshot, TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            state.receive_strings()?;
+            let mut strings = Vec::with_capacity(state.strings_table.len());
+            for index in 0..state.strings_table.len() {
+                strings.push(state.strings_table.resolve(index as u32)?);
+            }
+            Ok(TreeSnapshot {
+                tree: Arc::new(state.reader.reader.get_ref().clone()),
+                strings: Arc::new(strings),
+                grammar_table: Arc::new(state.grammar_table.clone()),
+                grammar: state.grammar.clone(),
+                nan_policy: state.nan_policy,
+                varfloats: state.varfloats,
+                runs: state.frames.is_some(),
+            })
+        })
     }
 
-    fn enable_file_structure_print(&mut self) {
-        self.file_format_print_enabled = true;
+    /// Read the container version of a file, without reading the rest of the file.
+    ///
+    /// Files with a legacy version are still read by all constructors, but should
+    /// eventually be upgraded.
+    pub fn container_version<R: Read>(mut reader: R) -> Result<ContainerVersion, TokenReaderError> {
+        Self::read_container_version(&mut reader)
     }
-    fn disable_file_structure_print(&mut self) {
-        self.file_format_print_enabled = false;
+
+    /// The metadata recorded by the encoder, or `None` if the file has no metadata.
+    ///
+    /// Only the headers preceding the metadata are read, so the file needs neither
+    /// its string dictionary nor its keys.
+    pub fn metadata<R: Read>(reader: R) -> Result<Option<Metadata>, TokenReaderError> {
+        Self::read_metadata_and_profile(reader)
+            .map(|(metadata, _)| metadata)
     }
-    fn is_file_structure_print_enabled(&self) -> bool {
-        self.file_format_print_enabled
+
+    /// The startup profile recorded by the encoder, or `None` if the file has no profile.
+    ///
+    /// As with `metadata`, only the headers preceding the profile are read.
+    pub fn profile<R: Read>(reader: R) -> Result<Option<StartupProfile>, TokenReaderError> {
+        Self::read_metadata_and_profile(reader)
+            .map(|(_, profile)| profile)
     }
-    fn prepare_file_structure_column(&mut self) {
-        if self.is_file_structure_print_enabled() {
-            if self.newline {
-                self.newline = false;
-                print!("{:5} : {:<24}# ", "", "");
+
+    fn read_metadata_and_profile<R: Read>(mut reader: R) -> Result<(Option<Metadata>, Option<StartupProfile>), TokenReaderError> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)
+            .map_err(TokenReaderError::ReadError)?;
+        let mut reader = Cursor::new(&data);
+        Self::read_container_version(&mut reader)?;
+        if data[reader.position() as usize..].starts_with(HEADER_GRAMMAR_ID.as_bytes()) {
+            reader.read_const(HEADER_GRAMMAR_ID.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            read_grammar_id(&mut reader)
+                .map_err(TokenReaderError::ReadError)?;
+        }
+        if data[reader.position() as usize..].starts_with(HEADER_STRING_DICTIONARY.as_bytes()) {
+            reader.read_const(HEADER_STRING_DICTIONARY.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            let mut hash = [0; 32];
+            reader.read_exact(&mut hash)
+                .map_err(TokenReaderError::ReadError)?;
+        }
+        let metadata =
+            if data[reader.position() as usize..].starts_with(HEADER_METADATA.as_bytes()) {
+                reader.read_const(HEADER_METADATA.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(read_metadata(&mut reader)
+                    .map_err(TokenReaderError::ReadError)?)
             } else {
-                print!(" ");
-            }
-        };
+                None
+            };
+        let profile =
+            if data[reader.position() as usize..].starts_with(HEADER_PROFILE.as_bytes()) {
+                reader.read_const(HEADER_PROFILE.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(StartupProfile::read(&mut reader)
+                    .map_err(TokenReaderError::ReadError)?)
+            } else {
+                None
+            };
+        Ok((metadata, profile))
     }
-    fn newline_for_file_structure_print(&mut self) {
-        if self.is_file_structure_print_enabled() {
-            println!("");
-            self.newline = true;
-        };
+
+    fn read_container_version<R: Read>(reader: &mut R) -> Result<ContainerVersion, TokenReaderError> {
+        const MAGIC_HEADER: &'static [u8; 5] = b"BINJS";
+        reader.read_const(MAGIC_HEADER)
+            .map_err(TokenReaderError::ReadError)?;
+        ContainerVersion::read(reader)
+            .map_err(TokenReaderError::ReadError)?
+            .ok_or(TokenReaderError::BadHeader)
     }
-    fn newline_for_file_structure_print_if_necessary(&mut self) {
-        if !self.newline {
-            self.newline_for_file_structure_print();
-        };
+
+    /// The names of the entries of an archive, in the order in which they were written,
+    /// or `None` if the file contains a single tree.
+    pub fn entries<R: Read + Seek>(reader: R, options: &Options) -> Result<Option<Vec<SharedString>>, TokenReaderError> {
+        let (_, manifest) = Self::read_sections(reader, options, true, None)?;
+        Ok(manifest.map(|manifest| manifest.into_iter()
+            .map(|entry| entry.name)
+            .collect()))
     }
-}
-impl std::io::Read for DumpCursor {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let offset = self.seek(SeekFrom::Current(0))?;
-        let x = self.reader.read(buf);
-        if self.is_file_structure_print_enabled() {
-            self.newline_for_file_structure_print_if_necessary();
-            print!("{:5} : {:<24}#",
-                   offset,
-                   buf
-                   .iter()
-                   .map(|b| format!("{:02x}", b))
-                   .collect::<Vec<String>>()
-                   .join(" "));
-            self.newline = false;
+
+    /// Extract a single section of a file, for offline analysis.
+    ///
+    /// `name` is one of `SECTION_NAMES`. The file is checked as by `with_options`,
+    /// except that invalid strings are reported in the listing rather than rejected.
+    pub fn section<R: Read + Seek>(reader: R, options: &Options, name: &str) -> Result<Section, TokenReaderError> {
+        use std::fmt::Write;
+
+        let mut raw_sections = vec![];
+        let (implem, manifest) = Self::read_sections(reader, options, true, Some(&mut raw_sections))?;
+        let (name, raw) = raw_sections.into_iter()
+            .find(|&(found, _)| found == name)
+            .ok_or_else(|| TokenReaderError::NoSuchSection(name.to_string()))?;
+
+        // Writing to a `String` cannot fail.
+        let mut listing = String::new();
+        match name {
+            "grammar" => {
+                for (index, description) in implem.grammar_table.map.iter() {
+                    writeln!(listing, "{:5} : {}", index, description.kind).unwrap();
+                }
+            }
+            "strings" => {
+                for index in 0..implem.strings_table.len() as u32 {
+                    match implem.strings_table.resolve(index) {
+                        Ok(None) => writeln!(listing, "{:5} : null", index).unwrap(),
+                        Ok(Some(string)) => writeln!(listing, "{:5} : \"{}\"", index, escaped_wtf8::for_print(&string)).unwrap(),
+                        Err(err) => writeln!(listing, "{:5} : <invalid string: {:?}>", index, err).unwrap(),
+                    }
+                }
+            }
+            "manifest" => {
+                for entry in manifest.iter().flat_map(|entries| entries.iter()) {
+                    writeln!(listing, "{} : offset {}, {} bytes", entry.name, entry.offset, entry.byte_len).unwrap();
+                }
+            }
+            "positions" => {
+                if let Some(ref positions) = implem.positions {
+                    for &(ref path, ref location) in &positions.locations {
+                        writeln!(listing, "{} : {}", path, location).unwrap();
+                    }
+                    for comment in &positions.comments {
+                        writeln!(listing, "{} comment at {} : {:?}", comment.kind, comment.location, comment.text).unwrap();
+                    }
+                }
+            }
+            _ => {
+                // The decompressed tree.
+                for (index, chunk) in implem.reader.reader.get_ref().chunks(16).enumerate() {
+                    writeln!(listing, "{:8} : {}", index * 16, chunk.iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect::<Vec<String>>()
+                        .join(" ")).unwrap();
+                }
+            }
         }
-        x
-    }
-}
-impl std::io::Seek for DumpCursor {
-    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
-        self.reader.seek(pos)
+        Ok(Section {
+            name,
+            raw,
+            listing,
+        })
     }
-}
 
-/// The state of the `TreeTokenReader`.
-///
-/// Use a `PoisonLock` to access this state.
-pub struct ReaderState {
-    reader: DumpCursor,
-    pub strings_table: Table<Option<SharedString>>,
-    pub grammar_table: Table<NodeDescription>,
-}
+    /// Read a string, without checking or converting it.
+    ///
+    /// This is the counterpart of `TokenReader::string_at` for readers created with
+    /// lazy strings, although it may be used with any reader.
+    pub fn string_handle_at(&mut self, _path: &Path) -> Result<Option<StringHandle>, TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            state.receive_strings()?;
+            let index = state.reader.read_varnum()
+                .map_err(TokenReaderError::ReadError)?;
+            match state.strings_table.range(index)? {
+                None => {
+                    print_file_structure!(state.reader, "string=None");
+                    Ok(None)
+                }
+                Some((start, stop)) => {
+                    print_file_structure!(state.reader, "string=#{}", index);
+                    Ok(Some(StringHandle {
+                        table: state.strings_table.clone(),
+                        index,
+                        start,
+                        stop,
+                    }))
+                }
+            }
+        })
+    }
 
-pub struct TreeTokenReader {
-    // Shared with all children.
-    owner: Rc<RefCell<PoisonLock<ReaderState>>>,
-}
+    /// Read all the sections of a file, returning the manifest if the file is an archive.
+    ///
+    /// If the file has a checksum section, it is verified before returning, unless
+    /// specified otherwise by `options.integrity`. Likewise, if it specifies a public
+    /// key, the signature is verified before returning. If the content sections are
+    /// encrypted, they are decrypted with the key it specifies.
+    ///
+    /// Unless `lazy_strings` is set, all strings are checked and converted before returning.
+    ///
+    /// If `raw_sections` is specified, the name and bytes of each section are appended to it.
+    fn read_sections<R: Read + Seek>(mut source: R, options: &Options, lazy_strings: bool, raw_sections: Option<&mut Vec<(&'static str, Vec<u8>)>>) -> Result<(ReaderState, Option<Vec<ArchiveEntry>>), TokenReaderError> {
+        // Load the file to memory, so that we may compute checksums.
+        let mut data = vec![];
+        source.read_to_end(&mut data)
+            .map_err(TokenReaderError::ReadError)?;
+        let mut reader = Cursor::new(&data);
 
+        // The name and offset of each section.
+        let mut sections = vec![];
 
-impl TreeTokenReader {
-    pub fn new<R: Read + Seek>(mut reader: R) -> Result<Self, TokenReaderError> {
         // Check magic headers.
-        const MAGIC_HEADER: &'static [u8; 5] = b"BINJS";
-        const FORMAT_VERSION: u32 = 1;
+        let ContainerVersion { number, is_archive, varfloats, extended, checksum, nan_policy } = Self::read_container_version(&mut reader)?;
+        debug!(target: "multipart", "Container version: {}", number);
 
-        reader.read_const(MAGIC_HEADER)
-            .map_err(TokenReaderError::ReadError)?;
+        // Read grammar identifier, if any.
+        let grammar =
+            if data[reader.position() as usize..].starts_with(HEADER_GRAMMAR_ID.as_bytes()) {
+                reader.read_const(HEADER_GRAMMAR_ID.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                let grammar = read_grammar_id(&mut reader)
+                    .map_err(TokenReaderError::ReadError)?
+                    .with_extended(extended);
+                debug!(target: "multipart", "Grammar: {}", grammar);
+                Some(grammar)
+            } else if extended {
+                // Vendor extensions require a grammar identifier.
+                return Err(TokenReaderError::BadHeader);
+            } else {
+                None
+            };
 
-        let version = reader.read_varnum()
-            .map_err(TokenReaderError::ReadError)?;
+        // Read string dictionary identifier, if any.
+        let string_dictionary =
+            if data[reader.position() as usize..].starts_with(HEADER_STRING_DICTIONARY.as_bytes()) {
+                Some(read_string_dictionary(&mut reader, options)?)
+            } else {
+                None
+            };
+
+        // Skip metadata, if any.
+        if data[reader.position() as usize..].starts_with(HEADER_METADATA.as_bytes()) {
+            reader.read_const(HEADER_METADATA.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            let metadata = read_metadata(&mut reader)
+                .map_err(TokenReaderError::ReadError)?;
+            debug!(target: "multipart", "Metadata: {:?}", metadata);
+        }
 
-        if version != FORMAT_VERSION {
-            return Err(TokenReaderError::BadHeader)
+        // Skip startup profile, if any.
+        if data[reader.position() as usize..].starts_with(HEADER_PROFILE.as_bytes()) {
+            reader.read_const(HEADER_PROFILE.as_bytes())
+                .map_err(TokenReaderError::ReadError)?;
+            let profile = StartupProfile::read(&mut reader)
+                .map_err(TokenReaderError::ReadError)?;
+            debug!(target: "multipart", "Startup profile: {} functions executed", profile.executed().len());
         }
 
+        // Read signature, if any.
+        let signature_start = reader.position() as usize;
+        let signature =
+            if data[reader.position() as usize..].starts_with(HEADER_SIGNATURE.as_bytes()) {
+                reader.read_const(HEADER_SIGNATURE.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                let mut signature = vec![0; bytes::signature::SIGNATURE_LENGTH];
+                reader.read_exact(&mut signature)
+                    .map_err(TokenReaderError::ReadError)?;
+                Some(signature)
+            } else {
+                None
+            };
+
+        // Decrypt content sections, if they are encrypted.
+        let decrypted;
+        let mut encrypted = None;
+        let is_encrypted = data[reader.position() as usize..].starts_with(HEADER_ENCRYPTED.as_bytes());
+        let content : &[u8] =
+            if is_encrypted {
+                reader.read_const(HEADER_ENCRYPTED.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                let mut nonce = [0; bytes::encryption::NONCE_LENGTH];
+                reader.read_exact(&mut nonce)
+                    .map_err(TokenReaderError::ReadError)?;
+                let byte_len = reader.read_varnum()
+                    .map_err(TokenReaderError::ReadError)? as usize;
+                let start = reader.position() as usize;
+                if start + byte_len > data.len() {
+                    return Err(TokenReaderError::BadLength {
+                        expected: byte_len,
+                        got: data.len() - start
+                    })
+                }
+                let key = options.integrity.encryption_key
+                    .ok_or(TokenReaderError::BadEncryption)?;
+                decrypted = bytes::encryption::decrypt(&key, &nonce, &data[start..start + byte_len])
+                    .map_err(|_| TokenReaderError::BadEncryption)?;
+                encrypted = Some(&data[start..start + byte_len]);
+                reader.set_position((start + byte_len) as u64);
+                &decrypted
+            } else {
+                &data[reader.position() as usize..]
+            };
+        let mut content_reader = Cursor::new(content);
+
         // At this stage, we could start parallelizing reads between grammar table and strings table, possibly even the tree.
-        reader.read_const(HEADER_GRAMMAR_TABLE.as_bytes())
+        sections.push(("grammar", content_reader.position() as usize));
+        content_reader.read_const(HEADER_GRAMMAR_TABLE.as_bytes())
             .map_err(TokenReaderError::ReadError)?;
 
         // Read grammar table
         let grammar_deserializer = TableDeserializer {
             deserializer: NodeDescriptionDeserializer
         };
-        let grammar_table = Compression::decompress(&mut reader, &grammar_deserializer)
+        let grammar_table = Compression::decompress(&mut content_reader, &grammar_deserializer)
             .map_err(TokenReaderError::BadCompression)?;
         debug!(target: "multipart", "Grammar table: {:?}",
             grammar_table.map);
 
-        // Read strings table
-        reader.read_const(HEADER_STRINGS_TABLE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
-        let strings_deserializer = TableDeserializer {
-            deserializer: None /* Option<SharedString> */
-        };
-        let strings_table = Compression::decompress(&mut reader, &strings_deserializer)
-            .map_err(TokenReaderError::BadCompression)?;
+        // Read strings table, unless it follows the tree.
+        let split_prelude = tree_header(&content[content_reader.position() as usize..]).is_some();
+        let mut strings_table =
+            if split_prelude {
+                None
+            } else {
+                sections.push(("strings", content_reader.position() as usize));
+                let front_coded = content[content_reader.position() as usize..].starts_with(HEADER_STRINGS_TABLE_FRONT_CODED.as_bytes());
+                Some(read_strings_table(&mut content_reader, front_coded, string_dictionary.as_ref())?)
+            };
+
+        // Read manifest, if this is an archive.
+        let manifest =
+            if is_archive {
+                sections.push(("manifest", content_reader.position() as usize));
+                content_reader.read_const(HEADER_MANIFEST.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                let manifest = Compression::decompress(&mut content_reader, &ManifestDeserializer)
+                    .map_err(TokenReaderError::BadCompression)?;
+                debug!(target: "multipart", "Manifest: {:?}", manifest);
+                Some(manifest)
+            } else {
+                None
+            };
 
         // Decompress tree section to memory (we could as well stream it)
-        reader.read_const(HEADER_TREE.as_bytes())
-            .map_err(TokenReaderError::ReadError)?;
-        let decompressed_tree = Compression::decompress(&mut reader, &BufDeserializer)
-            .map_err(TokenReaderError::BadCompression)?;
+        sections.push(("tree", content_reader.position() as usize));
+        let (header, runs, chunked) = tree_header(&content[content_reader.position() as usize..])
+            .unwrap_or((HEADER_TREE, false, false)); // Rejected by `read_tree_section`.
+        let decompressed_tree = read_tree_section(&mut content_reader, header, chunked)?;
+
+        if split_prelude {
+            sections.push(("strings", content_reader.position() as usize));
+            let front_coded = content[content_reader.position() as usize..].starts_with(HEADER_STRINGS_TABLE_FRONT_CODED.as_bytes());
+            strings_table = Some(read_strings_table(&mut content_reader, front_coded, string_dictionary.as_ref())?);
+        }
+        let strings_table = strings_table
+            .unwrap(); // Read either before or after the tree.
+
+        // Read source positions, if any.
+        let positions =
+            if content[content_reader.position() as usize..].starts_with(HEADER_POSITIONS.as_bytes()) {
+                sections.push(("positions", content_reader.position() as usize));
+                content_reader.read_const(HEADER_POSITIONS.as_bytes())
+                    .map_err(TokenReaderError::ReadError)?;
+                let positions = Compression::decompress(&mut content_reader, &PositionsDeserializer)
+                    .map_err(TokenReaderError::BadCompression)?;
+                debug!(target: "multipart", "Source positions: {} locations, {} comments",
+                    positions.locations.len(),
+                    positions.comments.len());
+                Some(positions)
+            } else {
+                None
+            };
+
+        let content_end = content_reader.position() as usize;
+        if !is_encrypted {
+            let position = reader.position();
+            reader.set_position(position + content_end as u64);
+        }
+
+        verify_integrity(&data, reader.position() as usize, checksum, content, encrypted.unwrap_or(content), content_end, &sections, signature_start, signature, &options.integrity)?;
+
+        if let Some(raw_sections) = raw_sections {
+            let ends = sections.iter()
+                .skip(1)
+                .map(|&(_, start)| start)
+                .chain(std::iter::once(content_end));
+            for (&(name, start), end) in sections.iter().zip(ends) {
+                raw_sections.push((name, content[start..end].to_vec()));
+            }
+        }
+
+        if !lazy_strings {
+            strings_table.resolve_all()?;
+        }
+
         let implem = ReaderState {
-            strings_table,
+            strings_table: Rc::new(strings_table),
             grammar_table,
+            grammar,
+            positions,
+            nan_policy,
+            varfloats,
+            frames: if runs { Some(vec![]) } else { None },
+            deferred: None,
+            pending_strings: None,
+            chunks: None,
             reader: DumpCursor::new(decompressed_tree)
         };
 
-        Ok(TreeTokenReader {
-            owner: Rc::new(RefCell::new(PoisonLock::new(implem)))
-        })
+        Ok((implem, manifest))
     }
 }
 
@@ -295,22 +2249,18 @@ impl TokenReader for TreeTokenReader {
         self.owner.borrow_mut().try(|state| {
             let index = state.reader.read_varnum()
                 .map_err(TokenReaderError::ReadError)?;
-            match state.strings_table.get(index) {
-                Some(result) => {
-                    debug!(target: "multipart", "Reading string {:?} => {:?}", index, result);
-                    match result {
-                        Some(s) => {
-                            print_file_structure!(state.reader, "string=\"{}\"", escaped_wtf8::for_print(s));
-                            Ok(Some(s.clone()))
-                        },
-                        None => {
-                            print_file_structure!(state.reader, "string=None");
-                            Ok(None)
-                        }
-                    }
+            state.receive_string(index)?;
+            let result = state.strings_table.resolve(index)?;
+            debug!(target: "multipart", "Reading string {:?} => {:?}", index, result);
+            match result {
+                Some(ref s) => {
+                    print_file_structure!(state.reader, "string=\"{}\"", escaped_wtf8::for_print(s));
+                },
+                None => {
+                    print_file_structure!(state.reader, "string=None");
                 }
-                None => Err(TokenReaderError::BadStringIndex(index))
             }
+            Ok(result)
         })
     }
 
@@ -318,11 +2268,18 @@ impl TokenReader for TreeTokenReader {
     /// Read a single `f64`. Note that all numbers are `f64`.
     fn float_at(&mut self, _path: &Path) -> Result<Option<f64>, TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
-            let mut buf : [u8; 8] = unsafe { std::mem::uninitialized() };
-            state.reader.read(&mut buf)
-                .map_err(TokenReaderError::ReadError)?;
-            let result = bytes::float::float_of_bytes(&buf);
-            debug!(target: "multipart", "Reading float {:?} => {:?}", buf, result);
+            let result = if state.varfloats {
+                let nan_policy = state.nan_policy;
+                state.reader.read_maybe_varfloat(nan_policy)
+                    .map_err(TokenReaderError::ReadError)?
+            } else {
+                let mut buf : [u8; 8] = unsafe { std::mem::uninitialized() };
+                state.reader.read(&mut buf)
+                    .map_err(TokenReaderError::ReadError)?;
+                bytes::float::float_of_bytes_with_policy(&buf, state.nan_policy)
+                    .map_err(TokenReaderError::ReadError)?
+            };
+            debug!(target: "multipart", "Reading float => {:?}", result);
             match result {
                 Some(f) => {
                     print_file_structure!(state.reader, "float={}", f);
@@ -335,6 +2292,40 @@ impl TokenReader for TreeTokenReader {
         })
     }
 
+    fn big_int_at(&mut self, _path: &Path) -> Result<Option<BigInt>, TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            let result = state.reader.read_maybe_varbigint()
+                .map_err(TokenReaderError::ReadError)?;
+            debug!(target: "multipart", "Reading big_int => {:?}", result);
+            match result {
+                Some(ref value) => {
+                    print_file_structure!(state.reader, "big_int={}", value);
+                },
+                None => {
+                    print_file_structure!(state.reader, "big_int=None");
+                }
+            };
+            Ok(result.map(BigInt::from_string))
+        })
+    }
+
+    fn reg_exp_flags_at(&mut self, _path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            let result = state.reader.read_maybe_reg_exp_flags()
+                .map_err(TokenReaderError::ReadError)?;
+            debug!(target: "multipart", "Reading reg_exp_flags => {:?}", result);
+            match result {
+                Some(ref value) => {
+                    print_file_structure!(state.reader, "reg_exp_flags={}", value.as_str());
+                },
+                None => {
+                    print_file_structure!(state.reader, "reg_exp_flags=None");
+                }
+            };
+            Ok(result)
+        })
+    }
+
     /// Read a single `u32`.
     fn unsigned_long_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
@@ -383,6 +2374,50 @@ impl TokenReader for TreeTokenReader {
         })
     }
 
+    /// If `defer_lazy_subtrees` has been called, skip the contents.
+    ///
+    /// If the contents belong to a missing chunk, skip them, as they are not there.
+    fn skip_lazy_at(&mut self, byte_len: u32, path: &Path) -> Result<bool, TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            if state.deferred.is_none() && state.chunks.is_none() {
+                return Ok(false);
+            }
+            let interface = path.get(0)
+                .map(|item| item.interface().clone())
+                .ok_or(TokenReaderError::InvalidValue)?;
+            let offset = state.reader.seek(SeekFrom::Current(0))
+                .map_err(TokenReaderError::ReadError)?;
+            if let Some(ref mut chunks) = state.chunks {
+                if let Ok(hole) = chunks.holes.binary_search_by_key(&offset, |&(offset, _)| offset) {
+                    let chunk = chunks.holes[hole].1;
+                    debug!(target: "multipart", "Skipping missing chunk #{} of {} at {}", chunk, interface.as_str(), offset);
+                    print_file_structure!(state.reader, "missing chunk #{}", chunk);
+                    chunks.skipped.push(MissingChunk {
+                        chunk,
+                        interface,
+                        byte_len,
+                    });
+                    return Ok(true);
+                }
+            }
+            if state.deferred.is_none() {
+                return Ok(false);
+            }
+            state.reader.seek(SeekFrom::Current(byte_len as i64))
+                .map_err(TokenReaderError::ReadError)?;
+            debug!(target: "multipart", "Deferring {} bytes of {} at {}", byte_len, interface.as_str(), offset);
+            print_file_structure!(state.reader, "deferred");
+            if let Some(ref mut deferred) = state.deferred {
+                deferred.push(DeferredSubtree {
+                    interface,
+                    offset,
+                    byte_len,
+                });
+            }
+            Ok(true)
+        })
+    }
+
     /// Start reading a list.
     ///
     /// Returns an extractor for that list and the number of elements
@@ -393,10 +2428,23 @@ impl TokenReader for TreeTokenReader {
             let list_len = state.reader.read_varnum()
                 .map_err(TokenReaderError::ReadError)?;
             debug!(target: "multipart", "Reading list with {} items", list_len);
+            if let Some(ref mut frames) = state.frames {
+                frames.push(Frame::List(None));
+            }
             Ok(list_len)
         })
     }
 
+    fn exit_list_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            match state.frames.as_mut().map(|frames| frames.pop()) {
+                None | Some(Some(Frame::List(None))) => Ok(()),
+                // A run that extends past the end of its list.
+                _ => Err(TokenReaderError::InvalidValue)
+            }
+        })
+    }
+
     /// Start reading a tagged tuple.
     ///
     /// Returns the tag name, `None` for fields and a
@@ -404,18 +2452,60 @@ impl TokenReader for TreeTokenReader {
     /// to that tuple. The sub-extractor MUST be consumed entirely.
     fn enter_tagged_tuple_at(&mut self, _path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
         self.owner.borrow_mut().try(|state| {
-            let index = state.reader.read_varnum()
-                .map_err(TokenReaderError::ReadError)?;
-            let description = state.grammar_table.get(index)
-                .ok_or(TokenReaderError::BadKindIndex(index))?;
-
-            let tag = InterfaceName(description.kind.clone());
+            // In a run, only the first item carries the tag.
+            let continued = match state.frames.as_mut().and_then(|frames| frames.last_mut()) {
+                Some(&mut Frame::List(ref mut run)) => match run.take() {
+                    Some((tag, remaining)) => {
+                        if remaining > 1 {
+                            *run = Some((tag.clone(), remaining - 1));
+                        }
+                        Some(tag)
+                    }
+                    None => None
+                },
+                _ => None
+            };
+            let tag = match continued {
+                Some(tag) => tag,
+                None => {
+                    let value = state.reader.read_varnum()
+                        .map_err(TokenReaderError::ReadError)?;
+                    let index = if state.frames.is_some() { value >> 1 } else { value };
+                    let description = state.grammar_table.get(index)
+                        .ok_or(TokenReaderError::BadKindIndex(index))?;
+                    let tag = InterfaceName(description.kind.clone());
+                    if state.frames.is_some() && value & 1 == 1 {
+                        let following = state.reader.read_varnum()
+                            .map_err(TokenReaderError::ReadError)?;
+                        match state.frames.as_mut().and_then(|frames| frames.last_mut()) {
+                            Some(&mut Frame::List(ref mut run)) if following > 0 => {
+                                *run = Some((tag.clone(), following));
+                            }
+                            // Runs only make sense for items of lists.
+                            _ => return Err(TokenReaderError::InvalidValue)
+                        }
+                    }
+                    tag
+                }
+            };
+            if let Some(ref mut frames) = state.frames {
+                frames.push(Frame::TaggedTuple);
+            }
             debug!(target: "multipart", "Reading tagged tuple with kind \"{}\"",
                 tag.as_shared_string());
             Ok((tag, None))
         })
     }
 
+    fn exit_tagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.owner.borrow_mut().try(|state| {
+            match state.frames.as_mut().map(|frames| frames.pop()) {
+                None | Some(Some(Frame::TaggedTuple)) => Ok(()),
+                _ => Err(TokenReaderError::InvalidValue)
+            }
+        })
+    }
+
     /// Start reading an untagged tuple. The sub-extractor MUST
     /// be consumed entirely.
     fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
@@ -453,6 +2543,12 @@ impl FileStructurePrinter for TreeTokenReader {
             Ok(())
         });
     }
+    fn print_file_structure_label(&mut self, label: std::fmt::Arguments) {
+        let _: Result<(),()> = self.owner.borrow_mut().try(|state| {
+            state.reader.print_file_structure_label(label);
+            Ok(())
+        });
+    }
     fn newline_for_file_structure_print(&mut self) {
         let _: Result<(),()> = self.owner.borrow_mut().try(|state| {
             state.reader.newline_for_file_structure_print();
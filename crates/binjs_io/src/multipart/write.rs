@@ -1,12 +1,17 @@
 use bytes;
+use bytes::bigint::*;
 use bytes::compress::*;
+use bytes::float::{ NaNPolicy, WriteVarFloat };
+use bytes::regexp::*;
 use bytes::varnum::*;
 use io::*;
-use ::{ CompressionTarget, TokenWriterError };
+use ::{ CompressionTarget, GrammarId, TokenWriterError };
 use escaped_wtf8;
 use multipart::*;
+use positions::SourcePositions;
+use startup::StartupProfile;
 
-use binjs_shared::{ FieldName, InterfaceName, SharedString };
+use binjs_shared::{ BigInt, FieldName, InterfaceName, RegExpFlags, SharedString };
 
 use std;
 use std::collections::{ HashMap, HashSet };
@@ -154,6 +159,18 @@ impl<Entry> WriterTable<Entry> where Entry: Eq + Hash + Clone + Serializable + F
 
 
 impl<Entry> WriterTable<Entry> where Entry: Eq + Hash + Clone + Serializable + FormatInTable + Debug + Ord {
+    /// Sort entries by number of uses and entry data specific ordering,
+    /// assigning their `TableIndex`.
+    fn sorted(&self) -> Vec<&TableEntry<Entry>> {
+        let mut contents : Vec<_> = self.map.values().collect();
+        contents.sort_unstable();
+        for i in 0..contents.len() {
+            let mut borrow = contents[i].index.index.borrow_mut();
+            *borrow = Some(i as u32);
+        }
+        contents
+    }
+
     /// Get an entry from the header.
     ///
     /// The number of entries is incremented by 1.
@@ -198,16 +215,7 @@ impl<Entry> WriterTable<Entry> where Entry: Eq + Hash + Clone + Serializable + F
 impl<Entry> Serializable for WriterTable<Entry> where Entry: Eq + Hash + Clone + Serializable + FormatInTable + Debug + Ord {
     fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
         let mut total = 0;
-
-        // Sort entries by number of uses and entry data specific ordering
-        let mut contents : Vec<_> = self.map.values().collect();
-        contents.sort_unstable();
-
-        // Assign TableIndex
-        for i in 0..contents.len() {
-            let mut borrow = contents[i].index.index.borrow_mut();
-            *borrow = Some(i as u32);
-        }
+        let mut contents = self.sorted();
 
         // Serialize each entry
         let mut serialized = Vec::with_capacity(contents.len());
@@ -243,6 +251,72 @@ impl<Entry> Serializable for WriterTable<Entry> where Entry: Eq + Hash + Clone +
 }
 
 
+/// The WTF-8 bytes of `data`, if it is a string of at least `threshold` bytes,
+/// which is written as a blob rather than in the strings table.
+fn as_blob(data: &Option<SharedString>, threshold: Option<usize>) -> Option<Vec<u8>> {
+    match (data.as_ref(), threshold) {
+        // Escaping never makes a string shorter.
+        (Some(data), Some(threshold)) if data.len() >= threshold => {
+            let bytes = escaped_wtf8::unescape(data.deref().as_bytes());
+            if bytes.len() >= threshold {
+                Some(bytes.into_owned())
+            } else {
+                None
+            }
+        }
+        _ => None
+    }
+}
+
+impl WriterTable<Option<SharedString>> {
+    /// As `write`, but the strings of at least `blob_threshold` bytes are replaced with
+    /// `BLOB_PLACEHOLDER`, and their bytes appended to `blobs`, in the order of the table.
+    fn write_with_blobs<W: Write>(&self, blob_threshold: Option<usize>, out: &mut W, blobs: &mut Vec<Vec<u8>>) -> Result<usize, std::io::Error> {
+        let contents = self.sorted();
+        let mut total = out.write_varnum(contents.len() as u32)?;
+        for entry in contents {
+            total += match as_blob(&entry.data, blob_threshold) {
+                None => entry.data.write(out)?,
+                Some(blob) => {
+                    blobs.push(blob);
+                    let total = out.write_varnum(BLOB_PLACEHOLDER.len() as u32)?;
+                    out.write_all(&BLOB_PLACEHOLDER)?;
+                    total + BLOB_PLACEHOLDER.len()
+                }
+            };
+        }
+        Ok(total)
+    }
+
+    /// A front-coded strings table is serialized as
+    ///
+    /// - the window of the front coding (varnum);
+    /// - number of entries (varnum);
+    /// - for each entry, the WTF-8 bytes of the entry, front-coded (see `bytes::frontcoding`),
+    ///   with the null string represented as [255, 0] and blobs as `BLOB_PLACEHOLDER`.
+    ///
+    /// The bytes of the blobs are appended to `blobs`, as in `write_with_blobs`.
+    fn write_front_coded<W: Write>(&self, window: usize, blob_threshold: Option<usize>, out: &mut W, blobs: &mut Vec<Vec<u8>>) -> Result<usize, std::io::Error> {
+        let contents = self.sorted();
+        let mut total = out.write_varnum(window as u32)?;
+        total += out.write_varnum(contents.len() as u32)?;
+        let mut encoder = bytes::frontcoding::FrontEncoder::new(window);
+        for entry in contents {
+            if let Some(blob) = as_blob(&entry.data, blob_threshold) {
+                blobs.push(blob);
+                total += encoder.write(&BLOB_PLACEHOLDER, out)?;
+                continue;
+            }
+            total += match entry.data {
+                None => encoder.write(&[255, 0], out)?,
+                Some(ref data) => encoder.write(&escaped_wtf8::unescape(data.deref().as_bytes()), out)?,
+            };
+        }
+        Ok(total)
+    }
+}
+
+
 #[derive(PartialOrd, Ord, PartialEq, Eq, Clone, Hash, Debug)] // FIXME: Clone shouldn't be necessary. Sigh.
 pub struct NodeDescription {
     kind: InterfaceName,
@@ -267,6 +341,9 @@ impl FormatInTable for NodeDescription {
     const HAS_LENGTH_INDEX : bool = false;
 }
 
+/// Runs of list items with the same tag shorter than this are not worth a run header.
+const MIN_RUN_LEN: usize = 3;
+
 /// The tree, as it is being built.
 enum UnresolvedTreeNode {
     /// An index into the table of strings.
@@ -275,6 +352,10 @@ enum UnresolvedTreeNode {
     /// An index into the table of nodes.
     UnresolvedNodeIndex(TableIndex<NodeDescription>),
 
+    /// An index into the table of nodes, in a tree with runs, with the number
+    /// of subsequent items in the run if this node starts a run.
+    UnresolvedRunIndex(TableIndex<NodeDescription>, Option<u32>),
+
     /// A subtree, preceded by the number of bytes it takes.
     UnresolvedOffset(Option<Box<UnresolvedTree>>),
     Tuple(Vec<Rc<UnresolvedTree>>),
@@ -288,6 +369,9 @@ struct UnresolvedTree {
 enum ResolvedTree {
     Tuple(Vec<ResolvedTree>),
     Encoded(Vec<u8>),
+
+    /// The contents of a lazy field.
+    Lazy(Box<ResolvedTree>),
 }
 
 impl ResolvedTree {
@@ -304,6 +388,27 @@ impl ResolvedTree {
                 }
                 Ok(total)
             }
+            ResolvedTree::Lazy(ref contents) => contents.write(out)
+        }
+    }
+
+    /// Write the tree, except for the contents of the outermost lazy fields, which are
+    /// appended to `chunks`, along with the offset in `out` at which they belong.
+    fn write_chunked(&self, out: &mut Vec<u8>, chunks: &mut Vec<(usize, Vec<u8>)>) -> Result<(), std::io::Error> {
+        match *self {
+            ResolvedTree::Encoded(ref buf) => out.write_all(&*buf),
+            ResolvedTree::Tuple(ref items) => {
+                for item in items {
+                    item.write_chunked(out, chunks)?;
+                }
+                Ok(())
+            }
+            ResolvedTree::Lazy(ref contents) => {
+                let mut chunk = vec![];
+                contents.write(&mut chunk)?;
+                chunks.push((out.len(), chunk));
+                Ok(())
+            }
         }
     }
 }
@@ -329,6 +434,21 @@ impl UnresolvedTree {
                 let byte_len : usize = buf.write_varnum(index).unwrap(); // This operation can't fail.
 
 
+                (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(buf))
+            }
+            UnresolvedRunIndex(index, run) => {
+                let index = index.index()
+                    .expect("Node index should have been resolved by now.");
+                let mut buf = Vec::with_capacity(4);
+                let mut byte_len : usize = buf.write_varnum(index * 2 + run.is_some() as u32).unwrap(); // This operation can't fail.
+                if let Some(following) = run {
+                    byte_len += buf.write_varnum(following).unwrap(); // This operation can't fail.
+                    stats.runs.entries += 1;
+                    stats.runs.own_bytes += byte_len;
+                    stats.runs.total_bytes += byte_len;
+                    stats.runs.shallow_bytes += byte_len;
+                }
+
                 (byte_len as u32, byte_len as u32, ResolvedTree::Encoded(buf))
             }
             UnresolvedOffset(None) => {
@@ -340,7 +460,7 @@ impl UnresolvedTree {
                 let offset_byte_len = buf.write_varnum(sub_byte_len).unwrap(); // This operation can't fail.
                 let offset_resolved = ResolvedTree::Encoded(buf);
 
-                (sub_byte_len + offset_byte_len as u32, offset_byte_len as u32, ResolvedTree::Tuple(vec![offset_resolved, ResolvedTree::Tuple(vec![sub_resolved])]))
+                (sub_byte_len + offset_byte_len as u32, offset_byte_len as u32, ResolvedTree::Tuple(vec![offset_resolved, ResolvedTree::Lazy(Box::new(sub_resolved))]))
             }
             Tuple(mut subtrees) => {
                 let mut byte_len = 0;
@@ -446,6 +566,18 @@ impl UnresolvedTree {
                 stats.float.total_bytes += total;
                 stats.float.shallow_bytes += own;
             }
+            Nature::BigInt => {
+                stats.big_int.entries += 1;
+                stats.big_int.own_bytes += own;
+                stats.big_int.total_bytes += total;
+                stats.big_int.shallow_bytes += own;
+            }
+            Nature::RegExpFlags => {
+                stats.reg_exp_flags.entries += 1;
+                stats.reg_exp_flags.own_bytes += own;
+                stats.reg_exp_flags.total_bytes += total;
+                stats.reg_exp_flags.shallow_bytes += own;
+            }
             Nature::UnsignedLong => {
                 stats.unsigned_long.entries += 1;
                 stats.unsigned_long.own_bytes += own;
@@ -474,6 +606,8 @@ enum Nature {
     TaggedTuple(TableIndex<NodeDescription>),
     TaggedTupleHeader(TableIndex<NodeDescription>),
     Float,
+    BigInt,
+    RegExpFlags,
     UnsignedLong,
     Bool,
     String(TableIndex<Option<SharedString>>),
@@ -534,9 +668,200 @@ impl TreeTokenWriter {
             grammar_table: WriterTable::new(),
             strings_table: WriterTable::new(),
             root: None,
+            entries: vec![],
             data: Vec::with_capacity(1024),
             targets,
-            statistics: Statistics::default()
+            statistics: Statistics::default(),
+            checksum: false,
+            sign_key: None,
+            encryption_key: None,
+            grammar: None,
+            positions: None,
+            front_coding: None,
+            blob_threshold: None,
+            blob_compression: Compression::Identity,
+            nan_policy: NaNPolicy::default(),
+            varfloats: false,
+            runs: false,
+            split_prelude: false,
+            chunks: false,
+            string_dictionary: None,
+            metadata: None,
+            profile: None,
+            reproducible: false,
+            shared_statistics: None,
+            section_starts: vec![],
+        }
+    }
+
+    /// If specified, write the identifier of the grammar used to encode, so
+    /// that decoders may reject files encoded with a grammar they do not support.
+    pub fn with_grammar(self, grammar: Option<GrammarId>) -> Self {
+        TreeTokenWriter {
+            grammar,
+            ..self
+        }
+    }
+
+    /// If specified, write the source positions of the tree in a side section.
+    ///
+    /// Ignored for archives.
+    pub fn with_positions(self, positions: Option<SourcePositions>) -> Self {
+        TreeTokenWriter {
+            positions,
+            ..self
+        }
+    }
+
+    /// If specified, sign the content sections with this Ed25519 secret key.
+    pub fn with_sign_key(self, sign_key: Option<[u8; 32]>) -> Self {
+        TreeTokenWriter {
+            sign_key,
+            ..self
+        }
+    }
+
+    /// If specified, encrypt the content sections with this AES-256-GCM key.
+    pub fn with_encryption_key(self, encryption_key: Option<[u8; 32]>) -> Self {
+        TreeTokenWriter {
+            encryption_key,
+            ..self
+        }
+    }
+
+    /// If specified, front-code the strings table, sharing prefixes between each
+    /// string and the strings among the previous `window` entries of the table.
+    pub fn with_front_coding(self, front_coding: Option<usize>) -> Self {
+        TreeTokenWriter {
+            front_coding,
+            ..self
+        }
+    }
+
+    /// If specified, write the strings of at least `blob_threshold` bytes as blobs,
+    /// outside of the strings table, compressed with `blob_compression` rather than
+    /// with the compression of the strings table.
+    ///
+    /// Readers stitch the blobs back into the strings table.
+    pub fn with_blobs(self, blob_threshold: Option<usize>, blob_compression: Compression) -> Self {
+        TreeTokenWriter {
+            blob_threshold,
+            blob_compression,
+            ..self
+        }
+    }
+
+    /// How NaN values are written. Readers must use the same policy.
+    pub fn with_nan_policy(self, nan_policy: NaNPolicy) -> Self {
+        TreeTokenWriter {
+            nan_policy,
+            ..self
+        }
+    }
+
+    /// If `true`, represent floats as varfloats, which makes integers and short
+    /// decimal numbers shorter, at the cost of a container version unknown to
+    /// older readers.
+    pub fn with_varfloats(self, varfloats: bool) -> Self {
+        TreeTokenWriter {
+            varfloats,
+            ..self
+        }
+    }
+
+    /// If `true`, only write the tag of the first item of each run of list items
+    /// with the same tag.
+    pub fn with_runs(self, runs: bool) -> Self {
+        TreeTokenWriter {
+            runs,
+            ..self
+        }
+    }
+
+    /// If `true`, write the strings table after the tree rather than before, so that
+    /// readers may decompress the tree before reading the strings table, see
+    /// `TreeTokenReader::with_deferred_strings`.
+    ///
+    /// Ignored for archives and chunked trees, whose clients need the strings table
+    /// before the first chunk.
+    pub fn with_split_prelude(self, split_prelude: bool) -> Self {
+        TreeTokenWriter {
+            split_prelude,
+            ..self
+        }
+    }
+
+    /// If `true`, compress the toplevel of the tree and the contents of each outermost
+    /// lazy field as independent chunks, listed in an index, so that clients may fetch
+    /// some of the chunks with HTTP range requests, see `TreeTokenReader::with_chunks`.
+    ///
+    /// Ignored for archives.
+    pub fn with_chunks(self, chunks: bool) -> Self {
+        TreeTokenWriter {
+            chunks,
+            ..self
+        }
+    }
+
+    /// If specified, compress the strings table with this brotli custom dictionary, and
+    /// write its hash, so that readers may reject files compressed with a dictionary they
+    /// do not have.
+    ///
+    /// The dictionary is only used if the strings table is compressed with brotli,
+    /// possibly as a candidate of `Compression::Auto`.
+    pub fn with_string_dictionary(self, string_dictionary: Option<BrotliDictionary>) -> Self {
+        TreeTokenWriter {
+            string_dictionary,
+            ..self
+        }
+    }
+
+    /// If specified, record this metadata, e.g. the version of the encoder, in the file.
+    /// Decoders ignore it.
+    pub fn with_metadata(self, metadata: Option<Metadata>) -> Self {
+        TreeTokenWriter {
+            metadata,
+            ..self
+        }
+    }
+
+    /// If specified, record this startup profile in the file, e.g. the profile used to
+    /// decide which functions to encode eagerly. Decoders skip it.
+    ///
+    /// Ignored for archives.
+    pub fn with_profile(self, profile: Option<StartupProfile>) -> Self {
+        TreeTokenWriter {
+            profile,
+            ..self
+        }
+    }
+
+    /// If `true`, guarantee that writing the same tree with the same options yields the
+    /// same bytes, e.g. for reproducible builds: the metadata is written without the time
+    /// of encoding and the options, which may contain paths, and encryption, which uses
+    /// a random nonce, fails.
+    pub fn with_reproducible(self, reproducible: bool) -> Self {
+        TreeTokenWriter {
+            reproducible,
+            ..self
+        }
+    }
+
+    /// If specified, add the statistics of this file to `statistics` once done,
+    /// e.g. to display them after encoding several files.
+    pub fn with_statistics(self, shared_statistics: Option<Rc<RefCell<Statistics>>>) -> Self {
+        TreeTokenWriter {
+            shared_statistics,
+            ..self
+        }
+    }
+
+    /// If `true`, append a checksum section, so that readers may detect
+    /// truncated or corrupted files.
+    pub fn with_checksum(self, checksum: bool) -> Self {
+        TreeTokenWriter {
+            checksum,
+            ..self
         }
     }
 
@@ -546,19 +871,230 @@ impl TreeTokenWriter {
         Tree(result)
     }
 
+    /// Rewrite the items of a list so that, in each run of at least `MIN_RUN_LEN`
+    /// consecutive tagged tuples with the same tag, only the first tagged tuple
+    /// carries the tag, followed by the number of subsequent items in the run.
+    fn collapse_runs(items: Vec<Rc<UnresolvedTree>>) -> Vec<Rc<UnresolvedTree>> {
+        fn tag(item: &UnresolvedTree) -> Option<&TableIndex<NodeDescription>> {
+            match item.nature {
+                Nature::TaggedTuple(ref index) => Some(index),
+                _ => None
+            }
+        }
+        let mut result = Vec::with_capacity(items.len());
+        let mut start = 0;
+        while start < items.len() {
+            let mut end = start + 1;
+            if let Some(run_tag) = tag(&items[start]) {
+                while end < items.len() && tag(&items[end]).map_or(false, |other| Rc::ptr_eq(&run_tag.index, &other.index)) {
+                    end += 1;
+                }
+            }
+            if end - start < MIN_RUN_LEN {
+                result.extend(items[start..end].iter().cloned());
+                start = end;
+                continue;
+            }
+            for (position, item) in items[start..end].iter().enumerate() {
+                let (index, fields) = match (&item.nature, &item.data) {
+                    (&Nature::TaggedTuple(ref index), &UnresolvedTreeNode::Tuple(ref data)) => (index.clone(), data[1].clone()),
+                    _ => panic!("Tagged tuples should be a tuple of a header and fields")
+                };
+                let mut data = Vec::with_capacity(2);
+                if position == 0 {
+                    data.push(Rc::new(UnresolvedTree {
+                        data: UnresolvedTreeNode::UnresolvedRunIndex(index.clone(), Some((end - start - 1) as u32)),
+                        nature: Nature::TaggedTupleHeader(index.clone()),
+                    }));
+                }
+                data.push(fields);
+                result.push(Rc::new(UnresolvedTree {
+                    data: UnresolvedTreeNode::Tuple(data),
+                    nature: Nature::TaggedTuple(index),
+                }));
+            }
+            start = end;
+        }
+        result
+    }
+
+    /// Mark the tree written so far as an entry of an archive.
+    ///
+    /// If at least one entry is ended, `done()` produces an archive, in which
+    /// all entries share the same grammar table and strings table.
+    pub fn end_entry(&mut self, name: &str) {
+        let root = self.root.take()
+            .expect("Cannot end an archive entry before writing its tree");
+        self.entries.push((SharedString::from_string(name.to_string()), root));
+    }
+
+    /// Write the index of the chunks, then the toplevel chunk and the contents of the
+    /// lazy fields `chunks`, each compressed independently, to the byte stream.
+    fn write_chunks(&mut self, toplevel: &[u8], chunks: &[(usize, Vec<u8>)]) -> Result<CompressionResult, TokenWriterError> {
+        let mut result = CompressionResult {
+            before_bytes: 0,
+            after_bytes: 0,
+            algorithms: HashSet::new(),
+        };
+        let mut stored = Vec::with_capacity(chunks.len() + 1);
+        for chunk in std::iter::once(toplevel).chain(chunks.iter().map(|&(_, ref chunk)| chunk.as_slice())) {
+            let mut buf = vec![];
+            result += self.targets.tree.format.compress(chunk, &mut buf)
+                .map_err(TokenWriterError::WriteError)?;
+            stored.push(buf);
+        }
+        debug!(target: "multipart", "Writing {} lazy chunks", chunks.len());
+
+        self.data.write_varnum(chunks.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        self.data.write_varnum(stored[0].len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        for (&(offset, _), buf) in chunks.iter().zip(&stored[1..]) {
+            self.data.write_varnum(offset as u32)
+                .map_err(TokenWriterError::WriteError)?;
+            self.data.write_varnum(buf.len() as u32)
+                .map_err(TokenWriterError::WriteError)?;
+        }
+        for buf in &stored {
+            self.data.write_all(buf)
+                .map_err(TokenWriterError::WriteError)?;
+        }
+        Ok(result)
+    }
+
+    /// Write the strings table to the byte stream.
+    fn write_strings_table(&mut self) -> Result<(), TokenWriterError> {
+        let start = self.data.len();
+        self.section_starts.push(start);
+        let header = match self.front_coding {
+            None => HEADER_STRINGS_TABLE,
+            Some(_) => HEADER_STRINGS_TABLE_FRONT_CODED,
+        };
+        self.data.write_all(header.as_bytes())
+            .map_err(TokenWriterError::WriteError)?;
+        let mut blobs = vec![];
+        match self.front_coding {
+            None => {
+                self.strings_table.write_with_blobs(self.blob_threshold, &mut self.targets.strings_table, &mut blobs)
+                    .map_err(TokenWriterError::WriteError)?;
+            }
+            Some(window) => {
+                // Measure the table without front coding, for statistics.
+                let mut plain = bytes::lengthwriter::LengthWriter::new();
+                self.strings_table.write_with_blobs(self.blob_threshold, &mut plain, &mut vec![])
+                    .map_err(TokenWriterError::WriteError)?;
+                self.statistics.strings_table.before_front_coding = Some(plain.len().into());
+
+                self.strings_table.write_front_coded(window, self.blob_threshold, &mut self.targets.strings_table, &mut blobs)
+                    .map_err(TokenWriterError::WriteError)?;
+            }
+        }
+        let (data, compression) = self.targets.strings_table.done_with_dictionary(self.string_dictionary.as_ref())
+            .map_err(TokenWriterError::WriteError)?;
+        self.data.write_all(data.as_ref())
+            .map_err(TokenWriterError::WriteError)?;
+        self.statistics.add_compressed_section("strings", header.len(), self.data.len() - start, &compression);
+        self.statistics.strings_table.entries = self.strings_table.map.len();
+        self.statistics.strings_table.max_entries = self.strings_table.map.len();
+        self.statistics.strings_table.compression = compression;
+        if !blobs.is_empty() {
+            self.write_blobs(&blobs)?;
+        }
+        Ok(())
+    }
+
+    /// Write the blobs of the strings table to the byte stream, immediately after the
+    /// strings table, as part of the same section.
+    fn write_blobs(&mut self, blobs: &[Vec<u8>]) -> Result<(), TokenWriterError> {
+        debug!(target: "multipart", "Writing {} blobs", blobs.len());
+        let mut buf = vec![];
+        buf.write_varnum(blobs.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        for blob in blobs {
+            buf.write_varnum(blob.len() as u32)
+                .map_err(TokenWriterError::WriteError)?;
+            buf.write_all(blob)
+                .map_err(TokenWriterError::WriteError)?;
+        }
+        let start = self.data.len();
+        self.data.write_all(HEADER_BLOBS.as_bytes())
+            .map_err(TokenWriterError::WriteError)?;
+        let compression = self.blob_compression.compress(&buf, &mut self.data)
+            .map_err(TokenWriterError::WriteError)?;
+        self.statistics.uncompressed_bytes += HEADER_BLOBS.len() + compression.before_bytes;
+        self.statistics.add_compressed_section("blobs", HEADER_BLOBS.len(), self.data.len() - start, &compression);
+        Ok(())
+    }
+
     pub fn done(mut self) -> Result<Box<[u8]>, TokenWriterError> {
         const MAGIC_HEADER: &[u8; 5] = b"BINJS";
+        if self.reproducible && self.encryption_key.is_some() {
+            return Err(TokenWriterError::WriteError(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                "Encrypted files are not reproducible, as encryption uses a random nonce")));
+        }
+
         // Write header to byte stream
+        let start = self.data.len();
         self.data.write_all(MAGIC_HEADER)
             .map_err(TokenWriterError::WriteError)?;
         self.statistics.uncompressed_bytes += MAGIC_HEADER.len();
 
-        const FORMAT_VERSION : u32 = 1;
-        self.data.write_varnum(FORMAT_VERSION)
+        let is_archive = !self.entries.is_empty();
+        let extended = self.grammar.as_ref()
+            .map_or(false, |grammar| grammar.extended);
+        let byte_len = ContainerVersion::current(is_archive, self.varfloats, extended, self.checksum, self.nan_policy)
+            .write(&mut self.data)
             .map_err(TokenWriterError::WriteError)?;
-        self.statistics.uncompressed_bytes += std::mem::size_of_val(&FORMAT_VERSION);
+        self.statistics.uncompressed_bytes += byte_len;
+        self.statistics.add_section("header", self.data.len() - start);
+
+        // Write grammar identifier to byte stream.
+        if let Some(ref grammar) = self.grammar {
+            self.data.write_all(HEADER_GRAMMAR_ID.as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+            let byte_len = write_grammar_id(&mut self.data, grammar)
+                .map_err(TokenWriterError::WriteError)?;
+            self.statistics.uncompressed_bytes += HEADER_GRAMMAR_ID.len() + byte_len;
+            self.statistics.add_section("grammar-id", HEADER_GRAMMAR_ID.len() + byte_len);
+        }
+
+        // Write string dictionary identifier to byte stream.
+        if let Some(ref dictionary) = self.string_dictionary {
+            self.data.write_all(HEADER_STRING_DICTIONARY.as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+            self.data.write_all(dictionary.hash())
+                .map_err(TokenWriterError::WriteError)?;
+            self.statistics.uncompressed_bytes += HEADER_STRING_DICTIONARY.len() + dictionary.hash().len();
+            self.statistics.add_section("string-dictionary", HEADER_STRING_DICTIONARY.len() + dictionary.hash().len());
+        }
+
+        // Write metadata to byte stream.
+        let metadata = match self.metadata {
+            Some(ref metadata) if self.reproducible => Some(metadata.reproducible()),
+            ref metadata => metadata.clone(),
+        };
+        if let Some(ref metadata) = metadata {
+            self.data.write_all(HEADER_METADATA.as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+            let byte_len = write_metadata(&mut self.data, metadata)
+                .map_err(TokenWriterError::WriteError)?;
+            self.statistics.uncompressed_bytes += HEADER_METADATA.len() + byte_len;
+            self.statistics.add_section("metadata", HEADER_METADATA.len() + byte_len);
+        }
+
+        // Write startup profile to byte stream.
+        if let (false, Some(profile)) = (is_archive, self.profile.as_ref()) {
+            self.data.write_all(HEADER_PROFILE.as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+            let byte_len = profile.write(&mut self.data)
+                .map_err(TokenWriterError::WriteError)?;
+            self.statistics.uncompressed_bytes += HEADER_PROFILE.len() + byte_len;
+            self.statistics.add_section("profile", HEADER_PROFILE.len() + byte_len);
+        }
 
         // Write grammar table to byte stream.
+        let start = self.data.len();
+        self.section_starts.push(start);
         self.data.write_all(HEADER_GRAMMAR_TABLE.as_bytes())
             .map_err(TokenWriterError::WriteError)?;
         self.statistics.uncompressed_bytes += HEADER_GRAMMAR_TABLE.len();
@@ -569,24 +1105,20 @@ impl TreeTokenWriter {
                 .map_err(TokenWriterError::WriteError)?;
             self.data.write_all(data.as_ref())
                 .map_err(TokenWriterError::WriteError)?;
+            self.statistics.add_compressed_section("grammar", HEADER_GRAMMAR_TABLE.len(), self.data.len() - start, &compression);
             self.statistics.grammar_table.entries = self.grammar_table.map.len();
             self.statistics.grammar_table.max_entries = self.grammar_table.map.len();
             self.statistics.grammar_table.compression = compression;
         }
 
-        // Write strings table to byte stream.
-        self.data.write_all(HEADER_STRINGS_TABLE.as_bytes())
-            .map_err(TokenWriterError::WriteError)?;
-        {
-            self.strings_table.write(&mut self.targets.strings_table)
-                .map_err(TokenWriterError::WriteError)?;
-            let (data, compression) = self.targets.strings_table.done()
-                .map_err(TokenWriterError::WriteError)?;
-            self.data.write_all(data.as_ref())
-                .map_err(TokenWriterError::WriteError)?;
-            self.statistics.strings_table.entries = self.strings_table.map.len();
-            self.statistics.strings_table.max_entries = self.strings_table.map.len();
-            self.statistics.strings_table.compression = compression;
+        // Write strings table to byte stream, unless it follows the tree.
+        let chunked = self.chunks && !is_archive;
+        let split_prelude = self.split_prelude && !is_archive && !chunked;
+        if split_prelude {
+            // Assign the indices written in the tree. Writing the table later assigns the same indices.
+            self.strings_table.sorted();
+        } else {
+            self.write_strings_table()?;
         }
 
         // Compute more statistics on strings.
@@ -618,29 +1150,107 @@ impl TreeTokenWriter {
 
 
         // Write tree itself to byte stream.
-        if let Some(root) = self.root {
+        let roots = if is_archive {
+            assert!(self.root.is_none(), "Some data was written after the last archive entry");
+            std::mem::replace(&mut self.entries, vec![])
+        } else {
+            self.root.take()
+                .into_iter()
+                .map(|root| (SharedString::from_str(""), root))
+                .collect()
+        };
+        if !roots.is_empty() {
+            let number_of_roots = roots.len();
             let mut tree_buf = Vec::with_capacity(2048);
-            let root = std::rc::Rc::try_unwrap(root.0)
-                .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
-            let (_, resolved) = root.resolve(&mut self.statistics);
-            resolved.write(&mut tree_buf)
-                .map_err(TokenWriterError::WriteError)?;
+            let mut manifest = Vec::with_capacity(number_of_roots);
+            let mut chunks = vec![];
+            for (name, root) in roots {
+                let start = tree_buf.len();
+                let root = std::rc::Rc::try_unwrap(root.0)
+                    .unwrap_or_else(|e| panic!("Could not unwrap tree, it still has {} consumers", std::rc::Rc::strong_count(&e)));
+                let (_, resolved) = root.resolve(&mut self.statistics);
+                if chunked {
+                    resolved.write_chunked(&mut tree_buf, &mut chunks)
+                        .map_err(TokenWriterError::WriteError)?;
+                } else {
+                    resolved.write(&mut tree_buf)
+                        .map_err(TokenWriterError::WriteError)?;
+                }
+                manifest.push((name, start, tree_buf.len() - start));
+            }
 
-            self.data.write_all(HEADER_TREE.as_bytes())
+            if is_archive {
+                // Write manifest to byte stream, using the same compression as the tree.
+                let mut manifest_buf = Vec::with_capacity(256);
+                manifest_buf.write_varnum(manifest.len() as u32)
+                    .map_err(TokenWriterError::WriteError)?;
+                for (name, offset, byte_len) in manifest {
+                    Some(name).write(&mut manifest_buf)
+                        .map_err(TokenWriterError::WriteError)?;
+                    manifest_buf.write_varnum(offset as u32)
+                        .map_err(TokenWriterError::WriteError)?;
+                    manifest_buf.write_varnum(byte_len as u32)
+                        .map_err(TokenWriterError::WriteError)?;
+                }
+                let start = self.data.len();
+                self.section_starts.push(start);
+                self.data.write_all(HEADER_MANIFEST.as_bytes())
+                    .map_err(TokenWriterError::WriteError)?;
+                self.statistics.uncompressed_bytes += HEADER_MANIFEST.len() + manifest_buf.len();
+                let compression = self.targets.tree.format.compress(&manifest_buf, &mut self.data)
+                    .map_err(TokenWriterError::WriteError)?;
+                self.statistics.add_compressed_section("manifest", HEADER_MANIFEST.len(), self.data.len() - start, &compression);
+            }
+
+            let start = self.data.len();
+            self.section_starts.push(start);
+            let header = match (self.runs, chunked) {
+                (false, false) => HEADER_TREE,
+                (true, false) => HEADER_TREE_RUNS,
+                (false, true) => HEADER_TREE_CHUNKS,
+                (true, true) => HEADER_TREE_RUNS_CHUNKS,
+            };
+            self.data.write_all(header.as_bytes())
                 .map_err(TokenWriterError::WriteError)?;
-            {
+            if chunked {
+                let compression = self.write_chunks(&tree_buf, &chunks)?;
+                self.statistics.add_compressed_section("tree", header.len(), self.data.len() - start, &compression);
+                self.statistics.tree.entries = number_of_roots;
+                self.statistics.tree.max_entries = number_of_roots;
+                self.statistics.tree.compression = compression;
+            } else {
                 tree_buf.write(&mut self.targets.tree)
                     .map_err(TokenWriterError::WriteError)?;
                 let (data, compression) = self.targets.tree.done()
                     .map_err(TokenWriterError::WriteError)?;
                 self.data.write_all(data.as_ref())
                     .map_err(TokenWriterError::WriteError)?;
-                self.statistics.tree.entries = 1;
-                self.statistics.tree.max_entries = 1;
+                self.statistics.add_compressed_section("tree", header.len(), self.data.len() - start, &compression);
+                self.statistics.tree.entries = number_of_roots;
+                self.statistics.tree.max_entries = number_of_roots;
                 self.statistics.tree.compression = compression;
             }
         }
 
+        if split_prelude {
+            self.write_strings_table()?;
+        }
+
+        // Write source positions to byte stream, using the same compression as the tree.
+        if let (false, Some(positions)) = (is_archive, self.positions.take()) {
+            let mut positions_buf = Vec::with_capacity(1024);
+            positions.write(&mut positions_buf)
+                .map_err(TokenWriterError::WriteError)?;
+            let start = self.data.len();
+            self.section_starts.push(start);
+            self.data.write_all(HEADER_POSITIONS.as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+            self.statistics.uncompressed_bytes += HEADER_POSITIONS.len() + positions_buf.len();
+            let compression = self.targets.tree.format.compress(&positions_buf, &mut self.data)
+                .map_err(TokenWriterError::WriteError)?;
+            self.statistics.add_compressed_section("positions", HEADER_POSITIONS.len(), self.data.len() - start, &compression);
+        }
+
         // Compute more statistics on nodes.
         for (key, value) in self.grammar_table.map {
             let index = value.index.index.borrow()
@@ -661,11 +1271,103 @@ impl TreeTokenWriter {
                 }
             }
         }
-        self.statistics.number_of_files = 1;
+        // Compute checksums of sections. If the file is encrypted, they are replaced with
+        // checksums of the encrypted sections below, so as not to reveal anything about
+        // the content. Encryption preserves the offsets of the sections.
+        let content_start = self.section_starts[0];
+        let section_ranges : Vec<_> = {
+            let ends = self.section_starts.iter()
+                .skip(1)
+                .cloned()
+                .chain(std::iter::once(self.data.len()));
+            self.section_starts.iter()
+                .zip(ends)
+                .map(|(start, end)| (start - content_start, end - content_start))
+                .collect()
+        };
+        let checksums_of = |content: &[u8]| -> Vec<_> {
+            section_ranges.iter()
+                .map(|&(start, end)| bytes::checksum::crc32(&content[start..end]))
+                .collect()
+        };
+        let mut section_checksums = if self.checksum {
+            checksums_of(&self.data[content_start..])
+        } else {
+            vec![]
+        };
+
+        if self.sign_key.is_some() || self.encryption_key.is_some() {
+            let content = self.data.split_off(content_start);
+            if let Some(ref key) = self.sign_key {
+                // Write signature before the content sections. It covers the prelude written
+                // so far, including the container flags, followed by the content sections.
+                let signature = bytes::signature::sign(key, &[&self.data[..], &content[..]].concat())
+                    .map_err(TokenWriterError::WriteError)?;
+                self.data.write_all(HEADER_SIGNATURE.as_bytes())
+                    .map_err(TokenWriterError::WriteError)?;
+                self.data.write_all(&signature)
+                    .map_err(TokenWriterError::WriteError)?;
+                self.statistics.add_section("signature", HEADER_SIGNATURE.len() + signature.len());
+            }
+            if let Some(ref key) = self.encryption_key {
+                // Write the content sections as a single encrypted block.
+                let start = self.data.len();
+                let (nonce, encrypted) = bytes::encryption::encrypt(key, &content)
+                    .map_err(TokenWriterError::WriteError)?;
+                if self.checksum {
+                    section_checksums = checksums_of(&encrypted);
+                }
+                self.data.write_all(HEADER_ENCRYPTED.as_bytes())
+                    .map_err(TokenWriterError::WriteError)?;
+                self.data.write_all(&nonce)
+                    .map_err(TokenWriterError::WriteError)?;
+                self.data.write_varnum(encrypted.len() as u32)
+                    .map_err(TokenWriterError::WriteError)?;
+                self.data.write_all(&encrypted)
+                    .map_err(TokenWriterError::WriteError)?;
+                // The overhead of encryption, as the encrypted sections are recorded before encryption.
+                // The sections compressed so far are exactly the content sections.
+                for section in self.statistics.per_section.iter_mut() {
+                    if !section.algorithms.is_empty() {
+                        section.encrypted = true;
+                    }
+                }
+                let byte_len = self.data.len() - start - content.len();
+                self.statistics.add_section("encryption", byte_len);
+            } else {
+                self.data.write_all(&content)
+                    .map_err(TokenWriterError::WriteError)?;
+            }
+            self.statistics.uncompressed_bytes += self.data.len() - content_start - content.len();
+        }
+
+        if self.checksum {
+            // Write checksum section to byte stream.
+            let mut checksum_buf = Vec::with_capacity(64);
+            checksum_buf.write_all(HEADER_CHECKSUM.as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+            checksum_buf.write_varnum(section_checksums.len() as u32)
+                .map_err(TokenWriterError::WriteError)?;
+            for checksum in section_checksums {
+                bytes::checksum::write_checksum(&mut checksum_buf, checksum)
+                    .map_err(TokenWriterError::WriteError)?;
+            }
+            bytes::checksum::write_checksum(&mut checksum_buf, bytes::checksum::crc32(&self.data))
+                .map_err(TokenWriterError::WriteError)?;
+            self.data.write_all(&checksum_buf)
+                .map_err(TokenWriterError::WriteError)?;
+            self.statistics.uncompressed_bytes += checksum_buf.len();
+            self.statistics.add_section("checksum", checksum_buf.len());
+        }
+
+        self.statistics.number_of_files = std::cmp::max(self.statistics.tree.entries, 1);
         self.statistics.compressed_bytes = self.data.len();
         self.statistics.uncompressed_bytes += self.statistics.grammar_table.compression.before_bytes
             + self.statistics.strings_table.compression.before_bytes
             + self.statistics.tree.compression.before_bytes;
+        if let Some(ref shared_statistics) = self.shared_statistics {
+            *shared_statistics.borrow_mut() += self.statistics.clone();
+        }
         Ok(self.data.clone().into_boxed_slice())
     }
 }
@@ -679,7 +1381,18 @@ impl TokenWriterWithTree for TreeTokenWriter {
     }
 
     fn float(&mut self, value: Option<f64>) -> Result<Self::Tree, TokenWriterError> {
-        let bytes : Vec<_> = bytes::float::bytes_of_float(value).iter().cloned().collect();
+        let bytes : Vec<_> = if self.varfloats {
+            let mut bytes = Vec::new();
+            bytes.write_maybe_varfloat(value, self.nan_policy)
+                .map_err(TokenWriterError::WriteError)?;
+            bytes
+        } else {
+            bytes::float::bytes_of_float_with_policy(value, self.nan_policy)
+                .map_err(TokenWriterError::WriteError)?
+                .iter()
+                .cloned()
+                .collect()
+        };
         debug!(target: "multipart", "writing float {:?} => {:?}", value, bytes);
         Ok(self.register(UnresolvedTree {
             nature: Nature::Float,
@@ -687,6 +1400,28 @@ impl TokenWriterWithTree for TreeTokenWriter {
         }))
     }
 
+    fn big_int(&mut self, value: Option<&BigInt>) -> Result<Self::Tree, TokenWriterError> {
+        let mut bytes = Vec::new();
+        bytes.write_maybe_varbigint(value.map(BigInt::as_str))
+            .map_err(TokenWriterError::WriteError)?;
+        debug!(target: "multipart", "writing big_int {:?} => {:?}", value, bytes);
+        Ok(self.register(UnresolvedTree {
+            nature: Nature::BigInt,
+            data: UnresolvedTreeNode::Encoded(bytes),
+        }))
+    }
+
+    fn reg_exp_flags(&mut self, value: Option<&RegExpFlags>) -> Result<Self::Tree, TokenWriterError> {
+        let mut bytes = Vec::with_capacity(1);
+        bytes.write_maybe_reg_exp_flags(value)
+            .map_err(TokenWriterError::WriteError)?;
+        debug!(target: "multipart", "writing reg_exp_flags {:?} => {:?}", value, bytes);
+        Ok(self.register(UnresolvedTree {
+            nature: Nature::RegExpFlags,
+            data: UnresolvedTreeNode::Encoded(bytes),
+        }))
+    }
+
     fn unsigned_long(&mut self, value: u32) -> Result<Self::Tree, TokenWriterError> {
         let mut bytes = Vec::with_capacity(4);
         bytes.write_varnum(value as u32)
@@ -749,7 +1484,11 @@ impl TokenWriterWithTree for TreeTokenWriter {
         let children : Vec<_> = children.drain(..)
             .map(|tree| tree.0.clone())
             .collect();
-        items.extend(children);
+        if self.runs {
+            items.extend(Self::collapse_runs(children));
+        } else {
+            items.extend(children);
+        }
         debug!(target: "multipart", "writing list with {} => {} items", len, items.len());
         Ok(self.register(UnresolvedTree {
             data: UnresolvedTreeNode::Tuple(items),
@@ -835,7 +1574,11 @@ impl TokenWriterWithTree for TreeTokenWriter {
             };
 
             let prefix = Rc::new(UnresolvedTree {
-                data: UnresolvedTreeNode::UnresolvedNodeIndex(index.clone()),
+                data: if self.runs {
+                    UnresolvedTreeNode::UnresolvedRunIndex(index.clone(), None)
+                } else {
+                    UnresolvedTreeNode::UnresolvedNodeIndex(index.clone())
+                },
                 nature: Nature::TaggedTupleHeader(index.clone()),
             });
 
@@ -859,11 +1602,71 @@ pub struct TreeTokenWriter {
 
     root: Option<Tree>,
 
+    /// The entries of the archive, if we are writing an archive.
+    entries: Vec<(SharedString, Tree)>,
+
     data: Vec<u8>,
 
     targets: Targets,
 
     statistics: Statistics,
+
+    /// If `true`, append a checksum section.
+    checksum: bool,
+
+    /// If specified, the Ed25519 secret key used to sign the content sections.
+    sign_key: Option<[u8; 32]>,
+
+    /// If specified, the AES-256-GCM key used to encrypt the content sections.
+    encryption_key: Option<[u8; 32]>,
+
+    /// If specified, the grammar used to encode.
+    grammar: Option<GrammarId>,
+
+    /// If specified, the source positions of the tree.
+    positions: Option<SourcePositions>,
+
+    /// If specified, the window of the front coding of the strings table.
+    front_coding: Option<usize>,
+
+    /// If specified, strings of at least this many bytes are written as blobs.
+    blob_threshold: Option<usize>,
+
+    /// The compression of the blobs.
+    blob_compression: Compression,
+
+    /// How NaN values are written.
+    nan_policy: NaNPolicy,
+
+    /// If `true`, floats are represented as varfloats.
+    varfloats: bool,
+
+    /// If `true`, only the first item of each run of list items with the same tag carries the tag.
+    runs: bool,
+
+    /// If `true`, the strings table is written after the tree.
+    split_prelude: bool,
+
+    /// If `true`, the tree is written as independently compressed chunks.
+    chunks: bool,
+
+    /// If specified, the brotli custom dictionary used to compress the strings table.
+    string_dictionary: Option<BrotliDictionary>,
+
+    /// If specified, the metadata recorded in the file.
+    metadata: Option<Metadata>,
+
+    /// If specified, the startup profile recorded in the file.
+    profile: Option<StartupProfile>,
+
+    /// If `true`, nothing that varies between runs is written.
+    reproducible: bool,
+
+    /// If specified, statistics to which the statistics of this file are added.
+    shared_statistics: Option<Rc<RefCell<Statistics>>>,
+
+    /// The offset of each section in `data`, used to compute checksums.
+    section_starts: Vec<usize>,
 }
 
 
@@ -877,6 +1680,10 @@ pub struct SectionStatistics {
     pub max_entries: usize,
 
     pub compression: CompressionResult,
+
+    /// If the section is front-coded, the number of uncompressed bytes it would
+    /// take without front coding.
+    pub before_front_coding: Option<usize>,
 }
 
 impl Default for SectionStatistics {
@@ -888,7 +1695,8 @@ impl Default for SectionStatistics {
                 before_bytes: 0,
                 after_bytes: 0,
                 algorithms: HashSet::new(),
-            }
+            },
+            before_front_coding: None,
         }
     }
 }
@@ -908,6 +1716,72 @@ impl AddAssign for SectionStatistics {
             self.max_entries = rhs.max_entries;
         }
         self.compression += rhs.compression;
+        self.before_front_coding = match (self.before_front_coding, rhs.before_front_coding) {
+            (None, None) => None,
+            (x, y) => Some(x.unwrap_or(0) + y.unwrap_or(0)),
+        };
+    }
+}
+
+/// The sizes of one section of a file, as written, e.g. to find the sections that
+/// compress poorly.
+#[derive(Clone, Debug, Default)]
+pub struct SectionSizes {
+    /// The name of the section, as in `ContainerLayout`, or `header` for the magic
+    /// header and the version.
+    pub name: &'static str,
+
+    /// Number of bytes before compression, including the header of the section.
+    pub uncompressed_bytes: usize,
+
+    /// Number of bytes in the file, including the header of the section.
+    ///
+    /// If `encrypted`, bytes before encryption, see section `encryption`.
+    pub compressed_bytes: usize,
+
+    /// The compression formats used by the section, empty if the section is
+    /// never compressed.
+    pub algorithms: HashSet<Compression>,
+
+    /// If `true`, the section is encrypted. Its sizes are those before encryption,
+    /// the overhead of encryption is the size of section `encryption`.
+    pub encrypted: bool,
+}
+impl SectionSizes {
+    /// `compressed_bytes / uncompressed_bytes`, i.e. lower is better.
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed_bytes == 0 {
+            return 1.;
+        }
+        self.compressed_bytes as f64 / self.uncompressed_bytes as f64
+    }
+}
+impl AddAssign for SectionSizes {
+    fn add_assign(&mut self, mut rhs: Self) {
+        self.uncompressed_bytes += rhs.uncompressed_bytes;
+        self.compressed_bytes += rhs.compressed_bytes;
+        self.algorithms.extend(rhs.algorithms.drain());
+        self.encrypted |= rhs.encrypted;
+    }
+}
+impl Display for SectionSizes {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        if self.name == "encryption" {
+            return write!(f, "{}: {} bytes of overhead, not including the encrypted sections",
+                self.name,
+                self.compressed_bytes);
+        }
+        let mut algorithms : Vec<_> = self.algorithms.iter()
+            .map(Compression::name)
+            .collect();
+        algorithms.sort();
+        write!(f, "{}: {} bytes uncompressed, {} bytes compressed{}, ratio {:.2}, compression [{}]",
+            self.name,
+            self.uncompressed_bytes,
+            self.compressed_bytes,
+            if self.encrypted { " before encryption" } else { "" },
+            self.ratio(),
+            if algorithms.is_empty() { "none".to_string() } else { algorithms.join(", ") })
     }
 }
 
@@ -950,6 +1824,10 @@ pub struct Statistics {
     pub strings_table: SectionStatistics,
     pub tree: SectionStatistics,
 
+    /// The sizes of each section, in the order of the file. When collating results
+    /// across several files, sizes are summed by section name.
+    pub per_section: Vec<SectionSizes>,
+
     pub per_kind_index: VecMap<NodeStatistics>,
     pub per_kind_name: HashMap<InterfaceName, NodeStatistics>,
     pub per_description: HashMap<NodeDescription, NodeStatistics>,
@@ -965,6 +1843,8 @@ pub struct Statistics {
 
     pub bool: NodeStatistics,
     pub float: NodeStatistics,
+    pub big_int: NodeStatistics,
+    pub reg_exp_flags: NodeStatistics,
     pub unsigned_long: NodeStatistics,
     pub string: NodeStatistics,
     pub list: NodeStatistics,
@@ -973,10 +1853,17 @@ pub struct Statistics {
     pub tagged_header: NodeStatistics,
     pub tagged_tuple: NodeStatistics,
 
+    /// The headers of runs of list items with the same tag, if the tree has runs.
+    pub runs: NodeStatistics,
+
     pub number_of_files: usize,
     pub uncompressed_bytes: usize,
     pub compressed_bytes: usize,
     pub source_bytes: Option<usize>,
+
+    /// If sources are recorded with `add_source_compression`, their byte length
+    /// with gzip and brotli, for comparison.
+    pub source_compression: Option<SourceCompression>,
 }
 impl AddAssign for Statistics {
     fn add_assign(&mut self, rhs: Self) {
@@ -991,6 +1878,15 @@ impl Add for Statistics {
         self.strings_table += rhs.strings_table;
         self.tree += rhs.tree;
 
+        for section in rhs.per_section.drain(..) {
+            let position = self.per_section.iter()
+                .position(|mine| mine.name == section.name);
+            match position {
+                Some(position) => self.per_section[position] += section,
+                None => self.per_section.push(section),
+            }
+        }
+
         for (key, value) in rhs.per_kind_index.drain() {
             use vec_map::Entry::*;
             match self.per_kind_index.entry(key) {
@@ -1060,12 +1956,15 @@ impl Add for Statistics {
 
         self.bool += rhs.bool;
         self.float += rhs.float;
+        self.big_int += rhs.big_int;
+        self.reg_exp_flags += rhs.reg_exp_flags;
         self.unsigned_long += rhs.unsigned_long;
         self.string += rhs.string;
         self.list += rhs.list;
         self.list_header += rhs.list_header;
         self.tagged_header += rhs.tagged_header;
         self.tagged_tuple += rhs.tagged_tuple;
+        self.runs += rhs.runs;
 
         self.number_of_files += rhs.number_of_files;
         self.compressed_bytes += rhs.compressed_bytes;
@@ -1074,6 +1973,13 @@ impl Add for Statistics {
             (Some(x), Some(y)) => Some(x + y),
             _ => None
         };
+        self.source_compression = match (self.source_compression.take(), rhs.source_compression.take()) {
+            (Some(mut x), Some(y)) => {
+                x += y;
+                Some(x)
+            }
+            (x, y) => x.or(y)
+        };
 
         self
     }
@@ -1084,6 +1990,40 @@ impl Statistics {
         self.source_bytes = Some(source_bytes);
         self
     }
+
+    /// Record the sizes of a section that is never compressed.
+    fn add_section(&mut self, name: &'static str, bytes: usize) {
+        self.per_section.push(SectionSizes {
+            name,
+            uncompressed_bytes: bytes,
+            compressed_bytes: bytes,
+            algorithms: HashSet::new(),
+            encrypted: false,
+        });
+    }
+
+    /// Record the sizes of a section made of an uncompressed header of `header_bytes`
+    /// bytes, followed by data compressed as described by `compression`, spanning
+    /// `compressed_bytes` bytes in total.
+    fn add_compressed_section(&mut self, name: &'static str, header_bytes: usize, compressed_bytes: usize, compression: &CompressionResult) {
+        self.per_section.push(SectionSizes {
+            name,
+            uncompressed_bytes: header_bytes + compression.before_bytes,
+            compressed_bytes,
+            algorithms: compression.algorithms.clone(),
+            encrypted: false,
+        });
+    }
+
+    /// Record a source compressed with gzip and brotli, to compare them with BinJS
+    /// in the statistics. This also counts the source in `source_bytes`.
+    pub fn add_source_compression(&mut self, compression: SourceCompression) {
+        self.source_bytes = Some(self.source_bytes.unwrap_or(0) + compression.source_bytes);
+        let mut total = self.source_compression.take()
+            .unwrap_or_default();
+        total += compression;
+        self.source_compression = Some(total);
+    }
 }
 
 // Shortcuts to display statistics
@@ -1111,6 +2051,10 @@ impl<'a> Display for SectionAndStatistics<'a> {
         write!(f, "]\n")?;
         write!(f, "\t\t\tUncompressed bytes: {} ({:.2}%)\n", self.section.compression.before_bytes, 100. * (self.section.compression.before_bytes as f64) / (self.total_uncompressed_bytes as f64))?;
         write!(f, "\t\t\tCompressed bytes: {} ({:.2}%)\n", self.section.compression.after_bytes, 100. * (self.section.compression.after_bytes as f64) / (self.total_compressed_bytes as f64))?;
+        if let Some(before) = self.section.before_front_coding {
+            let saved = before as f64 - self.section.compression.before_bytes as f64;
+            write!(f, "\t\t\tUncompressed bytes without front coding: {} (front coding saves {:.2}%)\n", before, 100. * saved / (before as f64))?;
+        }
         Ok(())
     }
 }
@@ -1216,6 +2160,8 @@ impl Display for Statistics {
 
         let total_number_of_tokens = self.bool.entries
             + self.float.entries
+            + self.big_int.entries
+            + self.reg_exp_flags.entries
             + self.unsigned_long.entries
             + self.string.entries
             + self.list.entries
@@ -1228,7 +2174,8 @@ Statistics
 \t\tTotal uncompressed bytes: {total_uncompressed_bytes}
 \t\tTotal compressed bytes: {total_compressed_bytes}
 \t\tRatio: {compression_ratio}
-\tSections:
+{source_comparison}\tSizes per section:
+{per_section}\tSections:
 \t\tGrammar:
 {section_grammar}
 \t\tStrings:
@@ -1240,11 +2187,14 @@ Statistics
 \tTokens:
 {token_bool}
 {token_float}
+{token_big_int}
+{token_reg_exp_flags}
 {token_unsigned_long}
 {token_offset}
 {token_string}
 {token_list}
 {token_tagged_tuple}
+{token_runs}
 \tLists per size:
 {lists_per_size}
 \tStrings per size:
@@ -1263,6 +2213,13 @@ Statistics
             None => "<not available>".to_string(),
             Some(ref bytes) => format!("{:.2}", (self.compressed_bytes as f64) / (*bytes as f64))
         },
+        source_comparison = match self.source_compression {
+            None => String::new(),
+            Some(ref compression) => format!("{}", compression.compare(self.compressed_bytes))
+        },
+        per_section = self.per_section.iter()
+            .map(|section| format!("\t\t{}\n", section))
+            .collect::<String>(),
         lists_per_size = ListLengthsAndNumber(list_per_size, "length".to_string()),
         strings_per_size = ListLengthsAndNumber(strings_per_size, "length".to_string()),
         strings_per_usage = ListLengthsAndNumber(strings_per_usage, "occurrences".to_string()),
@@ -1299,6 +2256,20 @@ Statistics
             total_uncompressed_bytes: self.uncompressed_bytes,
             header_bytes: 0,
         },
+        token_big_int = NodeAndStatistics {
+            name: "BigInt",
+            stats: &self.big_int,
+            total_number_of_entries: total_number_of_tokens,
+            total_uncompressed_bytes: self.uncompressed_bytes,
+            header_bytes: 0,
+        },
+        token_reg_exp_flags = NodeAndStatistics {
+            name: "RegExpFlags",
+            stats: &self.reg_exp_flags,
+            total_number_of_entries: total_number_of_tokens,
+            total_uncompressed_bytes: self.uncompressed_bytes,
+            header_bytes: 0,
+        },
         token_unsigned_long = NodeAndStatistics {
             name: "UnsignedLong",
             stats: &self.unsigned_long,
@@ -1334,6 +2305,13 @@ Statistics
             total_uncompressed_bytes: self.uncompressed_bytes,
             header_bytes: self.tagged_header.own_bytes,
         },
+        token_runs = NodeAndStatistics {
+            name: "Runs",
+            stats: &self.runs,
+            total_number_of_entries: total_number_of_tokens,
+            total_uncompressed_bytes: self.uncompressed_bytes,
+            header_bytes: 0,
+        },
         )
     }
 }
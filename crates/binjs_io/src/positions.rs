@@ -0,0 +1,190 @@
+//! Source positions and comments, which formats may carry alongside the tree.
+//!
+//! Positions are not part of the AST. They are attached to nodes by path, i.e. the
+//! sequence of field names and list indices leading from the root to the node,
+//! e.g. `statements[0].expression.callee`.
+
+use bytes::varnum::*;
+
+use std;
+use std::io::{ Read, Write };
+
+/// A position in the source text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    /// The line, as numbered by the parser.
+    pub line: u32,
+
+    /// The column, as numbered by the parser.
+    pub column: u32,
+
+    /// The offset in the source text.
+    pub offset: u32,
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The range of source text covered by a node or a comment.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// A comment of the source text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Comment {
+    /// The kind of comment, as named by the parser, e.g. `"SingleLine"` or `"MultiLine"`.
+    pub kind: String,
+
+    /// The text of the comment, without delimiters.
+    pub text: String,
+
+    pub location: Location,
+}
+
+/// The positions of the nodes of a tree and, optionally, the comments of the source text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourcePositions {
+    /// The location of nodes, by path from the root.
+    pub locations: Vec<(String, Location)>,
+
+    /// The comments, in order of appearance.
+    pub comments: Vec<Comment>,
+}
+impl SourcePositions {
+    /// Write these positions, uncompressed.
+    ///
+    /// The representation is:
+    ///
+    /// - the number of locations (`varnum`);
+    /// - for each location,
+    ///   - the path (see below);
+    ///   - the location (see below);
+    /// - the number of comments (`varnum`);
+    /// - for each comment,
+    ///   - the kind (see below);
+    ///   - the text (see below);
+    ///   - the location (see below).
+    ///
+    /// Strings are written as their byte length (`varnum`), followed by their utf-8 encoding.
+    /// Locations are written as the line, column and offset of their start, then of their
+    /// end (6 `varnum`).
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
+        let mut written = out.write_varnum(self.locations.len() as u32)?;
+        for &(ref path, ref location) in &self.locations {
+            written += write_string(out, path)?;
+            written += write_location(out, location)?;
+        }
+        written += out.write_varnum(self.comments.len() as u32)?;
+        for comment in &self.comments {
+            written += write_string(out, &comment.kind)?;
+            written += write_string(out, &comment.text)?;
+            written += write_location(out, &comment.location)?;
+        }
+        Ok(written)
+    }
+
+    /// Read positions written by `write`.
+    pub fn read<R: Read>(inp: &mut R) -> Result<Self, std::io::Error> {
+        let number_of_locations = inp.read_varnum()?;
+        let mut locations = Vec::with_capacity(number_of_locations as usize);
+        for _ in 0..number_of_locations {
+            let path = read_string(inp)?;
+            let location = read_location(inp)?;
+            locations.push((path, location));
+        }
+        let number_of_comments = inp.read_varnum()?;
+        let mut comments = Vec::with_capacity(number_of_comments as usize);
+        for _ in 0..number_of_comments {
+            let kind = read_string(inp)?;
+            let text = read_string(inp)?;
+            let location = read_location(inp)?;
+            comments.push(Comment {
+                kind,
+                text,
+                location,
+            });
+        }
+        Ok(SourcePositions {
+            locations,
+            comments,
+        })
+    }
+}
+
+fn write_string<W: Write>(out: &mut W, string: &str) -> Result<usize, std::io::Error> {
+    let written = out.write_varnum(string.len() as u32)?;
+    out.write_all(string.as_bytes())?;
+    Ok(written + string.len())
+}
+
+fn read_string<R: Read>(inp: &mut R) -> Result<String, std::io::Error> {
+    let byte_len = inp.read_varnum()?;
+    let mut bytes = vec![0; byte_len as usize];
+    inp.read_exact(&mut bytes)?;
+    String::from_utf8(bytes)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+fn write_location<W: Write>(out: &mut W, location: &Location) -> Result<usize, std::io::Error> {
+    let mut written = 0;
+    for position in &[location.start, location.end] {
+        written += out.write_varnum(position.line)?;
+        written += out.write_varnum(position.column)?;
+        written += out.write_varnum(position.offset)?;
+    }
+    Ok(written)
+}
+
+fn read_location<R: Read>(inp: &mut R) -> Result<Location, std::io::Error> {
+    let mut read_position = || -> Result<Position, std::io::Error> {
+        Ok(Position {
+            line: inp.read_varnum()?,
+            column: inp.read_varnum()?,
+            offset: inp.read_varnum()?,
+        })
+    };
+    let start = read_position()?;
+    let end = read_position()?;
+    Ok(Location {
+        start,
+        end,
+    })
+}
+
+#[test]
+fn test_positions_roundtrip() {
+    let position = |line, column, offset| Position { line, column, offset };
+    let positions = SourcePositions {
+        locations: vec![
+            ("".to_string(), Location { start: position(1, 0, 0), end: position(2, 10, 25) }),
+            ("statements[0].expression".to_string(), Location { start: position(2, 0, 15), end: position(2, 9, 24) }),
+        ],
+        comments: vec![
+            Comment {
+                kind: "SingleLine".to_string(),
+                text: " héhé".to_string(),
+                location: Location { start: position(1, 0, 0), end: position(1, 8, 8) },
+            }
+        ],
+    };
+    let mut buf = vec![];
+    let written = positions.write(&mut buf)
+        .expect("Could not write positions");
+    assert_eq!(written, buf.len());
+
+    let read = SourcePositions::read(&mut std::io::Cursor::new(buf))
+        .expect("Could not read positions");
+    assert_eq!(read, positions);
+}
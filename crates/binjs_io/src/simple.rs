@@ -7,7 +7,7 @@ use io::*;
 use ::{ TokenReaderError, TokenWriterError };
 use util::{ PoisonLock, Pos, ReadConst };
 
-use binjs_shared::{ FieldName, InterfaceName, SharedString };
+use binjs_shared::{ BigInt, FieldName, InterfaceName, RegExpFlags, SharedString };
 
 use std;
 use std::cell::RefCell;
@@ -122,6 +122,27 @@ impl<R> TokenReader for TreeTokenReader<R> where R: Read + Seek {
         })
     }
 
+    fn big_int_at(&mut self, _path: &Path) -> Result<Option<BigInt>, TokenReaderError> {
+        use bytes::bigint::ReadVarBigInt;
+        debug!(target: "simple_reader", "big_int");
+        let mut owner = self.owner.borrow_mut();
+        owner.try(|state| {
+            let result = state.reader.read_maybe_varbigint()
+                .map_err(TokenReaderError::ReadError)?;
+            Ok(result.map(BigInt::from_string))
+        })
+    }
+
+    fn reg_exp_flags_at(&mut self, _path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        use bytes::regexp::ReadRegExpFlags;
+        debug!(target: "simple_reader", "reg_exp_flags");
+        let mut owner = self.owner.borrow_mut();
+        owner.try(|state| {
+            state.reader.read_maybe_reg_exp_flags()
+                .map_err(TokenReaderError::ReadError)
+        })
+    }
+
     fn unsigned_long_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
         debug!(target: "simple_reader", "unsigned_long");
         let mut owner = self.owner.borrow_mut();
@@ -297,19 +318,20 @@ impl TreeTokenWriter {
         // To be substituted to any Offset field.
         let mut byte_len = FOOTER.len() as u32;
 
-        // First check if children[1] is Offset. If so, compute the length of the rest
-        // of the children and substitute that Length to Offset. Note that Offset at any
-        // other position than children[1] is an error.
+        // If a child is Offset, compute the length of the children after it and
+        // substitute that length to Offset. Note that children[0] is the header
+        // written by `tagged_tuple`, and that a tuple has at most one Offset.
+        let mut has_offset = false;
         for (i, item) in children.iter().enumerate() {
             match *(item.0) {
-                TreeItem::Bytes(_) if i < 2 => {
-                    // That's before any instance of Offset, ignore it.
-                }
-                TreeItem::Bytes(ref bytes) => {
+                TreeItem::Bytes(ref bytes) if has_offset => {
                     byte_len += bytes.len() as u32;
                 }
-                TreeItem::Offset if i == 1 => {
-                    // Ok, that's the only place where `Offset` is valid.
+                TreeItem::Bytes(_) => {
+                    // That's before any instance of Offset, ignore it.
+                }
+                TreeItem::Offset if i > 0 && !has_offset => {
+                    has_offset = true;
                 }
                 TreeItem::Offset => {
                     return Err(TokenWriterError::InvalidOffsetField)
@@ -350,6 +372,22 @@ impl TokenWriterWithTree for TreeTokenWriter {
         Ok(self.register(bytes.iter().cloned().collect()))
     }
 
+    fn big_int(&mut self, data: Option<&BigInt>) -> Result<Self::Tree, TokenWriterError> {
+        use bytes::bigint::WriteVarBigInt;
+        let mut buf = Vec::new();
+        buf.write_maybe_varbigint(data.map(BigInt::as_str))
+            .map_err(TokenWriterError::WriteError)?;
+        Ok(self.register(buf))
+    }
+
+    fn reg_exp_flags(&mut self, data: Option<&RegExpFlags>) -> Result<Self::Tree, TokenWriterError> {
+        use bytes::regexp::WriteRegExpFlags;
+        let mut buf = Vec::new();
+        buf.write_maybe_reg_exp_flags(data)
+            .map_err(TokenWriterError::WriteError)?;
+        Ok(self.register(buf))
+    }
+
     fn bool(&mut self, data: Option<bool>) -> Result<Self::Tree, TokenWriterError> {
         debug!(target: "simple_writer", "TreeTokenWriter: bool");
         let result = bytes::bool::bytes_of_bool(data).iter().cloned().collect();
@@ -501,7 +539,7 @@ impl ::FormatProvider for FormatProvider {
 
 #[test]
 fn test_simple_io() {
-    use binjs_shared::{ FieldName, InterfaceName, SharedString };
+    use binjs_shared::{ BigInt, FieldName, InterfaceName, RegExpFlags, SharedString };
     use binjs_shared::ast::Path;
     use io::TokenWriterWithTree;
     use std::fs::*;
@@ -547,6 +585,37 @@ fn test_simple_io() {
         assert_eq!(escapes_string, data);
     }
 
+    eprintln!("Testing BigInt I/O");
+
+    {
+        let data = BigInt::from_str("-123456789012345678901234567890");
+        let mut writer = TreeTokenWriter::new();
+        writer.big_int(Some(&data))
+            .expect("Writing BigInt");
+
+        let result = writer.data().unwrap();
+        let mut reader = TreeTokenReader::new(Cursor::new(result));
+        let big_int = reader.big_int_at(&path)
+            .expect("Reading BigInt")
+            .expect("Non-null BigInt");
+        assert_eq!(big_int, data);
+    }
+
+    eprintln!("Testing RegExp flags I/O");
+
+    {
+        let mut writer = TreeTokenWriter::new();
+        writer.reg_exp_flags(Some(&RegExpFlags::from_str("ug")))
+            .expect("Writing flags");
+
+        let result = writer.data().unwrap();
+        let mut reader = TreeTokenReader::new(Cursor::new(result));
+        let flags = reader.reg_exp_flags_at(&path)
+            .expect("Reading flags")
+            .expect("Non-null flags");
+        assert_eq!(flags, RegExpFlags::from_str("gu"));
+    }
+
     eprintln!("Testing untagged tuple I/O");
 
     {
@@ -0,0 +1,137 @@
+//! Startup profiles, recording which functions were executed at startup, e.g. to encode
+//! them eagerly and the other functions lazily.
+//!
+//! Functions are identified by their index in source order among the functions that may
+//! be lazified, i.e. function declarations, function expressions, methods, getters and
+//! setters, see `binjs_es6::lazy::Policy::Profile`. The first function has ID 0.
+
+use bytes::varnum::*;
+
+use std;
+use std::io::{ Read, Write };
+
+/// The functions executed at startup, by function ID.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StartupProfile {
+    /// For each function ID, `true` if the function was executed at startup.
+    /// Functions beyond the end were not executed.
+    executed: Vec<bool>,
+}
+impl StartupProfile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that function `id` was executed at startup.
+    pub fn set_executed(&mut self, id: u32) {
+        let id = id as usize;
+        if id >= self.executed.len() {
+            self.executed.resize(id + 1, false);
+        }
+        self.executed[id] = true;
+    }
+
+    /// `true` if function `id` was executed at startup.
+    pub fn is_executed(&self, id: u32) -> bool {
+        self.executed.get(id as usize)
+            .cloned()
+            .unwrap_or(false)
+    }
+
+    /// The IDs of the functions executed at startup, in increasing order.
+    pub fn executed(&self) -> Vec<u32> {
+        self.executed.iter()
+            .enumerate()
+            .filter(|&(_, executed)| *executed)
+            .map(|(id, _)| id as u32)
+            .collect()
+    }
+
+    /// Parse a profile from text, as written by `Display`: the ID of each function
+    /// executed at startup, one per line. Empty lines and lines starting with `#`
+    /// are ignored.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut profile = Self::new();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let id = line.parse::<u32>()
+                .map_err(|e| format!("Invalid function ID {:?} at line {}: {}", line, number + 1, e))?;
+            profile.set_executed(id);
+        }
+        Ok(profile)
+    }
+
+    /// Read a profile from a file, see `parse`.
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Self, std::io::Error> {
+        let mut source = String::new();
+        std::fs::File::open(path)?
+            .read_to_string(&mut source)?;
+        Self::parse(&source)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    /// Write this profile, uncompressed.
+    ///
+    /// The representation is:
+    ///
+    /// - the number of functions covered by the profile (`varnum`);
+    /// - a bit per function, set if the function was executed at startup, function `id`
+    ///   being bit `1 << (id % 8)` of byte `id / 8`, padded with zeros to a whole byte.
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<usize, std::io::Error> {
+        let mut bits = vec![0u8; (self.executed.len() + 7) / 8];
+        for id in self.executed() {
+            bits[id as usize / 8] |= 1 << (id % 8);
+        }
+        let written = out.write_varnum(self.executed.len() as u32)?;
+        out.write_all(&bits)?;
+        Ok(written + bits.len())
+    }
+
+    /// Read a profile written by `write`.
+    pub fn read<R: Read>(inp: &mut R) -> Result<Self, std::io::Error> {
+        let number_of_functions = inp.read_varnum()? as usize;
+        let mut bits = vec![0u8; (number_of_functions + 7) / 8];
+        inp.read_exact(&mut bits)?;
+        let executed = (0..number_of_functions)
+            .map(|id| bits[id / 8] & (1 << (id % 8)) != 0)
+            .collect();
+        Ok(StartupProfile {
+            executed,
+        })
+    }
+}
+
+impl std::fmt::Display for StartupProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        for id in self.executed() {
+            write!(f, "{}\n", id)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_startup_profile_roundtrip() {
+    let profile = StartupProfile::parse("# Executed at startup.\n0\n3\n\n10\n")
+        .expect("Could not parse profile");
+    assert!(profile.is_executed(0));
+    assert!(!profile.is_executed(1));
+    assert!(profile.is_executed(10));
+    assert!(!profile.is_executed(11));
+    assert_eq!(profile.executed(), vec![0, 3, 10]);
+    assert_eq!(format!("{}", profile), "0\n3\n10\n");
+    assert!(StartupProfile::parse("1\nfoo\n").is_err());
+
+    let mut buf = vec![];
+    let written = profile.write(&mut buf)
+        .expect("Could not write profile");
+    assert_eq!(written, buf.len());
+    assert_eq!(buf.len(), 3);
+
+    let read = StartupProfile::read(&mut std::io::Cursor::new(buf))
+        .expect("Could not read profile");
+    assert_eq!(read, profile);
+}
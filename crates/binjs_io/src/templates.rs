@@ -0,0 +1,860 @@
+//! An experimental encoding that factors out the shapes of subtrees that are
+//! repeated across the file.
+//!
+//! The *shape* of a subtree is the tag of its root and, down to a given depth,
+//! the tags of the tagged tuples it contains. Everything else, i.e. lists,
+//! primitive values and tagged tuples below that depth, are the *holes* of the
+//! shape. Shapes that appear often enough are stored once, as *templates*, after
+//! which each instance of a template is encoded as the index of the template,
+//! followed by the contents of its holes.
+//!
+//! The file is laid out as follows:
+//!
+//! - "[TEMPLATES]";
+//! - the number of tags (varnum), then each tag, as its byte length (varnum)
+//!   followed by its UTF-8 bytes;
+//! - the number of templates (varnum), then each template, in prefix order,
+//!   a hole being represented as 0 (varnum) and a tagged tuple as its tag
+//!   index + 1 (varnum), followed by its number of fields (varnum);
+//! - "[TREE]";
+//! - the tree.
+//!
+//! In the tree, each hole is represented as follows:
+//!
+//! - a tagged tuple is either `2 * tag index` (varnum), followed by its fields,
+//!   or `2 * template index + 1` (varnum), followed by the holes of the template,
+//!   in prefix order;
+//! - a list is its number of items (varnum), followed by its items;
+//! - a string is `byte length + 1` (varnum), followed by its UTF-8 bytes, or 0
+//!   (varnum) for null;
+//! - a float is a varfloat, a BigInt is a varbigint, an unsigned long is a varnum
+//!   and a bool is a single byte.
+//!
+//! As the tree may only be read sequentially, offsets are not written, and
+//! always read as 0.
+
+use bytes::bigint::{ ReadVarBigInt, WriteVarBigInt };
+use bytes::float::{ NaNPolicy, ReadVarFloat, WriteVarFloat };
+use bytes::regexp::{ ReadRegExpFlags, WriteRegExpFlags };
+use bytes::varnum::{ ReadVarNum, WriteVarNum };
+use io::{ FileStructurePrinter, Path, TokenReader, TokenWriterWithTree };
+use util::ReadConst;
+use ::{ TokenReaderError, TokenWriterError };
+
+use binjs_shared::{ BigInt, FieldName, InterfaceName, RegExpFlags, SharedString };
+
+use std;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{ Read, Write };
+use std::rc::Rc;
+
+use clap;
+
+const HEADER_TEMPLATES: &str = "[TEMPLATES]";
+const HEADER_TREE: &str = "[TREE]";
+
+/// The default maximal depth of templates.
+pub const DEFAULT_DEPTH: usize = 3;
+
+/// The default minimal number of instances of a shape to make it a template.
+pub const DEFAULT_MIN_USES: usize = 4;
+
+/// The maximal depth of templates, both when writing and when reading.
+///
+/// Templates are read from the file, don't let them exhaust the stack.
+pub const MAX_DEPTH: usize = 32;
+
+#[derive(Clone)]
+pub struct Options {
+    /// The maximal depth of templates. With a depth of 2, templates may fix the
+    /// tag of a tagged tuple and those of its fields. With a depth of 3, they may
+    /// also fix the tags of the fields of its fields, etc.
+    depth: usize,
+
+    /// The minimal number of instances of a shape to make it a template.
+    min_uses: usize,
+
+    /// Statistics obtained while writing. If several files are written with
+    /// the same options, the statistics are accumulated.
+    statistics: Rc<RefCell<Statistics>>,
+}
+impl Options {
+    /// # Panics
+    ///
+    /// If `depth` is not in [2, MAX_DEPTH].
+    pub fn new(depth: usize, min_uses: usize) -> Self {
+        assert!(depth >= 2 && depth <= MAX_DEPTH);
+        Options {
+            depth,
+            min_uses,
+            statistics: Rc::new(RefCell::new(Statistics::default())),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn min_uses(&self) -> usize {
+        self.min_uses
+    }
+
+    pub fn statistics_for_write(&self) -> Statistics {
+        self.statistics.borrow()
+            .clone()
+    }
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEPTH, DEFAULT_MIN_USES)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Statistics {
+    pub number_of_files: usize,
+
+    /// The number of templates stored.
+    pub templates: usize,
+
+    /// The number of bytes taken by the templates.
+    pub templates_bytes: usize,
+
+    /// The number of tagged tuples, in templates or not.
+    pub tagged_tuples: usize,
+
+    /// The number of tagged tuples encoded as the root of an instance of a template.
+    pub instances: usize,
+
+    /// The number of tagged tuples whose tag is implied by a template.
+    pub implied_tags: usize,
+
+    /// The number of bytes taken by the trees.
+    pub tree_bytes: usize,
+}
+impl std::fmt::Display for Statistics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "Templates:
+\tFiles: {number_of_files}
+\tTemplates: {templates} ({templates_bytes} bytes)
+\tInstances: {instances}
+\tTags implied by a template: {implied_tags} of {tagged_tuples}
+\tTree: {tree_bytes} bytes
+",
+            number_of_files = self.number_of_files,
+            templates = self.templates,
+            templates_bytes = self.templates_bytes,
+            instances = self.instances,
+            implied_tags = self.implied_tags,
+            tagged_tuples = self.tagged_tuples,
+            tree_bytes = self.tree_bytes,
+        )
+    }
+}
+
+/// The shape of a subtree.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Shape {
+    /// A list, a primitive value or a tagged tuple, written in full.
+    Hole,
+
+    /// A tagged tuple with a fixed tag, along with the shapes of its fields.
+    Tagged(InterfaceName, Rc<Vec<Shape>>),
+}
+impl Shape {
+    /// The number of tagged tuples in this shape.
+    fn size(&self) -> usize {
+        match *self {
+            Shape::Hole => 0,
+            Shape::Tagged(_, ref fields) => 1 + fields.iter()
+                .map(Shape::size)
+                .sum::<usize>()
+        }
+    }
+
+    fn write(&self, tags: &HashMap<InterfaceName, u32>, out: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        match *self {
+            Shape::Hole => {
+                out.write_varnum(0)?;
+            }
+            Shape::Tagged(ref tag, ref fields) => {
+                let index = tags.get(tag)
+                    .expect("All tags should have been collected by now");
+                out.write_varnum(index + 1)?;
+                out.write_varnum(fields.len() as u32)?;
+                for field in fields.iter() {
+                    field.write(tags, out)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(inp: &mut R, tags: &[InterfaceName], depth: usize) -> Result<Shape, TokenReaderError> {
+        let value = inp.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        if value == 0 {
+            return Ok(Shape::Hole);
+        }
+        if depth == 0 {
+            return Err(TokenReaderError::InvalidValue);
+        }
+        let tag = tags.get((value - 1) as usize)
+            .cloned()
+            .ok_or(TokenReaderError::BadKindIndex(value - 1))?;
+        let len = inp.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let mut fields = vec![];
+        for _ in 0..len {
+            fields.push(Shape::read(inp, tags, depth - 1)?);
+        }
+        Ok(Shape::Tagged(tag, Rc::new(fields)))
+    }
+}
+
+/// The tree, as it is being built.
+enum Node {
+    /// A primitive value, already encoded.
+    Leaf(Vec<u8>),
+    List(Vec<Rc<Node>>),
+    Tagged(InterfaceName, Vec<Rc<Node>>),
+}
+impl Node {
+    /// The shape of this node, down to `depth`.
+    fn shape(&self, depth: usize) -> Shape {
+        match *self {
+            Node::Tagged(ref tag, ref children) if depth > 0 => {
+                let fields = children.iter()
+                    .map(|child| child.shape(depth - 1))
+                    .collect();
+                Shape::Tagged(tag.clone(), Rc::new(fields))
+            }
+            _ => Shape::Hole
+        }
+    }
+
+    fn children(&self) -> &[Rc<Node>] {
+        match *self {
+            Node::Leaf(_) => &[],
+            Node::List(ref children) |
+            Node::Tagged(_, ref children) => children.as_slice(),
+        }
+    }
+
+    /// The number of tagged tuples in this subtree.
+    fn tagged_tuples(&self) -> usize {
+        let own = match *self {
+            Node::Tagged(..) => 1,
+            _ => 0
+        };
+        own + self.children()
+            .iter()
+            .map(|child| child.tagged_tuples())
+            .sum::<usize>()
+    }
+
+    /// Collect the tags of this subtree, in order of first appearance.
+    fn collect_tags(&self, tags: &mut Vec<InterfaceName>, indices: &mut HashMap<InterfaceName, u32>) {
+        if let Node::Tagged(ref tag, _) = *self {
+            if !indices.contains_key(tag) {
+                indices.insert(tag.clone(), tags.len() as u32);
+                tags.push(tag.clone());
+            }
+        }
+        for child in self.children() {
+            child.collect_tags(tags, indices);
+        }
+    }
+
+    /// Count the shapes of depth 2 to `depth` of all the tagged tuples of this subtree,
+    /// ignoring shapes that contain a single tagged tuple.
+    fn count_shapes(&self, depth: usize, counts: &mut HashMap<Shape, usize>) {
+        if let Node::Tagged(..) = *self {
+            let mut previous = None;
+            for shape_depth in 2..depth + 1 {
+                let shape = self.shape(shape_depth);
+                // A subtree shallower than `depth` has the same shape at all larger depths.
+                if shape.size() > 1 && previous.as_ref() != Some(&shape) {
+                    *counts.entry(shape.clone())
+                        .or_insert(0) += 1;
+                }
+                previous = Some(shape);
+            }
+        }
+        for child in self.children() {
+            child.count_shapes(depth, counts);
+        }
+    }
+}
+
+/// Abstract type for the contents of the tree.
+#[derive(Clone)]
+pub struct Tree(Rc<Node>);
+
+/// Write a tree with a set of templates, counting their instances.
+struct TreeWriter<'a> {
+    depth: usize,
+    tags: &'a HashMap<InterfaceName, u32>,
+    templates: HashMap<Shape, u32>,
+
+    /// The number of instances of each template, by template index.
+    uses: Vec<usize>,
+}
+impl<'a> TreeWriter<'a> {
+    fn new(depth: usize, tags: &'a HashMap<InterfaceName, u32>, templates: &[Shape]) -> Self {
+        TreeWriter {
+            depth,
+            tags,
+            templates: templates.iter()
+                .cloned()
+                .enumerate()
+                .map(|(index, shape)| (shape, index as u32))
+                .collect(),
+            uses: vec![0; templates.len()],
+        }
+    }
+
+    fn write_hole(&mut self, node: &Node, out: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        match *node {
+            Node::Leaf(ref bytes) => {
+                out.write_all(bytes)?;
+            }
+            Node::List(ref items) => {
+                out.write_varnum(items.len() as u32)?;
+                for item in items {
+                    self.write_hole(item, out)?;
+                }
+            }
+            Node::Tagged(ref tag, ref children) => {
+                // Prefer the deepest template, as it implies more tags.
+                let template = (2..self.depth + 1).rev()
+                    .filter_map(|depth| {
+                        let shape = node.shape(depth);
+                        self.templates.get(&shape)
+                            .map(|index| (*index, shape))
+                    })
+                    .next();
+                match template {
+                    Some((index, shape)) => {
+                        out.write_varnum(index * 2 + 1)?;
+                        self.uses[index as usize] += 1;
+                        self.write_instance(node, &shape, out)?;
+                    }
+                    None => {
+                        let index = self.tags.get(tag)
+                            .expect("All tags should have been collected by now");
+                        out.write_varnum(index * 2)?;
+                        for child in children {
+                            self.write_hole(child, out)?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_instance(&mut self, node: &Node, shape: &Shape, out: &mut Vec<u8>) -> Result<(), std::io::Error> {
+        match (node, shape) {
+            (_, &Shape::Hole) => self.write_hole(node, out),
+            (&Node::Tagged(_, ref children), &Shape::Tagged(_, ref fields)) => {
+                for (child, field) in children.iter().zip(fields.iter()) {
+                    self.write_instance(child, field, out)?;
+                }
+                Ok(())
+            }
+            _ => panic!("A template should match its instances")
+        }
+    }
+
+    /// The templates used at least `min_uses` times, most used first.
+    fn used_templates(&self, min_uses: usize) -> Vec<Shape> {
+        let mut used : Vec<_> = self.templates.iter()
+            .map(|(shape, index)| (self.uses[*index as usize], shape))
+            .filter(|&(uses, _)| uses >= min_uses)
+            .collect();
+        used.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        used.into_iter()
+            .map(|(_, shape)| shape.clone())
+            .collect()
+    }
+}
+
+/// A writer for the templates format.
+pub struct Encoder {
+    options: Options,
+
+    /// The latest tree written, i.e. the root once we are done.
+    root: Option<Rc<Node>>,
+}
+impl Encoder {
+    pub fn new(options: Options) -> Self {
+        Encoder {
+            options,
+            root: None,
+        }
+    }
+
+    fn register(&mut self, node: Node) -> Tree {
+        let node = Rc::new(node);
+        self.root = Some(node.clone());
+        Tree(node)
+    }
+
+    fn leaf<F>(&mut self, f: F) -> Result<Tree, TokenWriterError> where F: FnOnce(&mut Vec<u8>) -> Result<usize, std::io::Error> {
+        let mut buf = vec![];
+        f(&mut buf)
+            .map_err(TokenWriterError::WriteError)?;
+        Ok(self.register(Node::Leaf(buf)))
+    }
+}
+
+impl TokenWriterWithTree for Encoder {
+    type Tree = Tree;
+    type Data = Vec<u8>;
+
+    fn done(self) -> Result<Self::Data, TokenWriterError> {
+        let root = self.root
+            .unwrap_or_else(|| Rc::new(Node::Leaf(vec![])));
+        let depth = self.options.depth;
+        let min_uses = self.options.min_uses;
+
+        let mut tags = vec![];
+        let mut tag_indices = HashMap::new();
+        root.collect_tags(&mut tags, &mut tag_indices);
+
+        // As instances prefer deeper templates, some shapes are used less often
+        // than they appear. Write the tree once with all the shapes that appear
+        // often enough, then keep those that are actually used often enough.
+        let mut counts = HashMap::new();
+        root.count_shapes(depth, &mut counts);
+        let candidates : Vec<Shape> = counts.into_iter()
+            .filter(|&(_, count)| count >= min_uses)
+            .map(|(shape, _)| shape)
+            .collect();
+        let mut writer = TreeWriter::new(depth, &tag_indices, &candidates);
+        writer.write_hole(&root, &mut vec![])
+            .map_err(TokenWriterError::WriteError)?;
+        let templates = writer.used_templates(min_uses);
+        debug!(target: "templates", "Keeping {} templates out of {} candidates", templates.len(), candidates.len());
+
+        let mut data = vec![];
+        data.write_all(HEADER_TEMPLATES.as_bytes())
+            .map_err(TokenWriterError::WriteError)?;
+        data.write_varnum(tags.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        for tag in &tags {
+            data.write_varnum(tag.as_str().len() as u32)
+                .map_err(TokenWriterError::WriteError)?;
+            data.write_all(tag.as_str().as_bytes())
+                .map_err(TokenWriterError::WriteError)?;
+        }
+        let templates_start = data.len();
+        data.write_varnum(templates.len() as u32)
+            .map_err(TokenWriterError::WriteError)?;
+        for template in &templates {
+            template.write(&tag_indices, &mut data)
+                .map_err(TokenWriterError::WriteError)?;
+        }
+        let templates_bytes = data.len() - templates_start;
+
+        data.write_all(HEADER_TREE.as_bytes())
+            .map_err(TokenWriterError::WriteError)?;
+        let tree_start = data.len();
+        let mut writer = TreeWriter::new(depth, &tag_indices, &templates);
+        writer.write_hole(&root, &mut data)
+            .map_err(TokenWriterError::WriteError)?;
+
+        let mut statistics = self.options.statistics.borrow_mut();
+        statistics.number_of_files += 1;
+        statistics.templates += templates.len();
+        statistics.templates_bytes += templates_bytes;
+        statistics.tagged_tuples += root.tagged_tuples();
+        for (template, uses) in templates.iter().zip(writer.uses.iter()) {
+            statistics.instances += uses;
+            statistics.implied_tags += uses * (template.size() - 1);
+        }
+        statistics.tree_bytes += data.len() - tree_start;
+        Ok(data)
+    }
+
+    fn float(&mut self, value: Option<f64>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_maybe_varfloat(value, NaNPolicy::default()))
+    }
+
+    fn big_int(&mut self, value: Option<&BigInt>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_maybe_varbigint(value.map(BigInt::as_str)))
+    }
+
+    fn reg_exp_flags(&mut self, value: Option<&RegExpFlags>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_maybe_reg_exp_flags(value))
+    }
+
+    fn bool(&mut self, value: Option<bool>) -> Result<Self::Tree, TokenWriterError> {
+        let bytes = ::bytes::bool::bytes_of_bool(value);
+        self.leaf(|buf| buf.write_all(&bytes).map(|_| bytes.len()))
+    }
+
+    fn offset(&mut self) -> Result<Self::Tree, TokenWriterError> {
+        Ok(self.register(Node::Leaf(vec![])))
+    }
+
+    fn unsigned_long(&mut self, value: u32) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| buf.write_varnum(value))
+    }
+
+    fn string(&mut self, value: Option<&SharedString>) -> Result<Self::Tree, TokenWriterError> {
+        self.leaf(|buf| match value {
+            None => buf.write_varnum(0),
+            Some(value) => {
+                let len = buf.write_varnum(value.len() as u32 + 1)?;
+                buf.write_all(value.as_bytes())?;
+                Ok(len + value.len())
+            }
+        })
+    }
+
+    fn list(&mut self, items: Vec<Self::Tree>) -> Result<Self::Tree, TokenWriterError> {
+        let items = items.into_iter()
+            .map(|item| item.0)
+            .collect();
+        Ok(self.register(Node::List(items)))
+    }
+
+    fn tagged_tuple(&mut self, tag: &InterfaceName, children: &[(&FieldName, Self::Tree)]) -> Result<Self::Tree, TokenWriterError> {
+        let children = children.iter()
+            .map(|&(_, ref child)| child.0.clone())
+            .collect();
+        Ok(self.register(Node::Tagged(tag.clone(), children)))
+    }
+}
+
+/// A list or a tagged tuple being read.
+enum Frame {
+    /// A list, or a tagged tuple written in full, whose children are all holes.
+    Holes,
+
+    /// A tagged tuple that belongs to an instance of a template, with the shapes
+    /// of its fields and the number of fields read so far.
+    Instance(Rc<Vec<Shape>>, usize),
+}
+
+/// A reader for the templates format.
+pub struct Decoder<R: Read> {
+    reader: R,
+    tags: Vec<InterfaceName>,
+
+    /// The templates, all of them `Shape::Tagged`.
+    templates: Vec<Shape>,
+
+    /// The lists and tagged tuples being read, innermost last.
+    frames: Vec<Frame>,
+}
+impl<R: Read> Decoder<R> {
+    pub fn new(mut reader: R) -> Result<Self, TokenReaderError> {
+        reader.read_const(HEADER_TEMPLATES.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        let number_of_tags = reader.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let mut tags = vec![];
+        for _ in 0..number_of_tags {
+            let byte_len = reader.read_varnum()
+                .map_err(TokenReaderError::ReadError)? as usize;
+            let mut bytes = vec![];
+            reader.by_ref()
+                .take(byte_len as u64)
+                .read_to_end(&mut bytes)
+                .map_err(TokenReaderError::ReadError)?;
+            if bytes.len() != byte_len {
+                return Err(TokenReaderError::BadLength { expected: byte_len, got: bytes.len() });
+            }
+            let tag = String::from_utf8(bytes)
+                .map_err(TokenReaderError::Encoding)?;
+            tags.push(InterfaceName::from_string(tag));
+        }
+
+        let number_of_templates = reader.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        let mut templates = vec![];
+        for _ in 0..number_of_templates {
+            match Shape::read(&mut reader, &tags, MAX_DEPTH)? {
+                Shape::Hole => return Err(TokenReaderError::InvalidValue),
+                template => templates.push(template)
+            }
+        }
+        debug!(target: "templates", "Read {} tags, {} templates", tags.len(), templates.len());
+
+        reader.read_const(HEADER_TREE.as_bytes())
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(Decoder {
+            reader,
+            tags,
+            templates,
+            frames: vec![],
+        })
+    }
+
+    /// Consume the next child of the innermost list or tagged tuple, returning its shape.
+    fn next_shape(&mut self) -> Result<Shape, TokenReaderError> {
+        match self.frames.last_mut() {
+            Some(&mut Frame::Instance(ref fields, ref mut position)) => {
+                let shape = fields.get(*position)
+                    .cloned()
+                    .ok_or(TokenReaderError::InvalidValue)?;
+                *position += 1;
+                Ok(shape)
+            }
+            _ => Ok(Shape::Hole)
+        }
+    }
+
+    /// Consume the next child of the innermost list or tagged tuple, which must be a hole.
+    fn hole(&mut self) -> Result<(), TokenReaderError> {
+        match self.next_shape()? {
+            Shape::Hole => Ok(()),
+            // The template expects a tagged tuple.
+            Shape::Tagged(..) => Err(TokenReaderError::InvalidValue)
+        }
+    }
+}
+
+impl<R: Read> FileStructurePrinter for Decoder<R> {}
+
+impl<R: Read> TokenReader for Decoder<R> {
+    fn string_at(&mut self, _path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        self.hole()?;
+        let value = self.reader.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        if value == 0 {
+            return Ok(None);
+        }
+        let byte_len = (value - 1) as usize;
+        let mut bytes = vec![];
+        self.reader.by_ref()
+            .take(byte_len as u64)
+            .read_to_end(&mut bytes)
+            .map_err(TokenReaderError::ReadError)?;
+        if bytes.len() != byte_len {
+            return Err(TokenReaderError::BadLength { expected: byte_len, got: bytes.len() });
+        }
+        let string = String::from_utf8(bytes)
+            .map_err(TokenReaderError::Encoding)?;
+        Ok(Some(SharedString::from_string(string)))
+    }
+
+    fn float_at(&mut self, _path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        self.hole()?;
+        self.reader.read_maybe_varfloat(NaNPolicy::default())
+            .map_err(TokenReaderError::ReadError)
+    }
+
+    fn big_int_at(&mut self, _path: &Path) -> Result<Option<BigInt>, TokenReaderError> {
+        self.hole()?;
+        let value = self.reader.read_maybe_varbigint()
+            .map_err(TokenReaderError::ReadError)?;
+        Ok(value.map(BigInt::from_string))
+    }
+
+    fn reg_exp_flags_at(&mut self, _path: &Path) -> Result<Option<RegExpFlags>, TokenReaderError> {
+        self.hole()?;
+        self.reader.read_maybe_reg_exp_flags()
+            .map_err(TokenReaderError::ReadError)
+    }
+
+    fn unsigned_long_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        self.hole()?;
+        self.reader.read_varnum()
+            .map_err(TokenReaderError::ReadError)
+    }
+
+    fn bool_at(&mut self, _path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        self.hole()?;
+        let mut buf : [u8; 1] = [0];
+        self.reader.read_exact(&mut buf)
+            .map_err(TokenReaderError::ReadError)?;
+        ::bytes::bool::bool_of_bytes(&buf)
+            .map_err(|_| TokenReaderError::invalid_value(&"bool"))
+    }
+
+    fn offset_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        self.hole()?;
+        Ok(0)
+    }
+
+    fn enter_list_at(&mut self, _path: &Path) -> Result<u32, TokenReaderError> {
+        self.hole()?;
+        let len = self.reader.read_varnum()
+            .map_err(TokenReaderError::ReadError)?;
+        self.frames.push(Frame::Holes);
+        Ok(len)
+    }
+
+    fn exit_list_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        match self.frames.pop() {
+            Some(Frame::Holes) => Ok(()),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn enter_tagged_tuple_at(&mut self, _path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
+        let shape = match self.next_shape()? {
+            Shape::Hole => {
+                let value = self.reader.read_varnum()
+                    .map_err(TokenReaderError::ReadError)?;
+                let index = value >> 1;
+                if value & 1 == 0 {
+                    let tag = self.tags.get(index as usize)
+                        .cloned()
+                        .ok_or(TokenReaderError::BadKindIndex(index))?;
+                    self.frames.push(Frame::Holes);
+                    return Ok((tag, None));
+                }
+                self.templates.get(index as usize)
+                    .cloned()
+                    .ok_or(TokenReaderError::InvalidValue)?
+            }
+            shape => shape
+        };
+        match shape {
+            Shape::Tagged(tag, fields) => {
+                self.frames.push(Frame::Instance(fields, 0));
+                Ok((tag, None))
+            }
+            Shape::Hole => Err(TokenReaderError::InvalidValue) // Templates are never holes.
+        }
+    }
+
+    fn exit_tagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        match self.frames.pop() {
+            Some(Frame::Holes) => Ok(()),
+            Some(Frame::Instance(ref fields, position)) if position == fields.len() => Ok(()),
+            _ => Err(TokenReaderError::InvalidValue)
+        }
+    }
+
+    fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        unimplemented!()
+    }
+}
+
+/// Command-line management.
+pub struct FormatProvider;
+impl ::FormatProvider for FormatProvider {
+    fn subcommand<'a, 'b>(&self) -> clap::App<'a, 'b> {
+        use clap::*;
+        SubCommand::with_name("templates")
+            .about("(EXPERIMENTAL) Store the shapes of subtrees that are repeated across the file as templates, then encode their instances as a template and the contents of its holes.")
+            .arg(Arg::with_name("depth")
+                .long("depth")
+                .takes_value(true)
+                .default_value("3")
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(depth) if depth >= 2 && depth <= MAX_DEPTH => Ok(()),
+                    Ok(_) => Err(format!("Invalid depth, expected a number in [2, {}]", MAX_DEPTH)),
+                    Err(e) => Err(format!("Invalid number {}", e))
+                })
+                .help("Maximal depth of templates. 2 = a tagged tuple and its fields, 3 = also the fields of its fields, etc.")
+            )
+            .arg(Arg::with_name("min-uses")
+                .long("min-uses")
+                .takes_value(true)
+                .default_value("4")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Minimal number of instances of a shape to store it as a template.")
+            )
+    }
+
+    fn handle_subcommand(&self, matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
+        let options = match matches {
+            None => Options::default(),
+            Some(matches) => {
+                let depth = matches.value_of("depth")
+                    .unwrap() // Guaranteed by `clap`.
+                    .parse::<usize>()
+                    .unwrap(); // Guaranteed by `clap`.
+                let min_uses = matches.value_of("min-uses")
+                    .unwrap() // Guaranteed by `clap`.
+                    .parse::<usize>()
+                    .unwrap(); // Guaranteed by `clap`.
+                Options::new(depth, min_uses)
+            }
+        };
+        Ok(::Format::Templates {
+            options
+        })
+    }
+}
+
+#[test]
+fn test_templates_roundtrip() {
+    use binjs_shared::ast::Path;
+    use std::io::Cursor;
+
+    let path = Path::new();
+
+    // `Call(Identifier(name), [Literal(value)])`, repeated, then a lonely `Identifier`.
+    let write = |options: Options| {
+        let mut encoder = Encoder::new(options);
+        let mut calls = vec![];
+        for i in 0..20 {
+            let name = encoder.string(Some(&SharedString::from_string(format!("f{}", i)))).unwrap();
+            let callee = encoder.tagged_tuple(&InterfaceName::from_str("Identifier"), &[(&FieldName::from_str("name"), name)]).unwrap();
+            let value = encoder.float(Some(i as f64)).unwrap();
+            let argument = encoder.tagged_tuple(&InterfaceName::from_str("Literal"), &[(&FieldName::from_str("value"), value)]).unwrap();
+            let arguments = encoder.list(vec![argument]).unwrap();
+            calls.push(encoder.tagged_tuple(&InterfaceName::from_str("Call"), &[
+                (&FieldName::from_str("callee"), callee),
+                (&FieldName::from_str("arguments"), arguments),
+            ]).unwrap());
+        }
+        let name = encoder.string(None).unwrap();
+        calls.push(encoder.tagged_tuple(&InterfaceName::from_str("Identifier"), &[(&FieldName::from_str("name"), name)]).unwrap());
+        encoder.list(calls).unwrap();
+        encoder.done()
+            .expect("Could not finalize data")
+    };
+
+    let options = Options::default();
+    let data = write(options.clone());
+    let statistics = options.statistics_for_write();
+    assert_eq!(statistics.templates, 1);
+    assert_eq!(statistics.instances, 20);
+    assert_eq!(statistics.implied_tags, 20);
+    assert_eq!(statistics.tagged_tuples, 61);
+
+    // Without templates, the file is larger.
+    let plain = write(Options::new(DEFAULT_DEPTH, std::usize::MAX));
+    assert!(data.len() < plain.len());
+
+    for data in &[data, plain] {
+        let mut decoder = Decoder::new(Cursor::new(data))
+            .expect("Could not read header");
+        assert_eq!(decoder.enter_list_at(&path).unwrap(), 21);
+        for i in 0..20 {
+            assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Call"));
+            assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Identifier"));
+            assert_eq!(decoder.string_at(&path).unwrap(), Some(SharedString::from_string(format!("f{}", i))));
+            decoder.exit_tagged_tuple_at(&path).unwrap();
+            assert_eq!(decoder.enter_list_at(&path).unwrap(), 1);
+            assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Literal"));
+            assert_eq!(decoder.float_at(&path).unwrap(), Some(i as f64));
+            decoder.exit_tagged_tuple_at(&path).unwrap();
+            decoder.exit_list_at(&path).unwrap();
+            decoder.exit_tagged_tuple_at(&path).unwrap();
+        }
+        assert_eq!(decoder.enter_tagged_tuple_at(&path).unwrap().0, InterfaceName::from_str("Identifier"));
+        assert_eq!(decoder.string_at(&path).unwrap(), None);
+        decoder.exit_tagged_tuple_at(&path).unwrap();
+        decoder.exit_list_at(&path).unwrap();
+    }
+
+    // Reading a value that doesn't match the template fails.
+    let mut decoder = Decoder::new(Cursor::new(write(Options::default())))
+        .expect("Could not read header");
+    decoder.enter_list_at(&path).unwrap();
+    decoder.enter_tagged_tuple_at(&path).unwrap();
+    assert!(decoder.string_at(&path).is_err());
+}
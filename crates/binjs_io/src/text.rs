@@ -0,0 +1,449 @@
+//! A line-oriented trace of tokens, and the matching reader.
+//!
+//! Each token is written on its own line, as its path in the AST, its kind and its value,
+//! separated by a single space, e.g.
+//!
+//! ```text
+//! / tagged Script
+//! /Script.statements list 1
+//! /Script.statements tagged ExpressionStatement
+//! /Script.statements/ExpressionStatement.expression tagged LiteralStringExpression
+//! /Script.statements/ExpressionStatement.expression/LiteralStringExpression.value string "foo\n"
+//! /Script.statements/ExpressionStatement.expression end_tagged LiteralStringExpression
+//! /Script.statements end_tagged ExpressionStatement
+//! /Script.statements end_list
+//! / end_tagged Script
+//! ```
+//!
+//! This format is designed to compare the tokens written by two encoders, or by two
+//! versions of an encoder, with standard text tools, e.g. `diff` or `grep`. Strings are
+//! quoted and escaped, null values and null interfaces are written `null`. Offsets are
+//! not written, as they depend on the binary format.
+//!
+//! The reader checks that each token is read at the path at which it was written.
+//! Blank lines and lines starting with `#` are ignored, so that traces may be annotated.
+
+use io::{ FileStructurePrinter, Path, TokenReader, TokenWriter };
+use ::{ TokenReaderError, TokenWriterError };
+
+use binjs_shared::{ FieldName, IdentifierName, InterfaceName, Node, PropertyKey, SharedString };
+
+use std;
+use std::io::{ BufRead, BufReader, Read, Write };
+use std::rc::Rc;
+
+use clap;
+
+/// The value of null strings, floats, bools and interfaces.
+const NULL: &str = "null";
+
+/// Render `path` as e.g. `/Script.statements/ExpressionStatement.expression`.
+fn path_to_string(path: &Path) -> String {
+    if path.len() == 0 {
+        return "/".to_string();
+    }
+    let mut result = String::new();
+    for item in path.iter() {
+        let (_, ref field) = *item.field();
+        result.push('/');
+        result.push_str(item.interface().as_str());
+        result.push('.');
+        result.push_str(field.as_str());
+    }
+    result
+}
+
+/// Quote and escape `value`, so that it fits on a single line.
+fn quote(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => result.push(c)
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// The reverse of `quote`, or `None` if `value` is not a quoted string.
+fn unquote(value: &str) -> Option<String> {
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return None;
+    }
+    let mut result = String::with_capacity(value.len() - 2);
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            return None;
+        }
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next()? {
+            '"' => result.push('"'),
+            '\\' => result.push('\\'),
+            'n' => result.push('\n'),
+            'r' => result.push('\r'),
+            't' => result.push('\t'),
+            'u' => {
+                if chars.next()? != '{' {
+                    return None;
+                }
+                let digits : String = chars.by_ref()
+                    .take_while(|c| *c != '}')
+                    .collect();
+                let code = u32::from_str_radix(&digits, 16).ok()?;
+                result.push(std::char::from_u32(code)?);
+            }
+            _ => return None
+        }
+    }
+    Some(result)
+}
+
+/// Write one token per line.
+pub struct Encoder {
+    data: Vec<u8>,
+}
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder {
+            data: vec![],
+        }
+    }
+
+    fn line(&mut self, path: &Path, kind: &str, value: Option<&str>) -> Result<(), TokenWriterError> {
+        let path = path_to_string(path);
+        let result = match value {
+            Some(value) => writeln!(self.data, "{} {} {}", path, kind, value),
+            None => writeln!(self.data, "{} {}", path, kind)
+        };
+        result.map_err(TokenWriterError::WriteError)
+    }
+
+    fn maybe_string(&mut self, path: &Path, kind: &str, value: Option<&SharedString>) -> Result<(), TokenWriterError> {
+        match value {
+            Some(value) => self.line(path, kind, Some(&quote(value.as_str()))),
+            None => self.line(path, kind, Some(NULL))
+        }
+    }
+}
+
+impl TokenWriter for Encoder {
+    type Data = Vec<u8>;
+
+    fn done(self) -> Result<Self::Data, TokenWriterError> {
+        Ok(self.data)
+    }
+
+    fn enter_tagged_tuple_at(&mut self, _node: &Node, tag: &InterfaceName, _children: &[&FieldName], path: &Path) -> Result<(), TokenWriterError> {
+        let tag = if tag.as_str().is_empty() { NULL } else { tag.as_str() };
+        self.line(path, "tagged", Some(tag))
+    }
+
+    fn exit_tagged_tuple_at(&mut self, _node: &Node, tag: &InterfaceName, _children: &[&FieldName], path: &Path) -> Result<(), TokenWriterError> {
+        let tag = if tag.as_str().is_empty() { NULL } else { tag.as_str() };
+        self.line(path, "end_tagged", Some(tag))
+    }
+
+    fn enter_list_at(&mut self, len: usize, path: &Path) -> Result<(), TokenWriterError> {
+        self.line(path, "list", Some(&len.to_string()))
+    }
+
+    fn exit_list_at(&mut self, path: &Path) -> Result<(), TokenWriterError> {
+        self.line(path, "end_list", None)
+    }
+
+    fn string_at(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenWriterError> {
+        self.maybe_string(path, "string", value)
+    }
+
+    fn string_enum_at(&mut self, value: &SharedString, path: &Path) -> Result<(), TokenWriterError> {
+        self.line(path, "string_enum", Some(&quote(value.as_str())))
+    }
+
+    fn identifier_name_at(&mut self, value: Option<&IdentifierName>, path: &Path) -> Result<(), TokenWriterError> {
+        self.maybe_string(path, "identifier_name", value.map(IdentifierName::as_shared_string))
+    }
+
+    fn property_key_at(&mut self, value: Option<&PropertyKey>, path: &Path) -> Result<(), TokenWriterError> {
+        self.maybe_string(path, "property_key", value.map(PropertyKey::as_shared_string))
+    }
+
+    fn float_at(&mut self, value: Option<f64>, path: &Path) -> Result<(), TokenWriterError> {
+        match value {
+            Some(value) => self.line(path, "float", Some(&format!("{}", value))),
+            None => self.line(path, "float", Some(NULL))
+        }
+    }
+
+    fn unsigned_long_at(&mut self, value: u32, path: &Path) -> Result<(), TokenWriterError> {
+        self.line(path, "unsigned_long", Some(&value.to_string()))
+    }
+
+    fn bool_at(&mut self, value: Option<bool>, path: &Path) -> Result<(), TokenWriterError> {
+        match value {
+            Some(true) => self.line(path, "bool", Some("true")),
+            Some(false) => self.line(path, "bool", Some("false")),
+            None => self.line(path, "bool", Some(NULL))
+        }
+    }
+
+    fn offset_at(&mut self, path: &Path) -> Result<(), TokenWriterError> {
+        self.line(path, "offset", None)
+    }
+}
+
+/// Read one token per line, as written by `Encoder`.
+pub struct Decoder<R: Read> {
+    lines: std::io::Lines<BufReader<R>>,
+
+    /// The number of the latest line read, starting at 1.
+    line: usize,
+
+    /// The tags of the enclosing tagged tuples, innermost last.
+    tags: Vec<InterfaceName>,
+}
+impl<R: Read> Decoder<R> {
+    pub fn new(source: R) -> Self {
+        Decoder {
+            lines: BufReader::new(source).lines(),
+            line: 0,
+            tags: vec![],
+        }
+    }
+
+    /// An error at the latest line read.
+    fn error(&self, message: String) -> TokenReaderError {
+        TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Line {}: {}", self.line, message)))
+    }
+
+    /// Read the next token, which must be of kind `kind`, at `path`, returning its value,
+    /// or the empty string if it has none.
+    fn next(&mut self, path: &Path, kind: &str) -> Result<String, TokenReaderError> {
+        let line = loop {
+            let line = self.lines.next()
+                .ok_or_else(|| TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("Expected {} after line {}", kind, self.line))))?
+                .map_err(TokenReaderError::ReadError)?;
+            self.line += 1;
+            if !line.trim().is_empty() && !line.starts_with('#') {
+                break line;
+            }
+        };
+        let mut parts = line.splitn(3, ' ');
+        let found_path = parts.next().unwrap_or("");
+        let found_kind = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("");
+        let expected_path = path_to_string(path);
+        if found_path != expected_path {
+            return Err(self.error(format!("Expected path {}, got {}", expected_path, found_path)));
+        }
+        if found_kind != kind {
+            return Err(self.error(format!("Expected {}, got {}", kind, found_kind)));
+        }
+        Ok(value.to_string())
+    }
+
+    fn maybe_string(&mut self, path: &Path, kind: &str) -> Result<Option<SharedString>, TokenReaderError> {
+        let value = self.next(path, kind)?;
+        if value == NULL {
+            return Ok(None);
+        }
+        self.string(value)
+            .map(Some)
+    }
+
+    fn string(&self, value: String) -> Result<SharedString, TokenReaderError> {
+        match unquote(&value) {
+            Some(string) => Ok(SharedString::from_string(string)),
+            None => Err(self.error(format!("Invalid string {}", value)))
+        }
+    }
+}
+
+impl<R: Read> FileStructurePrinter for Decoder<R> {}
+
+impl<R: Read> TokenReader for Decoder<R> {
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        self.maybe_string(path, "string")
+    }
+
+    fn string_enum_at(&mut self, path: &Path) -> Result<SharedString, TokenReaderError> {
+        let value = self.next(path, "string_enum")?;
+        self.string(value)
+    }
+
+    fn identifier_name_at(&mut self, path: &Path) -> Result<Option<IdentifierName>, TokenReaderError> {
+        Ok(self.maybe_string(path, "identifier_name")?
+            .map(IdentifierName))
+    }
+
+    fn property_key_at(&mut self, path: &Path) -> Result<Option<PropertyKey>, TokenReaderError> {
+        Ok(self.maybe_string(path, "property_key")?
+            .map(PropertyKey))
+    }
+
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        let value = self.next(path, "float")?;
+        if value == NULL {
+            return Ok(None);
+        }
+        value.parse::<f64>()
+            .map(Some)
+            .map_err(|_| self.error(format!("Invalid float {}", value)))
+    }
+
+    fn unsigned_long_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        let value = self.next(path, "unsigned_long")?;
+        value.parse::<u32>()
+            .map_err(|_| self.error(format!("Invalid unsigned long {}", value)))
+    }
+
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        let value = self.next(path, "bool")?;
+        match value.as_str() {
+            "true" => Ok(Some(true)),
+            "false" => Ok(Some(false)),
+            NULL => Ok(None),
+            _ => Err(self.error(format!("Invalid bool {}", value)))
+        }
+    }
+
+    fn offset_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.next(path, "offset")?;
+        Ok(0)
+    }
+
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        let value = self.next(path, "list")?;
+        value.parse::<u32>()
+            .map_err(|_| self.error(format!("Invalid list length {}", value)))
+    }
+
+    fn exit_list_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        self.next(path, "end_list")?;
+        Ok(())
+    }
+
+    fn enter_tagged_tuple_at(&mut self, path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
+        let value = self.next(path, "tagged")?;
+        let tag = if value == NULL {
+            InterfaceName::from_str("")
+        } else {
+            InterfaceName::from_string(value)
+        };
+        self.tags.push(tag.clone());
+        Ok((tag, None))
+    }
+
+    fn exit_tagged_tuple_at(&mut self, path: &Path) -> Result<(), TokenReaderError> {
+        let value = self.next(path, "end_tagged")?;
+        let tag = self.tags.pop()
+            .ok_or(TokenReaderError::InvalidValue)?;
+        let expected = if tag.as_str().is_empty() { NULL } else { tag.as_str() };
+        if value != expected {
+            return Err(self.error(format!("Expected end_tagged {}, got {}", expected, value)));
+        }
+        Ok(())
+    }
+
+    fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+}
+
+/// Command-line management.
+pub struct FormatProvider;
+impl ::FormatProvider for FormatProvider {
+    fn subcommand<'a, 'b>(&self) -> clap::App<'a, 'b> {
+        use clap::*;
+        SubCommand::with_name("text")
+            .about("(EXPERIMENTAL) Write one token per line, with its path in the AST. This format is designed to compare the tokens written by two encoders with standard text tools, e.g. `diff`, and is not considered useful for any other reason.")
+    }
+
+    fn handle_subcommand(&self, _matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
+        Ok(::Format::Text)
+    }
+}
+
+#[test]
+fn test_text_roundtrip() {
+    use std::io::Cursor;
+
+    struct Dummy;
+    impl Node for Dummy {
+        fn name(&self) -> &'static str {
+            "Dummy"
+        }
+    }
+
+    let node = Dummy;
+    let script = InterfaceName::from_str("Script");
+    let literal = InterfaceName::from_str("LiteralStringExpression");
+    let null = InterfaceName::from_str("");
+    let statements = FieldName::from_str("statements");
+    let value = FieldName::from_str("value");
+
+    let root = Path::new();
+    let mut in_statements = Path::new();
+    in_statements.enter_interface(script.clone());
+    in_statements.enter_field((0, statements.clone()));
+    let mut in_value = in_statements.clone();
+    in_value.enter_interface(literal.clone());
+    in_value.enter_field((0, value.clone()));
+
+    let mut encoder = Encoder::new();
+    encoder.enter_tagged_tuple_at(&node, &script, &[&statements], &root).unwrap();
+    encoder.enter_list_at(2, &in_statements).unwrap();
+    encoder.enter_tagged_tuple_at(&node, &literal, &[&value], &in_statements).unwrap();
+    encoder.string_at(Some(&SharedString::from_str("\"foo\"\n\u{1}bar é")), &in_value).unwrap();
+    encoder.float_at(Some(-0.5), &in_value).unwrap();
+    encoder.bool_at(None, &in_value).unwrap();
+    encoder.unsigned_long_at(42, &in_value).unwrap();
+    encoder.identifier_name_at(None, &in_value).unwrap();
+    encoder.string_enum_at(&SharedString::from_str("null"), &in_value).unwrap();
+    encoder.exit_tagged_tuple_at(&node, &literal, &[&value], &in_statements).unwrap();
+    encoder.enter_tagged_tuple_at(&node, &null, &[], &in_statements).unwrap();
+    encoder.exit_tagged_tuple_at(&node, &null, &[], &in_statements).unwrap();
+    encoder.exit_list_at(&in_statements).unwrap();
+    encoder.exit_tagged_tuple_at(&node, &script, &[&statements], &root).unwrap();
+    let data = encoder.done()
+        .unwrap();
+
+    let source = String::from_utf8(data)
+        .expect("Invalid UTF-8");
+    assert_eq!(source.lines().count(), 14);
+    assert!(source.contains("/Script.statements/LiteralStringExpression.value string \"\\\"foo\\\"\\n\\u{1}bar é\"\n"));
+
+    // Annotations are ignored.
+    let annotated = format!("# A comment\n\n{}", source);
+    let mut decoder = Decoder::new(Cursor::new(annotated.as_bytes()));
+    assert_eq!(decoder.enter_tagged_tuple_at(&root).unwrap().0, script);
+    assert_eq!(decoder.enter_list_at(&in_statements).unwrap(), 2);
+    assert_eq!(decoder.enter_tagged_tuple_at(&in_statements).unwrap().0, literal);
+    assert_eq!(decoder.string_at(&in_value).unwrap(), Some(SharedString::from_str("\"foo\"\n\u{1}bar é")));
+    assert_eq!(decoder.float_at(&in_value).unwrap(), Some(-0.5));
+    assert_eq!(decoder.bool_at(&in_value).unwrap(), None);
+    assert_eq!(decoder.unsigned_long_at(&in_value).unwrap(), 42);
+    assert_eq!(decoder.identifier_name_at(&in_value).unwrap(), None);
+    assert_eq!(decoder.string_enum_at(&in_value).unwrap(), SharedString::from_str("null"));
+    decoder.exit_tagged_tuple_at(&in_statements).unwrap();
+    assert_eq!(decoder.enter_tagged_tuple_at(&in_statements).unwrap().0, null);
+    decoder.exit_tagged_tuple_at(&in_statements).unwrap();
+    decoder.exit_list_at(&in_statements).unwrap();
+    decoder.exit_tagged_tuple_at(&root).unwrap();
+
+    // Tokens read at another path, or of another kind, are rejected.
+    let mut decoder = Decoder::new(Cursor::new(source.as_bytes()));
+    assert!(decoder.enter_tagged_tuple_at(&in_statements).is_err());
+    let mut decoder = Decoder::new(Cursor::new(source.as_bytes()));
+    assert!(decoder.enter_list_at(&root).is_err());
+}
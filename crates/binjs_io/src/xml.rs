@@ -1,23 +1,39 @@
-//! A trivial exporter to xml.
+//! A trivial exporter to xml, and the matching importer.
 //!
-//! Used mainly to extract statistics and/or to compare with XML-based compression mechanisms.
+//! Used mainly to extract statistics and/or to compare with XML-based compression mechanisms,
+//! and to debug decoders: a dump may be edited by hand, then re-encoded to another format,
+//! e.g. with `binjs_convert --from xml`.
+//!
+//! Each interface is an element named after the interface, containing one element per field,
+//! in the order of the grammar. Each list is a sequence of `<_item>` elements. Values are
+//! written as text, null values and null interfaces as `<_null/>`. Offsets are not written,
+//! their field is left empty.
 
-use ::{ TokenWriterWithTree, TokenWriterError };
+use io::{ FileStructurePrinter, Path, TokenReader };
+use ::{ TokenReaderError, TokenWriterWithTree, TokenWriterError };
 
 use binjs_shared::{ FieldName, InterfaceName, SharedString };
 
+use std;
 use std::rc::Rc;
-use std::io::Write;
+use std::io::{ Read, Write };
 
 use clap;
 use xml_rs;
 
+/// The element standing for a null value or a null interface.
+const NULL: &str = "_null";
+
+/// The element wrapping each item of a list.
+const ITEM: &str = "_item";
+
 #[derive(Debug)]
 pub enum SubTree {
     String(Option<SharedString>),
     Bool(Option<bool>),
     Float(Option<f64>),
     U32(u32),
+    Offset,
     List(Vec<Rc<SubTree>>),
     Node {
         name: SharedString,
@@ -31,16 +47,17 @@ impl SubTree {
         use self::SubTree::*;
         match *self {
             String(Some(ref s)) => { out.write(XmlEvent::characters(s.as_str()))?; }
-            String(None) => {},
+            String(None) => { Self::write_null(out)?; },
             Bool(Some(true)) => { out.write(XmlEvent::characters("true"))?; }
             Bool(Some(false)) => { out.write(XmlEvent::characters("false"))?; }
-            Bool(None) => {},
+            Bool(None) => { Self::write_null(out)?; },
             Float(Some(ref x)) => { out.write(XmlEvent::characters(&format!("{}", x)))?; }
-            Float(None) => {},
+            Float(None) => { Self::write_null(out)?; },
             U32(ref value) => { out.write(XmlEvent::characters(&format!("{}", value)))?; }
+            Offset => {},
             List(ref children) => {
                 for c in children {
-                    out.write(XmlEvent::start_element("_item"))?;
+                    out.write(XmlEvent::start_element(ITEM))?;
                     c.write(out)?;
                     out.write(XmlEvent::end_element())?;
                 }
@@ -48,7 +65,7 @@ impl SubTree {
             Node { ref name, ref children } => {
                 if name.len() == 0 {
                     assert_eq!(children.len(), 0);
-                    // Nothing to write
+                    Self::write_null(out)?;
                 } else {
                     out.write(XmlEvent::start_element(name.as_str()))?;
                     for (ref name, ref c) in children {
@@ -62,6 +79,13 @@ impl SubTree {
         }
         Ok(())
     }
+
+    fn write_null<W: Write>(out: &mut xml_rs::writer::EventWriter<W>) -> xml_rs::writer::Result<()> {
+        use xml_rs::writer::*;
+        out.write(XmlEvent::start_element(NULL))?;
+        out.write(XmlEvent::end_element())?;
+        Ok(())
+    }
 }
 
 pub struct Encoder {
@@ -115,8 +139,8 @@ impl TokenWriterWithTree for Encoder {
     }
 
     fn offset(&mut self) -> Result<Self::Tree, TokenWriterError> {
-        // FIXME: We'll want to build a forest and put skippable stuff after the rest.
-        unimplemented!()
+        // Offsets only make sense for binary formats, they are recomputed when re-encoding.
+        self.register(SubTree::Offset)
     }
 
     fn done(self) -> Result<Self::Data, TokenWriterError> {
@@ -130,16 +154,309 @@ impl TokenWriterWithTree for Encoder {
     }
 }
 
+/// An element of the document, with its text and child elements.
+#[derive(Debug)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>,
+}
+impl Element {
+    fn new(name: String) -> Self {
+        Element {
+            name,
+            text: String::new(),
+            children: vec![],
+        }
+    }
+
+    /// Parse a document into a synthetic element, whose only child is the root element.
+    fn parse<R: Read>(source: R) -> Result<Self, TokenReaderError> {
+        use xml_rs::reader::*;
+        let mut stack = vec![Element::new(String::new())];
+        for event in EventReader::new(source) {
+            let event = event.map_err(|err| TokenReaderError::ReadError(std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())))?;
+            match event {
+                XmlEvent::StartElement { name, .. } => {
+                    stack.push(Element::new(name.local_name));
+                }
+                XmlEvent::EndElement { .. } => {
+                    let element = stack.pop()
+                        .expect("The parser guarantees that elements are balanced");
+                    stack.last_mut()
+                        .expect("The parser guarantees that elements are balanced")
+                        .children
+                        .push(element);
+                }
+                XmlEvent::Characters(text) | XmlEvent::Whitespace(text) | XmlEvent::CData(text) => {
+                    stack.last_mut()
+                        .expect("The parser guarantees that elements are balanced")
+                        .text
+                        .push_str(&text);
+                }
+                _ => {}
+            }
+        }
+        let document = stack.pop()
+            .expect("The parser guarantees that elements are balanced");
+        if document.children.len() != 1 {
+            return Err(TokenReaderError::invalid_value(&"Expected exactly one root element"));
+        }
+        Ok(document)
+    }
+}
+
+/// The elements left to read in an enclosing list or tagged tuple, or in the document.
+struct Elements {
+    /// If `true`, the elements are the fields of a tagged tuple, named after
+    /// the fields of the grammar.
+    fields: bool,
+
+    elements: std::vec::IntoIter<Element>,
+}
+
+/// Read an xml document, as written by `Encoder`.
+///
+/// The document is parsed entirely before decoding. The elements of the fields
+/// of tagged tuples must be named after the fields expected by the grammar, as
+/// found in the path, otherwise decoding fails.
+pub struct Decoder {
+    /// The elements left to read in each enclosing list or tagged tuple,
+    /// innermost last. Each of these elements wraps one value.
+    stack: Vec<Elements>,
+}
+impl Decoder {
+    pub fn new<R: Read>(source: R) -> Result<Self, TokenReaderError> {
+        let document = Element::parse(source)?;
+        Ok(Decoder {
+            stack: vec![Elements {
+                fields: false,
+                elements: vec![document].into_iter(),
+            }],
+        })
+    }
+
+    /// Consume the next element of the innermost list or tagged tuple. In a
+    /// tagged tuple, the element must be named after the field at the end of `path`.
+    fn next(&mut self, path: &Path) -> Result<Element, TokenReaderError> {
+        let (element, fields) = self.stack.last_mut()
+            .and_then(|elements| elements.elements.next()
+                .map(|element| (element, elements.fields)))
+            .ok_or_else(|| TokenReaderError::invalid_value(&"Missing element"))?;
+        if fields {
+            let expected = path.get(0)
+                .map(|item| &(item.field().1));
+            if expected.map(|field| field.as_str()) != Some(element.name.as_str()) {
+                return Err(TokenReaderError::invalid_value(&format!("Expected field {:?}, found element {}", expected, element.name)));
+            }
+        }
+        Ok(element)
+    }
+
+    /// Consume the next element, which must contain either text or `<_null/>`.
+    fn text(&mut self, path: &Path) -> Result<Option<String>, TokenReaderError> {
+        let element = self.next(path)?;
+        match element.children.len() {
+            0 => Ok(Some(element.text)),
+            1 if element.children[0].name == NULL => Ok(None),
+            _ => Err(TokenReaderError::invalid_value(&element))
+        }
+    }
+
+    /// Leave the innermost list or tagged tuple, which must have been read entirely.
+    fn exit(&mut self) -> Result<(), TokenReaderError> {
+        let mut remaining = self.stack.pop()
+            .ok_or(TokenReaderError::InvalidValue)?;
+        match remaining.elements.next() {
+            None => Ok(()),
+            Some(element) => Err(TokenReaderError::invalid_value(&element))
+        }
+    }
+}
+
+impl FileStructurePrinter for Decoder {}
+
+impl TokenReader for Decoder {
+    fn string_at(&mut self, path: &Path) -> Result<Option<SharedString>, TokenReaderError> {
+        Ok(self.text(path)?
+            .map(SharedString::from_string))
+    }
+
+    fn float_at(&mut self, path: &Path) -> Result<Option<f64>, TokenReaderError> {
+        match self.text(path)? {
+            None => Ok(None),
+            Some(text) => text.trim()
+                .parse::<f64>()
+                .map(Some)
+                .map_err(|_| TokenReaderError::invalid_value(&text))
+        }
+    }
+
+    fn unsigned_long_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        let text = self.text(path)?
+            .ok_or_else(|| TokenReaderError::invalid_value(&"Unexpected null unsigned long"))?;
+        text.trim()
+            .parse::<u32>()
+            .map_err(|_| TokenReaderError::invalid_value(&text))
+    }
+
+    fn bool_at(&mut self, path: &Path) -> Result<Option<bool>, TokenReaderError> {
+        match self.text(path)? {
+            None => Ok(None),
+            Some(text) => match text.trim() {
+                "true" => Ok(Some(true)),
+                "false" => Ok(Some(false)),
+                _ => Err(TokenReaderError::invalid_value(&text))
+            }
+        }
+    }
+
+    fn offset_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        self.next(path)?;
+        Ok(0)
+    }
+
+    fn enter_list_at(&mut self, path: &Path) -> Result<u32, TokenReaderError> {
+        let element = self.next(path)?;
+        if let Some(child) = element.children.iter().find(|child| child.name != ITEM) {
+            return Err(TokenReaderError::invalid_value(child));
+        }
+        let len = element.children.len() as u32;
+        self.stack.push(Elements {
+            fields: false,
+            elements: element.children.into_iter(),
+        });
+        Ok(len)
+    }
+
+    fn exit_list_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.exit()
+    }
+
+    fn enter_tagged_tuple_at(&mut self, path: &Path) -> Result<(InterfaceName, Option<Rc<Box<[FieldName]>>>), TokenReaderError> {
+        let mut element = self.next(path)?;
+        if element.children.len() != 1 {
+            return Err(TokenReaderError::invalid_value(&element));
+        }
+        let node = element.children.pop()
+            .unwrap(); // Just checked.
+        let name = if node.name == NULL {
+            String::new()
+        } else {
+            node.name
+        };
+        self.stack.push(Elements {
+            fields: true,
+            elements: node.children.into_iter(),
+        });
+        Ok((InterfaceName::from_string(name), None))
+    }
+
+    fn exit_tagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        self.exit()
+    }
+
+    fn enter_untagged_tuple_at(&mut self, _path: &Path) -> Result<(), TokenReaderError> {
+        Ok(())
+    }
+}
+
 /// Command-line management.
 pub struct FormatProvider;
 impl ::FormatProvider for FormatProvider {
     fn subcommand<'a, 'b>(&self) -> clap::App<'a, 'b> {
         use clap::*;
         SubCommand::with_name("xml")
-            .about("(EXPERIMENTAL) Encode to xml. This format is designed to help gather statistics, and is not considered useful for any other reason.")
+            .about("(EXPERIMENTAL) Encode to xml. This format is designed to help gather statistics and to debug decoders, as a dump may be edited, then re-encoded. It is not considered useful for any other reason.")
     }
 
     fn handle_subcommand(&self, _matches: Option<&clap::ArgMatches>) -> Result<::Format, ::std::io::Error> {
         Ok(::Format::XML)
     }
 }
+
+#[test]
+fn test_xml_roundtrip() {
+    use binjs_shared::ast::PathItem;
+    use std::io::Cursor;
+
+    // The path of field `field`, the `index`-th field of `interface`. Readers only look at
+    // the innermost field.
+    let at = |interface: &'static str, index: usize, field: &'static str| Path::from(vec![PathItem {
+        interface: InterfaceName::from_str(interface),
+        field: (index, FieldName::from_str(field)),
+    }]);
+    let root = Path::new();
+
+    // `Program { name, nothing, value, flag, count, skip, items: [Identifier(name), null] }`.
+    let mut encoder = Encoder::new();
+    let name = encoder.string(Some(&SharedString::from_str("<foo> & \"bar\""))).unwrap();
+    let nothing = encoder.string(None).unwrap();
+    let value = encoder.float(Some(1.5)).unwrap();
+    let flag = encoder.bool(None).unwrap();
+    let count = encoder.unsigned_long(42).unwrap();
+    let skip = encoder.offset().unwrap();
+    let identifier_name = encoder.string(Some(&SharedString::from_str(" spaced "))).unwrap();
+    let identifier = encoder.tagged_tuple(&InterfaceName::from_str("Identifier"), &[(&FieldName::from_str("name"), identifier_name)]).unwrap();
+    let null = encoder.tagged_tuple(&InterfaceName::from_str(""), &[]).unwrap();
+    let items = encoder.list(vec![identifier, null]).unwrap();
+    encoder.tagged_tuple(&InterfaceName::from_str("Program"), &[
+        (&FieldName::from_str("name"), name),
+        (&FieldName::from_str("nothing"), nothing),
+        (&FieldName::from_str("value"), value),
+        (&FieldName::from_str("flag"), flag),
+        (&FieldName::from_str("count"), count),
+        (&FieldName::from_str("skip"), skip),
+        (&FieldName::from_str("items"), items),
+    ]).unwrap();
+    let data = encoder.done()
+        .expect("Could not finalize data");
+
+    // Edit the dump, as a human would.
+    let source = String::from_utf8(data)
+        .expect("Invalid UTF-8")
+        .replace("1.5", "2.5");
+
+    let mut decoder = Decoder::new(Cursor::new(source.as_bytes()))
+        .expect("Could not parse document");
+    assert_eq!(decoder.enter_tagged_tuple_at(&root).unwrap().0, InterfaceName::from_str("Program"));
+    assert_eq!(decoder.string_at(&at("Program", 0, "name")).unwrap(), Some(SharedString::from_str("<foo> & \"bar\"")));
+    assert_eq!(decoder.string_at(&at("Program", 1, "nothing")).unwrap(), None);
+    assert_eq!(decoder.float_at(&at("Program", 2, "value")).unwrap(), Some(2.5));
+    assert_eq!(decoder.bool_at(&at("Program", 3, "flag")).unwrap(), None);
+    assert_eq!(decoder.unsigned_long_at(&at("Program", 4, "count")).unwrap(), 42);
+    assert_eq!(decoder.offset_at(&at("Program", 5, "skip")).unwrap(), 0);
+    let items = at("Program", 6, "items");
+    assert_eq!(decoder.enter_list_at(&items).unwrap(), 2);
+    assert_eq!(decoder.enter_tagged_tuple_at(&items).unwrap().0, InterfaceName::from_str("Identifier"));
+    assert_eq!(decoder.string_at(&at("Identifier", 0, "name")).unwrap(), Some(SharedString::from_str(" spaced ")));
+    decoder.exit_tagged_tuple_at(&items).unwrap();
+    assert_eq!(decoder.enter_tagged_tuple_at(&items).unwrap().0, InterfaceName::from_str(""));
+    decoder.exit_tagged_tuple_at(&items).unwrap();
+    decoder.exit_list_at(&items).unwrap();
+    decoder.exit_tagged_tuple_at(&root).unwrap();
+
+    // Fields that are left over are rejected.
+    let mut decoder = Decoder::new(Cursor::new(source.as_bytes()))
+        .expect("Could not parse document");
+    decoder.enter_tagged_tuple_at(&root).unwrap();
+    decoder.string_at(&at("Program", 0, "name")).unwrap();
+    assert!(decoder.exit_tagged_tuple_at(&root).is_err());
+
+    // Fields that are not those of the grammar are rejected.
+    let mut decoder = Decoder::new(Cursor::new(source.as_bytes()))
+        .expect("Could not parse document");
+    decoder.enter_tagged_tuple_at(&root).unwrap();
+    assert!(decoder.string_at(&at("Program", 0, "nothing")).is_err());
+
+    let renamed = source.replace("<count>", "<counter>")
+        .replace("</count>", "</counter>");
+    let mut decoder = Decoder::new(Cursor::new(renamed.as_bytes()))
+        .expect("Could not parse document");
+    decoder.enter_tagged_tuple_at(&root).unwrap();
+    decoder.string_at(&at("Program", 0, "name")).unwrap();
+    decoder.string_at(&at("Program", 1, "nothing")).unwrap();
+    decoder.float_at(&at("Program", 2, "value")).unwrap();
+    decoder.bool_at(&at("Program", 3, "flag")).unwrap();
+    assert!(decoder.unsigned_long_at(&at("Program", 4, "count")).is_err());
+}
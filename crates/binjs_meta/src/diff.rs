@@ -0,0 +1,311 @@
+//! Compare two versions of a grammar, to support grammar evolution decisions.
+//!
+//! Each change is classified as breaking if files written with the old grammar
+//! may not be decoded correctly with the new grammar. Interfaces are identified
+//! by name rather than by position, so the order in which they are declared is
+//! not part of the wire format and is not reported.
+//!
+//! This classification is meant to guide grammar evolution, not decoding: any change
+//! changes the fingerprint of the grammar, and decoders reject files whose grammar
+//! fingerprint they do not support, whether or not the changes are breaking.
+
+use spec::*;
+use util::*;
+
+use std;
+use std::collections::HashSet;
+
+use itertools::Itertools;
+
+/// A single difference between two grammars.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Change {
+    InterfaceAdded(String),
+    InterfaceRemoved(String),
+    FieldAdded { interface: String, field: String },
+    FieldRemoved { interface: String, field: String },
+    /// The fields present in both grammars are not in the same order.
+    FieldsReordered { interface: String, old: Vec<String>, new: Vec<String> },
+    FieldTypeChanged { interface: String, field: String, old: String, new: String, widened: bool },
+    FieldLazinessChanged { interface: String, field: String, lazy: bool },
+    StringEnumAdded(String),
+    StringEnumRemoved(String),
+    /// `appended` is `true` if the value was added after all the values of the old grammar.
+    StringEnumValueAdded { string_enum: String, value: String, appended: bool },
+    StringEnumValueRemoved { string_enum: String, value: String },
+    /// The values present in both grammars are not in the same order.
+    StringEnumValuesReordered { string_enum: String, old: Vec<String>, new: Vec<String> },
+    TypedefAdded(String),
+    TypedefRemoved(String),
+    TypedefChanged { typedef: String, old: String, new: String, widened: bool },
+}
+impl Change {
+    /// `true` if files written with the old grammar may not be decoded correctly
+    /// with the new grammar.
+    ///
+    /// A change that is not breaking does not make files written with the old grammar
+    /// acceptable by decoders of the new grammar: these decoders still reject them,
+    /// as their fingerprint differs, see the module documentation.
+    ///
+    /// Adding a string enum value anywhere but at the end, or reordering values,
+    /// is considered breaking, as some formats and engine tables identify values
+    /// by position.
+    pub fn is_breaking(&self) -> bool {
+        use self::Change::*;
+        match *self {
+            InterfaceAdded(_)
+            | StringEnumAdded(_)
+            | TypedefAdded(_) => false,
+            FieldTypeChanged { widened, .. }
+            | TypedefChanged { widened, .. } => !widened,
+            StringEnumValueAdded { appended, .. } => !appended,
+            InterfaceRemoved(_)
+            | FieldAdded { .. }
+            | FieldRemoved { .. }
+            | FieldsReordered { .. }
+            | FieldLazinessChanged { .. }
+            | StringEnumRemoved(_)
+            | StringEnumValueRemoved { .. }
+            | StringEnumValuesReordered { .. }
+            | TypedefRemoved(_) => true,
+        }
+    }
+}
+impl std::fmt::Display for Change {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        use self::Change::*;
+        write!(formatter, "[{}] ", if self.is_breaking() { "breaking" } else { "non-breaking" })?;
+        match *self {
+            InterfaceAdded(ref name) =>
+                write!(formatter, "interface {} added", name),
+            InterfaceRemoved(ref name) =>
+                write!(formatter, "interface {} removed", name),
+            FieldAdded { ref interface, ref field } =>
+                write!(formatter, "field {}.{} added", interface, field),
+            FieldRemoved { ref interface, ref field } =>
+                write!(formatter, "field {}.{} removed", interface, field),
+            FieldsReordered { ref interface, ref old, ref new } =>
+                write!(formatter, "fields of {} reordered from ({}) to ({})", interface, old.iter().format(", "), new.iter().format(", ")),
+            FieldTypeChanged { ref interface, ref field, ref old, ref new, .. } =>
+                write!(formatter, "type of field {}.{} changed from {} to {}", interface, field, old, new),
+            FieldLazinessChanged { ref interface, ref field, lazy } =>
+                write!(formatter, "field {}.{} made {}", interface, field, if lazy { "lazy" } else { "eager" }),
+            StringEnumAdded(ref name) =>
+                write!(formatter, "string enum {} added", name),
+            StringEnumRemoved(ref name) =>
+                write!(formatter, "string enum {} removed", name),
+            StringEnumValueAdded { ref string_enum, ref value, .. } =>
+                write!(formatter, "value {:?} added to string enum {}", value, string_enum),
+            StringEnumValueRemoved { ref string_enum, ref value } =>
+                write!(formatter, "value {:?} removed from string enum {}", value, string_enum),
+            StringEnumValuesReordered { ref string_enum, ref old, ref new } =>
+                write!(formatter, "values of string enum {} reordered from ({:?}) to ({:?})", string_enum, old.iter().format(", "), new.iter().format(", ")),
+            TypedefAdded(ref name) =>
+                write!(formatter, "typedef {} added", name),
+            TypedefRemoved(ref name) =>
+                write!(formatter, "typedef {} removed", name),
+            TypedefChanged { ref typedef, ref old, ref new, .. } =>
+                write!(formatter, "typedef {} changed from {} to {}", typedef, old, new),
+        }
+    }
+}
+
+/// The differences between two grammars, sorted by kind of declaration, then by name.
+pub struct SpecDiff {
+    pub changes: Vec<Change>,
+}
+impl SpecDiff {
+    pub fn new(old: &Spec, new: &Spec) -> Self {
+        let mut changes = vec![];
+        Self::diff_interfaces(old, new, &mut changes);
+        Self::diff_string_enums(old, new, &mut changes);
+        Self::diff_typedefs(old, new, &mut changes);
+        SpecDiff {
+            changes
+        }
+    }
+
+    /// `true` if any change is breaking.
+    pub fn is_breaking(&self) -> bool {
+        self.changes.iter()
+            .any(Change::is_breaking)
+    }
+
+    fn diff_interfaces(old: &Spec, new: &Spec, changes: &mut Vec<Change>) {
+        let names = old.interfaces_by_name().keys()
+            .chain(new.interfaces_by_name().keys())
+            .map(|name| name.to_str())
+            .unique()
+            .sorted();
+        for name in names {
+            let old_interface = old.get_node_name(name)
+                .and_then(|name| old.interfaces_by_name().get(name));
+            let new_interface = new.get_node_name(name)
+                .and_then(|name| new.interfaces_by_name().get(name));
+            let (old_interface, new_interface) = match (old_interface, new_interface) {
+                (None, Some(_)) => {
+                    changes.push(Change::InterfaceAdded(name.to_string()));
+                    continue;
+                }
+                (Some(_), None) => {
+                    changes.push(Change::InterfaceRemoved(name.to_string()));
+                    continue;
+                }
+                (Some(old_interface), Some(new_interface)) => (old_interface, new_interface),
+                (None, None) => continue,
+            };
+
+            let field_names = |interface: &Interface| -> Vec<String> {
+                interface.contents()
+                    .fields()
+                    .iter()
+                    .map(|field| field.name().to_str().to_string())
+                    .collect()
+            };
+            let old_fields = field_names(old_interface);
+            let new_fields = field_names(new_interface);
+            for field in &new_fields {
+                if !old_fields.contains(field) {
+                    changes.push(Change::FieldAdded { interface: name.to_string(), field: field.clone() });
+                }
+            }
+            for field in &old_fields {
+                if !new_fields.contains(field) {
+                    changes.push(Change::FieldRemoved { interface: name.to_string(), field: field.clone() });
+                }
+            }
+            let (old_common, new_common) = Self::common(&old_fields, &new_fields);
+            if old_common != new_common {
+                changes.push(Change::FieldsReordered { interface: name.to_string(), old: old_common, new: new_common });
+            }
+
+            for old_field in old_interface.contents().fields() {
+                let new_field = match new_interface.contents().fields().iter().find(|field| field.name().to_str() == old_field.name().to_str()) {
+                    Some(field) => field,
+                    None => continue,
+                };
+                if let Some((old_type, new_type, widened)) = Self::diff_types(old_field.type_(), new_field.type_()) {
+                    changes.push(Change::FieldTypeChanged {
+                        interface: name.to_string(),
+                        field: old_field.name().to_str().to_string(),
+                        old: old_type,
+                        new: new_type,
+                        widened,
+                    });
+                }
+                if old_field.is_lazy() != new_field.is_lazy() {
+                    changes.push(Change::FieldLazinessChanged {
+                        interface: name.to_string(),
+                        field: old_field.name().to_str().to_string(),
+                        lazy: new_field.is_lazy(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn diff_string_enums(old: &Spec, new: &Spec, changes: &mut Vec<Change>) {
+        let names = old.string_enums_by_name().keys()
+            .chain(new.string_enums_by_name().keys())
+            .map(|name| name.to_str())
+            .unique()
+            .sorted();
+        for name in names {
+            let old_enum = old.get_node_name(name)
+                .and_then(|name| old.string_enums_by_name().get(name));
+            let new_enum = new.get_node_name(name)
+                .and_then(|name| new.string_enums_by_name().get(name));
+            let (old_values, new_values) = match (old_enum, new_enum) {
+                (None, Some(_)) => {
+                    changes.push(Change::StringEnumAdded(name.to_string()));
+                    continue;
+                }
+                (Some(_), None) => {
+                    changes.push(Change::StringEnumRemoved(name.to_string()));
+                    continue;
+                }
+                (Some(old_enum), Some(new_enum)) => (old_enum.strings(), new_enum.strings()),
+                (None, None) => continue,
+            };
+
+            // Values are appended if all the old values come first, in any order.
+            let last_old = new_values.iter()
+                .rposition(|value| old_values.contains(value));
+            for (index, value) in new_values.iter().enumerate() {
+                if !old_values.contains(value) {
+                    changes.push(Change::StringEnumValueAdded {
+                        string_enum: name.to_string(),
+                        value: value.clone(),
+                        appended: last_old.map_or(true, |last_old| index > last_old),
+                    });
+                }
+            }
+            for value in old_values {
+                if !new_values.contains(value) {
+                    changes.push(Change::StringEnumValueRemoved { string_enum: name.to_string(), value: value.clone() });
+                }
+            }
+            let (old_common, new_common) = Self::common(old_values, new_values);
+            if old_common != new_common {
+                changes.push(Change::StringEnumValuesReordered { string_enum: name.to_string(), old: old_common, new: new_common });
+            }
+        }
+    }
+
+    fn diff_typedefs(old: &Spec, new: &Spec, changes: &mut Vec<Change>) {
+        let names = old.typedefs_by_name().keys()
+            .chain(new.typedefs_by_name().keys())
+            .map(|name| name.to_str())
+            .unique()
+            .sorted();
+        for name in names {
+            let old_typedef = old.get_node_name(name)
+                .and_then(|name| old.typedefs_by_name().get(name));
+            let new_typedef = new.get_node_name(name)
+                .and_then(|name| new.typedefs_by_name().get(name));
+            match (old_typedef, new_typedef) {
+                (None, Some(_)) => changes.push(Change::TypedefAdded(name.to_string())),
+                (Some(_), None) => changes.push(Change::TypedefRemoved(name.to_string())),
+                (Some(old_type), Some(new_type)) => {
+                    if let Some((old_type, new_type, widened)) = Self::diff_types(old_type, new_type) {
+                        changes.push(Change::TypedefChanged { typedef: name.to_string(), old: old_type, new: new_type, widened });
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// The items of `old` that are also in `new` and the items of `new` that are also
+    /// in `old`, each in their own order.
+    fn common(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+        let old_set : HashSet<_> = old.iter().collect();
+        let new_set : HashSet<_> = new.iter().collect();
+        (old.iter().filter(|item| new_set.contains(item)).cloned().collect(),
+         new.iter().filter(|item| old_set.contains(item)).cloned().collect())
+    }
+
+    /// If the types differ, their descriptions and whether the new type accepts
+    /// every value of the old type, i.e. it is optional or a larger sum.
+    fn diff_types(old: &Type, new: &Type) -> Option<(String, String, bool)> {
+        let old_description = Spec::describe_type(old);
+        let new_description = Spec::describe_type(new);
+        if old_description == new_description {
+            return None;
+        }
+        let alternatives = |spec: &TypeSpec| -> HashSet<String> {
+            match *spec {
+                TypeSpec::TypeSum(ref sum) => sum.types()
+                    .iter()
+                    .map(Spec::describe_type_spec)
+                    .collect(),
+                ref other => Some(Spec::describe_type_spec(other))
+                    .into_iter()
+                    .collect()
+            }
+        };
+        let widened = (new.is_optional() || !old.is_optional())
+            && alternatives(old.spec()).is_subset(&alternatives(new.spec()));
+        Some((old_description, new_description, widened))
+    }
+}
@@ -5,6 +5,8 @@ use std::collections::{ HashMap, HashSet };
 
 use itertools::Itertools;
 
+use serde_json;
+
 /// A tool designed to replace all anonymous types in a specification
 /// of the language by explicitly named types.
 ///
@@ -191,6 +193,9 @@ impl TypeDeanonymizer {
             TypeSpec::Number |
             TypeSpec::UnsignedLong |
             TypeSpec::PropertyKey |
+            TypeSpec::BigInt |
+            TypeSpec::RegExpPattern |
+            TypeSpec::RegExpFlags |
             TypeSpec::IdentifierName |
             TypeSpec::String |
             TypeSpec::Offset |
@@ -207,6 +212,9 @@ impl TypeDeanonymizer {
                 // See https://github.com/Yoric/ecmascript-binary-ast/pull/1
                 let name = match *type_spec {
                     TypeSpec::PropertyKey => self.builder.node_name("PropertyKey"),
+                    TypeSpec::BigInt => self.builder.node_name("BigInt"),
+                    TypeSpec::RegExpPattern => self.builder.node_name("RegExpPattern"),
+                    TypeSpec::RegExpFlags => self.builder.node_name("RegExpFlags"),
                     TypeSpec::IdentifierName => self.builder.node_name("IdentifierName"),
                     _ => self.builder.node_name(&format!("@@{:?}", type_spec)),
                 };
@@ -248,6 +256,9 @@ impl TypeDeanonymizer {
                             Some(IsNullable { content: Primitive::String, .. }) => Type::string().required(),
                             Some(IsNullable { content: Primitive::IdentifierName, .. }) => Type::identifier_name().required(),
                             Some(IsNullable { content: Primitive::PropertyKey, .. }) => Type::property_key().required(),
+                            Some(IsNullable { content: Primitive::BigInt, .. }) => Type::big_int().required(),
+                            Some(IsNullable { content: Primitive::RegExpPattern, .. }) => Type::reg_exp_pattern().required(),
+                            Some(IsNullable { content: Primitive::RegExpFlags, .. }) => Type::reg_exp_flags().required(),
                             Some(IsNullable { content: Primitive::Number, .. }) => Type::number().required(),
                             Some(IsNullable { content: Primitive::UnsignedLong, .. }) => Type::unsigned_long().required(),
                             Some(IsNullable { content: Primitive::Boolean, .. }) => Type::bool().required(),
@@ -389,6 +400,12 @@ impl TypeName {
                 "IdentifierName".to_string(),
             TypeSpec::PropertyKey =>
                 "PropertyKey".to_string(),
+            TypeSpec::BigInt =>
+                "BigInt".to_string(),
+            TypeSpec::RegExpPattern =>
+                "RegExpPattern".to_string(),
+            TypeSpec::RegExpFlags =>
+                "RegExpFlags".to_string(),
             TypeSpec::TypeSum(ref sum) => {
                 format!("{}", sum.types()
                     .iter()
@@ -424,6 +441,12 @@ impl ToWebidl {
                 "string".to_string(),
             TypeSpec::PropertyKey =>
                 "[PropertyKey] string".to_string(),
+            TypeSpec::BigInt =>
+                "[BigInt] string".to_string(),
+            TypeSpec::RegExpPattern =>
+                "[RegExpPattern] string".to_string(),
+            TypeSpec::RegExpFlags =>
+                "[RegExpFlags] string".to_string(),
             TypeSpec::IdentifierName =>
                 "[IdentifierName] string".to_string(),
             TypeSpec::Number =>
@@ -484,3 +507,259 @@ impl ToWebidl {
         result
     }
 }
+
+/// Export a specification as a machine-readable JSON description of the tokens
+/// written for each node.
+///
+/// Designed for generating the specification document and for checking
+/// alternative implementations against this one.
+pub struct ToJSONDescription;
+impl ToJSONDescription {
+    /// Export a Spec.
+    ///
+    /// Interfaces, string enums and typedefs are sorted by name, fields and the values
+    /// of string enums are in the order in which they are written.
+    pub fn spec(spec: &Spec) -> serde_json::Value {
+        let interfaces : Vec<_> = spec.interfaces_by_name()
+            .iter()
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .into_iter()
+            .map(|(_, interface)| Self::interface(spec, interface))
+            .collect();
+        let string_enums : Vec<_> = spec.string_enums_by_name()
+            .iter()
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .into_iter()
+            .map(|(name, string_enum)| json!({
+                "name": name.to_str(),
+                "values": string_enum.strings()
+            }))
+            .collect();
+        let typedefs : Vec<_> = spec.typedefs_by_name()
+            .iter()
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .into_iter()
+            .map(|(name, type_)| json!({
+                "name": name.to_str(),
+                "type": Self::type_(type_)
+            }))
+            .collect();
+        json!({
+            "root": spec.get_root_name().to_str(),
+            "fingerprint": format!("{:016x}", spec.fingerprint()),
+            "interfaces": interfaces,
+            "string_enums": string_enums,
+            "typedefs": typedefs
+        })
+    }
+
+    /// Export an Interface.
+    ///
+    /// A node is written as a tagged tuple, followed by its fields. A lazy field is
+    /// preceded by an `offset` token, the byte length of the field.
+    pub fn interface(spec: &Spec, interface: &Interface) -> serde_json::Value {
+        let fields : Vec<_> = interface.contents()
+            .fields()
+            .iter()
+            .map(|field| json!({
+                "name": field.name().to_str(),
+                "type": Self::type_(field.type_()),
+                "token": Self::token(spec, field.type_().spec()),
+                "lazy": field.is_lazy()
+            }))
+            .collect();
+        json!({
+            "name": interface.name().to_str(),
+            "scope": interface.is_scope(),
+            "fields": fields
+        })
+    }
+
+    /// Export a Type.
+    pub fn type_(type_: &Type) -> serde_json::Value {
+        let mut result = Self::type_spec(type_.spec());
+        result["optional"] = json!(type_.is_optional());
+        result
+    }
+
+    fn type_spec(spec: &TypeSpec) -> serde_json::Value {
+        match *spec {
+            TypeSpec::Array { ref contents, supports_empty } => json!({
+                "kind": "array",
+                "contents": Self::type_(contents),
+                "supports_empty": supports_empty
+            }),
+            TypeSpec::NamedType(ref name) => json!({
+                "kind": "named",
+                "name": name.to_str()
+            }),
+            TypeSpec::TypeSum(ref sum) => json!({
+                "kind": "sum",
+                "types": sum.types()
+                    .iter()
+                    .map(Self::type_spec)
+                    .collect::<Vec<_>>()
+            }),
+            ref primitive => json!({
+                "kind": format!("{:?}", primitive)
+            })
+        }
+    }
+
+    /// The kind of the first token written for a value of type `spec`, i.e. the
+    /// `TokenWriter` method called, without its `_at` suffix.
+    ///
+    /// Typedefs are resolved. `void` values are not written.
+    pub fn token(spec: &Spec, type_spec: &TypeSpec) -> &'static str {
+        match *type_spec {
+            TypeSpec::Array { .. } => "list",
+            TypeSpec::TypeSum(_) => "tagged_tuple",
+            TypeSpec::NamedType(ref name) => match spec.get_type_by_name(name) {
+                Some(NamedType::Interface(_)) => "tagged_tuple",
+                Some(NamedType::StringEnum(_)) => "string_enum",
+                Some(NamedType::Typedef(ref type_)) => Self::token(spec, type_.spec()),
+                None => panic!("Unknown type {}", name.to_str()),
+            },
+            TypeSpec::Boolean => "bool",
+            TypeSpec::String => "string",
+            TypeSpec::Number => "float",
+            TypeSpec::UnsignedLong => "unsigned_long",
+            TypeSpec::Offset => "offset",
+            TypeSpec::Void => "void",
+            TypeSpec::IdentifierName => "identifier_name",
+            TypeSpec::PropertyKey => "property_key",
+            TypeSpec::BigInt => "big_int",
+            TypeSpec::RegExpPattern => "reg_exp_pattern",
+            TypeSpec::RegExpFlags => "reg_exp_flags",
+        }
+    }
+}
+
+/// Export a specification as TypeScript definitions of the AST, as exported
+/// to JSON by the generated `ToJSON` implementations, e.g. by
+/// `binjs_decode --output-json internal`.
+///
+/// Each interface `Foo` is exported as a TypeScript interface `Foo`, with a field
+/// `type: "Foo"`, so that TypeScript may narrow sums of interfaces. Lazy fields are
+/// preceded by their byte length, as field `{name}_skip`.
+pub struct ToTypeScript;
+impl ToTypeScript {
+    /// Export a Spec, as the contents of a `.d.ts` file.
+    pub fn spec(spec: &Spec) -> String {
+        let mut result = String::new();
+        result.push_str("// This file was autogenerated by binjs_meta, do not edit.
+// TypeScript definitions of the AST, as written by `binjs_decode --output-json internal`.
+
+// A position in the source text, as written by `binjs_decode --source-positions`.
+export interface Position {
+    line: number;
+    column: number;
+    offset: number;
+}
+
+export interface SourceLocation {
+    start: Position;
+    end: Position;
+}
+
+export interface Comment {
+    type: string;
+    text: string;
+    start: Position;
+    end: Position;
+}
+");
+
+        let interfaces = spec.interfaces_by_name()
+            .iter()
+            .filter(|&(name, _)| name != spec.get_null_name())
+            .sorted_by(|a, b| a.0.cmp(b.0));
+
+        result.push_str("\n// ----- Interfaces (by lexicographical order)\n");
+        for &(name, interface) in &interfaces {
+            result.push_str(&Self::interface(interface, name == spec.get_root_name()));
+        }
+
+        result.push_str("\n// ----- String enums (by lexicographical order)\n");
+        for (name, string_enum) in spec.string_enums_by_name().iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+            result.push_str(&format!("export type {name} = {values};\n",
+                name = name.to_str(),
+                values = string_enum.strings()
+                    .iter()
+                    .map(|value| format!("{:?}", value))
+                    .format(" | ")));
+        }
+
+        result.push_str("\n// ----- Typedefs (by lexicographical order)\n");
+        for (name, type_) in spec.typedefs_by_name().iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+            result.push_str(&format!("export type {name} = {type_};\n",
+                name = name.to_str(),
+                type_ = Self::type_(type_)));
+        }
+
+        result.push_str(&format!("\n// Any node of the AST.\nexport type Node =\n    {};\n",
+            interfaces.iter()
+                .map(|&(name, _)| name.to_str())
+                .format("\n    | ")));
+        result
+    }
+
+    /// Export an Interface. Only the root has comments.
+    pub fn interface(interface: &Interface, is_root: bool) -> String {
+        let mut result = format!("\nexport interface {name} {{\n    type: \"{name}\";\n",
+            name = interface.name().to_str());
+        for field in interface.contents().fields() {
+            if let Some(doc) = field.doc() {
+                result.push_str(&format!("    /** {} */\n", doc));
+            }
+            if field.is_lazy() {
+                result.push_str(&format!("    {}_skip: number;\n", field.name().to_str()));
+            }
+            result.push_str(&format!("    {name}: {type_};\n",
+                name = field.name().to_str(),
+                type_ = Self::type_(field.type_())));
+        }
+        result.push_str("    loc?: SourceLocation;\n");
+        if is_root {
+            result.push_str("    comments?: Comment[];\n");
+        }
+        result.push_str("}\n");
+        result
+    }
+
+    /// Export a Type.
+    pub fn type_(type_: &Type) -> String {
+        let spec = Self::type_spec(type_.spec());
+        if type_.is_optional() {
+            format!("{} | null", spec)
+        } else {
+            spec
+        }
+    }
+
+    /// Export a TypeSpec.
+    pub fn type_spec(spec: &TypeSpec) -> String {
+        match *spec {
+            TypeSpec::Array { ref contents, .. } =>
+                format!("Array<{}>", Self::type_(contents)),
+            TypeSpec::NamedType(ref name) if name.to_str() == "" =>
+                "null".to_string(),
+            TypeSpec::NamedType(ref name) =>
+                name.to_str().to_string(),
+            TypeSpec::TypeSum(ref sum) =>
+                format!("{}", sum.types()
+                    .iter()
+                    .map(Self::type_spec)
+                    .format(" | ")),
+            TypeSpec::Boolean =>
+                "boolean".to_string(),
+            TypeSpec::Number | TypeSpec::UnsignedLong | TypeSpec::Offset =>
+                "number".to_string(),
+            TypeSpec::Void =>
+                "null".to_string(),
+            TypeSpec::String | TypeSpec::IdentifierName | TypeSpec::PropertyKey
+            | TypeSpec::BigInt | TypeSpec::RegExpPattern | TypeSpec::RegExpFlags =>
+                "string".to_string(),
+        }
+    }
+}
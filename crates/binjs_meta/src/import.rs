@@ -1,11 +1,14 @@
-use spec::{ self, Laziness, SpecBuilder, TypeSum };
+use spec::{ self, Laziness, Spec, SpecBuilder, SpecOptions, TypeSum };
 
+use webidl;
 use webidl::ast::*;
 
 pub struct Importer {
     builder: SpecBuilder,
     /// The interfaces we have traversed so far.
     path: Vec<String>,
+    /// `true` while importing a grammar extension rather than the base grammar.
+    is_extension: bool,
 }
 impl Importer {
     /// Import an AST into a SpecBuilder.
@@ -63,13 +66,117 @@ impl Importer {
     /// }
     /// ```
     pub fn import(ast: &AST) -> SpecBuilder {
+        Self::import_extended(ast, &[])
+    }
+
+    /// Import an AST into a SpecBuilder, followed by grammar extensions, in order.
+    ///
+    /// Extensions let vendors add interfaces and fields to the base grammar without
+    /// forking it. In addition to new interfaces, string enums and typedefs, an
+    /// extension may contain:
+    ///
+    /// - `partial interface Foo { ... };`, appending fields to interface `Foo`
+    ///   of the base grammar, after its own fields;
+    /// - `typedef (Bar or Baz) Foo;`, where `Foo` is a sum of the base grammar,
+    ///   adding `Bar` and `Baz` to the alternatives of `Foo`.
+    ///
+    /// ```
+    /// extern crate binjs_meta;
+    /// extern crate webidl;
+    /// use webidl;
+    /// use binjs_meta::spec::{ Spec, SpecOptions };
+    /// use binjs_meta::util::ToStr;
+    ///
+    /// let ast = webidl::parse_string("
+    ///    typedef (Foo or Bar) Statement;
+    ///    interface Foo {
+    ///      attribute boolean value;
+    ///    };
+    ///    interface Bar {
+    ///    };
+    /// ").expect("Could not parse");
+    /// let extension = webidl::parse_string("
+    ///    typedef Probe Statement;
+    ///    interface Probe {
+    ///      attribute unsigned long id;
+    ///    };
+    ///    partial interface Foo {
+    ///      attribute unsigned long counter;
+    ///    };
+    /// ").expect("Could not parse");
+    ///
+    /// let mut builder = binjs_meta::import::Importer::import_extended(&ast, &[extension]);
+    ///
+    /// let fake_root = builder.node_name("@@ROOT@@"); // Unused
+    /// let null = builder.node_name(""); // Used
+    /// let spec = builder.into_spec(SpecOptions {
+    ///     root: &fake_root,
+    ///     null: &null,
+    /// });
+    ///
+    /// let name_foo = spec.get_node_name("Foo")
+    ///     .expect("Missing name Foo");
+    /// let interface_foo = spec.get_interface_by_name(&name_foo)
+    ///     .expect("Missing interface Foo");
+    /// let fields : Vec<_> = interface_foo.contents()
+    ///     .fields()
+    ///     .iter()
+    ///     .map(|field| field.name().to_str())
+    ///     .collect();
+    /// assert_eq!(fields, vec!["value", "counter"]);
+    ///
+    /// let name_statement = spec.get_node_name("Statement")
+    ///     .expect("Missing name Statement");
+    /// let statement = spec.typedefs_by_name()
+    ///     .get(&name_statement)
+    ///     .expect("Missing typedef Statement");
+    /// assert_eq!(Spec::describe_type(statement), "(Foo or Bar or Probe)");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If an extension redefines a declaration of the base grammar, other than as
+    /// above, or if a partial interface does not extend an existing interface.
+    pub fn import_extended(ast: &AST, extensions: &[AST]) -> SpecBuilder {
         let mut importer = Importer {
             path: Vec::with_capacity(256),
-            builder: SpecBuilder::new()
+            builder: SpecBuilder::new(),
+            is_extension: false,
         };
         importer.import_ast(ast);
+        importer.is_extension = true;
+        for extension in extensions {
+            importer.import_ast(extension);
+        }
         importer.builder
     }
+
+    /// Parse a webidl source into a `Spec`, starting at interface `root`.
+    ///
+    /// As in the ES6 grammar, `null` is represented by an empty interface with an empty name.
+    /// This is used to load a grammar at runtime, rather than generating code at build time.
+    pub fn load(source: &str, root: &str) -> Result<Spec, webidl::ParseError> {
+        Self::load_extended(source, &[], root)
+    }
+
+    /// Parse a webidl source and grammar extensions into a `Spec`, starting at interface `root`.
+    ///
+    /// See `import_extended` for the declarations that extensions may contain.
+    pub fn load_extended(source: &str, extensions: &[&str], root: &str) -> Result<Spec, webidl::ParseError> {
+        let ast = webidl::parse_string(source)?;
+        let extensions = extensions.iter()
+            .map(|extension| webidl::parse_string(extension))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut builder = Self::import_extended(&ast, &extensions);
+        let root = builder.node_name(root);
+        let null = builder.node_name("");
+        builder.add_interface(&null)
+            .unwrap();
+        Ok(builder.into_spec(SpecOptions {
+            root: &root,
+            null: &null,
+        }))
+    }
     fn import_ast(&mut self, ast: &AST) {
         for definition in ast {
             self.import_definition(&definition)
@@ -102,21 +209,52 @@ impl Importer {
                 .required(),
             "PropertyKey" => spec::TypeSpec::PropertyKey
                 .required(),
+            "BigInt" => spec::TypeSpec::BigInt
+                .required(),
+            "RegExpPattern" => spec::TypeSpec::RegExpPattern
+                .required(),
+            "RegExpFlags" => spec::TypeSpec::RegExpFlags
+                .required(),
             _ => self.convert_type(&*typedef.type_)
         };
         debug!(target: "meta::import", "Importing typedef {type_:?} {name:?}",
             type_ = type_,
             name = name);
+        if self.is_extension {
+            if let Some(mut node) = self.builder.get_typedef_mut(&name) {
+                // Extend a sum of the base grammar.
+                let types : Vec<_> = match (&node.spec, &type_.spec) {
+                    (&spec::TypeSpec::TypeSum(ref base), &spec::TypeSpec::TypeSum(ref extension)) =>
+                        base.types()
+                            .iter()
+                            .chain(extension.types())
+                            .cloned()
+                            .collect(),
+                    (&spec::TypeSpec::TypeSum(ref base), extension) =>
+                        base.types()
+                            .iter()
+                            .chain(Some(extension))
+                            .cloned()
+                            .collect(),
+                    _ => panic!("Error: Extension typedef {} does not extend a sum.", name)
+                };
+                node.with_spec(spec::TypeSpec::TypeSum(TypeSum::new(types)));
+                return;
+            }
+        }
         let mut node = self.builder.add_typedef(&name)
             .unwrap_or_else(|| panic!("Error: Name {} is defined more than once in the spec.", name));
         assert!(!type_.is_optional());
         node.with_spec(type_.spec);
     }
     fn import_interface(&mut self, interface: &Interface) {
-        let interface = if let &Interface::NonPartial(ref interface) = interface {
-            interface
-        } else {
-            panic!("Expected a non-partial interface, got {:?}", interface);
+        let interface = match *interface {
+            Interface::NonPartial(ref interface) => interface,
+            Interface::Partial(ref interface) if self.is_extension => {
+                self.import_partial_interface(interface);
+                return;
+            }
+            _ => panic!("Expected a non-partial interface, got {:?}", interface)
         };
 
         // Handle special, hardcoded, interfaces.
@@ -139,8 +277,43 @@ impl Importer {
         self.path.push(interface.name.clone());
 
         // Now handle regular stuff.
+        let mut fields = self.import_fields(&interface.members);
+        let name = self.builder.node_name(&interface.name);
+        let mut node = self.builder.add_interface(&name)
+            .expect("Name already present");
+        for (field_name, field_type, laziness) in fields.drain(..) {
+            node.with_field_laziness(&field_name, field_type, laziness);
+        }
+
+        for extended_attribute in &interface.extended_attributes {
+            use webidl::ast::ExtendedAttribute::NoArguments;
+            use webidl::ast::Other::Identifier;
+            if let &NoArguments(Identifier(ref id)) = extended_attribute.as_ref() {
+                if &*id == "Skippable" {
+                    panic!("Encountered deprecated attribute [Skippable]");
+                }
+                if &*id == "Scope" {
+                    node.with_scope(true);
+                }
+            }
+        }
+        self.path.pop();
+    }
+    /// Append the fields of a partial interface to an interface of the base grammar.
+    fn import_partial_interface(&mut self, interface: &PartialInterface) {
+        self.path.push(interface.name.clone());
+        let mut fields = self.import_fields(&interface.members);
+        let name = self.builder.node_name(&interface.name);
+        let mut node = self.builder.get_interface(&name)
+            .unwrap_or_else(|| panic!("Error: Partial interface {} does not extend an interface.", name));
+        for (field_name, field_type, laziness) in fields.drain(..) {
+            node.with_field_laziness(&field_name, field_type, laziness);
+        }
+        self.path.pop();
+    }
+    fn import_fields(&mut self, members: &[InterfaceMember]) -> Vec<(spec::FieldName, spec::Type, Laziness)> {
         let mut fields = Vec::new();
-        for member in &interface.members {
+        for member in members {
             if let InterfaceMember::Attribute(Attribute::Regular(ref attribute)) = *member {
                 use webidl::ast::ExtendedAttribute::NoArguments;
                 use webidl::ast::Other::Identifier;
@@ -163,26 +336,7 @@ impl Importer {
                 panic!("Expected an attribute, got {:?}", member);
             }
         }
-        let name = self.builder.node_name(&interface.name);
-        let mut node = self.builder.add_interface(&name)
-            .expect("Name already present");
-        for (field_name, field_type, laziness) in fields.drain(..) {
-            node.with_field_laziness(&field_name, field_type, laziness);
-        }
-
-        for extended_attribute in &interface.extended_attributes {
-            use webidl::ast::ExtendedAttribute::NoArguments;
-            use webidl::ast::Other::Identifier;
-            if let &NoArguments(Identifier(ref id)) = extended_attribute.as_ref() {
-                if &*id == "Skippable" {
-                    panic!("Encountered deprecated attribute [Skippable]");
-                }
-                if &*id == "Scope" {
-                    node.with_scope(true);
-                }
-            }
-        }
-        self.path.pop();
+        fields
     }
     fn convert_type(&mut self, t: &Type) -> spec::Type {
         let spec = match t.kind {
@@ -6,9 +6,15 @@ extern crate itertools;
 
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate serde_json;
+extern crate tracing;
 extern crate webidl;
 
 
+/// Comparing two versions of the Syntax.
+pub mod diff;
+
 /// Generic tools for generating implementations of the Syntax.
 pub mod export;
 
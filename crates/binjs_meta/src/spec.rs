@@ -227,6 +227,21 @@ pub enum TypeSpec {
     /// A key for a property. For the time being, we make no distinction between variants such
     /// as `LiteralPropertyName` and `IdentifierName`-as-property-keys.
     PropertyKey,
+
+    /// The value of a BigInt literal, i.e. an integer of arbitrary size.
+    ///
+    /// Actually maps to a string of decimal digits in webidl.
+    BigInt,
+
+    /// The pattern of a RegExp literal, i.e. the source between the slashes.
+    ///
+    /// Actually maps to a string in webidl.
+    RegExpPattern,
+
+    /// The flags of a RegExp literal, e.g. `"gi"`.
+    ///
+    /// Actually maps to a string in webidl.
+    RegExpFlags,
 }
 
 #[derive(Clone, Debug)]
@@ -333,6 +348,9 @@ impl TypeSpec {
             TypeSpec::Offset => Some(IsNullable::non_nullable(Primitive::Offset)),
             TypeSpec::IdentifierName => Some(IsNullable::non_nullable(Primitive::IdentifierName)),
             TypeSpec::PropertyKey => Some(IsNullable::non_nullable(Primitive::PropertyKey)),
+            TypeSpec::BigInt => Some(IsNullable::non_nullable(Primitive::BigInt)),
+            TypeSpec::RegExpPattern => Some(IsNullable::non_nullable(Primitive::RegExpPattern)),
+            TypeSpec::RegExpFlags => Some(IsNullable::non_nullable(Primitive::RegExpFlags)),
             TypeSpec::NamedType(ref name) => {
                 match spec.get_type_by_name(name).unwrap() {
                     NamedType::Interface(ref interface) =>
@@ -372,6 +390,9 @@ pub enum Primitive {
     Interface(Rc<Interface>),
     IdentifierName,
     PropertyKey,
+    BigInt,
+    RegExpPattern,
+    RegExpFlags,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -439,6 +460,15 @@ impl Type {
     pub fn property_key() -> TypeSpec {
         TypeSpec::PropertyKey
     }
+    pub fn big_int() -> TypeSpec {
+        TypeSpec::BigInt
+    }
+    pub fn reg_exp_pattern() -> TypeSpec {
+        TypeSpec::RegExpPattern
+    }
+    pub fn reg_exp_flags() -> TypeSpec {
+        TypeSpec::RegExpFlags
+    }
 
     /// An `offset` type, holding a number of bytes in the binary file.
     pub fn offset() -> TypeSpec {
@@ -589,7 +619,7 @@ impl InterfaceDeclaration {
         self.with_field_aux(name, type_, None, Laziness::Eager)
     }
     pub fn with_field_lazy(&mut self, name: &FieldName, type_: Type) -> &mut Self {
-        self.with_field_aux(name, type_, None, Laziness::Eager)
+        self.with_field_aux(name, type_, None, Laziness::Lazy)
     }
     pub fn with_field_laziness(&mut self, name: &FieldName, type_: Type, laziness: Laziness) -> &mut Self {
         self.with_field_aux(name, type_, None, laziness)
@@ -714,6 +744,11 @@ impl SpecBuilder {
             map(RefCell::borrow)
     }
 
+    pub fn get_typedef_mut(&mut self, name: &NodeName) -> Option<RefMut<Type>> {
+        self.typedefs_by_name.get(name).
+            map(RefCell::borrow_mut)
+    }
+
     /// Generate the graph.
     pub fn into_spec<'a>(self, options: SpecOptions<'a>) -> Spec {
         // 1. Collect node names.
@@ -789,7 +824,7 @@ impl SpecBuilder {
             }
             for name in &used_typenames {
                 // Built-in types
-                if name.to_str() == "IdentifierName" || name.to_str() == "Identifier" || name.to_str() == "PropertyKey" {
+                if name.to_str() == "IdentifierName" || name.to_str() == "Identifier" || name.to_str() == "PropertyKey" || name.to_str() == "BigInt" || name.to_str() == "RegExpPattern" || name.to_str() == "RegExpFlags" {
                     continue;
                 }
                 if typedefs_by_name.contains_key(name) {
@@ -844,7 +879,10 @@ impl SpecBuilder {
                         | TypeSpec::Offset
                         | TypeSpec::UnsignedLong
                         | TypeSpec::IdentifierName
-                        | TypeSpec::PropertyKey => {
+                        | TypeSpec::PropertyKey
+                        | TypeSpec::BigInt
+                        | TypeSpec::RegExpPattern
+                        | TypeSpec::RegExpFlags => {
                         debug!(target: "spec", "classify_type => don't put me in an interface");
                         TypeClassification::Primitive
                     }
@@ -860,7 +898,7 @@ impl SpecBuilder {
                         // Start lookup for this name.
                         cache.insert(name.clone(), None);
                         let result =
-                            if name.to_str() == "IdentifierName" || name.to_str() == "Identifier" || name.to_str() == "PropertyKey" {
+                            if name.to_str() == "IdentifierName" || name.to_str() == "Identifier" || name.to_str() == "PropertyKey" || name.to_str() == "BigInt" || name.to_str() == "RegExpPattern" || name.to_str() == "RegExpFlags" {
                                 TypeClassification::Primitive
                             } else if interfaces_by_name.contains_key(name) {
                                 let mut names = HashSet::new();
@@ -1065,6 +1103,57 @@ impl Spec {
         self.get_type_by_name(&self.root)
             .unwrap()
     }
+
+    /// A fingerprint of the grammar, which changes whenever an interface, a field,
+    /// a string enum or a typedef changes.
+    ///
+    /// Unlike `std::hash::Hash`, the result depends neither on the version of Rust
+    /// nor on the order of declarations, so it may be stored in files.
+    pub fn fingerprint(&self) -> u64 {
+        let mut description = String::new();
+        for (name, interface) in self.interfaces_by_name.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+            description.push_str(&format!("interface {} {{ ", name.to_str()));
+            for field in interface.contents().fields() {
+                description.push_str(&format!("{}{}: {}; ",
+                    if field.is_lazy() { "lazy " } else { "" },
+                    field.name().to_str(),
+                    Self::describe_type(field.type_())));
+            }
+            description.push_str("}\n");
+        }
+        for (name, string_enum) in self.string_enums_by_name.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+            description.push_str(&format!("enum {} {{ {} }}\n", name.to_str(), string_enum.strings().iter().format(", ")));
+        }
+        for (name, type_) in self.typedefs_by_name.iter().sorted_by(|a, b| a.0.cmp(b.0)) {
+            description.push_str(&format!("typedef {} {}\n", Self::describe_type(type_), name.to_str()));
+        }
+
+        // FNV-1a.
+        description.bytes()
+            .fold(0xcbf29ce484222325, |hash : u64, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+
+    /// A description of `type_`, as used by `fingerprint`, e.g. `[Statement]` or `(A or B)?`.
+    pub fn describe_type(type_: &Type) -> String {
+        let spec = Self::describe_type_spec(type_.spec());
+        if type_.is_optional() {
+            format!("{}?", spec)
+        } else {
+            spec
+        }
+    }
+
+    /// A description of `spec`, as used by `fingerprint`.
+    pub fn describe_type_spec(spec: &TypeSpec) -> String {
+        match *spec {
+            TypeSpec::Array { ref contents, supports_empty } =>
+                format!("[{}]{}", Self::describe_type(contents), if supports_empty { "" } else { "+" }),
+            TypeSpec::NamedType(ref name) => name.to_str().to_string(),
+            TypeSpec::TypeSum(ref sum) =>
+                format!("({})", sum.types().iter().map(Self::describe_type_spec).format(" or ")),
+            ref primitive => format!("{:?}", primitive)
+        }
+    }
 }
 
 /// Informations passed during the creation of a `Spec` object.
@@ -2,6 +2,8 @@
 
 extern crate inflector;
 
+use tracing;
+
 pub trait ToStr {
     /// Return the value as a `str`.
     fn to_str(&self) -> &str;
@@ -266,12 +268,12 @@ impl<T> Reindentable for T where T: ToStr {
                 .count();
             let mut lines = vec![];
             'per_line: for line in str.lines() {
-                eprintln!("Inspecting line {}", line);
+                tracing::trace!(target: "reflow", "Inspecting line {}", line);
                 let text = &line[indent_len..];
                 let mut gobbled = 0;
                 while text.len() > gobbled {
                     let mut rest = &text[gobbled..];
-                    eprintln!("Line still contains {} ({})", rest, gobbled);
+                    tracing::trace!(target: "reflow", "Line still contains {} ({})", rest, gobbled);
                     if rest.len() + prefix.len() > columns {
                         // Try and find the largest prefix of `text` that fits within `columns`.
                         let mut iterator = rest.chars()
@@ -290,7 +292,7 @@ impl<T> Reindentable for T where T: ToStr {
 
                         match (last_whitespace_before_break, first_whitespace_after_break) {
                             (None, None) => {
-                                eprintln!("Ok, string didn't contain any whitespace: '{}'", rest);
+                                tracing::trace!(target: "reflow", "Ok, string didn't contain any whitespace: '{}'", rest);
                                 // Oh, `rest` does not contain any whitespace. Well, use everything.
                                 lines.push(format!("{prefix}{rest}",
                                     prefix = prefix,
@@ -298,7 +300,7 @@ impl<T> Reindentable for T where T: ToStr {
                                 continue 'per_line
                             }
                             (Some(pos), _) | (None, Some(pos)) if pos != 0 => {
-                                eprintln!("Best whitespace found at {}", pos);
+                                tracing::trace!(target: "reflow", "Best whitespace found at {}", pos);
                                 // Use `rest[0..pos]`, trimmed right.
                                 gobbled += pos + 1;
                                 let line = format!("{prefix}{rest}",
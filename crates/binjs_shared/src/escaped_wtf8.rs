@@ -1,25 +1,26 @@
-/// An utility to convert between WTF-8 and UTF-8 + special; escape sequence.
-///
-/// The escape sequence has the following syntax:
-///   \x7F (single delete character) + XXXX (4 hex digits in ASCII)
-/// where XXXX is either 007F or lone surrogate's code unit.
-/// All code units in that range should be escaped, and no other code units
-/// are allowed to be escaped.
-///
-/// This escape sequence is supposed to be used in encoder/decoder's internal
-/// representation of any kind of string, in order to use str/String type for
-/// WTF-8 strings.
-///
-/// Deserializers are supposed to escape input WTF-8 string to get internal
-/// escaped-UTF-8 string, and serializers are supposed to unescape the internal
-/// escaped UTF-8 string to generate WTF-8 string.
-///
-/// \x7F is chosen because it's representable in single byte without escape
-/// in JSON, and most likely unused in actual JS code.
-
+//! An utility to convert between WTF-8 and UTF-8 + special; escape sequence.
+//!
+//! The escape sequence has the following syntax:
+//!   \x7F (single delete character) + XXXX (4 hex digits in ASCII)
+//! where XXXX is either 007F or lone surrogate's code unit.
+//! All code units in that range should be escaped, and no other code units
+//! are allowed to be escaped.
+//!
+//! This escape sequence is supposed to be used in encoder/decoder's internal
+//! representation of any kind of string, in order to use str/String type for
+//! WTF-8 strings.
+//!
+//! Deserializers are supposed to escape input WTF-8 string to get internal
+//! escaped-UTF-8 string, and serializers are supposed to unescape the internal
+//! escaped UTF-8 string to generate WTF-8 string.
+//!
+//! \x7F is chosen because it's representable in single byte without escape
+//! in JSON, and most likely unused in actual JS code.
+
+use std;
 use std::borrow::Cow;
 
-use binjs_shared::{ SharedString };
+use shared_string::SharedString;
 
 const LONE_SURROGATE_ESCAPE_CHAR: u8 = 0x7F;
 
@@ -67,84 +68,87 @@ fn is_unit_3(c: u8) -> bool {
 /// lone surrogate with \x7F + XXXX (4 hex digits) and return the byte array.
 /// If not, return the given `bytes`.
 ///
-/// This assumes the input is well-formed WTF-8, and panics otherwise.
+/// Malformed WTF-8 is copied as is, so that it is rejected when the result
+/// is converted to a `String`.
 pub fn escape(bytes: Vec<u8>) -> Vec<u8> {
-    let pos = bytes
-        .as_slice()
-        .iter()
-        .position(|&c|
-                  c == LONE_SURROGATE_ESCAPE_CHAR ||
-                  c == LONE_SURROGATE_UNIT_1);
-
-    //   ...... \x7F XXXX ......
-    //   ^      ^
-    //   |      |
-    // bytes   end
-    //
-    //   ...... \xED \xA0 \x80 ......
-    //   ^      ^
-    //   |      |
-    // bytes   end
-    let mut end = if let Some(end) = pos {
-        end
-    } else {
+    if !bytes.iter().any(|&c| c == LONE_SURROGATE_ESCAPE_CHAR || c == LONE_SURROGATE_UNIT_1) {
         return bytes;
-    };
-
-    let mut input = bytes.as_slice();
-
-    let mut buf: Vec<u8> = Vec::with_capacity(input.len().next_power_of_two());
-    loop {
-        let head = &input[..end];
-        buf.extend_from_slice(head);
+    }
 
-        let tail = if input[end] == LONE_SURROGATE_ESCAPE_CHAR {
+    let mut buf: Vec<u8> = Vec::with_capacity(bytes.len().next_power_of_two());
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        if c == LONE_SURROGATE_ESCAPE_CHAR {
             buf.extend_from_slice(b"\x7F007F");
-
-            end + 1
+            i += 1;
+        } else if c == LONE_SURROGATE_UNIT_1 && i + 2 < bytes.len()
+            && is_unit_2(bytes[i + 1]) && is_unit_3(bytes[i + 2])
+        {
+            let codepoint = (((c as u16) & 0x0F) << 12) |
+                (((bytes[i + 1] & 0x3F) as u16) << 6) |
+                ((bytes[i + 2] & 0x3F) as u16);
+            push_escaped(&mut buf, codepoint);
+            i += 3;
         } else {
-            if is_unit_2(input[end + 1]) && is_unit_3(input[end + 2]) {
-                let codepoint = (((input[end] as u16) & 0x0F) << 12) |
-                    (((input[end + 1] & 0x3F) as u16) << 6) |
-                    ((input[end + 2] & 0x3F) as u16);
-
-                buf.push(LONE_SURROGATE_ESCAPE_CHAR);
-                buf.push(encode_hex_char(((codepoint >> 12) & 0xf) as u8));
-                buf.push(encode_hex_char(((codepoint >> 8) & 0xf) as u8));
-                buf.push(encode_hex_char(((codepoint >> 4) & 0xf) as u8));
-                buf.push(encode_hex_char((codepoint & 0xf) as u8));
-            } else {
-                buf.push(input[end]);
-                buf.push(input[end + 1]);
-                buf.push(input[end + 2]);
-            }
-
-            end + 3
-        };
-
-        // ...... \xED \xA0 \x80 ......
-        //                       ^
-        //                       |
-        //                     input
-        input = &input[tail..];
+            // Includes the 3-byte sequences starting with \xED that are not
+            // surrogates, which are copied byte by byte.
+            buf.push(c);
+            i += 1;
+        }
+    }
+    buf
+}
 
-        let pos = input
-            .iter()
-            .position(|&c| c == LONE_SURROGATE_ESCAPE_CHAR);
+/// Push \x7F + XXXX (4 hex digits).
+fn push_escaped(buf: &mut Vec<u8>, code_unit: u16) {
+    buf.push(LONE_SURROGATE_ESCAPE_CHAR);
+    buf.push(encode_hex_char(((code_unit >> 12) & 0xf) as u8));
+    buf.push(encode_hex_char(((code_unit >> 8) & 0xf) as u8));
+    buf.push(encode_hex_char(((code_unit >> 4) & 0xf) as u8));
+    buf.push(encode_hex_char((code_unit & 0xf) as u8));
+}
 
-        // ...... \xED \xA0 \x80 ...... \xED \xA0 \x80 ......
-        //                       ^      ^
-        //                       |      |
-        //                     input   end
-        end = if let Some(end) = pos {
-            end
-        } else {
-            buf.extend_from_slice(&input);
-            break;
-        };
-    };
+/// Convert a sequence of UTF-16 code units, e.g. a JS string, to escaped
+/// UTF-8, escaping lone surrogates.
+pub fn from_utf16(units: &[u16]) -> String {
+    let mut buf = Vec::with_capacity(units.len());
+    for item in std::char::decode_utf16(units.iter().cloned()) {
+        match item {
+            Ok(c) if c as u32 == LONE_SURROGATE_ESCAPE_CHAR as u32 => buf.extend_from_slice(b"\x7F007F"),
+            Ok(c) => {
+                let mut bytes = [0; 4];
+                buf.extend_from_slice(c.encode_utf8(&mut bytes).as_bytes());
+            }
+            Err(err) => push_escaped(&mut buf, err.unpaired_surrogate()),
+        }
+    }
+    String::from_utf8(buf)
+        .expect("Escaped string should be valid UTF-8")
+}
 
-    buf
+/// Convert escaped UTF-8 to a sequence of UTF-16 code units, e.g. a JS string,
+/// restoring lone surrogates.
+///
+/// This assumes the input is well-formed escaped UTF-8, which is the result of
+/// escape function, and panics otherwise.
+pub fn to_utf16(s: &str) -> Vec<u16> {
+    let mut units = Vec::with_capacity(s.len());
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while let Some(pos) = bytes[i..].iter().position(|&c| c == LONE_SURROGATE_ESCAPE_CHAR) {
+        units.extend(s[i..i + pos].encode_utf16());
+        let start = i + pos;
+        let code_unit =
+            (decode_hex_char(bytes[start + 1]) << 12) |
+            (decode_hex_char(bytes[start + 2]) << 8) |
+            (decode_hex_char(bytes[start + 3]) << 4) |
+            decode_hex_char(bytes[start + 4]);
+        units.push(code_unit);
+        i = start + 5;
+    }
+    units.extend(s[i..].encode_utf16());
+    units
 }
 
 /// If the given `bytes` contains any escaped lone surropgate, unescape all
@@ -295,3 +299,20 @@ pub fn for_print(s: &SharedString) -> SharedString {
     SharedString::from_string(String::from_utf8(buf)
                               .expect("Escaped string should be valid UTF-8"))
 }
+
+#[test]
+fn test_escaped_wtf8() {
+    // "a" + lone lead surrogate + "b" + \x7F + lone trail surrogate + valid pair.
+    let units : Vec<u16> = vec![0x61, 0xD800, 0x62, 0x7F, 0xDC00, 0xD83D, 0xDE00];
+    let escaped = from_utf16(&units);
+    assert_eq!(escaped, "a\x7FD800b\x7F007F\x7FDC00\u{1F600}");
+    assert_eq!(to_utf16(&escaped), units);
+
+    // Both lone surrogates are encoded as WTF-8.
+    let wtf8 = unescape(escaped.as_bytes()).into_owned();
+    assert_eq!(wtf8, b"a\xED\xA0\x80b\x7F\xED\xB0\x80\xF0\x9F\x98\x80".to_vec());
+    assert_eq!(escape(wtf8), escaped.as_bytes());
+
+    // Malformed WTF-8 is not a valid string.
+    assert!(String::from_utf8(escape(vec![0x61, 0xED, 0xA0])).is_err());
+}
@@ -1,13 +1,352 @@
-use ::{ IdentifierName, PropertyKey, SharedString };
+use ::{ BigInt, IdentifierName, PropertyKey, RegExpFlags, RegExpPattern, SharedString };
 
-use json;
-use json::JsonValue as JSON;
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json;
 
+use std;
+
+/// A JSON value.
+///
+/// Objects preserve the order in which their entries were inserted.
+pub type JSON = serde_json::Value;
+
+/// A JSON object.
+pub type JSONObject = serde_json::Map<String, JSON>;
+
+/// Build a `JSON` object, e.g. `object!{ "type" => "Script", "statements" => array![] }`.
+///
+/// Keys may be anything that converts into a `String` and values anything that converts
+/// into a `JSON`.
+#[macro_export]
+macro_rules! object {
+    () => {
+        $crate::JSON::Object($crate::JSONObject::new())
+    };
+    ( $( $key:expr => $value:expr ),+ $(,)* ) => {{
+        let mut object = $crate::JSONObject::new();
+        $(
+            object.insert(::std::convert::Into::<::std::string::String>::into($key), ::std::convert::Into::<$crate::JSON>::into($value));
+        )+
+        $crate::JSON::Object(object)
+    }};
+}
+
+/// Build a `JSON` array, e.g. `array![1, "two", object!{}]`.
+#[macro_export]
+macro_rules! array {
+    () => {
+        $crate::JSON::Array(Vec::new())
+    };
+    ( $( $value:expr ),+ $(,)* ) => {
+        $crate::JSON::Array(vec![ $( ::std::convert::Into::<$crate::JSON>::into($value) ),+ ])
+    };
+}
+
+/// Shorthands for manipulating `JSON` values, which spare us from converting
+/// to arrays or objects at each step.
+pub trait JSONExt {
+    /// A compact representation of the value.
+    fn dump(&self) -> String;
+
+    /// A human-readable representation of the value, indented by `spaces` spaces.
+    fn pretty(&self, spaces: u16) -> String;
+
+    /// The number of items of an array or of entries of an object, 0 for other values.
+    fn len(&self) -> usize;
+
+    /// `true` for empty arrays, empty objects, and values that are neither.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The items of an array, nothing for other values.
+    fn members(&self) -> std::slice::Iter<JSON>;
+
+    /// The items of an array, nothing for other values.
+    fn members_mut(&mut self) -> std::slice::IterMut<JSON>;
+
+    /// The value as a `u32`, if it is a non-negative integer small enough.
+    fn as_u32(&self) -> Option<u32>;
+
+    /// Append an item to an array.
+    ///
+    /// Fails if the value is not an array.
+    fn push<T: Into<JSON>>(&mut self, value: T) -> Result<(), ()>;
+
+    /// Remove an entry from an object, returning `JSON::Null` if there is
+    /// no such entry or if the value is not an object.
+    fn remove(&mut self, key: &str) -> JSON;
+}
+impl JSONExt for JSON {
+    fn dump(&self) -> String {
+        self.to_string()
+    }
+
+    fn pretty(&self, spaces: u16) -> String {
+        let indent = vec![b' '; spaces as usize];
+        let mut buf = Vec::new();
+        {
+            let formatter = serde_json::ser::PrettyFormatter::with_indent(&indent);
+            let mut serializer = serde_json::Serializer::with_formatter(&mut buf, formatter);
+            self.serialize(&mut serializer)
+                .expect("Could not serialize JSON");
+        }
+        String::from_utf8(buf)
+            .expect("Invalid UTF-8 in serialized JSON")
+    }
+
+    fn len(&self) -> usize {
+        match *self {
+            JSON::Array(ref array) => array.len(),
+            JSON::Object(ref object) => object.len(),
+            _ => 0
+        }
+    }
+
+    fn members(&self) -> std::slice::Iter<JSON> {
+        match *self {
+            JSON::Array(ref array) => array.iter(),
+            _ => (&[]).iter()
+        }
+    }
+
+    fn members_mut(&mut self) -> std::slice::IterMut<JSON> {
+        match *self {
+            JSON::Array(ref mut array) => array.iter_mut(),
+            _ => (&mut []).iter_mut()
+        }
+    }
+
+    fn as_u32(&self) -> Option<u32> {
+        self.as_u64()
+            .and_then(|value| if value <= std::u32::MAX as u64 { Some(value as u32) } else { None })
+    }
+
+    fn push<T: Into<JSON>>(&mut self, value: T) -> Result<(), ()> {
+        match *self {
+            JSON::Array(ref mut array) => {
+                array.push(value.into());
+                Ok(())
+            }
+            _ => Err(())
+        }
+    }
+
+    fn remove(&mut self, key: &str) -> JSON {
+        match *self {
+            JSON::Object(ref mut object) => object.remove(key)
+                .unwrap_or(JSON::Null),
+            _ => JSON::Null
+        }
+    }
+}
+
+/// A step of the path to a value that could not be imported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FromJSONPathItem {
+    /// A field of an interface, e.g. `ExpressionStatement.expression`.
+    Field {
+        interface: String,
+        field: String,
+    },
+
+    /// An item of a list.
+    Index(usize),
+}
+impl std::fmt::Display for FromJSONPathItem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            FromJSONPathItem::Field { ref interface, ref field } => write!(f, ".{}.{}", interface, field),
+            FromJSONPathItem::Index(index) => write!(f, "[{}]", index),
+        }
+    }
+}
+
+/// An error while importing an AST from JSON.
+///
+/// Errors are created where the offending value is found, then located as they
+/// propagate through the enclosing nodes, so that `path` leads from the root of
+/// the AST to the offending value.
+///
+/// ```
+/// # #[macro_use] extern crate binjs_shared;
+/// use binjs_shared::{ FromJSONError, FromJSONPathItem };
+///
+/// # fn main() {
+/// let error = FromJSONError::new("Instance of Expression", &object!{ "type" => "CallExpresion", "arguments" => array![] })
+///     .with_valid(&["CallExpression", "NewExpression"])
+///     .in_field("ExpressionStatement", "expression")
+///     .at_index(3)
+///     .in_field("Script", "statements");
+///
+/// assert_eq!(error.field(), Some("expression"));
+/// assert_eq!(error.path[1], FromJSONPathItem::Index(3));
+/// assert_eq!(error.suggestion.as_ref().unwrap(), "Did you mean CallExpression?");
+/// assert_eq!(error.to_string(),
+///     ".Script.statements[3].ExpressionStatement.expression: expected Instance of Expression (one of CallExpression, NewExpression), got {\"type\": \"CallExpresion\", ...}. Did you mean CallExpression?");
+/// # }
+/// ```
 #[derive(Debug)]
 pub struct FromJSONError {
+    /// A description of the expected value, e.g. `"String"` or `"Instance of Expression"`.
     pub expected: String,
+
+    /// A short description of the value found, see `FromJSONError::describe`.
     pub got: String,
+
+    /// From the root of the AST to the offending value.
+    pub path: Vec<FromJSONPathItem>,
+
+    /// The interfaces or string enum values that would have been accepted, if known.
+    pub valid: Vec<String>,
+
+    /// A hint for fixing the AST, if any.
+    pub suggestion: Option<String>,
 }
+impl FromJSONError {
+    /// The maximal number of characters of strings in descriptions.
+    const MAX_STRING_LEN: usize = 40;
+
+    /// The maximal number of valid values listed by `Display`.
+    const MAX_VALID_DISPLAYED: usize = 8;
+
+    pub fn new(expected: &str, got: &JSON) -> Self {
+        let suggestion = if got.is_null() {
+            Some("The value is missing: check that the field is present and correctly spelled.".to_string())
+        } else {
+            None
+        };
+        FromJSONError {
+            expected: expected.to_string(),
+            got: Self::describe(got),
+            path: vec![],
+            valid: vec![],
+            suggestion,
+        }
+    }
+
+    /// Specify the interfaces or string enum values that would have been accepted,
+    /// suggesting the closest one if the value found looks like a misspelling.
+    pub fn with_valid(mut self, valid: &[&str]) -> Self {
+        if self.suggestion.is_none() {
+            self.suggestion = Self::name_in_description(&self.got)
+                .and_then(|got| valid.iter()
+                    .map(|candidate| (Self::distance(got, candidate), candidate))
+                    .filter(|&(distance, candidate)| distance <= 2 && distance < candidate.len() / 2 || got.eq_ignore_ascii_case(candidate))
+                    .min())
+                .map(|(_, candidate)| format!("Did you mean {}?", candidate));
+        }
+        self.valid = valid.iter()
+            .map(|name| name.to_string())
+            .collect();
+        self
+    }
+
+    /// Locate the error in field `field` of an instance of `interface`.
+    pub fn in_field(mut self, interface: &str, field: &str) -> Self {
+        self.path.insert(0, FromJSONPathItem::Field {
+            interface: interface.to_string(),
+            field: field.to_string(),
+        });
+        self
+    }
+
+    /// Locate the error in item `index` of a list.
+    pub fn at_index(mut self, index: usize) -> Self {
+        self.path.insert(0, FromJSONPathItem::Index(index));
+        self
+    }
+
+    /// The name of the field containing the offending value, if any.
+    pub fn field(&self) -> Option<&str> {
+        self.path.iter()
+            .rev()
+            .filter_map(|item| match *item {
+                FromJSONPathItem::Field { ref field, .. } => Some(field.as_str()),
+                FromJSONPathItem::Index(_) => None,
+            })
+            .next()
+    }
+
+    /// A short description of `value`, rather than the entire subtree.
+    ///
+    /// Nodes are described by their `type`, strings are truncated.
+    pub fn describe(value: &JSON) -> String {
+        match *value {
+            JSON::Object(ref object) => match object.get("type").and_then(JSON::as_str) {
+                Some(kind) => format!("{{\"type\": {:?}, ...}}", kind),
+                None => format!("object with fields {}", object.keys()
+                    .map(|key| format!("{:?}", key))
+                    .join(", ")),
+            },
+            JSON::Array(ref array) => format!("list of {} items", array.len()),
+            JSON::String(ref string) if string.chars().count() > Self::MAX_STRING_LEN => {
+                let prefix : String = string.chars()
+                    .take(Self::MAX_STRING_LEN)
+                    .collect();
+                format!("{:?}...", prefix)
+            }
+            _ => value.dump(),
+        }
+    }
+
+    /// The name of a node or the string of a string enum in a description built
+    /// by `describe`, if any.
+    fn name_in_description(description: &str) -> Option<&str> {
+        let description = if description.starts_with("{\"type\": \"") {
+            &description["{\"type\": \"".len()..]
+        } else if description.starts_with('"') {
+            &description[1..]
+        } else {
+            return None;
+        };
+        description.split('"')
+            .next()
+    }
+
+    /// The Levenshtein distance between two names.
+    fn distance(a: &str, b: &str) -> usize {
+        let b : Vec<char> = b.chars().collect();
+        let mut previous : Vec<usize> = (0..b.len() + 1).collect();
+        for (i, a_char) in a.chars().enumerate() {
+            let mut current = vec![i + 1];
+            for (j, b_char) in b.iter().enumerate() {
+                let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+                let cost = std::cmp::min(substitution, std::cmp::min(previous[j + 1], current[j]) + 1);
+                current.push(cost);
+            }
+            previous = current;
+        }
+        previous[b.len()]
+    }
+}
+impl std::fmt::Display for FromJSONError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        if self.path.is_empty() {
+            write!(f, "<root>")?;
+        }
+        for item in &self.path {
+            item.fmt(f)?;
+        }
+        write!(f, ": expected {}", self.expected)?;
+        if self.valid.len() > 1 {
+            write!(f, " (one of {}", self.valid.iter()
+                .take(Self::MAX_VALID_DISPLAYED)
+                .join(", "))?;
+            if self.valid.len() > Self::MAX_VALID_DISPLAYED {
+                write!(f, ", and {} more", self.valid.len() - Self::MAX_VALID_DISPLAYED)?;
+            }
+            write!(f, ")")?;
+        }
+        write!(f, ", got {}", self.got)?;
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, ". {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for FromJSONError {}
 
 /// A data structure that may be imported from JSON.
 pub trait FromJSON: Sized {
@@ -16,45 +355,29 @@ pub trait FromJSON: Sized {
 impl FromJSON for bool {
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
         match value.as_bool() {
-            None => Err(FromJSONError {
-                expected: "Boolean".to_string(),
-                got: value.dump()
-            }),
+            None => Err(FromJSONError::new("Boolean", value)),
             Some(ref s) => Ok(*s)
         }
     }
 }
 impl FromJSON for f64 {
+    /// Import a number, or one of the strings `"NaN"`, `"Infinity"` and `"-Infinity"`,
+    /// which JSON cannot represent as numbers.
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
-        // JSON.as_f64() doesn't keep the precision.
-        match value {
-            JSON::Number(n) => {
-                if n.is_nan() {
-                    Ok(std::f64::NAN)
-                } else {
-                    let (positive, mantissa, exponent) = n.as_parts();
-                    let as_str = if positive {
-                        format!("{}e{}", mantissa, exponent)
-                    } else {
-                        format!("-{}e{}", mantissa, exponent)
-                    };
-                    Ok(as_str.parse::<f64>().expect("cannot parse the number!"))
-                }
-            }
-            _ => Err(FromJSONError {
-                expected: "Number".to_string(),
-                got: value.dump()
-            })
+        match *value {
+            JSON::Number(ref number) => number.as_f64()
+                .ok_or_else(|| FromJSONError::new("Number", value)),
+            JSON::String(ref s) if s == "NaN" => Ok(std::f64::NAN),
+            JSON::String(ref s) if s == "Infinity" => Ok(std::f64::INFINITY),
+            JSON::String(ref s) if s == "-Infinity" => Ok(std::f64::NEG_INFINITY),
+            _ => Err(FromJSONError::new("Number, \"NaN\", \"Infinity\" or \"-Infinity\"", value)),
         }
     }
 }
 impl FromJSON for u32 {
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
         match value.as_u32() {
-            None => Err(FromJSONError {
-                expected: "Number".to_string(),
-                got: value.dump()
-            }),
+            None => Err(FromJSONError::new("Number", value)),
             Some(ref s) => Ok(*s as u32)
         }
     }
@@ -62,10 +385,7 @@ impl FromJSON for u32 {
 impl FromJSON for String {
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
         match value.as_str() {
-            None => Err(FromJSONError {
-                expected: "String".to_string(),
-                got: value.dump()
-            }),
+            None => Err(FromJSONError::new("String", value)),
             Some(ref s) => Ok(s.to_string())
         }
     }
@@ -73,10 +393,7 @@ impl FromJSON for String {
 impl FromJSON for SharedString {
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
         match value.as_str() {
-            None => Err(FromJSONError {
-                expected: "String".to_string(),
-                got: value.dump()
-            }),
+            None => Err(FromJSONError::new("String", value)),
             Some(ref s) => Ok(SharedString::from_string(s.to_string()))
         }
     }
@@ -84,10 +401,7 @@ impl FromJSON for SharedString {
 impl FromJSON for IdentifierName {
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
         match value.as_str() {
-            None => Err(FromJSONError {
-                expected: "Identifier or IdentifierName".to_string(),
-                got: value.dump()
-            }),
+            None => Err(FromJSONError::new("Identifier or IdentifierName", value)),
             Some(ref s) => Ok(IdentifierName::from_string(s.to_string()))
         }
     }
@@ -95,29 +409,56 @@ impl FromJSON for IdentifierName {
 impl FromJSON for PropertyKey {
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
         match value.as_str() {
-            None => Err(FromJSONError {
-                expected: "PropertyKey".to_string(),
-                got: value.dump()
-            }),
+            None => Err(FromJSONError::new("PropertyKey", value)),
             Some(ref s) => Ok(PropertyKey::from_string(s.to_string()))
         }
     }
 }
+impl FromJSON for BigInt {
+    fn import(value: &JSON) -> Result<Self, FromJSONError> {
+        let is_decimal = |s: &str| {
+            let digits = if s.starts_with('-') { &s[1..] } else { s };
+            !digits.is_empty() && digits.bytes().all(|c| c.is_ascii_digit())
+        };
+        match value.as_str() {
+            Some(s) if is_decimal(s) => Ok(BigInt::from_string(s.to_string())),
+            _ => Err(FromJSONError::new("BigInt (decimal digits)", value)),
+        }
+    }
+}
+impl FromJSON for RegExpPattern {
+    fn import(value: &JSON) -> Result<Self, FromJSONError> {
+        match value.as_str() {
+            None => Err(FromJSONError::new("RegExpPattern", value)),
+            Some(ref s) => Ok(RegExpPattern::from_string(s.to_string()))
+        }
+    }
+}
+impl FromJSON for RegExpFlags {
+    /// Import flags, in canonical order, e.g. `"ig"` is imported as `"gi"`.
+    fn import(value: &JSON) -> Result<Self, FromJSONError> {
+        let flags = value.as_str()
+            .and_then(|s| RegExpFlags::from_string(s.to_string()).to_bits())
+            .and_then(RegExpFlags::from_bits);
+        match flags {
+            Some(flags) => Ok(flags),
+            None => Err(FromJSONError::new(&format!("RegExpFlags (among \"{}\", each at most once)", RegExpFlags::ALL), value)),
+        }
+    }
+}
 impl<T> FromJSON for Vec<T> where T: FromJSON {
     fn import(value: &JSON) -> Result<Self, FromJSONError> {
         match *value {
             JSON::Array(ref array) => {
                 let mut result = Vec::with_capacity(array.len());
-                for item in array {
-                    let imported = FromJSON::import(item)?;
+                for (index, item) in array.iter().enumerate() {
+                    let imported = FromJSON::import(item)
+                        .map_err(|err| err.at_index(index))?;
                     result.push(imported);
                 }
                 Ok(result)
             },
-            _ => Err(FromJSONError {
-                expected: "Array".to_string(),
-                got: value.dump(),
-            })
+            _ => Err(FromJSONError::new("Array", value))
         }
     }
 }
@@ -142,22 +483,32 @@ pub trait ToJSON {
 }
 impl ToJSON for String {
     fn export(&self) -> JSON {
-        json::from(self.clone())
+        JSON::from(self.clone())
     }
 }
 impl ToJSON for bool {
     fn export(&self) -> JSON {
-        json::from(self.clone())
+        JSON::from(self.clone())
     }
 }
 impl ToJSON for f64 {
+    /// Export a number. As JSON cannot represent NaN and infinities as numbers,
+    /// they are exported as strings `"NaN"`, `"Infinity"` and `"-Infinity"`.
     fn export(&self) -> JSON {
-        json::from(self.clone())
+        if self.is_nan() {
+            JSON::from("NaN")
+        } else if *self == std::f64::INFINITY {
+            JSON::from("Infinity")
+        } else if *self == std::f64::NEG_INFINITY {
+            JSON::from("-Infinity")
+        } else {
+            JSON::from(*self)
+        }
     }
 }
 impl ToJSON for u32 {
     fn export(&self) -> JSON {
-        json::from(self.clone())
+        JSON::from(self.clone())
     }
 }
 impl<T> ToJSON for Vec<T> where T: ToJSON {
@@ -206,8 +557,51 @@ impl ToJSON for PropertyKey {
     }
 }
 
+impl ToJSON for BigInt {
+    fn export(&self) -> JSON {
+        self.as_str().to_string().export()
+    }
+}
+
+impl ToJSON for RegExpPattern {
+    fn export(&self) -> JSON {
+        self.as_str().to_string().export()
+    }
+}
+
+impl ToJSON for RegExpFlags {
+    fn export(&self) -> JSON {
+        self.as_str().to_string().export()
+    }
+}
+
 impl ToJSON for SharedString {
     fn export(&self) -> JSON {
         self.as_str().to_string().export()
     }
 }
+
+#[test]
+fn test_f64_roundtrip() {
+    let values = [
+        0.1,
+        1.0 / 3.0,
+        -0.0,
+        2.2250738585072014e-308,
+        1.7976931348623157e308,
+        std::f64::INFINITY,
+        std::f64::NEG_INFINITY,
+    ];
+    for value in values.iter() {
+        let source = value.export().dump();
+        let json: JSON = serde_json::from_str(&source)
+            .expect("Could not parse JSON");
+        let imported = f64::import(&json)
+            .expect("Could not import number");
+        assert_eq!(imported.to_bits(), value.to_bits(), "Number {} changed through {}", value, source);
+    }
+
+    let nan = std::f64::NAN.export();
+    assert_eq!(nan, JSON::from("NaN"));
+    assert!(f64::import(&nan).expect("Could not import NaN").is_nan());
+}
@@ -1,12 +1,13 @@
 #[macro_use]
 extern crate downcast_rs;
 extern crate itertools;
-extern crate json;
 #[macro_use]
 extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate unicode_xid;
 
 mod json_conversion;
 pub use json_conversion::*;
@@ -14,12 +15,15 @@ pub use json_conversion::*;
 pub mod ast;
 pub use ast::Node;
 
+/// Representing strings with lone surrogates, as found in JS, in Rust strings.
+pub mod escaped_wtf8;
+
 pub mod mru;
 mod shared_string;
 pub use shared_string::SharedString;
 
 
-#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[derive(Clone, Debug, PartialEq, Eq, Default, Deserialize, Serialize)]
 pub struct Offset(pub u32);
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -36,9 +40,110 @@ pub enum VisitMe<T> {
 /// An identifier, inside the grammar.
 shared_string!(pub IdentifierName);
 pub type Identifier = IdentifierName;
+impl IdentifierName {
+    /// The name given by Shift to anonymous default exports, e.g. `export default function() {}`.
+    pub const ANONYMOUS_DEFAULT: &'static str = "*default*";
+
+    /// Check that this is a valid ECMAScript IdentifierName, once its escape
+    /// sequences have been resolved.
+    ///
+    /// Characters are checked with Unicode properties XID_Start and XID_Continue,
+    /// which only differ from ID_Start and ID_Continue for a handful of compatibility
+    /// characters. `ANONYMOUS_DEFAULT` is also accepted.
+    pub fn is_valid(&self) -> bool {
+        self.as_str() == Self::ANONYMOUS_DEFAULT || is_identifier_name(self.as_str())
+    }
+}
+
+/// Check that `name` is a valid ECMAScript IdentifierName, see `IdentifierName::is_valid`.
+fn is_identifier_name(name: &str) -> bool {
+    use unicode_xid::UnicodeXID;
+
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c == '$' || c == '_' || c.is_xid_start() => {}
+        _ => return false
+    }
+    chars.all(|c| c == '$' || c == '\u{200C}' || c == '\u{200D}' || c.is_xid_continue())
+}
+
+#[test]
+fn test_identifier_name_is_valid() {
+    for name in &["x", "$", "_foo", "a1", "\u{e9}t\u{e9}", "\u{3c0}", "a\u{200D}", "*default*"] {
+        assert!(IdentifierName::from_str(*name).is_valid(), "{:?} should be valid", name);
+    }
+    for name in &["", "1a", "a-b", "a b", "\u{200D}a", "foo()", "*"] {
+        assert!(!IdentifierName::from_str(*name).is_valid(), "{:?} should be invalid", name);
+    }
+    assert!(PropertyKey::from_str("foo").is_valid());
+    assert!(!PropertyKey::from_str("*default*").is_valid());
+    assert!(!PropertyKey::from_str("foo bar").is_valid());
+}
 
 /// A property, inside the grammar.
 shared_string!(pub PropertyKey);
+impl PropertyKey {
+    /// Check that this is a valid ECMAScript IdentifierName, as required where the
+    /// grammar uses property keys, i.e. static member expressions and the names of
+    /// import and export specifiers. See `IdentifierName::is_valid`.
+    pub fn is_valid(&self) -> bool {
+        is_identifier_name(self.as_str())
+    }
+}
+
+/// The value of a BigInt literal, inside the grammar, as a string of decimal
+/// digits, optionally preceded by `-`, e.g. `"255"` for `0xFFn`.
+shared_string!(pub BigInt);
+
+/// The pattern of a RegExp literal, inside the grammar.
+shared_string!(pub RegExpPattern);
+
+/// The flags of a RegExp literal, inside the grammar, e.g. `"gi"`.
+shared_string!(pub RegExpFlags);
+impl RegExpFlags {
+    /// All the flags, in canonical order.
+    pub const ALL: &'static str = "gimsuy";
+
+    /// Convert the flags into a bitfield, in which flag `ALL[i]` is bit `1 << i`.
+    ///
+    /// Returns `None` if a flag is unknown or appears more than once.
+    pub fn to_bits(&self) -> Option<u8> {
+        let mut bits = 0;
+        for c in self.as_str().chars() {
+            let bit = 1 << Self::ALL.find(c)?;
+            if bits & bit != 0 {
+                return None;
+            }
+            bits |= bit;
+        }
+        Some(bits)
+    }
+
+    /// Convert a bitfield back into flags, in canonical order.
+    ///
+    /// Returns `None` if the bitfield contains unknown bits.
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        if bits >> Self::ALL.len() != 0 {
+            return None;
+        }
+        let flags = Self::ALL.chars()
+            .enumerate()
+            .filter(|&(i, _)| bits & (1 << i) != 0)
+            .map(|(_, c)| c)
+            .collect();
+        Some(RegExpFlags::from_string(flags))
+    }
+}
+
+#[test]
+fn test_reg_exp_flags() {
+    assert_eq!(RegExpFlags::from_str("").to_bits(), Some(0));
+    assert_eq!(RegExpFlags::from_str("yig").to_bits(), Some(0b100011));
+    assert_eq!(RegExpFlags::from_bits(0b100011), Some(RegExpFlags::from_str("giy")));
+    assert_eq!(RegExpFlags::from_str("gg").to_bits(), None);
+    assert_eq!(RegExpFlags::from_str("x").to_bits(), None);
+    assert_eq!(RegExpFlags::from_bits(0b1000000), None);
+}
 
 /// An interface *of* the grammar.
 shared_string!(pub InterfaceName);
@@ -1,7 +1,10 @@
+use escaped_wtf8;
+
 use std;
+use std::borrow::Cow;
 use std::hash::Hash;
 use std::ops::Deref;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use serde::de::{ Deserialize, Deserializer };
 use serde::ser::{ Serialize, Serializer };
@@ -9,10 +12,14 @@ use serde::ser::{ Serialize, Serializer };
 /// An implementation of strings that may easily be shared without copies.
 ///
 /// Static strings may be imported without copy, while dynamic strings
-/// are converted into `Rc`.
+/// are converted into `Arc`, so that shared strings may be shared between threads.
+///
+/// As JS strings may contain lone surrogates, which cannot be represented in UTF-8,
+/// lone surrogates are escaped, see `escaped_wtf8`. Use `from_utf16`/`to_utf16` or
+/// `from_wtf8`/`to_wtf8` to convert without loss from/to other representations.
 #[derive(Clone, Debug, Eq, Ord)]
 pub enum SharedString {
-    Dynamic(Rc<String>),
+    Dynamic(Arc<String>),
     Static(&'static str)
 }
 impl Deref for SharedString {
@@ -20,7 +27,7 @@ impl Deref for SharedString {
     fn deref(&self) -> &str {
         match *self {
             SharedString::Static(ref s) => *s,
-            SharedString::Dynamic(ref rc) => rc.deref()
+            SharedString::Dynamic(ref arc) => arc.deref()
         }
     }
 }
@@ -75,7 +82,7 @@ impl<'de> Deserialize<'de> for SharedString {
             D: Deserializer<'de>
     {
         let dynamic = String::deserialize(deserializer)?;
-        Ok(SharedString::Dynamic(Rc::new(dynamic)))
+        Ok(SharedString::Dynamic(Arc::new(dynamic)))
     }
 }
 impl SharedString {
@@ -85,12 +92,44 @@ impl SharedString {
     pub fn from_str(value: &'static str) -> Self {
         SharedString::Static(value)
     }
-    pub fn from_rc_string(value: Rc<String>) -> Self {
+    pub fn from_arc_string(value: Arc<String>) -> Self {
         SharedString::Dynamic(value)
     }
     pub fn from_string(value: String) -> Self {
-        SharedString::Dynamic(Rc::new(value))
+        SharedString::Dynamic(Arc::new(value))
+    }
+
+    /// Create a string from UTF-16 code units, which may contain lone surrogates.
+    pub fn from_utf16(units: &[u16]) -> Self {
+        SharedString::from_string(escaped_wtf8::from_utf16(units))
+    }
+
+    /// The UTF-16 code units of this string, including lone surrogates.
+    pub fn to_utf16(&self) -> Vec<u16> {
+        escaped_wtf8::to_utf16(self.as_str())
     }
+
+    /// Create a string from WTF-8 bytes, which may contain lone surrogates.
+    ///
+    /// Fails if the bytes are not valid WTF-8.
+    pub fn from_wtf8(bytes: Vec<u8>) -> Result<Self, std::string::FromUtf8Error> {
+        String::from_utf8(escaped_wtf8::escape(bytes))
+            .map(SharedString::from_string)
+    }
+
+    /// The WTF-8 bytes of this string, including lone surrogates.
+    pub fn to_wtf8(&self) -> Cow<[u8]> {
+        escaped_wtf8::unescape(self.as_str().as_bytes())
+    }
+}
+
+#[test]
+fn test_lone_surrogates() {
+    let units = [0x61, 0xD800, 0x62];
+    let string = SharedString::from_utf16(&units);
+    assert_eq!(string.to_utf16(), units.to_vec());
+    assert_eq!(string.to_wtf8().as_ref(), b"a\xED\xA0\x80b");
+    assert_eq!(SharedString::from_wtf8(string.to_wtf8().into_owned()), Ok(string));
 }
 
 #[macro_export]
@@ -105,8 +144,8 @@ macro_rules! shared_string {
             pub fn from_string(value: String) -> Self {
                 $name(shared_string::SharedString::from_string(value))
             }
-            pub fn from_rc_string(value: std::rc::Rc<String>) -> Self {
-                $name(shared_string::SharedString::from_rc_string(value))
+            pub fn from_arc_string(value: std::sync::Arc<String>) -> Self {
+                $name(shared_string::SharedString::from_arc_string(value))
             }
             pub fn as_str(&self) -> &str {
                 self.0.as_str()
@@ -0,0 +1,253 @@
+//! A curated API for encoding and decoding JavaScript, meant to remain stable across
+//! releases.
+//!
+//! The other modules of this crate re-export the internal crates of the workspace,
+//! whose APIs change with the format. Users who only need to encode and decode
+//! JavaScript should stick to the types of this module, which are also re-exported
+//! at the root of the crate.
+//!
+//! Encoding and decoding recurse through the AST, so deeply nested sources may need
+//! a thread with a large stack, e.g. 20Mb.
+//!
+//! ```no_run
+//! let encoder = binjs::Encoder::new()
+//!     .with_lazification(1);
+//! let data = encoder.encode_source("function foo() { return 1; }")
+//!     .expect("Could not encode");
+//! let source = binjs::Decoder::new()
+//!     .decode_to_source(&data)
+//!     .expect("Could not decode");
+//! ```
+
+use binjs_es6;
+use binjs_es6::ast::{ Program, Walker, WalkPath };
+use binjs_generic;
+use binjs_io::Format;
+use binjs_io::entropy;
+use binjs_io::entropy::dictionary::{ Dictionary as Tables, Instances };
+use binjs_io::entropy::probabilities::{ InstancesToProbabilities, SymbolInfo };
+use binjs_meta;
+use binjs_shared::{ FromJSON, ToJSON };
+
+use source::{ Shift, SourceParser };
+use source::shift::Error as ParseError;
+
+use bincode;
+
+use std;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::{ Arc, Mutex };
+
+pub use binjs_io::{ Error, ErrorKind };
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Error::new(ErrorKind::Parse, &error)
+    }
+}
+
+/// Counters on the files encoded or decoded by an `Encoder` or a `Decoder`.
+#[derive(Clone, Debug, Default)]
+pub struct Statistics {
+    files: usize,
+    source_bytes: usize,
+    encoded_bytes: usize,
+}
+impl Statistics {
+    /// The number of files successfully encoded or decoded.
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// The total length of the sources, in bytes.
+    pub fn source_bytes(&self) -> usize {
+        self.source_bytes
+    }
+
+    /// The total length of the binaries, in bytes.
+    pub fn encoded_bytes(&self) -> usize {
+        self.encoded_bytes
+    }
+
+    /// The ratio between the length of binaries and that of sources, or 0 if no file
+    /// has been processed.
+    pub fn ratio(&self) -> f64 {
+        if self.source_bytes == 0 {
+            0.
+        } else {
+            self.encoded_bytes as f64 / self.source_bytes as f64
+        }
+    }
+
+    fn add(&mut self, source_bytes: usize, encoded_bytes: usize) {
+        self.files += 1;
+        self.source_bytes += source_bytes;
+        self.encoded_bytes += encoded_bytes;
+    }
+}
+
+/// A dictionary of probabilities, for the entropy format.
+///
+/// Binaries encoded with a dictionary may only be decoded with the same dictionary.
+/// Cloning is cheap, the probability tables are shared.
+#[derive(Clone)]
+pub struct Dictionary {
+    tables: Arc<Tables<SymbolInfo>>,
+}
+impl Dictionary {
+    /// Load a dictionary written by `binjs_dict`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let bytes = std::fs::read(path)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Load a dictionary from the contents of a file written by `binjs_dict`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let instances : Tables<Instances> = bincode::deserialize(bytes)
+            .map_err(|err| Error::new(ErrorKind::Dictionary, &*err))?;
+        Ok(Dictionary {
+            tables: Arc::new(instances.instances_to_probabilities("dictionary")),
+        })
+    }
+}
+impl std::fmt::Debug for Dictionary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str("Dictionary")
+    }
+}
+
+/// The format of binaries: the entropy format with `dictionary`, if specified, or
+/// the multipart format.
+fn format(dictionary: &Option<Dictionary>) -> Format {
+    match *dictionary {
+        Some(ref dictionary) => Format::Entropy {
+            options: entropy::Options::with_shared_dictionary(dictionary.tables.clone()),
+        },
+        None => Format::from_args(&["multipart"])
+            .expect("The multipart format has no required arguments")
+    }
+}
+
+/// Encoding JavaScript sources.
+///
+/// Sources are parsed with Shift, which requires Node.js and the `shift-parser` package.
+pub struct Encoder {
+    parser: Shift,
+    lazification: u32,
+    dictionary: Option<Dictionary>,
+    statistics: Mutex<Statistics>,
+}
+impl Encoder {
+    /// An encoder to the multipart format, without lazy functions.
+    pub fn new() -> Self {
+        Encoder {
+            parser: Shift::new(),
+            lazification: 0,
+            dictionary: None,
+            statistics: Mutex::new(Statistics::default()),
+        }
+    }
+
+    /// Encode functions as lazy, up to `layers` layers of nested functions. 0 = no
+    /// lazy functions, 1 = functions at toplevel, 2 = also functions in functions at
+    /// toplevel, etc.
+    pub fn with_lazification(self, layers: u32) -> Self {
+        Encoder {
+            lazification: layers,
+            ..self
+        }
+    }
+
+    /// Encode to the entropy format with `dictionary`, instead of the multipart format.
+    pub fn with_dictionary(self, dictionary: Dictionary) -> Self {
+        Encoder {
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    /// Encode a source.
+    pub fn encode_source(&self, source: &str) -> Result<Vec<u8>, Error> {
+        let json = self.parser.parse_str(source)?;
+        let mut ast = Program::import(&json)?;
+        binjs_es6::scopes::AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+        ast.walk(&mut WalkPath::new(), &mut binjs_es6::lazy::LazifierVisitor::new(self.lazification))
+            .map_err(|()| Error::without_source(ErrorKind::Annotation))?;
+        let data = binjs_es6::io::Encoder::new()
+            .encode(&mut format(&self.dictionary), &ast)?;
+        let data = (*data).as_ref().to_vec();
+        self.statistics.lock()
+            .unwrap()
+            .add(source.len(), data.len());
+        Ok(data)
+    }
+
+    /// Encode the source at `path`.
+    pub fn encode_file<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>, Error> {
+        let source = std::fs::read_to_string(path)?;
+        self.encode_source(&source)
+    }
+
+    /// The files encoded so far.
+    pub fn statistics(&self) -> Statistics {
+        self.statistics.lock()
+            .unwrap()
+            .clone()
+    }
+}
+
+/// Decoding binaries to JavaScript sources.
+///
+/// Sources are printed with Shift, which requires Node.js and the `shift-codegen` package.
+pub struct Decoder {
+    printer: Shift,
+    dictionary: Option<Dictionary>,
+    statistics: Mutex<Statistics>,
+}
+impl Decoder {
+    /// A decoder from the multipart format.
+    pub fn new() -> Self {
+        Decoder {
+            printer: Shift::new(),
+            dictionary: None,
+            statistics: Mutex::new(Statistics::default()),
+        }
+    }
+
+    /// Decode from the entropy format with `dictionary`, instead of the multipart format.
+    pub fn with_dictionary(self, dictionary: Dictionary) -> Self {
+        Decoder {
+            dictionary: Some(dictionary),
+            ..self
+        }
+    }
+
+    /// Decode a binary to a source.
+    pub fn decode_to_source(&self, data: &[u8]) -> Result<String, Error> {
+        let ast : Program = binjs_es6::io::Decoder::new()
+            .decode(&mut format(&self.dictionary), Cursor::new(data))?;
+
+        let mut builder = binjs_meta::spec::SpecBuilder::new();
+        let _ = binjs_generic::es6::Library::new(&mut builder);
+        let spec_options = binjs_meta::spec::SpecOptions {
+            null: &builder.node_name(""),
+            root: &builder.node_name("Program"),
+        };
+        let spec = builder.into_spec(spec_options);
+        let source = self.printer.to_source(&spec, &ast.export())
+            .map_err(|err| Error::new(ErrorKind::Print, &err))?;
+        self.statistics.lock()
+            .unwrap()
+            .add(source.len(), data.len());
+        Ok(source)
+    }
+
+    /// The files decoded so far.
+    pub fn statistics(&self) -> Statistics {
+        self.statistics.lock()
+            .unwrap()
+            .clone()
+    }
+}
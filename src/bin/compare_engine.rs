@@ -0,0 +1,88 @@
+//! Check that encoding then decoding JavaScript sources preserves their behavior,
+//! by running the original and the roundtripped sources with a JS engine.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate glob;
+
+use binjs::source::Shift;
+use binjs::util::engine::{ compare, Engine };
+
+use std::path::{ Path, PathBuf };
+
+use clap::*;
+
+/// The source files in `path`, recursively if `path` is a directory.
+fn source_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_dir() {
+        let pattern = format!("{}/**/*.js", path.display());
+        glob::glob(&pattern)
+            .expect("Invalid path")
+            .map(|entry| entry.expect("Invalid entry"))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS engine comparison")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Encode and decode JavaScript sources, then check that the original and roundtripped sources behave the same when run with a JavaScript engine.")
+        .args(&[
+            Arg::with_name("in")
+                .long("in")
+                .short("i")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true)
+                .required(true)
+                .help("Input files or directories to use. Directories are searched for .js files recursively."),
+            Arg::with_name("engine")
+                .long("engine")
+                .takes_value(true)
+                .default_value("node")
+                .help("The command used to run a script, e.g. `node`, `d8` or `js`, followed by its options. The path of the script is appended to the command. Scripts may use `print` to produce output, with all engines."),
+            Arg::with_name("keep-going")
+                .long("keep-going")
+                .help("Do not stop at the first file that behaves differently."),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    let engine = Engine::new(matches.value_of("engine")
+        .unwrap()); // Guaranteed by `clap`.
+    let keep_going = matches.is_present("keep-going");
+    let parser = Shift::new();
+
+    println!("Using format: {}", format.name());
+    let mut failures = 0;
+    for path in matches.values_of("in")
+        .unwrap() // Guaranteed by `clap`.
+        .flat_map(|path| source_files(Path::new(path)))
+    {
+        let comparison = compare(&engine, &parser, &mut format, &path)
+            .unwrap_or_else(|err| panic!("Could not compare {:?}: {:?}", path, err));
+        if comparison.is_same() {
+            println!("{:?}: same behavior", path);
+            continue;
+        }
+        failures += 1;
+        println!("{:?}: different behavior", path);
+        println!("Original: {:?}", comparison.original_outcome);
+        println!("Roundtripped: {:?}", comparison.roundtripped_outcome);
+        println!("Roundtripped source:\n{}", comparison.roundtripped);
+        if !keep_going {
+            break;
+        }
+    }
+    if failures > 0 {
+        eprintln!("{} file(s) behave differently after a roundtrip", failures);
+        std::process::exit(1);
+    }
+}
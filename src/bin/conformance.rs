@@ -0,0 +1,264 @@
+//! Check that files produced by another implementation of the BinJS encoder
+//! decode to the same ASTs as their sources, reporting a conformance scorecard.
+
+extern crate binjs;
+#[macro_use]
+extern crate binjs_shared;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::generic::{ FromJSON, Offset };
+use binjs::io::Format;
+use binjs::runner::{ guard, Report };
+use binjs::source::{ Shift, SourceParser, SourceType };
+use binjs::specialized::es6::ast::{ Program, Visitor, Walker, WalkPath };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::io::Cursor;
+use std::path::Path;
+use std::thread;
+
+use clap::*;
+
+/// The result of checking a single pair of files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    /// The binary decodes to the AST of the source and meets all the requested constraints.
+    Pass,
+    /// The binary has no matching source.
+    MissingSource,
+    /// Shift could not parse the source, or the parsed AST could not be imported.
+    Parse,
+    /// The binary could not be decoded.
+    Decode,
+    /// The binary decodes to an AST that differs from the AST of the source.
+    Mismatch,
+    /// With `--max-size-ratio` or `--byte-exact`, this implementation could not encode
+    /// the source, so there is no reference encoding to compare with.
+    Encode,
+    /// With `--max-size-ratio`, the binary is too large compared to the reference encoding.
+    Size,
+    /// With `--byte-exact`, the binary differs from the reference encoding.
+    Bytes,
+    /// The checker itself failed.
+    Panic,
+}
+impl binjs::runner::Outcome for Outcome {
+    fn name(&self) -> &'static str {
+        match *self {
+            Outcome::Pass => "pass",
+            Outcome::MissingSource => "missing-source",
+            Outcome::Parse => "parse",
+            Outcome::Decode => "decode",
+            Outcome::Mismatch => "mismatch",
+            Outcome::Encode => "encode",
+            Outcome::Size => "size",
+            Outcome::Bytes => "bytes",
+            Outcome::Panic => "panic",
+        }
+    }
+    fn panic() -> Self {
+        Outcome::Panic
+    }
+}
+
+/// A visitor designed to reset offsets to 0, as offsets depend on the encoder.
+struct OffsetCleanerVisitor;
+impl Visitor<()> for OffsetCleanerVisitor {
+    fn visit_offset(&mut self, _path: &WalkPath, node: &mut Offset) -> std::result::Result<(), ()> {
+        *node = binjs::generic::Offset(0);
+        Ok(())
+    }
+}
+
+/// The constraints checked for each pair of files.
+struct Options {
+    /// The number of layers of functions lazified by the external encoder.
+    lazification: u32,
+
+    /// If specified, the maximal ratio between the size of the binary and that of
+    /// the reference encoding.
+    max_size_ratio: Option<f64>,
+
+    /// If `true`, the binary must be identical to the reference encoding.
+    byte_exact: bool,
+}
+
+/// Check the binary at `path` against the source with the same name and extension `.js`.
+fn check(parser: &Shift, format: &mut Format, options: &Options, path: &Path) -> Report<Outcome> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(err) => return Report::new(Outcome::Decode)
+            .with_detail("size", 0)
+            .with_error(format!("{:?}", err))
+    };
+    let size = data.len();
+    let source_path = path.with_extension("js");
+    if !source_path.is_file() {
+        return Report::new(Outcome::MissingSource)
+            .with_detail("size", size);
+    }
+
+    let reference = match guard(|| {
+        let json = parser.parse_file(&source_path)
+            .map_err(|err| format!("{:?}", err))?;
+        let mut ast = Program::import(&json)
+            .map_err(|err| format!("{:?}", err))?;
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+        let mut path = WalkPath::new();
+        ast.walk(&mut path, &mut binjs::specialized::es6::lazy::LazifierVisitor::new(options.lazification))
+            .map_err(|err| format!("{:?}", err))?;
+        Ok(ast)
+    }) {
+        Ok(ast) => ast,
+        Err(err) => return Report::new(Outcome::Parse)
+            .with_detail("size", size)
+            .with_error(err)
+    };
+
+    let mut decoded : Program = match guard(|| Decoder::new()
+        .decode(format, Cursor::new(&data))
+        .map_err(|err| format!("{:?}", err)))
+    {
+        Ok(decoded) => decoded,
+        Err(err) => return Report::new(Outcome::Decode)
+            .with_detail("size", size)
+            .with_error(err)
+    };
+    let mut reference_clean = reference.clone();
+    let mut path = WalkPath::new();
+    decoded.walk(&mut path, &mut OffsetCleanerVisitor)
+        .expect("Could not cleanup offsets");
+    reference_clean.walk(&mut path, &mut OffsetCleanerVisitor)
+        .expect("Could not cleanup offsets");
+    if decoded != reference_clean {
+        return Report::new(Outcome::Mismatch)
+            .with_detail("size", size);
+    }
+
+    if options.max_size_ratio.is_none() && !options.byte_exact {
+        return Report::new(Outcome::Pass)
+            .with_detail("size", size);
+    }
+    let reference_data = match guard(|| {
+        let data = Encoder::new()
+            .encode(format, &reference)
+            .map_err(|err| format!("{:?}", err))?;
+        Ok((*data).as_ref().to_vec())
+    }) {
+        Ok(data) => data,
+        Err(err) => return Report::new(Outcome::Encode)
+            .with_detail("size", size)
+            .with_error(format!("Could not encode reference for {:?}: {}", source_path, err))
+    };
+    let mut report = Report::new(Outcome::Pass)
+        .with_detail("size", size)
+        .with_detail("reference_size", reference_data.len());
+    if let Some(ratio) = options.max_size_ratio {
+        if size as f64 > ratio * reference_data.len() as f64 {
+            report.outcome = Outcome::Size;
+            return report;
+        }
+    }
+    if options.byte_exact && data != reference_data {
+        report.outcome = Outcome::Bytes;
+    }
+    report
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS conformance checker")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Check that the files produced by another implementation of the BinJS encoder decode to the ASTs of their sources, reporting a conformance scorecard, as JSON.")
+        .args(&[
+            Arg::with_name("dir")
+                .long("dir")
+                .takes_value(true)
+                .required(true)
+                .help("Directory containing pairs of files NAME.js and NAME.binjs, where NAME.binjs was produced by the external encoder from NAME.js. Subdirectories are also checked."),
+            Arg::with_name("lazify")
+                .long("lazify")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<u32>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Number of layers of functions lazified by the external encoder. 0 = no lazification, 1 = functions at toplevel, 2 = also functions in functions at toplevel, etc."),
+            Arg::with_name("max-size-ratio")
+                .long("max-size-ratio")
+                .takes_value(true)
+                .validator(|s| s.parse::<f64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Also check that each binary is at most this many times as large as the encoding of its source by this implementation, with the same format, e.g. 1.1."),
+            Arg::with_name("byte-exact")
+                .long("byte-exact")
+                .help("Also check that each binary is identical to the encoding of its source by this implementation, with the same format, e.g. for the vectors of `binjs_encode --emit-test-vectors`."),
+            Arg::with_name("out")
+                .long("out")
+                .short("o")
+                .takes_value(true)
+                .help("Write the JSON scorecard to this file. If not specified or `-`, the scorecard is written to stdout."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print the outcome of each file on stderr"),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let root = Path::new(matches.value_of("dir")
+        .unwrap()); // Guaranteed by `clap`.
+    let pattern = format!("{}/**/*.binjs", root.display());
+    let dest_path = matches.value_of("out")
+        .filter(|path| *path != "-");
+    let quiet = matches.is_present("quiet");
+    let options = Options {
+        lazification: matches.value_of("lazify")
+            .unwrap() // Guaranteed by `clap`.
+            .parse()
+            .unwrap(), // Checked by the validator.
+        max_size_ratio: matches.value_of("max-size-ratio")
+            .map(|ratio| ratio.parse()
+                .unwrap()), // Checked by the validator.
+        byte_exact: matches.is_present("byte-exact"),
+    };
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    let parser = Shift::new()
+        .with_source_type(SourceType::Auto);
+
+    let summary = binjs::runner::run(root, &pattern, quiet, |path| {
+        Some(check(&parser, &mut format, &options, path))
+    });
+
+    let total = summary.total();
+    let passed = summary.count(Outcome::Pass);
+    let scorecard = summary.export(object!{
+        "format" => format.name(),
+        "total" => total,
+        "conformance" => if total == 0 { 0. } else { passed as f64 / total as f64 }
+    });
+    binjs::runner::write_summary(&scorecard, dest_path)
+        .expect("Could not write scorecard");
+
+    if passed != total {
+        std::process::exit(1);
+    }
+}
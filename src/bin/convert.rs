@@ -0,0 +1,110 @@
+//! Convert a BinJS from one format to another, without going through the
+//! text source.
+//!
+//! This avoids parsing and annotating sources again, e.g. to migrate a corpus
+//! to a new format.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::generic::io::Transcoder;
+use binjs::io::Format;
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::specialized::es6::io::grammar_id;
+
+use std::fs::*;
+use std::io::*;
+use std::thread;
+
+use clap::*;
+
+macro_rules! progress {
+    ($quiet:expr, $($args:tt)*) => {
+        if !$quiet {
+            println!($($args)*);
+        }
+    }
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+/// Parse the format specified by argument `name`.
+fn format_of_matches(matches: &ArgMatches, name: &str) -> Format {
+    let args : Vec<&str> = matches.value_of(name)
+        .unwrap() // Guaranteed by `clap`.
+        .split_whitespace()
+        .collect();
+    Format::from_args(&args)
+        .unwrap_or_else(|e| panic!("Could not parse --{}: {}", name, e))
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS converter")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Convert a JavaScript BinJS source from one format to another, without going through the text source.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("Input file to use. Must be a BinJS source file."),
+            Arg::with_name("OUTPUT")
+                .required(true)
+                .help("Output file to use. Will be overwritten."),
+            Arg::with_name("from")
+                .long("from")
+                .takes_value(true)
+                .default_value("multipart")
+                .help("The format of INPUT, followed by its options, as for the `advanced` subcommand of `binjs_encode`, e.g. `multipart` or `\"entropy --dictionary foo.dict\"`."),
+            Arg::with_name("to")
+                .long("to")
+                .takes_value(true)
+                .required(true)
+                .help("The format of OUTPUT, followed by its options, as for `--from`."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print progress"),
+        ])
+        .get_matches();
+
+    let source_path = matches.value_of("INPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let dest_path = matches.value_of("OUTPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let quiet = matches.is_present("quiet");
+
+    let mut from = format_of_matches(&matches, "from");
+    let mut to = format_of_matches(&matches, "to");
+    progress!(quiet, "Converting from {} to {}.", from.name(), to.name());
+
+    let source = BufReader::new(File::open(source_path)
+        .expect("Could not open source"));
+    let mut builder = SpecBuilder::new();
+    let _ = binjs::generic::es6::Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Program"),
+    };
+    let spec = builder.into_spec(spec_options);
+
+    let data = Transcoder::new(&spec, grammar_id())
+        .transcode(&mut from, &mut to, source)
+        .expect("Could not convert");
+
+    File::create(dest_path)
+        .and_then(|mut dest| dest.write_all((*data).as_ref()))
+        .expect("Could not write destination file");
+    progress!(quiet, "Wrote {} bytes.", (*data).as_ref().len());
+}
@@ -0,0 +1,134 @@
+//! Encode a JSON AST (Shift or ESTree) to a BinJS.
+//!
+//! This makes it possible to plug the encoder at the end of an existing
+//! JavaScript pipeline (e.g. Babel), without having to go through the
+//! Shift parser embedded in `binjs_encode`.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate serde_json;
+
+use binjs::generic::FromJSON;
+use binjs::source::{ FromESTree, Shift };
+use binjs::specialized::es6::io::Encoder;
+
+use std::fs::*;
+use std::io::*;
+use std::thread;
+
+use clap::*;
+
+macro_rules! progress {
+    ($quiet:expr, $($args:tt)*) => {
+        if !$quiet {
+            println!($($args)*);
+        }
+    }
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS JSON encoder")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Encode a JavaScript AST, represented as JSON, to a JavaScript binary source in the BinJS format.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .help("Input file to use. Must be a JSON file. If not specified, stdin is used"),
+            Arg::with_name("OUTPUT")
+                .help("Output file to use. Will be overwritten. If not specified, stdout is used"),
+            Arg::with_name("flavor")
+                .long("flavor")
+                .takes_value(true)
+                .possible_values(&["auto", "shift", "estree"])
+                .default_value("auto")
+                .help("The flavor of JSON AST. `shift` is the format produced by shift-parser, `estree` is the format produced by acorn, babel, esprima, etc. `auto` detects the flavor from the root of the AST."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print progress"),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let source_path = matches.value_of("INPUT");
+    let dest_path = matches.value_of("OUTPUT");
+    let quiet = matches.is_present("quiet") || dest_path.is_none();
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    progress!(quiet, "Using format: {}", format.name());
+
+    progress!(quiet, "Reading.");
+    let mut source = String::new();
+    match source_path {
+        Some(path) => {
+            File::open(path)
+                .expect("Could not open source")
+                .read_to_string(&mut source)
+                .expect("Could not read source");
+        }
+        None => {
+            stdin().read_to_string(&mut source)
+                .expect("Failed to read from stdin");
+        }
+    }
+    let mut json : binjs::generic::JSON = serde_json::from_str(&source)
+        .expect("Could not parse JSON");
+
+    let is_estree = match matches.value_of("flavor") {
+        Some("shift") => false,
+        Some("estree") => true,
+        _ => json["type"] == "Program"
+    };
+    if is_estree {
+        progress!(quiet, "Converting from ESTree.");
+        json = FromESTree.convert(json)
+            .expect("Could not convert from ESTree");
+    }
+    Shift::new()
+        .convert_shift_json(&mut json)
+        .expect("Could not convert from Shift");
+
+    let mut ast = match binjs::specialized::es6::ast::Program::import(&json) {
+        Ok(ast) => ast,
+        Err(err) => {
+            eprintln!("Could not import AST: {}", err);
+            std::process::exit(1);
+        }
+    };
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+
+    progress!(quiet, "Encoding.");
+    let encoder = Encoder::new();
+    let data = encoder.encode(&mut format, &ast)
+        .expect("Could not encode");
+
+    progress!(quiet, "Writing.");
+    match dest_path {
+        Some(path) => {
+            let mut dest = File::create(path)
+                .expect("Could not create destination file");
+            dest.write((*data).as_ref())
+                .expect("Could not write destination file");
+        }
+        None => {
+            stdout().write((*data).as_ref())
+                .expect("Could not write to stdout");
+        }
+    }
+}
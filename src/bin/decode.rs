@@ -4,14 +4,15 @@ extern crate binjs;
 extern crate clap;
 extern crate env_logger;
 
-use binjs::generic::ToJSON;
+use binjs::generic::{ JSONExt, ToJSON };
 use binjs::specialized::es6::io::Decoder;
-use binjs::source::Shift;
+use binjs::specialized::es6::scope_checks::ScopeCheckPolicy;
+use binjs::source::{ Shift, ToESTree };
 
 use std::fs::*;
 use std::io::*;
 
-use clap::*;
+use clap::{ App, Arg, ArgMatches };
 
 macro_rules! progress {
     ($quiet:expr, $($args:tt)*) => {
@@ -29,6 +30,27 @@ struct Options<'a> {
     /// The OUTPUT path, or None if not specified.
     dest_path: Option<&'a str>,
 
+    /// If specified, the flavor of JSON to write to OUTPUT
+    /// (one of "shift", "estree", "internal") instead of
+    /// pretty-printed JavaScript source.
+    output_json: Option<&'a str>,
+
+    /// If specified, the entry of the archive to decode.
+    entry: Option<&'a str>,
+
+    /// True if --source-positions is specified.
+    source_positions: bool,
+
+    /// If specified, the file to which the symbols read are written,
+    /// in the folded stacks format.
+    profile: Option<&'a str>,
+
+    /// If specified, the number of threads used to decode lazy functions.
+    decode_jobs: Option<usize>,
+
+    /// What to do if the scope annotations of the AST are inconsistent.
+    scope_checks: ScopeCheckPolicy,
+
     /// The format used to decode.
     ///
     /// The decoder will not attempt to sniff the format used.
@@ -43,9 +65,9 @@ fn main() {
         .about("Decode a JavaScript BinJS source to a JavaScript text source.")
         .args(&[
             Arg::with_name("INPUT")
-                .help("Input file to use. Must be a BinJS source file. If not specified, stdin is used"),
+                .help("Input file to use. Must be a BinJS source file. If not specified or `-`, stdin is used"),
             Arg::with_name("OUTPUT")
-                .help("Output file to use. Will be overwritten. If not specified, stdout is used"),
+                .help("Output file to use. Will be overwritten. If not specified or `-`, stdout is used"),
             Arg::with_name("dump")
                 .long("dump")
                 .takes_value(false)
@@ -57,80 +79,245 @@ fn main() {
             Arg::with_name("print-json")
                 .long("print-json")
                 .help("Print JSON of parse tree"),
+            Arg::with_name("output-json")
+                .long("output-json")
+                .takes_value(true)
+                .possible_values(&["shift", "estree", "internal"])
+                .help("Write the decoded AST as JSON, in the given flavor, instead of JavaScript source. `shift` and `estree` are the formats used by the Shift and ESTree tooling, `internal` is the AST used by BinJS."),
+            Arg::with_name("entry")
+                .long("entry")
+                .takes_value(true)
+                .help("If INPUT is an archive, the path of the entry to decode. Multipart format only."),
+            Arg::with_name("verify-key")
+                .long("verify-key")
+                .takes_value(true)
+                .help("File containing an Ed25519 public key, hex-encoded. If specified, refuse to decode files that are not signed with the matching secret key. Multipart format only."),
+            Arg::with_name("encryption-key")
+                .long("encryption-key")
+                .takes_value(true)
+                .help("File containing an AES-256 key, hex-encoded. Required to decode encrypted files. Multipart format only."),
+            Arg::with_name("section")
+                .long("section")
+                .takes_value(true)
+                .possible_values(&binjs::io::multipart::SECTION_NAMES)
+                .help("Instead of decoding the file, write a listing of the decompressed contents of a single section to OUTPUT. Multipart format only."),
+            Arg::with_name("raw")
+                .long("raw")
+                .requires("section")
+                .help("With --section, write the bytes of the section as stored in the file, i.e. compressed, instead of a listing."),
+            Arg::with_name("source-positions")
+                .long("source-positions")
+                .requires("output-json")
+                .conflicts_with("entry")
+                .help("With --output-json internal, reattach the source positions and comments stored by `binjs_encode --source-positions`, as fields `loc` of nodes and `comments` of the root. Multipart format only."),
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["entry", "source-positions"])
+                .help("Write the number of symbols read while decoding, by path in the AST and kind of symbol, to FILE, in the folded stacks format used by flame graph tools. Requires building with `--features profiling`."),
+            Arg::with_name("decode-jobs")
+                .long("decode-jobs")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with_all(&["entry", "profile"])
+                .validator(|s| s.parse::<usize>()
+                    .map_err(|e| format!("Invalid number {}", e))
+                    .and_then(|jobs| if jobs > 0 { Ok(()) } else { Err("Expected at least one job".to_string()) }))
+                .help("Decode the contents of lazy functions with N threads, then stitch them into the AST. Multipart format only, other formats are decoded sequentially."),
+            Arg::with_name("scope-checks")
+                .long("scope-checks")
+                .takes_value(true)
+                .possible_values(ScopeCheckPolicy::NAMES)
+                .default_value("ignore")
+                .help("Check that the scope annotations of the AST are consistent, e.g. that no name is declared twice in the same scope. `warn` logs each inconsistency, `reject` fails to decode. Not supported with --profile."),
         ])
         .subcommand(binjs::io::Format::subcommand())
         .get_matches();
 
+    if let Err(err) = run(&matches) {
+        eprintln!("{:?}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches) -> std::result::Result<(), binjs::Error> {
     // Common options.
-    let source_path = matches.value_of("INPUT");
-    let dest_path = matches.value_of("OUTPUT");
+    let source_path = matches.value_of("INPUT")
+        .filter(|path| *path != "-");
+    let dest_path = matches.value_of("OUTPUT")
+        .filter(|path| *path != "-");
     let quiet = matches.is_present("quiet") || dest_path.is_none();
 
     // Format options.
-    let format = binjs::io::Format::from_matches(&matches)
+    let mut format = binjs::io::Format::from_matches(matches)
         .expect("Could not parse encoding format");
     progress!(quiet, "Using format: {}", format.name());
 
+    if let Some(path) = matches.value_of("verify-key") {
+        let key = binjs::io::bytes::signature::read_key(path)?;
+        format.multipart_options_mut()
+            .expect("Signatures are only supported by the multipart format")
+            .integrity.verify_key = Some(key);
+    }
+
+    if let Some(path) = matches.value_of("encryption-key") {
+        let key = binjs::io::bytes::signature::read_key(path)?;
+        format.multipart_options_mut()
+            .expect("Encryption is only supported by the multipart format")
+            .integrity.encryption_key = Some(key);
+    }
+
+    if let Some(name) = matches.value_of("section") {
+        progress!(quiet, "Extracting section {}.", name);
+        let options = format.multipart_options_mut()
+            .expect("Sections are only supported by the multipart format")
+            .clone();
+        let mut buffer = Vec::new();
+        match source_path {
+            Some(path) => File::open(path)?
+                .read_to_end(&mut buffer),
+            None => stdin().read_to_end(&mut buffer)
+        }?;
+        let section = binjs::io::multipart::TreeTokenReader::section(Cursor::new(&buffer), &options, name)?;
+        let bytes = if matches.is_present("raw") {
+            section.raw
+        } else {
+            section.listing.into_bytes()
+        };
+        write_output(dest_path, &bytes)?;
+        return Ok(());
+    }
+
     // Setup.
     let mut options = Options {
         print_json: matches.is_present("print-json"),
         dest_path,
+        output_json: matches.value_of("output-json"),
+        entry: matches.value_of("entry"),
+        source_positions: matches.is_present("source-positions"),
+        profile: matches.value_of("profile"),
+        decode_jobs: matches.value_of("decode-jobs")
+            .map(|jobs| jobs.parse()
+                .unwrap()), // Checked by the validator.
+        scope_checks: matches.value_of("scope-checks")
+            .and_then(ScopeCheckPolicy::parse)
+            .unwrap_or_default(),
         format,
     };
+    if options.source_positions && options.output_json != Some("internal") {
+        panic!("--source-positions requires --output-json internal");
+    }
+    if options.profile.is_some() && !cfg!(feature = "profiling") {
+        panic!("--profile requires building with `--features profiling`");
+    }
 
     progress!(quiet, "Reading.");
-    let tree : binjs::specialized::es6::ast::Script = match source_path {
+    let (tree, positions) : (binjs::specialized::es6::ast::Program, _) = match source_path {
         Some(path) => {
-            parse_tree(&|| BufReader::new(File::open(path)
-                                          .expect("Could not open source")),
-                       &mut options)
+            let file = File::open(path)?;
+            parse_tree(BufReader::new(file), &mut options)?
         }
         None => {
             let mut buffer = Vec::new();
-            stdin().read_to_end(&mut buffer)
-                .expect("Failed to read from stdin");
+            stdin().read_to_end(&mut buffer)?;
 
-            parse_tree(&|| Cursor::new(&buffer), &mut options)
+            parse_tree(Cursor::new(&buffer), &mut options)?
         }
     };
 
-    let json = tree.export();
+    let mut json = tree.export();
+    if let (true, Some(positions)) = (options.source_positions, positions) {
+        progress!(quiet, "Reattaching source positions.");
+        binjs::source::positions::reattach(&mut json, &positions)?;
+    }
     if options.print_json {
         progress!(quiet, "Printing to screen...");
         let pretty = json.pretty(2);
         println!("{}", pretty);
     }
 
-    progress!(quiet, "Pretty-printing");
     let mut builder = binjs::meta::spec::SpecBuilder::new();
     let _ = binjs::generic::es6::Library::new(&mut builder);
     let spec_options = binjs::meta::spec::SpecOptions {
         null: &builder.node_name(""),
-        root: &builder.node_name("Script"),
+        root: &builder.node_name("Program"),
     };
     let spec = builder.into_spec(spec_options);
     let printer = Shift::new();
-    let source = printer.to_source(&spec, &json)
-        .expect("Could not pretty-print");
+
+    let source = match options.output_json {
+        None => {
+            progress!(quiet, "Pretty-printing");
+            printer.to_source(&spec, &json)
+                .map_err(|err| binjs::Error::new(binjs::ErrorKind::Print, &err))?
+        }
+        Some("internal") => {
+            progress!(quiet, "Exporting JSON");
+            json.pretty(2)
+        }
+        Some(flavor) => {
+            progress!(quiet, "Converting to {} JSON", flavor);
+            let mut converted = printer.to_shift_json(&spec, &json)
+                .map_err(|err| binjs::Error::new(binjs::ErrorKind::Print, &err))?;
+            if flavor == "estree" {
+                ToESTree.convert(&mut converted)
+                    .map_err(|err| binjs::Error::new(binjs::ErrorKind::Print, &err))?;
+            }
+            converted.pretty(2)
+        }
+    };
 
     progress!(quiet, "Writing.");
-    match options.dest_path {
+    write_output(options.dest_path, source.as_bytes())?;
+    Ok(())
+}
+
+/// Write `bytes` to `dest_path`, or to stdout if not specified.
+fn write_output(dest_path: Option<&str>, bytes: &[u8]) -> std::result::Result<(), binjs::Error> {
+    match dest_path {
         Some(path) => {
-            let mut dest = File::create(path)
-                .expect("Could not create destination file");
-            dest.write(source.as_bytes())
-                .expect("Could not write destination file");
+            let mut dest = File::create(path)?;
+            dest.write_all(bytes)?;
         }
         None => {
-            stdout().write(source.as_bytes())
-                .expect("Could not write destination file");
+            let stdout = stdout();
+            let mut lock = stdout.lock();
+            lock.write_all(bytes)
+                .and_then(|_| lock.flush())?;
         }
     }
+    Ok(())
 }
 
-fn parse_tree<R: Read + Seek>(get_stream: &Fn() -> R, options: &mut Options) -> binjs::specialized::es6::ast::Script
+/// Decode a tree from `stream`, with its source positions, if any.
+fn parse_tree<R: Read + Seek>(stream: R, options: &mut Options) -> std::result::Result<(binjs::specialized::es6::ast::Program, Option<binjs::io::positions::SourcePositions>), binjs::Error>
 {
-    let decoder = Decoder::new();
-    decoder.decode(&mut options.format, get_stream())
-        .expect("Could not decode")
+    let decoder = Decoder::new()
+        .with_scope_checks(options.scope_checks);
+    if let Some(path) = options.profile {
+        return Ok((parse_tree_profiled(&decoder, stream, &mut options.format, path)?, None));
+    }
+    if let Some(jobs) = options.decode_jobs {
+        return decoder.decode_parallel(&mut options.format, stream, jobs);
+    }
+    match options.entry {
+        None => decoder.decode_with_positions(&mut options.format, stream),
+        Some(entry) => decoder.decode_entry(&mut options.format, stream, entry)
+            .map(|tree| (tree, None))
+    }
+}
+
+/// Decode a tree, writing the symbols read to `dest_path`.
+#[cfg(feature = "profiling")]
+fn parse_tree_profiled<R: Read + Seek>(decoder: &Decoder, stream: R, format: &mut binjs::io::Format, dest_path: &str) -> Result<binjs::specialized::es6::ast::Program, binjs::Error> {
+    let (tree, profile) = decoder.decode_profiled(format, stream)?;
+    let mut dest = File::create(dest_path)?;
+    profile.write_folded(&mut dest)?;
+    Ok(tree)
+}
+
+#[cfg(not(feature = "profiling"))]
+fn parse_tree_profiled<R: Read + Seek>(_: &Decoder, _: R, _: &mut binjs::io::Format, _: &str) -> std::result::Result<binjs::specialized::es6::ast::Program, binjs::Error> {
+    unreachable!() // Checked in `run`.
 }
@@ -0,0 +1,133 @@
+//! Compute a delta between two versions of a BinJS, or apply such a delta.
+//!
+//! This lets a server ship small updates when a website revs its bundle,
+//! instead of the entire new version.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::delta::Delta;
+use binjs::io::entropy::adaptive;
+use binjs::specialized::es6::ast::Program;
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::fs::*;
+use std::io::*;
+use std::thread;
+
+use clap::*;
+
+macro_rules! progress {
+    ($quiet:expr, $($args:tt)*) => {
+        if !$quiet {
+            println!($($args)*);
+        }
+    }
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn read_tree(format: &mut binjs::io::Format, path: &str) -> Program {
+    let source = BufReader::new(File::open(path)
+        .unwrap_or_else(|e| panic!("Could not open {}: {:?}", path, e)));
+    Decoder::new()
+        .decode(format, source)
+        .unwrap_or_else(|e| panic!("Could not decode {}: {:?}", path, e))
+}
+
+fn write_file(path: &str, data: &[u8]) {
+    let mut dest = File::create(path)
+        .unwrap_or_else(|e| panic!("Could not create destination file {}: {:?}", path, e));
+    dest.write_all(data)
+        .expect("Could not write destination file");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS delta")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Compute a delta between two versions of a JavaScript BinJS source, or apply such a delta.")
+        .args(&[
+            Arg::with_name("OLD")
+                .required(true)
+                .help("The old version. Must be a BinJS source file."),
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("The new version (BinJS source file) or, with --apply, the delta."),
+            Arg::with_name("OUTPUT")
+                .required(true)
+                .help("The delta or, with --apply, the new version. Will be overwritten."),
+            Arg::with_name("apply")
+                .long("apply")
+                .help("Apply the delta INPUT to OLD, instead of computing a delta."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print progress"),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let old_path = matches.value_of("OLD")
+        .unwrap(); // Guaranteed by `clap`.
+    let input_path = matches.value_of("INPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let output_path = matches.value_of("OUTPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let quiet = matches.is_present("quiet");
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    progress!(quiet, "Using format: {}", format.name());
+
+    // Deltas are always entropy coded, with the options of the format if it is
+    // itself the adaptive entropy format.
+    let options = match format {
+        binjs::io::Format::AdaptiveEntropy { ref options } => options.clone(),
+        _ => adaptive::Options::default(),
+    };
+    let delta = Delta::new(options);
+
+    progress!(quiet, "Reading {}.", old_path);
+    let old = read_tree(&mut format, old_path);
+
+    if matches.is_present("apply") {
+        progress!(quiet, "Reading delta {}.", input_path);
+        let mut data = vec![];
+        File::open(input_path)
+            .expect("Could not open delta")
+            .read_to_end(&mut data)
+            .expect("Could not read delta");
+
+        progress!(quiet, "Applying delta.");
+        let new = delta.apply(&old, &data)
+            .expect("Could not apply delta");
+
+        progress!(quiet, "Encoding.");
+        let data = Encoder::new()
+            .encode(&mut format, &new)
+            .expect("Could not encode");
+        write_file(output_path, (*data).as_ref());
+    } else {
+        progress!(quiet, "Reading {}.", input_path);
+        let new = read_tree(&mut format, input_path);
+
+        progress!(quiet, "Computing delta.");
+        let data = delta.diff(&old, &new)
+            .expect("Could not compute delta");
+        progress!(quiet, "Delta: {} bytes.", data.len());
+        write_file(output_path, &data);
+    }
+}
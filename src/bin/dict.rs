@@ -0,0 +1,794 @@
+//! Inspect the contents of an entropy dictionary, compare two dictionaries,
+//! prune a dictionary to a size budget, merge dictionaries with weights,
+//! suggest additions to a dictionary from the strings of a corpus that are
+//! missing from it, or train a dictionary while measuring how well it
+//! compresses files outside of its training set.
+
+extern crate binjs;
+extern crate bincode;
+extern crate clap;
+extern crate env_logger;
+extern crate rand;
+
+use binjs::generic::FromJSON;
+use binjs::io::{ Path as IOPath, TokenSerializer };
+use binjs::io::entropy;
+use binjs::io::entropy::dictionary::{ ContextInformation, Dictionary, DictionaryBuilder, Instances, KindedStringMap, PathPredict, WindowPredict };
+use binjs::io::entropy::probabilities::InstancesToProbabilities;
+use binjs::source::{ Shift, SourceParser, SourceType };
+use binjs::specialized::es6::ast::{ Program, Script };
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::fs::File;
+use std::hash::Hash;
+use std::path::{ Path, PathBuf };
+
+use clap::{ App, AppSettings, Arg, ArgMatches, SubCommand };
+
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand::rngs::StdRng;
+
+/// The names of the tables of a dictionary.
+const TABLES : [&'static str; 12] = [
+    "bool_by_path",
+    "float_by_path",
+    "unsigned_long_by_path",
+    "string_enum_by_path",
+    "property_key_by_path",
+    "property_key_by_window",
+    "identifier_name_by_path",
+    "identifier_name_by_window",
+    "interface_name_by_path",
+    "string_literal_by_path",
+    "string_literal_by_window",
+    "list_length_by_path",
+];
+
+/// Invoke `$action!(name, field)` for each table of a dictionary.
+macro_rules! with_tables {
+    ($action:ident) => {
+        $action!("bool_by_path", bool_by_path);
+        $action!("float_by_path", float_by_path);
+        $action!("unsigned_long_by_path", unsigned_long_by_path);
+        $action!("string_enum_by_path", string_enum_by_path);
+        $action!("property_key_by_path", property_key_by_path);
+        $action!("property_key_by_window", property_key_by_window);
+        $action!("identifier_name_by_path", identifier_name_by_path);
+        $action!("identifier_name_by_window", identifier_name_by_window);
+        $action!("interface_name_by_path", interface_name_by_path);
+        $action!("string_literal_by_path", string_literal_by_path);
+        $action!("string_literal_by_window", string_literal_by_window);
+        $action!("list_length_by_path", list_length_by_path);
+    }
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.
+    } else {
+        100. * part as f64 / total as f64
+    }
+}
+
+fn count(instances: &Instances) -> usize {
+    Into::<usize>::into(*instances)
+}
+
+/// A summary of the contents of a table.
+struct Summary {
+    /// The number of distinct states, e.g. (path, value) pairs.
+    states: usize,
+
+    /// The number of symbols in the sample used to build the table.
+    symbols: usize,
+
+    /// The average entropy, in bits per symbol.
+    entropy: f64,
+}
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{} states, {} symbols, {:.2} bits/symbol", self.states, self.symbols, self.entropy)
+    }
+}
+
+/// Inspecting a table of a dictionary.
+trait Inspect {
+    fn summary(&self) -> Summary;
+
+    /// Print the contents of the table, with at most `limit` values per context.
+    fn show(&self, limit: usize);
+
+    /// Print at most `limit` differences between this table and `other`.
+    fn diff(&self, other: &Self, limit: usize);
+}
+
+impl<V> Inspect for PathPredict<V, Instances> where V: Eq + Hash + Clone + Ord + Debug {
+    fn summary(&self) -> Summary {
+        let mut states = 0;
+        let mut symbols = 0;
+        let mut bits = 0.;
+        for (_, info) in self.iter() {
+            let total = info.total();
+            states += info.len();
+            symbols += total;
+            bits += info.entropy() * total as f64;
+        }
+        Summary {
+            states,
+            symbols,
+            entropy: if symbols == 0 { 0. } else { bits / symbols as f64 },
+        }
+    }
+
+    fn show(&self, limit: usize) {
+        println!("  depth {}", self.depth());
+        let mut paths : Vec<_> = self.iter().collect();
+        paths.sort_by_key(|&(_, info)| Reverse(info.total()));
+        for (path, info) in paths {
+            let total = info.total();
+            println!("  {:?}: {} symbols, {:.2} bits/symbol", path, total, info.entropy());
+            let mut values : Vec<_> = info.iter()
+                .map(|(value, instances)| (value, count(instances)))
+                .collect();
+            values.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            for &(value, instances) in values.iter().take(limit) {
+                println!("    {:?}: {} ({:.2}%)", value, instances, percent(instances, total));
+            }
+            if values.len() > limit {
+                println!("    ... ({} more)", values.len() - limit);
+            }
+        }
+    }
+
+    fn diff(&self, other: &Self, limit: usize) {
+        if self.depth() != other.depth() {
+            println!("  depth {} -> {}", self.depth(), other.depth());
+        }
+        let paths : HashSet<_> = self.iter()
+            .chain(other.iter())
+            .map(|(path, _)| path)
+            .collect();
+        let mut changes = vec![];
+        for path in paths {
+            let before = self.get(path);
+            let after = other.get(path);
+            let values : HashSet<&V> = before.into_iter()
+                .chain(after.into_iter())
+                .flat_map(|info| info.iter().map(|(value, _)| value))
+                .collect();
+            let instances = |info: Option<&ContextInformation<V, Instances>>, value: &V| {
+                info.and_then(|info| info.get(value))
+                    .map_or(0, count)
+            };
+            for value in values {
+                let old = instances(before, value);
+                let new = instances(after, value);
+                if old != new {
+                    changes.push((path, value, old, new));
+                }
+            }
+        }
+        changes.sort_by_key(|&(_, _, old, new)| Reverse(if old > new { old - new } else { new - old }));
+        for &(path, value, old, new) in changes.iter().take(limit) {
+            let sign =
+                if old == 0 { "+" }
+                else if new == 0 { "-" }
+                else { "~" };
+            println!("  {} {:?} {:?}: {} -> {}", sign, path, value, old, new);
+        }
+        if changes.len() > limit {
+            println!("  ... ({} more changes)", changes.len() - limit);
+        }
+    }
+}
+
+impl<V> Inspect for WindowPredict<V, Instances> where V: Eq + Hash + Clone + Ord + Debug {
+    fn summary(&self) -> Summary {
+        let info = self.info();
+        Summary {
+            states: info.len(),
+            symbols: info.total(),
+            entropy: info.entropy(),
+        }
+    }
+
+    fn show(&self, limit: usize) {
+        let info = self.info();
+        let total = info.total();
+        let back_references = info.iter()
+            .filter(|&(prediction, _)| prediction.is_back_reference())
+            .map(|(_, instances)| count(instances))
+            .sum();
+        println!("  width {}, {} values in dictionary, {:.2}% back references",
+            self.width(),
+            self.values().len(),
+            percent(back_references, total));
+        let mut predictions : Vec<_> = info.iter()
+            .map(|(prediction, instances)| (prediction, count(instances)))
+            .collect();
+        predictions.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        for &(prediction, instances) in predictions.iter().take(limit) {
+            println!("    {}: {} ({:.2}%)", prediction, instances, percent(instances, total));
+        }
+        if predictions.len() > limit {
+            println!("    ... ({} more)", predictions.len() - limit);
+        }
+        println!("  values:");
+        for (index, value) in self.values().iter().enumerate().take(limit) {
+            println!("    {}: {:?}", index, value);
+        }
+        if self.values().len() > limit {
+            println!("    ... ({} more)", self.values().len() - limit);
+        }
+    }
+
+    fn diff(&self, other: &Self, limit: usize) {
+        if self.width() != other.width() {
+            println!("  width {} -> {}", self.width(), other.width());
+        }
+        let before : HashSet<&V> = self.values().iter().collect();
+        let after : HashSet<&V> = other.values().iter().collect();
+        let mut added : Vec<_> = after.difference(&before).collect();
+        let mut removed : Vec<_> = before.difference(&after).collect();
+        added.sort();
+        removed.sort();
+        println!("  {} values added, {} values removed", added.len(), removed.len());
+        for value in added.iter().take(limit) {
+            println!("  + {:?}", value);
+        }
+        for value in removed.iter().take(limit) {
+            println!("  - {:?}", value);
+        }
+    }
+}
+
+fn load(path: &str) -> Dictionary<Instances> {
+    let source = File::open(path)
+        .unwrap_or_else(|e| panic!("Could not open dictionary {}: {:?}", path, e));
+    bincode::deserialize_from(source)
+        .unwrap_or_else(|e| panic!("Could not decode dictionary {}: {:?}", path, e))
+}
+
+fn show(dictionary: &Dictionary<Instances>, category: Option<&str>, limit: usize) {
+    macro_rules! show_table {
+        ($name:expr, $field:ident) => {
+            if category.map_or(true, |category| category == $name) {
+                println!("{}: {}", $name, dictionary.$field.summary());
+                dictionary.$field.show(limit);
+            }
+        }
+    }
+    with_tables!(show_table);
+}
+
+fn summary(dictionary: &Dictionary<Instances>) {
+    let mut bits = 0.;
+    macro_rules! summarize_table {
+        ($name:expr, $field:ident) => {
+            let summary = dictionary.$field.summary();
+            println!("{}: {}", $name, summary);
+            // Window tables and path tables predict the same symbols.
+            if !$name.ends_with("_by_window") {
+                bits += summary.entropy * summary.symbols as f64;
+            }
+        }
+    }
+    with_tables!(summarize_table);
+    println!("Estimated size of the sample with path prediction: {:.0} bytes", bits / 8.);
+}
+
+fn diff(before: &Dictionary<Instances>, after: &Dictionary<Instances>, category: Option<&str>, limit: usize) {
+    macro_rules! diff_table {
+        ($name:expr, $field:ident) => {
+            if category.map_or(true, |category| category == $name) {
+                println!("{}:\n  {}\n  {}", $name, before.$field.summary(), after.$field.summary());
+                before.$field.diff(&after.$field, limit);
+            }
+        }
+    }
+    with_tables!(diff_table);
+}
+
+/// Collect the source files in `path` with one of `extensions`, recursively.
+fn collect_sources(path: &Path, extensions: &[&str], sources: &mut Vec<PathBuf>) {
+    if path.is_dir() {
+        let entries = std::fs::read_dir(path)
+            .unwrap_or_else(|e| panic!("Could not open directory {:?}: {:?}", path, e));
+        for entry in entries {
+            let entry = entry.expect("Could not read directory entry");
+            collect_sources(&entry.path(), extensions, sources);
+        }
+    } else if let Some(Some(extension)) = path.extension().map(std::ffi::OsStr::to_str) {
+        if extensions.contains(&extension) {
+            sources.push(path.to_path_buf());
+        }
+    }
+}
+
+/// Parse and annotate a JS source file.
+fn parse(parser: &Shift, source: &Path) -> Script {
+    let json = parser.parse_file(source)
+        .unwrap_or_else(|e| panic!("Could not parse {:?}: {:?}", source, e));
+    let mut ast = Script::import(&json)
+        .unwrap_or_else(|e| panic!("Could not import AST of {:?}: {:?}", source, e));
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+    ast
+}
+
+/// Parse and annotate a JS script or module, as `parser` detects it.
+fn parse_program(parser: &Shift, source: &Path) -> Result<Program, String> {
+    let json = parser.parse_file(source)
+        .map_err(|e| format!("Could not parse: {:?}", e))?;
+    let mut ast = Program::import(&json)
+        .map_err(|e| format!("Could not import AST: {:?}", e))?;
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+    Ok(ast)
+}
+
+/// Encode `sources` with the fallback section enabled and print the suggestions.
+///
+/// Files that cannot be parsed or encoded are skipped and reported, they don't
+/// contribute to the suggestions.
+fn suggest(dictionary: &Dictionary<Instances>, sources: &[PathBuf], source_type: SourceType, limit: usize) {
+    let options = entropy::Options::new(dictionary.clone().instances_to_probabilities("dictionary"))
+        .with_fallback();
+    let parser = Shift::new()
+        .with_source_type(source_type);
+    let mut failures = 0;
+    for source in sources {
+        let result = parse_program(&parser, source)
+            .and_then(|ast| {
+                // Statistics are only recorded once the file is done.
+                let encoder = entropy::write::Encoder::new(options.clone());
+                let mut serializer = binjs::specialized::es6::io::Serializer::new(encoder);
+                serializer.serialize(&ast, &mut IOPath::new())
+                    .map_err(|e| format!("Could not encode: {:?}", e))?;
+                serializer.done()
+                    .map_err(|e| format!("Could not encode: {:?}", e))?;
+                Ok(())
+            });
+        if let Err(err) = result {
+            eprintln!("Skipping {:?}: {}", source, err);
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        eprintln!("Skipped {} of {} files.", failures, sources.len());
+    }
+
+    let statistics = options.fallback_statistics_for_write()
+        .unwrap(); // Guaranteed by `with_fallback`.
+    print!("{}", statistics);
+    let suggestions = statistics.suggestions(dictionary);
+    for suggestion in suggestions.iter().take(limit) {
+        println!("  {:.0} bytes: {} {:?} {:?} ({} instances in {} files)",
+            suggestion.savings,
+            suggestion.fallback.kind.table(),
+            suggestion.fallback.path,
+            suggestion.fallback.value,
+            suggestion.instances.instances,
+            suggestion.instances.files);
+    }
+    if suggestions.len() > limit {
+        println!("  ... ({} more)", suggestions.len() - limit);
+    }
+}
+
+/// Build a dictionary from `sources`.
+fn build(sources: &[PathBuf], depth: usize, width: usize) -> Dictionary<Instances> {
+    let parser = Shift::new();
+    let mut dictionary = Dictionary::new(depth, width);
+    let mut files_containing_string = KindedStringMap::default();
+    for source in sources {
+        let ast = parse(&parser, source);
+        let builder = DictionaryBuilder::new(&mut dictionary, &mut files_containing_string);
+        let mut serializer = binjs::specialized::es6::io::Serializer::new(builder);
+        serializer.serialize(&ast, &mut IOPath::new())
+            .unwrap_or_else(|e| panic!("Could not sample {:?}: {:?}", source, e));
+        serializer.done()
+            .unwrap_or_else(|e| panic!("Could not sample {:?}: {:?}", source, e));
+    }
+    dictionary
+}
+
+/// The size of a corpus, once encoded with a dictionary.
+#[derive(Default)]
+struct CorpusSize {
+    /// The number of files encoded.
+    files: usize,
+
+    /// The number of files that could not be encoded, as they contain
+    /// values missing from the dictionary, other than strings.
+    failures: usize,
+
+    /// The size of the sources of the files encoded, in bytes.
+    source_bytes: u64,
+
+    /// The size of the files encoded, in bytes.
+    encoded_bytes: u64,
+
+    /// The number of strings of the files encoded.
+    strings: usize,
+
+    /// The number of strings missing from the dictionary, written to the
+    /// fallback section of their file.
+    fallbacks: usize,
+}
+impl std::fmt::Display for CorpusSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let ratio =
+            if self.source_bytes == 0 {
+                0.
+            } else {
+                100. * self.encoded_bytes as f64 / self.source_bytes as f64
+            };
+        write!(f, "{} files, {} => {} bytes ({:.2}%), {} of {} strings missing from the dictionary ({:.2}%), {} files could not be encoded",
+            self.files,
+            self.source_bytes,
+            self.encoded_bytes,
+            ratio,
+            self.fallbacks,
+            self.strings,
+            percent(self.fallbacks, self.strings),
+            self.failures)
+    }
+}
+
+/// Encode `sources` with `dictionary`, writing missing strings to fallback sections.
+fn evaluate(dictionary: &Dictionary<Instances>, sources: &[PathBuf]) -> CorpusSize {
+    let options = entropy::Options::new(dictionary.clone().instances_to_probabilities("dictionary"))
+        .with_fallback();
+    let parser = Shift::new();
+    let mut result = CorpusSize::default();
+    for source in sources {
+        let ast = parse(&parser, source);
+        let encoder = entropy::write::Encoder::new(options.clone());
+        let mut serializer = binjs::specialized::es6::io::Serializer::new(encoder);
+        let encoded = serializer.serialize(&ast, &mut IOPath::new())
+            .and_then(|_| serializer.done());
+        match encoded {
+            Ok(data) => {
+                result.files += 1;
+                result.encoded_bytes += data.len() as u64;
+                result.source_bytes += std::fs::metadata(source)
+                    .unwrap_or_else(|e| panic!("Could not read {:?}: {:?}", source, e))
+                    .len();
+            }
+            Err(e) => {
+                eprintln!("Could not encode {:?}: {:?}", source, e);
+                result.failures += 1;
+            }
+        }
+    }
+    let statistics = options.fallback_statistics_for_write()
+        .unwrap(); // Guaranteed by `with_fallback`.
+    result.strings = statistics.values;
+    result.fallbacks = statistics.fallbacks;
+    result
+}
+
+/// Train a dictionary on `sources`, but for a fraction `holdout` of them, then report
+/// the size of both the training set and the held-out set once encoded.
+///
+/// Files are held out at random, with `seed`, so that the same command yields the same
+/// dictionary.
+fn train(mut sources: Vec<PathBuf>, holdout: f64, seed: u64, depth: usize, width: usize) -> Dictionary<Instances> {
+    // Make the split independent from the order in which the file system lists files.
+    sources.sort();
+    sources.shuffle(&mut StdRng::seed_from_u64(seed));
+    let mut held_out_len = (sources.len() as f64 * holdout).round() as usize;
+    if holdout > 0. && sources.len() > 1 {
+        // Always keep at least one file on each side.
+        held_out_len = std::cmp::min(std::cmp::max(held_out_len, 1), sources.len() - 1);
+    }
+    let training = sources.split_off(held_out_len);
+    let held_out = sources;
+    println!("Training on {} files, holding out {} files", training.len(), held_out.len());
+
+    let dictionary = build(&training, depth, width);
+    println!("Training set: {}", evaluate(&dictionary, &training));
+    if !held_out.is_empty() {
+        println!("Held-out set: {}", evaluate(&dictionary, &held_out));
+    }
+    dictionary
+}
+
+fn main() {
+    env_logger::init();
+
+    let category = Arg::with_name("category")
+        .long("category")
+        .takes_value(true)
+        .possible_values(&TABLES)
+        .help("Only inspect this table.");
+
+    let matches = App::new("BinJS dictionary inspector")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Inspect the contents of an entropy dictionary (generated by binjs_generate_prediction_tables), compare, prune or merge dictionaries, suggest additions to a dictionary from a corpus, or train a dictionary with a held-out set.")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("summary")
+            .about("Print the size and entropy of each table.")
+            .arg(Arg::with_name("DICTIONARY")
+                .required(true)
+                .help("The dictionary to inspect.")))
+        .subcommand(SubCommand::with_name("show")
+            .about("Print the symbols and probabilities of each table.")
+            .args(&[
+                Arg::with_name("DICTIONARY")
+                    .required(true)
+                    .help("The dictionary to inspect."),
+                category.clone(),
+                Arg::with_name("limit")
+                    .long("limit")
+                    .takes_value(true)
+                    .default_value("10")
+                    .validator(|s| s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid number {}", e)))
+                    .help("The maximal number of values to print per path or window."),
+            ]))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Print the differences between two dictionaries.")
+            .args(&[
+                Arg::with_name("BEFORE")
+                    .required(true)
+                    .help("The old dictionary."),
+                Arg::with_name("AFTER")
+                    .required(true)
+                    .help("The new dictionary."),
+                category,
+                Arg::with_name("limit")
+                    .long("limit")
+                    .takes_value(true)
+                    .default_value("20")
+                    .validator(|s| s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid number {}", e)))
+                    .help("The maximal number of differences to print per table."),
+            ]))
+        .subcommand(SubCommand::with_name("prune")
+            .about("Prune a dictionary to a maximal size, reporting the expected compression loss.")
+            .args(&[
+                Arg::with_name("DICTIONARY")
+                    .required(true)
+                    .help("The dictionary to prune."),
+                Arg::with_name("OUTPUT")
+                    .required(true)
+                    .help("The file to which the pruned dictionary is written. Will be overwritten."),
+                Arg::with_name("max-size")
+                    .long("max-size")
+                    .takes_value(true)
+                    .required(true)
+                    .validator(|s| s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid number {}", e)))
+                    .help("The maximal size of the pruned dictionary, in bytes."),
+            ]))
+        .subcommand(SubCommand::with_name("merge")
+            .about("Merge dictionaries trained on distinct corpora, e.g. a framework corpus and an application corpus, each dictionary contributing to the result in proportion to its weight.")
+            .args(&[
+                Arg::with_name("OUTPUT")
+                    .required(true)
+                    .help("The file to which the merged dictionary is written. Will be overwritten."),
+                Arg::with_name("in")
+                    .long("in")
+                    .short("i")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true)
+                    .required(true)
+                    .help("A dictionary to merge. May be specified multiple times."),
+                Arg::with_name("weight")
+                    .long("weight")
+                    .short("w")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .takes_value(true)
+                    .validator(|s| s.parse::<f64>()
+                        .map_err(|e| format!("Invalid number {}", e))
+                        .and_then(|weight|
+                            if weight >= 0. {
+                                Ok(())
+                            } else {
+                                Err(format!("Invalid weight {}, expected a non-negative number", weight))
+                            }))
+                    .help("The weight of each dictionary, in the order of --in, e.g. `--in framework.entropy --weight 0.7 --in app.entropy --weight 0.3`. Defaults to the same weight for all dictionaries."),
+            ]))
+        .subcommand(SubCommand::with_name("suggest")
+            .about("Encode a corpus, writing the strings missing from a dictionary to a fallback section, and suggest the strings to add to the dictionary, ranked by projected savings across the corpus.")
+            .args(&[
+                Arg::with_name("DICTIONARY")
+                    .required(true)
+                    .help("The dictionary used to encode the corpus."),
+                Arg::with_name("in")
+                    .long("in")
+                    .short("i")
+                    .multiple(true)
+                    .takes_value(true)
+                    .required(true)
+                    .help("JS source files or directories of the corpus, with extension .js, .mjs or .cjs. May be specified multiple times."),
+                Arg::with_name("source-type")
+                    .long("source-type")
+                    .takes_value(true)
+                    .possible_values(&["script", "module", "auto"])
+                    .default_value("auto")
+                    .help("Parse sources as scripts, as ES modules, or detect it: with `auto`, .mjs files are modules, .cjs files are scripts, and other sources are parsed as scripts, then as modules if they contain `import` or `export` declarations."),
+                Arg::with_name("limit")
+                    .long("limit")
+                    .takes_value(true)
+                    .default_value("20")
+                    .validator(|s| s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid number {}", e)))
+                    .help("The maximal number of suggestions to print."),
+            ]))
+        .subcommand(SubCommand::with_name("train")
+            .about("Build a dictionary from a corpus, holding out part of the corpus, then compare the size of the training set and of the held-out set once encoded with the dictionary. A held-out set that compresses much worse than the training set indicates that the dictionary overfits its training set.")
+            .args(&[
+                Arg::with_name("in")
+                    .long("in")
+                    .short("i")
+                    .multiple(true)
+                    .takes_value(true)
+                    .required(true)
+                    .help("JS source files or directories of the corpus. May be specified multiple times."),
+                Arg::with_name("out")
+                    .long("out")
+                    .short("o")
+                    .takes_value(true)
+                    .help("The file to which the dictionary is written. Will be overwritten."),
+                Arg::with_name("holdout")
+                    .long("holdout")
+                    .takes_value(true)
+                    .default_value("0.1")
+                    .validator(|s| s.parse::<f64>()
+                        .map_err(|e| format!("Invalid number {}", e))
+                        .and_then(|holdout|
+                            if holdout >= 0. && holdout < 1. {
+                                Ok(())
+                            } else {
+                                Err(format!("Invalid fraction {}, expected a number in [0, 1)", holdout))
+                            }))
+                    .help("The fraction of the files of the corpus held out from training."),
+                Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .default_value("0")
+                    .validator(|s| s.parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid number {}", e)))
+                    .help("The seed used to pick the held-out files."),
+                Arg::with_name("depth")
+                    .long("depth")
+                    .takes_value(true)
+                    .default_value("3")
+                    .validator(|s| s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid number {}", e)))
+                    .help("Maximal path length to store in the dictionary."),
+                Arg::with_name("window-width")
+                    .long("window-width")
+                    .takes_value(true)
+                    .default_value("32")
+                    .validator(|s| s.parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|e| format!("Invalid number {}", e)))
+                    .help("String window width."),
+            ]))
+        .get_matches();
+
+    let limit = |matches: &ArgMatches| matches.value_of("limit")
+        .unwrap() // Guaranteed by `clap`.
+        .parse::<usize>()
+        .unwrap(); // Guaranteed by `clap`.
+
+    match matches.subcommand() {
+        ("summary", Some(matches)) => {
+            let dictionary = load(matches.value_of("DICTIONARY").unwrap()); // Guaranteed by `clap`.
+            summary(&dictionary);
+        }
+        ("show", Some(matches)) => {
+            let dictionary = load(matches.value_of("DICTIONARY").unwrap()); // Guaranteed by `clap`.
+            show(&dictionary, matches.value_of("category"), limit(matches));
+        }
+        ("diff", Some(matches)) => {
+            let before = load(matches.value_of("BEFORE").unwrap()); // Guaranteed by `clap`.
+            let after = load(matches.value_of("AFTER").unwrap()); // Guaranteed by `clap`.
+            diff(&before, &after, matches.value_of("category"), limit(matches));
+        }
+        ("prune", Some(matches)) => {
+            let mut dictionary = load(matches.value_of("DICTIONARY").unwrap()); // Guaranteed by `clap`.
+            let max_size = matches.value_of("max-size")
+                .unwrap() // Guaranteed by `clap`.
+                .parse::<u64>()
+                .unwrap(); // Guaranteed by `clap`.
+            let report = dictionary.prune(max_size)
+                .expect("Could not prune dictionary");
+            println!("{}", report);
+            if report.bytes_after > max_size {
+                eprintln!("Warning: even an empty dictionary exceeds {} bytes", max_size);
+            }
+            let output = matches.value_of("OUTPUT").unwrap(); // Guaranteed by `clap`.
+            let dest = File::create(output)
+                .unwrap_or_else(|e| panic!("Could not create {}: {:?}", output, e));
+            bincode::serialize_into(dest, &dictionary)
+                .expect("Could not serialize entropy dictionary");
+        }
+        ("merge", Some(matches)) => {
+            let paths : Vec<_> = matches.values_of("in")
+                .unwrap() // Guaranteed by `clap`.
+                .collect();
+            let weights : Vec<f64> = match matches.values_of("weight") {
+                None => paths.iter()
+                    .map(|_| 1.)
+                    .collect(),
+                Some(weights) => weights.map(|weight| weight.parse()
+                        .unwrap()) // Guaranteed by `clap`.
+                    .collect()
+            };
+            if weights.len() != paths.len() {
+                eprintln!("Expected one --weight per --in, got {} weights for {} dictionaries", weights.len(), paths.len());
+                std::process::exit(1);
+            }
+            let dictionaries : Vec<_> = paths.iter()
+                .zip(weights)
+                .map(|(path, weight)| {
+                    let dictionary = load(path);
+                    println!("{}: {} symbols, depth {}, width {}, weight {}",
+                        path, dictionary.symbols(), dictionary.depth(), dictionary.width(), weight);
+                    (dictionary, weight)
+                })
+                .collect();
+            let merged = Dictionary::merge_weighted(dictionaries)
+                .unwrap(); // Guaranteed by `clap`.
+            println!("Merged: {} symbols, depth {}, width {}", merged.symbols(), merged.depth(), merged.width());
+            let output = matches.value_of("OUTPUT").unwrap(); // Guaranteed by `clap`.
+            let dest = File::create(output)
+                .unwrap_or_else(|e| panic!("Could not create {}: {:?}", output, e));
+            bincode::serialize_into(dest, &merged)
+                .expect("Could not serialize entropy dictionary");
+        }
+        ("suggest", Some(matches)) => {
+            let dictionary = load(matches.value_of("DICTIONARY").unwrap()); // Guaranteed by `clap`.
+            let mut sources = vec![];
+            for path in matches.values_of("in").unwrap() { // Guaranteed by `clap`.
+                collect_sources(Path::new(path), &["js", "mjs", "cjs"], &mut sources);
+            }
+            let source_type = SourceType::from_name(matches.value_of("source-type")
+                .unwrap()) // Guaranteed by `clap`.
+                .unwrap(); // Checked by `clap`.
+            suggest(&dictionary, &sources, source_type, limit(matches));
+        }
+        ("train", Some(matches)) => {
+            let mut sources = vec![];
+            for path in matches.values_of("in").unwrap() { // Guaranteed by `clap`.
+                collect_sources(Path::new(path), &["js"], &mut sources);
+            }
+            let number = |name: &str| matches.value_of(name)
+                .unwrap() // Guaranteed by `clap`.
+                .parse::<usize>()
+                .unwrap(); // Guaranteed by `clap`.
+            let holdout = matches.value_of("holdout")
+                .unwrap() // Guaranteed by `clap`.
+                .parse::<f64>()
+                .unwrap(); // Guaranteed by `clap`.
+            let seed = matches.value_of("seed")
+                .unwrap() // Guaranteed by `clap`.
+                .parse::<u64>()
+                .unwrap(); // Guaranteed by `clap`.
+            let dictionary = train(sources, holdout, seed, number("depth"), number("window-width"));
+            if let Some(output) = matches.value_of("out") {
+                let dest = File::create(output)
+                    .unwrap_or_else(|e| panic!("Could not create {}: {:?}", output, e));
+                bincode::serialize_into(dest, &dictionary)
+                    .expect("Could not serialize entropy dictionary");
+            }
+        }
+        _ => unreachable!() // Guaranteed by `clap`.
+    }
+}
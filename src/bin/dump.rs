@@ -4,8 +4,10 @@ extern crate binjs;
 extern crate clap;
 extern crate env_logger;
 
+use binjs::generic::ToJSON;
 use binjs::io::Deserialization;
 use binjs::io::FileStructurePrinter;
+use binjs::util::dot::DotExporter;
 
 use std::fs::*;
 use std::io::*;
@@ -21,24 +23,143 @@ fn main() {
         .about("Dump a JavaScript BinJS file structure to stdout.")
         .args(&[
             Arg::with_name("INPUT")
-                .required(true)
-                .help("Input file to use. Must be a BinJS source file.")
+                .help("Input file to use. Must be a BinJS source file. If not specified or `-`, stdin is used."),
+            Arg::with_name("annotated-hex")
+                .long("annotated-hex")
+                .help("Print an xxd-style dump of the entire file, labelling each range of bytes with what it encodes."),
+            Arg::with_name("dot")
+                .long("dot")
+                .conflicts_with("annotated-hex")
+                .help("Print a GraphViz (DOT) graph of the decoded tree, e.g. `binjs_dump --dot file.binjs | dot -Tsvg > tree.svg`."),
+            Arg::with_name("dot-max-depth")
+                .long("dot-max-depth")
+                .takes_value(true)
+                .value_name("DEPTH")
+                .requires("dot")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid depth: {}", e)))
+                .help("With --dot, hide the descendants of the nodes at this depth, the root being at depth 0."),
+            Arg::with_name("dot-path")
+                .long("dot-path")
+                .takes_value(true)
+                .value_name("PATH")
+                .requires("dot")
+                .help("With --dot, only show the subtree at this path, a sequence of field names and list indices, e.g. `statements[0].expression`."),
+            Arg::with_name("metadata")
+                .long("metadata")
+                .conflicts_with_all(&["annotated-hex", "dot"])
+                .help("Print the metadata recorded by `binjs_encode --metadata`, e.g. the version of the encoder and the time of encoding, without decoding the file."),
+            Arg::with_name("profile")
+                .long("profile")
+                .conflicts_with_all(&["annotated-hex", "dot", "metadata"])
+                .help("Print the startup profile recorded by `binjs_encode --profile`, i.e. the IDs of the functions executed at startup, one per line, without decoding the file."),
         ])
     .get_matches();
 
     let source_path = matches.value_of("INPUT")
-        .expect("Expected input file");
+        .filter(|path| *path != "-");
+    let mode = if matches.is_present("dot") {
+        let exporter = DotExporter::new()
+            .with_max_depth(matches.value_of("dot-max-depth")
+                .map(|depth| depth.parse()
+                    .unwrap())); // Checked by the validator.
+        Mode::Dot(exporter, matches.value_of("dot-path"))
+    } else if matches.is_present("annotated-hex") {
+        Mode::AnnotatedHex
+    } else if matches.is_present("metadata") {
+        Mode::Metadata
+    } else if matches.is_present("profile") {
+        Mode::Profile
+    } else {
+        println!("Reading.");
+        Mode::Structure
+    };
+
+    match source_path {
+        Some(path) => {
+            let file = File::open(path)
+                .expect("Could not open source");
+            dump(BufReader::new(file), mode);
+        }
+        None => {
+            let mut buffer = Vec::new();
+            stdin().read_to_end(&mut buffer)
+                .expect("Failed to read from stdin");
+            dump(Cursor::new(buffer), mode);
+        }
+    }
+}
+
+/// What to print.
+enum Mode<'a> {
+    /// The structure of the file, as read.
+    Structure,
 
-    println!("Reading.");
-    let file = File::open(source_path)
-        .expect("Could not open source");
-    let stream = BufReader::new(file);
+    /// An xxd-style dump of the file.
+    AnnotatedHex,
+
+    /// The metadata recorded by the encoder.
+    Metadata,
+
+    /// The startup profile recorded by the encoder.
+    Profile,
+
+    /// A DOT graph of the decoded tree, or of the subtree at a path.
+    Dot(DotExporter, Option<&'a str>),
+}
+
+fn dump<R: Read + Seek>(stream: R, mode: Mode) {
+    if let Mode::Dot(exporter, path) = mode {
+        let reader = binjs::io::multipart::TreeTokenReader::new(stream)
+            .expect("Could not decode as multipart");
+        let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
+        let tree : binjs::specialized::es6::ast::Program = deserializer.deserialize(&mut binjs::specialized::es6::ast::IOPath::new())
+            .expect("Could not decode");
+        let json = tree.export();
+        let subtree = binjs::util::dot::select(&json, path.unwrap_or(""))
+            .expect("Could not find path");
+        print!("{}", exporter.export(subtree));
+        return;
+    }
+
+    if let Mode::Metadata = mode {
+        let metadata = binjs::io::multipart::TreeTokenReader::metadata(stream)
+            .expect("Could not decode as multipart");
+        match metadata {
+            Some(metadata) => print!("{}", metadata),
+            None => println!("No metadata.")
+        }
+        return;
+    }
+
+    if let Mode::Profile = mode {
+        let profile = binjs::io::multipart::TreeTokenReader::profile(stream)
+            .expect("Could not decode as multipart");
+        match profile {
+            Some(profile) => print!("{}", profile),
+            None => println!("No startup profile.")
+        }
+        return;
+    }
+
+    if let Mode::AnnotatedHex = mode {
+        let (dump, reader) = binjs::io::multipart::AnnotatedHex::new(stream, &binjs::io::multipart::Options::default())
+            .expect("Could not decode as multipart");
+        let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
+        let _tree : binjs::specialized::es6::ast::Program = deserializer.deserialize(&mut binjs::specialized::es6::ast::IOPath::new())
+            .expect("Could not decode");
+        let stdout = stdout();
+        dump.print(&mut stdout.lock())
+            .expect("Could not write to stdout");
+        return;
+    }
 
     println!("Attempting to decode as multipart.");
     if let Ok(mut reader) = binjs::io::multipart::TreeTokenReader::new(stream) {
         reader.enable_file_structure_print();
         let mut deserializer = binjs::specialized::es6::io::Deserializer::new(reader);
-        let _tree : binjs::specialized::es6::ast::Script = deserializer.deserialize(&mut binjs::specialized::es6::ast::IOPath::new())
+        let _tree : binjs::specialized::es6::ast::Program = deserializer.deserialize(&mut binjs::specialized::es6::ast::IOPath::new())
             .expect("Could not decode");
     } else {
         println!("not supported format.");
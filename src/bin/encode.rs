@@ -3,42 +3,216 @@
 extern crate binjs;
 extern crate clap;
 extern crate env_logger;
+#[macro_use]
+extern crate binjs_shared;
 extern crate log;
+extern crate notify;
+extern crate tracing;
 
+use binjs::cache::AnnotationCache;
 use binjs::io::{ CompressionTarget, Format };
-use binjs::source::{ Shift, SourceParser };
-use binjs::generic::FromJSON;
+use binjs::io::bytes::compress::SourceCompression;
+use binjs::io::multipart::Metadata;
+use binjs::io::startup::StartupProfile;
+use binjs::io::statistics::{ Aggregate, Bytes, ContentInfo, CsvWriter, Instances };
+use binjs::io::progress::{ Phase, ProgressSink };
+use binjs::source::{ Babel, DaemonPool, External, Shift, SourceParser, SourceType };
+use binjs::generic::{ FromJSON, JSON, JSONExt };
 use binjs::specialized::es6::io::Encoder;
 use binjs::specialized::es6::ast::Walker;
+use binjs::specialized::es6::lazy::Policy;
 
+use std::collections::HashMap;
 use std::fs::*;
 use std::io::*;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
 use std::thread;
 use std::path::{ Path, PathBuf };
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
 
 use clap::*;
 
-fn export_section(dest_bin_path: &Option<PathBuf>, target: &mut CompressionTarget, extension: &str) {
+use notify::{ DebouncedEvent, RecursiveMode, Watcher };
+
+/// With `--watch`, the delay during which successive events on a file are merged, in ms.
+const WATCH_DELAY_MS: u64 = 200;
+
+fn export_section(dest_bin_path: &Option<PathBuf>, target: &mut CompressionTarget, extension: &str) -> std::result::Result<(), std::io::Error> {
     let path = dest_bin_path
         .clone()
         .expect("Cannot write partial file without a destination")
         .with_extension(extension);
-    let mut file = File::create(path.clone())
-        .unwrap_or_else(|e| panic!("Could not create destination file {:?}: {:?}", path, e));
-    let (data, _) = target.done()
-        .expect("Could not finalize compression");
-    file.write(data.as_ref())
-        .expect("Could not write destination file");
+    let mut file = File::create(path)?;
+    let (data, _) = target.done()?;
+    file.write_all(data.as_ref())?;
     target.reset();
+    Ok(())
+}
+
+/// The name of the step during which encoding a file failed, as reported with `--keep-going`.
+fn phase_name(kind: binjs::ErrorKind) -> &'static str {
+    match kind {
+        binjs::ErrorKind::Io => "io",
+        binjs::ErrorKind::Parse => "parse",
+        binjs::ErrorKind::Annotation => "annotation",
+        _ => "encoding",
+    }
+}
+
+/// The exit code of `binjs_encode` if a file fails during step `kind`.
+fn exit_code(kind: binjs::ErrorKind) -> i32 {
+    match kind {
+        binjs::ErrorKind::Parse => 2,
+        binjs::ErrorKind::Annotation => 3,
+        binjs::ErrorKind::Io => 4,
+        _ => 5,
+    }
+}
+
+/// A file that could not be encoded.
+struct Failure {
+    /// The source path, or `-` for stdin.
+    path: String,
+    error: binjs::Error,
+}
+impl Failure {
+    fn new<E: Into<binjs::Error>>(path: Option<&Path>, error: E) -> Self {
+        Failure {
+            path: path.map_or_else(|| "-".to_string(), |path| path.to_string_lossy().into_owned()),
+            error: error.into(),
+        }
+    }
+
+    /// Build a function turning errors into failures, for use with `map_err`.
+    fn with<'a, E: Into<binjs::Error>>(path: Option<&'a Path>) -> impl Fn(E) -> Failure + 'a {
+        move |error| Failure::new(path, error)
+    }
+
+    fn phase(&self) -> &'static str {
+        phase_name(self.error.kind())
+    }
+
+    fn to_json(&self) -> JSON {
+        object!{
+            "file" => self.path.clone(),
+            "phase" => self.phase(),
+            "error" => format!("{:?}", self.error)
+        }
+    }
+}
+
+/// A progress bar, displayed on stderr with `--progress`.
+struct ProgressBar {
+    /// The total number of files to encode.
+    total_files: usize,
+    /// The number of files encoded so far.
+    files: usize,
+    nodes: usize,
+    bytes: usize,
+    phase: Phase,
+}
+impl ProgressBar {
+    /// The width of the bar, in characters.
+    const WIDTH: usize = 30;
+
+    fn new(total_files: usize) -> Self {
+        ProgressBar {
+            total_files,
+            files: 0,
+            nodes: 0,
+            bytes: 0,
+            phase: Phase::Parse,
+        }
+    }
+
+    fn file_done(&mut self) {
+        self.files += 1;
+        self.draw();
+    }
+
+    fn draw(&self) {
+        let filled = if self.total_files == 0 {
+            Self::WIDTH
+        } else {
+            Self::WIDTH * self.files / self.total_files
+        };
+        eprint!("\r[{}{}] {}/{} files, {} nodes, {} bytes ({})   ",
+            "=".repeat(filled),
+            " ".repeat(Self::WIDTH - filled),
+            self.files,
+            self.total_files,
+            self.nodes,
+            self.bytes,
+            self.phase);
+    }
+
+    fn finish(&self) {
+        self.draw();
+        eprintln!();
+    }
+}
+impl ProgressSink for ProgressBar {
+    fn phase(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.draw();
+    }
+    fn nodes(&mut self, count: usize) {
+        self.nodes += count;
+        self.draw();
+    }
+    fn bytes(&mut self, count: usize) {
+        self.bytes += count;
+        self.draw();
+    }
 }
 
 struct Options<'a> {
     parser: &'a Shift,
+    /// The parsers used for non-JavaScript sources, by extension.
+    babel: &'a HashMap<&'static str, Babel>,
+    /// If `--parser-cmd` is specified, the parser used instead of `parser`.
+    external: Option<&'a External>,
     format: Format,
     dest_dir: Option<PathBuf>,
-    lazification: u32,
+    lazification: Policy,
+    /// If `--profile` is specified, the startup profile recorded in each file.
+    profile: Option<StartupProfile>,
+    /// If `true`, move the toplevel function declarations executed at startup according
+    /// to `profile` before the other statements.
+    reorder_functions: bool,
     show_ast: bool,
     quiet: bool,
+    /// If `--archive` is specified, the ASTs to encode in the archive, by entry name.
+    archive: Option<Vec<(String, binjs::specialized::es6::ast::Program)>>,
+    /// If `--progress` is specified, the progress bar.
+    progress: Option<ProgressBar>,
+    /// If `true`, continue with the next file after a failure.
+    keep_going: bool,
+    /// With `--keep-going`, the files that could not be encoded so far.
+    failures: Vec<Failure>,
+    /// If `--grammar` is specified, the encoder for the grammar loaded at runtime.
+    grammar: Option<&'a binjs::generic::io::Encoder>,
+    /// If `true`, store source positions and comments alongside the tree.
+    source_positions: bool,
+    /// If `true`, encode identifier names that are not valid ECMAScript IdentifierNames.
+    allow_invalid_identifiers: bool,
+    /// If `--cache-dir` is specified, the cache of annotated ASTs.
+    cache: Option<AnnotationCache>,
+    /// The options of the parsers that change the parsed AST, as part of cache keys.
+    parser_options: String,
+    /// If `--explain` is specified, the number of subtrees, strings and symbols to show.
+    explain: Option<usize>,
+    /// If `true`, compress sources with gzip and brotli, to compare them with BinJS in statistics.
+    compare_sources: bool,
+    /// With `--compare-sources`, the sources encoded so far, compressed with gzip and brotli.
+    source_compression: SourceCompression,
+    /// With `--compare-sources`, the byte length of the files encoded so far.
+    encoded_bytes: usize,
+    /// If `--stats-aggregate` is specified, the statistics of the files encoded so far.
+    aggregate: Option<Aggregate>,
+    /// If `--stats-csv` is specified, the file to which the statistics of each file are written.
+    stats_csv: Option<CsvWriter<BufWriter<File>>>,
 }
 
 macro_rules! progress {
@@ -56,53 +230,120 @@ enum Source<'a> {
 
 struct EncodeParams<'a> {
     source: Source<'a>,
+    /// The name of the entry, if the source is part of an archive.
+    entry: Option<String>,
     dest_bin_path: Option<PathBuf>,
     dest_txt_path: Option<PathBuf>,
 }
 
+/// With `--metadata`, record the current time as the time of encoding, unless
+/// encoding with `--reproducible`.
+fn stamp_metadata(format: &mut Format) {
+    if let Some(options) = format.multipart_options_mut().filter(|options| !options.reproducible) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        options.metadata = options.metadata.take()
+            .map(|metadata| metadata.with(Metadata::TIMESTAMP, &timestamp.to_string()));
+    }
+}
+
+/// Count the files that `handle_path` will encode.
+fn count_files(babel: &HashMap<&'static str, Babel>, source_path: &Path) -> usize {
+    let is_dir = std::fs::metadata(source_path)
+        .unwrap()
+        .is_dir();
+    if is_dir {
+        return std::fs::read_dir(source_path)
+            .expect("Could not open directory")
+            .map(|dir| count_files(babel, dir.unwrap().path().as_path()))
+            .sum();
+    }
+    match source_path.extension().map(std::ffi::OsStr::to_str) {
+        Some(Some("js")) | Some(Some("mjs")) | Some(Some("cjs")) => 1,
+        Some(Some(extension)) if babel.contains_key(extension) => 1,
+        _ => 0
+    }
+}
+
+/// The paths of the copy of the source and of the encoded file
+/// for `source_path`, in destination directory `dest_dir`.
+fn dest_paths(dest_dir: &Path, source_path: &Path, sub_dir: &Path, extension: &str) -> (PathBuf, PathBuf) {
+    let file_name = source_path.file_stem()
+        .expect("Could not extract file name");
+
+    let mut bin_path = dest_dir.join(sub_dir);
+    bin_path.push(file_name);
+    bin_path.set_extension("binjs");
+
+    let mut txt_path = dest_dir.join(sub_dir);
+    txt_path.push(file_name);
+    txt_path.set_extension(extension);
+
+    (txt_path, bin_path)
+}
+
 fn handle_path<'a>(options: &mut Options<'a>,
     source_path: &Path,
     sub_dir: &Path)
 {
     progress!(options.quiet, "Treating {:?} ({:?})", source_path, sub_dir);
+    let result = handle_path_aux(options, source_path, sub_dir);
+    if let Err(failure) = result {
+        report_failure(options, failure);
+    }
+}
+
+/// Record a failure, or exit immediately without `--keep-going`.
+fn report_failure<'a>(options: &mut Options<'a>, failure: Failure) {
+    eprintln!("Could not encode {} ({}): {:?}", failure.path, failure.phase(), failure.error);
+    if !options.keep_going {
+        std::process::exit(exit_code(failure.error.kind()));
+    }
+    if let Some(ref mut bar) = options.progress {
+        bar.file_done();
+    }
+    options.failures.push(failure);
+}
+
+fn handle_path_aux<'a>(options: &mut Options<'a>,
+    source_path: &Path,
+    sub_dir: &Path) -> std::result::Result<(), Failure>
+{
+    let io_failure = Failure::with::<std::io::Error>(Some(source_path));
     let is_dir = std::fs::metadata(source_path)
-        .unwrap()
+        .map_err(&io_failure)?
         .is_dir();
     if is_dir {
         let file_name = source_path.file_name()
             .unwrap_or_else(|| panic!("Invalid source path {:?}", source_path));
         let sub_dir = sub_dir.join(file_name);
         for entry in std::fs::read_dir(source_path)
-            .expect("Could not open directory")
-            .map(|dir| dir.unwrap())
+            .map_err(&io_failure)?
         {
+            let entry = entry.map_err(&io_failure)?;
             handle_path(options, entry.path().as_path(), &sub_dir);
         }
-        return;
-    }
-    if let Some(Some("js")) = source_path.extension().map(std::ffi::OsStr::to_str) {
-        // Proceed
-    } else {
-        progress!(options.quiet, "Skipping {:?}", source_path);
-        return;
+        return Ok(());
     }
+    let extension = match source_path.extension().map(std::ffi::OsStr::to_str) {
+        Some(Some("js")) => "js",
+        Some(Some("mjs")) => "mjs",
+        Some(Some("cjs")) => "cjs",
+        Some(Some(extension)) if options.babel.contains_key(extension) => extension,
+        _ => {
+            progress!(options.quiet, "Skipping {:?}", source_path);
+            return Ok(());
+        }
+    };
     let (dest_txt_path, dest_bin_path) = match options.dest_dir {
         None => (None, None), // Use stdout
         Some(ref d) => {
-            let file_name = source_path.file_stem()
-                .expect("Could not extract file name");
-
             std::fs::create_dir_all(d.join(sub_dir))
-                .expect("Could not find or create destination directory");
-
-            let mut bin_path = d.join(sub_dir);
-            bin_path.push(file_name);
-            bin_path.set_extension("binjs");
-
-            let mut txt_path = d.join(sub_dir);
-            txt_path.push(file_name);
-            txt_path.set_extension("js");
+                .map_err(&io_failure)?;
 
+            let (txt_path, bin_path) = dest_paths(d, source_path, sub_dir, extension);
             (Some(txt_path), Some(bin_path))
         }
     };
@@ -115,47 +356,151 @@ fn handle_path<'a>(options: &mut Options<'a>,
 
     progress!(options.quiet, "Parsing.");
 
+    let entry = source_path.file_name()
+        .map(|file_name| sub_dir.join(file_name).to_string_lossy().into_owned());
+
     handle_path_or_text(options, EncodeParams {
         source: Source::FromFile { path: source_path },
+        entry,
         dest_bin_path,
         dest_txt_path,
-    });
+    })
+}
+
+/// Write raw bytes to stdout, e.g. to a pipe.
+fn write_stdout(data: &[u8]) {
+    let stdout = stdout();
+    let mut lock = stdout.lock();
+    lock.write_all(data)
+        .and_then(|_| lock.flush())
+        .expect("Could not write to stdout");
 }
 
 fn handle_path_or_text<'a>(options: &mut Options<'a>,
-    params: EncodeParams)
+    params: EncodeParams) -> std::result::Result<(), Failure>
 {
-    let (source_path, source_len, json) = match params.source {
+    let (source_path, source_len, extension) = match params.source {
         Source::FromFile { path } => {
             (Some(path),
              std::fs::metadata(path)
-                 .expect("Could not open source")
+                 .map_err(|e| Failure::new(Some(path), e))?
                  .len(),
-             options.parser.parse_file(path)
-                 .expect("Could not parse source"))
+             path.extension()
+                 .and_then(std::ffi::OsStr::to_str)
+                 .unwrap_or("js"))
+        }
+        Source::FromStdin { ref text } => (None, text.len() as u64, "js")
+    };
+
+    // With `--cache-dir`, reuse the annotated AST of a previous run, if possible.
+    let cache_key = match options.cache {
+        None => None,
+        Some(_) => {
+            let source = match params.source {
+                Source::FromFile { path } => std::fs::read(path)
+                    .map_err(Failure::with(source_path))?,
+                Source::FromStdin { ref text } => text.as_bytes().to_vec(),
+            };
+            Some(AnnotationCache::key(&source, &format!("{};{}", extension, options.parser_options)))
+        }
+    };
+    let cached = match (options.cache.as_ref(), cache_key.as_ref()) {
+        (Some(cache), Some(key)) => cache.get(key),
+        _ => None
+    };
+
+    let (mut ast, positions) = match cached {
+        Some((ast, positions)) => {
+            progress!(options.quiet, "Using cached AST.");
+            (ast, Some(positions))
         }
-        Source::FromStdin { text } => {
-            (None,
-             text.len() as u64,
-             options.parser.parse_str(text.as_str())
-             .expect("Could not parse source"))
+        None => {
+            if let Some(ref mut bar) = options.progress {
+                bar.phase(Phase::Parse);
+            }
+            let parse_span = tracing::info_span!("parse").entered();
+            let mut json = match params.source {
+                Source::FromFile { path } => {
+                    match (options.babel.get(extension), options.external) {
+                        (Some(babel), _) => babel.parse_file(path),
+                        (None, Some(external)) => external.parse_file(path),
+                        (None, None) => options.parser.parse_file(path)
+                    }.map_err(|e| Failure::new(Some(path), e))?
+                }
+                Source::FromStdin { ref text } => {
+                    match options.external {
+                        Some(external) => external.parse_str(text.as_str()),
+                        None => options.parser.parse_str(text.as_str())
+                    }.map_err(|e| Failure::new(None, e))?
+                }
+            };
+            parse_span.exit();
+            let positions = if options.source_positions || options.lazification.needs_locations() || options.cache.is_some() {
+                // Positions are not part of the grammar, remove them in any case.
+                Some(binjs::source::positions::collect(&mut json))
+            } else {
+                None
+            };
+
+            if let Some(ref mut bar) = options.progress {
+                bar.phase(Phase::Annotate);
+            }
+            let _annotation_span = tracing::info_span!("annotation").entered();
+            let mut ast = binjs::specialized::es6::ast::Program::import(&json)
+                .map_err(Failure::with(source_path))?;
+            binjs::specialized::es6::scopes::AnnotationVisitor::new()
+                .annotate_program(&mut ast);
+
+            if let (Some(cache), Some(key), Some(positions)) = (options.cache.as_ref(), cache_key.as_ref(), positions.as_ref()) {
+                cache.insert(key, &ast, positions)
+                    .map_err(Failure::with(source_path))?;
+            }
+            (ast, positions)
+        }
+    };
+    // Locate functions in the AST rather than in the output of the parser, so that
+    // they are listed in the order in which the lazifier walks them.
+    let function_locations = match positions {
+        Some(ref positions) if options.lazification.needs_locations() => {
+            use binjs::generic::ToJSON;
+            let mut json = ast.export();
+            binjs::source::positions::reattach(&mut json, positions)
+                .map_err(Failure::with(source_path))?;
+            binjs::source::positions::function_locations(&json)
         }
+        _ => vec![]
+    };
+    let mut positions = if options.source_positions {
+        positions
+    } else {
+        None
     };
     let dest_bin_path = params.dest_bin_path;
     let dest_txt_path = params.dest_txt_path;
 
-    let mut ast = binjs::specialized::es6::ast::Script::import(&json)
-        .expect("Could not import AST");
-    binjs::specialized::es6::scopes::AnnotationVisitor::new()
-        .annotate_script(&mut ast);
+    if let Some(ref mut bar) = options.progress {
+        bar.phase(Phase::Annotate);
+    }
+    let annotation_span = tracing::info_span!("annotation").entered();
 
-    if options.lazification > 0 {
+    if options.lazification != Policy::none() {
         progress!(options.quiet, "Introducing laziness.");
         let mut path = binjs::specialized::es6::ast::WalkPath::new();
-        let mut visitor = binjs::specialized::es6::lazy::LazifierVisitor::new(options.lazification);
+        let mut visitor = binjs::specialized::es6::lazy::LazifierVisitor::with_policy(options.lazification.clone(), function_locations);
         ast.walk(&mut path, &mut visitor)
-            .expect("Could not introduce laziness");
+            .map_err(|()| Failure::new(source_path, binjs::Error::without_source(binjs::ErrorKind::Annotation)))?;
     }
+    // The profile recorded in the file, whose function IDs follow the order of the encoded AST.
+    let mut profile = options.profile.clone();
+    if let (true, Some(original)) = (options.reorder_functions, options.profile.as_ref()) {
+        progress!(options.quiet, "Reordering functions.");
+        let order = binjs::specialized::es6::reorder::reorder_program(&mut ast, original);
+        if let Some(ref mut positions) = positions {
+            binjs::source::positions::reorder_statements(positions, &order);
+        }
+        profile = Some(binjs::specialized::es6::reorder::reorder_profile(&mut ast, &order, original));
+    }
+    annotation_span.exit();
 
     if options.show_ast {
         use binjs::generic::ToJSON;
@@ -163,28 +508,88 @@ fn handle_path_or_text<'a>(options: &mut Options<'a>,
         println!("{:#}", json);
     }
 
+    if let Some(ref mut archive) = options.archive {
+        let entry = params.entry
+            .expect("Archives require --in");
+        progress!(options.quiet, "Adding {} to archive.", entry);
+        archive.push((entry, ast));
+        if let Some(ref mut bar) = options.progress {
+            bar.file_done();
+        }
+        return Ok(());
+    }
+
     progress!(options.quiet, "Encoding.");
-    let encoder = Encoder::new();
-    let data = encoder.encode(&mut options.format, &ast)
-        .expect("Could not encode");
+    stamp_metadata(&mut options.format);
+    let content_before = content_statistics(&options.format);
+    let data = match options.grammar {
+        Some(encoder) => {
+            use binjs::generic::ToJSON;
+            let json = ast.export();
+            match options.progress {
+                Some(ref mut bar) => encoder.encode_with_progress(&mut options.format, &json, bar),
+                None => encoder.encode(&mut options.format, &json)
+            }.map_err(Failure::with(source_path))?
+        }
+        None => {
+            let encoder = Encoder::new()
+                .with_positions(positions)
+                .with_profile(profile)
+                .with_invalid_identifiers(options.allow_invalid_identifiers);
+            match options.progress {
+                Some(ref mut bar) => encoder.encode_with_progress(&mut options.format, &ast, bar),
+                None => encoder.encode(&mut options.format, &ast)
+            }.map_err(Failure::with(source_path))?
+        }
+    };
     if dest_txt_path.is_some() {
-        options.format.with_sections::<_, ()>(|contents, name| {
-            export_section(&dest_bin_path, contents, name);
-            Ok(())
+        options.format.with_sections(|contents, name| {
+            export_section(&dest_bin_path, contents, name)
         })
-        .expect("Could not write sections");
+        .map_err(Failure::with(source_path))?;
     };
     let dest_len = data.as_ref().as_ref().len();
 
+    // The bytes and instances of each category of content in this file, if known.
+    let content = match (content_before, content_statistics(&options.format)) {
+        (Some((bytes_before, instances_before)), Some((bytes, instances))) =>
+            Some((bytes.since(&bytes_before), instances.since(&instances_before))),
+        _ => None
+    };
+    if let Some(ref mut aggregate) = options.aggregate {
+        aggregate.add_file(source_len as usize, dest_len, content.as_ref()
+            .map(|&(ref bytes, _)| bytes.clone()));
+    }
+    if let Some(ref mut csv) = options.stats_csv {
+        let file = source_path.map_or_else(|| "-".to_string(), |path| path.to_string_lossy().into_owned());
+        csv.write_file(&file, dest_len, content.as_ref()
+            .map(|&(ref bytes, ref instances)| (bytes, instances)))
+            .map_err(Failure::with(source_path))?;
+    }
+
+    if options.compare_sources {
+        let source = match params.source {
+            Source::FromFile { path } => std::fs::read(path)
+                .map_err(Failure::with(source_path))?,
+            Source::FromStdin { ref text } => text.as_bytes().to_vec(),
+        };
+        let compression = SourceCompression::new(&source)
+            .map_err(Failure::with(source_path))?;
+        if let Format::Multipart { ref stats, .. } = options.format {
+            // Included in the multipart statistics.
+            stats.borrow_mut().add_source_compression(compression.clone());
+        }
+        options.source_compression += compression;
+        options.encoded_bytes += dest_len;
+    }
+
     if let Some(ref bin_path) = dest_bin_path {
         progress!(options.quiet, "Writing binary file.");
-        let mut dest = File::create(bin_path)
-            .unwrap_or_else(|e| panic!("Could not create destination file {:?}: {:?}", bin_path, e));
-        dest.write((*data).as_ref())
-            .expect("Could not write destination file");
+        File::create(bin_path)
+            .and_then(|mut dest| dest.write_all((*data).as_ref()))
+            .map_err(Failure::with(source_path))?;
     } else {
-        stdout().write((*data).as_ref())
-            .expect("Could not write to stdout");
+        write_stdout((*data).as_ref());
     }
 
     if let Some(ref txt_path) = dest_txt_path {
@@ -194,11 +599,150 @@ fn handle_path_or_text<'a>(options: &mut Options<'a>,
             progress!(options.quiet, "Copying source file.");
 
             std::fs::copy(source_path.unwrap(), txt_path)
-                .expect("Could not copy source file");
+                .map_err(Failure::with(source_path))?;
         }
     }
 
+    if let Some(ref mut bar) = options.progress {
+        bar.file_done();
+    }
+
     progress!(options.quiet, "Successfully compressed {} bytes => {} bytes", source_len, dest_len);
+
+    if let Some(top) = options.explain {
+        explain(&options.format, (*data).as_ref(), top, dest_bin_path.is_none());
+    }
+    Ok(())
+}
+
+/// With entropy formats, the number of bytes and instances written so far in each
+/// category of content.
+fn content_statistics(format: &Format) -> Option<(ContentInfo<Bytes>, ContentInfo<Instances>)> {
+    match *format {
+        Format::Entropy { options: ref entropy } |
+        Format::HuffmanEntropy { options: ref entropy } =>
+            Some((entropy.content_lengths_for_write(), entropy.content_instances_for_write())),
+        _ => None
+    }
+}
+
+/// With `--explain`, show where the bytes of an encoded file go.
+///
+/// Explanations are printed to stderr if the encoded file is written to stdout.
+fn explain(format: &Format, data: &[u8], top: usize, to_stdout: bool) {
+    let text = match *format {
+        Format::Multipart { .. } => {
+            match binjs::explain::Explanation::new(data, top) {
+                Ok(explanation) => format!("{}", explanation),
+                Err(err) => format!("Could not explain file: {:?}", err)
+            }
+        }
+        _ => "Explanations are only available for the multipart format".to_string()
+    };
+    if to_stdout {
+        eprint!("{}", text);
+    } else {
+        print!("{}", text);
+    }
+}
+
+/// Watch `sources` and re-encode files as they change, until the process is killed.
+///
+/// Outputs are mirrored in the destination directory: the outputs of a source file
+/// that is removed are removed, too.
+fn watch<'a>(options: &mut Options<'a>, sources: &[&Path]) {
+    // Failures are reported, but must not stop the watcher.
+    options.keep_going = true;
+
+    let dest_dir = options.dest_dir.clone()
+        .expect("--watch requires --out");
+    std::fs::create_dir_all(&dest_dir)
+        .expect("Could not find or create destination directory");
+    let dest_dir = std::fs::canonicalize(dest_dir)
+        .expect("Could not resolve destination directory");
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut watcher = notify::watcher(tx, Duration::from_millis(WATCH_DELAY_MS))
+        .expect("Could not create watcher");
+    let mut roots = Vec::with_capacity(sources.len());
+    for source in sources {
+        watcher.watch(source, RecursiveMode::Recursive)
+            .unwrap_or_else(|e| panic!("Could not watch {:?}: {:?}", source, e));
+        let root = std::fs::canonicalize(source)
+            .unwrap_or_else(|e| panic!("Could not resolve {:?}: {:?}", source, e));
+        roots.push(root);
+    }
+    eprintln!("Watching for changes.");
+
+    // Determine the `sub_dir` argument of `handle_path` for a file, as if
+    // it had been found by walking the source trees.
+    let sub_dir_of = |path: &Path| -> Option<PathBuf> {
+        if path.starts_with(&dest_dir) {
+            // Don't re-encode our own outputs.
+            return None;
+        }
+        for root in &roots {
+            if path == root.as_path() {
+                return Some(PathBuf::new());
+            }
+            if let Ok(relative) = path.strip_prefix(root) {
+                let mut sub_dir = PathBuf::from(root.file_name()?);
+                if let Some(parent) = relative.parent() {
+                    sub_dir.push(parent);
+                }
+                return Some(sub_dir);
+            }
+        }
+        None
+    };
+
+    loop {
+        let event = rx.recv()
+            .expect("Watcher disconnected");
+        let (removed, changed) = match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => (None, Some(path)),
+            DebouncedEvent::Remove(path) => (Some(path), None),
+            DebouncedEvent::Rename(old, new) => (Some(old), Some(new)),
+            DebouncedEvent::Error(error, path) => {
+                eprintln!("Watch error {:?} ({:?})", error, path);
+                continue;
+            }
+            _ => continue
+        };
+        for path in removed.iter().chain(changed.iter()) {
+            // Remove stale outputs, including the copy of the source,
+            // which `handle_path` would not overwrite.
+            let (sub_dir, extension) = match (sub_dir_of(path), path.extension().and_then(std::ffi::OsStr::to_str)) {
+                (Some(sub_dir), Some(extension)) => (sub_dir, extension),
+                _ => continue
+            };
+            let (txt_path, bin_path) = dest_paths(&dest_dir, path, &sub_dir, extension);
+            for output in &[txt_path, bin_path] {
+                if output.exists() {
+                    std::fs::remove_file(output)
+                        .unwrap_or_else(|e| panic!("Could not remove {:?}: {:?}", output, e));
+                }
+            }
+        }
+        if let Some(path) = changed {
+            let sub_dir = match sub_dir_of(&path) {
+                Some(sub_dir) => sub_dir,
+                None => continue
+            };
+            if !path.exists() {
+                continue;
+            }
+            eprintln!("Re-encoding {:?}.", path);
+            // Don't let a syntax error in the middle of editing a file stop the watcher.
+            let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                handle_path(options, &path, &sub_dir)
+            }));
+            if result.is_err() {
+                eprintln!("Could not encode {:?}.", path);
+            }
+            options.failures.clear();
+        }
+    }
 }
 
 fn main() {
@@ -225,15 +769,43 @@ fn main_aux() {
                 .short("i")
                 .multiple(true)
                 .takes_value(true)
-                .help("Input files to use. Must be JS source file. May be specified multiple times. If not specified, stdin is used."),
+                .help("Input files to use. Must be JS source file. May be specified multiple times. If not specified or `-`, stdin is used."),
             Arg::with_name("out")
                 .long("out")
                 .short("o")
                 .takes_value(true)
-                .help("Output directory to use. Files in this directory may be overwritten. Requires --in. If not specified, stdout is used"),
+                .help("Output directory to use. Files in this directory may be overwritten. Requires --in. If not specified or `-`, stdout is used"),
             Arg::with_name("statistics")
                 .long("show-stats")
                 .help("Show statistics."),
+            Arg::with_name("explain")
+                .long("explain")
+                .conflicts_with_all(&["archive", "grammar", "encryption-key"])
+                .help("After encoding each file, show its sections, and its most expensive subtrees, strings and categories of symbols, by bytes. If the tree is compressed, costs in the tree are in uncompressed bytes, and labelled as such. Multipart format only."),
+            Arg::with_name("explain-top")
+                .long("explain-top")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("10")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("With --explain, the number of subtrees, strings and categories of symbols to show."),
+            Arg::with_name("compare-sources")
+                .long("compare-sources")
+                .requires("statistics")
+                .conflicts_with("archive")
+                .help("With --show-stats, also compress each source with gzip -9 and brotli -11, and show the ratios between BinJS and the compressed sources."),
+            Arg::with_name("stats-aggregate")
+                .long("stats-aggregate")
+                .conflicts_with("archive")
+                .help("After encoding all files, e.g. a directory, show statistics aggregated across files: total sizes, and the mean and percentiles of per-file ratios. With entropy formats, also the bytes of each category of content, and the distribution of its share of each file."),
+            Arg::with_name("stats-csv")
+                .long("stats-csv")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("archive")
+                .help("Write the statistics of each file to this file, as CSV, with one row per file and category of content: file, category, bytes, instances, and their percentages of the file. Categories of content are only available with entropy formats, other formats only have a row `total` per file."),
             Arg::with_name("show-ast")
                 .long("show-ast")
                 .help("Show pos-processed ast"),
@@ -241,14 +813,144 @@ fn main_aux() {
                 .long("lazify")
                 .takes_value(true)
                 .default_value("0")
-                .validator(|s| s.parse::<u32>()
-                    .map(|_| ())
-                    .map_err(|e| format!("Invalid number {}", e)))
-                .help("Number of layers of functions to lazify. 0 = no lazification, 1 = functions at toplevel, 2 = also functions in functions at toplevel, etc."),
+                .validator(|s| Policy::parse(&s)
+                    .map(|_| ()))
+                .help("Which functions to lazify. `none`, `all`, a number of layers of functions (0 = no lazification, 1 = functions at toplevel, 2 = also functions in functions at toplevel, etc.), `min-bytes=N` for functions whose source is at least N bytes long, or `spans=START-END,...` for functions whose source spans exactly one of these ranges of byte offsets."),
+            Arg::with_name("profile")
+                .long("profile")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with("archive")
+                .help("Lazify the functions that were not executed at startup according to this startup profile, instead of following --lazify. The profile lists the IDs of the functions executed at startup, one per line, the ID of a function being its index among function declarations, function expressions, methods, getters and setters, in source order. With the multipart format, the profile is also recorded in the file."),
+            Arg::with_name("reorder-functions")
+                .long("reorder-functions")
+                .requires("profile")
+                .help("Move the toplevel function declarations of scripts that were executed at startup according to --profile before the other statements, so that decoders read them first, e.g. in the first chunks with multipart --chunks. Declarations are only moved where this does not change the behavior of the script. Modules are left unchanged."),
             Arg::with_name("quiet")
                 .long("quiet")
                 .short("q")
                 .help("Do not print progress"),
+            Arg::with_name("typescript")
+                .long("typescript")
+                .help("Also encode .ts and .tsx files, stripping type annotations. No type checking is performed. JSX in .tsx files is lowered as with --jsx. Requires @babel/parser."),
+            Arg::with_name("jsx")
+                .long("jsx")
+                .help("Also encode .jsx files, lowering JSX to function calls. Requires @babel/parser."),
+            Arg::with_name("jsx-pragma")
+                .long("jsx-pragma")
+                .takes_value(true)
+                .default_value("React.createElement")
+                .help("The function called to create JSX elements."),
+            Arg::with_name("jsx-pragma-frag")
+                .long("jsx-pragma-frag")
+                .takes_value(true)
+                .default_value("React.Fragment")
+                .help("The component used for JSX fragments."),
+            Arg::with_name("archive")
+                .long("archive")
+                .takes_value(true)
+                .requires("in")
+                .conflicts_with("out")
+                .help("Encode all input files as the entries of a single archive, sharing their tables. Entries are named after their path relative to the --in argument. If `-`, the archive is written to stdout. Multipart format only."),
+            Arg::with_name("sign-key")
+                .long("sign-key")
+                .takes_value(true)
+                .help("File containing an Ed25519 secret key, hex-encoded. If specified, sign the encoded files with this key. Multipart format only."),
+            Arg::with_name("encryption-key")
+                .long("encryption-key")
+                .takes_value(true)
+                .help("File containing an AES-256 key, hex-encoded. If specified, encrypt the encoded files with this key, which must be shared out-of-band with the decoder. Multipart format only."),
+            Arg::with_name("metadata")
+                .long("metadata")
+                .help("Record the version of the encoder, the format options, the hash of the string dictionary, if any, and the time of encoding in each encoded file, e.g. to audit what produced it. Decoders ignore it, `binjs_dump --metadata` prints it. Multipart format only."),
+            Arg::with_name("reproducible")
+                .long("reproducible")
+                .conflicts_with("encryption-key")
+                .help("Guarantee that encoding the same sources with the same options yields the same bytes, whenever and wherever the encoder runs, e.g. for reproducible builds. With --metadata, only the version of the encoder and the hash of the string dictionary are recorded, not the time of encoding or the format options. Incompatible with encryption, which uses a random nonce."),
+            Arg::with_name("allow-invalid-identifiers")
+                .long("allow-invalid-identifiers")
+                .help("Encode identifier names that are not valid ECMAScript IdentifierNames, e.g. produced by a custom --parser-cmd, instead of failing. Not checked with --grammar."),
+            Arg::with_name("watch")
+                .long("watch")
+                .requires("in")
+                .requires("out")
+                .conflicts_with("archive")
+                .help("After encoding, keep watching the input files and directories, re-encoding source files as they change. Outputs are kept in sync in the output directory."),
+            Arg::with_name("keep-going")
+                .long("keep-going")
+                .help("Do not stop at the first file that cannot be encoded. Instead, encode the other files, then report all failures as JSON, as a list of {file, phase, error}. The exit code is that of the first failure: 2 for parse errors, 3 for annotation errors, 4 for I/O errors, 5 for encoding errors."),
+            Arg::with_name("error-report")
+                .long("error-report")
+                .takes_value(true)
+                .requires("keep-going")
+                .help("With --keep-going, write the report of failures to this file instead of stderr."),
+            Arg::with_name("timing-json")
+                .long("timing-json")
+                .takes_value(true)
+                .help("Write the time spent in each phase of encoding (parse, annotation, entropy coding, compression, ...) to this file, as JSON. Dictionary lookups are only timed when built with feature `trace-symbols`."),
+            Arg::with_name("progress")
+                .long("progress")
+                .help("Display a progress bar on stderr. Implies --quiet."),
+            Arg::with_name("grammar")
+                .long("grammar")
+                .takes_value(true)
+                .conflicts_with("archive")
+                .help("A WebIDL grammar, loaded at runtime instead of the grammar compiled into the encoder. The AST is still parsed and annotated as ES6, then encoded following this grammar, which makes it possible to experiment with changes to the grammar without rebuilding. Slower than the compiled grammar."),
+            Arg::with_name("source-positions")
+                .long("source-positions")
+                .conflicts_with_all(&["archive", "grammar"])
+                .help("Store the source positions of nodes and the comments of the source in a side section, so that the decoder may reattach them. JavaScript sources only. Multipart format only."),
+            Arg::with_name("emit-test-vectors")
+                .long("emit-test-vectors")
+                .takes_value(true)
+                .value_name("DIR")
+                .conflicts_with_all(&["in", "out", "archive", "watch", "grammar", "source-positions"])
+                .help("Instead of encoding sources, write a set of canonical small sources to this directory, alongside their exact encoding with the chosen format and their decoded AST, as conformance vectors for independent implementations of the format. See `index.json` in the directory for the list of vectors."),
+            Arg::with_name("parser-daemons")
+                .long("parser-daemons")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("0")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number of daemons: {}", e)))
+                .help("Parse sources in long-lived Node processes, keeping up to N of them alive between files, instead of launching Node for each file. Much faster on large corpora. If 0, launch Node for each file."),
+            Arg::with_name("parser-cmd")
+                .long("parser-cmd")
+                .takes_value(true)
+                .value_name("CMD")
+                .help("Parse JavaScript sources with this command (a program and its arguments, separated by spaces), e.g. a wrapper around esprima or Babel, instead of the bundled Shift parser. The command must implement the line-delimited JSON protocol documented in `binjs::source::external`. TypeScript and JSX sources are still parsed with Babel."),
+            Arg::with_name("source-type")
+                .long("source-type")
+                .takes_value(true)
+                .possible_values(&["script", "module", "auto"])
+                .default_value("script")
+                .help("Parse sources as scripts, as ES modules, or detect it: with `auto`, .mjs files are modules, .cjs files are scripts, and other sources are parsed as scripts, then as modules if they contain `import` or `export` declarations."),
+            Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Cache the parsed and annotated AST of each source in this directory, keyed by a hash of the source, and reuse it instead of parsing sources whose AST is in the cache. Laziness is introduced after reading the cache, so runs with different --lazify options share entries. The directory may be shared by concurrent runs."),
+            Arg::with_name("cache-max-mb")
+                .long("cache-max-mb")
+                .takes_value(true)
+                .requires("cache-dir")
+                .validator(|s| s.parse::<u64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number of megabytes: {}", e)))
+                .help("After encoding, evict the least recently written entries of the cache until it fits in this many megabytes."),
+            Arg::with_name("cache-max-days")
+                .long("cache-max-days")
+                .takes_value(true)
+                .requires("cache-dir")
+                .validator(|s| s.parse::<u64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number of days: {}", e)))
+                .help("After encoding, evict the entries of the cache written more than this many days ago."),
+            Arg::with_name("clear-cache")
+                .long("clear-cache")
+                .requires("cache-dir")
+                .help("Remove all entries of the cache before encoding."),
         ])
         .subcommand(binjs::io::Format::subcommand())
         .get_matches();
@@ -257,8 +959,12 @@ fn main_aux() {
     let sources : Vec<_> = matches.values_of("in")
         .map_or_else(|| Vec::new(),
                      |input| input
+                     .filter(|path| *path != "-")
                      .map(Path::new)
                      .collect());
+    if !sources.is_empty() && matches.values_of("in").map_or(false, |mut input| input.any(|path| path == "-")) {
+        panic!("Cannot read from stdin and from files at the same time");
+    }
 
     let dest_dir = if sources.len() == 0 {
         // If --in is not specified, --out is not used even if specified.
@@ -266,63 +972,396 @@ fn main_aux() {
         None
     } else {
         match matches.value_of("out") {
-            None => None,
+            None | Some("-") => None,
             Some(path) => Some(Path::new(path).to_path_buf())
         }
     };
 
-    let quiet = matches.is_present("quiet") || dest_dir.is_none();
+    let archive_path = matches.value_of("archive");
+    let show_progress = matches.is_present("progress");
+    let to_stdout = match archive_path {
+        Some(path) => path == "-",
+        None => dest_dir.is_none()
+    };
+    let quiet = matches.is_present("quiet") || show_progress || to_stdout;
 
     // Format options.
-    let format = binjs::io::Format::from_matches(&matches)
+    let mut format = binjs::io::Format::from_matches(&matches)
         .expect("Could not parse encoding format");
     progress!(quiet, "Using format: {}", format.name());
 
+    if let Some(path) = matches.value_of("sign-key") {
+        let key = binjs::io::bytes::signature::read_key(path)
+            .expect("Could not read signing key");
+        let public = binjs::io::bytes::signature::public_key(&key)
+            .expect("Invalid signing key");
+        progress!(quiet, "Signing with public key {}", binjs::io::bytes::signature::to_hex(&public));
+        format.multipart_options_mut()
+            .expect("Signatures are only supported by the multipart format")
+            .integrity.sign_key = Some(key);
+    }
+
+    if let Some(path) = matches.value_of("encryption-key") {
+        let key = binjs::io::bytes::signature::read_key(path)
+            .expect("Could not read encryption key");
+        format.multipart_options_mut()
+            .expect("Encryption is only supported by the multipart format")
+            .integrity.encryption_key = Some(key);
+    }
+
+    if matches.is_present("metadata") {
+        let format_options = format!("{} ({})", format.name(), format.options());
+        let options = format.multipart_options_mut()
+            .expect("Metadata is only supported by the multipart format");
+        let mut metadata = Metadata::new()
+            .with(Metadata::ENCODER, concat!("binjs_encode ", env!("CARGO_PKG_VERSION")))
+            .with(Metadata::OPTIONS, &format_options);
+        if let Some(ref dictionary) = options.string_dictionary {
+            metadata = metadata.with(Metadata::DICTIONARY, &binjs::io::bytes::signature::to_hex(dictionary.hash()));
+        }
+        options.metadata = Some(metadata);
+    }
+
+    if matches.is_present("reproducible") {
+        // Other formats neither record metadata nor encrypt.
+        if let Some(options) = format.multipart_options_mut() {
+            options.reproducible = true;
+        }
+    }
+
+    if let Some(dir) = matches.value_of("emit-test-vectors") {
+        // Nothing is written to stdout.
+        let quiet = matches.is_present("quiet");
+        progress!(quiet, "Writing test vectors to {}.", dir);
+        let index = binjs::util::vectors::emit(&Shift::new(), &mut format, dir)
+            .expect("Could not emit test vectors");
+        progress!(quiet, "Wrote {} test vectors.", index["vectors"].len());
+        return;
+    }
+
     let show_stats = matches.is_present("statistics");
 
+    let timings = binjs::util::timing::Timings::new();
+    let timing_path = matches.value_of("timing-json");
+    if timing_path.is_some() {
+        timings.install()
+            .expect("Could not install timing subscriber");
+    }
+
     // Setup.
-    let parser = Shift::new();
+    let source_positions = matches.is_present("source-positions");
+    if source_positions && format.multipart_options_mut().is_none() {
+        panic!("Source positions are only supported by the multipart format");
+    }
+    let profile = matches.value_of("profile")
+        .map(|path| StartupProfile::from_file(path)
+            .unwrap_or_else(|e| panic!("Could not read profile {:?}: {:?}", path, e)));
+    let lazification = match profile {
+        Some(ref profile) => Policy::Profile(profile.clone()),
+        None => Policy::parse(matches.value_of("lazify").expect("Missing lazify"))
+            .unwrap() // Checked by the validator.
+    };
+    let cache = matches.value_of("cache-dir")
+        .map(|dir| {
+            let max_bytes = matches.value_of("cache-max-mb")
+                .map(|mb| mb.parse::<u64>()
+                    .unwrap() // Checked by the validator.
+                    * 1024 * 1024);
+            let max_age = matches.value_of("cache-max-days")
+                .map(|days| Duration::from_secs(days.parse::<u64>()
+                    .unwrap() // Checked by the validator.
+                    * 24 * 60 * 60));
+            AnnotationCache::new(dir)
+                .expect("Could not find or create cache directory")
+                .with_max_bytes(max_bytes)
+                .with_max_age(max_age)
+        });
+    if let Some(ref cache) = cache {
+        if matches.is_present("clear-cache") {
+            progress!(quiet, "Clearing cache.");
+            cache.clear()
+                .expect("Could not clear cache");
+        }
+    }
+    let daemons = match matches.value_of("parser-daemons")
+        .unwrap() // Guaranteed by `clap`.
+        .parse::<usize>()
+        .unwrap() // Checked by the validator.
+    {
+        0 => None,
+        max_idle => Some(Arc::new(DaemonPool::new("node", max_idle)))
+    };
+    let source_type = SourceType::from_name(matches.value_of("source-type")
+        .unwrap()) // Guaranteed by `clap`.
+        .unwrap(); // Checked by `clap`.
+    // Cache entries always contain positions, whether or not this run needs them.
+    let parser = Shift::new()
+        .with_positions(source_positions || lazification.needs_locations() || cache.is_some())
+        .with_source_type(source_type)
+        .with_daemons(daemons.clone());
+    let external = matches.value_of("parser-cmd")
+        .map(|command| {
+            let external = External::new(command)
+                .with_source_type(source_type)
+                .with_positions(source_positions || lazification.needs_locations() || cache.is_some());
+            let capabilities = external.capabilities()
+                .expect("Could not launch parser command");
+            progress!(quiet, "Using parser {} ({:?} ASTs).", command, capabilities.ast);
+            external
+        });
+    let mut babel = HashMap::new();
+    let typescript = matches.is_present("typescript");
+    let jsx = matches.is_present("jsx");
+    let jsx_pragma = matches.value_of("jsx-pragma")
+        .unwrap(); // Guaranteed by `clap`.
+    let jsx_pragma_frag = matches.value_of("jsx-pragma-frag")
+        .unwrap(); // Guaranteed by `clap`.
+    if typescript {
+        babel.insert("ts", Babel::new()
+            .with_daemons(daemons.clone())
+            .with_source_type(source_type)
+            .with_typescript(true));
+        // `.tsx` files may always contain JSX.
+        babel.insert("tsx", Babel::new()
+            .with_daemons(daemons.clone())
+            .with_source_type(source_type)
+            .with_typescript(true)
+            .with_jsx(jsx_pragma, jsx_pragma_frag));
+    }
+    if jsx {
+        babel.insert("jsx", Babel::new()
+            .with_daemons(daemons.clone())
+            .with_source_type(source_type)
+            .with_jsx(jsx_pragma, jsx_pragma_frag));
+    }
+
 
-    let lazification = str::parse(matches.value_of("lazify").expect("Missing lazify"))
-        .expect("Invalid number");
+    let grammar = matches.value_of("grammar")
+        .map(|path| {
+            let path = Path::new(path);
+            let mut source = String::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut source))
+                .expect("Could not read grammar");
+            let spec = binjs::meta::import::Importer::load(&source, "Script")
+                .expect("Could not parse grammar");
+            let name = path.file_stem()
+                .and_then(std::ffi::OsStr::to_str)
+                .unwrap_or("unknown");
+            binjs::generic::io::Encoder::new(&spec, name)
+        });
+    if let Some(ref encoder) = grammar {
+        progress!(quiet, "Using grammar: {}", encoder.grammar());
+    }
+
+    let mut parser_options = match matches.value_of("parser-cmd") {
+        None => format!("jsx-pragma={};jsx-pragma-frag={}", jsx_pragma, jsx_pragma_frag),
+        Some(command) => format!("jsx-pragma={};jsx-pragma-frag={};parser-cmd={}", jsx_pragma, jsx_pragma_frag, command),
+    };
+    if source_type != SourceType::Script {
+        // Scripts keep the keys of earlier versions.
+        parser_options.push_str(&format!(";source-type={}", source_type.name()));
+    }
 
     let mut options = Options {
         parser: &parser,
+        babel: &babel,
+        external: external.as_ref(),
         format,
         dest_dir,
         lazification,
+        profile,
+        reorder_functions: matches.is_present("reorder-functions"),
         show_ast: matches.is_present("show-ast"),
-        quiet
+        quiet,
+        archive: archive_path.map(|_| vec![]),
+        progress: None,
+        keep_going: matches.is_present("keep-going"),
+        failures: vec![],
+        grammar: grammar.as_ref(),
+        source_positions,
+        allow_invalid_identifiers: matches.is_present("allow-invalid-identifiers"),
+        cache,
+        parser_options,
+        explain: if matches.is_present("explain") {
+            Some(matches.value_of("explain-top")
+                .unwrap() // Guaranteed by `clap`.
+                .parse()
+                .unwrap()) // Checked by the validator.
+        } else {
+            None
+        },
+        compare_sources: matches.is_present("compare-sources"),
+        source_compression: SourceCompression::default(),
+        encoded_bytes: 0,
+        stats_csv: matches.value_of("stats-csv")
+            .map(|path| {
+                let file = File::create(path)
+                    .unwrap_or_else(|e| panic!("Could not create statistics file {:?}: {:?}", path, e));
+                CsvWriter::new(BufWriter::new(file))
+                    .expect("Could not write statistics file")
+            }),
+        aggregate: if matches.is_present("stats-aggregate") {
+            Some(Aggregate::default())
+        } else {
+            None
+        },
     };
 
+    if show_progress {
+        let total_files = if sources.len() == 0 {
+            1
+        } else {
+            sources.iter()
+                .map(|path| count_files(&babel, path))
+                .sum()
+        };
+        options.progress = Some(ProgressBar::new(total_files));
+    }
+
     if sources.len() == 0 {
         // Use stdin if --in is not specified.
         let mut buffer = String::new();
         stdin().read_to_string(&mut buffer)
             .expect("Failed to read from stdin");
 
-        handle_path_or_text(&mut options, EncodeParams {
+        let result = handle_path_or_text(&mut options, EncodeParams {
             source: Source::FromStdin { text: buffer },
+            entry: None,
             dest_bin_path: None,
             dest_txt_path: None
         });
+        if let Err(failure) = result {
+            report_failure(&mut options, failure);
+        }
     } else {
-        for source_path in sources {
+        for source_path in &sources {
             handle_path(&mut options, source_path, PathBuf::new().as_path());
         }
     }
 
+    if let Some(ref cache) = options.cache {
+        let eviction = cache.evict()
+            .expect("Could not evict cache entries");
+        progress!(options.quiet, "Cache: evicted {} entries ({} bytes), keeping {} entries ({} bytes).",
+            eviction.removed_entries, eviction.removed_bytes,
+            eviction.remaining_entries, eviction.remaining_bytes);
+    }
+
+    if matches.is_present("watch") {
+        watch(&mut options, &sources);
+    }
+
+    if let (Some(path), Some(ref archive)) = (archive_path, options.archive.as_ref()) {
+        progress!(options.quiet, "Encoding archive with {} entries.", archive.len());
+        let entries : Vec<_> = archive.iter()
+            .map(|&(ref name, ref ast)| (name.as_str(), ast))
+            .collect();
+        if let Some(ref mut bar) = options.progress {
+            bar.phase(Phase::Encode);
+        }
+        stamp_metadata(&mut options.format);
+        let data = Encoder::new()
+            .with_invalid_identifiers(options.allow_invalid_identifiers)
+            .encode_archive(&mut options.format, &entries)
+            .expect("Could not encode archive");
+        if let Some(ref mut bar) = options.progress {
+            bar.bytes((*data).as_ref().len());
+        }
+        if path == "-" {
+            write_stdout((*data).as_ref());
+        } else {
+            progress!(options.quiet, "Writing archive {}.", path);
+            let mut dest = File::create(path)
+                .unwrap_or_else(|e| panic!("Could not create destination file {:?}: {:?}", path, e));
+            dest.write((*data).as_ref())
+                .expect("Could not write destination file");
+        }
+    }
+
+    if let Some(ref bar) = options.progress {
+        bar.finish();
+    }
+
+    if let Some(path) = timing_path {
+        progress!(options.quiet, "Writing timings to {}.", path);
+        let mut dest = File::create(path)
+            .unwrap_or_else(|e| panic!("Could not create timing file {:?}: {:?}", path, e));
+        dest.write_all(timings.to_json().pretty(2).as_bytes())
+            .expect("Could not write timing file");
+    }
+
     if show_stats {
         match options.format {
             Format::Multipart { ref stats, .. } => {
                 progress!(options.quiet, "Statistics: {}", stats.borrow());
             }
-            Format::Entropy { options: ref entropy } => {
+            Format::Entropy { options: ref entropy } |
+            Format::HuffmanEntropy { options: ref entropy } => {
+                progress!(options.quiet, "Statistics: {}", entropy.statistics_for_write());
+                if let Some(recency) = entropy.recency_statistics_for_write() {
+                    progress!(options.quiet, "{}", recency);
+                }
+                if let Some(fallback) = entropy.fallback_statistics_for_write() {
+                    progress!(options.quiet, "{}", fallback);
+                }
+                progress!(options.quiet, "{}", entropy.entropy_bounds_for_write());
+            }
+            Format::AdaptiveEntropy { options: ref entropy } => {
                 progress!(options.quiet, "Statistics: {}", entropy.statistics_for_write());
             }
+            Format::Templates { options: ref templates } => {
+                progress!(options.quiet, "Statistics: {}", templates.statistics_for_write());
+            }
+            Format::Dag { options: ref dag } => {
+                progress!(options.quiet, "Statistics: {}", dag.statistics_for_write());
+            }
             _ => {
                 progress!(options.quiet, "No stats available for this format");
             }
         }
+        match options.format {
+            Format::Multipart { .. } => {
+                // Already included in the statistics.
+            }
+            _ if options.compare_sources => {
+                progress!(options.quiet, "Comparison with compressed sources:\n{}", options.source_compression.compare(options.encoded_bytes));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(ref mut csv) = options.stats_csv {
+        csv.flush()
+            .expect("Could not write statistics file");
+    }
+
+    if let Some(ref aggregate) = options.aggregate {
+        // Requested explicitly, so shown even with --quiet.
+        if to_stdout {
+            eprint!("{}", aggregate);
+        } else {
+            print!("{}", aggregate);
+        }
+    }
+
+    if options.keep_going {
+        let mut report = array![];
+        for failure in &options.failures {
+            report.push(failure.to_json())
+                .expect("Report is an array");
+        }
+        match matches.value_of("error-report") {
+            Some(path) => {
+                let mut dest = File::create(path)
+                    .unwrap_or_else(|e| panic!("Could not create error report {:?}: {:?}", path, e));
+                dest.write_all(report.pretty(2).as_bytes())
+                    .expect("Could not write error report");
+            }
+            None => eprintln!("{}", report.pretty(2))
+        }
+        if let Some(failure) = options.failures.first() {
+            std::process::exit(exit_code(failure.error.kind()));
+        }
     }
 }
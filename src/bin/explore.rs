@@ -0,0 +1,409 @@
+//! Explore a BinJS file in a terminal: navigate the decoded tree and inspect
+//! the bytes encoding each node.
+//!
+//! The screen is split into three panes: the tree, the bytes of the selected node,
+//! each range labelled with what it encodes, and statistics on the selected subtree.
+//! Multipart format only.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate termion;
+
+use binjs::io::Deserialization;
+use binjs::io::multipart::{ AnnotatedHex, Annotation, Options, StructureNode };
+use binjs::specialized::es6::ast::{ IOPath, Program };
+use binjs::specialized::es6::io::Deserializer;
+
+use std::collections::{ HashMap, HashSet };
+use std::fs::File;
+use std::io::{ Write, stdin, stdout };
+
+use clap::{ App, Arg };
+use termion::cursor::Goto;
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+/// The maximal height of the statistics pane, in lines.
+const STATS_HEIGHT: usize = 12;
+
+/// The maximal number of bytes shown for each annotation in the bytes pane.
+const BYTES_PER_ANNOTATION: usize = 8;
+
+const HELP: &str = "up/down: move  right: expand  left: collapse  e: expand all  PgUp/PgDn: scroll  q: quit";
+
+/// A visible line of the tree pane.
+struct Row {
+    /// The index of the node among the roots, then among the children of each ancestor.
+    path: Vec<usize>,
+    depth: usize,
+}
+
+struct Explorer {
+    roots: Vec<StructureNode>,
+
+    /// The annotations of the tree, as offsets in `data`.
+    annotations: Vec<Annotation>,
+
+    /// The decompressed tree.
+    data: Vec<u8>,
+
+    /// The offset of the tree in the file, if it is stored uncompressed.
+    /// Offsets are then shown in the file rather than in the decompressed tree.
+    tree_start: Option<usize>,
+
+    /// The paths of the nodes whose children are visible.
+    expanded: HashSet<Vec<usize>>,
+
+    rows: Vec<Row>,
+    selected: usize,
+
+    /// The first visible row.
+    scroll: usize,
+}
+impl Explorer {
+    fn new(roots: Vec<StructureNode>, annotations: Vec<Annotation>, data: Vec<u8>, tree_start: Option<usize>) -> Self {
+        let expanded = (0..roots.len())
+            .map(|index| vec![index])
+            .collect();
+        let mut explorer = Explorer {
+            roots,
+            annotations,
+            data,
+            tree_start,
+            expanded,
+            rows: vec![],
+            selected: 0,
+            scroll: 0,
+        };
+        explorer.update_rows();
+        explorer
+    }
+
+    fn node(&self, path: &[usize]) -> &StructureNode {
+        let mut node = &self.roots[path[0]];
+        for &index in &path[1..] {
+            node = &node.children[index];
+        }
+        node
+    }
+
+    fn selected_node(&self) -> Option<&StructureNode> {
+        self.rows.get(self.selected)
+            .map(|row| self.node(&row.path))
+    }
+
+    /// An offset in the decompressed tree, as shown to the user.
+    fn offset(&self, offset: usize) -> usize {
+        self.tree_start.unwrap_or(0) + offset
+    }
+
+    fn update_rows(&mut self) {
+        let mut rows = vec![];
+        for (index, root) in self.roots.iter().enumerate() {
+            Self::collect_rows(root, vec![index], 0, &self.expanded, &mut rows);
+        }
+        self.rows = rows;
+        if self.selected >= self.rows.len() {
+            self.selected = self.rows.len().saturating_sub(1);
+        }
+    }
+
+    fn collect_rows(node: &StructureNode, path: Vec<usize>, depth: usize, expanded: &HashSet<Vec<usize>>, rows: &mut Vec<Row>) {
+        let is_expanded = expanded.contains(&path);
+        rows.push(Row {
+            path: path.clone(),
+            depth,
+        });
+        if is_expanded {
+            for (index, child) in node.children.iter().enumerate() {
+                let mut child_path = path.clone();
+                child_path.push(index);
+                Self::collect_rows(child, child_path, depth + 1, expanded, rows);
+            }
+        }
+    }
+
+    /// The paths of `node` and its descendants that have children.
+    fn collect_parents(node: &StructureNode, path: Vec<usize>, paths: &mut Vec<Vec<usize>>) {
+        if node.children.is_empty() {
+            return;
+        }
+        for (index, child) in node.children.iter().enumerate() {
+            let mut child_path = path.clone();
+            child_path.push(index);
+            Self::collect_parents(child, child_path, paths);
+        }
+        paths.push(path);
+    }
+
+    /// Handle a key, returning `false` to quit.
+    ///
+    /// `page` is the number of rows moved by PgUp/PgDn.
+    fn handle(&mut self, key: Key, page: usize) -> bool {
+        let last = self.rows.len().saturating_sub(1);
+        match key {
+            Key::Char('q') | Key::Esc | Key::Ctrl('c') => return false,
+            Key::Up | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => self.selected = std::cmp::min(self.selected + 1, last),
+            Key::PageUp => self.selected = self.selected.saturating_sub(page),
+            Key::PageDown => self.selected = std::cmp::min(self.selected + page, last),
+            Key::Home | Key::Char('g') => self.selected = 0,
+            Key::End | Key::Char('G') => self.selected = last,
+            Key::Right | Key::Char('l') | Key::Char('\n') => self.expand(),
+            Key::Left | Key::Char('h') => self.collapse(),
+            Key::Char('e') => self.expand_all(),
+            _ => {}
+        }
+        true
+    }
+
+    fn expand(&mut self) {
+        let path = match self.rows.get(self.selected) {
+            Some(row) => row.path.clone(),
+            None => return
+        };
+        if self.node(&path).children.is_empty() {
+            return;
+        }
+        if self.expanded.contains(&path) {
+            // Move to the first child.
+            self.selected += 1;
+        } else {
+            self.expanded.insert(path);
+            self.update_rows();
+        }
+    }
+
+    fn expand_all(&mut self) {
+        let path = match self.rows.get(self.selected) {
+            Some(row) => row.path.clone(),
+            None => return
+        };
+        let mut paths = vec![];
+        Self::collect_parents(self.node(&path), path.clone(), &mut paths);
+        self.expanded.extend(paths);
+        self.update_rows();
+    }
+
+    fn collapse(&mut self) {
+        let path = match self.rows.get(self.selected) {
+            Some(row) => row.path.clone(),
+            None => return
+        };
+        if self.expanded.remove(&path) {
+            self.update_rows();
+        } else if path.len() > 1 {
+            // Move to the parent.
+            let parent = &path[..path.len() - 1];
+            if let Some(index) = self.rows.iter().position(|row| &row.path[..] == parent) {
+                self.selected = index;
+            }
+        }
+    }
+
+    fn row_text(&self, row: &Row, width: usize) -> String {
+        let node = self.node(&row.path);
+        let marker =
+            if node.children.is_empty() {
+                " "
+            } else if self.expanded.contains(&row.path) {
+                "-"
+            } else {
+                "+"
+            };
+        let field = match node.field {
+            Some(ref field) => format!(".{}: ", field),
+            None => String::new()
+        };
+        let left = format!("{}{} {}{}", "  ".repeat(row.depth), marker, field, node.label);
+        let right = format!(" {}B", node.byte_len());
+        let left_width = width.saturating_sub(right.chars().count());
+        let mut text = truncate(&left, left_width);
+        let padding = left_width - text.chars().count();
+        text.push_str(&" ".repeat(padding));
+        text.push_str(&right);
+        truncate(&text, width)
+    }
+
+    /// The lines of the bytes pane: each range of bytes encoding the selected subtree,
+    /// with what it encodes.
+    fn bytes_lines(&self, height: usize) -> Vec<String> {
+        let node = match self.selected_node() {
+            Some(node) => node,
+            None => return vec![]
+        };
+        let mut lines = vec![
+            format!("Bytes {:08x}..{:08x}{}",
+                self.offset(node.start),
+                self.offset(node.end),
+                if self.tree_start.is_some() { "" } else { " (decompressed tree)" }),
+            String::new(),
+        ];
+        let annotations : Vec<_> = self.annotations.iter()
+            .filter(|annotation| annotation.start < annotation.end && annotation.start >= node.start && annotation.end <= node.end)
+            .collect();
+        let available = height.saturating_sub(lines.len());
+        let shown = if annotations.len() > available {
+            available.saturating_sub(1)
+        } else {
+            annotations.len()
+        };
+        for annotation in &annotations[..shown] {
+            let bytes = &self.data[annotation.start..annotation.end];
+            let mut hex = bytes.iter()
+                .take(BYTES_PER_ANNOTATION)
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<Vec<String>>()
+                .join(" ");
+            if bytes.len() > BYTES_PER_ANNOTATION {
+                hex.push_str("..");
+            }
+            lines.push(format!("{:08x}: {:<25} {}", self.offset(annotation.start), hex, annotation.label));
+        }
+        if shown < annotations.len() {
+            lines.push(format!("... and {} more", annotations.len() - shown));
+        }
+        lines.truncate(height);
+        lines
+    }
+
+    /// The lines of the statistics pane: the size of the selected subtree and the
+    /// bytes spent on each kind of node, excluding their descendants.
+    fn stats_lines(&self, height: usize) -> Vec<String> {
+        let node = match self.selected_node() {
+            Some(node) => node,
+            None => return vec![]
+        };
+        let descendants = node.descendants();
+        let total : usize = self.roots.iter()
+            .map(StructureNode::byte_len)
+            .sum();
+        let mut lines = vec![
+            format!("Subtree: {} nodes, {} bytes ({:.1}% of the tree)",
+                descendants.len(),
+                node.byte_len(),
+                if total == 0 { 0. } else { 100. * node.byte_len() as f64 / total as f64 }),
+            format!("{:<30} {:>7} {:>9}", "kind", "count", "own bytes"),
+        ];
+
+        let mut by_kind : HashMap<&str, (usize, usize)> = HashMap::new();
+        for descendant in &descendants {
+            // e.g. `string="foo"` is counted as `string`, `list (length=3)` as `list`.
+            let kind = descendant.label.split(|c: char| c == '=' || c == ' ')
+                .next()
+                .unwrap_or("");
+            let entry = by_kind.entry(kind)
+                .or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += descendant.own_byte_len();
+        }
+        let mut by_kind : Vec<_> = by_kind.into_iter()
+            .collect();
+        by_kind.sort_by(|a, b| (b.1).1.cmp(&(a.1).1).then(a.0.cmp(&b.0)));
+        for (kind, (count, bytes)) in by_kind.into_iter().take(height.saturating_sub(lines.len())) {
+            lines.push(format!("{:<30} {:>7} {:>9}", kind, count, bytes));
+        }
+        lines.truncate(height);
+        lines
+    }
+
+    fn draw<W: Write>(&mut self, out: &mut W) -> Result<(), std::io::Error> {
+        let (width, height) = termion::terminal_size()?;
+        let (width, height) = (width as usize, height as usize);
+        let tree_width = width * 3 / 5;
+        let side_width = width.saturating_sub(tree_width + 1);
+        // The last line shows the keys.
+        let body_height = height.saturating_sub(1);
+        let stats_height = std::cmp::min(STATS_HEIGHT, body_height / 2);
+        let bytes_height = body_height - stats_height;
+
+        // Keep the selection visible.
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if body_height > 0 && self.selected >= self.scroll + body_height {
+            self.scroll = self.selected + 1 - body_height;
+        }
+
+        write!(out, "{}", termion::clear::All)?;
+        for line in 0..body_height {
+            let index = self.scroll + line;
+            if let Some(row) = self.rows.get(index) {
+                let text = self.row_text(row, tree_width);
+                if index == self.selected {
+                    write!(out, "{}{}{}{}", Goto(1, line as u16 + 1), termion::style::Invert, text, termion::style::Reset)?;
+                } else {
+                    write!(out, "{}{}", Goto(1, line as u16 + 1), text)?;
+                }
+            }
+            write!(out, "{}|", Goto(tree_width as u16 + 1, line as u16 + 1))?;
+        }
+
+        let x = tree_width as u16 + 2;
+        for (line, text) in self.bytes_lines(bytes_height).into_iter().enumerate() {
+            write!(out, "{}{}", Goto(x, line as u16 + 1), truncate(&text, side_width))?;
+        }
+        for (line, text) in self.stats_lines(stats_height).into_iter().enumerate() {
+            write!(out, "{}{}", Goto(x, (bytes_height + line) as u16 + 1), truncate(&text, side_width))?;
+        }
+        write!(out, "{}{}", Goto(1, height as u16), truncate(HELP, width))?;
+        out.flush()
+    }
+}
+
+/// The first `width` characters of `text`.
+fn truncate(text: &str, width: usize) -> String {
+    text.chars()
+        .take(width)
+        .collect()
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS explorer")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Explore a JavaScript BinJS file in the terminal: navigate the decoded tree and inspect how each node is encoded.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("Input file to use. Must be a BinJS file in the multipart format, not an archive."),
+        ])
+        .get_matches();
+
+    let source_path = matches.value_of("INPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let file = File::open(source_path)
+        .expect("Could not open source");
+    let (dump, reader) = AnnotatedHex::new(file, &Options::default())
+        .expect("Could not decode as multipart");
+    let mut deserializer = Deserializer::new(reader);
+    let _tree : Program = deserializer.deserialize(&mut IOPath::new())
+        .expect("Could not decode");
+
+    let explorer = {
+        let tree = dump.tree();
+        Explorer::new(tree.structure(), tree.annotations.clone(), tree.data.clone(), dump.tree_start())
+    };
+    explore(explorer)
+        .expect("Could not run the explorer");
+}
+
+fn explore(mut explorer: Explorer) -> Result<(), std::io::Error> {
+    let mut screen = AlternateScreen::from(stdout().into_raw_mode()?);
+    write!(screen, "{}", termion::cursor::Hide)?;
+    explorer.draw(&mut screen)?;
+    for key in stdin().keys() {
+        let page = termion::terminal_size()
+            .map(|(_, height)| std::cmp::max(height as usize, 2) - 1)
+            .unwrap_or(1);
+        if !explorer.handle(key?, page) {
+            break;
+        }
+        explorer.draw(&mut screen)?;
+    }
+    write!(screen, "{}", termion::cursor::Show)?;
+    screen.flush()
+}
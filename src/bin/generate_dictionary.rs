@@ -7,11 +7,12 @@ extern crate clap;
 extern crate env_logger;
 extern crate log;
 
-use binjs::source::{ Shift, SourceParser };
+use binjs::source::{ Shift, SourceParser, SourceType };
 use binjs::generic::FromJSON;
 use binjs::specialized::es6::ast::Walker;
 use binjs::io::{ Path as IOPath, TokenSerializer };
 use binjs::io::entropy::dictionary::{ Dictionary, DictionaryBuilder, KindedStringMap, FilesContaining, Instances };
+use binjs::io::entropy::smoothing::{ Method, Smoothing };
 
 use std::fs::*;
 use std::thread;
@@ -80,10 +81,10 @@ fn handle_path_or_text<'a>(options: &mut Options<'a>,
     let json = options.parser.parse_file(source)
         .expect("Could not parse source");
 
-    let mut ast = binjs::specialized::es6::ast::Script::import(&json)
+    let mut ast = binjs::specialized::es6::ast::Program::import(&json)
         .expect("Could not import AST");
     binjs::specialized::es6::scopes::AnnotationVisitor::new()
-        .annotate_script(&mut ast);
+        .annotate_program(&mut ast);
 
     if options.lazification > 0 {
         progress!(options.quiet, "Introducing laziness.");
@@ -166,7 +167,7 @@ fn main_aux() {
                 .validator(|s| s.parse::<u32>()
                     .map(|_| ())
                     .map_err(|e| format!("Invalid number {}", e)))
-                .help("Maximal path length to store in the dictionary."),
+                .help("Maximal path length to store in the dictionary. 0 = no context, 1 = parent and field, 2 = also grand parent, etc. Encoders may use any smaller depth with this dictionary."),
             Arg::with_name("window-width")
                 .long("window-width")
                 .takes_value(true)
@@ -175,6 +176,39 @@ fn main_aux() {
                     .map(|_| ())
                     .map_err(|e| format!("Invalid number {}", e)))
                 .help("String window width."),
+            Arg::with_name("max-size")
+                .long("max-size")
+                .takes_value(true)
+                .validator(|s| s.parse::<u64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Prune the dictionary to at most this number of bytes, dropping the entries that cover the fewest symbols per byte. Values dropped from the dictionary can no longer be encoded."),
+            Arg::with_name("cutoff")
+                .long("cutoff")
+                .takes_value(true)
+                .default_value("1")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Drop the values that appear fewer than this number of times at their path. Values dropped from the dictionary can no longer be encoded."),
+            Arg::with_name("smoothing")
+                .long("smoothing")
+                .takes_value(true)
+                .possible_values(&Method::names())
+                .help("Smooth the probabilities of the dictionary, so that values that are rare in the sample are not too expensive to encode."),
+            Arg::with_name("smoothing-parameter")
+                .long("smoothing-parameter")
+                .takes_value(true)
+                .requires("smoothing")
+                .validator(|s| s.parse::<f64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("The pseudo-count added by laplace smoothing (default 1) or the discount of kneser-ney smoothing (default 0.75)."),
+            Arg::with_name("held-out")
+                .long("held-out")
+                .multiple(true)
+                .takes_value(true)
+                .help("JS source files, not used to build the dictionary, on which to report the cross-entropy of the dictionary before and after smoothing. May be specified multiple times."),
         ])
         .get_matches();
 
@@ -205,7 +239,8 @@ fn main_aux() {
         width = width);
 
     // Setup.
-    let parser = Shift::new();
+    let parser = Shift::new()
+        .with_source_type(SourceType::Auto);
     let mut dictionary = Dictionary::new(depth, width);
     let mut files_containing_string = KindedStringMap::default();
     let mut number_of_files = 0;
@@ -224,8 +259,55 @@ fn main_aux() {
 
     progress!(quiet, "Successfully generated dictionary from {} files", number_of_files);
 
+    // Process held-out files, into a dictionary of their own.
+    let held_out = matches.values_of("held-out")
+        .map(|held_out| {
+            let mut held_out_dictionary = Dictionary::new(depth, width);
+            let mut held_out_files_containing_string = KindedStringMap::default();
+            let mut held_out_number_of_files = 0;
+            for source_path in held_out.map(Path::new) {
+                handle_path(&mut options, &mut held_out_dictionary, &mut held_out_files_containing_string, &mut held_out_number_of_files,
+                    source_path, /* local root */ Path::new(""));
+            }
+            progress!(quiet, "Successfully sampled {} held-out files", held_out_number_of_files);
+            held_out_dictionary
+        });
+
     // FIXME: Remove strings that appear in a single file.
 
+    if let Some(max_size) = matches.value_of("max-size") {
+        let max_size = str::parse(max_size)
+            .expect("Invalid number");
+        let report = dictionary.prune(max_size)
+            .expect("Could not prune dictionary");
+        progress!(quiet, "Pruned dictionary: {}", report);
+        if report.bytes_after > max_size {
+            eprintln!("Warning: even an empty dictionary exceeds {} bytes", max_size);
+        }
+    }
+
+    let cutoff = str::parse(matches.value_of("cutoff").unwrap())
+        .expect("Invalid number");
+    let mut smoothing = Smoothing::new()
+        .with_cutoff(cutoff);
+    if let Some(name) = matches.value_of("smoothing") {
+        let parameter = matches.value_of("smoothing-parameter")
+            .map(|parameter| str::parse(parameter)
+                .expect("Invalid number"));
+        let method = Method::from_name(name, parameter)
+            .unwrap(); // Guaranteed by `clap`.
+        smoothing = smoothing.with_method(method);
+    }
+    if let Some(ref held_out) = held_out {
+        progress!(quiet, "Held-out cross-entropy before smoothing: {}", dictionary.cross_entropy(held_out));
+    }
+    let report = dictionary.smooth(&smoothing);
+    progress!(quiet, "Smoothed dictionary: {}", report);
+    if let Some(ref held_out) = held_out {
+        // Report even with `--quiet`, as this is the point of `--held-out`.
+        println!("Held-out cross-entropy: {}", dictionary.cross_entropy(held_out));
+    }
+
     // Write dictionaries.
     DirBuilder::new()
         .recursive(true)
@@ -0,0 +1,72 @@
+//! Compare two versions of a WebIDL grammar, reporting added, removed and
+//! reordered declarations, and which of these changes are breaking.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::meta::diff::SpecDiff;
+use binjs::meta::import::Importer;
+use binjs::meta::spec::Spec;
+
+use std::fs::*;
+use std::io::*;
+
+use clap::*;
+
+fn load(path: &str, root: &str) -> Spec {
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", path, e));
+    Importer::load(&source, root)
+        .unwrap_or_else(|e| panic!("Could not parse {}: {:?}", path, e))
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS grammar diff")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Compare two versions of a WebIDL grammar, classifying each change as breaking or not. Exits with status 1 if any change is breaking. Decoders reject files written with another grammar in any case, as the grammar fingerprint changes.")
+        .args(&[
+            Arg::with_name("OLD")
+                .required(true)
+                .help("The old grammar, a WebIDL file."),
+            Arg::with_name("NEW")
+                .required(true)
+                .help("The new grammar, a WebIDL file."),
+            Arg::with_name("root")
+                .long("root")
+                .takes_value(true)
+                .default_value("Script")
+                .help("The root interface of both grammars."),
+        ])
+        .get_matches();
+
+    let root = matches.value_of("root")
+        .unwrap(); // Guaranteed by `clap`.
+    let old = load(matches.value_of("OLD").unwrap(), root); // Guaranteed by `clap`.
+    let new = load(matches.value_of("NEW").unwrap(), root); // Guaranteed by `clap`.
+
+    let diff = SpecDiff::new(&old, &new);
+    for change in &diff.changes {
+        println!("{}", change);
+    }
+    if diff.changes.is_empty() {
+        println!("No changes.");
+        return;
+    }
+
+    let breaking = diff.changes.iter()
+        .filter(|change| change.is_breaking())
+        .count();
+    println!("{} changes, {} breaking.", diff.changes.len(), breaking);
+    if old.fingerprint() != new.fingerprint() {
+        println!("The grammar fingerprint changes from {:016x} to {:016x}, so decoders of the new grammar reject files written with the old grammar, even if no change is breaking.",
+            old.fingerprint(), new.fingerprint());
+    }
+    if diff.is_breaking() {
+        std::process::exit(1);
+    }
+}
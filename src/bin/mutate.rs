@@ -0,0 +1,179 @@
+//! Produce corrupted variants of a valid BinJS file, labelled with the behavior
+//! expected from a decoder, to build a corpus of negative tests for engines.
+//!
+//! See `binjs::mutate` for the mutations.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate rand;
+#[macro_use]
+extern crate serde_json;
+
+use binjs::mutate::{ Expected, MutationKind, Mutator };
+
+use std::fs::*;
+use std::io::*;
+use std::path::Path;
+use std::thread;
+
+use clap::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS mutator")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Produce corrupted variants of a valid multipart BinJS file, labelled with the behavior expected from a decoder, to build a corpus of negative tests.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("A valid multipart BinJS file. Mutations of the tree require the tree to be stored uncompressed, e.g. `binjs_encode multipart --section-compression identity`."),
+            Arg::with_name("out")
+                .long("out")
+                .short("o")
+                .takes_value(true)
+                .required(true)
+                .help("The directory to which variants are written, along with `manifest.json`, which lists each variant with its mutation and expected behavior."),
+            Arg::with_name("count")
+                .long("count")
+                .short("n")
+                .takes_value(true)
+                .default_value("100")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("The number of variants to produce."),
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<u64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("The seed used to pick mutations."),
+            Arg::with_name("mutation")
+                .long("mutation")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .possible_values(&MutationKind::NAMES)
+                .help("A mutation to apply. May be specified multiple times. By default, all mutations."),
+            Arg::with_name("verify")
+                .long("verify")
+                .help("Decode each variant with the reference decoder, and fail if it does not behave as expected."),
+        ])
+        .get_matches();
+
+    let count : usize = matches.value_of("count")
+        .unwrap() // Guaranteed by `clap`.
+        .parse()
+        .unwrap(); // Checked by the validator.
+    let seed : u64 = matches.value_of("seed")
+        .unwrap() // Guaranteed by `clap`.
+        .parse()
+        .unwrap(); // Checked by the validator.
+    let kinds : Vec<MutationKind> = match matches.values_of("mutation") {
+        Some(names) => names
+            .map(|name| MutationKind::parse(name)
+                .unwrap()) // Checked by `clap`.
+            .collect(),
+        None => MutationKind::ALL.to_vec()
+    };
+
+    let mut data = vec![];
+    File::open(matches.value_of("INPUT").unwrap()) // Guaranteed by `clap`.
+        .and_then(|mut file| file.read_to_end(&mut data))
+        .expect("Could not read input");
+    let mutator = Mutator::new(data)
+        .expect("Could not read input as a multipart file");
+    if !mutator.has_uncompressed_tree() {
+        eprintln!("Warning: the tree is compressed, only truncations are possible.");
+    }
+    if mutator.has_checksums() {
+        eprintln!("Warning: the file contains checksums, which reject most mutations before the decoder reads them.");
+    }
+
+    let out = Path::new(matches.value_of("out").unwrap()); // Guaranteed by `clap`.
+    create_dir_all(out)
+        .expect("Could not create output directory");
+
+    let verify = matches.is_present("verify");
+    if verify {
+        // Report panics as failures, without cluttering stderr.
+        std::panic::set_hook(Box::new(|_| {}));
+    }
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut manifest = vec![];
+    let mut failures = 0;
+    let mut attempts = 0;
+    while manifest.len() < count {
+        // Give up if the selected mutations are impossible for this file.
+        attempts += 1;
+        if attempts > 100 * (count + 1) {
+            eprintln!("Could only produce {} variants.", manifest.len());
+            break;
+        }
+        let kind = *kinds.choose(&mut rng)
+            .unwrap(); // `clap` guarantees at least one mutation.
+        let mutant = match mutator.mutate(kind, &mut rng) {
+            Some(mutant) => mutant,
+            None => continue
+        };
+
+        let name = format!("{:04}-{}.binjs", manifest.len(), kind.name());
+        File::create(out.join(&name))
+            .and_then(|mut file| file.write_all(&mutant.data))
+            .expect("Could not write variant");
+
+        let mut entry = json!({
+            "file": name,
+            "mutation": kind.name(),
+            "offset": mutant.offset,
+            "description": mutant.description,
+            "expected": mutant.expected.name(),
+        });
+        if verify {
+            let outcome = mutant.check();
+            if !mutant.expected.accepts(outcome) {
+                eprintln!("{}: {}, expected {}, got {:?}", name, mutant.description, mutant.expected.name(), outcome);
+                failures += 1;
+            }
+            entry["outcome"] = json!(format!("{:?}", outcome).to_lowercase());
+        }
+        manifest.push(entry);
+    }
+    if verify {
+        let _ = std::panic::take_hook();
+    }
+
+    File::create(out.join("manifest.json"))
+        .and_then(|mut file| serde_json::to_writer_pretty(&mut file, &manifest)
+            .map_err(From::from))
+        .expect("Could not write manifest");
+
+    let rejects = manifest.iter()
+        .filter(|entry| entry["expected"] == Expected::Reject.name())
+        .count();
+    eprintln!("Wrote {} variants, {} of which must be rejected.", manifest.len(), rejects);
+    if failures > 0 {
+        eprintln!("{} variants were not decoded as expected.", failures);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,164 @@
+//! Reduce a source that fails to roundtrip to a minimal failing AST.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate serde_json;
+
+use binjs::generic::{ FromJSON, JSON, JSONExt };
+use binjs::io::Format;
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::reduce::Reducer;
+use binjs::source::{ Shift, SourceParser, SourceType };
+use binjs::specialized::es6::ast::Program;
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::fs::File;
+use std::io::{ Cursor, Read };
+use std::panic::AssertUnwindSafe;
+use std::thread;
+
+use clap::*;
+
+/// The manner in which an AST fails to roundtrip.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Failure {
+    /// The AST could not be encoded, or the encoder panicked.
+    Encode,
+    /// The encoded AST could not be decoded, or the decoder panicked.
+    Decode,
+    /// The decoded AST differs from the encoded AST.
+    Mismatch,
+}
+impl Failure {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "encode" => Some(Failure::Encode),
+            "decode" => Some(Failure::Decode),
+            "mismatch" => Some(Failure::Mismatch),
+            _ => None
+        }
+    }
+}
+
+/// Roundtrip `json`, returning the failure, if any.
+///
+/// ASTs that cannot be imported do not fail, as they are not valid inputs.
+fn roundtrip(format: &mut Format, json: &JSON) -> Option<Failure> {
+    let mut ast = match Program::import(json) {
+        Ok(ast) => ast,
+        Err(_) => return None
+    };
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+    let data = match std::panic::catch_unwind(AssertUnwindSafe(|| Encoder::new().encode(format, &ast))) {
+        Ok(Ok(data)) => (*data).as_ref().to_vec(),
+        _ => return Some(Failure::Encode)
+    };
+    let decoded : Program = match std::panic::catch_unwind(AssertUnwindSafe(|| Decoder::new().decode(format, Cursor::new(&data)))) {
+        Ok(Ok(decoded)) => decoded,
+        _ => return Some(Failure::Decode)
+    };
+    if decoded != ast {
+        return Some(Failure::Mismatch)
+    }
+    None
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS reducer")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Reduce a source that fails to roundtrip through BinJS to a minimal AST that fails in the same manner, then print it as JSON and JavaScript.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("The failing input. A JavaScript source, or with --json, an AST in the internal JSON format of BinJS, e.g. as printed by `binjs_encode --show-ast`."),
+            Arg::with_name("json")
+                .long("json")
+                .help("INPUT is an AST in the internal JSON format of BinJS, rather than a JavaScript source."),
+            Arg::with_name("failure")
+                .long("failure")
+                .takes_value(true)
+                .possible_values(&["encode", "decode", "mismatch", "any"])
+                .help("The failure to preserve while reducing. By default, the failure of INPUT."),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    let parser = Shift::new()
+        .with_source_type(SourceType::Auto);
+
+    let path = matches.value_of("INPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let json = if matches.is_present("json") {
+        let mut source = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut source))
+            .expect("Could not read input");
+        serde_json::from_str(&source)
+            .expect("Could not parse input as JSON")
+    } else {
+        parser.parse_file(path)
+            .expect("Could not parse input")
+    };
+
+    // Failures are expected while reducing, don't clutter stderr with panics.
+    std::panic::set_hook(Box::new(|_| {}));
+    let initial = roundtrip(&mut format, &json);
+    let _ = std::panic::take_hook();
+
+    let expected = match matches.value_of("failure") {
+        None => Some(initial.expect("The input roundtrips successfully, there is nothing to reduce")),
+        Some("any") => None,
+        Some(name) => Failure::from_name(name),
+    };
+    if initial.is_none() || (expected.is_some() && initial != expected) {
+        panic!("The input does not fail as expected, it fails with {:?}", initial);
+    }
+    eprintln!("Reducing input failing with {:?}.", initial.unwrap());
+
+    std::panic::set_hook(Box::new(|_| {}));
+    let mut reducer = Reducer::new(|candidate: &JSON| {
+        match (roundtrip(&mut format, candidate), expected) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (found, expected) => found == expected,
+        }
+    });
+    let reduced = reducer.reduce(json);
+    let _ = std::panic::take_hook();
+    eprintln!("Reduced after {} attempts, {} simplifications.",
+        reducer.statistics().attempts,
+        reducer.statistics().reductions);
+
+    println!("{}", reduced.pretty(2));
+
+    // The reduced AST may be too broken to pretty-print, e.g. if scopes don't match.
+    let mut builder = SpecBuilder::new();
+    let _ = binjs::generic::es6::Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Program"),
+    };
+    let spec = builder.into_spec(spec_options);
+    match parser.to_source(&spec, &reduced) {
+        Ok(source) => println!("{}", source),
+        Err(err) => eprintln!("Could not pretty-print the reduced AST: {:?}", err),
+    }
+}
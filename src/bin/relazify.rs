@@ -0,0 +1,123 @@
+//! Change the functions encoded as lazy in a BinJS, without going through
+//! the text source.
+//!
+//! This lets deployments tune laziness once they know which functions are
+//! used during startup.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::generic::ToJSON;
+use binjs::specialized::es6::ast::{ Script, Walker, WalkPath };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+use binjs::specialized::es6::lazy::{ EagerifierVisitor, LazifierVisitor, Policy };
+
+use std::fs::*;
+use std::io::*;
+use std::thread;
+
+use clap::*;
+
+macro_rules! progress {
+    ($quiet:expr, $($args:tt)*) => {
+        if !$quiet {
+            println!($($args)*);
+        }
+    }
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS relazifier")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Change the functions encoded as lazy in a JavaScript BinJS source, without going through the text source. The file is written with the same format.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("Input file to use. Must be a BinJS source file."),
+            Arg::with_name("OUTPUT")
+                .required(true)
+                .help("Output file to use. Will be overwritten."),
+            Arg::with_name("lazify")
+                .long("lazify")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| Policy::parse(&s)
+                    .map(|_| ()))
+                .help("Which functions to lazify, as with `binjs_encode --lazify`. Functions that were lazy in INPUT are first made eager. `min-bytes=N` and `spans=...` require the source positions stored by `binjs_encode --source-positions`."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print progress"),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let source_path = matches.value_of("INPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let dest_path = matches.value_of("OUTPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let quiet = matches.is_present("quiet");
+    let policy = Policy::parse(matches.value_of("lazify").unwrap()) // Guaranteed by `clap`.
+        .unwrap(); // Checked by the validator.
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    progress!(quiet, "Using format: {}", format.name());
+
+    progress!(quiet, "Reading {}.", source_path);
+    let mut source = vec![];
+    File::open(source_path)
+        .and_then(|mut file| file.read_to_end(&mut source))
+        .expect("Could not read source");
+    let (mut ast, positions) : (Script, _) = Decoder::new()
+        .decode_with_positions(&mut format, Cursor::new(&source))
+        .expect("Could not decode");
+
+    progress!(quiet, "Removing laziness.");
+    EagerifierVisitor::new()
+        .annotate_script(&mut ast);
+
+    let locations = if policy.needs_locations() {
+        let positions = positions.as_ref()
+            .expect("This policy requires source positions, which INPUT does not contain");
+        let mut json = ast.export();
+        binjs::source::positions::reattach(&mut json, positions)
+            .expect("Could not reattach source positions");
+        binjs::source::positions::function_locations(&json)
+    } else {
+        vec![]
+    };
+
+    if policy != Policy::none() {
+        progress!(quiet, "Introducing laziness.");
+        ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::with_policy(policy, locations))
+            .expect("Could not introduce laziness");
+    }
+
+    progress!(quiet, "Encoding.");
+    let data = Encoder::new()
+        .with_positions(positions)
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let data = (*data).as_ref();
+    progress!(quiet, "Size: {} bytes, instead of {} bytes.", data.len(), source.len());
+
+    File::create(dest_path)
+        .and_then(|mut dest| dest.write_all(data))
+        .expect("Could not write destination file");
+}
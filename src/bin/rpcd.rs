@@ -0,0 +1,555 @@
+//! A long-running encoding/decoding service, exposing a JSON-RPC 2.0 API over a
+//! unix socket or TCP.
+//!
+//! Build systems (Bazel workers, webpack plugins, etc.) encoding many small files
+//! spend most of their time launching processes. This server keeps running between
+//! files. Each connection is a stream of requests, one JSON object per line, each
+//! answered by a response on one line, in the order of the requests. Batches (arrays
+//! of requests) and notifications (requests without an `id`) follow JSON-RPC 2.0.
+//!
+//! Methods:
+//!
+//! - `encode`, params `{"source": "..."}` or `{"path": "foo.js"}`, with optional
+//!   `"output": "foo.binjs"`, `"lazify": N` and `"format": ["multipart", ...]`.
+//!   Result `{"bytes": N}`, with `"data"`, the hex-encoded binary, unless `output`
+//!   is specified;
+//! - `decode`, params `{"data": "<hex>"}` or `{"path": "foo.binjs"}`, with optional
+//!   `"output": "foo.js"` and `"format"`. Result `{"length": N}`, with `"source"`
+//!   unless `output` is specified;
+//! - `validate`, same params as `decode`. Result `{"valid": true}`, or
+//!   `{"valid": false, "error": "..."}` if the binary cannot be decoded;
+//! - `stats`, no params. Result: counters since the server started.
+//!
+//! `format` is the name of a format followed by its arguments, as in
+//! `binjs_encode advanced ...`. If unspecified, the format of `--format` is used.
+
+extern crate binjs;
+#[macro_use]
+extern crate binjs_shared;
+extern crate clap;
+extern crate env_logger;
+extern crate serde_json;
+
+use binjs::generic::{ FromJSON, JSON, ToJSON };
+use binjs::io::Format;
+use binjs::io::bytes::signature::to_hex;
+use binjs::source::{ DaemonPool, Shift, SourceParser };
+use binjs::specialized::es6::ast::{ Program, Walker, WalkPath };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::collections::BTreeMap;
+use std::io::{ BufRead, BufReader, Cursor, Read, Write };
+use std::net::{ TcpListener, TcpStream };
+use std::panic::AssertUnwindSafe;
+use std::sync::{ Arc, Mutex };
+use std::thread;
+use std::time::Instant;
+
+use clap::{ App, Arg };
+
+/// Invalid JSON was received.
+const PARSE_ERROR: i64 = -32700;
+
+/// The JSON sent is not a valid request object.
+const INVALID_REQUEST: i64 = -32600;
+
+/// The method does not exist.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Invalid method parameters.
+const INVALID_PARAMS: i64 = -32602;
+
+/// The request is valid, but could not be executed, e.g. the source could not be parsed.
+const APPLICATION_ERROR: i64 = -32000;
+
+/// An error, reported as the `error` member of a response.
+struct Error {
+    code: i64,
+    message: String,
+}
+impl Error {
+    fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        Error {
+            code,
+            message: message.into(),
+        }
+    }
+    fn params<S: Into<String>>(message: S) -> Self {
+        Error::new(INVALID_PARAMS, message)
+    }
+    fn application<E: std::fmt::Debug>(error: E) -> Self {
+        Error::new(APPLICATION_ERROR, format!("{:?}", error))
+    }
+}
+
+/// Counters shared by all connections, reported by method `stats`.
+#[derive(Default)]
+struct Statistics {
+    connections: usize,
+    requests: usize,
+    errors: usize,
+
+    /// Bytes of sources and binaries read, whether sent inline or as paths.
+    bytes_in: usize,
+
+    /// Bytes of sources and binaries produced.
+    bytes_out: usize,
+
+    /// The number of requests per method, including unknown methods.
+    methods: BTreeMap<String, usize>,
+}
+
+/// The state shared by all connections.
+struct Server {
+    started: Instant,
+    lazification: u32,
+
+    /// The arguments of the default format, e.g. `["multipart"]`.
+    format: Vec<String>,
+
+    daemons: Option<Arc<DaemonPool>>,
+    statistics: Mutex<Statistics>,
+}
+
+/// The state of a single connection.
+///
+/// Formats and parsers are not shared between threads, so each connection has its own.
+struct Connection<'a> {
+    server: &'a Server,
+    parser: Shift,
+    format: Format,
+}
+impl<'a> Connection<'a> {
+    fn new(server: &'a Server) -> Result<Self, std::io::Error> {
+        Ok(Connection {
+            server,
+            parser: Shift::new()
+                .with_daemons(server.daemons.clone()),
+            format: make_format(&server.format)?,
+        })
+    }
+
+    /// Handle a line received from the client, returning the line to send back, if any.
+    fn handle_line(&mut self, line: &str) -> Option<JSON> {
+        let message : JSON = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(err) => return Some(error_response(JSON::Null, Error::new(PARSE_ERROR, format!("{}", err))))
+        };
+        match message {
+            JSON::Array(ref batch) if batch.is_empty() =>
+                Some(error_response(JSON::Null, Error::new(INVALID_REQUEST, "Empty batch"))),
+            JSON::Array(batch) => {
+                let responses : Vec<JSON> = batch.iter()
+                    .filter_map(|request| self.handle_request(request))
+                    .collect();
+                if responses.is_empty() {
+                    // A batch of notifications.
+                    None
+                } else {
+                    Some(JSON::Array(responses))
+                }
+            }
+            request => self.handle_request(&request)
+        }
+    }
+
+    /// Handle a single request, returning its response, or `None` for notifications.
+    fn handle_request(&mut self, request: &JSON) -> Option<JSON> {
+        let (id, method) = match (request.get("jsonrpc"), request.get("method")) {
+            (Some(&JSON::String(ref version)), Some(&JSON::String(ref method))) if version == "2.0" =>
+                (request.get("id").cloned(), method.clone()),
+            _ => {
+                self.server.statistics.lock().unwrap().errors += 1;
+                return Some(error_response(request.get("id").cloned().unwrap_or(JSON::Null), Error::new(INVALID_REQUEST, "Expected a JSON-RPC 2.0 request")))
+            }
+        };
+        let empty = object!{};
+        let params = request.get("params")
+            .unwrap_or(&empty);
+
+        {
+            let mut statistics = self.server.statistics.lock().unwrap();
+            statistics.requests += 1;
+            *statistics.methods.entry(method.clone()).or_insert(0) += 1;
+        }
+        let result = if params.is_object() {
+            // Failures are reported to the client, the server keeps running.
+            std::panic::catch_unwind(AssertUnwindSafe(|| self.call(&method, params)))
+                .unwrap_or_else(|_| Err(Error::new(APPLICATION_ERROR, "panic")))
+        } else {
+            Err(Error::params("Expected params as an object"))
+        };
+        if result.is_err() {
+            self.server.statistics.lock().unwrap().errors += 1;
+        }
+
+        // Notifications are not answered, even in case of error.
+        let id = id?;
+        Some(match result {
+            Ok(result) => object!{
+                "jsonrpc" => "2.0",
+                "id" => id,
+                "result" => result
+            },
+            Err(error) => error_response(id, error)
+        })
+    }
+
+    fn call(&mut self, method: &str, params: &JSON) -> Result<JSON, Error> {
+        match method {
+            "encode" => self.encode(params),
+            "decode" => self.decode(params),
+            "validate" => self.validate(params),
+            "stats" => Ok(self.stats()),
+            _ => Err(Error::new(METHOD_NOT_FOUND, format!("Unknown method {}", method)))
+        }
+    }
+
+    fn encode(&mut self, params: &JSON) -> Result<JSON, Error> {
+        let lazification = match params.get("lazify") {
+            None => self.server.lazification,
+            Some(value) => value.as_u64()
+                .ok_or_else(|| Error::params("Expected `lazify` as a number"))? as u32
+        };
+        let json = match (string_param(params, "source")?, string_param(params, "path")?) {
+            (Some(source), None) => {
+                self.add_bytes_in(source.len());
+                self.parser.parse_str(source)
+                    .map_err(Error::application)?
+            }
+            (None, Some(path)) => {
+                let source = std::fs::read_to_string(path)
+                    .map_err(Error::application)?;
+                self.add_bytes_in(source.len());
+                self.parser.parse_str(&source)
+                    .map_err(Error::application)?
+            }
+            _ => return Err(Error::params("Expected exactly one of `source` and `path`"))
+        };
+        let mut ast = Program::import(&json)
+            .map_err(Error::application)?;
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+        ast.walk(&mut WalkPath::new(), &mut binjs::specialized::es6::lazy::LazifierVisitor::new(lazification))
+            .map_err(Error::application)?;
+
+        let mut format = self.format_param(params)?;
+        let data = Encoder::new()
+            .encode(format.as_mut().unwrap_or(&mut self.format), &ast)
+            .map_err(Error::application)?;
+        let data = (*data).as_ref();
+        self.add_bytes_out(data.len());
+
+        let mut result = object!{
+            "bytes" => data.len()
+        };
+        match string_param(params, "output")? {
+            Some(output) => std::fs::write(output, data)
+                .map_err(Error::application)?,
+            None => result["data"] = JSON::from(to_hex(data))
+        }
+        Ok(result)
+    }
+
+    fn decode(&mut self, params: &JSON) -> Result<JSON, Error> {
+        let ast = self.decode_program(params)?;
+
+        let mut builder = binjs::meta::spec::SpecBuilder::new();
+        let _ = binjs::generic::es6::Library::new(&mut builder);
+        let spec_options = binjs::meta::spec::SpecOptions {
+            null: &builder.node_name(""),
+            root: &builder.node_name("Program"),
+        };
+        let spec = builder.into_spec(spec_options);
+        let source = self.parser.to_source(&spec, &ast.export())
+            .map_err(Error::application)?;
+        self.add_bytes_out(source.len());
+
+        let mut result = object!{
+            "length" => source.len()
+        };
+        match string_param(params, "output")? {
+            Some(output) => std::fs::write(output, source.as_bytes())
+                .map_err(Error::application)?,
+            None => result["source"] = JSON::from(source)
+        }
+        Ok(result)
+    }
+
+    fn validate(&mut self, params: &JSON) -> Result<JSON, Error> {
+        match self.decode_program(params) {
+            Ok(_) => Ok(object!{
+                "valid" => true
+            }),
+            Err(Error { code: APPLICATION_ERROR, message }) => Ok(object!{
+                "valid" => false,
+                "error" => message
+            }),
+            Err(err) => Err(err)
+        }
+    }
+
+    fn stats(&self) -> JSON {
+        let statistics = self.server.statistics.lock().unwrap();
+        let mut methods = object!{};
+        for (method, count) in &statistics.methods {
+            methods[method.as_str()] = JSON::from(*count);
+        }
+        let uptime = self.server.started.elapsed();
+        object!{
+            "uptime_ms" => uptime.as_secs() * 1000 + uptime.subsec_millis() as u64,
+            "connections" => statistics.connections,
+            "requests" => statistics.requests,
+            "errors" => statistics.errors,
+            "bytes_in" => statistics.bytes_in,
+            "bytes_out" => statistics.bytes_out,
+            "methods" => methods
+        }
+    }
+
+    /// Decode the binary specified by `data` or `path`.
+    fn decode_program(&mut self, params: &JSON) -> Result<Program, Error> {
+        let data = match (string_param(params, "data")?, string_param(params, "path")?) {
+            (Some(hex), None) => from_hex(hex)
+                .map_err(Error::params)?,
+            (None, Some(path)) => std::fs::read(path)
+                .map_err(Error::application)?,
+            _ => return Err(Error::params("Expected exactly one of `data` and `path`"))
+        };
+        self.add_bytes_in(data.len());
+        let mut format = self.format_param(params)?;
+        Decoder::new()
+            .decode(format.as_mut().unwrap_or(&mut self.format), Cursor::new(&data))
+            .map_err(Error::application)
+    }
+
+    /// The format specified by the `format` param, if any.
+    fn format_param(&self, params: &JSON) -> Result<Option<Format>, Error> {
+        let args = match params.get("format") {
+            None => return Ok(None),
+            Some(&JSON::Array(ref args)) => args,
+            Some(_) => return Err(Error::params("Expected `format` as an array of strings"))
+        };
+        let args = args.iter()
+            .map(|arg| arg.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| Error::params("Expected `format` as an array of strings")))
+            .collect::<Result<Vec<_>, _>>()?;
+        make_format(&args)
+            .map(Some)
+            .map_err(|err| Error::params(format!("{}", err)))
+    }
+
+    fn add_bytes_in(&self, bytes: usize) {
+        self.server.statistics.lock().unwrap().bytes_in += bytes;
+    }
+    fn add_bytes_out(&self, bytes: usize) {
+        self.server.statistics.lock().unwrap().bytes_out += bytes;
+    }
+}
+
+fn error_response(id: JSON, error: Error) -> JSON {
+    object!{
+        "jsonrpc" => "2.0",
+        "id" => id,
+        "error" => object!{
+            "code" => error.code,
+            "message" => error.message
+        }
+    }
+}
+
+/// Access an optional string member of `params`.
+fn string_param<'a>(params: &'a JSON, name: &str) -> Result<Option<&'a str>, Error> {
+    match params.get(name) {
+        None | Some(&JSON::Null) => Ok(None),
+        Some(&JSON::String(ref value)) => Ok(Some(value.as_str())),
+        Some(_) => Err(Error::params(format!("Expected `{}` as a string", name)))
+    }
+}
+
+/// Create a format from its name followed by its arguments.
+fn make_format(args: &[String]) -> Result<Format, std::io::Error> {
+    let args : Vec<&str> = args.iter()
+        .map(String::as_str)
+        .collect();
+    Format::from_args(&args)
+}
+
+/// Decode a hex-encoded binary of any length.
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return Err("Expected `data` as an even number of hex digits".to_string());
+    }
+    (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[2 * i .. 2 * i + 2], 16)
+            .map_err(|err| format!("Invalid hex digits in `data`: {}", err)))
+        .collect()
+}
+
+/// A connected client.
+trait Stream: Read + Write + Send + 'static + Sized {
+    fn try_clone(&self) -> Result<Self, std::io::Error>;
+}
+impl Stream for TcpStream {
+    fn try_clone(&self) -> Result<Self, std::io::Error> {
+        TcpStream::try_clone(self)
+    }
+}
+#[cfg(unix)]
+impl Stream for std::os::unix::net::UnixStream {
+    fn try_clone(&self) -> Result<Self, std::io::Error> {
+        std::os::unix::net::UnixStream::try_clone(self)
+    }
+}
+
+/// Serve each client of `incoming` on its own thread.
+fn serve<S: Stream, I: Iterator<Item = Result<S, std::io::Error>>>(server: Arc<Server>, incoming: I, quiet: bool) {
+    for stream in incoming {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("Could not accept connection: {}", err);
+                continue;
+            }
+        };
+        server.statistics.lock().unwrap().connections += 1;
+        let server = server.clone();
+        thread::Builder::new()
+            .name("connection".to_string())
+            .stack_size(20 * 1024 * 1024)
+            .spawn(move || {
+                if let Err(err) = handle_connection(&server, stream) {
+                    if !quiet {
+                        eprintln!("Connection closed: {}", err);
+                    }
+                }
+            })
+            .expect("Could not launch connection thread");
+    }
+}
+
+fn handle_connection<S: Stream>(server: &Server, stream: S) -> Result<(), std::io::Error> {
+    let mut connection = Connection::new(server)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = connection.handle_line(&line) {
+            writer.write_all(response.to_string().as_bytes())?;
+            writer.write_all(b"\n")?;
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS RPC server")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Serve encoding, decoding and validation requests, as JSON-RPC 2.0 over a unix socket or TCP, one JSON object per line, without launching a process per file. See the documentation of `src/bin/rpcd.rs` for the list of methods.")
+        .args(&[
+            Arg::with_name("socket")
+                .long("socket")
+                .takes_value(true)
+                .value_name("PATH")
+                .required_unless("tcp")
+                .conflicts_with("tcp")
+                .help("Listen on a unix socket at this path. The file must not exist."),
+            Arg::with_name("tcp")
+                .long("tcp")
+                .takes_value(true)
+                .value_name("ADDR")
+                .help("Listen on this TCP address, e.g. 127.0.0.1:7777. The API gives access to the file system, so the address should not be reachable by untrusted clients."),
+            Arg::with_name("lazify")
+                .long("lazify")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<u32>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Number of layers of functions to lazify when encoding, unless specified by the request. 0 = no lazification, 1 = functions at toplevel, 2 = also functions in functions at toplevel, etc."),
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .default_value("multipart")
+                .help("The format used unless specified by the request: the name of a format followed by its arguments, separated by spaces, e.g. \"multipart --front-coding 8\"."),
+            Arg::with_name("parser-daemons")
+                .long("parser-daemons")
+                .takes_value(true)
+                .value_name("N")
+                .default_value("4")
+                .validator(|s| s.parse::<usize>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number of daemons: {}", e)))
+                .help("Parse and pretty-print sources in long-lived Node processes, keeping up to N of them alive between requests. If 0, launch Node for each request."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print the connections on stderr"),
+        ])
+        .get_matches();
+
+    let format : Vec<String> = matches.value_of("format")
+        .unwrap() // Guaranteed by `clap`.
+        .split_whitespace()
+        .map(str::to_string)
+        .collect();
+    // Fail early rather than on the first connection.
+    make_format(&format)
+        .expect("Could not parse encoding format");
+
+    let server = Arc::new(Server {
+        started: Instant::now(),
+        lazification: matches.value_of("lazify")
+            .unwrap() // Guaranteed by `clap`.
+            .parse()
+            .unwrap(), // Checked by the validator.
+        format,
+        daemons: match matches.value_of("parser-daemons")
+            .unwrap() // Guaranteed by `clap`.
+            .parse::<usize>()
+            .unwrap() // Checked by the validator.
+        {
+            0 => None,
+            max_idle => Some(Arc::new(DaemonPool::new("node", max_idle)))
+        },
+        statistics: Mutex::new(Statistics::default()),
+    });
+    let quiet = matches.is_present("quiet");
+
+    // Panics are reported to clients, don't clutter stderr.
+    std::panic::set_hook(Box::new(|_| {}));
+
+    if let Some(addr) = matches.value_of("tcp") {
+        let listener = TcpListener::bind(addr)
+            .expect("Could not listen on TCP address");
+        if !quiet {
+            eprintln!("Listening on {}", listener.local_addr().expect("Could not get local address"));
+        }
+        serve(server, listener.incoming(), quiet);
+    } else {
+        let path = matches.value_of("socket")
+            .unwrap(); // Guaranteed by `clap`.
+        listen_unix(server, path, quiet);
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(server: Arc<Server>, path: &str, quiet: bool) {
+    let listener = std::os::unix::net::UnixListener::bind(path)
+        .expect("Could not listen on unix socket");
+    if !quiet {
+        eprintln!("Listening on {}", path);
+    }
+    serve(server, listener.incoming(), quiet);
+}
+
+#[cfg(not(unix))]
+fn listen_unix(_: Arc<Server>, _: &str, _: bool) {
+    panic!("Unix sockets are not supported on this platform, use --tcp");
+}
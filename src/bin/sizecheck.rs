@@ -0,0 +1,208 @@
+//! Encode a corpus and compare the size of each file, and of the entire corpus,
+//! against a stored baseline, failing if sizes regress beyond a threshold.
+//!
+//! This is meant to be run by CI on changes to the format, with a baseline
+//! committed along with the corpus and refreshed with `--update`.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+extern crate glob;
+extern crate serde_json;
+
+use binjs::sizecheck::{ encoded_size, FileChange, Sizes };
+use binjs::source::{ Shift, SourceType };
+
+use std::fs::File;
+use std::io::{ Read, Write };
+use std::path::Path;
+use std::thread;
+
+use clap::*;
+
+fn load(path: &Path) -> Sizes {
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .expect("Could not read baseline");
+    let json : serde_json::Value = serde_json::from_str(&source)
+        .expect("Could not parse baseline as JSON");
+    Sizes::import(&json)
+        .unwrap_or_else(|err| panic!("{}", err))
+}
+
+fn store(sizes: &Sizes, path: &Path) {
+    File::create(path)
+        .and_then(|mut file| {
+            serde_json::to_writer_pretty(&mut file, &sizes.export())?;
+            writeln!(file)
+        })
+        .expect("Could not write baseline");
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS size regression checker")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Encode a corpus of JavaScript sources and compare the size of each file, and the total size, against a baseline, failing if sizes regress beyond a threshold.")
+        .args(&[
+            Arg::with_name("BASELINE")
+                .required(true)
+                .help("The baseline, as written by `--update`: a JSON file listing the encoded size of each file of the corpus."),
+            Arg::with_name("CORPUS")
+                .required(true)
+                .help("A directory containing the `.js` and `.mjs` sources of the corpus. Subdirectories are also encoded."),
+            Arg::with_name("threshold")
+                .long("threshold")
+                .takes_value(true)
+                .default_value("0.5")
+                .validator(|s| s.parse::<f64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Fail if the total size of the files of the baseline grows by more than this percentage."),
+            Arg::with_name("file-threshold")
+                .long("file-threshold")
+                .takes_value(true)
+                .default_value("5")
+                .validator(|s| s.parse::<f64>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Fail if the size of any file grows by more than this percentage."),
+            Arg::with_name("lazify")
+                .long("lazify")
+                .takes_value(true)
+                .default_value("0")
+                .validator(|s| s.parse::<u32>()
+                    .map(|_| ())
+                    .map_err(|e| format!("Invalid number {}", e)))
+                .help("Number of layers of functions to lazify. 0 = no lazification, 1 = functions at toplevel, 2 = also functions in functions at toplevel, etc."),
+            Arg::with_name("source-type")
+                .long("source-type")
+                .takes_value(true)
+                .possible_values(&["script", "module", "auto"])
+                .default_value("auto")
+                .help("Parse sources as scripts, as ES modules, or detect it: with `auto`, .mjs files are modules, and other sources are parsed as scripts, then as modules if they contain `import` or `export` declarations."),
+            Arg::with_name("update")
+                .long("update")
+                .help("Rather than comparing sizes, write them to BASELINE, e.g. after an intended change in the format."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Only print regressions, not the size of each file."),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let baseline_path = Path::new(matches.value_of("BASELINE")
+        .unwrap()); // Guaranteed by `clap`.
+    let root = Path::new(matches.value_of("CORPUS")
+        .unwrap()); // Guaranteed by `clap`.
+    let threshold : f64 = matches.value_of("threshold")
+        .unwrap() // Guaranteed by `clap`.
+        .parse()
+        .unwrap(); // Checked by the validator.
+    let file_threshold : f64 = matches.value_of("file-threshold")
+        .unwrap() // Guaranteed by `clap`.
+        .parse()
+        .unwrap(); // Checked by the validator.
+    let lazification = matches.value_of("lazify")
+        .unwrap() // Guaranteed by `clap`.
+        .parse()
+        .unwrap(); // Checked by the validator.
+    let quiet = matches.is_present("quiet");
+    let source_type = SourceType::from_name(matches.value_of("source-type")
+        .unwrap()) // Guaranteed by `clap`.
+        .unwrap(); // Checked by `clap`.
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    let parser = Shift::new()
+        .with_source_type(source_type);
+
+    // Failures are reported below, don't clutter stderr with panics.
+    std::panic::set_hook(Box::new(|_| {}));
+    let mut current = Sizes::new(&format);
+    let mut failures = 0;
+    let patterns = [
+        format!("{}/**/*.js", root.display()),
+        format!("{}/**/*.mjs", root.display()),
+    ];
+    for entry in patterns.iter()
+        .flat_map(|pattern| glob::glob(pattern)
+            .expect("Invalid glob pattern"))
+    {
+        let path = entry.expect("Invalid entry");
+        let name = path.strip_prefix(root)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        match encoded_size(&parser, &mut format, lazification, &path) {
+            Ok(size) => {
+                current.files.insert(name, size);
+            }
+            Err(err) => {
+                eprintln!("{}: {}", name, err);
+                failures += 1;
+            }
+        }
+    }
+    let _ = std::panic::take_hook();
+
+    if matches.is_present("update") {
+        store(&current, baseline_path);
+        eprintln!("Wrote baseline of {} files, {} bytes.", current.files.len(), current.total());
+        if failures > 0 {
+            eprintln!("{} files could not be encoded.", failures);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let baseline = load(baseline_path);
+    let comparison = match baseline.compare(&current, file_threshold, threshold) {
+        Ok(comparison) => comparison,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    for (name, change) in &comparison.files {
+        match *change {
+            FileChange::Added(size) if !quiet => println!("{}: {} bytes, not in baseline", name, size),
+            FileChange::Removed(_) if !quiet => println!("{}: not in corpus", name),
+            FileChange::Changed { before, after, delta, regression: true } =>
+                println!("{}: {} => {} bytes ({:+.2}%), REGRESSION", name, before, after, delta),
+            FileChange::Changed { before, after, delta, regression: false } if !quiet =>
+                println!("{}: {} => {} bytes ({:+.2}%)", name, before, after, delta),
+            _ => {}
+        }
+    }
+
+    println!("Total: {} => {} bytes ({:+.2}%)", comparison.before, comparison.after, comparison.delta);
+    if comparison.regression {
+        println!("Total size regressed by more than {}%.", threshold);
+    }
+    if comparison.regressions() > 0 || failures > 0 {
+        if failures > 0 {
+            eprintln!("{} files could not be encoded.", failures);
+        }
+        std::process::exit(1);
+    }
+    if comparison.delta < 0. {
+        println!("Sizes improved, consider refreshing the baseline with `--update`.");
+    }
+}
@@ -0,0 +1,79 @@
+//! Export a machine-readable JSON description of the binary format: the
+//! tokens written for each node of the grammar and the layout of the
+//! multipart container.
+//!
+//! The specification document and alternative implementations may be
+//! generated or checked from this description. With `--typescript`, export
+//! TypeScript definitions of the AST instead, for JS tooling consuming the
+//! output of `binjs_decode`.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+#[macro_use]
+extern crate serde_json;
+
+use binjs::io::multipart::ContainerLayout;
+use binjs::meta::export::{ ToJSONDescription, ToTypeScript };
+use binjs::meta::import::Importer;
+
+use std::fs::*;
+use std::io::*;
+
+use clap::*;
+
+/// The grammar compiled into the encoder.
+const GRAMMAR_ES6: &str = include_str!("../../spec/es6.webidl");
+
+fn main() {
+    env_logger::init();
+
+    let matches = App::new("BinJS format description")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Export a machine-readable JSON description of the binary format.")
+        .args(&[
+            Arg::with_name("OUTPUT")
+                .help("Output file to use. Will be overwritten. If unspecified, print to stdout."),
+            Arg::with_name("grammar")
+                .long("grammar")
+                .takes_value(true)
+                .help("A WebIDL grammar to describe instead of the grammar compiled into the encoder."),
+            Arg::with_name("typescript")
+                .long("typescript")
+                .help("Instead of the JSON description, export TypeScript definitions (.d.ts) of the AST written by `binjs_decode --output-json internal`."),
+        ])
+        .get_matches();
+
+    let source = match matches.value_of("grammar") {
+        None => GRAMMAR_ES6.to_string(),
+        Some(path) => {
+            let mut source = String::new();
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut source))
+                .expect("Could not read grammar");
+            source
+        }
+    };
+    let spec = Importer::load(&source, "Script")
+        .expect("Could not parse grammar");
+
+    let output = if matches.is_present("typescript") {
+        ToTypeScript::spec(&spec)
+    } else {
+        let description = json!({
+            "grammar": ToJSONDescription::spec(&spec),
+            "container": ContainerLayout::new()
+        });
+        serde_json::to_string_pretty(&description)
+            .expect("Could not serialize description")
+    };
+
+    match matches.value_of("OUTPUT") {
+        None => println!("{}", output),
+        Some(path) => {
+            File::create(path)
+                .and_then(|mut dest| dest.write_all(output.as_bytes()))
+                .expect("Could not write destination file");
+        }
+    }
+}
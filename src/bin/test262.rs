@@ -0,0 +1,219 @@
+//! Encode and decode every file of a test262 checkout, reporting which files
+//! do not survive the roundtrip and why.
+
+extern crate binjs;
+#[macro_use]
+extern crate binjs_shared;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::runner::{ guard, Report };
+use binjs::source::{ Shift, SourceParser, SourceType };
+use binjs::specialized::es6::ast::Program;
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::io::Cursor;
+use std::path::Path;
+use std::thread;
+
+use clap::*;
+
+/// The result of the roundtrip of a single file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Outcome {
+    /// The decoded AST is identical to the encoded AST.
+    Pass,
+    /// The test is not meant to be parsed, e.g. tests of syntax errors.
+    Skip,
+    /// Shift could not parse the source.
+    Parse,
+    /// The parsed AST could not be imported or annotated.
+    Annotate,
+    /// The AST could not be encoded.
+    Encode,
+    /// The file could not be decoded.
+    Decode,
+    /// The decoded AST differs from the encoded AST.
+    Mismatch,
+    /// The runner itself failed.
+    Panic,
+}
+impl binjs::runner::Outcome for Outcome {
+    fn name(&self) -> &'static str {
+        match *self {
+            Outcome::Pass => "pass",
+            Outcome::Skip => "skip",
+            Outcome::Parse => "parse",
+            Outcome::Annotate => "annotate",
+            Outcome::Encode => "encode",
+            Outcome::Decode => "decode",
+            Outcome::Mismatch => "mismatch",
+            Outcome::Panic => "panic",
+        }
+    }
+    fn panic() -> Self {
+        Outcome::Panic
+    }
+}
+
+/// The front matter of a test, i.e. the YAML between `/*---` and `---*/`.
+fn front_matter(source: &str) -> Option<&str> {
+    let start = source.find("/*---")?;
+    let end = source[start..].find("---*/")?;
+    Some(&source[start..start + end])
+}
+
+/// The reason to skip a test, if any, from its front matter.
+fn skip_reason(source: &str) -> Option<&'static str> {
+    let front_matter = front_matter(source)?;
+    if front_matter.contains("negative:") && (front_matter.contains("phase: parse") || front_matter.contains("phase: early")) {
+        return Some("syntax error expected");
+    }
+    None
+}
+
+/// `true` if the front matter of the test has the flag `module`, i.e. the test
+/// must be parsed as a module.
+fn is_module(source: &str) -> bool {
+    front_matter(source)
+        .map_or(false, |front_matter| front_matter.lines()
+            .any(|line| line.trim_start().starts_with("flags:") && line.contains("module")))
+}
+
+/// The parsers for scripts and modules.
+struct Parsers {
+    script: Shift,
+    module: Shift,
+}
+
+/// Encode then decode the file at `path`.
+fn roundtrip(parsers: &Parsers, format: &mut Format, path: &Path) -> Report<Outcome> {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => return Report::new(Outcome::Parse).with_error(format!("{:?}", err))
+    };
+    if let Some(reason) = skip_reason(&source) {
+        return Report::new(Outcome::Skip).with_error(reason.to_string());
+    }
+    let parser = if is_module(&source) {
+        &parsers.module
+    } else {
+        &parsers.script
+    };
+
+    let json = match guard(|| parser.parse_file(path).map_err(|err| format!("{:?}", err))) {
+        Ok(json) => json,
+        Err(err) => return Report::new(Outcome::Parse).with_error(err)
+    };
+    let ast = match guard(|| {
+        let mut ast = Program::import(&json)
+            .map_err(|err| format!("{:?}", err))?;
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+        Ok(ast)
+    }) {
+        Ok(ast) => ast,
+        Err(err) => return Report::new(Outcome::Annotate).with_error(err)
+    };
+    let data = match guard(|| {
+        let data = Encoder::new()
+            .encode(format, &ast)
+            .map_err(|err| format!("{:?}", err))?;
+        Ok((*data).as_ref().to_vec())
+    }) {
+        Ok(data) => data,
+        Err(err) => return Report::new(Outcome::Encode).with_error(err)
+    };
+    let decoded : Program = match guard(|| Decoder::new()
+        .decode(format, Cursor::new(&data))
+        .map_err(|err| format!("{:?}", err)))
+    {
+        Ok(decoded) => decoded,
+        Err(err) => return Report::new(Outcome::Decode).with_error(err)
+    };
+    if decoded != ast {
+        return Report::new(Outcome::Mismatch);
+    }
+    Report::new(Outcome::Pass)
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS test262 runner")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Encode and decode every file of a test262 checkout, reporting the files that do not survive the roundtrip, as JSON.")
+        .args(&[
+            Arg::with_name("test262")
+                .long("test262")
+                .takes_value(true)
+                .required(true)
+                .help("Path to a test262 checkout. Files are read from its `test` directory."),
+            Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .default_value("**/*.js")
+                .help("Only run the files matching this glob pattern, relative to the `test` directory, e.g. `built-ins/Array/**/*.js`."),
+            Arg::with_name("out")
+                .long("out")
+                .short("o")
+                .takes_value(true)
+                .help("Write the JSON summary to this file. If not specified or `-`, the summary is written to stdout."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print the outcome of each file on stderr"),
+        ])
+        .subcommand(binjs::io::Format::subcommand())
+        .get_matches();
+
+    let root = Path::new(matches.value_of("test262")
+        .unwrap()) // Guaranteed by `clap`.
+        .join("test");
+    let pattern = format!("{}/{}", root.display(), matches.value_of("filter")
+        .unwrap()); // Guaranteed by `clap`.
+    let dest_path = matches.value_of("out")
+        .filter(|path| *path != "-");
+    let quiet = matches.is_present("quiet");
+
+    let mut format = binjs::io::Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+    let parsers = Parsers {
+        script: Shift::new()
+            .with_source_type(SourceType::Script),
+        module: Shift::new()
+            .with_source_type(SourceType::Module),
+    };
+
+    let summary = binjs::runner::run(&root, &pattern, quiet, |path| {
+        let is_fixture = path.to_str()
+            .map_or(false, |name| name.contains("_FIXTURE"));
+        if is_fixture {
+            // Not a test, imported by other tests.
+            return None;
+        }
+        Some(roundtrip(&parsers, &mut format, path))
+    });
+
+    let total = summary.total();
+    let summary = summary.export(object!{
+        "format" => format.name(),
+        "total" => total
+    });
+    binjs::runner::write_summary(&summary, dest_path)
+        .expect("Could not write summary");
+}
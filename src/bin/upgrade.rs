@@ -0,0 +1,131 @@
+//! Rewrite a BinJS multipart file written with a legacy container version,
+//! using the current container version.
+//!
+//! Readers still accept legacy versions, but this lets corpora collected
+//! with earlier encoders follow the format as it evolves.
+
+extern crate binjs;
+extern crate clap;
+extern crate env_logger;
+
+use binjs::io::Format;
+use binjs::io::multipart::TreeTokenReader;
+use binjs::specialized::es6::ast::Program;
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::fs::*;
+use std::io::*;
+use std::thread;
+
+use clap::*;
+
+macro_rules! progress {
+    ($quiet:expr, $($args:tt)*) => {
+        if !$quiet {
+            println!($($args)*);
+        }
+    }
+}
+
+fn main() {
+    thread::Builder::new()
+        .name("large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main_aux();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main_aux() {
+    env_logger::init();
+
+    let matches = App::new("BinJS upgrader")
+        .author("David Teller, <dteller@mozilla.com>")
+        .about("Rewrite a JavaScript BinJS multipart file written with a legacy container version, using the current container version.")
+        .args(&[
+            Arg::with_name("INPUT")
+                .required(true)
+                .help("Input file to use. Must be a BinJS multipart file, possibly an archive."),
+            Arg::with_name("OUTPUT")
+                .required(true)
+                .help("Output file to use. Will be overwritten."),
+            Arg::with_name("force")
+                .long("force")
+                .help("Rewrite INPUT even if it already uses the current container version."),
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print progress"),
+        ])
+        .subcommand(Format::subcommand())
+        .get_matches();
+
+    let source_path = matches.value_of("INPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let dest_path = matches.value_of("OUTPUT")
+        .unwrap(); // Guaranteed by `clap`.
+    let quiet = matches.is_present("quiet");
+
+    let mut format = Format::from_matches(&matches)
+        .expect("Could not parse encoding format");
+
+    progress!(quiet, "Reading {}.", source_path);
+    let mut source = vec![];
+    File::open(source_path)
+        .and_then(|mut file| file.read_to_end(&mut source))
+        .expect("Could not read source");
+
+    let version = TreeTokenReader::container_version(Cursor::new(&source))
+        .expect("Could not read container version");
+    progress!(quiet, "Container version: {}.", version.number);
+    if version.is_current() && !matches.is_present("force") {
+        progress!(quiet, "Nothing to upgrade.");
+        return;
+    }
+
+    // Keep the representation of floats. Other options are taken from the command line.
+    let entries = match format {
+        Format::Multipart { ref mut options, .. } => {
+            options.varfloats = version.varfloats;
+            options.nan_policy = version.nan_policy;
+            TreeTokenReader::entries(Cursor::new(&source), options)
+                .expect("Could not read source")
+        }
+        _ => panic!("Only the multipart format has container versions")
+    };
+
+    let data = match entries {
+        None => {
+            let (ast, positions) : (Program, _) = Decoder::new()
+                .decode_with_positions(&mut format, Cursor::new(&source))
+                .expect("Could not decode");
+            Encoder::new()
+                .with_positions(positions)
+                .encode(&mut format, &ast)
+                .expect("Could not encode")
+        }
+        Some(names) => {
+            progress!(quiet, "Upgrading {} entries.", names.len());
+            let asts : Vec<Program> = names.iter()
+                .map(|name| Decoder::new()
+                    .decode_entry(&mut format, Cursor::new(&source), name)
+                    .unwrap_or_else(|e| panic!("Could not decode entry {}: {:?}", name, e)))
+                .collect();
+            let entries : Vec<(&str, &Program)> = names.iter()
+                .map(|name| name.as_str())
+                .zip(asts.iter())
+                .collect();
+            Encoder::new()
+                .encode_archive(&mut format, &entries)
+                .expect("Could not encode")
+        }
+    };
+
+    File::create(dest_path)
+        .and_then(|mut dest| dest.write_all((*data).as_ref()))
+        .expect("Could not write destination file");
+    progress!(quiet, "Wrote {} bytes.", (*data).as_ref().len());
+}
@@ -0,0 +1,26 @@
+//! Comparing the streams of the entropy format with their theoretical bounds on a
+//! corpus, to see which streams lose bytes in the bit-level coder rather than in the
+//! predictions of the dictionary, see `binjs_io::entropy::bounds`.
+
+use binjs_es6::ast::Program;
+use binjs_es6::io::{ IOPath, Serializer };
+use binjs_io::{ TokenSerializer, TokenWriterError };
+use binjs_io::entropy::Options;
+use binjs_io::entropy::bounds::StreamBound;
+use binjs_io::entropy::write::Encoder;
+use binjs_io::statistics::ContentInfo;
+
+/// Encode each AST of `corpus` with the entropy format and `options`, which hold the
+/// dictionary, then return, for each stream, the bytes written and the Shannon-optimal
+/// size of the stream given the probabilities of the dictionary.
+///
+/// The result only covers `corpus`: the statistics of `options` are left untouched.
+pub fn entropy_bounds(options: &Options, corpus: &[Program]) -> Result<ContentInfo<StreamBound>, TokenWriterError> {
+    let options = options.with_new_statistics();
+    for ast in corpus {
+        let mut serializer = Serializer::new(Encoder::new(options.clone()));
+        serializer.serialize(ast, &mut IOPath::new())?;
+        serializer.done()?;
+    }
+    Ok(options.entropy_bounds_for_write())
+}
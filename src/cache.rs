@@ -0,0 +1,216 @@
+//! An on-disk cache of parsed and annotated ASTs, keyed by a hash of the source.
+//!
+//! Parsing and annotating dominate the time spent by `binjs_encode` when a corpus
+//! is encoded repeatedly, e.g. while tuning formats. Entries store the AST, annotated
+//! with scopes but not with laziness, which depends on the options of each run, along
+//! with its source positions. They are written in the multipart format, without
+//! compression, which is compact and fast to decode.
+
+use binjs_es6::ast::Program;
+use binjs_es6::io::{ Decoder, Encoder, grammar_id };
+use binjs_io::{ self, Compression, CompressionTarget, Format, TokenWriterError };
+use binjs_io::multipart::{ Options, Statistics, Targets };
+use binjs_io::positions::SourcePositions;
+
+use sha2::{ Digest, Sha256 };
+
+use std;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{ Cursor, ErrorKind, Read, Write };
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+use std::time::{ Duration, SystemTime };
+
+/// Changed whenever the contents of entries change, e.g. if scope annotations change,
+/// so that entries written by earlier versions are never used.
+const CACHE_VERSION: &str = "binjs-annotation-cache-1";
+
+/// The extension of entries.
+const ENTRY_EXTENSION: &str = "binjs";
+
+/// The outcome of `AnnotationCache::evict`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Eviction {
+    pub removed_entries: usize,
+    pub removed_bytes: u64,
+    pub remaining_entries: usize,
+    pub remaining_bytes: u64,
+}
+
+/// A directory of annotated ASTs, keyed by a hash of their source.
+///
+/// Several processes may share a cache: entries are written atomically and
+/// unreadable entries are ignored.
+pub struct AnnotationCache {
+    dir: PathBuf,
+
+    /// If specified, `evict` removes the oldest entries until the cache fits in this many bytes.
+    max_bytes: Option<u64>,
+
+    /// If specified, `evict` removes the entries written earlier than this.
+    max_age: Option<Duration>,
+}
+impl AnnotationCache {
+    /// Use `dir` as a cache, creating it if necessary.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, std::io::Error> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(AnnotationCache {
+            dir: dir.as_ref().to_path_buf(),
+            max_bytes: None,
+            max_age: None,
+        })
+    }
+
+    pub fn with_max_bytes(self, max_bytes: Option<u64>) -> Self {
+        AnnotationCache {
+            max_bytes,
+            ..self
+        }
+    }
+
+    pub fn with_max_age(self, max_age: Option<Duration>) -> Self {
+        AnnotationCache {
+            max_age,
+            ..self
+        }
+    }
+
+    /// The key of `source`, once parsed by `parser`.
+    ///
+    /// `parser` describes the parser and its options, as the same source may be parsed
+    /// differently, e.g. as JavaScript or as TypeScript. The key also depends on the
+    /// grammar, so that changing the grammar does not require clearing the cache.
+    pub fn key(source: &[u8], parser: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input(CACHE_VERSION.as_bytes());
+        hasher.input(b"\0");
+        hasher.input(format!("{}", grammar_id()).as_bytes());
+        hasher.input(b"\0");
+        hasher.input(parser.as_bytes());
+        hasher.input(b"\0");
+        hasher.input(source);
+        hasher.result()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The annotated AST and source positions stored for `key`, if any.
+    ///
+    /// Entries that cannot be decoded, e.g. truncated entries, are removed.
+    pub fn get(&self, key: &str) -> Option<(Program, SourcePositions)> {
+        let path = self.entry_path(key);
+        let mut data = vec![];
+        if File::open(&path).and_then(|mut file| file.read_to_end(&mut data)).is_err() {
+            return None;
+        }
+        match Decoder::new().decode_with_positions(&mut Self::format(), Cursor::new(&data)) {
+            Ok((ast, Some(positions))) => Some((ast, positions)),
+            _ => {
+                debug!(target: "cache", "Removing unreadable entry {:?}", path);
+                let _ = std::fs::remove_file(&path);
+                None
+            }
+        }
+    }
+
+    /// Store an annotated AST and its source positions for `key`.
+    pub fn insert(&self, key: &str, ast: &Program, positions: &SourcePositions) -> Result<(), binjs_io::Error> {
+        let data = Encoder::new()
+            .with_positions(Some(positions.clone()))
+            .encode(&mut Self::format(), ast)?;
+
+        // Write to a temporary file, then rename, so that concurrent readers
+        // never see a partial entry.
+        let path = self.entry_path(key);
+        let tmp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        File::create(&tmp_path)
+            .and_then(|mut file| file.write_all((*data).as_ref()))
+            .and_then(|_| std::fs::rename(&tmp_path, &path))
+            .map_err(|err| binjs_io::Error::from(TokenWriterError::WriteError(err)))
+    }
+
+    /// Remove the entries older than the maximal age, then the oldest entries until the
+    /// cache fits in the maximal size. Entries are dated by the time at which they were written.
+    pub fn evict(&self) -> Result<Eviction, std::io::Error> {
+        let mut entries = self.entries()?;
+        // Oldest first.
+        entries.sort();
+
+        let now = SystemTime::now();
+        let mut eviction = Eviction {
+            remaining_entries: entries.len(),
+            remaining_bytes: entries.iter()
+                .map(|&(_, len, _)| len)
+                .sum(),
+            ..Eviction::default()
+        };
+        for (modified, len, path) in entries {
+            let is_too_old = match (self.max_age, now.duration_since(modified)) {
+                (Some(max_age), Ok(age)) => age > max_age,
+                _ => false
+            };
+            let is_too_large = self.max_bytes
+                .map_or(false, |max_bytes| eviction.remaining_bytes > max_bytes);
+            if !is_too_old && !is_too_large {
+                // All remaining entries are more recent.
+                break;
+            }
+            Self::remove(&path)?;
+            eviction.removed_entries += 1;
+            eviction.removed_bytes += len;
+            eviction.remaining_entries -= 1;
+            eviction.remaining_bytes -= len;
+        }
+        Ok(eviction)
+    }
+
+    /// Remove all entries.
+    pub fn clear(&self) -> Result<(), std::io::Error> {
+        for (_, _, path) in self.entries()? {
+            Self::remove(&path)?;
+        }
+        Ok(())
+    }
+
+    /// The modification date, byte length and path of each entry.
+    fn entries(&self) -> Result<Vec<(SystemTime, u64, PathBuf)>, std::io::Error> {
+        let mut entries = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(std::ffi::OsStr::to_str) != Some(ENTRY_EXTENSION) {
+                continue;
+            }
+            let metadata = std::fs::metadata(&path)?;
+            entries.push((metadata.modified()?, metadata.len(), path));
+        }
+        Ok(entries)
+    }
+
+    /// Remove an entry, which another process may have removed already.
+    fn remove(path: &Path) -> Result<(), std::io::Error> {
+        match std::fs::remove_file(path) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result
+        }
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", key, ENTRY_EXTENSION))
+    }
+
+    /// The format of entries.
+    fn format() -> Format {
+        Format::Multipart {
+            targets: Targets {
+                strings_table: CompressionTarget::new(Compression::Identity),
+                grammar_table: CompressionTarget::new(Compression::Identity),
+                tree: CompressionTarget::new(Compression::Identity),
+            },
+            stats: Rc::new(RefCell::new(Statistics::default()
+                .with_source_bytes(0))),
+            options: Options::default(),
+        }
+    }
+}
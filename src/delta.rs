@@ -0,0 +1,59 @@
+//! Computing and applying deltas between two versions of the same AST.
+//!
+//! When a website revs its bundle, most of the AST is typically unchanged.
+//! A delta lets a server ship only the parts of the AST that have changed,
+//! to be applied by the client on top of the version it already has.
+//!
+//! Deltas are computed on the tokens written by the serializer of both versions,
+//! see `binjs_io::entropy::delta`: unchanged or moved ranges of tokens are copied
+//! from the old version, and new tokens are entropy coded with models that have
+//! already learnt the old version, so that the delta does not pay again for the
+//! values, e.g. the strings, that the client already has.
+
+use binjs_es6::ast::{ IOPath, Program };
+use binjs_es6::io::{ Deserializer, Serializer };
+use binjs_io::{ Deserialization, TokenReaderError, TokenSerializer, TokenWriterError };
+use binjs_io::entropy::adaptive::Options;
+use binjs_io::entropy::delta::{ self, Patcher, RecordedToken, TokenRecorder };
+
+/// A data structure designed to compute and apply deltas.
+pub struct Delta {
+    delta: delta::Delta,
+}
+impl Delta {
+    /// Code the new tokens of deltas with the adaptive entropy coder, configured
+    /// by `options`. Deltas record their options, so `apply` does not depend on them.
+    pub fn new(options: Options) -> Self {
+        Delta {
+            delta: delta::Delta::new(options),
+        }
+    }
+
+    /// Compute the delta from `old` to `new`.
+    pub fn diff(&self, old: &Program, new: &Program) -> Result<Vec<u8>, TokenWriterError> {
+        self.delta.diff(&Self::record(old)?, &Self::record(new)?)
+    }
+
+    /// Apply `delta`, computed by `diff`, to `old`.
+    ///
+    /// Fails if `delta` was computed from another old version.
+    pub fn apply(&self, old: &Program, delta: &[u8]) -> Result<Program, TokenReaderError> {
+        let old = Self::record(old)
+            .map_err(|err| TokenReaderError::invalid_value(&err))?;
+        let mut deserializer = Deserializer::new(Patcher::new(&old, delta)?);
+        let new : Program = deserializer.deserialize(&mut IOPath::new())?;
+        deserializer.reader.done()?;
+        Ok(new)
+    }
+
+    /// The tokens of `ast`, as written by the serializer.
+    fn record(ast: &Program) -> Result<Vec<RecordedToken>, TokenWriterError> {
+        let mut tokens = vec![];
+        {
+            let mut serializer = Serializer::new(TokenRecorder::new(&mut tokens));
+            serializer.serialize(ast, &mut IOPath::new())?;
+            serializer.done()?;
+        }
+        Ok(tokens)
+    }
+}
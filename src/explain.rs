@@ -0,0 +1,230 @@
+//! Explaining where the bytes of a multipart file go.
+//!
+//! The file is read with an `AnnotatedHex`, as by `binjs_explore`, which attributes
+//! each byte of the tree to the node, list or value it encodes. From this, we rank:
+//!
+//! - the sections of the file, e.g. the strings table or the tree;
+//! - the subtrees of the AST, by the bytes encoding them and their descendants;
+//! - the strings, by the bytes of their references in the tree;
+//! - the categories of symbols, e.g. the kind of each `CallExpression`, or floats.
+//!
+//! If the tree is compressed, costs in the tree are in bytes before compression,
+//! which is what the compressor sees, and are labelled as such when displayed.
+
+use binjs_es6::ast::{ IOPath, Program };
+use binjs_es6::io::Deserializer;
+use binjs_io::{ Deserialization, TokenReaderError };
+use binjs_io::multipart::{ AnnotatedHex, Options, StructureNode };
+
+use std;
+use std::collections::HashMap;
+use std::fmt::{ Display, Formatter };
+use std::io::Cursor;
+
+/// A node is only a wrapper around one of its children, and not worth reporting,
+/// if the child takes at least this share of its bytes.
+const WRAPPER_SHARE : f64 = 0.9;
+
+/// The bytes spent on something, e.g. a section or a string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cost {
+    /// e.g. `[STRINGS]`, `statements[3]: FunctionDeclaration` or `"foo"`.
+    pub label: String,
+
+    /// The number of occurrences, e.g. of a string in the tree.
+    pub count: usize,
+
+    pub bytes: usize,
+}
+
+/// The costs of a file, most expensive first.
+pub struct Explanation {
+    /// The byte length of the file.
+    pub file_bytes: usize,
+
+    /// Each section of the file, in the order of the file, labelled with its header.
+    /// Bytes before the first header are labelled `(prefix)`.
+    pub sections: Vec<Cost>,
+
+    /// The byte length of the tree, before compression.
+    pub tree_bytes: usize,
+
+    /// `true` if the tree is compressed, in which case costs in the tree are in bytes
+    /// before compression.
+    pub tree_compressed: bool,
+
+    /// The most expensive subtrees, labelled with their path in the AST, in the syntax
+    /// of `binjs_dump --dot-path`. Subtrees that are only wrappers around a child are
+    /// skipped, in favor of the child.
+    pub subtrees: Vec<Cost>,
+
+    /// The most expensive strings, by the bytes of their references in the tree.
+    pub strings: Vec<Cost>,
+
+    /// The most expensive categories of symbols, e.g. `string` or `CallExpression`,
+    /// by the bytes of the symbols themselves, excluding their descendants.
+    pub categories: Vec<Cost>,
+}
+impl Explanation {
+    /// Explain a multipart file, keeping the `top` most expensive subtrees, strings
+    /// and categories. The file must not be encrypted or an archive.
+    pub fn new(data: &[u8], top: usize) -> Result<Self, TokenReaderError> {
+        let (dump, reader) = AnnotatedHex::new(Cursor::new(data), &Options::default())?;
+        let mut deserializer = Deserializer::new(reader);
+        let _ : Program = deserializer.deserialize(&mut IOPath::new())?;
+
+        // Sections, delimited by their headers.
+        let mut starts = vec![("(prefix)".to_string(), 0)];
+        for annotation in dump.container() {
+            let label = annotation.label.as_str();
+            if label.starts_with("header \"") && label.ends_with('"') {
+                starts.push((label["header \"".len()..label.len() - 1].to_string(), annotation.start));
+            }
+        }
+        let mut sections = vec![];
+        for (i, &(ref label, start)) in starts.iter().enumerate() {
+            let end = starts.get(i + 1)
+                .map_or(data.len(), |&(_, end)| end);
+            if end > start {
+                sections.push(Cost {
+                    label: label.clone(),
+                    count: 1,
+                    bytes: end - start,
+                });
+            }
+        }
+
+        let tree = dump.tree();
+        let roots = tree.structure();
+        let tree_bytes = roots.iter()
+            .map(StructureNode::byte_len)
+            .sum();
+
+        let mut subtrees = vec![];
+        for (i, root) in roots.iter().enumerate() {
+            let path = if roots.len() == 1 { String::new() } else { format!("[{}]", i) };
+            collect_subtrees(root, path, &mut subtrees);
+        }
+        subtrees.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.label.cmp(&b.label)));
+        subtrees.truncate(top);
+
+        let mut strings : HashMap<&str, Cost> = HashMap::new();
+        let mut categories : HashMap<&str, Cost> = HashMap::new();
+        for node in roots.iter().flat_map(StructureNode::descendants) {
+            let label = node.label.as_str();
+            if label.starts_with("string=") {
+                add(&mut strings, &label["string=".len()..], node.own_byte_len());
+            }
+            // As in `binjs_explore`, e.g. `string="foo"` is counted as `string`,
+            // `list (length=3)` as `list`.
+            let category = label.split(|c: char| c == '=' || c == ' ')
+                .next()
+                .unwrap_or("");
+            add(&mut categories, category, node.own_byte_len());
+        }
+
+        Ok(Explanation {
+            file_bytes: data.len(),
+            sections,
+            tree_bytes,
+            tree_compressed: dump.tree_start().is_none(),
+            subtrees,
+            strings: most_expensive(strings, top),
+            categories: most_expensive(categories, top),
+        })
+    }
+
+    /// `bytes`, as a percentage of the tree.
+    fn share(&self, bytes: usize) -> f64 {
+        if self.tree_bytes == 0 {
+            0.
+        } else {
+            100. * bytes as f64 / self.tree_bytes as f64
+        }
+    }
+}
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "File: {} bytes\n", self.file_bytes)?;
+        write!(f, "\tSections:\n")?;
+        for section in &self.sections {
+            write!(f, "\t\t{:>9} bytes ({:5.1}%)  {}\n",
+                section.bytes,
+                100. * section.bytes as f64 / self.file_bytes as f64,
+                section.label)?;
+        }
+        // Costs in the tree do not add up to the size of its section if it is compressed.
+        let unit = if self.tree_compressed {
+            let compressed : usize = self.sections.iter()
+                .filter(|section| section.label.starts_with("[TREE"))
+                .map(|section| section.bytes)
+                .sum();
+            write!(f, "\tTree: {} uncompressed bytes, compressed to {} bytes. Costs below are in uncompressed bytes.\n", self.tree_bytes, compressed)?;
+            "uncompressed bytes"
+        } else {
+            write!(f, "\tTree: {} bytes.\n", self.tree_bytes)?;
+            "bytes"
+        };
+        write!(f, "\tMost expensive subtrees:\n")?;
+        for subtree in &self.subtrees {
+            write!(f, "\t\t{:>9} {} ({:5.1}%)  {}\n", subtree.bytes, unit, self.share(subtree.bytes), subtree.label)?;
+        }
+        write!(f, "\tMost expensive strings, by references in the tree:\n")?;
+        for string in &self.strings {
+            write!(f, "\t\t{:>9} {} ({:5.1}%)  {} x{}\n", string.bytes, unit, self.share(string.bytes), string.label, string.count)?;
+        }
+        write!(f, "\tMost expensive symbols, excluding descendants:\n")?;
+        for category in &self.categories {
+            write!(f, "\t\t{:>9} {} ({:5.1}%)  {} x{}\n", category.bytes, unit, self.share(category.bytes), category.label, category.count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add one occurrence of `label`, encoded with `bytes`.
+fn add<'a>(costs: &mut HashMap<&'a str, Cost>, label: &'a str, bytes: usize) {
+    let cost = costs.entry(label)
+        .or_insert_with(|| Cost {
+            label: label.to_string(),
+            count: 0,
+            bytes: 0,
+        });
+    cost.count += 1;
+    cost.bytes += bytes;
+}
+
+/// The `top` most expensive of `costs`, most expensive first.
+fn most_expensive(costs: HashMap<&str, Cost>, top: usize) -> Vec<Cost> {
+    let mut costs : Vec<_> = costs.into_iter()
+        .map(|(_, cost)| cost)
+        .collect();
+    costs.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.label.cmp(&b.label)));
+    costs.truncate(top);
+    costs
+}
+
+/// Collect `node`, found at `path`, and its descendants that have children,
+/// skipping wrappers.
+fn collect_subtrees(node: &StructureNode, path: String, subtrees: &mut Vec<Cost>) {
+    if node.children.is_empty() {
+        return;
+    }
+    let is_wrapper = node.children.iter()
+        .any(|child| child.byte_len() as f64 >= WRAPPER_SHARE * node.byte_len() as f64);
+    if !is_wrapper && node.byte_len() > 0 {
+        subtrees.push(Cost {
+            label: format!("{}: {}", if path.is_empty() { "(root)" } else { path.as_str() }, node.label),
+            count: 1,
+            bytes: node.byte_len(),
+        });
+    }
+    let is_list = node.label.starts_with("list ");
+    for (i, child) in node.children.iter().enumerate() {
+        let child_path = match (is_list, child.field.as_ref()) {
+            (true, _) | (false, None) => format!("{}[{}]", path, i),
+            (false, Some(field)) if path.is_empty() => field.clone(),
+            (false, Some(field)) => format!("{}.{}", path, field),
+        };
+        collect_subtrees(child, child_path, subtrees);
+    }
+}
@@ -17,6 +17,12 @@
 //! This crate offers a (WIP) reference implementation for BinJS, a vendor-neutral
 //! JavaScript format designed to optimize parsing speed and, when possible,
 //! loading speed.
+//!
+//! # Stability
+//!
+//! `Encoder`, `Decoder`, `Dictionary`, `Statistics` and `Error`, at the root of this
+//! crate, follow semantic versioning, see module `api`. The other modules expose the
+//! internals of the implementation, which change along with the format.
 
 #![feature(box_patterns)]
 
@@ -24,6 +30,7 @@ extern crate binjs_generic;
 extern crate binjs_es6;
 extern crate binjs_io;
 extern crate binjs_meta;
+#[macro_use]
 extern crate binjs_shared;
 
 #[allow(unused_imports)]
@@ -31,13 +38,17 @@ extern crate binjs_shared;
 extern crate assert_matches;
 #[cfg(test)]
 extern crate env_logger;
+extern crate bincode;
+extern crate glob;
 extern crate itertools;
 #[macro_use]
-extern crate json;
-#[macro_use]
 extern crate log;
 extern crate rand;
 extern crate lzw;
+extern crate serde_json;
+extern crate sha2;
+extern crate tracing;
+extern crate tracing_subscriber;
 extern crate vec_map;
 
 /// Working with a generic (i.e. JSON-based) representation
@@ -71,6 +82,34 @@ pub mod meta {
     pub use binjs_meta::*;
 }
 
+/// A curated API for encoding and decoding, stable across releases.
+pub mod api;
+pub use api::{ Decoder, Dictionary, Encoder, Error, ErrorKind, Statistics };
+
+/// Comparing the size of the streams of the entropy format with their theoretical bounds.
+pub mod bounds;
+
+/// Caching parsed and annotated ASTs across runs.
+pub mod cache;
+
+/// Computing and applying deltas between two versions of an AST.
+pub mod delta;
+
+/// Explaining which parts of a file take the most bytes.
+pub mod explain;
+
+/// Corrupting valid files, to test how decoders handle invalid input.
+pub mod mutate;
+
+/// Reducing failing ASTs to minimal failing ASTs.
+pub mod reduce;
+
+/// Checking every file of a directory and summarizing the outcomes.
+pub mod runner;
+
+/// Comparing the encoded sizes of a corpus with a baseline.
+pub mod sizecheck;
+
 /// Parsing source JavaScript.
 pub mod source;
 
@@ -0,0 +1,370 @@
+//! Corrupting valid multipart files, to build a corpus of negative tests for decoders.
+//!
+//! The file is first read with an `AnnotatedHex`, which tells us which bytes encode
+//! what, e.g. the kind of each node or the index of each string. Mutations then
+//! corrupt these bytes in ways that are meaningful for the format:
+//!
+//! - `FlipKind` replaces the kind of a node with another kind of the grammar table;
+//! - `FlipString` replaces a string, e.g. the value of a string enum, with another
+//!   string of the strings table;
+//! - `KindOutOfRange` and `StringOutOfRange` replace an index with an index past the
+//!   end of its table;
+//! - `Truncate` cuts the file, either at the start of a section or anywhere before
+//!   the end of the tree.
+//!
+//! Flipped values are encoded with the same number of bytes, so the rest of the file
+//! is still read as it was written. Each variant is labelled with the behavior
+//! expected from a decoder, see `Expected`.
+//!
+//! Mutations of the tree require the tree to be stored uncompressed, e.g.
+//! `binjs_encode multipart --section-compression identity`.
+
+use binjs_es6::ast::{ IOPath, Program };
+use binjs_es6::io::Deserializer;
+use binjs_io::{ Deserialization, TokenReaderError };
+use binjs_io::bytes::varnum::{ ReadVarNum, WriteVarNum };
+use binjs_io::multipart::{ AnnotatedHex, Options, TreeTokenReader };
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+
+use std;
+use std::io::Cursor;
+use std::panic::AssertUnwindSafe;
+
+/// The ways in which a file may be corrupted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MutationKind {
+    FlipKind,
+    FlipString,
+    KindOutOfRange,
+    StringOutOfRange,
+    Truncate,
+}
+impl MutationKind {
+    /// All the mutations, in the order of `NAMES`.
+    pub const ALL: [MutationKind; 5] = [
+        MutationKind::FlipKind,
+        MutationKind::FlipString,
+        MutationKind::KindOutOfRange,
+        MutationKind::StringOutOfRange,
+        MutationKind::Truncate,
+    ];
+
+    /// The names of mutations, as accepted by `parse`.
+    pub const NAMES: [&'static str; 5] = ["flip-kind", "flip-string", "kind-out-of-range", "string-out-of-range", "truncate"];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::NAMES.iter()
+            .position(|candidate| *candidate == name)
+            .map(|index| Self::ALL[index])
+    }
+
+    pub fn name(&self) -> &'static str {
+        let index = Self::ALL.iter()
+            .position(|candidate| candidate == self)
+            .unwrap(); // All mutations are in `ALL`.
+        Self::NAMES[index]
+    }
+}
+
+/// The behavior expected from a decoder reading a corrupted file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expected {
+    /// The decoder must report an error.
+    Reject,
+
+    /// The decoder may either report an error or decode a different AST,
+    /// e.g. if a node is replaced with another node that has the same fields.
+    /// In either case, it must not crash.
+    NoCrash,
+}
+impl Expected {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Expected::Reject => "reject",
+            Expected::NoCrash => "no-crash",
+        }
+    }
+
+    /// `true` if `outcome` is acceptable.
+    pub fn accepts(&self, outcome: Outcome) -> bool {
+        match (*self, outcome) {
+            (_, Outcome::Panicked) => false,
+            (Expected::Reject, Outcome::Decoded) => false,
+            _ => true,
+        }
+    }
+}
+
+/// The behavior of our own decoder reading a corrupted file, see `Mutant::check`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Rejected,
+    Decoded,
+    Panicked,
+}
+
+/// A corrupted file.
+pub struct Mutant {
+    pub kind: MutationKind,
+
+    /// The offset of the corruption in the file.
+    pub offset: usize,
+
+    /// A human-readable description of the corruption.
+    pub description: String,
+
+    pub expected: Expected,
+
+    /// The contents of the corrupted file.
+    pub data: Vec<u8>,
+}
+impl Mutant {
+    /// Decode the corrupted file as a multipart `Program`.
+    ///
+    /// Panics are caught, but still reported by the panic hook, which callers may replace.
+    pub fn check(&self) -> Outcome {
+        let result = std::panic::catch_unwind(AssertUnwindSafe(|| decode(&self.data)));
+        match result {
+            Ok(Ok(_)) => Outcome::Decoded,
+            Ok(Err(_)) => Outcome::Rejected,
+            Err(_) => Outcome::Panicked,
+        }
+    }
+}
+
+fn decode(data: &[u8]) -> Result<Program, TokenReaderError> {
+    let reader = TreeTokenReader::new(Cursor::new(data))?;
+    let mut deserializer = Deserializer::new(reader);
+    deserializer.deserialize(&mut IOPath::new())
+}
+
+/// A varnum-encoded index in the tree.
+struct Index {
+    /// The bytes encoding the index, as offsets in the file.
+    start: usize,
+    end: usize,
+
+    /// The index.
+    value: u32,
+}
+
+/// Produce corrupted variants of a file.
+pub struct Mutator {
+    data: Vec<u8>,
+
+    /// The kinds of nodes in the tree, empty if the tree is compressed.
+    kinds: Vec<Index>,
+
+    /// The strings in the tree, empty if the tree is compressed.
+    strings: Vec<Index>,
+
+    number_of_kinds: u32,
+    number_of_strings: u32,
+
+    /// If `true`, kinds are shifted left by one bit, the lowest bit marking runs.
+    runs: bool,
+
+    /// The offsets at which sections start.
+    sections: Vec<usize>,
+
+    /// The offset of the end of the tree, i.e. of the checksums if any.
+    content_end: usize,
+
+    checksums: bool,
+}
+impl Mutator {
+    /// Read a valid multipart file, which must not be encrypted, signed or an archive.
+    pub fn new(data: Vec<u8>) -> Result<Self, TokenReaderError> {
+        let (dump, reader) = AnnotatedHex::new(Cursor::new(&data), &Options::default())?;
+        let mut deserializer = Deserializer::new(reader);
+        let _ : Program = deserializer.deserialize(&mut IOPath::new())?;
+
+        let mut number_of_kinds = 0;
+        let mut number_of_strings = 0;
+        let mut runs = false;
+        let mut sections = vec![];
+        let mut content_end = data.len();
+        let mut checksums = false;
+        for annotation in dump.container() {
+            let label = annotation.label.as_str();
+            if label.starts_with("number of node kinds=") {
+                number_of_kinds = label["number of node kinds=".len()..].parse()
+                    .map_err(|_| TokenReaderError::InvalidValue)?;
+            } else if label.starts_with("number of strings=") {
+                number_of_strings = label["number of strings=".len()..].parse()
+                    .map_err(|_| TokenReaderError::InvalidValue)?;
+            } else if label == "header \"[CHECKSUM]\"" {
+                content_end = annotation.start;
+                checksums = true;
+            } else if label.starts_with("header \"[") {
+                if label.starts_with("header \"[TREE-RUNS") {
+                    runs = true;
+                }
+                sections.push(annotation.start);
+            }
+        }
+
+        let mut kinds = vec![];
+        let mut strings = vec![];
+        if let Some(tree_start) = dump.tree_start() {
+            let tree = dump.tree();
+            for annotation in &tree.annotations {
+                let label = annotation.label.as_str();
+                let is_kind = label.ends_with(" {") && !label.starts_with("list ");
+                let is_string = label.starts_with("string=");
+                if !is_kind && !is_string {
+                    continue;
+                }
+                let start = tree_start + annotation.start;
+                let end = tree_start + annotation.end;
+
+                // Labels may cover several values, e.g. a kind and the length of its
+                // run. Only keep labels that cover a single varnum.
+                let mut value = 0;
+                match Cursor::new(&data[start..end]).read_varnum_to(&mut value) {
+                    Ok(byte_len) if byte_len == end - start && byte_len > 0 => {},
+                    _ => continue
+                }
+                let index = Index {
+                    start,
+                    end,
+                    value,
+                };
+                if is_kind {
+                    kinds.push(index);
+                } else {
+                    strings.push(index);
+                }
+            }
+        }
+
+        Ok(Mutator {
+            data,
+            kinds,
+            strings,
+            number_of_kinds,
+            number_of_strings,
+            runs,
+            sections,
+            content_end,
+            checksums,
+        })
+    }
+
+    /// `true` if the file contains checksums, in which case all corruptions before
+    /// the checksums are rejected by decoders that check them.
+    pub fn has_checksums(&self) -> bool {
+        self.checksums
+    }
+
+    /// `true` if the tree is stored uncompressed, which is required by all mutations
+    /// but `Truncate`.
+    pub fn has_uncompressed_tree(&self) -> bool {
+        !self.kinds.is_empty()
+    }
+
+    /// Produce a variant of the file, corrupted by a mutation of kind `kind`,
+    /// or `None` if the file offers nothing to corrupt in this manner.
+    pub fn mutate<R: Rng>(&self, kind: MutationKind, rng: &mut R) -> Option<Mutant> {
+        match kind {
+            MutationKind::FlipKind => {
+                let index = self.kinds.choose(rng)?;
+                let kind = self.kind_of(index.value);
+                let replacement = self.flip(index, kind, self.number_of_kinds, true, rng)?;
+                Some(self.replace(MutationKind::FlipKind, index, self.tag_of(replacement, index.value), Expected::NoCrash,
+                    format!("node kind #{} replaced with node kind #{}", kind, replacement)))
+            }
+            MutationKind::FlipString => {
+                let index = self.strings.choose(rng)?;
+                let replacement = self.flip(index, index.value, self.number_of_strings, false, rng)?;
+                Some(self.replace(MutationKind::FlipString, index, replacement, Expected::NoCrash,
+                    format!("string #{} replaced with string #{}", index.value, replacement)))
+            }
+            MutationKind::KindOutOfRange => {
+                let index = self.kinds.choose(rng)?;
+                let replacement = self.number_of_kinds + rng.gen_range(0, 16);
+                Some(self.replace(MutationKind::KindOutOfRange, index, self.tag_of(replacement, index.value), Expected::Reject,
+                    format!("node kind #{} replaced with node kind #{}, out of {}", self.kind_of(index.value), replacement, self.number_of_kinds)))
+            }
+            MutationKind::StringOutOfRange => {
+                let index = self.strings.choose(rng)?;
+                let replacement = self.number_of_strings + rng.gen_range(0, 16);
+                Some(self.replace(MutationKind::StringOutOfRange, index, replacement, Expected::Reject,
+                    format!("string #{} replaced with string #{}, out of {}", index.value, replacement, self.number_of_strings)))
+            }
+            MutationKind::Truncate => {
+                if self.content_end == 0 {
+                    return None;
+                }
+                // Either at the start of a section, or anywhere.
+                let offset = match self.sections.choose(rng) {
+                    Some(offset) if rng.gen::<bool>() => *offset,
+                    _ => rng.gen_range(0, self.content_end)
+                };
+                Some(Mutant {
+                    kind: MutationKind::Truncate,
+                    offset,
+                    description: format!("file truncated to {} bytes, out of {}", offset, self.data.len()),
+                    expected: Expected::Reject,
+                    data: self.data[..offset].to_vec(),
+                })
+            }
+        }
+    }
+
+    /// The node kind encoded as `tag`.
+    fn kind_of(&self, tag: u32) -> u32 {
+        if self.runs {
+            tag >> 1
+        } else {
+            tag
+        }
+    }
+
+    /// The tag encoding `kind`, instead of `previous`.
+    fn tag_of(&self, kind: u32, previous: u32) -> u32 {
+        if self.runs {
+            (kind << 1) | (previous & 1)
+        } else {
+            kind
+        }
+    }
+
+    /// Pick a value of `0..len` other than `value`, encoded with as many bytes as `index`.
+    /// If `is_kind`, values are node kinds, encoded as tags.
+    fn flip<R: Rng>(&self, index: &Index, value: u32, len: u32, is_kind: bool, rng: &mut R) -> Option<u32> {
+        let byte_len = index.end - index.start;
+        let candidates : Vec<u32> = (0..len)
+            .filter(|candidate| *candidate != value)
+            .filter(|candidate| {
+                let encoded = if is_kind { self.tag_of(*candidate, index.value) } else { *candidate };
+                varnum(encoded).len() == byte_len
+            })
+            .collect();
+        candidates.choose(rng)
+            .cloned()
+    }
+
+    fn replace(&self, kind: MutationKind, index: &Index, value: u32, expected: Expected, description: String) -> Mutant {
+        let mut data = Vec::with_capacity(self.data.len());
+        data.extend_from_slice(&self.data[..index.start]);
+        data.extend(varnum(value));
+        data.extend_from_slice(&self.data[index.end..]);
+        Mutant {
+            kind,
+            offset: index.start,
+            description,
+            expected,
+            data,
+        }
+    }
+}
+
+fn varnum(value: u32) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.write_varnum(value)
+        .unwrap(); // Writing to a `Vec` cannot fail.
+    bytes
+}
@@ -0,0 +1,251 @@
+//! Reducing a failing AST to a minimal failing AST.
+//!
+//! The reducer repeatedly attempts to simplify the AST, keeping a simplification
+//! if and only if the AST still fails, until no simplification is kept. The
+//! simplifications are:
+//!
+//! - removing chunks of items from lists, then single items, as in delta debugging;
+//! - replacing the value of a field with `null`;
+//! - replacing a node with one of its child nodes.
+//!
+//! The reducer knows nothing about the grammar. Simplifications that produce invalid
+//! ASTs are expected to be rejected by the test, e.g. because the AST may not be
+//! imported, or because it fails in a different manner.
+
+use binjs_shared::JSON;
+
+use std;
+
+/// A step from a node to a child.
+#[derive(Clone, Debug)]
+enum Step {
+    Field(String),
+    Index(usize),
+}
+
+fn get_mut<'a>(value: &'a mut JSON, path: &[Step]) -> Option<&'a mut JSON> {
+    let mut value = value;
+    for step in path {
+        value = match *step {
+            Step::Field(ref name) => value.get_mut(name.as_str())?,
+            Step::Index(index) => value.get_mut(index)?,
+        };
+    }
+    Some(value)
+}
+
+/// The paths to all the values of `value`, parents before children.
+fn paths(value: &JSON, prefix: &mut Vec<Step>, result: &mut Vec<Vec<Step>>) {
+    result.push(prefix.clone());
+    match *value {
+        JSON::Array(ref array) => {
+            for (index, item) in array.iter().enumerate() {
+                prefix.push(Step::Index(index));
+                paths(item, prefix, result);
+                prefix.pop();
+            }
+        }
+        JSON::Object(ref object) => {
+            for (name, field) in object.iter() {
+                if name == "type" {
+                    continue;
+                }
+                prefix.push(Step::Field(name.clone()));
+                paths(field, prefix, result);
+                prefix.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The child nodes of `value`, i.e. objects found in its fields, directly or in lists.
+fn child_nodes(value: &JSON) -> Vec<JSON> {
+    let mut result = vec![];
+    if let JSON::Object(ref object) = *value {
+        for (name, field) in object.iter() {
+            if name == "type" {
+                continue;
+            }
+            match *field {
+                JSON::Object(_) => result.push(field.clone()),
+                JSON::Array(ref array) => {
+                    result.extend(array.iter()
+                        .filter(|item| item.is_object())
+                        .cloned())
+                }
+                _ => {}
+            }
+        }
+    }
+    result
+}
+
+/// Statistics on a reduction.
+#[derive(Clone, Debug, Default)]
+pub struct Statistics {
+    /// The number of candidate ASTs tested.
+    pub attempts: usize,
+
+    /// The number of simplifications kept.
+    pub reductions: usize,
+}
+
+/// A data structure designed to reduce failing ASTs.
+pub struct Reducer<F> where F: FnMut(&JSON) -> bool {
+    /// Returns `true` if an AST still fails.
+    is_failing: F,
+
+    statistics: Statistics,
+}
+impl<F> Reducer<F> where F: FnMut(&JSON) -> bool {
+    /// Create a reducer keeping the simplifications for which `is_failing` returns `true`.
+    pub fn new(is_failing: F) -> Self {
+        Reducer {
+            is_failing,
+            statistics: Statistics::default(),
+        }
+    }
+
+    /// Reduce `ast`, which is expected to fail.
+    pub fn reduce(&mut self, ast: JSON) -> JSON {
+        let mut ast = ast;
+        loop {
+            let before = self.statistics.reductions;
+            ast = self.reduce_lists(ast);
+            ast = self.reduce_nodes(ast);
+            if self.statistics.reductions == before {
+                return ast;
+            }
+        }
+    }
+
+    /// Statistics on the reductions so far.
+    pub fn statistics(&self) -> &Statistics {
+        &self.statistics
+    }
+
+    /// Test `candidate`, returning it if it still fails.
+    fn attempt(&mut self, candidate: JSON) -> Option<JSON> {
+        self.statistics.attempts += 1;
+        if (self.is_failing)(&candidate) {
+            self.statistics.reductions += 1;
+            Some(candidate)
+        } else {
+            None
+        }
+    }
+
+    /// Remove chunks of items from all lists, halving the size of chunks
+    /// down to single items.
+    fn reduce_lists(&mut self, ast: JSON) -> JSON {
+        let mut ast = ast;
+        let mut all_paths = vec![];
+        paths(&ast, &mut vec![], &mut all_paths);
+        for path in all_paths {
+            let mut chunk = match get_mut(&mut ast, &path) {
+                Some(&mut JSON::Array(ref array)) if !array.is_empty() => array.len(),
+                // The path may have disappeared with an earlier reduction.
+                _ => continue,
+            };
+            while chunk > 0 {
+                let mut start = 0;
+                loop {
+                    let len = match get_mut(&mut ast, &path) {
+                        Some(&mut JSON::Array(ref array)) => array.len(),
+                        _ => 0,
+                    };
+                    if start >= len {
+                        break;
+                    }
+                    let mut candidate = ast.clone();
+                    if let Some(&mut JSON::Array(ref mut array)) = get_mut(&mut candidate, &path) {
+                        let end = std::cmp::min(start + chunk, array.len());
+                        array.drain(start..end);
+                    }
+                    match self.attempt(candidate) {
+                        Some(reduced) => ast = reduced,
+                        None => start += chunk,
+                    }
+                }
+                chunk /= 2;
+            }
+        }
+        ast
+    }
+
+    /// Replace values with `null` and nodes with their child nodes.
+    fn reduce_nodes(&mut self, ast: JSON) -> JSON {
+        let mut ast = ast;
+        let mut all_paths = vec![];
+        paths(&ast, &mut vec![], &mut all_paths);
+        'per_path: for path in all_paths {
+            let value = match get_mut(&mut ast, &path) {
+                Some(value) => value.clone(),
+                // The path may have disappeared with an earlier reduction.
+                None => continue,
+            };
+            if !path.is_empty() && !value.is_null() {
+                let mut candidate = ast.clone();
+                *get_mut(&mut candidate, &path).unwrap() = JSON::Null; // Just checked.
+                if let Some(reduced) = self.attempt(candidate) {
+                    ast = reduced;
+                    continue 'per_path;
+                }
+            }
+            for child in child_nodes(&value) {
+                let mut candidate = ast.clone();
+                *get_mut(&mut candidate, &path).unwrap() = child; // Just checked.
+                if let Some(reduced) = self.attempt(candidate) {
+                    ast = reduced;
+                    continue 'per_path;
+                }
+            }
+        }
+        ast
+    }
+}
+
+#[test]
+fn test_reduce() {
+    // An AST fails if it contains an identifier `bad`.
+    fn contains_bad(value: &JSON) -> bool {
+        match *value {
+            JSON::Array(ref array) => array.iter().any(contains_bad),
+            JSON::Object(ref object) => object.get("name").and_then(JSON::as_str) == Some("bad")
+                || object.values().any(contains_bad),
+            _ => false,
+        }
+    }
+    let identifier = |name: &str| object!{
+        "type" => "IdentifierExpression",
+        "name" => name
+    };
+    let ast = object!{
+        "type" => "Script",
+        "statements" => array![
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => identifier("good")
+            },
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => object!{
+                    "type" => "BinaryExpression",
+                    "operator" => "+",
+                    "left" => identifier("good"),
+                    "right" => identifier("bad")
+                }
+            },
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => identifier("good")
+            }
+        ]
+    };
+
+    let mut reducer = Reducer::new(contains_bad);
+    let reduced = reducer.reduce(ast);
+    assert_eq!(reduced, identifier("bad"));
+    assert!(reducer.statistics().reductions > 0);
+}
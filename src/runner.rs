@@ -0,0 +1,163 @@
+//! Running a check on every file of a directory, e.g. a test suite, and
+//! summarizing the outcome of each file as JSON.
+//!
+//! Checks may panic, e.g. on ASTs that the encoder does not support. Panics
+//! are caught and recorded as the failure of the file being checked, so that
+//! one bad file does not abort the whole run.
+
+use binjs_shared::{ JSON, JSONExt, JSONObject };
+
+use glob;
+
+use std;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+
+/// The outcome of checking a single file.
+pub trait Outcome: Copy + Ord {
+    /// The name of the outcome, as written in summaries.
+    fn name(&self) -> &'static str;
+
+    /// The outcome of a check that panicked.
+    fn panic() -> Self;
+}
+
+/// The result of checking a single file.
+pub struct Report<O> {
+    pub outcome: O,
+
+    /// A description of the failure, if any.
+    pub error: Option<String>,
+
+    /// Additional entries for the file in the summary.
+    pub details: JSONObject,
+}
+impl<O> Report<O> {
+    pub fn new(outcome: O) -> Self {
+        Report {
+            outcome,
+            error: None,
+            details: JSONObject::new(),
+        }
+    }
+    pub fn with_error(self, error: String) -> Self {
+        Report {
+            error: Some(error),
+            ..self
+        }
+    }
+    pub fn with_detail<T: Into<JSON>>(mut self, key: &str, value: T) -> Self {
+        self.details.insert(key.to_string(), value.into());
+        self
+    }
+}
+
+/// The outcomes of all the files checked.
+pub struct Summary<O> {
+    /// The number of files for each outcome.
+    pub counts: BTreeMap<O, usize>,
+
+    /// One JSON object per file, with its name, outcome, error and details.
+    pub files: Vec<JSON>,
+}
+impl<O: Outcome> Summary<O> {
+    /// The number of files checked.
+    pub fn total(&self) -> usize {
+        self.files.len()
+    }
+
+    /// The number of files with a given outcome.
+    pub fn count(&self, outcome: O) -> usize {
+        self.counts.get(&outcome)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Export the summary, after the entries of `header`.
+    pub fn export(self, mut header: JSON) -> JSON {
+        for (outcome, count) in self.counts {
+            header[outcome.name()] = JSON::from(count);
+        }
+        header["files"] = JSON::Array(self.files);
+        header
+    }
+}
+
+/// Run `f`, turning panics into errors.
+pub fn guard<T, F: FnOnce() -> Result<T, String>>(f: F) -> Result<T, String> {
+    std::panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| {
+            let message = payload.downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(format!("panic: {}", message))
+        })
+}
+
+/// Run `check` on each file matching `pattern`.
+///
+/// Files are named in the summary by their path relative to `root`. `check`
+/// returns `None` for files that should not appear in the summary at all. Unless
+/// `quiet`, the outcome of each file is printed on stderr as soon as it is known.
+pub fn run<O, F>(root: &Path, pattern: &str, quiet: bool, mut check: F) -> Summary<O>
+    where O: Outcome,
+          F: FnMut(&Path) -> Option<Report<O>>
+{
+    // Panics are recorded in the summary, don't clutter stderr with them.
+    std::panic::set_hook(Box::new(|_| {}));
+
+    let mut summary = Summary {
+        counts: BTreeMap::new(),
+        files: vec![],
+    };
+    for entry in glob::glob(pattern)
+        .expect("Invalid glob pattern")
+    {
+        let path = entry.expect("Invalid entry");
+        let name = path.strip_prefix(root)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let report = match guard(|| Ok(check(&path))) {
+            Ok(Some(report)) => report,
+            Ok(None) => continue,
+            Err(err) => Report::new(O::panic()).with_error(err)
+        };
+        if !quiet {
+            eprintln!("{}: {}", name, report.outcome.name());
+        }
+        *summary.counts.entry(report.outcome).or_insert(0) += 1;
+
+        let mut file = object!{
+            "file" => name,
+            "result" => report.outcome.name(),
+            "error" => report.error.map_or(JSON::Null, JSON::from)
+        };
+        for (key, value) in report.details {
+            file[key] = value;
+        }
+        summary.files.push(file);
+    }
+    let _ = std::panic::take_hook();
+
+    summary
+}
+
+/// Write a JSON summary to the file at `dest`, or to stdout if `dest` is `None`.
+pub fn write_summary(summary: &JSON, dest: Option<&str>) -> std::io::Result<()> {
+    let pretty = summary.pretty(2);
+    match dest {
+        Some(path) => {
+            File::create(path)
+                .and_then(|mut dest| dest.write_all(pretty.as_bytes()))
+        }
+        None => {
+            println!("{}", pretty);
+            Ok(())
+        }
+    }
+}
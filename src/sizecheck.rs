@@ -0,0 +1,203 @@
+//! Comparing the encoded size of each file of a corpus, and of the entire corpus,
+//! against a stored baseline, see `binjs_sizecheck`.
+//!
+//! Sizes are only comparable if they were obtained with the same format and the same
+//! format options, see `Format::options`, so comparing sizes obtained with another
+//! format is an error rather than a regression.
+
+use binjs_es6;
+use binjs_es6::ast::{ Program, Walker, WalkPath };
+use binjs_io::Format;
+use binjs_shared::{ FromJSON, JSON };
+
+use source::{ Shift, SourceParser };
+
+use std;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+
+/// The sizes of the encoded files of a corpus.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sizes {
+    /// The name of the format, e.g. `Multipart`, see `Format::name`.
+    pub format: String,
+
+    /// The options of the format, see `Format::options`.
+    pub options: String,
+
+    /// Path relative to the corpus => encoded size, in bytes.
+    pub files: BTreeMap<String, usize>,
+}
+impl Sizes {
+    /// No files yet, encoded with `format`.
+    pub fn new(format: &Format) -> Self {
+        Sizes {
+            format: format.name(),
+            options: format.options(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Read sizes exported with `export`.
+    pub fn import(json: &JSON) -> Result<Self, String> {
+        let format = json["format"].as_str()
+            .ok_or_else(|| "Invalid baseline, expected a string `format`".to_string())?
+            .to_string();
+        let options = json["options"].as_str()
+            .ok_or_else(|| "Invalid baseline, expected a string `options`, refresh it with `--update`".to_string())?
+            .to_string();
+        let mut files = BTreeMap::new();
+        for (name, size) in json["files"].as_object()
+            .ok_or_else(|| "Invalid baseline, expected an object `files`".to_string())?
+        {
+            let size = size.as_u64()
+                .ok_or_else(|| format!("Invalid baseline, expected a number as size of {}", name))?;
+            files.insert(name.clone(), size as usize);
+        }
+        Ok(Sizes {
+            format,
+            options,
+            files,
+        })
+    }
+
+    pub fn export(&self) -> JSON {
+        object!{
+            "format" => self.format.clone(),
+            "options" => self.options.clone(),
+            "total" => self.total(),
+            "files" => JSON::Object(self.files.iter()
+                .map(|(name, &size)| (name.clone(), JSON::from(size)))
+                .collect())
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.files.values()
+            .sum()
+    }
+
+    /// Compare the sizes of `current` with these sizes, flagging the files that grow
+    /// by more than `file_threshold` percents and a total that grows by more than
+    /// `threshold` percents. Only the files present in both are compared.
+    ///
+    /// Fails if `current` was obtained with another format or other format options.
+    pub fn compare(&self, current: &Sizes, file_threshold: f64, threshold: f64) -> Result<Comparison, String> {
+        if self.format != current.format || self.options != current.options {
+            return Err(format!("The baseline was written with format {} ({}), not {} ({}), refresh it with `--update`.",
+                self.format, self.options, current.format, current.options));
+        }
+        let mut files = BTreeMap::new();
+        let mut before = 0;
+        let mut after = 0;
+        for (name, &size) in &current.files {
+            let file_change = match self.files.get(name) {
+                Some(&previous) => {
+                    before += previous;
+                    after += size;
+                    let delta = change(previous, size);
+                    FileChange::Changed {
+                        before: previous,
+                        after: size,
+                        delta,
+                        regression: delta > file_threshold,
+                    }
+                }
+                None => FileChange::Added(size)
+            };
+            files.insert(name.clone(), file_change);
+        }
+        for (name, &size) in &self.files {
+            if !current.files.contains_key(name) {
+                files.insert(name.clone(), FileChange::Removed(size));
+            }
+        }
+        let delta = change(before, after);
+        Ok(Comparison {
+            files,
+            before,
+            after,
+            delta,
+            regression: delta > threshold,
+        })
+    }
+}
+
+/// The change of size of a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileChange {
+    /// The file is not in the baseline.
+    Added(usize),
+
+    /// The file is only in the baseline.
+    Removed(usize),
+
+    /// The file is in both, `delta` is the relative change in percents.
+    Changed {
+        before: usize,
+        after: usize,
+        delta: f64,
+        regression: bool,
+    },
+}
+
+/// The changes of size between a baseline and a corpus.
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    /// Path relative to the corpus => change.
+    pub files: BTreeMap<String, FileChange>,
+
+    /// The total size of the files present in both, in the baseline.
+    pub before: usize,
+
+    /// The total size of the files present in both, in the corpus.
+    pub after: usize,
+
+    /// The relative change of the total size, in percents.
+    pub delta: f64,
+
+    /// `true` if the total size regressed beyond the threshold.
+    pub regression: bool,
+}
+impl Comparison {
+    /// The number of files whose size regressed beyond the threshold, plus one if the
+    /// total size did.
+    pub fn regressions(&self) -> usize {
+        let files = self.files.values()
+            .filter(|change| match **change {
+                FileChange::Changed { regression, .. } => regression,
+                _ => false
+            })
+            .count();
+        if self.regression { files + 1 } else { files }
+    }
+}
+
+/// The relative change from `before` to `after`, in percents.
+pub fn change(before: usize, after: usize) -> f64 {
+    if before == 0 {
+        if after == 0 { 0. } else { std::f64::INFINITY }
+    } else {
+        100. * (after as f64 - before as f64) / before as f64
+    }
+}
+
+/// Encode the script or module at `path`, as parsed by `parser`, and return its size,
+/// turning errors and panics into messages.
+pub fn encoded_size(parser: &Shift, format: &mut Format, lazification: u32, path: &Path) -> Result<usize, String> {
+    std::panic::catch_unwind(AssertUnwindSafe(|| {
+        let json = parser.parse_file(path)
+            .map_err(|err| format!("Could not parse: {:?}", err))?;
+        let mut ast = Program::import(&json)
+            .map_err(|err| format!("Could not import AST: {:?}", err))?;
+        binjs_es6::scopes::AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+        ast.walk(&mut WalkPath::new(), &mut binjs_es6::lazy::LazifierVisitor::new(lazification))
+            .map_err(|err| format!("Could not introduce laziness: {:?}", err))?;
+        let data = binjs_es6::io::Encoder::new()
+            .encode(format, &ast)
+            .map_err(|err| format!("Could not encode: {:?}", err))?;
+        Ok((*data).as_ref().len())
+    })).unwrap_or_else(|_| Err("panic".to_string()))
+}
@@ -0,0 +1,172 @@
+//! Read the data through a call to the Babel parser.
+//!
+//! Babel understands a number of syntactic extensions to JavaScript
+//! that the Shift parser does not support. We use it to parse these
+//! extensions into an ESTree AST, lower them to plain JavaScript, then
+//! convert the result to a BinJS AST through the Shift AST.
+
+use binjs_shared::JSON;
+
+use std::path::*;
+use std::sync::Arc;
+
+use source::daemon::DaemonPool;
+use source::estree::FromESTree;
+use source::jsx::LowerJSX;
+use source::parser::{ SourceParser, SourceType };
+use source::shift::{ Error, Shift };
+use source::typescript::StripTypes;
+
+/// Using a Node + Babel binary to parse an AST.
+pub struct Babel {
+    /// The instance of Shift, used to launch Node and
+    /// convert from the Shift AST.
+    shift: Shift,
+
+    /// If `true`, accept TypeScript sources and strip their types.
+    typescript: bool,
+
+    /// If specified, accept JSX and lower it to function calls.
+    jsx: Option<LowerJSX>,
+
+    /// The goal symbol with which sources are parsed.
+    source_type: SourceType,
+}
+
+impl Babel {
+    pub fn new() -> Self {
+        Babel {
+            shift: Shift::new(),
+            typescript: false,
+            jsx: None,
+            source_type: SourceType::Script,
+        }
+    }
+
+    /// Parse sources as scripts, modules, or either, `SourceType::Script` by default.
+    pub fn with_source_type(self, source_type: SourceType) -> Self {
+        Babel {
+            source_type,
+            ..self
+        }
+    }
+
+    /// If specified, run Babel in a pool of long-lived Node processes, see `Shift::with_daemons`.
+    pub fn with_daemons(self, daemons: Option<Arc<DaemonPool>>) -> Self {
+        Babel {
+            shift: self.shift.with_daemons(daemons),
+            ..self
+        }
+    }
+
+    /// Accept TypeScript sources, stripping type annotations.
+    ///
+    /// This performs no type checking.
+    pub fn with_typescript(self, typescript: bool) -> Self {
+        Babel {
+            typescript,
+            ..self
+        }
+    }
+
+    /// Accept JSX, lowering elements to calls to `pragma` (e.g. `React.createElement`)
+    /// and fragments to `pragma_frag` (e.g. `React.Fragment`).
+    pub fn with_jsx(self, pragma: &str, pragma_frag: &str) -> Self {
+        Babel {
+            jsx: Some(LowerJSX::new(pragma, pragma_frag)),
+            ..self
+        }
+    }
+
+    /// The list of Babel plugins for this configuration.
+    fn plugins(&self) -> Vec<&'static str> {
+        let mut plugins = vec!["estree"];
+        if self.typescript {
+            plugins.push("typescript");
+        }
+        if self.jsx.is_some() {
+            plugins.push("jsx");
+        }
+        plugins
+    }
+
+    fn parse_script(&self, source: &str, source_type: SourceType) -> Result<JSON, Error> {
+        // A script to parse a string, write it to stdout as JSON.
+        let script = format!(
+            r##"
+            var parse = require('@babel/parser').parse;
+            {source}
+
+            var parsed = parse(source, {{
+                sourceType: {source_type:?},
+                plugins: {plugins:?}
+            }});
+
+            return JSON.stringify(parsed.program, function(key, value) {{
+                switch (key) {{
+                    case "loc": case "start": case "end": case "range":
+                    case "extra": case "comments":
+                    case "leadingComments": case "trailingComments": case "innerComments":
+                        return undefined;
+                }}
+                if (value instanceof RegExp) {{
+                    return null;
+                }}
+                if (typeof value == "bigint") {{
+                    /* Not representable in JSON, ESTree also stores the value as `bigint`. */
+                    return null;
+                }}
+                return value;
+            }});
+            "##,
+            source = source,
+            source_type = match source_type {
+                SourceType::Script => "script",
+                SourceType::Module => "module",
+                // Babel parses sources as modules if they contain `import` or `export` declarations.
+                SourceType::Auto => "unambiguous",
+            },
+            plugins = self.plugins());
+        let estree = self.shift.parse_script_json_output(&script)?;
+        self.convert(estree)
+    }
+
+    /// Convert an ESTree AST produced by Babel into a BinJS AST.
+    fn convert(&self, mut estree: JSON) -> Result<JSON, Error> {
+        if self.typescript {
+            StripTypes.strip(&mut estree)
+                .map_err(Error::InvalidAST)?;
+        }
+        if let Some(ref jsx) = self.jsx {
+            jsx.lower(&mut estree)
+                .map_err(Error::InvalidAST)?;
+        }
+        let mut ast = FromESTree.convert(estree)
+            .map_err(Error::InvalidAST)?;
+        self.shift.convert_shift_json(&mut ast)?;
+        Ok(ast)
+    }
+}
+
+impl SourceParser for Babel {
+    type Error = Error;
+    fn parse_str(&self, data: &str) -> Result<JSON, Error> {
+        // Escape `"`.
+        let data = data
+            .replace("\\", "\\\\")
+            .replace("\"", "\\\"")
+            .replace("\r", "\\r")
+            .replace("\n", "\\n");
+        self.parse_script(&format!("var source = \"{}\";", data), self.source_type)
+    }
+
+    /// Parse a text source file, using Babel.
+    fn parse_file<P: AsRef<Path>>(&self, path: P) -> Result<JSON, Error> {
+        let path = path.as_ref().to_str()
+            .ok_or_else(||Error::InvalidPath(path.as_ref().to_path_buf()))?;
+        self.parse_script(&format!(r##"
+            var fs      = require('fs');
+            var source  = fs.readFileSync({:?}, {{encoding: "utf-8"}});
+            "##, path), self.source_type.for_path(Path::new(path)))
+    }
+}
@@ -0,0 +1,175 @@
+//! Long-lived Node processes, evaluating the scripts of `Shift` and `Babel`.
+//!
+//! Launching Node and loading the parser dominates the time spent parsing each
+//! file of a corpus. A daemon is a Node process that keeps running between
+//! scripts. It reads requests from its stdin and writes responses to its stdout,
+//! one JSON object per line:
+//!
+//! - request `{"script": "..."}`, where the script is the body of a function
+//!   returning a string;
+//! - response `{"result": "..."}` if the function returned, or
+//!   `{"error": "..."}` if it threw.
+//!
+//! The same line-delimited protocol is used by external parsers, see `source::external`.
+
+use binjs_shared::JSON;
+
+use serde_json;
+
+use std;
+use std::io::{ BufRead, BufReader, Write };
+use std::path::{ Path, PathBuf };
+use std::process::{ Child, ChildStdin, ChildStdout, Command, Stdio };
+use std::sync::Mutex;
+
+use source::shift::Error;
+
+/// The script run by each daemon.
+const DAEMON_SCRIPT: &str = r##"
+var readline = require('readline');
+var lines = readline.createInterface({ input: process.stdin, terminal: false });
+lines.on('line', function(line) {
+    var response;
+    try {
+        var request = JSON.parse(line);
+        response = { result: new Function('require', request.script)(require) };
+    } catch (ex) {
+        response = { error: String(ex && ex.stack || ex) };
+    }
+    process.stdout.write(JSON.stringify(response) + "\n");
+});
+"##;
+
+/// A long-lived process, answering the requests written to its stdin with
+/// responses written to its stdout, one JSON object per line.
+pub struct Daemon {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+impl Daemon {
+    /// Launch `command`, piping its stdin and stdout.
+    pub fn spawn(mut command: Command) -> Result<Self, Error> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(Error::CouldNotLaunch)?;
+        let stdin = child.stdin.take()
+            .unwrap(); // Piped above.
+        let stdout = child.stdout.take()
+            .unwrap(); // Piped above.
+        debug!(target: "Shift", "Launched daemon {}", child.id());
+        Ok(Daemon {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        })
+    }
+
+    /// Launch a Node daemon, evaluating scripts.
+    fn spawn_node(bin_path: &Path, node_memory: &str) -> Result<Self, Error> {
+        let mut command = Command::new(bin_path);
+        command.arg(node_memory)
+            .arg("-e")
+            .arg(DAEMON_SCRIPT)
+            .env("NODE_PATH", "node_modules")
+            .stderr(Stdio::null());
+        Self::spawn(command)
+    }
+
+    /// Send `request` and wait for the response.
+    pub fn request(&mut self, request: &JSON) -> Result<JSON, Error> {
+        writeln!(self.stdin, "{}", request)
+            .and_then(|_| self.stdin.flush())
+            .map_err(Error::ExecutionError)?;
+
+        let mut line = String::new();
+        let len = self.stdout.read_line(&mut line)
+            .map_err(Error::ExecutionError)?;
+        if len == 0 {
+            // The daemon has exited, e.g. out of memory.
+            let status = self.child.wait()
+                .map_err(Error::ExecutionError)?;
+            return Err(Error::ReturnedError(status));
+        }
+        serde_json::from_str(&line)
+            .map_err(Error::JsonError)
+    }
+
+    /// Run a script in a Node daemon.
+    fn run(&mut self, script: &str) -> Result<String, Error> {
+        let response = self.request(&object!{ "script" => script })?;
+        match (response["result"].as_str(), response["error"].as_str()) {
+            (Some(result), _) => Ok(result.to_string()),
+            (None, Some(error)) => Err(Error::ScriptError(error.to_string())),
+            (None, None) => Err(Error::ScriptError(format!("Invalid response {}", response))),
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+}
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A pool of daemons, shared by parsers.
+///
+/// Each script is run by an idle daemon, or by a new daemon if all are busy.
+/// Daemons that fail are discarded, so a crash affects a single script.
+pub struct DaemonPool {
+    bin_path: PathBuf,
+
+    /// The argument of Node specifying its memory limit.
+    node_memory: String,
+
+    /// The maximal number of idle daemons kept alive.
+    max_idle: usize,
+
+    idle: Mutex<Vec<Daemon>>,
+}
+impl DaemonPool {
+    /// Create a pool of daemons running Node binary `bin_path`, keeping at most
+    /// `max_idle` of them alive between scripts.
+    ///
+    /// Daemons are launched lazily.
+    pub fn new<P: AsRef<Path>>(bin_path: P, max_idle: usize) -> Self {
+        DaemonPool {
+            bin_path: bin_path.as_ref().to_path_buf(),
+            node_memory: ::source::shift::node_memory(),
+            max_idle,
+            idle: Mutex::new(vec![]),
+        }
+    }
+
+    /// Run `script`, the body of a function returning a string, in a daemon.
+    pub fn run(&self, script: &str) -> Result<String, Error> {
+        let daemon = self.idle.lock()
+            .unwrap()
+            .pop();
+        let mut daemon = match daemon {
+            Some(daemon) => daemon,
+            None => Daemon::spawn_node(&self.bin_path, &self.node_memory)?
+        };
+        let result = daemon.run(script);
+        match result {
+            Err(Error::ScriptError(_)) | Ok(_) => {
+                // The daemon is still healthy.
+                let mut idle = self.idle.lock()
+                    .unwrap();
+                if idle.len() < self.max_idle {
+                    idle.push(daemon);
+                }
+            }
+            _ => {
+                debug!(target: "Shift", "Discarding daemon {}", daemon.id());
+            }
+        }
+        result
+    }
+}
@@ -0,0 +1,2205 @@
+//! Conversion between the Shift AST and the ESTree AST.
+//!
+//! ESTree (https://github.com/estree/estree) is the AST format used by
+//! most of the JavaScript tooling ecosystem (acorn, babel, eslint, ...).
+//! We do not convert directly between ESTree and the BinJS AST. Rather,
+//! we use the Shift AST as a pivot, as we already know how to convert
+//! between Shift and BinJS (see module `shift`).
+//!
+//! Only ES2016 scripts are supported, as this is the scope of the Shift
+//! AST we use.
+
+use binjs_generic::syntax::ASTError;
+
+use binjs_io::bytes::bigint::{ decimal_of_magnitude, magnitude_of_digits };
+
+use binjs_shared::{ JSON, JSONExt, JSONObject as Object };
+
+use std;
+
+/// Remove a field from an object, returning `null` if the field is absent.
+fn take(object: &mut Object, key: &str) -> JSON {
+    object.remove(key)
+        .unwrap_or(JSON::Null)
+}
+
+/// Determine whether a property name may be printed as an identifier.
+fn is_identifier_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' || c == '$' => {},
+        _ => return false
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Interpret the escape sequences of the raw value of a template element, as
+/// the `cooked` value of ESTree, see `TV` in the specifications.
+///
+/// Returns `None` if the raw value contains an invalid escape sequence, which
+/// is only legal in tagged templates, or a lone surrogate, which a string may
+/// not hold.
+fn cook(raw: &str) -> Option<String> {
+    fn digits(chars: &mut std::iter::Peekable<std::str::Chars>, len: usize) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..len {
+            value = value * 16 + chars.next()?.to_digit(16)?;
+        }
+        Some(value)
+    }
+    fn code_point(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<u32> {
+        if chars.peek() != Some(&'{') {
+            return digits(chars, 4);
+        }
+        chars.next();
+        let mut value: u32 = 0;
+        let mut empty = true;
+        loop {
+            match chars.next()? {
+                '}' if !empty => return Some(value),
+                c => {
+                    value = value * 16 + c.to_digit(16)?;
+                    if value > 0x10FFFF {
+                        return None;
+                    }
+                    empty = false;
+                }
+            }
+        }
+    }
+    let mut cooked = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {}
+            '\r' => {
+                // Line terminators are normalized to `\n`.
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                cooked.push('\n');
+                continue;
+            }
+            _ => {
+                cooked.push(c);
+                continue;
+            }
+        }
+        match chars.next()? {
+            'b' => cooked.push('\u{8}'),
+            'f' => cooked.push('\u{C}'),
+            'n' => cooked.push('\n'),
+            'r' => cooked.push('\r'),
+            't' => cooked.push('\t'),
+            'v' => cooked.push('\u{B}'),
+            '0' => match chars.peek() {
+                Some(c) if c.is_digit(10) => return None,
+                _ => cooked.push('\0')
+            },
+            '1'..='9' => return None,
+            'x' => cooked.push(std::char::from_u32(digits(&mut chars, 2)?)?),
+            'u' => {
+                let value = code_point(&mut chars)?;
+                let value = if (0xD800..0xDC00).contains(&value) {
+                    // A surrogate pair, e.g. `\uD83D\uDE00`.
+                    if chars.next()? != '\\' || chars.next()? != 'u' {
+                        return None;
+                    }
+                    let low = code_point(&mut chars)?;
+                    if !(0xDC00..0xE000).contains(&low) {
+                        return None;
+                    }
+                    0x10000 + ((value - 0xD800) << 10) + (low - 0xDC00)
+                } else {
+                    value
+                };
+                cooked.push(std::char::from_u32(value)?);
+            }
+            '\r' => {
+                // Line continuation.
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+            }
+            '\n' | '\u{2028}' | '\u{2029}' => {
+                // Line continuation.
+            }
+            c => cooked.push(c)
+        }
+    }
+    Some(cooked)
+}
+
+/// A data structure designed to convert from Shift AST to ESTree AST.
+///
+/// Conversion is performed bottom-up: by the time we convert a node,
+/// all its children have already been converted, with the exception
+/// of property names, which are kept as `StaticPropertyName` or
+/// `ComputedPropertyName` until their parent is converted, as ESTree
+/// stores the `computed` flag on the parent.
+pub struct ToESTree;
+impl ToESTree {
+    /// Convert a Shift AST into an ESTree AST, in place.
+    ///
+    /// Fails if the Shift AST is malformed, e.g. a property name is neither a
+    /// `StaticPropertyName` nor a `ComputedPropertyName`.
+    pub fn convert(&self, value: &mut JSON) -> Result<(), ASTError> {
+        let replacement = match *value {
+            JSON::Array(ref mut array) => {
+                for value in array {
+                    self.convert(value)?;
+                }
+                None
+            }
+            JSON::Object(ref mut object) => {
+                for (_, value) in object.iter_mut() {
+                    self.convert(value)?;
+                }
+                self.convert_object(object)?
+            }
+            _ => None
+        };
+        if let Some(replacement) = replacement {
+            *value = replacement;
+        }
+        Ok(())
+    }
+
+    /// Convert the `moduleSpecifier` of an import or export into a `Literal`.
+    fn module_specifier(&self, object: &mut Object) -> JSON {
+        object!{
+            "type" => "Literal",
+            "value" => take(object, "moduleSpecifier")
+        }
+    }
+
+    /// Convert a property name into a pair `(key, computed)`.
+    fn property_key(&self, mut name: JSON) -> Result<(JSON, bool), ASTError> {
+        let result = match name["type"].as_str() {
+            Some("StaticPropertyName") => {
+                let value = name.remove("value");
+                let is_identifier = value.as_str()
+                    .map(is_identifier_name)
+                    .unwrap_or(false);
+                if is_identifier {
+                    (object!{
+                        "type" => "Identifier",
+                        "name" => value
+                    }, false)
+                } else {
+                    (object!{
+                        "type" => "Literal",
+                        "value" => value
+                    }, false)
+                }
+            }
+            Some("ComputedPropertyName") => {
+                (name.remove("expression"), true)
+            }
+            _ => return Err(invalid(&name, "StaticPropertyName or ComputedPropertyName"))
+        };
+        Ok(result)
+    }
+
+    /// Convert a list of Shift parameters (`FormalParameters`, already converted
+    /// to an intermediate object) to an ESTree list of parameters.
+    fn params(&self, mut params: JSON) -> JSON {
+        let mut items = params.remove("items");
+        let rest = params.remove("rest");
+        if !rest.is_null() {
+            items.push(object!{
+                "type" => "RestElement",
+                "argument" => rest
+            }).expect("Expected an array of parameters");
+        }
+        items
+    }
+
+    /// Convert a Shift `FunctionBody`, once converted to ESTree `BlockStatement`,
+    /// or an expression, to the body of an ESTree function.
+    fn function(&self, object: &mut Object, type_: &str, is_expression: bool) -> JSON {
+        let params = match object.remove("params") {
+            Some(params) => self.params(params),
+            None => {
+                // Setters have a single `param`, getters have none.
+                match object.remove("param") {
+                    Some(param) => array![param],
+                    None => array![]
+                }
+            }
+        };
+        object!{
+            "type" => type_,
+            "id" => take(object, "name"),
+            "params" => params,
+            "body" => take(object, "body"),
+            "generator" => object.get("isGenerator").and_then(JSON::as_bool).unwrap_or(false),
+            "async" => object.get("isAsync").and_then(JSON::as_bool).unwrap_or(false),
+            "expression" => is_expression
+        }
+    }
+
+    /// Convert a Shift `Method`, `Getter` or `Setter`, to an ESTree `Property`.
+    /// Class elements further convert this `Property` into a `MethodDefinition`.
+    fn method(&self, object: &mut Object, kind: &str) -> Result<JSON, ASTError> {
+        let (key, computed) = self.property_key(take(object, "name"))?;
+        let value = self.function(object, "FunctionExpression", false);
+        Ok(object!{
+            "type" => "Property",
+            "key" => key,
+            "computed" => computed,
+            "value" => value,
+            "kind" => kind,
+            "method" => kind == "init",
+            "shorthand" => false
+        })
+    }
+
+    /// Convert a Shift `ClassDeclaration` or `ClassExpression`.
+    fn class(&self, object: &mut Object, type_: &str) -> JSON {
+        let mut body = take(object, "elements");
+        for element in body.members_mut() {
+            let mut property = element.remove("method");
+            let is_static = element["isStatic"].as_bool().unwrap_or(false);
+            let kind = match property["kind"].as_str() {
+                Some("get") => "get",
+                Some("set") => "set",
+                _ if !is_static && property["key"]["name"] == "constructor" => "constructor",
+                _ => "method"
+            };
+            *element = object!{
+                "type" => "MethodDefinition",
+                "key" => property.remove("key"),
+                "computed" => property.remove("computed"),
+                "value" => property.remove("value"),
+                "kind" => kind,
+                "static" => is_static
+            };
+        }
+        object!{
+            "type" => type_,
+            "id" => take(object, "name"),
+            "superClass" => take(object, "super"),
+            "body" => object!{
+                "type" => "ClassBody",
+                "body" => body
+            }
+        }
+    }
+
+    /// Convert a Shift `TemplateExpression`, without its tag.
+    fn template(&self, object: &mut Object) -> JSON {
+        let mut quasis = array![];
+        let mut expressions = array![];
+        let elements = take(object, "elements");
+        let len = elements.len();
+        for (i, mut element) in elements.members().cloned().enumerate() {
+            if let Some("TemplateElement") = element["type"].as_str() {
+                let raw = element.remove("rawValue");
+                let cooked = match raw.as_str().and_then(cook) {
+                    Some(cooked) => JSON::from(cooked),
+                    None => JSON::Null
+                };
+                quasis.push(object!{
+                    "type" => "TemplateElement",
+                    "value" => object!{
+                        "raw" => raw,
+                        "cooked" => cooked
+                    },
+                    "tail" => i + 1 == len
+                }).unwrap();
+            } else {
+                expressions.push(element).unwrap();
+            }
+        }
+        object!{
+            "type" => "TemplateLiteral",
+            "quasis" => quasis,
+            "expressions" => expressions
+        }
+    }
+
+    fn convert_object(&self, object: &mut Object) -> Result<Option<JSON>, ASTError> {
+        let kind = match object["type"].as_str() {
+            Some(kind) => kind.to_string(),
+            None => return Ok(None)
+        };
+        // By alphabetical order
+        let result = match kind.as_str() {
+            "ArrayAssignmentTarget" | "ArrayBinding" => {
+                let mut elements = take(object, "elements");
+                let rest = take(object, "rest");
+                if !rest.is_null() {
+                    elements.push(object!{
+                        "type" => "RestElement",
+                        "argument" => rest
+                    }).unwrap();
+                }
+                object!{
+                    "type" => "ArrayPattern",
+                    "elements" => elements
+                }
+            }
+            "ArrowExpression" => {
+                let is_expression = object["body"]["type"] != "BlockStatement";
+                let mut result = self.function(object, "ArrowFunctionExpression", is_expression);
+                result.remove("id");
+                result
+            }
+            "AssignmentExpression" => {
+                object!{
+                    "type" => "AssignmentExpression",
+                    "operator" => "=",
+                    "left" => take(object, "binding"),
+                    "right" => take(object, "expression")
+                }
+            }
+            "AssignmentTargetIdentifier" | "BindingIdentifier" | "IdentifierExpression" => {
+                object!{
+                    "type" => "Identifier",
+                    "name" => take(object, "name")
+                }
+            }
+            "AssignmentTargetPropertyIdentifier" | "BindingPropertyIdentifier" => {
+                let binding = take(object, "binding");
+                let init = take(object, "init");
+                let value = if init.is_null() {
+                    binding.clone()
+                } else {
+                    object!{
+                        "type" => "AssignmentPattern",
+                        "left" => binding.clone(),
+                        "right" => init
+                    }
+                };
+                object!{
+                    "type" => "Property",
+                    "key" => binding,
+                    "computed" => false,
+                    "value" => value,
+                    "kind" => "init",
+                    "method" => false,
+                    "shorthand" => true
+                }
+            }
+            "AssignmentTargetPropertyProperty" | "BindingPropertyProperty" | "DataProperty" => {
+                let (key, computed) = self.property_key(take(object, "name"))?;
+                let value = match object.remove("binding") {
+                    Some(binding) => binding,
+                    None => take(object, "expression")
+                };
+                object!{
+                    "type" => "Property",
+                    "key" => key,
+                    "computed" => computed,
+                    "value" => value,
+                    "kind" => "init",
+                    "method" => false,
+                    "shorthand" => false
+                }
+            }
+            "AssignmentTargetWithDefault" | "BindingWithDefault" => {
+                object!{
+                    "type" => "AssignmentPattern",
+                    "left" => take(object, "binding"),
+                    "right" => take(object, "init")
+                }
+            }
+            "AwaitExpression" => {
+                object!{
+                    "type" => "AwaitExpression",
+                    "argument" => take(object, "expression")
+                }
+            }
+            "BinaryExpression" => {
+                let operator = take(object, "operator");
+                let left = take(object, "left");
+                let right = take(object, "right");
+                match operator.as_str() {
+                    Some(",") => {
+                        let mut expressions = array![];
+                        let mut left = left;
+                        if let Some("SequenceExpression") = left["type"].as_str() {
+                            for expression in left["expressions"].members_mut() {
+                                expressions.push(expression.take()).unwrap();
+                            }
+                        } else {
+                            expressions.push(left).unwrap();
+                        }
+                        expressions.push(right).unwrap();
+                        object!{
+                            "type" => "SequenceExpression",
+                            "expressions" => expressions
+                        }
+                    }
+                    Some("&&") | Some("||") => {
+                        object!{
+                            "type" => "LogicalExpression",
+                            "operator" => operator,
+                            "left" => left,
+                            "right" => right
+                        }
+                    }
+                    _ => {
+                        object!{
+                            "type" => "BinaryExpression",
+                            "operator" => operator,
+                            "left" => left,
+                            "right" => right
+                        }
+                    }
+                }
+            }
+            "Block" | "BlockStatement" => {
+                // At this stage, the `block` of a `BlockStatement` has already
+                // been converted to a `BlockStatement`.
+                if let Some(block) = object.remove("block") {
+                    block
+                } else {
+                    object!{
+                        "type" => "BlockStatement",
+                        "body" => take(object, "statements")
+                    }
+                }
+            }
+            "BreakStatement" | "ContinueStatement" => {
+                let label = take(object, "label");
+                object!{
+                    "type" => kind.as_str(),
+                    "label" => if label.is_null() {
+                        JSON::Null
+                    } else {
+                        object!{
+                            "type" => "Identifier",
+                            "name" => label
+                        }
+                    }
+                }
+            }
+            "CallExpression" | "NewExpression" => {
+                object!{
+                    "type" => kind.as_str(),
+                    "callee" => take(object, "callee"),
+                    "arguments" => take(object, "arguments")
+                }
+            }
+            "CatchClause" => {
+                object!{
+                    "type" => "CatchClause",
+                    "param" => take(object, "binding"),
+                    "body" => take(object, "body")
+                }
+            }
+            "ClassDeclaration" | "ClassExpression" => {
+                self.class(object, &kind)
+            }
+            "CompoundAssignmentExpression" => {
+                object!{
+                    "type" => "AssignmentExpression",
+                    "operator" => take(object, "operator"),
+                    "left" => take(object, "binding"),
+                    "right" => take(object, "expression")
+                }
+            }
+            "ComputedMemberAssignmentTarget" | "ComputedMemberExpression" => {
+                object!{
+                    "type" => "MemberExpression",
+                    "object" => take(object, "object"),
+                    "property" => take(object, "expression"),
+                    "computed" => true
+                }
+            }
+            "Directive" => {
+                let raw = take(object, "rawValue");
+                object!{
+                    "type" => "ExpressionStatement",
+                    "expression" => object!{
+                        "type" => "Literal",
+                        "value" => raw.clone()
+                    },
+                    "directive" => raw
+                }
+            }
+            "Export" => {
+                object!{
+                    "type" => "ExportNamedDeclaration",
+                    "declaration" => take(object, "declaration"),
+                    "specifiers" => array![],
+                    "source" => JSON::Null
+                }
+            }
+            "ExportAllFrom" => {
+                object!{
+                    "type" => "ExportAllDeclaration",
+                    "source" => self.module_specifier(object)
+                }
+            }
+            "ExportDefault" => {
+                let mut declaration = take(object, "body");
+                // Shift names anonymous default declarations `*default*`.
+                if declaration["id"]["name"] == "*default*" {
+                    declaration["id"] = JSON::Null;
+                }
+                object!{
+                    "type" => "ExportDefaultDeclaration",
+                    "declaration" => declaration
+                }
+            }
+            "ExportFrom" | "ExportLocals" => {
+                let source = if kind == "ExportFrom" {
+                    self.module_specifier(object)
+                } else {
+                    JSON::Null
+                };
+                object!{
+                    "type" => "ExportNamedDeclaration",
+                    "declaration" => JSON::Null,
+                    "specifiers" => take(object, "namedExports"),
+                    "source" => source
+                }
+            }
+            "ExportFromSpecifier" | "ExportLocalSpecifier" => {
+                // `name` is a string in `ExportFromSpecifier` and an `IdentifierExpression`,
+                // already converted, in `ExportLocalSpecifier`.
+                let local = match take(object, "name") {
+                    JSON::String(name) => object!{
+                        "type" => "Identifier",
+                        "name" => name
+                    },
+                    local => local
+                };
+                let exported = match take(object, "exportedName") {
+                    JSON::Null => local.clone(),
+                    name => object!{
+                        "type" => "Identifier",
+                        "name" => name
+                    }
+                };
+                object!{
+                    "type" => "ExportSpecifier",
+                    "local" => local,
+                    "exported" => exported
+                }
+            }
+            "FormalParameters" => {
+                // Converted by the parent.
+                return Ok(None)
+            }
+            "ForInStatement" | "ForOfStatement" | "WhileStatement" | "DoWhileStatement"
+            | "ForStatement" | "IfStatement" | "ConditionalExpression" | "ExpressionStatement"
+            | "EmptyStatement" | "DebuggerStatement" | "ThisExpression" | "WithStatement" | "Super"
+            | "ArrayExpression" | "ObjectExpression" | "SwitchStatement" | "VariableDeclaration" => {
+                // Same structure, up to field names.
+                if let Some(declarators) = object.remove("declarators") {
+                    object.insert("declarations".to_string(), declarators);
+                }
+                return Ok(None)
+            }
+            "FunctionBody" => {
+                let mut body = take(object, "directives");
+                for statement in take(object, "statements").members().cloned() {
+                    body.push(statement).unwrap();
+                }
+                object!{
+                    "type" => "BlockStatement",
+                    "body" => body
+                }
+            }
+            "FunctionDeclaration" | "FunctionExpression" => {
+                self.function(object, &kind, false)
+            }
+            "Getter" => {
+                self.method(object, "get")?
+            }
+            "Import" | "ImportNamespace" => {
+                let mut specifiers = array![];
+                let default_binding = take(object, "defaultBinding");
+                if !default_binding.is_null() {
+                    specifiers.push(object!{
+                        "type" => "ImportDefaultSpecifier",
+                        "local" => default_binding
+                    }).unwrap();
+                }
+                if kind == "Import" {
+                    for specifier in take(object, "namedImports").members().cloned() {
+                        specifiers.push(specifier).unwrap();
+                    }
+                } else {
+                    specifiers.push(object!{
+                        "type" => "ImportNamespaceSpecifier",
+                        "local" => take(object, "namespaceBinding")
+                    }).unwrap();
+                }
+                object!{
+                    "type" => "ImportDeclaration",
+                    "specifiers" => specifiers,
+                    "source" => self.module_specifier(object)
+                }
+            }
+            "ImportSpecifier" => {
+                let local = take(object, "binding");
+                let imported = match take(object, "name") {
+                    JSON::Null => local.clone(),
+                    name => object!{
+                        "type" => "Identifier",
+                        "name" => name
+                    }
+                };
+                object!{
+                    "type" => "ImportSpecifier",
+                    "imported" => imported,
+                    "local" => local
+                }
+            }
+            "LabeledStatement" => {
+                object!{
+                    "type" => "LabeledStatement",
+                    "label" => object!{
+                        "type" => "Identifier",
+                        "name" => take(object, "label")
+                    },
+                    "body" => take(object, "body")
+                }
+            }
+            "LiteralBooleanExpression" | "LiteralNumericExpression" | "LiteralStringExpression" => {
+                object!{
+                    "type" => "Literal",
+                    "value" => take(object, "value")
+                }
+            }
+            "LiteralBigIntExpression" => {
+                // BigInt values cannot be represented in JSON, ESTree uses `bigint` instead.
+                object!{
+                    "type" => "Literal",
+                    "value" => JSON::Null,
+                    "bigint" => take(object, "value")
+                }
+            }
+            "LiteralInfinityExpression" => {
+                // ESTree has no representation for infinite literals.
+                object!{
+                    "type" => "Identifier",
+                    "name" => "Infinity"
+                }
+            }
+            "LiteralNullExpression" => {
+                object!{
+                    "type" => "Literal",
+                    "value" => JSON::Null
+                }
+            }
+            "LiteralRegExpExpression" => {
+                let mut flags = String::new();
+                for &(field, flag) in &[("global", 'g'), ("ignoreCase", 'i'), ("multiLine", 'm'), ("dotAll", 's'), ("unicode", 'u'), ("sticky", 'y')] {
+                    if let Some(true) = object.get(field).and_then(JSON::as_bool) {
+                        flags.push(flag);
+                    }
+                }
+                object!{
+                    "type" => "Literal",
+                    "value" => JSON::Null,
+                    "regex" => object!{
+                        "pattern" => take(object, "pattern"),
+                        "flags" => flags
+                    }
+                }
+            }
+            "Method" => {
+                self.method(object, "init")?
+            }
+            "Module" => {
+                let mut body = take(object, "directives");
+                for item in take(object, "items").members().cloned() {
+                    body.push(item).unwrap();
+                }
+                object!{
+                    "type" => "Program",
+                    "sourceType" => "module",
+                    "body" => body
+                }
+            }
+            "NewTargetExpression" => {
+                object!{
+                    "type" => "MetaProperty",
+                    "meta" => object!{
+                        "type" => "Identifier",
+                        "name" => "new"
+                    },
+                    "property" => object!{
+                        "type" => "Identifier",
+                        "name" => "target"
+                    }
+                }
+            }
+            "ObjectAssignmentTarget" | "ObjectBinding" => {
+                object!{
+                    "type" => "ObjectPattern",
+                    "properties" => take(object, "properties")
+                }
+            }
+            "ReturnStatement" | "ThrowStatement" => {
+                object!{
+                    "type" => kind.as_str(),
+                    "argument" => take(object, "expression")
+                }
+            }
+            "Script" => {
+                let mut body = take(object, "directives");
+                for statement in take(object, "statements").members().cloned() {
+                    body.push(statement).unwrap();
+                }
+                object!{
+                    "type" => "Program",
+                    "sourceType" => "script",
+                    "body" => body
+                }
+            }
+            "Setter" => {
+                self.method(object, "set")?
+            }
+            "ShorthandProperty" => {
+                let name = take(object, "name");
+                object!{
+                    "type" => "Property",
+                    "key" => name.clone(),
+                    "computed" => false,
+                    "value" => name,
+                    "kind" => "init",
+                    "method" => false,
+                    "shorthand" => true
+                }
+            }
+            "SpreadElement" => {
+                object!{
+                    "type" => "SpreadElement",
+                    "argument" => take(object, "expression")
+                }
+            }
+            "StaticMemberAssignmentTarget" | "StaticMemberExpression" => {
+                object!{
+                    "type" => "MemberExpression",
+                    "object" => take(object, "object"),
+                    "property" => object!{
+                        "type" => "Identifier",
+                        "name" => take(object, "property")
+                    },
+                    "computed" => false
+                }
+            }
+            "SwitchCase" => {
+                return Ok(None)
+            }
+            "SwitchDefault" => {
+                object!{
+                    "type" => "SwitchCase",
+                    "test" => JSON::Null,
+                    "consequent" => take(object, "consequent")
+                }
+            }
+            "SwitchStatementWithDefault" => {
+                let mut cases = take(object, "preDefaultCases");
+                cases.push(take(object, "defaultCase")).unwrap();
+                for case in take(object, "postDefaultCases").members().cloned() {
+                    cases.push(case).unwrap();
+                }
+                object!{
+                    "type" => "SwitchStatement",
+                    "discriminant" => take(object, "discriminant"),
+                    "cases" => cases
+                }
+            }
+            "TemplateExpression" => {
+                let tag = take(object, "tag");
+                let quasi = self.template(object);
+                if tag.is_null() {
+                    quasi
+                } else {
+                    object!{
+                        "type" => "TaggedTemplateExpression",
+                        "tag" => tag,
+                        "quasi" => quasi
+                    }
+                }
+            }
+            "TryCatchStatement" | "TryFinallyStatement" => {
+                object!{
+                    "type" => "TryStatement",
+                    "block" => take(object, "body"),
+                    "handler" => take(object, "catchClause"),
+                    "finalizer" => take(object, "finalizer")
+                }
+            }
+            "UnaryExpression" => {
+                object!{
+                    "type" => "UnaryExpression",
+                    "operator" => take(object, "operator"),
+                    "argument" => take(object, "operand"),
+                    "prefix" => true
+                }
+            }
+            "UpdateExpression" => {
+                object!{
+                    "type" => "UpdateExpression",
+                    "operator" => take(object, "operator"),
+                    "argument" => take(object, "operand"),
+                    "prefix" => take(object, "isPrefix")
+                }
+            }
+            "VariableDeclarationStatement" => {
+                take(object, "declaration")
+            }
+            "VariableDeclarator" => {
+                object!{
+                    "type" => "VariableDeclarator",
+                    "id" => take(object, "binding"),
+                    "init" => take(object, "init")
+                }
+            }
+            "YieldExpression" | "YieldGeneratorExpression" => {
+                object!{
+                    "type" => "YieldExpression",
+                    "argument" => take(object, "expression"),
+                    "delegate" => kind == "YieldGeneratorExpression"
+                }
+            }
+            _ => {
+                // Property names are converted by their parent.
+                return Ok(None)
+            }
+        };
+        Ok(Some(result))
+    }
+}
+
+#[test]
+fn test_estree_basic() {
+    // Shift AST for `function foo(a, ...b) { "use strict"; return a; }`.
+    let mut ast = object!{
+        "type" => "Script",
+        "directives" => array![],
+        "statements" => array![
+            object!{
+                "type" => "FunctionDeclaration",
+                "isAsync" => false,
+                "isGenerator" => false,
+                "name" => object!{
+                    "type" => "BindingIdentifier",
+                    "name" => "foo"
+                },
+                "params" => object!{
+                    "type" => "FormalParameters",
+                    "items" => array![
+                        object!{
+                            "type" => "BindingIdentifier",
+                            "name" => "a"
+                        }
+                    ],
+                    "rest" => object!{
+                        "type" => "BindingIdentifier",
+                        "name" => "b"
+                    }
+                },
+                "body" => object!{
+                    "type" => "FunctionBody",
+                    "directives" => array![
+                        object!{
+                            "type" => "Directive",
+                            "rawValue" => "use strict"
+                        }
+                    ],
+                    "statements" => array![
+                        object!{
+                            "type" => "ReturnStatement",
+                            "expression" => object!{
+                                "type" => "IdentifierExpression",
+                                "name" => "a"
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    };
+    ToESTree.convert(&mut ast)
+        .expect("Could not convert to ESTree");
+
+    let expected = object!{
+        "type" => "Program",
+        "sourceType" => "script",
+        "body" => array![
+            object!{
+                "type" => "FunctionDeclaration",
+                "id" => object!{
+                    "type" => "Identifier",
+                    "name" => "foo"
+                },
+                "params" => array![
+                    object!{
+                        "type" => "Identifier",
+                        "name" => "a"
+                    },
+                    object!{
+                        "type" => "RestElement",
+                        "argument" => object!{
+                            "type" => "Identifier",
+                            "name" => "b"
+                        }
+                    }
+                ],
+                "body" => object!{
+                    "type" => "BlockStatement",
+                    "body" => array![
+                        object!{
+                            "type" => "ExpressionStatement",
+                            "expression" => object!{
+                                "type" => "Literal",
+                                "value" => "use strict"
+                            },
+                            "directive" => "use strict"
+                        },
+                        object!{
+                            "type" => "ReturnStatement",
+                            "argument" => object!{
+                                "type" => "Identifier",
+                                "name" => "a"
+                            }
+                        }
+                    ]
+                },
+                "generator" => false,
+                "async" => false,
+                "expression" => false
+            }
+        ]
+    };
+    assert_eq!(ast, expected);
+}
+
+/// Convert an ESTree value, expected to be an array, into its members.
+fn members(value: JSON, expected: &str) -> Result<Vec<JSON>, ASTError> {
+    match value {
+        JSON::Array(array) => Ok(array),
+        _ => Err(invalid(&value, expected))
+    }
+}
+
+/// Convert the `bigint` of an ESTree literal, which may be written in any base,
+/// e.g. `"0xFF"`, into decimal digits, e.g. `"255"`.
+fn decimal_big_int(value: &str) -> Option<String> {
+    let (digits, radix) = match value.get(..2) {
+        Some("0x") | Some("0X") => (&value[2..], 16),
+        Some("0o") | Some("0O") => (&value[2..], 8),
+        Some("0b") | Some("0B") => (&value[2..], 2),
+        _ => (value, 10)
+    };
+    let magnitude = magnitude_of_digits(&digits.replace('_', ""), radix)?;
+    Some(decimal_of_magnitude(false, &magnitude))
+}
+
+fn invalid(got: &JSON, expected: &str) -> ASTError {
+    ASTError::InvalidValue {
+        got: got.dump(),
+        expected: expected.to_owned()
+    }
+}
+
+/// A data structure designed to convert from ESTree AST to Shift AST.
+///
+/// Conversion is performed top-down, as the Shift node used to represent
+/// an ESTree `Identifier`, `ObjectPattern`, etc. depends on the context
+/// (binding, expression or assignment target).
+pub struct FromESTree;
+impl FromESTree {
+    /// Convert an ESTree `Program` into a Shift `Script` or `Module`, depending on its `sourceType`.
+    pub fn convert(&self, mut program: JSON) -> Result<JSON, ASTError> {
+        if program["type"] != "Program" {
+            return Err(invalid(&program, "Program"));
+        }
+        if program["sourceType"] == "module" {
+            let (directives, items) = self.body_with(program.remove("body"), &|item| self.module_item(item))?;
+            return Ok(object!{
+                "type" => "Module",
+                "directives" => directives,
+                "items" => items
+            });
+        }
+        let (directives, statements) = self.body(program.remove("body"))?;
+        Ok(object!{
+            "type" => "Script",
+            "directives" => directives,
+            "statements" => statements
+        })
+    }
+
+    /// Convert a list of statements into a pair (directives, statements).
+    fn body(&self, body: JSON) -> Result<(JSON, JSON), ASTError> {
+        self.body_with(body, &|statement| self.statement(statement))
+    }
+
+    /// Convert a list of items into a pair (directives, items), converting items with `convert`.
+    fn body_with(&self, body: JSON, convert: &Fn(JSON) -> Result<JSON, ASTError>) -> Result<(JSON, JSON), ASTError> {
+        let mut directives = array![];
+        let mut statements = array![];
+        let mut is_prologue = true;
+        for statement in members(body, "list of statements")? {
+            if is_prologue {
+                if let Some(directive) = statement["directive"].as_str() {
+                    directives.push(object!{
+                        "type" => "Directive",
+                        "rawValue" => directive
+                    }).unwrap();
+                    continue;
+                }
+                is_prologue = false;
+            }
+            statements.push(convert(statement)?).unwrap();
+        }
+        Ok((directives, statements))
+    }
+
+    /// Convert an item of a module: an import or export declaration, or a statement.
+    fn module_item(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let kind = self.kind(&node, "ModuleItem")?;
+        // By alphabetical order
+        let result = match kind.as_str() {
+            "ExportAllDeclaration" => {
+                if !node["exported"].is_null() {
+                    // `export * as name from ...` is not part of the grammar.
+                    return Err(invalid(&node["exported"], "null"));
+                }
+                object!{
+                    "type" => "ExportAllFrom",
+                    "moduleSpecifier" => self.module_specifier(node.remove("source"))?
+                }
+            }
+            "ExportDefaultDeclaration" => {
+                let declaration = node.remove("declaration");
+                let is_declaration = match declaration["type"].as_str() {
+                    Some("FunctionDeclaration") | Some("ClassDeclaration") => true,
+                    _ => false
+                };
+                let body = if is_declaration {
+                    let mut body = self.statement(declaration)?;
+                    // Shift names anonymous default declarations `*default*`.
+                    if body["name"].is_null() {
+                        body["name"] = object!{
+                            "type" => "BindingIdentifier",
+                            "name" => "*default*"
+                        };
+                    }
+                    body
+                } else {
+                    self.expression(declaration)?
+                };
+                object!{
+                    "type" => "ExportDefault",
+                    "body" => body
+                }
+            }
+            "ExportNamedDeclaration" => {
+                let declaration = node.remove("declaration");
+                if !declaration.is_null() {
+                    let is_variable_declaration = declaration["type"] == "VariableDeclaration";
+                    let declaration = if is_variable_declaration {
+                        self.variable_declaration(declaration)?
+                    } else {
+                        self.statement(declaration)?
+                    };
+                    object!{
+                        "type" => "Export",
+                        "declaration" => declaration
+                    }
+                } else {
+                    let source = node.remove("source");
+                    let mut specifiers = array![];
+                    for mut specifier in members(node.remove("specifiers"), "list of ExportSpecifier")? {
+                        let local = self.identifier_name(specifier.remove("local"))?;
+                        let exported = self.identifier_name(specifier.remove("exported"))?;
+                        let exported_name = if exported == local {
+                            JSON::Null
+                        } else {
+                            exported
+                        };
+                        let specifier = if source.is_null() {
+                            object!{
+                                "type" => "ExportLocalSpecifier",
+                                "name" => object!{
+                                    "type" => "IdentifierExpression",
+                                    "name" => local
+                                },
+                                "exportedName" => exported_name
+                            }
+                        } else {
+                            object!{
+                                "type" => "ExportFromSpecifier",
+                                "name" => local,
+                                "exportedName" => exported_name
+                            }
+                        };
+                        specifiers.push(specifier).unwrap();
+                    }
+                    if source.is_null() {
+                        object!{
+                            "type" => "ExportLocals",
+                            "namedExports" => specifiers
+                        }
+                    } else {
+                        object!{
+                            "type" => "ExportFrom",
+                            "namedExports" => specifiers,
+                            "moduleSpecifier" => self.module_specifier(source)?
+                        }
+                    }
+                }
+            }
+            "ImportDeclaration" => {
+                let module_specifier = self.module_specifier(node.remove("source"))?;
+                let mut default_binding = JSON::Null;
+                let mut namespace_binding = JSON::Null;
+                let mut named_imports = array![];
+                for mut specifier in members(node.remove("specifiers"), "list of ImportSpecifier")? {
+                    let kind = self.kind(&specifier, "ImportSpecifier")?;
+                    match kind.as_str() {
+                        "ImportDefaultSpecifier" => {
+                            default_binding = self.binding(specifier.remove("local"))?;
+                        }
+                        "ImportNamespaceSpecifier" => {
+                            namespace_binding = self.binding(specifier.remove("local"))?;
+                        }
+                        "ImportSpecifier" => {
+                            let binding = self.binding(specifier.remove("local"))?;
+                            let name = self.identifier_name(specifier.remove("imported"))?;
+                            let name = if binding["name"] == name {
+                                JSON::Null
+                            } else {
+                                name
+                            };
+                            named_imports.push(object!{
+                                "type" => "ImportSpecifier",
+                                "name" => name,
+                                "binding" => binding
+                            }).unwrap();
+                        }
+                        _ => return Err(invalid(&specifier, "ImportSpecifier"))
+                    }
+                }
+                if namespace_binding.is_null() {
+                    object!{
+                        "type" => "Import",
+                        "moduleSpecifier" => module_specifier,
+                        "defaultBinding" => default_binding,
+                        "namedImports" => named_imports
+                    }
+                } else {
+                    object!{
+                        "type" => "ImportNamespace",
+                        "moduleSpecifier" => module_specifier,
+                        "defaultBinding" => default_binding,
+                        "namespaceBinding" => namespace_binding
+                    }
+                }
+            }
+            _ => self.statement(node)?
+        };
+        Ok(result)
+    }
+
+    /// The module specifier of an import or export, from a string `Literal`.
+    fn module_specifier(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let value = node.remove("value");
+        if value.is_string() {
+            Ok(value)
+        } else {
+            Err(invalid(&node, "string Literal"))
+        }
+    }
+
+    /// The name of an `Identifier`, used as an `IdentifierName`.
+    fn identifier_name(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        if node["type"] != "Identifier" {
+            return Err(invalid(&node, "Identifier"));
+        }
+        Ok(node.remove("name"))
+    }
+
+    fn kind(&self, node: &JSON, expected: &str) -> Result<String, ASTError> {
+        node["type"].as_str()
+            .map(str::to_string)
+            .ok_or_else(|| invalid(node, expected))
+    }
+
+    fn optional_statement(&self, node: JSON) -> Result<JSON, ASTError> {
+        if node.is_null() {
+            Ok(JSON::Null)
+        } else {
+            self.statement(node)
+        }
+    }
+
+    fn statement(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let kind = self.kind(&node, "Statement")?;
+        // By alphabetical order
+        let result = match kind.as_str() {
+            "BlockStatement" => object!{
+                "type" => "BlockStatement",
+                "block" => self.block(node)?
+            },
+            "BreakStatement" | "ContinueStatement" => object!{
+                "type" => kind.as_str(),
+                "label" => self.label(node.remove("label"))
+            },
+            "ClassDeclaration" => self.class(node, "ClassDeclaration")?,
+            "DebuggerStatement" | "EmptyStatement" => object!{
+                "type" => kind.as_str()
+            },
+            "DoWhileStatement" | "WhileStatement" => object!{
+                "type" => kind.as_str(),
+                "test" => self.expression(node.remove("test"))?,
+                "body" => self.statement(node.remove("body"))?
+            },
+            "ExpressionStatement" => object!{
+                "type" => "ExpressionStatement",
+                "expression" => self.expression(node.remove("expression"))?
+            },
+            "ForInStatement" | "ForOfStatement" => object!{
+                "type" => kind.as_str(),
+                "left" => self.for_in_of_left(node.remove("left"))?,
+                "right" => self.expression(node.remove("right"))?,
+                "body" => self.statement(node.remove("body"))?
+            },
+            "ForStatement" => {
+                let init = node.remove("init");
+                let init = match init["type"].as_str() {
+                    None => JSON::Null,
+                    Some("VariableDeclaration") => self.variable_declaration(init)?,
+                    Some(_) => self.expression(init)?
+                };
+                object!{
+                    "type" => "ForStatement",
+                    "init" => init,
+                    "test" => self.optional_expression(node.remove("test"))?,
+                    "update" => self.optional_expression(node.remove("update"))?,
+                    "body" => self.statement(node.remove("body"))?
+                }
+            }
+            "FunctionDeclaration" => self.function(node, "FunctionDeclaration")?,
+            "IfStatement" => object!{
+                "type" => "IfStatement",
+                "test" => self.expression(node.remove("test"))?,
+                "consequent" => self.statement(node.remove("consequent"))?,
+                "alternate" => self.optional_statement(node.remove("alternate"))?
+            },
+            "LabeledStatement" => object!{
+                "type" => "LabeledStatement",
+                "label" => self.label(node.remove("label")),
+                "body" => self.statement(node.remove("body"))?
+            },
+            "ReturnStatement" => object!{
+                "type" => "ReturnStatement",
+                "expression" => self.optional_expression(node.remove("argument"))?
+            },
+            "SwitchStatement" => self.switch(node)?,
+            "ThrowStatement" => object!{
+                "type" => "ThrowStatement",
+                "expression" => self.expression(node.remove("argument"))?
+            },
+            "TryStatement" => {
+                let body = self.block(node.remove("block"))?;
+                let handler = node.remove("handler");
+                let catch_clause = if handler.is_null() {
+                    JSON::Null
+                } else {
+                    self.catch_clause(handler)?
+                };
+                let finalizer = node.remove("finalizer");
+                if finalizer.is_null() {
+                    object!{
+                        "type" => "TryCatchStatement",
+                        "body" => body,
+                        "catchClause" => catch_clause
+                    }
+                } else {
+                    object!{
+                        "type" => "TryFinallyStatement",
+                        "body" => body,
+                        "catchClause" => catch_clause,
+                        "finalizer" => self.block(finalizer)?
+                    }
+                }
+            }
+            "VariableDeclaration" => object!{
+                "type" => "VariableDeclarationStatement",
+                "declaration" => self.variable_declaration(node)?
+            },
+            "WithStatement" => object!{
+                "type" => "WithStatement",
+                "object" => self.expression(node.remove("object"))?,
+                "body" => self.statement(node.remove("body"))?
+            },
+            _ => return Err(invalid(&node, "Statement"))
+        };
+        Ok(result)
+    }
+
+    fn block(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        if node["type"] != "BlockStatement" {
+            return Err(invalid(&node, "BlockStatement"));
+        }
+        let mut statements = array![];
+        for statement in members(node.remove("body"), "list of statements")? {
+            statements.push(self.statement(statement)?).unwrap();
+        }
+        Ok(object!{
+            "type" => "Block",
+            "statements" => statements
+        })
+    }
+
+    fn label(&self, mut node: JSON) -> JSON {
+        if node.is_null() {
+            JSON::Null
+        } else {
+            node.remove("name")
+        }
+    }
+
+    fn catch_clause(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        Ok(object!{
+            "type" => "CatchClause",
+            "binding" => self.binding(node.remove("param"))?,
+            "body" => self.block(node.remove("body"))?
+        })
+    }
+
+    fn switch(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let discriminant = self.expression(node.remove("discriminant"))?;
+        let mut pre_default_cases = array![];
+        let mut default_case = JSON::Null;
+        let mut post_default_cases = array![];
+        for mut case in members(node.remove("cases"), "list of SwitchCase")? {
+            let mut consequent = array![];
+            for statement in members(case.remove("consequent"), "list of statements")? {
+                consequent.push(self.statement(statement)?).unwrap();
+            }
+            let test = case.remove("test");
+            if test.is_null() {
+                default_case = object!{
+                    "type" => "SwitchDefault",
+                    "consequent" => consequent
+                };
+                continue;
+            }
+            let case = object!{
+                "type" => "SwitchCase",
+                "test" => self.expression(test)?,
+                "consequent" => consequent
+            };
+            if default_case.is_null() {
+                pre_default_cases.push(case).unwrap();
+            } else {
+                post_default_cases.push(case).unwrap();
+            }
+        }
+        if default_case.is_null() {
+            Ok(object!{
+                "type" => "SwitchStatement",
+                "discriminant" => discriminant,
+                "cases" => pre_default_cases
+            })
+        } else {
+            Ok(object!{
+                "type" => "SwitchStatementWithDefault",
+                "discriminant" => discriminant,
+                "preDefaultCases" => pre_default_cases,
+                "defaultCase" => default_case,
+                "postDefaultCases" => post_default_cases
+            })
+        }
+    }
+
+    fn variable_declaration(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let mut declarators = array![];
+        for mut declarator in members(node.remove("declarations"), "list of VariableDeclarator")? {
+            declarators.push(object!{
+                "type" => "VariableDeclarator",
+                "binding" => self.binding(declarator.remove("id"))?,
+                "init" => self.optional_expression(declarator.remove("init"))?
+            }).unwrap();
+        }
+        Ok(object!{
+            "type" => "VariableDeclaration",
+            "kind" => node.remove("kind"),
+            "declarators" => declarators
+        })
+    }
+
+    fn for_in_of_left(&self, node: JSON) -> Result<JSON, ASTError> {
+        if node["type"] == "VariableDeclaration" {
+            self.variable_declaration(node)
+        } else {
+            self.assignment_target(node)
+        }
+    }
+
+    fn optional_expression(&self, node: JSON) -> Result<JSON, ASTError> {
+        if node.is_null() {
+            Ok(JSON::Null)
+        } else {
+            self.expression(node)
+        }
+    }
+
+    /// Convert an expression that may also be a `SpreadElement`, e.g. a call argument.
+    fn expression_or_spread(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        if node["type"] == "SpreadElement" {
+            Ok(object!{
+                "type" => "SpreadElement",
+                "expression" => self.expression(node.remove("argument"))?
+            })
+        } else {
+            self.expression(node)
+        }
+    }
+
+    fn expressions_or_spread(&self, list: JSON) -> Result<JSON, ASTError> {
+        let mut result = array![];
+        for item in members(list, "list of expressions")? {
+            result.push(self.expression_or_spread(item)?).unwrap();
+        }
+        Ok(result)
+    }
+
+    fn expression(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let kind = self.kind(&node, "Expression")?;
+        // By alphabetical order
+        let result = match kind.as_str() {
+            "ArrayExpression" => {
+                let mut elements = array![];
+                for element in members(node.remove("elements"), "list of expressions")? {
+                    let element = if element.is_null() {
+                        JSON::Null
+                    } else {
+                        self.expression_or_spread(element)?
+                    };
+                    elements.push(element).unwrap();
+                }
+                object!{
+                    "type" => "ArrayExpression",
+                    "elements" => elements
+                }
+            }
+            "ArrowFunctionExpression" => {
+                let body = node.remove("body");
+                let body = if body["type"] == "BlockStatement" {
+                    self.function_body(body)?
+                } else {
+                    self.expression(body)?
+                };
+                object!{
+                    "type" => "ArrowExpression",
+                    "isAsync" => node["async"].as_bool().unwrap_or(false),
+                    "params" => self.params(node.remove("params"))?,
+                    "body" => body
+                }
+            }
+            "AssignmentExpression" => {
+                let binding = self.assignment_target(node.remove("left"))?;
+                let expression = self.expression(node.remove("right"))?;
+                if node["operator"] == "=" {
+                    object!{
+                        "type" => "AssignmentExpression",
+                        "binding" => binding,
+                        "expression" => expression
+                    }
+                } else {
+                    object!{
+                        "type" => "CompoundAssignmentExpression",
+                        "operator" => node.remove("operator"),
+                        "binding" => binding,
+                        "expression" => expression
+                    }
+                }
+            }
+            "AwaitExpression" => object!{
+                "type" => "AwaitExpression",
+                "expression" => self.expression(node.remove("argument"))?
+            },
+            "BinaryExpression" | "LogicalExpression" => object!{
+                "type" => "BinaryExpression",
+                "operator" => node.remove("operator"),
+                "left" => self.expression(node.remove("left"))?,
+                "right" => self.expression(node.remove("right"))?
+            },
+            "CallExpression" | "NewExpression" => object!{
+                "type" => kind.as_str(),
+                "callee" => self.expression(node.remove("callee"))?,
+                "arguments" => self.expressions_or_spread(node.remove("arguments"))?
+            },
+            "ClassExpression" => self.class(node, "ClassExpression")?,
+            "ConditionalExpression" => object!{
+                "type" => "ConditionalExpression",
+                "test" => self.expression(node.remove("test"))?,
+                "consequent" => self.expression(node.remove("consequent"))?,
+                "alternate" => self.expression(node.remove("alternate"))?
+            },
+            "FunctionExpression" => self.function(node, "FunctionExpression")?,
+            "Identifier" => object!{
+                "type" => "IdentifierExpression",
+                "name" => node.remove("name")
+            },
+            "Literal" => self.literal(node)?,
+            "MemberExpression" => {
+                let object = self.expression(node.remove("object"))?;
+                if node["computed"] == true {
+                    object!{
+                        "type" => "ComputedMemberExpression",
+                        "object" => object,
+                        "expression" => self.expression(node.remove("property"))?
+                    }
+                } else {
+                    object!{
+                        "type" => "StaticMemberExpression",
+                        "object" => object,
+                        "property" => node["property"].remove("name")
+                    }
+                }
+            }
+            "MetaProperty" => {
+                if node["meta"]["name"] != "new" || node["property"]["name"] != "target" {
+                    return Err(invalid(&node, "new.target"));
+                }
+                object!{
+                    "type" => "NewTargetExpression"
+                }
+            }
+            "ObjectExpression" => {
+                let mut properties = array![];
+                for property in members(node.remove("properties"), "list of Property")? {
+                    properties.push(self.object_property(property)?).unwrap();
+                }
+                object!{
+                    "type" => "ObjectExpression",
+                    "properties" => properties
+                }
+            }
+            "SequenceExpression" => {
+                let mut expressions = members(node.remove("expressions"), "list of expressions")?
+                    .into_iter();
+                let first = expressions.next()
+                    .ok_or_else(|| invalid(&node, "non-empty SequenceExpression"))?;
+                let mut result = self.expression(first)?;
+                for expression in expressions {
+                    result = object!{
+                        "type" => "BinaryExpression",
+                        "operator" => ",",
+                        "left" => result,
+                        "right" => self.expression(expression)?
+                    };
+                }
+                result
+            }
+            "Super" => object!{
+                "type" => "Super"
+            },
+            "TaggedTemplateExpression" => {
+                let tag = self.expression(node.remove("tag"))?;
+                let mut template = self.template(node.remove("quasi"))?;
+                template["tag"] = tag;
+                template
+            }
+            "TemplateLiteral" => self.template(node)?,
+            "ThisExpression" => object!{
+                "type" => "ThisExpression"
+            },
+            "UnaryExpression" => object!{
+                "type" => "UnaryExpression",
+                "operator" => node.remove("operator"),
+                "operand" => self.expression(node.remove("argument"))?
+            },
+            "UpdateExpression" => object!{
+                "type" => "UpdateExpression",
+                "isPrefix" => node.remove("prefix"),
+                "operator" => node.remove("operator"),
+                "operand" => self.assignment_target(node.remove("argument"))?
+            },
+            "YieldExpression" => {
+                if node["delegate"] == true {
+                    object!{
+                        "type" => "YieldGeneratorExpression",
+                        "expression" => self.expression(node.remove("argument"))?
+                    }
+                } else {
+                    object!{
+                        "type" => "YieldExpression",
+                        "expression" => self.optional_expression(node.remove("argument"))?
+                    }
+                }
+            }
+            _ => return Err(invalid(&node, "Expression"))
+        };
+        Ok(result)
+    }
+
+    fn literal(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let value = node.remove("value");
+        let result = match value {
+            JSON::Null if node.get("bigint").is_some() => {
+                let big_int = node.remove("bigint");
+                let decimal = big_int.as_str()
+                    .and_then(decimal_big_int)
+                    .ok_or_else(|| invalid(&big_int, "BigInt digits"))?;
+                object!{
+                    "type" => "LiteralBigIntExpression",
+                    "value" => decimal
+                }
+            }
+            JSON::Null if node.get("regex").is_some() => {
+                let mut regex = node.remove("regex");
+                let flags = regex.remove("flags");
+                let flags = flags.as_str()
+                    .unwrap_or("");
+                object!{
+                    "type" => "LiteralRegExpExpression",
+                    "pattern" => regex.remove("pattern"),
+                    "global" => flags.contains('g'),
+                    "ignoreCase" => flags.contains('i'),
+                    "multiLine" => flags.contains('m'),
+                    "dotAll" => flags.contains('s'),
+                    "sticky" => flags.contains('y'),
+                    "unicode" => flags.contains('u')
+                }
+            }
+            JSON::Null => object!{
+                "type" => "LiteralNullExpression"
+            },
+            JSON::Bool(_) => object!{
+                "type" => "LiteralBooleanExpression",
+                "value" => value
+            },
+            JSON::Number(_) => object!{
+                "type" => "LiteralNumericExpression",
+                "value" => value
+            },
+            JSON::String(_) => object!{
+                "type" => "LiteralStringExpression",
+                "value" => value
+            },
+            _ => return Err(invalid(&value, "Literal value"))
+        };
+        Ok(result)
+    }
+
+    fn template(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let mut elements = array![];
+        let mut expressions = members(node.remove("expressions"), "list of expressions")?
+            .into_iter();
+        for mut quasi in members(node.remove("quasis"), "list of TemplateElement")? {
+            elements.push(object!{
+                "type" => "TemplateElement",
+                "rawValue" => quasi["value"].remove("raw")
+            }).unwrap();
+            if let Some(expression) = expressions.next() {
+                elements.push(self.expression(expression)?).unwrap();
+            }
+        }
+        Ok(object!{
+            "type" => "TemplateExpression",
+            "tag" => JSON::Null,
+            "elements" => elements
+        })
+    }
+
+    fn property_name(&self, key: JSON, computed: bool) -> Result<JSON, ASTError> {
+        if computed {
+            return Ok(object!{
+                "type" => "ComputedPropertyName",
+                "expression" => self.expression(key)?
+            });
+        }
+        let value = match key["type"].as_str() {
+            Some("Identifier") => key["name"].clone(),
+            Some("Literal") if key["value"].is_number() => JSON::from(key["value"].dump()),
+            Some("Literal") => key["value"].clone(),
+            _ => return Err(invalid(&key, "Identifier or Literal"))
+        };
+        Ok(object!{
+            "type" => "StaticPropertyName",
+            "value" => value
+        })
+    }
+
+    /// Convert a `Property` of an `ObjectExpression`.
+    fn object_property(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        if node["type"] != "Property" {
+            return Err(invalid(&node, "Property"));
+        }
+        let computed = node["computed"] == true;
+        let key = node.remove("key");
+        let value = node.remove("value");
+        let kind = node["kind"].as_str()
+            .unwrap_or("init")
+            .to_string();
+        if kind != "init" || node["method"] == true {
+            return self.method(key, computed, value, &kind);
+        }
+        if node["shorthand"] == true {
+            return Ok(object!{
+                "type" => "ShorthandProperty",
+                "name" => self.expression(key)?
+            });
+        }
+        Ok(object!{
+            "type" => "DataProperty",
+            "name" => self.property_name(key, computed)?,
+            "expression" => self.expression(value)?
+        })
+    }
+
+    /// Convert a method, getter or setter, used either as an object property
+    /// or as a class element.
+    fn method(&self, key: JSON, computed: bool, mut function: JSON, kind: &str) -> Result<JSON, ASTError> {
+        let name = self.property_name(key, computed)?;
+        let body = self.function_body(function.remove("body"))?;
+        let result = match kind {
+            "get" => object!{
+                "type" => "Getter",
+                "name" => name,
+                "body" => body
+            },
+            "set" => {
+                let param = members(function.remove("params"), "list of parameters")?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| invalid(&function, "setter with one parameter"))?;
+                object!{
+                    "type" => "Setter",
+                    "name" => name,
+                    "param" => self.binding(param)?,
+                    "body" => body
+                }
+            }
+            _ => object!{
+                "type" => "Method",
+                "isAsync" => function["async"].as_bool().unwrap_or(false),
+                "isGenerator" => function["generator"].as_bool().unwrap_or(false),
+                "name" => name,
+                "params" => self.params(function.remove("params"))?,
+                "body" => body
+            }
+        };
+        Ok(result)
+    }
+
+    fn class(&self, mut node: JSON, type_: &str) -> Result<JSON, ASTError> {
+        let id = node.remove("id");
+        let name = if id.is_null() {
+            JSON::Null
+        } else {
+            self.binding(id)?
+        };
+        let mut elements = array![];
+        for mut definition in members(node["body"].remove("body"), "list of MethodDefinition")? {
+            let computed = definition["computed"] == true;
+            let kind = definition["kind"].as_str()
+                .unwrap_or("method")
+                .to_string();
+            let method = self.method(definition.remove("key"), computed, definition.remove("value"), &kind)?;
+            elements.push(object!{
+                "type" => "ClassElement",
+                "isStatic" => definition["static"].as_bool().unwrap_or(false),
+                "method" => method
+            }).unwrap();
+        }
+        Ok(object!{
+            "type" => type_,
+            "name" => name,
+            "super" => self.optional_expression(node.remove("superClass"))?,
+            "elements" => elements
+        })
+    }
+
+    fn function(&self, mut node: JSON, type_: &str) -> Result<JSON, ASTError> {
+        let id = node.remove("id");
+        let name = if id.is_null() {
+            JSON::Null
+        } else {
+            self.binding(id)?
+        };
+        Ok(object!{
+            "type" => type_,
+            "isAsync" => node["async"].as_bool().unwrap_or(false),
+            "isGenerator" => node["generator"].as_bool().unwrap_or(false),
+            "name" => name,
+            "params" => self.params(node.remove("params"))?,
+            "body" => self.function_body(node.remove("body"))?
+        })
+    }
+
+    fn function_body(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        if node["type"] != "BlockStatement" {
+            return Err(invalid(&node, "BlockStatement"));
+        }
+        let (directives, statements) = self.body(node.remove("body"))?;
+        Ok(object!{
+            "type" => "FunctionBody",
+            "directives" => directives,
+            "statements" => statements
+        })
+    }
+
+    fn params(&self, params: JSON) -> Result<JSON, ASTError> {
+        let mut items = array![];
+        let mut rest = JSON::Null;
+        for mut param in members(params, "list of parameters")? {
+            if param["type"] == "RestElement" {
+                rest = self.binding(param.remove("argument"))?;
+            } else {
+                items.push(self.binding(param)?).unwrap();
+            }
+        }
+        Ok(object!{
+            "type" => "FormalParameters",
+            "items" => items,
+            "rest" => rest
+        })
+    }
+
+    fn binding(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let kind = self.kind(&node, "Binding")?;
+        let result = match kind.as_str() {
+            "ArrayPattern" => {
+                let (elements, rest) = self.array_pattern(node, &|node| self.binding(node))?;
+                object!{
+                    "type" => "ArrayBinding",
+                    "elements" => elements,
+                    "rest" => rest
+                }
+            }
+            "AssignmentPattern" => object!{
+                "type" => "BindingWithDefault",
+                "binding" => self.binding(node.remove("left"))?,
+                "init" => self.expression(node.remove("right"))?
+            },
+            "Identifier" => object!{
+                "type" => "BindingIdentifier",
+                "name" => node.remove("name")
+            },
+            "ObjectPattern" => {
+                let mut properties = array![];
+                for mut property in members(node.remove("properties"), "list of Property")? {
+                    let property = if property["shorthand"] == true {
+                        let (binding, init) = self.shorthand_pattern(property.remove("value"))?;
+                        object!{
+                            "type" => "BindingPropertyIdentifier",
+                            "binding" => self.binding(binding)?,
+                            "init" => init
+                        }
+                    } else {
+                        let computed = property["computed"] == true;
+                        object!{
+                            "type" => "BindingPropertyProperty",
+                            "name" => self.property_name(property.remove("key"), computed)?,
+                            "binding" => self.binding(property.remove("value"))?
+                        }
+                    };
+                    properties.push(property).unwrap();
+                }
+                object!{
+                    "type" => "ObjectBinding",
+                    "properties" => properties
+                }
+            }
+            _ => return Err(invalid(&node, "Binding"))
+        };
+        Ok(result)
+    }
+
+    fn assignment_target(&self, mut node: JSON) -> Result<JSON, ASTError> {
+        let kind = self.kind(&node, "AssignmentTarget")?;
+        let result = match kind.as_str() {
+            "ArrayPattern" => {
+                let (elements, rest) = self.array_pattern(node, &|node| self.assignment_target(node))?;
+                object!{
+                    "type" => "ArrayAssignmentTarget",
+                    "elements" => elements,
+                    "rest" => rest
+                }
+            }
+            "AssignmentPattern" => object!{
+                "type" => "AssignmentTargetWithDefault",
+                "binding" => self.assignment_target(node.remove("left"))?,
+                "init" => self.expression(node.remove("right"))?
+            },
+            "Identifier" => object!{
+                "type" => "AssignmentTargetIdentifier",
+                "name" => node.remove("name")
+            },
+            "MemberExpression" => {
+                let mut expression = self.expression(node)?;
+                let kind = if expression["type"] == "ComputedMemberExpression" {
+                    "ComputedMemberAssignmentTarget"
+                } else {
+                    "StaticMemberAssignmentTarget"
+                };
+                expression["type"] = JSON::from(kind);
+                expression
+            }
+            "ObjectPattern" => {
+                let mut properties = array![];
+                for mut property in members(node.remove("properties"), "list of Property")? {
+                    let property = if property["shorthand"] == true {
+                        let (binding, init) = self.shorthand_pattern(property.remove("value"))?;
+                        object!{
+                            "type" => "AssignmentTargetPropertyIdentifier",
+                            "binding" => self.assignment_target(binding)?,
+                            "init" => init
+                        }
+                    } else {
+                        let computed = property["computed"] == true;
+                        object!{
+                            "type" => "AssignmentTargetPropertyProperty",
+                            "name" => self.property_name(property.remove("key"), computed)?,
+                            "binding" => self.assignment_target(property.remove("value"))?
+                        }
+                    };
+                    properties.push(property).unwrap();
+                }
+                object!{
+                    "type" => "ObjectAssignmentTarget",
+                    "properties" => properties
+                }
+            }
+            _ => return Err(invalid(&node, "AssignmentTarget"))
+        };
+        Ok(result)
+    }
+
+    /// Split the value of a shorthand pattern property `{ a = init }`
+    /// into `(a, init)`, where `init` is already converted.
+    fn shorthand_pattern(&self, mut value: JSON) -> Result<(JSON, JSON), ASTError> {
+        if value["type"] == "AssignmentPattern" {
+            let init = self.expression(value.remove("right"))?;
+            Ok((value.remove("left"), init))
+        } else {
+            Ok((value, JSON::Null))
+        }
+    }
+
+    /// Convert the elements of an `ArrayPattern` into `(elements, rest)`.
+    fn array_pattern(&self, mut node: JSON, convert: &Fn(JSON) -> Result<JSON, ASTError>) -> Result<(JSON, JSON), ASTError> {
+        let mut elements = array![];
+        let mut rest = JSON::Null;
+        for mut element in members(node.remove("elements"), "list of patterns")? {
+            if element.is_null() {
+                elements.push(JSON::Null).unwrap();
+            } else if element["type"] == "RestElement" {
+                rest = convert(element.remove("argument"))?;
+            } else {
+                elements.push(convert(element)?).unwrap();
+            }
+        }
+        Ok((elements, rest))
+    }
+}
+
+#[test]
+fn test_estree_roundtrip() {
+    // Shift AST for `var {a, b: [c = 1]} = f(...x); label: for (;;) break label;`.
+    let shift = object!{
+        "type" => "Script",
+        "directives" => array![],
+        "statements" => array![
+            object!{
+                "type" => "VariableDeclarationStatement",
+                "declaration" => object!{
+                    "type" => "VariableDeclaration",
+                    "kind" => "var",
+                    "declarators" => array![
+                        object!{
+                            "type" => "VariableDeclarator",
+                            "binding" => object!{
+                                "type" => "ObjectBinding",
+                                "properties" => array![
+                                    object!{
+                                        "type" => "BindingPropertyIdentifier",
+                                        "binding" => object!{
+                                            "type" => "BindingIdentifier",
+                                            "name" => "a"
+                                        },
+                                        "init" => JSON::Null
+                                    },
+                                    object!{
+                                        "type" => "BindingPropertyProperty",
+                                        "name" => object!{
+                                            "type" => "StaticPropertyName",
+                                            "value" => "b"
+                                        },
+                                        "binding" => object!{
+                                            "type" => "ArrayBinding",
+                                            "elements" => array![
+                                                object!{
+                                                    "type" => "BindingWithDefault",
+                                                    "binding" => object!{
+                                                        "type" => "BindingIdentifier",
+                                                        "name" => "c"
+                                                    },
+                                                    "init" => object!{
+                                                        "type" => "LiteralNumericExpression",
+                                                        "value" => 1
+                                                    }
+                                                }
+                                            ],
+                                            "rest" => JSON::Null
+                                        }
+                                    }
+                                ]
+                            },
+                            "init" => object!{
+                                "type" => "CallExpression",
+                                "callee" => object!{
+                                    "type" => "IdentifierExpression",
+                                    "name" => "f"
+                                },
+                                "arguments" => array![
+                                    object!{
+                                        "type" => "SpreadElement",
+                                        "expression" => object!{
+                                            "type" => "IdentifierExpression",
+                                            "name" => "x"
+                                        }
+                                    }
+                                ]
+                            }
+                        }
+                    ]
+                }
+            },
+            object!{
+                "type" => "LabeledStatement",
+                "label" => "label",
+                "body" => object!{
+                    "type" => "ForStatement",
+                    "init" => JSON::Null,
+                    "test" => JSON::Null,
+                    "update" => JSON::Null,
+                    "body" => object!{
+                        "type" => "BreakStatement",
+                        "label" => "label"
+                    }
+                }
+            }
+        ]
+    };
+    let mut estree = shift.clone();
+    ToESTree.convert(&mut estree)
+        .expect("Could not convert to ESTree");
+    assert_eq!(estree["type"], "Program");
+
+    let roundtrip = FromESTree.convert(estree)
+        .expect("Could not convert from ESTree");
+    assert_eq!(roundtrip, shift);
+}
+
+#[test]
+fn test_estree_big_int() {
+    // Shift AST for `255n;`.
+    let shift = object!{
+        "type" => "Script",
+        "directives" => array![],
+        "statements" => array![
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => object!{
+                    "type" => "LiteralBigIntExpression",
+                    "value" => "255"
+                }
+            }
+        ]
+    };
+    let mut estree = shift.clone();
+    ToESTree.convert(&mut estree)
+        .expect("Could not convert to ESTree");
+    assert_eq!(estree["body"][0]["expression"], object!{
+        "type" => "Literal",
+        "value" => JSON::Null,
+        "bigint" => "255"
+    });
+
+    // ESTree producers may preserve the base of the literal.
+    for &digits in &["255", "0xFF", "0o377", "0b1111_1111"] {
+        estree["body"][0]["expression"]["bigint"] = JSON::from(digits);
+        let roundtrip = FromESTree.convert(estree.clone())
+            .expect("Could not convert from ESTree");
+        assert_eq!(roundtrip, shift);
+    }
+    estree["body"][0]["expression"]["bigint"] = JSON::from("0xFG");
+    assert!(FromESTree.convert(estree).is_err());
+}
+
+#[test]
+fn test_estree_template_cooked() {
+    assert_eq!(cook("foo"), Some("foo".to_string()));
+    assert_eq!(cook(r"a\nb\tc\\d\`e\${f}"), Some("a\nb\tc\\d`e${f}".to_string()));
+    assert_eq!(cook(r"\x41B\u{43}\u{1F600}😀\0"), Some("ABC\u{1F600}\u{1F600}\0".to_string()));
+    assert_eq!(cook("a\\\nb\\\r\nc\r\nd\re"), Some("abc\nd\ne".to_string()));
+
+    // Only legal in tagged templates.
+    for raw in &[r"\unicode", r"\xG0", r"\u{110000}", r"\01", r"\1", r"\uD83D", "\\"] {
+        assert_eq!(cook(raw), None, "{}", raw);
+    }
+
+    // Shift AST for `` tag`\unicode ${x}\n` ``.
+    let mut ast = object!{
+        "type" => "TemplateExpression",
+        "tag" => object!{
+            "type" => "IdentifierExpression",
+            "name" => "tag"
+        },
+        "elements" => array![
+            object!{
+                "type" => "TemplateElement",
+                "rawValue" => r"\unicode "
+            },
+            object!{
+                "type" => "IdentifierExpression",
+                "name" => "x"
+            },
+            object!{
+                "type" => "TemplateElement",
+                "rawValue" => r"\n"
+            }
+        ]
+    };
+    ToESTree.convert(&mut ast)
+        .expect("Could not convert to ESTree");
+    assert_eq!(ast["quasi"]["quasis"][0]["value"], object!{
+        "raw" => r"\unicode ",
+        "cooked" => JSON::Null
+    });
+    assert_eq!(ast["quasi"]["quasis"][1]["value"], object!{
+        "raw" => r"\n",
+        "cooked" => "\n"
+    });
+}
+
+#[test]
+fn test_estree_invalid_property_name() {
+    // Shift AST for `({ a: 1 })`, with an invalid property name.
+    let mut ast = object!{
+        "type" => "ObjectExpression",
+        "properties" => array![
+            object!{
+                "type" => "DataProperty",
+                "name" => object!{
+                    "type" => "IdentifierExpression",
+                    "name" => "a"
+                },
+                "expression" => object!{
+                    "type" => "LiteralNumericExpression",
+                    "value" => 1
+                }
+            }
+        ]
+    };
+    assert!(ToESTree.convert(&mut ast).is_err());
+}
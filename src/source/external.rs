@@ -0,0 +1,284 @@
+//! Parsing JavaScript with an external parser, e.g. esprima, a configured Babel
+//! or a custom parser, rather than with the bundled Shift tooling.
+//!
+//! # Protocol
+//!
+//! The parser is a long-lived command, launched on the first source. It reads
+//! requests from its stdin and writes responses to its stdout, one JSON object
+//! per line. Its stderr is inherited, e.g. for diagnostics.
+//!
+//! The first request negotiates capabilities:
+//!
+//! - request `{"type": "hello", "version": 1}`;
+//! - response `{"type": "hello", "version": 1, "goals": ["script", "module"], "ast": "shift", "positions": false}`,
+//!   where `goals` lists the goals the parser accepts, `ast` is the format of the ASTs
+//!   it produces, either `"shift"` or `"estree"`, and `positions` (optional, `false` by default)
+//!   specifies whether it may annotate nodes with source positions.
+//!
+//! Each subsequent request parses a source:
+//!
+//! - request `{"type": "parse", "source": "...", "goal": "script", "positions": false}`;
+//! - response `{"type": "ast", "ast": {...}}` if the source could be parsed, or
+//!   `{"type": "error", "message": "...", "line": 1, "column": 10}` otherwise, where
+//!   `line` and `column` are optional.
+//!
+//! If `positions` is `true`, Shift ASTs may annotate nodes with a field `loc` and the root
+//! with a field `comments`, as `Shift::with_positions`.
+//!
+//! ESTree ASTs are converted through the Shift AST.
+//!
+//! With `SourceType::Auto`, sources are parsed as scripts, then as modules if the parser
+//! rejects them and accepts modules.
+//!
+//! Strings of the AST are valid Unicode. Lone surrogates must be written as `\u007F`
+//! followed by their code unit as four hexadecimal digits, and `\u007F` itself as
+//! `\u007F007F`, see crates/binjs_shared/src/escaped_wtf8.rs.
+
+use binjs_shared::{ JSON, JSONExt };
+
+use std;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Mutex;
+
+use source::daemon::Daemon;
+use source::estree::FromESTree;
+use source::parser::{ SourceParser, SourceType };
+use source::shift::{ Error, Shift };
+
+/// The version of the protocol implemented by this module.
+pub const PROTOCOL_VERSION: u64 = 1;
+
+/// The format of the ASTs produced by an external parser.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ASTFormat {
+    Shift,
+    ESTree,
+}
+
+/// The capabilities announced by an external parser.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Either `SourceType::Script` or `SourceType::Module`.
+    pub goals: Vec<SourceType>,
+    pub ast: ASTFormat,
+    pub positions: bool,
+}
+impl Capabilities {
+    fn from_json(response: &JSON) -> Result<Self, Error> {
+        if response["type"].as_str() != Some("hello") {
+            return Err(Error::ProtocolError(format!("Expected hello, got {}", response.dump())));
+        }
+        if response["version"].as_u64() != Some(PROTOCOL_VERSION) {
+            return Err(Error::ProtocolError(format!("Unsupported protocol version {}", response["version"].dump())));
+        }
+        let goals = response["goals"].as_array()
+            .ok_or_else(|| Error::ProtocolError(format!("Invalid goals {}", response["goals"].dump())))?
+            .iter()
+            .filter_map(JSON::as_str)
+            .filter_map(|name| match name {
+                "script" => Some(SourceType::Script),
+                "module" => Some(SourceType::Module),
+                _ => None
+            })
+            .collect();
+        let ast = match response["ast"].as_str() {
+            Some("shift") => ASTFormat::Shift,
+            Some("estree") => ASTFormat::ESTree,
+            _ => return Err(Error::ProtocolError(format!("Unsupported AST format {}", response["ast"].dump())))
+        };
+        Ok(Capabilities {
+            goals,
+            ast,
+            positions: response["positions"].as_bool().unwrap_or(false),
+        })
+    }
+}
+
+/// Using an external command to parse an AST.
+pub struct External {
+    /// The program and its arguments.
+    command: Vec<String>,
+
+    /// The goal symbol with which sources are parsed.
+    source_type: SourceType,
+
+    /// If `true`, ask the parser to annotate nodes with their source positions.
+    positions: bool,
+
+    /// The running parser and its capabilities, once launched.
+    process: Mutex<Option<(Daemon, Capabilities)>>,
+
+    /// Used to convert from the Shift AST.
+    shift: Shift,
+}
+
+impl External {
+    /// Use `command`, a program followed by its arguments, separated by whitespace.
+    ///
+    /// The command is launched lazily.
+    pub fn new(command: &str) -> Self {
+        External {
+            command: command.split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            source_type: SourceType::Script,
+            positions: false,
+            process: Mutex::new(None),
+            shift: Shift::new(),
+        }
+    }
+
+    /// Parse sources as scripts, modules, or either, `SourceType::Script` by default.
+    pub fn with_source_type(self, source_type: SourceType) -> Self {
+        External {
+            source_type,
+            ..self
+        }
+    }
+
+    /// If `true` and the parser supports it, annotate nodes with their source positions,
+    /// see `Shift::with_positions`.
+    pub fn with_positions(self, positions: bool) -> Self {
+        External {
+            positions,
+            ..self
+        }
+    }
+
+    /// The capabilities of the parser, launching it if necessary.
+    ///
+    /// Fails with `Error::UnsupportedGoal` if the parser does not accept the source type
+    /// of this instance, so that callers can report misconfigurations before parsing.
+    pub fn capabilities(&self) -> Result<Capabilities, Error> {
+        let mut process = self.process.lock()
+            .unwrap();
+        let capabilities = self.launch(&mut process)?
+            .1
+            .clone();
+        Self::check_goal(&capabilities, self.source_type)?;
+        Ok(capabilities)
+    }
+
+    /// The running parser, launched and negotiated with if necessary.
+    fn launch<'a>(&self, process: &'a mut Option<(Daemon, Capabilities)>) -> Result<&'a mut (Daemon, Capabilities), Error> {
+        if process.is_none() {
+            let (program, args) = self.command.split_first()
+                .ok_or_else(|| Error::ProtocolError("Empty parser command".to_string()))?;
+            let mut command = Command::new(program);
+            command.args(args);
+            let mut daemon = Daemon::spawn(command)?;
+            let response = daemon.request(&object!{
+                "type" => "hello",
+                "version" => PROTOCOL_VERSION
+            })?;
+            let capabilities = Capabilities::from_json(&response)?;
+            debug!(target: "External", "Parser {} has capabilities {:?}", daemon.id(), capabilities);
+            *process = Some((daemon, capabilities));
+        }
+        Ok(process.as_mut()
+            .unwrap()) // Just launched.
+    }
+
+    /// Check that the parser accepts `source_type`. With `SourceType::Auto`, modules are optional.
+    fn check_goal(capabilities: &Capabilities, source_type: SourceType) -> Result<(), Error> {
+        let goal = match source_type {
+            SourceType::Auto => SourceType::Script,
+            goal => goal
+        };
+        if capabilities.goals.contains(&goal) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedGoal(goal.name().to_string()))
+        }
+    }
+
+    /// Parse `source` with `source_type`, returning the AST as produced by the parser.
+    fn parse(&self, daemon: &mut Daemon, capabilities: &Capabilities, source: &str, source_type: SourceType) -> Result<JSON, Error> {
+        Self::check_goal(capabilities, source_type)?;
+        match source_type {
+            SourceType::Auto => {
+                match self.request(daemon, capabilities, source, SourceType::Script) {
+                    Err(err @ Error::SyntaxError { .. }) => {
+                        if !capabilities.goals.contains(&SourceType::Module) {
+                            return Err(err);
+                        }
+                        match self.request(daemon, capabilities, source, SourceType::Module) {
+                            // Report the error as a script.
+                            Err(Error::SyntaxError { .. }) => Err(err),
+                            result => result
+                        }
+                    }
+                    result => result
+                }
+            }
+            goal => self.request(daemon, capabilities, source, goal)
+        }
+    }
+
+    /// Send a parse request with goal `goal`, either `SourceType::Script` or `SourceType::Module`.
+    fn request(&self, daemon: &mut Daemon, capabilities: &Capabilities, source: &str, goal: SourceType) -> Result<JSON, Error> {
+        let mut response = daemon.request(&object!{
+            "type" => "parse",
+            "source" => source,
+            "goal" => goal.name(),
+            "positions" => self.positions && capabilities.positions
+        })?;
+        match response["type"].as_str() {
+            Some("ast") => Ok(response.remove("ast")),
+            Some("error") => Err(Error::SyntaxError {
+                message: response["message"].as_str()
+                    .unwrap_or("")
+                    .to_string(),
+                line: response["line"].as_u64(),
+                column: response["column"].as_u64(),
+            }),
+            _ => Err(Error::ProtocolError(format!("Unexpected response {}", response.dump())))
+        }
+    }
+
+    /// Convert an AST produced by the parser into a BinJS AST.
+    fn convert(&self, capabilities: &Capabilities, ast: JSON) -> Result<JSON, Error> {
+        let mut ast = match capabilities.ast {
+            ASTFormat::Shift => ast,
+            ASTFormat::ESTree => FromESTree.convert(ast)
+                .map_err(Error::InvalidAST)?,
+        };
+        self.shift.convert_shift_json(&mut ast)?;
+        Ok(ast)
+    }
+
+    /// Parse `source` with `source_type`, relaunching the parser if it misbehaves.
+    fn parse_str_as(&self, source: &str, source_type: SourceType) -> Result<JSON, Error> {
+        let mut process = self.process.lock()
+            .unwrap();
+        let (result, capabilities) = {
+            let &mut (ref mut daemon, ref capabilities) = self.launch(&mut process)?;
+            (self.parse(daemon, capabilities, source, source_type), capabilities.clone())
+        };
+        match result {
+            Ok(ast) => self.convert(&capabilities, ast),
+            Err(err @ Error::SyntaxError { .. }) | Err(err @ Error::UnsupportedGoal(_)) => Err(err),
+            Err(err) => {
+                // The parser may be in an inconsistent state, relaunch it for the next source.
+                *process = None;
+                Err(err)
+            }
+        }
+    }
+}
+
+impl SourceParser for External {
+    type Error = Error;
+    fn parse_str(&self, source: &str) -> Result<JSON, Error> {
+        self.parse_str_as(source, self.source_type)
+    }
+
+    /// Parse a text source file, sending its contents to the parser.
+    fn parse_file<P: AsRef<Path>>(&self, path: P) -> Result<JSON, Error> {
+        let source = String::from_utf8(std::fs::read(path.as_ref())
+            .map_err(Error::CouldNotReadFile)?)
+            .map_err(Error::InvalidUTF8)?;
+        self.parse_str_as(&source, self.source_type.for_path(path.as_ref()))
+    }
+}
@@ -0,0 +1,429 @@
+//! Lowering JSX to plain JavaScript on an ESTree AST.
+//!
+//! `<Foo bar={baz}>text</Foo>` is lowered to
+//! `React.createElement(Foo, {bar: baz}, "text")`, following the
+//! classic React runtime. The pragma (`React.createElement`) and the
+//! fragment pragma (`React.Fragment`) are configurable.
+
+use binjs_generic::syntax::ASTError;
+
+use binjs_shared::{ JSON, JSONExt };
+
+/// A data structure designed to lower JSX elements into function calls.
+pub struct LowerJSX {
+    /// The function called to create elements, e.g. `React.createElement`.
+    pragma: String,
+
+    /// The component used for fragments, e.g. `React.Fragment`.
+    pragma_frag: String,
+}
+
+impl LowerJSX {
+    pub fn new(pragma: &str, pragma_frag: &str) -> Self {
+        LowerJSX {
+            pragma: pragma.to_string(),
+            pragma_frag: pragma_frag.to_string(),
+        }
+    }
+
+    /// Lower all JSX elements and fragments of an ESTree AST, in place.
+    pub fn lower(&self, value: &mut JSON) -> Result<(), ASTError> {
+        match *value {
+            JSON::Array(ref mut array) => {
+                for item in array.iter_mut() {
+                    self.lower(item)?;
+                }
+                return Ok(())
+            }
+            JSON::Object(ref mut object) => {
+                for (_, field) in object.iter_mut() {
+                    self.lower(field)?;
+                }
+            }
+            _ => return Ok(())
+        }
+        let replacement = match value["type"].as_str() {
+            Some("JSXElement") | Some("JSXFragment") => self.element(value.take())?,
+            _ => return Ok(())
+        };
+        *value = replacement;
+        Ok(())
+    }
+
+    /// Convert a dotted name such as `React.createElement` to an expression.
+    fn dotted_name(&self, name: &str) -> JSON {
+        let mut parts = name.split('.');
+        let mut result = object!{
+            "type" => "Identifier",
+            "name" => parts.next().unwrap_or("")
+        };
+        for part in parts {
+            result = object!{
+                "type" => "MemberExpression",
+                "object" => result,
+                "property" => object!{
+                    "type" => "Identifier",
+                    "name" => part
+                },
+                "computed" => false
+            };
+        }
+        result
+    }
+
+    /// Convert the name of an element to an expression.
+    ///
+    /// Lowercase names are intrinsic elements (i.e. HTML tags), represented
+    /// as strings, other names are references to components.
+    fn tag(&self, mut name: JSON) -> Result<JSON, ASTError> {
+        let result = match name["type"].as_str() {
+            Some("JSXIdentifier") => {
+                let is_intrinsic = name["name"].as_str()
+                    .and_then(|name| name.chars().next())
+                    .map(|c| c.is_lowercase())
+                    .unwrap_or(false);
+                if is_intrinsic {
+                    object!{
+                        "type" => "Literal",
+                        "value" => name.remove("name")
+                    }
+                } else if name["name"] == "this" {
+                    object!{
+                        "type" => "ThisExpression"
+                    }
+                } else {
+                    object!{
+                        "type" => "Identifier",
+                        "name" => name.remove("name")
+                    }
+                }
+            }
+            Some("JSXMemberExpression") => {
+                let property = name["property"].remove("name");
+                object!{
+                    "type" => "MemberExpression",
+                    "object" => self.tag_object(name.remove("object"))?,
+                    "property" => object!{
+                        "type" => "Identifier",
+                        "name" => property
+                    },
+                    "computed" => false
+                }
+            }
+            Some("JSXNamespacedName") => {
+                object!{
+                    "type" => "Literal",
+                    "value" => format!("{}:{}",
+                        name["namespace"]["name"].as_str().unwrap_or(""),
+                        name["name"]["name"].as_str().unwrap_or(""))
+                }
+            }
+            _ => return Err(ASTError::InvalidValue {
+                got: name.dump(),
+                expected: "JSX element name".to_string()
+            })
+        };
+        Ok(result)
+    }
+
+    /// Convert the object of a `JSXMemberExpression`, which is never intrinsic.
+    fn tag_object(&self, mut object: JSON) -> Result<JSON, ASTError> {
+        if object["type"] == "JSXIdentifier" {
+            Ok(object!{
+                "type" => "Identifier",
+                "name" => object.remove("name")
+            })
+        } else {
+            self.tag(object)
+        }
+    }
+
+    /// Convert the attributes of an element to the `props` argument.
+    fn props(&self, attributes: JSON) -> Result<JSON, ASTError> {
+        // Consecutive attributes are grouped in object literals,
+        // spread attributes are merged with `Object.assign`.
+        let mut segments = vec![];
+        let mut properties = array![];
+        for mut attribute in attributes.members().cloned() {
+            if attribute["type"] == "JSXSpreadAttribute" {
+                if !properties.is_empty() {
+                    segments.push(object!{
+                        "type" => "ObjectExpression",
+                        "properties" => properties.take()
+                    });
+                    properties = array![];
+                }
+                segments.push(attribute.remove("argument"));
+                continue;
+            }
+            let key = match attribute["name"]["type"].as_str() {
+                Some("JSXIdentifier") => {
+                    let name = attribute["name"].remove("name");
+                    if name.as_str().map(|name| name.contains('-')).unwrap_or(false) {
+                        object!{
+                            "type" => "Literal",
+                            "value" => name
+                        }
+                    } else {
+                        object!{
+                            "type" => "Identifier",
+                            "name" => name
+                        }
+                    }
+                }
+                _ => self.tag(attribute.remove("name"))?
+            };
+            let value = attribute.remove("value");
+            let value = match value["type"].as_str() {
+                None => object!{
+                    "type" => "Literal",
+                    "value" => true
+                },
+                Some("JSXExpressionContainer") => {
+                    let mut value = value;
+                    value.remove("expression")
+                }
+                Some(_) => value
+            };
+            properties.push(object!{
+                "type" => "Property",
+                "key" => key,
+                "computed" => false,
+                "value" => value,
+                "kind" => "init",
+                "method" => false,
+                "shorthand" => false
+            }).unwrap();
+        }
+        if !properties.is_empty() {
+            segments.push(object!{
+                "type" => "ObjectExpression",
+                "properties" => properties
+            });
+        }
+        let result = match segments.len() {
+            0 => object!{
+                "type" => "Literal",
+                "value" => JSON::Null
+            },
+            1 if segments[0]["type"] == "ObjectExpression" => segments.pop().unwrap(),
+            _ => {
+                let mut arguments = array![
+                    object!{
+                        "type" => "ObjectExpression",
+                        "properties" => array![]
+                    }
+                ];
+                for segment in segments {
+                    arguments.push(segment).unwrap();
+                }
+                object!{
+                    "type" => "CallExpression",
+                    "callee" => self.dotted_name("Object.assign"),
+                    "arguments" => arguments
+                }
+            }
+        };
+        Ok(result)
+    }
+
+    /// Convert the text of a JSX child, following the whitespace rules of JSX:
+    /// lines are trimmed, empty lines are removed, and the remaining lines are
+    /// joined with a single space.
+    fn text(&self, text: &str) -> Option<String> {
+        let lines : Vec<_> = text.lines().collect();
+        let last = lines.len().saturating_sub(1);
+        let mut result = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            let mut line = *line;
+            if i != 0 {
+                line = line.trim_start();
+            }
+            if i != last {
+                line = line.trim_end();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if !result.is_empty() {
+                result.push(' ');
+            }
+            result.push_str(line);
+        }
+        if result.is_empty() {
+            None
+        } else {
+            Some(result)
+        }
+    }
+
+    /// Lower a `JSXElement` or `JSXFragment`, whose children have already been lowered.
+    fn element(&self, mut element: JSON) -> Result<JSON, ASTError> {
+        let mut arguments = array![];
+        if element["type"] == "JSXFragment" {
+            arguments.push(self.dotted_name(&self.pragma_frag)).unwrap();
+            arguments.push(object!{
+                "type" => "Literal",
+                "value" => JSON::Null
+            }).unwrap();
+        } else {
+            let mut opening = element.remove("openingElement");
+            arguments.push(self.tag(opening.remove("name"))?).unwrap();
+            arguments.push(self.props(opening.remove("attributes"))?).unwrap();
+        }
+        for mut child in element.remove("children").members().cloned() {
+            let child = match child["type"].as_str() {
+                Some("JSXText") => {
+                    match child["value"].as_str().and_then(|text| self.text(text)) {
+                        None => continue,
+                        Some(text) => object!{
+                            "type" => "Literal",
+                            "value" => text
+                        }
+                    }
+                }
+                Some("JSXExpressionContainer") => {
+                    if child["expression"]["type"] == "JSXEmptyExpression" {
+                        continue;
+                    }
+                    child.remove("expression")
+                }
+                Some("JSXSpreadChild") => object!{
+                    "type" => "SpreadElement",
+                    "argument" => child.remove("expression")
+                },
+                _ => child
+            };
+            arguments.push(child).unwrap();
+        }
+        Ok(object!{
+            "type" => "CallExpression",
+            "callee" => self.dotted_name(&self.pragma),
+            "arguments" => arguments
+        })
+    }
+}
+
+#[test]
+fn test_lower_jsx() {
+    // ESTree AST for `<div id="a" {...b}>  hello
+    //    world {c}</div>`.
+    let mut ast = object!{
+        "type" => "ExpressionStatement",
+        "expression" => object!{
+            "type" => "JSXElement",
+            "openingElement" => object!{
+                "type" => "JSXOpeningElement",
+                "name" => object!{
+                    "type" => "JSXIdentifier",
+                    "name" => "div"
+                },
+                "attributes" => array![
+                    object!{
+                        "type" => "JSXAttribute",
+                        "name" => object!{
+                            "type" => "JSXIdentifier",
+                            "name" => "id"
+                        },
+                        "value" => object!{
+                            "type" => "Literal",
+                            "value" => "a"
+                        }
+                    },
+                    object!{
+                        "type" => "JSXSpreadAttribute",
+                        "argument" => object!{
+                            "type" => "Identifier",
+                            "name" => "b"
+                        }
+                    }
+                ]
+            },
+            "children" => array![
+                object!{
+                    "type" => "JSXText",
+                    "value" => "  hello\n    world "
+                },
+                object!{
+                    "type" => "JSXExpressionContainer",
+                    "expression" => object!{
+                        "type" => "Identifier",
+                        "name" => "c"
+                    }
+                }
+            ]
+        }
+    };
+    LowerJSX::new("h", "Fragment").lower(&mut ast)
+        .expect("Could not lower JSX");
+    let expected = object!{
+        "type" => "ExpressionStatement",
+        "expression" => object!{
+            "type" => "CallExpression",
+            "callee" => object!{
+                "type" => "Identifier",
+                "name" => "h"
+            },
+            "arguments" => array![
+                object!{
+                    "type" => "Literal",
+                    "value" => "div"
+                },
+                object!{
+                    "type" => "CallExpression",
+                    "callee" => object!{
+                        "type" => "MemberExpression",
+                        "object" => object!{
+                            "type" => "Identifier",
+                            "name" => "Object"
+                        },
+                        "property" => object!{
+                            "type" => "Identifier",
+                            "name" => "assign"
+                        },
+                        "computed" => false
+                    },
+                    "arguments" => array![
+                        object!{
+                            "type" => "ObjectExpression",
+                            "properties" => array![]
+                        },
+                        object!{
+                            "type" => "ObjectExpression",
+                            "properties" => array![
+                                object!{
+                                    "type" => "Property",
+                                    "key" => object!{
+                                        "type" => "Identifier",
+                                        "name" => "id"
+                                    },
+                                    "computed" => false,
+                                    "value" => object!{
+                                        "type" => "Literal",
+                                        "value" => "a"
+                                    },
+                                    "kind" => "init",
+                                    "method" => false,
+                                    "shorthand" => false
+                                }
+                            ]
+                        },
+                        object!{
+                            "type" => "Identifier",
+                            "name" => "b"
+                        }
+                    ]
+                },
+                object!{
+                    "type" => "Literal",
+                    "value" => "  hello world "
+                },
+                object!{
+                    "type" => "Identifier",
+                    "name" => "c"
+                }
+            ]
+        }
+    };
+    assert_eq!(ast, expected);
+}
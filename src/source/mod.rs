@@ -3,8 +3,33 @@
 //! Reading a JavaScript text source file into an AST.
 
 mod parser;
-pub use self::parser::SourceParser;
+pub use self::parser::{ SourceParser, SourceType };
 
 /// Parsing JavaScript using the Shift source parser (in Node).
 pub mod shift;
-pub use self::shift::Shift;
\ No newline at end of file
+pub use self::shift::Shift;
+
+/// Converting between the Shift AST and the ESTree AST.
+pub mod estree;
+pub use self::estree::{ FromESTree, ToESTree };
+
+/// Running the Node parsers in long-lived processes.
+pub mod daemon;
+pub use self::daemon::DaemonPool;
+
+/// Parsing JavaScript using an external parser, through a documented protocol.
+pub mod external;
+pub use self::external::External;
+
+/// Parsing JavaScript extensions using the Babel source parser (in Node).
+pub mod babel;
+pub use self::babel::Babel;
+
+/// Stripping TypeScript types.
+pub mod typescript;
+
+/// Lowering JSX.
+pub mod jsx;
+
+/// Collecting and reattaching source positions and comments.
+pub mod positions;
@@ -1,7 +1,48 @@
+use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::path::Path;
 
-use json::JsonValue as JSON;
+use binjs_shared::JSON;
+
+/// The goal symbol with which sources are parsed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceType {
+    /// Parse sources as scripts, producing a `Script`.
+    Script,
+    /// Parse sources as modules, producing a `Module`.
+    Module,
+    /// Parse sources as scripts, or as modules if they contain `import` or `export`
+    /// declarations.
+    Auto,
+}
+impl SourceType {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SourceType::Script => "script",
+            SourceType::Module => "module",
+            SourceType::Auto => "auto",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "script" => Some(SourceType::Script),
+            "module" => Some(SourceType::Module),
+            "auto" => Some(SourceType::Auto),
+            _ => None
+        }
+    }
+
+    /// The source type with which to parse file `path`: with `SourceType::Auto`,
+    /// `.mjs` files are modules and `.cjs` files are scripts.
+    pub fn for_path(&self, path: &Path) -> SourceType {
+        match (*self, path.extension().and_then(OsStr::to_str)) {
+            (SourceType::Auto, Some("mjs")) => SourceType::Module,
+            (SourceType::Auto, Some("cjs")) => SourceType::Script,
+            (source_type, _) => source_type
+        }
+    }
+}
 
 /// A source that can parse files to JSON ASTs.
 pub trait SourceParser {
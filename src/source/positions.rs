@@ -0,0 +1,322 @@
+//! Moving source positions between JSON ASTs and `SourcePositions`.
+//!
+//! Parsers configured to report positions (e.g. `Shift::with_positions`) attach a field
+//! `loc` to nodes and a field `comments` to the root. As these fields are not part of
+//! the grammar, they must be collected before encoding and may be reattached after
+//! decoding.
+
+use binjs_io::positions::{ Comment, Location, Position, SourcePositions };
+use binjs_generic::syntax::ASTError;
+use binjs_shared::{ JSON, JSONExt };
+
+/// Remove the fields `loc` of all nodes of `ast` and the field `comments` of its root,
+/// returning them as `SourcePositions`.
+///
+/// Malformed positions are ignored.
+pub fn collect(ast: &mut JSON) -> SourcePositions {
+    let mut positions = SourcePositions::default();
+    if let JSON::Array(comments) = ast.remove("comments") {
+        for comment in &comments {
+            let kind = comment["type"].as_str();
+            let text = comment["text"].as_str();
+            let location = location_from_json(&comment["start"], &comment["end"]);
+            if let (Some(kind), Some(text), Some(location)) = (kind, text, location) {
+                positions.comments.push(Comment {
+                    kind: kind.to_string(),
+                    text: text.to_string(),
+                    location,
+                });
+            }
+        }
+    }
+    collect_aux(ast, &mut String::new(), &mut positions.locations);
+    positions
+}
+
+fn collect_aux(value: &mut JSON, path: &mut String, locations: &mut Vec<(String, Location)>) {
+    let len = path.len();
+    match *value {
+        JSON::Array(ref mut array) => {
+            for (index, item) in array.iter_mut().enumerate() {
+                path.push_str(&format!("[{}]", index));
+                collect_aux(item, path, locations);
+                path.truncate(len);
+            }
+        }
+        JSON::Object(ref mut object) => {
+            if let Some(loc) = object.remove("loc") {
+                if let Some(location) = location_from_json(&loc["start"], &loc["end"]) {
+                    locations.push((path.clone(), location));
+                }
+            }
+            for (name, field) in object.iter_mut() {
+                if len != 0 {
+                    path.push('.');
+                }
+                path.push_str(name);
+                collect_aux(field, path, locations);
+                path.truncate(len);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The locations of the function declarations, function expressions, methods,
+/// getters and setters of `ast`, i.e. the functions that may be lazified, see
+/// `binjs_es6::lazy::Policy`. Functions without a location are listed as `None`,
+/// so that the n-th location is always that of the n-th function.
+///
+/// Functions are listed in the order in which they are walked, provided that the
+/// fields of `ast` follow the grammar, e.g. if `ast` was exported from a `Program`
+/// and its positions then reattached with `reattach`. This is not necessarily the
+/// case of the output of a parser.
+pub fn function_locations(ast: &JSON) -> Vec<Option<Location>> {
+    let mut locations = vec![];
+    function_locations_aux(ast, &mut locations);
+    locations
+}
+
+fn function_locations_aux(value: &JSON, locations: &mut Vec<Option<Location>>) {
+    match *value {
+        JSON::Array(ref array) => {
+            for item in array {
+                function_locations_aux(item, locations);
+            }
+        }
+        JSON::Object(ref object) => {
+            match value["type"].as_str() {
+                Some("EagerFunctionDeclaration") | Some("EagerFunctionExpression")
+                | Some("EagerMethod") | Some("EagerGetter") | Some("EagerSetter") => {
+                    locations.push(location_from_json(&value["loc"]["start"], &value["loc"]["end"]));
+                }
+                _ => {}
+            }
+            for (_, field) in object.iter() {
+                function_locations_aux(field, locations);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Attach `positions` to `ast`, as a field `loc` of nodes and a field `comments`
+/// of the root, in the format produced by `Shift::with_positions`.
+///
+/// Fails if `positions` do not match the structure of `ast`, e.g. if they were
+/// collected from a different AST.
+pub fn reattach(ast: &mut JSON, positions: &SourcePositions) -> Result<(), ASTError> {
+    if !ast.is_object() {
+        return Err(ASTError::invalid_value(ast, "node"));
+    }
+    for &(ref path, ref location) in &positions.locations {
+        let node = node_at_mut(ast, path)?;
+        if !node.is_object() {
+            return Err(ASTError::invalid_value(node, "node"));
+        }
+        node["loc"] = object!{
+            "start" => position_to_json(&location.start),
+            "end" => position_to_json(&location.end)
+        };
+    }
+    if !positions.comments.is_empty() {
+        let comments : Vec<JSON> = positions.comments.iter()
+            .map(|comment| object!{
+                "type" => comment.kind.as_str(),
+                "text" => comment.text.as_str(),
+                "start" => position_to_json(&comment.location.start),
+                "end" => position_to_json(&comment.location.end)
+            })
+            .collect();
+        ast["comments"] = JSON::Array(comments);
+    }
+    Ok(())
+}
+
+/// Update the paths of `positions` after the toplevel statements of a script were
+/// reordered, `order` holding the original index of each statement, as returned by
+/// `binjs_es6::reorder::reorder_script`.
+pub fn reorder_statements(positions: &mut SourcePositions, order: &[usize]) {
+    let mut new_index = vec![0; order.len()];
+    for (new, &old) in order.iter().enumerate() {
+        new_index[old] = new;
+    }
+    for &mut (ref mut path, _) in &mut positions.locations {
+        let remapped = {
+            let suffix = match path.find(']') {
+                Some(end) if path.starts_with("statements[") => end,
+                _ => continue
+            };
+            match path["statements[".len()..suffix].parse::<usize>() {
+                Ok(old) if old < new_index.len() =>
+                    format!("statements[{}{}", new_index[old], &path[suffix..]),
+                _ => continue
+            }
+        };
+        *path = remapped;
+    }
+}
+
+/// Access the descendant of `ast` designated by a path, as produced by `collect`.
+fn node_at_mut<'a>(ast: &'a mut JSON, path: &str) -> Result<&'a mut JSON, ASTError> {
+    let mut node = ast;
+    for segment in path.split('.').filter(|segment| !segment.is_empty()) {
+        let mut parts = segment.split('[');
+        let name = parts.next()
+            .unwrap(); // `split` always yields at least one item.
+        if !name.is_empty() {
+            node = node.get_mut(name)
+                .ok_or_else(|| ASTError::missing_field(path))?;
+        }
+        for part in parts {
+            let index = part.trim_end_matches(']').parse::<usize>()
+                .map_err(|_| ASTError::InvalidField(path.to_string()))?;
+            node = node.get_mut(index)
+                .ok_or_else(|| ASTError::missing_field(path))?;
+        }
+    }
+    Ok(node)
+}
+
+fn position_from_json(value: &JSON) -> Option<Position> {
+    Some(Position {
+        line: value["line"].as_u64()? as u32,
+        column: value["column"].as_u64()? as u32,
+        offset: value["offset"].as_u64()? as u32,
+    })
+}
+
+fn location_from_json(start: &JSON, end: &JSON) -> Option<Location> {
+    Some(Location {
+        start: position_from_json(start)?,
+        end: position_from_json(end)?,
+    })
+}
+
+fn position_to_json(position: &Position) -> JSON {
+    object!{
+        "line" => position.line,
+        "column" => position.column,
+        "offset" => position.offset
+    }
+}
+
+#[test]
+fn test_function_locations() {
+    let position = |offset: u32| object!{
+        "line" => 1,
+        "column" => offset,
+        "offset" => offset
+    };
+    let ast = object!{
+        "type" => "Script",
+        "statements" => array![
+            object!{
+                "type" => "EagerFunctionDeclaration",
+                "contents" => object!{
+                    "type" => "FunctionOrMethodContents",
+                    "body" => array![
+                        object!{
+                            "type" => "ExpressionStatement",
+                            "expression" => object!{
+                                "type" => "EagerFunctionExpression",
+                                "loc" => object!{ "start" => position(20), "end" => position(34) }
+                            }
+                        }
+                    ]
+                },
+                "loc" => object!{ "start" => position(0), "end" => position(40) }
+            },
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => object!{
+                    "type" => "EagerArrowExpressionWithExpression",
+                    "loc" => object!{ "start" => position(41), "end" => position(50) }
+                }
+            },
+            object!{
+                "type" => "EagerFunctionDeclaration"
+            }
+        ]
+    };
+    let spans : Vec<_> = function_locations(&ast).iter()
+        .map(|location| location.as_ref()
+            .map(|location| (location.start.offset, location.end.offset)))
+        .collect();
+    assert_eq!(spans, vec![Some((0, 40)), Some((20, 34)), None]);
+}
+
+#[test]
+fn test_collect_reattach() {
+    let position = |line: u32, column: u32, offset: u32| object!{
+        "line" => line,
+        "column" => column,
+        "offset" => offset
+    };
+    let mut ast = object!{
+        "type" => "Script",
+        "statements" => array![
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => object!{
+                    "type" => "IdentifierExpression",
+                    "name" => "foo",
+                    "loc" => object!{ "start" => position(2, 0, 11), "end" => position(2, 3, 14) }
+                },
+                "loc" => object!{ "start" => position(2, 0, 11), "end" => position(2, 4, 15) }
+            }
+        ],
+        "loc" => object!{ "start" => position(1, 0, 0), "end" => position(2, 4, 15) },
+        "comments" => array![
+            object!{
+                "type" => "SingleLine",
+                "text" => " hello",
+                "start" => position(1, 0, 0),
+                "end" => position(1, 8, 8)
+            }
+        ]
+    };
+
+    let positions = collect(&mut ast);
+    assert_eq!(ast, object!{
+        "type" => "Script",
+        "statements" => array![
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => object!{
+                    "type" => "IdentifierExpression",
+                    "name" => "foo"
+                }
+            }
+        ]
+    });
+    assert_eq!(positions.comments.len(), 1);
+    let paths : Vec<_> = positions.locations.iter()
+        .map(|&(ref path, _)| path.as_str())
+        .collect();
+    assert_eq!(paths, vec!["", "statements[0]", "statements[0].expression"]);
+
+    reattach(&mut ast, &positions)
+        .expect("Could not reattach positions");
+    assert_eq!(collect(&mut ast), positions);
+
+    // Positions do not match a different AST.
+    let mut other = object!{
+        "type" => "Script",
+        "statements" => array![]
+    };
+    assert!(reattach(&mut other, &positions).is_err());
+}
+
+#[test]
+fn test_reorder_statements() {
+    let mut positions = SourcePositions::default();
+    for path in &["", "statements[0]", "statements[1].expression", "statements[10]", "directives[0]"] {
+        positions.locations.push((path.to_string(), Location::default()));
+    }
+    reorder_statements(&mut positions, &[1, 10, 0, 2, 3, 4, 5, 6, 7, 8, 9]);
+    let paths : Vec<_> = positions.locations.iter()
+        .map(|&(ref path, _)| path.as_str())
+        .collect();
+    assert_eq!(paths, vec!["", "statements[2]", "statements[0].expression", "statements[1]", "directives[0]"]);
+}
@@ -1,19 +1,23 @@
 //! Read the data through a call to the Shift parser
 
-use json;
-use json::object::Object;
-use json::JsonValue as JSON;
+use binjs_shared::{ JSON, JSONExt };
+
+use serde_json;
 
 use std;
 use std::env;
 use std::io::{ Write };
 use std::path::*;
 use std::process::*;
+use std::sync::Arc;
 
 use binjs_meta::spec::{ Interface, NodeName, Spec };
+use binjs_generic::es6::fields::{ arrow_expression_contents_with_expression, arrow_expression_contents_with_function_body, asserted_parameter_scope, block, catch_clause, eager_arrow_expression_with_expression, eager_arrow_expression_with_function_body, eager_function_declaration, eager_function_expression, eager_getter, eager_method, eager_setter, for_in_of_binding, for_in_statement, for_of_statement, formal_parameters, function_expression_contents, function_or_method_contents, getter_contents, identifier_expression, lazy_arrow_expression_with_expression, lazy_arrow_expression_with_function_body, lazy_function_declaration, lazy_function_expression, lazy_getter, lazy_method, lazy_setter, literal_big_int_expression, literal_reg_exp_expression, module, script, setter_contents, variable_declaration, variable_declarator };
+use binjs_generic::path::{ ASTField, ASTPath };
 use binjs_generic::syntax::{ASTError, MutASTVisitor, MutASTWalker, WalkPath };
 
-use source::parser::SourceParser;
+use source::daemon::DaemonPool;
+use source::parser::{ SourceParser, SourceType };
 
 #[derive(Debug)]
 pub enum Error {
@@ -22,15 +26,76 @@ pub enum Error {
     ExecutionError(std::io::Error),
     CouldNotCreateFile(std::io::Error),
     ReturnedError(ExitStatus),
-    JsonError(json::JsonError),
+    JsonError(serde_json::Error),
     InvalidPath(PathBuf),
     InvalidUTF8(std::string::FromUtf8Error),
     InvalidAST(ASTError),
+    /// A script run by a daemon threw this exception.
+    ScriptError(String),
+    /// An external parser rejected the source.
+    SyntaxError { message: String, line: Option<u64>, column: Option<u64> },
+    /// An external parser does not accept this goal, e.g. `module`.
+    UnsupportedGoal(String),
+    /// An external parser does not follow the protocol of `source::external`.
+    ProtocolError(String),
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        match *self {
+            Error::CouldNotLaunch(_) => write!(f, "could not launch parser"),
+            Error::CouldNotReadFile(_) => write!(f, "could not read file"),
+            Error::ExecutionError(_) => write!(f, "could not communicate with parser"),
+            Error::CouldNotCreateFile(_) => write!(f, "could not create file"),
+            Error::ReturnedError(ref status) => write!(f, "parser failed with {}", status),
+            Error::JsonError(_) => write!(f, "parser returned invalid JSON"),
+            Error::InvalidPath(ref path) => write!(f, "invalid path {}", path.display()),
+            Error::InvalidUTF8(_) => write!(f, "parser returned invalid UTF-8"),
+            Error::InvalidAST(_) => write!(f, "parser returned an invalid AST"),
+            Error::ScriptError(ref exception) => write!(f, "script threw {}", exception),
+            Error::SyntaxError { ref message, line: Some(line), column: Some(column) } =>
+                write!(f, "syntax error at {}:{}: {}", line, column, message),
+            Error::SyntaxError { ref message, .. } => write!(f, "syntax error: {}", message),
+            Error::UnsupportedGoal(ref goal) => write!(f, "unsupported goal {}", goal),
+            Error::ProtocolError(ref message) => write!(f, "protocol error: {}", message),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(std::error::Error + 'static)> {
+        match *self {
+            Error::CouldNotLaunch(ref err)
+            | Error::CouldNotReadFile(ref err)
+            | Error::ExecutionError(ref err)
+            | Error::CouldNotCreateFile(ref err) => Some(err),
+            Error::JsonError(ref err) => Some(err),
+            Error::InvalidUTF8(ref err) => Some(err),
+            Error::InvalidAST(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+/// The argument of Node specifying its memory limit, from `NODE_MAX_OLD_SPACE_SIZE`.
+pub fn node_memory() -> String {
+    match env::var("NODE_MAX_OLD_SPACE_SIZE") {
+        Err(_) => String::from("--max_old_space_size=2048"),
+        Ok(v) => format!("--max_old_space_size={}", v)
+    }
 }
 
 /// Using a Node + Shift binary to parse an AST.
 pub struct Shift {
-    bin_path: PathBuf
+    bin_path: PathBuf,
+
+    /// If `true`, annotate nodes with their source positions and the root with comments.
+    positions: bool,
+
+    /// If specified, run scripts in these long-lived Node processes, rather than
+    /// launching Node for each script.
+    daemons: Option<Arc<DaemonPool>>,
+
+    /// The goal symbol with which sources are parsed.
+    source_type: SourceType,
 }
 
 impl Shift {
@@ -40,7 +105,104 @@ impl Shift {
 
     pub fn with_path<P: AsRef<Path>>(bin_path: P) -> Self {
         Shift {
-            bin_path: bin_path.as_ref().to_path_buf()
+            bin_path: bin_path.as_ref().to_path_buf(),
+            positions: false,
+            daemons: None,
+            source_type: SourceType::Script,
+        }
+    }
+
+    /// Parse sources as scripts, modules, or either, `SourceType::Script` by default.
+    pub fn with_source_type(self, source_type: SourceType) -> Self {
+        Shift {
+            source_type,
+            ..self
+        }
+    }
+
+    /// If specified, run scripts in a pool of long-lived Node processes, which
+    /// may be shared with other parsers, rather than launching Node for each
+    /// script.
+    pub fn with_daemons(self, daemons: Option<Arc<DaemonPool>>) -> Self {
+        Shift {
+            daemons,
+            ..self
+        }
+    }
+
+    /// If `true`, annotate each node of the parsed AST with a field `loc`
+    /// containing its source positions, and the root with a field `comments`
+    /// containing the comments of the source.
+    ///
+    /// These fields are not part of the grammar. Use `source::positions::collect`
+    /// to remove them before encoding.
+    pub fn with_positions(self, positions: bool) -> Self {
+        Shift {
+            positions,
+            ..self
+        }
+    }
+
+    /// The part of a script parsing `source` (a JS expression) with `source_type`
+    /// and returning the AST as a JSON string.
+    fn parse_source_script(&self, source: &str, source_type: SourceType) -> String {
+        let (parse_script, parse_module) = if self.positions {
+            ("parseScriptWithLocation", "parseModuleWithLocation")
+        } else {
+            ("parseScript", "parseModule")
+        };
+        let parse = match source_type {
+            SourceType::Script => format!("shift.{}(source, {{ earlyErrors: false }})", parse_script),
+            SourceType::Module => format!("shift.{}(source, {{ earlyErrors: false }})", parse_module),
+            // Sources that contain `import` or `export` declarations cannot be parsed as scripts.
+            SourceType::Auto => format!(
+                r##"(function() {{
+                    try {{
+                        return shift.{script}(source, {{ earlyErrors: false }});
+                    }} catch (ex) {{
+                        try {{
+                            return shift.{module}(source, {{ earlyErrors: false }});
+                        }} catch (_) {{
+                            throw ex;
+                        }}
+                    }}
+                }})()"##,
+                script = parse_script,
+                module = parse_module),
+        };
+        if self.positions {
+            format!(
+                r##"
+                var shift = require('shift-parser');
+                var source = {source};
+                var parsed = {parse};
+                var annotate = function(node) {{
+                    if (node === null || typeof node !== "object") {{
+                        return;
+                    }}
+                    for (var key in node) {{
+                        annotate(node[key]);
+                    }}
+                    var loc = parsed.locations.get(node);
+                    if (loc) {{
+                        node.loc = {{ start: loc.start, end: loc.end }};
+                    }}
+                }};
+                annotate(parsed.tree);
+                parsed.tree.comments = parsed.comments;
+                return JSON.stringify(parsed.tree);
+                "##,
+                source = source,
+                parse = parse)
+        } else {
+            format!(
+                r##"
+                var shift = require('shift-parser');
+                var source = {source};
+                return JSON.stringify({parse});
+                "##,
+                source = source,
+                parse = parse)
         }
     }
 
@@ -57,15 +219,25 @@ impl Shift {
             console.warn(ex);
             /* rethrow */ throw ex;
         }};
-        var process = require('process');
-        /* See crates/binjs_io/src/escaped_wtf8.rs */
+        /* See crates/binjs_shared/src/escaped_wtf8.rs */
         result = result
             .replace(/[\u007F\uD800-\uDFFF]/ug, function(m) {{
                 if (m == "\u007F") {{
                     return "\u007F007F";
                 }}
-                return "\u007F" + m.charCodeAt(0).toString(16);
+                return "\u007F" + m.charCodeAt(0).toString(16).toUpperCase();
             }});
+        "##,
+        script);
+
+        if let Some(ref daemons) = self.daemons {
+            debug!(target: "Shift", "Sending script to daemon {}", script);
+            return daemons.run(&format!("{}\nreturn result;", script));
+        }
+
+        let script = format!(r##"
+        {}
+        var process = require('process');
         process.stdout.write(result);
         console.warn(result);
         "##,
@@ -73,13 +245,8 @@ impl Shift {
 
         debug!(target: "Shift", "Launching script {}", script);
 
-        let node_memory = match env::var("NODE_MAX_OLD_SPACE_SIZE") {
-            Err(_) => String::from("--max_old_space_size=2048"),
-            Ok(v) => format!("--max_old_space_size={}", v)
-        };
-
         let mut child = Command::new(&*self.bin_path)
-            .arg(node_memory)
+            .arg(node_memory())
             .env("NODE_PATH", "node_modules")
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
@@ -106,15 +273,19 @@ impl Shift {
         Ok(result)
     }
 
-    fn parse_script_json_output(&self, script: &str) -> Result<JSON, Error> {
+    /// Run a script with Node, parsing the value it returns as JSON.
+    ///
+    /// Used by other front-ends that depend on Node tooling.
+    pub fn parse_script_json_output(&self, script: &str) -> Result<JSON, Error> {
         let stdout = self.parse_script_output(script)?;
 
         // Now attempt to parse JSON
-        json::parse(&stdout)
+        serde_json::from_str(&stdout)
             .map_err(Error::JsonError)
     }
 
-    pub fn to_source(&self, syntax: &Spec, ast: &JSON) -> Result<String, Error> {
+    /// Convert a BinJS AST into a Shift AST.
+    pub fn to_shift_json(&self, syntax: &Spec, ast: &JSON) -> Result<JSON, Error> {
         let mut ast = ast.clone();
 
         debug!(target: "Shift", "Preparing source\n{:#}", ast);
@@ -122,6 +293,19 @@ impl Shift {
         walker.walk(&mut ast)
             .map_err(Error::InvalidAST)?;
         debug!(target: "Shift", "Prepared source\n{:#}", ast);
+        Ok(ast)
+    }
+
+    /// Convert a Shift AST, e.g. produced by an external tool, into a BinJS AST, in place.
+    pub fn convert_shift_json(&self, ast: &mut JSON) -> Result<(), Error> {
+        FromShift.convert(ast)
+            .map_err(Error::InvalidAST)
+    }
+
+    pub fn to_source(&self, syntax: &Spec, ast: &JSON) -> Result<String, Error> {
+        let mut ast = self.to_shift_json(syntax, ast)?;
+        print_big_int_literals(&mut ast)
+            .map_err(Error::InvalidAST)?;
 
 
         // Escape `"`.
@@ -134,8 +318,20 @@ impl Shift {
         let script = format!(
             r##"
             var codegen = require('shift-codegen').default;
-            var ast     = JSON.parse("{}");
-            return codegen(ast);
+            /* Restore lone surrogates, see crates/binjs_shared/src/escaped_wtf8.rs */
+            var ast     = JSON.parse("{}", function(key, value) {{
+                if (typeof value != "string") {{
+                    return value;
+                }}
+                return value.replace(/\u007F([0-9A-Fa-f]{{4}})/g, function(m, hex) {{
+                    return String.fromCharCode(parseInt(hex, 16));
+                }});
+            }});
+            /* Print lone surrogates and \u007F as escapes, as they may only appear in literals */
+            return codegen(ast)
+                .replace(/[\u007F\uD800-\uDFFF]/ug, function(m) {{
+                    return "\\u" + ("000" + m.charCodeAt(0).toString(16).toUpperCase()).slice(-4);
+                }});
             "##,
             data);
         self.parse_script_output(&script)
@@ -157,19 +353,10 @@ impl SourceParser for Shift {
             .replace("\n", "\\n");
 
         // A script to parse a string, write it to stdout as JSON.
-        let script = format!(
-            r##"
-            var parseScript = require('shift-parser').parseScript;
-            var data = "{}";
-
-            var parsed = parseScript(data, {{ earlyErrors: false }});
-
-            return JSON.stringify(parsed);
-            "##,
-            data);
+        let script = self.parse_source_script(&format!("\"{}\"", data), self.source_type);
 
         let mut ast = self.parse_script_json_output(&script)?;
-        FromShift.convert(&mut ast);
+        self.convert_shift_json(&mut ast)?;
         Ok(ast)
     }
 
@@ -179,82 +366,425 @@ impl SourceParser for Shift {
             .ok_or_else(||Error::InvalidPath(path.as_ref().to_path_buf()))?;
 
         // A script to parse a source file, write it to stdout as JSON.
-        let script = format!(
-            r##"
-            var parseScript = require('shift-parser').parseScript;
-            var fs      = require('fs');
-
-            var source  = fs.readFileSync({:?}, {{encoding: "utf-8"}});
-            return JSON.stringify(parseScript(source));
-            "##,
-            path);
+        let script = self.parse_source_script(
+            &format!("require('fs').readFileSync({:?}, {{encoding: \"utf-8\"}})", path),
+            self.source_type.for_path(Path::new(path)));
         let mut ast = self.parse_script_json_output(&script)?;
-        FromShift.convert(&mut ast);
+        self.convert_shift_json(&mut ast)?;
         Ok(ast)
     }
 }
 
-#[derive(PartialEq)]
-enum FunctionKind {
-    FunctionDeclaration,
-    Method,
-    FunctionExpression,
-    ArrowExpression,
-    ArrowExpressionWithFunctionBody,
-    ArrowExpressionWithExpression,
-    Getter,
-    Setter,
+/// The fields of the Shift AST that have no counterpart in the es6 grammar, in the style
+/// of `binjs_generic::es6::fields`.
+///
+/// Shift has no webidl grammar, so these fields are written by hand rather than generated.
+/// Shift nodes whose interface and field have the same names in the es6 grammar, e.g.
+/// `VariableDeclaration.declarators`, use the generated fields.
+mod shift_fields {
+    pub mod arrow_expression {
+        use binjs_generic::path::ASTField;
+        pub const IS_ASYNC: ASTField = ASTField { interface: "ArrowExpression", field: "isAsync" };
+        pub const PARAMS: ASTField = ASTField { interface: "ArrowExpression", field: "params" };
+        pub const BODY: ASTField = ASTField { interface: "ArrowExpression", field: "body" };
+    }
+
+    pub mod block_statement {
+        use binjs_generic::path::ASTField;
+        pub const BLOCK: ASTField = ASTField { interface: "BlockStatement", field: "block" };
+    }
+
+    pub mod function_body {
+        use binjs_generic::path::ASTField;
+        pub const DIRECTIVES: ASTField = ASTField { interface: "FunctionBody", field: "directives" };
+        pub const STATEMENTS: ASTField = ASTField { interface: "FunctionBody", field: "statements" };
+    }
+
+    pub mod function_declaration {
+        use binjs_generic::path::ASTField;
+        pub const IS_ASYNC: ASTField = ASTField { interface: "FunctionDeclaration", field: "isAsync" };
+        pub const PARAMS: ASTField = ASTField { interface: "FunctionDeclaration", field: "params" };
+        pub const BODY: ASTField = ASTField { interface: "FunctionDeclaration", field: "body" };
+    }
+
+    pub mod function_expression {
+        use binjs_generic::path::ASTField;
+        pub const IS_ASYNC: ASTField = ASTField { interface: "FunctionExpression", field: "isAsync" };
+        pub const PARAMS: ASTField = ASTField { interface: "FunctionExpression", field: "params" };
+        pub const BODY: ASTField = ASTField { interface: "FunctionExpression", field: "body" };
+    }
+
+    pub mod getter {
+        use binjs_generic::path::ASTField;
+        pub const BODY: ASTField = ASTField { interface: "Getter", field: "body" };
+    }
+
+    pub mod literal_reg_exp_expression {
+        use binjs_generic::path::ASTField;
+        pub const GLOBAL: ASTField = ASTField { interface: "LiteralRegExpExpression", field: "global" };
+        pub const IGNORE_CASE: ASTField = ASTField { interface: "LiteralRegExpExpression", field: "ignoreCase" };
+        pub const MULTI_LINE: ASTField = ASTField { interface: "LiteralRegExpExpression", field: "multiLine" };
+        pub const DOT_ALL: ASTField = ASTField { interface: "LiteralRegExpExpression", field: "dotAll" };
+        pub const UNICODE: ASTField = ASTField { interface: "LiteralRegExpExpression", field: "unicode" };
+        pub const STICKY: ASTField = ASTField { interface: "LiteralRegExpExpression", field: "sticky" };
+
+        /// Each flag, with its character in `RegExpFlags`, in canonical order.
+        pub const ALL_FLAGS: [(ASTField, char); 6] = [
+            (GLOBAL, 'g'),
+            (IGNORE_CASE, 'i'),
+            (MULTI_LINE, 'm'),
+            (DOT_ALL, 's'),
+            (UNICODE, 'u'),
+            (STICKY, 'y'),
+        ];
+    }
+
+    pub mod method {
+        use binjs_generic::path::ASTField;
+        pub const IS_ASYNC: ASTField = ASTField { interface: "Method", field: "isAsync" };
+        pub const PARAMS: ASTField = ASTField { interface: "Method", field: "params" };
+        pub const BODY: ASTField = ASTField { interface: "Method", field: "body" };
+    }
+
+    pub mod setter {
+        use binjs_generic::path::ASTField;
+        pub const PARAM: ASTField = ASTField { interface: "Setter", field: "param" };
+        pub const BODY: ASTField = ASTField { interface: "Setter", field: "body" };
+    }
+
+    pub mod variable_declaration_statement {
+        use binjs_generic::path::ASTField;
+        pub const DECLARATION: ASTField = ASTField { interface: "VariableDeclarationStatement", field: "declaration" };
+    }
+}
+
+/// The fields of a Shift function node.
+struct ShiftFunctionFields {
+    /// `isAsync`, if the node has it.
+    is_async: Option<ASTField>,
+
+    /// `params`, or `param` for setters, if the node has parameters.
+    params: Option<ASTField>,
+
+    /// `body`. The type of the node is `body.interface`.
+    body: ASTField,
+}
+
+/// The fields of the contents of a BinJS function node.
+struct ContentsFields {
+    is_this_captured: Option<ASTField>,
+    is_function_name_captured: Option<ASTField>,
+    parameter_scope: Option<ASTField>,
+
+    /// `params`, or `param` for setters, if the function has parameters.
+    params: Option<ASTField>,
+    body_scope: ASTField,
+
+    /// `body`. The type of the contents is `body.interface`.
+    body: ASTField,
+}
+
+/// The fields of a BinJS function node, which `FromShift` creates from a single
+/// Shift node, and `ToShift` merges back into one.
+struct FunctionFields {
+    shift: ShiftFunctionFields,
+    is_async: Option<ASTField>,
+    length: Option<ASTField>,
+
+    /// `directives`, unless the body of the function is an expression.
+    directives: Option<ASTField>,
+
+    /// `contents`. The type of the node is `contents.interface`.
+    contents: ASTField,
+    contents_skip: Option<ASTField>,
+    contents_fields: ContentsFields,
 }
 
+const SHIFT_FUNCTION_DECLARATION: ShiftFunctionFields = ShiftFunctionFields {
+    is_async: Some(shift_fields::function_declaration::IS_ASYNC),
+    params: Some(shift_fields::function_declaration::PARAMS),
+    body: shift_fields::function_declaration::BODY,
+};
+const SHIFT_FUNCTION_EXPRESSION: ShiftFunctionFields = ShiftFunctionFields {
+    is_async: Some(shift_fields::function_expression::IS_ASYNC),
+    params: Some(shift_fields::function_expression::PARAMS),
+    body: shift_fields::function_expression::BODY,
+};
+const SHIFT_METHOD: ShiftFunctionFields = ShiftFunctionFields {
+    is_async: Some(shift_fields::method::IS_ASYNC),
+    params: Some(shift_fields::method::PARAMS),
+    body: shift_fields::method::BODY,
+};
+const SHIFT_GETTER: ShiftFunctionFields = ShiftFunctionFields {
+    is_async: None,
+    params: None,
+    body: shift_fields::getter::BODY,
+};
+const SHIFT_SETTER: ShiftFunctionFields = ShiftFunctionFields {
+    is_async: None,
+    params: Some(shift_fields::setter::PARAM),
+    body: shift_fields::setter::BODY,
+};
+const SHIFT_ARROW_EXPRESSION: ShiftFunctionFields = ShiftFunctionFields {
+    is_async: Some(shift_fields::arrow_expression::IS_ASYNC),
+    params: Some(shift_fields::arrow_expression::PARAMS),
+    body: shift_fields::arrow_expression::BODY,
+};
+
+const FUNCTION_OR_METHOD_CONTENTS: ContentsFields = ContentsFields {
+    is_this_captured: Some(function_or_method_contents::IS_THIS_CAPTURED),
+    is_function_name_captured: None,
+    parameter_scope: Some(function_or_method_contents::PARAMETER_SCOPE),
+    params: Some(function_or_method_contents::PARAMS),
+    body_scope: function_or_method_contents::BODY_SCOPE,
+    body: function_or_method_contents::BODY,
+};
+const FUNCTION_EXPRESSION_CONTENTS: ContentsFields = ContentsFields {
+    is_this_captured: Some(function_expression_contents::IS_THIS_CAPTURED),
+    is_function_name_captured: Some(function_expression_contents::IS_FUNCTION_NAME_CAPTURED),
+    parameter_scope: Some(function_expression_contents::PARAMETER_SCOPE),
+    params: Some(function_expression_contents::PARAMS),
+    body_scope: function_expression_contents::BODY_SCOPE,
+    body: function_expression_contents::BODY,
+};
+const GETTER_CONTENTS: ContentsFields = ContentsFields {
+    is_this_captured: Some(getter_contents::IS_THIS_CAPTURED),
+    is_function_name_captured: None,
+    parameter_scope: None,
+    params: None,
+    body_scope: getter_contents::BODY_SCOPE,
+    body: getter_contents::BODY,
+};
+const SETTER_CONTENTS: ContentsFields = ContentsFields {
+    is_this_captured: Some(setter_contents::IS_THIS_CAPTURED),
+    is_function_name_captured: None,
+    parameter_scope: Some(setter_contents::PARAMETER_SCOPE),
+    params: Some(setter_contents::PARAM),
+    body_scope: setter_contents::BODY_SCOPE,
+    body: setter_contents::BODY,
+};
+const ARROW_EXPRESSION_CONTENTS_WITH_FUNCTION_BODY: ContentsFields = ContentsFields {
+    is_this_captured: None,
+    is_function_name_captured: None,
+    parameter_scope: Some(arrow_expression_contents_with_function_body::PARAMETER_SCOPE),
+    params: Some(arrow_expression_contents_with_function_body::PARAMS),
+    body_scope: arrow_expression_contents_with_function_body::BODY_SCOPE,
+    body: arrow_expression_contents_with_function_body::BODY,
+};
+const ARROW_EXPRESSION_CONTENTS_WITH_EXPRESSION: ContentsFields = ContentsFields {
+    is_this_captured: None,
+    is_function_name_captured: None,
+    parameter_scope: Some(arrow_expression_contents_with_expression::PARAMETER_SCOPE),
+    params: Some(arrow_expression_contents_with_expression::PARAMS),
+    body_scope: arrow_expression_contents_with_expression::BODY_SCOPE,
+    body: arrow_expression_contents_with_expression::BODY,
+};
+
+const EAGER_FUNCTION_DECLARATION: FunctionFields = FunctionFields {
+    shift: SHIFT_FUNCTION_DECLARATION,
+    is_async: Some(eager_function_declaration::IS_ASYNC),
+    length: Some(eager_function_declaration::LENGTH),
+    directives: Some(eager_function_declaration::DIRECTIVES),
+    contents: eager_function_declaration::CONTENTS,
+    contents_skip: None,
+    contents_fields: FUNCTION_OR_METHOD_CONTENTS,
+};
+const LAZY_FUNCTION_DECLARATION: FunctionFields = FunctionFields {
+    shift: SHIFT_FUNCTION_DECLARATION,
+    is_async: Some(lazy_function_declaration::IS_ASYNC),
+    length: Some(lazy_function_declaration::LENGTH),
+    directives: Some(lazy_function_declaration::DIRECTIVES),
+    contents: lazy_function_declaration::CONTENTS,
+    contents_skip: Some(lazy_function_declaration::CONTENTS_SKIP),
+    contents_fields: FUNCTION_OR_METHOD_CONTENTS,
+};
+const EAGER_FUNCTION_EXPRESSION: FunctionFields = FunctionFields {
+    shift: SHIFT_FUNCTION_EXPRESSION,
+    is_async: Some(eager_function_expression::IS_ASYNC),
+    length: Some(eager_function_expression::LENGTH),
+    directives: Some(eager_function_expression::DIRECTIVES),
+    contents: eager_function_expression::CONTENTS,
+    contents_skip: None,
+    contents_fields: FUNCTION_EXPRESSION_CONTENTS,
+};
+const LAZY_FUNCTION_EXPRESSION: FunctionFields = FunctionFields {
+    shift: SHIFT_FUNCTION_EXPRESSION,
+    is_async: Some(lazy_function_expression::IS_ASYNC),
+    length: Some(lazy_function_expression::LENGTH),
+    directives: Some(lazy_function_expression::DIRECTIVES),
+    contents: lazy_function_expression::CONTENTS,
+    contents_skip: Some(lazy_function_expression::CONTENTS_SKIP),
+    contents_fields: FUNCTION_EXPRESSION_CONTENTS,
+};
+const EAGER_METHOD: FunctionFields = FunctionFields {
+    shift: SHIFT_METHOD,
+    is_async: Some(eager_method::IS_ASYNC),
+    length: Some(eager_method::LENGTH),
+    directives: Some(eager_method::DIRECTIVES),
+    contents: eager_method::CONTENTS,
+    contents_skip: None,
+    contents_fields: FUNCTION_OR_METHOD_CONTENTS,
+};
+const LAZY_METHOD: FunctionFields = FunctionFields {
+    shift: SHIFT_METHOD,
+    is_async: Some(lazy_method::IS_ASYNC),
+    length: Some(lazy_method::LENGTH),
+    directives: Some(lazy_method::DIRECTIVES),
+    contents: lazy_method::CONTENTS,
+    contents_skip: Some(lazy_method::CONTENTS_SKIP),
+    contents_fields: FUNCTION_OR_METHOD_CONTENTS,
+};
+const EAGER_GETTER: FunctionFields = FunctionFields {
+    shift: SHIFT_GETTER,
+    is_async: None,
+    length: None,
+    directives: Some(eager_getter::DIRECTIVES),
+    contents: eager_getter::CONTENTS,
+    contents_skip: None,
+    contents_fields: GETTER_CONTENTS,
+};
+const LAZY_GETTER: FunctionFields = FunctionFields {
+    shift: SHIFT_GETTER,
+    is_async: None,
+    length: None,
+    directives: Some(lazy_getter::DIRECTIVES),
+    contents: lazy_getter::CONTENTS,
+    contents_skip: Some(lazy_getter::CONTENTS_SKIP),
+    contents_fields: GETTER_CONTENTS,
+};
+const EAGER_SETTER: FunctionFields = FunctionFields {
+    shift: SHIFT_SETTER,
+    is_async: None,
+    length: Some(eager_setter::LENGTH),
+    directives: Some(eager_setter::DIRECTIVES),
+    contents: eager_setter::CONTENTS,
+    contents_skip: None,
+    contents_fields: SETTER_CONTENTS,
+};
+const LAZY_SETTER: FunctionFields = FunctionFields {
+    shift: SHIFT_SETTER,
+    is_async: None,
+    length: Some(lazy_setter::LENGTH),
+    directives: Some(lazy_setter::DIRECTIVES),
+    contents: lazy_setter::CONTENTS,
+    contents_skip: Some(lazy_setter::CONTENTS_SKIP),
+    contents_fields: SETTER_CONTENTS,
+};
+const EAGER_ARROW_EXPRESSION_WITH_FUNCTION_BODY: FunctionFields = FunctionFields {
+    shift: SHIFT_ARROW_EXPRESSION,
+    is_async: Some(eager_arrow_expression_with_function_body::IS_ASYNC),
+    length: Some(eager_arrow_expression_with_function_body::LENGTH),
+    directives: Some(eager_arrow_expression_with_function_body::DIRECTIVES),
+    contents: eager_arrow_expression_with_function_body::CONTENTS,
+    contents_skip: None,
+    contents_fields: ARROW_EXPRESSION_CONTENTS_WITH_FUNCTION_BODY,
+};
+const LAZY_ARROW_EXPRESSION_WITH_FUNCTION_BODY: FunctionFields = FunctionFields {
+    shift: SHIFT_ARROW_EXPRESSION,
+    is_async: Some(lazy_arrow_expression_with_function_body::IS_ASYNC),
+    length: Some(lazy_arrow_expression_with_function_body::LENGTH),
+    directives: Some(lazy_arrow_expression_with_function_body::DIRECTIVES),
+    contents: lazy_arrow_expression_with_function_body::CONTENTS,
+    contents_skip: Some(lazy_arrow_expression_with_function_body::CONTENTS_SKIP),
+    contents_fields: ARROW_EXPRESSION_CONTENTS_WITH_FUNCTION_BODY,
+};
+const EAGER_ARROW_EXPRESSION_WITH_EXPRESSION: FunctionFields = FunctionFields {
+    shift: SHIFT_ARROW_EXPRESSION,
+    is_async: Some(eager_arrow_expression_with_expression::IS_ASYNC),
+    length: Some(eager_arrow_expression_with_expression::LENGTH),
+    directives: None,
+    contents: eager_arrow_expression_with_expression::CONTENTS,
+    contents_skip: None,
+    contents_fields: ARROW_EXPRESSION_CONTENTS_WITH_EXPRESSION,
+};
+const LAZY_ARROW_EXPRESSION_WITH_EXPRESSION: FunctionFields = FunctionFields {
+    shift: SHIFT_ARROW_EXPRESSION,
+    is_async: Some(lazy_arrow_expression_with_expression::IS_ASYNC),
+    length: Some(lazy_arrow_expression_with_expression::LENGTH),
+    directives: None,
+    contents: lazy_arrow_expression_with_expression::CONTENTS,
+    contents_skip: Some(lazy_arrow_expression_with_expression::CONTENTS_SKIP),
+    contents_fields: ARROW_EXPRESSION_CONTENTS_WITH_EXPRESSION,
+};
+
 struct ParameterScopeAndFunctionLength {
-    scope: json::JsonValue,
+    scope: JSON,
     length: usize
 }
 
+/// Shift does not support BigInt literals. Replace them with identifiers that
+/// print as the literal, e.g. `255n`.
+fn print_big_int_literals(value: &mut JSON) -> Result<(), ASTError> {
+    if value["type"] == "LiteralBigIntExpression" {
+        let name = format!("{}n", literal_big_int_expression::VALUE.take(value)?.as_str().unwrap_or("0"));
+        value["type"] = JSON::from("IdentifierExpression");
+        return identifier_expression::NAME.set(value, JSON::from(name));
+    }
+    match *value {
+        JSON::Array(ref mut array) => {
+            for value in array {
+                print_big_int_literals(value)?;
+            }
+        }
+        JSON::Object(ref mut object) => {
+            for (_, value) in object.iter_mut() {
+                print_big_int_literals(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
 /// A data structure designed to convert from Shift AST to BinJS AST.
 struct FromShift;
 impl FromShift {
-    fn convert(&self, value: &mut JSON) {
-        use json::JsonValue::*;
+    fn convert(&self, value: &mut JSON) -> Result<(), ASTError> {
         match *value {
-            Array(ref mut array) => {
+            JSON::Array(ref mut array) => {
                 for value in array {
-                    self.convert(value);
+                    self.convert(value)?;
                 }
+                return Ok(());
             }
-            Object(ref mut object) => {
+            JSON::Object(ref mut object) => {
                 for (_, value) in object.iter_mut() {
-                    self.convert(value);
+                    self.convert(value)?;
                 }
-                self.convert_object(object)
             }
-            _ => {}
+            _ => return Ok(())
         }
+        self.convert_object(value)
     }
 
-    fn make_eager(&self, object: &mut json::object::Object) {
-        let kind = format!("Eager{}", object["type"].as_str().unwrap());
-        object.insert("type", json::from(kind));
-    }
-
-    fn dummy_declared_scope(&self, name: &str) -> json::JsonValue {
-        let mut scope = Object::new();
-        scope["type"] = json::from(name);
-        scope["declaredNames"] = array![];
-        scope["hasDirectEval"] = JSON::Boolean(false);
-        JSON::Object(scope)
+    fn dummy_declared_scope(&self, name: &str) -> JSON {
+        object!{
+            "type" => name,
+            "declaredNames" => array![],
+            "hasDirectEval" => false
+        }
     }
 
-    fn parameter_scope_and_length<'a, I>(&self, params: I) -> ParameterScopeAndFunctionLength
-    where I: IntoIterator<Item=&'a json::JsonValue> {
-        let mut scope = Object::new();
+    /// Compute the scope and length of `params`, i.e. `FormalParameters`, or the single
+    /// parameter of a setter.
+    fn parameter_scope_and_length(&self, params: &JSON) -> Result<ParameterScopeAndFunctionLength, ASTError> {
+        let mut scope = object!{
+            "type" => "AssertedParameterScope",
+            "paramNames" => array![],
+            "hasDirectEval" => false
+        };
         let mut length = 0;
-        scope["type"] = json::from("AssertedParameterScope");
-        scope["paramNames"] = array![];
-        scope["hasDirectEval"] = JSON::Boolean(false);
 
+        let items : Vec<&JSON> =
+            if params["type"] == "FormalParameters" {
+                formal_parameters::ITEMS.get(params)?
+                    .members()
+                    .collect()
+            } else {
+                vec![params]
+            };
         let mut is_simple_parameter_list = true;
-        for item in params {
+        for item in items {
             match item["type"].as_str() {
                 Some("BindingIdentifier") => {
                     length += 1;
@@ -271,110 +801,91 @@ impl FromShift {
             }
         }
 
-        scope["isSimpleParameterList"] = JSON::Boolean(is_simple_parameter_list);
+        asserted_parameter_scope::IS_SIMPLE_PARAMETER_LIST.set(&mut scope, JSON::Bool(is_simple_parameter_list))?;
 
-        ParameterScopeAndFunctionLength {
-            scope: JSON::Object(scope),
+        Ok(ParameterScopeAndFunctionLength {
+            scope,
             length
-        }
+        })
     }
 
-    fn dummy_bound_names_scope(&self) -> json::JsonValue {
-        let mut scope = Object::new();
-        scope["type"] = json::from("AssertedBoundNamesScope");
-        scope["boundNames"] = array![];
-        scope["hasDirectEval"] = JSON::Boolean(true);
-        JSON::Object(scope)
-    }
-
-    fn create_function_contents(&self, object: &mut json::object::Object, kind: FunctionKind) {
-        assert!(kind != FunctionKind::ArrowExpressionWithFunctionBody);
-        assert!(kind != FunctionKind::ArrowExpressionWithExpression);
-        if kind != FunctionKind::Getter && kind != FunctionKind::Setter {
-            // `isAsync` is not supported by the parser yet.
-            if let None = object.get("isAsync") {
-                object["isAsync"] = JSON::Boolean(false)
-            }
+    fn dummy_bound_names_scope(&self) -> JSON {
+        object!{
+            "type" => "AssertedBoundNamesScope",
+            "boundNames" => array![],
+            "hasDirectEval" => true
         }
+    }
 
-        let directives = if object["body"].has_key("directives") {
-            object["body"].remove("directives")
-        } else {
-            json::JsonValue::new_array()
+    /// Rewrite a Shift function node `value` into the eager BinJS node described by `fields`,
+    /// moving its parameters and body into its contents.
+    fn create_function_contents(&self, value: &mut JSON, fields: &FunctionFields) -> Result<(), ASTError> {
+        // `isAsync` is not supported by the parser yet.
+        let is_async = match fields.shift.is_async {
+            Some(field) => field.remove(value)?
+                .and_then(|is_async| is_async.as_bool())
+                .unwrap_or(false),
+            None => false
+        };
+        let params = match fields.shift.params {
+            Some(field) => Some(field.take(value)?),
+            None => None
         };
+        let mut body = fields.shift.body.take(value)?;
+        let directives =
+            if body["type"] == "FunctionBody" {
+                let directives = shift_fields::function_body::DIRECTIVES.take(&mut body)?;
+                body = shift_fields::function_body::STATEMENTS.take(&mut body)?;
+                directives
+            } else {
+                array![]
+            };
 
-        let is_expression_body;
-        let mut body = object.remove("body").unwrap();
-        match body["type"].as_str() {
-            Some("FunctionBody") => {
-                body = body.remove("statements");
-                is_expression_body = false;
-            }
-            _ => {
-                is_expression_body = true;
-            }
+        value["type"] = JSON::from(fields.contents.interface);
+        if let Some(field) = fields.is_async {
+            field.set(value, JSON::Bool(is_async))?;
         }
 
-        let mut contents = Object::new();
-        match kind {
-            FunctionKind::FunctionDeclaration | FunctionKind::Method => {
-                contents["type"] = json::from("FunctionOrMethodContents");
-                contents["isThisCaptured"] = JSON::Boolean(false);
-            }
-            FunctionKind::FunctionExpression => {
-                contents["type"] = json::from("FunctionExpressionContents");
-                contents["isFunctionNameCaptured"] = JSON::Boolean(false);
-                contents["isThisCaptured"] = JSON::Boolean(false);
-            }
-            FunctionKind::ArrowExpression => {
-                if is_expression_body {
-                    object["type"] = json::from("ArrowExpressionWithExpression");
-                    contents["type"] = json::from("ArrowExpressionContentsWithExpression");
-                } else {
-                    object["type"] = json::from("ArrowExpressionWithFunctionBody");
-                    contents["type"] = json::from("ArrowExpressionContentsWithFunctionBody");
-                }
-            }
-            FunctionKind::Getter => {
-                contents["type"] = json::from("GetterContents");
-                contents["isThisCaptured"] = JSON::Boolean(false);
-            }
-            FunctionKind::Setter => {
-                contents["type"] = json::from("SetterContents");
-                contents["isThisCaptured"] = JSON::Boolean(false);
+        let contents_fields = &fields.contents_fields;
+        let mut contents = object!{
+            "type" => contents_fields.body.interface
+        };
+        if let Some(field) = contents_fields.is_function_name_captured {
+            field.set(&mut contents, JSON::Bool(false))?;
+        }
+        if let Some(field) = contents_fields.is_this_captured {
+            field.set(&mut contents, JSON::Bool(false))?;
+        }
+        if let (Some(field), Some(params)) = (contents_fields.params, params) {
+            let scope_and_length = self.parameter_scope_and_length(&params)?;
+            if let Some(scope_field) = contents_fields.parameter_scope {
+                scope_field.set(&mut contents, scope_and_length.scope)?;
             }
-            _ => {
-                panic!("unexpected FunctionKind");
+            if let Some(length_field) = fields.length {
+                length_field.set(value, JSON::from(scope_and_length.length))?;
             }
+            field.set(&mut contents, params)?;
         }
-        if kind != FunctionKind::Getter {
-            if kind == FunctionKind::Setter {
-                contents["param"] = object.remove("param").unwrap();
-                let scope_and_length = self.parameter_scope_and_length(vec![&contents["param"]]);
-                contents["parameterScope"] = scope_and_length.scope;
-                object["length"] = json::from(scope_and_length.length);
-            } else {
-                contents["params"] = object.remove("params").unwrap();
-                assert!(contents["params"]["items"].is_array());
-                let scope_and_length = self.parameter_scope_and_length(contents["params"]["items"].members());
-                contents["parameterScope"] = scope_and_length.scope;
-                object["length"] = json::from(scope_and_length.length);
-            }
+        contents_fields.body_scope.set(&mut contents, self.dummy_declared_scope("AssertedVarScope"))?;
+        contents_fields.body.set(&mut contents, body)?;
+        fields.contents.set(value, contents)?;
+        if let Some(field) = fields.directives {
+            field.set(value, directives)?;
         }
-        contents["bodyScope"] = self.dummy_declared_scope("AssertedVarScope");
-        contents["body"] = body;
-        object.insert("contents", JSON::Object(contents));
-        object.insert("directives", directives);
-        self.make_eager(object);
+        Ok(())
     }
 
-    fn convert_object(&self, object: &mut json::object::Object) {
-        // By alphabetical order
-        match object["type"].as_str() {
-            Some("Block") => {
-                object.insert("scope", self.dummy_declared_scope("AssertedBlockScope"));
+    fn convert_object(&self, value: &mut JSON) -> Result<(), ASTError> {
+        // By alphabetical order. Objects without a type, e.g. source positions, are left untouched.
+        let kind = match value["type"].as_str() {
+            Some(kind) => kind.to_string(),
+            None => return Ok(())
+        };
+        match &kind as &str {
+            "Block" => {
+                block::SCOPE.set(value, self.dummy_declared_scope("AssertedBlockScope"))?;
             }
-            Some("BlockStatement") => {
+            "BlockStatement" => {
                 // Rewrite
                 //
                 // BlockStatement {
@@ -388,77 +899,80 @@ impl FromShift {
                 // Block {
                 //    ...foo
                 // }
-                let mut remove = match object.remove("block") {
-                    Some(JSON::Object(remove)) => remove,
-                    _ => panic!("No field `block` in a `BlockStatement`")
-                };
-                std::mem::swap(object, &mut remove);
-                // At this stage
-                // - `remove` is the `BlockStatement`
-                // - `object` is the `Block`
+                *value = shift_fields::block_statement::BLOCK.take(value)?;
             }
-            Some("ForInStatement") | Some("ForOfStatement") => {
+            "ForInStatement" | "ForOfStatement" => {
                 // In Shift, `left` is a `VariableDeclaration or AssignmentTarget`.
                 // In BinJS, `left` is a `ForInOfBinding or AssignmentTarget`.
-                if let Some("VariableDeclaration") = object["left"]["type"].as_str() {
-                    object["left"]["type"] = json::from("ForInOfBinding");
-                    let binding = object["left"]["declarators"][0].remove("binding");
-                    object["left"]["binding"] = binding;
-                    object["left"].remove("declarators");
+                let left =
+                    if kind == "ForInStatement" {
+                        for_in_statement::LEFT.get_mut(value)?
+                    } else {
+                        for_of_statement::LEFT.get_mut(value)?
+                    };
+                if left["type"] == "VariableDeclaration" {
+                    let binding = ASTPath::new()
+                        .with_field(variable_declaration::DECLARATORS)
+                        .with_index(0)
+                        .with_field(variable_declarator::BINDING)
+                        .get_mut(left)?
+                        .take();
+                    variable_declaration::DECLARATORS.take(left)?;
+                    left["type"] = JSON::from("ForInOfBinding");
+                    for_in_of_binding::BINDING.set(left, binding)?;
                 }
             }
-            Some("FunctionDeclaration") => {
-                self.create_function_contents(object, FunctionKind::FunctionDeclaration);
+            "FunctionDeclaration" => {
+                self.create_function_contents(value, &EAGER_FUNCTION_DECLARATION)?;
             }
-            Some("Method") => {
-                self.create_function_contents(object, FunctionKind::Method);
+            "Method" => {
+                self.create_function_contents(value, &EAGER_METHOD)?;
             }
-            Some("FunctionExpression") => {
-                self.create_function_contents(object, FunctionKind::FunctionExpression);
+            "FunctionExpression" => {
+                self.create_function_contents(value, &EAGER_FUNCTION_EXPRESSION)?;
             }
-            Some("ArrowExpression") => {
-                self.create_function_contents(object, FunctionKind::ArrowExpression);
+            "ArrowExpression" => {
+                if shift_fields::arrow_expression::BODY.get(value)?["type"] == "FunctionBody" {
+                    self.create_function_contents(value, &EAGER_ARROW_EXPRESSION_WITH_FUNCTION_BODY)?;
+                } else {
+                    self.create_function_contents(value, &EAGER_ARROW_EXPRESSION_WITH_EXPRESSION)?;
+                }
             }
-            Some("Getter") => {
-                self.create_function_contents(object, FunctionKind::Getter);
+            "Getter" => {
+                self.create_function_contents(value, &EAGER_GETTER)?;
             }
-            Some("Setter") => {
-                self.create_function_contents(object, FunctionKind::Setter);
+            "Setter" => {
+                self.create_function_contents(value, &EAGER_SETTER)?;
             }
-            Some("LabeledStatement") => {
+            "LabeledStatement" => {
                 // Rewrite type
-                object["type"] = json::from("LabelledStatement");
+                value["type"] = JSON::from("LabelledStatement");
             }
-            Some("LiteralRegExpExpression") => {
+            "LiteralRegExpExpression" => {
+                // Flags in canonical order, see `RegExpFlags::ALL`. Older versions of Shift
+                // do not have all the flags.
                 let mut flags = String::new();
-                if let JSON::Boolean(true) = object["global"] {
-                    flags.push('g');
-                }
-                if let JSON::Boolean(true) = object["ignoreCase"] {
-                    flags.push('i');
-                }
-                if let JSON::Boolean(true) = object["multiLine"] {
-                    flags.push('m');
-                }
-                if let JSON::Boolean(true) = object["sticky"] {
-                    flags.push('y');
-                }
-                if let JSON::Boolean(true) = object["unicode"] {
-                    flags.push('u');
+                for &(field, flag) in &shift_fields::literal_reg_exp_expression::ALL_FLAGS {
+                    if let Some(JSON::Bool(true)) = field.remove(value)? {
+                        flags.push(flag);
+                    }
                 }
-                object.insert("flags", json::from(flags));
+                literal_reg_exp_expression::FLAGS.set(value, JSON::from(flags))?;
             }
-            Some("Script") => {
-                object.insert("scope", self.dummy_declared_scope("AssertedScriptGlobalScope"));
+            "Script" => {
+                script::SCOPE.set(value, self.dummy_declared_scope("AssertedScriptGlobalScope"))?;
             }
-            Some("CatchClause") => {
-                object.insert("bindingScope", self.dummy_bound_names_scope());
+            "Module" => {
+                module::SCOPE.set(value, self.dummy_declared_scope("AssertedVarScope"))?;
             }
-            Some("StaticPropertyName") => {
+            "CatchClause" => {
+                catch_clause::BINDING_SCOPE.set(value, self.dummy_bound_names_scope())?;
+            }
+            "StaticPropertyName" => {
                 // Change type.
-                object["type"] = json::from("LiteralPropertyName");
+                value["type"] = JSON::from("LiteralPropertyName");
             }
-            Some("VariableDeclarationStatement") => {
+            "VariableDeclarationStatement" => {
                 // Rewrite
                 //
                 // VariableDeclarationStatement {
@@ -472,20 +986,14 @@ impl FromShift {
                 // VariableDeclaration {
                 //    ...foo
                 // }
-                let mut remove = match object.remove("declaration") {
-                    Some(JSON::Object(remove)) => remove,
-                    _ => panic!("No field `declaration` in a `VariableDeclarationStatement`")
-                };
-                std::mem::swap(object, &mut remove);
-                // At this stage
-                // - `remove` is the `VariableDeclarationStatement`
-                // - `object` is the `VariableDeclaration`
+                *value = shift_fields::variable_declaration_statement::DECLARATION.take(value)?;
             }
-            Some("IdentifierExpression") => {
-                debug!(target: "Shift", "FromShift IdentifierExpression {:?}", object);
+            "IdentifierExpression" => {
+                debug!(target: "Shift", "FromShift IdentifierExpression {:?}", value);
             }
             _ => { /* No change */ }
         }
+        Ok(())
     }
 }
 
@@ -493,61 +1001,64 @@ impl FromShift {
 
 struct ToShift;
 impl ToShift {
-    fn remove_eager_or_lazy(&self, obj: &mut json::object::Object) {
-        let kind = { obj["type"].as_str().unwrap().to_string()  };
-        const EAGER : &'static str = "Eager";
-        const LAZY : &'static str = "Lazy";
-
-        if kind.starts_with(EAGER) {
-            obj["type"] = json::from(&kind[EAGER.len()..])
-        } else if kind.starts_with(LAZY) {
-            obj["type"] = json::from(&kind[LAZY.len()..])
-        } else {
-            panic!()
+    /// Rewrite a BinJS function node `value`, described by `fields`, into a Shift node,
+    /// moving the parameters and body of its contents back into it.
+    fn remove_function_contents(&self, value: &mut JSON, fields: &FunctionFields) -> Result<(), ASTError> {
+        // Remove unused fields.
+        if let Some(field) = fields.length {
+            field.remove(value)?;
         }
-    }
-    fn remove_with_suffix(&self, obj: &mut json::object::Object) {
-        let kind = { obj["type"].as_str().unwrap().to_string()  };
-        const WITHFUNCTIONBODY : &'static str = "WithFunctionBody";
-        const WITHEXPRESSION : &'static str = "WithExpression";
-
-        if kind.ends_with(WITHFUNCTIONBODY) {
-            obj["type"] = json::from(&kind[..(kind.len() - WITHFUNCTIONBODY.len())])
-        } else if kind.ends_with(WITHEXPRESSION) {
-            obj["type"] = json::from(&kind[..(kind.len() - WITHEXPRESSION.len())])
+        if let Some(field) = fields.contents_skip {
+            field.remove(value)?;
         }
-    }
-    fn remove_function_contents(&self, obj: &mut json::object::Object, kind: FunctionKind) {
-        self.remove_eager_or_lazy(obj);
-        self.remove_with_suffix(obj);
-
-        // Remove unused fields.
-        obj.remove("isAsync");
-        obj.remove("scope");
-        obj.remove("contents_skip");
-        obj.remove("length");
+        let is_async = match fields.is_async {
+            Some(field) => field.remove(value)?,
+            None => None
+        };
+        let directives = match fields.directives {
+            Some(field) => Some(field.take(value)?),
+            None => None
+        };
 
         // Move some fields back from *Contents.
-        let mut contents = obj.remove("contents").unwrap();
-        obj["params"] = contents.remove("params");
+        let contents_fields = &fields.contents_fields;
+        let mut contents = fields.contents.take(value)?;
+        let params = match contents_fields.params {
+            Some(field) => Some(field.take(&mut contents)?),
+            None => None
+        };
+        let body = contents_fields.body.take(&mut contents)?;
 
-        if kind == FunctionKind::ArrowExpressionWithExpression {
-            obj["body"] = contents.remove("body");
-        } else {
-            let mut body = Object::new();
-            body["type"] = json::from("FunctionBody");
-            body["directives"] = obj.remove("directives").unwrap();
-            body["statements"] = contents.remove("body");
-            obj["body"] = JSON::Object(body);
+        value["type"] = JSON::from(fields.shift.body.interface);
+        if let (Some(field), Some(is_async)) = (fields.shift.is_async, is_async) {
+            field.set(value, is_async)?;
+        }
+        if let (Some(field), Some(params)) = (fields.shift.params, params) {
+            field.set(value, params)?;
         }
+        let body = match directives {
+            // The body is an expression.
+            None => body,
+            Some(directives) => {
+                let mut function_body = object!{
+                    "type" => "FunctionBody"
+                };
+                shift_fields::function_body::DIRECTIVES.set(&mut function_body, directives)?;
+                shift_fields::function_body::STATEMENTS.set(&mut function_body, body)?;
+                function_body
+            }
+        };
+        fields.shift.body.set(value, body)
     }
 }
 impl MutASTVisitor for ToShift {
     fn exit_interface(&mut self, _path: &WalkPath, value: &mut JSON, interface: &Interface, name: &NodeName) -> Result<(), ASTError> {
         debug!(target: "Shift", "Should I rewrite {:?} at {:?}", interface.name(), name);
-        match (name.to_str(), interface.name().to_str(), value) {
-            ("Statement", "Block", &mut JSON::Object(ref mut object)) => {
-                debug!(target: "Shift", "Yes I should: from {}", object.dump());
+        match (name.to_str(), interface.name().to_str()) {
+            // The items of a `Module` are walked with the name of the module.
+            ("Statement", "Block")
+            | ("Module", "Block") => {
+                debug!(target: "Shift", "Yes I should: from {:?}", value);
                 // Rewrite
                 //
                 // Block { // Used as Statement
@@ -561,16 +1072,14 @@ impl MutASTVisitor for ToShift {
                 //       ...foo
                 //    }
                 // }
-                let mut insert = Object::new();
-                insert["type"] = json::from("BlockStatement");
-                std::mem::swap(&mut insert, object);
-                // From here,
-                // - `object` is `BlockStatement`.
-                // - `insert` is `Block`.
-                object["block"] = JSON::Object(insert);
-                debug!(target: "Shift", "into {}", object.dump());
-            }
-            ("Statement", "VariableDeclaration", &mut JSON::Object(ref mut object)) => {
+                let block = std::mem::replace(value, object!{
+                    "type" => "BlockStatement"
+                });
+                shift_fields::block_statement::BLOCK.set(value, block)?;
+                debug!(target: "Shift", "into {:?}", value);
+            }
+            ("Statement", "VariableDeclaration")
+            | ("Module", "VariableDeclaration") => {
                 // Rewrite
                 //
                 // VariableDeclaration { // Used as Statement
@@ -584,55 +1093,41 @@ impl MutASTVisitor for ToShift {
                 //       ...foo
                 //    }
                 // }
-                let mut insert = Object::new();
-                insert["type"] = json::from("VariableDeclarationStatement");
-                std::mem::swap(&mut insert, object);
-                // From here,
-                // - `object` is `VariableDeclarationStatement`.
-                // - `insert` is `VariableDeclaration`.
-                object["declaration"] = JSON::Object(insert);
-            }
-            (_, "Script", &mut JSON::Object(ref mut object)) => {
+                let declaration = std::mem::replace(value, object!{
+                    "type" => "VariableDeclarationStatement"
+                });
+                shift_fields::variable_declaration_statement::DECLARATION.set(value, declaration)?;
+            }
+            (_, "Script") => {
                 // Remove unused field.
-                object.remove("scope");
+                script::SCOPE.take(value)?;
             }
-            (_, "CatchClause", &mut JSON::Object(ref mut object)) => {
+            (_, "Module") => {
                 // Remove unused field.
-                object.remove("bindingScope");
+                module::SCOPE.take(value)?;
             }
-            (_, "LabelledStatement", &mut JSON::Object(ref mut object)) => {
+            (_, "CatchClause") => {
+                // Remove unused field.
+                catch_clause::BINDING_SCOPE.take(value)?;
+            }
+            (_, "LabelledStatement") => {
                 // Change type.
-                object["type"] = json::from("LabeledStatement");
+                value["type"] = JSON::from("LabeledStatement");
             }
-            (_, "LiteralPropertyName", &mut JSON::Object(ref mut object)) => {
+            (_, "LiteralPropertyName") => {
                 // Change type.
-                object["type"] = json::from("StaticPropertyName");
-            }
-            (_, "LiteralRegExpExpression", &mut JSON::Object(ref mut object)) => {
-                let mut global = false;
-                let mut ignore_case = false;
-                let mut multi_line = false;
-                let mut sticky = false;
-                let mut unicode = false;
-                if let Some(flags) = object["flags"].as_str() {
-                    for c in flags.chars() {
-                        match c {
-                            'g' => { global = true; }
-                            'i' => { ignore_case = true; }
-                            'm' => { multi_line = true; }
-                            'y' => { sticky = true; }
-                            'u' => { unicode = true; }
-                            _ => { /* ignore */ }
-                        }
-                    }
+                value["type"] = JSON::from("StaticPropertyName");
+            }
+            (_, "LiteralRegExpExpression") => {
+                let flags = literal_reg_exp_expression::FLAGS.get(value)?
+                    .as_str()
+                    .unwrap_or("")
+                    .to_string();
+                for &(field, flag) in &shift_fields::literal_reg_exp_expression::ALL_FLAGS {
+                    field.set(value, JSON::from(flags.contains(flag)))?;
                 }
-                object.insert("global", json::from(global));
-                object.insert("ignoreCase", json::from(ignore_case));
-                object.insert("multiLine", json::from(multi_line));
-                object.insert("sticky", json::from(sticky));
-                object.insert("unicode", json::from(unicode));
             }
-            (_, "ForInOfBinding", &mut JSON::Object(ref mut object)) => {
+            (_, "ForInOfBinding") => {
                 // Rewrite
                 //
                 // ForInOfBinding {
@@ -651,46 +1146,59 @@ impl MutASTVisitor for ToShift {
                 //      }
                 //    }]
                 // }
-                object["type"] = json::from("VariableDeclaration");
-                let binding = object.remove("binding");
-                object["declarators"] = array![
-                    object!{
-                        "type" => "VariableDeclarator",
-                        "init" => json::Null,
-                        "binding" => binding
-                    }
-                ];
+                let binding = for_in_of_binding::BINDING.take(value)?;
+                let mut declarator = object!{
+                    "type" => "VariableDeclarator"
+                };
+                variable_declarator::INIT.set(&mut declarator, JSON::Null)?;
+                variable_declarator::BINDING.set(&mut declarator, binding)?;
+                value["type"] = JSON::from("VariableDeclaration");
+                variable_declaration::DECLARATORS.set(value, array![declarator])?;
+            }
+            (_, "EagerFunctionExpression") => {
+                self.remove_function_contents(value, &EAGER_FUNCTION_EXPRESSION)?;
+            }
+            (_, "LazyFunctionExpression") => {
+                self.remove_function_contents(value, &LAZY_FUNCTION_EXPRESSION)?;
             }
-            (_, "EagerFunctionExpression", &mut JSON::Object(ref mut object))
-            | (_, "LazyFunctionExpression", &mut JSON::Object(ref mut object))=> {
-                self.remove_function_contents(object, FunctionKind::FunctionExpression);
+            (_, "EagerFunctionDeclaration") => {
+                self.remove_function_contents(value, &EAGER_FUNCTION_DECLARATION)?;
             }
-            (_, "EagerFunctionDeclaration", &mut JSON::Object(ref mut object))
-            | (_, "LazyFunctionDeclaration", &mut JSON::Object(ref mut object)) => {
-                self.remove_function_contents(object, FunctionKind::FunctionDeclaration);
+            (_, "LazyFunctionDeclaration") => {
+                self.remove_function_contents(value, &LAZY_FUNCTION_DECLARATION)?;
             }
-            (_, "EagerMethod", &mut JSON::Object(ref mut object))
-            | (_, "LazyMethod", &mut JSON::Object(ref mut object)) => {
-                self.remove_function_contents(object, FunctionKind::Method);
+            (_, "EagerMethod") => {
+                self.remove_function_contents(value, &EAGER_METHOD)?;
             }
-            (_, "EagerGetter", &mut JSON::Object(ref mut object))
-            | (_, "LazyGetter", &mut JSON::Object(ref mut object)) => {
-                self.remove_function_contents(object, FunctionKind::Getter);
+            (_, "LazyMethod") => {
+                self.remove_function_contents(value, &LAZY_METHOD)?;
             }
-            (_, "EagerSetter", &mut JSON::Object(ref mut object))
-            | (_, "LazySetter", &mut JSON::Object(ref mut object)) => {
-                self.remove_function_contents(object, FunctionKind::Setter);
+            (_, "EagerGetter") => {
+                self.remove_function_contents(value, &EAGER_GETTER)?;
             }
-            (_, "EagerArrowExpressionWithFunctionBody", &mut JSON::Object(ref mut object))
-            | (_, "LazyArrowExpressionWithFunctionBody", &mut JSON::Object(ref mut object)) => {
-                self.remove_function_contents(object, FunctionKind::ArrowExpressionWithFunctionBody);
+            (_, "LazyGetter") => {
+                self.remove_function_contents(value, &LAZY_GETTER)?;
             }
-            (_, "EagerArrowExpressionWithExpression", &mut JSON::Object(ref mut object))
-            | (_, "LazyArrowExpressionWithExpression", &mut JSON::Object(ref mut object)) => {
-                self.remove_function_contents(object, FunctionKind::ArrowExpressionWithExpression);
+            (_, "EagerSetter") => {
+                self.remove_function_contents(value, &EAGER_SETTER)?;
             }
-            | (_, "IdentifierExpression", &mut JSON::Object(ref mut object)) => {
-                debug!(target: "Shift", "IdentifierExpression {:?}", object);
+            (_, "LazySetter") => {
+                self.remove_function_contents(value, &LAZY_SETTER)?;
+            }
+            (_, "EagerArrowExpressionWithFunctionBody") => {
+                self.remove_function_contents(value, &EAGER_ARROW_EXPRESSION_WITH_FUNCTION_BODY)?;
+            }
+            (_, "LazyArrowExpressionWithFunctionBody") => {
+                self.remove_function_contents(value, &LAZY_ARROW_EXPRESSION_WITH_FUNCTION_BODY)?;
+            }
+            (_, "EagerArrowExpressionWithExpression") => {
+                self.remove_function_contents(value, &EAGER_ARROW_EXPRESSION_WITH_EXPRESSION)?;
+            }
+            (_, "LazyArrowExpressionWithExpression") => {
+                self.remove_function_contents(value, &LAZY_ARROW_EXPRESSION_WITH_EXPRESSION)?;
+            }
+            (_, "IdentifierExpression") => {
+                debug!(target: "Shift", "IdentifierExpression {:?}", value);
                 // FIXME: We probably need to rewrite the IdentifierDefinition.
             }
             _ => {
@@ -740,7 +1248,7 @@ fn test_shift_basic() {
                     "params" => object!{
                         "type" => "FormalParameters",
                         "items" => array![],
-                        "rest" => json::Null,
+                        "rest" => JSON::Null,
                     },
                     "bodyScope" => object!{
                         "type" => "AssertedVarScope",
@@ -761,3 +1269,118 @@ fn test_shift_basic() {
 
     assert_eq!(parsed, expected);
 }
+
+#[test]
+fn test_shift_lone_surrogates() {
+    use binjs_generic::es6::Library;
+    use binjs_meta::spec::{ SpecBuilder, SpecOptions };
+
+    let shift = Shift::new();
+    let parsed = shift.parse_str("\"a\\uD800b\\u007F\";")
+        .expect("Error in parse_str");
+    assert_eq!(parsed["statements"][0]["expression"]["value"].as_str(), Some("a\x7FD800b\x7F007F"));
+
+    let mut builder = SpecBuilder::new();
+    let _ = Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Script"),
+    };
+    let spec = builder.into_spec(spec_options);
+    let source = shift.to_source(&spec, &parsed)
+        .expect("Error in to_source");
+    assert!(source.contains("a\\uD800b\\u007F"), "Unexpected source {}", source);
+}
+
+#[test]
+fn test_shift_big_int() {
+    use binjs_generic::es6::Library;
+    use binjs_meta::spec::{ SpecBuilder, SpecOptions };
+
+    // Shift cannot parse BigInt literals, so we introduce one in the AST.
+    let shift = Shift::new();
+    let mut parsed = shift.parse_str("a;")
+        .expect("Error in parse_str");
+    parsed["statements"][0]["expression"] = object!{
+        "type" => "LiteralBigIntExpression",
+        "value" => "18446744073709551616"
+    };
+
+    let mut builder = SpecBuilder::new();
+    let _ = Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Script"),
+    };
+    let spec = builder.into_spec(spec_options);
+    let source = shift.to_source(&spec, &parsed)
+        .expect("Error in to_source");
+    assert_eq!(source.trim(), "18446744073709551616n;");
+}
+
+#[test]
+fn test_shift_convert_roundtrip() {
+    use binjs_generic::es6::Library;
+    use binjs_meta::spec::{ SpecBuilder, SpecOptions };
+
+    // Converting does not require Node.
+    let shift = Shift::new();
+    let original = object!{
+        "type" => "Script",
+        "directives" => array![],
+        "statements" => array![
+            object!{
+                "type" => "ExpressionStatement",
+                "expression" => object!{
+                    "type" => "ObjectExpression",
+                    "properties" => array![
+                        object!{
+                            "type" => "Setter",
+                            "name" => object!{
+                                "type" => "StaticPropertyName",
+                                "value" => "foo"
+                            },
+                            "param" => object!{
+                                "type" => "BindingIdentifier",
+                                "name" => "x"
+                            },
+                            "body" => object!{
+                                "type" => "FunctionBody",
+                                "directives" => array![],
+                                "statements" => array![]
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    };
+    let mut converted = original.clone();
+    shift.convert_shift_json(&mut converted)
+        .expect("Could not convert from Shift");
+    let setter = &converted["statements"][0]["expression"]["properties"][0];
+    assert_eq!(setter["type"], "EagerSetter");
+    assert_eq!(setter["length"], 1);
+    assert_eq!(setter["contents"]["type"], "SetterContents");
+    assert_eq!(setter["contents"]["param"]["name"], "x");
+    assert_eq!(setter["contents"]["parameterScope"]["isSimpleParameterList"], true);
+
+    let mut builder = SpecBuilder::new();
+    let _ = Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Script"),
+    };
+    let spec = builder.into_spec(spec_options);
+    let roundtrip = shift.to_shift_json(&spec, &converted)
+        .expect("Could not convert to Shift");
+    assert_eq!(roundtrip, original);
+
+    // Nodes that do not have the expected fields are rejected.
+    let mut invalid = original.clone();
+    invalid["statements"][0]["expression"]["properties"][0]
+        .as_object_mut()
+        .unwrap()
+        .remove("body");
+    assert!(shift.convert_shift_json(&mut invalid).is_err());
+}
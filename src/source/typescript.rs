@@ -0,0 +1,280 @@
+//! Stripping TypeScript type annotations from an ESTree AST.
+//!
+//! This is a transpile-only pass, in the spirit of `tsc --isolatedModules`
+//! or esbuild: we perform no type checking, we simply remove all the
+//! constructions that only exist at the type level. Constructions that
+//! have a runtime semantics (`enum`, `namespace`, parameter properties)
+//! are rejected.
+
+use binjs_generic::syntax::ASTError;
+
+use binjs_shared::{ JSON, JSONExt };
+
+/// Fields that only carry type information.
+const TYPE_FIELDS : [&'static str; 9] = [
+    "typeAnnotation",
+    "returnType",
+    "typeParameters",
+    "superTypeParameters",
+    "implements",
+    "accessibility",
+    "declare",
+    "definite",
+    "abstract",
+];
+
+/// Nodes whose field `optional` marks an optional parameter or member, e.g.
+/// `x?: T`, which only carries type information.
+///
+/// On other nodes, e.g. `MemberExpression` or `CallExpression`, the field
+/// `optional` denotes optional chaining, which has a runtime semantics.
+const OPTIONAL_TYPED : [&'static str; 8] = [
+    "Identifier",
+    "ObjectPattern",
+    "ArrayPattern",
+    "AssignmentPattern",
+    "RestElement",
+    "ClassProperty",
+    "PropertyDefinition",
+    "MethodDefinition",
+];
+
+/// Nodes that wrap an expression with type information.
+const TYPE_WRAPPERS : [&'static str; 4] = [
+    "TSAsExpression",
+    "TSTypeAssertion",
+    "TSNonNullExpression",
+    "TSSatisfiesExpression",
+];
+
+/// Nodes that have a runtime semantics and cannot be simply stripped.
+const UNSUPPORTED : [&'static str; 5] = [
+    "TSEnumDeclaration",
+    "TSModuleDeclaration",
+    "TSParameterProperty",
+    "TSImportEqualsDeclaration",
+    "TSExportAssignment",
+];
+
+/// A data structure designed to remove type annotations from
+/// a TypeScript AST in ESTree format.
+pub struct StripTypes;
+impl StripTypes {
+    /// Strip types from an ESTree AST, in place.
+    pub fn strip(&self, value: &mut JSON) -> Result<(), ASTError> {
+        let replacement = match *value {
+            JSON::Array(ref mut array) => {
+                array.retain(|item| !Self::is_type_only(item));
+                for item in array.iter_mut() {
+                    self.strip(item)?;
+                }
+                None
+            }
+            JSON::Object(_) => {
+                if let Some(kind) = value["type"].as_str() {
+                    if UNSUPPORTED.contains(&kind) {
+                        return Err(ASTError::InvalidValue {
+                            got: value.dump(),
+                            expected: "TypeScript construction that may be stripped without transpilation".to_string(),
+                        });
+                    }
+                }
+                let is_wrapper = match value["type"].as_str() {
+                    Some(kind) => TYPE_WRAPPERS.contains(&kind),
+                    None => false
+                };
+                if is_wrapper {
+                    let mut expression = value.remove("expression");
+                    self.strip(&mut expression)?;
+                    Some(expression)
+                } else {
+                    for field in TYPE_FIELDS.iter() {
+                        value.remove(field);
+                    }
+                    let is_optional_typed = match value["type"].as_str() {
+                        Some(kind) => OPTIONAL_TYPED.contains(&kind),
+                        None => false
+                    };
+                    if is_optional_typed {
+                        value.remove("optional");
+                    }
+                    // Remove the `this` pseudo-parameter.
+                    if let Some(&mut JSON::Array(ref mut params)) = value.get_mut("params") {
+                        let is_this = params.first()
+                            .map(|param| param["type"] == "Identifier" && param["name"] == "this")
+                            .unwrap_or(false);
+                        if is_this {
+                            params.remove(0);
+                        }
+                    }
+                    if let JSON::Object(ref mut object) = *value {
+                        for (_, field) in object.iter_mut() {
+                            self.strip(field)?;
+                        }
+                    }
+                    None
+                }
+            }
+            _ => None
+        };
+        if let Some(replacement) = replacement {
+            *value = replacement;
+        }
+        Ok(())
+    }
+
+    /// Determine whether a statement or class member exists only at
+    /// the type level, in which case it should simply be removed.
+    fn is_type_only(value: &JSON) -> bool {
+        if value["declare"] == true {
+            return true;
+        }
+        match value["type"].as_str() {
+            Some("TSTypeAliasDeclaration")
+            | Some("TSInterfaceDeclaration")
+            | Some("TSDeclareFunction")
+            | Some("TSDeclareMethod")
+            | Some("TSIndexSignature") => true,
+            Some("MethodDefinition") => value["abstract"] == true,
+            _ => false
+        }
+    }
+}
+
+#[test]
+fn test_strip_types() {
+    // ESTree AST for `type T = number; function f(this: T, x: T): T { return x as T; }`.
+    let mut ast = object!{
+        "type" => "Program",
+        "sourceType" => "script",
+        "body" => array![
+            object!{
+                "type" => "TSTypeAliasDeclaration",
+                "id" => object!{
+                    "type" => "Identifier",
+                    "name" => "T"
+                }
+            },
+            object!{
+                "type" => "FunctionDeclaration",
+                "id" => object!{
+                    "type" => "Identifier",
+                    "name" => "f"
+                },
+                "params" => array![
+                    object!{
+                        "type" => "Identifier",
+                        "name" => "this",
+                        "typeAnnotation" => object!{ "type" => "TSTypeAnnotation" }
+                    },
+                    object!{
+                        "type" => "Identifier",
+                        "name" => "x",
+                        "typeAnnotation" => object!{ "type" => "TSTypeAnnotation" }
+                    }
+                ],
+                "returnType" => object!{ "type" => "TSTypeAnnotation" },
+                "body" => object!{
+                    "type" => "BlockStatement",
+                    "body" => array![
+                        object!{
+                            "type" => "ReturnStatement",
+                            "argument" => object!{
+                                "type" => "TSAsExpression",
+                                "expression" => object!{
+                                    "type" => "Identifier",
+                                    "name" => "x"
+                                },
+                                "typeAnnotation" => object!{ "type" => "TSTypeReference" }
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    };
+    StripTypes.strip(&mut ast)
+        .expect("Could not strip types");
+    let expected = object!{
+        "type" => "Program",
+        "sourceType" => "script",
+        "body" => array![
+            object!{
+                "type" => "FunctionDeclaration",
+                "id" => object!{
+                    "type" => "Identifier",
+                    "name" => "f"
+                },
+                "params" => array![
+                    object!{
+                        "type" => "Identifier",
+                        "name" => "x"
+                    }
+                ],
+                "body" => object!{
+                    "type" => "BlockStatement",
+                    "body" => array![
+                        object!{
+                            "type" => "ReturnStatement",
+                            "argument" => object!{
+                                "type" => "Identifier",
+                                "name" => "x"
+                            }
+                        }
+                    ]
+                }
+            }
+        ]
+    };
+    assert_eq!(ast, expected);
+}
+
+#[test]
+fn test_strip_types_optional_chaining() {
+    // ESTree AST for `function f(x?: T) { return x?.y; }`.
+    let mut ast = object!{
+        "type" => "FunctionDeclaration",
+        "id" => object!{
+            "type" => "Identifier",
+            "name" => "f"
+        },
+        "params" => array![
+            object!{
+                "type" => "Identifier",
+                "name" => "x",
+                "optional" => true,
+                "typeAnnotation" => object!{ "type" => "TSTypeAnnotation" }
+            }
+        ],
+        "body" => object!{
+            "type" => "BlockStatement",
+            "body" => array![
+                object!{
+                    "type" => "ReturnStatement",
+                    "argument" => object!{
+                        "type" => "MemberExpression",
+                        "object" => object!{
+                            "type" => "Identifier",
+                            "name" => "x"
+                        },
+                        "property" => object!{
+                            "type" => "Identifier",
+                            "name" => "y"
+                        },
+                        "computed" => false,
+                        "optional" => true
+                    }
+                }
+            ]
+        }
+    };
+    StripTypes.strip(&mut ast)
+        .expect("Could not strip types");
+
+    // The optional parameter loses its annotation, the optional chain is kept.
+    assert_eq!(ast["params"][0], object!{
+        "type" => "Identifier",
+        "name" => "x"
+    });
+    assert_eq!(ast["body"]["body"][0]["argument"]["optional"], true);
+}
@@ -0,0 +1,174 @@
+//! Export JSON ASTs as GraphViz (DOT) graphs, e.g. to visualize structures in
+//! presentations or while debugging.
+//!
+//! Each node is a box listing its kind and its scalar fields. Nodes and lists of
+//! nodes are linked to their parent by edges labelled with the field name.
+
+use binjs_shared::{ JSON, JSONExt };
+
+/// The maximal number of characters of the values shown in boxes.
+const MAX_VALUE_LEN: usize = 40;
+
+/// Export ASTs as DOT graphs.
+///
+/// ```
+/// # #[macro_use] extern crate binjs_shared;
+/// extern crate binjs;
+///
+/// use binjs::util::dot::DotExporter;
+///
+/// # fn main() {
+/// let ast = object!{
+///     "type" => "IdentifierExpression",
+///     "name" => "foo"
+/// };
+/// let dot = DotExporter::new()
+///     .export(&ast);
+/// assert!(dot.starts_with("digraph ast {"));
+/// assert!(dot.contains("n0 [label=\"IdentifierExpression\\n name = \\\"foo\\\"\\l\"];"));
+/// # }
+/// ```
+pub struct DotExporter {
+    /// If specified, the descendants of nodes deeper than this are hidden.
+    max_depth: Option<usize>,
+}
+impl DotExporter {
+    pub fn new() -> Self {
+        DotExporter {
+            max_depth: None,
+        }
+    }
+
+    /// Hide the descendants of nodes at depth `max_depth`, the root being at depth 0.
+    pub fn with_max_depth(self, max_depth: Option<usize>) -> Self {
+        DotExporter {
+            max_depth,
+            ..self
+        }
+    }
+
+    /// A DOT graph of `ast`.
+    pub fn export(&self, ast: &JSON) -> String {
+        let mut out = String::new();
+        out.push_str("digraph ast {\n");
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+        let mut next_id = 0;
+        self.node(ast, 0, &mut next_id, &mut out);
+        out.push_str("}\n");
+        out
+    }
+
+    /// Write `value` and its descendants, returning the id of its box.
+    fn node(&self, value: &JSON, depth: usize, next_id: &mut usize, out: &mut String) -> usize {
+        let id = *next_id;
+        *next_id += 1;
+
+        let mut label;
+        let mut children = vec![];
+        match *value {
+            JSON::Object(ref object) => {
+                label = format!("{}\\n", escape(object.get("type")
+                    .and_then(JSON::as_str)
+                    .unwrap_or("(object)")));
+                for (key, field) in object {
+                    if key == "type" {
+                        continue;
+                    }
+                    if Self::is_tree(field) {
+                        children.push((key.clone(), field));
+                    } else {
+                        label.push_str(&format!(" {} = {}\\l", escape(key), escape(&short(field))));
+                    }
+                }
+            }
+            JSON::Array(ref items) => {
+                label = format!("list (length={})\\n", items.len());
+                for (index, item) in items.iter().enumerate() {
+                    children.push((format!("[{}]", index), item));
+                }
+            }
+            _ => {
+                label = escape(&short(value));
+            }
+        }
+
+        let is_truncated = !children.is_empty() && self.max_depth.map_or(false, |max_depth| depth >= max_depth);
+        if is_truncated {
+            label.push_str(&format!(" ({} children hidden)\\l", children.len()));
+        }
+        out.push_str(&format!("    n{} [label=\"{}\"{}];\n", id, label, if is_truncated { ", style=dashed" } else { "" }));
+        if !is_truncated {
+            for (name, child) in children {
+                let child_id = self.node(child, depth + 1, next_id, out);
+                out.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", id, child_id, escape(&name)));
+            }
+        }
+        id
+    }
+
+    /// `true` for nodes and lists containing nodes, which are shown as boxes of their own.
+    fn is_tree(value: &JSON) -> bool {
+        match *value {
+            JSON::Object(_) => true,
+            JSON::Array(ref items) => items.iter()
+                .any(Self::is_tree),
+            _ => false
+        }
+    }
+}
+
+/// The descendant of `ast` designated by `path`, a sequence of field names and list
+/// indices, e.g. `statements[0].expression`.
+pub fn select<'a>(ast: &'a JSON, path: &str) -> Result<&'a JSON, String> {
+    let mut node = ast;
+    for segment in path.split('.').filter(|segment| !segment.is_empty()) {
+        let mut parts = segment.split('[');
+        let field = parts.next()
+            .unwrap(); // `split` always returns at least one item.
+        if !field.is_empty() {
+            node = match node.get(field) {
+                Some(child) => child,
+                None => return Err(format!("No field {} in {}", field, short(&node["type"])))
+            };
+        }
+        for index in parts {
+            let index = index.trim_end_matches(']');
+            let index : usize = index.parse()
+                .map_err(|_| format!("Invalid index [{}] in {}", index, path))?;
+            node = match node.get(index) {
+                Some(child) => child,
+                None => return Err(format!("No item [{}] in a list of {} items", index, node.len()))
+            };
+        }
+    }
+    Ok(node)
+}
+
+/// A compact representation of `value`, truncated to `MAX_VALUE_LEN` characters.
+fn short(value: &JSON) -> String {
+    let dump = value.dump();
+    if dump.chars().count() <= MAX_VALUE_LEN {
+        return dump;
+    }
+    let mut result : String = dump.chars()
+        .take(MAX_VALUE_LEN)
+        .collect();
+    result.push_str("...");
+    result
+}
+
+/// Escape `text` for use in a DOT string.
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(c);
+            }
+            '\n' => result.push_str("\\n"),
+            _ => result.push(c)
+        }
+    }
+    result
+}
@@ -0,0 +1,162 @@
+//! Running JavaScript sources with an external engine, to check that a roundtrip
+//! through BinJS preserves the behavior of programs, not just their AST.
+
+use binjs_es6::ast::Program;
+use binjs_es6::io::{ Decoder, Encoder };
+use binjs_es6::scopes::AnnotationVisitor;
+use binjs_generic::es6::Library;
+use binjs_io::{ self, Format };
+use binjs_meta::spec::{ SpecBuilder, SpecOptions };
+use binjs_shared::{ FromJSON, FromJSONError, ToJSON };
+use source::{ shift, Shift, SourceParser };
+use util::get_temporary_file;
+
+use std;
+use std::io::{ Cursor, Write };
+use std::path::Path;
+use std::process::Command;
+
+/// Defines `print` for engines that only have `console.log` (e.g. Node), so that
+/// test scripts may print their results in the same manner with all engines.
+const PRELUDE: &str = "if (typeof print === \"undefined\") { var print = function() { console.log.apply(console, arguments); }; }\n";
+
+#[derive(Debug)]
+pub enum Error {
+    /// The engine could not be launched.
+    CouldNotLaunch(std::io::Error),
+
+    /// The source file could not be read.
+    CouldNotReadSource(std::io::Error),
+
+    /// The script could not be written to a temporary file.
+    CouldNotWriteScript(std::io::Error),
+
+    Parse(shift::Error),
+    Import(FromJSONError),
+    Encode(binjs_io::Error),
+    Decode(binjs_io::Error),
+}
+
+/// The observable result of running a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    /// The exit code, or `None` if the engine was killed by a signal.
+    pub status: Option<i32>,
+
+    pub stdout: String,
+
+    pub stderr: String,
+}
+
+/// A JavaScript engine, e.g. `node`, `d8` or `js` (the SpiderMonkey shell).
+pub struct Engine {
+    /// The program to launch.
+    program: String,
+
+    /// Arguments passed to the program before the path of the script.
+    args: Vec<String>,
+}
+impl Engine {
+    /// Create an engine from a command line, e.g. `"node"` or `"d8 --harmony"`.
+    ///
+    /// The path of the script to run is appended to the command line.
+    pub fn new(command: &str) -> Self {
+        let mut words = command.split_whitespace()
+            .map(str::to_string);
+        let program = words.next()
+            .unwrap_or_else(|| "node".to_string());
+        Engine {
+            program,
+            args: words.collect(),
+        }
+    }
+
+    /// Run a JavaScript source, which may use `print` to produce output.
+    pub fn run(&self, source: &str) -> Result<Outcome, Error> {
+        let (path, mut file) = get_temporary_file("js")
+            .map_err(Error::CouldNotWriteScript)?;
+        file.write_all(PRELUDE.as_bytes())
+            .and_then(|_| file.write_all(source.as_bytes()))
+            .map_err(Error::CouldNotWriteScript)?;
+        drop(file);
+
+        debug!(target: "engine", "Running {} {:?} {:?}", self.program, self.args, path);
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .arg(&path)
+            .output();
+        let _ = std::fs::remove_file(&path);
+        let output = output
+            .map_err(Error::CouldNotLaunch)?;
+        Ok(Outcome {
+            status: output.status.code(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+/// The result of comparing the behavior of a source file before and after a roundtrip.
+pub struct Comparison {
+    /// The source, as decoded from BinJS and pretty-printed.
+    pub roundtripped: String,
+
+    /// The outcome of running the original source.
+    pub original_outcome: Outcome,
+
+    /// The outcome of running the roundtripped source.
+    pub roundtripped_outcome: Outcome,
+}
+impl Comparison {
+    /// `true` if both sources printed the same output and exited with the same status.
+    ///
+    /// Errors are not compared, as they typically mention the path of the script.
+    pub fn is_same(&self) -> bool {
+        self.original_outcome.status == self.roundtripped_outcome.status
+            && self.original_outcome.stdout == self.roundtripped_outcome.stdout
+    }
+}
+
+/// Encode the source file at `path` with `format`, decode it, then run both the
+/// original and the roundtripped sources with `engine`.
+pub fn compare<P: AsRef<Path>>(engine: &Engine, parser: &Shift, format: &mut Format, path: P) -> Result<Comparison, Error> {
+    let path = path.as_ref();
+    let original = std::fs::read_to_string(path)
+        .map_err(Error::CouldNotReadSource)?;
+    let roundtripped = roundtrip(parser, format, path)?;
+
+    let original_outcome = engine.run(&original)?;
+    let roundtripped_outcome = engine.run(&roundtripped)?;
+    Ok(Comparison {
+        roundtripped,
+        original_outcome,
+        roundtripped_outcome,
+    })
+}
+
+/// Encode the source file at `path` with `format`, decode it and pretty-print it.
+fn roundtrip(parser: &Shift, format: &mut Format, path: &Path) -> Result<String, Error> {
+    let json = parser.parse_file(path)
+        .map_err(Error::Parse)?;
+    let mut ast = Program::import(&json)
+        .map_err(Error::Import)?;
+    AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+
+    let data = Encoder::new()
+        .encode(format, &ast)
+        .map_err(Error::Encode)?;
+    let decoded : Program = Decoder::new()
+        .decode(format, Cursor::new((*data).as_ref()))
+        .map_err(Error::Decode)?;
+
+    let mut builder = SpecBuilder::new();
+    let _ = Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Program"),
+    };
+    let spec = builder.into_spec(spec_options);
+    parser.to_source(&spec, &decoded.export())
+        .map_err(Error::Parse)
+}
@@ -2,13 +2,25 @@ use binjs_generic::syntax::ASTError;
 
 use rand;
 use rand::distributions::Alphanumeric;
-use json::JsonValue as JSON;
-use json::object::Object as Object;
+use binjs_shared::{ JSON, JSONExt, JSONObject as Object };
+use serde_json;
 
 use std;
 use std::fs::File;
 use std::path::*;
 
+/// Measuring the time spent in each phase of encoding.
+pub mod timing;
+
+/// Comparing the behavior of sources before and after a roundtrip, using a JS engine.
+pub mod engine;
+
+/// Reference test vectors, for independent implementations of the format.
+pub mod vectors;
+
+/// Exporting ASTs as GraphViz graphs.
+pub mod dot;
+
 pub fn get_temporary_file(extension: &str) -> std::result::Result<(PathBuf, File), std::io::Error> {
     use rand::Rng;
     let directory = std::env::temp_dir();
@@ -35,6 +47,12 @@ pub fn get_temporary_file(extension: &str) -> std::result::Result<(PathBuf, File
     Err(error.unwrap())
 }
 
+/// Access a `JSON` value as an array or an object, failing with an `ASTError`.
+///
+/// To access the fields of nodes, see the typed paths of `binjs::generic::path`.
+///
+/// As `JSON` has inherent methods with the same names, these methods must be
+/// called as e.g. `JSONAs::as_array(&value, "description")`.
 pub trait JSONAs {
     fn as_array(&self, description: &str) -> Result<&Vec<JSON>, ASTError>;
     fn as_array_mut(&mut self, description: &str) -> Result<&mut Vec<JSON>, ASTError>;
@@ -89,6 +107,7 @@ impl JSONAs for JSON {
 ///
 /// Most of these tools are useful largely because lifetime management in a mutable JSON AST is
 /// complicated.
+#[deprecated(note = "Use the typed fields of `binjs::generic::path` to access the fields of nodes, or `JSONAs` for other values")]
 pub trait JSONGetter {
     fn get_bool(&self, name: &str, description: &str) -> Result<bool, ASTError>;
     fn get_string(&self, name: &str, description: &str) -> Result<&str, ASTError>;
@@ -98,55 +117,58 @@ pub trait JSONGetter {
     fn get_object_mut(&mut self, name: &str, description: &str) -> Result<&mut Object, ASTError>;
 }
 
+/// The error returned when `object` does not have the expected field.
+fn invalid_object(object: &Object, description: &str) -> ASTError {
+    ASTError::InvalidValue {
+        got: serde_json::to_string(object)
+            .expect("Could not serialize JSON"),
+        expected: description.to_owned()
+    }
+}
+
+#[allow(deprecated)]
 impl JSONGetter for Object {
     fn get_bool(&self, name: &str, description: &str) -> Result<bool, ASTError> {
-        match self[name].as_bool() {
-            Some(b) => Ok(b),
-            None => {
-                Err(ASTError::InvalidValue {
-                    got: self.dump(),
-                    expected: description.to_owned()
-                })
-            }
-        }
+        self.get(name)
+            .and_then(JSON::as_bool)
+            .ok_or_else(|| invalid_object(self, description))
     }
     fn get_string(&self, name: &str, description: &str) -> Result<&str, ASTError> {
-        let found;
-        if let Some(field) = self.get(name) {
-            found = field.as_str().is_some()
-        } else {
-            found = false;
-        }
-        if found { // Workaround for borrow checks
-            Ok(self.get(name)
-                .unwrap() // Checked above.
-                .as_str()
-                .unwrap()) // Checked above
-        } else {
-            Err(ASTError::InvalidValue {
-                got: self.dump(),
-                expected: description.to_owned()
-            })
-        }
+        self.get(name)
+            .and_then(JSON::as_str)
+            .ok_or_else(|| invalid_object(self, description))
     }
     fn get_array(&self, name: &str, description: &str) -> Result<&Vec<JSON>, ASTError> {
-        self[name].as_array(description)
+        match self.get(name) {
+            Some(value) => JSONAs::as_array(value, description),
+            None => Err(invalid_object(self, description))
+        }
     }
     fn get_array_mut(&mut self, name: &str, description: &str) -> Result<&mut Vec<JSON>, ASTError> {
-        self[name].as_array_mut(description)
+        if !self.contains_key(name) {
+            return Err(invalid_object(self, description));
+        }
+        JSONAs::as_array_mut(self.get_mut(name).unwrap(), description) // Checked above.
     }
     fn get_object(&self, name: &str, description: &str) -> Result<&Object, ASTError> {
-        self[name].as_object(description)
+        match self.get(name) {
+            Some(value) => JSONAs::as_object(value, description),
+            None => Err(invalid_object(self, description))
+        }
     }
     fn get_object_mut(&mut self, name: &str, description: &str) -> Result<&mut Object, ASTError> {
-        self[name].as_object_mut(description)
+        if !self.contains_key(name) {
+            return Err(invalid_object(self, description));
+        }
+        JSONAs::as_object_mut(self.get_mut(name).unwrap(), description) // Checked above.
     }
 }
 
+#[allow(deprecated)]
 impl JSONGetter for JSON {
     fn get_bool(&self, name: &str, description: &str) -> Result<bool, ASTError> {
         match self[name] {
-            JSON::Boolean(b) => return Ok(b),
+            JSON::Bool(b) => return Ok(b),
             _ => {}
         };
         Err(ASTError::InvalidValue {
@@ -173,7 +195,7 @@ impl JSONGetter for JSON {
         })
     }
     fn get_array_mut(&mut self, name: &str, description: &str) -> Result<&mut Vec<JSON>, ASTError> {
-        self[name].as_array_mut(description)
+        JSONAs::as_array_mut(&mut self[name], description)
     }
     fn get_object(&self, name: &str, description: &str) -> Result<&Object, ASTError> {
         match self[name] {
@@ -186,7 +208,7 @@ impl JSONGetter for JSON {
         })
     }
     fn get_object_mut(&mut self, name: &str, description: &str) -> Result<&mut Object, ASTError> {
-        self[name].as_object_mut(description)
+        JSONAs::as_object_mut(&mut self[name], description)
     }
 }
 
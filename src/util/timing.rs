@@ -0,0 +1,118 @@
+//! Measuring the time spent in each `tracing` span.
+//!
+//! The encoder is instrumented with spans for each phase (parsing, annotation,
+//! entropy coding, compression, ...), and for each dictionary lookup with feature
+//! `trace-symbols`. Installing a `Timings` as the `tracing` subscriber lets us
+//! find out where encoding time goes.
+//!
+//! Events of the instrumented modules are forwarded to `log` while no subscriber
+//! is installed, so `RUST_LOG` keeps working.
+
+use binjs_shared::JSON;
+
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use tracing;
+use tracing::Subscriber;
+use tracing::span::Id;
+use tracing_subscriber;
+use tracing_subscriber::layer::{ Context, Layer, SubscriberExt };
+use tracing_subscriber::registry::LookupSpan;
+
+/// The time spent in all spans with the same name.
+#[derive(Clone, Default)]
+struct Timing {
+    /// The number of times spans with this name were entered.
+    calls: usize,
+
+    /// The total time spent in spans with this name, including nested spans.
+    total: Duration,
+}
+
+/// The instant at which a span was entered, stored in the span extensions.
+struct Entered(Instant);
+
+/// A collection of timings, by span name.
+#[derive(Clone, Default)]
+pub struct Timings {
+    by_name: Arc<Mutex<HashMap<&'static str, Timing>>>,
+}
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Install a subscriber collecting timings into `self` for all threads.
+    ///
+    /// Fails if a subscriber is already installed.
+    pub fn install(&self) -> Result<(), tracing::dispatcher::SetGlobalDefaultError> {
+        let subscriber = tracing_subscriber::registry()
+            .with(TimingLayer {
+                timings: self.clone()
+            });
+        tracing::subscriber::set_global_default(subscriber)
+    }
+
+    /// Export the timings as `{ name: { "calls": number, "total_ms": number } }`.
+    pub fn to_json(&self) -> JSON {
+        let by_name = self.by_name.lock()
+            .expect("Timings are poisoned");
+        let mut result = object!{};
+        for (name, timing) in by_name.iter() {
+            result[*name] = object!{
+                "calls" => timing.calls,
+                "total_ms" => timing.total.as_secs_f64() * 1000.
+            };
+        }
+        result
+    }
+}
+
+/// A `tracing_subscriber` layer adding the time spent in each span to a `Timings`.
+struct TimingLayer {
+    timings: Timings,
+}
+impl<S> Layer<S> for TimingLayer where S: Subscriber + for<'a> LookupSpan<'a> {
+    fn on_enter(&self, id: &Id, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut()
+                .insert(Entered(Instant::now()));
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<S>) {
+        if let Some(span) = ctx.span(id) {
+            let entered = span.extensions_mut()
+                .remove::<Entered>();
+            if let Some(Entered(start)) = entered {
+                let mut by_name = self.timings.by_name.lock()
+                    .expect("Timings are poisoned");
+                let timing = by_name.entry(span.name())
+                    .or_insert_with(Timing::default);
+                timing.calls += 1;
+                timing.total += start.elapsed();
+            }
+        }
+    }
+}
+
+#[test]
+fn test_timings() {
+    let timings = Timings::new();
+    let subscriber = tracing_subscriber::registry()
+        .with(TimingLayer {
+            timings: timings.clone()
+        });
+    tracing::subscriber::with_default(subscriber, || {
+        for _ in 0..3 {
+            let _span = tracing::info_span!("outer").entered();
+            let _inner = tracing::trace_span!("inner").entered();
+        }
+    });
+    let json = timings.to_json();
+    assert_eq!(json["outer"]["calls"], 3);
+    assert_eq!(json["inner"]["calls"], 3);
+    assert!(json["outer"]["total_ms"].as_f64().unwrap() >= json["inner"]["total_ms"].as_f64().unwrap());
+}
@@ -0,0 +1,111 @@
+//! Reference test vectors, i.e. canonical small sources alongside their exact
+//! encoding and their decoded AST, to check independent implementations of the
+//! format against this one.
+//!
+//! For each vector `NAME`, `emit` writes:
+//!
+//! - `NAME.js`, the source;
+//! - `NAME.binjs`, the source encoded with the chosen format;
+//! - `NAME.json`, the AST obtained by decoding `NAME.binjs`, in the internal JSON
+//!   format of BinJS, including scope annotations;
+//!
+//! and a file `index.json` listing the format and the vectors.
+
+use binjs_es6::ast::Program;
+use binjs_es6::io::{ Decoder, Encoder };
+use binjs_es6::scopes::AnnotationVisitor;
+use binjs_io::{ self, Format };
+use binjs_shared::{ FromJSON, FromJSONError, JSON, JSONExt, ToJSON };
+use source::{ shift, Shift, SourceParser };
+
+use std;
+use std::fs::File;
+use std::io::{ Cursor, Write };
+use std::path::Path;
+
+/// The canonical inputs, as `(name, source)`.
+///
+/// Each vector exercises a small part of the grammar, so that an implementation
+/// failing on a vector points to the feature at fault. Names are stable, new
+/// vectors should be appended rather than inserted.
+pub const SOURCES: &[(&str, &str)] = &[
+    ("empty", ""),
+    ("literals", "null; true; false; 0; 1.5; -2; \"string\"; /regexp/gi;\n"),
+    ("identifiers", "var a = 1; let b = a; const c = b;\n"),
+    ("arrays", "[]; [1, , 2]; [...a];\n"),
+    ("objects", "({}); ({ a: 1, \"b\": 2, [c]: 3, d() {}, get e() { return 1; }, set e(v) {} });\n"),
+    ("operators", "a + b * c; -a; !a; typeof a; a++; --a; a = b += c; a && b || c; a ? b : c;\n"),
+    ("control", "if (a) b; else c; while (a) break; do continue; while (a); for (;;) {} for (a in b); for (a of b); switch (a) { case 1: default: }\n"),
+    ("exceptions", "try { throw a; } catch (e) {} finally {}\n"),
+    ("functions", "function f(a, b = 1, ...c) { return a; } (function () {}); (a => a); (function* g() { yield 1; }); (async function h() { await a; });\n"),
+    ("classes", "class A extends B { constructor() { super(); } static m() {} }\n"),
+    ("templates", "`a${b}c`; f`d`;\n"),
+    ("destructuring", "var { a, b: [c, d = 1] } = e;\n"),
+    ("scopes", "var a; function f(b) { let c; { const d = a; } return function () { eval(\"b\"); }; }\n"),
+    ("directives", "\"use strict\"; function f() { \"use asm\"; }\n"),
+];
+
+#[derive(Debug)]
+pub enum Error {
+    /// A file could not be written.
+    CouldNotWrite(std::io::Error),
+
+    Parse(shift::Error),
+    Import(FromJSONError),
+    Encode(binjs_io::Error),
+    Decode(binjs_io::Error),
+}
+
+/// Write all the vectors of `SOURCES`, encoded with `format`, to directory `dir`,
+/// which is created if necessary. Existing files are overwritten.
+///
+/// Returns the index, as written to `index.json`.
+pub fn emit<P: AsRef<Path>>(parser: &Shift, format: &mut Format, dir: P) -> Result<JSON, Error> {
+    let dir = dir.as_ref();
+    std::fs::create_dir_all(dir)
+        .map_err(Error::CouldNotWrite)?;
+
+    let mut vectors = array![];
+    for &(name, source) in SOURCES {
+        debug!(target: "vectors", "Emitting test vector {}", name);
+        let json = parser.parse_str(source)
+            .map_err(Error::Parse)?;
+        let mut ast = Program::import(&json)
+            .map_err(Error::Import)?;
+        AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+
+        let data = Encoder::new()
+            .encode(format, &ast)
+            .map_err(Error::Encode)?;
+        let data = (*data).as_ref();
+        let decoded : Program = Decoder::new()
+            .decode(format, Cursor::new(data))
+            .map_err(Error::Decode)?;
+
+        write(&dir.join(format!("{}.js", name)), source.as_bytes())?;
+        write(&dir.join(format!("{}.binjs", name)), data)?;
+        write(&dir.join(format!("{}.json", name)), decoded.export().pretty(2).as_bytes())?;
+
+        vectors.push(object!{
+            "name" => name,
+            "source" => format!("{}.js", name),
+            "binary" => format!("{}.binjs", name),
+            "ast" => format!("{}.json", name),
+            "size" => data.len()
+        }).expect("Vectors are an array");
+    }
+
+    let index = object!{
+        "format" => format.name(),
+        "vectors" => vectors
+    };
+    write(&dir.join("index.json"), index.pretty(2).as_bytes())?;
+    Ok(index)
+}
+
+fn write(path: &Path, data: &[u8]) -> Result<(), Error> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(Error::CouldNotWrite)
+}
@@ -0,0 +1,25 @@
+// Closures, hoisting and scopes.
+function counter(start) {
+    var count = start;
+    return function() {
+        count += 1;
+        return count;
+    };
+}
+var next = counter(10);
+next();
+print(next(), hoisted(), typeof later);
+
+function hoisted() {
+    return "hoisted";
+}
+
+var later = 1;
+
+for (let i = 0; i < 3; ++i) {
+    setTimeoutLike(() => print("loop", i));
+}
+
+function setTimeoutLike(callback) {
+    callback();
+}
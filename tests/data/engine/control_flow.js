@@ -0,0 +1,34 @@
+// Loops, labels, switch and exceptions.
+var results = [];
+outer: for (var i = 0; i < 5; i++) {
+    for (var j = 0; j < 5; j++) {
+        if (j == 3) {
+            continue outer;
+        }
+        if (i == 4) {
+            break outer;
+        }
+        results.push(i * j);
+    }
+}
+print(results.join(","));
+
+function classify(x) {
+    switch (typeof x) {
+        case "number":
+            return x % 2 == 0 ? "even" : "odd";
+        case "string":
+            return "string of length " + x.length;
+        default:
+            return "other";
+    }
+}
+print(classify(3), classify(4), classify("abc"), classify(null));
+
+try {
+    null.foo;
+} catch (ex) {
+    print("caught", ex instanceof TypeError);
+} finally {
+    print("finally");
+}
@@ -0,0 +1,7 @@
+// Literals, which are easy to mangle in a roundtrip.
+print(0.1 + 0.2, 1e21, -0 === 0, 1 / -0, 0x10, NaN !== NaN);
+print("quotes \" and ' and \\ and é and \n newline".length);
+print(/a+(b|c)/gi.test("xAAC"), /\d{2,}/.source);
+var object = { a: 1, "b c": [1, 2, [3]], get d() { return this.a + 1; } };
+print(JSON.stringify(object), object.d);
+print([1, , 3].length, [1, 2, 3].map(x => x * 2).join(" "));
@@ -0,0 +1,54 @@
+// A parser implementing the protocol of `binjs::source::external` by
+// wrapping shift-parser, for tests.
+//
+// Usage: node shift_parser.js [--no-module] [--bad-positions]
+//
+// With `--bad-positions`, the root of each AST has a field that is not part of
+// the grammar, with a source position, as positions that do not match the AST.
+module.paths.push(require('path').join(process.cwd(), 'node_modules'));
+
+var parser = require('shift-parser');
+var readline = require('readline');
+
+var args = process.argv.slice(2);
+var goals = args.indexOf('--no-module') == -1 ? ["script", "module"] : ["script"];
+var badPositions = args.indexOf('--bad-positions') != -1;
+
+/* See crates/binjs_shared/src/escaped_wtf8.rs */
+function escape(text) {
+    return text.replace(/[\u007F\uD800-\uDFFF]/ug, function(m) {
+        if (m == "\u007F") {
+            return "\u007F007F";
+        }
+        return "\u007F" + m.charCodeAt(0).toString(16).toUpperCase();
+    });
+}
+
+var lines = readline.createInterface({ input: process.stdin, terminal: false });
+lines.on('line', function(line) {
+    var request = JSON.parse(line);
+    var response;
+    switch (request.type) {
+        case "hello":
+            response = { type: "hello", version: 1, goals: goals, ast: "shift", positions: false };
+            break;
+        case "parse":
+            try {
+                var parse = request.goal == "module" ? parser.parseModule : parser.parseScript;
+                var ast = parse(request.source, { earlyErrors: false });
+                if (badPositions) {
+                    var position = { line: 1, column: 0, offset: 0 };
+                    ast.notInGrammar = { loc: { start: position, end: position } };
+                }
+                response = { type: "ast", ast: ast };
+            } catch (ex) {
+                response = { type: "error", message: String(ex.description || ex), line: ex.line, column: ex.column };
+            }
+            break;
+        default:
+            response = { type: "unknown" };
+    }
+    process.stdout.write(JSON.stringify(response, function(key, value) {
+        return typeof value == "string" ? escape(value) : value;
+    }) + "\n");
+});
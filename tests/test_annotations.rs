@@ -6,13 +6,12 @@ fn main() {
 
 extern crate binjs;
 #[macro_use]
-extern crate json;
+extern crate binjs_shared;
 #[macro_use]
 extern crate test_logger;
 
 use binjs::source::*;
-use binjs::util::JSONAs;
-use json::JsonValue as JSON;
+use binjs::generic::{ JSON, JSONExt };
 
 test!(test_annotations_scopes_1, {
     println!("Preparing test.");
@@ -30,10 +29,10 @@ test!(test_annotations_scopes_1, {
     // Toplevel
     assert_eq!(ast["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["h"]
     });
 
@@ -41,10 +40,10 @@ test!(test_annotations_scopes_1, {
     let body = &ast["body"][0]["body"];
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["y"]
     });
 
@@ -52,9 +51,9 @@ test!(test_annotations_scopes_1, {
     let body = &body["body"][0];
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
         "BINJS:LetDeclaredNames" => array!["x"],
         "BINJS:VarDeclaredNames" => array!["y"]
     });
@@ -63,10 +62,10 @@ test!(test_annotations_scopes_1, {
     let body = &body["body"][1];
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["y"]
     });
 });
@@ -87,10 +86,10 @@ test!(test_annotations_scopes_2, {
     // Toplevel
     assert_eq!(ast["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["f"]
     });
 
@@ -99,10 +98,10 @@ test!(test_annotations_scopes_2, {
     println!("{}", body.pretty(2));
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["g", "i"]
     });
 
@@ -110,10 +109,10 @@ test!(test_annotations_scopes_2, {
     let g = &body["body"][0]["body"];
     assert_eq!(g["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["x"]
     });
 
@@ -122,10 +121,10 @@ test!(test_annotations_scopes_2, {
     println!("{}", for_loop.pretty(2));
     assert_eq!(for_loop["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["i"]
     });
 });
@@ -148,10 +147,10 @@ test!(test_annotations_capture_1, {
     // Toplevel
     assert_eq!(ast["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["f"]
     });
 
@@ -159,18 +158,18 @@ test!(test_annotations_capture_1, {
     let scope = &ast["body"][0]["BINJS:Scope"];
     assert_eq!(scope["BINJS:CapturedNames"], array!["b", "f"]);
     assert_eq!(scope["BINJS:HasDirectEval"], false);
-    assert_eq!(scope["BINJS:ConstDeclaredNames"], JSON::new_array());
-    assert_eq!(scope["BINJS:LetDeclaredNames"], JSON::new_array());
+    assert_eq!(scope["BINJS:ConstDeclaredNames"], array![]);
+    assert_eq!(scope["BINJS:LetDeclaredNames"], array![]);
     assert_eq!(scope["BINJS:VarDeclaredNames"], array!["g"]); // FIXME: Is this the right place for g?
 
     // Function body
     let body = &ast["body"][0]["body"];
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["g"]
     });
 
@@ -178,10 +177,10 @@ test!(test_annotations_capture_1, {
     let g = &body["body"][0]["body"];
     assert_eq!(g["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["c", "d"]
     });
 });
@@ -205,10 +204,10 @@ test!(test_annotations_capture_2, {
     // Toplevel
     assert_eq!(ast["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["f"]
     });
 
@@ -216,10 +215,10 @@ test!(test_annotations_capture_2, {
     let scope = &ast["body"][0]["BINJS:Scope"];
     assert_eq!(scope, &object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["g"]
     });
 
@@ -227,10 +226,10 @@ test!(test_annotations_capture_2, {
     let body = &ast["body"][0]["body"];
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["g"]
     });
 
@@ -238,10 +237,10 @@ test!(test_annotations_capture_2, {
     let g = &body["body"][0]["body"];
     assert_eq!(g["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["c", "d"]
     });
 });
@@ -266,8 +265,8 @@ test!(test_annotations_capture_3, {
         "type" => "BINJS:Scope",
         "BINJS:CapturedNames" => array!["f"],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["f", "g"]
     });
 });
@@ -290,10 +289,10 @@ test!(test_annotations_scopes_3, {
     // Toplevel
     assert_eq!(ast["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["f"]
     });
 
@@ -302,22 +301,22 @@ test!(test_annotations_scopes_3, {
     println!("{}", body.pretty(2));
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
         "BINJS:LetDeclaredNames" => array!["x"],
-        "BINJS:VarDeclaredNames" => JSON::new_array()
+        "BINJS:VarDeclaredNames" => array![]
     });
 
     // Block 1
     let block = &body["body"][1];
     assert_eq!(block["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
         "BINJS:ConstDeclaredNames" => array!["y"],
         "BINJS:LetDeclaredNames" => array!["g"],
-        "BINJS:VarDeclaredNames" => JSON::new_array()
+        "BINJS:VarDeclaredNames" => array![]
     });
 });
 
@@ -341,8 +340,8 @@ test!(test_annotations_capture_4, {
         "type" => "BINJS:Scope",
         "BINJS:CapturedNames" => array!["print"],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["f", "print"]
     });
 
@@ -353,8 +352,8 @@ test!(test_annotations_capture_4, {
         "type" => "BINJS:Scope",
         "BINJS:CapturedNames" => array!["x"], // FIXME: Is this right? Should `print` be considered captured?
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["g", "x"]
     });
 });
@@ -377,10 +376,10 @@ test!(test_annotations_eval_1, {
     // Toplevel
     assert_eq!(ast["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => true,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["foo"]
     });
 
@@ -388,11 +387,11 @@ test!(test_annotations_eval_1, {
     let scope = &ast["body"][0];
     assert_eq!(scope["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => true,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
-        "BINJS:VarDeclaredNames" => JSON::new_array()
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
+        "BINJS:VarDeclaredNames" => array![]
     });
 
     // Function body
@@ -400,22 +399,22 @@ test!(test_annotations_eval_1, {
     println!("{}", body.pretty(2));
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => true,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
-        "BINJS:VarDeclaredNames" => JSON::new_array()
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
+        "BINJS:VarDeclaredNames" => array![]
     });
 
     // Block 1
     let scope = &ast["body"][1];
     assert_eq!(scope["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
-        "BINJS:VarDeclaredNames" => JSON::new_array()
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
+        "BINJS:VarDeclaredNames" => array![]
     });
 });
 
@@ -437,10 +436,10 @@ test!(test_annotations_eval_2, {
     // Toplevel
     assert_eq!(ast["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["foo"]
     });
 
@@ -448,10 +447,10 @@ test!(test_annotations_eval_2, {
     let body = &ast["body"][0]["body"];
     assert_eq!(body["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
         "BINJS:VarDeclaredNames" => array!["eval"]
     });
 
@@ -459,11 +458,11 @@ test!(test_annotations_eval_2, {
     let scope = &ast["body"][1];
     assert_eq!(scope["BINJS:Scope"], object!{
         "type" => "BINJS:Scope",
-        "BINJS:CapturedNames" => JSON::new_array(),
+        "BINJS:CapturedNames" => array![],
         "BINJS:HasDirectEval" => false,
-        "BINJS:ConstDeclaredNames" => JSON::new_array(),
-        "BINJS:LetDeclaredNames" => JSON::new_array(),
-        "BINJS:VarDeclaredNames" => JSON::new_array()
+        "BINJS:ConstDeclaredNames" => array![],
+        "BINJS:LetDeclaredNames" => array![],
+        "BINJS:VarDeclaredNames" => array![]
     });
 });
 
@@ -482,13 +481,13 @@ test!(test_directives_1, {
 
     println!("{}", ast.pretty(2));
 
-    assert_eq!(ast["directives"].as_array("").unwrap().len(), 0);
+    assert_eq!(ast["directives"].as_array().unwrap().len(), 0);
 
     let ref foo = ast["body"][0];
-    assert_eq!(foo["body"]["directives"].as_array("").unwrap().len(), 0);
+    assert_eq!(foo["body"]["directives"].as_array().unwrap().len(), 0);
 
     let ref bar = foo["body"]["body"][0];
-    assert_eq!(bar["body"]["directives"].as_array("").unwrap().len(), 0);
+    assert_eq!(bar["body"]["directives"].as_array().unwrap().len(), 0);
 });
 
 test!(test_directives_2, {
@@ -506,13 +505,13 @@ test!(test_directives_2, {
 
     println!("{}", ast.pretty(2));
 
-    assert_eq!(ast["directives"].as_array("").unwrap().len(), 0);
+    assert_eq!(ast["directives"].as_array().unwrap().len(), 0);
 
     let ref foo = ast["body"][0];
-    assert_eq!(foo["body"]["directives"].as_array("").unwrap().len(), 0);
+    assert_eq!(foo["body"]["directives"].as_array().unwrap().len(), 0);
 
     let ref bar = foo["body"]["body"][0];
-    let array = bar["directives"].as_array("").unwrap();
+    let array = bar["directives"].as_array().unwrap();
     assert_eq!(array.len(), 1);
     assert_eq!(array[0].as_str().unwrap(), "use strict");
 });
@@ -533,15 +532,15 @@ test!(test_directives_3, {
 
     println!("{}", ast.pretty(2));
 
-    assert_eq!(ast["directives"].as_array("").unwrap().len(), 0);
+    assert_eq!(ast["directives"].as_array().unwrap().len(), 0);
 
     let ref foo = ast["body"][0];
-    let array = foo["directives"].as_array("").unwrap();
+    let array = foo["directives"].as_array().unwrap();
     assert_eq!(array.len(), 1);
     assert_eq!(array[0].as_str(), Some("use strict"));
 
     let ref bar = foo["body"]["body"][0];
-    let array = bar["directives"].as_array("").unwrap();
+    let array = bar["directives"].as_array().unwrap();
     assert_eq!(array.len(), 1);
     assert_eq!(array[0].as_str(), Some("something different"));
 });
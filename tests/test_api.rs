@@ -0,0 +1,54 @@
+//! Encode and decode through the stable API.
+
+extern crate binjs;
+
+use binjs::{ Decoder, Dictionary, Encoder, ErrorKind };
+
+use std::error::Error;
+
+#[test]
+fn test_api_roundtrip() {
+    let source = "function foo(x) { return x * 2; } foo(21);";
+    let encoder = Encoder::new()
+        .with_lazification(1);
+    let data = encoder.encode_source(source)
+        .expect("Could not encode");
+
+    let decoder = Decoder::new();
+    let decoded = decoder.decode_to_source(&data)
+        .expect("Could not decode");
+
+    // Printing is canonical, so a second roundtrip is exact.
+    let data2 = encoder.encode_source(&decoded)
+        .expect("Could not encode");
+    assert_eq!(decoder.decode_to_source(&data2)
+        .expect("Could not decode"), decoded);
+
+    let statistics = encoder.statistics();
+    assert_eq!(statistics.files(), 2);
+    assert_eq!(statistics.source_bytes(), source.len() + decoded.len());
+    assert_eq!(statistics.encoded_bytes(), data.len() + data2.len());
+    assert_eq!(decoder.statistics().files(), 2);
+}
+
+#[test]
+fn test_api_errors() {
+    let error = Encoder::new()
+        .encode_source("function (")
+        .expect_err("Invalid source should not encode");
+    assert_eq!(error.kind(), ErrorKind::Parse);
+    assert_eq!(error.to_string(), "could not parse source");
+    // The details are chained.
+    assert!(error.source().is_some());
+
+    let error = Decoder::new()
+        .decode_to_source(b"not a binjs file")
+        .expect_err("Garbage should not decode");
+    assert_eq!(error.kind(), ErrorKind::Decode);
+    assert!(error.source().is_some());
+    assert_eq!(Decoder::new().statistics().files(), 0);
+
+    let error = Dictionary::from_bytes(b"not a dictionary")
+        .expect_err("Garbage should not load as a dictionary");
+    assert_eq!(error.kind(), ErrorKind::Dictionary);
+}
@@ -0,0 +1,94 @@
+//! Generate random ASTs from the grammar, encode them, then decode them
+//! into an arena, ensuring that we obtain the same AST.
+#![cfg(feature = "arena")]
+
+extern crate binjs;
+extern crate rand;
+
+use binjs::generic::{ FromJSON, Offset };
+use binjs::generic::pick::{ Pick, Picker };
+use binjs::io::Format;
+use binjs::meta::export::TypeDeanonymizer;
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::specialized::es6::arena::{ self, Bump, ToAST };
+use binjs::specialized::es6::ast::{ Script, Visitor, Walker, WalkPath };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::io::Cursor;
+use std::thread;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+/// The number of ASTs generated by a run.
+const NUMBER_OF_ASTS : usize = 20;
+
+/// The seed of the generator.
+const SEED : u64 = 0x6172656e61;
+
+/// The depth after which the generator produces the smallest possible subtrees.
+const DEPTH_LIMIT : isize = 6;
+
+/// A visitor designed to reset offsets to 0.
+struct OffsetCleanerVisitor;
+impl Visitor<()> for OffsetCleanerVisitor {
+    fn visit_offset(&mut self, _path: &WalkPath, node: &mut Offset) -> Result<(), ()> {
+        *node = binjs::generic::Offset(0);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_arena_roundtrip() {
+    thread::Builder::new()
+        .name("test_arena_roundtrip large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(SEED);
+
+    let mut builder = SpecBuilder::new();
+    let _ = binjs::generic::es6::Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Script"),
+    };
+    let spec = builder.into_spec(spec_options);
+    let spec = TypeDeanonymizer::new(&spec)
+        .into_spec(SpecOptions {
+            root: spec.get_root_name(),
+            null: spec.get_null_name(),
+        });
+
+    for i in 0..NUMBER_OF_ASTS {
+        let json = Picker.random(&spec, &mut rng, DEPTH_LIMIT);
+        let ast = Script::import(&json)
+            .unwrap_or_else(|err| panic!("Generated an invalid AST (AST {}): {:?}\n{:#}", i, err, json));
+
+        let multipart = Format::from_args(&["multipart"])
+            .expect("Could not parse format");
+        for format in &mut [Format::simple(), multipart] {
+            let data = Encoder::new()
+                .encode(format, &ast)
+                .unwrap_or_else(|err| panic!("Could not encode (AST {}, format {}): {:?}", i, format.name(), err));
+
+            let bump = Bump::new();
+            let decoded : arena::Script = Decoder::new()
+                .decode_in(format, Cursor::new((*data).as_ref()), &bump)
+                .unwrap_or_else(|err| panic!("Could not decode (AST {}, format {}): {:?}", i, format.name(), err));
+            let mut decoded = decoded.to_ast();
+
+            // Offsets are computed by the encoder, while they are 0 in `ast`.
+            decoded.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+                .expect("Could not cleanup offsets");
+            assert!(decoded == ast, "Roundtrip mismatch (AST {}, format {})", i, format.name());
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Encode a program to an `AsyncWrite`, then decode it from an `AsyncRead`.
+#![cfg(feature = "async")]
+
+extern crate binjs;
+extern crate futures;
+
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::async_io;
+use binjs::specialized::es6::ast::Program;
+
+use std::io::Cursor;
+
+use futures::Future;
+
+#[test]
+fn test_async_roundtrip() {
+    let parser = Shift::new();
+    let json = parser.parse_str("function foo(x) { return x + 1; } foo(\"bar\");")
+        .expect("Could not parse source");
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+
+    let mut format = Format::simple();
+    let dest = async_io::encode(&mut format, &ast, Cursor::new(Vec::new()))
+        .wait()
+        .expect("Could not encode");
+
+    let mut format = Format::simple();
+    let (_, decoded) = async_io::decode(&mut format, Cursor::new(dest.into_inner()))
+        .wait()
+        .expect("Could not decode");
+    assert_eq!(ast, decoded);
+}
@@ -0,0 +1,69 @@
+//! Store annotated ASTs in the cache used by `binjs_encode --cache-dir`.
+
+extern crate binjs;
+
+use binjs::cache::{ AnnotationCache, Eviction };
+use binjs::generic::FromJSON;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Program;
+
+#[test]
+fn test_cache() {
+    let dir = std::env::temp_dir()
+        .join(format!("binjs-test-cache-{}", std::process::id()));
+    let cache = AnnotationCache::new(&dir)
+        .expect("Could not create cache");
+
+    let source = "function foo(x) { return x * 2; } foo(21);";
+    let parser = Shift::new()
+        .with_positions(true);
+    let mut json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let positions = binjs::source::positions::collect(&mut json);
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+
+    // Keys depend on the source and on the parser.
+    let key = AnnotationCache::key(source.as_bytes(), "js");
+    assert_eq!(key, AnnotationCache::key(source.as_bytes(), "js"));
+    assert_ne!(key, AnnotationCache::key(b"foo(21);", "js"));
+    assert_ne!(key, AnnotationCache::key(source.as_bytes(), "ts"));
+
+    assert!(cache.get(&key).is_none());
+    cache.insert(&key, &ast, &positions)
+        .expect("Could not insert entry");
+    let (cached_ast, cached_positions) = cache.get(&key)
+        .expect("Missing entry");
+    assert_eq!(cached_ast, ast);
+    assert_eq!(cached_positions, positions);
+
+    // Unreadable entries are ignored.
+    let other = AnnotationCache::key(b"foo(21);", "js");
+    std::fs::write(dir.join(format!("{}.binjs", other)), b"BINJS")
+        .expect("Could not write entry");
+    assert!(cache.get(&other).is_none());
+
+    // Nothing is evicted without limits.
+    let eviction = cache.evict()
+        .expect("Could not evict");
+    assert_eq!(eviction.removed_entries, 0);
+    assert_eq!(eviction.remaining_entries, 1);
+
+    let eviction = AnnotationCache::new(&dir)
+        .expect("Could not open cache")
+        .with_max_bytes(Some(0))
+        .evict()
+        .expect("Could not evict");
+    assert_eq!(eviction, Eviction {
+        removed_entries: 1,
+        removed_bytes: eviction.removed_bytes,
+        remaining_entries: 0,
+        remaining_bytes: 0,
+    });
+    assert!(cache.get(&key).is_none());
+
+    std::fs::remove_dir_all(&dir)
+        .expect("Could not remove cache");
+}
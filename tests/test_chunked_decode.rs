@@ -0,0 +1,89 @@
+//! Decode the toplevel of a chunked file before its lazy functions have been received.
+
+extern crate binjs;
+
+use binjs::generic::FromJSON;
+use binjs::io::{ CompressionTarget, Deserialization, Format };
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{ Options, Statistics, Targets, TreeTokenReader };
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::{ Program, WalkPath, Walker };
+use binjs::specialized::es6::io::{ Decoder, Deserializer, Encoder, IOPath };
+use binjs::specialized::es6::lazy::{ LazifierVisitor, Policy };
+use binjs::specialized::es6::parallel::{ decode_subtree, Stitcher };
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+#[test]
+fn test_chunked_decode() {
+    let parser = Shift::new();
+    let source = "
+        function foo(a, b) { var x = 'foo'; return function() { return x + a + b; } }
+        var bar = function baz() { return 'bar'; };
+        var obj = { get qux() { return 1; }, set qux(v) {}, method(c) { return () => c; } };
+        foo(1, 2)();
+    ";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::with_policy(Policy::all(), vec![]))
+        .expect("Could not introduce laziness");
+
+    let mut format = Format::Multipart {
+        targets: Targets {
+            grammar_table: CompressionTarget::new(Compression::Identity),
+            strings_table: CompressionTarget::new(Compression::Identity),
+            tree: CompressionTarget::new(Compression::Gzip),
+        },
+        stats: Rc::new(RefCell::new(Statistics::default())),
+        options: Options {
+            chunks: true,
+            ..Options::default()
+        },
+    };
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let data : &[u8] = (*data).as_ref();
+
+    // The file is decoded entirely as any other file.
+    let expected : Program = Decoder::new()
+        .decode(&mut format, Cursor::new(data))
+        .expect("Could not decode");
+
+    // Fetch the toplevel chunk only.
+    let index = TreeTokenReader::chunk_index(data)
+        .expect("Could not read chunk index")
+        .expect("The file is chunked");
+    let prefix = &data[..index.chunks[0].end as usize];
+    let reader = TreeTokenReader::with_chunks(prefix, &Options::default())
+        .expect("Could not create reader");
+    let mut deserializer = Deserializer::new(reader);
+    let mut partial : Program = deserializer.deserialize(&mut IOPath::new())
+        .expect("Could not decode toplevel");
+    assert!(partial != expected);
+
+    // All the chunks but the toplevel chunk are missing.
+    let missing = deserializer.reader.missing_chunks();
+    assert!(!missing.is_empty());
+    assert_eq!(index.chunks.len(), missing.len() + 1);
+
+    // Fetch the rest.
+    let mut contents = vec![];
+    for missing in &missing {
+        let range = index.chunks[missing.chunk].clone();
+        let subtree = deserializer.reader.receive_chunk(missing, &data[range.start as usize..range.end as usize])
+            .expect("Could not receive chunk");
+        contents.push(decode_subtree(&mut deserializer, &subtree)
+            .expect("Could not decode chunk"));
+    }
+    Stitcher::new(contents)
+        .stitch(&mut partial)
+        .expect("Could not stitch chunks");
+    assert_eq!(partial, expected);
+}
@@ -0,0 +1,32 @@
+//! Parse sources in long-lived Node processes.
+
+extern crate binjs;
+
+use binjs::source::{ DaemonPool, Shift, SourceParser };
+
+use std::sync::Arc;
+
+#[test]
+fn test_daemon() {
+    let daemons = Arc::new(DaemonPool::new("node", 1));
+    let parser = Shift::new()
+        .with_daemons(Some(daemons));
+    let one_shot = Shift::new();
+
+    let sources = [
+        "function foo(x) { return x * 2; } foo(21);",
+        "var s = \"line\\nbreak\"; /* comment */ s += '\\u2028';",
+        "class Foo { get bar() { return 1; } }",
+    ];
+    for source in &sources {
+        let parsed = parser.parse_str(source)
+            .expect("Could not parse source with daemon");
+        let expected = one_shot.parse_str(source)
+            .expect("Could not parse source");
+        assert_eq!(parsed, expected);
+    }
+
+    // Syntax errors are reported, and do not affect the next sources.
+    assert!(parser.parse_str("function (").is_err());
+    assert!(parser.parse_str(sources[0]).is_ok());
+}
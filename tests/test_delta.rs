@@ -0,0 +1,66 @@
+//! Compute and apply deltas between two versions of a module.
+
+extern crate binjs;
+
+use binjs::delta::Delta;
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::io::entropy::adaptive::Options;
+use binjs::source::{ Shift, SourceParser, SourceType };
+use binjs::specialized::es6::ast::Program;
+use binjs::specialized::es6::io::Encoder;
+
+fn parse(source: &str) -> Program {
+    let json = Shift::new()
+        .with_source_type(SourceType::Module)
+        .parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+    ast
+}
+
+fn function(i: usize) -> String {
+    format!("export function handler_{i}(event) {{ if (event.kind === 'kind_{kind}') {{ return log('handled', {i}, event.target); }} return null; }}\n",
+        i = i,
+        kind = i % 5)
+}
+
+#[test]
+fn test_delta_module() {
+    let old_source : String = (0..40)
+        .map(function)
+        .collect();
+    let old = parse(&format!("import {{ log }} from 'log';\n{}", old_source));
+
+    // Edit a function, add another one and move a few.
+    let mut functions : Vec<_> = (0..40)
+        .map(function)
+        .collect();
+    functions[3] = functions[3].replace("'handled'", "'processed'");
+    functions.insert(20, function(100));
+    let moved : Vec<_> = functions.drain(30..35).collect();
+    functions.splice(5..5, moved);
+    let new_source : String = functions.concat();
+    let new = parse(&format!("import {{ log }} from 'log';\n{}", new_source));
+
+    let delta = Delta::new(Options::default());
+    let data = delta.diff(&old, &new)
+        .expect("Could not compute delta");
+    assert_eq!(delta.apply(&old, &data).expect("Could not apply delta"), new);
+
+    // The delta is much smaller than the new version, encoded with the same options.
+    let mut format = Format::AdaptiveEntropy {
+        options: Options::default(),
+    };
+    let encoded = Encoder::new()
+        .encode(&mut format, &new)
+        .expect("Could not encode");
+    let encoded : &[u8] = (*encoded).as_ref();
+    assert!(data.len() * 4 < encoded.len(), "Delta of {} bytes, file of {} bytes", data.len(), encoded.len());
+
+    // The delta may not be applied to another version.
+    assert!(delta.apply(&new, &data).is_err());
+}
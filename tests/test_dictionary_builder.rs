@@ -180,6 +180,199 @@ test!(test_entropy_roundtrip, {
     }
 });
 
+test!(test_entropy_shared_dictionary, {
+    use std::sync::Arc;
+
+    let parser = Shift::new();
+    let sources = [
+        "var x = y",
+        "function foo(x, y) { return x + y; }",
+        "foo(1, 2); foo('a', 'b');",
+    ];
+
+    let mut dictionary = Dictionary::new(3, 32);
+    let mut files_containing_string = KindedStringMap::default();
+    let mut scripts = Vec::new();
+    for source in &sources {
+        let ast  = parser.parse_str(source)
+            .expect("Could not parse source");
+        let mut ast = binjs::specialized::es6::ast::Script::import(&ast)
+            .expect("Could not import AST");
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_script(&mut ast);
+
+        let builder = DictionaryBuilder::new(&mut dictionary, &mut files_containing_string);
+        let mut serializer = binjs::specialized::es6::io::Serializer::new(builder);
+        serializer.serialize(&ast, &mut IOPath::new())
+            .expect("Could not walk");
+        let _ = serializer.done()
+            .expect("Could not walk");
+        scripts.push(ast);
+    }
+
+    // Load the dictionary once, share it between all threads.
+    let shared = Arc::new(dictionary.instances_to_probabilities("dictionary"));
+
+    let encoded : Vec<_> = scripts.iter()
+        .map(|ast| {
+            let encoder = entropy::write::Encoder::new(entropy::Options::with_shared_dictionary(shared.clone()));
+            let mut serializer = binjs::specialized::es6::io::Serializer::new(encoder);
+            serializer.serialize(ast, &mut IOPath::new())
+                .expect("Could not walk");
+            serializer.done()
+                .expect("Could not walk")
+        })
+        .collect();
+
+    println!("Decoding concurrently");
+    let threads : Vec<_> = (0..4)
+        .map(|_| {
+            let shared = shared.clone();
+            let encoded = encoded.clone();
+            let scripts = scripts.clone();
+            std::thread::spawn(move || {
+                for (data, ast) in encoded.into_iter().zip(scripts) {
+                    let options = entropy::Options::with_shared_dictionary(shared.clone());
+                    let decoder = entropy::read::Decoder::new(options, std::io::Cursor::new(data))
+                        .expect("Could not create decoder");
+                    let mut deserializer = binjs::specialized::es6::io::Deserializer::new(decoder);
+                    let mut script : Script = deserializer.deserialize(&mut IOPath::new())
+                        .expect("Could not deserialize");
+                    script.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+                        .expect("Could not cleanup offsets");
+                    assert_eq!(ast, script);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join()
+            .expect("Decoding thread panicked");
+    }
+});
+
+test!(test_entropy_fallback, {
+    let parser = Shift::new();
+    let parse = |source: &str| {
+        let ast  = parser.parse_str(source)
+            .expect("Could not parse source");
+        let mut ast = binjs::specialized::es6::ast::Script::import(&ast)
+            .expect("Could not import AST");
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_script(&mut ast);
+        ast
+    };
+
+    let mut dictionary = Dictionary::new(3, 32);
+    let mut files_containing_string = KindedStringMap::default();
+    {
+        let builder = DictionaryBuilder::new(&mut dictionary, &mut files_containing_string);
+        let mut serializer = binjs::specialized::es6::io::Serializer::new(builder);
+        serializer.serialize(&parse("var x = y"), &mut IOPath::new())
+            .expect("Could not walk");
+        let _ = serializer.done()
+            .expect("Could not walk");
+    }
+    let sample = dictionary.clone();
+    let probability_tables = dictionary.instances_to_probabilities("dictionary");
+
+    // Same structure, identifier names missing from the dictionary.
+    let sources = ["var z = w", "var z = y", "var x = z"];
+    for options in vec![
+        entropy::Options::new(probability_tables.clone()),
+        entropy::Options::new(probability_tables.clone()).with_recency(4)
+    ] {
+        let options = options.with_fallback();
+        for source in &sources {
+            let ast = parse(source);
+            let encoder = entropy::write::Encoder::new(options.clone());
+            let mut serializer = binjs::specialized::es6::io::Serializer::new(encoder);
+            serializer.serialize(&ast, &mut IOPath::new())
+                .expect("Could not walk");
+            let data = serializer.done()
+                .expect("Could not walk");
+
+            // The fallback section and the recency window are announced by the header.
+            let decoder = entropy::read::Decoder::new(entropy::Options::new(probability_tables.clone()), std::io::Cursor::new(data))
+                .expect("Could not create decoder");
+            let mut deserializer = binjs::specialized::es6::io::Deserializer::new(decoder);
+            let mut script : Script = deserializer.deserialize(&mut IOPath::new())
+                .expect("Could not deserialize");
+            script.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+                .expect("Could not cleanup offsets");
+            assert_eq!(ast, script);
+        }
+
+        let statistics = options.fallback_statistics_for_write()
+            .expect("Fallback statistics are available");
+        assert_eq!(statistics.files, sources.len());
+        let values : std::collections::HashSet<_> = statistics.strings.keys()
+            .map(|fallback| fallback.value.as_str())
+            .collect();
+        let expected : std::collections::HashSet<_> = ["w", "z"].iter()
+            .cloned()
+            .collect();
+        assert_eq!(values, expected);
+
+        // `z` appears in all files, so it is the most profitable addition.
+        let suggestions = statistics.suggestions(&sample);
+        assert_eq!(suggestions[0].fallback.value.as_str(), "z");
+    }
+});
+
+test!(test_entropy_bounds, {
+    let parser = Shift::new();
+    let sources = [
+        "var x = y",
+        "function foo(x, y) { return x + y; }",
+        "foo(1, 2); foo('a', 'b');",
+    ];
+
+    let mut dictionary = Dictionary::new(3, 32);
+    let mut files_containing_string = KindedStringMap::default();
+    let mut programs = Vec::new();
+    for source in &sources {
+        let ast  = parser.parse_str(source)
+            .expect("Could not parse source");
+        let mut ast = binjs::specialized::es6::ast::Program::import(&ast)
+            .expect("Could not import AST");
+        binjs::specialized::es6::scopes::AnnotationVisitor::new()
+            .annotate_program(&mut ast);
+
+        let builder = DictionaryBuilder::new(&mut dictionary, &mut files_containing_string);
+        let mut serializer = binjs::specialized::es6::io::Serializer::new(builder);
+        serializer.serialize(&ast, &mut IOPath::new())
+            .expect("Could not walk");
+        let _ = serializer.done()
+            .expect("Could not walk");
+        programs.push(ast);
+    }
+
+    let options = entropy::Options::new(dictionary.instances_to_probabilities("dictionary"));
+    let bounds = binjs::bounds::entropy_bounds(&options, &programs)
+        .expect("Could not compute bounds");
+    assert!(bounds.interface_names.symbols > 0);
+    assert!(bounds.interface_names.bound_bits > 0.);
+    assert!(bounds.interface_names.actual_bytes > 0);
+    for (name, bound) in bounds.iter() {
+        assert!(bound.bound_bits >= 0., "Negative bound for {}", name);
+        if bound.symbols == 0 {
+            assert_eq!(bound.bound_bits, 0., "Bound without symbols for {}", name);
+        }
+    }
+    assert!(format!("{}", bounds).contains("interface_names: symbols"));
+
+    // Each call only covers its own corpus, and leaves the statistics of the options untouched.
+    let twice = binjs::bounds::entropy_bounds(&options, &programs)
+        .expect("Could not compute bounds");
+    for ((name, first), (_, second)) in bounds.iter().zip(twice.iter()) {
+        assert_eq!(first.symbols, second.symbols, "Different symbols for {}", name);
+        assert_eq!(first.actual_bytes, second.actual_bytes, "Different bytes for {}", name);
+        assert!((first.bound_bits - second.bound_bits).abs() < 1e-6, "Different bounds for {}", name);
+    }
+    assert_eq!(options.entropy_bounds_for_write().interface_names.symbols, 0);
+});
+
 fn check_strings<T, F>(found: &HashMap<T, FilesContaining>, expected: Vec<(&str, usize)>, f: F)
     where
         F: Fn(&str) -> T,
@@ -0,0 +1,36 @@
+//! Export ASTs as DOT graphs.
+
+extern crate binjs;
+
+use binjs::source::{ Shift, SourceParser };
+use binjs::util::dot::{ select, DotExporter };
+
+#[test]
+fn test_dot() {
+    let json = Shift::new()
+        .parse_str("foo(1, \"bar\");")
+        .expect("Could not parse source");
+
+    let call = select(&json, "statements[0].expression")
+        .expect("Could not select call");
+    assert_eq!(call["type"], "CallExpression");
+    assert!(select(&json, "statements[1]").is_err());
+    assert!(select(&json, "statement").is_err());
+
+    let dot = DotExporter::new()
+        .export(call);
+    assert!(dot.starts_with("digraph ast {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains("[label=\"callee\"]"));
+    assert!(dot.contains("[label=\"[1]\"]"));
+    assert!(dot.contains("value = \\\"bar\\\""));
+    assert!(!dot.contains("hidden"));
+
+    // Truncated at the root.
+    let dot = DotExporter::new()
+        .with_max_depth(Some(0))
+        .export(call);
+    assert!(dot.contains("(2 children hidden)"));
+    assert!(dot.contains("style=dashed"));
+    assert!(!dot.contains("->"));
+}
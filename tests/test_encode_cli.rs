@@ -0,0 +1,128 @@
+//! Run `binjs_encode` on files that fail at different steps, checking its exit code
+//! and, with `--keep-going`, its report of failures.
+
+extern crate serde_json;
+
+use std::path::{ Path, PathBuf };
+use std::process::{ Command, Output };
+
+const PARSER: &str = "node tests/data/external/shift_parser.js";
+
+/// A fresh directory for the files of test `name`.
+fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("binjs-test-encode-cli-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir)
+        .expect("Could not create directory");
+    dir
+}
+
+/// Run `binjs_encode` from the root of the repository, where the parsers are installed.
+fn encode(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_binjs_encode"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .arg("--quiet")
+        .args(args)
+        .output()
+        .expect("Could not launch binjs_encode")
+}
+
+fn write(dir: &Path, name: &str, source: &str) -> String {
+    let path = dir.join(name);
+    std::fs::write(&path, source)
+        .expect("Could not write source");
+    path.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_exit_codes() {
+    let dir = test_dir("exit-codes");
+    let out = dir.join("out");
+    let out = out.to_str()
+        .unwrap();
+    let valid = write(&dir, "valid.js", "function foo(x) { return x * 2; } foo(21);");
+    let syntax_error = write(&dir, "syntax_error.js", "function (");
+    let missing = dir.join("missing.js");
+    let missing = missing.to_str()
+        .unwrap();
+
+    let output = encode(&["--in", &valid, "--out", out]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(dir.join("out/valid.binjs").exists());
+
+    // Parse errors.
+    let output = encode(&["--in", &syntax_error, "--out", out]);
+    assert_eq!(output.status.code(), Some(2));
+
+    // Positions that do not match the AST are only reattached, and rejected, to
+    // find the functions to lazify.
+    let parser = format!("{} --bad-positions", PARSER);
+    let output = encode(&["--in", &valid, "--out", out, "--parser-cmd", &parser, "--lazify", "min-bytes=0"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    // I/O errors.
+    let output = encode(&["--in", missing, "--out", out]);
+    assert_eq!(output.status.code(), Some(4));
+
+    // Without `--keep-going`, the first failure stops the run.
+    std::fs::remove_dir_all(dir.join("out"))
+        .expect("Could not remove output");
+    let output = encode(&["--in", &syntax_error, "--in", &valid, "--out", out]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(!dir.join("out/valid.binjs").exists());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_keep_going() {
+    let dir = test_dir("keep-going");
+    let out = dir.join("out");
+    let out = out.to_str()
+        .unwrap();
+    let report_path = dir.join("report.json");
+    let report_path = report_path.to_str()
+        .unwrap();
+    let syntax_error = write(&dir, "syntax_error.js", "function (");
+    let valid = write(&dir, "valid.js", "var x = 1;");
+    let missing = dir.join("missing.js");
+    let missing = missing.to_str()
+        .unwrap();
+
+    let output = encode(&["--keep-going", "--error-report", report_path,
+        "--in", &syntax_error, "--in", &valid, "--in", missing, "--out", out]);
+
+    // The exit code is that of the first failure, and the other files are encoded.
+    assert_eq!(output.status.code(), Some(2));
+    assert!(dir.join("out/valid.binjs").exists());
+
+    let report : serde_json::Value = serde_json::from_slice(&std::fs::read(report_path)
+        .expect("Could not read report"))
+        .expect("Could not parse report");
+    let failures = report.as_array()
+        .expect("The report should be a list");
+    assert_eq!(failures.len(), 2);
+    assert_eq!(failures[0]["file"], syntax_error.as_str());
+    assert_eq!(failures[0]["phase"], "parse");
+    assert!(failures[0]["error"].as_str().map_or(false, |error| !error.is_empty()));
+    assert_eq!(failures[1]["file"], missing);
+    assert_eq!(failures[1]["phase"], "io");
+    assert!(failures[1]["error"].as_str().map_or(false, |error| !error.is_empty()));
+
+    // Without `--error-report`, the report is written to stderr.
+    let output = encode(&["--keep-going", "--in", &valid, "--in", missing, "--out", out]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("\"phase\": \"io\""), "Unexpected stderr {}", stderr);
+
+    // Without failures, the report is empty and the run succeeds.
+    let output = encode(&["--keep-going", "--error-report", report_path, "--in", &valid, "--out", out]);
+    assert_eq!(output.status.code(), Some(0));
+    let report : serde_json::Value = serde_json::from_slice(&std::fs::read(report_path)
+        .expect("Could not read report"))
+        .expect("Could not parse report");
+    assert_eq!(report.as_array().map(Vec::len), Some(0));
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
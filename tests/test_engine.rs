@@ -0,0 +1,56 @@
+//! Encode JavaScript sources, decode them, then run both the original and the
+//! roundtripped sources with a JS engine, checking that they behave the same.
+//!
+//! Requires a JS engine, hence the feature. The engine defaults to `node` and may be
+//! specified with environment variable `BINJS_TEST_ENGINE`, e.g. `d8` or `js`.
+#![cfg(feature = "engine-tests")]
+
+extern crate binjs;
+extern crate glob;
+
+use binjs::io::{ CompressionTarget, Format };
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{ Options, Statistics, Targets };
+use binjs::source::Shift;
+use binjs::util::engine::{ compare, Engine };
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const PATH : &'static str = "tests/data/engine/*.js";
+
+#[test]
+fn test_engine_roundtrip() {
+    let engine = Engine::new(&std::env::var("BINJS_TEST_ENGINE")
+        .unwrap_or_else(|_| "node".to_string()));
+    let parser = Shift::new();
+
+    let mut formats = vec![
+        Format::simple(),
+        Format::Multipart {
+            targets: Targets {
+                grammar_table: CompressionTarget::new(Compression::Identity),
+                strings_table: CompressionTarget::new(Compression::Identity),
+                tree: CompressionTarget::new(Compression::Identity),
+            },
+            stats: Rc::new(RefCell::new(Statistics::default())),
+            options: Options::default(),
+        },
+    ];
+
+    let path = format!("{}/{}", env!("CARGO_MANIFEST_DIR"), PATH);
+    for entry in glob::glob(&path)
+        .expect("Invalid glob pattern")
+    {
+        let entry = entry.expect("Invalid entry");
+        for format in &mut formats {
+            let comparison = compare(&engine, &parser, format, &entry)
+                .expect("Could not compare");
+            assert_eq!(comparison.original_outcome.status, Some(0),
+                "{:?} failed with the original source: {}", entry, comparison.original_outcome.stderr);
+            assert!(comparison.is_same(),
+                "{:?} behaves differently after a roundtrip with format {}.\nOriginal: {:?}\nRoundtripped: {:?}\nRoundtripped source:\n{}",
+                entry, format.name(), comparison.original_outcome, comparison.roundtripped_outcome, comparison.roundtripped);
+        }
+    }
+}
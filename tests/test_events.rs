@@ -0,0 +1,97 @@
+//! Read a file as a stream of events, ensure that reading it with the grammar
+//! loaded at runtime, without building an AST, reports the same events as the
+//! compiled deserializer.
+
+extern crate binjs;
+
+use binjs::generic::{ FromJSON, InterfaceName, SharedString };
+use binjs::io::{ Format, GrammarId, Path, TokenReaderError };
+use binjs::io::events::EventHandler;
+use binjs::meta::import::Importer;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::{ Script, Walker, WalkPath };
+use binjs::specialized::es6::io::{ grammar_id, Decoder, Encoder };
+
+use std::io::{ Cursor, Read };
+
+fn load_spec() -> binjs::meta::spec::Spec {
+    let mut source = String::new();
+    std::fs::File::open("spec/es6.webidl")
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .expect("Could not read grammar");
+    Importer::load(&source, "Script")
+        .expect("Could not parse grammar")
+}
+
+/// Record the structure of the tree and its strings.
+#[derive(Default)]
+struct Recorder {
+    events: Vec<String>,
+}
+impl EventHandler for Recorder {
+    fn enter_interface(&mut self, name: &InterfaceName, path: &Path) -> Result<(), TokenReaderError> {
+        self.events.push(format!("{} {}", path.len(), name.as_str()));
+        Ok(())
+    }
+    fn enter_list(&mut self, len: u32, path: &Path) -> Result<(), TokenReaderError> {
+        self.events.push(format!("{} [{}]", path.len(), len));
+        Ok(())
+    }
+    fn string(&mut self, value: Option<&SharedString>, path: &Path) -> Result<(), TokenReaderError> {
+        self.events.push(format!("{} {:?}", path.len(), value.map(SharedString::as_str)));
+        Ok(())
+    }
+    fn offset(&mut self, _byte_len: u32, skipped: bool, path: &Path) -> Result<(), TokenReaderError> {
+        self.events.push(format!("{} offset, skipped: {}", path.len(), skipped));
+        Ok(())
+    }
+}
+
+#[test]
+fn test_events() {
+    let parser = Shift::new();
+    let json = parser.parse_str("function foo(x) { return x + \"bar\"; } foo(1);")
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+    ast.walk(&mut WalkPath::new(), &mut binjs::specialized::es6::lazy::LazifierVisitor::new(1))
+        .expect("Could not introduce laziness");
+
+    let data = Encoder::new()
+        .encode(&mut Format::simple(), &ast)
+        .expect("Could not encode");
+    let data = (*data).as_ref().to_vec();
+
+    let (decoded, specialized) : (Script, Recorder) = Decoder::new()
+        .decode_with_events(&mut Format::simple(), Cursor::new(&data), Recorder::default())
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+    assert_eq!(specialized.events[0], "0 Script");
+    assert!(specialized.events.iter().any(|event| event.ends_with("Some(\"bar\")")));
+    assert!(specialized.events.iter().any(|event| event.ends_with("offset, skipped: false")));
+
+    let generic = binjs::generic::io::EventDecoder::new(&load_spec(), grammar_id())
+        .decode(&mut Format::simple(), Cursor::new(&data), Recorder::default())
+        .expect("Could not read with the runtime grammar");
+    assert_eq!(generic.events, specialized.events);
+
+    // Files declaring another grammar are rejected.
+    let mut multipart = Format::from_args(&["multipart"])
+        .expect("Could not parse format");
+    let data = Encoder::new()
+        .encode(&mut multipart, &ast)
+        .expect("Could not encode");
+    binjs::generic::io::EventDecoder::new(&load_spec(), grammar_id())
+        .decode(&mut multipart, Cursor::new((*data).as_ref()), Recorder::default())
+        .expect("Could not read with the runtime grammar");
+    let other = GrammarId::new("other", 0);
+    match binjs::generic::io::EventDecoder::new(&load_spec(), other)
+        .decode(&mut multipart, Cursor::new((*data).as_ref()), Recorder::default())
+    {
+        Err(TokenReaderError::UnsupportedGrammar(ref grammar)) => assert_eq!(*grammar, grammar_id()),
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Reading a file declaring another grammar should fail")
+    }
+}
@@ -0,0 +1,76 @@
+//! Explain an encoded file, ensure that the costs add up and that the
+//! expensive parts of the source are reported.
+
+extern crate binjs;
+
+use binjs::explain::Explanation;
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Encoder;
+
+fn encode(source: &str, args: &[&str]) -> Vec<u8> {
+    let parser = Shift::new();
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let mut format = Format::from_args(args)
+        .expect("Could not parse format");
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    (*data).as_ref().to_vec()
+}
+
+#[test]
+fn test_explain() {
+    let source = "function small() { return 1; }
+        function large(a, b, c) { return [a + b, b * c, c - a, a / b, \"foo\", \"foo\", \"foo\"]; }
+        small(large(1, 2, 3));";
+    for compression in &["identity", "br"] {
+        let args = ["multipart", "--section-compression", compression];
+        let data = encode(source, &args);
+        let explanation = Explanation::new(&data, 20)
+            .expect("Could not explain file");
+
+        assert_eq!(explanation.file_bytes, data.len());
+        assert_eq!(explanation.sections.iter().map(|section| section.bytes).sum::<usize>(), data.len());
+        assert_eq!(explanation.tree_compressed, *compression == "br");
+        assert!(explanation.tree_bytes > 0);
+
+        // The largest function, or one of its subtrees, is reported before the
+        // smallest one, if at all.
+        assert!(explanation.subtrees.len() <= 20);
+        let large = explanation.subtrees.iter()
+            .position(|subtree| subtree.label.starts_with("statements[1]"))
+            .expect("Missing subtree");
+        if let Some(small) = explanation.subtrees.iter()
+            .position(|subtree| subtree.label.starts_with("statements[0]"))
+        {
+            assert!(small > large);
+        }
+        for pair in explanation.subtrees.windows(2) {
+            assert!(pair[0].bytes >= pair[1].bytes);
+        }
+
+        let foo = explanation.strings.iter()
+            .find(|string| string.label == "\"foo\"")
+            .expect("Missing string");
+        assert_eq!(foo.count, 3);
+
+        let categories : usize = explanation.categories.iter()
+            .map(|category| category.bytes)
+            .sum();
+        assert!(categories <= explanation.tree_bytes);
+        let text = format!("{}", explanation);
+        assert!(text.contains("Most expensive subtrees"));
+
+        // Costs in a compressed tree are labelled as such.
+        assert_eq!(text.contains("uncompressed bytes"), explanation.tree_compressed);
+    }
+}
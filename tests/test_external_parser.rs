@@ -0,0 +1,67 @@
+//! Parse sources with an external parser, through the protocol of `source::external`.
+
+extern crate binjs;
+
+use binjs::source::{ External, Shift, SourceParser, SourceType };
+use binjs::source::external::ASTFormat;
+use binjs::source::shift::Error;
+
+const PARSER: &str = "node tests/data/external/shift_parser.js";
+
+#[test]
+fn test_external_parser() {
+    let parser = External::new(PARSER);
+    let shift = Shift::new();
+
+    let capabilities = parser.capabilities()
+        .expect("Could not launch parser");
+    assert_eq!(capabilities.ast, ASTFormat::Shift);
+    assert_eq!(capabilities.goals, vec![SourceType::Script, SourceType::Module]);
+
+    let sources = [
+        "function foo(x) { return x * 2; } foo(21);",
+        "var s = \"line\\nbreak\"; /* comment */ s += '\\u2028' + '\\uD800';",
+        "class Foo { get bar() { return 1; } }",
+    ];
+    for source in &sources {
+        let parsed = parser.parse_str(source)
+            .expect("Could not parse source with external parser");
+        let expected = shift.parse_str(source)
+            .expect("Could not parse source");
+        assert_eq!(parsed, expected);
+    }
+
+    // Syntax errors are structured, and do not affect the next sources.
+    match parser.parse_str("function (") {
+        Err(Error::SyntaxError { line, .. }) => assert_eq!(line, Some(1)),
+        other => panic!("Unexpected result {:?}", other),
+    }
+    assert!(parser.parse_str(sources[0]).is_ok());
+}
+
+#[test]
+fn test_external_parser_goals() {
+    let parser = External::new(&format!("{} --no-module", PARSER))
+        .with_source_type(SourceType::Module);
+    match parser.capabilities() {
+        Err(Error::UnsupportedGoal(ref goal)) => assert_eq!(goal, "module"),
+        other => panic!("Unexpected result {:?}", other),
+    }
+    assert!(parser.parse_str("foo();").is_err());
+
+    let parser = External::new(PARSER)
+        .with_source_type(SourceType::Module);
+    let ast = parser.parse_str("export default 1;")
+        .expect("Could not parse module");
+    assert_eq!(ast["type"], "Module");
+
+    // With `SourceType::Auto`, modules are detected by their content.
+    let parser = External::new(PARSER)
+        .with_source_type(SourceType::Auto);
+    let ast = parser.parse_str("import foo from \"foo\"; foo();")
+        .expect("Could not parse module");
+    assert_eq!(ast["type"], "Module");
+    let ast = parser.parse_str("foo();")
+        .expect("Could not parse script");
+    assert_eq!(ast["type"], "Script");
+}
@@ -0,0 +1,74 @@
+//! Encode a script following the ES6 grammar loaded at runtime, ensure that we
+//! obtain the same file as with the grammar compiled into the encoder.
+
+extern crate binjs;
+
+use binjs::generic::{ FromJSON, ToJSON };
+use binjs::io::{ Compression, CompressionTarget, Format };
+use binjs::io::multipart::{ Options, Statistics, Targets };
+use binjs::meta::import::Importer;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::cell::RefCell;
+use std::io::{ Cursor, Read };
+use std::rc::Rc;
+
+fn load_spec() -> binjs::meta::spec::Spec {
+    let mut source = String::new();
+    std::fs::File::open("spec/es6.webidl")
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .expect("Could not read grammar");
+    Importer::load(&source, "Script")
+        .expect("Could not parse grammar")
+}
+
+fn multipart() -> Format {
+    Format::Multipart {
+        targets: Targets {
+            strings_table: CompressionTarget::new(Compression::Identity),
+            grammar_table: CompressionTarget::new(Compression::Identity),
+            tree: CompressionTarget::new(Compression::Identity),
+        },
+        stats: Rc::new(RefCell::new(Statistics::default()
+            .with_source_bytes(0))),
+        options: Options::default(),
+    }
+}
+
+#[test]
+fn test_generic_encoder() {
+    let parser = Shift::new();
+    let json = parser.parse_str("function foo(x) { return x + 1; } foo(\"bar\");")
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let spec = load_spec();
+    let generic = binjs::generic::io::Encoder::new(&spec, "es6");
+    assert_eq!(generic.grammar(), &binjs::specialized::es6::io::grammar_id());
+
+    let specialized = Encoder::new()
+        .encode(&mut Format::simple(), &ast)
+        .expect("Could not encode with the compiled grammar");
+    let dynamic = generic
+        .encode(&mut Format::simple(), &ast.export())
+        .expect("Could not encode with the runtime grammar");
+    assert_eq!((*specialized).as_ref(), (*dynamic).as_ref());
+
+    // Files declare the runtime grammar, which the specialized decoder accepts.
+    let dynamic = generic
+        .encode(&mut multipart(), &ast.export())
+        .expect("Could not encode with the runtime grammar");
+    let _ : Script = Decoder::new()
+        .decode(&mut multipart(), Cursor::new((*dynamic).as_ref().to_vec()))
+        .expect("Could not decode");
+
+    // Values that do not match the grammar are rejected.
+    let mut json = ast.export();
+    json["statements"] = "not a list".into();
+    assert!(generic.encode(&mut Format::simple(), &json).is_err());
+}
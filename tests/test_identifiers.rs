@@ -0,0 +1,110 @@
+//! Reject identifier names that are not valid ECMAScript IdentifierNames when encoding.
+
+extern crate binjs;
+
+use binjs::generic::{ FromJSON, IdentifierName, PropertyKey, VisitMe };
+use binjs::ErrorKind;
+use binjs::io::Format;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::{ BindingIdentifier, Script, StaticMemberExpression, Visitor, Walker, WalkPath };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+use binjs::specialized::es6::scopes::AnnotationVisitor;
+
+use std::error::Error;
+use std::io::Cursor;
+
+/// A visitor renaming all bindings `foo` to `foo bar`, which is not a valid identifier.
+struct InvalidRenamer;
+impl Visitor<()> for InvalidRenamer {
+    fn enter_binding_identifier(&mut self, _path: &WalkPath, node: &mut BindingIdentifier) -> Result<VisitMe<()>, ()> {
+        if node.name == "foo" {
+            node.name = IdentifierName::from_str("foo bar");
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+}
+
+/// A visitor renaming all properties `bar` to `bar baz`, which is not a valid identifier.
+struct InvalidPropertyRenamer;
+impl Visitor<()> for InvalidPropertyRenamer {
+    fn enter_static_member_expression(&mut self, _path: &WalkPath, node: &mut StaticMemberExpression) -> Result<VisitMe<()>, ()> {
+        if node.property == "bar" {
+            node.property = PropertyKey::from_str("bar baz");
+        }
+        Ok(VisitMe::HoldThis(()))
+    }
+}
+
+#[test]
+fn test_invalid_identifiers() {
+    let parser = Shift::new();
+    let json = parser.parse_str("var foo = 1; var $\u{e9}t\u{e9}_2 = foo;")
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let mut format = Format::simple();
+    Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Valid identifiers should be encoded");
+
+    ast.walk(&mut WalkPath::new(), &mut InvalidRenamer)
+        .expect("Could not rename");
+    match Encoder::new().encode(&mut format, &ast) {
+        Err(ref err) if err.kind() == ErrorKind::Encode => {
+            let message = err.source()
+                .expect("Missing cause")
+                .to_string();
+            assert!(message.starts_with("invalid identifier name: \"foo bar\" at "), "Unexpected message {}", message);
+            assert!(message.contains("BindingIdentifier"), "The message should contain the path, got {}", message);
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Invalid identifiers should not be encoded"),
+    }
+
+    // The escape hatch.
+    let data = Encoder::new()
+        .with_invalid_identifiers(true)
+        .encode(&mut format, &ast)
+        .expect("Invalid identifiers should be encoded when allowed");
+    let decoded : Script = Decoder::new()
+        .decode(&mut format, Cursor::new((*data).as_ref()))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+}
+
+#[test]
+fn test_invalid_property_keys() {
+    let parser = Shift::new();
+    let json = parser.parse_str("var foo = {}; foo.bar;")
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let mut format = Format::simple();
+    Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Valid property keys should be encoded");
+
+    ast.walk(&mut WalkPath::new(), &mut InvalidPropertyRenamer)
+        .expect("Could not rename");
+    match Encoder::new().encode(&mut format, &ast) {
+        Err(ref err) if err.kind() == ErrorKind::Encode => {
+            let message = err.source()
+                .expect("Missing cause")
+                .to_string();
+            assert!(message.starts_with("invalid identifier name: \"bar baz\" at "), "Unexpected message {}", message);
+            assert!(message.contains("StaticMemberExpression"), "The message should contain the path, got {}", message);
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Invalid property keys should not be encoded"),
+    }
+    Encoder::new()
+        .with_invalid_identifiers(true)
+        .encode(&mut format, &ast)
+        .expect("Invalid property keys should be encoded when allowed");
+}
@@ -0,0 +1,47 @@
+//! Explain why malformed ASTs cannot be imported.
+
+extern crate binjs;
+
+use binjs::generic::{ FromJSON, FromJSONPathItem, JSONExt };
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+
+#[test]
+fn test_import_errors() {
+    let json = Shift::new()
+        .parse_str("var x; foo(1, 2);")
+        .expect("Could not parse source");
+
+    // A misspelled interface.
+    let mut misspelled = json.clone();
+    misspelled["statements"][1]["expression"]["type"] = "CallExpresion".into();
+    let error = Script::import(&misspelled)
+        .expect_err("Import should fail");
+    assert_eq!(error.path, vec![
+        FromJSONPathItem::Field { interface: "Script".to_string(), field: "statements".to_string() },
+        FromJSONPathItem::Index(1),
+        FromJSONPathItem::Field { interface: "ExpressionStatement".to_string(), field: "expression".to_string() },
+    ]);
+    assert_eq!(error.field(), Some("expression"));
+    assert!(error.valid.contains(&"CallExpression".to_string()));
+    assert_eq!(error.got, "{\"type\": \"CallExpresion\", ...}");
+    assert_eq!(error.suggestion, Some("Did you mean CallExpression?".to_string()));
+    assert!(error.to_string().starts_with(".Script.statements[1].ExpressionStatement.expression: expected Instance of Expression"));
+
+    // A missing field.
+    let mut missing = json.clone();
+    missing["statements"][1]["expression"].remove("callee");
+    let error = Script::import(&missing)
+        .expect_err("Import should fail");
+    assert_eq!(error.field(), Some("callee"));
+    assert_eq!(error.got, "null");
+    assert!(error.suggestion.is_some());
+
+    // An invalid string enum value.
+    let mut invalid = json.clone();
+    invalid["statements"][0]["declaration"]["kind"] = "Var".into();
+    let error = Script::import(&invalid)
+        .expect_err("Import should fail");
+    assert_eq!(error.field(), Some("kind"));
+    assert_eq!(error.suggestion, Some("Did you mean var?".to_string()));
+}
@@ -0,0 +1,213 @@
+//! List the lazy functions of an encoded script.
+
+extern crate binjs;
+
+use binjs::generic::{ FromJSON, ToJSON };
+use binjs::io::Format;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::{ Program, Script, WalkPath, Walker };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+use binjs::specialized::es6::lazy::{ LazifierVisitor, LazyFunctionKind };
+
+use std::io::Cursor;
+
+#[test]
+fn test_lazy_functions() {
+    let parser = Shift::new();
+    let source = "
+        function foo(a, b, ...rest) { var x; return function() { return x; } }
+        var bar = function baz() {};
+        var obj = { get qux() { return 1; }, set qux(v) {}, method(c) { eval(c); } };
+    ";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::new(1))
+        .expect("Could not introduce laziness");
+
+    // Multipart files let us skip the contents of lazy functions, other formats are decoded entirely.
+    let formats = vec![
+        Format::simple(),
+        Format::from_args(&["multipart"])
+            .expect("Could not parse format"),
+    ];
+    for mut format in formats {
+        let data = Encoder::new()
+            .encode(&mut format, &ast)
+            .expect("Could not encode");
+        let functions = Decoder::new()
+            .lazy_functions(&mut format, Cursor::new((*data).as_ref()))
+            .expect("Could not list lazy functions");
+
+        let summary : Vec<_> = functions.iter()
+            .map(|function| (function.kind, function.name.as_ref().map(String::as_str), function.parameters))
+            .collect();
+        assert_eq!(summary, vec![
+            (LazyFunctionKind::Declaration, Some("foo"), 3),
+            (LazyFunctionKind::Expression, Some("baz"), 0),
+            (LazyFunctionKind::Getter, Some("qux"), 0),
+            (LazyFunctionKind::Setter, Some("qux"), 1),
+            (LazyFunctionKind::Method, Some("method"), 1),
+        ], "With format {}", format.name());
+        for function in &functions {
+            assert!(function.byte_len > 0);
+        }
+
+        let foo = &functions[0].scope;
+        assert_eq!(foo.parameter_names, 3);
+        assert_eq!(foo.declared_names, 1);
+        assert_eq!(foo.captured_names, 1);
+        assert!(!foo.has_direct_eval);
+        assert!(functions[4].scope.has_direct_eval);
+    }
+}
+
+#[test]
+fn test_lazy_functions_nested() {
+    let parser = Shift::new();
+    let source = "
+        function foo() { return function bar(x) { return x; }; }
+        function baz(y) {}
+    ";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::new(2))
+        .expect("Could not introduce laziness");
+
+    // `bar` is lazy, but nested in `foo`, so it is not listed.
+    let mut format = Format::from_args(&["multipart"])
+        .expect("Could not parse format");
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let functions = Decoder::new()
+        .lazy_functions(&mut format, Cursor::new((*data).as_ref()))
+        .expect("Could not list lazy functions");
+    let summary : Vec<_> = functions.iter()
+        .map(|function| (function.kind, function.name.as_ref().map(String::as_str), function.parameters, function.scope.parameter_names))
+        .collect();
+    assert_eq!(summary, vec![
+        (LazyFunctionKind::Declaration, Some("foo"), 0, 0),
+        (LazyFunctionKind::Declaration, Some("baz"), 1, 1),
+    ]);
+}
+
+#[test]
+fn test_lazification_policy() {
+    use binjs::specialized::es6::lazy::Policy;
+
+    assert_eq!(Policy::parse("none"), Ok(Policy::none()));
+    assert_eq!(Policy::parse("2"), Ok(Policy::Depth(2)));
+    assert_eq!(Policy::parse("min-bytes=100"), Ok(Policy::MinBytes(100)));
+    assert_eq!(Policy::parse("spans=0-10,20-30"), Ok(Policy::Spans(vec![(0, 10), (20, 30)])));
+    for invalid in &["some", "min-bytes=", "spans=10", "-1"] {
+        assert!(Policy::parse(invalid).is_err(), "{}", invalid);
+    }
+
+    // Lazify functions by size, whatever their depth.
+    let parser = Shift::new()
+        .with_positions(true);
+    let source = "function small() {}\nfunction large() { function nested_and_large() { return 1 + 2 + 3; } }";
+    let mut json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let positions = binjs::source::positions::collect(&mut json);
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+    let mut json = ast.export();
+    binjs::source::positions::reattach(&mut json, &positions)
+        .expect("Could not reattach positions");
+    let locations = binjs::source::positions::function_locations(&json);
+    assert_eq!(locations.len(), 3);
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::with_policy(Policy::MinBytes(40), locations))
+        .expect("Could not introduce laziness");
+
+    let names : Vec<_> = binjs::specialized::es6::lazy::LazyFunctionCollector::new()
+        .collect(&mut ast)
+        .into_iter()
+        .filter_map(|function| function.name)
+        .collect();
+    assert_eq!(names, vec!["large", "nested_and_large"]);
+}
+
+#[test]
+fn test_eagerify() {
+    use binjs::specialized::es6::lazy::EagerifierVisitor;
+
+    let parser = Shift::new();
+    let source = "function foo() { return { get bar() { return function() {}; } }; }";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+    let reference = ast.clone();
+
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::with_policy(binjs::specialized::es6::lazy::Policy::all(), vec![]))
+        .expect("Could not introduce laziness");
+    assert_ne!(ast, reference);
+    EagerifierVisitor::new()
+        .annotate_script(&mut ast);
+    assert_eq!(ast, reference);
+}
+
+#[test]
+fn test_profile_lazification() {
+    use binjs::io::multipart::TreeTokenReader;
+    use binjs::io::startup::StartupProfile;
+    use binjs::specialized::es6::lazy::{ LazyFunctionCollector, Policy };
+
+    let parser = Shift::new();
+    let source = "
+        function startup() { return helper(); }
+        function helper() { return { get later() { return 1; } }; }
+        function later() { return function() {}; }
+    ";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    // Functions 0 (`startup`) and 1 (`helper`) are executed at startup, but not the
+    // getter (2), `later` (3) or the function expression (4).
+    let profile = StartupProfile::parse("0\n1\n")
+        .expect("Could not parse profile");
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::with_policy(Policy::Profile(profile.clone()), vec![]))
+        .expect("Could not introduce laziness");
+    let lazy : Vec<_> = LazyFunctionCollector::new()
+        .collect(&mut ast)
+        .into_iter()
+        .map(|function| (function.kind, function.name))
+        .collect();
+    assert_eq!(lazy, vec![
+        (LazyFunctionKind::Getter, Some("later".to_string())),
+        (LazyFunctionKind::Declaration, Some("later".to_string())),
+        (LazyFunctionKind::Expression, None),
+    ]);
+
+    // The profile is recorded in multipart files.
+    let mut format = Format::from_args(&["multipart"])
+        .expect("Could not parse format");
+    let data = Encoder::new()
+        .with_profile(Some(profile.clone()))
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let recorded = TreeTokenReader::profile(Cursor::new((*data).as_ref()))
+        .expect("Could not read profile");
+    assert_eq!(recorded, Some(profile));
+    let decoded : Script = Decoder::new()
+        .decode(&mut format, Cursor::new((*data).as_ref()))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+}
@@ -0,0 +1,67 @@
+//! Compare versions of a small grammar, ensure that changes are reported and
+//! classified.
+
+extern crate binjs;
+
+use binjs::meta::diff::{ Change, SpecDiff };
+use binjs::meta::import::Importer;
+
+const OLD: &str = "
+typedef (Foo or Bar) FooOrBar;
+enum Kind { \"a\", \"b\" };
+interface Script : Node {
+  attribute FooOrBar item;
+  attribute Kind kind;
+  attribute DOMString first;
+  attribute DOMString second;
+};
+interface Foo : Node {
+  attribute DOMString name;
+};
+interface Bar : Node {
+  attribute boolean flag;
+};
+";
+
+#[test]
+fn test_meta_diff() {
+    let diff = |new: &str| {
+        let old = Importer::load(OLD, "Script")
+            .expect("Could not parse old grammar");
+        let new = Importer::load(new, "Script")
+            .expect("Could not parse new grammar");
+        SpecDiff::new(&old, &new)
+    };
+
+    assert!(diff(OLD).changes.is_empty());
+
+    // Non-breaking changes.
+    let compatible = OLD
+        .replace("typedef (Foo or Bar) FooOrBar;", "typedef (Foo or Bar or Baz) FooOrBar;")
+        .replace("\"a\", \"b\"", "\"a\", \"b\", \"c\"")
+        + "interface Baz : Node { };";
+    let result = diff(&compatible);
+    assert!(!result.is_breaking(), "Unexpected breaking changes {:?}", result.changes);
+    assert_eq!(result.changes, vec![
+        Change::InterfaceAdded("Baz".to_string()),
+        Change::StringEnumValueAdded { string_enum: "Kind".to_string(), value: "c".to_string(), appended: true },
+        Change::TypedefChanged { typedef: "FooOrBar".to_string(), old: "(Foo or Bar)".to_string(), new: "(Foo or Bar or Baz)".to_string(), widened: true },
+    ]);
+
+    // Breaking changes.
+    let breaking = OLD
+        .replace("attribute DOMString first;\n  attribute DOMString second;", "attribute DOMString second;\n  attribute DOMString first;")
+        .replace("\"a\", \"b\"", "\"c\", \"a\", \"b\"")
+        .replace("attribute boolean flag;", "attribute boolean flag;\n  attribute DOMString label;")
+        .replace("attribute DOMString name;", "attribute double name;");
+    let result = diff(&breaking);
+    assert!(result.changes.iter().all(Change::is_breaking), "Unexpected non-breaking changes {:?}", result.changes);
+    assert_eq!(result.changes, vec![
+        Change::FieldAdded { interface: "Bar".to_string(), field: "label".to_string() },
+        Change::FieldTypeChanged { interface: "Foo".to_string(), field: "name".to_string(), old: "String".to_string(), new: "Number".to_string(), widened: false },
+        Change::FieldsReordered { interface: "Script".to_string(),
+            old: vec!["item".to_string(), "kind".to_string(), "first".to_string(), "second".to_string()],
+            new: vec!["item".to_string(), "kind".to_string(), "second".to_string(), "first".to_string()] },
+        Change::StringEnumValueAdded { string_enum: "Kind".to_string(), value: "c".to_string(), appended: false },
+    ]);
+}
@@ -0,0 +1,61 @@
+//! Parse, annotate, encode and decode ES modules.
+
+extern crate binjs;
+
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::source::{ Shift, SourceParser, SourceType };
+use binjs::specialized::es6::ast::{ AssertedDeclaredKind, Program };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::io::Cursor;
+
+#[test]
+fn test_module_roundtrip() {
+    let parser = Shift::new()
+        .with_source_type(SourceType::Module);
+    let json = parser.parse_str("import foo, { bar as baz } from 'foo'; export function qux() { return foo(baz); } export default 1;")
+        .expect("Could not parse source");
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+
+    {
+        let module = match ast {
+            Program::Module(ref module) => module,
+            _ => panic!("Expected a module")
+        };
+        let kind = |name: &str| module.scope.declared_names.iter()
+            .find(|declared| declared.name == name)
+            .map(|declared| declared.kind.clone());
+        assert_eq!(kind("foo"), Some(AssertedDeclaredKind::ConstLexical));
+        assert_eq!(kind("baz"), Some(AssertedDeclaredKind::ConstLexical));
+        assert_eq!(kind("bar"), None);
+        assert_eq!(kind("qux"), Some(AssertedDeclaredKind::Var));
+    }
+
+    let mut format = Format::simple();
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let decoded : Program = Decoder::new()
+        .decode(&mut format, Cursor::new((*data).as_ref()))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+}
+
+#[test]
+fn test_source_type_auto() {
+    let parser = Shift::new()
+        .with_source_type(SourceType::Auto);
+    let module = parser.parse_str("export var x = 1;")
+        .expect("Could not parse module");
+    assert_eq!(module["type"], "Module");
+    let script = parser.parse_str("var x = 1; with (x) {}")
+        .expect("Could not parse script");
+    assert_eq!(script["type"], "Script");
+
+    // Scripts are still parsed as scripts by default.
+    assert!(Shift::new().parse_str("export var x = 1;").is_err());
+}
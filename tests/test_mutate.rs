@@ -0,0 +1,56 @@
+//! Corrupt a file with each mutation, ensure that the reference decoder behaves
+//! as the variants are labelled.
+
+extern crate binjs;
+extern crate rand;
+
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::mutate::{ Expected, MutationKind, Mutator };
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Encoder;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+#[test]
+fn test_mutate() {
+    let parser = Shift::new();
+    let json = parser.parse_str("function foo(x) { return typeof x === \"string\"; } foo(\"bar\");")
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let mut format = Format::from_args(&["multipart", "--section-compression", "identity"])
+        .expect("Could not parse format");
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+
+    let mutator = Mutator::new((*data).as_ref().to_vec())
+        .expect("Could not read file");
+    assert!(mutator.has_uncompressed_tree());
+    assert!(!mutator.has_checksums());
+
+    let mut rng = StdRng::seed_from_u64(0);
+    for kind in MutationKind::ALL.iter() {
+        assert_eq!(MutationKind::parse(kind.name()), Some(*kind));
+        for _ in 0..20 {
+            let mutant = mutator.mutate(*kind, &mut rng)
+                .expect("Could not mutate");
+            assert_eq!(mutant.kind, *kind);
+            match *kind {
+                MutationKind::FlipKind | MutationKind::FlipString => {
+                    assert_eq!(mutant.expected, Expected::NoCrash);
+                    assert_eq!(mutant.data.len(), (*data).as_ref().len());
+                }
+                _ => assert_eq!(mutant.expected, Expected::Reject)
+            }
+            let outcome = mutant.check();
+            assert!(mutant.expected.accepts(outcome), "{}: got {:?}", mutant.description, outcome);
+        }
+    }
+}
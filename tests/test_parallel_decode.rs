@@ -0,0 +1,71 @@
+//! Decode the contents of lazy functions in parallel.
+
+extern crate binjs;
+
+use binjs::generic::FromJSON;
+use binjs::io::{ CompressionTarget, Format };
+use binjs::io::bytes::compress::Compression;
+use binjs::io::multipart::{ Options, Statistics, Targets };
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::{ Program, WalkPath, Walker };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+use binjs::specialized::es6::lazy::{ LazifierVisitor, Policy };
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+
+#[test]
+fn test_parallel_decode() {
+    let parser = Shift::new();
+    let source = "
+        function foo(a, b) { var x = 'foo'; return function() { return x + a + b; } }
+        var bar = function baz() { return 'bar'; };
+        var obj = { get qux() { return 1; }, set qux(v) {}, method(c) { return () => c; } };
+        foo(1, 2)();
+    ";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::with_policy(Policy::all(), vec![]))
+        .expect("Could not introduce laziness");
+
+    let mut format = Format::Multipart {
+        targets: Targets {
+            grammar_table: CompressionTarget::new(Compression::Identity),
+            strings_table: CompressionTarget::new(Compression::Identity),
+            tree: CompressionTarget::new(Compression::Identity),
+        },
+        stats: Rc::new(RefCell::new(Statistics::default())),
+        options: Options::default(),
+    };
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+
+    let (sequential, _) : (Program, _) = Decoder::new()
+        .decode_with_positions(&mut format, Cursor::new((*data).as_ref()))
+        .expect("Could not decode sequentially");
+    for jobs in &[1, 2, 8] {
+        let (parallel, _) = Decoder::new()
+            .decode_parallel(&mut format, Cursor::new((*data).as_ref()), *jobs)
+            .expect("Could not decode in parallel");
+        assert_eq!(parallel, sequential, "With {} jobs", jobs);
+    }
+
+    // Other formats are decoded sequentially.
+    let mut format = Format::simple();
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let sequential : Program = Decoder::new()
+        .decode(&mut format, Cursor::new((*data).as_ref()))
+        .expect("Could not decode sequentially");
+    let (decoded, _) = Decoder::new()
+        .decode_parallel(&mut format, Cursor::new((*data).as_ref()), 2)
+        .expect("Could not decode");
+    assert_eq!(decoded, sequential);
+}
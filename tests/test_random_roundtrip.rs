@@ -0,0 +1,125 @@
+//! Generate random ASTs from the grammar, encode them, then decode them,
+//! ensuring that we obtain the same AST.
+//!
+//! Runs use `DEFAULT_SEED`, unless another seed is specified with environment
+//! variable `BINJS_RANDOM_SEED`. Failures report their seed, so that they may be reproduced.
+
+extern crate binjs;
+extern crate rand;
+
+use binjs::generic::{ FromJSON, Offset };
+use binjs::generic::pick::{ Pick, Picker };
+use binjs::io::{ CompressionTarget, Format };
+use binjs::io::multipart::{ Integrity, Options, Statistics, Targets };
+use binjs::meta::export::TypeDeanonymizer;
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::specialized::es6::ast::{ Script, Visitor, Walker, WalkPath };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+use std::thread;
+
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
+
+/// The number of ASTs generated by a run.
+const NUMBER_OF_ASTS : usize = 50;
+
+/// The seed used unless `BINJS_RANDOM_SEED` is specified.
+const DEFAULT_SEED : u64 = 0x62696e6a73;
+
+/// The depth after which the generator produces the smallest possible subtrees.
+const DEPTH_LIMIT : isize = 6;
+
+/// A visitor designed to reset offsets to 0.
+struct OffsetCleanerVisitor;
+impl Visitor<()> for OffsetCleanerVisitor {
+    fn visit_offset(&mut self, _path: &WalkPath, node: &mut Offset) -> Result<(), ()> {
+        *node = binjs::generic::Offset(0);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_random_roundtrip() {
+    thread::Builder::new()
+        .name("test_random_roundtrip large stack dedicated thread".to_string())
+        .stack_size(20 * 1024 * 1024)
+        .spawn(|| {
+            main();
+        })
+        .expect("Could not launch dedicated thread")
+        .join()
+        .expect("Error in dedicated thread");
+}
+
+fn main() {
+    let seed = match std::env::var("BINJS_RANDOM_SEED") {
+        Ok(seed) => seed.parse()
+            .expect("Invalid BINJS_RANDOM_SEED"),
+        Err(_) => DEFAULT_SEED
+    };
+    eprintln!("Using seed {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let mut builder = SpecBuilder::new();
+    let _ = binjs::generic::es6::Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Script"),
+    };
+    let spec = builder.into_spec(spec_options);
+
+    // Deanonymize the grammar, as encoders do, so that lazy fields are preceded
+    // by the `_skip` offset that the specialized AST expects.
+    let spec = TypeDeanonymizer::new(&spec)
+        .into_spec(SpecOptions {
+            root: spec.get_root_name(),
+            null: spec.get_null_name(),
+        });
+
+    for i in 0..NUMBER_OF_ASTS {
+        let json = Picker.random(&spec, &mut rng, DEPTH_LIMIT);
+        let ast = Script::import(&json)
+            .unwrap_or_else(|err| panic!("Generated an invalid AST (seed {}, AST {}): {:?}\n{:#}", seed, i, err, json));
+
+        // Formats that may be decoded without a dictionary.
+        let mut formats = vec![
+            Format::simple(),
+            Format::XML,
+            Format::Text,
+            Format::Multipart {
+                targets: Targets {
+                    grammar_table: rng.gen::<CompressionTarget>(),
+                    strings_table: rng.gen::<CompressionTarget>(),
+                    tree: rng.gen::<CompressionTarget>(),
+                },
+                stats: Rc::new(RefCell::new(Statistics::default())),
+                options: Options {
+                    integrity: Integrity {
+                        write_checksum: rng.gen(),
+                        ..Integrity::default()
+                    },
+                    split_prelude: rng.gen(),
+                    chunks: rng.gen(),
+                    ..Options::default()
+                },
+            },
+        ];
+        for format in &mut formats {
+            let data = Encoder::new()
+                .encode(format, &ast)
+                .unwrap_or_else(|err| panic!("Could not encode (seed {}, AST {}, format {}): {:?}", seed, i, format.name(), err));
+            let mut decoded : Script = Decoder::new()
+                .decode(format, Cursor::new((*data).as_ref()))
+                .unwrap_or_else(|err| panic!("Could not decode (seed {}, AST {}, format {}): {:?}", seed, i, format.name(), err));
+
+            // Offsets are computed by the encoder, while they are 0 in `ast`.
+            decoded.walk(&mut WalkPath::new(), &mut OffsetCleanerVisitor)
+                .expect("Could not cleanup offsets");
+            assert!(decoded == ast, "Roundtrip mismatch (seed {}, AST {}, format {})", seed, i, format.name());
+        }
+    }
+}
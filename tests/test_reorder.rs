@@ -0,0 +1,114 @@
+//! Move the toplevel functions executed at startup first.
+
+extern crate binjs;
+
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::io::startup::StartupProfile;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::{ Program, Script, Statement, WalkPath, Walker };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+use binjs::specialized::es6::lazy::{ LazifierVisitor, Policy };
+use binjs::specialized::es6::reorder::{ reorder_profile, reorder_program, reorder_script };
+
+use std::io::Cursor;
+
+fn names(script: &Script) -> Vec<String> {
+    script.statements.iter()
+        .map(|statement| match *statement {
+            Statement::EagerFunctionDeclaration(ref declaration) => declaration.name.name.as_str().to_string(),
+            Statement::LazyFunctionDeclaration(ref declaration) => format!("lazy {}", declaration.name.name.as_str()),
+            _ => "statement".to_string(),
+        })
+        .collect()
+}
+
+#[test]
+fn test_reorder_functions() {
+    let parser = Shift::new();
+    // Function ids: cold = 0, its expression = 1, hot = 2, twice = 3, twice = 4, main = 5.
+    let source = "
+        function cold() { return function() {}; }
+        var x = 1;
+        function hot() { return x; }
+        function twice() {}
+        function twice() {}
+        main();
+        function main() { hot(); twice(); }
+    ";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let profile = StartupProfile::parse("2\n4\n5\n")
+        .expect("Could not parse profile");
+
+    // Functions are identified by their index before reordering, so laziness may be introduced first.
+    ast.walk(&mut WalkPath::new(), &mut LazifierVisitor::with_policy(Policy::Profile(profile.clone()), vec![]))
+        .expect("Could not introduce laziness");
+    let order = reorder_script(&mut ast, &profile);
+    assert_eq!(order, vec![2, 6, 0, 1, 3, 4, 5]);
+
+    // Declarations sharing a name are not moved, as the last one wins.
+    assert_eq!(names(&ast), vec!["hot", "main", "lazy cold", "statement", "lazy twice", "twice", "statement"]);
+
+    // Nothing moves without a profile.
+    let mut reordered = ast.clone();
+    let order = reorder_script(&mut reordered, &StartupProfile::new());
+    assert_eq!(order, (0..7).collect::<Vec<_>>());
+    assert_eq!(reordered, ast);
+
+    let mut format = Format::simple();
+    let data = Encoder::new()
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let decoded : Script = Decoder::new()
+        .decode(&mut format, Cursor::new((*data).as_ref()))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+}
+
+#[test]
+fn test_reorder_profile() {
+    use binjs::io::multipart::TreeTokenReader;
+
+    let parser = Shift::new();
+    // Function ids: cold = 0, its expression = 1, hot = 2, twice = 3, twice = 4, main = 5.
+    let source = "
+        function cold() { return function() {}; }
+        var x = 1;
+        function hot() { return x; }
+        function twice() {}
+        function twice() {}
+        main();
+        function main() { hot(); twice(); }
+    ";
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Program::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_program(&mut ast);
+
+    let profile = StartupProfile::parse("2\n4\n5\n")
+        .expect("Could not parse profile");
+    let order = reorder_program(&mut ast, &profile);
+
+    // Function ids after reordering: hot = 0, main = 1, cold = 2, its expression = 3, twice = 4, twice = 5.
+    let reordered = reorder_profile(&mut ast, &order, &profile);
+    assert_eq!(reordered.executed(), vec![0, 1, 5]);
+
+    // The file records the profile of the reordered functions.
+    let mut format = Format::from_args(&["multipart"])
+        .expect("Could not parse format");
+    let data = Encoder::new()
+        .with_profile(Some(reordered.clone()))
+        .encode(&mut format, &ast)
+        .expect("Could not encode");
+    let recorded = TreeTokenReader::profile(Cursor::new((*data).as_ref()))
+        .expect("Could not read profile");
+    assert_eq!(recorded, Some(reordered));
+}
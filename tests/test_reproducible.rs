@@ -0,0 +1,85 @@
+//! Encode the same source twice, from different directories, at different times,
+//! ensure that reproducible files are identical.
+
+extern crate binjs;
+
+use binjs::generic::FromJSON;
+use binjs::io::Format;
+use binjs::io::multipart::{ Metadata, TreeTokenReader };
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Encoder;
+
+use std::io::Cursor;
+use std::path::Path;
+
+/// Encode the source stored in `dir`, with the string dictionary stored in `dir`,
+/// recording `timestamp` and the path of the dictionary in the metadata.
+fn encode(dir: &Path, timestamp: u64, reproducible: bool, encryption_key: Option<[u8; 32]>) -> Result<Vec<u8>, binjs::Error> {
+    let source = dir.join("source.js");
+    let dictionary = dir.join("strings.dict");
+    std::fs::create_dir_all(dir)
+        .expect("Could not create directory");
+    std::fs::write(&source, "function foo(x) { return \"foo\" + x; } foo(\"bar\");")
+        .expect("Could not write source");
+    std::fs::write(&dictionary, "foobarbaz")
+        .expect("Could not write dictionary");
+
+    let json = Shift::new()
+        .parse_file(&source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let dictionary = dictionary.to_str()
+        .expect("Invalid path");
+    let mut format = Format::from_args(&["multipart", "--section-compression", "br", "--string-dictionary", dictionary, "--checksum"])
+        .expect("Could not parse format");
+    {
+        let options = format.multipart_options_mut()
+            .expect("Missing multipart options");
+        options.metadata = Some(Metadata::new()
+            .with(Metadata::ENCODER, "test")
+            .with(Metadata::OPTIONS, &format!("multipart --string-dictionary {}", dictionary))
+            .with(Metadata::TIMESTAMP, &timestamp.to_string()));
+        options.reproducible = reproducible;
+        options.integrity.encryption_key = encryption_key;
+    }
+    let data = Encoder::new()
+        .encode(&mut format, &ast)?;
+    Ok((*data).as_ref().to_vec())
+}
+
+#[test]
+fn test_reproducible() {
+    let dir = std::env::temp_dir()
+        .join(format!("binjs-test-reproducible-{}", std::process::id()));
+    let first = dir.join("first");
+    let second = dir.join("second");
+
+    let data = encode(&first, 1, true, None)
+        .expect("Could not encode");
+    assert_eq!(data, encode(&second, 2, true, None).expect("Could not encode again"));
+
+    // Only the entries that do not vary between runs are recorded.
+    let metadata = TreeTokenReader::metadata(Cursor::new(&data))
+        .expect("Could not read metadata")
+        .expect("Missing metadata");
+    assert_eq!(metadata.get(Metadata::ENCODER), Some("test"));
+    assert_eq!(metadata.get(Metadata::TIMESTAMP), None);
+    assert_eq!(metadata.get(Metadata::OPTIONS), None);
+
+    // Otherwise, the time and the paths are recorded.
+    assert_ne!(encode(&first, 1, false, None).expect("Could not encode"),
+        encode(&second, 1, false, None).expect("Could not encode again"));
+    assert_ne!(encode(&first, 1, false, None).expect("Could not encode"),
+        encode(&first, 2, false, None).expect("Could not encode again"));
+
+    // Encryption uses a random nonce.
+    assert!(encode(&first, 1, true, Some([42; 32])).is_err());
+
+    std::fs::remove_dir_all(&dir)
+        .expect("Could not remove directory");
+}
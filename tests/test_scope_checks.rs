@@ -0,0 +1,114 @@
+//! Check the consistency of scope annotations when decoding.
+
+extern crate binjs;
+
+use binjs::generic::{ FromJSON, IdentifierName };
+use binjs::ErrorKind;
+use binjs::io::Format;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::{ AssertedDeclaredKind, AssertedDeclaredName, Script };
+use binjs::specialized::es6::io::{ Decoder, Encoder };
+use binjs::specialized::es6::scope_checks::{ check, ScopeCheckPolicy };
+use binjs::specialized::es6::scopes::AnnotationVisitor;
+
+use std::error::Error;
+use std::io::Cursor;
+
+fn annotated(source: &str) -> Script {
+    let parser = Shift::new();
+    let json = parser.parse_str(source)
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+    ast
+}
+
+fn decode(ast: &Script, policy: ScopeCheckPolicy) -> Result<Script, binjs::Error> {
+    let mut format = Format::simple();
+    let data = Encoder::new()
+        .encode(&mut format, ast)
+        .expect("Could not encode");
+    Decoder::new()
+        .with_scope_checks(policy)
+        .decode(&mut format, Cursor::new((*data).as_ref()))
+}
+
+#[test]
+fn test_scope_checks() {
+    let mut ast = annotated("
+        var x = 1;
+        const y = 2;
+        function foo(a, a) { var a; let b; return function() { return a + b; } }
+        try { let z; } catch (e) { }
+    ");
+
+    // Annotations computed by the encoder are consistent.
+    assert_eq!(check(&mut ast), Vec::<String>::new());
+    decode(&ast, ScopeCheckPolicy::Reject)
+        .expect("Could not decode consistent annotations");
+
+    // Declare `x` a second time, as a lexical.
+    ast.scope.declared_names.push(AssertedDeclaredName {
+        name: IdentifierName::from_str("x"),
+        kind: AssertedDeclaredKind::NonConstLexical,
+        is_captured: false,
+    });
+    let problems = check(&mut ast);
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("\"x\" is declared both as Var and as NonConstLexical"), "Unexpected problem {}", problems[0]);
+
+    match decode(&ast, ScopeCheckPolicy::Reject) {
+        Err(ref err) if err.kind() == ErrorKind::Decode => {
+            let message = err.source()
+                .expect("Missing cause")
+                .to_string();
+            assert_eq!(message, format!("inconsistent scopes: {}", problems[0]));
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Inconsistent annotations should be rejected"),
+    }
+    let decoded = decode(&ast, ScopeCheckPolicy::Warn)
+        .expect("Warnings should not prevent decoding");
+    assert_eq!(decoded, ast);
+    decode(&ast, ScopeCheckPolicy::Ignore)
+        .expect("Scope annotations should not be checked by default");
+}
+
+#[test]
+fn test_captured_scope_checks() {
+    let mut ast = annotated("
+        var x = 1;
+        var y = 2;
+        function foo() { x = y; return function() { return y; } }
+    ");
+
+    // Annotations computed by the encoder are consistent.
+    assert_eq!(check(&mut ast), Vec::<String>::new());
+
+    // Pretend that `x`, which `foo` assigns, is not captured.
+    for declared in ast.scope.declared_names.iter_mut() {
+        if declared.name == "x" {
+            assert!(declared.is_captured);
+            declared.is_captured = false;
+        }
+    }
+    let problems = check(&mut ast);
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].contains("\"x\" is used in a nested function but is not declared as captured"), "Unexpected problem {}", problems[0]);
+
+    match decode(&ast, ScopeCheckPolicy::Reject) {
+        Err(ref err) if err.kind() == ErrorKind::Decode => {
+            let message = err.source()
+                .expect("Missing cause")
+                .to_string();
+            assert_eq!(message, format!("inconsistent scopes: {}", problems[0]));
+        }
+        Err(err) => panic!("Unexpected error {:?}", err),
+        Ok(_) => panic!("Inconsistent annotations should be rejected"),
+    }
+    let decoded = decode(&ast, ScopeCheckPolicy::Warn)
+        .expect("Warnings should not prevent decoding");
+    assert_eq!(decoded, ast);
+}
@@ -0,0 +1,26 @@
+//! Serialize a strongly-typed AST with serde, then deserialize it, ensure that
+//! we obtain the same AST.
+
+extern crate binjs;
+extern crate bincode;
+
+use binjs::generic::FromJSON;
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+
+#[test]
+fn test_serde_roundtrip() {
+    let parser = Shift::new();
+    let json = parser.parse_str("function foo(x) { return x + 1; } foo(\"bar\"); var y = typeof foo;")
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let bytes = bincode::serialize(&ast)
+        .expect("Could not serialize");
+    let decoded : Script = bincode::deserialize(&bytes)
+        .expect("Could not deserialize");
+    assert_eq!(ast, decoded);
+}
@@ -0,0 +1,94 @@
+//! Compare the encoded sizes of a corpus with a baseline.
+
+extern crate binjs;
+
+use binjs::io::Format;
+use binjs::sizecheck::{ encoded_size, FileChange, Sizes };
+use binjs::source::{ Shift, SourceType };
+
+fn sizes(args: &[&str], files: &[(&str, usize)]) -> Sizes {
+    let format = Format::from_args(args)
+        .expect("Could not parse format");
+    let mut sizes = Sizes::new(&format);
+    for &(name, size) in files {
+        sizes.files.insert(name.to_string(), size);
+    }
+    sizes
+}
+
+#[test]
+fn test_compare() {
+    let baseline = sizes(&["multipart"], &[("a.js", 100), ("b.js", 200), ("removed.js", 10)]);
+    let current = sizes(&["multipart"], &[("a.js", 110), ("b.js", 199), ("added.mjs", 10)]);
+
+    let comparison = baseline.compare(&current, 5., 1.)
+        .expect("Could not compare");
+    assert_eq!(comparison.before, 300);
+    assert_eq!(comparison.after, 309);
+    assert!(comparison.regression);
+    assert_eq!(comparison.files["a.js"], FileChange::Changed { before: 100, after: 110, delta: 10., regression: true });
+    assert_eq!(comparison.files["b.js"], FileChange::Changed { before: 200, after: 199, delta: -0.5, regression: false });
+    assert_eq!(comparison.files["added.mjs"], FileChange::Added(10));
+    assert_eq!(comparison.files["removed.js"], FileChange::Removed(10));
+    assert_eq!(comparison.regressions(), 2);
+
+    let comparison = baseline.compare(&current, 20., 5.)
+        .expect("Could not compare");
+    assert_eq!(comparison.regressions(), 0);
+
+    // Baselines survive being stored.
+    assert_eq!(Sizes::import(&baseline.export()), Ok(baseline));
+}
+
+#[test]
+fn test_compare_other_format() {
+    let baseline = sizes(&["multipart", "--section-compression", "br"], &[("a.js", 100)]);
+
+    // Another format, with the same sizes.
+    let current = sizes(&["expanded"], &[("a.js", 100)]);
+    assert!(baseline.compare(&current, 5., 1.).is_err());
+
+    // The same format, with other options.
+    let current = sizes(&["multipart", "--section-compression", "gzip"], &[("a.js", 100)]);
+    assert!(baseline.compare(&current, 5., 1.).is_err());
+    let current = sizes(&["multipart", "--section-compression", "br", "--runs"], &[("a.js", 100)]);
+    assert!(baseline.compare(&current, 5., 1.).is_err());
+
+    let current = sizes(&["multipart", "--section-compression", "br"], &[("a.js", 100)]);
+    assert!(baseline.compare(&current, 5., 1.).is_ok());
+
+    // Baselines written without options cannot be compared.
+    let mut json = baseline.export();
+    json.as_object_mut()
+        .unwrap()
+        .remove("options");
+    assert!(Sizes::import(&json).is_err());
+}
+
+#[test]
+fn test_encoded_size_module() {
+    let dir = std::env::temp_dir()
+        .join(format!("binjs-test-sizecheck-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)
+        .expect("Could not create directory");
+    let module = dir.join("module.mjs");
+    std::fs::write(&module, "import foo from 'foo'; export default foo;")
+        .expect("Could not write module");
+    let script = dir.join("script.js");
+    std::fs::write(&script, "var foo = 1;")
+        .expect("Could not write script");
+
+    let mut format = Format::simple();
+    let parser = Shift::new()
+        .with_source_type(SourceType::Auto);
+    encoded_size(&parser, &mut format, 0, &module)
+        .expect("Could not encode module");
+    encoded_size(&parser, &mut format, 0, &script)
+        .expect("Could not encode script");
+
+    // Modules cannot be parsed as scripts.
+    let parser = Shift::new();
+    assert!(encoded_size(&parser, &mut format, 0, &module).is_err());
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,82 @@
+//! Export the machine-readable description of the ES6 grammar, ensure that
+//! it matches the grammar.
+
+extern crate binjs;
+#[macro_use]
+extern crate serde_json;
+
+use binjs::io::multipart::ContainerLayout;
+use binjs::meta::export::{ ToJSONDescription, ToTypeScript };
+use binjs::meta::import::Importer;
+
+use std::io::Read;
+
+fn load_spec() -> binjs::meta::spec::Spec {
+    let mut source = String::new();
+    std::fs::File::open("spec/es6.webidl")
+        .and_then(|mut file| file.read_to_string(&mut source))
+        .expect("Could not read grammar");
+    Importer::load(&source, "Script")
+        .expect("Could not parse grammar")
+}
+
+#[test]
+fn test_spec_metadata() {
+    let spec = load_spec();
+
+    let description = ToJSONDescription::spec(&spec);
+    assert_eq!(description["root"], "Script");
+    assert_eq!(description["fingerprint"], format!("{:016x}", spec.fingerprint()));
+
+    let find = |kind: &str, name: &str| description[kind].as_array()
+        .expect("Missing list")
+        .iter()
+        .find(|item| item["name"] == name)
+        .unwrap_or_else(|| panic!("Missing {}", name))
+        .clone();
+
+    // String enums keep the order of their values.
+    assert_eq!(find("string_enums", "VariableDeclarationKind")["values"], json!(["var", "let", "const"]));
+
+    // Fields keep their order, typedefs are resolved to find tokens.
+    let lazy = find("interfaces", "LazyFunctionDeclaration");
+    let fields = lazy["fields"].as_array()
+        .expect("Missing fields");
+    let names : Vec<_> = fields.iter()
+        .map(|field| field["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["isAsync", "isGenerator", "name", "length", "directives", "contents"]);
+    let tokens : Vec<_> = fields.iter()
+        .map(|field| field["token"].as_str().unwrap())
+        .collect();
+    assert_eq!(tokens, ["bool", "bool", "tagged_tuple", "unsigned_long", "list", "tagged_tuple"]);
+    assert_eq!(fields[5]["lazy"], true);
+    assert_eq!(fields[4]["type"]["kind"], "array");
+
+    let name = &find("interfaces", "IdentifierExpression")["fields"][0];
+    assert_eq!(name["token"], "identifier_name");
+    assert_eq!(name["type"], json!({ "kind": "IdentifierName", "optional": false }));
+
+    // The container layout is serializable, and lists sections in order.
+    let layout = serde_json::to_value(ContainerLayout::new())
+        .expect("Could not serialize container layout");
+    let sections : Vec<_> = layout["sections"].as_array()
+        .expect("Missing sections")
+        .iter()
+        .map(|section| section["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(&sections[6..12], ["grammar", "strings", "blobs", "manifest", "tree", "positions"]);
+}
+
+#[test]
+fn test_typescript_definitions() {
+    let definitions = ToTypeScript::spec(&load_spec());
+
+    assert!(definitions.contains("export interface IdentifierExpression {\n    type: \"IdentifierExpression\";\n    name: string;\n    loc?: SourceLocation;\n}\n"));
+    assert!(definitions.contains("export type VariableDeclarationKind = \"var\" | \"let\" | \"const\";\n"));
+    assert!(definitions.contains("    contents_skip: number;\n    contents: FunctionOrMethodContents;\n"));
+    assert!(definitions.contains("    comments?: Comment[];\n"));
+
+    // The null interface is represented as `null`.
+    assert!(!definitions.contains("export interface  {"));
+}
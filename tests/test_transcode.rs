@@ -0,0 +1,59 @@
+//! Convert files between formats without going through the text source.
+
+extern crate binjs;
+
+use binjs::generic::FromJSON;
+use binjs::generic::io::Transcoder;
+use binjs::io::Format;
+use binjs::meta::spec::{ SpecBuilder, SpecOptions };
+use binjs::source::{ Shift, SourceParser };
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::{ grammar_id, Decoder, Encoder };
+
+use std::io::Cursor;
+
+#[test]
+fn test_transcode() {
+    let parser = Shift::new();
+    let json = parser.parse_str("function foo(x) { return x * 2; } foo(21);")
+        .expect("Could not parse source");
+    let mut ast = Script::import(&json)
+        .expect("Could not import AST");
+    binjs::specialized::es6::scopes::AnnotationVisitor::new()
+        .annotate_script(&mut ast);
+
+    let mut simple = Format::from_args(&["expanded"])
+        .expect("Could not parse format");
+    let mut multipart = Format::from_args(&["multipart", "--section-compression", "br"])
+        .expect("Could not parse format");
+    assert!(Format::from_args(&["unknown-format"]).is_err());
+
+    let data = Encoder::new()
+        .encode(&mut simple, &ast)
+        .expect("Could not encode");
+    let mut builder = SpecBuilder::new();
+    let _ = binjs::generic::es6::Library::new(&mut builder);
+    let spec_options = SpecOptions {
+        null: &builder.node_name(""),
+        root: &builder.node_name("Program"),
+    };
+    let spec = builder.into_spec(spec_options);
+    let transcoder = Transcoder::new(&spec, grammar_id());
+
+    let transcoded = transcoder
+        .transcode(&mut simple, &mut multipart, Cursor::new((*data).as_ref()))
+        .expect("Could not transcode");
+    let decoded : Script = Decoder::new()
+        .decode(&mut multipart, Cursor::new((*transcoded).as_ref()))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+
+    // And back, from a file that declares its grammar.
+    let back = transcoder
+        .transcode(&mut multipart, &mut simple, Cursor::new((*transcoded).as_ref()))
+        .expect("Could not transcode back");
+    let decoded : Script = Decoder::new()
+        .decode(&mut simple, Cursor::new((*back).as_ref()))
+        .expect("Could not decode");
+    assert_eq!(decoded, ast);
+}
@@ -0,0 +1,45 @@
+//! Emit the reference test vectors, ensure that they are deterministic and
+//! that the binaries decode to the ASTs written alongside them.
+
+extern crate binjs;
+extern crate serde_json;
+
+use binjs::generic::{ ToJSON, JSON };
+use binjs::io::Format;
+use binjs::source::Shift;
+use binjs::specialized::es6::ast::Script;
+use binjs::specialized::es6::io::Decoder;
+use binjs::util::vectors::{ emit, SOURCES };
+
+use std::io::Cursor;
+
+#[test]
+fn test_vectors() {
+    let parser = Shift::new();
+    let dir = std::env::temp_dir()
+        .join(format!("binjs-test-vectors-{}", std::process::id()));
+
+    let mut format = Format::simple();
+    let index = emit(&parser, &mut format, dir.join("first"))
+        .expect("Could not emit test vectors");
+    assert_eq!(index["vectors"].as_array().map(Vec::len), Some(SOURCES.len()));
+    emit(&parser, &mut format, dir.join("second"))
+        .expect("Could not emit test vectors again");
+
+    for &(name, source) in SOURCES {
+        let read = |sub: &str, extension: &str| std::fs::read(dir.join(sub).join(format!("{}.{}", name, extension)))
+            .expect("Could not read test vector");
+
+        assert_eq!(read("first", "js"), source.as_bytes());
+        assert_eq!(read("first", "binjs"), read("second", "binjs"), "Vector {} is not deterministic", name);
+
+        let decoded : Script = Decoder::new()
+            .decode(&mut format, Cursor::new(read("first", "binjs")))
+            .expect("Could not decode test vector");
+        let expected : JSON = serde_json::from_slice(&read("first", "json"))
+            .expect("Could not parse decoded AST");
+        assert_eq!(decoded.export(), expected, "Vector {} does not decode to its AST", name);
+    }
+
+    let _ = std::fs::remove_dir_all(&dir);
+}